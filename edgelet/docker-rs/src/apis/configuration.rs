@@ -8,16 +8,23 @@
  * Generated by: https://github.com/swagger-api/swagger-codegen.git
  */
 
+use std::time::Duration;
+
 use failure::err_msg;
 use failure::Error;
+use futures::Future;
 use hyper::client::connect::Connect;
-use hyper::{Client, Uri};
+use hyper::{Body, Client, Request, Response, Uri};
+use tokio_timer::Timeout;
+
+use super::Error as ApiError;
 
 pub struct Configuration<C: Connect> {
     pub base_path: String,
     pub user_agent: Option<String>,
     pub client: Client<C>,
     pub uri_composer: Box<Fn(&str, &str) -> Result<Uri, Error> + Send + Sync>,
+    pub request_timeout: Option<Duration>,
 }
 
 impl<C: Connect> Configuration<C> {
@@ -31,6 +38,47 @@ impl<C: Connect> Configuration<C> {
                     .parse()
                     .map_err(|_| format_err!("Url parse error"))
             }),
+            request_timeout: None,
+        }
+    }
+}
+
+impl<C> Configuration<C>
+where
+    C: Connect + 'static,
+    <C as Connect>::Transport: 'static,
+    <C as Connect>::Future: 'static,
+{
+    /// Sends a request, failing with `Error::Timeout` if the response doesn't
+    /// start within `request_timeout`.
+    pub fn request<T>(
+        &self,
+        req: Request<Body>,
+    ) -> Box<Future<Item = Response<Body>, Error = ApiError<T>> + Send>
+    where
+        T: Send + 'static,
+    {
+        self.request_with_timeout(req, self.request_timeout)
+    }
+
+    pub fn request_with_timeout<T>(
+        &self,
+        req: Request<Body>,
+        timeout: Option<Duration>,
+    ) -> Box<Future<Item = Response<Body>, Error = ApiError<T>> + Send>
+    where
+        T: Send + 'static,
+    {
+        let response = self.client.request(req).map_err(ApiError::from);
+        match timeout {
+            Some(timeout) => Box::new(Timeout::new(response, timeout).map_err(|e| {
+                if e.is_inner() {
+                    e.into_inner().expect("timeout error should have an inner error")
+                } else {
+                    ApiError::Timeout
+                }
+            })),
+            None => Box::new(response),
         }
     }
 }
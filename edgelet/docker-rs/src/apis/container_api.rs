@@ -93,7 +93,7 @@ pub trait ContainerApi: Send + Sync {
         &self,
         id: &str,
         signal: &str,
-    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send>;
     fn container_list(
         &self,
         all: bool,
@@ -152,7 +152,7 @@ pub trait ContainerApi: Send + Sync {
         &self,
         id: &str,
         ps_args: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse2001, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::InlineResponse2001, Error = Error<serde_json::Value>> + Send>;
     fn container_unpause(
         &self,
         id: &str,
@@ -213,9 +213,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -262,9 +260,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -318,12 +314,11 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -377,12 +372,11 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -426,9 +420,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -487,9 +479,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -544,9 +534,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -589,9 +577,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -639,9 +625,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -665,7 +649,7 @@ where
         &self,
         id: &str,
         signal: &str,
-    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::POST;
@@ -692,9 +676,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -747,9 +729,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -807,12 +787,18 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // Following the logs keeps the request open for as long as the
+        // container runs, so it can't be subject to the request timeout.
+        let timeout = if follow {
+            None
+        } else {
+            configuration.request_timeout
+        };
+
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, timeout)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     if status.is_success() {
@@ -852,9 +838,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -900,9 +884,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -953,9 +935,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1004,9 +984,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1050,12 +1028,11 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1102,9 +1079,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1151,9 +1126,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1200,12 +1173,11 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1225,7 +1197,8 @@ where
         &self,
         id: &str,
         ps_args: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse2001, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::InlineResponse2001, Error = Error<serde_json::Value>> + Send>
+    {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -1252,9 +1225,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1301,9 +1272,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1354,9 +1323,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1404,12 +1371,11 @@ where
             .body(hyper::Body::empty())
             .expect("could not build hyper::Request");
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -1472,9 +1438,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
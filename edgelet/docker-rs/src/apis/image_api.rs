@@ -93,7 +93,7 @@ pub trait ImageApi: Send + Sync {
         name: &str,
         force: bool,
         noprune: bool,
-    ) -> Box<Future<Item = Vec<ImageDeleteResponseItem>, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = Vec<ImageDeleteResponseItem>, Error = Error<serde_json::Value>> + Send>;
     fn image_get(
         &self,
         name: &str,
@@ -115,7 +115,7 @@ pub trait ImageApi: Send + Sync {
         all: bool,
         filters: &str,
         digests: bool,
-    ) -> Box<Future<Item = Vec<::models::ImageSummary>, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = Vec<::models::ImageSummary>, Error = Error<serde_json::Value>> + Send>;
     fn image_load(
         &self,
         images_tarball: Vec<u8>,
@@ -177,9 +177,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -283,9 +281,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -351,9 +347,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -417,12 +411,11 @@ where
         req.headers_mut()
             .typed_insert(&typed_headers::ContentLength(serialized_len as u64));
 
+        // This request can legitimately outlast the request timeout.
         // send request
         Box::new(
             configuration
-                .client
-                .request(req)
-                .map_err(|e| Error::from(e))
+                .request_with_timeout(req, None)
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -443,7 +436,7 @@ where
         name: &str,
         force: bool,
         noprune: bool,
-    ) -> Box<Future<Item = Vec<ImageDeleteResponseItem>, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = Vec<ImageDeleteResponseItem>, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::DELETE;
@@ -471,9 +464,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -522,9 +513,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -573,9 +562,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -622,9 +609,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -671,9 +656,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -697,7 +680,7 @@ where
         all: bool,
         filters: &str,
         digests: bool,
-    ) -> Box<Future<Item = Vec<::models::ImageSummary>, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = Vec<::models::ImageSummary>, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -726,9 +709,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -786,9 +767,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -834,9 +813,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -889,9 +866,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -942,9 +917,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -997,9 +970,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -7,6 +7,7 @@ pub enum Error<T> {
     Hyper(hyper::Error),
     Serde(serde_json::Error),
     Api(ApiError<T>),
+    Timeout,
 }
 
 #[derive(Debug)]
@@ -43,7 +43,10 @@ pub trait NetworkApi: Send + Sync {
         &self,
         network_config: ::models::NetworkConfig,
     ) -> Box<Future<Item = ::models::InlineResponse2011, Error = Error<serde_json::Value>> + Send>;
-    fn network_delete(&self, id: &str) -> Box<Future<Item = (), Error = Error<serde_json::Value>>>;
+    fn network_delete(
+        &self,
+        id: &str,
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send>;
     fn network_disconnect(
         &self,
         id: &str,
@@ -54,7 +57,7 @@ pub trait NetworkApi: Send + Sync {
         id: &str,
         verbose: bool,
         scope: &str,
-    ) -> Box<Future<Item = ::models::Network, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::Network, Error = Error<serde_json::Value>> + Send>;
     fn network_list(
         &self,
         filters: &str,
@@ -106,9 +109,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -159,9 +160,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -181,7 +180,10 @@ where
         )
     }
 
-    fn network_delete(&self, id: &str) -> Box<Future<Item = (), Error = Error<serde_json::Value>>> {
+    fn network_delete(
+        &self,
+        id: &str,
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::DELETE;
@@ -205,9 +207,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -258,9 +258,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -281,7 +279,7 @@ where
         id: &str,
         verbose: bool,
         scope: &str,
-    ) -> Box<Future<Item = ::models::Network, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::Network, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -309,9 +307,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -360,9 +356,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -411,9 +405,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
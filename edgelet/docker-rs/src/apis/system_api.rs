@@ -53,7 +53,9 @@ pub trait SystemApi: Send + Sync {
     fn system_ping(&self) -> Box<Future<Item = String, Error = Error<serde_json::Value>>>;
     fn system_version(
         &self,
-    ) -> Box<Future<Item = ::models::InlineResponse20011, Error = Error<serde_json::Value>>>;
+    ) -> Box<
+        Future<Item = ::models::InlineResponse20011, Error = Error<serde_json::Value>> + Send,
+    >;
 }
 
 impl<C> SystemApi for SystemApiClient<C>
@@ -96,9 +98,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -144,9 +144,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -200,9 +198,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -248,9 +244,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -293,9 +287,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -316,7 +308,9 @@ where
 
     fn system_version(
         &self,
-    ) -> Box<Future<Item = ::models::InlineResponse20011, Error = Error<serde_json::Value>>> {
+    ) -> Box<
+        Future<Item = ::models::InlineResponse20011, Error = Error<serde_json::Value>> + Send,
+    > {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -340,9 +334,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
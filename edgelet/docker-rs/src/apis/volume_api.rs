@@ -37,24 +37,24 @@ pub trait VolumeApi: Send + Sync {
     fn volume_create(
         &self,
         volume_config: ::models::VolumeConfig,
-    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>> + Send>;
     fn volume_delete(
         &self,
         name: &str,
         force: bool,
-    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send>;
     fn volume_inspect(
         &self,
         name: &str,
-    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>> + Send>;
     fn volume_list(
         &self,
         filters: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse20015, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::InlineResponse20015, Error = Error<serde_json::Value>> + Send>;
     fn volume_prune(
         &self,
         filters: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse20016, Error = Error<serde_json::Value>>>;
+    ) -> Box<Future<Item = ::models::InlineResponse20016, Error = Error<serde_json::Value>> + Send>;
 }
 
 impl<C> VolumeApi for VolumeApiClient<C>
@@ -66,7 +66,7 @@ where
     fn volume_create(
         &self,
         volume_config: ::models::VolumeConfig,
-    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::POST;
@@ -97,9 +97,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -122,7 +120,7 @@ where
         &self,
         name: &str,
         force: bool,
-    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = (), Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::DELETE;
@@ -149,9 +147,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -170,7 +166,7 @@ where
     fn volume_inspect(
         &self,
         name: &str,
-    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::Volume, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -194,9 +190,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -218,7 +212,7 @@ where
     fn volume_list(
         &self,
         filters: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse20015, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::InlineResponse20015, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::GET;
@@ -245,9 +239,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
@@ -270,7 +262,7 @@ where
     fn volume_prune(
         &self,
         filters: &str,
-    ) -> Box<Future<Item = ::models::InlineResponse20016, Error = Error<serde_json::Value>>> {
+    ) -> Box<Future<Item = ::models::InlineResponse20016, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
         let method = hyper::Method::POST;
@@ -297,9 +289,7 @@ where
         // send request
         Box::new(
             configuration
-                .client
                 .request(req)
-                .map_err(|e| Error::from(e))
                 .and_then(|resp| {
                     let (http::response::Parts { status, .. }, body) = resp.into_parts();
                     body.concat2()
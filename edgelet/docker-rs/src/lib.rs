@@ -19,6 +19,7 @@ extern crate futures;
 extern crate hyper;
 extern crate serde;
 extern crate serde_json;
+extern crate tokio_timer;
 extern crate typed_headers;
 extern crate url;
 
@@ -42,7 +42,7 @@ pub struct ContainerSummary {
     #[serde(rename = "NetworkSettings")]
     network_settings: ContainerNetworkSettings,
     #[serde(rename = "Mounts")]
-    mounts: Vec<::models::Mount>,
+    mounts: Vec<::models::MountPoint>,
 }
 
 impl ContainerSummary {
@@ -61,7 +61,7 @@ impl ContainerSummary {
         status: String,
         host_config: ContainerHostConfig,
         network_settings: ContainerNetworkSettings,
-        mounts: Vec<::models::Mount>,
+        mounts: Vec<::models::MountPoint>,
     ) -> Self {
         ContainerSummary {
             id,
@@ -89,7 +89,7 @@ impl ContainerSummary {
         self.id = id;
         self
     }
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -111,7 +111,7 @@ impl ContainerSummary {
         self.image = image;
         self
     }
-    pub fn image(&self) -> &String {
+    pub fn image(&self) -> &str {
         &self.image
     }
 
@@ -122,7 +122,7 @@ impl ContainerSummary {
         self.image_id = image_id;
         self
     }
-    pub fn image_id(&self) -> &String {
+    pub fn image_id(&self) -> &str {
         &self.image_id
     }
 
@@ -133,7 +133,7 @@ impl ContainerSummary {
         self.command = command;
         self
     }
-    pub fn command(&self) -> &String {
+    pub fn command(&self) -> &str {
         &self.command
     }
 
@@ -199,7 +199,7 @@ impl ContainerSummary {
         self.state = state;
         self
     }
-    pub fn state(&self) -> &String {
+    pub fn state(&self) -> &str {
         &self.state
     }
 
@@ -210,7 +210,7 @@ impl ContainerSummary {
         self.status = status;
         self
     }
-    pub fn status(&self) -> &String {
+    pub fn status(&self) -> &str {
         &self.status
     }
 
@@ -236,14 +236,14 @@ impl ContainerSummary {
         &self.network_settings
     }
 
-    pub fn set_mounts(&mut self, mounts: Vec<::models::Mount>) {
+    pub fn set_mounts(&mut self, mounts: Vec<::models::MountPoint>) {
         self.mounts = mounts;
     }
-    pub fn with_mounts(mut self, mounts: Vec<::models::Mount>) -> Self {
+    pub fn with_mounts(mut self, mounts: Vec<::models::MountPoint>) -> Self {
         self.mounts = mounts;
         self
     }
-    pub fn mounts(&self) -> &[::models::Mount] {
+    pub fn mounts(&self) -> &[::models::MountPoint] {
         &self.mounts
     }
 }
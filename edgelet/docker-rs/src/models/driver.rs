@@ -41,7 +41,7 @@ impl Driver {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
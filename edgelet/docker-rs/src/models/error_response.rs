@@ -35,7 +35,7 @@ impl ErrorResponse {
         self
     }
 
-    pub fn message(&self) -> &String {
+    pub fn message(&self) -> &str {
         &self.message
     }
 }
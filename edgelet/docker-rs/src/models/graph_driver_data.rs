@@ -39,7 +39,7 @@ impl GraphDriverData {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -35,7 +35,7 @@ impl IdResponse {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 }
@@ -110,7 +110,7 @@ impl Image {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -157,7 +157,7 @@ impl Image {
         self
     }
 
-    pub fn parent(&self) -> &String {
+    pub fn parent(&self) -> &str {
         &self.parent
     }
 
@@ -170,7 +170,7 @@ impl Image {
         self
     }
 
-    pub fn comment(&self) -> &String {
+    pub fn comment(&self) -> &str {
         &self.comment
     }
 
@@ -183,7 +183,7 @@ impl Image {
         self
     }
 
-    pub fn created(&self) -> &String {
+    pub fn created(&self) -> &str {
         &self.created
     }
 
@@ -196,7 +196,7 @@ impl Image {
         self
     }
 
-    pub fn container(&self) -> &String {
+    pub fn container(&self) -> &str {
         &self.container
     }
 
@@ -226,7 +226,7 @@ impl Image {
         self
     }
 
-    pub fn docker_version(&self) -> &String {
+    pub fn docker_version(&self) -> &str {
         &self.docker_version
     }
 
@@ -239,7 +239,7 @@ impl Image {
         self
     }
 
-    pub fn author(&self) -> &String {
+    pub fn author(&self) -> &str {
         &self.author
     }
 
@@ -269,7 +269,7 @@ impl Image {
         self
     }
 
-    pub fn architecture(&self) -> &String {
+    pub fn architecture(&self) -> &str {
         &self.architecture
     }
 
@@ -282,7 +282,7 @@ impl Image {
         self
     }
 
-    pub fn os(&self) -> &String {
+    pub fn os(&self) -> &str {
         &self.os
     }
 
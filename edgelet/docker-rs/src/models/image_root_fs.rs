@@ -42,7 +42,7 @@ impl ImageRootFs {
         self
     }
 
-    pub fn _type(&self) -> &String {
+    pub fn _type(&self) -> &str {
         &self._type
     }
 
@@ -71,7 +71,7 @@ impl ImageSummary {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -84,7 +84,7 @@ impl ImageSummary {
         self
     }
 
-    pub fn parent_id(&self) -> &String {
+    pub fn parent_id(&self) -> &str {
         &self.parent_id
     }
 
@@ -41,7 +41,7 @@ impl InlineResponse20010 {
         self
     }
 
-    pub fn status(&self) -> &String {
+    pub fn status(&self) -> &str {
         &self.status
     }
 
@@ -38,7 +38,7 @@ impl InlineResponse2002 {
         self
     }
 
-    pub fn path(&self) -> &String {
+    pub fn path(&self) -> &str {
         &self.path
     }
 
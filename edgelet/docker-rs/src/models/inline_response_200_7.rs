@@ -55,7 +55,7 @@ impl InlineResponse2007 {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -81,7 +81,7 @@ impl InlineResponse2007 {
         self
     }
 
-    pub fn created_by(&self) -> &String {
+    pub fn created_by(&self) -> &str {
         &self.created_by
     }
 
@@ -120,7 +120,7 @@ impl InlineResponse2007 {
         self
     }
 
-    pub fn comment(&self) -> &String {
+    pub fn comment(&self) -> &str {
         &self.comment
     }
 }
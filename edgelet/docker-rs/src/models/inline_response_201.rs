@@ -38,7 +38,7 @@ impl InlineResponse201 {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -79,7 +79,7 @@ impl NetworkConfig {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
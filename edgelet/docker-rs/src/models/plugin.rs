@@ -78,7 +78,7 @@ impl Plugin {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
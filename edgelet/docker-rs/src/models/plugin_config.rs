@@ -116,7 +116,7 @@ impl PluginConfig {
         self
     }
 
-    pub fn description(&self) -> &String {
+    pub fn description(&self) -> &str {
         &self.description
     }
 
@@ -129,7 +129,7 @@ impl PluginConfig {
         self
     }
 
-    pub fn documentation(&self) -> &String {
+    pub fn documentation(&self) -> &str {
         &self.documentation
     }
 
@@ -168,7 +168,7 @@ impl PluginConfig {
         self
     }
 
-    pub fn work_dir(&self) -> &String {
+    pub fn work_dir(&self) -> &str {
         &self.work_dir
     }
 
@@ -224,7 +224,7 @@ impl PluginConfig {
         self
     }
 
-    pub fn propagated_mount(&self) -> &String {
+    pub fn propagated_mount(&self) -> &str {
         &self.propagated_mount
     }
 
@@ -47,7 +47,7 @@ impl PluginConfigArgs {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -60,7 +60,7 @@ impl PluginConfigArgs {
         self
     }
 
-    pub fn description(&self) -> &String {
+    pub fn description(&self) -> &str {
         &self.description
     }
 
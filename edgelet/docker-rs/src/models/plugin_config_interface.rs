@@ -52,7 +52,7 @@ impl PluginConfigInterface {
         self
     }
 
-    pub fn socket(&self) -> &String {
+    pub fn socket(&self) -> &str {
         &self.socket
     }
 }
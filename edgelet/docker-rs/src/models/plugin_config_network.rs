@@ -31,7 +31,7 @@ impl PluginConfigNetwork {
         self
     }
 
-    pub fn _type(&self) -> &String {
+    pub fn _type(&self) -> &str {
         &self._type
     }
 }
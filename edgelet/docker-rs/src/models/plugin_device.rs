@@ -42,7 +42,7 @@ impl PluginDevice {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -55,7 +55,7 @@ impl PluginDevice {
         self
     }
 
-    pub fn description(&self) -> &String {
+    pub fn description(&self) -> &str {
         &self.description
     }
 
@@ -81,7 +81,7 @@ impl PluginDevice {
         self
     }
 
-    pub fn path(&self) -> &String {
+    pub fn path(&self) -> &str {
         &self.path
     }
 }
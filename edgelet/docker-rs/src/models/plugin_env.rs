@@ -42,7 +42,7 @@ impl PluginEnv {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -55,7 +55,7 @@ impl PluginEnv {
         self
     }
 
-    pub fn description(&self) -> &String {
+    pub fn description(&self) -> &str {
         &self.description
     }
 
@@ -81,7 +81,7 @@ impl PluginEnv {
         self
     }
 
-    pub fn value(&self) -> &String {
+    pub fn value(&self) -> &str {
         &self.value
     }
 }
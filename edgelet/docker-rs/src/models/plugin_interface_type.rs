@@ -39,7 +39,7 @@ impl PluginInterfaceType {
         self
     }
 
-    pub fn prefix(&self) -> &String {
+    pub fn prefix(&self) -> &str {
         &self.prefix
     }
 
@@ -52,7 +52,7 @@ impl PluginInterfaceType {
         self
     }
 
-    pub fn capability(&self) -> &String {
+    pub fn capability(&self) -> &str {
         &self.capability
     }
 
@@ -65,7 +65,7 @@ impl PluginInterfaceType {
         self
     }
 
-    pub fn version(&self) -> &String {
+    pub fn version(&self) -> &str {
         &self.version
     }
 }
@@ -59,7 +59,7 @@ impl PluginMount {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -72,7 +72,7 @@ impl PluginMount {
         self
     }
 
-    pub fn description(&self) -> &String {
+    pub fn description(&self) -> &str {
         &self.description
     }
 
@@ -98,7 +98,7 @@ impl PluginMount {
         self
     }
 
-    pub fn source(&self) -> &String {
+    pub fn source(&self) -> &str {
         &self.source
     }
 
@@ -111,7 +111,7 @@ impl PluginMount {
         self
     }
 
-    pub fn destination(&self) -> &String {
+    pub fn destination(&self) -> &str {
         &self.destination
     }
 
@@ -124,7 +124,7 @@ impl PluginMount {
         self
     }
 
-    pub fn _type(&self) -> &String {
+    pub fn _type(&self) -> &str {
         &self._type
     }
 
@@ -97,7 +97,7 @@ impl Port {
         self
     }
 
-    pub fn _type(&self) -> &String {
+    pub fn _type(&self) -> &str {
         &self._type
     }
 }
@@ -78,7 +78,7 @@ impl Volume {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -91,7 +91,7 @@ impl Volume {
         self
     }
 
-    pub fn driver(&self) -> &String {
+    pub fn driver(&self) -> &str {
         &self.driver
     }
 
@@ -104,7 +104,7 @@ impl Volume {
         self
     }
 
-    pub fn mountpoint(&self) -> &String {
+    pub fn mountpoint(&self) -> &str {
         &self.mountpoint
     }
 
@@ -164,7 +164,7 @@ impl Volume {
         self
     }
 
-    pub fn scope(&self) -> &String {
+    pub fn scope(&self) -> &str {
         &self.scope
     }
 
@@ -30,6 +30,8 @@ extern crate url;
 
 extern crate edgelet_core;
 extern crate edgelet_http;
+#[cfg(test)]
+extern crate edgelet_test_utils;
 
 pub mod error;
 mod model;
@@ -255,9 +255,9 @@ pub struct DeviceRegistrationResult {
     /// TPM registration result.
     #[serde(rename = "tpm", skip_serializing_if = "Option::is_none")]
     tpm: Option<TpmRegistrationResult>,
-    /// X509 registration result.
-    #[serde(skip_deserializing)]
-    x509: Option<String>,
+    /// X509 registration result, which is passed on as it is.
+    #[serde(rename = "x509", skip_serializing_if = "Option::is_none")]
+    x509: Option<Value>,
     /// Registration ID.
     #[serde(rename = "registrationId")]
     registration_id: String,
@@ -279,6 +279,10 @@ pub struct DeviceRegistrationResult {
     /// Enrollment status.
     #[serde(rename = "status")]
     status: String,
+    /// How the device came to be assigned, like initialAssignment or
+    /// deviceDataMigrated.
+    #[serde(rename = "substatus", skip_serializing_if = "Option::is_none")]
+    substatus: Option<String>,
     /// Error code.
     #[serde(
         rename = "errorCode",
@@ -313,6 +317,7 @@ impl DeviceRegistrationResult {
             assigned_hub: None,
             device_id: None,
             status,
+            substatus: None,
             error_code: None,
             error_message: None,
             last_updated_date_time_utc: None,
@@ -414,6 +419,23 @@ impl DeviceRegistrationResult {
         &self.status
     }
 
+    pub fn set_substatus(&mut self, substatus: String) {
+        self.substatus = Some(substatus);
+    }
+
+    pub fn with_substatus(mut self, substatus: String) -> Self {
+        self.substatus = Some(substatus);
+        self
+    }
+
+    pub fn substatus(&self) -> Option<&str> {
+        self.substatus.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_substatus(&mut self) {
+        self.substatus = None;
+    }
+
     pub fn set_error_code(&mut self, error_code: i32) {
         self.error_code = Some(error_code);
     }
@@ -482,3 +504,80 @@ impl DeviceRegistrationResult {
         self.etag = None;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use edgelet_test_utils::json::{assert_round_trip, UNKNOWN_FIELD};
+    use serde_json;
+
+    use super::*;
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn device_registration_round_trips() {
+        assert_round_trip::<DeviceRegistration>(&json(
+            r#"{
+                "registrationId": "edge-device-01",
+                "tpm": { "endorsementKey": "ek", "storageRootKey": "srk" }
+            }"#,
+        ));
+    }
+
+    #[test]
+    fn tpm_attestation_round_trips() {
+        assert_round_trip::<TpmAttestation>(&json(
+            r#"{ "endorsementKey": "ek", "storageRootKey": "srk" }"#,
+        ));
+    }
+
+    #[test]
+    fn tpm_registration_result_round_trips() {
+        assert_round_trip::<TpmRegistrationResult>(&json(r#"{ "authenticationKey": "key" }"#));
+    }
+
+    fn device_registration_result() -> &'static str {
+        r#"{
+            "tpm": { "authenticationKey": "key" },
+            "x509": {
+                "certificateInfo": { "subjectName": "CN=edge-device-01" },
+                "enrollmentGroupId": "group"
+            },
+            "registrationId": "edge-device-01",
+            "createdDateTimeUtc": "2018-10-01T00:00:00Z",
+            "assignedHub": "hub.azure-devices.net",
+            "deviceId": "edge-device-01",
+            "status": "assigned",
+            "substatus": "initialAssignment",
+            "errorCode": 400,
+            "errorMessage": "message",
+            "lastUpdatedDateTimeUtc": "2018-10-01T00:00:01Z",
+            "etag": "\"etag\""
+        }"#
+    }
+
+    #[test]
+    fn device_registration_result_round_trips() {
+        let output = assert_round_trip::<DeviceRegistrationResult>(&json(
+            device_registration_result(),
+        ));
+
+        // The x509 result is passed on whole, later fields included.
+        assert!(output["x509"].get(UNKNOWN_FIELD).is_some());
+    }
+
+    #[test]
+    fn registration_operation_status_round_trips() {
+        let status = format!(
+            r#"{{
+                "operationId": "operation",
+                "status": "assigned",
+                "registrationState": {}
+            }}"#,
+            device_registration_result()
+        );
+        assert_round_trip::<RegistrationOperationStatus>(&json(&status));
+    }
+}
@@ -7,15 +7,15 @@ use base64;
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use futures::future::Either;
-use futures::{future, Future};
+use futures::{future, stream, Future};
 use hyper::{Method, StatusCode};
 use percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 use serde_json;
 use tokio::prelude::*;
-use tokio::timer::Interval;
 use url::form_urlencoded::Serializer as UrlSerializer;
 
 use edgelet_core::crypto::{Activate, KeyIdentity, KeyStore, Sign, Signature, SignatureAlgorithm};
+use edgelet_core::{Clock, SystemClock};
 use edgelet_http::client::{Client, ClientImpl, TokenSource};
 use edgelet_http::ErrorKind as HttpErrorKind;
 use error::{Error, ErrorKind};
@@ -86,6 +86,18 @@ where
     }
 }
 
+/// How the device proves its identity to DPS.
+#[derive(Clone)]
+enum Attestation {
+    /// With the endorsement and storage root keys of its TPM. DPS answers
+    /// with a challenge that only the TPM can decrypt, and assigns the device
+    /// a new key in the end.
+    Tpm { ek: Bytes, srk: Bytes },
+    /// With the device key already in the key store, which for a device in
+    /// an enrollment group is derived from the key of the group.
+    SymmetricKey,
+}
+
 pub struct DpsClient<C, K, A>
 where
     C: ClientImpl,
@@ -95,9 +107,9 @@ where
     client: Arc<RwLock<Client<C, DpsTokenSource<K>>>>,
     scope_id: String,
     registration_id: String,
-    tpm_ek: Bytes,
-    tpm_srk: Bytes,
+    attestation: Attestation,
     key_store: A,
+    clock: Arc<Clock>,
 }
 
 impl<C, K, A> DpsClient<C, K, A>
@@ -118,12 +130,39 @@ where
             client: Arc::new(RwLock::new(client)),
             scope_id,
             registration_id,
-            tpm_ek,
-            tpm_srk,
+            attestation: Attestation::Tpm {
+                ek: tpm_ek,
+                srk: tpm_srk,
+            },
+            key_store,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Registers a device that attests with a symmetric key, which has to be
+    /// in `key_store` as the primary device key.
+    pub fn with_symmetric_key(
+        client: Client<C, DpsTokenSource<K>>,
+        scope_id: String,
+        registration_id: String,
+        key_store: A,
+    ) -> Result<Self, Error> {
+        Ok(DpsClient {
+            client: Arc::new(RwLock::new(client)),
+            scope_id,
+            registration_id,
+            attestation: Attestation::SymmetricKey,
             key_store,
+            clock: Arc::new(SystemClock),
         })
     }
 
+    /// Sets the clock DPS is polled for the registration status by.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     fn get_tpm_challenge_key(body: &str, key_store: &mut A) -> Result<K, Error> {
         serde_json::from_str(body).map_err(Error::from).and_then(
             |tpm_challenge: TpmRegistrationResult| {
@@ -250,15 +289,16 @@ where
         operation_id: String,
         key: K,
         retry_count: u64,
+        clock: Arc<Clock>,
     ) -> Box<Future<Item = Option<DeviceRegistrationResult>, Error = Error> + Send> {
         debug!(
             "DPS registration result will retry {} times every {} seconds",
             retry_count, DPS_ASSIGNMENT_RETRY_INTERVAL_SECS
         );
-        let chain = Interval::new(
-            Instant::now(),
-            Duration::from_secs(DPS_ASSIGNMENT_RETRY_INTERVAL_SECS),
-        ).take(retry_count)
+        let interval = Duration::from_secs(DPS_ASSIGNMENT_RETRY_INTERVAL_SECS);
+        let chain = stream::unfold(clock.instant(), move |next: Instant| {
+            Some(clock.delay(next).map(move |()| (next, next + interval)))
+        }).take(retry_count)
         .map_err(|_| Error::from(ErrorKind::TimerError))
         .and_then(move |_instant: Instant| {
             debug!("Ask DPS for registration status");
@@ -350,6 +390,24 @@ where
         Box::new(r)
     }
 
+    /// Registers with the device key, which DPS accepts without a
+    /// challenge.
+    fn register_with_symmetric_key(
+        client: &Arc<RwLock<Client<C, DpsTokenSource<K>>>>,
+        scope_id: &str,
+        registration_id: &str,
+        key_store: &A,
+    ) -> Box<Future<Item = Option<RegistrationOperationStatus>, Error = Error> + Send> {
+        let registration =
+            DeviceRegistration::new().with_registration_id(registration_id.to_string());
+        match key_store.get(&KeyIdentity::Device, "primary") {
+            Ok(key) => {
+                Self::get_operation_id(client, scope_id, registration_id, &registration, key)
+            }
+            Err(err) => Box::new(future::err(Error::from(err))),
+        }
+    }
+
     pub fn register(&self) -> Box<Future<Item = (String, String), Error = Error> + Send> {
         let key_store = self.key_store.clone();
         let mut key_store_status = self.key_store.clone();
@@ -358,20 +416,29 @@ where
         let scope_id_status = self.scope_id.clone();
         let registration_id = self.registration_id.clone();
         let registration_id_status = self.registration_id.clone();
-        let tpm_ek = self.tpm_ek.clone();
-        let tpm_srk = self.tpm_srk.clone();
+        let attestation = self.attestation.clone();
+        let clock = self.clock.clone();
         info!(
             "Starting DPS registration with scope_id \"{}\", registration_id \"{}\"",
             scope_id, registration_id,
         );
-        let r = Self::register_with_auth(
-            &self.client,
-            scope_id,
-            registration_id,
-            &tpm_ek,
-            &tpm_srk,
-            &self.key_store,
-        ).and_then(
+        let registration = match self.attestation {
+            Attestation::Tpm { ref ek, ref srk } => Self::register_with_auth(
+                &self.client,
+                scope_id,
+                registration_id,
+                ek,
+                srk,
+                &self.key_store,
+            ),
+            Attestation::SymmetricKey => Self::register_with_symmetric_key(
+                &self.client,
+                &scope_id,
+                &registration_id,
+                &self.key_store,
+            ),
+        };
+        let r = registration.and_then(
             move |operation_status: Option<RegistrationOperationStatus>| match key_store
                 .get(&KeyIdentity::Device, "primary")
             {
@@ -387,6 +454,7 @@ where
                             s.operation_id().clone(),
                             k.clone(),
                             retry_count,
+                            clock,
                         ))
                     },
                 ),
@@ -396,6 +464,10 @@ where
             operation_status
                 .ok_or_else(|| Error::from(ErrorKind::NotAssigned))
                 .and_then(|s| -> Result<(String, String), Error> {
+                    // The device keeps the key it attested with.
+                    if let Attestation::SymmetricKey = attestation {
+                        return get_device_info(&s);
+                    }
                     let tpm_result_inner = s.clone();
                     let tpm_result = s.tpm();
                     tpm_result
@@ -516,6 +588,72 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn server_register_with_symmetric_key_success() {
+        let handler = |req: Request<Body>| {
+            // The device signs every request with its key, without a challenge.
+            assert!(req.headers().get(hyper::header::AUTHORIZATION).is_some());
+            let (parts, body) = req.into_parts();
+            let response = if parts.method == Method::PUT {
+                assert_eq!(
+                    "/scope/registrations/reg/register",
+                    parts.uri.path()
+                );
+                let body = body.concat2().wait().unwrap();
+                let registration: DeviceRegistration = serde_json::from_slice(&body).unwrap();
+                assert_eq!(Some("reg"), registration.registration_id());
+                assert!(registration.tpm().is_none());
+                RegistrationOperationStatus::new("operation".to_string())
+                    .with_status("assigning".to_string())
+            } else {
+                assert_eq!(
+                    "/scope/registrations/reg/operations/operation",
+                    parts.uri.path()
+                );
+                RegistrationOperationStatus::new("operation".to_string())
+                    .with_status("assigned".to_string())
+                    .with_registration_state(
+                        DeviceRegistrationResult::new("reg".to_string(), "assigned".to_string())
+                            .with_device_id("device".to_string())
+                            .with_assigned_hub("hub.azure-devices.net".to_string()),
+                    )
+            };
+            future::ok(Response::new(
+                serde_json::to_string(&response).unwrap().into(),
+            ))
+        };
+        let client = Client::new(
+            handler,
+            None,
+            "2017-11-15",
+            Url::parse("https://global.azure-devices-provisioning.net/").unwrap(),
+        ).unwrap();
+        let mut key_store = MemoryKeyStore::new();
+        key_store
+            .activate_identity_key(KeyIdentity::Device, "primary".to_string(), "key")
+            .unwrap();
+        let dps = DpsClient::with_symmetric_key(
+            client,
+            "scope".to_string(),
+            "reg".to_string(),
+            key_store.clone(),
+        ).unwrap();
+        let (device_id, hub) = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(dps.register())
+            .unwrap();
+        assert_eq!("device", device_id);
+        assert_eq!("hub.azure-devices.net", hub);
+        // The device keeps the key it registered with.
+        assert_eq!(
+            b"key",
+            key_store
+                .get(&KeyIdentity::Device, "primary")
+                .unwrap()
+                .as_ref()
+        );
+    }
+
     #[test]
     fn server_register_gets_404_fails() {
         let handler = |_req: Request<Body>| {
@@ -661,6 +799,7 @@ mod tests {
             "operation".to_string(),
             key,
             3,
+            Arc::new(SystemClock),
         );
         let task = dps_operation.map(|result| {
             match result {
@@ -705,6 +844,7 @@ mod tests {
             "operation".to_string(),
             key,
             3,
+            Arc::new(SystemClock),
         );
         let task = dps_operation.map(|result| {
             match result {
@@ -1,5 +1,8 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::sync::Arc;
+
+use epoch::ModuleEpochs;
 use error::Error;
 use futures::future::Either;
 use futures::{future, Future, Stream};
@@ -12,22 +15,113 @@ pub enum Policy {
     Module(&'static str),
 }
 
-pub struct Authorization<M>
+/// Maps the process id of a caller to the name of the module that owns the
+/// process, or `None` if the process doesn't belong to any module.
+pub trait PidResolver {
+    type ResolveFuture: Future<Item = Option<String>, Error = Error> + Send;
+    type ProcessesFuture: Future<Item = Vec<Pid>, Error = Error> + Send;
+
+    fn resolve(&self, pid: Pid) -> Self::ResolveFuture;
+
+    /// The processes running in the module right now, which a caller that
+    /// `resolve` didn't find in the module is looked for in before it is
+    /// rejected, in case the module just restarted.
+    fn processes(&self, name: &str) -> Self::ProcessesFuture;
+
+    /// Where resolved processes are remembered for as long as their modules
+    /// don't change. Without it every caller is resolved.
+    fn epochs(&self) -> Option<ModuleEpochs> {
+        None
+    }
+}
+
+impl<M> PidResolver for M
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: Into<Error>,
+{
+    type ResolveFuture = Box<Future<Item = Option<String>, Error = Error> + Send>;
+    type ProcessesFuture = Box<Future<Item = Vec<Pid>, Error = Error> + Send>;
+
+    /// Matches the process against the main process of each module first,
+    /// and then against every process running in the modules, so that the
+    /// processes a module starts are told to belong to it too.
+    fn resolve(&self, pid: Pid) -> Self::ResolveFuture {
+        let runtime = self.clone();
+        let resolved = self
+            .list_with_details()
+            .map_err(|e| e.into())
+            .collect()
+            .and_then(move |modules| {
+                let running: Vec<(String, Pid)> = modules
+                    .iter()
+                    .map(|(m, rs)| (m.name().to_string(), rs.pid()))
+                    .filter(|(_, main)| match *main {
+                        Pid::Value(_) => true,
+                        Pid::None | Pid::Any => false,
+                    }).collect();
+                if let Some((name, _)) = running.iter().find(|(_, main)| *main == pid) {
+                    return Either::A(future::ok(Some(name.clone())));
+                }
+
+                let tops = running.into_iter().map(move |(name, _)| {
+                    runtime.top(&name).then(move |processes| match processes {
+                        Ok(processes) => Ok::<_, Error>((name, processes)),
+                        Err(err) => {
+                            let err: Error = err.into();
+                            debug!("Could not list the processes of module {}: {}", name, err);
+                            Ok((name, vec![]))
+                        }
+                    })
+                });
+                Either::B(future::join_all(tops).map(move |modules| {
+                    modules
+                        .into_iter()
+                        .find(|(_, processes)| processes.contains(&pid))
+                        .map(|(name, _)| name)
+                }))
+            });
+        Box::new(resolved)
+    }
+
+    fn processes(&self, name: &str) -> Self::ProcessesFuture {
+        Box::new(self.top(name).map_err(|e| e.into()))
+    }
+
+    fn epochs(&self) -> Option<ModuleEpochs> {
+        ModuleRuntime::epochs(self).cloned()
+    }
+}
+
+pub struct Authorization<R>
 where
-    M: 'static + ModuleRuntime,
+    R: 'static + PidResolver,
 {
-    runtime: M,
+    resolver: Arc<R>,
     policy: Policy,
+    allow_host_processes: bool,
+    epochs: Option<ModuleEpochs>,
 }
 
-impl<M> Authorization<M>
+impl<R> Authorization<R>
 where
-    M: 'static + ModuleRuntime,
-    M::Error: Into<Error>,
-    <M::Module as Module>::Error: Into<Error>,
+    R: 'static + PidResolver,
 {
-    pub fn new(runtime: M, policy: Policy) -> Self {
-        Authorization { runtime, policy }
+    pub fn new(resolver: R, policy: Policy) -> Self {
+        let epochs = resolver.epochs();
+        Authorization {
+            resolver: Arc::new(resolver),
+            policy,
+            allow_host_processes: false,
+            epochs,
+        }
+    }
+
+    /// Lets processes that don't belong to any module call on behalf of any
+    /// module. This is only meant to be used while bringing up a device.
+    pub fn with_allow_host_processes(mut self, allow_host_processes: bool) -> Self {
+        self.allow_host_processes = allow_host_processes;
+        self
     }
 
     pub fn authorize(
@@ -52,26 +146,25 @@ where
         name: Option<String>,
         pid: Pid,
     ) -> impl Future<Item = bool, Error = Error> {
-        name.map_or_else(
-            || Either::A(future::ok(false)),
-            |name| Either::B(
-                self.runtime
-                    .list_with_details()
-                    .map_err(|e| e.into())
-                    .filter_map(move |(m, rs)| if m.name() == name { Some(rs) } else { None })
-                    .into_future()
-                    .then(move |result| match result {
-                        Ok((Some(rs), _)) => {
-                            let authorized = rs.pid() == pid;
-                            if !authorized {
-                                info!("Request not authorized - expected caller pid: {}, actual caller pid: {}", rs.pid(), pid);
-                            }
-                            Ok(authorized)
-                        },
-                        Ok((None, _)) => Ok(false),
-                        Err((err, _)) => Err(err),
-                    })),
-        )
+        let allow_host_processes = self.allow_host_processes;
+        match (name, pid) {
+            (None, _) | (_, Pid::None) => Either::A(future::ok(false)),
+            // the caller's process can't be identified on this transport
+            (Some(_), Pid::Any) => Either::A(future::ok(true)),
+            (Some(name), Pid::Value(value)) => {
+                let resolver = self.resolver.clone();
+                let epochs = self.epochs.clone();
+                let owner = match self.epochs.as_ref().and_then(|epochs| epochs.owner(pid)) {
+                    Some(owner) => Either::A(future::ok(Some(owner))),
+                    None => Either::B(self.resolve(pid)),
+                };
+                Either::B(owner.and_then(move |owner| match owner {
+                    Some(ref owner) if *owner == name => Either::A(future::ok(true)),
+                    None if allow_host_processes => Either::A(future::ok(true)),
+                    owner => Either::B(refresh(&*resolver, epochs, name, value, owner)),
+                }))
+            }
+        }
     }
 
     fn auth_module(
@@ -81,10 +174,69 @@ where
     ) -> impl Future<Item = bool, Error = Error> {
         self.auth_caller(Some(expected_name.to_string()), pid)
     }
+
+    /// Resolves the caller's process and remembers the module it belongs to.
+    fn resolve(&self, pid: Pid) -> impl Future<Item = Option<String>, Error = Error> {
+        let epochs = self.epochs.clone();
+        let stamp = epochs.as_ref().map_or(0, ModuleEpochs::stamp);
+        self.resolver.resolve(pid).map(move |owner| {
+            if let (Some(epochs), Some(owner)) = (epochs, owner.as_ref()) {
+                epochs.remember(pid, owner, stamp);
+            }
+            owner
+        })
+    }
+}
+
+/// Looks for the caller's process among those running in the module before
+/// rejecting it, since the module may have restarted since the process was
+/// resolved. Not finding the module's processes rejects the caller.
+fn refresh<R>(
+    resolver: &R,
+    epochs: Option<ModuleEpochs>,
+    name: String,
+    pid: i32,
+    owner: Option<String>,
+) -> impl Future<Item = bool, Error = Error>
+where
+    R: PidResolver,
+{
+    let stamp = epochs.as_ref().map_or(0, ModuleEpochs::stamp);
+    resolver.processes(&name).then(move |processes| {
+        let authorized = match processes {
+            Ok(processes) => processes.iter().any(|p| match *p {
+                Pid::Value(p) => p == pid,
+                Pid::None | Pid::Any => false,
+            }),
+            Err(err) => {
+                debug!("Could not list the processes of module {}: {}", name, err);
+                false
+            }
+        };
+        if authorized {
+            info!("Caller pid {} was found running in module {}", pid, name);
+            if let Some(epochs) = epochs {
+                epochs.remember(Pid::Value(pid), &name, stamp);
+            }
+        } else {
+            match owner {
+                Some(owner) => info!(
+                    "Request not authorized - caller pid {} belongs to module {}, not {}",
+                    pid, owner, name
+                ),
+                None => info!(
+                    "Request not authorized - caller pid {} does not belong to any module",
+                    pid
+                ),
+            }
+        }
+        Ok(authorized)
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
     use std::time::Duration;
 
     use super::*;
@@ -94,9 +246,10 @@ mod tests {
     use futures::stream::Empty;
     use futures::{future, stream};
     use module::{
-        LogOptions, Module, ModuleRegistry, ModuleRuntimeState, ModuleSpec,
-        SystemInfo as CoreSystemInfo,
+        IntegrityReport, LogOptions, Module, ModuleInspection, ModuleRegistry, ModuleRuntimeState,
+        ModuleSpec, SystemInfo as CoreSystemInfo,
     };
+    use shutdown::StopAllOptions;
 
     #[test]
     fn should_authorize_anonymous() {
@@ -233,6 +386,285 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn should_reject_host_process_by_default() {
+        let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
+        let auth = Authorization::new(runtime, Policy::Caller);
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(1))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_authorize_host_process_when_allowed() {
+        let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
+        let auth = Authorization::new(runtime, Policy::Caller).with_allow_host_processes(true);
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(1))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_other_module_when_host_processes_allowed() {
+        let runtime = TestModuleList::new(vec![
+            TestModule::new("xyz", 987),
+            TestModule::new("abc", 123),
+        ]);
+        let auth = Authorization::new(runtime, Policy::Caller).with_allow_host_processes(true);
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(987))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_resolve_child_processes_of_modules() {
+        let runtime = TestModuleList::new(vec![
+            TestModule::new("xyz", 987).with_children(vec![988]),
+            TestModule::new("abc", 123),
+        ]);
+        let auth = Authorization::new(runtime, Policy::Caller).with_allow_host_processes(true);
+        assert_eq!(
+            true,
+            auth.authorize(Some("xyz".to_string()), Pid::Value(988))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(988))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_authorize_any_pid() {
+        let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
+        let auth = Authorization::new(runtime, Policy::Caller);
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Any)
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_no_pid() {
+        let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
+        let auth = Authorization::new(runtime, Policy::Caller).with_allow_host_processes(true);
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::None)
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_use_resolver_for_child_processes() {
+        let resolver = TestResolver(vec![(123, "abc"), (124, "abc"), (987, "xyz")]);
+        let auth = Authorization::new(resolver, Policy::Caller);
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(124))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(987))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_reused_pid_after_restart() {
+        let resolver = RestartingResolver::new(vec![(123, "abc"), (987, "xyz")]);
+        let auth = Authorization::new(resolver.clone(), Policy::Caller);
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(123))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(123))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(1, resolver.resolved());
+
+        // abc restarts as 456, and its old process id goes to xyz.
+        resolver.restart(vec![(456, "abc"), (123, "xyz")], "abc");
+
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(123))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            true,
+            auth.authorize(Some("xyz".to_string()), Pid::Value(123))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(456))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(3, resolver.resolved());
+    }
+
+    #[test]
+    fn should_authorize_caller_found_in_restarted_module() {
+        let resolver = RestartingResolver::new(vec![(123, "abc")]);
+        let auth = Authorization::new(resolver.clone(), Policy::Caller);
+
+        // The module restarted, but the modules were listed before it did.
+        resolver.restart_unlisted(vec![456], "abc");
+
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(456))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(
+            true,
+            auth.authorize(Some("abc".to_string()), Pid::Value(456))
+                .wait()
+                .unwrap()
+        );
+        assert_eq!(1, resolver.resolved());
+        assert_eq!(
+            false,
+            auth.authorize(Some("abc".to_string()), Pid::Value(789))
+                .wait()
+                .unwrap()
+        );
+    }
+
+    struct TestResolver(Vec<(i32, &'static str)>);
+
+    impl PidResolver for TestResolver {
+        type ResolveFuture = FutureResult<Option<String>, Error>;
+        type ProcessesFuture = FutureResult<Vec<Pid>, Error>;
+
+        fn resolve(&self, pid: Pid) -> Self::ResolveFuture {
+            future::ok(
+                self.0
+                    .iter()
+                    .find(|&&(p, _)| Pid::Value(p) == pid)
+                    .map(|&(_, name)| name.to_string()),
+            )
+        }
+
+        fn processes(&self, name: &str) -> Self::ProcessesFuture {
+            future::ok(
+                self.0
+                    .iter()
+                    .filter(|&&(_, n)| n == name)
+                    .map(|&(p, _)| Pid::Value(p))
+                    .collect(),
+            )
+        }
+    }
+
+    #[derive(Default)]
+    struct Processes {
+        /// The processes the modules are listed with.
+        listed: Vec<(i32, &'static str)>,
+        /// The processes running in the modules.
+        running: Vec<(i32, &'static str)>,
+        resolved: usize,
+    }
+
+    /// Resolves processes like a runtime whose modules restart.
+    #[derive(Clone, Default)]
+    struct RestartingResolver {
+        processes: Arc<Mutex<Processes>>,
+        epochs: ModuleEpochs,
+    }
+
+    impl RestartingResolver {
+        fn new(processes: Vec<(i32, &'static str)>) -> Self {
+            let resolver = RestartingResolver::default();
+            {
+                let mut current = resolver.processes.lock().unwrap();
+                current.listed = processes.clone();
+                current.running = processes;
+            }
+            resolver
+        }
+
+        fn restart(&self, processes: Vec<(i32, &'static str)>, name: &str) {
+            let mut current = self.processes.lock().unwrap();
+            current.listed = processes.clone();
+            current.running = processes;
+            self.epochs.bump(name);
+        }
+
+        fn restart_unlisted(&self, pids: Vec<i32>, name: &'static str) {
+            let mut current = self.processes.lock().unwrap();
+            current.running.retain(|&(_, n)| n != name);
+            current.running.extend(pids.into_iter().map(|pid| (pid, name)));
+            self.epochs.bump(name);
+        }
+
+        fn resolved(&self) -> usize {
+            self.processes.lock().unwrap().resolved
+        }
+    }
+
+    impl PidResolver for RestartingResolver {
+        type ResolveFuture = FutureResult<Option<String>, Error>;
+        type ProcessesFuture = FutureResult<Vec<Pid>, Error>;
+
+        fn resolve(&self, pid: Pid) -> Self::ResolveFuture {
+            let mut processes = self.processes.lock().unwrap();
+            processes.resolved += 1;
+            future::ok(
+                processes
+                    .listed
+                    .iter()
+                    .find(|&&(p, _)| Pid::Value(p) == pid)
+                    .map(|&(_, name)| name.to_string()),
+            )
+        }
+
+        fn processes(&self, name: &str) -> Self::ProcessesFuture {
+            future::ok(
+                self.processes
+                    .lock()
+                    .unwrap()
+                    .running
+                    .iter()
+                    .filter(|&&(_, n)| n == name)
+                    .map(|&(p, _)| Pid::Value(p))
+                    .collect(),
+            )
+        }
+
+        fn epochs(&self) -> Option<ModuleEpochs> {
+            Some(self.epochs.clone())
+        }
+    }
+
     struct TestConfig {}
 
     #[derive(Clone, Copy)]
@@ -246,6 +678,7 @@ mod tests {
     struct TestModule {
         name: String,
         pid: i32,
+        children: Vec<i32>,
         behavior: TestModuleBehavior,
     }
 
@@ -255,6 +688,7 @@ mod tests {
             TestModule {
                 name,
                 pid,
+                children: vec![],
                 behavior: TestModuleBehavior::Default,
             }
         }
@@ -264,9 +698,15 @@ mod tests {
             TestModule {
                 name,
                 pid,
+                children: vec![],
                 behavior,
             }
         }
+
+        pub fn with_children(mut self, children: Vec<i32>) -> Self {
+            self.children = children;
+            self
+        }
     }
 
     macro_rules! notimpl_error {
@@ -366,8 +806,14 @@ mod tests {
         type RestartFuture = FutureResult<(), Self::Error>;
         type StartFuture = FutureResult<(), Self::Error>;
         type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
         type SystemInfoFuture = FutureResult<CoreSystemInfo, Self::Error>;
         type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = FutureResult<(), Self::Error>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
 
         fn init(&self) -> Self::InitFuture {
             notimpl_error!()
@@ -393,6 +839,10 @@ mod tests {
             notimpl_error!()
         }
 
+        fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
+            notimpl_error!()
+        }
+
         fn remove(&self, _id: &str) -> Self::RemoveFuture {
             notimpl_error!()
         }
@@ -427,5 +877,37 @@ mod tests {
         fn remove_all(&self) -> Self::RemoveAllFuture {
             notimpl_error!()
         }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            notimpl_error!()
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            notimpl_error!()
+        }
+
+        fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+            notimpl_error!()
+        }
+
+        fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            notimpl_error!()
+        }
+
+        fn top(&self, id: &str) -> Self::TopFuture {
+            future::ok(
+                self.modules
+                    .iter()
+                    .filter(|m| m.name == id)
+                    .filter(|m| match m.behavior {
+                        TestModuleBehavior::NoPid => false,
+                        _ => true,
+                    }).flat_map(|m| {
+                        let mut pids = vec![Pid::Value(m.pid)];
+                        pids.extend(m.children.iter().map(|&child| Pid::Value(child)));
+                        pids
+                    }).collect(),
+            )
+        }
     }
 }
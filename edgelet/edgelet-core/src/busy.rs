@@ -0,0 +1,65 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Whether the module runtime is creating or removing modules, as it does
+//! while a deployment is being applied. Housekeeping that would get in the
+//! way, like removing unused images, waits until it is done.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Clones share the flag. It stays set for as long as any of the guards it
+/// handed out is alive.
+#[derive(Clone, Debug, Default)]
+pub struct BusyFlag {
+    operations: Arc<AtomicUsize>,
+}
+
+impl BusyFlag {
+    pub fn new() -> Self {
+        BusyFlag::default()
+    }
+
+    /// Sets the flag until the returned guard is dropped.
+    pub fn set(&self) -> BusyGuard {
+        self.operations.fetch_add(1, Ordering::SeqCst);
+        BusyGuard {
+            operations: self.operations.clone(),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.operations.load(Ordering::SeqCst) > 0
+    }
+}
+
+#[must_use]
+#[derive(Debug)]
+pub struct BusyGuard {
+    operations: Arc<AtomicUsize>,
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.operations.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_is_set_while_any_guard_is_alive() {
+        let flag = BusyFlag::new();
+        assert!(!flag.is_set());
+
+        let first = flag.clone().set();
+        let second = flag.set();
+        assert!(flag.is_set());
+
+        drop(first);
+        assert!(flag.is_set());
+        drop(second);
+        assert!(!flag.is_set());
+    }
+}
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 /// Enumerator for `CERTIFICATE_TYPE`
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum CertificateType {
     Unknown,
     Client,
@@ -10,14 +10,14 @@ pub enum CertificateType {
 }
 
 /// Enumerator for `CERTIFICATE_ISSUER`
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum CertificateIssuer {
     DefaultCa,
     DeviceCa,
 }
 
 /// Globally supported properties of certificates in the Edge.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CertificateProperties {
     validity_in_secs: u64,
     common_name: String,
@@ -0,0 +1,39 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use futures::Future;
+use tokio::timer::{Delay, Error as TimerError};
+
+/// Where time comes from for anything that waits or checks for expiry, such
+/// as the watchdog's restart backoff, SAS token renewal, DPS polling and
+/// certificate validity. Tests substitute a clock they can advance by hand.
+pub trait Clock: Send + Sync {
+    /// The current wall clock time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The current monotonic time.
+    fn instant(&self) -> Instant;
+
+    /// A future that completes once the clock reaches `deadline`.
+    fn delay(&self, deadline: Instant) -> Box<Future<Item = (), Error = TimerError> + Send>;
+}
+
+/// The clock of the system, with delays run by the tokio timer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay(&self, deadline: Instant) -> Box<Future<Item = (), Error = TimerError> + Send> {
+        Box::new(Delay::new(deadline))
+    }
+}
@@ -11,7 +11,7 @@ use chrono::{DateTime, Utc};
 use consistenttime::ct_u8_slice_eq;
 use failure::ResultExt;
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use sha2::{Sha256, Sha512};
 
 use certificate_properties::CertificateProperties;
 use error::{Error, ErrorKind};
@@ -61,9 +61,19 @@ pub trait KeyStore {
     fn get(&self, identity: &KeyIdentity, key_name: &str) -> Result<Self::Key, Error>;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum SignatureAlgorithm {
     HMACSHA256,
+    HMACSHA512,
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureAlgorithm::HMACSHA256 => write!(f, "HMACSHA256"),
+            SignatureAlgorithm::HMACSHA512 => write!(f, "HMACSHA512"),
+        }
+    }
 }
 
 pub trait Signature {
@@ -133,6 +143,25 @@ pub trait Certificate {
     fn get_valid_to(&self) -> Result<DateTime<Utc>, Error>;
 }
 
+/// Issues certificates for keys held outside the HSM, such as those of leaf
+/// devices, from their certificate signing requests.
+pub trait SignCertificateRequest {
+    /// The PEM chain of the CA the requests are signed with, starting with
+    /// the CA itself.
+    fn issuer_chain(&self) -> Result<String, Error>;
+
+    /// When the CA the requests are signed with expires.
+    fn issuer_valid_to(&self) -> Result<DateTime<Utc>, Error>;
+
+    /// Issues a certificate for the subject and public key of the PEM
+    /// `request`, valid for `validity_in_secs` from now, and returns its PEM.
+    fn sign_certificate_request(
+        &self,
+        request: &[u8],
+        validity_in_secs: u64,
+    ) -> Result<String, Error>;
+}
+
 pub trait GetTrustBundle {
     type Certificate: Certificate;
 
@@ -148,6 +177,13 @@ pub trait MasterEncryptionKey {
     fn destroy_key(&self) -> Result<(), Error>;
 }
 
+/// Replaces the device CA the HSM generates for itself when no device CA is
+/// configured (quick start mode). Device CAs provided by the operator are
+/// never replaced.
+pub trait RenewQuickstartCa {
+    fn renew_quickstart_ca(&self) -> Result<(), Error>;
+}
+
 pub trait Encrypt {
     type Buffer: AsRef<[u8]>;
 
@@ -229,6 +265,14 @@ impl Sign for MemoryKey {
                 // the security provided by the `MacResult` (https://docs.rs/hmac/0.5.0/hmac/)
                 let code_bytes = result.code();
 
+                Digest::new(Bytes::from(code_bytes.as_ref()))
+            }
+            SignatureAlgorithm::HMACSHA512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new(&self.key).map_err(|_| ErrorKind::Sign(self.key.len()))?;
+                mac.input(data);
+                let code_bytes = mac.result().code();
+
                 Digest::new(Bytes::from(code_bytes.as_ref()))
             }
         };
@@ -437,6 +481,53 @@ mod tests {
         assert_ne!(expected, result_hmac256.as_bytes());
     }
 
+    #[test]
+    fn sha512_sign_test_positive() {
+        //Arrange
+        let in_memory_key = MemoryKey {
+            key: Bytes::from("key"),
+        };
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let signature_algorithm = SignatureAlgorithm::HMACSHA512;
+        //Act
+        let result_hmac512 = in_memory_key.sign(signature_algorithm, data).unwrap();
+
+        //Assert
+        let expected_bytes = [
+            0xb4, 0x2a, 0xf0, 0x90, 0x57, 0xba, 0xc1, 0xe2, 0xd4, 0x17, 0x08, 0xe4, 0x8a, 0x90,
+            0x2e, 0x09, 0xb5, 0xff, 0x7f, 0x12, 0xab, 0x42, 0x8a, 0x4f, 0xe8, 0x66, 0x53, 0xc7,
+            0x3d, 0xd2, 0x48, 0xfb, 0x82, 0xf9, 0x48, 0xa5, 0x49, 0xf7, 0xb7, 0x91, 0xa5, 0xb4,
+            0x19, 0x15, 0xee, 0x4d, 0x1e, 0xc3, 0x93, 0x53, 0x57, 0xe4, 0xe2, 0x31, 0x72, 0x50,
+            0xd0, 0x37, 0x2a, 0xfa, 0x2e, 0xbe, 0xeb, 0x3a,
+        ];
+        let expected_signature = Digest::new(Bytes::from(expected_bytes.as_ref()));
+
+        assert_eq!(expected_bytes.as_ref(), result_hmac512.as_bytes());
+        assert_eq!(expected_signature, result_hmac512);
+    }
+
+    #[test]
+    fn sha512_and_sha256_signatures_differ() {
+        //Arrange
+        let in_memory_key = MemoryKey {
+            key: Bytes::from("key"),
+        };
+        let data = b"The quick brown fox jumps over the lazy dog";
+
+        //Act
+        let result_hmac256 = in_memory_key
+            .sign(SignatureAlgorithm::HMACSHA256, data)
+            .unwrap();
+        let result_hmac512 = in_memory_key
+            .sign(SignatureAlgorithm::HMACSHA512, data)
+            .unwrap();
+
+        //Assert
+        assert_eq!(32, result_hmac256.as_bytes().len());
+        assert_eq!(64, result_hmac512.as_bytes().len());
+        assert_ne!(result_hmac256, result_hmac512);
+    }
+
     //MemoryKeyStoreTests
     #[test]
     fn create_empty_memory_keystore() {
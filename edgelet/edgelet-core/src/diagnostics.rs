@@ -0,0 +1,170 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+/// How many runtime errors are kept by default. Older ones are dropped.
+pub const DEFAULT_RUNTIME_ERROR_CAPACITY: usize = 200;
+
+/// A container runtime operation that failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeErrorRecord {
+    at: DateTime<Utc>,
+    operation: String,
+    module: Option<String>,
+    kind: String,
+    message: String,
+}
+
+impl RuntimeErrorRecord {
+    pub fn new(at: DateTime<Utc>, operation: &str, kind: &str, message: String) -> Self {
+        RuntimeErrorRecord {
+            at,
+            operation: operation.to_string(),
+            module: None,
+            kind: kind.to_string(),
+            message,
+        }
+    }
+
+    pub fn with_module(mut self, module: Option<String>) -> Self {
+        self.module = module;
+        self
+    }
+
+    pub fn at(&self) -> DateTime<Utc> {
+        self.at
+    }
+
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// The module the operation was about, if it was about one.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_ref().map(AsRef::as_ref)
+    }
+
+    /// The kind of the error, without the details `message` has.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The error and its causes.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// The most recent container runtime errors, kept in memory so that they
+/// can still be looked at after they have scrolled out of the daemon's logs.
+/// The buffer is allocated up front and only failures touch it, so
+/// operations that succeed cost nothing. Clones share the errors.
+#[derive(Clone, Debug)]
+pub struct RuntimeErrorLog {
+    records: Arc<Mutex<VecDeque<RuntimeErrorRecord>>>,
+    capacity: usize,
+}
+
+impl RuntimeErrorLog {
+    pub fn new(capacity: usize) -> Self {
+        RuntimeErrorLog {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keeps `record`, dropping the oldest error when the log is full.
+    pub fn record(&self, record: RuntimeErrorRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        // Records are only ever added or dropped whole, so a panic while the
+        // lock was held cannot have left them inconsistent.
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// The errors that are kept, oldest first.
+    pub fn records(&self) -> Vec<RuntimeErrorRecord> {
+        let records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        records.iter().cloned().collect()
+    }
+}
+
+impl Default for RuntimeErrorLog {
+    fn default() -> Self {
+        RuntimeErrorLog::new(DEFAULT_RUNTIME_ERROR_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn record(at: DateTime<Utc>, module: &str) -> RuntimeErrorRecord {
+        RuntimeErrorRecord::new(
+            at,
+            "start",
+            "NotFound",
+            format!("No such container: {}", module),
+        ).with_module(Some(module.to_string()))
+    }
+
+    #[test]
+    fn records_are_kept_oldest_first() {
+        let now = Utc::now();
+        let log = RuntimeErrorLog::default();
+        log.record(record(now, "m1"));
+        log.record(record(now + Duration::seconds(1), "m2"));
+
+        let records = log.records();
+        assert_eq!(2, records.len());
+        assert_eq!(Some("m1"), records[0].module());
+        assert_eq!(Some("m2"), records[1].module());
+        assert_eq!("NotFound", records[1].kind());
+        assert_eq!("No such container: m2", records[1].message());
+    }
+
+    #[test]
+    fn oldest_records_are_dropped_when_full() {
+        let now = Utc::now();
+        let log = RuntimeErrorLog::new(3);
+        for i in 0..5 {
+            log.record(record(now + Duration::seconds(i), &format!("m{}", i)));
+        }
+
+        let modules: Vec<_> = log
+            .records()
+            .iter()
+            .map(|record| record.module().unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["m2", "m3", "m4"], modules);
+    }
+
+    #[test]
+    fn clones_share_records() {
+        let log = RuntimeErrorLog::default();
+        log.clone().record(record(Utc::now(), "m1"));
+        assert_eq!(1, log.records().len());
+    }
+
+    #[test]
+    fn nothing_is_kept_without_capacity() {
+        let log = RuntimeErrorLog::new(0);
+        log.record(record(Utc::now(), "m1"));
+        assert!(log.records().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Disk space of the filesystems that fill up as modules are deployed: the
+//! one the container runtime keeps images and containers on, and the one
+//! holding the home directory of the daemon.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The size of a filesystem and how much of it is available to the daemon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiskSpace {
+    total_bytes: u64,
+    available_bytes: u64,
+}
+
+impl DiskSpace {
+    pub fn new(total_bytes: u64, available_bytes: u64) -> Self {
+        DiskSpace {
+            total_bytes,
+            available_bytes,
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn available_bytes(&self) -> u64 {
+        self.available_bytes
+    }
+}
+
+/// Finds out the disk space of the filesystem a path is on.
+pub trait DiskStats: Send + Sync {
+    fn disk_space(&self, path: &Path) -> io::Result<DiskSpace>;
+}
+
+/// The disk space of the filesystem `path` is on, or why it could not be
+/// found out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskUsage {
+    path: PathBuf,
+    space: Result<DiskSpace, String>,
+}
+
+impl DiskUsage {
+    pub fn new(path: PathBuf, space: Result<DiskSpace, String>) -> Self {
+        DiskUsage { path, space }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn space(&self) -> Result<DiskSpace, &str> {
+        self.space.as_ref().map(|space| *space).map_err(AsRef::as_ref)
+    }
+}
+
+/// The disk usage of the filesystems a deployment fills up. The container
+/// runtime's is missing when it did not say where it keeps its data.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SystemResources {
+    runtime_root: Option<DiskUsage>,
+    homedir: Option<DiskUsage>,
+}
+
+impl SystemResources {
+    pub fn new() -> Self {
+        SystemResources::default()
+    }
+
+    /// The filesystem the container runtime keeps images and containers on.
+    pub fn runtime_root(&self) -> Option<&DiskUsage> {
+        self.runtime_root.as_ref()
+    }
+
+    pub fn with_runtime_root(mut self, runtime_root: Option<DiskUsage>) -> Self {
+        self.runtime_root = runtime_root;
+        self
+    }
+
+    /// The filesystem the home directory of the daemon is on.
+    pub fn homedir(&self) -> Option<&DiskUsage> {
+        self.homedir.as_ref()
+    }
+
+    pub fn with_homedir(mut self, homedir: Option<DiskUsage>) -> Self {
+        self.homedir = homedir;
+        self
+    }
+}
+
+/// Measures the system resources, with the disk statistics of the platform.
+#[derive(Clone)]
+pub struct DiskMonitor {
+    stats: Arc<DiskStats>,
+    homedir: PathBuf,
+}
+
+impl DiskMonitor {
+    pub fn new(stats: Arc<DiskStats>, homedir: PathBuf) -> Self {
+        DiskMonitor { stats, homedir }
+    }
+
+    /// Measures the filesystems of the home directory and of `runtime_root`,
+    /// where the container runtime said it keeps its data.
+    pub fn resources(&self, runtime_root: Option<&Path>) -> SystemResources {
+        SystemResources::new()
+            .with_runtime_root(runtime_root.map(|path| self.usage(path)))
+            .with_homedir(Some(self.usage(&self.homedir)))
+    }
+
+    /// Measures the filesystem `path` is on.
+    pub fn usage(&self, path: &Path) -> DiskUsage {
+        let space = self.stats.disk_space(path).map_err(|err| {
+            debug!("Could not get the disk space of {}: {}", path.display(), err);
+            err.to_string()
+        });
+        DiskUsage::new(path.to_path_buf(), space)
+    }
+}
+
+impl fmt::Debug for DiskMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DiskMonitor")
+            .field("homedir", &self.homedir)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Knows the disk space of some paths and is denied access to the rest.
+    struct TestDiskStats(HashMap<PathBuf, DiskSpace>);
+
+    impl DiskStats for TestDiskStats {
+        fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+            self.0
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+
+    fn monitor(known: &[(&str, DiskSpace)]) -> DiskMonitor {
+        let stats = known
+            .iter()
+            .map(|&(path, space)| (PathBuf::from(path), space))
+            .collect();
+        DiskMonitor::new(
+            Arc::new(TestDiskStats(stats)),
+            PathBuf::from("/var/lib/iotedge"),
+        )
+    }
+
+    #[test]
+    fn runtime_root_and_homedir_are_measured() {
+        let docker = DiskSpace::new(32_000_000_000, 20_000_000_000);
+        let iotedge = DiskSpace::new(8_000_000_000, 100_000_000);
+        let monitor = monitor(&[("/var/lib/docker", docker), ("/var/lib/iotedge", iotedge)]);
+
+        let resources = monitor.resources(Some(Path::new("/var/lib/docker")));
+
+        let runtime_root = resources.runtime_root().unwrap();
+        assert_eq!(Path::new("/var/lib/docker"), runtime_root.path());
+        assert_eq!(Ok(docker), runtime_root.space());
+        let homedir = resources.homedir().unwrap();
+        assert_eq!(Path::new("/var/lib/iotedge"), homedir.path());
+        assert_eq!(Ok(iotedge), homedir.space());
+    }
+
+    #[test]
+    fn unknown_runtime_root_is_left_out() {
+        let iotedge = DiskSpace::new(8_000_000_000, 4_000_000_000);
+        let monitor = monitor(&[("/var/lib/iotedge", iotedge)]);
+
+        let resources = monitor.resources(None);
+
+        assert_eq!(None, resources.runtime_root());
+        assert_eq!(Ok(iotedge), resources.homedir().unwrap().space());
+    }
+
+    #[test]
+    fn filesystems_that_cannot_be_measured_are_reported_as_such() {
+        let iotedge = DiskSpace::new(8_000_000_000, 4_000_000_000);
+        let monitor = monitor(&[("/var/lib/iotedge", iotedge)]);
+
+        let resources = monitor.resources(Some(Path::new("/var/lib/docker")));
+
+        let runtime_root = resources.runtime_root().unwrap();
+        assert_eq!(Path::new("/var/lib/docker"), runtime_root.path());
+        assert_eq!(
+            Err(io::Error::from(io::ErrorKind::PermissionDenied)
+                .to_string()
+                .as_str()),
+            runtime_root.space()
+        );
+        assert_eq!(Ok(iotedge), resources.homedir().unwrap().space());
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! The environment variables iotedged injects into the edge runtime module
+//! when it creates it, which tell the module who it is, which device and hub
+//! it belongs to and where the daemon's APIs are.
+
+use std::collections::HashMap;
+
+use identity::Identity;
+use module::ModuleSpec;
+
+/// The host name of the IoT Hub the device belongs to.
+pub const IOTHUB_HOSTNAME_KEY: &str = "IOTEDGE_IOTHUBHOSTNAME";
+
+/// The host name downstream devices and modules connect to the device by,
+/// lower cased. The edge agent gives it to the edge hub container as an alias
+/// in the network so that TLS cert validation works.
+pub const GATEWAY_HOSTNAME_KEY: &str = "IOTEDGE_GATEWAYHOSTNAME";
+
+/// The same host name under the name edge agents before
+/// `IOTEDGE_GATEWAYHOSTNAME` read it from.
+pub const EDGE_DEVICE_HOSTNAME_KEY: &str = "EDGEDEVICEHOSTNAME";
+
+/// The host name of the parent device, for devices that sit below another
+/// edge device. It is only set when a parent is configured.
+pub const PARENT_HOSTNAME_KEY: &str = "IOTEDGE_PARENTHOSTNAME";
+
+/// The IoT Hub device identifier.
+pub const DEVICEID_KEY: &str = "IOTEDGE_DEVICEID";
+
+/// The IoT Hub module identifier.
+pub const MODULEID_KEY: &str = "IOTEDGE_MODULEID";
+
+/// The generation identifier IoT Hub gave the module's identity.
+pub const MODULE_GENERATIONID_KEY: &str = "IOTEDGE_MODULEGENERATIONID";
+
+/// The URI of the workload API, which the module uses for its own needs and
+/// mounts into module containers when it refers to a Unix domain socket.
+pub const WORKLOAD_URI_KEY: &str = "IOTEDGE_WORKLOADURI";
+
+/// The URI of the management API, which the module uses to manage module
+/// lifetimes and identities.
+pub const MANAGEMENT_URI_KEY: &str = "IOTEDGE_MANAGEMENTURI";
+
+/// How modules authenticate to other server modules, like the edge hub.
+pub const AUTHSCHEME_KEY: &str = "IOTEDGE_AUTHSCHEME";
+
+/// The largest version of the APIs the daemon supports.
+pub const API_VERSION_KEY: &str = "IOTEDGE_APIVERSION";
+
+/// Set to "true" when the workload API serves the certificates signed
+/// deployment manifests are verified with, at `/trust-bundle/manifest`.
+pub const MANIFEST_TRUST_BUNDLE_KEY: &str = "IOTEDGE_MANIFESTTRUSTBUNDLE";
+
+/// What started the edge runtime, which is always iotedged.
+pub const EDGE_RUNTIME_MODE_KEY: &str = "Mode";
+
+/// The docker network modules are attached to.
+pub const NETWORKID_KEY: &str = "NetworkId";
+
+const AUTH_SCHEME: &str = "sasToken";
+const EDGE_RUNTIME_MODE: &str = "iotedged";
+
+/// A variable and how its value is found. Variables without a value are
+/// left out.
+type Variable = (&'static str, fn(&ModuleEnvSettings, &Identity) -> Option<String>);
+
+/// Every variable that is injected, in no particular order.
+const VARIABLES: &[Variable] = &[
+    (IOTHUB_HOSTNAME_KEY, |settings, _| {
+        non_empty(&settings.iothub_hostname)
+    }),
+    (GATEWAY_HOSTNAME_KEY, |settings, _| {
+        non_empty(&settings.gateway_hostname.to_lowercase())
+    }),
+    (EDGE_DEVICE_HOSTNAME_KEY, |settings, _| {
+        non_empty(&settings.gateway_hostname.to_lowercase())
+    }),
+    (PARENT_HOSTNAME_KEY, |settings, _| {
+        settings.parent_hostname.as_ref().and_then(|p| non_empty(p))
+    }),
+    (DEVICEID_KEY, |settings, _| non_empty(&settings.device_id)),
+    (MODULEID_KEY, |_, identity| non_empty(identity.module_id())),
+    (MODULE_GENERATIONID_KEY, |_, identity| {
+        non_empty(identity.generation_id())
+    }),
+    (WORKLOAD_URI_KEY, |settings, _| non_empty(&settings.workload_uri)),
+    (MANAGEMENT_URI_KEY, |settings, _| {
+        non_empty(&settings.management_uri)
+    }),
+    (AUTHSCHEME_KEY, |_, _| Some(AUTH_SCHEME.to_string())),
+    (API_VERSION_KEY, |settings, _| non_empty(&settings.api_version)),
+    (MANIFEST_TRUST_BUNDLE_KEY, |settings, _| {
+        if settings.manifest_trust_bundle {
+            Some("true".to_string())
+        } else {
+            None
+        }
+    }),
+    (EDGE_RUNTIME_MODE_KEY, |_, _| Some(EDGE_RUNTIME_MODE.to_string())),
+    (NETWORKID_KEY, |settings, _| non_empty(&settings.network_id)),
+];
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Which of the variables are injected. With `allow` set only the variables
+/// it lists are, and the variables `deny` lists never are.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EnvInjection {
+    allow: Option<Vec<String>>,
+    deny: Vec<String>,
+}
+
+impl EnvInjection {
+    pub fn allow(&self) -> Option<&[String]> {
+        self.allow.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_allow(mut self, allow: Option<Vec<String>>) -> Self {
+        self.allow = allow;
+        self
+    }
+
+    pub fn deny(&self) -> &[String] {
+        &self.deny
+    }
+
+    pub fn with_deny(mut self, deny: Vec<String>) -> Self {
+        self.deny = deny;
+        self
+    }
+
+    pub fn injects(&self, name: &str) -> bool {
+        self.allow
+            .as_ref()
+            .map_or(true, |allow| allow.iter().any(|allowed| allowed == name))
+            && !self.deny.iter().any(|denied| denied == name)
+    }
+}
+
+/// What the device is configured with that the injected variables are made
+/// of. Values that are left empty are not injected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleEnvSettings {
+    iothub_hostname: String,
+    device_id: String,
+    gateway_hostname: String,
+    parent_hostname: Option<String>,
+    workload_uri: String,
+    management_uri: String,
+    api_version: String,
+    network_id: String,
+    manifest_trust_bundle: bool,
+    injection: EnvInjection,
+}
+
+impl ModuleEnvSettings {
+    pub fn new(iothub_hostname: &str, device_id: &str, gateway_hostname: &str) -> Self {
+        ModuleEnvSettings {
+            iothub_hostname: iothub_hostname.to_string(),
+            device_id: device_id.to_string(),
+            gateway_hostname: gateway_hostname.to_string(),
+            ..ModuleEnvSettings::default()
+        }
+    }
+
+    pub fn with_parent_hostname(mut self, parent_hostname: Option<String>) -> Self {
+        self.parent_hostname = parent_hostname;
+        self
+    }
+
+    pub fn with_workload_uri(mut self, workload_uri: String) -> Self {
+        self.workload_uri = workload_uri;
+        self
+    }
+
+    pub fn with_management_uri(mut self, management_uri: String) -> Self {
+        self.management_uri = management_uri;
+        self
+    }
+
+    pub fn with_api_version(mut self, api_version: String) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn with_network_id(mut self, network_id: String) -> Self {
+        self.network_id = network_id;
+        self
+    }
+
+    /// Whether the workload API serves a manifest trust bundle.
+    pub fn with_manifest_trust_bundle(mut self, manifest_trust_bundle: bool) -> Self {
+        self.manifest_trust_bundle = manifest_trust_bundle;
+        self
+    }
+
+    pub fn with_injection(mut self, injection: EnvInjection) -> Self {
+        self.injection = injection;
+        self
+    }
+
+    pub fn injection(&self) -> &EnvInjection {
+        &self.injection
+    }
+}
+
+/// Builds the environment of a module.
+pub struct ModuleEnv;
+
+impl ModuleEnv {
+    /// The environment `spec` is created with: the variables the injection
+    /// settings allow, overridden by those the spec sets itself. Injected
+    /// values never replace a variable the spec sets, even to an empty value.
+    pub fn for_module<T, I>(
+        spec: &ModuleSpec<T>,
+        settings: &ModuleEnvSettings,
+        identity: &I,
+    ) -> HashMap<String, String>
+    where
+        I: Identity,
+    {
+        let mut env: HashMap<String, String> = VARIABLES
+            .iter()
+            .filter(|&&(name, _)| settings.injection.injects(name))
+            .filter_map(|&(name, value)| {
+                value(settings, identity).map(|value| (name.to_string(), value))
+            }).collect();
+        env.extend(
+            spec.env()
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        env
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use identity::AuthType;
+
+    struct TestIdentity;
+
+    impl Identity for TestIdentity {
+        fn module_id(&self) -> &str {
+            "$edgeAgent"
+        }
+
+        fn managed_by(&self) -> &str {
+            "iotedge"
+        }
+
+        fn generation_id(&self) -> &str {
+            "g1"
+        }
+
+        fn auth_type(&self) -> AuthType {
+            AuthType::Sas
+        }
+    }
+
+    fn spec(env: &[(&str, &str)]) -> ModuleSpec<()> {
+        let env = env
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        ModuleSpec::new("edgeAgent", "docker", (), env).unwrap()
+    }
+
+    fn settings() -> ModuleEnvSettings {
+        ModuleEnvSettings::new("hub.azure-devices.net", "d1", "Edge-Device")
+            .with_workload_uri("unix:///var/run/iotedge/workload.sock".to_string())
+            .with_management_uri("unix:///var/run/iotedge/mgmt.sock".to_string())
+            .with_api_version("2018-06-28".to_string())
+            .with_network_id("azure-iot-edge".to_string())
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    const DEFAULT_ENV: &[(&str, &str)] = &[
+        ("IOTEDGE_IOTHUBHOSTNAME", "hub.azure-devices.net"),
+        ("IOTEDGE_GATEWAYHOSTNAME", "edge-device"),
+        ("EDGEDEVICEHOSTNAME", "edge-device"),
+        ("IOTEDGE_DEVICEID", "d1"),
+        ("IOTEDGE_MODULEID", "$edgeAgent"),
+        ("IOTEDGE_MODULEGENERATIONID", "g1"),
+        ("IOTEDGE_WORKLOADURI", "unix:///var/run/iotedge/workload.sock"),
+        ("IOTEDGE_MANAGEMENTURI", "unix:///var/run/iotedge/mgmt.sock"),
+        ("IOTEDGE_AUTHSCHEME", "sasToken"),
+        ("IOTEDGE_APIVERSION", "2018-06-28"),
+        ("Mode", "iotedged"),
+        ("NetworkId", "azure-iot-edge"),
+    ];
+
+    #[test]
+    fn injects_every_variable_by_default() {
+        assert_eq!(
+            env(DEFAULT_ENV),
+            ModuleEnv::for_module(&spec(&[]), &settings(), &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn injects_the_parent_hostname_when_there_is_a_parent() {
+        let settings = settings().with_parent_hostname(Some("parent.contoso.com".to_string()));
+        let mut expected = env(DEFAULT_ENV);
+        expected.insert(
+            "IOTEDGE_PARENTHOSTNAME".to_string(),
+            "parent.contoso.com".to_string(),
+        );
+        assert_eq!(
+            expected,
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn injects_the_manifest_trust_bundle_flag_when_there_is_a_bundle() {
+        let settings = settings().with_manifest_trust_bundle(true);
+        let mut expected = env(DEFAULT_ENV);
+        expected.insert("IOTEDGE_MANIFESTTRUSTBUNDLE".to_string(), "true".to_string());
+        assert_eq!(
+            expected,
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn leaves_out_empty_values() {
+        let settings = ModuleEnvSettings::new("hub.azure-devices.net", "d1", "");
+        assert_eq!(
+            env(&[
+                ("IOTEDGE_IOTHUBHOSTNAME", "hub.azure-devices.net"),
+                ("IOTEDGE_DEVICEID", "d1"),
+                ("IOTEDGE_MODULEID", "$edgeAgent"),
+                ("IOTEDGE_MODULEGENERATIONID", "g1"),
+                ("IOTEDGE_AUTHSCHEME", "sasToken"),
+                ("Mode", "iotedged"),
+            ]),
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn injects_only_allowed_variables() {
+        let settings = settings().with_injection(EnvInjection::default().with_allow(Some(vec![
+            "IOTEDGE_DEVICEID".to_string(),
+            "IOTEDGE_MODULEID".to_string(),
+            "IOTEDGE_UNKNOWN".to_string(),
+        ])));
+        assert_eq!(
+            env(&[
+                ("IOTEDGE_DEVICEID", "d1"),
+                ("IOTEDGE_MODULEID", "$edgeAgent"),
+            ]),
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn denied_variables_are_not_injected() {
+        let settings = settings().with_injection(
+            EnvInjection::default()
+                .with_allow(Some(vec![
+                    "IOTEDGE_DEVICEID".to_string(),
+                    "IOTEDGE_MODULEID".to_string(),
+                ])).with_deny(vec!["IOTEDGE_MODULEID".to_string()]),
+        );
+        assert_eq!(
+            env(&[("IOTEDGE_DEVICEID", "d1")]),
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+
+        let settings = settings.with_injection(EnvInjection::default().with_deny(vec![
+            "EDGEDEVICEHOSTNAME".to_string(),
+            "NetworkId".to_string(),
+        ]));
+        let mut expected = env(DEFAULT_ENV);
+        expected.remove("EDGEDEVICEHOSTNAME");
+        expected.remove("NetworkId");
+        assert_eq!(
+            expected,
+            ModuleEnv::for_module(&spec(&[]), &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn spec_variables_take_precedence() {
+        let spec = spec(&[
+            ("IOTEDGE_APIVERSION", "2018-01-01"),
+            ("IOTEDGE_DEVICEID", ""),
+            ("RuntimeLogLevel", "debug"),
+        ]);
+        let mut expected = env(DEFAULT_ENV);
+        expected.insert("IOTEDGE_APIVERSION".to_string(), "2018-01-01".to_string());
+        expected.insert("IOTEDGE_DEVICEID".to_string(), String::new());
+        expected.insert("RuntimeLogLevel".to_string(), "debug".to_string());
+        assert_eq!(
+            expected,
+            ModuleEnv::for_module(&spec, &settings(), &TestIdentity)
+        );
+
+        // Variables the spec sets are kept even when injecting them is denied.
+        let settings = settings().with_injection(
+            EnvInjection::default().with_deny(vec!["IOTEDGE_APIVERSION".to_string()]),
+        );
+        assert_eq!(
+            expected,
+            ModuleEnv::for_module(&spec, &settings, &TestIdentity)
+        );
+    }
+
+    #[test]
+    fn injection_deserializes_with_defaults() {
+        let injection: EnvInjection = serde_json::from_str("{}").unwrap();
+        assert_eq!(EnvInjection::default(), injection);
+        assert!(injection.injects("IOTEDGE_DEVICEID"));
+
+        let injection: EnvInjection =
+            serde_json::from_str(r#"{"allow": ["Mode"], "deny": ["NetworkId"]}"#).unwrap();
+        assert!(injection.injects("Mode"));
+        assert!(!injection.injects("NetworkId"));
+        assert!(!injection.injects("IOTEDGE_DEVICEID"));
+    }
+}
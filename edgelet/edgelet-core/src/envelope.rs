@@ -0,0 +1,283 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! An envelope around the HSM's ciphertexts that authenticates them no matter
+//! which cipher the HSM uses, so that a truncated or altered ciphertext is
+//! rejected instead of decrypting to garbage.
+//!
+//! An envelope is the version byte, the nonce the HSM encrypted with, an
+//! HMAC-SHA256 tag and the HSM's ciphertext. The tag covers the version, the
+//! nonce, the initialization vector of the caller and the ciphertext, and is
+//! keyed per client id, which includes the caller's associated data.
+
+use consistenttime::ct_u8_slice_eq;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crypto::{Decrypt, Encrypt, MakeRandom};
+use error::{Error, ErrorKind};
+
+/// The HSM starts its own ciphertexts with version 1, which is how those from
+/// before the envelope are told apart.
+const ENVELOPE_VERSION: u8 = 2;
+const LEGACY_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 32;
+
+/// How much longer an envelope is than the ciphertext in it.
+pub const ENVELOPE_OVERHEAD: usize = 1 + NONCE_LEN + TAG_LEN;
+
+/// The client ids of modules start with the module's name, which can't start
+/// with a nul, so the keys of the tags can't be had through the workload API.
+const MAC_KEY_DOMAIN: &[u8] = b"\0edgelet envelope tag key";
+
+/// Puts what `hsm` encrypts in an envelope, and only decrypts what is in an
+/// intact one. Ciphertexts from before the envelope are still handed to the
+/// HSM as they are, for one release, after which they are rejected.
+#[derive(Clone, Debug)]
+pub struct Envelope<T> {
+    hsm: T,
+}
+
+impl<T> Envelope<T>
+where
+    T: Encrypt + Decrypt + MakeRandom,
+{
+    pub fn new(hsm: T) -> Self {
+        Envelope { hsm }
+    }
+
+    /// The tag of an envelope. Its key is derived by having the HSM encrypt a
+    /// constant, so the key never leaves iotedged and is the same each time
+    /// for the same client id.
+    fn tag(
+        &self,
+        client_id: &[u8],
+        nonce: &[u8],
+        initialization_vector: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let mut key_id = MAC_KEY_DOMAIN.to_vec();
+        key_id.extend_from_slice(client_id);
+        let key = self.hsm.encrypt(&key_id, MAC_KEY_DOMAIN, &[0; NONCE_LEN])?;
+        let key = Sha256::digest(key.as_ref());
+
+        let mut mac = Hmac::<Sha256>::new(&key).map_err(|_| ErrorKind::Sign(key.len()))?;
+        mac.input(&[ENVELOPE_VERSION]);
+        mac.input(nonce);
+        mac.input(format!("{:016x}", initialization_vector.len()).as_bytes());
+        mac.input(initialization_vector);
+        mac.input(ciphertext);
+        Ok(mac.result().code().to_vec())
+    }
+}
+
+impl<T> Encrypt for Envelope<T>
+where
+    T: Encrypt + Decrypt + MakeRandom,
+{
+    type Buffer = Vec<u8>;
+
+    /// The HSM encrypts with a nonce of its own rather than with
+    /// `initialization_vector`, which callers don't always pick well, and the
+    /// tag ties the envelope to `initialization_vector` instead.
+    fn encrypt(
+        &self,
+        client_id: &[u8],
+        plaintext: &[u8],
+        initialization_vector: &[u8],
+    ) -> Result<Self::Buffer, Error> {
+        let mut nonce = [0_u8; NONCE_LEN];
+        self.hsm.get_random_bytes(&mut nonce)?;
+        let ciphertext = self.hsm.encrypt(client_id, plaintext, &nonce)?;
+        let ciphertext = ciphertext.as_ref();
+        let tag = self.tag(client_id, &nonce, initialization_vector, ciphertext)?;
+
+        let mut envelope = Vec::with_capacity(ENVELOPE_OVERHEAD + ciphertext.len());
+        envelope.push(ENVELOPE_VERSION);
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&tag);
+        envelope.extend_from_slice(ciphertext);
+        Ok(envelope)
+    }
+}
+
+impl<T> Decrypt for Envelope<T>
+where
+    T: Encrypt + Decrypt + MakeRandom,
+{
+    type Buffer = Vec<u8>;
+
+    fn decrypt(
+        &self,
+        client_id: &[u8],
+        ciphertext: &[u8],
+        initialization_vector: &[u8],
+    ) -> Result<Self::Buffer, Error> {
+        match ciphertext.first() {
+            Some(&ENVELOPE_VERSION) if ciphertext.len() >= ENVELOPE_OVERHEAD => (),
+            Some(&LEGACY_VERSION) => {
+                debug!("Decrypting a ciphertext from before the envelope");
+                return self
+                    .hsm
+                    .decrypt(client_id, ciphertext, initialization_vector)
+                    .map(|plaintext| plaintext.as_ref().to_vec());
+            }
+            _ => return Err(Error::from(ErrorKind::PayloadTampered)),
+        }
+
+        let (nonce, rest) = ciphertext[1..].split_at(NONCE_LEN);
+        let (tag, ciphertext) = rest.split_at(TAG_LEN);
+        let expected = self.tag(client_id, nonce, initialization_vector, ciphertext)?;
+        if !ct_u8_slice_eq(&expected, tag) {
+            return Err(Error::from(ErrorKind::PayloadTampered));
+        }
+        self.hsm
+            .decrypt(client_id, ciphertext, nonce)
+            .map(|plaintext| plaintext.as_ref().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// An HSM whose cipher authenticates nothing, like the backends that
+    /// decrypt altered ciphertexts to garbage: the plaintext XORed with a key
+    /// stream of the client id and initialization vector, after the HSM's
+    /// version byte.
+    #[derive(Clone, Debug, Default)]
+    struct XorHsm {
+        random: Arc<AtomicUsize>,
+    }
+
+    fn xor(client_id: &[u8], data: &[u8], initialization_vector: &[u8]) -> Vec<u8> {
+        let mut seed = client_id.to_vec();
+        seed.extend_from_slice(initialization_vector);
+        let stream = Sha256::digest(&seed);
+        data.iter()
+            .zip(stream.iter().cycle())
+            .map(|(d, s)| d ^ s)
+            .collect()
+    }
+
+    impl Encrypt for XorHsm {
+        type Buffer = Vec<u8>;
+
+        fn encrypt(
+            &self,
+            client_id: &[u8],
+            plaintext: &[u8],
+            initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, Error> {
+            let mut ciphertext = vec![LEGACY_VERSION];
+            ciphertext.extend(xor(client_id, plaintext, initialization_vector));
+            Ok(ciphertext)
+        }
+    }
+
+    impl Decrypt for XorHsm {
+        type Buffer = Vec<u8>;
+
+        fn decrypt(
+            &self,
+            client_id: &[u8],
+            ciphertext: &[u8],
+            initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, Error> {
+            Ok(xor(client_id, &ciphertext[1..], initialization_vector))
+        }
+    }
+
+    impl MakeRandom for XorHsm {
+        fn get_random_bytes(&self, buffer: &mut [u8]) -> Result<(), Error> {
+            let n = self.random.fetch_add(1, Ordering::SeqCst);
+            for (i, b) in buffer.iter_mut().enumerate() {
+                *b = (n + i) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    const CLIENT_ID: &[u8] = b"sensorI";
+    const IV: &[u8] = b"initialization vector";
+    const PLAINTEXT: &[u8] = b"a secret worth keeping";
+
+    fn assert_tampered(result: Result<Vec<u8>, Error>) {
+        match result {
+            Err(ref err) => match *err.kind() {
+                ErrorKind::PayloadTampered => (),
+                ref kind => panic!("unexpected error kind {:?}", kind),
+            },
+            Ok(plaintext) => panic!("decrypted tampered ciphertext to {:?}", plaintext),
+        }
+    }
+
+    #[test]
+    fn envelopes_round_trip() {
+        let envelope = Envelope::new(XorHsm::default());
+
+        let ciphertext = envelope.encrypt(CLIENT_ID, PLAINTEXT, IV).unwrap();
+
+        assert_eq!(ENVELOPE_VERSION, ciphertext[0]);
+        assert_eq!(ENVELOPE_OVERHEAD + 1 + PLAINTEXT.len(), ciphertext.len());
+        assert_eq!(
+            PLAINTEXT.to_vec(),
+            envelope.decrypt(CLIENT_ID, &ciphertext, IV).unwrap()
+        );
+
+        // Each encryption has a nonce of its own.
+        let again = envelope.encrypt(CLIENT_ID, PLAINTEXT, IV).unwrap();
+        assert_ne!(ciphertext, again);
+        assert_eq!(
+            PLAINTEXT.to_vec(),
+            envelope.decrypt(CLIENT_ID, &again, IV).unwrap()
+        );
+    }
+
+    #[test]
+    fn altered_envelopes_are_rejected() {
+        let envelope = Envelope::new(XorHsm::default());
+        let ciphertext = envelope.encrypt(CLIENT_ID, PLAINTEXT, IV).unwrap();
+
+        // Flipping the version byte makes an unknown version of it.
+        for i in 0..ciphertext.len() {
+            let mut flipped = ciphertext.clone();
+            flipped[i] ^= 0x01;
+            assert_tampered(envelope.decrypt(CLIENT_ID, &flipped, IV));
+        }
+        for len in 0..ciphertext.len() {
+            assert_tampered(envelope.decrypt(CLIENT_ID, &ciphertext[..len], IV));
+        }
+        let mut extended = ciphertext.clone();
+        extended.push(0);
+        assert_tampered(envelope.decrypt(CLIENT_ID, &extended, IV));
+    }
+
+    #[test]
+    fn envelopes_are_bound_to_client_and_initialization_vector() {
+        let envelope = Envelope::new(XorHsm::default());
+        let ciphertext = envelope.encrypt(CLIENT_ID, PLAINTEXT, IV).unwrap();
+
+        // The client id holds the associated data.
+        assert_tampered(envelope.decrypt(b"sensorIother data", &ciphertext, IV));
+        assert_tampered(envelope.decrypt(b"cameraI", &ciphertext, IV));
+        assert_tampered(envelope.decrypt(CLIENT_ID, &ciphertext, b"another vector"));
+    }
+
+    #[test]
+    fn ciphertexts_from_before_the_envelope_are_decrypted() {
+        let hsm = XorHsm::default();
+        let legacy = hsm.encrypt(CLIENT_ID, PLAINTEXT, IV).unwrap();
+
+        let envelope = Envelope::new(hsm);
+
+        assert_eq!(
+            PLAINTEXT.to_vec(),
+            envelope.decrypt(CLIENT_ID, &legacy, IV).unwrap()
+        );
+    }
+}
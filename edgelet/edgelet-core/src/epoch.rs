@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Which module each caller's process was found to belong to, for as long as
+//! nothing happened to the module since.
+//!
+//! Every module has an epoch, which the runtime bumps when it starts, stops,
+//! restarts or removes the module, or sees that the module's process changed.
+//! A process is only taken to still belong to a module while the module's
+//! epoch is the one it had when the process was resolved, since the process
+//! ids of a container that stopped can be handed to anything else.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use pid::Pid;
+
+#[derive(Debug, Default)]
+struct Epochs {
+    /// Bumped with any module's epoch, so a resolution that raced with a bump
+    /// is told apart.
+    stamp: u64,
+    epochs: HashMap<String, u64>,
+    /// The process each module was last seen running.
+    pids: HashMap<String, i32>,
+    /// The module each process was resolved to, and the module's epoch then.
+    owners: HashMap<i32, (String, u64)>,
+}
+
+/// Clones share the epochs and the processes resolved under them.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleEpochs {
+    inner: Arc<Mutex<Epochs>>,
+}
+
+impl ModuleEpochs {
+    pub fn new() -> Self {
+        ModuleEpochs::default()
+    }
+
+    /// Forgets every process resolved to the module.
+    pub fn bump(&self, name: &str) {
+        let mut epochs = self.lock();
+        epochs.stamp += 1;
+        *epochs.epochs.entry(name.to_string()).or_insert(0) += 1;
+        debug!("Processes of module {} need to be resolved again", name);
+    }
+
+    pub fn epoch(&self, name: &str) -> u64 {
+        self.lock().epochs.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Bumps the epoch of the module when it runs another process than when
+    /// it was last seen.
+    pub fn observe(&self, name: &str, pid: Pid) {
+        let pid = match pid {
+            Pid::Value(pid) => Some(pid),
+            Pid::None | Pid::Any => None,
+        };
+        let changed = {
+            let mut epochs = self.lock();
+            let previous = match pid {
+                Some(pid) => epochs.pids.insert(name.to_string(), pid),
+                None => epochs.pids.remove(name),
+            };
+            previous.is_some() && previous != pid
+        };
+        if changed {
+            self.bump(name);
+        }
+    }
+
+    /// Marks the start of a resolution whose outcome is handed to `remember`.
+    pub fn stamp(&self) -> u64 {
+        self.lock().stamp
+    }
+
+    /// The module the process belongs to, if it was resolved since the
+    /// module's epoch last changed.
+    pub fn owner(&self, pid: Pid) -> Option<String> {
+        let pid = match pid {
+            Pid::Value(pid) => pid,
+            Pid::None | Pid::Any => return None,
+        };
+        let mut epochs = self.lock();
+        let owner = match epochs.owners.get(&pid) {
+            Some(&(ref name, epoch)) if epochs.epochs.get(name).cloned().unwrap_or(0) == epoch => {
+                Some(name.clone())
+            }
+            Some(_) => None,
+            None => return None,
+        };
+        if owner.is_none() {
+            epochs.owners.remove(&pid);
+        }
+        owner
+    }
+
+    /// Records the module the process was resolved to, unless any module's
+    /// epoch changed since `stamp`, in which case the resolution may already
+    /// be stale.
+    pub fn remember(&self, pid: Pid, name: &str, stamp: u64) {
+        let pid = match pid {
+            Pid::Value(pid) => pid,
+            Pid::None | Pid::Any => return,
+        };
+        let mut epochs = self.lock();
+        if epochs.stamp == stamp {
+            let epoch = epochs.epochs.get(name).cloned().unwrap_or(0);
+            epochs.owners.insert(pid, (name.to_string(), epoch));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Epochs> {
+        // Each change is made whole while the lock is held, so a panic while
+        // it was held cannot have left the epochs inconsistent.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolved_processes_are_remembered_until_the_module_changes() {
+        let epochs = ModuleEpochs::new();
+        let stamp = epochs.stamp();
+        epochs.remember(Pid::Value(123), "abc", stamp);
+        epochs.remember(Pid::Value(987), "xyz", stamp);
+        assert_eq!(Some("abc".to_string()), epochs.owner(Pid::Value(123)));
+
+        epochs.bump("abc");
+
+        assert_eq!(None, epochs.owner(Pid::Value(123)));
+        assert_eq!(Some("xyz".to_string()), epochs.owner(Pid::Value(987)));
+        assert_eq!(1, epochs.epoch("abc"));
+    }
+
+    #[test]
+    fn resolutions_that_raced_with_a_change_are_not_remembered() {
+        let epochs = ModuleEpochs::new();
+        let stamp = epochs.stamp();
+        epochs.bump("abc");
+        epochs.remember(Pid::Value(123), "abc", stamp);
+        assert_eq!(None, epochs.owner(Pid::Value(123)));
+    }
+
+    #[test]
+    fn new_processes_bump_the_epoch() {
+        let epochs = ModuleEpochs::new();
+        epochs.observe("abc", Pid::Value(123));
+        epochs.observe("abc", Pid::Value(123));
+        assert_eq!(0, epochs.epoch("abc"));
+
+        epochs.observe("abc", Pid::Value(456));
+        assert_eq!(1, epochs.epoch("abc"));
+
+        epochs.observe("abc", Pid::None);
+        assert_eq!(2, epochs.epoch("abc"));
+        epochs.observe("abc", Pid::Value(456));
+        assert_eq!(2, epochs.epoch("abc"));
+    }
+}
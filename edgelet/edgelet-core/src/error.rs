@@ -8,6 +8,8 @@ use edgelet_utils::Error as UtilsError;
 use failure::{Backtrace, Context, Fail};
 use tokio;
 
+use crypto::SignatureAlgorithm;
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -28,6 +30,8 @@ pub enum ErrorKind {
     Sign(usize),
     #[fail(display = "A error occurred retrieving a key from the key store.")]
     KeyStore,
+    #[fail(display = "Signature algorithm {} is not supported by the key", _0)]
+    UnsupportedSignatureAlgorithm(SignatureAlgorithm),
     #[fail(display = "Item not found.")]
     NotFound,
     #[fail(display = "Utils error")]
@@ -48,8 +52,21 @@ pub enum ErrorKind {
     TokioTimer,
     #[fail(display = "Parse error")]
     Parse,
+    #[fail(
+        display = "Invalid log tail \"{}\", expected \"all\" or a number of lines",
+        _0
+    )]
+    InvalidLogTail(String),
+    #[fail(display = "Invalid signal {:?}", _0)]
+    InvalidSignal(String),
+    #[fail(display = "Signal {} may not be sent to modules", _0)]
+    SignalNotAllowed(String),
     #[fail(display = "Http error")]
     Http,
+    #[fail(display = "Could not read the registry credentials")]
+    RegistryCredentials,
+    #[fail(display = "The ciphertext was tampered with or is corrupt")]
+    PayloadTampered,
 }
 
 impl Fail for Error {
@@ -0,0 +1,388 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Changes of the status of modules, broadcast to local listeners such as the
+//! management API's event stream.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::{self, Loop};
+use futures::sync::mpsc::{self, Receiver, Sender};
+use futures::{Future, Poll, Stream};
+use log::Level;
+
+use edgelet_utils::log_failure;
+
+use clock::Clock;
+use error::Error;
+use module::{Module, ModuleRuntime, ModuleRuntimeState, ModuleStatus};
+
+/// How often `watch_modules` checks on the modules while anyone listens.
+pub const DEFAULT_WATCH_INTERVAL_SECS: u64 = 2;
+
+/// A module whose status changed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModuleStateChange {
+    name: String,
+    /// `None` for a module that wasn't there before.
+    #[serde(rename = "oldStatus")]
+    old_status: Option<ModuleStatus>,
+    #[serde(rename = "newStatus")]
+    new_status: ModuleStatus,
+    #[serde(rename = "exitCode", skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i64>,
+    timestamp: DateTime<Utc>,
+}
+
+impl ModuleStateChange {
+    pub fn new(
+        name: String,
+        old_status: Option<ModuleStatus>,
+        new_status: ModuleStatus,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        ModuleStateChange {
+            name,
+            old_status,
+            new_status,
+            exit_code: None,
+            timestamp,
+        }
+    }
+
+    pub fn with_exit_code(mut self, exit_code: Option<i64>) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn old_status(&self) -> Option<&ModuleStatus> {
+        self.old_status.as_ref()
+    }
+
+    pub fn new_status(&self) -> &ModuleStatus {
+        &self.new_status
+    }
+
+    pub fn exit_code(&self) -> Option<i64> {
+        self.exit_code
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    next_id: u64,
+    senders: HashMap<u64, Sender<ModuleStateChange>>,
+    /// The status of each module when they were last observed, or `None`
+    /// while nobody listens.
+    statuses: Option<HashMap<String, ModuleStatus>>,
+}
+
+/// Hands every change of a module's status to each subscriber. A subscriber
+/// that doesn't keep up is dropped, which ends its stream once it has read
+/// what was buffered for it, rather than changes piling up for it without
+/// bound. Clones share the subscribers.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleEvents {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl ModuleEvents {
+    pub fn new() -> Self {
+        ModuleEvents::default()
+    }
+
+    /// Subscribes to the changes published from now on. At most `buffer`
+    /// changes are held for the subscriber before it is dropped.
+    pub fn subscribe(&self, buffer: usize) -> Subscription {
+        let (sender, changes) = mpsc::channel(buffer);
+        let mut subscribers = self.lock();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.senders.insert(id, sender);
+        Subscription {
+            id,
+            changes,
+            subscribers: Arc::downgrade(&self.subscribers),
+        }
+    }
+
+    /// How many subscribers there are.
+    pub fn subscribers(&self) -> usize {
+        self.lock().senders.len()
+    }
+
+    pub fn publish(&self, change: &ModuleStateChange) {
+        let mut subscribers = self.lock();
+        subscribers
+            .senders
+            .retain(|_, sender| match sender.try_send(change.clone()) {
+                Ok(()) => true,
+                Err(ref err) if err.is_full() => {
+                    warn!("Dropping a subscriber to module events that fell behind");
+                    false
+                }
+                Err(_) => false,
+            });
+    }
+
+    /// Publishes a change for each module whose status differs from when the
+    /// modules were last observed. While nobody listens nothing is kept, and
+    /// the first observation after that is only remembered, so subscribers
+    /// don't get every module as a change when they subscribe.
+    pub fn observe(&self, modules: &[(String, ModuleRuntimeState)], at: DateTime<Utc>) {
+        let changes = {
+            let mut subscribers = self.lock();
+            if subscribers.senders.is_empty() {
+                subscribers.statuses = None;
+                return;
+            }
+
+            let current: HashMap<String, ModuleStatus> = modules
+                .iter()
+                .map(|&(ref name, ref state)| (name.clone(), *state.status()))
+                .collect();
+            let changes = match subscribers.statuses {
+                None => vec![],
+                Some(ref previous) => modules
+                    .iter()
+                    .filter(|&&(ref name, ref state)| previous.get(name) != Some(state.status()))
+                    .map(|&(ref name, ref state)| {
+                        ModuleStateChange::new(
+                            name.clone(),
+                            previous.get(name).cloned(),
+                            *state.status(),
+                            at,
+                        ).with_exit_code(state.exit_code())
+                    }).collect(),
+            };
+            subscribers.statuses = Some(current);
+            changes
+        };
+
+        for change in &changes {
+            self.publish(change);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Subscribers> {
+        // The subscribers are only ever added or removed whole, so a panic
+        // while the lock was held cannot have left them inconsistent.
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// The changes published to a subscriber. Dropping the subscription
+/// unsubscribes.
+#[derive(Debug)]
+pub struct Subscription {
+    id: u64,
+    changes: Receiver<ModuleStateChange>,
+    subscribers: Weak<Mutex<Subscribers>>,
+}
+
+impl Stream for Subscription {
+    type Item = ModuleStateChange;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.changes.poll()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            let mut subscribers = subscribers.lock().unwrap_or_else(|err| err.into_inner());
+            subscribers.senders.remove(&self.id);
+        }
+    }
+}
+
+/// Checks on the modules every `interval` while anyone is subscribed to
+/// `events`, and publishes the changes of their status. Failing to list the
+/// modules is logged and tried again at the next check.
+pub fn watch_modules<M>(
+    runtime: M,
+    events: ModuleEvents,
+    clock: Arc<Clock>,
+    interval: Duration,
+) -> impl Future<Item = (), Error = Error>
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+{
+    future::loop_fn((), move |()| {
+        let events = events.clone();
+        let clock_copy = clock.clone();
+        let observed = if events.subscribers() == 0 {
+            events.observe(&[], clock.now());
+            future::Either::A(future::ok(()))
+        } else {
+            let listed = runtime
+                .list_with_details()
+                .map(|(module, state)| (module.name().to_string(), state))
+                .collect()
+                .map(move |modules| events.observe(&modules, clock_copy.now()))
+                .or_else(|err| {
+                    let err: Error = err.into();
+                    warn!("Could not check on modules for module events:");
+                    log_failure(Level::Warn, &err);
+                    Ok::<(), Error>(())
+                });
+            future::Either::B(listed)
+        };
+        let clock = clock.clone();
+        observed
+            .and_then(move |()| clock.delay(clock.instant() + interval).map_err(Error::from))
+            .map(|()| Loop::<(), ()>::Continue(()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use futures::Async;
+
+    use super::*;
+
+    fn at() -> DateTime<Utc> {
+        Utc.ymd(2018, 10, 15).and_hms(14, 0, 0)
+    }
+
+    fn state(status: ModuleStatus, exit_code: Option<i64>) -> ModuleRuntimeState {
+        ModuleRuntimeState::default()
+            .with_status(status)
+            .with_exit_code(exit_code)
+    }
+
+    fn modules(modules: &[(&str, ModuleStatus)]) -> Vec<(String, ModuleRuntimeState)> {
+        modules
+            .iter()
+            .map(|&(name, status)| (name.to_string(), state(status, None)))
+            .collect()
+    }
+
+    fn ready(subscription: &mut Subscription) -> Vec<ModuleStateChange> {
+        let mut changes = vec![];
+        while let Ok(Async::Ready(Some(change))) = subscription.poll() {
+            changes.push(change);
+        }
+        changes
+    }
+
+    #[test]
+    fn changes_of_status_are_published() {
+        let events = ModuleEvents::new();
+        let mut subscription = events.subscribe(16);
+        events.observe(
+            &modules(&[
+                ("edgeHub", ModuleStatus::Running),
+                ("sensor", ModuleStatus::Running),
+            ]),
+            at(),
+        );
+
+        let mut observed = modules(&[
+            ("edgeHub", ModuleStatus::Running),
+            ("opcua", ModuleStatus::Stopped),
+        ]);
+        observed.push(("sensor".to_string(), state(ModuleStatus::Failed, Some(137))));
+        events.observe(&observed, at());
+
+        assert_eq!(
+            vec![
+                ModuleStateChange::new(
+                    "opcua".to_string(),
+                    None,
+                    ModuleStatus::Stopped,
+                    at()
+                ),
+                ModuleStateChange::new(
+                    "sensor".to_string(),
+                    Some(ModuleStatus::Running),
+                    ModuleStatus::Failed,
+                    at()
+                ).with_exit_code(Some(137)),
+            ],
+            ready(&mut subscription)
+        );
+    }
+
+    #[test]
+    fn nothing_is_kept_while_nobody_listens() {
+        let events = ModuleEvents::new();
+        events.observe(&modules(&[("sensor", ModuleStatus::Running)]), at());
+
+        let mut subscription = events.subscribe(16);
+        events.observe(&modules(&[("sensor", ModuleStatus::Stopped)]), at());
+        events.observe(&modules(&[("sensor", ModuleStatus::Stopped)]), at());
+
+        assert!(ready(&mut subscription).is_empty());
+    }
+
+    #[test]
+    fn dropped_subscriptions_unsubscribe() {
+        let events = ModuleEvents::new();
+        let first = events.subscribe(16);
+        let second = events.subscribe(16);
+        assert_eq!(2, events.subscribers());
+
+        drop(first);
+        assert_eq!(1, events.subscribers());
+        drop(second);
+        assert_eq!(0, events.subscribers());
+    }
+
+    #[test]
+    fn subscribers_that_fall_behind_are_dropped() {
+        let events = ModuleEvents::new();
+        let mut slow = events.subscribe(1);
+        let mut fast = events.subscribe(16);
+
+        for status in &[
+            ModuleStatus::Running,
+            ModuleStatus::Stopped,
+            ModuleStatus::Running,
+            ModuleStatus::Stopped,
+        ] {
+            let change = ModuleStateChange::new("sensor".to_string(), None, *status, at());
+            events.publish(&change);
+            assert_eq!(Ok(Async::Ready(Some(change))), fast.poll());
+        }
+
+        assert_eq!(1, events.subscribers());
+        // The slow subscriber gets what was buffered for it, then its
+        // stream ends.
+        let buffered = ready(&mut slow);
+        assert!(!buffered.is_empty() && buffered.len() < 4);
+        assert_eq!(Ok(Async::Ready(None)), slow.poll());
+    }
+
+    #[test]
+    fn changes_serialize_as_events() {
+        let change = ModuleStateChange::new(
+            "sensor".to_string(),
+            Some(ModuleStatus::Running),
+            ModuleStatus::Failed,
+            at(),
+        ).with_exit_code(Some(1));
+
+        let expected = concat!(
+            r#"{"name":"sensor","oldStatus":"running","newStatus":"failed","#,
+            r#""exitCode":1,"timestamp":"2018-10-15T14:00:00Z"}"#
+        );
+        assert_eq!(expected, ::serde_json::to_string(&change).unwrap());
+    }
+}
@@ -0,0 +1,372 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+
+/// How old a check may get before it no longer says anything about the
+/// subsystem. The daemon checks its subsystems about once a minute.
+const DEFAULT_STALE_AFTER_SECS: u64 = 180;
+
+/// The state of one of the daemon's subsystems.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubsystemStatus {
+    Ok,
+    Down,
+    /// Not checked yet.
+    Unknown,
+    /// Not checked for longer than the staleness limit.
+    Stale,
+}
+
+impl fmt::Display for SubsystemStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            SubsystemStatus::Ok => "ok",
+            SubsystemStatus::Down => "down",
+            SubsystemStatus::Unknown => "unknown",
+            SubsystemStatus::Stale => "stale",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// The state of the daemon as a whole. It is `Degraded` when only
+/// non-critical subsystems are not ok.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Unhealthy => "unhealthy",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubsystemHealth {
+    status: SubsystemStatus,
+    last_checked: Option<DateTime<Utc>>,
+    message: Option<String>,
+}
+
+impl SubsystemHealth {
+    pub fn status(&self) -> SubsystemStatus {
+        self.status
+    }
+
+    pub fn last_checked(&self) -> Option<DateTime<Utc>> {
+        self.last_checked
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(AsRef::as_ref)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthReport {
+    status: HealthStatus,
+    uptime: Duration,
+    runtime: SubsystemHealth,
+    identity: SubsystemHealth,
+    provisioning: SubsystemHealth,
+    watchdog: SubsystemHealth,
+    integrity: SubsystemHealth,
+}
+
+impl HealthReport {
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.uptime
+    }
+
+    /// Whether the container runtime answered.
+    pub fn runtime(&self) -> &SubsystemHealth {
+        &self.runtime
+    }
+
+    /// Whether the identity backend answered. This is not critical, since
+    /// modules keep running while it is unreachable.
+    pub fn identity(&self) -> &SubsystemHealth {
+        &self.identity
+    }
+
+    /// Whether the device is provisioned.
+    pub fn provisioning(&self) -> &SubsystemHealth {
+        &self.provisioning
+    }
+
+    /// When the watchdog last checked the edge runtime module.
+    pub fn watchdog(&self) -> &SubsystemHealth {
+        &self.watchdog
+    }
+
+    /// Whether the modules that exist are the ones the runtime last recorded
+    /// creating. Only discrepancies make the daemon degraded; a runtime that
+    /// keeps no record is never checked.
+    pub fn integrity(&self) -> &SubsystemHealth {
+        &self.integrity
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Check {
+    at: Option<DateTime<Utc>>,
+    error: Option<String>,
+}
+
+impl Check {
+    fn record(&mut self, result: Result<(), String>, now: DateTime<Utc>) {
+        self.at = Some(now);
+        self.error = result.err();
+    }
+
+    fn health(&self, now: DateTime<Utc>, stale_after: ChronoDuration) -> SubsystemHealth {
+        let status = match self.at {
+            None => SubsystemStatus::Unknown,
+            Some(at) if now.signed_duration_since(at) > stale_after => SubsystemStatus::Stale,
+            Some(_) if self.error.is_some() => SubsystemStatus::Down,
+            Some(_) => SubsystemStatus::Ok,
+        };
+        SubsystemHealth {
+            status,
+            last_checked: self.at,
+            message: self.error.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    started: DateTime<Utc>,
+    runtime: Check,
+    identity: Check,
+    provisioned: Option<bool>,
+    watchdog: Check,
+    integrity: Check,
+}
+
+/// The last known state of the daemon's subsystems. Background tasks record
+/// what they find, so that reporting the health of the daemon never waits
+/// on the network.
+#[derive(Clone, Debug)]
+pub struct DaemonHealth {
+    state: Arc<RwLock<State>>,
+    stale_after: Duration,
+}
+
+impl DaemonHealth {
+    pub fn new(started: DateTime<Utc>) -> Self {
+        DaemonHealth {
+            state: Arc::new(RwLock::new(State {
+                started,
+                runtime: Check::default(),
+                identity: Check::default(),
+                provisioned: None,
+                watchdog: Check::default(),
+                integrity: Check::default(),
+            })),
+            stale_after: Duration::from_secs(DEFAULT_STALE_AFTER_SECS),
+        }
+    }
+
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after
+    }
+
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    pub fn record_runtime(&self, result: Result<(), String>, now: DateTime<Utc>) {
+        self.write(|state| state.runtime.record(result, now));
+    }
+
+    pub fn record_identity(&self, result: Result<(), String>, now: DateTime<Utc>) {
+        self.write(|state| state.identity.record(result, now));
+    }
+
+    pub fn record_provisioned(&self, provisioned: bool) {
+        self.write(|state| state.provisioned = Some(provisioned));
+    }
+
+    pub fn record_watchdog_run(&self, now: DateTime<Utc>) {
+        self.write(|state| state.watchdog.record(Ok(()), now));
+    }
+
+    pub fn record_integrity(&self, result: Result<(), String>, now: DateTime<Utc>) {
+        self.write(|state| state.integrity.record(result, now));
+    }
+
+    pub fn report(&self, now: DateTime<Utc>) -> HealthReport {
+        let state = self.state.read().unwrap_or_else(|err| err.into_inner());
+        let stale_after = ChronoDuration::from_std(self.stale_after)
+            .unwrap_or_else(|_| ChronoDuration::max_value());
+
+        let provisioning = SubsystemHealth {
+            status: match state.provisioned {
+                Some(true) => SubsystemStatus::Ok,
+                Some(false) => SubsystemStatus::Down,
+                None => SubsystemStatus::Unknown,
+            },
+            last_checked: None,
+            message: match state.provisioned {
+                Some(false) => Some("Device is not provisioned".to_string()),
+                _ => None,
+            },
+        };
+        let runtime = state.runtime.health(now, stale_after);
+        let identity = state.identity.health(now, stale_after);
+        let watchdog = state.watchdog.health(now, stale_after);
+        let integrity = state.integrity.health(now, stale_after);
+
+        let status = if [&runtime, &provisioning, &watchdog]
+            .iter()
+            .any(|subsystem| subsystem.status != SubsystemStatus::Ok)
+        {
+            HealthStatus::Unhealthy
+        } else if identity.status != SubsystemStatus::Ok
+            || integrity.status == SubsystemStatus::Down
+        {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        };
+
+        HealthReport {
+            status,
+            uptime: now
+                .signed_duration_since(state.started)
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0)),
+            runtime,
+            identity,
+            provisioning,
+            watchdog,
+            integrity,
+        }
+    }
+
+    fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut State),
+    {
+        // Every field is replaced as a whole, so a panic while the lock was
+        // held cannot have left the state inconsistent.
+        let mut state = self.state.write().unwrap_or_else(|err| err.into_inner());
+        f(&mut state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy(started: DateTime<Utc>) -> DaemonHealth {
+        let health = DaemonHealth::new(started);
+        health.record_runtime(Ok(()), started);
+        health.record_identity(Ok(()), started);
+        health.record_provisioned(true);
+        health.record_watchdog_run(started);
+        health
+    }
+
+    #[test]
+    fn nothing_checked_yet_is_unhealthy() {
+        let started = Utc::now();
+        let report = DaemonHealth::new(started).report(started);
+        assert_eq!(HealthStatus::Unhealthy, report.status());
+        assert_eq!(SubsystemStatus::Unknown, report.runtime().status());
+        assert_eq!(SubsystemStatus::Unknown, report.provisioning().status());
+        assert_eq!(None, report.watchdog().last_checked());
+    }
+
+    #[test]
+    fn all_subsystems_ok_is_healthy() {
+        let started = Utc::now();
+        let report = healthy(started).report(started + ChronoDuration::seconds(30));
+        assert_eq!(HealthStatus::Healthy, report.status());
+        assert_eq!(Duration::from_secs(30), report.uptime());
+        assert_eq!(SubsystemStatus::Ok, report.runtime().status());
+        assert_eq!(Some(started), report.watchdog().last_checked());
+    }
+
+    #[test]
+    fn unreachable_identity_backend_is_degraded() {
+        let started = Utc::now();
+        let health = healthy(started);
+        health.record_identity(Err("connection refused".to_string()), started);
+
+        let report = health.report(started);
+        assert_eq!(HealthStatus::Degraded, report.status());
+        assert_eq!(SubsystemStatus::Down, report.identity().status());
+        assert_eq!(Some("connection refused"), report.identity().message());
+    }
+
+    #[test]
+    fn unreachable_runtime_is_unhealthy() {
+        let started = Utc::now();
+        let health = healthy(started);
+        health.record_runtime(Err("no such file".to_string()), started);
+        assert_eq!(HealthStatus::Unhealthy, health.report(started).status());
+
+        health.record_runtime(Ok(()), started);
+        let report = health.report(started);
+        assert_eq!(HealthStatus::Healthy, report.status());
+        assert_eq!(None, report.runtime().message());
+    }
+
+    #[test]
+    fn module_discrepancies_are_degraded() {
+        let started = Utc::now();
+        let health = healthy(started);
+        assert_eq!(
+            SubsystemStatus::Unknown,
+            health.report(started).integrity().status()
+        );
+
+        health.record_integrity(Err("module tempSensor is missing".to_string()), started);
+        let report = health.report(started);
+        assert_eq!(HealthStatus::Degraded, report.status());
+        assert_eq!(
+            Some("module tempSensor is missing"),
+            report.integrity().message()
+        );
+
+        health.record_integrity(Ok(()), started);
+        assert_eq!(HealthStatus::Healthy, health.report(started).status());
+    }
+
+    #[test]
+    fn old_checks_are_stale() {
+        let started = Utc::now();
+        let health = healthy(started).with_stale_after(Duration::from_secs(60));
+        assert_eq!(
+            HealthStatus::Healthy,
+            health.report(started + ChronoDuration::seconds(60)).status()
+        );
+
+        let later = started + ChronoDuration::seconds(61);
+        health.record_runtime(Ok(()), later);
+        health.record_identity(Ok(()), later);
+        let report = health.report(later);
+        assert_eq!(HealthStatus::Unhealthy, report.status());
+        assert_eq!(SubsystemStatus::Stale, report.watchdog().status());
+        assert_eq!(SubsystemStatus::Ok, report.runtime().status());
+    }
+}
@@ -1,9 +1,19 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::collections::HashSet;
 use std::fmt;
+use std::usize;
 
 use failure::Fail;
-use futures::Future;
+use futures::future::Either;
+use futures::{stream, Future, Stream};
+
+/// The `managedBy` value of the identities iotedge creates. `sync` leaves
+/// identities managed by anything else alone.
+pub const MANAGED_BY_IOTEDGE: &str = "iotedge";
+
+/// How many identities `sync` creates or deletes at once.
+const SYNC_PARALLELISM: usize = 4;
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum AuthType {
@@ -68,6 +78,97 @@ impl IdentitySpec {
     }
 }
 
+/// Which identities `IdentityManager::list_with_filter` returns, in order of
+/// their module ids: those managed by `managed_by`, starting after the module
+/// id `after`, without the first `skip` of them and at most `top` of them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdentityFilter {
+    managed_by: Option<String>,
+    after: Option<String>,
+    skip: usize,
+    top: Option<usize>,
+}
+
+impl IdentityFilter {
+    pub fn new() -> Self {
+        IdentityFilter::default()
+    }
+
+    /// Only identities whose `managedBy` is exactly this, like
+    /// `MANAGED_BY_IOTEDGE` for the identities iotedge manages.
+    pub fn managed_by(&self) -> Option<&str> {
+        self.managed_by.as_ref().map(String::as_str)
+    }
+
+    pub fn with_managed_by(mut self, managed_by: Option<String>) -> Self {
+        self.managed_by = managed_by;
+        self
+    }
+
+    /// Only identities whose module ids come after this one.
+    pub fn after(&self) -> Option<&str> {
+        self.after.as_ref().map(String::as_str)
+    }
+
+    pub fn with_after(mut self, after: Option<String>) -> Self {
+        self.after = after;
+        self
+    }
+
+    pub fn skip(&self) -> usize {
+        self.skip
+    }
+
+    pub fn with_skip(mut self, skip: usize) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    pub fn top(&self) -> Option<usize> {
+        self.top
+    }
+
+    pub fn with_top(mut self, top: Option<usize>) -> Self {
+        self.top = top;
+        self
+    }
+
+    pub fn matches<I: Identity>(&self, identity: &I) -> bool {
+        self.managed_by
+            .as_ref()
+            .map_or(true, |managed_by| identity.managed_by() == managed_by)
+            && self
+                .after
+                .as_ref()
+                .map_or(true, |after| identity.module_id() > after.as_str())
+    }
+
+    /// Applies the filter to all the identities there are.
+    pub fn apply<I: Identity>(&self, identities: Vec<I>) -> Vec<I> {
+        let mut identities: Vec<I> = identities
+            .into_iter()
+            .filter(|identity| self.matches(identity))
+            .collect();
+        identities.sort_by(|a, b| a.module_id().cmp(b.module_id()));
+        identities
+            .into_iter()
+            .skip(self.skip)
+            .take(self.top.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// Tells why an `IdentityManager` failed where callers handle the reason.
+pub trait IdentityError {
+    /// Whether an identity could not be created because one with the same
+    /// module id already exists.
+    fn is_conflict(&self) -> bool;
+
+    /// Whether an identity was left alone because its generation id is not
+    /// the one the change was made on condition of.
+    fn is_stale(&self) -> bool;
+}
+
 pub trait IdentityManager {
     type Identity: Identity;
     type Error: Fail;
@@ -76,10 +177,462 @@ pub trait IdentityManager {
     type ListFuture: Future<Item = Vec<Self::Identity>, Error = Self::Error> + Send;
     type GetFuture: Future<Item = Option<Self::Identity>, Error = Self::Error> + Send;
     type DeleteFuture: Future<Item = (), Error = Self::Error> + Send;
+    type RotateFuture: Future<Item = Self::Identity, Error = Self::Error> + Send;
 
     fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture;
     fn update(&mut self, id: IdentitySpec) -> Self::UpdateFuture;
     fn list(&self) -> Self::ListFuture;
     fn get(&self, id: IdentitySpec) -> Self::GetFuture;
+
+    /// Deletes an identity. When `id` has a generation id, the identity is
+    /// only deleted if it still has that generation id, so a request made
+    /// before the identity was created anew doesn't delete the new one.
     fn delete(&mut self, id: IdentitySpec) -> Self::DeleteFuture;
+
+    /// Replaces the keys of an existing identity. The identity comes back
+    /// with a new generation id, so anything still holding the old one (and
+    /// the keys derived from it) is no longer recognized.
+    fn rotate_keys(&mut self, id: IdentitySpec) -> Self::RotateFuture;
+
+    /// Lists the identities `filter` selects. Managers whose backend can't
+    /// filter them list them all and filter them here.
+    fn list_with_filter(
+        &self,
+        filter: IdentityFilter,
+    ) -> Box<Future<Item = Vec<Self::Identity>, Error = Self::Error> + Send>
+    where
+        Self: 'static,
+    {
+        Box::new(self.list().map(move |identities| filter.apply(identities)))
+    }
+
+    /// Creates the identities in `desired` that do not exist yet and deletes
+    /// those managed by iotedge that are not in it, a few at a time. Failing
+    /// to create or delete an identity is reported rather than failing the
+    /// sync as a whole.
+    fn sync(
+        &self,
+        desired: Vec<IdentitySpec>,
+    ) -> Box<Future<Item = SyncReport, Error = Self::Error> + Send>
+    where
+        Self: 'static + Clone + Send,
+    {
+        let manager = self.clone();
+        let result = self.list().and_then(move |current| {
+            let mut report = SyncReport::default();
+            let mut changes = vec![];
+
+            let desired_ids: HashSet<String> = desired
+                .iter()
+                .map(|spec| spec.module_id().to_string())
+                .collect();
+            for identity in &current {
+                if !is_managed_by_iotedge(identity) {
+                    report.skipped.push(identity.module_id().to_string());
+                } else if !desired_ids.contains(identity.module_id()) {
+                    changes.push(Change::Delete(identity.module_id().to_string()));
+                }
+            }
+
+            let mut seen = HashSet::new();
+            for spec in desired {
+                let exists = current
+                    .iter()
+                    .any(|identity| identity.module_id() == spec.module_id());
+                if !exists && seen.insert(spec.module_id().to_string()) {
+                    let spec = if spec.managed_by().is_some() {
+                        spec
+                    } else {
+                        spec.with_managed_by(MANAGED_BY_IOTEDGE.to_string())
+                    };
+                    changes.push(Change::Create(spec));
+                }
+            }
+
+            let changes = changes.into_iter().map(move |change| {
+                let mut manager = manager.clone();
+                let (module_id, created, result) = match change {
+                    Change::Create(spec) => (
+                        spec.module_id().to_string(),
+                        true,
+                        Either::A(manager.create(spec).map(|_| ())),
+                    ),
+                    Change::Delete(module_id) => (
+                        module_id.clone(),
+                        false,
+                        Either::B(manager.delete(IdentitySpec::new(&module_id))),
+                    ),
+                };
+                result.then(move |result| {
+                    Ok((module_id, created, result.map_err(|err| err.to_string())))
+                })
+            });
+            stream::iter_ok(changes)
+                .buffer_unordered(SYNC_PARALLELISM)
+                .collect()
+                .map(move |results| {
+                    for (module_id, created, result) in results {
+                        match result {
+                            Ok(()) if created => report.created.push(module_id),
+                            Ok(()) => report.removed.push(module_id),
+                            Err(err) => report.failed.push((module_id, err)),
+                        }
+                    }
+                    report.sort();
+                    report
+                })
+        });
+        Box::new(result)
+    }
+}
+
+fn is_managed_by_iotedge<I: Identity>(identity: &I) -> bool {
+    identity.managed_by().eq_ignore_ascii_case(MANAGED_BY_IOTEDGE)
+}
+
+enum Change {
+    Create(IdentitySpec),
+    Delete(String),
+}
+
+/// What `IdentityManager::sync` did, by module id.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    created: Vec<String>,
+    removed: Vec<String>,
+    skipped: Vec<String>,
+    failed: Vec<(String, String)>,
+}
+
+impl SyncReport {
+    pub fn created(&self) -> &[String] {
+        &self.created
+    }
+
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+
+    /// Identities left alone because they are not managed by iotedge.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Identities that could not be created or deleted, with the error.
+    pub fn failed(&self) -> &[(String, String)] {
+        &self.failed
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    fn sort(&mut self) {
+        self.created.sort();
+        self.removed.sort();
+        self.skipped.sort();
+        self.failed.sort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cmp;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "Could not change {}", _0)]
+    struct TestError(String);
+
+    struct TestIdentity {
+        module_id: String,
+        managed_by: String,
+    }
+
+    impl Identity for TestIdentity {
+        fn module_id(&self) -> &str {
+            &self.module_id
+        }
+
+        fn managed_by(&self) -> &str {
+            &self.managed_by
+        }
+
+        fn generation_id(&self) -> &str {
+            "1"
+        }
+
+        fn auth_type(&self) -> AuthType {
+            AuthType::Sas
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        identities: Vec<(String, String)>,
+        failing: Vec<String>,
+        in_flight: usize,
+        max_in_flight: usize,
+    }
+
+    /// An identity manager whose changes take a while and fail for the
+    /// module ids it is told to fail for.
+    #[derive(Clone, Default)]
+    struct TestIdentityManager {
+        state: Arc<Mutex<State>>,
+    }
+
+    impl TestIdentityManager {
+        fn new(identities: &[(&str, &str)]) -> Self {
+            let manager = TestIdentityManager::default();
+            manager.state.lock().unwrap().identities = identities
+                .iter()
+                .map(|&(id, managed_by)| (id.to_string(), managed_by.to_string()))
+                .collect();
+            manager
+        }
+
+        fn with_failing(self, module_id: &str) -> Self {
+            self.state.lock().unwrap().failing.push(module_id.to_string());
+            self
+        }
+
+        fn module_ids(&self) -> Vec<String> {
+            let mut ids: Vec<String> = self
+                .state
+                .lock()
+                .unwrap()
+                .identities
+                .iter()
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.sort();
+            ids
+        }
+
+        fn change<F>(
+            &self,
+            module_id: String,
+            apply: F,
+        ) -> Box<Future<Item = (), Error = TestError> + Send>
+        where
+            F: 'static + FnOnce(&mut State) + Send,
+        {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.in_flight += 1;
+                state.max_in_flight = cmp::max(state.max_in_flight, state.in_flight);
+            }
+            let state = self.state.clone();
+            Box::new(
+                Delay::new(Instant::now() + Duration::from_millis(10)).then(move |_| {
+                    let mut state = state.lock().unwrap();
+                    state.in_flight -= 1;
+                    if state.failing.contains(&module_id) {
+                        Err(TestError(module_id))
+                    } else {
+                        apply(&mut state);
+                        Ok(())
+                    }
+                }),
+            )
+        }
+    }
+
+    impl IdentityManager for TestIdentityManager {
+        type Identity = TestIdentity;
+        type Error = TestError;
+        type CreateFuture = Box<Future<Item = Self::Identity, Error = Self::Error> + Send>;
+        type UpdateFuture = Box<Future<Item = Self::Identity, Error = Self::Error> + Send>;
+        type ListFuture = Box<Future<Item = Vec<Self::Identity>, Error = Self::Error> + Send>;
+        type GetFuture = Box<Future<Item = Option<Self::Identity>, Error = Self::Error> + Send>;
+        type DeleteFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+        type RotateFuture = Box<Future<Item = Self::Identity, Error = Self::Error> + Send>;
+
+        fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture {
+            let identity = TestIdentity {
+                module_id: id.module_id().to_string(),
+                managed_by: id.managed_by().unwrap_or("").to_string(),
+            };
+            let entry = (identity.module_id.clone(), identity.managed_by.clone());
+            Box::new(
+                self.change(id.module_id().to_string(), move |state| {
+                    state.identities.push(entry)
+                }).map(|_| identity),
+            )
+        }
+
+        fn update(&mut self, _id: IdentitySpec) -> Self::UpdateFuture {
+            unimplemented!()
+        }
+
+        fn list(&self) -> Self::ListFuture {
+            let identities = self
+                .state
+                .lock()
+                .unwrap()
+                .identities
+                .iter()
+                .map(|(module_id, managed_by)| TestIdentity {
+                    module_id: module_id.clone(),
+                    managed_by: managed_by.clone(),
+                }).collect();
+            Box::new(future::ok(identities))
+        }
+
+        fn get(&self, _id: IdentitySpec) -> Self::GetFuture {
+            unimplemented!()
+        }
+
+        fn delete(&mut self, id: IdentitySpec) -> Self::DeleteFuture {
+            let module_id = id.module_id().to_string();
+            self.change(module_id.clone(), move |state| {
+                state.identities.retain(|(id, _)| *id != module_id)
+            })
+        }
+
+        fn rotate_keys(&mut self, _id: IdentitySpec) -> Self::RotateFuture {
+            unimplemented!()
+        }
+    }
+
+    fn specs(ids: &[&str]) -> Vec<IdentitySpec> {
+        ids.iter().map(|id| IdentitySpec::new(id)).collect()
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn list_with_filter_filters_listed_identities() {
+        let manager = TestIdentityManager::new(&[
+            ("m3", MANAGED_BY_IOTEDGE),
+            ("m1", MANAGED_BY_IOTEDGE),
+            ("other", "someone else"),
+            ("m2", "IoTEdge"),
+            ("m4", MANAGED_BY_IOTEDGE),
+        ]);
+        let list = |filter: IdentityFilter| -> Vec<String> {
+            manager
+                .list_with_filter(filter)
+                .wait()
+                .unwrap()
+                .iter()
+                .map(|identity| identity.module_id().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            strings(&["m1", "m2", "m3", "m4", "other"]),
+            list(IdentityFilter::new())
+        );
+        assert_eq!(
+            strings(&["m1", "m3", "m4"]),
+            list(IdentityFilter::new().with_managed_by(Some(MANAGED_BY_IOTEDGE.to_string())))
+        );
+        assert_eq!(
+            strings(&["m3"]),
+            list(
+                IdentityFilter::new()
+                    .with_managed_by(Some(MANAGED_BY_IOTEDGE.to_string()))
+                    .with_skip(1)
+                    .with_top(Some(1))
+            )
+        );
+        assert_eq!(
+            strings(&["m4", "other"]),
+            list(IdentityFilter::new().with_after(Some("m3".to_string())))
+        );
+    }
+
+    #[test]
+    fn sync_creates_and_removes_identities() {
+        let manager = TestIdentityManager::new(&[
+            ("m1", MANAGED_BY_IOTEDGE),
+            ("m2", MANAGED_BY_IOTEDGE),
+            ("$edgeAgent", ""),
+            ("other", "someone else"),
+        ]);
+
+        let report = Runtime::new()
+            .unwrap()
+            .block_on(manager.sync(specs(&["m2", "m3", "m4", "m3"])))
+            .unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(strings(&["m3", "m4"]), report.created());
+        assert_eq!(strings(&["m1"]), report.removed());
+        assert_eq!(strings(&["$edgeAgent", "other"]), report.skipped());
+        assert_eq!(
+            strings(&["$edgeAgent", "m2", "m3", "m4", "other"]),
+            manager.module_ids()
+        );
+
+        // Identities sync creates are managed by iotedge, so a later sync
+        // can remove them.
+        let report = Runtime::new()
+            .unwrap()
+            .block_on(manager.sync(specs(&[])))
+            .unwrap();
+        assert_eq!(strings(&["m2", "m3", "m4"]), report.removed());
+    }
+
+    #[test]
+    fn sync_leaves_identities_managed_by_others_alone() {
+        let manager = TestIdentityManager::new(&[("m1", "someone else")]);
+
+        let report = Runtime::new()
+            .unwrap()
+            .block_on(manager.sync(specs(&["m1"])))
+            .unwrap();
+
+        assert!(report.created().is_empty());
+        assert_eq!(strings(&["m1"]), report.skipped());
+        assert_eq!(strings(&["m1"]), manager.module_ids());
+    }
+
+    #[test]
+    fn sync_reports_failures() {
+        let manager = TestIdentityManager::new(&[("m1", MANAGED_BY_IOTEDGE)])
+            .with_failing("m1")
+            .with_failing("m3");
+
+        let report = Runtime::new()
+            .unwrap()
+            .block_on(manager.sync(specs(&["m2", "m3"])))
+            .unwrap();
+
+        assert!(!report.is_success());
+        assert_eq!(strings(&["m2"]), report.created());
+        assert_eq!(
+            &[
+                ("m1".to_string(), "Could not change m1".to_string()),
+                ("m3".to_string(), "Could not change m3".to_string()),
+            ],
+            report.failed()
+        );
+    }
+
+    #[test]
+    fn sync_bounds_concurrency() {
+        let ids: Vec<String> = (0..10).map(|i| format!("m{}", i)).collect();
+        let manager = TestIdentityManager::new(&[]);
+
+        let desired = ids.iter().map(|id| IdentitySpec::new(id)).collect();
+        let report = Runtime::new()
+            .unwrap()
+            .block_on(manager.sync(desired))
+            .unwrap();
+
+        assert_eq!(10, report.created().len());
+        assert_eq!(SYNC_PARALLELISM, manager.state.lock().unwrap().max_in_flight);
+    }
 }
@@ -0,0 +1,313 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Removes the images no module was created from, once a day at a set local
+//! time.
+//!
+//! An image is only removed once it is older than a minimum age, as told by
+//! the creation time the runtime reports for it, so that the age holds across
+//! restarts of the daemon. Images whose creation time isn't known are kept.
+//!
+//! Whether images are collected, and when and which, can be changed while the
+//! collector runs through its `ImageGcPolicy`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{
+    DateTime, Duration as ChronoDuration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone, Utc,
+};
+use edgelet_utils::log_failure;
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use log::Level;
+
+use busy::BusyFlag;
+use clock::{Clock, SystemClock};
+use module::{ImageInfo, Module, ModuleRegistry, ModuleRuntime};
+
+/// How soon a collection that came while modules were being created or
+/// removed is tried again.
+const BUSY_RETRY_SECS: u64 = 60;
+
+/// How old an unused image gets before it is removed, unless set otherwise.
+pub const DEFAULT_MIN_IMAGE_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Whether unused images are collected, the local time of day they are
+/// collected at, and how old they get before they are removed. Clones share
+/// the policy. Each cleanup reads it anew, but a new cleanup time is only
+/// kept after the cleanup the collector is already waiting for.
+#[derive(Clone, Debug)]
+pub struct ImageGcPolicy {
+    inner: Arc<Mutex<Policy>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Policy {
+    enabled: bool,
+    cleanup_time: NaiveTime,
+    min_age: Duration,
+}
+
+impl Default for ImageGcPolicy {
+    fn default() -> Self {
+        ImageGcPolicy::new(
+            true,
+            NaiveTime::from_hms(2, 0, 0),
+            Duration::from_secs(DEFAULT_MIN_IMAGE_AGE_SECS),
+        )
+    }
+}
+
+impl ImageGcPolicy {
+    pub fn new(enabled: bool, cleanup_time: NaiveTime, min_age: Duration) -> Self {
+        ImageGcPolicy {
+            inner: Arc::new(Mutex::new(Policy {
+                enabled,
+                cleanup_time,
+                min_age,
+            })),
+        }
+    }
+
+    /// Replaces the policy of every collector sharing it.
+    pub fn set(&self, enabled: bool, cleanup_time: NaiveTime, min_age: Duration) {
+        *self.lock() = Policy {
+            enabled,
+            cleanup_time,
+            min_age,
+        };
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.lock().enabled
+    }
+
+    pub fn cleanup_time(&self) -> NaiveTime {
+        self.lock().cleanup_time
+    }
+
+    pub fn min_age(&self) -> Duration {
+        self.lock().min_age
+    }
+
+    fn lock(&self) -> MutexGuard<Policy> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+pub struct ImageGarbageCollector<M> {
+    runtime: M,
+    policy: ImageGcPolicy,
+    busy: BusyFlag,
+    clock: Arc<Clock>,
+    utc_offset: Option<FixedOffset>,
+}
+
+impl<M> ImageGarbageCollector<M>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+{
+    /// Collects images at 02:00 local time, removing unused ones older than
+    /// a week. `busy` is the flag the runtime sets while it creates or removes
+    /// modules, which holds collections back.
+    pub fn new(runtime: M, busy: BusyFlag) -> Self {
+        ImageGarbageCollector {
+            runtime,
+            policy: ImageGcPolicy::default(),
+            busy,
+            clock: Arc::new(SystemClock),
+            utc_offset: None,
+        }
+    }
+
+    /// Sets whether images are collected, when, and which. Keep a clone of
+    /// the policy to change it while the collector runs.
+    pub fn with_policy(mut self, policy: ImageGcPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the clock collections are scheduled by.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets the offset from UTC the cleanup time is in, instead of that of
+    /// the local time zone.
+    pub fn with_utc_offset(mut self, utc_offset: FixedOffset) -> Self {
+        self.utc_offset = Some(utc_offset);
+        self
+    }
+
+    /// Collects images every day while the policy has collection enabled,
+    /// for as long as the future is polled.
+    pub fn run(self) -> impl Future<Item = (), Error = ()> {
+        future::loop_fn((self, None), |(collector, last_run)| {
+            let now = collector.local(collector.clock.now());
+            let wait = until_cleanup(now, collector.policy.cleanup_time(), last_run)
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0));
+            let delay = collector.clock.delay(collector.clock.instant() + wait);
+            delay
+                .then(move |result| {
+                    if let Err(err) = result {
+                        warn!("Image garbage collection timer failed: {}", err);
+                    }
+                    collector.collect_when_idle()
+                }).map(Loop::Continue)
+        })
+    }
+
+    fn local(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        let offset = self
+            .utc_offset
+            .unwrap_or_else(|| Local.offset_from_utc_datetime(&now.naive_utc()));
+        now.with_timezone(&offset).naive_local()
+    }
+
+    // Waits for the runtime to finish creating or removing modules, then
+    // collects, again if the runtime got busy in the middle of it.
+    fn collect_when_idle(self) -> impl Future<Item = (Self, Option<NaiveDate>), Error = ()> {
+        future::loop_fn(self, |collector| {
+            if collector.busy.is_set() {
+                info!(
+                    "Modules are being created or removed, removing unused images in {} seconds",
+                    BUSY_RETRY_SECS
+                );
+                let retry = collector.clock.instant() + Duration::from_secs(BUSY_RETRY_SECS);
+                let delay = collector.clock.delay(retry);
+                Either::A(delay.then(move |_| Ok(Loop::Continue(collector))))
+            } else {
+                Either::B(collector.collect())
+            }
+        })
+    }
+
+    // Removes the images no module uses that are older than the minimum age,
+    // and returns the local date it ran on. The collection is skipped until
+    // the next cleanup time if the runtime can't be reached or collection is
+    // disabled, and tried again if modules started being created or removed
+    // while it was underway.
+    fn collect(self) -> impl Future<Item = Loop<(Self, Option<NaiveDate>), Self>, Error = ()> {
+        let now = self.clock.now();
+        let last_run = Some(self.local(now).date());
+        if !self.policy.enabled() {
+            debug!("Image garbage collection is disabled, keeping unused images");
+            return Either::A(future::ok(Loop::Break((self, last_run))));
+        }
+        let min_age = self.policy.min_age();
+        info!("Removing unused images older than {} seconds", min_age.as_secs());
+        let listed = self.runtime.list().join(self.runtime.registry().images());
+        Either::B(listed.then(move |listed| {
+            let (modules, images) = match listed {
+                Ok(listed) => listed,
+                Err(err) => {
+                    warn!("Could not list modules and images, skipping image garbage collection:");
+                    log_failure(Level::Warn, &err);
+                    return Either::A(future::ok(Loop::Break((self, last_run))));
+                }
+            };
+            let in_use: Option<HashSet<String>> = modules
+                .iter()
+                .map(|module| module.image_id().map(ToOwned::to_owned))
+                .collect();
+            let in_use = match in_use {
+                Some(in_use) => in_use,
+                None => {
+                    warn!("Modules don't tell their images, skipping image garbage collection");
+                    return Either::A(future::ok(Loop::Break((self, last_run))));
+                }
+            };
+
+            let unused = unused_images(&in_use, images, now, min_age);
+            if unused.is_empty() {
+                info!("No unused images to remove");
+                return Either::A(future::ok(Loop::Break((self, last_run))));
+            }
+            if self.busy.is_set() {
+                return Either::A(future::ok(Loop::Continue(self)));
+            }
+
+            let sizes: HashMap<String, u64> = unused
+                .iter()
+                .map(|image| (image.id().to_string(), image.size()))
+                .collect();
+            info!("Removing {} unused images", sizes.len());
+            let pruned = self
+                .runtime
+                .registry()
+                .prune_images(sizes.keys().cloned().collect());
+            Either::B(pruned.then(move |result| {
+                match result {
+                    Ok(removed) => {
+                        let bytes: u64 = removed.iter().filter_map(|id| sizes.get(id)).sum();
+                        info!(
+                            "Removed {} unused images, freeing {} bytes",
+                            removed.len(),
+                            bytes
+                        );
+                    }
+                    Err(err) => {
+                        warn!("Could not remove unused images:");
+                        log_failure(Level::Warn, &err);
+                    }
+                }
+                Ok(Loop::Break((self, last_run)))
+            }))
+        }))
+    }
+}
+
+// How long it is from `now` until the next time of day `at`, on a later day
+// than that of the last collection.
+fn until_cleanup(now: NaiveDateTime, at: NaiveTime, last_run: Option<NaiveDate>) -> ChronoDuration {
+    let mut next = now.date().and_time(at);
+    if next <= now || last_run.map_or(false, |last_run| next.date() <= last_run) {
+        next += ChronoDuration::days(1);
+    }
+    next.signed_duration_since(now)
+}
+
+// Returns the images no module uses that were created at least `min_age`
+// before `now`.
+fn unused_images(
+    in_use: &HashSet<String>,
+    images: Vec<ImageInfo>,
+    now: DateTime<Utc>,
+    min_age: Duration,
+) -> Vec<ImageInfo> {
+    let min_age = ChronoDuration::from_std(min_age).unwrap_or_else(|_| ChronoDuration::max_value());
+    images
+        .into_iter()
+        .filter(|image| {
+            !in_use.contains(image.id()) && image.created_at().map_or(false, |created_at| {
+                now.signed_duration_since(*created_at) >= min_age
+            })
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleanup_is_at_the_next_time_of_day_after_the_last_one() {
+        let at = NaiveTime::from_hms(2, 0, 0);
+        let day = NaiveDate::from_ymd(2018, 1, 1);
+
+        let before = day.and_hms(1, 30, 0);
+        assert_eq!(ChronoDuration::minutes(30), until_cleanup(before, at, None));
+        let after = day.and_hms(2, 0, 0);
+        assert_eq!(ChronoDuration::days(1), until_cleanup(after, at, None));
+
+        // A timer that fires a little early doesn't collect twice.
+        let early = day.and_hms(1, 59, 59);
+        assert_eq!(
+            ChronoDuration::seconds(24 * 60 * 60 + 1),
+            until_cleanup(early, at, Some(day))
+        );
+    }
+}
@@ -32,28 +32,67 @@ extern crate tokio;
 extern crate edgelet_utils;
 
 mod authorization;
+mod busy;
 mod certificate_properties;
+mod clock;
 pub mod crypto;
+mod diagnostics;
+mod disk;
+pub mod env;
+mod envelope;
+mod epoch;
 mod error;
+mod events;
+mod health;
 mod identity;
+mod image_gc;
+pub mod metrics;
 mod module;
 pub mod pid;
+mod restart;
+mod running;
+mod shutdown;
+mod signal;
+mod start;
 pub mod watchdog;
 pub mod workload;
 
-pub use authorization::{Authorization, Policy};
+pub use authorization::{Authorization, PidResolver, Policy};
+pub use busy::{BusyFlag, BusyGuard};
 pub use certificate_properties::{CertificateIssuer, CertificateProperties, CertificateType};
+pub use clock::{Clock, SystemClock};
 pub use crypto::{
     Certificate, CreateCertificate, Decrypt, Encrypt, GetTrustBundle, KeyBytes, KeyIdentity,
-    KeyStore, MasterEncryptionKey, PrivateKey, Signature, IOTEDGED_CA_ALIAS,
+    KeyStore, MakeRandom, MasterEncryptionKey, PrivateKey, RenewQuickstartCa, Signature,
+    SignCertificateRequest, IOTEDGED_CA_ALIAS,
 };
+pub use diagnostics::{RuntimeErrorLog, RuntimeErrorRecord, DEFAULT_RUNTIME_ERROR_CAPACITY};
+pub use disk::{DiskMonitor, DiskSpace, DiskStats, DiskUsage, SystemResources};
+pub use env::{EnvInjection, ModuleEnv, ModuleEnvSettings};
+pub use envelope::{Envelope, ENVELOPE_OVERHEAD};
+pub use epoch::ModuleEpochs;
 pub use error::{Error, ErrorKind};
-pub use identity::{AuthType, Identity, IdentityManager, IdentitySpec};
+pub use events::{
+    watch_modules, ModuleEvents, ModuleStateChange, Subscription, DEFAULT_WATCH_INTERVAL_SECS,
+};
+pub use health::{DaemonHealth, HealthReport, HealthStatus, SubsystemHealth, SubsystemStatus};
+pub use identity::{
+    AuthType, Identity, IdentityError, IdentityFilter, IdentityManager, IdentitySpec, SyncReport,
+    MANAGED_BY_IOTEDGE,
+};
+pub use image_gc::{ImageGarbageCollector, ImageGcPolicy, DEFAULT_MIN_IMAGE_AGE_SECS};
+pub use metrics::{Metric, MetricKind, Metrics, MetricsRegistry};
 pub use module::{
-    LogOptions, LogTail, Module, ModuleRegistry, ModuleRuntime, ModuleRuntimeState, ModuleSpec,
-    ModuleStatus, SystemInfo,
+    Discrepancy, ImageInfo, IntegrityReport, LogOptions, LogTail, Module, ModuleHealth,
+    ModuleInspection, ModuleRegistry, ModuleRuntime, ModuleRuntimeState, ModuleSpec, ModuleStatus,
+    RegistryCredential, RegistryCredentials, SystemInfo,
 };
-pub use workload::WorkloadConfig;
+pub use restart::{ModuleRestarts, Restart, RestartPolicy};
+pub use running::RunningModules;
+pub use shutdown::{stop_in_order, StopAllOptions};
+pub use signal::{KillSignals, SAFE_SIGNALS};
+pub use start::{init_with_retries, start_and_confirm, StartOutcome};
+pub use workload::{PayloadLimits, WorkloadConfig};
 
 lazy_static! {
     static ref VERSION: String = option_env!("VERSION")
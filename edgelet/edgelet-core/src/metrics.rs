@@ -0,0 +1,584 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Metrics about the daemon itself, rendered in the Prometheus text
+//! exposition format.
+//!
+//! The metrics the daemon records are declared here, so that their names and
+//! labels stay stable. Dashboards and alerts depend on them; rename or remove
+//! one only together with a note in the changelog.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the buckets durations are counted in. Image
+/// pulls can take minutes, so the buckets go further than usual.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A metric the daemon records. Every metric is declared as one of the
+/// constants in this module.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn help(&self) -> &'static str {
+        self.help
+    }
+
+    pub fn kind(&self) -> MetricKind {
+        self.kind
+    }
+}
+
+/// Container runtime operations, successful or not.
+///
+/// Labels: `operation` - one of `create`, `start`, `stop`, `restart`,
+/// `remove`, `list`, `pull` and `remove_image`.
+pub const RUNTIME_OPERATIONS: Metric = Metric {
+    name: "edgelet_runtime_operations_total",
+    help: "Container runtime operations",
+    kind: MetricKind::Counter,
+};
+
+/// Container runtime operations that failed.
+///
+/// Labels: `operation`, as for `RUNTIME_OPERATIONS`, and `error` - the kind
+/// of error the operation failed with, such as `NotFound`.
+pub const RUNTIME_OPERATION_FAILURES: Metric = Metric {
+    name: "edgelet_runtime_operation_failures_total",
+    help: "Container runtime operations that failed",
+    kind: MetricKind::Counter,
+};
+
+/// How long container runtime operations took.
+///
+/// Labels: `operation`, as for `RUNTIME_OPERATIONS`.
+pub const RUNTIME_OPERATION_DURATION: Metric = Metric {
+    name: "edgelet_runtime_operation_duration_seconds",
+    help: "How long container runtime operations took",
+    kind: MetricKind::Histogram,
+};
+
+/// Container runtime operations that took longer than the threshold set for
+/// them. These are counted in `RUNTIME_OPERATION_DURATION` too.
+///
+/// Labels: `operation`, as for `RUNTIME_OPERATIONS`.
+pub const RUNTIME_SLOW_OPERATIONS: Metric = Metric {
+    name: "edgelet_runtime_slow_operations_total",
+    help: "Container runtime operations that took longer than their threshold",
+    kind: MetricKind::Counter,
+};
+
+/// Requests answered by the daemon's HTTP servers.
+///
+/// Labels: `server` - `mgmt`, `work` or `metrics`, `method` and `status`
+/// - the numeric status code of the response.
+pub const HTTP_REQUESTS: Metric = Metric {
+    name: "edgelet_http_requests_total",
+    help: "Requests answered by the HTTP servers",
+    kind: MetricKind::Counter,
+};
+
+/// How long the daemon's HTTP servers took to answer requests.
+///
+/// Labels: `server`, as for `HTTP_REQUESTS`.
+pub const HTTP_REQUEST_DURATION: Metric = Metric {
+    name: "edgelet_http_request_duration_seconds",
+    help: "How long the HTTP servers took to answer requests",
+    kind: MetricKind::Histogram,
+};
+
+/// Requests the daemon's HTTP servers are working on.
+///
+/// Labels: `server`, as for `HTTP_REQUESTS`.
+pub const HTTP_REQUESTS_IN_FLIGHT: Metric = Metric {
+    name: "edgelet_http_requests_in_flight",
+    help: "Requests the HTTP servers are working on",
+    kind: MetricKind::Gauge,
+};
+
+/// Module log streams the management API is forwarding from the container
+/// runtime.
+pub const LOG_STREAMS_OPEN: Metric = Metric {
+    name: "edgelet_log_streams_open",
+    help: "Module log streams forwarded from the container runtime",
+    kind: MetricKind::Gauge,
+};
+
+/// Module log streams the management API stopped forwarding.
+///
+/// Labels: `reason` - `finished` when the container runtime ended the stream,
+/// `module_removed` when it ended it because the module was removed,
+/// `failed` when reading it failed and `client_gone` when the client went
+/// away first.
+pub const LOG_STREAMS_ENDED: Metric = Metric {
+    name: "edgelet_log_streams_ended_total",
+    help: "Module log streams that stopped being forwarded",
+    kind: MetricKind::Counter,
+};
+
+/// Modules the watchdog restarted because they were not running.
+///
+/// Labels: `module` - the name of the module.
+pub const WATCHDOG_RESTARTS: Metric = Metric {
+    name: "edgelet_watchdog_module_restarts_total",
+    help: "Modules the watchdog restarted because they were not running",
+    kind: MetricKind::Counter,
+};
+
+/// Running modules the watchdog restarted because they kept reporting
+/// themselves unhealthy. These are not counted in `WATCHDOG_RESTARTS`.
+///
+/// Labels: `module` - the name of the module.
+pub const WATCHDOG_UNHEALTHY_RESTARTS: Metric = Metric {
+    name: "edgelet_watchdog_unhealthy_restarts_total",
+    help: "Modules the watchdog restarted because they kept reporting themselves unhealthy",
+    kind: MetricKind::Counter,
+};
+
+/// Records metrics. Recording a metric with a method that does not fit its
+/// kind, such as observing a counter, does nothing.
+pub trait Metrics: Send + Sync {
+    /// Adds one to a counter.
+    fn increment(&self, metric: &Metric, labels: &[(&str, &str)]);
+
+    /// Adds `delta`, which may be negative, to a gauge.
+    fn add(&self, metric: &Metric, labels: &[(&str, &str)], delta: f64);
+
+    /// Counts a value, such as a duration in seconds, in a histogram.
+    fn observe(&self, metric: &Metric, labels: &[(&str, &str)], value: f64);
+}
+
+/// Converts a duration to the seconds metrics are recorded in.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_precision_loss))]
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// The name of the variant of an error kind, for use as the value of an
+/// `error` label. Any data the variant carries is left out, since it would
+/// give every error a series of its own.
+pub fn error_kind_label<K: Debug>(kind: &K) -> String {
+    let debug = format!("{:?}", kind);
+    debug
+        .split(|c: char| c == '(' || c == '{' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
+        .to_string()
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    /// How many values fell into each bucket, but not into the previous one.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Counter(u64),
+    Gauge(f64),
+    Histogram(Histogram),
+}
+
+#[derive(Debug)]
+struct Family {
+    metric: Metric,
+    series: BTreeMap<Labels, Value>,
+}
+
+/// Keeps the metrics recorded in memory for as long as the daemon runs.
+/// Clones share the metrics they record.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsRegistry {
+    families: Arc<Mutex<BTreeMap<&'static str, Family>>>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        MetricsRegistry::default()
+    }
+
+    /// The value of a counter, or 0 if it was never incremented.
+    pub fn counter(&self, metric: &Metric, labels: &[(&str, &str)]) -> u64 {
+        match self.value(metric, labels) {
+            Some(Value::Counter(value)) => value,
+            _ => 0,
+        }
+    }
+
+    /// The value of a gauge, or 0 if it was never changed.
+    pub fn gauge(&self, metric: &Metric, labels: &[(&str, &str)]) -> f64 {
+        match self.value(metric, labels) {
+            Some(Value::Gauge(value)) => value,
+            _ => 0.0,
+        }
+    }
+
+    /// How many values a histogram counted.
+    pub fn histogram_count(&self, metric: &Metric, labels: &[(&str, &str)]) -> u64 {
+        match self.value(metric, labels) {
+            Some(Value::Histogram(histogram)) => histogram.count,
+            _ => 0,
+        }
+    }
+
+    /// The metrics in the Prometheus text exposition format, ordered by name
+    /// and labels.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for family in self.lock().values() {
+            let metric = family.metric;
+            let _ = writeln!(out, "# HELP {} {}", metric.name, escape_help(metric.help));
+            let _ = writeln!(out, "# TYPE {} {}", metric.name, metric.kind);
+            for (labels, value) in &family.series {
+                match *value {
+                    Value::Counter(value) => {
+                        let _ = writeln!(out, "{}{} {}", metric.name, render_labels(labels), value);
+                    }
+                    Value::Gauge(value) => {
+                        let _ = writeln!(out, "{}{} {}", metric.name, render_labels(labels), value);
+                    }
+                    Value::Histogram(ref histogram) => {
+                        render_histogram(&mut out, metric.name, labels, histogram);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn value(&self, metric: &Metric, labels: &[(&str, &str)]) -> Option<Value> {
+        self.lock()
+            .get(metric.name)
+            .and_then(|family| family.series.get(&to_labels(labels)).cloned())
+    }
+
+    fn update<F>(&self, metric: &Metric, labels: &[(&str, &str)], init: Value, update: F)
+    where
+        F: FnOnce(&mut Value),
+    {
+        let mut families = self.lock();
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            metric: *metric,
+            series: BTreeMap::new(),
+        });
+        update(family.series.entry(to_labels(labels)).or_insert(init));
+    }
+
+    fn lock(&self) -> MutexGuard<BTreeMap<&'static str, Family>> {
+        self.families
+            .lock()
+            .expect("Failed to acquire the metrics lock")
+    }
+}
+
+impl Metrics for MetricsRegistry {
+    fn increment(&self, metric: &Metric, labels: &[(&str, &str)]) {
+        if metric.kind == MetricKind::Counter {
+            self.update(metric, labels, Value::Counter(0), |value| {
+                if let Value::Counter(ref mut value) = *value {
+                    *value += 1;
+                }
+            });
+        }
+    }
+
+    fn add(&self, metric: &Metric, labels: &[(&str, &str)], delta: f64) {
+        if metric.kind == MetricKind::Gauge {
+            self.update(metric, labels, Value::Gauge(0.0), |value| {
+                if let Value::Gauge(ref mut value) = *value {
+                    *value += delta;
+                }
+            });
+        }
+    }
+
+    fn observe(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        if metric.kind == MetricKind::Histogram {
+            self.update(
+                metric,
+                labels,
+                Value::Histogram(Histogram::new()),
+                |histogram| {
+                    if let Value::Histogram(ref mut histogram) = *histogram {
+                        histogram.observe(value);
+                    }
+                },
+            );
+        }
+    }
+}
+
+/// Labels are kept sorted by name, so that the order they are given in does
+/// not matter.
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+fn render_labels(labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+            .collect();
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn render_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &[(String, String)],
+    histogram: &Histogram,
+) {
+    let mut cumulative = 0;
+    for (bound, count) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += count;
+        let mut bucket_labels = labels.to_vec();
+        bucket_labels.push(("le".to_string(), bound.to_string()));
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            render_labels(&bucket_labels),
+            cumulative
+        );
+    }
+    let mut bucket_labels = labels.to_vec();
+    bucket_labels.push(("le".to_string(), "+Inf".to_string()));
+    let _ = writeln!(
+        out,
+        "{}_bucket{} {}",
+        name,
+        render_labels(&bucket_labels),
+        histogram.count
+    );
+    let _ = writeln!(
+        out,
+        "{}_sum{} {}",
+        name,
+        render_labels(labels),
+        histogram.sum
+    );
+    let _ = writeln!(
+        out,
+        "{}_count{} {}",
+        name,
+        render_labels(labels),
+        histogram.count
+    );
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_COUNTER: Metric = Metric {
+        name: "test_events_total",
+        help: "Test events",
+        kind: MetricKind::Counter,
+    };
+
+    const TEST_GAUGE: Metric = Metric {
+        name: "test_in_flight",
+        help: "Test work in flight",
+        kind: MetricKind::Gauge,
+    };
+
+    const TEST_HISTOGRAM: Metric = Metric {
+        name: "test_duration_seconds",
+        help: "Test durations",
+        kind: MetricKind::Histogram,
+    };
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    enum TestErrorKind {
+        NotFound(String),
+        Conflict,
+        Invalid { reason: String },
+    }
+
+    #[test]
+    fn counters_are_kept_per_label_set() {
+        let registry = MetricsRegistry::new();
+        registry.increment(&TEST_COUNTER, &[("operation", "start"), ("module", "m1")]);
+        registry.increment(&TEST_COUNTER, &[("module", "m1"), ("operation", "start")]);
+        registry.increment(&TEST_COUNTER, &[("operation", "stop"), ("module", "m1")]);
+
+        assert_eq!(
+            2,
+            registry.counter(&TEST_COUNTER, &[("module", "m1"), ("operation", "start")])
+        );
+        assert_eq!(
+            1,
+            registry.counter(&TEST_COUNTER, &[("module", "m1"), ("operation", "stop")])
+        );
+        assert_eq!(
+            0,
+            registry.counter(&TEST_COUNTER, &[("module", "m2"), ("operation", "stop")])
+        );
+    }
+
+    #[test]
+    fn recording_with_the_wrong_kind_is_ignored() {
+        let registry = MetricsRegistry::new();
+        registry.observe(&TEST_COUNTER, &[], 1.0);
+        registry.increment(&TEST_GAUGE, &[]);
+        registry.add(&TEST_HISTOGRAM, &[], 1.0);
+
+        assert_eq!("", registry.render());
+    }
+
+    #[test]
+    fn render_counters_and_gauges() {
+        let registry = MetricsRegistry::new();
+        registry.increment(&TEST_COUNTER, &[("module", "m\"1\"\n")]);
+        registry.add(&TEST_GAUGE, &[], 2.0);
+        registry.add(&TEST_GAUGE, &[], -1.5);
+
+        assert_eq!(
+            "# HELP test_events_total Test events\n\
+             # TYPE test_events_total counter\n\
+             test_events_total{module=\"m\\\"1\\\"\\n\"} 1\n\
+             # HELP test_in_flight Test work in flight\n\
+             # TYPE test_in_flight gauge\n\
+             test_in_flight 0.5\n",
+            registry.render()
+        );
+    }
+
+    #[test]
+    fn render_histogram() {
+        let registry = MetricsRegistry::new();
+        registry.observe(&TEST_HISTOGRAM, &[("operation", "pull")], 0.25);
+        registry.observe(&TEST_HISTOGRAM, &[("operation", "pull")], 0.5);
+        registry.observe(&TEST_HISTOGRAM, &[("operation", "pull")], 1000.0);
+
+        let rendered = registry.render();
+        for line in &[
+            "# TYPE test_duration_seconds histogram",
+            "test_duration_seconds_bucket{operation=\"pull\",le=\"0.1\"} 0",
+            "test_duration_seconds_bucket{operation=\"pull\",le=\"0.25\"} 1",
+            "test_duration_seconds_bucket{operation=\"pull\",le=\"0.5\"} 2",
+            "test_duration_seconds_bucket{operation=\"pull\",le=\"300\"} 2",
+            "test_duration_seconds_bucket{operation=\"pull\",le=\"+Inf\"} 3",
+            "test_duration_seconds_sum{operation=\"pull\"} 1000.75",
+            "test_duration_seconds_count{operation=\"pull\"} 3",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == *line),
+                "{} not in {}",
+                line,
+                rendered
+            );
+        }
+        assert_eq!(
+            3,
+            registry.histogram_count(&TEST_HISTOGRAM, &[("operation", "pull")])
+        );
+    }
+
+    #[test]
+    fn clones_share_metrics() {
+        let registry = MetricsRegistry::new();
+        let metrics: Arc<Metrics> = Arc::new(registry.clone());
+        metrics.increment(&TEST_COUNTER, &[]);
+        metrics.add(&TEST_GAUGE, &[], 3.0);
+
+        assert_eq!(1, registry.counter(&TEST_COUNTER, &[]));
+        assert_eq!("3", registry.gauge(&TEST_GAUGE, &[]).to_string());
+    }
+
+    #[test]
+    fn error_kind_label_is_the_variant_name() {
+        assert_eq!(
+            "NotFound",
+            error_kind_label(&TestErrorKind::NotFound("m1".to_string()))
+        );
+        assert_eq!("Conflict", error_kind_label(&TestErrorKind::Conflict));
+        assert_eq!(
+            "Invalid",
+            error_kind_label(&TestErrorKind::Invalid {
+                reason: "bad".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn duration_secs_keeps_fractions() {
+        assert_eq!(
+            "1.5",
+            duration_secs(Duration::from_millis(1500)).to_string()
+        );
+    }
+}
@@ -1,20 +1,25 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::default::Default;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
 use std::string::ToString;
 use std::time::Duration;
 
 use chrono::prelude::*;
-use failure::Fail;
-use futures::{Future, Stream};
+use failure::{Fail, ResultExt};
+use futures::{future, Future, Stream};
 use pid::Pid;
+use shutdown::StopAllOptions;
 use serde_json;
 
-use error::{Error, Result};
+use busy::BusyFlag;
+use disk::SystemResources;
+use epoch::ModuleEpochs;
+use error::{Error, ErrorKind, Result};
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -23,6 +28,7 @@ pub enum ModuleStatus {
     Running,
     Stopped,
     Failed,
+    Backoff,
 }
 
 impl FromStr for ModuleStatus {
@@ -45,15 +51,28 @@ impl fmt::Display for ModuleStatus {
     }
 }
 
+/// The result of the health checks a module runs on itself.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleHealth {
+    /// The module has not passed or failed enough checks yet.
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct ModuleRuntimeState {
     status: ModuleStatus,
     exit_code: Option<i64>,
     status_description: Option<String>,
+    created_at: Option<DateTime<Utc>>,
     started_at: Option<DateTime<Utc>>,
     finished_at: Option<DateTime<Utc>>,
     image_id: Option<String>,
     pid: Pid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health: Option<ModuleHealth>,
 }
 
 impl Default for ModuleRuntimeState {
@@ -62,10 +81,12 @@ impl Default for ModuleRuntimeState {
             status: ModuleStatus::Unknown,
             exit_code: None,
             status_description: None,
+            created_at: None,
             started_at: None,
             finished_at: None,
             image_id: None,
             pid: Pid::None,
+            health: None,
         }
     }
 }
@@ -98,6 +119,15 @@ impl ModuleRuntimeState {
         self
     }
 
+    pub fn created_at(&self) -> Option<&DateTime<Utc>> {
+        self.created_at.as_ref()
+    }
+
+    pub fn with_created_at(mut self, created_at: Option<DateTime<Utc>>) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
     pub fn started_at(&self) -> Option<&DateTime<Utc>> {
         self.started_at.as_ref()
     }
@@ -133,6 +163,16 @@ impl ModuleRuntimeState {
         self.pid = pid;
         self
     }
+
+    /// The health of the module, for modules that check it.
+    pub fn health(&self) -> Option<ModuleHealth> {
+        self.health
+    }
+
+    pub fn with_health(mut self, health: Option<ModuleHealth>) -> Self {
+        self.health = health;
+        self
+    }
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -143,6 +183,8 @@ pub struct ModuleSpec<T> {
     config: T,
     #[serde(default = "HashMap::new")]
     env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    runtime: Option<String>,
 }
 
 impl<T> Clone for ModuleSpec<T>
@@ -155,6 +197,7 @@ where
             type_: self.type_.clone(),
             config: self.config.clone(),
             env: self.env.clone(),
+            runtime: self.runtime.clone(),
         }
     }
 }
@@ -166,6 +209,7 @@ impl<T> ModuleSpec<T> {
             type_: ensure_not_empty!(type_).to_string(),
             config,
             env,
+            runtime: None,
         })
     }
 
@@ -212,6 +256,17 @@ impl<T> ModuleSpec<T> {
         self.env = env;
         self
     }
+
+    /// The container engine the module runs on, when the runtime has more
+    /// than one. `None` is the primary engine.
+    pub fn runtime(&self) -> Option<&str> {
+        self.runtime.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_runtime(mut self, runtime: Option<String>) -> Self {
+        self.runtime = runtime;
+        self
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -229,30 +284,33 @@ impl Default for LogTail {
 impl FromStr for LogTail {
     type Err = Error;
 
+    /// Reads "all" or a number of lines. Anything else, including negative
+    /// numbers, is an error rather than all of the logs.
     fn from_str(s: &str) -> Result<Self> {
-        let tail = if s == "all" {
-            LogTail::All
+        if s == "all" {
+            Ok(LogTail::All)
         } else {
-            let num = s.parse()?;
-            LogTail::Num(num)
-        };
-        Ok(tail)
+            let num = s.parse::<u64>().context(ErrorKind::InvalidLogTail(s.to_string()))?;
+            Ok(LogTail::Num(num))
+        }
     }
 }
 
-impl ToString for LogTail {
-    fn to_string(&self) -> String {
-        match self {
-            LogTail::All => "all".to_string(),
-            LogTail::Num(n) => n.to_string(),
+impl fmt::Display for LogTail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogTail::All => write!(f, "all"),
+            LogTail::Num(n) => write!(f, "{}", n),
         }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct LogOptions {
     follow: bool,
     tail: LogTail,
+    since: i32,
+    timestamps: bool,
 }
 
 impl LogOptions {
@@ -260,6 +318,8 @@ impl LogOptions {
         LogOptions {
             follow: false,
             tail: LogTail::All,
+            since: 0,
+            timestamps: false,
         }
     }
 
@@ -273,6 +333,20 @@ impl LogOptions {
         self
     }
 
+    /// Only returns logs written at or after `since`, a Unix timestamp. 0
+    /// returns all logs.
+    pub fn with_since(mut self, since: i32) -> Self {
+        self.since = since;
+        self
+    }
+
+    /// Prefixes every log line with the time it was written, in RFC3339
+    /// format.
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
     pub fn follow(&self) -> bool {
         self.follow
     }
@@ -280,6 +354,14 @@ impl LogOptions {
     pub fn tail(&self) -> &LogTail {
         &self.tail
     }
+
+    pub fn since(&self) -> i32 {
+        self.since
+    }
+
+    pub fn timestamps(&self) -> bool {
+        self.timestamps
+    }
 }
 
 pub trait Module {
@@ -291,6 +373,30 @@ pub trait Module {
     fn type_(&self) -> &str;
     fn config(&self) -> &Self::Config;
     fn runtime_state(&self) -> Self::RuntimeStateFuture;
+
+    /// The named volumes the module keeps its data in, which outlive the
+    /// module itself.
+    fn volumes(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// The stop priority the module carries itself, which decides when
+    /// `ModuleRuntime::stop_all` stops it. See `StopAllOptions`.
+    fn stop_priority(&self) -> Option<i64> {
+        None
+    }
+
+    /// How long the module is given to stop before it is killed, when it
+    /// sets that itself.
+    fn stop_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The id of the image the module was created from, when the runtime
+    /// knows it.
+    fn image_id(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub trait ModuleRegistry {
@@ -301,16 +407,151 @@ pub trait ModuleRegistry {
 
     fn pull(&self, config: &Self::Config) -> Self::PullFuture;
     fn remove(&self, name: &str) -> Self::RemoveFuture;
+
+    /// Pulls the image of `module` where the module is going to run. Only
+    /// registries of runtimes with more than one container engine need more
+    /// than the config for that.
+    fn pull_module(&self, module: &ModuleSpec<Self::Config>) -> Self::PullFuture {
+        self.pull(module.config())
+    }
+
+    /// The images the container runtime keeps. Registries that can't list
+    /// them have none, which leaves image garbage collection nothing to do.
+    fn images(&self) -> Box<Future<Item = Vec<ImageInfo>, Error = Self::Error> + Send> {
+        Box::new(future::ok(vec![]))
+    }
+
+    /// Removes the images with the given ids, skipping those that can't be
+    /// removed, such as images a container was created from. Returns the ids
+    /// of the images removed.
+    fn prune_images(
+        &self,
+        _ids: Vec<String>,
+    ) -> Box<Future<Item = Vec<String>, Error = Self::Error> + Send> {
+        Box::new(future::ok(vec![]))
+    }
+}
+
+/// An image the container runtime keeps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageInfo {
+    id: String,
+    names: Vec<String>,
+    size: u64,
+    created_at: Option<DateTime<Utc>>,
+}
+
+impl ImageInfo {
+    pub fn new(id: String, size: u64) -> Self {
+        ImageInfo {
+            id,
+            names: vec![],
+            size,
+            created_at: None,
+        }
+    }
+
+    pub fn with_names(mut self, names: Vec<String>) -> Self {
+        self.names = names;
+        self
+    }
+
+    pub fn with_created_at(mut self, created_at: Option<DateTime<Utc>>) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The repository and tag names the image goes by, if any.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The size of the image in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// When the image was created, if the runtime tells.
+    pub fn created_at(&self) -> Option<&DateTime<Utc>> {
+        self.created_at.as_ref()
+    }
+}
+
+/// Credentials for an image registry. They can be kept by the daemon and
+/// referred to by name from module configs, so that the configs never carry
+/// them.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistryCredential {
+    server_address: String,
+    username: String,
+    password: String,
+}
+
+impl RegistryCredential {
+    pub fn new(server_address: String, username: String, password: String) -> Self {
+        RegistryCredential {
+            server_address,
+            username,
+            password,
+        }
+    }
+
+    pub fn server_address(&self) -> &str {
+        &self.server_address
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+/// Leaves the password out, so that logging a credential never leaks it.
+impl fmt::Debug for RegistryCredential {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RegistryCredential")
+            .field("server_address", &self.server_address)
+            .field("username", &self.username)
+            .finish()
+    }
 }
 
-#[derive(Debug)]
+/// Looks up the registry credentials that module configs refer to by name.
+pub trait RegistryCredentials: Send + Sync {
+    /// The credential called `name`, or `None` if there is none.
+    fn credential(&self, name: &str) -> Result<Option<RegistryCredential>>;
+}
+
+#[derive(Clone, Debug)]
 pub struct SystemInfo {
     /// OS Type of the Host. Example of value expected: \"linux\" and \"windows\".
     os_type: String,
     /// Hardware architecture of the host. Example of value expected: arm32, x86, amd64
     architecture: String,
     /// iotedge version string
-    version: &'static str,
+    version: String,
+    /// Version of the container runtime, if it reported one
+    runtime_version: Option<String>,
+    /// API version the container runtime serves, if it reported one
+    runtime_api_version: Option<String>,
+    /// Storage driver of the container runtime, if it reported one
+    storage_driver: Option<String>,
+    /// Details the storage driver reported about itself
+    storage_driver_status: HashMap<String, String>,
+    /// Default logging driver of the container runtime, if it reported one
+    logging_driver: Option<String>,
+    /// Directory the container runtime keeps its data in, if it reported one
+    runtime_root_dir: Option<PathBuf>,
+    /// Disk space left for deployments, if it was measured
+    resources: Option<SystemResources>,
 }
 
 impl SystemInfo {
@@ -318,7 +559,14 @@ impl SystemInfo {
         SystemInfo {
             os_type,
             architecture,
-            version: super::version(),
+            version: super::version().to_string(),
+            runtime_version: None,
+            runtime_api_version: None,
+            storage_driver: None,
+            storage_driver_status: HashMap::new(),
+            logging_driver: None,
+            runtime_root_dir: None,
+            resources: None,
         }
     }
 
@@ -331,7 +579,219 @@ impl SystemInfo {
     }
 
     pub fn version(&self) -> &str {
-        self.version
+        &self.version
+    }
+
+    /// Sets the version of iotedged, for system information that was
+    /// received from a daemon rather than put together locally.
+    pub fn with_version(mut self, version: String) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn runtime_version(&self) -> Option<&str> {
+        self.runtime_version.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_runtime_version(mut self, runtime_version: Option<String>) -> Self {
+        self.runtime_version = runtime_version;
+        self
+    }
+
+    pub fn runtime_api_version(&self) -> Option<&str> {
+        self.runtime_api_version.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_runtime_api_version(mut self, runtime_api_version: Option<String>) -> Self {
+        self.runtime_api_version = runtime_api_version;
+        self
+    }
+
+    pub fn storage_driver(&self) -> Option<&str> {
+        self.storage_driver.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_storage_driver(mut self, storage_driver: Option<String>) -> Self {
+        self.storage_driver = storage_driver;
+        self
+    }
+
+    pub fn storage_driver_status(&self) -> &HashMap<String, String> {
+        &self.storage_driver_status
+    }
+
+    pub fn with_storage_driver_status(
+        mut self,
+        storage_driver_status: HashMap<String, String>,
+    ) -> Self {
+        self.storage_driver_status = storage_driver_status;
+        self
+    }
+
+    pub fn logging_driver(&self) -> Option<&str> {
+        self.logging_driver.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_logging_driver(mut self, logging_driver: Option<String>) -> Self {
+        self.logging_driver = logging_driver;
+        self
+    }
+
+    pub fn runtime_root_dir(&self) -> Option<&Path> {
+        self.runtime_root_dir.as_ref().map(PathBuf::as_path)
+    }
+
+    pub fn with_runtime_root_dir(mut self, runtime_root_dir: Option<PathBuf>) -> Self {
+        self.runtime_root_dir = runtime_root_dir;
+        self
+    }
+
+    pub fn resources(&self) -> Option<&SystemResources> {
+        self.resources.as_ref()
+    }
+
+    pub fn with_resources(mut self, resources: Option<SystemResources>) -> Self {
+        self.resources = resources;
+        self
+    }
+}
+
+/// A difference between the modules the runtime last recorded that it had
+/// created and the modules that actually exist.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discrepancy {
+    /// The module was created but no longer exists.
+    Missing(String),
+    /// The module exists but was not created by this runtime, or was created
+    /// after its state was last recorded.
+    Unexpected(String),
+    /// The module exists but runs a different image than the one it was
+    /// created with.
+    ImageChanged {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Discrepancy::Missing(ref name) => write!(f, "module {} is missing", name),
+            Discrepancy::Unexpected(ref name) => write!(f, "module {} is unexpected", name),
+            Discrepancy::ImageChanged {
+                ref name,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "module {} runs image {} instead of {}",
+                name, actual, expected
+            ),
+        }
+    }
+}
+
+/// The result of comparing the modules a runtime recorded with the modules
+/// that exist.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IntegrityReport {
+    discrepancies: Vec<Discrepancy>,
+    /// Whether the recorded state could not be read and was rebuilt from the
+    /// modules that exist.
+    rebuilt: bool,
+}
+
+impl IntegrityReport {
+    pub fn new(discrepancies: Vec<Discrepancy>) -> Self {
+        IntegrityReport {
+            discrepancies,
+            rebuilt: false,
+        }
+    }
+
+    pub fn with_rebuilt(mut self, rebuilt: bool) -> Self {
+        self.rebuilt = rebuilt;
+        self
+    }
+
+    pub fn discrepancies(&self) -> &[Discrepancy] {
+        &self.discrepancies
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    pub fn rebuilt(&self) -> bool {
+        self.rebuilt
+    }
+}
+
+/// The configuration a module actually runs with, as resolved by the runtime
+/// when the module was created: the environment the module sees includes
+/// variables added by the runtime, not just those in the module's spec.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleInspection {
+    name: String,
+    image: String,
+    image_id: Option<String>,
+    env: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    create_options: serde_json::Value,
+}
+
+impl ModuleInspection {
+    pub fn new(name: String, image: String) -> Self {
+        ModuleInspection {
+            name,
+            image,
+            ..ModuleInspection::default()
+        }
+    }
+
+    pub fn with_image_id(mut self, image_id: Option<String>) -> Self {
+        self.image_id = image_id;
+        self
+    }
+
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_create_options(mut self, create_options: serde_json::Value) -> Self {
+        self.create_options = create_options;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub fn image_id(&self) -> Option<&str> {
+        self.image_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    pub fn create_options(&self) -> &serde_json::Value {
+        &self.create_options
     }
 }
 
@@ -356,14 +816,23 @@ pub trait ModuleRuntime {
     type RestartFuture: Future<Item = (), Error = Self::Error> + Send;
     type StartFuture: Future<Item = (), Error = Self::Error> + Send;
     type StopFuture: Future<Item = (), Error = Self::Error> + Send;
+    type TopFuture: Future<Item = Vec<Pid>, Error = Self::Error> + Send;
     type SystemInfoFuture: Future<Item = SystemInfo, Error = Self::Error> + Send;
     type RemoveAllFuture: Future<Item = (), Error = Self::Error> + Send;
+    type CheckIntegrityFuture: Future<Item = IntegrityReport, Error = Self::Error> + Send;
+    type InspectFuture: Future<Item = ModuleInspection, Error = Self::Error> + Send;
+    type KillFuture: Future<Item = (), Error = Self::Error> + Send;
+    type StopAllFuture: Future<Item = (), Error = Self::Error> + Send;
+    type UpdateFuture: Future<Item = (), Error = Self::Error> + Send;
 
     fn init(&self) -> Self::InitFuture;
     fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture;
     fn start(&self, id: &str) -> Self::StartFuture;
     fn stop(&self, id: &str, wait_before_kill: Option<Duration>) -> Self::StopFuture;
     fn restart(&self, id: &str) -> Self::RestartFuture;
+    /// Sends `signal`, like SIGHUP, to the main process of the module, for
+    /// modules that react to signals without being restarted.
+    fn kill(&self, id: &str, signal: &str) -> Self::KillFuture;
     fn remove(&self, id: &str) -> Self::RemoveFuture;
     fn system_info(&self) -> Self::SystemInfoFuture;
     fn list(&self) -> Self::ListFuture;
@@ -371,6 +840,30 @@ pub trait ModuleRuntime {
     fn logs(&self, id: &str, options: &LogOptions) -> Self::LogsFuture;
     fn registry(&self) -> &Self::ModuleRegistry;
     fn remove_all(&self) -> Self::RemoveAllFuture;
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture;
+    fn inspect(&self, id: &str) -> Self::InspectFuture;
+    fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture;
+    fn update(&self, module: ModuleSpec<Self::Config>, start: bool) -> Self::UpdateFuture;
+    /// The processes running in the module, its own and any it started.
+    fn top(&self, id: &str) -> Self::TopFuture;
+
+    /// Where the runtime bumps the epochs of the modules it starts, stops or
+    /// sees restarted, if it does.
+    fn epochs(&self) -> Option<&ModuleEpochs> {
+        None
+    }
+
+    /// The flag the runtime keeps set while it creates or removes modules,
+    /// if it does.
+    fn busy(&self) -> Option<&BusyFlag> {
+        None
+    }
+
+    /// Asks the container runtime for the system information anew, for
+    /// runtimes whose `system_info` answers with what they were told last.
+    fn refresh_system_info(&self) -> Self::SystemInfoFuture {
+        self.system_info()
+    }
 }
 
 #[cfg(test)]
@@ -389,6 +882,7 @@ mod tests {
             ("running", ModuleStatus::Running),
             ("stopped", ModuleStatus::Stopped),
             ("failed", ModuleStatus::Failed),
+            ("backoff", ModuleStatus::Backoff),
         ]
     }
 
@@ -408,6 +902,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn log_tail_round_trips() {
+        for &(s, tail) in &[
+            ("all", LogTail::All),
+            ("0", LogTail::Num(0)),
+            ("100", LogTail::Num(100)),
+        ] {
+            assert_eq!(tail, LogTail::from_str(s).unwrap());
+            assert_eq!(s, tail.to_string());
+        }
+    }
+
+    #[test]
+    fn invalid_log_tails_fail() {
+        for s in &["", "-1", "ALL", "10 lines", "1.5"] {
+            match *LogTail::from_str(s).unwrap_err().kind() {
+                ErrorKind::InvalidLogTail(ref tail) => assert_eq!(s, tail),
+                ref kind => panic!("Expected an invalid log tail, got {:?}", kind),
+            }
+        }
+    }
+
     #[test]
     fn module_config_empty_name_fails() {
         match ModuleSpec::new("", "docker", 10_i32, HashMap::new()) {
@@ -452,6 +968,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn registry_credential_debug_hides_password() {
+        let credential = RegistryCredential::new(
+            "contoso.azurecr.io".to_string(),
+            "contoso".to_string(),
+            "hunter2".to_string(),
+        );
+
+        let debug = format!("{:?}", credential);
+
+        assert!(debug.contains("contoso.azurecr.io"));
+        assert!(!debug.contains("hunter2"));
+    }
+
     #[test]
     fn system_info_new_and_access_succeed() {
         //arrange
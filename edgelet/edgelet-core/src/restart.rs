@@ -0,0 +1,490 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use module::{ModuleHealth, ModuleRuntimeState, ModuleStatus};
+
+const DEFAULT_BASE_DELAY_SECS: u64 = 10;
+const DEFAULT_MAX_DELAY_SECS: u64 = 300;
+const DEFAULT_HEALTHY_AFTER_SECS: u64 = 600;
+const DEFAULT_UNHEALTHY_GRACE_PERIOD_SECS: u64 = 300;
+
+/// How the watchdog restarts a module that keeps failing. The wait between
+/// restarts doubles from `base_delay` up to `max_delay`, and the count starts
+/// over once the module has stayed up for `healthy_after`. With
+/// `max_restarts` set, the watchdog stops restarting the module after that
+/// many consecutive restarts.
+///
+/// With `unhealthy_checks` set, a running module that reports itself
+/// unhealthy that many checks in a row is restarted too. Its health is not
+/// looked at again until `unhealthy_grace_period` after that restart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestartPolicy {
+    base_delay: Duration,
+    max_delay: Duration,
+    healthy_after: Duration,
+    max_restarts: Option<u32>,
+    unhealthy_checks: Option<u32>,
+    unhealthy_grace_period: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            base_delay: Duration::from_secs(DEFAULT_BASE_DELAY_SECS),
+            max_delay: Duration::from_secs(DEFAULT_MAX_DELAY_SECS),
+            healthy_after: Duration::from_secs(DEFAULT_HEALTHY_AFTER_SECS),
+            max_restarts: None,
+            unhealthy_checks: None,
+            unhealthy_grace_period: Duration::from_secs(DEFAULT_UNHEALTHY_GRACE_PERIOD_SECS),
+        }
+    }
+}
+
+impl RestartPolicy {
+    pub fn new() -> Self {
+        RestartPolicy::default()
+    }
+
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn healthy_after(&self) -> Duration {
+        self.healthy_after
+    }
+
+    pub fn with_healthy_after(mut self, healthy_after: Duration) -> Self {
+        self.healthy_after = healthy_after;
+        self
+    }
+
+    pub fn max_restarts(&self) -> Option<u32> {
+        self.max_restarts
+    }
+
+    pub fn with_max_restarts(mut self, max_restarts: Option<u32>) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    pub fn unhealthy_checks(&self) -> Option<u32> {
+        self.unhealthy_checks
+    }
+
+    pub fn with_unhealthy_checks(mut self, unhealthy_checks: Option<u32>) -> Self {
+        self.unhealthy_checks = unhealthy_checks;
+        self
+    }
+
+    pub fn unhealthy_grace_period(&self) -> Duration {
+        self.unhealthy_grace_period
+    }
+
+    pub fn with_unhealthy_grace_period(mut self, unhealthy_grace_period: Duration) -> Self {
+        self.unhealthy_grace_period = unhealthy_grace_period;
+        self
+    }
+
+    /// The time to wait after the `restarts`th consecutive restart before
+    /// the next one.
+    pub fn delay(&self, restarts: u32) -> Duration {
+        2_u32
+            .checked_pow(restarts.saturating_sub(1))
+            .and_then(|factor| self.base_delay.checked_mul(factor))
+            .map_or(self.max_delay, |delay| cmp::min(delay, self.max_delay))
+    }
+}
+
+/// What to do about a module that is not running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Restart {
+    /// Start it now. Should it fail again, the next restart is held back
+    /// for the given time.
+    Now(Duration),
+    /// Leave it alone for the given time.
+    After(Duration),
+    /// Leave it alone; it has been restarted `max_restarts` times.
+    GiveUp,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Restarts {
+    count: u32,
+    last: Instant,
+    next: Instant,
+    gave_up: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Unhealthy {
+    checks: u32,
+    grace_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct State {
+    policy: RestartPolicy,
+    modules: HashMap<String, Restarts>,
+    unhealthy: HashMap<String, Unhealthy>,
+}
+
+/// Consecutive restarts of each module, shared between the watchdog, which
+/// decides when to restart modules, and the module runtime, which reports
+/// the modules that are being held back.
+#[derive(Clone, Debug)]
+pub struct ModuleRestarts(Arc<Mutex<State>>);
+
+impl Default for ModuleRestarts {
+    fn default() -> Self {
+        ModuleRestarts::new(RestartPolicy::default())
+    }
+}
+
+impl ModuleRestarts {
+    pub fn new(policy: RestartPolicy) -> Self {
+        ModuleRestarts(Arc::new(Mutex::new(State {
+            policy,
+            modules: HashMap::new(),
+            unhealthy: HashMap::new(),
+        })))
+    }
+
+    pub fn policy(&self) -> RestartPolicy {
+        self.lock().policy
+    }
+
+    /// Replaces the policy. Restarts already scheduled keep their time.
+    pub fn set_policy(&self, policy: RestartPolicy) {
+        self.lock().policy = policy;
+    }
+
+    /// Notes that `name` is running. Once it has been up for the policy's
+    /// `healthy_after` since it was last restarted, its restarts are
+    /// forgotten.
+    pub fn running(&self, name: &str, now: Instant) {
+        let mut state = self.lock();
+        let healthy_after = state.policy.healthy_after;
+        let healthy = state
+            .modules
+            .get(name)
+            .map_or(false, |restarts| now >= restarts.last + healthy_after);
+        if healthy {
+            debug!("Module {} is healthy, resetting its restart count", name);
+            state.modules.remove(name);
+        }
+    }
+
+    /// Decides whether `name`, found not running at `now`, is restarted.
+    /// Deciding to restart it counts as a restart.
+    pub fn failed(&self, name: &str, now: Instant) -> Restart {
+        let mut state = self.lock();
+        let policy = state.policy;
+        let restarts = state.modules.entry(name.to_string()).or_insert(Restarts {
+            count: 0,
+            last: now,
+            next: now,
+            gave_up: false,
+        });
+
+        if restarts.gave_up {
+            Restart::GiveUp
+        } else if now < restarts.next {
+            Restart::After(restarts.next - now)
+        } else if policy
+            .max_restarts
+            .map_or(false, |max_restarts| restarts.count >= max_restarts)
+        {
+            restarts.gave_up = true;
+            Restart::GiveUp
+        } else {
+            restarts.count += 1;
+            let delay = policy.delay(restarts.count);
+            restarts.last = now;
+            restarts.next = now + delay;
+            Restart::Now(delay)
+        }
+    }
+
+    /// Decides whether `name`, found running with `health` at `now`, is
+    /// restarted for having been unhealthy for the policy's
+    /// `unhealthy_checks` consecutive checks. Deciding to restart it starts
+    /// its grace period, during which its health is ignored.
+    pub fn unhealthy(&self, name: &str, health: Option<ModuleHealth>, now: Instant) -> bool {
+        let mut state = self.lock();
+        let policy = state.policy;
+        let unhealthy_checks = match policy.unhealthy_checks {
+            Some(unhealthy_checks) => unhealthy_checks,
+            None => {
+                state.unhealthy.remove(name);
+                return false;
+            }
+        };
+        let unhealthy = state.unhealthy.entry(name.to_string()).or_insert(Unhealthy {
+            checks: 0,
+            grace_until: None,
+        });
+
+        if unhealthy.grace_until.map_or(false, |grace_until| now < grace_until) {
+            return false;
+        }
+        unhealthy.grace_until = None;
+
+        if health == Some(ModuleHealth::Unhealthy) {
+            unhealthy.checks += 1;
+        } else {
+            unhealthy.checks = 0;
+        }
+
+        if unhealthy.checks >= cmp::max(unhealthy_checks, 1) {
+            unhealthy.checks = 0;
+            unhealthy.grace_until = Some(now + policy.unhealthy_grace_period);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reports a module that is not running as `Backoff` while its next
+    /// restart is held back, and as `Failed` once the watchdog gave up on it.
+    pub fn report(
+        &self,
+        name: &str,
+        state: ModuleRuntimeState,
+        now: Instant,
+    ) -> ModuleRuntimeState {
+        if *state.status() == ModuleStatus::Running {
+            return state;
+        }
+
+        match self.lock().modules.get(name) {
+            Some(restarts) if restarts.gave_up => state
+                .with_status(ModuleStatus::Failed)
+                .with_status_description(Some(format!(
+                    "Not restarted again after {} restarts",
+                    restarts.count
+                ))),
+            Some(restarts) if now < restarts.next => state
+                .with_status(ModuleStatus::Backoff)
+                .with_status_description(Some(format!(
+                    "Restarting in {} seconds after {} restarts",
+                    (restarts.next - now).as_secs(),
+                    restarts.count
+                ))),
+            _ => state,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        // The state is only updated in place, so a panic while the lock was
+        // held cannot have left it inconsistent.
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy::new()
+            .with_base_delay(Duration::from_secs(10))
+            .with_max_delay(Duration::from_secs(60))
+            .with_healthy_after(Duration::from_secs(300))
+    }
+
+    /// Simulates a module that fails as soon as it is started, checked every
+    /// second, and returns the seconds at which it was restarted.
+    fn crash_loop(restarts: &ModuleRestarts, start: Instant, secs: u64) -> Vec<u64> {
+        (0..secs)
+            .filter(|sec| match restarts.failed("crashy", start + Duration::from_secs(*sec)) {
+                Restart::Now(_) => true,
+                Restart::After(_) | Restart::GiveUp => false,
+            }).collect()
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = policy();
+        let delays: Vec<_> = (1..8).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(vec![10, 20, 40, 60, 60, 60, 60], delays);
+        assert_eq!(Duration::from_secs(60), policy.delay(u32::max_value()));
+    }
+
+    #[test]
+    fn crash_looping_module_is_restarted_with_backoff() {
+        let restarts = ModuleRestarts::new(policy());
+        let start = Instant::now();
+
+        assert_eq!(
+            vec![0, 10, 30, 70, 130, 190, 250],
+            crash_loop(&restarts, start, 300)
+        );
+    }
+
+    #[test]
+    fn healthy_module_starts_over() {
+        let restarts = ModuleRestarts::new(policy());
+        let start = Instant::now();
+        assert_eq!(vec![0, 10, 30], crash_loop(&restarts, start, 60));
+
+        // Up, but not for long enough.
+        restarts.running("crashy", start + Duration::from_secs(100));
+        assert_eq!(
+            Restart::Now(Duration::from_secs(60)),
+            restarts.failed("crashy", start + Duration::from_secs(100))
+        );
+
+        restarts.running("crashy", start + Duration::from_secs(400));
+        assert_eq!(
+            Restart::Now(Duration::from_secs(10)),
+            restarts.failed("crashy", start + Duration::from_secs(400))
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_restarts() {
+        let restarts = ModuleRestarts::new(policy().with_max_restarts(Some(3)));
+        let start = Instant::now();
+
+        assert_eq!(vec![0, 10, 30], crash_loop(&restarts, start, 300));
+        assert_eq!(
+            Restart::GiveUp,
+            restarts.failed("crashy", start + Duration::from_secs(1000))
+        );
+    }
+
+    #[test]
+    fn modules_are_tracked_separately() {
+        let restarts = ModuleRestarts::new(policy());
+        let start = Instant::now();
+        assert_eq!(vec![0, 10], crash_loop(&restarts, start, 20));
+
+        assert_eq!(
+            Restart::Now(Duration::from_secs(10)),
+            restarts.failed("other", start + Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn held_back_modules_are_reported() {
+        let restarts = ModuleRestarts::new(policy().with_max_restarts(Some(1)));
+        let start = Instant::now();
+        let stopped = ModuleRuntimeState::default().with_status(ModuleStatus::Failed);
+
+        restarts.failed("crashy", start);
+        let state = restarts.report("crashy", stopped.clone(), start + Duration::from_secs(4));
+        assert_eq!(ModuleStatus::Backoff, *state.status());
+        assert_eq!(
+            Some("Restarting in 6 seconds after 1 restarts"),
+            state.status_description()
+        );
+
+        let running = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        let state = restarts.report("crashy", running, start + Duration::from_secs(4));
+        assert_eq!(ModuleStatus::Running, *state.status());
+        assert_eq!(None, state.status_description());
+
+        assert_eq!(
+            Restart::GiveUp,
+            restarts.failed("crashy", start + Duration::from_secs(10))
+        );
+        let state = restarts.report("crashy", stopped.clone(), start + Duration::from_secs(10));
+        assert_eq!(ModuleStatus::Failed, *state.status());
+        assert_eq!(
+            Some("Not restarted again after 1 restarts"),
+            state.status_description()
+        );
+
+        let state = restarts.report("other", stopped, start);
+        assert_eq!(ModuleStatus::Failed, *state.status());
+        assert_eq!(None, state.status_description());
+    }
+
+    fn unhealthy_policy() -> RestartPolicy {
+        policy()
+            .with_unhealthy_checks(Some(3))
+            .with_unhealthy_grace_period(Duration::from_secs(100))
+    }
+
+    /// Checks `name` once a second with the given health and returns the
+    /// seconds at which it was restarted.
+    fn health_checks(
+        restarts: &ModuleRestarts,
+        start: Instant,
+        health: &[Option<ModuleHealth>],
+    ) -> Vec<u64> {
+        (0..)
+            .zip(health)
+            .filter(|&(sec, health)| {
+                restarts.unhealthy("sick", *health, start + Duration::from_secs(sec))
+            }).map(|(sec, _)| sec)
+            .collect()
+    }
+
+    #[test]
+    fn unhealthy_modules_are_left_alone_by_default() {
+        let restarts = ModuleRestarts::new(policy());
+        let start = Instant::now();
+
+        let health = vec![Some(ModuleHealth::Unhealthy); 10];
+        assert!(health_checks(&restarts, start, &health).is_empty());
+    }
+
+    #[test]
+    fn unhealthy_streak_restarts_module() {
+        let restarts = ModuleRestarts::new(unhealthy_policy());
+        let start = Instant::now();
+
+        let health = vec![
+            Some(ModuleHealth::Starting),
+            Some(ModuleHealth::Unhealthy),
+            Some(ModuleHealth::Unhealthy),
+            Some(ModuleHealth::Unhealthy),
+        ];
+        assert_eq!(vec![3], health_checks(&restarts, start, &health));
+    }
+
+    #[test]
+    fn recovering_resets_unhealthy_streak() {
+        let restarts = ModuleRestarts::new(unhealthy_policy());
+        let start = Instant::now();
+
+        let health = vec![
+            Some(ModuleHealth::Unhealthy),
+            Some(ModuleHealth::Unhealthy),
+            Some(ModuleHealth::Healthy),
+            Some(ModuleHealth::Unhealthy),
+            Some(ModuleHealth::Unhealthy),
+            None,
+            Some(ModuleHealth::Unhealthy),
+        ];
+        assert!(health_checks(&restarts, start, &health).is_empty());
+    }
+
+    #[test]
+    fn unhealthy_restart_starts_grace_period() {
+        let restarts = ModuleRestarts::new(unhealthy_policy());
+        let start = Instant::now();
+
+        let health = vec![Some(ModuleHealth::Unhealthy); 200];
+        assert_eq!(vec![2, 104], health_checks(&restarts, start, &health));
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json;
+
+/// Remembers which modules were running the last time the watchdog saw the
+/// edge runtime module running, so that offline start only starts the
+/// modules the deployment had running, and not the ones it stopped.
+///
+/// When opened on a file, the names are kept in it as JSON, so that they
+/// hold across restarts of the device. A file that is missing or cannot be
+/// read starts out empty. Failing to write the file is logged but does not
+/// fail the check that recorded the modules.
+#[derive(Clone, Debug, Default)]
+pub struct RunningModules {
+    names: Arc<Mutex<BTreeSet<String>>>,
+    path: Option<PathBuf>,
+}
+
+impl RunningModules {
+    pub fn new() -> Self {
+        RunningModules::default()
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let names = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|err| {
+                warn!(
+                    "Running modules in {} are corrupt and will be started over: {}",
+                    path.display(),
+                    err
+                );
+                BTreeSet::new()
+            }),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => {
+                warn!(
+                    "Could not read running modules from {} and will start them over: {}",
+                    path.display(),
+                    err
+                );
+                BTreeSet::new()
+            }
+        };
+
+        RunningModules {
+            names: Arc::new(Mutex::new(names)),
+            path: Some(path),
+        }
+    }
+
+    /// Records that the modules `names`, and only those, are running.
+    pub fn record(&self, names: BTreeSet<String>) {
+        let mut guard = self.names.lock().unwrap_or_else(|err| err.into_inner());
+        if *guard != names {
+            if let Some(ref path) = self.path {
+                save(path, &names);
+            }
+            *guard = names;
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .contains(name)
+    }
+}
+
+fn save(path: &Path, names: &BTreeSet<String>) {
+    // Write a temporary file and move it over the old one, so that the file
+    // is never left half written.
+    let tmp = path.with_extension("tmp");
+    let result = serde_json::to_vec(names)
+        .map_err(io::Error::from)
+        .and_then(|contents| fs::write(&tmp, contents))
+        .and_then(|()| fs::rename(&tmp, path));
+    if let Err(err) = result {
+        warn!(
+            "Could not save running modules to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn test_path(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("edgelet-core-running-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("running_modules.json")
+    }
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn running_modules_are_kept_across_restarts() {
+        let path = test_path("kept");
+
+        RunningModules::open(&path).record(names(&["edgeAgent", "tempSensor"]));
+        let running = RunningModules::open(&path);
+        assert!(running.contains("tempSensor"));
+        assert!(!running.contains("edgeHub"));
+
+        running.record(names(&["edgeAgent"]));
+        assert!(!RunningModules::open(&path).contains("tempSensor"));
+    }
+
+    #[test]
+    fn corrupt_running_modules_are_started_over() {
+        let path = test_path("corrupt");
+        fs::write(&path, "not json").unwrap();
+
+        let running = RunningModules::open(&path);
+        assert!(!running.contains("tempSensor"));
+
+        running.record(names(&["tempSensor"]));
+        assert!(RunningModules::open(&path).contains("tempSensor"));
+    }
+}
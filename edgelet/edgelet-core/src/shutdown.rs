@@ -0,0 +1,229 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use futures::{stream, Future, Stream};
+use tokio::timer::Timeout;
+
+use module::{Module, ModuleRuntime};
+
+// Enough time for edgeAgent to shut down gracefully, which includes updating
+// its reported properties.
+const DEFAULT_PHASE_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// How much longer than `phase_timeout` a phase may take before it is given up
+/// on, to allow for the runtime killing the modules that didn't stop.
+const PHASE_GRACE_SECS: u64 = 5;
+
+/// How `ModuleRuntime::stop_all` stops modules. Modules are stopped in phases
+/// of ascending stop priority, so that a module is stopped before the modules
+/// it depends on. The priority of a module is the one it carries itself, or
+/// else its position in `stop_last`, counting from one. Every other module has
+/// priority zero and is stopped first. By default, edgeHub is stopped after
+/// the modules that send through it, and edgeAgent last.
+///
+/// At most `max_concurrency` modules of a phase are stopped at once, and each
+/// is given `phase_timeout` to stop before it is killed. A phase that takes
+/// much longer than that is given up on, and the next phase starts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopAllOptions {
+    stop_last: Vec<String>,
+    phase_timeout: Duration,
+    max_concurrency: usize,
+}
+
+impl Default for StopAllOptions {
+    fn default() -> Self {
+        StopAllOptions {
+            stop_last: vec!["edgeHub".to_string(), "edgeAgent".to_string()],
+            phase_timeout: Duration::from_secs(DEFAULT_PHASE_TIMEOUT_SECS),
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+        }
+    }
+}
+
+impl StopAllOptions {
+    pub fn new() -> Self {
+        StopAllOptions::default()
+    }
+
+    pub fn stop_last(&self) -> &[String] {
+        &self.stop_last
+    }
+
+    pub fn with_stop_last(mut self, stop_last: Vec<String>) -> Self {
+        self.stop_last = stop_last;
+        self
+    }
+
+    pub fn phase_timeout(&self) -> Duration {
+        self.phase_timeout
+    }
+
+    pub fn with_phase_timeout(mut self, phase_timeout: Duration) -> Self {
+        self.phase_timeout = phase_timeout;
+        self
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// The stop priority of the module `name`, which carries `priority`
+    /// itself if it has one.
+    pub fn priority(&self, name: &str, priority: Option<i64>) -> i64 {
+        priority.unwrap_or_else(|| {
+            self.stop_last
+                .iter()
+                .position(|last| last == name)
+                .map_or(0, |position| position as i64 + 1)
+        })
+    }
+
+    /// Groups the modules, given as names and the priorities they carry, into
+    /// the phases they are stopped in.
+    pub fn phases<I>(&self, modules: I) -> Vec<Vec<String>>
+    where
+        I: IntoIterator<Item = (String, Option<i64>)>,
+    {
+        let mut phases = BTreeMap::new();
+        for (name, priority) in modules {
+            phases
+                .entry(self.priority(&name, priority))
+                .or_insert_with(Vec::new)
+                .push(name);
+        }
+        phases.into_iter().map(|(_, names)| names).collect()
+    }
+}
+
+/// Stops the modules of `runtime` in the order `options` sets, for runtimes
+/// to implement `stop_all` with. A module that fails to stop is logged and
+/// left behind, so that it can't keep the modules after it running.
+pub fn stop_in_order<M>(
+    runtime: &M,
+    options: &StopAllOptions,
+) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+{
+    let runtime = runtime.clone();
+    let options = options.clone();
+    runtime.list().and_then(move |modules| {
+        let phases = options.phases(
+            modules
+                .iter()
+                .map(|module| (module.name().to_string(), module.stop_priority())),
+        );
+        stream::iter_ok(phases).for_each(move |phase| stop_phase(&runtime, phase, &options))
+    })
+}
+
+fn stop_phase<M>(
+    runtime: &M,
+    phase: Vec<String>,
+    options: &StopAllOptions,
+) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+{
+    info!("Stopping modules {}", phase.join(", "));
+    let runtime = runtime.clone();
+    let phase_timeout = options.phase_timeout();
+    let stops = stream::iter_ok::<_, ()>(phase)
+        .map(move |name| {
+            runtime.stop(&name, Some(phase_timeout)).then(move |result| {
+                if let Err(err) = result {
+                    warn!("Could not stop module {}: {}", name, err);
+                }
+                Ok(())
+            })
+        }).buffer_unordered(options.max_concurrency())
+        .for_each(|()| Ok(()));
+
+    let deadline = phase_timeout + Duration::from_secs(PHASE_GRACE_SECS);
+    Timeout::new(stops, deadline).or_else(move |_| {
+        warn!(
+            "Modules did not stop within {} seconds, stopping the next ones",
+            deadline.as_secs()
+        );
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phases(options: &StopAllOptions, modules: &[(&str, Option<i64>)]) -> Vec<Vec<String>> {
+        options.phases(
+            modules
+                .iter()
+                .map(|&(name, priority)| (name.to_string(), priority)),
+        )
+    }
+
+    #[test]
+    fn user_modules_stop_before_edge_hub_and_edge_agent() {
+        let phases = phases(
+            &StopAllOptions::default(),
+            &[
+                ("edgeAgent", None),
+                ("tempSensor", None),
+                ("edgeHub", None),
+                ("filter", None),
+            ],
+        );
+
+        assert_eq!(
+            vec![
+                vec!["tempSensor".to_string(), "filter".to_string()],
+                vec!["edgeHub".to_string()],
+                vec!["edgeAgent".to_string()],
+            ],
+            phases
+        );
+    }
+
+    #[test]
+    fn carried_priority_overrides_the_list() {
+        let phases = phases(
+            &StopAllOptions::default(),
+            &[("edgeHub", None), ("store", Some(1)), ("edgeAgent", Some(0))],
+        );
+
+        assert_eq!(
+            vec![
+                vec!["edgeAgent".to_string()],
+                vec!["edgeHub".to_string(), "store".to_string()],
+            ],
+            phases
+        );
+    }
+
+    #[test]
+    fn stop_last_is_configurable() {
+        let options = StopAllOptions::default().with_stop_last(vec!["broker".to_string()]);
+
+        let phases = phases(&options, &[("broker", None), ("edgeHub", None)]);
+
+        assert_eq!(
+            vec![vec!["edgeHub".to_string()], vec!["broker".to_string()]],
+            phases
+        );
+    }
+
+    #[test]
+    fn max_concurrency_is_at_least_one() {
+        let options = StopAllOptions::default().with_max_concurrency(0);
+
+        assert_eq!(1, options.max_concurrency());
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Signals that may be sent to modules, for modules that reload their
+//! config on SIGHUP or dump their state on SIGUSR1 instead of being
+//! restarted. Others, like SIGKILL or SIGSTOP, would let a caller of the APIs
+//! stop a module without the runtime knowing, so they have to be opened up.
+
+use error::{Error, ErrorKind, Result};
+
+/// The signals modules may always be sent.
+pub const SAFE_SIGNALS: &[&str] = &["SIGHUP", "SIGUSR1", "SIGUSR2", "SIGTERM"];
+
+const SIGNAL_PREFIX: &str = "SIG";
+
+/// Which signals modules may be sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KillSignals {
+    /// Only `SAFE_SIGNALS`.
+    Safe,
+    /// Any signal the container runtime knows.
+    Any,
+}
+
+impl Default for KillSignals {
+    fn default() -> Self {
+        KillSignals::Safe
+    }
+}
+
+impl KillSignals {
+    /// Checks that `signal` may be sent, returning its name as the container
+    /// runtime expects it. Names are accepted with or without the `SIG`
+    /// prefix and in any case, so "hup" is SIGHUP. Signal numbers are only
+    /// accepted when any signal is.
+    pub fn check(self, signal: &str) -> Result<String> {
+        let upper = signal.trim().to_uppercase();
+        let is_number = !upper.is_empty() && upper.chars().all(|c| c.is_ascii_digit());
+        let name = if is_number || upper.starts_with(SIGNAL_PREFIX) {
+            upper
+        } else {
+            format!("{}{}", SIGNAL_PREFIX, upper)
+        };
+
+        let valid = (is_number || name.len() > SIGNAL_PREFIX.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-');
+        if !valid {
+            return Err(Error::from(ErrorKind::InvalidSignal(signal.to_string())));
+        }
+
+        match self {
+            KillSignals::Any => Ok(name),
+            KillSignals::Safe if SAFE_SIGNALS.contains(&name.as_str()) => Ok(name),
+            KillSignals::Safe => Err(Error::from(ErrorKind::SignalNotAllowed(name))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_signals_are_allowed() {
+        for &(signal, expected) in &[
+            ("SIGHUP", "SIGHUP"),
+            ("HUP", "SIGHUP"),
+            ("hup", "SIGHUP"),
+            ("sigusr1", "SIGUSR1"),
+            ("USR2", "SIGUSR2"),
+            ("SIGTERM", "SIGTERM"),
+        ] {
+            assert_eq!(expected, KillSignals::Safe.check(signal).unwrap());
+            assert_eq!(expected, KillSignals::Any.check(signal).unwrap());
+        }
+    }
+
+    #[test]
+    fn other_signals_need_any() {
+        for &(signal, expected) in &[("SIGKILL", "SIGKILL"), ("stop", "SIGSTOP"), ("9", "9")] {
+            match *KillSignals::Safe.check(signal).unwrap_err().kind() {
+                ErrorKind::SignalNotAllowed(ref name) => assert_eq!(expected, name),
+                ref kind => panic!("Expected {} not to be allowed, got {:?}", signal, kind),
+            }
+            assert_eq!(expected, KillSignals::Any.check(signal).unwrap());
+        }
+    }
+
+    #[test]
+    fn malformed_signals_are_invalid() {
+        for signal in &["", "SIG", "SIG HUP", "HUP;rm", "SIGHUP\n1"] {
+            match *KillSignals::Any.check(signal).unwrap_err().kind() {
+                ErrorKind::InvalidSignal(ref name) => assert_eq!(signal, name),
+                ref kind => panic!("Expected {:?} to be invalid, got {:?}", signal, kind),
+            }
+        }
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use tokio::timer::Delay;
+
+use module::{Module, ModuleRuntime, ModuleRuntimeState, ModuleStatus};
+
+/// How often a module that was just started is checked on.
+const POLL_INTERVAL_MILLIS: u64 = 250;
+
+/// What became of a module that was started with `start_and_confirm`.
+#[derive(Debug)]
+pub enum StartOutcome<M> {
+    /// The module is running.
+    Running(M, ModuleRuntimeState),
+    /// The module exited right after it was started. The state holds its exit
+    /// code.
+    Exited(M, ModuleRuntimeState),
+    /// The module was neither running nor exited when the wait was over.
+    Starting(M, ModuleRuntimeState),
+    /// The module was removed before it could be checked on.
+    Removed,
+}
+
+/// Starts a module, then checks on it until it is running or has exited, for
+/// at most `wait`. Unlike `ModuleRuntime::start`, which resolves as soon as
+/// the runtime accepts the request, this tells a module whose process exits
+/// right away, such as one with a bad entrypoint, from one that runs.
+pub fn start_and_confirm<M>(
+    runtime: &M,
+    id: &str,
+    wait: Duration,
+) -> impl Future<Item = StartOutcome<M::Module>, Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<M::Error>,
+{
+    let runtime = runtime.clone();
+    let id = id.to_string();
+    let deadline = Instant::now() + wait;
+    runtime.start(&id).and_then(move |()| {
+        future::loop_fn((), move |()| {
+            observe(&runtime, id.clone()).and_then(move |observed| {
+                let outcome = match observed {
+                    None => return Either::A(future::ok(Loop::Break(StartOutcome::Removed))),
+                    Some((module, state)) => {
+                        if *state.status() == ModuleStatus::Running {
+                            StartOutcome::Running(module, state)
+                        } else if has_exited(&state) {
+                            StartOutcome::Exited(module, state)
+                        } else {
+                            StartOutcome::Starting(module, state)
+                        }
+                    }
+                };
+                let now = Instant::now();
+                match outcome {
+                    StartOutcome::Starting(..) if now < deadline => {
+                        let poll = Duration::from_millis(POLL_INTERVAL_MILLIS);
+                        let next = cmp::min(now + poll, deadline);
+                        Either::B(Delay::new(next).then(|result| {
+                            if let Err(err) = result {
+                                warn!("Could not wait for the module to start: {}", err);
+                                return Ok(Loop::Break(outcome));
+                            }
+                            Ok(Loop::Continue(()))
+                        }))
+                    }
+                    outcome => Either::A(future::ok(Loop::Break(outcome))),
+                }
+            })
+        })
+    })
+}
+
+/// Initializes the runtime, trying up to `attempts` times, `retry_delay`
+/// apart, before giving up with the error of the last attempt. A container
+/// runtime that was only just started may not be ready to create the network
+/// modules are attached to, and no module may be created before it is.
+pub fn init_with_retries<M>(
+    runtime: &M,
+    attempts: u32,
+    retry_delay: Duration,
+) -> impl Future<Item = (), Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+{
+    let runtime = runtime.clone();
+    let attempts = cmp::max(attempts, 1);
+    future::loop_fn(1, move |attempt| {
+        runtime.init().then(move |result| match result {
+            Ok(()) => Either::A(future::ok(Loop::Break(()))),
+            Err(err) => {
+                if attempt >= attempts {
+                    return Either::A(future::err(err));
+                }
+                warn!(
+                    "Could not initialize the module runtime, attempt {} of {}: {}",
+                    attempt, attempts, err
+                );
+                Either::B(Delay::new(Instant::now() + retry_delay).then(move |result| {
+                    if let Err(timer_err) = result {
+                        warn!("Could not wait to initialize the runtime again: {}", timer_err);
+                        return Err(err);
+                    }
+                    Ok(Loop::Continue(attempt + 1))
+                }))
+            }
+        })
+    })
+}
+
+/// The module with the name `id` and its current state, if it still exists.
+fn observe<M>(
+    runtime: &M,
+    id: String,
+) -> impl Future<Item = Option<(M::Module, ModuleRuntimeState)>, Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<M::Error>,
+{
+    runtime.list().and_then(move |modules| {
+        match modules.into_iter().find(|module| module.name() == id) {
+            Some(module) => Either::A(
+                module
+                    .runtime_state()
+                    .map_err(Into::into)
+                    .map(|state| Some((module, state))),
+            ),
+            None => Either::B(future::ok(None)),
+        }
+    })
+}
+
+/// Whether the module ran and then exited, as opposed to not having been
+/// started yet.
+fn has_exited(state: &ModuleRuntimeState) -> bool {
+    match (state.exit_code(), state.started_at(), state.finished_at()) {
+        (Some(_), Some(started_at), Some(finished_at)) => finished_at >= started_at,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{Duration as ChronoDuration, Utc};
+    use futures::future::FutureResult;
+    use futures::stream::{self, Empty};
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+    use module::{
+        IntegrityReport, LogOptions, ModuleInspection, ModuleRegistry, ModuleSpec, SystemInfo,
+    };
+    use pid::Pid;
+    use shutdown::StopAllOptions;
+
+    #[derive(Clone, Copy, Debug, Fail)]
+    pub enum Error {
+        #[fail(display = "Module not found")]
+        ModuleNotFound,
+        #[fail(display = "Network not created")]
+        NetworkNotCreated,
+    }
+
+    /// A module that goes through `states`, one each time it is checked on,
+    /// and then stays in the last one.
+    #[derive(Clone)]
+    struct TestModule {
+        name: String,
+        states: Arc<Mutex<VecDeque<ModuleRuntimeState>>>,
+        checks: Arc<Mutex<usize>>,
+    }
+
+    impl TestModule {
+        fn new(name: &str, states: Vec<ModuleRuntimeState>) -> Self {
+            TestModule {
+                name: name.to_string(),
+                states: Arc::new(Mutex::new(states.into_iter().collect())),
+                checks: Arc::new(Mutex::new(0)),
+            }
+        }
+
+        fn checks(&self) -> usize {
+            *self.checks.lock().unwrap()
+        }
+    }
+
+    impl Module for TestModule {
+        type Config = ();
+        type Error = Error;
+        type RuntimeStateFuture = FutureResult<ModuleRuntimeState, Self::Error>;
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn type_(&self) -> &str {
+            "test"
+        }
+
+        fn config(&self) -> &Self::Config {
+            &()
+        }
+
+        fn runtime_state(&self) -> Self::RuntimeStateFuture {
+            *self.checks.lock().unwrap() += 1;
+            let mut states = self.states.lock().unwrap();
+            let state = if states.len() > 1 {
+                states.pop_front().unwrap()
+            } else {
+                states[0].clone()
+            };
+            future::ok(state)
+        }
+    }
+
+    /// A runtime whose init fails until it has been tried `init_failures`
+    /// times, like one whose network is created late, and that records the
+    /// calls made to it.
+    #[derive(Clone)]
+    struct TestRuntime {
+        modules: Vec<TestModule>,
+        init_failures: Arc<Mutex<u32>>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestRuntime {
+        fn new(modules: Vec<TestModule>) -> Self {
+            TestRuntime {
+                modules,
+                init_failures: Arc::new(Mutex::new(0)),
+                calls: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn with_init_failures(self, init_failures: u32) -> Self {
+            *self.init_failures.lock().unwrap() = init_failures;
+            self
+        }
+
+        fn record(&self, call: &str) {
+            self.calls.lock().unwrap().push(call.to_string());
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl ModuleRegistry for TestRuntime {
+        type Error = Error;
+        type PullFuture = FutureResult<(), Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type Config = ();
+
+        fn pull(&self, _config: &Self::Config) -> Self::PullFuture {
+            future::ok(())
+        }
+
+        fn remove(&self, _name: &str) -> Self::RemoveFuture {
+            future::ok(())
+        }
+    }
+
+    impl ModuleRuntime for TestRuntime {
+        type Error = Error;
+        type Config = ();
+        type Module = TestModule;
+        type ModuleRegistry = Self;
+        type Chunk = Vec<u8>;
+        type Logs = Empty<Self::Chunk, Self::Error>;
+
+        type CreateFuture = FutureResult<(), Self::Error>;
+        type InitFuture = FutureResult<(), Self::Error>;
+        type ListFuture = FutureResult<Vec<Self::Module>, Self::Error>;
+        type ListWithDetailsStream = Empty<(Self::Module, ModuleRuntimeState), Self::Error>;
+        type LogsFuture = FutureResult<Self::Logs, Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type RestartFuture = FutureResult<(), Self::Error>;
+        type StartFuture = FutureResult<(), Self::Error>;
+        type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
+        type SystemInfoFuture = FutureResult<SystemInfo, Self::Error>;
+        type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = FutureResult<(), Self::Error>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
+
+        fn init(&self) -> Self::InitFuture {
+            self.record("init");
+            let mut init_failures = self.init_failures.lock().unwrap();
+            if *init_failures > 0 {
+                *init_failures -= 1;
+                future::err(Error::NetworkNotCreated)
+            } else {
+                future::ok(())
+            }
+        }
+
+        fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
+            self.record(&format!("create {}", module.name()));
+            future::ok(())
+        }
+
+        fn start(&self, id: &str) -> Self::StartFuture {
+            self.record(&format!("start {}", id));
+            if id == "missing" {
+                future::err(Error::ModuleNotFound)
+            } else {
+                future::ok(())
+            }
+        }
+
+        fn stop(&self, _id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
+            future::ok(())
+        }
+
+        fn restart(&self, _id: &str) -> Self::RestartFuture {
+            future::ok(())
+        }
+
+        fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
+            future::ok(())
+        }
+
+        fn remove(&self, _id: &str) -> Self::RemoveFuture {
+            future::ok(())
+        }
+
+        fn system_info(&self) -> Self::SystemInfoFuture {
+            future::ok(SystemInfo::new("linux".to_string(), "x86_64".to_string()))
+        }
+
+        fn list(&self) -> Self::ListFuture {
+            future::ok(self.modules.clone())
+        }
+
+        fn list_with_details(&self) -> Self::ListWithDetailsStream {
+            stream::empty()
+        }
+
+        fn logs(&self, _id: &str, _options: &LogOptions) -> Self::LogsFuture {
+            future::ok(stream::empty())
+        }
+
+        fn registry(&self) -> &Self::ModuleRegistry {
+            self
+        }
+
+        fn remove_all(&self) -> Self::RemoveAllFuture {
+            future::ok(())
+        }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            future::ok(IntegrityReport::default())
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            future::ok(ModuleInspection::default())
+        }
+
+        fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+            future::ok(())
+        }
+
+        fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            future::ok(())
+        }
+
+        fn top(&self, _id: &str) -> Self::TopFuture {
+            future::ok(vec![])
+        }
+    }
+
+    fn created() -> ModuleRuntimeState {
+        ModuleRuntimeState::default().with_status(ModuleStatus::Stopped)
+    }
+
+    fn running() -> ModuleRuntimeState {
+        ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Running)
+            .with_started_at(Some(Utc::now()))
+    }
+
+    fn exited(exit_code: i64) -> ModuleRuntimeState {
+        let started_at = Utc::now();
+        ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Failed)
+            .with_exit_code(Some(exit_code))
+            .with_started_at(Some(started_at))
+            .with_finished_at(Some(started_at + ChronoDuration::milliseconds(3)))
+    }
+
+    fn start(module: &TestModule, wait: Duration) -> Result<StartOutcome<TestModule>, Error> {
+        let runtime = TestRuntime::new(vec![module.clone()]);
+        Runtime::new()
+            .unwrap()
+            .block_on(start_and_confirm(&runtime, &module.name, wait))
+    }
+
+    #[test]
+    fn running_module_is_confirmed() {
+        let module = TestModule::new("sensor", vec![running()]);
+        match start(&module, Duration::from_secs(5)).unwrap() {
+            StartOutcome::Running(module, state) => {
+                assert_eq!("sensor", module.name());
+                assert_eq!(ModuleStatus::Running, *state.status());
+            }
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(1, module.checks());
+    }
+
+    #[test]
+    fn immediately_exiting_module_reports_its_exit_code() {
+        let module = TestModule::new("sensor", vec![exited(127)]);
+        match start(&module, Duration::from_secs(5)).unwrap() {
+            StartOutcome::Exited(_, state) => assert_eq!(Some(127), state.exit_code()),
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(1, module.checks());
+    }
+
+    #[test]
+    fn slow_starting_module_is_checked_on_until_it_runs() {
+        let module = TestModule::new("sensor", vec![created(), created(), running()]);
+        match start(&module, Duration::from_secs(5)).unwrap() {
+            StartOutcome::Running(..) => (),
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(3, module.checks());
+    }
+
+    #[test]
+    fn module_that_exits_while_starting_reports_its_exit_code() {
+        let module = TestModule::new("sensor", vec![created(), exited(1)]);
+        match start(&module, Duration::from_secs(5)).unwrap() {
+            StartOutcome::Exited(_, state) => assert_eq!(Some(1), state.exit_code()),
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(2, module.checks());
+    }
+
+    #[test]
+    fn module_still_starting_when_the_wait_is_over() {
+        let module = TestModule::new("sensor", vec![created()]);
+        match start(&module, Duration::from_millis(300)).unwrap() {
+            StartOutcome::Starting(_, state) => {
+                assert_eq!(ModuleStatus::Stopped, *state.status())
+            }
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(3, module.checks());
+
+        let module = TestModule::new("sensor", vec![created()]);
+        match start(&module, Duration::from_secs(0)).unwrap() {
+            StartOutcome::Starting(..) => (),
+            _ => panic!("unexpected outcome"),
+        }
+        assert_eq!(1, module.checks());
+    }
+
+    #[test]
+    fn previous_exit_is_not_mistaken_for_the_new_start() {
+        let restarted = exited(2).with_started_at(Some(Utc::now() + ChronoDuration::seconds(1)));
+        let module = TestModule::new("sensor", vec![restarted, running()]);
+        match start(&module, Duration::from_secs(5)).unwrap() {
+            StartOutcome::Running(..) => (),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn removed_module() {
+        let runtime = TestRuntime::new(vec![]);
+        let outcome = Runtime::new()
+            .unwrap()
+            .block_on(start_and_confirm(
+                &runtime,
+                "sensor",
+                Duration::from_secs(5),
+            )).unwrap();
+        match outcome {
+            StartOutcome::Removed => (),
+            _ => panic!("unexpected outcome"),
+        }
+    }
+
+    #[test]
+    fn start_errors_are_returned() {
+        let module = TestModule::new("missing", vec![running()]);
+        match start(&module, Duration::from_secs(5)) {
+            Err(Error::ModuleNotFound) => (),
+            _ => panic!("expected the start error"),
+        }
+        assert_eq!(0, module.checks());
+    }
+
+    /// Initializes the runtime like iotedged does before it creates and
+    /// starts the edge agent.
+    fn init_then_start_agent(runtime: &TestRuntime, attempts: u32) -> Result<(), Error> {
+        let agent = ModuleSpec::new("edgeAgent", "test", (), Default::default()).unwrap();
+        let (creating, starting) = (runtime.clone(), runtime.clone());
+        let run = init_with_retries(runtime, attempts, Duration::from_millis(10))
+            .and_then(move |()| creating.create(agent))
+            .and_then(move |()| starting.start("edgeAgent"));
+        Runtime::new().unwrap().block_on(run)
+    }
+
+    #[test]
+    fn agent_is_created_once_the_runtime_is_initialized() {
+        let runtime = TestRuntime::new(vec![]).with_init_failures(2);
+        init_then_start_agent(&runtime, 5).unwrap();
+        assert_eq!(
+            vec!["init", "init", "init", "create edgeAgent", "start edgeAgent"],
+            runtime.calls()
+        );
+    }
+
+    #[test]
+    fn agent_is_not_created_when_the_runtime_cannot_be_initialized() {
+        let runtime = TestRuntime::new(vec![]).with_init_failures(5);
+        match init_then_start_agent(&runtime, 3) {
+            Err(Error::NetworkNotCreated) => (),
+            _ => panic!("expected the init error"),
+        }
+        assert_eq!(vec!["init", "init", "init"], runtime.calls());
+    }
+}
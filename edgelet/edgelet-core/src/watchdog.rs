@@ -1,30 +1,41 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use std::time::{Duration, Instant};
+use std::cmp;
+use std::sync::Arc;
+use std::time::Duration;
 
+use chrono::Utc;
 use edgelet_utils::log_failure;
-use futures::future::{self, Either, FutureResult};
+use futures::future::{self, Either, FutureResult, Loop};
 use futures::Future;
 use log::Level;
-use tokio::prelude::*;
-use tokio::timer::Interval;
 
+use clock::{Clock, SystemClock};
+use env::{ModuleEnv, ModuleEnvSettings};
 use error::{Error, ErrorKind};
+use health::DaemonHealth;
 use identity::{Identity, IdentityManager, IdentitySpec};
+use metrics::{Metrics, MetricsRegistry, WATCHDOG_RESTARTS, WATCHDOG_UNHEALTHY_RESTARTS};
 use module::{Module, ModuleRegistry, ModuleRuntime, ModuleSpec, ModuleStatus};
-
-// Time to allow EdgeAgent to gracefully shutdown (including stopping all modules, and updating reported properties)
-const EDGE_RUNTIME_STOP_TIME: Duration = Duration::from_secs(60);
-
-/// This variable holds the generation ID associated with the Edge Agent module.
-const MODULE_GENERATIONID: &str = "IOTEDGE_MODULEGENERATIONID";
+use restart::{ModuleRestarts, Restart};
+use running::RunningModules;
+use shutdown::StopAllOptions;
 
 /// This is the frequency with which the watchdog checks for the status of the edge runtime module.
+/// A module waiting to be restarted is checked again as soon as its restart is due.
 const WATCHDOG_FREQUENCY_SECS: u64 = 60;
 
 pub struct Watchdog<M, I> {
     runtime: M,
     id_mgr: I,
+    restarts: ModuleRestarts,
+    health: DaemonHealth,
+    offline_start: bool,
+    running: RunningModules,
+    metrics: Arc<Metrics>,
+    stop_options: StopAllOptions,
+    clock: Arc<Clock>,
+    module_env: Arc<ModuleEnvSettings>,
 }
 
 impl<M, I> Watchdog<M, I>
@@ -37,7 +48,73 @@ where
     I::Error: Into<Error>,
 {
     pub fn new(runtime: M, id_mgr: I) -> Self {
-        Watchdog { runtime, id_mgr }
+        Watchdog {
+            runtime,
+            id_mgr,
+            restarts: ModuleRestarts::default(),
+            health: DaemonHealth::new(Utc::now()),
+            offline_start: false,
+            running: RunningModules::default(),
+            metrics: Arc::new(MetricsRegistry::new()),
+            stop_options: StopAllOptions::default(),
+            clock: Arc::new(SystemClock),
+            module_env: Arc::new(ModuleEnvSettings::default()),
+        }
+    }
+
+    /// Sets the restart bookkeeping, and with it the restart policy, the
+    /// watchdog uses for failing modules. Share it with the module runtime
+    /// to have held back modules reported as such.
+    pub fn with_restarts(mut self, restarts: ModuleRestarts) -> Self {
+        self.restarts = restarts;
+        self
+    }
+
+    /// Sets the health state the watchdog records its runs in.
+    pub fn with_health(mut self, health: DaemonHealth) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Lets the watchdog start the modules that were running before while
+    /// the identity of the edge runtime module cannot be reached, such as
+    /// when the device boots without a connection to IoT Hub. Creating the
+    /// edge runtime module is retried with the usual restart backoff in the
+    /// meantime.
+    pub fn with_offline_start(mut self, offline_start: bool) -> Self {
+        self.offline_start = offline_start;
+        self
+    }
+
+    /// Sets where the modules seen running are remembered for offline start.
+    pub fn with_running_modules(mut self, running: RunningModules) -> Self {
+        self.running = running;
+        self
+    }
+
+    /// Sets where the module restarts the watchdog makes are counted.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Sets the order in which the modules are stopped on shutdown.
+    pub fn with_stop_options(mut self, stop_options: StopAllOptions) -> Self {
+        self.stop_options = stop_options;
+        self
+    }
+
+    /// Sets the clock the watchdog schedules its checks and restarts by.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets what the variables injected into the environment of the edge
+    /// runtime module are made of when the watchdog creates it.
+    pub fn with_module_env(mut self, module_env: ModuleEnvSettings) -> Self {
+        self.module_env = Arc::new(module_env);
+        self
     }
 
     // Start the edge runtime module (EdgeAgent). This also updates the identity of the module (module_id)
@@ -54,11 +131,23 @@ where
     {
         let runtime = self.runtime.clone();
         let runtime_copy = self.runtime.clone();
-        let name = spec.name().to_string();
+        let stop_options = self.stop_options;
         let id_mgr = self.id_mgr.clone();
         let module_id = module_id.to_string();
 
-        let watchdog = start_watchdog(runtime, id_mgr, spec, module_id);
+        let watchdog = start_watchdog(
+            runtime,
+            id_mgr,
+            spec,
+            module_id,
+            self.restarts,
+            self.health,
+            self.offline_start,
+            self.running,
+            self.metrics,
+            self.clock,
+            self.module_env,
+        );
 
         // Swallow any errors from shutdown_signal
         let shutdown_signal = shutdown_signal.then(|_| Ok(()));
@@ -69,32 +158,38 @@ where
         shutdown_signal
             .select(watchdog)
             .then(move |result| match result {
-                Ok(((), _)) => Either::A(stop_runtime(&runtime_copy, &name)),
+                Ok(((), _)) => Either::A(stop_runtime(&runtime_copy, &stop_options)),
                 Err((e, _)) => Either::B(future::err(e)),
             })
     }
 }
 
-// Stop EdgeAgent
-fn stop_runtime<M>(runtime: &M, name: &str) -> impl Future<Item = (), Error = Error>
+// Stop the modules, leaving EdgeAgent for last
+fn stop_runtime<M>(runtime: &M, options: &StopAllOptions) -> impl Future<Item = (), Error = Error>
 where
     M: 'static + ModuleRuntime + Clone,
     <M::Module as Module>::Config: Clone,
     M::Error: Into<Error>,
     <M::Module as Module>::Error: Into<Error>,
 {
-    info!("Stopping edge runtime module {}", name);
-    runtime
-        .stop(name, Some(EDGE_RUNTIME_STOP_TIME))
-        .map_err(|e| e.into())
+    info!("Stopping modules");
+    runtime.stop_all(options).map_err(|e| e.into())
 }
 
-// Start watchdog on a timer for 1 minute
+// Start watchdog on a timer for 1 minute, or sooner when a restart is due
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
 pub fn start_watchdog<M, I>(
     runtime: M,
     id_mgr: I,
     spec: ModuleSpec<<M::Module as Module>::Config>,
     module_id: String,
+    restarts: ModuleRestarts,
+    health: DaemonHealth,
+    offline_start: bool,
+    running: RunningModules,
+    metrics: Arc<Metrics>,
+    clock: Arc<Clock>,
+    module_env: Arc<ModuleEnvSettings>,
 ) -> impl Future<Item = (), Error = Error>
 where
     M: 'static + ModuleRuntime + Clone,
@@ -108,30 +203,55 @@ where
         "Starting watchdog with {} second frequency...",
         WATCHDOG_FREQUENCY_SECS
     );
-    Interval::new(Instant::now(), Duration::from_secs(WATCHDOG_FREQUENCY_SECS))
-        .map_err(Error::from)
-        .for_each(move |_| {
-            info!("Checking edge runtime status");
-            check_runtime(
-                runtime.clone(),
-                id_mgr.clone(),
-                spec.clone(),
-                module_id.clone(),
-            ).or_else(|e| {
-                warn!("Error in watchdog when checking for edge runtime status:");
-                log_failure(Level::Warn, &e);
-                future::ok(())
-            })
-        })
+    let frequency = Duration::from_secs(WATCHDOG_FREQUENCY_SECS);
+    future::loop_fn((), move |()| {
+        info!("Checking edge runtime status");
+        let health = health.clone();
+        let clock = clock.clone();
+        check_runtime(
+            runtime.clone(),
+            id_mgr.clone(),
+            spec.clone(),
+            module_id.clone(),
+            restarts.clone(),
+            offline_start,
+            running.clone(),
+            metrics.clone(),
+            clock.clone(),
+            module_env.clone(),
+        ).or_else(|e| {
+            warn!("Error in watchdog when checking for edge runtime status:");
+            log_failure(Level::Warn, &e);
+            future::ok(None)
+        }).and_then(move |next_check| {
+            health.record_watchdog_run(clock.now());
+            let wait = next_check.map_or(frequency, |next_check| cmp::min(next_check, frequency));
+            clock.delay(clock.instant() + wait).map_err(Error::from)
+        }).map(|()| Loop::<(), ()>::Continue(()))
+    })
 }
 
-// Check if the edge runtime module is running, and if not, start it.
+// Check if the edge runtime module is running, and if not, start it unless
+// its restart is being held back. Returns how soon the module needs to be
+// checked again, if sooner than usual. While it runs, the modules that are
+// running are recorded. With offline start, failing to get the identity of
+// the module, whether to create it or after starting it, starts the modules
+// recorded as running instead. A running module that has been unhealthy for
+// as long as the restart policy allows is stopped, within its own stop
+// timeout, and started again.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
 fn check_runtime<M, I>(
     runtime: M,
     id_mgr: I,
     spec: ModuleSpec<<M::Module as Module>::Config>,
     module_id: String,
-) -> impl Future<Item = (), Error = Error>
+    restarts: ModuleRestarts,
+    offline_start: bool,
+    running: RunningModules,
+    metrics: Arc<Metrics>,
+    clock: Arc<Clock>,
+    module_env: Arc<ModuleEnvSettings>,
+) -> impl Future<Item = Option<Duration>, Error = Error>
 where
     M: 'static + ModuleRuntime + Clone,
     <M::Module as Module>::Config: Clone,
@@ -142,24 +262,101 @@ where
 {
     let module = spec.name().to_string();
     get_edge_runtime_mod(&runtime, module.clone())
-        .and_then(|m| m.map(|m| m.runtime_state().map_err(|e| e.into())))
-        .and_then(move |state| match state {
-            Some(state) => {
-                let res = if *state.status() == ModuleStatus::Running {
+        .and_then(|m| {
+            m.map(|m| {
+                let stop_timeout = m.stop_timeout();
+                m.runtime_state()
+                    .map(move |state| (state, stop_timeout))
+                    .map_err(|e| e.into())
+            })
+        }).and_then(move |state| {
+            let (state, stop_timeout) = match state {
+                Some((state, stop_timeout)) => (Some(state), stop_timeout),
+                None => (None, None),
+            };
+            if let Some(ref state) = state {
+                if *state.status() == ModuleStatus::Running {
+                    let now = clock.instant();
+                    restarts.running(&module, now);
+                    if restarts.unhealthy(&module, state.health(), now) {
+                        warn!("Edge runtime is unhealthy, restarting it");
+                        metrics.increment(&WATCHDOG_UNHEALTHY_RESTARTS, &[("module", &module)]);
+                        let restart = runtime
+                            .stop(&module, stop_timeout)
+                            .and_then(move |_| runtime.start(&module))
+                            .map_err(|e| e.into())
+                            .map(|_| None);
+                        return Either::B(Either::B(Either::B(restart)));
+                    }
                     info!("Edge runtime is running.");
-                    future::Either::A(future::ok(()))
-                } else {
+                    let record = record_running_modules(&runtime, running).map(|_| None);
+                    return Either::B(Either::B(Either::A(record)));
+                }
+            }
+
+            let backoff = match restarts.failed(&module, clock.instant()) {
+                Restart::Now(backoff) => {
+                    metrics.increment(&WATCHDOG_RESTARTS, &[("module", &module)]);
+                    backoff
+                }
+                Restart::After(wait) => {
+                    info!(
+                        "Edge runtime is not running, restarting it in {} seconds",
+                        wait.as_secs()
+                    );
+                    return Either::A(future::ok(Some(wait)));
+                }
+                Restart::GiveUp => {
+                    warn!("Edge runtime is not running and has failed too often to restart it");
+                    return Either::A(future::ok(None));
+                }
+            };
+
+            let start = match state {
+                Some(state) => {
                     info!(
                         "Edge runtime status is {}, starting module now...",
                         *state.status(),
                     );
-                    future::Either::B(runtime.start(&module).map_err(|e| e.into()))
-                };
-                Either::A(res)
-            }
-
-            None => Either::B(create_and_start(runtime, &id_mgr, spec, &module_id)),
-        }).map(|_| ())
+                    let start = runtime.start(&module).map_err(|e| e.into());
+                    if offline_start {
+                        // An existing module is started without updating
+                        // its identity, so getting the identity is what
+                        // tells whether IoT Hub can be reached.
+                        let check = id_mgr
+                            .get(IdentitySpec::new(&module_id))
+                            .map(|_| ())
+                            .map_err(|e| e.into())
+                            .or_else(move |err: Error| {
+                                warn!("Could not get the edge runtime identity, starting cached modules:");
+                                log_failure(Level::Warn, &err);
+                                start_cached_modules(
+                                    runtime, module, running, restarts, metrics, clock,
+                                )
+                            });
+                        let start = start.then(move |result| check.then(move |_| result));
+                        Either::A(Either::B(start))
+                    } else {
+                        Either::A(Either::A(start))
+                    }
+                }
+                None => Either::B(
+                    create_and_start(runtime.clone(), &id_mgr, spec, &module_id, &module_env)
+                        .or_else(move |err| {
+                            if offline_start {
+                                warn!("Could not create the edge runtime module, starting cached modules:");
+                                log_failure(Level::Warn, &err);
+                                Either::A(start_cached_modules(
+                                    runtime, module, running, restarts, metrics, clock,
+                                ))
+                            } else {
+                                Either::B(future::err(err))
+                            }
+                        }),
+                ),
+            };
+            Either::B(Either::A(start.map(move |_| Some(backoff))))
+        })
 }
 
 // Gets the edge runtime module, if it exists.
@@ -182,6 +379,113 @@ where
         }).map_err(|e| e.into())
 }
 
+// Remembers which of the modules the runtime has are running. Failing to
+// tell is logged, and leaves what was remembered before.
+fn record_running_modules<M>(
+    runtime: &M,
+    running: RunningModules,
+) -> impl Future<Item = (), Error = Error>
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+    <M::Module as Module>::Error: Into<Error>,
+{
+    runtime
+        .list()
+        .map_err(|e| e.into())
+        .and_then(|modules| {
+            let states: Vec<_> = modules
+                .into_iter()
+                .map(|module| {
+                    let name = module.name().to_string();
+                    module
+                        .runtime_state()
+                        .map_err(|e| e.into())
+                        .map(move |state| {
+                            if *state.status() == ModuleStatus::Running {
+                                Some(name)
+                            } else {
+                                None
+                            }
+                        })
+                }).collect();
+            future::join_all(states)
+        }).then(move |names: Result<Vec<Option<String>>, Error>| {
+            match names {
+                Ok(names) => running.record(names.into_iter().filter_map(|name| name).collect()),
+                Err(err) => {
+                    warn!("Could not tell which modules are running:");
+                    log_failure(Level::Warn, &err);
+                }
+            }
+            Ok(())
+        })
+}
+
+// Starts the modules the runtime already has that were recorded as running
+// but are not, other than the edge runtime module `edge_runtime`, which is
+// started on its own. Modules that keep failing are held back the same way
+// as the edge runtime module. A module that cannot be started does not keep
+// the others from starting.
+fn start_cached_modules<M>(
+    runtime: M,
+    edge_runtime: String,
+    running: RunningModules,
+    restarts: ModuleRestarts,
+    metrics: Arc<Metrics>,
+    clock: Arc<Clock>,
+) -> impl Future<Item = (), Error = Error>
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+    <M::Module as Module>::Error: Into<Error>,
+{
+    runtime
+        .list()
+        .map_err(|e| e.into())
+        .and_then(move |modules| {
+            let starts: Vec<_> = modules
+                .into_iter()
+                .filter(|module| module.name() != edge_runtime)
+                .map(|module| {
+                    let runtime = runtime.clone();
+                    let running = running.clone();
+                    let restarts = restarts.clone();
+                    let metrics = metrics.clone();
+                    let clock = clock.clone();
+                    let name = module.name().to_string();
+                    let failed_name = name.clone();
+                    module
+                        .runtime_state()
+                        .map_err(|e| e.into())
+                        .and_then(move |state| {
+                            let now = clock.instant();
+                            if *state.status() == ModuleStatus::Running {
+                                restarts.running(&name, now);
+                                return Either::A(future::ok(()));
+                            }
+                            if !running.contains(&name) {
+                                info!("Not starting module {}, it was not running before", name);
+                                return Either::A(future::ok(()));
+                            }
+                            match restarts.failed(&name, now) {
+                                Restart::Now(_) => {
+                                    info!("Starting module {} while offline", name);
+                                    metrics.increment(&WATCHDOG_RESTARTS, &[("module", &name)]);
+                                    Either::B(runtime.start(&name).map_err(|e| e.into()))
+                                }
+                                Restart::After(_) | Restart::GiveUp => Either::A(future::ok(())),
+                            }
+                        }).or_else(move |err| {
+                            warn!("Could not start module {}:", failed_name);
+                            log_failure(Level::Warn, &err);
+                            Ok(())
+                        })
+                }).collect();
+            future::join_all(starts).map(|_| ())
+        })
+}
+
 // Gets and updates the identity of the module.
 fn update_identity<I>(
     id_mgr: &mut I,
@@ -218,6 +522,7 @@ fn create_and_start<M, I>(
     id_mgr: &I,
     spec: ModuleSpec<<M::Module as Module>::Config>,
     module_id: &str,
+    module_env: &Arc<ModuleEnvSettings>,
 ) -> impl Future<Item = (), Error = Error>
 where
     M: 'static + ModuleRuntime + Clone,
@@ -228,21 +533,19 @@ where
     I::Error: Into<Error>,
 {
     let module_name = spec.name().to_string();
+    let module_env = module_env.clone();
     info!("Creating and starting edge runtime module {}", module_name);
     let runtime_copy = runtime.clone();
 
     let mut id_mgr = id_mgr.clone();
-    update_identity(&mut id_mgr, module_id).and_then(|id| {
-        // add the generation ID for edge agent as an environment variable
-        let mut env = spec.env().clone();
-        env.insert(
-            MODULE_GENERATIONID.to_string(),
-            id.generation_id().to_string(),
-        );
+    update_identity(&mut id_mgr, module_id).and_then(move |id| {
+        // the generation ID is only known once the identity is, so this is
+        // where the environment of the edge runtime module is built
+        let env = ModuleEnv::for_module(&spec, &module_env, &id);
         let spec = spec.with_env(env);
         runtime
             .registry()
-            .pull(spec.clone().config())
+            .pull_module(&spec)
             .and_then(move |_| runtime.create(spec))
             .and_then(move |_| runtime_copy.start(&module_name))
             .map_err(|e| e.into())
@@ -253,12 +556,21 @@ where
 mod tests {
     use super::*;
     use std::cell::RefCell;
+    use std::collections::HashMap;
     use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
 
     use futures::future::{self, FutureResult};
+    use futures::stream::{self, Empty};
 
     use error::{Error as CoreError, ErrorKind as CoreErrorKind};
     use identity::{AuthType, Identity, IdentityManager, IdentitySpec};
+    use module::{
+        IntegrityReport, LogOptions, ModuleHealth, ModuleInspection, ModuleRuntimeState, SystemInfo,
+    };
+    use pid::Pid;
+    use restart::RestartPolicy;
+    use shutdown::stop_in_order;
 
     #[derive(Clone, Copy, Debug, Fail)]
     pub enum Error {
@@ -366,6 +678,7 @@ mod tests {
         type ListFuture = FutureResult<Vec<Self::Identity>, Self::Error>;
         type GetFuture = FutureResult<Option<Self::Identity>, Self::Error>;
         type DeleteFuture = FutureResult<(), Self::Error>;
+        type RotateFuture = FutureResult<Self::Identity, Self::Error>;
 
         fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture {
             self.state.borrow_mut().gen_id_sentinel += 1;
@@ -441,6 +754,10 @@ mod tests {
                 .map(|index| self.state.borrow_mut().identities.remove(index))
                 .map_or_else(|| future::err(Error::ModuleNotFound), |_| future::ok(()))
         }
+
+        fn rotate_keys(&mut self, _id: IdentitySpec) -> Self::RotateFuture {
+            unimplemented!()
+        }
     }
 
     #[test]
@@ -492,4 +809,566 @@ mod tests {
                 .auth_type
         );
     }
+
+    #[derive(Clone)]
+    pub struct TestModule {
+        name: String,
+        status: ModuleStatus,
+        health: Option<ModuleHealth>,
+        stop_timeout: Option<Duration>,
+    }
+
+    impl Module for TestModule {
+        type Config = ();
+        type Error = Error;
+        type RuntimeStateFuture = FutureResult<ModuleRuntimeState, Self::Error>;
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn type_(&self) -> &str {
+            "test"
+        }
+
+        fn config(&self) -> &Self::Config {
+            &()
+        }
+
+        fn runtime_state(&self) -> Self::RuntimeStateFuture {
+            future::ok(
+                ModuleRuntimeState::default()
+                    .with_status(self.status)
+                    .with_health(self.health),
+            )
+        }
+
+        fn stop_timeout(&self) -> Option<Duration> {
+            self.stop_timeout
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct TestRegistry;
+
+    impl ModuleRegistry for TestRegistry {
+        type Error = Error;
+        type PullFuture = FutureResult<(), Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type Config = ();
+
+        fn pull(&self, _config: &Self::Config) -> Self::PullFuture {
+            future::ok(())
+        }
+
+        fn remove(&self, _name: &str) -> Self::RemoveFuture {
+            future::ok(())
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct TestRuntime {
+        modules: Arc<Mutex<Vec<TestModule>>>,
+        started: Arc<Mutex<Vec<String>>>,
+        stopped: Arc<Mutex<Vec<String>>>,
+        stop_timeouts: Arc<Mutex<Vec<Option<Duration>>>>,
+        registry: TestRegistry,
+    }
+
+    impl TestRuntime {
+        pub fn new(modules: &[(&str, ModuleStatus)]) -> Self {
+            let modules = modules
+                .iter()
+                .map(|&(name, status)| TestModule {
+                    name: name.to_string(),
+                    status,
+                    health: None,
+                    stop_timeout: None,
+                }).collect();
+            TestRuntime {
+                modules: Arc::new(Mutex::new(modules)),
+                started: Arc::new(Mutex::new(vec![])),
+                stopped: Arc::new(Mutex::new(vec![])),
+                stop_timeouts: Arc::new(Mutex::new(vec![])),
+                registry: TestRegistry,
+            }
+        }
+
+        fn with_stop_timeout(self, name: &str, stop_timeout: Option<Duration>) -> Self {
+            for module in self.modules.lock().unwrap().iter_mut() {
+                if module.name == name {
+                    module.stop_timeout = stop_timeout;
+                }
+            }
+            self
+        }
+
+        /// Has the module report the given health from now on.
+        fn set_health(&self, name: &str, health: Option<ModuleHealth>) {
+            for module in self.modules.lock().unwrap().iter_mut() {
+                if module.name == name {
+                    module.health = health;
+                }
+            }
+        }
+
+        fn started(&self) -> Vec<String> {
+            let mut started = self.started.lock().unwrap().clone();
+            started.sort();
+            started
+        }
+
+        fn stopped(&self) -> Vec<String> {
+            self.stopped.lock().unwrap().clone()
+        }
+    }
+
+    impl ModuleRuntime for TestRuntime {
+        type Error = Error;
+        type Config = ();
+        type Module = TestModule;
+        type ModuleRegistry = TestRegistry;
+        type Chunk = Vec<u8>;
+        type Logs = Empty<Self::Chunk, Self::Error>;
+
+        type CreateFuture = FutureResult<(), Self::Error>;
+        type InitFuture = FutureResult<(), Self::Error>;
+        type ListFuture = FutureResult<Vec<Self::Module>, Self::Error>;
+        type ListWithDetailsStream = Empty<(Self::Module, ModuleRuntimeState), Self::Error>;
+        type LogsFuture = FutureResult<Self::Logs, Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type RestartFuture = FutureResult<(), Self::Error>;
+        type StartFuture = FutureResult<(), Self::Error>;
+        type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
+        type SystemInfoFuture = FutureResult<SystemInfo, Self::Error>;
+        type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
+
+        fn init(&self) -> Self::InitFuture {
+            future::ok(())
+        }
+
+        fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
+            self.modules.lock().unwrap().push(TestModule {
+                name: module.name().to_string(),
+                status: ModuleStatus::Stopped,
+                health: None,
+                stop_timeout: None,
+            });
+            future::ok(())
+        }
+
+        fn start(&self, id: &str) -> Self::StartFuture {
+            let mut modules = self.modules.lock().unwrap();
+            match modules.iter_mut().find(|m| m.name == id) {
+                Some(module) => {
+                    module.status = ModuleStatus::Running;
+                    self.started.lock().unwrap().push(id.to_string());
+                    future::ok(())
+                }
+                None => future::err(Error::ModuleNotFound),
+            }
+        }
+
+        fn stop(&self, id: &str, wait_before_kill: Option<Duration>) -> Self::StopFuture {
+            self.stopped.lock().unwrap().push(id.to_string());
+            self.stop_timeouts.lock().unwrap().push(wait_before_kill);
+            future::ok(())
+        }
+
+        fn restart(&self, _id: &str) -> Self::RestartFuture {
+            future::ok(())
+        }
+
+        fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
+            future::ok(())
+        }
+
+        fn remove(&self, _id: &str) -> Self::RemoveFuture {
+            future::ok(())
+        }
+
+        fn system_info(&self) -> Self::SystemInfoFuture {
+            future::ok(SystemInfo::new("linux".to_string(), "x86_64".to_string()))
+        }
+
+        fn list(&self) -> Self::ListFuture {
+            future::ok(self.modules.lock().unwrap().clone())
+        }
+
+        fn list_with_details(&self) -> Self::ListWithDetailsStream {
+            stream::empty()
+        }
+
+        fn logs(&self, _id: &str, _options: &LogOptions) -> Self::LogsFuture {
+            future::ok(stream::empty())
+        }
+
+        fn registry(&self) -> &Self::ModuleRegistry {
+            &self.registry
+        }
+
+        fn remove_all(&self) -> Self::RemoveAllFuture {
+            future::ok(())
+        }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            future::ok(IntegrityReport::default())
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            future::ok(ModuleInspection::default())
+        }
+
+        fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture {
+            Box::new(stop_in_order(self, options))
+        }
+
+        fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            future::ok(())
+        }
+
+        fn top(&self, _id: &str) -> Self::TopFuture {
+            future::ok(vec![])
+        }
+    }
+
+    fn edge_agent_spec() -> ModuleSpec<()> {
+        ModuleSpec::new("edgeAgent", "test", (), HashMap::new()).unwrap()
+    }
+
+    fn offline_hub() -> TestIdentityManager {
+        TestIdentityManager::new(vec![TestIdentity::new(
+            "$edgeAgent",
+            "iotedge",
+            "1",
+            AuthType::None,
+        )]).with_fail_get(true)
+    }
+
+    fn check(
+        runtime: &TestRuntime,
+        id_mgr: &TestIdentityManager,
+        restarts: &ModuleRestarts,
+        offline_start: bool,
+    ) -> Result<Option<Duration>, CoreError> {
+        check_with_metrics(
+            runtime,
+            id_mgr,
+            restarts,
+            offline_start,
+            &MetricsRegistry::new(),
+        )
+    }
+
+    fn check_with_metrics(
+        runtime: &TestRuntime,
+        id_mgr: &TestIdentityManager,
+        restarts: &ModuleRestarts,
+        offline_start: bool,
+        metrics: &MetricsRegistry,
+    ) -> Result<Option<Duration>, CoreError> {
+        check_with(
+            runtime,
+            id_mgr,
+            restarts,
+            offline_start,
+            &RunningModules::new(),
+            metrics,
+        )
+    }
+
+    fn check_with(
+        runtime: &TestRuntime,
+        id_mgr: &TestIdentityManager,
+        restarts: &ModuleRestarts,
+        offline_start: bool,
+        running: &RunningModules,
+        metrics: &MetricsRegistry,
+    ) -> Result<Option<Duration>, CoreError> {
+        check_runtime(
+            runtime.clone(),
+            id_mgr.clone(),
+            edge_agent_spec(),
+            "$edgeAgent".to_string(),
+            restarts.clone(),
+            offline_start,
+            running.clone(),
+            Arc::new(metrics.clone()),
+            Arc::new(SystemClock),
+            Arc::new(ModuleEnvSettings::default()),
+        ).wait()
+    }
+
+    fn running(names: &[&str]) -> RunningModules {
+        let running = RunningModules::new();
+        running.record(names.iter().map(|name| name.to_string()).collect());
+        running
+    }
+
+    fn check_offline(
+        runtime: &TestRuntime,
+        id_mgr: &TestIdentityManager,
+        restarts: &ModuleRestarts,
+        running: &RunningModules,
+    ) -> Result<Option<Duration>, CoreError> {
+        check_with(
+            runtime,
+            id_mgr,
+            restarts,
+            true,
+            running,
+            &MetricsRegistry::new(),
+        )
+    }
+
+    #[test]
+    fn offline_start_starts_cached_modules() {
+        let runtime = TestRuntime::new(&[
+            ("tempSensor", ModuleStatus::Stopped),
+            ("edgeHub", ModuleStatus::Failed),
+            ("local", ModuleStatus::Running),
+            ("debug", ModuleStatus::Stopped),
+        ]);
+        let restarts = ModuleRestarts::default();
+        let running = running(&["edgeHub", "tempSensor", "local"]);
+
+        let next_check = check_offline(&runtime, &offline_hub(), &restarts, &running).unwrap();
+
+        // The module that was stopped before is left alone.
+        assert_eq!(Some(Duration::from_secs(10)), next_check);
+        assert_eq!(vec!["edgeHub", "tempSensor"], runtime.started());
+    }
+
+    #[test]
+    fn offline_start_starts_cached_modules_with_the_edge_runtime() {
+        let runtime = TestRuntime::new(&[
+            ("edgeAgent", ModuleStatus::Stopped),
+            ("tempSensor", ModuleStatus::Stopped),
+        ]);
+        let running = running(&["edgeAgent", "tempSensor"]);
+
+        check_offline(&runtime, &offline_hub(), &ModuleRestarts::default(), &running).unwrap();
+        assert_eq!(vec!["edgeAgent", "tempSensor"], runtime.started());
+    }
+
+    #[test]
+    fn cached_modules_are_left_to_the_edge_runtime_while_online() {
+        let runtime = TestRuntime::new(&[
+            ("edgeAgent", ModuleStatus::Stopped),
+            ("tempSensor", ModuleStatus::Stopped),
+        ]);
+        let id_mgr = offline_hub().with_fail_get(false);
+        let running = running(&["edgeAgent", "tempSensor"]);
+
+        check_offline(&runtime, &id_mgr, &ModuleRestarts::default(), &running).unwrap();
+        assert_eq!(vec!["edgeAgent"], runtime.started());
+    }
+
+    #[test]
+    fn running_modules_are_recorded_while_the_edge_runtime_runs() {
+        let runtime = TestRuntime::new(&[
+            ("edgeAgent", ModuleStatus::Running),
+            ("tempSensor", ModuleStatus::Running),
+            ("debug", ModuleStatus::Stopped),
+        ]);
+        let running = RunningModules::new();
+
+        check_offline(&runtime, &offline_hub(), &ModuleRestarts::default(), &running).unwrap();
+        assert!(running.contains("edgeAgent"));
+        assert!(running.contains("tempSensor"));
+        assert!(!running.contains("debug"));
+        assert!(runtime.started().is_empty());
+    }
+
+    #[test]
+    fn without_offline_start_nothing_is_started() {
+        let runtime = TestRuntime::new(&[("tempSensor", ModuleStatus::Stopped)]);
+        let restarts = ModuleRestarts::default();
+
+        assert!(check(&runtime, &offline_hub(), &restarts, false).is_err());
+        assert!(runtime.started().is_empty());
+    }
+
+    #[test]
+    fn offline_start_keeps_retrying_the_edge_runtime() {
+        let runtime = TestRuntime::new(&[("tempSensor", ModuleStatus::Stopped)]);
+        let id_mgr = offline_hub();
+        let restarts = ModuleRestarts::new(
+            RestartPolicy::new()
+                .with_base_delay(Duration::from_secs(0))
+                .with_max_delay(Duration::from_secs(0)),
+        );
+
+        let running = running(&["tempSensor"]);
+
+        check_offline(&runtime, &id_mgr, &restarts, &running).unwrap();
+        check_offline(&runtime, &id_mgr, &restarts, &running).unwrap();
+        assert_eq!(vec!["tempSensor"], runtime.started());
+        assert_eq!(false, id_mgr.state.borrow().update_called);
+
+        // The hub is reachable again.
+        id_mgr.state.borrow_mut().fail_get = false;
+        check_offline(&runtime, &id_mgr, &restarts, &running).unwrap();
+        assert_eq!(true, id_mgr.state.borrow().update_called);
+        assert_eq!(vec!["edgeAgent", "tempSensor"], runtime.started());
+    }
+
+    #[test]
+    fn restarts_are_counted() {
+        let runtime = TestRuntime::new(&[
+            ("tempSensor", ModuleStatus::Stopped),
+            ("local", ModuleStatus::Running),
+        ]);
+        let restarts = ModuleRestarts::new(
+            RestartPolicy::new()
+                .with_base_delay(Duration::from_secs(0))
+                .with_max_delay(Duration::from_secs(0)),
+        );
+        let metrics = MetricsRegistry::new();
+        let running = running(&["tempSensor", "local"]);
+
+        check_with(&runtime, &offline_hub(), &restarts, true, &running, &metrics).unwrap();
+        check_with(&runtime, &offline_hub(), &restarts, true, &running, &metrics).unwrap();
+
+        assert_eq!(
+            2,
+            metrics.counter(&WATCHDOG_RESTARTS, &[("module", "edgeAgent")])
+        );
+        // Once started, tempSensor keeps running.
+        assert_eq!(
+            1,
+            metrics.counter(&WATCHDOG_RESTARTS, &[("module", "tempSensor")])
+        );
+        assert_eq!(
+            0,
+            metrics.counter(&WATCHDOG_RESTARTS, &[("module", "local")])
+        );
+    }
+
+    #[test]
+    fn shutdown_stops_edge_hub_and_edge_agent_last() {
+        let runtime = TestRuntime::new(&[
+            ("edgeAgent", ModuleStatus::Running),
+            ("edgeHub", ModuleStatus::Running),
+            ("tempSensor", ModuleStatus::Running),
+        ]);
+
+        stop_runtime(&runtime, &StopAllOptions::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(vec!["tempSensor", "edgeHub", "edgeAgent"], runtime.stopped());
+    }
+
+    fn unhealthy_restarts(checks: u32, grace_period: Duration) -> ModuleRestarts {
+        ModuleRestarts::new(
+            RestartPolicy::new()
+                .with_unhealthy_checks(Some(checks))
+                .with_unhealthy_grace_period(grace_period),
+        )
+    }
+
+    #[test]
+    fn unhealthy_streak_restarts_edge_runtime() {
+        let runtime = TestRuntime::new(&[("edgeAgent", ModuleStatus::Running)])
+            .with_stop_timeout("edgeAgent", Some(Duration::from_secs(45)));
+        runtime.set_health("edgeAgent", Some(ModuleHealth::Unhealthy));
+        let id_mgr = offline_hub();
+        let restarts = unhealthy_restarts(3, Duration::from_secs(0));
+        let metrics = MetricsRegistry::new();
+
+        check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        assert!(runtime.stopped().is_empty());
+
+        check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        assert_eq!(vec!["edgeAgent"], runtime.stopped());
+        assert_eq!(vec!["edgeAgent"], runtime.started());
+        assert_eq!(
+            vec![Some(Duration::from_secs(45))],
+            *runtime.stop_timeouts.lock().unwrap()
+        );
+        assert_eq!(
+            1,
+            metrics.counter(&WATCHDOG_UNHEALTHY_RESTARTS, &[("module", "edgeAgent")])
+        );
+        assert_eq!(
+            0,
+            metrics.counter(&WATCHDOG_RESTARTS, &[("module", "edgeAgent")])
+        );
+    }
+
+    #[test]
+    fn recovered_edge_runtime_is_not_restarted() {
+        let runtime = TestRuntime::new(&[("edgeAgent", ModuleStatus::Running)]);
+        let id_mgr = offline_hub();
+        let restarts = unhealthy_restarts(2, Duration::from_secs(0));
+
+        for health in &[
+            ModuleHealth::Unhealthy,
+            ModuleHealth::Healthy,
+            ModuleHealth::Unhealthy,
+            ModuleHealth::Starting,
+            ModuleHealth::Unhealthy,
+        ] {
+            runtime.set_health("edgeAgent", Some(*health));
+            check(&runtime, &id_mgr, &restarts, false).unwrap();
+        }
+
+        assert!(runtime.stopped().is_empty());
+        assert!(runtime.started().is_empty());
+    }
+
+    #[test]
+    fn unhealthy_edge_runtime_is_left_alone_by_default() {
+        let runtime = TestRuntime::new(&[("edgeAgent", ModuleStatus::Running)]);
+        runtime.set_health("edgeAgent", Some(ModuleHealth::Unhealthy));
+        let restarts = ModuleRestarts::default();
+
+        for _ in 0..5 {
+            check(&runtime, &offline_hub(), &restarts, false).unwrap();
+        }
+
+        assert!(runtime.stopped().is_empty());
+    }
+
+    #[test]
+    fn flapping_edge_runtime_gets_grace_period() {
+        let runtime = TestRuntime::new(&[("edgeAgent", ModuleStatus::Running)]);
+        runtime.set_health("edgeAgent", Some(ModuleHealth::Unhealthy));
+        let id_mgr = offline_hub();
+        let restarts = unhealthy_restarts(1, Duration::from_secs(3600));
+        let metrics = MetricsRegistry::new();
+
+        // Still unhealthy right after the restart, and then healthy and
+        // unhealthy in turns, all within the grace period.
+        for health in &[
+            ModuleHealth::Unhealthy,
+            ModuleHealth::Unhealthy,
+            ModuleHealth::Healthy,
+            ModuleHealth::Unhealthy,
+            ModuleHealth::Unhealthy,
+        ] {
+            runtime.set_health("edgeAgent", Some(*health));
+            check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        }
+
+        assert_eq!(vec!["edgeAgent"], runtime.stopped());
+        assert_eq!(
+            1,
+            metrics.counter(&WATCHDOG_UNHEALTHY_RESTARTS, &[("module", "edgeAgent")])
+        );
+
+        // Without a grace period, every unhealthy check restarts it.
+        let restarts = unhealthy_restarts(1, Duration::from_secs(0));
+        check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        check_with_metrics(&runtime, &id_mgr, &restarts, false, &metrics).unwrap();
+        assert_eq!(vec!["edgeAgent"; 3], runtime.stopped());
+    }
 }
@@ -1,11 +1,107 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::path::Path;
+
 use certificate_properties::CertificateType;
 
+const DEFAULT_MAX_PAYLOAD_SIZE: usize = 256 * 1024;
+const DEFAULT_MAX_ASSOCIATED_DATA_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_SIGN_PAYLOAD_SIZE: usize = 256 * 1024;
+const DEFAULT_MAX_SIGN_BATCH_SIZE: usize = 100;
+
 /// Trait to obtain configuration data needed by any implementation of the workload interface
 /// for module identity and certificate management.
 pub trait WorkloadConfig {
     fn iot_hub_name(&self) -> &str;
     fn device_id(&self) -> &str;
     fn get_cert_max_duration(&self, cert_type: CertificateType) -> i64;
+    fn allow_wildcard_sans(&self) -> bool;
+    fn additional_trusted_ca_dir(&self) -> Option<&Path>;
+    fn payload_limits(&self) -> PayloadLimits;
+    fn allow_host_processes(&self) -> bool;
+    fn device_identity_cert(&self) -> Option<&str>;
+    /// The modules that may have certificates issued for leaf devices.
+    fn leaf_cert_issuers(&self) -> &[String];
+    /// The PEM of the certificates signed deployment manifests are verified
+    /// with, if manifest signing is configured.
+    fn manifest_trust_bundle(&self) -> Option<&str>;
+}
+
+/// Upper bounds, in decoded bytes, for the data accepted by the workload encrypt, decrypt
+/// and sign operations, and for how many sign requests one batch may have.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PayloadLimits {
+    max_payload_size: usize,
+    max_associated_data_size: usize,
+    max_sign_payload_size: usize,
+    max_sign_batch_size: usize,
+}
+
+impl Default for PayloadLimits {
+    fn default() -> Self {
+        PayloadLimits {
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            max_associated_data_size: DEFAULT_MAX_ASSOCIATED_DATA_SIZE,
+            max_sign_payload_size: DEFAULT_MAX_SIGN_PAYLOAD_SIZE,
+            max_sign_batch_size: DEFAULT_MAX_SIGN_BATCH_SIZE,
+        }
+    }
+}
+
+impl PayloadLimits {
+    pub fn max_payload_size(&self) -> usize {
+        self.max_payload_size
+    }
+
+    pub fn with_max_payload_size(mut self, max_payload_size: usize) -> Self {
+        self.max_payload_size = max_payload_size;
+        self
+    }
+
+    pub fn max_associated_data_size(&self) -> usize {
+        self.max_associated_data_size
+    }
+
+    pub fn with_max_associated_data_size(mut self, max_associated_data_size: usize) -> Self {
+        self.max_associated_data_size = max_associated_data_size;
+        self
+    }
+
+    pub fn max_sign_payload_size(&self) -> usize {
+        self.max_sign_payload_size
+    }
+
+    pub fn with_max_sign_payload_size(mut self, max_sign_payload_size: usize) -> Self {
+        self.max_sign_payload_size = max_sign_payload_size;
+        self
+    }
+
+    pub fn max_sign_batch_size(&self) -> usize {
+        self.max_sign_batch_size
+    }
+
+    pub fn with_max_sign_batch_size(mut self, max_sign_batch_size: usize) -> Self {
+        self.max_sign_batch_size = max_sign_batch_size;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    #[test]
+    fn missing_limits_use_defaults() {
+        let limits: PayloadLimits = serde_json::from_str(r#"{"max_payload_size": 1024}"#).unwrap();
+        assert_eq!(1024, limits.max_payload_size());
+        assert_eq!(
+            DEFAULT_MAX_ASSOCIATED_DATA_SIZE,
+            limits.max_associated_data_size()
+        );
+        assert_eq!(DEFAULT_MAX_SIGN_PAYLOAD_SIZE, limits.max_sign_payload_size());
+        assert_eq!(DEFAULT_MAX_SIGN_BATCH_SIZE, limits.max_sign_batch_size());
+    }
 }
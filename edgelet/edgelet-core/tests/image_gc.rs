@@ -0,0 +1,193 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate chrono;
+extern crate edgelet_core;
+extern crate edgelet_test_utils;
+extern crate futures;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeZone, Utc};
+use edgelet_core::{
+    BusyFlag, ImageGarbageCollector, ImageGcPolicy, ImageInfo, ModuleRuntime, ModuleRuntimeState,
+};
+use edgelet_test_utils::identity::Error;
+use edgelet_test_utils::module::{TestConfig, TestModule, TestRuntime};
+use edgelet_test_utils::TestClock;
+use futures::executor::{self, Notify, Spawn};
+use futures::{Async, Future};
+
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+// Runs the collector as far as it gets without the clock moving.
+fn poll<F: Future>(collector: &mut Spawn<F>) {
+    match collector.poll_future_notify(&Arc::new(NoopNotify), 0) {
+        Ok(Async::NotReady) => (),
+        _ => panic!("image garbage collection stopped"),
+    }
+}
+
+// When the test clock starts.
+fn start() -> DateTime<Utc> {
+    Utc.ymd(2018, 1, 1).and_hms(0, 0, 0)
+}
+
+// A runtime whose only module runs "edge-agent", and that also has an
+// "unused" image, both created when the test clock starts.
+fn runtime() -> TestRuntime<Error> {
+    TestRuntime::new(Ok(TestModule::new(
+        "edgeAgent".to_string(),
+        TestConfig::new("edge-agent".to_string()),
+        Ok(ModuleRuntimeState::default()),
+    ))).with_images(vec![
+        ImageInfo::new("edge-agent".to_string(), 100).with_created_at(Some(start())),
+        ImageInfo::new("unused".to_string(), 10).with_created_at(Some(start())),
+    ])
+}
+
+// Collects at 01:00 at an offset of an hour from UTC, which is midnight UTC,
+// where the test clock starts.
+fn collector(runtime: TestRuntime<Error>, busy: BusyFlag, clock: &TestClock) -> impl Future {
+    let policy = ImageGcPolicy::new(true, NaiveTime::from_hms(1, 0, 0), Duration::from_secs(0));
+    collector_with_policy(runtime, busy, clock, policy)
+}
+
+fn collector_with_policy(
+    runtime: TestRuntime<Error>,
+    busy: BusyFlag,
+    clock: &TestClock,
+    policy: ImageGcPolicy,
+) -> impl Future {
+    ImageGarbageCollector::new(runtime, busy)
+        .with_policy(policy)
+        .with_utc_offset(FixedOffset::east(60 * 60))
+        .with_clock(Arc::new(clock.clone()))
+        .run()
+}
+
+#[test]
+fn images_are_collected_at_the_cleanup_time() {
+    let clock = TestClock::new(start());
+    let runtime = runtime();
+    let mut collector = executor::spawn(collector(runtime.clone(), BusyFlag::new(), &clock));
+
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+
+    clock.advance(Duration::from_secs(24 * 60 * 60 - 1));
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+
+    clock.advance(Duration::from_secs(1));
+    poll(&mut collector);
+    assert_eq!(vec!["unused".to_string()], runtime.registry().pruned());
+}
+
+#[test]
+fn images_are_collected_once_older_than_the_min_age() {
+    let clock = TestClock::new(start());
+    let runtime = runtime();
+    let collector = ImageGarbageCollector::new(runtime.clone(), BusyFlag::new())
+        .with_policy(ImageGcPolicy::new(
+            true,
+            NaiveTime::from_hms(2, 0, 0),
+            Duration::from_secs(2 * 24 * 60 * 60),
+        )).with_utc_offset(FixedOffset::east(0))
+        .with_clock(Arc::new(clock.clone()))
+        .run();
+    let mut collector = executor::spawn(collector);
+    poll(&mut collector);
+
+    // The image is removed by the first collection two days after it was
+    // created. The image of the running module is never removed.
+    for &(hours, pruned) in &[(2, false), (24, false), (24, true)] {
+        clock.advance(Duration::from_secs(hours * 60 * 60));
+        poll(&mut collector);
+        assert_eq!(pruned, !runtime.registry().pruned().is_empty());
+    }
+    assert_eq!(vec!["unused".to_string()], runtime.registry().pruned());
+}
+
+#[test]
+fn collection_waits_for_modules_to_be_created_and_removed() {
+    let clock = TestClock::new(start());
+    let runtime = runtime();
+    let busy = BusyFlag::new();
+    let mut collector = executor::spawn(collector(runtime.clone(), busy.clone(), &clock));
+    poll(&mut collector);
+
+    let deployment = busy.set();
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    poll(&mut collector);
+    clock.advance(Duration::from_secs(60));
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+
+    drop(deployment);
+    clock.advance(Duration::from_secs(59));
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+    clock.advance(Duration::from_secs(1));
+    poll(&mut collector);
+    assert_eq!(vec!["unused".to_string()], runtime.registry().pruned());
+}
+
+#[test]
+fn collection_is_skipped_while_the_runtime_is_down() {
+    let clock = TestClock::default();
+    let runtime = TestRuntime::new(Err(Error::General));
+    let mut collector = executor::spawn(collector(runtime, BusyFlag::new(), &clock));
+
+    // The collector keeps going, and tries again the next day.
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        poll(&mut collector);
+    }
+}
+
+#[test]
+fn images_of_unknown_age_are_kept() {
+    let clock = TestClock::new(start());
+    let runtime = TestRuntime::new(Ok(TestModule::new(
+        "edgeAgent".to_string(),
+        TestConfig::new("edge-agent".to_string()),
+        Ok(ModuleRuntimeState::default()),
+    ))).with_images(vec![ImageInfo::new("unused".to_string(), 10)]);
+    let mut collector = executor::spawn(collector(runtime.clone(), BusyFlag::new(), &clock));
+
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+}
+
+#[test]
+fn policy_changes_apply_while_the_collector_runs() {
+    let clock = TestClock::new(start());
+    let runtime = runtime();
+    let policy = ImageGcPolicy::new(false, NaiveTime::from_hms(1, 0, 0), Duration::from_secs(0));
+    let collector = collector_with_policy(runtime.clone(), BusyFlag::new(), &clock, policy.clone());
+    let mut collector = executor::spawn(collector);
+    poll(&mut collector);
+
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    poll(&mut collector);
+    assert!(runtime.registry().pruned().is_empty());
+
+    policy.set(true, NaiveTime::from_hms(1, 0, 0), Duration::from_secs(0));
+    clock.advance(Duration::from_secs(24 * 60 * 60));
+    poll(&mut collector);
+    assert_eq!(vec!["unused".to_string()], runtime.registry().pruned());
+}
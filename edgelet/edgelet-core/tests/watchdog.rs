@@ -0,0 +1,96 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_core;
+extern crate edgelet_test_utils;
+extern crate futures;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use edgelet_core::watchdog::Watchdog;
+use edgelet_core::{
+    metrics, Clock, DaemonHealth, MetricsRegistry, ModuleRestarts, ModuleRuntimeState, ModuleSpec,
+    ModuleStatus, RestartPolicy,
+};
+use edgelet_test_utils::identity::{Error, TestIdentityManager};
+use edgelet_test_utils::module::{TestConfig, TestModule, TestRuntime};
+use edgelet_test_utils::TestClock;
+use futures::executor::{self, Notify, Spawn};
+use futures::{future, Async, Future};
+
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+// Runs the watchdog as far as it gets without the clock moving.
+fn poll<F: Future>(watchdog: &mut Spawn<F>) {
+    match watchdog.poll_future_notify(&Arc::new(NoopNotify), 0) {
+        Ok(Async::NotReady) => (),
+        _ => panic!("watchdog stopped"),
+    }
+}
+
+fn crashing_edge_agent() -> TestRuntime<Error> {
+    let state = ModuleRuntimeState::default().with_status(ModuleStatus::Failed);
+    TestRuntime::new(Ok(TestModule::new(
+        "edgeAgent".to_string(),
+        TestConfig::new("microsoft/test-image".to_string()),
+        Ok(state),
+    )))
+}
+
+#[test]
+fn watchdog_backs_off_restarting_a_crashing_edge_runtime() {
+    let clock = TestClock::default();
+    let metrics = MetricsRegistry::new();
+    let health = DaemonHealth::new(clock.now());
+    let restarts = ModuleRestarts::new(
+        RestartPolicy::new()
+            .with_base_delay(Duration::from_secs(10))
+            .with_max_delay(Duration::from_secs(40)),
+    );
+    let spec = ModuleSpec::new(
+        "edgeAgent",
+        "test",
+        TestConfig::new("microsoft/test-image".to_string()),
+        HashMap::new(),
+    ).unwrap();
+
+    let watchdog = Watchdog::new(crashing_edge_agent(), TestIdentityManager::new(vec![]))
+        .with_restarts(restarts)
+        .with_health(health.clone())
+        .with_metrics(Arc::new(metrics.clone()))
+        .with_clock(Arc::new(clock.clone()))
+        .run_until(spec, "$edgeAgent", future::empty());
+    let mut watchdog = executor::spawn(watchdog);
+    let restarts = || metrics.counter(&metrics::WATCHDOG_RESTARTS, &[("module", "edgeAgent")]);
+
+    poll(&mut watchdog);
+    assert_eq!(1, restarts());
+    assert_eq!(
+        Some(clock.now()),
+        health.report(clock.now()).watchdog().last_checked()
+    );
+
+    // The wait between restarts doubles up to the policy's maximum.
+    for &(wait, count) in &[(10, 2), (20, 3), (40, 4), (40, 5)] {
+        clock.advance(Duration::from_secs(wait - 1));
+        poll(&mut watchdog);
+        assert_eq!(count - 1, restarts());
+
+        clock.advance(Duration::from_secs(1));
+        poll(&mut watchdog);
+        assert_eq!(count, restarts());
+    }
+}
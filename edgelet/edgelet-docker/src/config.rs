@@ -1,21 +1,64 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::fmt;
+
 use docker::models::{AuthConfig, ContainerCreateBody};
 use edgelet_utils::serde_clone;
+use regex::Regex;
 
 use error::Result;
+use image::ImageReference;
+
+lazy_static! {
+    /// The names docker accepts for volumes. The source of a bind that isn't
+    /// one is a host path, like `/data` or `C:\data`.
+    pub(crate) static ref VOLUME_NAME: Regex =
+        Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]+$").unwrap();
+}
+
+/// How to authenticate with the registry an image is pulled from.
+///
+/// Inline credentials are serialized as the object docker takes, while a
+/// reference is serialized as the bare name of the credential, like
+/// `"auth": "contoso"`.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RegistryAuth {
+    Inline(AuthConfig),
+    /// The name of a credential kept by the daemon, which is only resolved
+    /// when the image is pulled.
+    RegistryCredentialRef(String),
+}
+
+/// Leaves the password of inline credentials out, so that logging a config
+/// never leaks it.
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryAuth::Inline(ref auth) => f
+                .debug_struct("Inline")
+                .field("username", &auth.username())
+                .field("serveraddress", &auth.serveraddress())
+                .finish(),
+            RegistryAuth::RegistryCredentialRef(ref name) => f
+                .debug_tuple("RegistryCredentialRef")
+                .field(name)
+                .finish(),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DockerConfig {
-    image: String,
+    image: ImageReference,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "imageHash")]
     image_id: Option<String>,
     #[serde(default = "ContainerCreateBody::new")]
     create_options: ContainerCreateBody,
     #[serde(skip_serializing_if = "Option::is_none")]
-    auth: Option<AuthConfig>,
+    auth: Option<RegistryAuth>,
 }
 
 impl DockerConfig {
@@ -24,11 +67,12 @@ impl DockerConfig {
         create_options: ContainerCreateBody,
         auth: Option<AuthConfig>,
     ) -> Result<Self> {
+        let image = ensure_not_empty!(image.to_string());
         let config = DockerConfig {
-            image: ensure_not_empty!(image.to_string()),
+            image: ImageReference::parse(&image)?,
             image_id: None,
             create_options,
-            auth,
+            auth: auth.map(RegistryAuth::Inline),
         };
         Ok(config)
     }
@@ -37,11 +81,17 @@ impl DockerConfig {
         Ok(serde_clone(&self.create_options)?)
     }
 
+    /// The image as the config names it.
     pub fn image(&self) -> &str {
+        self.image.as_str()
+    }
+
+    /// The image split into its parts, with the tag defaulted.
+    pub fn image_reference(&self) -> &ImageReference {
         &self.image
     }
 
-    pub fn with_image(mut self, image: String) -> Self {
+    pub fn with_image(mut self, image: ImageReference) -> Self {
         self.image = image;
         self
     }
@@ -68,14 +118,64 @@ impl DockerConfig {
         self.create_options = create_options;
     }
 
-    pub fn auth(&self) -> Option<&AuthConfig> {
+    pub fn registry_auth(&self) -> Option<&RegistryAuth> {
         self.auth.as_ref()
     }
 
+    /// The inline credentials, if the config has any.
+    pub fn auth(&self) -> Option<&AuthConfig> {
+        match self.auth {
+            Some(RegistryAuth::Inline(ref auth)) => Some(auth),
+            _ => None,
+        }
+    }
+
     pub fn with_auth(mut self, auth: AuthConfig) -> Self {
-        self.auth = Some(auth);
+        self.auth = Some(RegistryAuth::Inline(auth));
         self
     }
+
+    /// The name of the credential the config refers to, if it refers to one.
+    pub fn credential_ref(&self) -> Option<&str> {
+        match self.auth {
+            Some(RegistryAuth::RegistryCredentialRef(ref name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn with_credential_ref(mut self, name: String) -> Self {
+        self.auth = Some(RegistryAuth::RegistryCredentialRef(name));
+        self
+    }
+
+    /// The named volumes the create options mount, either as binds of the
+    /// form `name:/path` or as mounts of type `volume`. Anonymous volumes and
+    /// host paths are left out.
+    pub fn volumes(&self) -> Vec<String> {
+        let host_config = match self.create_options.host_config() {
+            Some(host_config) => host_config,
+            None => return vec![],
+        };
+        let binds = host_config
+            .binds()
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|bind| bind.split(':').next());
+        let mounts = host_config
+            .mounts()
+            .unwrap_or(&[])
+            .iter()
+            .filter(|mount| mount._type() == Some("volume"))
+            .filter_map(|mount| mount.source());
+
+        let mut volumes: Vec<String> = vec![];
+        for name in binds.chain(mounts) {
+            if VOLUME_NAME.is_match(name) && !volumes.iter().any(|volume| volume == name) {
+                volumes.push(name.to_string());
+            }
+        }
+        volumes
+    }
 }
 
 #[cfg(test)]
@@ -86,6 +186,9 @@ mod tests {
     use docker::models::{ContainerCreateBody, HostConfig, HostConfigPortBindings};
     use serde_json;
 
+    use error::ErrorKind;
+    use image::ImageReferencePart;
+
     #[test]
     #[should_panic]
     fn empty_image_fails() {
@@ -98,6 +201,29 @@ mod tests {
         DockerConfig::new("    ", ContainerCreateBody::new(), None).unwrap();
     }
 
+    #[test]
+    fn invalid_image_fails() {
+        let err = DockerConfig::new("ubuntu::18.04", ContainerCreateBody::new(), None).unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidImage(ref image, ImageReferencePart::Tag) => {
+                assert_eq!("ubuntu::18.04", image)
+            }
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        let err = serde_json::from_value::<DockerConfig>(json!({"image": "Contoso/Sensor"}))
+            .unwrap_err();
+        assert!(err.to_string().contains("repository"), "{}", err);
+    }
+
+    #[test]
+    fn image_without_tag_is_kept_as_written() {
+        let config = DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap();
+        assert_eq!("ubuntu", config.image());
+        assert_eq!("ubuntu:latest", config.image_reference().to_string());
+        assert!(config.image_reference().tag_defaulted());
+    }
+
     #[test]
     fn docker_config_ser() {
         let mut labels = HashMap::new();
@@ -201,7 +327,7 @@ mod tests {
             "image": "ubuntu"
         });
         let config = serde_json::from_str::<DockerConfig>(&input_json.to_string()).unwrap();
-        assert_eq!(config.image, "ubuntu");
+        assert_eq!(config.image(), "ubuntu");
     }
 
     #[test]
@@ -231,7 +357,7 @@ mod tests {
         });
 
         let config = serde_json::from_str::<DockerConfig>(&input_json.to_string()).unwrap();
-        assert_eq!(config.image, "ubuntu");
+        assert_eq!(config.image(), "ubuntu");
         assert_eq!(&config.create_options.labels().unwrap()["k1"], "v1");
         assert_eq!(&config.create_options.labels().unwrap()["k2"], "v2");
 
@@ -276,7 +402,7 @@ mod tests {
         });
 
         let config: DockerConfig = serde_json::from_str(&input_json.to_string()).unwrap();
-        assert_eq!(config.image, "ubuntu");
+        assert_eq!(config.image(), "ubuntu");
         assert_eq!(&config.create_options.labels().unwrap()["k1"], "v1");
         assert_eq!(&config.create_options.labels().unwrap()["k2"], "v2");
 
@@ -291,4 +417,80 @@ mod tests {
             "27017"
         );
     }
+
+    #[test]
+    fn credential_ref_round_trips() {
+        let input_json = json!({
+            "image": "contoso.azurecr.io/sensor:1.0",
+            "auth": "contoso"
+        });
+
+        let config: DockerConfig = serde_json::from_value(input_json.clone()).unwrap();
+
+        assert_eq!(Some("contoso"), config.credential_ref());
+        assert!(config.auth().is_none());
+        let output_json = serde_json::to_value(&config).unwrap();
+        assert_eq!(input_json["auth"], output_json["auth"]);
+    }
+
+    #[test]
+    fn inline_auth_round_trips() {
+        let input_json = json!({
+            "image": "contoso.azurecr.io/sensor:1.0",
+            "auth": {
+                "username": "contoso",
+                "password": "hunter2",
+                "serveraddress": "contoso.azurecr.io"
+            }
+        });
+
+        let config: DockerConfig = serde_json::from_value(input_json.clone()).unwrap();
+
+        assert!(config.credential_ref().is_none());
+        assert_eq!("hunter2", config.auth().unwrap().password().unwrap());
+        let output_json = serde_json::to_value(&config).unwrap();
+        assert_eq!(input_json["auth"], output_json["auth"]);
+    }
+
+    #[test]
+    fn debug_hides_inline_password() {
+        let auth = AuthConfig::new()
+            .with_username("contoso".to_string())
+            .with_password("hunter2".to_string());
+        let config = DockerConfig::new("ubuntu", ContainerCreateBody::new(), Some(auth)).unwrap();
+
+        let debug = format!("{:?}", config);
+
+        assert!(debug.contains("contoso"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn volumes_are_named_volumes_of_binds_and_mounts() {
+        let create_options: ContainerCreateBody = serde_json::from_value(json!({
+            "HostConfig": {
+                "Binds": [
+                    "edgehub-data:/data",
+                    "/etc/iotedge:/etc/iotedge:ro",
+                    "C:\\ProgramData\\iotedge:C:\\iotedge",
+                    "edgehub-data:/backup",
+                ],
+                "Mounts": [
+                    { "Type": "volume", "Source": "mongo-data", "Target": "/data/db" },
+                    { "Type": "volume", "Target": "/scratch" },
+                    { "Type": "bind", "Source": "/var/log", "Target": "/logs" },
+                ]
+            }
+        })).unwrap();
+        let config = DockerConfig::new("ubuntu", create_options, None).unwrap();
+
+        assert_eq!(vec!["edgehub-data", "mongo-data"], config.volumes());
+    }
+
+    #[test]
+    fn no_volumes_without_host_config() {
+        let config = DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap();
+
+        assert!(config.volumes().is_empty());
+    }
 }
@@ -10,9 +10,11 @@ use url::ParseError;
 
 use docker::apis::{ApiError as DockerApiError, Error as DockerError};
 use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
-use edgelet_http::Error as HttpError;
+use edgelet_http::{is_timeout, Error as HttpError};
 use edgelet_utils::Error as UtilsError;
 
+use image::ImageReferencePart;
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -56,12 +58,35 @@ pub enum ErrorKind {
     UrlParse,
     #[fail(display = "{}", _0)]
     NotFound(String),
+    #[fail(display = "Module {} not found", _0)]
+    ModuleNotFound(String),
     #[fail(display = "Conflict with current operation")]
     Conflict,
     #[fail(display = "Container already in this state")]
     NotModified,
     #[fail(display = "Container runtime error")]
     Docker,
+    #[fail(display = "Container runtime request timed out")]
+    Timeout,
+    #[fail(display = "Container runtime operation {} timed out", _0)]
+    OperationTimedOut(&'static str),
+    #[fail(display = "Could not create the network {} for modules", _0)]
+    NetworkCreate(String),
+    #[fail(display = "Network {} is still used by the containers {}", _0, _1)]
+    NetworkInUse(String, String),
+    #[fail(display = "Container engine {} is not configured", _0)]
+    UnknownEngine(String),
+    #[fail(display = "Invalid image {} - the {} is not valid", _0, _1)]
+    InvalidImage(String, ImageReferencePart),
+    #[fail(
+        display = "Image {} is pulled from the parent device, but no parent hostname is configured",
+        _0
+    )]
+    NoParentHostname(String),
+    #[fail(display = "Could not send signal {:?} to the module", _0)]
+    InvalidSignal(String),
+    #[fail(display = "Invalid filter {} = {:?}", _0, _1)]
+    InvalidFilter(String, String),
     #[fail(display = "{}", _0)]
     FormattedDockerRuntime(String),
     #[fail(display = "Container runtime error - {:?}", _0)]
@@ -92,6 +117,19 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         self.inner.get_context()
     }
+
+    /// Converts an error about the container of the module `id`. A container
+    /// that doesn't exist is reported as the module not being found, with
+    /// what docker said as the cause, however docker said it.
+    pub fn for_module(id: &str, err: DockerError<serde_json::Value>) -> Self {
+        let err = Error::from(err);
+        match *err.kind() {
+            ErrorKind::NotFound(_) => Error {
+                inner: err.context(ErrorKind::ModuleNotFound(id.to_string())),
+            },
+            _ => err,
+        }
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -143,16 +181,29 @@ impl From<HttpError> for Error {
 impl From<DockerError<serde_json::Value>> for Error {
     fn from(err: DockerError<serde_json::Value>) -> Self {
         match err {
-            DockerError::Hyper(error) => Error {
-                inner: Error::from(error).context(ErrorKind::Docker),
-            },
+            DockerError::Hyper(error) => {
+                let kind = if is_timeout(&error) {
+                    ErrorKind::Timeout
+                } else {
+                    ErrorKind::Docker
+                };
+                Error {
+                    inner: Error::from(error).context(kind),
+                }
+            }
+            DockerError::Timeout => Error::from(ErrorKind::Timeout),
             DockerError::Serde(error) => Error {
                 inner: Error::from(error).context(ErrorKind::Docker),
             },
             DockerError::Api(error) => match error.code {
+                // Some 404s come without a message, and are just as much
+                // about something that doesn't exist.
                 StatusCode::NOT_FOUND => match get_message(error) {
                     Ok(message) => Error::from(ErrorKind::NotFound(message)),
-                    Err(e) => Error::from(ErrorKind::DockerRuntime(DockerError::Api(e))),
+                    Err(e) => Error {
+                        inner: Error::from(ErrorKind::DockerRuntime(DockerError::Api(e)))
+                            .context(ErrorKind::NotFound("Not found".to_string())),
+                    },
                 },
                 StatusCode::CONFLICT => Error::from(ErrorKind::Conflict),
                 StatusCode::NOT_MODIFIED => Error::from(ErrorKind::NotModified),
@@ -176,3 +227,60 @@ impl From<Error> for CoreError {
         CoreError::from(err.context(CoreErrorKind::ModuleRuntime))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edgelet_core::metrics::error_kind_label;
+
+    fn api_error(
+        code: StatusCode,
+        content: Option<serde_json::Value>,
+    ) -> DockerError<serde_json::Value> {
+        DockerError::Api(DockerApiError { code, content })
+    }
+
+    #[test]
+    fn inspect_statuses_map_to_error_kinds() {
+        let message = || Some(json!({ "message": "No such container: sensor" }));
+        let cases = vec![
+            (StatusCode::NOT_FOUND, message(), "ModuleNotFound"),
+            (StatusCode::NOT_FOUND, None, "ModuleNotFound"),
+            (StatusCode::NOT_FOUND, Some(json!("not json")), "ModuleNotFound"),
+            (StatusCode::CONFLICT, message(), "Conflict"),
+            (StatusCode::NOT_MODIFIED, None, "NotModified"),
+            (StatusCode::BAD_REQUEST, message(), "FormattedDockerRuntime"),
+            (StatusCode::INTERNAL_SERVER_ERROR, message(), "FormattedDockerRuntime"),
+            (StatusCode::INTERNAL_SERVER_ERROR, None, "DockerRuntime"),
+        ];
+
+        for (code, content, kind) in cases {
+            let err = Error::for_module("sensor", api_error(code, content.clone()));
+            assert_eq!(kind, error_kind_label(err.kind()), "{} {:?}", code, content);
+        }
+        let err = Error::for_module("sensor", DockerError::Timeout);
+        assert_eq!("Timeout", error_kind_label(err.kind()));
+    }
+
+    #[test]
+    fn missing_modules_carry_their_id() {
+        let err = Error::for_module("sensor", api_error(StatusCode::NOT_FOUND, None));
+
+        match *err.kind() {
+            ErrorKind::ModuleNotFound(ref id) => assert_eq!("sensor", id),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert_eq!("Not found", err.cause().unwrap().to_string());
+    }
+
+    #[test]
+    fn not_found_without_a_message_is_not_found() {
+        let err = Error::from(api_error(StatusCode::NOT_FOUND, None));
+
+        match *err.kind() {
+            ErrorKind::NotFound(_) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! The `filters` query parameter of docker's list endpoints.
+//!
+//! Docker takes the filters as a JSON object mapping each filter to the
+//! values it should match, like `{"label":["owner=edge"],"name":["net"]}`.
+//! Building the object with serde rather than by formatting strings keeps
+//! quotes and backslashes in the values from breaking out of them.
+
+use std::collections::BTreeMap;
+
+use serde_json;
+
+use error::{Error, ErrorKind, Result};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DockerFilters {
+    filters: BTreeMap<String, Vec<String>>,
+}
+
+impl DockerFilters {
+    pub fn new() -> Self {
+        DockerFilters::default()
+    }
+
+    /// Adds a value for the filter `key`. A filter with several values
+    /// matches what any of them matches.
+    ///
+    /// Keys are lower case words like `label` or `dangling`. Values can be
+    /// anything but empty or have control characters, which no name, id or
+    /// label docker hands out does.
+    pub fn add(mut self, key: &str, value: &str) -> Result<Self> {
+        let valid_key = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid_key || value.is_empty() || value.chars().any(char::is_control) {
+            return Err(Error::from(ErrorKind::InvalidFilter(
+                key.to_string(),
+                value.to_string(),
+            )));
+        }
+
+        let values = self
+            .filters
+            .entry(key.to_string())
+            .or_insert_with(Vec::new);
+        if !values.iter().any(|v| v == value) {
+            values.push(value.to_string());
+        }
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The filters as the JSON docker expects in the `filters` parameter.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.filters)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // How docker reads the parameter.
+    fn parse(json: &str) -> HashMap<String, Vec<String>> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn filters_serialize_to_lists_of_values() {
+        let filters = DockerFilters::new()
+            .add("label", "net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent")
+            .unwrap()
+            .add("name", "azure-iot-edge")
+            .unwrap()
+            .add("label", "tier=edge")
+            .unwrap();
+
+        assert_eq!(
+            json!({
+                "label": [
+                    "net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent",
+                    "tier=edge"
+                ],
+                "name": ["azure-iot-edge"]
+            }).to_string(),
+            filters.to_json().unwrap()
+        );
+    }
+
+    #[test]
+    fn repeated_values_are_added_once() {
+        let filters = DockerFilters::new()
+            .add("name", "edge")
+            .unwrap()
+            .add("name", "edge")
+            .unwrap();
+
+        assert_eq!(r#"{"name":["edge"]}"#, filters.to_json().unwrap());
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        for value in &[
+            r#"edge"},"dangling":{"true"#,
+            r#"C:\networks\edge"#,
+            "réseau-\u{1F310}-エッジ",
+            "'single' \"double\" `back`",
+            "\\\"",
+        ] {
+            let json = DockerFilters::new()
+                .add("name", value)
+                .unwrap()
+                .to_json()
+                .unwrap();
+
+            let filters = parse(&json);
+            assert_eq!(1, filters.len(), "{}", json);
+            assert_eq!(vec![value.to_string()], filters["name"]);
+        }
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for &(key, value) in &[
+            ("", "edge"),
+            ("name", ""),
+            ("Name", "edge"),
+            ("na\"me", "edge"),
+            ("name", "edge\n"),
+            ("name", "edge\u{0}"),
+            ("label", "tier=\tedge"),
+        ] {
+            let err = DockerFilters::new().add(key, value).unwrap_err();
+            match *err.kind() {
+                ErrorKind::InvalidFilter(ref k, ref v) => {
+                    assert_eq!(key, k.as_str());
+                    assert_eq!(value, v.as_str());
+                }
+                _ => panic!("unexpected error {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn no_filters_is_an_empty_object() {
+        let filters = DockerFilters::new();
+        assert!(filters.is_empty());
+        assert_eq!("{}", filters.to_json().unwrap());
+    }
+}
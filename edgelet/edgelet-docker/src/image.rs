@@ -0,0 +1,557 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! References to container images, like `contoso.azurecr.io/sensor:1.0`.
+//!
+//! The grammar is the one docker uses:
+//!
+//! ```text
+//! reference  := name [ ":" tag ] [ "@" digest ]
+//! name       := [ registry "/" ] component [ "/" component ]*
+//! registry   := host [ ":" port ]
+//! component  := [a-z0-9]+ ( ( [._] | "__" | "-"+ ) [a-z0-9]+ )*
+//! tag        := [A-Za-z0-9_] [A-Za-z0-9_.-]{0,127}
+//! digest     := algorithm ":" hex
+//! ```
+//!
+//! The first part of a name is only a registry if it looks like a host,
+//! that is if it has a `.` or a `:`, is `localhost` or has upper case
+//! letters, which no repository may have.
+//!
+//! On a device below another edge device, the registry may be `$upstream`,
+//! optionally with a port, which stands for the parent device. It is
+//! replaced by the hostname of the parent before the image is pulled.
+
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use error::{Error, ErrorKind, Result};
+
+/// The registry images without one are pulled from.
+pub const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// The registry that stands for the parent device.
+pub const UPSTREAM_PLACEHOLDER: &str = "$upstream";
+
+/// The tag images without a tag or a digest are pulled with.
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Names docker hub is known by in credentials.
+const DEFAULT_REGISTRY_ALIASES: &[&str] = &[
+    DEFAULT_REGISTRY,
+    "index.docker.io",
+    "registry-1.docker.io",
+    "registry.hub.docker.com",
+];
+
+/// The longest name, registry included, docker accepts.
+const MAX_NAME_LEN: usize = 255;
+
+lazy_static! {
+    static ref REGISTRY: Regex = Regex::new(concat!(
+        r"^(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])",
+        r"(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))*",
+        r"(?::[0-9]+)?$",
+    )).unwrap();
+    static ref COMPONENT: Regex = Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$").unwrap();
+    static ref TAG: Regex = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$").unwrap();
+    static ref DIGEST: Regex =
+        Regex::new(r"^[A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}$")
+            .unwrap();
+}
+
+/// The part of an image reference that is invalid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageReferencePart {
+    Registry,
+    Repository,
+    Tag,
+    Digest,
+}
+
+impl fmt::Display for ImageReferencePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let part = match *self {
+            ImageReferencePart::Registry => "registry",
+            ImageReferencePart::Repository => "repository",
+            ImageReferencePart::Tag => "tag",
+            ImageReferencePart::Digest => "digest",
+        };
+        f.write_str(part)
+    }
+}
+
+/// An image reference split into its parts.
+///
+/// The reference is kept as it was written too, which is what it is
+/// serialized as, so that configs read back the same. Formatting it gives
+/// the normalized reference instead, with the tag `latest` made explicit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageReference {
+    text: String,
+    registry: Option<String>,
+    repository: String,
+    tag: Option<String>,
+    digest: Option<String>,
+    tag_defaulted: bool,
+}
+
+impl ImageReference {
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = |part| Error::from(ErrorKind::InvalidImage(text.to_string(), part));
+
+        let (name, digest) = match text.find('@') {
+            Some(at) => (&text[..at], Some(&text[at + 1..])),
+            None => (text, None),
+        };
+        if let Some(digest) = digest {
+            if !DIGEST.is_match(digest) {
+                return Err(invalid(ImageReferencePart::Digest));
+            }
+        }
+
+        // A colon after the last slash starts the tag, while one before it
+        // is the port of the registry.
+        let (name, tag) = match name.rfind(':') {
+            Some(colon) if !name[colon..].contains('/') => {
+                (&name[..colon], Some(&name[colon + 1..]))
+            }
+            _ => (name, None),
+        };
+        if let Some(tag) = tag {
+            if !TAG.is_match(tag) {
+                return Err(invalid(ImageReferencePart::Tag));
+            }
+        }
+        // Any colon left in the last part of the name belongs to the tag too,
+        // as in `ubuntu::18.04`.
+        if name
+            .rsplit('/')
+            .next()
+            .map_or(false, |last| last.contains(':'))
+        {
+            return Err(invalid(ImageReferencePart::Tag));
+        }
+
+        if name.len() > MAX_NAME_LEN {
+            return Err(invalid(ImageReferencePart::Repository));
+        }
+        let (registry, repository) = match name.find('/') {
+            Some(slash) if is_registry(&name[..slash]) => {
+                (Some(&name[..slash]), &name[slash + 1..])
+            }
+            _ => (None, name),
+        };
+        if let Some(registry) = registry {
+            let host = if is_upstream(registry) {
+                &registry[1..]
+            } else {
+                registry
+            };
+            if !REGISTRY.is_match(host) {
+                return Err(invalid(ImageReferencePart::Registry));
+            }
+        }
+        if !repository
+            .split('/')
+            .all(|component| COMPONENT.is_match(component))
+        {
+            return Err(invalid(ImageReferencePart::Repository));
+        }
+
+        let tag_defaulted = tag.is_none() && digest.is_none();
+        let tag = if tag_defaulted {
+            Some(DEFAULT_TAG)
+        } else {
+            tag
+        };
+        Ok(ImageReference {
+            text: text.to_string(),
+            registry: registry.map(ToString::to_string),
+            repository: repository.to_string(),
+            tag: tag.map(ToString::to_string),
+            digest: digest.map(ToString::to_string),
+            tag_defaulted,
+        })
+    }
+
+    /// The reference as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The registry the reference names, if it names one.
+    pub fn registry(&self) -> Option<&str> {
+        self.registry.as_ref().map(AsRef::as_ref)
+    }
+
+    /// The registry the image is pulled from.
+    pub fn registry_or_default(&self) -> &str {
+        self.registry().unwrap_or(DEFAULT_REGISTRY)
+    }
+
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    /// The tag of the image, which is `latest` if the reference has neither
+    /// a tag nor a digest.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_ref().map(AsRef::as_ref)
+    }
+
+    /// Whether the registry is the placeholder for the parent device.
+    pub fn is_upstream(&self) -> bool {
+        self.registry().map_or(false, is_upstream)
+    }
+
+    /// The reference with the placeholder registry replaced by
+    /// `parent_hostname`, keeping any port. Other references are returned as
+    /// they are.
+    pub fn with_upstream(&self, parent_hostname: &str) -> Result<Self> {
+        if self.is_upstream() {
+            let text = format!(
+                "{}{}",
+                parent_hostname,
+                &self.text[UPSTREAM_PLACEHOLDER.len()..]
+            );
+            ImageReference::parse(&text)
+        } else {
+            Ok(self.clone())
+        }
+    }
+
+    /// The reference with its registry replaced by `registry`, for pulling
+    /// the image from a mirror. The official images of docker hub, like
+    /// `ubuntu`, are under `library/` on other registries.
+    pub fn with_registry(&self, registry: &str) -> Result<Self> {
+        let library = if self.is_from(DEFAULT_REGISTRY) && !self.repository.contains('/') {
+            "library/"
+        } else {
+            ""
+        };
+        let mut text = format!("{}/{}{}", registry, library, self.repository);
+        if let (Some(tag), false) = (self.tag(), self.tag_defaulted) {
+            text.push_str(&format!(":{}", tag));
+        }
+        if let Some(digest) = self.digest() {
+            text.push_str(&format!("@{}", digest));
+        }
+
+        let reference = ImageReference::parse(&text)?;
+        if reference.registry() == Some(registry) {
+            Ok(reference)
+        } else {
+            Err(Error::from(ErrorKind::InvalidImage(text, ImageReferencePart::Registry)))
+        }
+    }
+
+    /// Whether the tag is `latest` only because the reference had none.
+    pub fn tag_defaulted(&self) -> bool {
+        self.tag_defaulted
+    }
+
+    /// Whether credentials for `server_address` are for the registry the
+    /// image is pulled from. The address may be a URL, like docker hub's
+    /// `https://index.docker.io/v1/`.
+    pub fn is_from(&self, server_address: &str) -> bool {
+        let server = server_address
+            .trim_left_matches("https://")
+            .trim_left_matches("http://");
+        let server = server.split('/').next().unwrap_or("");
+        match self.registry() {
+            Some(registry) => registry.eq_ignore_ascii_case(server),
+            None => DEFAULT_REGISTRY_ALIASES
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(server)),
+        }
+    }
+}
+
+fn is_upstream(registry: &str) -> bool {
+    registry.starts_with(UPSTREAM_PLACEHOLDER)
+        && (registry.len() == UPSTREAM_PLACEHOLDER.len()
+            || registry[UPSTREAM_PLACEHOLDER.len()..].starts_with(':'))
+}
+
+fn is_registry(part: &str) -> bool {
+    is_upstream(part)
+        || part.contains('.')
+        || part.contains(':')
+        || part == "localhost"
+        || part.chars().any(|c| c.is_ascii_uppercase())
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref registry) = self.registry {
+            write!(f, "{}/", registry)?;
+        }
+        f.write_str(&self.repository)?;
+        if let Some(ref tag) = self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(ref digest) = self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ImageReference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ImageReference::parse(s)
+    }
+}
+
+impl Serialize for ImageReference {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for ImageReference {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        ImageReference::parse(&text).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json;
+
+    const DIGEST_HEX: &str = "9f8bd6bbd8f8d5b4a7c1e6b2f0a7e7d4c3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8";
+
+    #[test]
+    fn valid_references_are_split() {
+        let digest = format!("sha256:{}", DIGEST_HEX);
+        let cases: Vec<(String, Option<&str>, &str, Option<&str>, Option<&str>)> = vec![
+            ("ubuntu".to_string(), None, "ubuntu", Some("latest"), None),
+            (
+                "ubuntu:18.04".to_string(),
+                None,
+                "ubuntu",
+                Some("18.04"),
+                None,
+            ),
+            (
+                "microsoft/azureiotedge-agent:1.0".to_string(),
+                None,
+                "microsoft/azureiotedge-agent",
+                Some("1.0"),
+                None,
+            ),
+            (
+                "mcr.microsoft.com/azureiotedge-hub:1.0-linux-amd64".to_string(),
+                Some("mcr.microsoft.com"),
+                "azureiotedge-hub",
+                Some("1.0-linux-amd64"),
+                None,
+            ),
+            (
+                "localhost:5000/sensor".to_string(),
+                Some("localhost:5000"),
+                "sensor",
+                Some("latest"),
+                None,
+            ),
+            (
+                "localhost/sensor:1.0".to_string(),
+                Some("localhost"),
+                "sensor",
+                Some("1.0"),
+                None,
+            ),
+            (
+                "Contoso/sensor".to_string(),
+                Some("Contoso"),
+                "sensor",
+                Some("latest"),
+                None,
+            ),
+            (
+                "contoso/my_app__v2.sensor--beta".to_string(),
+                None,
+                "contoso/my_app__v2.sensor--beta",
+                Some("latest"),
+                None,
+            ),
+            (
+                format!("ubuntu@{}", digest),
+                None,
+                "ubuntu",
+                None,
+                Some(digest.as_str()),
+            ),
+            (
+                format!("contoso.azurecr.io:443/a/b/c:1.0@{}", digest),
+                Some("contoso.azurecr.io:443"),
+                "a/b/c",
+                Some("1.0"),
+                Some(digest.as_str()),
+            ),
+        ];
+
+        for (text, registry, repository, tag, digest) in cases {
+            let reference = ImageReference::parse(&text).unwrap();
+            assert_eq!(text, reference.as_str());
+            assert_eq!(registry, reference.registry(), "{}", text);
+            assert_eq!(repository, reference.repository(), "{}", text);
+            assert_eq!(tag, reference.tag(), "{}", text);
+            assert_eq!(digest, reference.digest(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn invalid_references_name_the_bad_part() {
+        let long_name = "a".repeat(MAX_NAME_LEN + 1);
+        let long_tag = format!("ubuntu:{}", "a".repeat(129));
+        let cases = vec![
+            ("", ImageReferencePart::Repository),
+            ("ubuntu::18.04", ImageReferencePart::Tag),
+            ("ubuntu:", ImageReferencePart::Tag),
+            ("ubuntu:-beta", ImageReferencePart::Tag),
+            ("ubuntu:18.04 ", ImageReferencePart::Tag),
+            (long_tag.as_str(), ImageReferencePart::Tag),
+            ("ubuntu@sha256:1234", ImageReferencePart::Digest),
+            ("ubuntu@", ImageReferencePart::Digest),
+            ("ubuntu@sha256:abc@sha256:def", ImageReferencePart::Digest),
+            ("-contoso.io/sensor", ImageReferencePart::Registry),
+            ("contoso.io:port/sensor", ImageReferencePart::Registry),
+            ("contoso..io/sensor", ImageReferencePart::Registry),
+            ("Ubuntu", ImageReferencePart::Repository),
+            ("contoso/Sensor", ImageReferencePart::Repository),
+            ("contoso//sensor", ImageReferencePart::Repository),
+            ("contoso/sensor/", ImageReferencePart::Repository),
+            ("contoso/-sensor", ImageReferencePart::Repository),
+            ("contoso/sensor_", ImageReferencePart::Repository),
+            ("contoso/sensor___v2", ImageReferencePart::Repository),
+            ("contoso.io/", ImageReferencePart::Repository),
+            (" ubuntu", ImageReferencePart::Repository),
+            ("$upstreams/sensor", ImageReferencePart::Repository),
+            ("$upstream:port/sensor", ImageReferencePart::Registry),
+            (long_name.as_str(), ImageReferencePart::Repository),
+        ];
+
+        for (text, part) in cases {
+            let err = ImageReference::parse(text).unwrap_err();
+            match *err.kind() {
+                ErrorKind::InvalidImage(ref image, actual) => {
+                    assert_eq!(text, image);
+                    assert_eq!(part, actual, "{:?}", text);
+                }
+                ref kind => panic!("unexpected error {:?} for {:?}", kind, text),
+            }
+        }
+    }
+
+    #[test]
+    fn upstream_placeholder_is_replaced_by_parent() {
+        let reference = ImageReference::parse("$upstream:443/azureiotedge-agent:1.0").unwrap();
+        assert!(reference.is_upstream());
+        assert_eq!(Some("$upstream:443"), reference.registry());
+
+        let upstream = reference.with_upstream("parent.contoso.com").unwrap();
+        assert!(!upstream.is_upstream());
+        assert_eq!("parent.contoso.com:443/azureiotedge-agent:1.0", upstream.as_str());
+        assert_eq!(Some("parent.contoso.com:443"), upstream.registry());
+        assert_eq!("azureiotedge-agent", upstream.repository());
+
+        let reference = ImageReference::parse("$upstream/sensor").unwrap();
+        let upstream = reference.with_upstream("10.0.0.4").unwrap();
+        assert_eq!("10.0.0.4/sensor", upstream.as_str());
+        assert_eq!("10.0.0.4/sensor:latest", upstream.to_string());
+
+        assert!(reference.with_upstream("bad host").is_err());
+    }
+
+    #[test]
+    fn references_without_placeholder_are_kept() {
+        for text in &["contoso.azurecr.io/sensor:1.0", "ubuntu", "upstream/sensor"] {
+            let reference = ImageReference::parse(text).unwrap();
+            assert!(!reference.is_upstream());
+            assert_eq!(reference, reference.with_upstream("parent").unwrap());
+        }
+    }
+
+    #[test]
+    fn registry_is_replaced_by_mirror() {
+        let reference = ImageReference::parse("contoso.azurecr.io/sensor:1.0").unwrap();
+        let mirrored = reference.with_registry("mirror.local:5000").unwrap();
+        assert_eq!("mirror.local:5000/sensor:1.0", mirrored.as_str());
+        assert_eq!(Some("mirror.local:5000"), mirrored.registry());
+
+        let reference = ImageReference::parse("ubuntu").unwrap();
+        let mirrored = reference.with_registry("mirror.local").unwrap();
+        assert_eq!("mirror.local/library/ubuntu", mirrored.as_str());
+        assert!(mirrored.tag_defaulted());
+
+        let text = format!("microsoft/sensor@sha256:{}", DIGEST_HEX);
+        let reference = ImageReference::parse(&text).unwrap();
+        let mirrored = reference.with_registry("mirror.local").unwrap();
+        assert_eq!(format!("mirror.local/{}", text), mirrored.as_str());
+
+        // A host that doesn't look like one would be taken for a repository.
+        assert!(reference.with_registry("mirror").is_err());
+        assert!(reference.with_registry("bad host").is_err());
+    }
+
+    #[test]
+    fn missing_tag_defaults_to_latest() {
+        let reference = ImageReference::parse("contoso.azurecr.io/sensor").unwrap();
+        assert!(reference.tag_defaulted());
+        assert_eq!("contoso.azurecr.io/sensor", reference.as_str());
+        assert_eq!("contoso.azurecr.io/sensor:latest", reference.to_string());
+
+        let reference = ImageReference::parse("contoso.azurecr.io/sensor:latest").unwrap();
+        assert!(!reference.tag_defaulted());
+
+        let text = format!("sensor@sha256:{}", DIGEST_HEX);
+        let reference = ImageReference::parse(&text).unwrap();
+        assert!(!reference.tag_defaulted());
+        assert_eq!(text, reference.to_string());
+    }
+
+    #[test]
+    fn credentials_match_the_registry_of_the_image() {
+        let reference = ImageReference::parse("contoso.azurecr.io/sensor:1.0").unwrap();
+        assert!(reference.is_from("contoso.azurecr.io"));
+        assert!(reference.is_from("https://Contoso.azurecr.io/v2/"));
+        assert!(!reference.is_from("fabrikam.azurecr.io"));
+        assert!(!reference.is_from("docker.io"));
+
+        let reference = ImageReference::parse("microsoft/sensor:1.0").unwrap();
+        assert_eq!(DEFAULT_REGISTRY, reference.registry_or_default());
+        assert!(reference.is_from("docker.io"));
+        assert!(reference.is_from("https://index.docker.io/v1/"));
+        assert!(!reference.is_from("contoso.azurecr.io"));
+    }
+
+    #[test]
+    fn references_serialize_as_written() {
+        let reference: ImageReference = serde_json::from_str(r#""ubuntu""#).unwrap();
+        assert_eq!("ubuntu:latest", reference.to_string());
+        assert_eq!(r#""ubuntu""#, serde_json::to_string(&reference).unwrap());
+
+        let err = serde_json::from_str::<ImageReference>(r#""ubuntu::1""#).unwrap_err();
+        assert!(err.to_string().contains("tag"), "{}", err);
+    }
+}
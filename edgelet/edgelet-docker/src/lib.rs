@@ -10,6 +10,7 @@
 #![cfg_attr(feature = "cargo-clippy", allow(stutter, use_self))]
 
 extern crate base64;
+extern crate bytes;
 extern crate chrono;
 #[macro_use]
 extern crate failure;
@@ -20,14 +21,13 @@ extern crate hyper;
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+extern crate regex;
 #[macro_use]
 extern crate serde_derive;
-#[cfg(test)]
 extern crate serde;
 // Need stuff other than macros from serde_json for non-test code.
 #[cfg(not(test))]
 extern crate serde_json;
-#[cfg(test)]
 extern crate tokio;
 extern crate url;
 
@@ -53,11 +53,30 @@ extern crate edgelet_test_utils;
 mod client;
 mod config;
 mod error;
+mod filters;
+mod image;
+mod logs;
+mod mirror;
 mod module;
+mod multi;
+mod null;
+mod plan;
 mod runtime;
+mod state;
+mod system_info;
 
-pub use config::DockerConfig;
+pub use config::{DockerConfig, RegistryAuth};
 pub use error::{Error, ErrorKind};
-pub use module::{DockerModule, MODULE_TYPE};
+pub use filters::DockerFilters;
+pub use image::{
+    ImageReference, ImageReferencePart, DEFAULT_REGISTRY, DEFAULT_TAG, UPSTREAM_PLACEHOLDER,
+};
+pub use logs::{LogLine, LogLines, StdStream, DEFAULT_MAX_LINE_LEN};
+pub use mirror::{RegistryMirror, RegistryMirrors};
+pub use module::{DockerModule, MODULE_TYPE, STOP_PRIORITY_LABEL};
+pub use multi::{EngineModule, MultiDockerModuleRuntime, DEFAULT_ENGINE};
+pub use null::{NullModule, NullModuleRuntime, NULL_RUNTIME_VERSION};
 
 pub use runtime::DockerModuleRuntime;
+pub use state::{ModuleRecord, ModuleStateStore};
+pub use system_info::DEFAULT_SYSTEM_INFO_TTL_SECS;
@@ -0,0 +1,676 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
+use std::str;
+
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::{Async, Poll, Stream};
+use serde::Serializer;
+
+/// The length of the header docker frames each log message with.
+const HEADER_LEN: usize = 8;
+
+/// The longest timestamp docker prefixes a message with, in RFC3339 with
+/// nanoseconds and a time zone offset.
+const MAX_TIMESTAMP_LEN: usize = 35;
+
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+/// The standard stream of a container a log line was written to.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdStream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+/// A line a module logged, without its line break.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LogLine {
+    stream: StdStream,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "serialize_text")]
+    text: Bytes,
+    truncated: bool,
+}
+
+impl LogLine {
+    pub fn new(stream: StdStream, timestamp: Option<DateTime<Utc>>, text: Bytes) -> Self {
+        LogLine {
+            stream,
+            timestamp,
+            text,
+            truncated: false,
+        }
+    }
+
+    pub fn stream(&self) -> StdStream {
+        self.stream
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
+    pub fn text(&self) -> &Bytes {
+        &self.text
+    }
+
+    /// Whether the line was longer than the longest line kept, and was cut
+    /// off.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+}
+
+fn serialize_text<S>(text: &Bytes, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&String::from_utf8_lossy(text))
+}
+
+/// Splits the logs docker streams for a container into lines.
+///
+/// Docker frames each message it streams with a header that tells the
+/// standard stream it was written to and its length, and the frames arrive in
+/// chunks that start and end anywhere. A line can also span several messages,
+/// with messages of the other streams in between. When the logs were asked for
+/// with timestamps, docker starts every message with one, which is parsed out
+/// of the line it starts and dropped from the messages that continue a line.
+///
+/// Lines longer than `max_line_len` are cut off and marked as truncated, so
+/// that a module that never writes a line break can't make the daemon buffer
+/// its logs without bound.
+pub struct LogLines<S> {
+    inner: S,
+    timestamps: bool,
+    max_line_len: usize,
+    header: BytesMut,
+    frame: Option<Frame>,
+    stdin: PartialLine,
+    stdout: PartialLine,
+    stderr: PartialLine,
+    ready: VecDeque<LogLine>,
+    done: bool,
+}
+
+/// The message the next payload bytes belong to.
+struct Frame {
+    /// `None` for streams that aren't known, whose messages are skipped.
+    stream: Option<StdStream>,
+    remaining: usize,
+    /// The start of the message, while it may still be a timestamp.
+    prefix: Option<BytesMut>,
+}
+
+#[derive(Default)]
+struct PartialLine {
+    started: bool,
+    timestamp: Option<DateTime<Utc>>,
+    text: BytesMut,
+    truncated: bool,
+}
+
+impl<S> LogLines<S>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+{
+    /// Splits `inner` into lines. Set `timestamps` if the logs were asked
+    /// for with timestamps, since otherwise text that happens to look like
+    /// one is taken for part of the line.
+    pub fn new(inner: S, timestamps: bool) -> Self {
+        LogLines {
+            inner,
+            timestamps,
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+            header: BytesMut::with_capacity(HEADER_LEN),
+            frame: None,
+            stdin: PartialLine::default(),
+            stdout: PartialLine::default(),
+            stderr: PartialLine::default(),
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    pub fn with_max_line_len(mut self, max_line_len: usize) -> Self {
+        self.max_line_len = max_line_len;
+        self
+    }
+
+    fn decode(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            match self.frame.take() {
+                None => {
+                    let n = cmp::min(HEADER_LEN - self.header.len(), data.len());
+                    self.header.extend_from_slice(&data[..n]);
+                    data = &data[n..];
+                    if self.header.len() == HEADER_LEN {
+                        self.start_frame();
+                    }
+                }
+                Some(mut frame) => {
+                    let n = cmp::min(frame.remaining, data.len());
+                    frame.remaining -= n;
+                    self.payload(&mut frame, &data[..n]);
+                    data = &data[n..];
+                    if frame.remaining > 0 {
+                        self.frame = Some(frame);
+                    } else {
+                        self.end_frame(frame);
+                    }
+                }
+            }
+        }
+    }
+
+    fn start_frame(&mut self) {
+        let stream = match self.header[0] {
+            0 => Some(StdStream::Stdin),
+            1 => Some(StdStream::Stdout),
+            2 => Some(StdStream::Stderr),
+            _ => None,
+        };
+        let remaining = self.header[4..]
+            .iter()
+            .fold(0, |len, &b| (len << 8) | usize::from(b));
+        self.header.clear();
+
+        if remaining > 0 {
+            let prefix = if self.timestamps && stream.is_some() {
+                Some(BytesMut::with_capacity(MAX_TIMESTAMP_LEN))
+            } else {
+                None
+            };
+            self.frame = Some(Frame {
+                stream,
+                remaining,
+                prefix,
+            });
+        }
+    }
+
+    fn payload(&mut self, frame: &mut Frame, mut data: &[u8]) {
+        let stream = match frame.stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        if let Some(mut prefix) = frame.prefix.take() {
+            let end = data.iter().position(|&b| b == b' ' || b == b'\n');
+            match end {
+                Some(end) if data[end] == b' ' && prefix.len() + end <= MAX_TIMESTAMP_LEN => {
+                    prefix.extend_from_slice(&data[..end]);
+                    data = &data[end + 1..];
+                    match parse_timestamp(&prefix) {
+                        Some(timestamp) => {
+                            let line = self.line_mut(stream);
+                            // The messages that continue a line have
+                            // timestamps of their own, which are dropped.
+                            if !line.started {
+                                line.started = true;
+                                line.timestamp = Some(timestamp);
+                            }
+                        }
+                        None => {
+                            prefix.extend_from_slice(b" ");
+                            self.text(stream, &prefix);
+                        }
+                    }
+                }
+                None if prefix.len() + data.len() <= MAX_TIMESTAMP_LEN => {
+                    prefix.extend_from_slice(data);
+                    frame.prefix = Some(prefix);
+                    return;
+                }
+                _ => self.text(stream, &prefix),
+            }
+        }
+
+        self.text(stream, data);
+    }
+
+    fn end_frame(&mut self, frame: Frame) {
+        if let (Some(stream), Some(prefix)) = (frame.stream, frame.prefix) {
+            self.text(stream, &prefix);
+        }
+    }
+
+    /// Adds `data` to the line being written to `stream`, and completes the
+    /// lines it breaks.
+    fn text(&mut self, stream: StdStream, mut data: &[u8]) {
+        while let Some(end) = data.iter().position(|&b| b == b'\n') {
+            self.append(stream, &data[..end]);
+            self.complete(stream);
+            data = &data[end + 1..];
+        }
+        self.append(stream, data);
+    }
+
+    fn append(&mut self, stream: StdStream, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let max_line_len = self.max_line_len;
+        let line = self.line_mut(stream);
+        line.started = true;
+        let room = max_line_len.saturating_sub(line.text.len());
+        if data.len() > room {
+            line.text.extend_from_slice(&data[..room]);
+            line.truncated = true;
+        } else {
+            line.text.extend_from_slice(data);
+        }
+    }
+
+    fn complete(&mut self, stream: StdStream) {
+        let line = mem::replace(self.line_mut(stream), PartialLine::default());
+        self.ready.push_back(
+            LogLine::new(stream, line.timestamp, line.text.freeze()).with_truncated(line.truncated),
+        );
+    }
+
+    /// Completes the lines that were still being written when the logs
+    /// ended.
+    fn flush(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            self.end_frame(frame);
+        }
+        self.header.clear();
+
+        for &stream in &[StdStream::Stdin, StdStream::Stdout, StdStream::Stderr] {
+            if self.line_mut(stream).started {
+                self.complete(stream);
+            }
+        }
+    }
+
+    fn line_mut(&mut self, stream: StdStream) -> &mut PartialLine {
+        match stream {
+            StdStream::Stdin => &mut self.stdin,
+            StdStream::Stdout => &mut self.stdout,
+            StdStream::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+impl<S> Stream for LogLines<S>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+{
+    type Item = LogLine;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(line) = self.ready.pop_front() {
+                return Ok(Async::Ready(Some(line)));
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => self.decode(chunk.as_ref()),
+                None => {
+                    self.done = true;
+                    self.flush();
+                }
+            }
+        }
+    }
+}
+
+fn parse_timestamp(prefix: &[u8]) -> Option<DateTime<Utc>> {
+    let prefix = str::from_utf8(prefix).ok()?;
+    DateTime::parse_from_rfc3339(prefix)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use futures::stream::iter_ok;
+    use futures::Future;
+
+    /// Frames `payload` the way docker does for `stream`.
+    fn frame(stream: u8, payload: &str) -> Vec<u8> {
+        let len = payload.len();
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+        let mut frame = vec![
+            stream,
+            0,
+            0,
+            0,
+            (len >> 24) as u8,
+            (len >> 16) as u8,
+            (len >> 8) as u8,
+            len as u8,
+        ];
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    }
+
+    fn lines(chunks: Vec<Vec<u8>>, timestamps: bool) -> Vec<LogLine> {
+        lines_with_max(chunks, timestamps, DEFAULT_MAX_LINE_LEN)
+    }
+
+    fn lines_with_max(chunks: Vec<Vec<u8>>, timestamps: bool, max: usize) -> Vec<LogLine> {
+        LogLines::new(iter_ok::<_, ()>(chunks), timestamps)
+            .with_max_line_len(max)
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    /// Splits `data` into chunks of `size` bytes.
+    fn chunks_of(data: &[u8], size: usize) -> Vec<Vec<u8>> {
+        data.chunks(size).map(ToOwned::to_owned).collect()
+    }
+
+    fn line(stream: StdStream, text: &str) -> LogLine {
+        LogLine::new(stream, None, Bytes::from(text))
+    }
+
+    fn stamped(stream: StdStream, timestamp: DateTime<Utc>, text: &str) -> LogLine {
+        LogLine::new(stream, Some(timestamp), Bytes::from(text))
+    }
+
+    fn time() -> DateTime<Utc> {
+        Utc.ymd(2018, 10, 15).and_hms_nano(14, 0, 0, 123_456_789)
+    }
+
+    #[test]
+    fn one_line_per_frame() {
+        let data = [frame(1, "Roses are red\n"), frame(2, "violets are blue\n")].concat();
+
+        assert_eq!(
+            vec![
+                line(StdStream::Stdout, "Roses are red"),
+                line(StdStream::Stderr, "violets are blue"),
+            ],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn frames_split_at_every_byte() {
+        let data = [
+            frame(1, "Roses are red\n"),
+            frame(2, "violets are blue\n"),
+            frame(0, "sugar is sweet\n"),
+        ].concat();
+        let expected = vec![
+            line(StdStream::Stdout, "Roses are red"),
+            line(StdStream::Stderr, "violets are blue"),
+            line(StdStream::Stdin, "sugar is sweet"),
+        ];
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(
+                expected,
+                lines(chunks_of(&data, size), false),
+                "chunks of {} bytes",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn header_split_across_chunks() {
+        let data = frame(1, "Roses are red\n");
+        let chunks = vec![data[..3].to_vec(), data[3..7].to_vec(), data[7..].to_vec()];
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses are red")],
+            lines(chunks, false)
+        );
+    }
+
+    #[test]
+    fn line_split_across_frames() {
+        let data = [frame(1, "Roses "), frame(1, "are "), frame(1, "red\n")].concat();
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses are red")],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn streams_interleave_within_a_line() {
+        let data = [
+            frame(1, "Roses "),
+            frame(2, "violets are blue\n"),
+            frame(1, "are red\n"),
+        ].concat();
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(
+                vec![
+                    line(StdStream::Stderr, "violets are blue"),
+                    line(StdStream::Stdout, "Roses are red"),
+                ],
+                lines(chunks_of(&data, size), false)
+            );
+        }
+    }
+
+    #[test]
+    fn several_lines_in_a_frame() {
+        let data = frame(1, "Roses are red\nviolets are blue\n\nsugar is sweet\n");
+
+        assert_eq!(
+            vec![
+                line(StdStream::Stdout, "Roses are red"),
+                line(StdStream::Stdout, "violets are blue"),
+                line(StdStream::Stdout, ""),
+                line(StdStream::Stdout, "sugar is sweet"),
+            ],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn unfinished_line_is_completed_at_the_end() {
+        let data = [frame(1, "Roses are red\nviolets"), frame(2, "sugar")].concat();
+
+        assert_eq!(
+            vec![
+                line(StdStream::Stdout, "Roses are red"),
+                line(StdStream::Stdout, "violets"),
+                line(StdStream::Stderr, "sugar"),
+            ],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn logs_cut_off_mid_frame_keep_what_arrived() {
+        let data = frame(1, "Roses are red\n");
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses")],
+            lines(vec![data[..13].to_vec()], false)
+        );
+    }
+
+    #[test]
+    fn logs_cut_off_mid_header_drop_the_header() {
+        let chunks = vec![
+            frame(1, "Roses are red\n"),
+            frame(2, "violets")[..4].to_vec(),
+        ];
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses are red")],
+            lines(chunks, false)
+        );
+    }
+
+    #[test]
+    fn empty_frames_and_chunks_are_skipped() {
+        let chunks = vec![vec![], frame(1, ""), frame(1, "Roses are red\n"), vec![]];
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses are red")],
+            lines(chunks, false)
+        );
+    }
+
+    #[test]
+    fn unknown_streams_are_skipped() {
+        let data = [frame(3, "garbage\n"), frame(1, "Roses are red\n")].concat();
+
+        assert_eq!(
+            vec![line(StdStream::Stdout, "Roses are red")],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn timestamps_are_parsed() {
+        let data = [
+            frame(1, "2018-10-15T14:00:00.123456789Z Roses are red\n"),
+            frame(2, "2018-10-15T16:00:00.123456789+02:00 violets are blue\n"),
+        ].concat();
+        let expected = vec![
+            stamped(StdStream::Stdout, time(), "Roses are red"),
+            stamped(StdStream::Stderr, time(), "violets are blue"),
+        ];
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(expected, lines(chunks_of(&data, size), true));
+        }
+    }
+
+    #[test]
+    fn continued_lines_keep_their_first_timestamp() {
+        let data = [
+            frame(1, "2018-10-15T14:00:00.123456789Z Roses "),
+            frame(1, "2018-10-15T14:00:01.123456789Z are red\n"),
+        ].concat();
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(
+                vec![stamped(StdStream::Stdout, time(), "Roses are red")],
+                lines(chunks_of(&data, size), true)
+            );
+        }
+    }
+
+    #[test]
+    fn timestamps_are_left_alone_unless_asked_for() {
+        let data = frame(1, "2018-10-15T14:00:00.123456789Z Roses are red\n");
+
+        assert_eq!(
+            vec![line(
+                StdStream::Stdout,
+                "2018-10-15T14:00:00.123456789Z Roses are red"
+            )],
+            lines(vec![data], false)
+        );
+    }
+
+    #[test]
+    fn lines_without_timestamps_are_kept_whole() {
+        let data = [
+            frame(1, "Roses are red\n"),
+            frame(1, "violets\n"),
+            frame(1, "a-rather-long-first-word-without-any-space-in-it\n"),
+            frame(1, "2018-10-15 is not a timestamp\n"),
+        ].concat();
+        let expected = vec![
+            line(StdStream::Stdout, "Roses are red"),
+            line(StdStream::Stdout, "violets"),
+            line(
+                StdStream::Stdout,
+                "a-rather-long-first-word-without-any-space-in-it",
+            ),
+            line(StdStream::Stdout, "2018-10-15 is not a timestamp"),
+        ];
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(expected, lines(chunks_of(&data, size), true));
+        }
+    }
+
+    #[test]
+    fn message_shorter_than_a_timestamp() {
+        let data = [frame(1, "violets"), frame(2, "Roses are red\n")].concat();
+
+        assert_eq!(
+            vec![
+                line(StdStream::Stderr, "Roses are red"),
+                line(StdStream::Stdout, "violets"),
+            ],
+            lines(vec![data], true)
+        );
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let data = [
+            frame(1, "Roses are red\n"),
+            frame(1, "violets "),
+            frame(1, "are blue\n"),
+            frame(1, "sugar\n"),
+        ].concat();
+        let expected = vec![
+            line(StdStream::Stdout, "Roses are").with_truncated(true),
+            line(StdStream::Stdout, "violets a").with_truncated(true),
+            line(StdStream::Stdout, "sugar"),
+        ];
+
+        for size in 1..data.len() + 1 {
+            assert_eq!(expected, lines_with_max(chunks_of(&data, size), false, 9));
+        }
+    }
+
+    #[test]
+    fn truncated_lines_keep_their_timestamp() {
+        let data = frame(1, "2018-10-15T14:00:00.123456789Z Roses are red\n");
+
+        assert_eq!(
+            vec![stamped(StdStream::Stdout, time(), "Roses").with_truncated(true)],
+            lines_with_max(vec![data], true, 5)
+        );
+    }
+
+    #[test]
+    fn lines_serialize_as_json() {
+        let stderr = stamped(StdStream::Stderr, time(), "violets are \u{1f499}");
+        assert_eq!(
+            json!({
+                "stream": "stderr",
+                "timestamp": "2018-10-15T14:00:00.123456789Z",
+                "text": "violets are \u{1f499}",
+                "truncated": false,
+            }),
+            ::serde_json::to_value(&stderr).unwrap()
+        );
+
+        let stdout = line(StdStream::Stdout, "Roses are red").with_truncated(true);
+        assert_eq!(
+            json!({ "stream": "stdout", "text": "Roses are red", "truncated": true }),
+            ::serde_json::to_value(&stdout).unwrap()
+        );
+    }
+}
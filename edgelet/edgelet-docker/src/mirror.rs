@@ -0,0 +1,181 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Registries whose images are pulled through a mirror, like a registry on
+//! the local network that caches them. The daemon's own mirrors only cover
+//! docker hub, and a pull doesn't fall back when they are down.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use error::{Error, ErrorKind};
+use image::ImageReference;
+
+/// Words in what docker says about a pull that the registry refused the
+/// credentials it was given.
+const AUTH_FAILURES: &[&str] = &["unauthorized", "authentication required", "denied"];
+
+/// A registry and the mirror its images are pulled through.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RegistryMirror {
+    registry: String,
+    mirror: String,
+    /// The registry credential to pull from the mirror with, by name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    credential: Option<String>,
+}
+
+impl RegistryMirror {
+    pub fn new(registry: String, mirror: String) -> Self {
+        RegistryMirror {
+            registry,
+            mirror,
+            credential: None,
+        }
+    }
+
+    pub fn with_credential(mut self, credential: Option<String>) -> Self {
+        self.credential = credential;
+        self
+    }
+
+    /// The registry, like `contoso.azurecr.io` or `docker.io`.
+    pub fn registry(&self) -> &str {
+        &self.registry
+    }
+
+    /// The host, and port, of the mirror.
+    pub fn mirror(&self) -> &str {
+        &self.mirror
+    }
+
+    pub fn credential(&self) -> Option<&str> {
+        self.credential.as_ref().map(AsRef::as_ref)
+    }
+}
+
+/// The mirrors images are pulled through, and the reference each image was
+/// last pulled as, which is what its containers are created from.
+///
+/// Clones share the references images were pulled as.
+#[derive(Clone, Debug, Default)]
+pub struct RegistryMirrors {
+    mirrors: Arc<Vec<RegistryMirror>>,
+    pulled: Arc<Mutex<HashMap<String, ImageReference>>>,
+}
+
+impl RegistryMirrors {
+    pub fn new(mirrors: Vec<RegistryMirror>) -> Self {
+        RegistryMirrors {
+            mirrors: Arc::new(mirrors),
+            pulled: Arc::default(),
+        }
+    }
+
+    /// The mirror `image` is pulled through, if its registry has one.
+    pub fn mirror(&self, image: &ImageReference) -> Option<&RegistryMirror> {
+        self.mirrors
+            .iter()
+            .find(|mirror| image.is_from(mirror.registry()))
+    }
+
+    /// Records that `image` was pulled as `pulled`, which is either `image`
+    /// itself or the reference to it on a mirror.
+    pub fn record_pulled(&self, image: &ImageReference, pulled: ImageReference) {
+        let mut records = self.lock();
+        if pulled == *image {
+            records.remove(&image.to_string());
+        } else {
+            info!("Image {} was pulled from a mirror as {}", image, pulled);
+            records.insert(image.to_string(), pulled);
+        }
+    }
+
+    /// The reference `image` was last pulled as. Images that weren't pulled
+    /// from a mirror are known by their own reference.
+    pub fn pulled_as(&self, image: &ImageReference) -> ImageReference {
+        self.lock()
+            .get(&image.to_string())
+            .cloned()
+            .unwrap_or_else(|| image.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, ImageReference>> {
+        // Each record is a single insert or removal, so a panic while the
+        // lock was held cannot have left the records inconsistent.
+        self.pulled.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Whether a pull from a mirror that failed with `err` is retried from the
+/// registry itself. It is when the mirror is down or doesn't have the image,
+/// but not when it refused the credentials, nor when the container runtime
+/// itself couldn't be reached.
+pub fn falls_back(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::NotFound(ref message) | ErrorKind::FormattedDockerRuntime(ref message) => {
+            let message = message.to_lowercase();
+            !AUTH_FAILURES.iter().any(|word| message.contains(word))
+        }
+        ErrorKind::DockerRuntime(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(text: &str) -> ImageReference {
+        ImageReference::parse(text).unwrap()
+    }
+
+    #[test]
+    fn images_are_mirrored_by_registry() {
+        let mirrors = RegistryMirrors::new(vec![
+            RegistryMirror::new("contoso.azurecr.io".to_string(), "mirror.local".to_string()),
+            RegistryMirror::new("docker.io".to_string(), "hub.local:5000".to_string()),
+        ]);
+
+        let mirror = mirrors.mirror(&reference("contoso.azurecr.io/sensor:1.0"));
+        assert_eq!(Some("mirror.local"), mirror.map(RegistryMirror::mirror));
+        let mirror = mirrors.mirror(&reference("ubuntu"));
+        assert_eq!(Some("hub.local:5000"), mirror.map(RegistryMirror::mirror));
+        assert_eq!(None, mirrors.mirror(&reference("fabrikam.azurecr.io/sensor")));
+    }
+
+    #[test]
+    fn images_are_known_by_the_reference_they_were_pulled_as() {
+        let mirrors = RegistryMirrors::default();
+        let image = reference("contoso.azurecr.io/sensor:1.0");
+        let mirrored = reference("mirror.local/sensor:1.0");
+        assert_eq!(image, mirrors.pulled_as(&image));
+
+        mirrors.clone().record_pulled(&image, mirrored.clone());
+        assert_eq!(mirrored, mirrors.pulled_as(&image));
+
+        mirrors.record_pulled(&image, image.clone());
+        assert_eq!(image, mirrors.pulled_as(&image));
+    }
+
+    #[test]
+    fn only_failures_of_the_mirror_fall_back() {
+        for message in &[
+            "Get https://mirror.local/v2/: dial tcp 10.0.0.4:443: connect: connection refused",
+            "manifest for mirror.local/sensor:1.0 not found",
+            "received unexpected HTTP status: 503 Service Unavailable",
+        ] {
+            let message = message.to_string();
+            assert!(falls_back(&Error::from(ErrorKind::FormattedDockerRuntime(message.clone()))));
+            assert!(falls_back(&Error::from(ErrorKind::NotFound(message))));
+        }
+        for message in &[
+            "Get https://mirror.local/v2/: unauthorized: authentication required",
+            "pull access denied for mirror.local/sensor",
+        ] {
+            let message = message.to_string();
+            assert!(!falls_back(&Error::from(ErrorKind::FormattedDockerRuntime(message))));
+        }
+        assert!(!falls_back(&Error::from(ErrorKind::Timeout)));
+        assert!(!falls_back(&Error::from(ErrorKind::Docker)));
+    }
+}
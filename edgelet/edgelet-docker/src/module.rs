@@ -1,6 +1,7 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use std::str::FromStr;
+use std::time::Duration;
 
 use chrono::prelude::*;
 use futures::Future;
@@ -9,12 +10,16 @@ use hyper::client::connect::Connect;
 use client::DockerClient;
 use config::DockerConfig;
 use edgelet_core::pid::Pid;
-use edgelet_core::{Module, ModuleRuntimeState, ModuleStatus};
+use edgelet_core::{Module, ModuleHealth, ModuleRuntimeState, ModuleStatus};
 use error::{Error, Result};
 
 pub const MODULE_TYPE: &str = "docker";
 pub const MIN_DATE: &str = "0001-01-01T00:00:00Z";
 
+/// The label that sets the stop priority of a module. Modules with a lower
+/// priority are stopped first.
+pub const STOP_PRIORITY_LABEL: &str = "net.azure-devices.edge.stop-priority";
+
 pub struct DockerModule<C: Connect> {
     client: DockerClient<C>,
     name: String,
@@ -31,6 +36,41 @@ impl<C: Connect> DockerModule<C> {
     }
 }
 
+impl<C: 'static + Connect> DockerModule<C> {
+    /// The processes running in the module's container.
+    pub fn top(&self) -> impl Future<Item = Vec<Pid>, Error = Error> + Send {
+        top(&self.client, &self.name)
+    }
+}
+
+/// The processes running in a container, by the `PID` column of what
+/// `docker top` reports. Containers that aren't running have none to report,
+/// which fails.
+pub fn top<C: 'static + Connect>(
+    client: &DockerClient<C>,
+    id: &str,
+) -> impl Future<Item = Vec<Pid>, Error = Error> + Send {
+    let name = id.to_string();
+    client
+        .container_api()
+        .container_top(id, "")
+        .map(|response| {
+            let column = response
+                .titles()
+                .and_then(|titles| titles.iter().position(|title| title == "PID"));
+            column.map_or_else(Vec::new, |column| {
+                response
+                    .processes()
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(|process| process.get(column))
+                    .filter_map(|pid| pid.parse().ok())
+                    .map(Pid::Value)
+                    .collect()
+            })
+        }).map_err(move |err| Error::for_module(&name, err))
+}
+
 fn status_from_exit_code(exit_code: Option<i64>) -> Option<ModuleStatus> {
     exit_code.map(|code| {
         if code == 0 {
@@ -41,6 +81,32 @@ fn status_from_exit_code(exit_code: Option<i64>) -> Option<ModuleStatus> {
     })
 }
 
+/// The health Docker reports for a container with health checks. Containers
+/// without any report `none`, or no health at all.
+fn health_from_status(status: &str) -> Option<ModuleHealth> {
+    match status {
+        "starting" => Some(ModuleHealth::Starting),
+        "healthy" => Some(ModuleHealth::Healthy),
+        "unhealthy" => Some(ModuleHealth::Unhealthy),
+        _ => None,
+    }
+}
+
+/// Parses a time the way Docker formats them, which is RFC 3339 with up to
+/// nanoseconds. Docker reports a time that was never set, like when a
+/// container that hasn't stopped finished, as the zero value `MIN_DATE`.
+fn parse_time(time: Option<&str>) -> Option<DateTime<Utc>> {
+    time.and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc))
+        .and_then(|time| {
+            if Some(time) == DateTime::from_str(MIN_DATE).ok() {
+                None
+            } else {
+                Some(time)
+            }
+        })
+}
+
 impl<C: 'static + Connect> Module for DockerModule<C> {
     type Config = DockerConfig;
     type Error = Error;
@@ -59,6 +125,7 @@ impl<C: 'static + Connect> Module for DockerModule<C> {
     }
 
     fn runtime_state(&self) -> Self::RuntimeStateFuture {
+        let name = self.name.clone();
         Box::new(
             self.client
                 .container_api()
@@ -82,24 +149,46 @@ impl<C: 'static + Connect> Module for DockerModule<C> {
                                 .with_status(status)
                                 .with_exit_code(state.exit_code())
                                 .with_status_description(state.status().map(ToOwned::to_owned))
-                                .with_started_at(
-                                    state
-                                        .started_at()
-                                        .and_then(|d| if d == MIN_DATE { None } else { Some(d) })
-                                        .and_then(|started_at| DateTime::from_str(started_at).ok()),
-                                ).with_finished_at(
-                                    state
-                                        .finished_at()
-                                        .and_then(|d| if d == MIN_DATE { None } else { Some(d) })
-                                        .and_then(|finished_at| {
-                                            DateTime::from_str(finished_at).ok()
-                                        }),
-                                ).with_image_id(resp.id().map(ToOwned::to_owned))
+                                .with_created_at(parse_time(resp.created()))
+                                .with_started_at(parse_time(state.started_at()))
+                                .with_finished_at(parse_time(state.finished_at()))
+                                .with_image_id(resp.id().map(ToOwned::to_owned))
                                 .with_pid(state.pid().map_or(Pid::None, Pid::Value))
+                                .with_health(
+                                    state
+                                        .health()
+                                        .and_then(|health| health.status())
+                                        .and_then(health_from_status),
+                                )
                         })
-                }).map_err(Error::from),
+                }).map_err(move |err| Error::for_module(&name, err)),
         )
     }
+
+    fn volumes(&self) -> Vec<String> {
+        self.config.volumes()
+    }
+
+    fn stop_priority(&self) -> Option<i64> {
+        self.config
+            .create_options()
+            .labels()
+            .and_then(|labels| labels.get(STOP_PRIORITY_LABEL))
+            .and_then(|priority| priority.parse().ok())
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+    fn stop_timeout(&self) -> Option<Duration> {
+        self.config
+            .create_options()
+            .stop_timeout()
+            .filter(|timeout| *timeout >= 0)
+            .map(|timeout| Duration::from_secs(timeout as u64))
+    }
+
+    fn image_id(&self) -> Option<&str> {
+        self.config.image_id()
+    }
 }
 
 #[cfg(test)]
@@ -115,7 +204,10 @@ mod tests {
 
     use docker::apis::client::APIClient;
     use docker::apis::configuration::Configuration;
-    use docker::models::{ContainerCreateBody, InlineResponse200, InlineResponse200State};
+    use docker::models::{
+        ContainerCreateBody, InlineResponse200, InlineResponse200State,
+        InlineResponse200StateHealth,
+    };
     use edgelet_core::pid::Pid;
     use edgelet_core::{Module, ModuleStatus};
     use edgelet_test_utils::JsonConnector;
@@ -135,6 +227,43 @@ mod tests {
         DockerClient::new(APIClient::new(config))
     }
 
+    #[test]
+    fn stop_priority_comes_from_label() {
+        let mut labels = ::std::collections::HashMap::new();
+        labels.insert(STOP_PRIORITY_LABEL.to_string(), "5".to_string());
+        let config =
+            DockerConfig::new("ubuntu", ContainerCreateBody::new().with_labels(labels), None)
+                .unwrap();
+        let labelled = DockerModule::new(create_api_client("boo"), "mod1", config).unwrap();
+        let unlabelled = DockerModule::new(
+            create_api_client("boo"),
+            "mod2",
+            DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap(),
+        ).unwrap();
+
+        assert_eq!(Some(5), labelled.stop_priority());
+        assert_eq!(None, unlabelled.stop_priority());
+    }
+
+    #[test]
+    fn stop_timeout_comes_from_create_options() {
+        let config =
+            DockerConfig::new("ubuntu", ContainerCreateBody::new().with_stop_timeout(30), None)
+                .unwrap();
+        let module = DockerModule::new(create_api_client("boo"), "mod1", config).unwrap();
+        let default = DockerModule::new(
+            create_api_client("boo"),
+            "mod2",
+            DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap(),
+        ).unwrap();
+
+        assert_eq!(
+            Some(::std::time::Duration::from_secs(30)),
+            module.stop_timeout()
+        );
+        assert_eq!(None, default.stop_timeout());
+    }
+
     #[test]
     fn new_instance() {
         let docker_module = DockerModule::new(
@@ -341,7 +470,8 @@ mod tests {
                             .with_status("stopped".to_string())
                             .with_started_at(started_at.clone())
                             .with_finished_at(finished_at.clone()),
-                    ).with_id("mod1".to_string()),
+                    ).with_id("mod1".to_string())
+                    .with_created(MIN_DATE.to_string()),
             ),
             "mod1",
             DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap(),
@@ -351,7 +481,84 @@ mod tests {
             .unwrap()
             .block_on(docker_module.runtime_state())
             .unwrap();
+        assert_eq!(None, runtime_state.created_at());
         assert_eq!(None, runtime_state.started_at());
         assert_eq!(None, runtime_state.finished_at());
     }
+
+    #[test]
+    fn module_runtime_state_of_running_container() {
+        let docker_module = DockerModule::new(
+            create_api_client(
+                InlineResponse200::new()
+                    .with_state(
+                        InlineResponse200State::new()
+                            .with_status("running".to_string())
+                            .with_started_at("2018-10-15T14:00:01.123456789Z".to_string())
+                            .with_finished_at(MIN_DATE.to_string()),
+                    ).with_id("mod1".to_string())
+                    .with_created("2018-10-15T13:59:58.5Z".to_string()),
+            ),
+            "mod1",
+            DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap(),
+        ).unwrap();
+
+        let runtime_state = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(docker_module.runtime_state())
+            .unwrap();
+        assert_eq!(
+            Some(&Utc.ymd(2018, 10, 15).and_hms_milli(13, 59, 58, 500)),
+            runtime_state.created_at()
+        );
+        assert_eq!(
+            Some(&Utc.ymd(2018, 10, 15).and_hms_nano(14, 0, 1, 123_456_789)),
+            runtime_state.started_at()
+        );
+        assert_eq!(None, runtime_state.finished_at());
+    }
+
+    #[test]
+    fn module_runtime_state_carries_health() {
+        for &(status, health) in &[
+            ("starting", Some(ModuleHealth::Starting)),
+            ("healthy", Some(ModuleHealth::Healthy)),
+            ("unhealthy", Some(ModuleHealth::Unhealthy)),
+            ("none", None),
+        ] {
+            let docker_module = DockerModule::new(
+                create_api_client(
+                    InlineResponse200::new().with_state(
+                        InlineResponse200State::new()
+                            .with_status("running".to_string())
+                            .with_health(
+                                InlineResponse200StateHealth::new()
+                                    .with_status(status.to_string())
+                                    .with_failing_streak(3),
+                            ),
+                    ),
+                ),
+                "mod1",
+                DockerConfig::new("ubuntu", ContainerCreateBody::new(), None).unwrap(),
+            ).unwrap();
+
+            let runtime_state = tokio::runtime::current_thread::Runtime::new()
+                .unwrap()
+                .block_on(docker_module.runtime_state())
+                .unwrap();
+            assert_eq!(health, runtime_state.health());
+        }
+    }
+
+    #[test]
+    fn parse_time_maps_zero_value_to_none() {
+        assert_eq!(None, parse_time(None));
+        assert_eq!(None, parse_time(Some(MIN_DATE)));
+        assert_eq!(None, parse_time(Some("0001-01-01T00:00:00.000000000Z")));
+        assert_eq!(None, parse_time(Some("yesterday")));
+        assert_eq!(
+            Some(Utc.ymd(2018, 10, 15).and_hms_nano(12, 0, 1, 123_456_789)),
+            parse_time(Some("2018-10-15T14:00:01.123456789+02:00"))
+        );
+    }
 }
@@ -0,0 +1,1001 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use edgelet_core::pid::Pid;
+use edgelet_core::{
+    stop_in_order, BusyFlag, ImageInfo, IntegrityReport, LogOptions, Module, ModuleEpochs,
+    ModuleInspection, ModuleRegistry, ModuleRuntime, ModuleRuntimeState, ModuleSpec,
+    StopAllOptions, SystemInfo as CoreSystemInfo,
+};
+use edgelet_utils::log_failure;
+use futures::{future, stream, Future, Stream};
+use log::Level;
+
+use error::{Error, ErrorKind, Result};
+use runtime::DockerModuleRuntime;
+
+/// The name of the primary container engine. Modules that don't name an
+/// engine run on it.
+pub const DEFAULT_ENGINE: &str = "default";
+
+#[derive(Clone)]
+struct Engine<M> {
+    name: String,
+    runtime: M,
+}
+
+/// Runs modules on several container engines, like when customer modules
+/// must run on a sandboxed engine of their own while the edge runtime
+/// modules run on the primary one. A module is created on the engine its
+/// spec names in `runtime`, and every operation on it afterwards goes to the
+/// engine it was found on. Listing merges the modules of every engine, so
+/// that an engine that is down hides only its own modules.
+#[derive(Clone)]
+pub struct MultiDockerModuleRuntime<M = DockerModuleRuntime> {
+    engines: Arc<Vec<Engine<M>>>,
+    /// The engine each module was last seen on, by index into `engines`.
+    placements: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl<M> MultiDockerModuleRuntime<M>
+where
+    M: ModuleRuntime<Error = Error> + Clone,
+{
+    pub fn new(primary: M) -> Self {
+        MultiDockerModuleRuntime {
+            engines: Arc::new(vec![Engine {
+                name: DEFAULT_ENGINE.to_string(),
+                runtime: primary,
+            }]),
+            placements: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Adds an engine modules can name to run on. Names are unique, and
+    /// `DEFAULT_ENGINE` is the primary engine.
+    pub fn with_engine(mut self, name: String, runtime: M) -> Self {
+        Arc::make_mut(&mut self.engines).push(Engine { name, runtime });
+        self
+    }
+
+    /// The engine a module names, by index into `engines`.
+    fn engine_named(&self, name: Option<&str>) -> Result<usize> {
+        match name {
+            None => Ok(0),
+            Some(name) => self
+                .engines
+                .iter()
+                .position(|engine| engine.name == name)
+                .ok_or_else(|| Error::from(ErrorKind::UnknownEngine(name.to_string()))),
+        }
+    }
+
+    fn placements(&self) -> MutexGuard<HashMap<String, usize>> {
+        // Placements are only inserted and removed whole, so a panic while
+        // the lock was held cannot have left them inconsistent.
+        self.placements
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<M> MultiDockerModuleRuntime<M>
+where
+    M: 'static + ModuleRuntime<Error = Error> + Clone + Send + Sync,
+    M::Module: 'static,
+    M::ListFuture: 'static,
+{
+    /// The engine module `id` runs on. Modules that haven't been seen yet
+    /// are looked for on every engine, and are taken to be on the primary
+    /// engine if no engine has them, so that the primary engine reports that
+    /// they don't exist.
+    fn locate(&self, id: &str) -> Box<Future<Item = usize, Error = Error> + Send> {
+        let known = if self.engines.len() == 1 {
+            Some(0)
+        } else {
+            self.placements().get(id).cloned()
+        };
+        match known {
+            Some(index) => Box::new(future::ok(index)),
+            None => {
+                let placements = self.placements.clone();
+                let id = id.to_string();
+                Box::new(self.list().map(move |_| {
+                    placements
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or(0)
+                }))
+            }
+        }
+    }
+
+    /// Runs `f` on every engine at once, and gives the name of each engine
+    /// with what `f` came to on it.
+    fn each_engine<F, T>(
+        &self,
+        f: F,
+    ) -> impl Future<Item = Vec<(String, Result<T::Item>)>, Error = Error> + Send
+    where
+        F: Fn(&M) -> T,
+        T: 'static + Future<Error = Error> + Send,
+        T::Item: Send,
+    {
+        future::join_all(
+            self.engines
+                .iter()
+                .map(|engine| {
+                    let name = engine.name.clone();
+                    f(&engine.runtime).then(move |result| Ok((name, result)))
+                }).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Keeps what the engines that could be reached came to, with a warning for
+/// each one that failed. Fails only when every engine failed.
+fn reachable<T>(results: Vec<(String, Result<T>)>, action: &str) -> Result<Vec<(usize, T)>> {
+    let mut reached = vec![];
+    let mut first_err = None;
+    for (index, (name, result)) in results.into_iter().enumerate() {
+        match result {
+            Ok(value) => reached.push((index, value)),
+            Err(err) => {
+                warn!("Could not {} on container engine {}.", action, name);
+                log_failure(Level::Warn, &err);
+                first_err = first_err.or_else(|| Some(err));
+            }
+        }
+    }
+    match first_err {
+        Some(err) if reached.is_empty() => Err(err),
+        _ => Ok(reached),
+    }
+}
+
+/// Fails with the first error of an engine, once every engine is done.
+fn everywhere<T>(results: Vec<(String, Result<T>)>) -> Result<()> {
+    results
+        .into_iter()
+        .map(|(_, result)| result.map(|_| ()))
+        .collect()
+}
+
+impl<M> ModuleRegistry for MultiDockerModuleRuntime<M>
+where
+    M: 'static + ModuleRuntime<Error = Error> + Clone,
+    <M::ModuleRegistry as ModuleRegistry>::PullFuture: 'static,
+    <M::ModuleRegistry as ModuleRegistry>::RemoveFuture: 'static,
+{
+    type Error = Error;
+    type PullFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type RemoveFuture = Box<Future<Item = (), Error = Self::Error>>;
+    type Config = M::Config;
+
+    /// Pulls an image onto the primary engine. See `pull_module`.
+    fn pull(&self, config: &Self::Config) -> Self::PullFuture {
+        Box::new(self.engines[0].runtime.registry().pull(config))
+    }
+
+    /// Removes an image from every engine that has it.
+    fn remove(&self, name: &str) -> Self::RemoveFuture {
+        let removals = self
+            .engines
+            .iter()
+            .map(|engine| {
+                let engine_name = engine.name.clone();
+                engine
+                    .runtime
+                    .registry()
+                    .remove(name)
+                    .then(move |result| Ok::<_, Error>((engine_name, result)))
+            }).collect::<Vec<_>>();
+        Box::new(
+            future::join_all(removals)
+                .and_then(|results| reachable(results, "remove the image").map(|_| ())),
+        )
+    }
+
+    /// Pulls the image of `module` onto the engine the module names.
+    fn pull_module(&self, module: &ModuleSpec<Self::Config>) -> Self::PullFuture {
+        match self.engine_named(module.runtime()) {
+            Ok(index) => Box::new(self.engines[index].runtime.registry().pull(module.config())),
+            Err(err) => Box::new(future::err(err)),
+        }
+    }
+
+    /// The images of the primary engine only, so that image garbage
+    /// collection leaves those of the other engines alone.
+    fn images(&self) -> Box<Future<Item = Vec<ImageInfo>, Error = Self::Error> + Send> {
+        self.engines[0].runtime.registry().images()
+    }
+
+    /// Removes images from the primary engine.
+    fn prune_images(
+        &self,
+        ids: Vec<String>,
+    ) -> Box<Future<Item = Vec<String>, Error = Self::Error> + Send> {
+        self.engines[0].runtime.registry().prune_images(ids)
+    }
+}
+
+impl<M> ModuleRuntime for MultiDockerModuleRuntime<M>
+where
+    M: 'static + ModuleRuntime<Error = Error> + Clone + Send + Sync,
+    M::Config: 'static,
+    M::Module: 'static,
+    M::Logs: 'static,
+    <M::ModuleRegistry as ModuleRegistry>::PullFuture: 'static,
+    <M::ModuleRegistry as ModuleRegistry>::RemoveFuture: 'static,
+    M::CreateFuture: 'static,
+    M::InitFuture: 'static,
+    M::ListFuture: 'static,
+    M::ListWithDetailsStream: 'static,
+    M::LogsFuture: 'static,
+    M::RemoveFuture: 'static,
+    M::RestartFuture: 'static,
+    M::StartFuture: 'static,
+    M::StopFuture: 'static,
+    M::TopFuture: 'static,
+    M::SystemInfoFuture: 'static,
+    M::RemoveAllFuture: 'static,
+    M::CheckIntegrityFuture: 'static,
+    M::InspectFuture: 'static,
+    M::KillFuture: 'static,
+    M::UpdateFuture: 'static,
+{
+    type Error = Error;
+    type Config = M::Config;
+    type Module = EngineModule<M::Module>;
+    type ModuleRegistry = Self;
+    type Chunk = M::Chunk;
+    type Logs = M::Logs;
+
+    type CreateFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type InitFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type ListFuture = Box<Future<Item = Vec<Self::Module>, Error = Self::Error> + Send>;
+    type ListWithDetailsStream =
+        Box<Stream<Item = (Self::Module, ModuleRuntimeState), Error = Self::Error> + Send>;
+    type LogsFuture = Box<Future<Item = Self::Logs, Error = Self::Error> + Send>;
+    type RemoveFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type RestartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type TopFuture = Box<Future<Item = Vec<Pid>, Error = Self::Error> + Send>;
+    type SystemInfoFuture = Box<Future<Item = CoreSystemInfo, Error = Self::Error> + Send>;
+    type RemoveAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type CheckIntegrityFuture = Box<Future<Item = IntegrityReport, Error = Self::Error> + Send>;
+    type InspectFuture = Box<Future<Item = ModuleInspection, Error = Self::Error> + Send>;
+    type KillFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type UpdateFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+
+    /// Initializes every engine. Only the primary engine has to be reachable;
+    /// the modules of the others fail to be created while they are down.
+    fn init(&self) -> Self::InitFuture {
+        Box::new(self.each_engine(M::init).and_then(|mut results| {
+            let (_, primary) = results.remove(0);
+            for (name, result) in results {
+                if let Err(err) = result {
+                    warn!("Could not initialize container engine {}.", name);
+                    log_failure(Level::Warn, &err);
+                }
+            }
+            primary
+        }))
+    }
+
+    fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
+        let index = match self.engine_named(module.runtime()) {
+            Ok(index) => index,
+            Err(err) => return Box::new(future::err(err)),
+        };
+        let placements = self.placements.clone();
+        let name = module.name().to_string();
+        Box::new(self.engines[index].runtime.create(module).map(move |_| {
+            placements
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(name, index);
+        }))
+    }
+
+    fn start(&self, id: &str) -> Self::StartFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.start(&id)),
+        )
+    }
+
+    fn stop(&self, id: &str, wait_before_kill: Option<Duration>) -> Self::StopFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.stop(&id, wait_before_kill)),
+        )
+    }
+
+    fn restart(&self, id: &str) -> Self::RestartFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.restart(&id)),
+        )
+    }
+
+    fn kill(&self, id: &str, signal: &str) -> Self::KillFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        let signal = signal.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.kill(&id, &signal)),
+        )
+    }
+
+    fn remove(&self, id: &str) -> Self::RemoveFuture {
+        let engines = self.engines.clone();
+        let placements = self.placements.clone();
+        let id = id.to_string();
+        Box::new(self.locate(&id).and_then(move |index| {
+            engines[index].runtime.remove(&id).map(move |_| {
+                placements
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .remove(&id);
+            })
+        }))
+    }
+
+    /// The system info of the primary engine.
+    fn system_info(&self) -> Self::SystemInfoFuture {
+        Box::new(self.engines[0].runtime.system_info())
+    }
+
+    fn refresh_system_info(&self) -> Self::SystemInfoFuture {
+        Box::new(self.engines[0].runtime.refresh_system_info())
+    }
+
+    fn list(&self) -> Self::ListFuture {
+        let engines = self.engines.clone();
+        let placements = self.placements.clone();
+        Box::new(self.each_engine(M::list).and_then(move |results| {
+            let lists = reachable(results, "list the modules")?;
+            let mut placements = placements.lock().unwrap_or_else(|err| err.into_inner());
+            let mut modules = vec![];
+            for (index, list) in lists {
+                for module in list {
+                    placements.insert(module.name().to_string(), index);
+                    modules.push(EngineModule::new(engines[index].name.clone(), module));
+                }
+            }
+            Ok(modules)
+        }))
+    }
+
+    /// Lists the modules of every engine with their state. The modules of an
+    /// engine are only listed once the engine listed all of them, so that an
+    /// engine that fails part way leaves none of its modules in the list.
+    fn list_with_details(&self) -> Self::ListWithDetailsStream {
+        let engines = self.engines.clone();
+        let details = self
+            .each_engine(|engine| engine.list_with_details().collect())
+            .and_then(move |results| {
+                let lists = reachable(results, "list the modules")?;
+                let modules = lists.into_iter().flat_map(move |(index, list)| {
+                    let name = engines[index].name.clone();
+                    list.into_iter().map(move |(module, state)| {
+                        (EngineModule::new(name.clone(), module), state)
+                    })
+                });
+                Ok(stream::iter_ok(modules.collect::<Vec<_>>()))
+            });
+        Box::new(details.flatten_stream())
+    }
+
+    fn logs(&self, id: &str, options: &LogOptions) -> Self::LogsFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        let options = options.clone();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.logs(&id, &options)),
+        )
+    }
+
+    fn registry(&self) -> &Self::ModuleRegistry {
+        self
+    }
+
+    /// Removes the modules of every engine. An engine that fails doesn't
+    /// keep the others from removing theirs.
+    fn remove_all(&self) -> Self::RemoveAllFuture {
+        let placements = self.placements.clone();
+        Box::new(self.each_engine(M::remove_all).and_then(move |results| {
+            placements
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clear();
+            everywhere(results)
+        }))
+    }
+
+    /// The discrepancies of every engine that could be checked.
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+        Box::new(self.each_engine(M::check_integrity).and_then(|results| {
+            let reports = reachable(results, "check the integrity of the modules")?;
+            let rebuilt = reports.iter().any(|(_, report)| report.rebuilt());
+            let discrepancies = reports
+                .into_iter()
+                .flat_map(|(_, report)| report.discrepancies().to_vec())
+                .collect();
+            Ok(IntegrityReport::new(discrepancies).with_rebuilt(rebuilt))
+        }))
+    }
+
+    fn inspect(&self, id: &str) -> Self::InspectFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.inspect(&id)),
+        )
+    }
+
+    /// Stops the modules of every engine together, in the order of their
+    /// stop priorities.
+    fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture {
+        Box::new(stop_in_order(self, options))
+    }
+
+    /// Updates a module on the engine it names. A module that moves to
+    /// another engine is removed from the one it was on and created again.
+    fn update(&self, module: ModuleSpec<Self::Config>, start: bool) -> Self::UpdateFuture {
+        let target = match self.engine_named(module.runtime()) {
+            Ok(index) => index,
+            Err(err) => return Box::new(future::err(err)),
+        };
+        let this = self.clone();
+        let name = module.name().to_string();
+        Box::new(self.locate(&name).and_then(move |current| {
+            if current == target {
+                return future::Either::A(this.engines[target].runtime.update(module, start));
+            }
+
+            info!(
+                "Moving module {} from container engine {} to {}",
+                name, this.engines[current].name, this.engines[target].name
+            );
+            let created = this.engines[current]
+                .runtime
+                .remove(&name)
+                .or_else(|err| match err.kind() {
+                    ErrorKind::NotFound(_) => Ok(()),
+                    _ => Err(err),
+                }).and_then(move |_| this.create(module).map(move |_| this))
+                .and_then(move |this| {
+                    if start {
+                        future::Either::A(this.start(&name))
+                    } else {
+                        future::Either::B(future::ok(()))
+                    }
+                });
+            future::Either::B(created)
+        }))
+    }
+
+    fn top(&self, id: &str) -> Self::TopFuture {
+        let engines = self.engines.clone();
+        let id = id.to_string();
+        Box::new(
+            self.locate(&id)
+                .and_then(move |index| engines[index].runtime.top(&id)),
+        )
+    }
+
+    /// The engines are expected to share the epochs, so the primary engine's
+    /// stand for all of them.
+    fn epochs(&self) -> Option<&ModuleEpochs> {
+        self.engines[0].runtime.epochs()
+    }
+
+    /// Likewise for the busy flag, which any engine creating or removing a
+    /// module sets.
+    fn busy(&self) -> Option<&BusyFlag> {
+        self.engines[0].runtime.busy()
+    }
+}
+
+/// A module of one of the engines of a `MultiDockerModuleRuntime`, with the
+/// name of that engine.
+pub struct EngineModule<T> {
+    engine: String,
+    module: T,
+}
+
+impl<T> EngineModule<T> {
+    pub fn new(engine: String, module: T) -> Self {
+        EngineModule { engine, module }
+    }
+
+    /// The name of the engine the module runs on.
+    pub fn engine(&self) -> &str {
+        &self.engine
+    }
+
+    pub fn module(&self) -> &T {
+        &self.module
+    }
+}
+
+impl<T: Module> Module for EngineModule<T> {
+    type Config = T::Config;
+    type Error = T::Error;
+    type RuntimeStateFuture = T::RuntimeStateFuture;
+
+    fn name(&self) -> &str {
+        self.module.name()
+    }
+
+    fn type_(&self) -> &str {
+        self.module.type_()
+    }
+
+    fn config(&self) -> &Self::Config {
+        self.module.config()
+    }
+
+    fn runtime_state(&self) -> Self::RuntimeStateFuture {
+        self.module.runtime_state()
+    }
+
+    fn volumes(&self) -> Vec<String> {
+        self.module.volumes()
+    }
+
+    fn stop_priority(&self) -> Option<i64> {
+        self.module.stop_priority()
+    }
+
+    fn image_id(&self) -> Option<&str> {
+        self.module.image_id()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use futures::future::FutureResult;
+
+    type Calls = Arc<Mutex<Vec<String>>>;
+
+    struct TestModule {
+        name: String,
+    }
+
+    impl Module for TestModule {
+        type Config = String;
+        type Error = Error;
+        type RuntimeStateFuture = FutureResult<ModuleRuntimeState, Self::Error>;
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn type_(&self) -> &str {
+            "docker"
+        }
+
+        fn config(&self) -> &Self::Config {
+            &self.name
+        }
+
+        fn runtime_state(&self) -> Self::RuntimeStateFuture {
+            future::ok(ModuleRuntimeState::default())
+        }
+    }
+
+    /// An engine that has `modules`, and records the operations on them in
+    /// `calls` as "engine operation module". An engine that is down fails
+    /// every operation.
+    #[derive(Clone)]
+    struct TestEngine {
+        name: &'static str,
+        modules: Arc<Mutex<Vec<String>>>,
+        up: bool,
+        calls: Calls,
+    }
+
+    impl TestEngine {
+        fn new(name: &'static str, modules: &[&str], up: bool, calls: &Calls) -> Self {
+            TestEngine {
+                name,
+                modules: Arc::new(Mutex::new(
+                    modules.iter().map(ToString::to_string).collect(),
+                )),
+                up,
+                calls: calls.clone(),
+            }
+        }
+
+        fn call(&self, operation: &str, id: &str) -> Result<()> {
+            if !self.up {
+                return Err(Error::from(ErrorKind::Transport));
+            }
+
+            let mut modules = self.modules.lock().unwrap();
+            match operation {
+                "create" => modules.push(id.to_string()),
+                "init" | "pull" | "remove_all" => (),
+                _ if !modules.iter().any(|module| module == id) => {
+                    return Err(Error::from(ErrorKind::NotFound(id.to_string())))
+                }
+                "remove" => modules.retain(|module| module != id),
+                _ => (),
+            }
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} {} {}", self.name, operation, id));
+            Ok(())
+        }
+
+        fn modules(&self) -> Result<Vec<TestModule>> {
+            if self.up {
+                Ok(self
+                    .modules
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|name| TestModule { name: name.clone() })
+                    .collect())
+            } else {
+                Err(Error::from(ErrorKind::Transport))
+            }
+        }
+    }
+
+    impl ModuleRegistry for TestEngine {
+        type Error = Error;
+        type PullFuture = FutureResult<(), Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type Config = String;
+
+        fn pull(&self, config: &Self::Config) -> Self::PullFuture {
+            future::result(self.call("pull", config))
+        }
+
+        fn remove(&self, _name: &str) -> Self::RemoveFuture {
+            unimplemented!()
+        }
+    }
+
+    impl ModuleRuntime for TestEngine {
+        type Error = Error;
+        type Config = String;
+        type Module = TestModule;
+        type ModuleRegistry = Self;
+        type Chunk = String;
+        type Logs = stream::Empty<String, Self::Error>;
+
+        type CreateFuture = FutureResult<(), Self::Error>;
+        type InitFuture = FutureResult<(), Self::Error>;
+        type ListFuture = FutureResult<Vec<Self::Module>, Self::Error>;
+        type ListWithDetailsStream =
+            Box<Stream<Item = (Self::Module, ModuleRuntimeState), Error = Self::Error> + Send>;
+        type LogsFuture = FutureResult<Self::Logs, Self::Error>;
+        type RemoveFuture = FutureResult<(), Self::Error>;
+        type RestartFuture = FutureResult<(), Self::Error>;
+        type StartFuture = FutureResult<(), Self::Error>;
+        type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
+        type SystemInfoFuture = FutureResult<CoreSystemInfo, Self::Error>;
+        type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = FutureResult<(), Self::Error>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
+
+        fn init(&self) -> Self::InitFuture {
+            future::result(self.call("init", ""))
+        }
+
+        fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
+            future::result(self.call("create", module.name()))
+        }
+
+        fn start(&self, id: &str) -> Self::StartFuture {
+            future::result(self.call("start", id))
+        }
+
+        fn stop(&self, id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
+            future::result(self.call("stop", id))
+        }
+
+        fn restart(&self, id: &str) -> Self::RestartFuture {
+            future::result(self.call("restart", id))
+        }
+
+        fn kill(&self, id: &str, _signal: &str) -> Self::KillFuture {
+            future::result(self.call("kill", id))
+        }
+
+        fn remove(&self, id: &str) -> Self::RemoveFuture {
+            future::result(self.call("remove", id))
+        }
+
+        fn system_info(&self) -> Self::SystemInfoFuture {
+            unimplemented!()
+        }
+
+        fn list(&self) -> Self::ListFuture {
+            future::result(self.modules())
+        }
+
+        fn list_with_details(&self) -> Self::ListWithDetailsStream {
+            let details = self.modules().map(|modules| {
+                modules
+                    .into_iter()
+                    .map(|module| (module, ModuleRuntimeState::default()))
+                    .collect::<Vec<_>>()
+            });
+            Box::new(
+                future::result(details)
+                    .map(stream::iter_ok)
+                    .flatten_stream(),
+            )
+        }
+
+        fn logs(&self, id: &str, _options: &LogOptions) -> Self::LogsFuture {
+            future::result(self.call("logs", id).map(|_| stream::empty()))
+        }
+
+        fn registry(&self) -> &Self::ModuleRegistry {
+            self
+        }
+
+        fn remove_all(&self) -> Self::RemoveAllFuture {
+            future::result(self.call("remove_all", ""))
+        }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            unimplemented!()
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            unimplemented!()
+        }
+
+        fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+            unimplemented!()
+        }
+
+        fn update(&self, module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            future::result(self.call("update", module.name()))
+        }
+
+        fn top(&self, id: &str) -> Self::TopFuture {
+            future::result(self.call("top", id).map(|_| vec![]))
+        }
+    }
+
+    /// The edge runtime modules on the primary engine and a customer module
+    /// on the sandbox engine.
+    fn runtime(sandbox_up: bool) -> (MultiDockerModuleRuntime<TestEngine>, Calls) {
+        let calls = Calls::default();
+        let primary = TestEngine::new(DEFAULT_ENGINE, &["edgeAgent", "edgeHub"], true, &calls);
+        let sandbox = TestEngine::new("sandbox", &["opcua"], sandbox_up, &calls);
+        let runtime =
+            MultiDockerModuleRuntime::new(primary).with_engine("sandbox".to_string(), sandbox);
+        (runtime, calls)
+    }
+
+    fn spec(name: &str, runtime: Option<&str>) -> ModuleSpec<String> {
+        ModuleSpec::new(name, "docker", name.to_string(), HashMap::new())
+            .unwrap()
+            .with_runtime(runtime.map(ToOwned::to_owned))
+    }
+
+    fn calls(calls: &Calls) -> Vec<String> {
+        calls.lock().unwrap().drain(..).collect()
+    }
+
+    #[test]
+    fn modules_are_created_on_the_engine_they_name() {
+        let (runtime, recorded) = runtime(true);
+
+        runtime.create(spec("tempSensor", None)).wait().unwrap();
+        runtime
+            .create(spec("filter", Some("sandbox")))
+            .wait()
+            .unwrap();
+        runtime
+            .create(spec("edgeHub", Some(DEFAULT_ENGINE)))
+            .wait()
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "default create tempSensor",
+                "sandbox create filter",
+                "default create edgeHub",
+            ],
+            calls(&recorded)
+        );
+    }
+
+    #[test]
+    fn unknown_engines_are_rejected() {
+        let (runtime, recorded) = runtime(true);
+
+        let err = runtime
+            .create(spec("filter", Some("gvisor")))
+            .wait()
+            .unwrap_err();
+
+        assert_eq!("Container engine gvisor is not configured", err.to_string());
+        assert!(calls(&recorded).is_empty());
+    }
+
+    #[test]
+    fn operations_go_to_the_engine_of_the_module() {
+        let (runtime, recorded) = runtime(true);
+
+        runtime.start("opcua").wait().unwrap();
+        runtime.stop("edgeHub", None).wait().unwrap();
+        runtime.restart("opcua").wait().unwrap();
+        runtime.logs("opcua", &LogOptions::new()).wait().unwrap();
+        ModuleRuntime::remove(&runtime, "opcua").wait().unwrap();
+
+        assert_eq!(
+            vec![
+                "sandbox start opcua",
+                "default stop edgeHub",
+                "sandbox restart opcua",
+                "sandbox logs opcua",
+                "sandbox remove opcua",
+            ],
+            calls(&recorded)
+        );
+    }
+
+    #[test]
+    fn missing_modules_are_reported_by_the_primary_engine() {
+        let (runtime, _) = runtime(true);
+
+        let err = runtime.start("nope").wait().unwrap_err();
+
+        match err.kind() {
+            ErrorKind::NotFound(id) => assert_eq!("nope", id),
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn pulls_go_to_the_engine_the_module_names() {
+        let (runtime, recorded) = runtime(true);
+
+        runtime
+            .registry()
+            .pull_module(&spec("filter", Some("sandbox")))
+            .wait()
+            .unwrap();
+        runtime
+            .registry()
+            .pull_module(&spec("tempSensor", None))
+            .wait()
+            .unwrap();
+
+        assert_eq!(
+            vec!["sandbox pull filter", "default pull tempSensor"],
+            calls(&recorded)
+        );
+    }
+
+    #[test]
+    fn modules_of_every_engine_are_listed() {
+        let (runtime, _) = runtime(true);
+
+        let mut modules: Vec<_> = runtime
+            .list()
+            .wait()
+            .unwrap()
+            .iter()
+            .map(|module| format!("{} {}", module.engine(), module.name()))
+            .collect();
+        modules.sort();
+        let mut details: Vec<_> = runtime
+            .list_with_details()
+            .collect()
+            .wait()
+            .unwrap()
+            .iter()
+            .map(|(module, _)| format!("{} {}", module.engine(), module.name()))
+            .collect();
+        details.sort();
+
+        let expected = vec!["default edgeAgent", "default edgeHub", "sandbox opcua"];
+        assert_eq!(expected, modules);
+        assert_eq!(expected, details);
+    }
+
+    #[test]
+    fn engines_that_are_down_only_hide_their_own_modules() {
+        let (runtime, recorded) = runtime(false);
+
+        let modules: Vec<_> = runtime
+            .list()
+            .wait()
+            .unwrap()
+            .iter()
+            .map(|module| module.name().to_string())
+            .collect();
+        let details = runtime.list_with_details().collect().wait().unwrap();
+        runtime.init().wait().unwrap();
+        runtime.start("edgeHub").wait().unwrap();
+        let err = runtime
+            .create(spec("filter", Some("sandbox")))
+            .wait()
+            .unwrap_err();
+
+        assert_eq!(vec!["edgeAgent", "edgeHub"], modules);
+        assert_eq!(2, details.len());
+        assert_eq!("Transport error", err.to_string());
+        assert_eq!(
+            vec!["default init ", "default start edgeHub"],
+            calls(&recorded)
+        );
+    }
+
+    #[test]
+    fn listing_fails_when_every_engine_is_down() {
+        let primary = TestEngine::new(DEFAULT_ENGINE, &["edgeAgent"], false, &Calls::default());
+        let runtime = MultiDockerModuleRuntime::new(primary);
+
+        assert!(runtime.list().wait().is_err());
+        assert!(runtime.list_with_details().collect().wait().is_err());
+        assert!(runtime.init().wait().is_err());
+    }
+
+    #[test]
+    fn remove_all_reaches_every_engine_even_when_one_fails() {
+        let (runtime, recorded) = runtime(false);
+
+        assert!(runtime.remove_all().wait().is_err());
+        assert_eq!(vec!["default remove_all "], calls(&recorded));
+    }
+
+    #[test]
+    fn modules_that_change_engine_are_moved() {
+        let (runtime, recorded) = runtime(true);
+
+        runtime
+            .update(spec("edgeHub", Some(DEFAULT_ENGINE)), true)
+            .wait()
+            .unwrap();
+        runtime.update(spec("opcua", None), true).wait().unwrap();
+
+        assert_eq!(
+            vec![
+                "default update edgeHub",
+                "sandbox remove opcua",
+                "default create opcua",
+                "default start opcua",
+            ],
+            calls(&recorded)
+        );
+    }
+}
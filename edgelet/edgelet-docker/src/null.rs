@@ -0,0 +1,546 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env::consts::{ARCH, OS};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::{future, stream, Future, Stream};
+use hyper::Body;
+use serde_json;
+
+use edgelet_core::pid::Pid;
+use edgelet_core::{
+    stop_in_order, IntegrityReport, LogOptions, Module, ModuleInspection, ModuleRegistry,
+    ModuleRuntime, ModuleRuntimeState, ModuleSpec, ModuleStatus, StopAllOptions,
+    SystemInfo as CoreSystemInfo,
+};
+
+use config::DockerConfig;
+use error::{Error, ErrorKind};
+use module::MODULE_TYPE as DOCKER_MODULE_TYPE;
+use runtime::Logs;
+
+/// The runtime version `NullModuleRuntime` reports in its system info.
+pub const NULL_RUNTIME_VERSION: &str = "none";
+
+/// What the logs of every module read, since nothing ever runs to write any.
+const LOG_MESSAGE: &str = "This module is not run by a container runtime, and has no logs.\n";
+
+/// A module runtime that keeps the modules it is asked to create in memory
+/// instead of running them. Modules go through the same states they would in
+/// docker, and the runtime fails the same requests docker would fail, so that
+/// the management API and the edge agent behave as they do on a device. It is
+/// meant for CI and device simulators, which have no container runtime.
+#[derive(Clone, Default)]
+pub struct NullModuleRuntime {
+    modules: Arc<Mutex<BTreeMap<String, NullModule>>>,
+    images: Arc<Mutex<BTreeSet<String>>>,
+}
+
+/// A module of `NullModuleRuntime`, with the state it was in when it was
+/// listed.
+#[derive(Clone, Debug)]
+pub struct NullModule {
+    name: String,
+    type_: String,
+    config: DockerConfig,
+    env: BTreeMap<String, String>,
+    state: ModuleRuntimeState,
+}
+
+impl NullModule {
+    fn new(spec: ModuleSpec<DockerConfig>) -> Self {
+        NullModule {
+            name: spec.name().to_string(),
+            type_: spec.type_().to_string(),
+            config: spec.config().clone(),
+            env: spec
+                .env()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            state: ModuleRuntimeState::default().with_status(ModuleStatus::Stopped),
+        }
+    }
+
+    fn start(&mut self) {
+        self.state = ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Running)
+            .with_started_at(Some(Utc::now()))
+            .with_image_id(self.config.image_id().map(ToString::to_string));
+    }
+
+    fn stop(&mut self) {
+        self.state = self
+            .state
+            .clone()
+            .with_status(ModuleStatus::Stopped)
+            .with_exit_code(Some(0))
+            .with_finished_at(Some(Utc::now()));
+    }
+
+    fn is_running(&self) -> bool {
+        *self.state.status() == ModuleStatus::Running
+    }
+}
+
+impl Module for NullModule {
+    type Config = DockerConfig;
+    type Error = Error;
+    type RuntimeStateFuture = future::FutureResult<ModuleRuntimeState, Self::Error>;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn type_(&self) -> &str {
+        &self.type_
+    }
+
+    fn config(&self) -> &Self::Config {
+        &self.config
+    }
+
+    fn runtime_state(&self) -> Self::RuntimeStateFuture {
+        future::ok(self.state.clone())
+    }
+}
+
+impl NullModuleRuntime {
+    pub fn new() -> Self {
+        NullModuleRuntime::default()
+    }
+
+    /// Runs `f` on the module called `id`, failing the way docker does when
+    /// there is no such container.
+    fn with_module<T, F>(&self, id: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut NullModule) -> Result<T, Error>,
+    {
+        let mut modules = self.modules.lock().expect("modules lock poisoned");
+        match modules.get_mut(id) {
+            Some(module) => f(module),
+            None => Err(not_found(id)),
+        }
+    }
+
+    fn modules(&self) -> Vec<NullModule> {
+        self.modules
+            .lock()
+            .expect("modules lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+fn not_found(id: &str) -> Error {
+    Error::from(ErrorKind::NotFound(format!("No such container: {}", id)))
+}
+
+/// Frames `message` as a stdout message, the way docker streams logs.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+fn log_frame(message: &str) -> Vec<u8> {
+    let len = message.len() as u32;
+    let mut frame = vec![
+        1,
+        0,
+        0,
+        0,
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8,
+    ];
+    frame.extend_from_slice(message.as_bytes());
+    frame
+}
+
+impl ModuleRegistry for NullModuleRuntime {
+    type Error = Error;
+    type PullFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type RemoveFuture = Box<Future<Item = (), Error = Self::Error>>;
+    type Config = DockerConfig;
+
+    fn pull(&self, config: &Self::Config) -> Self::PullFuture {
+        self.images
+            .lock()
+            .expect("images lock poisoned")
+            .insert(config.image().to_string());
+        Box::new(future::ok(()))
+    }
+
+    fn remove(&self, name: &str) -> Self::RemoveFuture {
+        let removed = self
+            .images
+            .lock()
+            .expect("images lock poisoned")
+            .remove(name);
+        if removed {
+            Box::new(future::ok(()))
+        } else {
+            Box::new(future::err(Error::from(ErrorKind::NotFound(format!(
+                "No such image: {}",
+                name
+            )))))
+        }
+    }
+}
+
+impl ModuleRuntime for NullModuleRuntime {
+    type Error = Error;
+    type Config = DockerConfig;
+    type Module = NullModule;
+    type ModuleRegistry = Self;
+    type Chunk = <Logs as Stream>::Item;
+    type Logs = Logs;
+
+    type CreateFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type InitFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type ListFuture = Box<Future<Item = Vec<Self::Module>, Error = Self::Error> + Send>;
+    type ListWithDetailsStream =
+        Box<Stream<Item = (Self::Module, ModuleRuntimeState), Error = Self::Error> + Send>;
+    type LogsFuture = Box<Future<Item = Self::Logs, Error = Self::Error> + Send>;
+    type RemoveFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type RestartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type TopFuture = Box<Future<Item = Vec<Pid>, Error = Self::Error> + Send>;
+    type SystemInfoFuture = Box<Future<Item = CoreSystemInfo, Error = Self::Error> + Send>;
+    type RemoveAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type CheckIntegrityFuture = Box<Future<Item = IntegrityReport, Error = Self::Error> + Send>;
+    type InspectFuture = Box<Future<Item = ModuleInspection, Error = Self::Error> + Send>;
+    type KillFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type UpdateFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+
+    fn init(&self) -> Self::InitFuture {
+        info!("No container runtime is configured; modules will only be recorded");
+        Box::new(future::ok(()))
+    }
+
+    fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
+        fensure!(module.type_(), module.type_() == DOCKER_MODULE_TYPE);
+
+        let mut modules = self.modules.lock().expect("modules lock poisoned");
+        if modules.contains_key(module.name()) {
+            return Box::new(future::err(Error::from(ErrorKind::Conflict)));
+        }
+        debug!("Recording module {}", module.name());
+        modules.insert(module.name().to_string(), NullModule::new(module));
+        Box::new(future::ok(()))
+    }
+
+    fn start(&self, id: &str) -> Self::StartFuture {
+        Box::new(future::result(self.with_module(id, |module| {
+            if module.is_running() {
+                Err(Error::from(ErrorKind::NotModified))
+            } else {
+                module.start();
+                Ok(())
+            }
+        })))
+    }
+
+    fn stop(&self, id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
+        Box::new(future::result(self.with_module(id, |module| {
+            if module.is_running() {
+                module.stop();
+                Ok(())
+            } else {
+                Err(Error::from(ErrorKind::NotModified))
+            }
+        })))
+    }
+
+    fn restart(&self, id: &str) -> Self::RestartFuture {
+        Box::new(future::result(self.with_module(id, |module| {
+            module.start();
+            Ok(())
+        })))
+    }
+
+    /// There are no processes to signal, so only whether the module exists
+    /// matters.
+    fn kill(&self, id: &str, _signal: &str) -> Self::KillFuture {
+        Box::new(future::result(self.with_module(id, |_| Ok(()))))
+    }
+
+    fn remove(&self, id: &str) -> Self::RemoveFuture {
+        let removed = self
+            .modules
+            .lock()
+            .expect("modules lock poisoned")
+            .remove(id);
+        match removed {
+            Some(_) => Box::new(future::ok(())),
+            None => Box::new(future::err(not_found(id))),
+        }
+    }
+
+    fn system_info(&self) -> Self::SystemInfoFuture {
+        Box::new(future::ok(
+            CoreSystemInfo::new(OS.to_string(), ARCH.to_string())
+                .with_runtime_version(Some(NULL_RUNTIME_VERSION.to_string())),
+        ))
+    }
+
+    fn list(&self) -> Self::ListFuture {
+        Box::new(future::ok(self.modules()))
+    }
+
+    fn list_with_details(&self) -> Self::ListWithDetailsStream {
+        let modules = self.modules().into_iter().map(|module| {
+            let state = module.state.clone();
+            (module, state)
+        });
+        Box::new(stream::iter_ok(modules))
+    }
+
+    fn logs(&self, id: &str, options: &LogOptions) -> Self::LogsFuture {
+        Box::new(future::result(self.with_module(id, |_| {
+            let message = if options.timestamps() {
+                format!("{} {}", Utc::now().to_rfc3339(), LOG_MESSAGE)
+            } else {
+                LOG_MESSAGE.to_string()
+            };
+            Ok(Logs::from(Body::from(log_frame(&message))))
+        })))
+    }
+
+    fn registry(&self) -> &Self::ModuleRegistry {
+        self
+    }
+
+    fn remove_all(&self) -> Self::RemoveAllFuture {
+        self.modules.lock().expect("modules lock poisoned").clear();
+        Box::new(future::ok(()))
+    }
+
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+        Box::new(future::ok(IntegrityReport::new(vec![])))
+    }
+
+    fn inspect(&self, id: &str) -> Self::InspectFuture {
+        Box::new(future::result(self.with_module(id, |module| {
+            let create_options = serde_json::to_value(module.config.create_options())?;
+            Ok(
+                ModuleInspection::new(module.name.clone(), module.config.image().to_string())
+                    .with_image_id(module.config.image_id().map(ToString::to_string))
+                    .with_env(module.env.clone())
+                    .with_create_options(create_options),
+            )
+        })))
+    }
+
+    fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture {
+        Box::new(stop_in_order(self, options))
+    }
+
+    fn update(&self, module: ModuleSpec<Self::Config>, start: bool) -> Self::UpdateFuture {
+        fensure!(module.type_(), module.type_() == DOCKER_MODULE_TYPE);
+
+        let name = module.name().to_string();
+        Box::new(future::result(self.with_module(&name, |old| {
+            *old = NullModule::new(module);
+            if start {
+                old.start();
+            }
+            Ok(())
+        })))
+    }
+
+    /// Nothing runs in the modules.
+    fn top(&self, id: &str) -> Self::TopFuture {
+        Box::new(future::result(self.with_module(id, |_| Ok(vec![]))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use docker::models::ContainerCreateBody;
+
+    use logs::{LogLines, StdStream};
+
+    fn spec(name: &str, image: &str) -> ModuleSpec<DockerConfig> {
+        let mut env = HashMap::new();
+        env.insert("K1".to_string(), "V1".to_string());
+        ModuleSpec::new(
+            name,
+            DOCKER_MODULE_TYPE,
+            DockerConfig::new(image, ContainerCreateBody::new(), None).unwrap(),
+            env,
+        ).unwrap()
+    }
+
+    fn status(runtime: &NullModuleRuntime, name: &str) -> ModuleStatus {
+        runtime
+            .list_with_details()
+            .filter(|(module, _)| module.name() == name)
+            .map(|(_, state)| *state.status())
+            .collect()
+            .wait()
+            .unwrap()
+            .pop()
+            .unwrap()
+    }
+
+    fn assert_not_found(err: &Error, id: &str) {
+        match *err.kind() {
+            ErrorKind::NotFound(ref message) => {
+                assert_eq!(&format!("No such container: {}", id), message)
+            }
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn modules_go_through_docker_states() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+        assert_eq!(ModuleStatus::Stopped, status(&runtime, "m1"));
+
+        runtime.start("m1").wait().unwrap();
+        assert_eq!(ModuleStatus::Running, status(&runtime, "m1"));
+        let module = runtime.list().wait().unwrap().pop().unwrap();
+        let state = module.runtime_state().wait().unwrap();
+        assert!(state.started_at().is_some());
+        assert_eq!(None, state.exit_code());
+
+        runtime.stop("m1", None).wait().unwrap();
+        assert_eq!(ModuleStatus::Stopped, status(&runtime, "m1"));
+        let module = runtime.list().wait().unwrap().pop().unwrap();
+        let state = module.runtime_state().wait().unwrap();
+        assert_eq!(Some(0), state.exit_code());
+        assert!(state.finished_at().is_some());
+
+        runtime.restart("m1").wait().unwrap();
+        assert_eq!(ModuleStatus::Running, status(&runtime, "m1"));
+
+        ModuleRuntime::remove(&runtime, "m1").wait().unwrap();
+        assert!(runtime.list().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn requests_fail_like_docker() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+
+        match *runtime.create(spec("m1", "img:2")).wait().unwrap_err().kind() {
+            ErrorKind::Conflict => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        match *runtime.stop("m1", None).wait().unwrap_err().kind() {
+            ErrorKind::NotModified => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        runtime.start("m1").wait().unwrap();
+        match *runtime.start("m1").wait().unwrap_err().kind() {
+            ErrorKind::NotModified => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        for err in &[
+            runtime.start("m2").wait().unwrap_err(),
+            runtime.stop("m2", None).wait().unwrap_err(),
+            runtime.restart("m2").wait().unwrap_err(),
+            ModuleRuntime::remove(&runtime, "m2").wait().unwrap_err(),
+            runtime.inspect("m2").wait().unwrap_err(),
+            runtime.logs("m2", &LogOptions::new()).wait().unwrap_err(),
+        ] {
+            assert_not_found(err, "m2");
+        }
+    }
+
+    #[test]
+    fn create_rejects_other_module_types() {
+        let runtime = NullModuleRuntime::new();
+        let module = ModuleSpec::new(
+            "m1",
+            "process",
+            DockerConfig::new("img:1", ContainerCreateBody::new(), None).unwrap(),
+            HashMap::new(),
+        ).unwrap();
+
+        assert!(runtime.create(module).wait().is_err());
+        assert!(runtime.list().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_replaces_the_module() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+        runtime.start("m1").wait().unwrap();
+
+        runtime.update(spec("m1", "img:2"), false).wait().unwrap();
+        assert_eq!(ModuleStatus::Stopped, status(&runtime, "m1"));
+        assert_eq!("img:2", runtime.inspect("m1").wait().unwrap().image());
+
+        runtime.update(spec("m1", "img:3"), true).wait().unwrap();
+        assert_eq!(ModuleStatus::Running, status(&runtime, "m1"));
+
+        assert!(runtime.update(spec("m2", "img:1"), true).wait().is_err());
+    }
+
+    #[test]
+    fn inspect_reports_the_spec() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+
+        let inspection = runtime.inspect("m1").wait().unwrap();
+        assert_eq!("m1", inspection.name());
+        assert_eq!("img:1", inspection.image());
+        assert_eq!(Some(&"V1".to_string()), inspection.env().get("K1"));
+    }
+
+    #[test]
+    fn logs_are_framed_like_docker() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+
+        let options = LogOptions::new().with_timestamps(true);
+        let logs = runtime.logs("m1", &options).wait().unwrap();
+        let lines = LogLines::new(logs, true).collect().wait().unwrap();
+
+        assert_eq!(1, lines.len());
+        assert_eq!(StdStream::Stdout, lines[0].stream());
+        assert!(lines[0].timestamp().is_some());
+        assert_eq!(LOG_MESSAGE.trim_right().as_bytes(), &lines[0].text()[..]);
+    }
+
+    #[test]
+    fn stop_all_stops_running_modules() {
+        let runtime = NullModuleRuntime::new();
+        runtime.create(spec("m1", "img:1")).wait().unwrap();
+        runtime.create(spec("m2", "img:1")).wait().unwrap();
+        runtime.start("m1").wait().unwrap();
+
+        runtime.stop_all(&StopAllOptions::default()).wait().unwrap();
+
+        assert_eq!(ModuleStatus::Stopped, status(&runtime, "m1"));
+        assert_eq!(ModuleStatus::Stopped, status(&runtime, "m2"));
+    }
+
+    #[test]
+    fn registry_records_pulled_images() {
+        let runtime = NullModuleRuntime::new();
+        let config = DockerConfig::new("img:1", ContainerCreateBody::new(), None).unwrap();
+
+        runtime.registry().pull(&config).wait().unwrap();
+        ModuleRegistry::remove(runtime.registry(), "img:1").wait().unwrap();
+        assert!(ModuleRegistry::remove(runtime.registry(), "img:1").wait().is_err());
+    }
+
+    #[test]
+    fn system_info_describes_the_host() {
+        let info = NullModuleRuntime::new().system_info().wait().unwrap();
+        assert_eq!(OS, info.os_type());
+        assert_eq!(ARCH, info.architecture());
+        assert_eq!(Some(NULL_RUNTIME_VERSION), info.runtime_version());
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Creating a module takes several calls to docker, like creating its volumes
+//! and then its container. When one of them fails, the objects the calls
+//! before it created would be left behind. A `Plan` runs such steps in order
+//! and, when one fails, undoes the steps that succeeded in reverse order.
+
+use futures::future::{self, IntoFuture};
+use futures::{stream, Future, Stream};
+use log::Level;
+
+use edgelet_utils::log_failure;
+
+use error::Error;
+
+/// How to undo a step that succeeded, like removing the container it
+/// created.
+pub struct Undo {
+    description: String,
+    action: Box<Future<Item = (), Error = Error> + Send>,
+}
+
+impl Undo {
+    /// `action` is only called if the plan is rolled back.
+    pub fn new<F, U>(description: String, action: F) -> Self
+    where
+        F: FnOnce() -> U + Send + 'static,
+        U: IntoFuture<Item = (), Error = Error>,
+        U::Future: Send + 'static,
+    {
+        Undo {
+            description,
+            action: Box::new(future::lazy(action)),
+        }
+    }
+}
+
+struct Step {
+    description: String,
+    run: Box<Future<Item = Option<Undo>, Error = Error> + Send>,
+}
+
+/// Steps that are run one after another, each once the ones before it
+/// succeeded.
+#[derive(Default)]
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+impl Plan {
+    pub fn new() -> Self {
+        Plan::default()
+    }
+
+    /// Adds `step`, which is called once the steps before it succeeded. It
+    /// says how to undo what it did, if anything.
+    pub fn then<F, S>(mut self, description: String, step: F) -> Self
+    where
+        F: FnOnce() -> S + Send + 'static,
+        S: IntoFuture<Item = Option<Undo>, Error = Error>,
+        S::Future: Send + 'static,
+    {
+        self.steps.push(Step {
+            description,
+            run: Box::new(future::lazy(step)),
+        });
+        self
+    }
+
+    /// Runs the steps. When one fails, the steps before it are undone and
+    /// its error is returned. Undoing them can fail too, which is logged
+    /// without replacing the error of the step.
+    pub fn execute(self) -> impl Future<Item = (), Error = Error> + Send {
+        stream::iter_ok::<_, (Error, Vec<Undo>)>(self.steps)
+            .fold(vec![], |mut undos, step| {
+                debug!("{}", step.description);
+                let description = step.description;
+                step.run.then(move |result| match result {
+                    Ok(undo) => {
+                        undos.extend(undo);
+                        Ok(undos)
+                    }
+                    Err(err) => {
+                        warn!("Could not {}, undoing the steps before it.", description);
+                        Err((err, undos))
+                    }
+                })
+            }).map(|_| ())
+            .or_else(|(err, undos)| rollback(undos).then(move |_| Err(err)))
+    }
+}
+
+fn rollback(undos: Vec<Undo>) -> impl Future<Item = (), Error = ()> + Send {
+    stream::iter_ok(undos.into_iter().rev()).for_each(|undo| {
+        let description = undo.description;
+        debug!("{}", description);
+        undo.action.then(move |result| {
+            if let Err(err) = result {
+                warn!("Could not {}.", description);
+                log_failure(Level::Warn, &err);
+            }
+            Ok(())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use error::ErrorKind;
+
+    use super::*;
+
+    /// Runs steps named by `outcomes` that succeed or fail as scripted, with
+    /// undos that succeed or fail as scripted too, or that have nothing to
+    /// undo. Returns the outcome of the plan and what was run, in order.
+    fn run(outcomes: &[(&'static str, bool, Option<bool>)]) -> (Result<(), Error>, Vec<String>) {
+        let log = Arc::new(Mutex::new(vec![]));
+        let plan = outcomes
+            .iter()
+            .fold(Plan::new(), |plan, &(name, succeeds, undo)| {
+                let log = log.clone();
+                plan.then(format!("run {}", name), move || {
+                    log.lock().unwrap().push(format!("run {}", name));
+                    if !succeeds {
+                        return Err(Error::from(ErrorKind::NotFound(name.to_string())));
+                    }
+                    Ok(undo.map(|undo_succeeds| {
+                        Undo::new(format!("undo {}", name), move || {
+                            log.lock().unwrap().push(format!("undo {}", name));
+                            if undo_succeeds {
+                                Ok(())
+                            } else {
+                                Err(Error::from(ErrorKind::Conflict))
+                            }
+                        })
+                    }))
+                })
+            });
+
+        let result = plan.execute().wait();
+        let log = log.lock().unwrap().clone();
+        (result, log)
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let (result, log) = run(&[
+            ("volume", true, Some(true)),
+            ("container", true, Some(true)),
+            ("record", true, None),
+        ]);
+
+        result.unwrap();
+        assert_eq!(vec!["run volume", "run container", "run record"], log);
+    }
+
+    #[test]
+    fn failed_steps_undo_the_steps_before_them_in_reverse() {
+        let (result, log) = run(&[
+            ("volume", true, Some(true)),
+            ("network", true, Some(true)),
+            ("container", false, Some(true)),
+            ("record", true, None),
+        ]);
+
+        match *result.unwrap_err().kind() {
+            ErrorKind::NotFound(ref step) => assert_eq!("container", step),
+            ref kind => panic!("Expected the error of the failed step, got {:?}", kind),
+        }
+        assert_eq!(
+            vec![
+                "run volume",
+                "run network",
+                "run container",
+                "undo network",
+                "undo volume",
+            ],
+            log
+        );
+    }
+
+    #[test]
+    fn steps_with_nothing_to_undo_are_skipped() {
+        let (result, log) = run(&[
+            ("volume", true, None),
+            ("container", true, Some(true)),
+            ("start", false, None),
+        ]);
+
+        assert!(result.is_err());
+        assert_eq!(
+            vec!["run volume", "run container", "run start", "undo container"],
+            log
+        );
+    }
+
+    #[test]
+    fn failed_undos_do_not_hide_the_error() {
+        let (result, log) = run(&[
+            ("volume", true, Some(true)),
+            ("network", true, Some(false)),
+            ("container", false, None),
+        ]);
+
+        match *result.unwrap_err().kind() {
+            ErrorKind::NotFound(ref step) => assert_eq!("container", step),
+            ref kind => panic!("Expected the error of the failed step, got {:?}", kind),
+        }
+        // The rest is still undone.
+        assert_eq!(
+            vec![
+                "run volume",
+                "run network",
+                "run container",
+                "undo network",
+                "undo volume",
+            ],
+            log
+        );
+    }
+
+    #[test]
+    fn failed_first_steps_undo_nothing() {
+        let (result, log) = run(&[("volume", false, Some(true)), ("container", true, None)]);
+
+        assert!(result.is_err());
+        assert_eq!(vec!["run volume"], log);
+    }
+}
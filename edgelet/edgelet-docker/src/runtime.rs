@@ -1,32 +1,59 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::From;
-use std::ops::Deref;
-use std::time::Duration;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use base64;
+use bytes::{Buf, Bytes};
+use chrono::{TimeZone, Utc};
+use failure::{Fail, ResultExt};
 use futures::prelude::*;
 use futures::{future, stream, Async, Stream};
 use hyper::{Body, Chunk as HyperChunk, Client};
 use log::Level;
+use regex::Regex;
 use serde_json;
+use tokio::timer::Delay;
 use url::Url;
 
 use client::DockerClient;
-use config::DockerConfig;
+use config::{DockerConfig, RegistryAuth, VOLUME_NAME};
 use docker::apis::client::APIClient;
 use docker::apis::configuration::Configuration;
-use docker::models::{ContainerCreateBody, NetworkConfig};
+use docker::models::{
+    AuthConfig, ContainerCreateBody, ContainerCreateBodyNetworkingConfig, ContainerSummary,
+    EndpointSettings, HostConfig, ImageSummary, InlineResponse200, Mount, NetworkConfig,
+    VolumeConfig,
+};
+use edgelet_core::metrics::{
+    duration_secs, error_kind_label, RUNTIME_OPERATIONS, RUNTIME_OPERATION_DURATION,
+    RUNTIME_OPERATION_FAILURES, RUNTIME_SLOW_OPERATIONS,
+};
+use edgelet_core::pid::Pid;
 use edgelet_core::{
-    LogOptions, Module, ModuleRegistry, ModuleRuntime, ModuleRuntimeState, ModuleSpec,
+    stop_in_order, BusyFlag, Clock, ImageInfo, IntegrityReport, LogOptions, Metrics,
+    MetricsRegistry, Module, KillSignals, ModuleEpochs, ModuleInspection, ModuleRegistry,
+    ModuleRestarts, ModuleRuntime, ModuleRuntimeState, ModuleSpec, RegistryCredentials,
+    RuntimeErrorLog, RuntimeErrorRecord, StopAllOptions, SystemClock,
     SystemInfo as CoreSystemInfo,
 };
-use edgelet_http::UrlConnector;
-use edgelet_utils::log_failure;
+use edgelet_http::{TlsConfig, UrlConnector, DEFAULT_CONNECT_TIMEOUT_SECS};
+use edgelet_utils::{log_failure, Timed};
 
 use error::{Error, ErrorKind, Result};
-use module::{DockerModule, MODULE_TYPE as DOCKER_MODULE_TYPE};
+use filters::DockerFilters;
+use image::ImageReference;
+use mirror::{falls_back, RegistryMirror, RegistryMirrors};
+use module::{top, DockerModule, MODULE_TYPE as DOCKER_MODULE_TYPE};
+use plan::{Plan, Undo};
+use state::{ModuleRecord, ModuleStateStore};
+use system_info::{is_connection_error, SystemInfoCache, DEFAULT_SYSTEM_INFO_TTL_SECS};
 
 const WAIT_BEFORE_KILL_SECONDS: i32 = 10;
 
@@ -34,37 +61,100 @@ static LABEL_KEY: &str = "net.azure-devices.edge.owner";
 static LABEL_VALUE: &str = "Microsoft.Azure.Devices.Edge.Agent";
 
 lazy_static! {
-    static ref LABELS: Vec<&'static str> = {
-        let mut labels = vec![];
-        labels.push("net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent");
-        labels
-    };
+    /// The container and network names and ids docker accepts. Checking them up
+    /// front also keeps them from adding segments to the request path.
+    static ref CONTAINER_ID: Regex = Regex::new(r"^[a-zA-Z0-9][a-zA-Z0-9_.-]*$").unwrap();
 }
 
 #[derive(Clone)]
 pub struct DockerModuleRuntime {
     client: DockerClient<UrlConnector>,
     network_id: Option<String>,
+    restarts: ModuleRestarts,
+    metrics: Arc<Metrics>,
+    clock: Arc<Clock>,
+    slow_thresholds: HashMap<&'static str, Duration>,
+    runtime_errors: RuntimeErrorLog,
+    state: Option<ModuleStateStore>,
+    default_dns: Vec<String>,
+    default_extra_hosts: Vec<String>,
+    purge_volumes: bool,
+    prune_images: bool,
+    case_insensitive_env: bool,
+    credentials: Option<Arc<RegistryCredentials>>,
+    stop_options: StopAllOptions,
+    operation_timeout: Option<Duration>,
+    parent_hostname: Option<String>,
+    epochs: ModuleEpochs,
+    signals: KillSignals,
+    mirrors: RegistryMirrors,
+    system_info: SystemInfoCache,
+    system_info_ttl: Duration,
+    busy: BusyFlag,
 }
 
 impl DockerModuleRuntime {
     pub fn new(docker_url: &Url) -> Result<Self> {
+        DockerModuleRuntime::with_tls_config(docker_url, &TlsConfig::default())
+    }
+
+    pub fn with_tls_config(docker_url: &Url, tls: &TlsConfig) -> Result<Self> {
+        DockerModuleRuntime::with_timeouts(
+            docker_url,
+            tls,
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            None,
+        )
+    }
+
+    /// Requests that are expected to run long, like image pulls, following
+    /// logs or stopping a container, aren't subject to `request_timeout`.
+    pub fn with_timeouts(
+        docker_url: &Url,
+        tls: &TlsConfig,
+        connect_timeout: Duration,
+        request_timeout: Option<Duration>,
+    ) -> Result<Self> {
         // build the hyper client
-        let client = Client::builder().build(UrlConnector::new(docker_url)?);
+        let connector =
+            UrlConnector::with_tls_config(docker_url, tls)?.with_connect_timeout(connect_timeout);
+        let scheme = connector.scheme();
+        let client = Client::builder().build(connector);
 
         // extract base path - the bit that comes after the scheme
         let base_path = get_base_path(docker_url);
         let mut configuration = Configuration::new(client);
         configuration.base_path = base_path.to_string();
+        configuration.request_timeout = request_timeout;
 
-        let scheme = docker_url.scheme().to_string();
         configuration.uri_composer = Box::new(move |base_path, path| {
-            Ok(UrlConnector::build_hyper_uri(&scheme, base_path, path)?)
+            Ok(UrlConnector::build_hyper_uri(scheme, base_path, path)?)
         });
 
         Ok(DockerModuleRuntime {
             client: DockerClient::new(APIClient::new(configuration)),
             network_id: None,
+            restarts: ModuleRestarts::default(),
+            metrics: Arc::new(MetricsRegistry::new()),
+            clock: Arc::new(SystemClock),
+            slow_thresholds: HashMap::new(),
+            runtime_errors: RuntimeErrorLog::default(),
+            state: None,
+            default_dns: vec![],
+            default_extra_hosts: vec![],
+            purge_volumes: false,
+            prune_images: false,
+            case_insensitive_env: false,
+            credentials: None,
+            stop_options: StopAllOptions::default(),
+            operation_timeout: None,
+            parent_hostname: None,
+            epochs: ModuleEpochs::default(),
+            signals: KillSignals::default(),
+            mirrors: RegistryMirrors::default(),
+            system_info: SystemInfoCache::default(),
+            system_info_ttl: Duration::from_secs(DEFAULT_SYSTEM_INFO_TTL_SECS),
+            busy: BusyFlag::default(),
         })
     }
 
@@ -73,29 +163,792 @@ impl DockerModuleRuntime {
         self
     }
 
-    fn merge_env(cur_env: Option<&[String]>, new_env: &HashMap<String, String>) -> Vec<String> {
-        // build a new merged hashmap containing string slices for keys and values
-        // pointing into String instances in new_env
-        let mut merged_env = HashMap::new();
-        merged_env.extend(new_env.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    /// Sets the restart bookkeeping of the watchdog, so that `list_with_details`
+    /// reports the modules it is holding back.
+    pub fn with_restarts(mut self, restarts: ModuleRestarts) -> Self {
+        self.restarts = restarts;
+        self
+    }
+
+    pub fn restarts(&self) -> &ModuleRestarts {
+        &self.restarts
+    }
+
+    /// Sets where the outcome and duration of container operations are
+    /// recorded.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Sets the clock the duration of container operations is measured with.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets how long `operation`, such as `pull` or `create`, may take before
+    /// it is logged and counted as slow. `None` never counts it as slow.
+    pub fn with_slow_threshold(
+        mut self,
+        operation: &'static str,
+        threshold: Option<Duration>,
+    ) -> Self {
+        match threshold {
+            Some(threshold) => {
+                self.slow_thresholds.insert(operation, threshold);
+            }
+            None => {
+                self.slow_thresholds.remove(operation);
+            }
+        }
+        self
+    }
+
+    /// Sets where the container operations that fail are recorded for
+    /// diagnostics.
+    pub fn with_runtime_errors(mut self, runtime_errors: RuntimeErrorLog) -> Self {
+        self.runtime_errors = runtime_errors;
+        self
+    }
+
+    /// Sets where the modules the runtime creates are recorded, so that
+    /// `check_integrity` can compare them with the modules that exist.
+    pub fn with_state_store(mut self, state: ModuleStateStore) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Sets the DNS servers of the modules that don't set their own.
+    pub fn with_default_dns(mut self, default_dns: Vec<IpAddr>) -> Self {
+        self.default_dns = default_dns.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Sets the host entries, in the form `host:ip`, of the modules that don't
+    /// set their own.
+    pub fn with_default_extra_hosts(mut self, default_extra_hosts: Vec<String>) -> Self {
+        self.default_extra_hosts = default_extra_hosts;
+        self
+    }
+
+    /// Sets whether `remove_all` removes the volumes created for modules too,
+    /// and with them the data the modules kept.
+    pub fn with_purge_volumes(mut self, purge_volumes: bool) -> Self {
+        self.purge_volumes = purge_volumes;
+        self
+    }
+
+    /// Sets which signals `kill` sends to modules. Only the safe ones are
+    /// sent unless this opens them all up.
+    pub fn with_signals(mut self, signals: KillSignals) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Sets the mirrors images of their registries are pulled through. Images
+    /// are pulled from the registry itself when the mirror can't serve them.
+    pub fn with_registry_mirrors(mut self, mirrors: Vec<RegistryMirror>) -> Self {
+        self.mirrors = RegistryMirrors::new(mirrors);
+        self
+    }
+
+    /// Sets whether `purge` removes the images labeled as owned by the edge
+    /// runtime too.
+    pub fn with_prune_images(mut self, prune_images: bool) -> Self {
+        self.prune_images = prune_images;
+        self
+    }
+
+    /// Sets whether environment variable names that differ only in case are
+    /// the same variable, as they are in Windows containers.
+    pub fn with_case_insensitive_env(mut self, case_insensitive_env: bool) -> Self {
+        self.case_insensitive_env = case_insensitive_env;
+        self
+    }
+
+    /// Sets the order in which `remove_all` stops the modules before removing
+    /// them.
+    pub fn with_stop_options(mut self, stop_options: StopAllOptions) -> Self {
+        self.stop_options = stop_options;
+        self
+    }
+
+    /// Sets how long creating, starting, stopping, restarting or removing a
+    /// module, listing the modules or getting the system information may take
+    /// before it fails with `OperationTimedOut`, so that a hung daemon can't
+    /// hold up its callers forever. Stopping a module may take as long again
+    /// as the module is given to stop. Pulls, logs and other long running
+    /// operations have no deadline.
+    pub fn with_operation_timeout(mut self, operation_timeout: Option<Duration>) -> Self {
+        self.operation_timeout = operation_timeout;
+        self
+    }
+
+    /// Sets where the registry credentials that module configs refer to by
+    /// name are looked up when images are pulled.
+    pub fn with_registry_credentials(mut self, credentials: Arc<RegistryCredentials>) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sets the hostname of the parent device, which replaces the `$upstream`
+    /// registry of images on a device below another edge device.
+    pub fn with_parent_hostname(mut self, parent_hostname: Option<String>) -> Self {
+        self.parent_hostname = parent_hostname;
+        self
+    }
+
+    /// Sets how long `system_info` answers with what the container runtime
+    /// reported last before asking it again. Failing to reach the runtime
+    /// forgets it sooner, so that a daemon upgraded meanwhile is noticed.
+    pub fn with_system_info_ttl(mut self, ttl: Duration) -> Self {
+        self.system_info_ttl = ttl;
+        self
+    }
+
+    /// Sets the flag the runtime keeps set while it creates, updates or
+    /// removes a module, which image garbage collection waits on.
+    pub fn with_busy(mut self, busy: BusyFlag) -> Self {
+        self.busy = busy;
+        self
+    }
+
+    /// Sets the epochs the runtime bumps when it starts, stops, restarts or
+    /// removes a module, or sees that its process changed. Runtimes of
+    /// several container engines share them.
+    pub fn with_epochs(mut self, epochs: ModuleEpochs) -> Self {
+        self.epochs = epochs;
+        self
+    }
+
+    /// Bumps the epoch of the module once `operation` is done, whether it
+    /// succeeded or not, since a failed operation may still have changed the
+    /// processes of the module.
+    fn changing<F>(&self, id: &str, operation: F) -> impl Future<Item = F::Item, Error = F::Error>
+    where
+        F: Future,
+    {
+        let epochs = self.epochs.clone();
+        let id = id.to_string();
+        operation.then(move |result| {
+            epochs.bump(&id);
+            result
+        })
+    }
+
+    /// Keeps the busy flag set until `operation` finishes.
+    fn while_busy<F>(&self, operation: F) -> impl Future<Item = F::Item, Error = F::Error>
+    where
+        F: Future,
+    {
+        let guard = self.busy.set();
+        operation.then(move |result| {
+            drop(guard);
+            result
+        })
+    }
+
+    /// The image of `config` as it is pulled and created, with an `$upstream`
+    /// registry replaced by the hostname of the parent device.
+    fn upstream_image(&self, config: &DockerConfig) -> Result<ImageReference> {
+        let image = config.image_reference();
+        if !image.is_upstream() {
+            return Ok(image.clone());
+        }
+        let parent_hostname = self
+            .parent_hostname
+            .as_ref()
+            .ok_or_else(|| ErrorKind::NoParentHostname(config.image().to_string()))?;
+        let upstream = image.with_upstream(parent_hostname)?;
+        info!(
+            "Image {} is pulled from the parent device as {}",
+            config.image(),
+            upstream.as_str()
+        );
+        Ok(upstream)
+    }
+
+    /// The credentials to pull `image`, the image of `config`, with, encoded
+    /// the way docker takes them. A credential that is referred to by name is
+    /// looked up only now, so that it never has to be part of the config.
+    fn registry_auth(&self, config: &DockerConfig, image: &ImageReference) -> Result<String> {
+        let auth = match config.registry_auth() {
+            None => return Ok("".to_string()),
+            Some(&RegistryAuth::Inline(ref auth)) => auth.clone(),
+            Some(&RegistryAuth::RegistryCredentialRef(ref name)) => self.stored_auth(name)?,
+        };
+        if let Some(server) = auth.serveraddress() {
+            if !image.is_from(server) {
+                warn!(
+                    "Credentials for {} are used to pull {} from {}",
+                    server,
+                    image.as_str(),
+                    image.registry_or_default()
+                );
+            }
+        }
+        encode_auth(&auth)
+    }
+
+    /// The credentials to pull from the mirror `mirror` with, encoded the way
+    /// docker takes them. The mirror is never sent the credentials of the
+    /// registry it mirrors, only those configured for it, if any.
+    fn mirror_auth(&self, mirror: &RegistryMirror) -> Result<String> {
+        match mirror.credential() {
+            Some(name) => encode_auth(&self.stored_auth(name)?),
+            None => Ok("".to_string()),
+        }
+    }
+
+    /// The registry credential called `name`.
+    fn stored_auth(&self, name: &str) -> Result<AuthConfig> {
+        let credential = match self.credentials {
+            Some(ref credentials) => credentials.credential(name).context(ErrorKind::Core)?,
+            None => None,
+        };
+        let credential = credential.ok_or_else(|| {
+            ErrorKind::NotFound(format!("Registry credential {} not found", name))
+        })?;
+        Ok(AuthConfig::new()
+            .with_username(credential.username().to_string())
+            .with_password(credential.password().to_string())
+            .with_serveraddress(credential.server_address().to_string()))
+    }
 
-        if let Some(env) = cur_env {
-            // extend merged_env with variables in cur_env (again, these are
-            // only string slices pointing into strings inside cur_env)
-            merged_env.extend(env.iter().filter_map(|s| {
+    /// Pulls `source`, which is `image` or the reference to it on a mirror,
+    /// with `creds`, and records that `image` was pulled as `source`.
+    fn pull_image(
+        &self,
+        image: ImageReference,
+        source: ImageReference,
+        creds: Result<String>,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        let mirrors = self.mirrors.clone();
+        let response = creds
+            .map(|creds| {
+                debug!("Pulling {}", source);
+                self.client
+                    .image_api()
+                    .image_create(&source.to_string(), "", "", "", "", &creds, "")
+                    .map_err(|err| {
+                        let e = Error::from(err);
+                        warn!("Attempt to pull image failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    })
+            }).into_future()
+            .flatten()
+            .map(move |_| mirrors.record_pulled(&image, source));
+
+        Box::new(response)
+    }
+
+    /// Lists the names of the volumes created for modules.
+    pub fn list_volumes(&self) -> Box<Future<Item = Vec<String>, Error = Error> + Send> {
+        let result = owner_filters()
+            .map(|filters| {
+                self.client
+                    .volume_api()
+                    .volume_list(&filters)
+                    .map(|response| {
+                        response
+                            .volumes()
+                            .iter()
+                            .map(|volume| volume.name().to_string())
+                            .collect()
+                    })
+                    .map_err(Error::from)
+            }).into_future()
+            .flatten()
+            .map_err(|err| {
+                warn!("Attempt to list volumes failed.");
+                log_failure(Level::Warn, &err);
+                err
+            });
+        Box::new(result)
+    }
+
+    /// Removes the volume `name`. Unless `force` is set, a volume that is in
+    /// use is not removed.
+    pub fn remove_volume(
+        &self,
+        name: &str,
+        force: bool,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        debug!("Removing volume {}", name);
+        Box::new(
+            self.client
+                .volume_api()
+                .volume_delete(fensure_matches!(name, VOLUME_NAME, "name"), force)
+                .map_err(|err| {
+                    let e = Error::from(err);
+                    warn!("Attempt to remove a volume failed.");
+                    log_failure(Level::Warn, &e);
+                    e
+                }),
+        )
+    }
+
+    /// Removes what the runtime created, for when the daemon is uninstalled:
+    /// the modules as `remove_all` does, then the network the modules were
+    /// attached to and, if asked to prune images, the images labeled as owned
+    /// by the edge runtime. A network left behind keeps its subnet, which a
+    /// later install can conflict with.
+    pub fn purge(&self) -> Box<Future<Item = (), Error = Error> + Send> {
+        let self_for_network = self.clone();
+        let self_for_images = self.clone();
+        Box::new(
+            self.remove_all()
+                .and_then(move |_| self_for_network.remove_network())
+                .and_then(move |_| {
+                    if self_for_images.prune_images {
+                        future::Either::A(self_for_images.remove_images())
+                    } else {
+                        future::Either::B(future::ok(()))
+                    }
+                }),
+        )
+    }
+
+    /// Removes the network modules are attached to. A network that doesn't
+    /// exist is taken as removed. A network that containers are still
+    /// attached to isn't removed, and the error names the containers.
+    pub fn remove_network(&self) -> Box<Future<Item = (), Error = Error> + Send> {
+        let id = match self.network_id {
+            Some(ref id) => id.clone(),
+            None => return Box::new(future::ok(())),
+        };
+        debug!("Removing network {}", id);
+        let client = self.client.clone();
+        Box::new(
+            self.client
+                .network_api()
+                .network_delete(fensure_matches!(&id, CONTAINER_ID, "network"))
+                .map_err(Error::from)
+                .or_else(move |err| {
+                    let not_found = match *err.kind() {
+                        ErrorKind::NotFound(_) => true,
+                        _ => false,
+                    };
+                    // Docker only tells that the network has active endpoints,
+                    // so the containers are looked up to say which they are.
+                    let in_use = match *err.kind() {
+                        ErrorKind::FormattedDockerRuntime(ref message) => {
+                            message.contains("active endpoints")
+                        }
+                        _ => false,
+                    };
+                    if not_found {
+                        debug!("Network {} was already removed", id);
+                        future::Either::A(future::ok(()))
+                    } else if in_use {
+                        future::Either::B(future::Either::A(
+                            client
+                                .network_api()
+                                .network_inspect(&id, false, "")
+                                .map_err(Error::from)
+                                .and_then(move |network| {
+                                    let mut containers = network
+                                        .containers()
+                                        .map(|containers| {
+                                            containers
+                                                .iter()
+                                                .map(|(id, container)| {
+                                                    container.name().unwrap_or(id).to_string()
+                                                }).collect::<Vec<_>>()
+                                        }).unwrap_or_default();
+                                    containers.sort();
+                                    Err(Error::from(ErrorKind::NetworkInUse(
+                                        id,
+                                        containers.join(", "),
+                                    )))
+                                }),
+                        ))
+                    } else {
+                        future::Either::B(future::Either::B(future::err(err)))
+                    }
+                }).map_err(|err| {
+                    warn!("Attempt to remove the network failed.");
+                    log_failure(Level::Warn, &err);
+                    err
+                }),
+        )
+    }
+
+    /// Removes the images labeled as owned by the edge runtime.
+    fn remove_images(&self) -> impl Future<Item = (), Error = Error> {
+        let client = self.client.clone();
+        owner_filters()
+            .map(|filters| {
+                self.client
+                    .image_api()
+                    .image_list(false, &filters, false)
+                    .and_then(move |images| {
+                        let removes = images.into_iter().map(move |image| {
+                            debug!("Removing image {}", image.id());
+                            client
+                                .image_api()
+                                .image_delete(image.id(), false, false)
+                                .map(|_| ())
+                        });
+                        future::join_all(removes).map(|_| ())
+                    }).map_err(Error::from)
+            }).into_future()
+            .flatten()
+            .map_err(|err| {
+                warn!("Attempt to remove images failed.");
+                log_failure(Level::Warn, &err);
+                err
+            })
+    }
+
+    /// Adds the steps that create the named volumes a module mounts,
+    /// labeled as created for modules, to `plan`. Volumes that already exist
+    /// are left as they are, so only the volumes the plan created are removed
+    /// when it is undone.
+    fn create_volumes(&self, plan: Plan, volumes: Vec<String>) -> Plan {
+        volumes.into_iter().fold(plan, |plan, name| {
+            let runtime = self.clone();
+            plan.then(format!("create volume {}", name), move || {
+                let inspected = runtime.client.volume_api().volume_inspect(&name);
+                inspected.then(move |result| {
+                    let err = match result {
+                        Ok(_) => return future::Either::A(future::ok(None)),
+                        Err(err) => Error::from(err),
+                    };
+                    let missing = match *err.kind() {
+                        ErrorKind::NotFound(_) => true,
+                        _ => false,
+                    };
+                    if !missing {
+                        return future::Either::A(future::err(err));
+                    }
+
+                    debug!("Creating volume {}", name);
+                    let mut labels = HashMap::new();
+                    labels.insert(LABEL_KEY.to_string(), LABEL_VALUE.to_string());
+                    let created = runtime.client.volume_api().volume_create(
+                        VolumeConfig::new()
+                            .with_name(name.clone())
+                            .with_labels(labels),
+                    );
+                    future::Either::B(created.map_err(Error::from).map(move |_| {
+                        Some(Undo::new(format!("remove volume {}", name), move || {
+                            runtime.remove_volume(&name, false)
+                        }))
+                    }))
+                })
+            })
+        })
+    }
+
+    /// The body docker takes to create the container of `module`, with the
+    /// image, environment and labels of the module added to its create
+    /// options.
+    fn module_create_options(
+        &self,
+        module: &ModuleSpec<DockerConfig>,
+    ) -> Result<ContainerCreateBody> {
+        let create_options = module.config().clone_create_options()?;
+        let image = self.mirrors.pulled_as(&self.upstream_image(module.config())?);
+
+        // merge environment variables
+        let merged_env = DockerModuleRuntime::merge_env(
+            create_options.env(),
+            module.env(),
+            self.case_insensitive_env,
+        );
+
+        let mut labels = create_options
+            .labels()
+            .cloned()
+            .unwrap_or_else(HashMap::new);
+        labels.insert(LABEL_KEY.to_string(), LABEL_VALUE.to_string());
+
+        Ok(self
+            .apply_host_defaults(create_options)
+            .with_image(image.as_str().to_string())
+            .with_env(merged_env)
+            .with_labels(labels))
+    }
+
+    /// Adds the steps that create the container `name`, along with the named
+    /// `volumes` it mounts, and record it in the state store to `plan`.
+    /// Undoing them removes the container again.
+    fn create_container(
+        &self,
+        plan: Plan,
+        name: String,
+        image: String,
+        create_options: ContainerCreateBody,
+        volumes: Vec<String>,
+    ) -> Plan {
+        let runtime = self.clone();
+        let client = self.client.clone();
+        let state = self.state.clone();
+        let record_name = name.clone();
+        self.create_volumes(plan, volumes)
+            .then(format!("create container {}", name), move || {
+                let created = runtime
+                    .client
+                    .container_api()
+                    .container_create(create_options, &name);
+                created.map_err(Error::from).map(move |_| {
+                    Some(Undo::new(format!("remove container {}", name), move || {
+                        <DockerModuleRuntime as ModuleRuntime>::remove(&runtime, &name)
+                    }))
+                })
+            }).then(format!("record container {}", record_name), move || match state {
+                Some(state) => future::Either::A(
+                    client
+                        .container_api()
+                        .container_inspect(&record_name, false)
+                        .then(move |result| {
+                            // The container exists either way, so it is
+                            // recorded even if its image can't be told.
+                            let image_id = result
+                                .ok()
+                                .and_then(|c| c.image().map(ToString::to_string));
+                            state.record_created(&record_name, ModuleRecord::new(image, image_id));
+                            Ok(None)
+                        }),
+                ),
+                None => future::Either::B(future::ok(None)),
+            })
+    }
+
+    /// Adds the default DNS servers and host entries to the host config of a
+    /// module, unless the module sets them itself.
+    fn apply_host_defaults(&self, create_options: ContainerCreateBody) -> ContainerCreateBody {
+        if self.default_dns.is_empty() && self.default_extra_hosts.is_empty() {
+            return create_options;
+        }
+
+        let mut host_config = create_options
+            .host_config()
+            .cloned()
+            .unwrap_or_else(HostConfig::new);
+        if host_config.dns().is_none() && !self.default_dns.is_empty() {
+            host_config.set_dns(self.default_dns.clone());
+        }
+        if host_config.extra_hosts().is_none() && !self.default_extra_hosts.is_empty() {
+            host_config.set_extra_hosts(self.default_extra_hosts.clone());
+        }
+        create_options.with_host_config(host_config)
+    }
+
+    /// Starts the operation `f` returns, recording under `operation` how long
+    /// it takes and whether it fails. Failing argument checks count as failed
+    /// operations too. Failures are also kept in the runtime error log, along
+    /// with the `module` the operation was about.
+    fn instrument<F, T>(
+        &self,
+        operation: &'static str,
+        module: Option<&str>,
+        f: F,
+    ) -> Instrumented<T>
+    where
+        F: FnOnce() -> T,
+        T: Future<Error = Error>,
+    {
+        self.metrics.increment(&RUNTIME_OPERATIONS, &[("operation", operation)]);
+        let clock = self.clock.clone();
+        let metrics = self.metrics.clone();
+        let mut inner = edgelet_utils::timed(operation, f())
+            .with_clock(Arc::new(move || clock.instant()))
+            .on_finish(move |elapsed| {
+                metrics.observe(
+                    &RUNTIME_OPERATION_DURATION,
+                    &[("operation", operation)],
+                    duration_secs(elapsed),
+                )
+            });
+        if let Some(threshold) = self.slow_thresholds.get(operation) {
+            let metrics = self.metrics.clone();
+            inner = inner.with_slow_threshold(*threshold).on_slow(move |_| {
+                metrics.increment(&RUNTIME_SLOW_OPERATIONS, &[("operation", operation)])
+            });
+        }
+        Instrumented {
+            inner,
+            operation,
+            module: module.map(ToString::to_string),
+            metrics: self.metrics.clone(),
+            runtime_errors: self.runtime_errors.clone(),
+            system_info: self.system_info.clone(),
+            deadline: None,
+        }
+    }
+
+    /// Instruments an operation that fails with `OperationTimedOut` when it
+    /// takes longer than the operation timeout.
+    fn timed<F, T>(&self, operation: &'static str, module: Option<&str>, f: F) -> Instrumented<T>
+    where
+        F: FnOnce() -> T,
+        T: Future<Error = Error>,
+    {
+        let mut instrumented = self.instrument(operation, module, f);
+        instrumented.deadline = self
+            .operation_timeout
+            .map(|timeout| Delay::new(Instant::now() + timeout));
+        instrumented
+    }
+
+    /// Merges the environment variables in `cur_env` over those in `new_env`.
+    /// With `case_insensitive`, names that differ only in case are merged as
+    /// one variable, which keeps the name it was last defined with.
+    fn merge_env(
+        cur_env: Option<&[String]>,
+        new_env: &HashMap<String, String>,
+        case_insensitive: bool,
+    ) -> Vec<String> {
+        // string slices for keys and values pointing into String instances in
+        // new_env, followed by those in cur_env so that they replace them
+        let vars = new_env.iter().map(|(k, v)| (k.as_str(), v.as_str())).chain(
+            cur_env.unwrap_or(&[]).iter().filter_map(|s| {
                 let mut tokens = s.splitn(2, '=');
                 tokens.next().map(|key| (key, tokens.next().unwrap_or("")))
-            }));
+            }),
+        );
+
+        let mut merged_env = HashMap::new();
+        for (key, value) in vars {
+            let name = if case_insensitive {
+                Cow::Owned(key.to_uppercase())
+            } else {
+                Cow::Borrowed(key)
+            };
+            merged_env.insert(name, (key, value));
         }
 
         // finally build a new Vec<String>; we alloc new strings here
         merged_env
-            .iter()
+            .values()
             .map(|(key, value)| format!("{}={}", key, value))
             .collect()
     }
 }
 
+/// A container operation that records its outcome and duration when it
+/// completes, and that fails once it runs past its deadline, if it has one.
+struct Instrumented<T> {
+    inner: Timed<T>,
+    operation: &'static str,
+    module: Option<String>,
+    metrics: Arc<Metrics>,
+    runtime_errors: RuntimeErrorLog,
+    system_info: SystemInfoCache,
+    deadline: Option<Delay>,
+}
+
+impl<T> Instrumented<T> {
+    /// Gives the operation `by` longer, for operations that wait on purpose.
+    fn extend_deadline(mut self, by: Duration) -> Self {
+        if let Some(ref mut deadline) = self.deadline {
+            let at = deadline.deadline() + by;
+            deadline.reset(at);
+        }
+        self
+    }
+}
+
+impl<T> Future for Instrumented<T>
+where
+    T: Future<Error = Error>,
+{
+    type Item = T::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut result = self.inner.poll();
+        if let Ok(Async::NotReady) = result {
+            let deadline = self.deadline.as_mut().map(Future::poll);
+            match deadline {
+                Some(Ok(Async::Ready(()))) => {
+                    self.inner.finish();
+                    result = Err(Error::from(ErrorKind::OperationTimedOut(self.operation)));
+                }
+                Some(Err(err)) => {
+                    // Without a timer the operation can only be waited for.
+                    warn!("Could not keep the deadline of {}: {}", self.operation, err);
+                    self.deadline = None;
+                    return result;
+                }
+                Some(Ok(Async::NotReady)) | None => return result,
+            }
+        }
+
+        if let Err(ref err) = result {
+            let error = error_kind_label(err.kind());
+            self.metrics.increment(
+                &RUNTIME_OPERATION_FAILURES,
+                &[("operation", self.operation), ("error", &error)],
+            );
+            record_error(
+                &self.runtime_errors,
+                self.operation,
+                self.module.take(),
+                err,
+            );
+            if is_connection_error(err) {
+                self.system_info.invalidate();
+            }
+        }
+        result
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+fn default_wait_before_kill() -> Duration {
+    Duration::from_secs(WAIT_BEFORE_KILL_SECONDS as u64)
+}
+
+/// Filters for the containers, volumes and images labeled as owned by the
+/// edge runtime.
+fn owner_filters() -> Result<String> {
+    DockerFilters::new()
+        .add("label", &format!("{}={}", LABEL_KEY, LABEL_VALUE))?
+        .to_json()
+}
+
+fn image_info(image: &ImageSummary) -> ImageInfo {
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+    let size = cmp::max(*image.size(), 0) as u64;
+    let created_at = Utc.timestamp_opt(i64::from(*image.created()), 0).single();
+    ImageInfo::new(image.id().to_string(), size)
+        .with_names(image.repo_tags().to_vec())
+        .with_created_at(created_at)
+}
+
+/// Encodes `auth` the way docker takes registry credentials.
+fn encode_auth(auth: &AuthConfig) -> Result<String> {
+    let json = serde_json::to_string(auth)?;
+    Ok(base64::encode(&json))
+}
+
+/// Keeps `err` in the runtime error log, with its causes.
+fn record_error(
+    runtime_errors: &RuntimeErrorLog,
+    operation: &str,
+    module: Option<String>,
+    err: &Error,
+) {
+    let mut message = err.to_string();
+    let mut fail: &Fail = err;
+    while let Some(cause) = fail.cause() {
+        message.push_str(&format!("\n\tcaused by: {}", cause));
+        fail = cause;
+    }
+    let record = RuntimeErrorRecord::new(
+        Utc::now(),
+        operation,
+        &error_kind_label(err.kind()),
+        message,
+    ).with_module(module);
+    runtime_errors.record(record);
+}
+
 fn get_base_path(url: &Url) -> &str {
     match url.scheme() {
         "unix" => url.path(),
@@ -110,46 +963,104 @@ impl ModuleRegistry for DockerModuleRuntime {
     type Config = DockerConfig;
 
     fn pull(&self, config: &Self::Config) -> Self::PullFuture {
-        let creds: Result<String> = config.auth().map_or_else(
-            || Ok("".to_string()),
-            |a| {
-                let json = serde_json::to_string(a)?;
-                Ok(base64::encode(&json))
-            },
-        );
+        Box::new(self.instrument("pull", None, || -> Self::PullFuture {
+            let image = match self.upstream_image(config) {
+                Ok(image) => image,
+                Err(err) => return Box::new(future::err(err)),
+            };
+            let creds = self.registry_auth(config, &image);
+
+            if image.tag_defaulted() {
+                warn!("Image {} has no tag, pulling {}", config.image(), image);
+            }
+            let mirror = match self.mirrors.mirror(&image) {
+                Some(mirror) => mirror,
+                None => return self.pull_image(image.clone(), image, creds),
+            };
+            let mirrored = match image.with_registry(mirror.mirror()) {
+                Ok(mirrored) => mirrored,
+                Err(err) => return Box::new(future::err(err)),
+            };
+
+            let runtime = self.clone();
+            let response = self
+                .pull_image(image.clone(), mirrored, self.mirror_auth(mirror))
+                .or_else(move |err| {
+                    if falls_back(&err) {
+                        warn!(
+                            "Could not pull {} from its mirror, pulling it from {} instead",
+                            image,
+                            image.registry_or_default()
+                        );
+                        future::Either::A(runtime.pull_image(image.clone(), image, creds))
+                    } else {
+                        future::Either::B(future::err(err))
+                    }
+                });
+
+            Box::new(response)
+        }))
+    }
 
-        let response = creds
-            .map(|creds| {
-                debug!("Pulling {}", config.image());
+    fn remove(&self, name: &str) -> Self::RemoveFuture {
+        Box::new(self.instrument("remove_image", None, || -> Self::RemoveFuture {
+            debug!("Removing {}", name);
+            Box::new(
                 self.client
                     .image_api()
-                    .image_create(config.image(), "", "", "", "", &creds, "")
+                    .image_delete(fensure_not_empty!(name), false, false)
+                    .map(|_| ())
                     .map_err(|err| {
                         let e = Error::from(err);
-                        warn!("Attempt to pull image failed.");
+                        warn!("Attempt to remove image failed.");
                         log_failure(Level::Warn, &e);
                         e
-                    })
-            }).into_future()
-            .flatten();
+                    }),
+            )
+        }))
+    }
 
-        Box::new(response)
+    /// Lists the images labeled as owned by the edge runtime.
+    fn images(&self) -> Box<Future<Item = Vec<ImageInfo>, Error = Self::Error> + Send> {
+        Box::new(self.instrument("list_images", None, || {
+            owner_filters()
+                .map(|filters| {
+                    self.client
+                        .image_api()
+                        .image_list(false, &filters, false)
+                        .map(|images| images.iter().map(image_info).collect())
+                        .map_err(Error::from)
+                }).into_future()
+                .flatten()
+        }))
     }
 
-    fn remove(&self, name: &str) -> Self::RemoveFuture {
-        debug!("Removing {}", name);
-        Box::new(
-            self.client
-                .image_api()
-                .image_delete(fensure_not_empty!(name), false, false)
-                .map(|_| ())
-                .map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to remove image failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }),
-        )
+    /// Removes the images one by one. Images a container was created from,
+    /// even one that isn't a module, are left, since the daemon only removes
+    /// them when forced to.
+    fn prune_images(
+        &self,
+        ids: Vec<String>,
+    ) -> Box<Future<Item = Vec<String>, Error = Self::Error> + Send> {
+        let client = self.client.clone();
+        Box::new(self.instrument("prune_images", None, move || {
+            let removals = ids.into_iter().map(move |id| {
+                debug!("Removing unused image {}", id);
+                client
+                    .image_api()
+                    .image_delete(&id, false, false)
+                    .then(move |result| match result {
+                        Ok(_) => Ok::<_, Error>(Some(id)),
+                        Err(err) => {
+                            debug!("Could not remove image {}:", id);
+                            log_failure(Level::Debug, &Error::from(err));
+                            Ok(None)
+                        }
+                    })
+            });
+            future::join_all(removals)
+                .map(|removed| removed.into_iter().filter_map(|id| id).collect())
+        }))
     }
 }
 
@@ -171,33 +1082,48 @@ impl ModuleRuntime for DockerModuleRuntime {
     type RestartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
     type StartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
     type StopFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type TopFuture = Box<Future<Item = Vec<Pid>, Error = Self::Error> + Send>;
     type SystemInfoFuture = Box<Future<Item = CoreSystemInfo, Error = Self::Error> + Send>;
     type RemoveAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type CheckIntegrityFuture = Box<Future<Item = IntegrityReport, Error = Self::Error> + Send>;
+    type InspectFuture = Box<Future<Item = ModuleInspection, Error = Self::Error> + Send>;
+    type KillFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type UpdateFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
 
     fn init(&self) -> Self::InitFuture {
+        let runtime_errors = self.runtime_errors.clone();
         let created = self.network_id.clone().map_or_else(
             || future::Either::B(future::ok(())),
             |id| {
-                let filter = format!(r#"{{"name":{{"{}":true}}}}"#, id);
+                let client = self.client.clone();
                 let client_copy = self.client.clone();
-                let fut = self
-                    .client
-                    .network_api()
-                    .network_list(&filter)
-                    .and_then(move |existing_networks| {
+                let network = id.clone();
+                let fut = DockerFilters::new()
+                    .add("name", &id)
+                    .and_then(|filters| filters.to_json())
+                    .into_future()
+                    .and_then(move |filters| {
+                        client
+                            .network_api()
+                            .network_list(&filters)
+                            .map_err(Error::from)
+                    }).and_then(move |existing_networks| {
                         if existing_networks.is_empty() {
                             let fut = client_copy
                                 .network_api()
                                 .network_create(NetworkConfig::new(id))
-                                .map(|_| ());
+                                .map(|_| ())
+                                .map_err(Error::from);
                             future::Either::A(fut)
                         } else {
                             future::Either::B(future::ok(()))
                         }
-                    }).map_err(|err| {
-                        let e = Error::from(err);
+                    }).map_err(move |e| {
+                        let e = Error::from(e.context(ErrorKind::NetworkCreate(network)));
                         warn!("Module runtime init failed.");
                         log_failure(Level::Warn, &e);
+                        record_error(&runtime_errors, "init", None, &e);
                         e
                     });
                 future::Either::A(fut)
@@ -208,233 +1134,507 @@ impl ModuleRuntime for DockerModuleRuntime {
     }
 
     fn create(&self, module: ModuleSpec<Self::Config>) -> Self::CreateFuture {
-        // we only want "docker" modules
-        fensure!(module.type_(), module.type_() == DOCKER_MODULE_TYPE);
-
-        let result = module
-            .config()
-            .clone_create_options()
-            .and_then(|create_options| {
-                // merge environment variables
-                let merged_env = DockerModuleRuntime::merge_env(create_options.env(), module.env());
-
-                let mut labels = create_options
-                    .labels()
-                    .cloned()
-                    .unwrap_or_else(HashMap::new);
-                labels.insert(LABEL_KEY.to_string(), LABEL_VALUE.to_string());
-
-                debug!(
-                    "Creating container {} with image {}",
-                    module.name(),
-                    module.config().image()
-                );
-
-                let create_options = create_options
-                    .with_image(module.config().image().to_string())
-                    .with_env(merged_env)
-                    .with_labels(labels);
-
-                // Here we don't add the container to the iot edge docker network as the edge-agent is expected to do that.
-                // It contains the logic to add a container to the iot edge network only if a network is not already specified.
-
-                Ok(self
-                    .client
-                    .container_api()
-                    .container_create(create_options, module.name())
-                    .map_err(Error::from)
-                    .map(|_| ()))
-            });
-
-        match result {
-            Ok(f) => Box::new(f),
-            Err(err) => {
-                warn!("Attempt to create a container failed.");
-                log_failure(Level::Warn, &err);
-                Box::new(future::err(err))
+        let created = self.timed("create", Some(module.name()), || -> Self::CreateFuture {
+            // we only want "docker" modules
+            fensure!(module.type_(), module.type_() == DOCKER_MODULE_TYPE);
+
+            debug!(
+                "Creating container {} with image {}",
+                module.name(),
+                module.config().image()
+            );
+
+            // Here we don't add the container to the iot edge docker network as the edge-agent is expected to do that.
+            // It contains the logic to add a container to the iot edge network only if a network is not already specified.
+            match self.module_create_options(&module) {
+                Ok(create_options) => Box::new(
+                    self.create_container(
+                        Plan::new(),
+                        module.name().to_string(),
+                        create_options.image().unwrap_or_default().to_string(),
+                        create_options,
+                        module.config().volumes(),
+                    ).execute(),
+                ),
+                Err(err) => {
+                    warn!("Attempt to create a container failed.");
+                    log_failure(Level::Warn, &err);
+                    Box::new(future::err(err))
+                }
             }
-        }
+        });
+        Box::new(self.while_busy(created))
     }
 
     fn start(&self, id: &str) -> Self::StartFuture {
-        debug!("Starting container {}", id);
-        Box::new(
-            self.client
-                .container_api()
-                .container_start(fensure_not_empty!(id), "")
-                .map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to start a container failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }).map(|_| ()),
-        )
+        Box::new(self.changing(id, self.timed("start", Some(id), || -> Self::StartFuture {
+            debug!("Starting container {}", id);
+            Box::new(
+                self.client
+                    .container_api()
+                    .container_start(fensure_matches!(id, CONTAINER_ID, "id"), "")
+                    .map_err(|err| {
+                        let e = Error::from(err);
+                        warn!("Attempt to start a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }).map(|_| ()),
+            )
+        })))
     }
 
     fn stop(&self, id: &str, wait_before_kill: Option<Duration>) -> Self::StopFuture {
-        debug!("Stopping container {}", id);
-
-        #[cfg_attr(
-            feature = "cargo-clippy",
-            allow(cast_possible_truncation, cast_sign_loss)
-        )]
-        Box::new(
-            self.client
-                .container_api()
-                .container_stop(
-                    fensure_not_empty!(id),
-                    wait_before_kill.map_or(WAIT_BEFORE_KILL_SECONDS, |s| match s.as_secs() {
-                        s if s > i32::max_value() as u64 => i32::max_value(),
-                        s => s as i32,
-                    }),
-                ).map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to stop a container failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }).map(|_| ()),
-        )
+        let stopped = self.timed("stop", Some(id), || -> Self::StopFuture {
+            debug!("Stopping container {}", id);
+
+            #[cfg_attr(
+                feature = "cargo-clippy",
+                allow(cast_possible_truncation, cast_sign_loss)
+            )]
+            Box::new(
+                self.client
+                    .container_api()
+                    .container_stop(
+                        fensure_matches!(id, CONTAINER_ID, "id"),
+                        wait_before_kill.map_or(WAIT_BEFORE_KILL_SECONDS, |s| match s.as_secs() {
+                            s if s > i32::max_value() as u64 => i32::max_value(),
+                            s => s as i32,
+                        }),
+                    ).map_err(|err| {
+                        let e = Error::from(err);
+                        warn!("Attempt to stop a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }).map(|_| ()),
+            )
+        }).extend_deadline(wait_before_kill.unwrap_or_else(default_wait_before_kill));
+        Box::new(self.changing(id, stopped))
     }
 
     fn system_info(&self) -> Self::SystemInfoFuture {
-        Box::new(
-            self.client
-                .system_api()
-                .system_info()
-                .map(|system_info| {
-                    CoreSystemInfo::new(
-                        system_info
-                            .os_type()
-                            .unwrap_or(&String::from("Unknown"))
-                            .to_string(),
-                        system_info
-                            .architecture()
-                            .unwrap_or(&String::from("Unknown"))
-                            .to_string(),
-                    )
-                }).map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to get system information failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }),
-        )
+        match self.system_info.get(self.clock.instant(), self.system_info_ttl) {
+            Some(system_info) => Box::new(future::ok(system_info)),
+            None => self.refresh_system_info(),
+        }
     }
 
-    fn restart(&self, id: &str) -> Self::RestartFuture {
-        debug!("Restarting container {}", id);
-        Box::new(
-            self.client
-                .container_api()
-                .container_restart(fensure_not_empty!(id), WAIT_BEFORE_KILL_SECONDS)
-                .map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to restart a container failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }).map(|_| ()),
-        )
+    fn refresh_system_info(&self) -> Self::SystemInfoFuture {
+        let cache = self.system_info.clone();
+        let generation = cache.generation();
+        let clock = self.clock.clone();
+
+        // Only /version reports the API version the daemon serves. Older
+        // daemons may not answer it, which is no reason to fail.
+        let api_version = self
+            .client
+            .system_api()
+            .system_version()
+            .map(|version| version.api_version().map(ToString::to_string))
+            .or_else(|err| {
+                debug!("Could not get the container runtime API version: {:?}", err);
+                Ok(None)
+            });
+
+        let system_info = self
+            .client
+            .system_api()
+            .system_info()
+            .join(api_version)
+            .map(|(system_info, api_version)| {
+                let runtime_version = system_info.server_version().map(ToString::to_string);
+                let storage_driver_status = system_info
+                    .driver_status()
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(|pair| match pair.as_slice() {
+                        [key, value] => Some((key.to_string(), value.to_string())),
+                        _ => None,
+                    }).collect();
+                CoreSystemInfo::new(
+                    system_info
+                        .os_type()
+                        .unwrap_or(&String::from("Unknown"))
+                        .to_string(),
+                    system_info
+                        .architecture()
+                        .unwrap_or(&String::from("Unknown"))
+                        .to_string(),
+                ).with_runtime_version(runtime_version)
+                .with_runtime_api_version(api_version)
+                .with_storage_driver(system_info.driver().map(ToString::to_string))
+                .with_storage_driver_status(storage_driver_status)
+                .with_logging_driver(system_info.logging_driver().map(ToString::to_string))
+                .with_runtime_root_dir(system_info.docker_root_dir().map(PathBuf::from))
+            }).map(move |system_info| {
+                cache.store(generation, &system_info, clock.instant());
+                system_info
+            }).map_err(|err| {
+                let e = Error::from(err);
+                warn!("Attempt to get system information failed.");
+                log_failure(Level::Warn, &e);
+                e
+            });
+
+        Box::new(self.timed("system_info", None, || system_info))
     }
 
-    fn remove(&self, id: &str) -> Self::RemoveFuture {
-        debug!("Removing container {}", id);
-        Box::new(
-            self.client
-                .container_api()
-                .container_delete(
-                    fensure_not_empty!(id),
-                    /* remove volumes */ false,
-                    /* force */ true,
-                    /* remove link */ false,
-                ).map_err(|err| {
-                    let e = Error::from(err);
-                    warn!("Attempt to remove a container failed.");
-                    log_failure(Level::Warn, &e);
-                    e
-                }).map(|_| ()),
-        )
+    fn restart(&self, id: &str) -> Self::RestartFuture {
+        let restarted = self.timed("restart", Some(id), || -> Self::RestartFuture {
+            debug!("Restarting container {}", id);
+            Box::new(
+                self.client
+                    .container_api()
+                    .container_restart(
+                        fensure_matches!(id, CONTAINER_ID, "id"),
+                        WAIT_BEFORE_KILL_SECONDS,
+                    ).map_err(|err| {
+                        let e = Error::from(err);
+                        warn!("Attempt to restart a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }).map(|_| ()),
+            )
+        }).extend_deadline(default_wait_before_kill());
+        Box::new(self.changing(id, restarted))
     }
 
-    fn list(&self) -> Self::ListFuture {
-        let mut filters = HashMap::new();
-        filters.insert("label", LABELS.deref());
+    fn kill(&self, id: &str, signal: &str) -> Self::KillFuture {
+        let signal = match self.signals.check(signal) {
+            Ok(signal) => signal,
+            Err(err) => {
+                return Box::new(future::err(Error::from(
+                    err.context(ErrorKind::InvalidSignal(signal.to_string())),
+                )))
+            }
+        };
 
-        let client_copy = self.client.clone();
+        Box::new(self.timed("kill", Some(id), || -> Self::KillFuture {
+            debug!("Sending {} to container {}", signal, id);
+            let module = id.to_string();
+            Box::new(
+                self.client
+                    .container_api()
+                    .container_kill(fensure_matches!(id, CONTAINER_ID, "id"), &signal)
+                    .map_err(move |err| {
+                        let e = Error::for_module(&module, err);
+                        warn!("Attempt to send a signal to a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }),
+            )
+        }))
+    }
 
-        let result = serde_json::to_string(&filters)
-            .map(|filters| {
+    fn remove(&self, id: &str) -> Self::RemoveFuture {
+        let removed = self.changing(id, self.timed("remove", Some(id), || -> Self::RemoveFuture {
+            debug!("Removing container {}", id);
+            let state = self.state.clone();
+            let name = id.to_string();
+            Box::new(
                 self.client
                     .container_api()
-                    .container_list(true, 0, false, &filters)
-                    .map(move |containers| {
-                        containers
-                            .iter()
-                            .flat_map(|container| {
-                                DockerConfig::new(
-                                    container.image(),
-                                    ContainerCreateBody::new()
-                                        .with_labels(container.labels().clone()),
-                                    None,
-                                ).map(|config| {
-                                    (
-                                        container,
-                                        config.with_image_id(container.image_id().clone()),
+                    .container_delete(
+                        fensure_matches!(id, CONTAINER_ID, "id"),
+                        /* remove volumes */ false,
+                        /* force */ true,
+                        /* remove link */ false,
+                    ).map_err(|err| {
+                        let e = Error::from(err);
+                        warn!("Attempt to remove a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }).map(move |_| {
+                        if let Some(state) = state {
+                            state.record_removed(&name);
+                        }
+                    }),
+            )
+        }));
+        Box::new(self.while_busy(removed))
+    }
+
+    fn list(&self) -> Self::ListFuture {
+        Box::new(self.timed("list", None, || -> Self::ListFuture {
+            let client_copy = self.client.clone();
+
+            let result = owner_filters()
+                .map(|filters| {
+                    self.client
+                        .container_api()
+                        .container_list(true, 0, false, &filters)
+                        .map(move |containers| {
+                            containers
+                                .iter()
+                                .flat_map(|container| {
+                                    DockerConfig::new(
+                                        container.image(),
+                                        summary_create_options(container),
+                                        None,
+                                    ).map(|config| {
+                                        (
+                                            container,
+                                            config.with_image_id(container.image_id().to_string()),
+                                        )
+                                    })
+                                }).flat_map(|(container, config)| {
+                                    DockerModule::new(
+                                        client_copy.clone(),
+                                        container
+                                            .names()
+                                            .iter()
+                                            .next()
+                                            .map_or("Unknown", |s| &s[1..]),
+                                        config,
                                     )
-                                })
-                            }).flat_map(|(container, config)| {
-                                DockerModule::new(
-                                    client_copy.clone(),
-                                    container
-                                        .names()
-                                        .iter()
-                                        .next()
-                                        .map_or("Unknown", |s| &s[1..]),
-                                    config,
-                                )
-                            }).collect()
-                    }).map_err(Error::from)
-            }).into_future()
-            .flatten()
-            .map_err(|err| {
-                warn!("Attempt to list containers failed.");
-                log_failure(Level::Warn, &err);
-                err
-            });
-        Box::new(result)
+                                }).collect()
+                        }).map_err(Error::from)
+                }).into_future()
+                .flatten()
+                .map_err(|err| {
+                    warn!("Attempt to list containers failed.");
+                    log_failure(Level::Warn, &err);
+                    err
+                });
+            Box::new(result)
+        }))
     }
 
     fn list_with_details(&self) -> Self::ListWithDetailsStream {
-        list_with_details(self)
+        let restarts = self.restarts.clone();
+        let epochs = self.epochs.clone();
+        Box::new(list_with_details(self).map(move |(module, state)| {
+            epochs.observe(module.name(), state.pid());
+            let state = restarts.report(module.name(), state, Instant::now());
+            (module, state)
+        }))
     }
 
     fn logs(&self, id: &str, options: &LogOptions) -> Self::LogsFuture {
         let tail = &options.tail().to_string();
+        let runtime_errors = self.runtime_errors.clone();
+        let module = id.to_string();
         let result = self
             .client
             .container_api()
-            .container_logs(id, options.follow(), true, true, 0, false, tail)
+            .container_logs(
+                id,
+                options.follow(),
+                true,
+                true,
+                options.since(),
+                options.timestamps(),
+                tail,
+            )
             .map(Logs)
-            .map_err(|err| {
+            .map_err(move |err| {
                 let e = Error::from(err);
                 warn!("Attempt to get container logs failed.");
                 log_failure(Level::Warn, &e);
+                record_error(&runtime_errors, "logs", Some(module), &e);
                 e
             });
         Box::new(result)
     }
 
-    fn registry(&self) -> &Self::ModuleRegistry {
-        self
+    fn registry(&self) -> &Self::ModuleRegistry {
+        self
+    }
+
+    fn remove_all(&self) -> Self::RemoveAllFuture {
+        let self_for_list = self.clone();
+        let self_for_remove = self.clone();
+        let self_for_purge = self.clone();
+        // Stop the modules in order first, so that none of them loses a
+        // module it depends on while it is still running.
+        Box::new(
+            self.stop_all(&self.stop_options)
+                .and_then(move |_| self_for_list.list())
+                .and_then(move |list| {
+                    let n = list.into_iter().map(move |c| {
+                        <DockerModuleRuntime as ModuleRuntime>::remove(&self_for_remove, c.name())
+                    });
+                    future::join_all(n).map(|_| ())
+                }).and_then(move |_| {
+                    if self_for_purge.purge_volumes {
+                        future::Either::A(self_for_purge.list_volumes().and_then(move |volumes| {
+                            let n = volumes
+                                .iter()
+                                .map(|volume| self_for_purge.remove_volume(volume, false))
+                                .collect::<Vec<_>>();
+                            future::join_all(n).map(|_| ())
+                        }))
+                    } else {
+                        future::Either::B(future::ok(()))
+                    }
+                }),
+        )
+    }
+
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+        match self.state.clone() {
+            Some(state) => Box::new(self.list().map(move |modules| {
+                let live = modules
+                    .iter()
+                    .map(|module| {
+                        let config = module.config();
+                        let record = ModuleRecord::new(
+                            config.image().to_string(),
+                            config.image_id().map(ToString::to_string),
+                        );
+                        (module.name().to_string(), record)
+                    }).collect::<BTreeMap<_, _>>();
+                state.check(live)
+            })),
+            None => Box::new(future::ok(IntegrityReport::default())),
+        }
+    }
+
+    fn inspect(&self, id: &str) -> Self::InspectFuture {
+        Box::new(self.instrument("inspect", Some(id), || -> Self::InspectFuture {
+            debug!("Inspecting container {}", id);
+            Box::new(
+                self.client
+                    .container_api()
+                    .container_inspect(fensure_matches!(id, CONTAINER_ID, "id"), false)
+                    .map_err(Error::from)
+                    .and_then(|container| module_inspection(&container))
+                    .map_err(|e| {
+                        warn!("Attempt to inspect a container failed.");
+                        log_failure(Level::Warn, &e);
+                        e
+                    }),
+            )
+        }))
+    }
+
+    fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture {
+        Box::new(stop_in_order(self, options))
+    }
+
+    /// Replaces the container of a module with one created from `module`. The
+    /// new container mounts the anonymous volumes of the old one and keeps
+    /// its network aliases, so that the data the module kept and the names
+    /// peers reach it by survive the update. If the new container can't be
+    /// created or started, the old one is created and started again.
+    fn update(&self, module: ModuleSpec<Self::Config>, start: bool) -> Self::UpdateFuture {
+        let updated = self.instrument("update", Some(module.name()), || -> Self::UpdateFuture {
+            fensure!(module.type_(), module.type_() == DOCKER_MODULE_TYPE);
+
+            let name = module.name().to_string();
+            debug!(
+                "Updating container {} with image {}",
+                name,
+                module.config().image()
+            );
+
+            let create_options = match self.module_create_options(&module) {
+                Ok(create_options) => create_options,
+                Err(err) => {
+                    warn!("Attempt to update a container failed.");
+                    log_failure(Level::Warn, &err);
+                    return Box::new(future::err(err));
+                }
+            };
+            let image = create_options.image().unwrap_or_default().to_string();
+            let volumes = module.config().volumes();
+
+            let runtime = self.clone();
+            let result = self
+                .client
+                .container_api()
+                .container_inspect(fensure_matches!(&name, CONTAINER_ID, "name"), false)
+                .map_err(Error::from)
+                .and_then(|old| {
+                    let preserved = Preserved::new(&old);
+                    let old_image = old
+                        .config()
+                        .and_then(|config| config.image())
+                        .unwrap_or("")
+                        .to_string();
+                    let old_options = module_inspection(&old).and_then(|inspection| {
+                        Ok(serde_json::from_value::<ContainerCreateBody>(
+                            inspection.create_options().clone(),
+                        )?)
+                    })?;
+                    let running = old
+                        .state()
+                        .and_then(|state| state.running())
+                        .map_or(false, |running| *running);
+                    Ok((preserved, old_image, old_options, running))
+                }).and_then(move |(preserved, old_image, old_options, running)| {
+                    // Undoing the steps of the update creates the old
+                    // container again, and starts it if it was running.
+                    let mut plan = Plan::new();
+                    if running {
+                        let runtime = runtime.clone();
+                        let name = name.clone();
+                        plan = plan.then(format!("stop container {}", name), move || {
+                            let stopped = runtime.stop(&name, None);
+                            stopped.map(move |_| {
+                                Some(Undo::new(format!("start container {}", name), move || {
+                                    runtime.start(&name)
+                                }))
+                            })
+                        });
+                    }
+
+                    let remove_runtime = runtime.clone();
+                    let remove_name = name.clone();
+                    let old_options = preserved.apply(old_options);
+                    plan = plan.then(format!("remove container {}", name), move || {
+                        let removed = <DockerModuleRuntime as ModuleRuntime>::remove(
+                            &remove_runtime,
+                            &remove_name,
+                        );
+                        removed.map(move |_| {
+                            Some(Undo::new(format!("restore container {}", remove_name), move || {
+                                remove_runtime
+                                    .create_container(
+                                        Plan::new(),
+                                        remove_name,
+                                        old_image,
+                                        old_options,
+                                        vec![],
+                                    ).execute()
+                            }))
+                        })
+                    });
+
+                    plan = runtime.create_container(
+                        plan,
+                        name.clone(),
+                        image,
+                        preserved.apply(create_options),
+                        volumes,
+                    );
+
+                    if start {
+                        plan = plan.then(format!("start container {}", name), move || {
+                            runtime.start(&name).map(|_| None)
+                        });
+                    }
+                    plan.execute()
+                }).map_err(|err| {
+                    warn!("Attempt to update a container failed.");
+                    log_failure(Level::Warn, &err);
+                    err
+                });
+            Box::new(result)
+        });
+        Box::new(self.while_busy(updated))
+    }
+
+    fn top(&self, id: &str) -> Self::TopFuture {
+        debug!("Listing the processes of container {}", id);
+        Box::new(top(&self.client, fensure_matches!(id, CONTAINER_ID, "id")))
     }
 
-    fn remove_all(&self) -> Self::RemoveAllFuture {
-        let self_for_remove = self.clone();
-        Box::new(self.list().and_then(move |list| {
-            let n = list.into_iter().map(move |c| {
-                <DockerModuleRuntime as ModuleRuntime>::remove(&self_for_remove, c.name())
-            });
-            future::join_all(n).map(|_| ())
-        }))
+    fn epochs(&self) -> Option<&ModuleEpochs> {
+        Some(&self.epochs)
+    }
+
+    fn busy(&self) -> Option<&BusyFlag> {
+        Some(&self.busy)
     }
 }
 
@@ -475,6 +1675,12 @@ impl Stream for Logs {
     }
 }
 
+impl From<Body> for Logs {
+    fn from(body: Body) -> Self {
+        Logs(body)
+    }
+}
+
 impl Into<Body> for Logs {
     fn into(self) -> Body {
         self.0
@@ -487,9 +1693,263 @@ impl AsRef<[u8]> for Chunk {
     }
 }
 
-/// Invokes `ModuleRuntime::list`, then `Module::runtime_state` on each Module.
-/// Modules whose `runtime_state` returns `NotFound` are filtered out from the result,
-/// instead of letting the whole `list_with_details` call fail.
+/// Hands over the buffer hyper read the chunk into, without copying it.
+impl From<Chunk> for Bytes {
+    fn from(chunk: Chunk) -> Bytes {
+        chunk.0.into_bytes()
+    }
+}
+
+/// Hands over the chunk hyper read, so that it can be forwarded to another
+/// body without copying it.
+impl From<Chunk> for HyperChunk {
+    fn from(chunk: Chunk) -> HyperChunk {
+        chunk.0
+    }
+}
+
+impl Buf for Chunk {
+    fn remaining(&self) -> usize {
+        self.0.remaining()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        Buf::bytes(&self.0)
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.0.advance(cnt)
+    }
+}
+
+/// Recovers the create options of a container that matter once it exists
+/// from its summary: its labels and the named volumes it mounts.
+fn summary_create_options(container: &ContainerSummary) -> ContainerCreateBody {
+    let create_options = ContainerCreateBody::new().with_labels(container.labels().clone());
+    let mounts: Vec<Mount> = container
+        .mounts()
+        .iter()
+        .filter(|mount| mount._type() == Some("volume"))
+        .filter_map(|mount| {
+            mount.name().map(|name| {
+                let volume = Mount::new()
+                    .with__type("volume".to_string())
+                    .with_source(name.to_string());
+                match mount.destination() {
+                    Some(destination) => volume.with_target(destination.to_string()),
+                    None => volume,
+                }
+            })
+        }).collect();
+    if mounts.is_empty() {
+        create_options
+    } else {
+        create_options.with_host_config(HostConfig::new().with_mounts(mounts))
+    }
+}
+
+/// Describes a module by what docker reports about its container. The
+/// container config holds the environment as docker resolved it, including
+/// variables from the image, so it is reported rather than the module's spec.
+fn module_inspection(container: &InlineResponse200) -> Result<ModuleInspection> {
+    let name = container
+        .name()
+        .map_or("", |name| name.trim_left_matches('/'));
+    let config = container.config();
+    let image = config.and_then(|config| config.image()).unwrap_or("");
+    let env = config
+        .and_then(|config| config.env())
+        .unwrap_or(&[])
+        .iter()
+        .map(|var| {
+            let mut tokens = var.splitn(2, '=');
+            let key = tokens.next().unwrap_or("").to_string();
+            (key, tokens.next().unwrap_or("").to_string())
+        }).collect();
+    let labels = config
+        .and_then(|config| config.labels())
+        .map(|labels| labels.clone().into_iter().collect())
+        .unwrap_or_default();
+
+    // The create options are the container config together with the host
+    // config, like the body docker takes to create a container.
+    let mut create_options = serde_json::to_value(config)?;
+    if let (Some(options), Some(host_config)) =
+        (create_options.as_object_mut(), container.host_config())
+    {
+        options.insert("HostConfig".to_string(), serde_json::to_value(host_config)?);
+    }
+
+    Ok(
+        ModuleInspection::new(name.to_string(), image.to_string())
+            .with_image_id(container.image().map(ToString::to_string))
+            .with_env(env)
+            .with_labels(labels)
+            .with_create_options(create_options),
+    )
+}
+
+/// What a module keeps when its container is replaced: the anonymous volumes
+/// that hold its data, and the aliases and static addresses it has on its
+/// networks.
+#[derive(Debug, Default)]
+struct Preserved {
+    volumes: Vec<Mount>,
+    endpoints: HashMap<String, EndpointSettings>,
+}
+
+impl Preserved {
+    fn new(container: &InlineResponse200) -> Self {
+        let host_config = container.host_config();
+        let binds = host_config.and_then(HostConfig::binds).unwrap_or(&[]);
+        let mounts = host_config.and_then(HostConfig::mounts).unwrap_or(&[]);
+
+        // The volumes the container was created with by name are created
+        // again by name, so only those docker named are carried over.
+        let volumes = container
+            .mounts()
+            .unwrap_or(&[])
+            .iter()
+            .filter(|mount| mount._type() == Some("volume"))
+            .filter_map(|mount| match (mount.name(), mount.destination()) {
+                (Some(name), Some(destination)) => Some((name, destination)),
+                _ => None,
+            }).filter(|&(name, _)| {
+                let prefix = format!("{}:", name);
+                !binds.iter().any(|bind| bind.starts_with(&prefix))
+                    && !mounts.iter().any(|mount| mount.source() == Some(name))
+            }).map(|(name, destination)| {
+                Mount::new()
+                    .with__type("volume".to_string())
+                    .with_source(name.to_string())
+                    .with_target(destination.to_string())
+            }).collect();
+
+        // Docker adds the short id of a container to its aliases, which
+        // belongs to the old container only.
+        let short_id = container.id().map_or("", |id| &id[..id.len().min(12)]);
+        let endpoints = container
+            .network_settings()
+            .and_then(|settings| settings.networks())
+            .map(|networks| {
+                networks
+                    .iter()
+                    .map(|(network, endpoint)| {
+                        let aliases = endpoint
+                            .aliases()
+                            .unwrap_or(&[])
+                            .iter()
+                            .filter(|alias| alias.as_str() != short_id)
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        let mut preserved = EndpointSettings::new();
+                        if !aliases.is_empty() {
+                            preserved.set_aliases(aliases);
+                        }
+                        if let Some(ipam_config) = endpoint.ipam_config() {
+                            preserved.set_ipam_config(ipam_config.clone());
+                        }
+                        (network.to_string(), preserved)
+                    }).collect()
+            }).unwrap_or_default();
+
+        Preserved { volumes, endpoints }
+    }
+
+    /// Adds what was preserved to the options of the new container, where
+    /// they don't set it themselves.
+    fn apply(&self, create_options: ContainerCreateBody) -> ContainerCreateBody {
+        let mut create_options = create_options;
+
+        let mut host_config = create_options
+            .host_config()
+            .cloned()
+            .unwrap_or_else(HostConfig::new);
+        let volumes = self
+            .volumes
+            .iter()
+            .filter(|volume| !mounts_target(&host_config, volume.target().unwrap_or("")))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !volumes.is_empty() {
+            let mut mounts = host_config.mounts().map_or_else(Vec::new, ToOwned::to_owned);
+            mounts.extend(volumes);
+            host_config.set_mounts(mounts);
+            create_options = create_options.with_host_config(host_config);
+        }
+
+        // A container is created on a single network, so only the endpoint of
+        // that network is carried over.
+        let network = create_options
+            .networking_config()
+            .and_then(|config| config.endpoints_config())
+            .and_then(|endpoints| endpoints.keys().next().cloned())
+            .or_else(|| {
+                create_options
+                    .host_config()
+                    .and_then(HostConfig::network_mode)
+                    .map(ToString::to_string)
+            });
+        let preserved = network.and_then(|network| {
+            self.endpoints
+                .get(&network)
+                .map(|endpoint| (network, endpoint))
+        });
+        if let Some((network, preserved)) = preserved {
+            let mut endpoints = create_options
+                .networking_config()
+                .and_then(|config| config.endpoints_config())
+                .cloned()
+                .unwrap_or_default();
+            let mut endpoint = endpoints
+                .remove(&network)
+                .unwrap_or_else(EndpointSettings::new);
+
+            let mut aliases = endpoint.aliases().map_or_else(Vec::new, ToOwned::to_owned);
+            for alias in preserved.aliases().unwrap_or(&[]) {
+                if !aliases.contains(alias) {
+                    aliases.push(alias.clone());
+                }
+            }
+            if !aliases.is_empty() {
+                endpoint.set_aliases(aliases);
+            }
+            if endpoint.ipam_config().is_none() {
+                if let Some(ipam_config) = preserved.ipam_config() {
+                    endpoint.set_ipam_config(ipam_config.clone());
+                }
+            }
+
+            endpoints.insert(network, endpoint);
+            create_options = create_options.with_networking_config(
+                ContainerCreateBodyNetworkingConfig::new().with_endpoints_config(endpoints),
+            );
+        }
+
+        create_options
+    }
+}
+
+/// Whether `host_config` already mounts something at `target`.
+fn mounts_target(host_config: &HostConfig, target: &str) -> bool {
+    let suffix = format!(":{}", target);
+    let infix = format!(":{}:", target);
+    host_config
+        .mounts()
+        .unwrap_or(&[])
+        .iter()
+        .any(|mount| mount.target() == Some(target))
+        || host_config
+            .binds()
+            .unwrap_or(&[])
+            .iter()
+            .any(|bind| bind.ends_with(&suffix) || bind.contains(&infix))
+}
+
+/// Lists the modules with their state. The state of each module comes from
+/// inspecting its container rather than from the container list, whose
+/// summaries have no start or finish times. Modules whose container is gone
+/// by the time it is inspected are left out, instead of failing the list.
 fn list_with_details<MR, M>(
     runtime: &MR,
 ) -> Box<Stream<Item = (M, ModuleRuntimeState), Error = Error> + Send>
@@ -513,7 +1973,7 @@ where
             .filter_map(|value| match value {
                 Ok(value) => Some(Ok(value)),
                 Err(err) => match err.kind() {
-                    ErrorKind::NotFound(_) => None,
+                    ErrorKind::ModuleNotFound(_) => None,
                     _ => Some(Err(err)),
                 },
             }).then(Result::unwrap), // Ok(Ok(_)) -> Ok(_), Ok(Err(_)) -> Err(_), Err(_) -> !
@@ -523,15 +1983,19 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io;
     use std::mem;
 
+    use failure::Fail;
     use futures::future::FutureResult;
     use futures::stream::Empty;
+    use hyper::StatusCode;
     #[cfg(unix)]
     use tempfile::NamedTempFile;
     use tokio;
     use url::Url;
 
+    use docker::apis::{ApiError as DockerApiError, Error as DockerError};
     use docker::models::ContainerCreateBody;
     use edgelet_core::pid::Pid;
     use edgelet_core::ModuleRegistry;
@@ -563,6 +2027,45 @@ mod tests {
             .unwrap();
     }
 
+    #[cfg(windows)]
+    #[test]
+    fn create_with_pipe_succeeds() {
+        let _mri =
+            DockerModuleRuntime::new(&Url::parse("npipe:////./pipe/docker_engine").unwrap())
+                .unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[should_panic(expected = "Invalid uri")]
+    fn invalid_pipe_path_fails() {
+        let _mri = DockerModuleRuntime::new(&Url::parse("npipe://./boo").unwrap()).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    #[should_panic(expected = "Invalid uri")]
+    fn pipe_uri_fails() {
+        let _mri =
+            DockerModuleRuntime::new(&Url::parse("npipe:////./pipe/docker_engine").unwrap())
+                .unwrap();
+    }
+
+    #[test]
+    fn create_with_https_succeeds() {
+        let _mri = DockerModuleRuntime::new(&Url::parse("https://localhost:2376/").unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "TLS is required")]
+    fn tcp_without_tls_fails_when_tls_is_required() {
+        let _mri = DockerModuleRuntime::with_tls_config(
+            &Url::parse("tcp://localhost:2376/").unwrap(),
+            &TlsConfig::new().with_require_tls(true),
+        ).unwrap();
+    }
+
     fn empty_test<F, R>(tester: F)
     where
         F: Fn(&mut DockerModuleRuntime) -> R,
@@ -604,7 +2107,10 @@ mod tests {
     fn merge_env_empty() {
         let cur_env = Some(&[][..]);
         let new_env = HashMap::new();
-        assert_eq!(0, DockerModuleRuntime::merge_env(cur_env, &new_env).len());
+        assert_eq!(
+            0,
+            DockerModuleRuntime::merge_env(cur_env, &new_env, false).len()
+        );
     }
 
     #[test]
@@ -612,7 +2118,7 @@ mod tests {
         let cur_env = Some(vec!["k1=v1".to_string(), "k2=v2".to_string()]);
         let new_env = HashMap::new();
         let mut merged_env =
-            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env);
+            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env, false);
         merged_env.sort();
         assert_eq!(vec!["k1=v1", "k2=v2"], merged_env);
     }
@@ -623,7 +2129,7 @@ mod tests {
         let mut new_env = HashMap::new();
         new_env.insert("k3".to_string(), "v3".to_string());
         let mut merged_env =
-            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env);
+            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env, false);
         merged_env.sort();
         assert_eq!(vec!["k1=v1", "k2=v2", "k3=v3"], merged_env);
     }
@@ -635,11 +2141,117 @@ mod tests {
         new_env.insert("k2".to_string(), "v02".to_string());
         new_env.insert("k3".to_string(), "v3".to_string());
         let mut merged_env =
-            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env);
+            DockerModuleRuntime::merge_env(cur_env.as_ref().map(AsRef::as_ref), &new_env, false);
         merged_env.sort();
         assert_eq!(vec!["k1=v1", "k2=v2", "k3=v3"], merged_env);
     }
 
+    fn mixed_case_env(case_insensitive: bool) -> Vec<String> {
+        let cur_env = vec!["PATH=C:\\app".to_string(), "k1=v1".to_string()];
+        let mut new_env = HashMap::new();
+        new_env.insert("Path".to_string(), "C:\\Windows".to_string());
+        let mut merged_env =
+            DockerModuleRuntime::merge_env(Some(&cur_env), &new_env, case_insensitive);
+        merged_env.sort();
+        merged_env
+    }
+
+    #[test]
+    fn merge_env_keeps_names_differing_in_case_apart() {
+        assert_eq!(
+            vec!["PATH=C:\\app", "Path=C:\\Windows", "k1=v1"],
+            mixed_case_env(false)
+        );
+    }
+
+    #[test]
+    fn merge_env_replaces_names_differing_in_case() {
+        assert_eq!(vec!["PATH=C:\\app", "k1=v1"], mixed_case_env(true));
+    }
+
+    #[test]
+    fn merge_env_keeps_casing_of_last_definition() {
+        let cur_env = vec!["path=C:\\app".to_string()];
+        let mut new_env = HashMap::new();
+        new_env.insert("PATH".to_string(), "C:\\Windows".to_string());
+        let merged_env = DockerModuleRuntime::merge_env(Some(&cur_env), &new_env, true);
+        assert_eq!(vec!["path=C:\\app"], merged_env);
+    }
+
+    #[test]
+    fn merge_env_never_keeps_duplicates() {
+        let cur_env = vec![
+            "Temp=C:\\temp".to_string(),
+            "TEMP=D:\\temp".to_string(),
+            "k1=v1".to_string(),
+        ];
+        let mut new_env = HashMap::new();
+        new_env.insert("Path".to_string(), "C:\\Windows".to_string());
+        new_env.insert("PATH".to_string(), "C:\\app".to_string());
+        new_env.insert("path".to_string(), "C:\\bin".to_string());
+
+        let merged_env = DockerModuleRuntime::merge_env(Some(&cur_env), &new_env, true);
+
+        // Which of the variables of the module wins isn't defined, since they
+        // come from a map, but only one of them is kept.
+        let mut names: Vec<_> = merged_env
+            .iter()
+            .map(|var| var.split('=').next().unwrap().to_uppercase())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["K1", "PATH", "TEMP"], names);
+        assert!(merged_env.contains(&"TEMP=D:\\temp".to_string()));
+    }
+
+    #[test]
+    fn inspection_reports_resolved_config() {
+        let container: InlineResponse200 = serde_json::from_str(
+            r#"{
+                "Id": "6d3e2a0f",
+                "Name": "/tempSensor",
+                "Image": "sha256:8b1f",
+                "Config": {
+                    "Image": "microsoft/azureiotedge-simulated-temperature-sensor:1.0",
+                    "Env": [
+                        "EdgeHubConnectionString=HostName=hub;SharedAccessKey=c2VjcmV0",
+                        "IOTEDGE_IOTHUBHOSTNAME=hub.azure-devices.net",
+                        "EMPTY"
+                    ],
+                    "Labels": {
+                        "net.azure-devices.edge.owner": "Microsoft.Azure.Devices.Edge.Agent"
+                    }
+                },
+                "HostConfig": { "Dns": ["1.1.1.1"] }
+            }"#,
+        ).unwrap();
+
+        let inspection = module_inspection(&container).unwrap();
+        assert_eq!("tempSensor", inspection.name());
+        assert_eq!(
+            "microsoft/azureiotedge-simulated-temperature-sensor:1.0",
+            inspection.image()
+        );
+        assert_eq!(Some("sha256:8b1f"), inspection.image_id());
+        assert_eq!(
+            "HostName=hub;SharedAccessKey=c2VjcmV0",
+            inspection.env()["EdgeHubConnectionString"]
+        );
+        assert_eq!(
+            "hub.azure-devices.net",
+            inspection.env()["IOTEDGE_IOTHUBHOSTNAME"]
+        );
+        assert_eq!("", inspection.env()["EMPTY"]);
+        assert_eq!(
+            "Microsoft.Azure.Devices.Edge.Agent",
+            inspection.labels()["net.azure-devices.edge.owner"]
+        );
+        assert_eq!(
+            json!(["1.1.1.1"]),
+            inspection.create_options()["HostConfig"]["Dns"]
+        );
+        assert_eq!(3, inspection.create_options()["Env"].as_array().unwrap().len());
+    }
+
     #[test]
     fn create_fails_for_non_docker_type() {
         let mri = DockerModuleRuntime::new(&Url::parse("http://localhost/").unwrap()).unwrap();
@@ -701,6 +2313,63 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn start_fails_for_invalid_id() {
+        let mri = DockerModuleRuntime::new(&Url::parse("http://localhost/").unwrap()).unwrap();
+
+        let task = mri.start("m1/../../images").then(|result| match result {
+            Ok(_) => panic!("Expected test to fail but it didn't!"),
+            Err(err) => match *err.kind() {
+                ErrorKind::Utils => {
+                    assert_eq!(
+                        r#"id must match ^[a-zA-Z0-9][a-zA-Z0-9_.-]*$, got "m1/../../images""#,
+                        Fail::cause(&err).unwrap().to_string()
+                    );
+                    Ok::<_, Error>(())
+                }
+                _ => panic!("Expected utils error. Got some other error."),
+            },
+        });
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+    }
+
+    #[test]
+    fn operations_are_counted() {
+        let metrics = MetricsRegistry::new();
+        let mri = DockerModuleRuntime::new(&Url::parse("http://localhost/").unwrap())
+            .unwrap()
+            .with_metrics(Arc::new(metrics.clone()));
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        assert!(runtime.block_on(mri.start("")).is_err());
+        assert!(runtime.block_on(mri.stop("", None)).is_err());
+        assert!(runtime.block_on(mri.start("m1/../images")).is_err());
+
+        assert_eq!(
+            2,
+            metrics.counter(&RUNTIME_OPERATIONS, &[("operation", "start")])
+        );
+        assert_eq!(
+            1,
+            metrics.counter(&RUNTIME_OPERATIONS, &[("operation", "stop")])
+        );
+        assert_eq!(
+            2,
+            metrics.counter(
+                &RUNTIME_OPERATION_FAILURES,
+                &[("operation", "start"), ("error", "Utils")]
+            )
+        );
+        assert_eq!(
+            2,
+            metrics.histogram_count(&RUNTIME_OPERATION_DURATION, &[("operation", "start")])
+        );
+    }
+
     #[test]
     fn stop_fails_for_empty_id() {
         let mri = DockerModuleRuntime::new(&Url::parse("http://localhost/").unwrap()).unwrap();
@@ -825,6 +2494,10 @@ mod tests {
                     name: "c".to_string(),
                     runtime_state_behavior: TestModuleRuntimeStateBehavior::NotFound,
                 },
+                TestModule {
+                    name: "e".to_string(),
+                    runtime_state_behavior: TestModuleRuntimeStateBehavior::DockerNotFound,
+                },
                 TestModule {
                     name: "d".to_string(),
                     runtime_state_behavior: TestModuleRuntimeStateBehavior::Default,
@@ -853,12 +2526,186 @@ mod tests {
         );
     }
 
+    #[test]
+    fn list_with_details_fails_on_other_errors() {
+        let runtime = TestModuleList {
+            modules: vec![
+                TestModule {
+                    name: "a".to_string(),
+                    runtime_state_behavior: TestModuleRuntimeStateBehavior::Default,
+                },
+                TestModule {
+                    name: "b".to_string(),
+                    runtime_state_behavior: TestModuleRuntimeStateBehavior::DockerFailed,
+                },
+            ],
+        };
+
+        let err = runtime.list_with_details().collect().wait().unwrap_err();
+
+        match *err.kind() {
+            ErrorKind::FormattedDockerRuntime(ref message) => assert_eq!("boom", message),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    fn host_defaults_runtime() -> DockerModuleRuntime {
+        DockerModuleRuntime::new(&Url::parse("http://localhost:2375/").unwrap())
+            .unwrap()
+            .with_default_dns(vec![
+                "10.0.0.53".parse().unwrap(),
+                "fd00::53".parse().unwrap(),
+            ]).with_default_extra_hosts(vec!["gateway.local:10.0.0.1".to_string()])
+    }
+
+    #[test]
+    fn host_defaults_are_applied() {
+        let create_options = ContainerCreateBody::new()
+            .with_host_config(HostConfig::new().with_memory(3_221_225_472));
+        let create_options = host_defaults_runtime().apply_host_defaults(create_options);
+
+        let host_config = create_options.host_config().unwrap();
+        assert_eq!(
+            Some(&["10.0.0.53".to_string(), "fd00::53".to_string()][..]),
+            host_config.dns()
+        );
+        assert_eq!(
+            Some(&["gateway.local:10.0.0.1".to_string()][..]),
+            host_config.extra_hosts()
+        );
+        assert_eq!(Some(3_221_225_472), host_config.memory());
+    }
+
+    #[test]
+    fn host_defaults_are_applied_without_host_config() {
+        let create_options = host_defaults_runtime().apply_host_defaults(ContainerCreateBody::new());
+
+        let host_config = create_options.host_config().unwrap();
+        assert_eq!(2, host_config.dns().unwrap().len());
+        assert_eq!(1, host_config.extra_hosts().unwrap().len());
+    }
+
+    #[test]
+    fn module_host_settings_override_defaults() {
+        let create_options = ContainerCreateBody::new().with_host_config(
+            HostConfig::new()
+                .with_dns(vec!["8.8.8.8".to_string()])
+                .with_extra_hosts(vec![]),
+        );
+        let create_options = host_defaults_runtime().apply_host_defaults(create_options);
+
+        let host_config = create_options.host_config().unwrap();
+        assert_eq!(Some(&["8.8.8.8".to_string()][..]), host_config.dns());
+        assert_eq!(Some(&[][..]), host_config.extra_hosts());
+    }
+
+    #[test]
+    fn no_host_defaults_leave_create_options_alone() {
+        let runtime =
+            DockerModuleRuntime::new(&Url::parse("http://localhost:2375/").unwrap()).unwrap();
+        let create_options = runtime.apply_host_defaults(ContainerCreateBody::new());
+        assert!(create_options.host_config().is_none());
+    }
+
+    fn log_stream(chunks: Vec<&'static str>) -> Logs {
+        Logs(Body::wrap_stream(stream::iter_ok::<_, io::Error>(chunks)))
+    }
+
+    #[test]
+    fn chunk_converts_to_bytes_without_copying() {
+        // Short buffers are stored inline and copied on every move, so use
+        // one that is long enough to be shared.
+        let bytes = Bytes::from(vec![b'x'; 1024]);
+        let chunk = Chunk(HyperChunk::from(bytes.clone()));
+
+        let converted = Bytes::from(chunk);
+        assert_eq!(bytes, converted);
+        assert_eq!(bytes.as_ptr(), converted.as_ptr());
+    }
+
+    #[test]
+    fn chunk_converts_to_hyper_chunk_without_copying() {
+        let bytes = Bytes::from(vec![b'x'; 1024]);
+        let chunk = Chunk(HyperChunk::from(bytes.clone()));
+
+        let converted = HyperChunk::from(chunk);
+        assert_eq!(&bytes[..], &converted[..]);
+        assert_eq!(bytes.as_ptr(), converted.as_ptr());
+    }
+
+    #[test]
+    fn chunk_is_buf() {
+        let mut chunk = Chunk(HyperChunk::from("module started"));
+        assert_eq!(14, chunk.remaining());
+
+        chunk.advance(7);
+        assert_eq!(b"started", Buf::bytes(&chunk));
+        assert_eq!(b"started", chunk.as_ref());
+    }
+
+    #[test]
+    fn logs_forward_without_copying() {
+        let chunks = log_stream(vec!["first line\n", "second line\n"])
+            .map(|chunk| {
+                let ptr = chunk.as_ref().as_ptr();
+                let forwarded = HyperChunk::from(chunk);
+                assert_eq!(ptr, forwarded.as_ptr());
+                forwarded
+            }).collect()
+            .wait()
+            .unwrap();
+
+        let lines: Vec<&[u8]> = chunks.iter().map(AsRef::as_ref).collect();
+        assert_eq!(vec![&b"first line\n"[..], &b"second line\n"[..]], lines);
+    }
+
+    #[test]
+    fn logs_forwarded_through_a_body_are_unchanged() {
+        let logs = log_stream(vec!["first line\n", "", "second line\n"]);
+        let body = Body::wrap_stream(logs.map(HyperChunk::from).map_err(Fail::compat));
+        let forwarded = body.concat2().wait().unwrap();
+        assert_eq!(&b"first line\nsecond line\n"[..], &forwarded[..]);
+
+        let bytes = log_stream(vec!["first line\n", "second line\n"])
+            .map(Bytes::from)
+            .concat2()
+            .wait()
+            .unwrap();
+        assert_eq!(&b"first line\nsecond line\n"[..], &bytes[..]);
+    }
+
+    #[test]
+    fn preserved_volumes_yield_to_new_mounts() {
+        let old: InlineResponse200 = serde_json::from_value(json!({
+            "Mounts": [
+                { "Type": "volume", "Name": "2c8b1e0f9a7d", "Destination": "/var/cache" },
+                { "Type": "volume", "Name": "4f1d9b3c7e2a", "Destination": "/var/lib/data" }
+            ]
+        })).unwrap();
+        let preserved = Preserved::new(&old);
+
+        let create_options = ContainerCreateBody::new().with_host_config(
+            HostConfig::new().with_binds(vec!["cache:/var/cache:ro".to_string()]),
+        );
+        let create_options = preserved.apply(create_options);
+
+        let host_config = create_options.host_config().unwrap();
+        let mounts = host_config.mounts().unwrap();
+        assert_eq!(1, mounts.len());
+        assert_eq!(Some("4f1d9b3c7e2a"), mounts[0].source());
+        assert_eq!(Some("/var/lib/data"), mounts[0].target());
+        assert!(create_options.networking_config().is_none());
+    }
+
     struct TestConfig;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     enum TestModuleRuntimeStateBehavior {
         Default,
         NotFound,
+        /// A 404 from docker without a message, as it comes over the wire.
+        DockerNotFound,
+        DockerFailed,
     }
 
     #[derive(Clone, Debug, PartialEq)]
@@ -890,7 +2737,25 @@ mod tests {
                     future::ok(ModuleRuntimeState::default().with_pid(Pid::Any))
                 }
                 TestModuleRuntimeStateBehavior::NotFound => {
-                    future::err(ErrorKind::NotFound(String::new()).into())
+                    future::err(ErrorKind::ModuleNotFound(self.name.clone()).into())
+                }
+                TestModuleRuntimeStateBehavior::DockerNotFound => {
+                    future::err(Error::for_module(
+                        &self.name,
+                        DockerError::Api(DockerApiError {
+                            code: StatusCode::NOT_FOUND,
+                            content: None,
+                        }),
+                    ))
+                }
+                TestModuleRuntimeStateBehavior::DockerFailed => {
+                    future::err(Error::for_module(
+                        &self.name,
+                        DockerError::Api(DockerApiError {
+                            code: StatusCode::INTERNAL_SERVER_ERROR,
+                            content: Some(json!({ "message": "boom" })),
+                        }),
+                    ))
                 }
             }
         }
@@ -934,8 +2799,14 @@ mod tests {
         type RestartFuture = FutureResult<(), Self::Error>;
         type StartFuture = FutureResult<(), Self::Error>;
         type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
         type SystemInfoFuture = FutureResult<CoreSystemInfo, Self::Error>;
         type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = FutureResult<(), Self::Error>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
 
         fn init(&self) -> Self::InitFuture {
             unimplemented!()
@@ -961,6 +2832,10 @@ mod tests {
             unimplemented!()
         }
 
+        fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
+            unimplemented!()
+        }
+
         fn remove(&self, _id: &str) -> Self::RemoveFuture {
             unimplemented!()
         }
@@ -984,5 +2859,25 @@ mod tests {
         fn remove_all(&self) -> Self::RemoveAllFuture {
             unimplemented!()
         }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            unimplemented!()
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            unimplemented!()
+        }
+
+        fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+            unimplemented!()
+        }
+
+        fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            unimplemented!()
+        }
+
+        fn top(&self, _id: &str) -> Self::TopFuture {
+            unimplemented!()
+        }
     }
 }
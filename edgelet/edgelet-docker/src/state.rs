@@ -0,0 +1,279 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use edgelet_core::{Discrepancy, IntegrityReport};
+use serde_json;
+
+/// A module the runtime created, with the image it was created from.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ModuleRecord {
+    image: String,
+    #[serde(rename = "imageId", skip_serializing_if = "Option::is_none")]
+    image_id: Option<String>,
+}
+
+impl ModuleRecord {
+    pub fn new(image: String, image_id: Option<String>) -> Self {
+        ModuleRecord { image, image_id }
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub fn image_id(&self) -> Option<&str> {
+        self.image_id.as_ref().map(AsRef::as_ref)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ModuleState {
+    modules: BTreeMap<String, ModuleRecord>,
+}
+
+/// Remembers across restarts of the daemon which modules the runtime created
+/// and which images they run, so that modules that went missing or were
+/// changed behind the daemon's back can be reported.
+///
+/// The state is kept in a JSON file. A file that is missing or cannot be read
+/// is rebuilt from the modules that exist the next time they are checked.
+/// Failing to write the file is logged but does not fail the operation that
+/// changed the state.
+#[derive(Clone, Debug)]
+pub struct ModuleStateStore {
+    path: PathBuf,
+    // `None` until the state has been read or rebuilt.
+    state: Arc<Mutex<Option<ModuleState>>>,
+}
+
+impl ModuleStateStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let state = match fs::read(&path) {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(state) => Some(state),
+                Err(err) => {
+                    warn!(
+                        "Module state in {} is corrupt and will be rebuilt: {}",
+                        path.display(),
+                        err
+                    );
+                    None
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!(
+                    "Could not read module state from {} and will rebuild it: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        };
+
+        ModuleStateStore {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records that the module `name` was created.
+    pub fn record_created(&self, name: &str, record: ModuleRecord) {
+        self.update(|state| {
+            state.modules.insert(name.to_string(), record);
+        });
+    }
+
+    /// Records that the module `name` was removed.
+    pub fn record_removed(&self, name: &str) {
+        self.update(|state| {
+            state.modules.remove(name);
+        });
+    }
+
+    /// Compares the recorded modules with the modules that exist. When there
+    /// is no usable record, it is rebuilt from the modules that exist instead.
+    pub fn check(&self, live: BTreeMap<String, ModuleRecord>) -> IntegrityReport {
+        let mut guard = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(ref state) = *guard {
+            return IntegrityReport::new(compare(&state.modules, &live));
+        }
+
+        let state = ModuleState { modules: live };
+        self.save(&state);
+        *guard = Some(state);
+        IntegrityReport::default().with_rebuilt(true)
+    }
+
+    fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut ModuleState),
+    {
+        let mut guard = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        // Until the state is rebuilt, the modules that exist are the only
+        // record there is, so changes don't need recording.
+        if let Some(ref mut state) = *guard {
+            f(state);
+            self.save(state);
+        }
+    }
+
+    fn save(&self, state: &ModuleState) {
+        // Write a temporary file and move it over the old one, so that the
+        // file is never left half written.
+        let tmp = self.path.with_extension("tmp");
+        let result = serde_json::to_vec(state)
+            .map_err(io::Error::from)
+            .and_then(|contents| fs::write(&tmp, contents))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if let Err(err) = result {
+            warn!(
+                "Could not save module state to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn compare(
+    recorded: &BTreeMap<String, ModuleRecord>,
+    live: &BTreeMap<String, ModuleRecord>,
+) -> Vec<Discrepancy> {
+    let mut discrepancies = vec![];
+    for (name, record) in recorded {
+        match live.get(name) {
+            None => discrepancies.push(Discrepancy::Missing(name.clone())),
+            Some(actual) => {
+                if let (Some(expected), Some(actual)) = (record.image_id(), actual.image_id()) {
+                    if expected != actual {
+                        discrepancies.push(Discrepancy::ImageChanged {
+                            name: name.clone(),
+                            expected: expected.to_string(),
+                            actual: actual.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for name in live.keys() {
+        if !recorded.contains_key(name) {
+            discrepancies.push(Discrepancy::Unexpected(name.clone()));
+        }
+    }
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn test_path(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("edgelet-docker-state-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("module_state.json")
+    }
+
+    fn modules(modules: &[(&str, &str)]) -> BTreeMap<String, ModuleRecord> {
+        modules
+            .iter()
+            .map(|&(name, image_id)| {
+                (
+                    name.to_string(),
+                    ModuleRecord::new("image".to_string(), Some(image_id.to_string())),
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn missing_store_is_rebuilt_from_live_modules() {
+        let path = test_path("missing");
+        let store = ModuleStateStore::open(&path);
+
+        let report = store.check(modules(&[("edgeAgent", "sha256:1")]));
+        assert!(report.rebuilt());
+        assert!(report.is_consistent());
+
+        let report = ModuleStateStore::open(&path).check(modules(&[("edgeAgent", "sha256:1")]));
+        assert!(!report.rebuilt());
+        assert!(report.is_consistent());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_store_is_rebuilt_from_live_modules() {
+        let path = test_path("corrupt");
+        fs::write(&path, b"{\"modules\": {\"edgeAg").unwrap();
+        let store = ModuleStateStore::open(&path);
+
+        let report = store.check(modules(&[("edgeAgent", "sha256:1")]));
+        assert!(report.rebuilt());
+        assert!(report.is_consistent());
+
+        let report = ModuleStateStore::open(&path).check(modules(&[]));
+        assert!(!report.rebuilt());
+        assert_eq!(
+            &[Discrepancy::Missing("edgeAgent".to_string())],
+            report.discrepancies()
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mismatches_are_detected() {
+        let path = test_path("mismatch");
+        let store = ModuleStateStore::open(&path);
+        store.check(modules(&[("edgeAgent", "sha256:1")]));
+        store.record_created(
+            "edgeHub",
+            ModuleRecord::new("image".to_string(), Some("sha256:2".to_string())),
+        );
+        store.record_created(
+            "tempSensor",
+            ModuleRecord::new("image".to_string(), Some("sha256:3".to_string())),
+        );
+
+        let store = ModuleStateStore::open(&path);
+        let report = store.check(modules(&[
+            ("edgeAgent", "sha256:1"),
+            ("edgeHub", "sha256:4"),
+            ("rogue", "sha256:5"),
+        ]));
+        assert!(!report.is_consistent());
+        assert_eq!(
+            &[
+                Discrepancy::ImageChanged {
+                    name: "edgeHub".to_string(),
+                    expected: "sha256:2".to_string(),
+                    actual: "sha256:4".to_string(),
+                },
+                Discrepancy::Missing("tempSensor".to_string()),
+                Discrepancy::Unexpected("rogue".to_string()),
+            ],
+            report.discrepancies()
+        );
+
+        store.record_removed("tempSensor");
+        let report = store.check(modules(&[
+            ("edgeAgent", "sha256:1"),
+            ("edgeHub", "sha256:2"),
+        ]));
+        assert!(report.is_consistent());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
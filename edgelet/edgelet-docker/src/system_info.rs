@@ -0,0 +1,126 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! The system information the container runtime reported last. Some daemons
+//! take a good part of a second to answer /info, since they enumerate their
+//! plugins and storage on every request, and the watchdog asks for it on
+//! every cycle.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use edgelet_core::SystemInfo as CoreSystemInfo;
+
+use error::{Error, ErrorKind};
+
+/// How long the system information is kept before the container runtime is
+/// asked for it again.
+pub const DEFAULT_SYSTEM_INFO_TTL_SECS: u64 = 300;
+
+#[derive(Default)]
+struct Cached {
+    info: Option<(CoreSystemInfo, Instant)>,
+    /// Bumped whenever the information is invalidated, so that information
+    /// asked for before then isn't cached once it comes back.
+    generation: usize,
+}
+
+/// Clones share the cached information.
+#[derive(Clone, Default)]
+pub struct SystemInfoCache {
+    cached: Arc<Mutex<Cached>>,
+}
+
+impl SystemInfoCache {
+    /// The cached information, unless it is `ttl` or more old at `now`.
+    pub fn get(&self, now: Instant, ttl: Duration) -> Option<CoreSystemInfo> {
+        self.lock().info.as_ref().and_then(|(info, cached)| {
+            if now.duration_since(*cached) < ttl {
+                Some(info.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The generation to store information asked for now under.
+    pub fn generation(&self) -> usize {
+        self.lock().generation
+    }
+
+    /// Keeps `info`, unless the cache was invalidated since `generation`.
+    pub fn store(&self, generation: usize, info: &CoreSystemInfo, now: Instant) {
+        let mut cached = self.lock();
+        if cached.generation == generation {
+            cached.info = Some((info.clone(), now));
+        }
+    }
+
+    pub fn invalidate(&self) {
+        let mut cached = self.lock();
+        cached.info = None;
+        cached.generation = cached.generation.wrapping_add(1);
+    }
+
+    fn lock(&self) -> MutexGuard<Cached> {
+        // Every change leaves the cache consistent, so a panic while the
+        // lock was held doesn't matter.
+        self.cached.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Whether `err` says the container runtime couldn't be reached, which is
+/// what a daemon that is restarting, say to be upgraded, looks like.
+pub fn is_connection_error(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::Docker | ErrorKind::Timeout | ErrorKind::OperationTimedOut(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(os_type: &str) -> CoreSystemInfo {
+        CoreSystemInfo::new(os_type.to_string(), "x86_64".to_string())
+    }
+
+    #[test]
+    fn information_is_kept_for_the_ttl() {
+        let cache = SystemInfoCache::default();
+        let ttl = Duration::from_secs(300);
+        let start = Instant::now();
+        assert!(cache.get(start, ttl).is_none());
+
+        cache.store(cache.generation(), &info("linux"), start);
+        let cached = cache.clone().get(start + Duration::from_secs(299), ttl);
+        assert_eq!(Some("linux"), cached.as_ref().map(CoreSystemInfo::os_type));
+        assert!(cache.get(start + ttl, ttl).is_none());
+    }
+
+    #[test]
+    fn information_asked_for_before_invalidation_is_not_kept() {
+        let cache = SystemInfoCache::default();
+        let ttl = Duration::from_secs(300);
+        let start = Instant::now();
+        cache.store(cache.generation(), &info("linux"), start);
+
+        let generation = cache.generation();
+        cache.clone().invalidate();
+        assert!(cache.get(start, ttl).is_none());
+        cache.store(generation, &info("windows"), start);
+        assert!(cache.get(start, ttl).is_none());
+    }
+
+    #[test]
+    fn only_unreachable_runtimes_are_connection_errors() {
+        assert!(is_connection_error(&Error::from(ErrorKind::Docker)));
+        assert!(is_connection_error(&Error::from(ErrorKind::Timeout)));
+        assert!(is_connection_error(&Error::from(
+            ErrorKind::OperationTimedOut("system_info")
+        )));
+        assert!(!is_connection_error(&Error::from(ErrorKind::NotFound(
+            "no such image".to_string()
+        ))));
+    }
+}
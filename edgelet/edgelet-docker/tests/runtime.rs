@@ -10,6 +10,7 @@
 
 #[cfg(unix)]
 extern crate base64;
+extern crate failure;
 extern crate futures;
 extern crate hyper;
 #[macro_use]
@@ -24,12 +25,14 @@ extern crate edgelet_docker;
 extern crate edgelet_test_utils;
 
 use std::collections::HashMap;
+use std::path::Path;
 use std::str;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+use failure::Fail;
 use futures::prelude::*;
-use futures::{future, Stream};
+use futures::{future, stream, Stream};
 use hyper::{Body, Error as HyperError, Method, Request, Response};
 use typed_headers::{mime, ContentLength, ContentType, HeaderMapExt};
 use url::form_urlencoded::parse as parse_query;
@@ -41,9 +44,17 @@ use docker::models::{
     ContainerCreateBody, ContainerHostConfig, ContainerNetworkSettings, ContainerSummary,
     HostConfig, HostConfigPortBindings, ImageDeleteResponseItem,
 };
-use edgelet_core::{LogOptions, LogTail, Module, ModuleRegistry, ModuleRuntime, ModuleSpec};
+use edgelet_core::metrics::{RUNTIME_OPERATION_DURATION, RUNTIME_SLOW_OPERATIONS};
+use edgelet_core::pid::Pid;
+use edgelet_core::{
+    Error as CoreError, KillSignals, LogOptions, LogTail, MetricsRegistry, Module, ModuleEpochs,
+    ModuleRegistry, ModuleRuntime, ModuleSpec, RegistryCredential, RegistryCredentials,
+    RuntimeErrorLog,
+};
+#[cfg(unix)]
+use edgelet_docker::RegistryMirror;
 use edgelet_docker::{DockerConfig, DockerModuleRuntime};
-use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server, TestClock};
 
 const IMAGE_NAME: &str = "nginx:latest";
 
@@ -450,6 +461,393 @@ fn image_pull_with_creds_succeeds() {
     runtime.block_on(task).unwrap();
 }
 
+struct TestCredentials;
+
+impl RegistryCredentials for TestCredentials {
+    fn credential(&self, name: &str) -> Result<Option<RegistryCredential>, CoreError> {
+        Ok(match name {
+            "contoso" => Some(RegistryCredential::new(
+                "contoso.azurecr.io".to_string(),
+                "contoso".to_string(),
+                "hunter2".to_string(),
+            )),
+            "mirror" => Some(RegistryCredential::new(
+                "mirror.local:5000".to_string(),
+                "mirror".to_string(),
+                "hunter3".to_string(),
+            )),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn image_pull_with_credential_ref_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.uri().path(), "/images/create");
+
+    let auth = req.headers().get("X-Registry-Auth").unwrap();
+    let auth_config: AuthConfig = serde_json::from_slice(&base64::decode(auth).unwrap()).unwrap();
+    assert_eq!(auth_config.username(), Some("contoso"));
+    assert_eq!(auth_config.password(), Some("hunter2"));
+    assert_eq!(auth_config.serveraddress(), Some("contoso.azurecr.io"));
+
+    let response = json!({
+        "Id": "img1",
+        "Warnings": []
+    }).to_string();
+
+    let mut response = Response::new(response.into());
+    response
+        .headers_mut()
+        .typed_insert(&ContentType(mime::APPLICATION_JSON));
+    Box::new(future::ok(response))
+}
+
+#[cfg(unix)]
+#[test]
+fn image_pull_with_credential_ref_succeeds() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, image_pull_with_credential_ref_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_registry_credentials(Arc::new(TestCredentials));
+
+    let config = DockerConfig::new(IMAGE_NAME, ContainerCreateBody::new(), None)
+        .unwrap()
+        .with_credential_ref("contoso".to_string());
+
+    let task = mri.pull(&config);
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+}
+
+#[test]
+fn image_pull_with_unknown_credential_ref_fails() {
+    let mri = DockerModuleRuntime::new(&Url::parse("http://localhost:2375/").unwrap())
+        .unwrap()
+        .with_registry_credentials(Arc::new(TestCredentials));
+
+    let config = DockerConfig::new(IMAGE_NAME, ContainerCreateBody::new(), None)
+        .unwrap()
+        .with_credential_ref("fabrikam".to_string());
+
+    let err = mri
+        .pull(&config)
+        .wait()
+        .expect_err("Expected runtime pull method to fail due to unknown credential.");
+
+    if let edgelet_docker::ErrorKind::NotFound(message) = err.kind() {
+        assert_eq!("Registry credential fabrikam not found", message);
+    } else {
+        panic!("Not found error is expected for unknown credential.");
+    }
+}
+
+const UPSTREAM_IMAGE_NAME: &str = "$upstream:443/azureiotedge-agent:1.0";
+const PARENT_IMAGE_NAME: &str = "parent.contoso.com:443/azureiotedge-agent:1.0";
+
+/// Checks that the image is pulled and created from the parent device.
+fn upstream_image_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    let response = json!({
+        "Id": "12345",
+        "Warnings": []
+    }).to_string();
+    let respond = move || {
+        let mut response = Response::new(response.into());
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        response
+    };
+
+    match req.uri().path() {
+        "/images/create" => {
+            let from_image = parse_query(req.uri().query().unwrap().as_bytes())
+                .find(|(key, _)| key == "fromImage")
+                .map(|(_, value)| value.into_owned());
+            assert_eq!(Some(PARENT_IMAGE_NAME.to_string()), from_image);
+            Box::new(future::ok(respond()))
+        }
+        "/containers/create" => Box::new(req.into_body().concat2().map(move |body| {
+            let create_options: ContainerCreateBody =
+                serde_json::from_slice(body.as_ref()).unwrap();
+            assert_eq!(Some(PARENT_IMAGE_NAME), create_options.image());
+            respond()
+        })),
+        path => panic!("unexpected request to {}", path),
+    }
+}
+
+#[test]
+fn upstream_images_are_pulled_and_created_from_parent() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, upstream_image_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_parent_hostname(Some("parent.contoso.com".to_string()));
+    let config = DockerConfig::new(UPSTREAM_IMAGE_NAME, ContainerCreateBody::new(), None).unwrap();
+    let module = ModuleSpec::new("edgeAgent", "docker", config.clone(), HashMap::new()).unwrap();
+
+    let task = mri.pull(&config).and_then(move |_| mri.create(module));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+}
+
+#[test]
+fn upstream_images_need_parent_hostname() {
+    let mri = DockerModuleRuntime::new(&Url::parse("http://localhost:2375/").unwrap()).unwrap();
+    let config = DockerConfig::new(UPSTREAM_IMAGE_NAME, ContainerCreateBody::new(), None).unwrap();
+
+    let err = mri
+        .pull(&config)
+        .wait()
+        .expect_err("Expected runtime pull method to fail without a parent hostname.");
+
+    match err.kind() {
+        edgelet_docker::ErrorKind::NoParentHostname(image) => {
+            assert_eq!(UPSTREAM_IMAGE_NAME, image.as_str())
+        }
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+}
+
+#[cfg(unix)]
+const MIRRORED_IMAGE_NAME: &str = "contoso.azurecr.io/sensor:1.0";
+#[cfg(unix)]
+const MIRROR_IMAGE_NAME: &str = "mirror.local:5000/sensor:1.0";
+
+/// Pulls `MIRRORED_IMAGE_NAME` through a mirror that fails pulls with
+/// `mirror_message` unless it's empty, and creates a module from it. Returns
+/// the images that were pulled, with the user they were pulled as, and that
+/// the module was created from.
+#[cfg(unix)]
+fn pull_through_mirror(
+    mirror_message: &'static str,
+) -> (Result<(), edgelet_docker::Error>, Vec<String>) {
+    let requests = Arc::new(RwLock::new(vec![]));
+    let requests_cloned = requests.clone();
+
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        match req.uri().path() {
+            "/images/create" => {
+                let from_image = parse_query(req.uri().query().unwrap().as_bytes())
+                    .find(|(key, _)| key == "fromImage")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap();
+                let auth = req.headers().get("X-Registry-Auth").unwrap();
+                let username = if auth.is_empty() {
+                    None
+                } else {
+                    let auth: AuthConfig =
+                        serde_json::from_slice(&base64::decode(auth).unwrap()).unwrap();
+                    auth.username().map(ToString::to_string)
+                };
+                requests.write().unwrap().push(format!(
+                    "pull {} as {}",
+                    from_image,
+                    username.unwrap_or_default()
+                ));
+                let response = if from_image == MIRROR_IMAGE_NAME && !mirror_message.is_empty() {
+                    json_response(
+                        hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                        &json!({ "message": mirror_message }),
+                    )
+                } else {
+                    json_response(hyper::StatusCode::OK, &json!({}))
+                };
+                future::Either::A(future::ok(response))
+            }
+            "/containers/create" => {
+                let requests = requests.clone();
+                future::Either::B(req.into_body().concat2().map(move |body| {
+                    let create_options: ContainerCreateBody =
+                        serde_json::from_slice(body.as_ref()).unwrap();
+                    requests
+                        .write()
+                        .unwrap()
+                        .push(format!("create {}", create_options.image().unwrap()));
+                    json_response(hyper::StatusCode::CREATED, &json!({ "Id": "12345" }))
+                }))
+            }
+            path => panic!("unexpected request to {}", path),
+        }
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_registry_credentials(Arc::new(TestCredentials))
+            .with_registry_mirrors(vec![
+                RegistryMirror::new(
+                    "contoso.azurecr.io".to_string(),
+                    "mirror.local:5000".to_string(),
+                ).with_credential(Some("mirror".to_string())),
+            ]);
+    let config = DockerConfig::new(MIRRORED_IMAGE_NAME, ContainerCreateBody::new(), None)
+        .unwrap()
+        .with_credential_ref("contoso".to_string());
+    let module = ModuleSpec::new("sensor", "docker", config.clone(), HashMap::new()).unwrap();
+
+    let task = mri.pull(&config).and_then(move |_| mri.create(module));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let result = runtime.block_on(task);
+
+    let requests = requests_cloned.read().unwrap().clone();
+    (result, requests)
+}
+
+#[test]
+fn images_a_container_uses_are_not_pruned() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let (status, response) = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/images/json") => {
+                let (_, filters) = parse_query(req.uri().query().unwrap().as_bytes())
+                    .find(|(key, _)| key == "filters")
+                    .unwrap();
+                let filters: HashMap<String, Vec<String>> =
+                    serde_json::from_str(&filters).unwrap();
+                assert_eq!(
+                    vec!["net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent"],
+                    filters["label"]
+                );
+
+                let image = |id: &str, tag: &str, size: i32| {
+                    json!({
+                        "Id": id,
+                        "ParentId": "",
+                        "RepoTags": [tag],
+                        "RepoDigests": [],
+                        "Created": 1_539_612_000,
+                        "Size": size,
+                        "SharedSize": 0,
+                        "VirtualSize": size,
+                        "Labels": {},
+                        "Containers": 0
+                    })
+                };
+                (
+                    hyper::StatusCode::OK,
+                    json!([
+                        image("sha256:e216a057b1cb", "edge-agent:1.0", 10),
+                        image("sha256:3f3a4b2c1d0e", "other:latest", 20),
+                    ]),
+                )
+            }
+            (&Method::DELETE, "/images/sha256:e216a057b1cb") => {
+                (hyper::StatusCode::OK, json!([{ "Deleted": "sha256:e216a057b1cb" }]))
+            }
+            (&Method::DELETE, "/images/sha256:3f3a4b2c1d0e") => (
+                hyper::StatusCode::CONFLICT,
+                json!({ "message": "image is being used by stopped container 4d5e" }),
+            ),
+            (method, path) => panic!("Unexpected request {} {}", method, path),
+        };
+
+        let mut response = Response::new(response.to_string().into());
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        Box::new(future::ok(response))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let images = runtime.block_on(mri.images()).unwrap();
+    assert_eq!(2, images.len());
+    assert_eq!("sha256:e216a057b1cb", images[0].id());
+    assert_eq!(&["edge-agent:1.0".to_string()], images[0].names());
+    assert_eq!(10, images[0].size());
+    assert_eq!(
+        Some(1_539_612_000),
+        images[0].created_at().map(|created_at| created_at.timestamp())
+    );
+
+    let ids = images.iter().map(|image| image.id().to_string()).collect();
+    let pruned = runtime.block_on(mri.prune_images(ids)).unwrap();
+    assert_eq!(vec!["sha256:e216a057b1cb".to_string()], pruned);
+}
+
+#[cfg(unix)]
+fn json_response(status: hyper::StatusCode, body: &serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(body.to_string().into());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .typed_insert(&ContentType(mime::APPLICATION_JSON));
+    response
+}
+
+#[cfg(unix)]
+#[test]
+fn images_are_pulled_and_created_from_mirror() {
+    let (result, requests) = pull_through_mirror("");
+
+    result.unwrap();
+    assert_eq!(
+        vec![
+            format!("pull {} as mirror", MIRROR_IMAGE_NAME),
+            format!("create {}", MIRROR_IMAGE_NAME),
+        ],
+        requests
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn images_are_pulled_from_registry_when_mirror_is_down() {
+    let (result, requests) = pull_through_mirror(
+        "Get https://mirror.local:5000/v2/: dial tcp 10.0.0.4:5000: connect: connection refused",
+    );
+
+    result.unwrap();
+    assert_eq!(
+        vec![
+            format!("pull {} as mirror", MIRROR_IMAGE_NAME),
+            format!("pull {} as contoso", MIRRORED_IMAGE_NAME),
+            format!("create {}", MIRRORED_IMAGE_NAME),
+        ],
+        requests
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn images_are_not_pulled_from_registry_when_mirror_refuses_credentials() {
+    let (result, requests) = pull_through_mirror("unauthorized: authentication required");
+
+    match result.unwrap_err().kind() {
+        edgelet_docker::ErrorKind::FormattedDockerRuntime(message) => {
+            assert!(message.contains("unauthorized"), "{}", message)
+        }
+        kind => panic!("unexpected error kind {:?}", kind),
+    }
+    assert_eq!(vec![format!("pull {} as mirror", MIRROR_IMAGE_NAME)], requests);
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 fn image_remove_handler(
     req: Request<Body>,
@@ -631,105 +1029,1020 @@ fn container_create_succeeds() {
     runtime.block_on(task).unwrap();
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn container_start_handler(
+fn container_create_with_host_defaults_handler(
     req: Request<Body>,
 ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
     assert_eq!(req.method(), &Method::POST);
-    assert_eq!(req.uri().path(), "/containers/m1/start");
+    assert_eq!(req.uri().path(), "/containers/create");
 
-    Box::new(future::ok(Response::new(Body::empty())))
+    let module = parse_query(req.uri().query().unwrap().as_bytes())
+        .find(|(key, _)| key == "name")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+    let response = json!({
+        "Id": "12345",
+        "Warnings": []
+    }).to_string();
+
+    Box::new(req.into_body().concat2().map(move |body| {
+        let create_options: ContainerCreateBody = serde_json::from_slice(body.as_ref()).unwrap();
+        let host_config = create_options.host_config().unwrap();
+
+        if module == "m1" {
+            assert_eq!(Some(&["10.0.0.53".to_string()][..]), host_config.dns());
+            assert_eq!(
+                Some(&["gateway.local:10.0.0.1".to_string()][..]),
+                host_config.extra_hosts()
+            );
+        } else {
+            assert_eq!(Some(&["8.8.8.8".to_string()][..]), host_config.dns());
+            assert_eq!(
+                Some(&["m2.local:10.0.0.2".to_string()][..]),
+                host_config.extra_hosts()
+            );
+        }
+
+        let mut response = Response::new(response.into());
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        response
+    }))
 }
 
 #[test]
-fn container_start_succeeds() {
+fn container_create_applies_host_defaults() {
     let port = get_unused_tcp_port();
-    let server = run_tcp_server("127.0.0.1", port, container_start_handler)
-        .map_err(|err| eprintln!("{}", err));
+    let server = run_tcp_server(
+        "127.0.0.1",
+        port,
+        container_create_with_host_defaults_handler,
+    ).map_err(|err| eprintln!("{}", err));
 
     let mri =
         DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
-            .unwrap();
+            .unwrap()
+            .with_default_dns(vec!["10.0.0.53".parse().unwrap()])
+            .with_default_extra_hosts(vec!["gateway.local:10.0.0.1".to_string()]);
 
-    let task = mri.start("m1");
+    let defaults = ModuleSpec::new(
+        "m1",
+        "docker",
+        DockerConfig::new(IMAGE_NAME, ContainerCreateBody::new(), None).unwrap(),
+        HashMap::new(),
+    ).unwrap();
+    let own = ContainerCreateBody::new().with_host_config(
+        HostConfig::new()
+            .with_dns(vec!["8.8.8.8".to_string()])
+            .with_extra_hosts(vec!["m2.local:10.0.0.2".to_string()]),
+    );
+    let overrides = ModuleSpec::new(
+        "m2",
+        "docker",
+        DockerConfig::new(IMAGE_NAME, own, None).unwrap(),
+        HashMap::new(),
+    ).unwrap();
+
+    let task = mri.create(defaults).and_then(move |_| mri.create(overrides));
 
     let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
     runtime.spawn(server);
     runtime.block_on(task).unwrap();
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn container_stop_handler(
-    req: Request<Body>,
-) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-    assert_eq!(req.method(), &Method::POST);
-    assert_eq!(req.uri().path(), "/containers/m1/stop");
-
-    Box::new(future::ok(Response::new(Body::empty())))
-}
-
 #[test]
-fn container_stop_succeeds() {
+fn container_create_creates_module_volumes() {
+    let volume_created_lock = Arc::new(RwLock::new(false));
+    let volume_created_lock_cloned = volume_created_lock.clone();
+
     let port = get_unused_tcp_port();
-    let server = run_tcp_server("127.0.0.1", port, container_stop_handler)
-        .map_err(|err| eprintln!("{}", err));
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let volume_created_lock = volume_created_lock.clone();
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let mut status = hyper::StatusCode::OK;
+            let response = if method == Method::GET {
+                // The volume doesn't exist yet.
+                assert_eq!("/volumes/edgehub-data", path);
+                status = hyper::StatusCode::NOT_FOUND;
+                json!({ "message": "no such volume" }).to_string()
+            } else if path == "/volumes/create" {
+                assert_eq!(Method::POST, method);
+                let config: serde_json::Value = serde_json::from_slice(body.as_ref()).unwrap();
+                assert_eq!("edgehub-data", config["Name"]);
+                assert_eq!(
+                    "Microsoft.Azure.Devices.Edge.Agent",
+                    config["Labels"]["net.azure-devices.edge.owner"]
+                );
+                *volume_created_lock.write().unwrap() = true;
+
+                json!({
+                    "Name": "edgehub-data",
+                    "Driver": "local",
+                    "Mountpoint": "/var/lib/docker/volumes/edgehub-data/_data",
+                    "Labels": config["Labels"],
+                    "Scope": "local",
+                    "Options": {}
+                }).to_string()
+            } else {
+                assert_eq!(Method::POST, method);
+                assert_eq!("/containers/create", path);
+                assert_eq!(true, *volume_created_lock.read().unwrap());
+
+                json!({
+                    "Id": "12345",
+                    "Warnings": []
+                }).to_string()
+            };
+
+            let mut response = Response::new(response.into());
+            *response.status_mut() = status;
+            response
+                .headers_mut()
+                .typed_insert(&ContentType(mime::APPLICATION_JSON));
+            response
+        }))
+    }).map_err(|err| eprintln!("{}", err));
 
     let mri =
         DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
             .unwrap();
 
-    let task = mri.stop("m1", None);
+    let create_options = ContainerCreateBody::new().with_host_config(
+        HostConfig::new().with_binds(vec!["edgehub-data:/iotedge/storage".to_string()]),
+    );
+    let module_config = ModuleSpec::new(
+        "m1",
+        "docker",
+        DockerConfig::new(IMAGE_NAME, create_options, None).unwrap(),
+        HashMap::new(),
+    ).unwrap();
+
+    let task = mri.create(module_config);
 
     let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
     runtime.spawn(server);
     runtime.block_on(task).unwrap();
-}
-
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn container_stop_with_timeout_handler(
-    req: Request<Body>,
-) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-    assert_eq!(req.method(), &Method::POST);
-    assert_eq!(req.uri().path(), "/containers/m1/stop");
-    assert_eq!(req.uri().query().unwrap(), "t=600");
 
-    Box::new(future::ok(Response::new(Body::empty())))
+    assert_eq!(true, *volume_created_lock_cloned.read().unwrap());
 }
 
 #[test]
-fn container_stop_with_timeout_succeeds() {
+fn container_create_failure_removes_the_volumes_it_created() {
+    let requests = Arc::new(RwLock::new(vec![]));
+    let requests_cloned = requests.clone();
+
     let port = get_unused_tcp_port();
-    let server = run_tcp_server("127.0.0.1", port, container_stop_with_timeout_handler)
-        .map_err(|err| eprintln!("{}", err));
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let request = format!("{} {}", req.method(), req.uri().path());
+        requests.write().unwrap().push(request.clone());
+
+        let (status, response) = match request.as_str() {
+            "GET /volumes/edgehub-data" => (
+                hyper::StatusCode::OK,
+                json!({
+                    "Name": "edgehub-data",
+                    "Driver": "local",
+                    "Mountpoint": "/var/lib/docker/volumes/edgehub-data/_data",
+                    "Scope": "local"
+                }).to_string(),
+            ),
+            "GET /volumes/cache" => (
+                hyper::StatusCode::NOT_FOUND,
+                json!({ "message": "no such volume" }).to_string(),
+            ),
+            "POST /volumes/create" => (
+                hyper::StatusCode::CREATED,
+                json!({
+                    "Name": "cache",
+                    "Driver": "local",
+                    "Mountpoint": "/var/lib/docker/volumes/cache/_data",
+                    "Scope": "local"
+                }).to_string(),
+            ),
+            "POST /containers/create" => (
+                hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": "create failed" }).to_string(),
+            ),
+            "DELETE /volumes/cache" => {
+                assert_eq!(Some("force=false"), req.uri().query());
+                (hyper::StatusCode::NO_CONTENT, String::new())
+            }
+            request => panic!("Unexpected request {}", request),
+        };
+
+        let mut response = Response::new(response.into());
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        Box::new(future::ok(response))
+    }).map_err(|err| eprintln!("{}", err));
 
     let mri =
         DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
             .unwrap();
 
-    let task = mri.stop("m1", Some(Duration::from_secs(600)));
+    let create_options = ContainerCreateBody::new().with_host_config(HostConfig::new().with_binds(
+        vec![
+            "edgehub-data:/iotedge/storage".to_string(),
+            "cache:/var/cache".to_string(),
+        ],
+    ));
+    let module_config = ModuleSpec::new(
+        "m1",
+        "docker",
+        DockerConfig::new(IMAGE_NAME, create_options, None).unwrap(),
+        HashMap::new(),
+    ).unwrap();
+
+    let task = mri.create(module_config);
 
     let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
     runtime.spawn(server);
-    runtime.block_on(task).unwrap();
-}
-
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn container_remove_handler(
-    req: Request<Body>,
-) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-    assert_eq!(req.method(), &Method::DELETE);
-    assert_eq!(req.uri().path(), "/containers/m1");
+    let err = runtime.block_on(task).unwrap_err();
 
-    Box::new(future::ok(Response::new(Body::empty())))
+    // The error is the one creating the container failed with, and only the
+    // volume that did not exist before is removed.
+    assert!(err.to_string().contains("create failed"), "{}", err);
+    assert_eq!(
+        vec![
+            "GET /volumes/edgehub-data",
+            "GET /volumes/cache",
+            "POST /volumes/create",
+            "POST /containers/create",
+            "DELETE /volumes/cache",
+        ],
+        *requests_cloned.read().unwrap()
+    );
 }
 
 #[test]
-fn container_remove_succeeds() {
-    let port = get_unused_tcp_port();
-    let server = run_tcp_server("127.0.0.1", port, container_remove_handler)
-        .map_err(|err| eprintln!("{}", err));
+fn remove_all_purges_module_volumes() {
+    let volume_removed_lock = Arc::new(RwLock::new(false));
+    let volume_removed_lock_cloned = volume_removed_lock.clone();
 
-    let mri =
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let response = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/containers/json") => json!([]).to_string(),
+            (&Method::GET, "/volumes") => {
+                let (_, filters) = parse_query(req.uri().query().unwrap().as_bytes())
+                    .find(|(key, _)| key == "filters")
+                    .unwrap();
+                let filters: HashMap<String, Vec<String>> =
+                    serde_json::from_str(&filters).unwrap();
+                assert_eq!(
+                    vec!["net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent"],
+                    filters["label"]
+                );
+
+                json!({
+                    "Volumes": [{
+                        "Name": "edgehub-data",
+                        "Driver": "local",
+                        "Mountpoint": "/var/lib/docker/volumes/edgehub-data/_data",
+                        "Labels": {
+                            "net.azure-devices.edge.owner": "Microsoft.Azure.Devices.Edge.Agent"
+                        },
+                        "Scope": "local",
+                        "Options": {}
+                    }],
+                    "Warnings": []
+                }).to_string()
+            }
+            (&Method::DELETE, "/volumes/edgehub-data") => {
+                assert_eq!(Some("force=false"), req.uri().query());
+                *volume_removed_lock.write().unwrap() = true;
+                return Box::new(future::ok(Response::new(Body::empty())));
+            }
+            (method, path) => panic!("Unexpected request {} {}", method, path),
+        };
+
+        let mut response = Response::new(response.into());
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        Box::new(future::ok(response))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_purge_volumes(true);
+
+    let task = mri.remove_all();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+
+    assert_eq!(true, *volume_removed_lock_cloned.read().unwrap());
+}
+
+/// Purges a runtime whose network `azure-iot-edge` docker deletes with
+/// `delete_status` and `delete_message`. Returns the outcome of the purge and
+/// the requests the runtime made.
+fn purge(
+    delete_status: hyper::StatusCode,
+    delete_message: &'static str,
+    prune_images: bool,
+) -> (Result<(), edgelet_docker::Error>, Vec<String>) {
+    let requests = Arc::new(RwLock::new(vec![]));
+    let requests_cloned = requests.clone();
+
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let request = format!("{} {}", req.method(), req.uri().path());
+        requests.write().unwrap().push(request);
+        let (status, response) = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/containers/json") => (hyper::StatusCode::OK, json!([])),
+            (&Method::DELETE, "/networks/azure-iot-edge") => {
+                (delete_status, json!({ "message": delete_message }))
+            }
+            (&Method::GET, "/networks/azure-iot-edge") => (
+                hyper::StatusCode::OK,
+                json!({
+                    "Name": "azure-iot-edge",
+                    "Id": "3f3a",
+                    "Containers": {
+                        "c2": { "Name": "edgeHub" },
+                        "c1": { "Name": "edgeAgent" }
+                    }
+                }),
+            ),
+            (&Method::GET, "/images/json") => {
+                let (_, filters) = parse_query(req.uri().query().unwrap().as_bytes())
+                    .find(|(key, _)| key == "filters")
+                    .unwrap();
+                let filters: HashMap<String, Vec<String>> =
+                    serde_json::from_str(&filters).unwrap();
+                assert_eq!(
+                    vec!["net.azure-devices.edge.owner=Microsoft.Azure.Devices.Edge.Agent"],
+                    filters["label"]
+                );
+                (
+                    hyper::StatusCode::OK,
+                    json!([{
+                        "Id": "sha256:e216a057b1cb",
+                        "ParentId": "",
+                        "RepoTags": ["edge-agent:1.0"],
+                        "RepoDigests": [],
+                        "Created": 1_539_612_000,
+                        "Size": 1,
+                        "SharedSize": 0,
+                        "VirtualSize": 1,
+                        "Labels": {},
+                        "Containers": 0
+                    }]),
+                )
+            }
+            (&Method::DELETE, "/images/sha256:e216a057b1cb") => {
+                (hyper::StatusCode::OK, json!([{ "Deleted": "sha256:e216a057b1cb" }]))
+            }
+            (method, path) => panic!("Unexpected request {} {}", method, path),
+        };
+
+        let mut response = Response::new(response.to_string().into());
+        *response.status_mut() = status;
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        Box::new(future::ok(response))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_network_id("azure-iot-edge".to_string())
+            .with_prune_images(prune_images);
+
+    let task = mri.purge();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let result = runtime.block_on(task);
+
+    let requests = requests_cloned.read().unwrap().clone();
+    (result, requests)
+}
+
+#[test]
+fn purge_removes_network_and_images() {
+    let (result, requests) = purge(hyper::StatusCode::NO_CONTENT, "", true);
+
+    result.unwrap();
+    assert!(requests.contains(&"DELETE /networks/azure-iot-edge".to_string()));
+    assert!(requests.contains(&"DELETE /images/sha256:e216a057b1cb".to_string()));
+}
+
+#[test]
+fn purge_keeps_images_unless_asked() {
+    let (result, requests) = purge(hyper::StatusCode::NO_CONTENT, "", false);
+
+    result.unwrap();
+    assert!(requests.contains(&"DELETE /networks/azure-iot-edge".to_string()));
+    assert!(!requests.iter().any(|request| request.contains("/images")));
+}
+
+#[test]
+fn purge_ignores_missing_network() {
+    let (result, _) = purge(
+        hyper::StatusCode::NOT_FOUND,
+        "network azure-iot-edge not found",
+        false,
+    );
+
+    result.unwrap();
+}
+
+#[test]
+fn purge_names_containers_on_network_in_use() {
+    let (result, requests) = purge(
+        hyper::StatusCode::FORBIDDEN,
+        "error while removing network: network azure-iot-edge id 3f3a has active endpoints",
+        true,
+    );
+
+    let err = result.unwrap_err();
+    match *err.kind() {
+        edgelet_docker::ErrorKind::NetworkInUse(ref network, ref containers) => {
+            assert_eq!("azure-iot-edge", network);
+            assert_eq!("edgeAgent, edgeHub", containers);
+        }
+        ref kind => panic!("Expected network in use, got {:?}", kind),
+    }
+    // Nothing more is removed once the network can't be.
+    assert!(!requests.iter().any(|request| request.contains("/images")));
+}
+
+/// Updates the module m1, which runs in the container `old`, to a module that
+/// runs `image` on the edge network. Creating a container of `failing_image`
+/// fails. Returns the outcome of the update, the requests the runtime made and
+/// the bodies it created containers with.
+fn update_module(
+    old: serde_json::Value,
+    image: &str,
+    failing_image: Option<&'static str>,
+    start: bool,
+) -> (
+    Result<(), edgelet_docker::Error>,
+    Vec<String>,
+    Vec<serde_json::Value>,
+) {
+    let requests = Arc::new(RwLock::new(vec![]));
+    let requests_cloned = requests.clone();
+    let created = Arc::new(RwLock::new(vec![]));
+    let created_cloned = created.clone();
+
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let request = format!("{} {}", req.method(), req.uri().path());
+        requests.write().unwrap().push(request.clone());
+        let old = old.clone();
+        let created = created.clone();
+
+        Box::new(req.into_body().concat2().map(move |body| {
+            let (status, response) = match request.as_str() {
+                "GET /containers/m1/json" => (hyper::StatusCode::OK, old.to_string()),
+                "POST /containers/create" => {
+                    let body: serde_json::Value = serde_json::from_slice(body.as_ref()).unwrap();
+                    let fails = failing_image.map_or(false, |image| body["Image"] == image);
+                    created.write().unwrap().push(body);
+                    if fails {
+                        (
+                            hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                            json!({ "message": "create failed" }).to_string(),
+                        )
+                    } else {
+                        (
+                            hyper::StatusCode::CREATED,
+                            json!({ "Id": "fedcba9876543210", "Warnings": [] }).to_string(),
+                        )
+                    }
+                }
+                "POST /containers/m1/stop"
+                | "DELETE /containers/m1"
+                | "POST /containers/m1/start" => (hyper::StatusCode::NO_CONTENT, String::new()),
+                request => panic!("Unexpected request {}", request),
+            };
+
+            let mut response = Response::new(response.into());
+            *response.status_mut() = status;
+            response
+                .headers_mut()
+                .typed_insert(&ContentType(mime::APPLICATION_JSON));
+            response
+        }))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let create_options = ContainerCreateBody::new()
+        .with_host_config(HostConfig::new().with_network_mode("azure-iot-edge".to_string()));
+    let module_config = ModuleSpec::new(
+        "m1",
+        "docker",
+        DockerConfig::new(image, create_options, None).unwrap(),
+        HashMap::new(),
+    ).unwrap();
+
+    let task = mri.update(module_config, start);
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let result = runtime.block_on(task);
+
+    let requests = requests_cloned.read().unwrap().clone();
+    let created = created_cloned.read().unwrap().clone();
+    (result, requests, created)
+}
+
+fn running_container_with_volumes() -> serde_json::Value {
+    json!({
+        "Id": "0123456789abcdef",
+        "Name": "/m1",
+        "Image": "sha256:old",
+        "State": { "Status": "running", "Running": true },
+        "Config": { "Image": "old-image:1.0", "Env": [], "Labels": {} },
+        "HostConfig": {
+            "Binds": ["edgehub-data:/iotedge/storage"],
+            "NetworkMode": "azure-iot-edge"
+        },
+        "Mounts": [
+            {
+                "Type": "volume",
+                "Name": "edgehub-data",
+                "Destination": "/iotedge/storage"
+            },
+            {
+                "Type": "volume",
+                "Name": "2c8b1e0f9a7d",
+                "Destination": "/var/cache"
+            }
+        ],
+        "NetworkSettings": {
+            "Networks": {
+                "azure-iot-edge": {
+                    "Aliases": ["m1", "0123456789ab"],
+                    "IPAddress": "172.18.0.5"
+                }
+            }
+        }
+    })
+}
+
+#[test]
+fn update_preserves_anonymous_volumes_and_aliases() {
+    let (result, requests, created) =
+        update_module(running_container_with_volumes(), IMAGE_NAME, None, true);
+
+    result.unwrap();
+    assert_eq!(
+        vec![
+            "GET /containers/m1/json",
+            "POST /containers/m1/stop",
+            "DELETE /containers/m1",
+            "POST /containers/create",
+            "POST /containers/m1/start",
+        ],
+        requests
+    );
+
+    assert_eq!(1, created.len());
+    assert_eq!(IMAGE_NAME, created[0]["Image"]);
+    // The named volume is left to the new create options, the anonymous one
+    // is carried over.
+    assert_eq!(
+        json!([{ "Type": "volume", "Source": "2c8b1e0f9a7d", "Target": "/var/cache" }]),
+        created[0]["HostConfig"]["Mounts"]
+    );
+    assert_eq!(
+        json!(["m1"]),
+        created[0]["NetworkingConfig"]["EndpointsConfig"]["azure-iot-edge"]["Aliases"]
+    );
+}
+
+#[test]
+fn update_restores_old_container_when_create_fails() {
+    let (result, requests, created) = update_module(
+        running_container_with_volumes(),
+        IMAGE_NAME,
+        Some(IMAGE_NAME),
+        true,
+    );
+
+    assert!(result.is_err());
+    assert_eq!(
+        vec![
+            "GET /containers/m1/json",
+            "POST /containers/m1/stop",
+            "DELETE /containers/m1",
+            "POST /containers/create",
+            "POST /containers/create",
+            "POST /containers/m1/start",
+        ],
+        requests
+    );
+
+    // The old container is created again from its own config, with its data.
+    assert_eq!(2, created.len());
+    assert_eq!("old-image:1.0", created[1]["Image"]);
+    assert_eq!(
+        json!(["edgehub-data:/iotedge/storage"]),
+        created[1]["HostConfig"]["Binds"]
+    );
+    assert_eq!(
+        json!([{ "Type": "volume", "Source": "2c8b1e0f9a7d", "Target": "/var/cache" }]),
+        created[1]["HostConfig"]["Mounts"]
+    );
+}
+
+#[test]
+fn update_without_volumes_adds_no_mounts() {
+    let old = json!({
+        "Id": "0123456789abcdef",
+        "Name": "/m1",
+        "State": { "Status": "exited", "Running": false },
+        "Config": { "Image": "old-image:1.0" },
+        "HostConfig": { "NetworkMode": "azure-iot-edge" },
+        "Mounts": []
+    });
+
+    let (result, requests, created) = update_module(old, IMAGE_NAME, None, false);
+
+    result.unwrap();
+    assert_eq!(
+        vec![
+            "GET /containers/m1/json",
+            "DELETE /containers/m1",
+            "POST /containers/create",
+        ],
+        requests
+    );
+    assert_eq!(1, created.len());
+    assert_eq!(json!(null), created[0]["HostConfig"]["Mounts"]);
+    assert_eq!(json!(null), created[0]["NetworkingConfig"]);
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_start_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    assert_eq!(req.uri().path(), "/containers/m1/start");
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_start_succeeds() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_start_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let task = mri.start("m1");
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_missing_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    let name = req.uri().path().split('/').nth(2).unwrap_or("").to_string();
+
+    let body = json!({ "message": format!("No such container: {}", name) }).to_string();
+    let mut response = Response::new(body.into());
+    *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+    response
+        .headers_mut()
+        .typed_insert(&ContentType(mime::APPLICATION_JSON));
+    Box::new(future::ok(response))
+}
+
+#[test]
+fn container_start_failures_are_recorded() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_missing_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let runtime_errors = RuntimeErrorLog::new(3);
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_runtime_errors(runtime_errors.clone());
+
+    // One after the other, so that they fail in order.
+    let task = stream::iter_ok(1..6)
+        .and_then(move |i| mri.start(&format!("m{}", i)).then(Ok::<_, ()>))
+        .collect();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let results = runtime.block_on(task).unwrap();
+    assert!(results.iter().all(Result::is_err));
+
+    // Only the most recent failures are kept.
+    let records = runtime_errors.records();
+    assert_eq!(3, records.len());
+    let modules: Vec<_> = records.iter().map(|record| record.module()).collect();
+    assert_eq!(vec![Some("m3"), Some("m4"), Some("m5")], modules);
+    for record in &records {
+        assert_eq!("start", record.operation());
+        assert_eq!("NotFound", record.kind());
+    }
+    assert_eq!("No such container: m5", records[2].message());
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_stop_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    assert_eq!(req.uri().path(), "/containers/m1/stop");
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_stop_succeeds() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_stop_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let task = mri.stop("m1", None);
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_kill_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    assert_eq!(req.uri().path(), "/containers/m1/kill");
+    let query: HashMap<_, _> = parse_query(req.uri().query().unwrap().as_bytes()).collect();
+    assert_eq!("SIGHUP", query["signal"]);
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_kill_sends_allowed_signal() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_kill_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(mri.kill("m1", "SIGHUP")).unwrap();
+    runtime.block_on(mri.kill("m1", "hup")).unwrap();
+}
+
+#[test]
+fn container_kill_refuses_disallowed_signal() {
+    // Nothing listens on the port, so the signal must be refused before the
+    // container engine is called.
+    let port = get_unused_tcp_port();
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    for signal in &["SIGKILL", "SIGSTOP", "9", "HUP;rm"] {
+        let err = runtime.block_on(mri.kill("m1", signal)).unwrap_err();
+        match err.kind() {
+            edgelet_docker::ErrorKind::InvalidSignal(name) => assert_eq!(signal, name),
+            kind => panic!("Expected {} to be refused, got {:?}", signal, kind),
+        }
+    }
+
+    let server = run_tcp_server("127.0.0.1", port, container_operation_handler)
+        .map_err(|err| eprintln!("{}", err));
+    runtime.spawn(server);
+    let mri = mri.with_signals(KillSignals::Any);
+    runtime.block_on(mri.kill("m1", "SIGKILL")).unwrap();
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_operation_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    assert!(req.uri().path().starts_with("/containers/m1/"));
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_operations_bump_module_epochs() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_operation_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let epochs = ModuleEpochs::new();
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_epochs(epochs.clone());
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(mri.stop("m1", None)).unwrap();
+    assert_eq!(1, epochs.epoch("m1"));
+    runtime.block_on(mri.start("m1")).unwrap();
+    runtime.block_on(mri.restart("m1")).unwrap();
+    assert_eq!(3, epochs.epoch("m1"));
+    assert_eq!(0, epochs.epoch("m2"));
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_top_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::GET);
+    assert_eq!(req.uri().path(), "/containers/m1/top");
+
+    let body = json!({
+        "Titles": ["UID", "PID", "PPID", "C", "STIME", "TTY", "TIME", "CMD"],
+        "Processes": [
+            ["root", "4242", "4200", "0", "10:00", "?", "00:00:00", "/bin/sh -c app"],
+            ["root", "4250", "4242", "0", "10:00", "?", "00:00:01", "app"]
+        ]
+    }).to_string();
+    let mut response = Response::new(body.into());
+    response
+        .headers_mut()
+        .typed_insert(&ContentType(mime::APPLICATION_JSON));
+    Box::new(future::ok(response))
+}
+
+#[test]
+fn container_top_lists_process_ids() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_top_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let pids = runtime.block_on(mri.top("m1")).unwrap();
+    assert_eq!(vec![Pid::Value(4242), Pid::Value(4250)], pids);
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_stop_with_timeout_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::POST);
+    assert_eq!(req.uri().path(), "/containers/m1/stop");
+    assert_eq!(req.uri().query().unwrap(), "t=600");
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_stop_with_timeout_succeeds() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_stop_with_timeout_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap();
+
+    let task = mri.stop("m1", Some(Duration::from_secs(600)));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(task).unwrap();
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn hung_handler(
+    _req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    Box::new(future::empty())
+}
+
+fn assert_timed_out(operation: &str, err: &edgelet_docker::Error) {
+    match *err.kind() {
+        edgelet_docker::ErrorKind::OperationTimedOut(op) => assert_eq!(operation, op),
+        ref kind => panic!("unexpected error kind {:?}", kind),
+    }
+}
+
+#[test]
+fn operations_on_a_hung_runtime_time_out() {
+    let port = get_unused_tcp_port();
+    let server =
+        run_tcp_server("127.0.0.1", port, hung_handler).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_operation_timeout(Some(Duration::from_millis(100)));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let err = runtime.block_on(mri.start("m1")).unwrap_err();
+    assert_timed_out("start", &err);
+    let err = runtime
+        .block_on(mri.stop("m1", Some(Duration::from_secs(0))))
+        .unwrap_err();
+    assert_timed_out("stop", &err);
+    let err = runtime.block_on(mri.system_info()).unwrap_err();
+    assert_timed_out("system_info", &err);
+}
+
+#[test]
+fn slow_operations_are_counted() {
+    let clock = TestClock::default();
+    let server_clock = clock.clone();
+    let port = get_unused_tcp_port();
+    // Starting takes 20 seconds on the clock of the runtime, restarting 45.
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let took = match req.uri().path() {
+            "/containers/m1/start" => 20,
+            "/containers/m1/restart" => 45,
+            path => panic!("unexpected request for {}", path),
+        };
+        server_clock.advance(Duration::from_secs(took));
+        future::ok::<_, HyperError>(Response::new(Body::empty()))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let metrics = MetricsRegistry::new();
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_metrics(Arc::new(metrics.clone()))
+            .with_clock(Arc::new(clock))
+            .with_slow_threshold("start", Some(Duration::from_secs(30)))
+            .with_slow_threshold("restart", Some(Duration::from_secs(30)));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(mri.start("m1")).unwrap();
+    runtime.block_on(mri.restart("m1")).unwrap();
+
+    for operation in &["start", "restart"] {
+        assert_eq!(
+            1,
+            metrics.histogram_count(&RUNTIME_OPERATION_DURATION, &[("operation", operation)])
+        );
+    }
+    assert_eq!(
+        0,
+        metrics.counter(&RUNTIME_SLOW_OPERATIONS, &[("operation", "start")])
+    );
+    assert_eq!(
+        1,
+        metrics.counter(&RUNTIME_SLOW_OPERATIONS, &[("operation", "restart")])
+    );
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn container_remove_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::DELETE);
+    assert_eq!(req.uri().path(), "/containers/m1");
+
+    Box::new(future::ok(Response::new(Body::empty())))
+}
+
+#[test]
+fn container_remove_succeeds() {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, container_remove_handler)
+        .map_err(|err| eprintln!("{}", err));
+
+    let mri =
         DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
             .unwrap();
 
@@ -1103,6 +2416,50 @@ fn runtime_init_network_exist_do_not_create() {
     assert_eq!(false, *create_got_called_lock_cloned.read().unwrap());
 }
 
+#[test]
+fn runtime_init_network_create_fails_names_network() {
+    let port = get_unused_tcp_port();
+
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let (status, response) = match *req.method() {
+            Method::GET => (hyper::StatusCode::OK, json!([]).to_string()),
+            _ => (
+                hyper::StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "message": "failed to create the bridge" }).to_string(),
+            ),
+        };
+        let response_len = response.len();
+
+        let mut response = Response::new(response.into());
+        response
+            .headers_mut()
+            .typed_insert(&ContentLength(response_len as u64));
+        response
+            .headers_mut()
+            .typed_insert(&ContentType(mime::APPLICATION_JSON));
+        *response.status_mut() = status;
+        Box::new(future::ok(response))
+    }).map_err(|err| eprintln!("{}", err));
+
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_network_id("azure-iot-edge".to_string());
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let err = runtime.block_on(mri.init()).unwrap_err();
+
+    match *err.kind() {
+        edgelet_docker::ErrorKind::NetworkCreate(ref network) => {
+            assert_eq!("azure-iot-edge", network)
+        }
+        ref kind => panic!("Expected network create failure, got {:?}", kind),
+    }
+    let cause = err.cause().unwrap().to_string();
+    assert!(cause.contains("failed to create the bridge"), "{}", cause);
+}
+
 #[test]
 fn runtime_system_info_succeed() {
     let system_info_got_called_lock = Arc::new(RwLock::new(false));
@@ -1117,14 +2474,25 @@ fn runtime_system_info_succeed() {
                 let mut system_info_got_called_w = system_info_got_called_lock.write().unwrap();
                 *system_info_got_called_w = true;
 
-                assert_eq!(req.uri().path(), "/info");
-
-                let response = json!(
-                                {
-                                    "OSType": "linux",
-                                    "Architecture": "x86_64",
-                                }
-                        ).to_string();
+                let response = match req.uri().path() {
+                    "/info" => json!({
+                        "OSType": "linux",
+                        "Architecture": "x86_64",
+                        "ServerVersion": "18.06.1-ce",
+                        "Driver": "devicemapper",
+                        "DriverStatus": [
+                            ["Pool Name", "docker-8:1-1234-pool"],
+                            ["Data loop file", "/var/lib/docker/devicemapper/data"],
+                        ],
+                        "LoggingDriver": "json-file",
+                        "DockerRootDir": "/var/lib/docker",
+                    }),
+                    "/version" => json!({
+                        "Version": "18.06.1-ce",
+                        "ApiVersion": "1.38",
+                    }),
+                    path => panic!("Unexpected request for {}", path),
+                }.to_string();
                 let response_len = response.len();
 
                 let mut response = Response::new(response.into());
@@ -1155,6 +2523,75 @@ fn runtime_system_info_succeed() {
     assert_eq!(true, *system_info_got_called_lock_cloned.read().unwrap());
     assert_eq!("linux", system_info.os_type());
     assert_eq!("x86_64", system_info.architecture());
+    assert_eq!(Some("18.06.1-ce"), system_info.runtime_version());
+    assert_eq!(Some("1.38"), system_info.runtime_api_version());
+    assert_eq!(Some("devicemapper"), system_info.storage_driver());
+    assert_eq!(
+        Some(&"/var/lib/docker/devicemapper/data".to_string()),
+        system_info.storage_driver_status().get("Data loop file")
+    );
+    assert_eq!(Some("json-file"), system_info.logging_driver());
+    assert_eq!(
+        Some(Path::new("/var/lib/docker")),
+        system_info.runtime_root_dir()
+    );
+}
+
+#[test]
+fn system_info_is_cached_until_refreshed_or_the_runtime_is_unreachable() {
+    let requests = Arc::new(RwLock::new(0));
+    let requests_copy = requests.clone();
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        let path = req.uri().path().to_string();
+        let response = match path.as_str() {
+            "/info" => {
+                *requests_copy.write().unwrap() += 1;
+                json!({ "OSType": "linux", "Architecture": "x86_64" })
+            }
+            "/version" => json!({ "ApiVersion": "1.38" }),
+            // Starting hangs, like a daemon that is being upgraded.
+            "/containers/m1/start" => return hung_handler(req),
+            path => panic!("unexpected request for {}", path),
+        };
+        let response: Box<Future<Item = Response<Body>, Error = HyperError> + Send> =
+            Box::new(future::ok(Response::new(response.to_string().into())));
+        response
+    }).map_err(|err| eprintln!("{}", err));
+
+    let clock = TestClock::default();
+    let mri =
+        DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()))
+            .with_system_info_ttl(Duration::from_secs(300))
+            .with_operation_timeout(Some(Duration::from_millis(100)));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let info_requests = || *requests.read().unwrap();
+
+    // Within the TTL the daemon is asked once.
+    let system_info = runtime.block_on(mri.system_info()).unwrap();
+    assert_eq!("linux", system_info.os_type());
+    clock.advance(Duration::from_secs(299));
+    runtime.block_on(mri.clone().system_info()).unwrap();
+    assert_eq!(1, info_requests());
+
+    // Past it, or when asked to, it is asked again.
+    clock.advance(Duration::from_secs(1));
+    runtime.block_on(mri.system_info()).unwrap();
+    assert_eq!(2, info_requests());
+    runtime.block_on(mri.refresh_system_info()).unwrap();
+    assert_eq!(3, info_requests());
+    runtime.block_on(mri.system_info()).unwrap();
+    assert_eq!(3, info_requests());
+
+    // A daemon that can't be reached may come back another version.
+    let err = runtime.block_on(mri.start("m1")).unwrap_err();
+    assert_timed_out("start", &err);
+    runtime.block_on(mri.system_info()).unwrap();
+    assert_eq!(4, info_requests());
 }
 
 #[test]
@@ -1171,6 +2608,13 @@ fn runtime_system_info_none_returns_unkown() {
                 let mut system_info_got_called_w = system_info_got_called_lock.write().unwrap();
                 *system_info_got_called_w = true;
 
+                // A daemon that does not answer /version still has its
+                // system information reported.
+                if req.uri().path() == "/version" {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+                    return Box::new(future::ok(response));
+                }
                 assert_eq!(req.uri().path(), "/info");
 
                 let response = json!({}).to_string();
@@ -1204,4 +2648,6 @@ fn runtime_system_info_none_returns_unkown() {
     assert_eq!(true, *system_info_got_called_lock_cloned.read().unwrap());
     assert_eq!("Unknown", system_info.os_type());
     assert_eq!("Unknown", system_info.architecture());
+    assert_eq!(None, system_info.runtime_version());
+    assert_eq!(None, system_info.runtime_api_version());
 }
@@ -1,15 +1,22 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use chrono::{DateTime, Utc};
+use std::env;
+use std::fs;
 use std::sync::{Arc, Mutex};
 
+use failure::ResultExt;
+
 use certificate_properties::convert_properties;
 use edgelet_core::{
     Certificate as CoreCertificate, CertificateProperties as CoreCertificateProperties,
     CreateCertificate as CoreCreateCertificate, Decrypt as CoreDecrypt, Encrypt as CoreEncrypt,
     Error as CoreError, GetTrustBundle as CoreGetTrustBundle, KeyBytes as CoreKeyBytes,
-    MasterEncryptionKey as CoreMasterEncryptionKey, PrivateKey as CorePrivateKey,
+    MakeRandom as CoreMakeRandom, MasterEncryptionKey as CoreMasterEncryptionKey,
+    PrivateKey as CorePrivateKey, RenewQuickstartCa as CoreRenewQuickstartCa,
+    SignCertificateRequest as CoreSignCertificateRequest,
 };
+use leaf;
 
 pub use error::{Error, ErrorKind};
 pub use hsm::{
@@ -19,9 +26,20 @@ pub use hsm::{
 use hsm::{
     CreateCertificate as HsmCreateCertificate,
     CreateMasterEncryptionKey as HsmCreateMasterEncryptionKey, Crypto as HsmCrypto,
-    DestroyMasterEncryptionKey as HsmDestroyMasterEncryptionKey,
+    DestroyMasterEncryptionKey as HsmDestroyMasterEncryptionKey, MakeRandom as HsmMakeRandom,
 };
 
+/// Environment variable holding the path of the device CA certificate chain.
+const DEVICE_CA_CERT_KEY: &str = "IOTEDGE_DEVICE_CA_CERT";
+/// Environment variable holding the path of the device CA private key.
+const DEVICE_CA_PK_KEY: &str = "IOTEDGE_DEVICE_CA_PK";
+
+/// Whether the operator configured a device CA, rather than leaving the HSM to
+/// generate a quick start one.
+fn is_device_ca_configured() -> bool {
+    env::var_os(DEVICE_CA_CERT_KEY).map_or(false, |path| !path.is_empty())
+}
+
 /// The TPM Key Store.
 /// Activate a private key, and then you can use that key to sign data.
 #[derive(Clone)]
@@ -62,6 +80,17 @@ impl CoreMasterEncryptionKey for Crypto {
     }
 }
 
+impl CoreMakeRandom for Crypto {
+    fn get_random_bytes(&self, buffer: &mut [u8]) -> Result<(), CoreError> {
+        self.crypto
+            .lock()
+            .expect("Lock on crypto structure failed")
+            .get_random_bytes(buffer)
+            .map_err(Error::from)
+            .map_err(CoreError::from)
+    }
+}
+
 impl CoreCreateCertificate for Crypto {
     type Certificate = Certificate;
 
@@ -89,6 +118,68 @@ impl CoreCreateCertificate for Crypto {
     }
 }
 
+impl CoreRenewQuickstartCa for Crypto {
+    /// Destroys the device CA and resets the HSM, which generates a new one
+    /// when it finds none. Everything else the HSM issued stays in place and
+    /// has to be issued again to chain up to the new device CA.
+    fn renew_quickstart_ca(&self) -> Result<(), CoreError> {
+        if is_device_ca_configured() {
+            return Err(CoreError::from(Error::from(ErrorKind::OperatorDeviceCa)));
+        }
+
+        let mut crypto = self.crypto.lock().expect("Lock on crypto structure failed");
+        let device_ca_alias = crypto.get_device_ca_alias();
+        crypto
+            .destroy_certificate(device_ca_alias)
+            .and_then(|_| crypto.reset())
+            .map_err(Error::from)
+            .map_err(CoreError::from)
+    }
+}
+
+/// Reads the device CA certificate chain the operator configured.
+fn read_device_ca_chain() -> Result<String, Error> {
+    match env::var_os(DEVICE_CA_CERT_KEY) {
+        Some(ref path) if !path.is_empty() => {
+            Ok(fs::read_to_string(path).context(ErrorKind::DeviceCaChain)?)
+        }
+        _ => Err(Error::from(ErrorKind::QuickstartDeviceCa)),
+    }
+}
+
+/// Reads the private key of the device CA the operator configured.
+fn read_device_ca_key() -> Result<Vec<u8>, Error> {
+    match env::var_os(DEVICE_CA_PK_KEY) {
+        Some(ref path) if !path.is_empty() => Ok(fs::read(path).context(ErrorKind::DeviceCaKey)?),
+        _ => Err(Error::from(ErrorKind::QuickstartDeviceCa)),
+    }
+}
+
+impl CoreSignCertificateRequest for Crypto {
+    fn issuer_chain(&self) -> Result<String, CoreError> {
+        read_device_ca_chain().map_err(CoreError::from)
+    }
+
+    fn issuer_valid_to(&self) -> Result<DateTime<Utc>, CoreError> {
+        read_device_ca_chain()
+            .and_then(|chain| leaf::valid_to(&chain, Utc::now()))
+            .map_err(CoreError::from)
+    }
+
+    /// Signs with the device CA the operator configured. The quick start
+    /// device CA's key never leaves the HSM, so it can't sign requests.
+    fn sign_certificate_request(
+        &self,
+        request: &[u8],
+        validity_in_secs: u64,
+    ) -> Result<String, CoreError> {
+        let chain = read_device_ca_chain().map_err(CoreError::from)?;
+        let key = read_device_ca_key().map_err(CoreError::from)?;
+        leaf::sign_request(&chain, &key, request, validity_in_secs, Utc::now())
+            .map_err(CoreError::from)
+    }
+}
+
 impl CoreEncrypt for Crypto {
     type Buffer = Buffer;
 
@@ -126,7 +217,7 @@ impl CoreDecrypt for Crypto {
 }
 
 impl CoreGetTrustBundle for Crypto {
-    type Certificate = Certificate;
+    type Certificate = TrustBundle;
 
     fn get_trust_bundle(&self) -> Result<Self::Certificate, CoreError> {
         let cert = self
@@ -136,7 +227,57 @@ impl CoreGetTrustBundle for Crypto {
             .get_trust_bundle()
             .map_err(Error::from)
             .map_err(CoreError::from)?;
-        Ok(Certificate(cert))
+
+        let device_ca_chain = match env::var_os(DEVICE_CA_CERT_KEY) {
+            Some(ref path) if !path.is_empty() => Some(
+                fs::read_to_string(path)
+                    .context(ErrorKind::DeviceCaChain)
+                    .map_err(Error::from)
+                    .map_err(CoreError::from)?,
+            ),
+            _ => None,
+        };
+
+        Ok(TrustBundle {
+            cert,
+            device_ca_chain,
+        })
+    }
+}
+
+/// The trust bundle served to modules: the device CA certificate chain, when one
+/// is configured, followed by the trusted CA certificates held by the HSM.
+#[derive(Debug)]
+pub struct TrustBundle {
+    cert: HsmCertificate,
+    device_ca_chain: Option<String>,
+}
+
+impl CoreCertificate for TrustBundle {
+    type Buffer = String;
+    type KeyBuffer = Vec<u8>;
+
+    fn pem(&self) -> Result<Self::Buffer, CoreError> {
+        let trusted = self
+            .cert
+            .pem()
+            .map_err(Error::from)
+            .map_err(CoreError::from)?;
+        Ok(match self.device_ca_chain {
+            Some(ref chain) => format!("{}\n{}", chain.trim_right(), trusted),
+            None => trusted,
+        })
+    }
+
+    fn get_private_key(&self) -> Result<Option<CorePrivateKey<Self::KeyBuffer>>, CoreError> {
+        Ok(None)
+    }
+
+    fn get_valid_to(&self) -> Result<DateTime<Utc>, CoreError> {
+        self.cert
+            .get_valid_to()
+            .map_err(Error::from)
+            .map_err(CoreError::from)
     }
 }
 
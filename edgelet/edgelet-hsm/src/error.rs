@@ -3,6 +3,7 @@
 use std::fmt;
 use std::fmt::Display;
 
+use edgelet_core::crypto::SignatureAlgorithm;
 use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
 use failure::{Backtrace, Context, Fail};
 use hsm::Error as HsmError;
@@ -24,6 +25,20 @@ pub enum ErrorKind {
     EmptyStrings,
     #[fail(display = "Only Device keys are allowed to be activated")]
     NoModuleActivation,
+    #[fail(display = "Could not read the device CA certificate chain")]
+    DeviceCaChain,
+    #[fail(display = "The device CA was provided by the operator and cannot be renewed")]
+    OperatorDeviceCa,
+    #[fail(display = "Could not read the device CA private key")]
+    DeviceCaKey,
+    #[fail(display = "Certificates for leaf devices need a device CA provided by the operator")]
+    QuickstartDeviceCa,
+    #[fail(display = "The certificate signing request is invalid")]
+    InvalidCertificateRequest,
+    #[fail(display = "Could not sign the certificate signing request")]
+    SignCertificateRequest,
+    #[fail(display = "Signature algorithm {} is not supported by the TPM", _0)]
+    UnsupportedSignatureAlgorithm(SignatureAlgorithm),
 }
 
 impl Fail for Error {
@@ -76,6 +91,12 @@ impl From<HsmError> for Error {
 
 impl From<Error> for CoreError {
     fn from(error: Error) -> Self {
-        CoreError::from(error.context(CoreErrorKind::KeyStore))
+        let kind = match *error.kind() {
+            ErrorKind::UnsupportedSignatureAlgorithm(alg) => {
+                CoreErrorKind::UnsupportedSignatureAlgorithm(alg)
+            }
+            _ => CoreErrorKind::KeyStore,
+        };
+        CoreError::from(error.context(kind))
     }
 }
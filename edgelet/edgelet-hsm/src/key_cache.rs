@@ -0,0 +1,398 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use edgelet_core::crypto::{KeyIdentity, KeyStore};
+use edgelet_core::Error as CoreError;
+
+/// How many derived keys are kept by default.
+pub const DEFAULT_KEY_CACHE_MAX_ENTRIES: usize = 256;
+
+type CacheKey = (KeyIdentity, String);
+type Slot<T> = Arc<Mutex<Option<T>>>;
+
+/// A key store that remembers the keys another key store derives, so that
+/// signing for a module does not walk the derivation path in the HSM every
+/// time.
+///
+/// At most `max_entries` keys are kept; the least recently used one makes
+/// room for a new one. A `max_entries` of 0 turns the cache off. Module key
+/// names include the module's generation id, so the keys of a module whose
+/// identity was recreated or had its keys rotated are derived afresh, while
+/// the old ones are never asked for again and fall out of the cache.
+///
+/// When several threads ask for the same key that is not cached yet, one of
+/// them derives it and the others wait for it.
+pub struct CachedKeyStore<K>
+where
+    K: KeyStore,
+{
+    inner: Arc<Inner<K>>,
+}
+
+struct Inner<K>
+where
+    K: KeyStore,
+{
+    store: K,
+    max_entries: usize,
+    cache: Mutex<Cache<K::Key>>,
+}
+
+impl<K> CachedKeyStore<K>
+where
+    K: KeyStore,
+    K::Key: Clone,
+{
+    pub fn new(store: K, max_entries: usize) -> Self {
+        CachedKeyStore {
+            inner: Arc::new(Inner {
+                store,
+                max_entries,
+                cache: Mutex::new(Cache::new()),
+            }),
+        }
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.inner.max_entries
+    }
+
+    /// The number of keys in the cache, including those being derived.
+    pub fn len(&self) -> usize {
+        self.lock_cache().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the keys of `identity`.
+    pub fn invalidate(&self, identity: &KeyIdentity) {
+        self.lock_cache().remove_identity(identity);
+    }
+
+    /// Forgets every key.
+    pub fn clear(&self) {
+        *self.lock_cache() = Cache::new();
+    }
+
+    fn lock_cache(&self) -> MutexGuard<Cache<K::Key>> {
+        self.inner
+            .cache
+            .lock()
+            .expect("Failed to acquire the key cache lock")
+    }
+}
+
+impl<K> KeyStore for CachedKeyStore<K>
+where
+    K: KeyStore,
+    K::Key: Clone,
+{
+    type Key = K::Key;
+
+    fn get(&self, identity: &KeyIdentity, key_name: &str) -> Result<Self::Key, CoreError> {
+        if self.inner.max_entries == 0 {
+            return self.inner.store.get(identity, key_name);
+        }
+
+        let slot = self
+            .lock_cache()
+            .slot(identity, key_name, self.inner.max_entries);
+
+        // Whoever locks the slot first derives the key; everyone else waits
+        // here and then finds it in the slot.
+        let mut cached = slot.lock().expect("Failed to acquire a key cache slot lock");
+        if let Some(ref key) = *cached {
+            return Ok(key.clone());
+        }
+
+        match self.inner.store.get(identity, key_name) {
+            Ok(key) => {
+                *cached = Some(key.clone());
+                Ok(key)
+            }
+            Err(err) => {
+                drop(cached);
+                self.lock_cache().remove_empty(identity, key_name, &slot);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<K> Clone for CachedKeyStore<K>
+where
+    K: KeyStore,
+{
+    fn clone(&self) -> Self {
+        CachedKeyStore {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Entry<T> {
+    slot: Slot<T>,
+    last_used: u64,
+}
+
+/// The cached keys, and the order they were last used in.
+struct Cache<T> {
+    entries: HashMap<CacheKey, Entry<T>>,
+    by_use: BTreeMap<u64, CacheKey>,
+    clock: u64,
+}
+
+impl<T> Cache<T> {
+    fn new() -> Self {
+        Cache {
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The slot for a key, marked as the most recently used one. A new slot
+    /// is empty until the key is derived into it.
+    fn slot(&mut self, identity: &KeyIdentity, key_name: &str, max_entries: usize) -> Slot<T> {
+        self.clock += 1;
+        let now = self.clock;
+        let cache_key = (identity.clone(), key_name.to_string());
+
+        if let Some(entry) = self.entries.get_mut(&cache_key) {
+            self.by_use.remove(&entry.last_used);
+            self.by_use.insert(now, cache_key);
+            entry.last_used = now;
+            return entry.slot.clone();
+        }
+
+        while self.entries.len() >= max_entries {
+            let oldest = match self.by_use.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            if let Some(evicted) = self.by_use.remove(&oldest) {
+                self.entries.remove(&evicted);
+            }
+        }
+
+        let slot = Arc::new(Mutex::new(None));
+        self.by_use.insert(now, cache_key.clone());
+        self.entries.insert(
+            cache_key,
+            Entry {
+                slot: slot.clone(),
+                last_used: now,
+            },
+        );
+        slot
+    }
+
+    /// Drops the slot of a key that could not be derived, unless it has
+    /// been replaced or filled in the meantime.
+    fn remove_empty(&mut self, identity: &KeyIdentity, key_name: &str, slot: &Slot<T>) {
+        let cache_key = (identity.clone(), key_name.to_string());
+        let last_used = match self.entries.get(&cache_key) {
+            Some(entry) if Arc::ptr_eq(&entry.slot, slot) => entry.last_used,
+            _ => return,
+        };
+        if slot.try_lock().map(|key| key.is_none()).unwrap_or(false) {
+            self.entries.remove(&cache_key);
+            self.by_use.remove(&last_used);
+        }
+    }
+
+    fn remove_identity(&mut self, identity: &KeyIdentity) {
+        let removed: Vec<CacheKey> = self
+            .entries
+            .keys()
+            .filter(|cache_key| cache_key.0 == *identity)
+            .cloned()
+            .collect();
+        for cache_key in removed {
+            if let Some(entry) = self.entries.remove(&cache_key) {
+                self.by_use.remove(&entry.last_used);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Barrier, Condvar};
+    use std::thread;
+
+    use edgelet_core::crypto::MemoryKey;
+    use edgelet_core::ErrorKind as CoreErrorKind;
+
+    /// Derives keys by naming them after the identity and key name, counting
+    /// the derivations. Derivations can be held up until `release` is called.
+    #[derive(Clone)]
+    struct CountingKeyStore {
+        derivations: Arc<AtomicUsize>,
+        gate: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl CountingKeyStore {
+        fn new() -> Self {
+            CountingKeyStore {
+                derivations: Arc::new(AtomicUsize::new(0)),
+                gate: Arc::new((Mutex::new(true), Condvar::new())),
+            }
+        }
+
+        fn held() -> Self {
+            let store = CountingKeyStore::new();
+            *store.gate.0.lock().unwrap() = false;
+            store
+        }
+
+        fn release(&self) {
+            *self.gate.0.lock().unwrap() = true;
+            self.gate.1.notify_all();
+        }
+
+        fn derivations(&self) -> usize {
+            self.derivations.load(Ordering::SeqCst)
+        }
+    }
+
+    impl KeyStore for CountingKeyStore {
+        type Key = MemoryKey;
+
+        fn get(&self, identity: &KeyIdentity, key_name: &str) -> Result<MemoryKey, CoreError> {
+            self.derivations.fetch_add(1, Ordering::SeqCst);
+            let mut open = self.gate.0.lock().unwrap();
+            while !*open {
+                open = self.gate.1.wait(open).unwrap();
+            }
+            match *identity {
+                KeyIdentity::Module(ref m) if m == "broken" => {
+                    Err(CoreError::from(CoreErrorKind::KeyStore))
+                }
+                KeyIdentity::Module(ref m) => Ok(MemoryKey::new(format!("{}{}", m, key_name))),
+                KeyIdentity::Device => Ok(MemoryKey::new(key_name)),
+            }
+        }
+    }
+
+    fn module(name: &str) -> KeyIdentity {
+        KeyIdentity::Module(name.to_string())
+    }
+
+    #[test]
+    fn keys_are_derived_once() {
+        let store = CountingKeyStore::new();
+        let cache = CachedKeyStore::new(store.clone(), 10);
+
+        let key = cache.get(&module("m1"), "primary1").unwrap();
+        assert_eq!(b"m1primary1", key.as_ref());
+        let key = cache.get(&module("m1"), "primary1").unwrap();
+        assert_eq!(b"m1primary1", key.as_ref());
+        assert_eq!(1, store.derivations());
+
+        // A new generation id makes for a new key name and so a new key.
+        let key = cache.get(&module("m1"), "primary2").unwrap();
+        assert_eq!(b"m1primary2", key.as_ref());
+        assert_eq!(2, store.derivations());
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn concurrent_requests_derive_a_key_once() {
+        let store = CountingKeyStore::held();
+        let cache = CachedKeyStore::new(store.clone(), 10);
+
+        let threads = 8;
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let cache = cache.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache.get(&module("m1"), "primary1").unwrap()
+                })
+            }).collect();
+
+        // Wait for the first derivation to start, then let it finish. However
+        // the other threads interleave with it, none of them derives again.
+        while store.derivations() == 0 {
+            thread::yield_now();
+        }
+        store.release();
+
+        for handle in handles {
+            assert_eq!(b"m1primary1", handle.join().unwrap().as_ref());
+        }
+        assert_eq!(1, store.derivations());
+    }
+
+    #[test]
+    fn least_recently_used_key_is_evicted() {
+        let store = CountingKeyStore::new();
+        let cache = CachedKeyStore::new(store.clone(), 2);
+
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m2"), "primary").unwrap();
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m3"), "primary").unwrap();
+        assert_eq!(3, store.derivations());
+        assert_eq!(2, cache.len());
+
+        // m2 was evicted, m1 and m3 were not.
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m3"), "primary").unwrap();
+        assert_eq!(3, store.derivations());
+        cache.get(&module("m2"), "primary").unwrap();
+        assert_eq!(4, store.derivations());
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn invalidate_forgets_the_keys_of_an_identity() {
+        let store = CountingKeyStore::new();
+        let cache = CachedKeyStore::new(store.clone(), 10);
+
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m1"), "secondary").unwrap();
+        cache.get(&module("m2"), "primary").unwrap();
+        cache.invalidate(&module("m1"));
+        assert_eq!(1, cache.len());
+
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m2"), "primary").unwrap();
+        assert_eq!(4, store.derivations());
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn failed_derivations_are_not_cached() {
+        let store = CountingKeyStore::new();
+        let cache = CachedKeyStore::new(store.clone(), 10);
+
+        assert!(cache.get(&module("broken"), "primary").is_err());
+        assert!(cache.get(&module("broken"), "primary").is_err());
+        assert_eq!(2, store.derivations());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_max_entries_turns_the_cache_off() {
+        let store = CountingKeyStore::new();
+        let cache = CachedKeyStore::new(store.clone(), 0);
+
+        cache.get(&module("m1"), "primary").unwrap();
+        cache.get(&module("m1"), "primary").unwrap();
+        assert_eq!(2, store.derivations());
+        assert!(cache.is_empty());
+    }
+}
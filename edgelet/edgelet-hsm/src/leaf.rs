@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Signs the certificate signing requests of leaf devices with the device
+//! CA. The HSM only issues certificates for keys it generates itself, so this
+//! needs the device CA's private key, which only a device CA provided by the
+//! operator comes with.
+
+use chrono::{DateTime, Duration, Utc};
+use failure::ResultExt;
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::x509::extension::{
+    AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectKeyIdentifier,
+};
+use openssl::x509::{X509Req, X509};
+
+use error::{Error, ErrorKind};
+
+/// Bits of randomness in the serial numbers of the certificates.
+const SERIAL_BITS: i32 = 128;
+
+/// When the first certificate of the PEM chain expires.
+pub fn valid_to(chain: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, Error> {
+    let ca = X509::from_pem(chain.as_bytes()).context(ErrorKind::DeviceCaChain)?;
+    let diff = Asn1Time::from_unix(now.timestamp())
+        .and_then(|now| now.diff(ca.not_after()))
+        .context(ErrorKind::DeviceCaChain)?;
+    Ok(now + Duration::days(i64::from(diff.days)) + Duration::seconds(i64::from(diff.secs)))
+}
+
+/// Issues a client certificate for the subject and public key of the PEM
+/// `request`, signed by the first certificate of the PEM `chain` with the PEM
+/// `key`.
+pub fn sign_request(
+    chain: &str,
+    key: &[u8],
+    request: &[u8],
+    validity_in_secs: u64,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    let request = X509Req::from_pem(request).context(ErrorKind::InvalidCertificateRequest)?;
+    let public_key = request
+        .public_key()
+        .context(ErrorKind::InvalidCertificateRequest)?;
+    if !request
+        .verify(&public_key)
+        .context(ErrorKind::InvalidCertificateRequest)?
+    {
+        return Err(Error::from(ErrorKind::InvalidCertificateRequest));
+    }
+
+    let ca = X509::from_pem(chain.as_bytes()).context(ErrorKind::DeviceCaChain)?;
+    let ca_key = PKey::private_key_from_pem(key).context(ErrorKind::DeviceCaKey)?;
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_wrap))]
+    let not_after = now.timestamp() + validity_in_secs as i64;
+    let cert = (|| {
+        let mut serial = BigNum::new()?;
+        serial.rand(SERIAL_BITS, MsbOption::MAYBE_ZERO, false)?;
+
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_serial_number(&Asn1Integer::from_bn(&serial)?)?;
+        builder.set_subject_name(request.subject_name())?;
+        builder.set_issuer_name(ca.subject_name())?;
+        builder.set_pubkey(&public_key)?;
+        builder.set_not_before(&Asn1Time::from_unix(now.timestamp())?)?;
+        builder.set_not_after(&Asn1Time::from_unix(not_after)?)?;
+
+        builder.append_extension(BasicConstraints::new().critical().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        builder.append_extension(ExtendedKeyUsage::new().client_auth().build()?)?;
+        let subject_key_id =
+            SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(&ca), None))?;
+        builder.append_extension(subject_key_id)?;
+        let authority_key_id = AuthorityKeyIdentifier::new()
+            .keyid(false)
+            .build(&builder.x509v3_context(Some(&ca), None))?;
+        builder.append_extension(authority_key_id)?;
+
+        builder.sign(&ca_key, MessageDigest::sha256())?;
+        builder.build().to_pem()
+    })().context(ErrorKind::SignCertificateRequest)?;
+
+    Ok(String::from_utf8_lossy(&cert).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::stack::Stack;
+    use openssl::x509::store::X509StoreBuilder;
+    use openssl::x509::{X509NameBuilder, X509ReqBuilder, X509StoreContext};
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn ca(key: &PKey<Private>, now: DateTime<Utc>) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "device ca").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(now.timestamp()).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::from_unix(now.timestamp() + 30 * 86400 + 60).unwrap())
+            .unwrap();
+        builder
+            .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn request(key: &PKey<Private>, cn: &str) -> Vec<u8> {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build().to_pem().unwrap()
+    }
+
+    #[test]
+    fn requests_are_signed_by_the_device_ca() {
+        let now = Utc::now();
+        let ca_key = key();
+        let ca = ca(&ca_key, now);
+        let chain = String::from_utf8(ca.to_pem().unwrap()).unwrap();
+        let leaf_key = key();
+
+        let pem = sign_request(
+            &chain,
+            &ca_key.private_key_to_pem_pkcs8().unwrap(),
+            &request(&leaf_key, "leaf1"),
+            3600,
+            now,
+        ).unwrap();
+        let cert = X509::from_pem(pem.as_bytes()).unwrap();
+
+        let cn = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap();
+        assert_eq!("leaf1", &*cn.data().as_utf8().unwrap());
+        assert!(cert.public_key().unwrap().public_eq(&leaf_key));
+        assert_eq!(
+            (now + Duration::seconds(3600)).timestamp(),
+            valid_to(&pem, now).unwrap().timestamp()
+        );
+
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(ca).unwrap();
+        let store = store.build();
+        let mut context = X509StoreContext::new().unwrap();
+        let verified = context
+            .init(&store, &cert, &Stack::new().unwrap(), |c| c.verify_cert())
+            .unwrap();
+        assert!(verified);
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        let now = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+        let ca_key = key();
+        let chain = String::from_utf8(ca(&ca_key, now).to_pem().unwrap()).unwrap();
+        let ca_key = ca_key.private_key_to_pem_pkcs8().unwrap();
+
+        let err = sign_request(&chain, &ca_key, b"not a request", 3600, now).unwrap_err();
+        assert_eq!(ErrorKind::InvalidCertificateRequest, *err.kind());
+
+        // A request whose signature doesn't match its public key.
+        let mut request = request(&key(), "leaf1");
+        let forged = X509Req::from_pem(&request).unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(forged.subject_name()).unwrap();
+        builder.set_pubkey(&key()).unwrap();
+        builder.sign(&key(), MessageDigest::sha256()).unwrap();
+        request = builder.build().to_pem().unwrap();
+        let err = sign_request(&chain, &ca_key, &request, 3600, now).unwrap_err();
+        assert_eq!(ErrorKind::InvalidCertificateRequest, *err.kind());
+    }
+
+    #[test]
+    fn device_ca_expiration() {
+        let now = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+        let chain = String::from_utf8(ca(&key(), now).to_pem().unwrap()).unwrap();
+        assert_eq!(
+            now + Duration::days(30) + Duration::seconds(60),
+            valid_to(&chain, now).unwrap()
+        );
+    }
+}
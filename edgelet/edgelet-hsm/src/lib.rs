@@ -15,12 +15,16 @@ extern crate edgelet_core;
 #[macro_use]
 extern crate failure;
 extern crate hsm;
+extern crate openssl;
 
 mod certificate_properties;
 mod crypto;
 mod error;
+mod key_cache;
+mod leaf;
 pub mod tpm;
 
-pub use crypto::{Certificate, Crypto};
+pub use crypto::{Certificate, Crypto, TrustBundle};
 pub use error::{Error, ErrorKind};
+pub use key_cache::{CachedKeyStore, DEFAULT_KEY_CACHE_MAX_ENTRIES};
 pub use tpm::{TpmKey, TpmKeyStore};
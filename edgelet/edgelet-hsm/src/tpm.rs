@@ -42,6 +42,11 @@ impl TpmKeyStore {
     }
 
     /// Activate and store a private key in the TPM.
+    /// The key is swapped in while holding the lock every signer takes, so a
+    /// sign request running at the same time uses either the old key or the
+    /// new one. Module keys are derived from this key and the name of the
+    /// key, which includes the module's generation id, so rotating a module's
+    /// keys does not activate anything here.
     pub fn activate_key(&self, key_value: &Bytes) -> Result<(), Error> {
         self.tpm
             .lock()
@@ -109,11 +114,18 @@ impl Sign for TpmKey {
     /// Sign data with this key.
     /// If an identity was given, we will derive a new key from the identity and sign the data.
     /// If an identity was not given, we will sign the data with the stored key.
+    /// The TPM only supports HMAC-SHA256.
     fn sign(
         &self,
-        _signature_algorithm: SignatureAlgorithm,
+        signature_algorithm: SignatureAlgorithm,
         data: &[u8],
     ) -> Result<Self::Signature, CoreError> {
+        if signature_algorithm != SignatureAlgorithm::HMACSHA256 {
+            return Err(CoreError::from(Error::from(
+                ErrorKind::UnsupportedSignatureAlgorithm(signature_algorithm),
+            )));
+        }
+
         match self.identity {
             KeyIdentity::Device => self
                 .tpm
@@ -1,62 +1,36 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Arc;
 use std::time::Duration;
 
+use edgelet_core::pid::Pid;
 use edgelet_core::SystemInfo as CoreSystemInfo;
 use edgelet_core::*;
 use edgelet_docker::{self, DockerConfig};
-use edgelet_http::{UrlConnector, API_VERSION};
 use futures::future::{self, FutureResult};
 use futures::prelude::*;
 use futures::stream;
-use hyper::{Body, Chunk as HyperChunk, Client};
-use management::apis::client::APIClient;
-use management::apis::configuration::Configuration;
-use management::models::{Config, ModuleDetails as HttpModuleDetails};
+use hyper::Chunk as HyperChunk;
+use management::models::{self, Config, ModuleDetails as HttpModuleDetails};
+use management::{Logs as ManagementLogs, ManagementClient};
+use serde::{Serialize, Serializer};
 use serde_json;
 use url::Url;
 
 use error::{Error, ErrorKind};
 
+/// The management API as a `ModuleRuntime`, for the iotedge tool.
+#[derive(Clone)]
 pub struct ModuleClient {
-    client: Arc<APIClient>,
+    client: ManagementClient,
 }
 
 impl ModuleClient {
     pub fn new(url: &Url) -> Result<Self, Error> {
-        let client = Client::builder().build(UrlConnector::new(url)?);
-
-        let base_path = get_base_path(url);
-        let mut configuration = Configuration::new(client);
-        configuration.base_path = base_path.to_string();
-
-        let scheme = url.scheme().to_string();
-        configuration.uri_composer = Box::new(move |base_path, path| {
-            Ok(UrlConnector::build_hyper_uri(&scheme, base_path, path)?)
-        });
-
-        let module_client = ModuleClient {
-            client: Arc::new(APIClient::new(configuration)),
-        };
-        Ok(module_client)
-    }
-}
-
-fn get_base_path(url: &Url) -> &str {
-    match url.scheme() {
-        "unix" => url.path(),
-        _ => url.as_str(),
-    }
-}
-
-impl Clone for ModuleClient {
-    fn clone(&self) -> Self {
-        ModuleClient {
-            client: self.client.clone(),
-        }
+        let client = ManagementClient::new(url)?;
+        Ok(ModuleClient { client })
     }
 }
 
@@ -97,6 +71,20 @@ impl Module for ModuleDetails {
     fn runtime_state(&self) -> Self::RuntimeStateFuture {
         future::result(runtime_status(&self.0))
     }
+
+    fn volumes(&self) -> Vec<String> {
+        self.0.config().volumes().map_or_else(Vec::new, ToOwned::to_owned)
+    }
+}
+
+/// Serializes as the management API returned it.
+impl Serialize for ModuleDetails {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
 }
 
 fn runtime_status(details: &HttpModuleDetails) -> Result<ModuleRuntimeState, Error> {
@@ -114,17 +102,35 @@ fn runtime_status(details: &HttpModuleDetails) -> Result<ModuleRuntimeState, Err
         .status()
         .exit_status()
         .and_then(|e| e.exit_time().parse().ok());
+    let create_time = details.status().create_time().and_then(|s| s.parse().ok());
     let start_time = details.status().start_time().and_then(|s| s.parse().ok());
 
     let state = ModuleRuntimeState::default()
         .with_status(status)
         .with_status_description(description)
         .with_exit_code(exit_code)
+        .with_created_at(create_time)
         .with_started_at(start_time)
         .with_finished_at(exit_time);
     Ok(state)
 }
 
+fn system_resources(resources: &models::SystemResources) -> SystemResources {
+    SystemResources::new()
+        .with_runtime_root(resources.runtime_root().map(disk_usage))
+        .with_homedir(resources.homedir().map(disk_usage))
+}
+
+// The server reports sizes it got as unsigned.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+fn disk_usage(usage: &models::DiskUsage) -> DiskUsage {
+    let space = match (usage.total_bytes(), usage.available_bytes()) {
+        (Some(&total), Some(&available)) => Ok(DiskSpace::new(total as u64, available as u64)),
+        _ => Err(usage.error().unwrap_or("unknown error").to_string()),
+    };
+    DiskUsage::new(PathBuf::from(usage.path()), space)
+}
+
 impl ModuleRegistry for ModuleClient {
     type Error = Error;
     type PullFuture = FutureResult<(), Self::Error>;
@@ -158,11 +164,42 @@ impl ModuleRuntime for ModuleClient {
     type RestartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
     type StartFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
     type StopFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type TopFuture = Box<Future<Item = Vec<Pid>, Error = Self::Error> + Send>;
     type SystemInfoFuture = Box<Future<Item = CoreSystemInfo, Error = Self::Error> + Send>;
     type RemoveAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+    type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+    type KillFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type StopAllFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type UpdateFuture = FutureResult<(), Self::Error>;
 
     fn system_info(&self) -> Self::SystemInfoFuture {
-        unimplemented!()
+        let system_info = self
+            .client
+            .system_info()
+            .map_err(Error::from)
+            .and_then(|info| {
+                // The daemon leaves these null when it couldn't reach the
+                // container runtime.
+                let (os_type, architecture) = match (info.os_type(), info.architecture()) {
+                    (Some(os_type), Some(architecture)) => (os_type, architecture),
+                    _ => {
+                        return Err(Error::from(ErrorKind::RuntimeUnavailable(
+                            info.runtime_error().unwrap_or_default().to_string(),
+                        )))
+                    }
+                };
+                Ok(CoreSystemInfo::new(os_type.to_string(), architecture.to_string())
+                    .with_version(info.version().to_string())
+                    .with_runtime_version(info.runtime_version().map(ToString::to_string))
+                    .with_runtime_api_version(info.runtime_api_version().map(ToString::to_string))
+                    .with_storage_driver(info.storage_driver().map(ToString::to_string))
+                    .with_storage_driver_status(
+                        info.storage_driver_status().cloned().unwrap_or_default(),
+                    ).with_logging_driver(info.logging_driver().map(ToString::to_string))
+                    .with_resources(info.resources().map(system_resources)))
+            });
+        Box::new(system_info)
     }
 
     fn init(&self) -> Self::InitFuture {
@@ -176,8 +213,7 @@ impl ModuleRuntime for ModuleClient {
     fn start(&self, id: &str) -> Self::StartFuture {
         let start = self
             .client
-            .module_api()
-            .start_module(API_VERSION, id)
+            .start_module(id)
             .map_err(Error::from)
             .then(|result| match result {
                 Err(e) => match *e.kind() {
@@ -192,8 +228,7 @@ impl ModuleRuntime for ModuleClient {
     fn stop(&self, id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
         let stop = self
             .client
-            .module_api()
-            .stop_module(API_VERSION, id)
+            .stop_module(id)
             .map_err(Error::from)
             .then(|result| match result {
                 Err(e) => match *e.kind() {
@@ -208,8 +243,7 @@ impl ModuleRuntime for ModuleClient {
     fn restart(&self, id: &str) -> Self::RestartFuture {
         let restart = self
             .client
-            .module_api()
-            .restart_module(API_VERSION, id)
+            .restart_module(id, None)
             .map_err(Error::from)
             .then(|result| match result {
                 Err(e) => match *e.kind() {
@@ -221,6 +255,10 @@ impl ModuleRuntime for ModuleClient {
         Box::new(restart)
     }
 
+    fn kill(&self, id: &str, signal: &str) -> Self::KillFuture {
+        Box::new(self.client.kill_module(id, signal).map_err(Error::from))
+    }
+
     fn remove(&self, _id: &str) -> Self::RemoveFuture {
         unimplemented!()
     }
@@ -228,14 +266,13 @@ impl ModuleRuntime for ModuleClient {
     fn list(&self) -> Self::ListFuture {
         let modules = self
             .client
-            .module_api()
-            .list_modules(API_VERSION)
+            .list_modules()
             .map(|list| {
                 list.modules()
                     .into_iter()
                     .cloned()
                     .map(|m| {
-                        let type_ = m.type_().clone();
+                        let type_ = m.type_().to_string();
                         let config = m.config().clone();
                         ModuleDetails(m, ModuleConfig(type_, config))
                     }).collect()
@@ -246,12 +283,11 @@ impl ModuleRuntime for ModuleClient {
     fn list_with_details(&self) -> Self::ListWithDetailsStream {
         let modules = self
             .client
-            .module_api()
-            .list_modules(API_VERSION)
+            .list_modules()
             .map_err(Error::from)
             .map(|list| {
                 let iter = list.modules().to_owned().into_iter().map(|m| {
-                    let type_ = m.type_().clone();
+                    let type_ = m.type_().to_string();
                     let config = m.config().clone();
                     let runtime_state = runtime_status(&m)?;
                     let module = ModuleDetails(m, ModuleConfig(type_, config));
@@ -264,11 +300,9 @@ impl ModuleRuntime for ModuleClient {
     }
 
     fn logs(&self, id: &str, options: &LogOptions) -> Self::LogsFuture {
-        let tail = &options.tail().to_string();
         let result = self
             .client
-            .module_api()
-            .module_logs(API_VERSION, id, options.follow(), tail)
+            .module_logs(id, options)
             .map(Logs)
             .map_err(Error::from);
         Box::new(result)
@@ -287,9 +321,29 @@ impl ModuleRuntime for ModuleClient {
             future::join_all(n).map(|_| ())
         }))
     }
+
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+        unimplemented!()
+    }
+
+    fn inspect(&self, _id: &str) -> Self::InspectFuture {
+        unimplemented!()
+    }
+
+    fn stop_all(&self, options: &StopAllOptions) -> Self::StopAllFuture {
+        Box::new(stop_in_order(self, options))
+    }
+
+    fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+        unimplemented!()
+    }
+
+    fn top(&self, _id: &str) -> Self::TopFuture {
+        unimplemented!()
+    }
 }
 
-pub struct Logs(Body);
+pub struct Logs(ManagementLogs);
 
 pub struct Chunk(HyperChunk);
 
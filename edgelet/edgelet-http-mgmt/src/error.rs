@@ -12,8 +12,8 @@ use http::{Error as HttpError, Response, StatusCode};
 use hyper::{Body, Error as HyperError, StatusCode as HyperStatusCode};
 use serde_json;
 
-use management::apis::Error as MgmtError;
 use management::models::ErrorResponse;
+use management::Error as ManagementClientError;
 
 use IntoResponse;
 
@@ -45,11 +45,19 @@ pub enum ErrorKind {
     #[fail(display = "Invalid or missing API version")]
     InvalidApiVersion,
     #[fail(display = "Client error")]
-    Client(MgmtError<serde_json::Value>),
+    Client,
     #[fail(display = "State not modified")]
     NotModified,
     #[fail(display = "Parse error")]
     Parse,
+    #[fail(display = "Module {} was removed while it was starting", _0)]
+    ModuleRemoved(String),
+    #[fail(display = "Module {} exited right after it started, with exit code {}", _0, _1)]
+    ModuleExited(String, i64),
+    #[fail(display = "Identity for module {} has another generation id", _0)]
+    StaleGenerationId(String),
+    #[fail(display = "The daemon could not query the container runtime: {}", _0)]
+    RuntimeUnavailable(String),
 }
 
 impl Fail for Error {
@@ -128,15 +136,14 @@ impl From<EdgeletHttpError> for Error {
     }
 }
 
-impl From<MgmtError<serde_json::Value>> for Error {
-    fn from(error: MgmtError<serde_json::Value>) -> Self {
-        match error {
-            MgmtError::Hyper(h) => From::from(h),
-            MgmtError::Serde(s) => From::from(s),
-            MgmtError::Api(ref e) if e.code == HyperStatusCode::NOT_MODIFIED => {
-                From::from(ErrorKind::NotModified)
+impl From<ManagementClientError> for Error {
+    fn from(error: ManagementClientError) -> Self {
+        if error.status() == Some(HyperStatusCode::NOT_MODIFIED) {
+            From::from(ErrorKind::NotModified)
+        } else {
+            Error {
+                inner: error.context(ErrorKind::Client),
             }
-            MgmtError::Api(_) => From::from(ErrorKind::Client(error)),
         }
     }
 }
@@ -170,6 +177,8 @@ impl IntoResponse for Error {
             ErrorKind::BadParam | ErrorKind::BadBody | ErrorKind::InvalidApiVersion => {
                 StatusCode::BAD_REQUEST
             }
+            ErrorKind::ModuleRemoved(_) => StatusCode::NOT_FOUND,
+            ErrorKind::StaleGenerationId(_) => StatusCode::PRECONDITION_FAILED,
             _ => {
                 error!("Internal server error: {}", message);
                 StatusCode::INTERNAL_SERVER_ERROR
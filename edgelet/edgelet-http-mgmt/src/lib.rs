@@ -9,7 +9,7 @@
 #![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
 #![cfg_attr(feature = "cargo-clippy", allow(stutter, use_self))]
 
-#[cfg(test)]
+extern crate base64;
 extern crate chrono;
 extern crate edgelet_core;
 extern crate edgelet_docker;
@@ -18,6 +18,8 @@ extern crate edgelet_http;
 extern crate edgelet_iothub;
 #[cfg(test)]
 extern crate edgelet_test_utils;
+#[macro_use]
+extern crate edgelet_utils;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
@@ -34,8 +36,11 @@ extern crate serde;
 #[cfg(test)]
 #[macro_use]
 extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
 #[cfg(not(test))]
 extern crate serde_json;
+extern crate tokio;
 extern crate url;
 
 use http::Response;
@@ -47,8 +52,8 @@ mod server;
 
 pub use client::ModuleClient;
 pub use error::{Error, ErrorKind};
-pub use server::ListModules;
-pub use server::ManagementService;
+pub use server::{EnvRedaction, ListModules};
+pub use server::{route_class, ManagementService};
 
 pub trait IntoResponse {
     fn into_response(self) -> Response<Body>;
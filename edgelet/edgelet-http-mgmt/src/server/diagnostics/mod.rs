@@ -0,0 +1,4 @@
+// Copyright (c) Microsoft. All rights reserved.
+mod runtime_errors;
+
+pub use self::runtime_errors::GetRuntimeErrors;
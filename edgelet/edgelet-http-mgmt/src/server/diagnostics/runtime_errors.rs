@@ -0,0 +1,124 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use chrono::SecondsFormat;
+use edgelet_core::{RuntimeErrorLog, RuntimeErrorRecord};
+use edgelet_http::route::{Handler, Parameters};
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use management::models::*;
+use serde_json;
+
+use error::ErrorKind;
+use IntoResponse;
+
+/// Reports the container runtime operations that failed most recently.
+pub struct GetRuntimeErrors {
+    runtime_errors: RuntimeErrorLog,
+}
+
+impl GetRuntimeErrors {
+    pub fn new(runtime_errors: RuntimeErrorLog) -> Self {
+        GetRuntimeErrors { runtime_errors }
+    }
+}
+
+impl Handler<Parameters> for GetRuntimeErrors {
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        debug!("Get Runtime Errors");
+        let body = RuntimeErrorList::new(
+            self.runtime_errors
+                .records()
+                .iter()
+                .map(runtime_error)
+                .collect(),
+        );
+
+        let response = match serde_json::to_string(&body).context(ErrorKind::Serde) {
+            Ok(b) => Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                .body(b.into())
+                .unwrap_or_else(|e| e.into_response()),
+            Err(e) => e.into_response(),
+        };
+
+        Box::new(future::ok(response))
+    }
+}
+
+fn runtime_error(record: &RuntimeErrorRecord) -> RuntimeError {
+    let mut error = RuntimeError::new(
+        record.at().to_rfc3339_opts(SecondsFormat::Secs, true),
+        record.operation().to_string(),
+        record.kind().to_string(),
+        record.message().to_string(),
+    );
+    if let Some(module) = record.module() {
+        error.set_module_id(module.to_string());
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use edgelet_http::route::Parameters;
+    use futures::Stream;
+
+    use super::*;
+
+    fn get(runtime_errors: RuntimeErrorLog) -> RuntimeErrorList {
+        let handler = GetRuntimeErrors::new(runtime_errors);
+        let request = Request::get("http://localhost/diagnostics/runtime-errors")
+            .body(Body::default())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response.into_body().concat2().wait().unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn no_errors() {
+        assert!(get(RuntimeErrorLog::default()).errors().is_empty());
+    }
+
+    #[test]
+    fn errors_are_listed_oldest_first() {
+        let runtime_errors = RuntimeErrorLog::default();
+        let at = Utc.ymd(2018, 4, 3).and_hms(9, 31, 0);
+        runtime_errors.record(
+            RuntimeErrorRecord::new(
+                at,
+                "start",
+                "NotFound",
+                "No such container: tempSensor".to_string(),
+            ).with_module(Some("tempSensor".to_string())),
+        );
+        runtime_errors.record(RuntimeErrorRecord::new(
+            at,
+            "list",
+            "Docker",
+            "Container runtime error".to_string(),
+        ));
+
+        let list = get(runtime_errors);
+        let errors = list.errors();
+        assert_eq!(2, errors.len());
+        assert_eq!("2018-04-03T09:31:00Z", errors[0].timestamp());
+        assert_eq!("start", errors[0].operation());
+        assert_eq!(Some("tempSensor"), errors[0].module_id());
+        assert_eq!("NotFound", errors[0].kind());
+        assert_eq!("No such container: tempSensor", errors[0].message());
+        assert_eq!("list", errors[1].operation());
+        assert_eq!(None, errors[1].module_id());
+    }
+}
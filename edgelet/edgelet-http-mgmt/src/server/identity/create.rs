@@ -11,8 +11,8 @@ use hyper::{Body, Error as HyperError};
 use serde::Serialize;
 use serde_json;
 
-use edgelet_core::{Identity as CoreIdentity, IdentityManager, IdentitySpec};
-use edgelet_http::route::{Handler, Parameters};
+use edgelet_core::{Identity as CoreIdentity, IdentityError, IdentityManager, IdentitySpec};
+use edgelet_http::route::{Handler, Parameters, Query};
 use management::models::{Identity, IdentitySpec as CreateIdentitySpec};
 
 use error::{Error, ErrorKind};
@@ -22,7 +22,7 @@ pub struct CreateIdentity<I>
 where
     I: 'static + IdentityManager,
     I::Identity: Serialize,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     id_manager: Arc<Mutex<I>>,
 }
@@ -31,7 +31,7 @@ impl<I> CreateIdentity<I>
 where
     I: 'static + IdentityManager,
     I::Identity: Serialize,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     pub fn new(id_manager: I) -> Self {
         CreateIdentity {
@@ -44,45 +44,80 @@ impl<I> Handler<Parameters> for CreateIdentity<I>
 where
     I: 'static + IdentityManager + Send,
     I::Identity: CoreIdentity + Serialize,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     fn handle(
         &self,
         req: Request<Body>,
         _params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let allow_existing = match Query::from_request(&req)
+            .get_bool("allowExisting")
+            .context(ErrorKind::BadParam)
+        {
+            Ok(allow_existing) => allow_existing.unwrap_or(false),
+            Err(e) => return Box::new(future::ok(Error::from(e).into_response())),
+        };
+
         let id_mgr = self.id_manager.clone();
-        let response =
-            read_request(req)
-                .and_then(move |spec| {
-                    let mut rid = id_mgr.lock().unwrap();
-                    rid.create(spec)
-                        .map(|identity| {
-                            let identity = Identity::new(
-                                identity.module_id().to_string(),
-                                identity.managed_by().to_string(),
-                                identity.generation_id().to_string(),
-                                identity.auth_type().to_string(),
-                            );
-
-                            match serde_json::to_string(&identity).context(ErrorKind::Serde) {
-                                Ok(b) => Response::builder()
-                                    .status(StatusCode::OK)
-                                    .header(CONTENT_TYPE, "application/json")
-                                    .header(CONTENT_LENGTH, b.len().to_string().as_str())
-                                    .body(b.into())
-                                    .unwrap_or_else(|e| e.into_response()),
-                                Err(e) => e.into_response(),
-                            }
-                        }).or_else(|e| future::ok(e.into_response()))
-                }).or_else(|e| {
-                    future::ok(e.into_response()) as FutureResult<Response<Body>, HyperError>
-                });
+        let response = read_request(req)
+            .and_then(move |spec| {
+                let module_id = spec.module_id().to_string();
+                let create = id_mgr.lock().unwrap().create(spec);
+                create.then(move |result| match result {
+                    Ok(identity) => {
+                        future::Either::A(future::ok(identity_response(&identity, StatusCode::OK)))
+                    }
+                    Err(e) => {
+                        if e.is_conflict() {
+                            // Hand back the identity that is in the way so the
+                            // caller can reconcile, or treat it as created when
+                            // asked to.
+                            let status = if allow_existing {
+                                StatusCode::OK
+                            } else {
+                                StatusCode::CONFLICT
+                            };
+                            let get = id_mgr.lock().unwrap().get(IdentitySpec::new(&module_id));
+                            future::Either::B(get.then(move |existing| {
+                                Ok(match existing {
+                                    Ok(Some(identity)) => identity_response(&identity, status),
+                                    Ok(None) => e.into_response(),
+                                    Err(e) => e.into_response(),
+                                })
+                            }))
+                        } else {
+                            future::Either::A(future::ok(e.into_response()))
+                        }
+                    }
+                })
+            }).or_else(|e| {
+                future::ok(e.into_response()) as FutureResult<Response<Body>, HyperError>
+            });
 
         Box::new(response)
     }
 }
 
+fn identity_response<T: CoreIdentity>(identity: &T, status: StatusCode) -> Response<Body> {
+    let identity = Identity::new(
+        identity.module_id().to_string(),
+        identity.managed_by().to_string(),
+        identity.generation_id().to_string(),
+        identity.auth_type().to_string(),
+    );
+
+    match serde_json::to_string(&identity).context(ErrorKind::Serde) {
+        Ok(b) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_LENGTH, b.len().to_string().as_str())
+            .body(b.into())
+            .unwrap_or_else(|e| e.into_response()),
+        Err(e) => e.into_response(),
+    }
+}
+
 fn read_request(req: Request<Body>) -> impl Future<Item = IdentitySpec, Error = Error> {
     req.into_body()
         .concat2()
@@ -237,4 +272,105 @@ mod tests {
             }).wait()
             .unwrap();
     }
+
+    #[test]
+    fn create_existing_returns_conflict_with_identity() {
+        let manager = TestIdentityManager::new(vec![TestIdentity::new(
+            "m1",
+            "iotedge",
+            "g1",
+            AuthType::Sas,
+        )]).with_fail_get(false);
+        let handler = CreateIdentity::new(manager);
+        let val = json!({ "moduleId": "m1" });
+        let request = Request::post("http://localhost/identities")
+            .body(serde_json::to_string(&val).unwrap().into())
+            .unwrap();
+
+        let response = handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let identity: TestIdentity = serde_json::from_slice(&body).unwrap();
+                assert_eq!("m1", identity.module_id());
+                assert_eq!("iotedge", identity.managed_by());
+                assert_eq!("g1", identity.generation_id());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn create_existing_with_allow_existing_succeeds() {
+        let manager = TestIdentityManager::new(vec![TestIdentity::new(
+            "m1",
+            "iotedge",
+            "g1",
+            AuthType::Sas,
+        )]).with_fail_get(false);
+        let handler = CreateIdentity::new(manager);
+        let val = json!({ "moduleId": "m1" });
+        let request = Request::post("http://localhost/identities?allowExisting=true")
+            .body(serde_json::to_string(&val).unwrap().into())
+            .unwrap();
+
+        let response = handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let identity: TestIdentity = serde_json::from_slice(&body).unwrap();
+                assert_eq!("m1", identity.module_id());
+                assert_eq!("g1", identity.generation_id());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn create_with_allow_existing_creates_missing_identity() {
+        let manager = TestIdentityManager::new(vec![]);
+        let handler = CreateIdentity::new(manager);
+        let val = json!({ "moduleId": "m1" });
+        let request = Request::post("http://localhost/identities?allowExisting=true")
+            .body(serde_json::to_string(&val).unwrap().into())
+            .unwrap();
+
+        let response = handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[test]
+    fn create_with_invalid_allow_existing_fails() {
+        let manager = TestIdentityManager::new(vec![]);
+        let handler = CreateIdentity::new(manager);
+        let val = json!({ "moduleId": "m1" });
+        let request = Request::post("http://localhost/identities?allowExisting=maybe")
+            .body(serde_json::to_string(&val).unwrap().into())
+            .unwrap();
+
+        let response = handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
 }
@@ -2,19 +2,25 @@
 
 use std::sync::Mutex;
 
-use edgelet_core::{IdentityManager, IdentitySpec};
-use edgelet_http::route::{Handler, Parameters};
+use edgelet_core::{IdentityError, IdentityManager, IdentitySpec};
+use edgelet_http::route::{Handler, Parameters, Query};
+use failure::{Fail, ResultExt};
 use futures::{future, Future};
+use http::header::IF_MATCH;
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
 
 use error::{Error, ErrorKind};
 use IntoResponse;
 
+/// Deletes an identity. A generation id, in the `generationId` query
+/// parameter or an `If-Match` header, makes the delete conditional: an
+/// identity that has been created anew since is left alone, and the request
+/// fails as a precondition that no longer holds.
 pub struct DeleteIdentity<I>
 where
     I: 'static + IdentityManager,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     id_manager: Mutex<I>,
 }
@@ -22,7 +28,7 @@ where
 impl<I> DeleteIdentity<I>
 where
     I: 'static + IdentityManager,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     pub fn new(id_manager: I) -> Self {
         DeleteIdentity {
@@ -34,36 +40,71 @@ where
 impl<I> Handler<Parameters> for DeleteIdentity<I>
 where
     I: 'static + IdentityManager + Send,
-    <I as IdentityManager>::Error: IntoResponse,
+    <I as IdentityManager>::Error: IntoResponse + IdentityError,
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-        let response = match params.name("name") {
-            Some(name) => {
+        let response = match params
+            .name("name")
+            .ok_or_else(|| Error::from(ErrorKind::BadParam))
+            .and_then(|name| Ok((name, generation_id(&req)?))) {
+            Ok((name, generation_id)) => {
+                let spec = IdentitySpec::new(name);
+                let spec = match generation_id {
+                    Some(generation_id) => spec.with_generation_id(generation_id),
+                    None => spec,
+                };
+                let module_id = name.to_string();
                 let result = self
                     .id_manager
                     .lock()
                     .unwrap()
-                    .delete(IdentitySpec::new(name))
+                    .delete(spec)
                     .map(|_| {
                         Response::builder()
                             .status(StatusCode::NO_CONTENT)
                             .body(Body::default())
                             .unwrap_or_else(|e| e.into_response())
-                    }).or_else(|e| future::ok(e.into_response()));
+                    }).or_else(move |e| {
+                        future::ok(if e.is_stale() {
+                            Error::from(e.context(ErrorKind::StaleGenerationId(module_id)))
+                                .into_response()
+                        } else {
+                            e.into_response()
+                        })
+                    });
                 future::Either::A(result)
             }
 
-            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+            Err(e) => future::Either::B(future::ok(e.into_response())),
         };
 
         Box::new(response)
     }
 }
 
+/// The generation id the delete is conditioned on, if any. A quoted entity
+/// tag is taken as the generation id it quotes, and `*` matches any.
+fn generation_id(req: &Request<Body>) -> Result<Option<String>, Error> {
+    let query = Query::from_request(req);
+    let value = match query.get("generationId").context(ErrorKind::BadParam)? {
+        Some(generation_id) => generation_id,
+        None => match req.headers().get(IF_MATCH) {
+            Some(value) => value.to_str().context(ErrorKind::BadParam)?,
+            None => return Ok(None),
+        },
+    };
+
+    match value.trim().trim_left_matches("W/").trim_matches('"') {
+        "" => Err(Error::from(ErrorKind::BadParam)),
+        "*" => Ok(None),
+        generation_id => Ok(Some(generation_id.to_string())),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use edgelet_core::{AuthType, Identity};
@@ -99,6 +140,75 @@ mod tests {
         );
     }
 
+    fn conditional_delete(uri: &str, if_match: Option<&str>) -> (Response<Body>, Vec<String>) {
+        let mut request = Request::delete(uri);
+        if let Some(if_match) = if_match {
+            request.header(IF_MATCH, if_match);
+        }
+        let request = request.body(Body::default()).unwrap();
+        let manager = TestIdentityManager::new(vec![
+            TestIdentity::new("m1", "iotedge", "1", AuthType::Sas),
+            TestIdentity::new("m2", "iotedge", "2", AuthType::Sas),
+        ]);
+        let handler = DeleteIdentity::new(manager);
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "m2".to_string())]);
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+        let list = handler.id_manager.lock().unwrap().list().wait().unwrap();
+        let module_ids = list.iter().map(|id| id.module_id().to_string()).collect();
+        (response, module_ids)
+    }
+
+    #[test]
+    fn delete_succeeds_when_generation_matches() {
+        for &(uri, if_match) in &[
+            ("http://localhost/identities/m2?generationId=2", None),
+            ("http://localhost/identities/m2", Some("\"2\"")),
+            ("http://localhost/identities/m2", Some("*")),
+        ] {
+            let (response, module_ids) = conditional_delete(uri, if_match);
+
+            assert_eq!(StatusCode::NO_CONTENT, response.status());
+            assert_eq!(vec!["m1".to_string()], module_ids);
+        }
+    }
+
+    #[test]
+    fn delete_fails_when_generation_differs() {
+        for &(uri, if_match) in &[
+            ("http://localhost/identities/m2?generationId=1", None),
+            ("http://localhost/identities/m2", Some("\"1\"")),
+        ] {
+            let (response, module_ids) = conditional_delete(uri, if_match);
+
+            assert_eq!(StatusCode::PRECONDITION_FAILED, response.status());
+            assert_eq!(vec!["m1".to_string(), "m2".to_string()], module_ids);
+            response
+                .into_body()
+                .concat2()
+                .and_then(|body| {
+                    let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+                    assert!(
+                        error
+                            .message()
+                            .starts_with("Identity for module m2 has another generation id")
+                    );
+                    Ok(())
+                }).wait()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn delete_with_empty_generation_is_bad_request() {
+        let (response, module_ids) =
+            conditional_delete("http://localhost/identities/m2?generationId=", None);
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(2, module_ids.len());
+    }
+
     #[test]
     fn delete_no_name_param() {
         let manager = TestIdentityManager::new(vec![]);
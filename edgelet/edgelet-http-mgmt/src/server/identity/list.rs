@@ -1,7 +1,11 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::{Identity as CoreIdentity, IdentityManager};
-use edgelet_http::route::{Handler, Parameters};
+use std::i32;
+
+use base64;
+use edgelet_core::{Identity as CoreIdentity, IdentityFilter, IdentityManager};
+use edgelet_http::route::{Handler, Parameters, Query};
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
 use failure::ResultExt;
 use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
@@ -11,7 +15,7 @@ use management::models::{Identity, IdentityList};
 use serde::Serialize;
 use serde_json;
 
-use error::ErrorKind;
+use error::{Error, ErrorKind};
 use IntoResponse;
 
 pub struct ListIdentities<I>
@@ -39,44 +43,101 @@ where
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         _params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-        let response = self.id_manager.list().then(|result| {
-            match result.context(ErrorKind::IdentityManager) {
-                Ok(identities) => {
-                    let body = IdentityList::new(
+        let (filter, top) = match filter_from_request(&req) {
+            Ok(filter) => filter,
+            Err(err) => return Box::new(future::ok(err.into_response())),
+        };
+        let response = self
+            .id_manager
+            .list_with_filter(filter)
+            .then(move |result| {
+                let mut identities = result.context(ErrorKind::IdentityManager)?;
+
+                // One identity more than the page holds was asked for, which
+                // tells whether another page follows.
+                let next = match top {
+                    Some(top) if identities.len() > top => {
+                        identities.truncate(top);
                         identities
-                            .iter()
-                            .map(|identity| {
-                                Identity::new(
-                                    identity.module_id().to_string(),
-                                    identity.managed_by().to_string(),
-                                    identity.generation_id().to_string(),
-                                    identity.auth_type().to_string(),
-                                )
-                            }).collect(),
-                    );
-                    let result = match serde_json::to_string(&body).context(ErrorKind::Serde) {
-                        Ok(b) => Response::builder()
-                            .status(StatusCode::OK)
-                            .header(CONTENT_TYPE, "application/json")
-                            .header(CONTENT_LENGTH, b.len().to_string().as_str())
-                            .body(b.into())
-                            .unwrap_or_else(|e| e.into_response()),
-                        Err(e) => e.into_response(),
-                    };
-
-                    future::ok(result)
+                            .last()
+                            .map(|identity| identity.module_id().to_string())
+                    }
+                    _ => None,
+                };
+                let mut body = IdentityList::new(
+                    identities
+                        .iter()
+                        .map(|identity| {
+                            Identity::new(
+                                identity.module_id().to_string(),
+                                identity.managed_by().to_string(),
+                                identity.generation_id().to_string(),
+                                identity.auth_type().to_string(),
+                            )
+                        }).collect(),
+                );
+                if let Some(last) = next {
+                    body.set_continuation_token(base64::encode_config(&last, base64::URL_SAFE));
                 }
-                Err(e) => future::ok(e.into_response()),
-            }
-        });
+                let b = serde_json::to_string(&body).context(ErrorKind::Serde)?;
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                    .body(b.into())?)
+            }).or_else(|e: Error| Ok(e.into_response()));
 
         Box::new(response)
     }
 }
 
+/// The identities a request asks for, and how many of them fit on a page.
+///
+/// Like the pages of modules, the continuation token holds the module id of
+/// the last identity of the previous page, so that the next page starts right
+/// after it no matter which identities were created or deleted in between.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+fn filter_from_request(req: &Request<Body>) -> Result<(IdentityFilter, Option<usize>), Error> {
+    let query = Query::from_request(req);
+    let managed_by = query
+        .get("managedBy")
+        .context(ErrorKind::BadParam)?
+        .map(ToString::to_string);
+    let top = query
+        .get_i32_in_range("top", 1, i32::MAX)
+        .context(ErrorKind::BadParam)?
+        .map(|top| top as usize);
+    let skip = query
+        .get_i32_in_range("skip", 0, i32::MAX)
+        .context(ErrorKind::BadParam)?
+        .map_or(0, |skip| skip as usize);
+    let after = match query.get("continuationToken").context(ErrorKind::BadParam)? {
+        Some(token) => Some(decode_token(token).context(ErrorKind::BadParam)?),
+        None => None,
+    };
+
+    let filter = IdentityFilter::new()
+        .with_managed_by(managed_by)
+        .with_after(after)
+        .with_skip(skip)
+        .with_top(top.map(|top| top + 1));
+    Ok((filter, top))
+}
+
+fn decode_token(token: &str) -> Result<String, HttpError> {
+    base64::decode_config(token, base64::URL_SAFE)
+        .ok()
+        .and_then(|module_id| String::from_utf8(module_id).ok())
+        .ok_or_else(|| {
+            HttpError::from(HttpErrorKind::InvalidQueryParameter(
+                "continuationToken".to_string(),
+            ))
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use edgelet_core::AuthType;
@@ -118,6 +179,103 @@ mod tests {
             .unwrap();
     }
 
+    fn manager() -> TestIdentityManager {
+        TestIdentityManager::new(vec![
+            TestIdentity::new("m4", "iotedge", "4", AuthType::Sas),
+            TestIdentity::new("other", "someone else", "5", AuthType::Sas),
+            TestIdentity::new("m2", "iotedge", "2", AuthType::Sas),
+            TestIdentity::new("m1", "iotedge", "1", AuthType::Sas),
+            TestIdentity::new("m3", "iotedge", "3", AuthType::Sas),
+            TestIdentity::new("m0", "IoTEdge", "0", AuthType::Sas),
+        ])
+    }
+
+    fn list(query: &str) -> (Vec<String>, Option<String>) {
+        let request = Request::get(format!("http://localhost/identities{}", query))
+            .body(Body::default())
+            .unwrap();
+        let response = ListIdentities::new(manager())
+            .handle(request, Parameters::new())
+            .wait()
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status(), "{}", query);
+        let body = response.into_body().concat2().wait().unwrap();
+        let list: IdentityList = serde_json::from_slice(&body).unwrap();
+        let module_ids = list
+            .identities()
+            .iter()
+            .map(|identity| identity.module_id().to_string())
+            .collect();
+        (module_ids, list.continuation_token().map(ToString::to_string))
+    }
+
+    #[test]
+    fn list_filters_by_managed_by() {
+        let (module_ids, token) = list("?managedBy=iotedge");
+        assert_eq!(vec!["m1", "m2", "m3", "m4"], module_ids);
+        assert_eq!(None, token);
+
+        let (module_ids, _) = list("?managedBy=someone%20else");
+        assert_eq!(vec!["other"], module_ids);
+
+        let (module_ids, _) = list("?managedBy=nobody");
+        assert!(module_ids.is_empty());
+    }
+
+    #[test]
+    fn list_pages_continue_after_the_last_identity_of_the_previous_page() {
+        let next = |token: Option<String>| format!("?top=2&continuationToken={}", token.unwrap());
+
+        let (module_ids, token) = list("?top=2");
+        assert_eq!(vec!["m0", "m1"], module_ids);
+
+        let (module_ids, token) = list(&next(token));
+        assert_eq!(vec!["m2", "m3"], module_ids);
+
+        let (module_ids, token) = list(&next(token));
+        assert_eq!(vec!["m4", "other"], module_ids);
+        assert_eq!(None, token);
+
+        let (module_ids, token) = list("?skip=4");
+        assert_eq!(vec!["m4", "other"], module_ids);
+        assert_eq!(None, token);
+
+        let (module_ids, token) = list("?top=6");
+        assert_eq!(6, module_ids.len());
+        assert_eq!(None, token);
+    }
+
+    #[test]
+    fn list_filters_and_pages_together() {
+        let (module_ids, token) = list("?managedBy=iotedge&top=2&skip=1");
+        assert_eq!(vec!["m2", "m3"], module_ids);
+
+        let query = format!("?managedBy=iotedge&top=2&continuationToken={}", token.unwrap());
+        let (module_ids, token) = list(&query);
+        assert_eq!(vec!["m4"], module_ids);
+        assert_eq!(None, token);
+    }
+
+    #[test]
+    fn list_rejects_bad_parameters() {
+        for query in &[
+            "?top=0",
+            "?top=x",
+            "?skip=-1",
+            "?continuationToken=%25%25",
+            "?managedBy=a&managedBy=b",
+        ] {
+            let request = Request::get(format!("http://localhost/identities{}", query))
+                .body(Body::default())
+                .unwrap();
+            let response = ListIdentities::new(manager())
+                .handle(request, Parameters::new())
+                .wait()
+                .unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", query);
+        }
+    }
+
     #[test]
     fn list_fails() {
         let manager = TestIdentityManager::new(vec![]).with_fail_list(true);
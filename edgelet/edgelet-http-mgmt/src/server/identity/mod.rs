@@ -3,11 +3,13 @@
 mod create;
 mod delete;
 mod list;
+mod rotate;
 mod update;
 
 pub use self::create::CreateIdentity;
 pub use self::delete::DeleteIdentity;
 pub use self::list::ListIdentities;
+pub use self::rotate::RotateIdentity;
 pub use self::update::UpdateIdentity;
 
 #[cfg(test)]
@@ -0,0 +1,186 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::sync::Mutex;
+
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use serde::Serialize;
+use serde_json;
+
+use edgelet_core::{Identity as CoreIdentity, IdentityManager, IdentitySpec};
+use edgelet_http::route::{Handler, Parameters};
+use management::models::Identity;
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+pub struct RotateIdentity<I>
+where
+    I: 'static + IdentityManager,
+    I::Identity: Serialize,
+    <I as IdentityManager>::Error: IntoResponse,
+{
+    id_manager: Mutex<I>,
+}
+
+impl<I> RotateIdentity<I>
+where
+    I: 'static + IdentityManager,
+    I::Identity: Serialize,
+    <I as IdentityManager>::Error: IntoResponse,
+{
+    pub fn new(id_manager: I) -> Self {
+        RotateIdentity {
+            id_manager: Mutex::new(id_manager),
+        }
+    }
+}
+
+impl<I> Handler<Parameters> for RotateIdentity<I>
+where
+    I: 'static + IdentityManager + Send,
+    I::Identity: CoreIdentity + Serialize,
+    <I as IdentityManager>::Error: IntoResponse,
+{
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let response = match params.name("name") {
+            Some(name) => {
+                let result = self
+                    .id_manager
+                    .lock()
+                    .unwrap()
+                    .rotate_keys(IdentitySpec::new(name))
+                    .map(|identity| write_response(&identity))
+                    .or_else(|e| future::ok(e.into_response()));
+                future::Either::A(result)
+            }
+
+            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+        };
+
+        Box::new(response)
+    }
+}
+
+fn write_response<I>(identity: &I) -> Response<Body>
+where
+    I: 'static + CoreIdentity + Serialize,
+{
+    let identity = Identity::new(
+        identity.module_id().to_string(),
+        identity.managed_by().to_string(),
+        identity.generation_id().to_string(),
+        identity.auth_type().to_string(),
+    );
+
+    match serde_json::to_string(&identity).context(ErrorKind::Serde) {
+        Ok(b) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_LENGTH, b.len().to_string().as_str())
+            .body(b.into())
+            .unwrap_or_else(|e| e.into_response()),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use edgelet_core::AuthType;
+    use edgelet_test_utils::identity::{TestIdentity, TestIdentityManager};
+    use futures::Stream;
+    use management::models::ErrorResponse;
+
+    use super::*;
+
+    #[test]
+    fn rotate_bumps_generation_id() {
+        let manager = TestIdentityManager::new(vec![
+            TestIdentity::new("m1", "iotedge", "g1", AuthType::Sas),
+            TestIdentity::new("m2", "iotedge", "g2", AuthType::Sas),
+        ]);
+        let handler = RotateIdentity::new(manager);
+        let request = Request::post("http://localhost/identities/m1/rotate")
+            .body(Body::default())
+            .unwrap();
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "m1".to_string())]);
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "application/json",
+            *response.headers().get(CONTENT_TYPE).unwrap()
+        );
+
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let identity: TestIdentity = serde_json::from_slice(&body).unwrap();
+                assert_eq!("m1", identity.module_id());
+                assert_eq!("iotedge", identity.managed_by());
+                assert_eq!("1", identity.generation_id());
+                assert_eq!(AuthType::Sas, identity.auth_type());
+                Ok(())
+            }).wait()
+            .unwrap();
+
+        let list = handler.id_manager.lock().unwrap().list().wait().unwrap();
+        let generation_ids: Vec<&str> = list.iter().map(|id| id.generation_id()).collect();
+        assert_eq!(vec!["1", "g2"], generation_ids);
+    }
+
+    #[test]
+    fn rotate_no_name_param() {
+        let manager = TestIdentityManager::new(vec![]);
+        let handler = RotateIdentity::new(manager);
+        let request = Request::post("http://localhost/identities//rotate")
+            .body(Body::default())
+            .unwrap();
+        let response = handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!("Bad parameter", error.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn rotate_fails_for_missing_identity() {
+        let manager = TestIdentityManager::new(vec![]);
+        let handler = RotateIdentity::new(manager);
+        let request = Request::post("http://localhost/identities/m1/rotate")
+            .body(Body::default())
+            .unwrap();
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "m1".to_string())]);
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!("Module not found", error.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+}
@@ -1,23 +1,31 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+mod diagnostics;
 mod identity;
 mod module;
 mod system_info;
 
 use std::error::Error as StdError;
+use std::sync::Arc;
 
 use edgelet_core::{
-    Error as CoreError, IdentityManager, Module, ModuleRegistry, ModuleRuntime, Policy,
+    DaemonHealth, DiskMonitor, Error as CoreError, IdentityError, IdentityManager, KillSignals,
+    MetricsRegistry, Module, ModuleEvents, ModuleRegistry, ModuleRuntime, Policy,
+    RuntimeErrorLog,
 };
 use edgelet_http::authorization::Authorization;
+use edgelet_http::metrics::GetMetrics;
 use edgelet_http::route::*;
+use edgelet_http::RouteClass;
 use failure;
 use futures::{future, Future};
+use http::Method;
 use hyper::service::{NewService, Service};
-use hyper::{Body, Request};
+use hyper::{Body, Chunk as HyperChunk, Request};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use self::diagnostics::*;
 use self::identity::*;
 pub use self::module::*;
 use self::system_info::*;
@@ -35,37 +43,63 @@ pub struct ManagementService {
 
 impl ManagementService {
     // clippy bug: https://github.com/rust-lang-nursery/rust-clippy/issues/3220
-    #[cfg_attr(feature = "cargo-clippy", allow(new_ret_no_self))]
-    pub fn new<M, I>(runtime: &M, identity: &I) -> impl Future<Item = Self, Error = failure::Error>
+    #[cfg_attr(
+        feature = "cargo-clippy",
+        allow(new_ret_no_self, too_many_arguments)
+    )]
+    pub fn new<M, I>(
+        runtime: &M,
+        identity: &I,
+        health: &DaemonHealth,
+        metrics: &MetricsRegistry,
+        runtime_errors: &RuntimeErrorLog,
+        redaction: &EnvRedaction,
+        signals: KillSignals,
+        events: &ModuleEvents,
+        disks: Option<&DiskMonitor>,
+    ) -> impl Future<Item = Self, Error = failure::Error>
     where
         M: 'static + ModuleRuntime + Clone + Send + Sync,
         <M::Module as Module>::Config: DeserializeOwned + Serialize,
         M::Error: IntoResponse,
         M::Error: Into<CoreError>,
-        <M::Module as Module>::Error: Into<CoreError>,
-        M::Logs: Into<Body>,
+        M::Module: Send,
+        <M::Module as Module>::Error: Into<CoreError> + Into<M::Error>,
+        M::Chunk: Into<HyperChunk>,
         <M::ModuleRegistry as ModuleRegistry>::Error: IntoResponse,
         I: 'static + IdentityManager + Clone + Send + Sync,
         I::Identity: Serialize,
-        I::Error: IntoResponse,
+        I::Error: IntoResponse + IdentityError,
     {
         let router = router!(
             get    "/modules"                         => Authorization::new(ListModules::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
             post   "/modules"                         => Authorization::new(CreateModule::new(runtime.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
+            get    "/modules/logs"                    => Authorization::new(MergedModuleLogs::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
+            get    "/modules/events"                  => Authorization::new(ModuleStateEvents::new(events.clone()), Policy::Anonymous, runtime.clone()),
+            post   "/modules/start"                   => Authorization::new(BulkModules::start(runtime.clone()), Policy::Anonymous, runtime.clone()),
+            post   "/modules/stop"                    => Authorization::new(BulkModules::stop(runtime.clone()), Policy::Anonymous, runtime.clone()),
             get    "/modules/(?P<name>[^/]+)"         => Authorization::new(GetModule, Policy::Anonymous, runtime.clone()),
             put    "/modules/(?P<name>[^/]+)"         => Authorization::new(UpdateModule::new(runtime.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
             delete "/modules/(?P<name>[^/]+)"         => Authorization::new(DeleteModule::new(runtime.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
             post   "/modules/(?P<name>[^/]+)/start"   => Authorization::new(StartModule::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
             post   "/modules/(?P<name>[^/]+)/stop"    => Authorization::new(StopModule::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
             post   "/modules/(?P<name>[^/]+)/restart" => Authorization::new(RestartModule::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
-            get    "/modules/(?P<name>[^/]+)/logs"    => Authorization::new(ModuleLogs::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
+            post   "/modules/(?P<name>[^/]+)/kill"    => Authorization::new(KillModule::new(runtime.clone()).with_signals(signals), Policy::Anonymous, runtime.clone()),
+            get    "/modules/(?P<name>[^/]+)/logs"    => Authorization::new(ModuleLogs::new(runtime.clone()).with_metrics(Arc::new(metrics.clone())), Policy::Anonymous, runtime.clone()),
+            get    "/modules/(?P<name>[^/]+)/config"  => Authorization::new(GetModuleConfig::new(runtime.clone(), redaction.clone()), Policy::Anonymous, runtime.clone()),
 
             get    "/identities"                      => Authorization::new(ListIdentities::new(identity.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
             post   "/identities"                      => Authorization::new(CreateIdentity::new(identity.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
             put    "/identities/(?P<name>[^/]+)"      => Authorization::new(UpdateIdentity::new(identity.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
             delete "/identities/(?P<name>[^/]+)"      => Authorization::new(DeleteIdentity::new(identity.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
+            post   "/identities/(?P<name>[^/]+)/rotate" => Authorization::new(RotateIdentity::new(identity.clone()), Policy::Module(&*AGENT_NAME), runtime.clone()),
 
-            get    "/systeminfo"                      => Authorization::new(GetSystemInfo::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
+            get    "/systeminfo"                      => Authorization::new(GetSystemInfo::new(runtime.clone()).with_disk_monitor(disks.cloned()), Policy::Anonymous, runtime.clone()),
+            get    "/systeminfo/health"               => Authorization::new(GetHealth::new(health.clone()), Policy::Anonymous, runtime.clone()),
+
+            get    "/diagnostics/runtime-errors"      => Authorization::new(GetRuntimeErrors::new(runtime_errors.clone()), Policy::Anonymous, runtime.clone()),
+
+            get    "/metrics"                         => Authorization::new(GetMetrics::new(metrics.clone()), Policy::Anonymous, runtime.clone()),
         );
 
         router
@@ -75,6 +109,17 @@ impl ManagementService {
     }
 }
 
+/// Listing the modules inspects each of them, and logs are streamed from the
+/// container runtime, so those requests are the expensive ones to serve.
+pub fn route_class(req: &Request<Body>) -> RouteClass {
+    let path = req.uri().path();
+    if req.method() == Method::GET && (path == "/modules" || path.ends_with("/logs")) {
+        RouteClass::Expensive
+    } else {
+        RouteClass::Cheap
+    }
+}
+
 impl Service for ManagementService {
     type ReqBody = <RouterService<RegexRecognizer> as Service>::ReqBody;
     type ResBody = <RouterService<RegexRecognizer> as Service>::ResBody;
@@ -98,3 +143,294 @@ impl NewService for ManagementService {
         future::ok(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use edgelet_core::pid::Pid;
+    use edgelet_core::{ModuleRuntimeState, ModuleStatus};
+    use edgelet_docker::{DockerModuleRuntime, NullModuleRuntime, NULL_RUNTIME_VERSION};
+    use edgelet_http::metrics::MetricsService;
+    use edgelet_test_utils::identity::TestIdentityManager;
+    use edgelet_test_utils::module::*;
+    use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+    use futures::Stream;
+    use http::{Response, StatusCode};
+    use hyper::Error as HyperError;
+    use management::models::{ModuleDetails, ModuleList, RuntimeErrorList, SystemInfo};
+    use serde_json;
+    use server::module::tests::Error;
+    use tokio::runtime::current_thread::Runtime;
+    use url::Url;
+
+    use super::*;
+
+    fn call<S>(service: &mut S, request: Request<Body>) -> (StatusCode, String)
+    where
+        S: Service<ReqBody = Body, ResBody = Body>,
+        S::Error: Debug,
+    {
+        let response = service.call(request).wait().unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    /// A request from a caller whose process can't be identified, as on a
+    /// TCP listener, so that it passes the edge agent's policy.
+    fn agent_request(method: &str, uri: &str, body: &str) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body.to_string().into())
+            .unwrap();
+        request.extensions_mut().insert(Pid::Any);
+        request
+    }
+
+    fn module_statuses(service: &mut ManagementService) -> Vec<(String, String)> {
+        let (status, body) = call(service, get("http://localhost/modules"));
+        assert_eq!(StatusCode::OK, status);
+        let list: ModuleList = serde_json::from_str(&body).unwrap();
+        list.modules()
+            .iter()
+            .map(|m| {
+                (
+                    m.name().to_string(),
+                    m.status().runtime_status().status().to_string(),
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn metrics_count_requests() {
+        let state = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(state));
+        let runtime = TestRuntime::new(Ok(module));
+        let metrics = MetricsRegistry::new();
+        let service = ManagementService::new(
+            &runtime,
+            &TestIdentityManager::new(vec![]),
+            &DaemonHealth::new(Utc::now()),
+            &metrics,
+            &RuntimeErrorLog::default(),
+            &EnvRedaction::default(),
+            KillSignals::default(),
+            &ModuleEvents::default(),
+            None,
+        ).wait()
+        .unwrap();
+        let mut service =
+            MetricsService::new("mgmt".to_string(), Arc::new(metrics.clone()), service);
+
+        assert_eq!(
+            StatusCode::OK,
+            call(&mut service, get("http://localhost/modules")).0
+        );
+        assert_eq!(
+            StatusCode::OK,
+            call(&mut service, get("http://localhost/modules")).0
+        );
+        let start = Request::post("http://localhost/modules/test-module/start")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, call(&mut service, start).0);
+        let missing = get("http://localhost/modules/test-module/nope");
+        assert_eq!(StatusCode::NOT_FOUND, call(&mut service, missing).0);
+
+        let (status, body) = call(&mut service, get("http://localhost/metrics"));
+        assert_eq!(StatusCode::OK, status);
+        assert!(body.contains(
+            "edgelet_http_requests_total{method=\"GET\",server=\"mgmt\",status=\"200\"} 2\n"
+        ));
+        assert!(body.contains(
+            "edgelet_http_requests_total{method=\"POST\",server=\"mgmt\",status=\"204\"} 1\n"
+        ));
+        assert!(body.contains(
+            "edgelet_http_requests_total{method=\"GET\",server=\"mgmt\",status=\"404\"} 1\n"
+        ));
+        assert!(body.contains("edgelet_http_request_duration_seconds_count{server=\"mgmt\"} 4\n"));
+    }
+    #[test]
+    fn modules_are_managed_over_the_null_runtime() {
+        let runtime = NullModuleRuntime::new();
+        let mut service = ManagementService::new(
+            &runtime,
+            &TestIdentityManager::new(vec![]),
+            &DaemonHealth::new(Utc::now()),
+            &MetricsRegistry::new(),
+            &RuntimeErrorLog::default(),
+            &EnvRedaction::default(),
+            KillSignals::default(),
+            &ModuleEvents::default(),
+            None,
+        ).wait()
+        .unwrap();
+
+        let spec = r#"{
+            "name": "sensor",
+            "type": "docker",
+            "config": {
+                "settings": {"image": "microsoft/sensor:1.0"},
+                "env": [{"key": "INTERVAL", "value": "5"}]
+            }
+        }"#;
+        let create = agent_request("POST", "http://localhost/modules", spec);
+        let (status, body) = call(&mut service, create);
+        assert_eq!(StatusCode::CREATED, status);
+        let details: ModuleDetails = serde_json::from_str(&body).unwrap();
+        assert_eq!("sensor", details.name());
+        assert_eq!("stopped", details.status().runtime_status().status());
+
+        let create = agent_request("POST", "http://localhost/modules", spec);
+        assert_eq!(StatusCode::CONFLICT, call(&mut service, create).0);
+
+        assert_eq!(
+            vec![("sensor".to_string(), "stopped".to_string())],
+            module_statuses(&mut service)
+        );
+
+        let start = agent_request("POST", "http://localhost/modules/sensor/start", "");
+        assert_eq!(StatusCode::NO_CONTENT, call(&mut service, start).0);
+        let start = agent_request("POST", "http://localhost/modules/sensor/start", "");
+        assert_eq!(StatusCode::NOT_MODIFIED, call(&mut service, start).0);
+        assert_eq!(
+            vec![("sensor".to_string(), "running".to_string())],
+            module_statuses(&mut service)
+        );
+
+        let restart = agent_request("POST", "http://localhost/modules/sensor/restart", "");
+        assert_eq!(StatusCode::NO_CONTENT, call(&mut service, restart).0);
+        assert_eq!(
+            vec![("sensor".to_string(), "running".to_string())],
+            module_statuses(&mut service)
+        );
+
+        let logs = get("http://localhost/modules/sensor/logs?format=lines");
+        let (status, body) = call(&mut service, logs);
+        assert_eq!(StatusCode::OK, status);
+        let line: serde_json::Value = serde_json::from_str(body.lines().next().unwrap()).unwrap();
+        assert_eq!("stdout", line["stream"]);
+
+        let logs = get("http://localhost/modules/logs?names=sensor,missing");
+        let (status, body) = call(&mut service, logs);
+        assert_eq!(StatusCode::OK, status);
+        let records: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, records.len());
+        assert_eq!("missing", records[0]["module"]);
+        assert!(records[0]["warning"].is_string());
+        assert_eq!("sensor", records[1]["module"]);
+        assert!(records[1]["timestamp"].is_string());
+
+        let (status, body) = call(&mut service, get("http://localhost/systeminfo"));
+        assert_eq!(StatusCode::OK, status);
+        let info: SystemInfo = serde_json::from_str(&body).unwrap();
+        assert_eq!(Some(NULL_RUNTIME_VERSION), info.runtime_version());
+
+        let stop = agent_request("POST", "http://localhost/modules/sensor/stop", "");
+        assert_eq!(StatusCode::NO_CONTENT, call(&mut service, stop).0);
+        assert_eq!(
+            vec![("sensor".to_string(), "stopped".to_string())],
+            module_statuses(&mut service)
+        );
+
+        let delete = agent_request("DELETE", "http://localhost/modules/sensor", "");
+        assert_eq!(StatusCode::NO_CONTENT, call(&mut service, delete).0);
+        let delete = agent_request("DELETE", "http://localhost/modules/sensor", "");
+        assert_eq!(StatusCode::NOT_FOUND, call(&mut service, delete).0);
+        assert!(module_statuses(&mut service).is_empty());
+    }
+
+    #[test]
+    fn failed_docker_operations_are_reported() {
+        // A docker daemon that has no containers.
+        let port = get_unused_tcp_port();
+        let docker = run_tcp_server("127.0.0.1", port, |req: Request<Body>| {
+            let name = req.uri().path().split('/').nth(2).unwrap_or("").to_string();
+            let body = json!({ "message": format!("No such container: {}", name) }).to_string();
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("content-type", "application/json")
+                .body(body.into())
+                .unwrap();
+            Box::new(future::ok(response))
+                as Box<Future<Item = Response<Body>, Error = HyperError> + Send>
+        }).map_err(|err| eprintln!("{}", err));
+
+        let runtime_errors = RuntimeErrorLog::new(2);
+        let runtime =
+            DockerModuleRuntime::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+                .unwrap()
+                .with_runtime_errors(runtime_errors.clone());
+        let mut service = ManagementService::new(
+            &runtime,
+            &TestIdentityManager::new(vec![]),
+            &DaemonHealth::new(Utc::now()),
+            &MetricsRegistry::new(),
+            &runtime_errors,
+            &EnvRedaction::default(),
+            KillSignals::default(),
+            &ModuleEvents::default(),
+            None,
+        ).wait()
+        .unwrap();
+
+        let mut tokio_runtime = Runtime::new().unwrap();
+        tokio_runtime.spawn(docker);
+        for name in &["m1", "m2", "m3"] {
+            let uri = format!("http://localhost/modules/{}/stop", name);
+            let stop = agent_request("POST", &uri, "");
+            let response = tokio_runtime.block_on(service.call(stop)).unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, response.status());
+        }
+
+        let (status, body) = call(&mut service, get("http://localhost/diagnostics/runtime-errors"));
+        assert_eq!(StatusCode::OK, status);
+        let list: RuntimeErrorList = serde_json::from_str(&body).unwrap();
+        let errors: Vec<_> = list
+            .errors()
+            .iter()
+            .map(|error| {
+                (
+                    error.operation(),
+                    error.module_id(),
+                    error.kind(),
+                    error.message(),
+                )
+            }).collect();
+        assert_eq!(
+            vec![
+                ("stop", Some("m2"), "NotFound", "No such container: m2"),
+                ("stop", Some("m3"), "NotFound", "No such container: m3"),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn listing_modules_and_reading_logs_are_expensive() {
+        for &(method, uri, class) in &[
+            ("GET", "/modules", RouteClass::Expensive),
+            ("GET", "/modules/logs", RouteClass::Expensive),
+            ("GET", "/modules/abc/logs", RouteClass::Expensive),
+            ("POST", "/modules", RouteClass::Cheap),
+            ("GET", "/modules/abc", RouteClass::Cheap),
+            ("GET", "/systeminfo", RouteClass::Cheap),
+        ] {
+            let request = agent_request(method, &format!("http://localhost{}", uri), "");
+            assert_eq!(class, route_class(&request), "{} {}", method, uri);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::BTreeMap;
+
+use edgelet_core::{Module, ModuleRuntime};
+use edgelet_http::route::{Handler, Parameters};
+use failure::ResultExt;
+use futures::{future, stream, Future, Stream};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use serde_json;
+
+use error::{Error, ErrorKind};
+use server::AGENT_NAME;
+use IntoResponse;
+
+/// The most modules inspected, started or stopped at the same time.
+const MAX_CONCURRENT_OPERATIONS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Start,
+    Stop,
+}
+
+/// Starts or stops the modules named in the request, or the modules whose
+/// labels match a selector. The edge agent is only part of the operation when
+/// it is named, so that selecting by labels can't stop the agent that asked.
+/// Every module is tried, and the result of each is in the response.
+pub struct BulkModules<M>
+where
+    M: 'static + ModuleRuntime + Clone,
+{
+    runtime: M,
+    operation: Operation,
+}
+
+impl<M> BulkModules<M>
+where
+    M: 'static + ModuleRuntime + Clone,
+{
+    pub fn start(runtime: M) -> Self {
+        BulkModules {
+            runtime,
+            operation: Operation::Start,
+        }
+    }
+
+    pub fn stop(runtime: M) -> Self {
+        BulkModules {
+            runtime,
+            operation: Operation::Stop,
+        }
+    }
+}
+
+impl<M> Handler<Parameters> for BulkModules<M>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: IntoResponse,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let runtime = self.runtime.clone();
+        let operation = self.operation;
+        let response = req.into_body().concat2().and_then(move |b| {
+            match parse_selector(&b) {
+                Ok(selector) => {
+                    let runtime_copy = runtime.clone();
+                    let result = select(runtime, selector)
+                        .and_then(move |names| {
+                            run(runtime_copy, operation, names).map_err(IntoResponse::into_response)
+                        }).map(results_response)
+                        .or_else(|e| future::ok(e.into_response()));
+                    future::Either::A(result)
+                }
+                Err(e) => future::Either::B(future::ok(e.into_response())),
+            }
+        });
+        Box::new(response)
+    }
+}
+
+/// Which modules an operation is for. Exactly one of the two is given.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Selector {
+    names: Option<Vec<String>>,
+    labels: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Selection {
+    Names(Vec<String>),
+    Labels(BTreeMap<String, String>),
+}
+
+fn parse_selector(body: &[u8]) -> Result<Selection, Error> {
+    let selector: Selector = serde_json::from_slice(body).context(ErrorKind::BadBody)?;
+    match (selector.names, selector.labels) {
+        (Some(names), None) => {
+            if names.is_empty() || names.iter().any(|name| name.is_empty()) {
+                return Err(Error::from(ErrorKind::BadBody));
+            }
+            let mut unique: Vec<String> = vec![];
+            for name in names {
+                if !unique.contains(&name) {
+                    unique.push(name);
+                }
+            }
+            Ok(Selection::Names(unique))
+        }
+        // An empty selector would match every module, which is never what
+        // was meant.
+        (None, Some(ref labels)) if labels.is_empty() => Err(Error::from(ErrorKind::BadBody)),
+        (None, Some(labels)) => Ok(Selection::Labels(labels)),
+        _ => Err(Error::from(ErrorKind::BadBody)),
+    }
+}
+
+/// The names of the modules the operation is for, in the order they are
+/// operated on. Modules selected by labels are inspected a few at a time, and
+/// those removed since they were listed are left out.
+fn select<M>(
+    runtime: M,
+    selection: Selection,
+) -> Box<Future<Item = Vec<String>, Error = Response<Body>> + Send>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: IntoResponse,
+{
+    match selection {
+        Selection::Names(names) => Box::new(future::ok(names)),
+        Selection::Labels(labels) => Box::new(
+            runtime
+                .list()
+                .map_err(IntoResponse::into_response)
+                .and_then(move |modules| {
+                    let names = modules
+                        .iter()
+                        .map(|module| module.name().to_string())
+                        .filter(|name| name != &*AGENT_NAME)
+                        .collect::<Vec<_>>();
+                    stream::iter_ok(names.clone())
+                        .map(move |name| {
+                            runtime.inspect(&name).then(move |result| match result {
+                                Ok(inspection) => Ok(Some((name, inspection))),
+                                Err(err) => {
+                                    let response = err.into_response();
+                                    if response.status() == StatusCode::NOT_FOUND {
+                                        Ok(None)
+                                    } else {
+                                        Err(response)
+                                    }
+                                }
+                            })
+                        }).buffer_unordered(MAX_CONCURRENT_OPERATIONS)
+                        .filter_map(|inspected| inspected)
+                        .filter(move |&(_, ref inspection)| {
+                            labels
+                                .iter()
+                                .all(|(key, value)| inspection.labels().get(key) == Some(value))
+                        }).map(|(name, _)| name)
+                        .collect()
+                        // Inspections finish in any order, so keep the order
+                        // the modules were listed in.
+                        .map(move |selected| {
+                            names
+                                .into_iter()
+                                .filter(|name| selected.contains(name))
+                                .collect()
+                        })
+                }),
+        ),
+    }
+}
+
+/// The result of the operation on one module.
+#[derive(Debug, Serialize)]
+struct ModuleResult {
+    name: String,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl ModuleResult {
+    fn succeeded(&self) -> bool {
+        self.code.is_none()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BulkResults {
+    modules: Vec<ModuleResult>,
+}
+
+fn run<M>(
+    runtime: M,
+    operation: Operation,
+    names: Vec<String>,
+) -> impl Future<Item = Vec<ModuleResult>, Error = M::Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: IntoResponse,
+{
+    let operations = names.into_iter().map(move |name| {
+        let done: Box<Future<Item = (), Error = M::Error> + Send> = match operation {
+            Operation::Start => Box::new(runtime.start(&name)),
+            Operation::Stop => Box::new(runtime.stop(&name, None)),
+        };
+        done.then(move |result| {
+            let result = match result {
+                Ok(()) => ModuleResult {
+                    name,
+                    status: "succeeded",
+                    code: None,
+                    message: None,
+                },
+                Err(err) => {
+                    let message = err.to_string();
+                    let code = err.into_response().status().as_u16();
+                    ModuleResult {
+                        name,
+                        status: "failed",
+                        code: Some(code),
+                        message: Some(message),
+                    }
+                }
+            };
+            Ok::<_, M::Error>(result)
+        })
+    });
+    stream::iter_ok(operations)
+        .buffered(MAX_CONCURRENT_OPERATIONS)
+        .collect()
+}
+
+fn results_response(results: Vec<ModuleResult>) -> Response<Body> {
+    let failed = results.iter().filter(|r| !r.succeeded()).count();
+    let status = if failed == 0 {
+        StatusCode::OK
+    } else if failed < results.len() {
+        StatusCode::MULTI_STATUS
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    serde_json::to_string(&BulkResults { modules: results })
+        .context(ErrorKind::Serde)
+        .map(|b| {
+            Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                .body(b.into())
+                .unwrap_or_else(|e| e.into_response())
+        }).unwrap_or_else(|e| Error::from(e).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use edgelet_core::ModuleInspection;
+    use edgelet_test_utils::module::*;
+    use management::models::ErrorResponse;
+    use serde_json::Value;
+    use server::module::tests::Error;
+
+    use super::*;
+
+    fn module(name: &str) -> TestModule<Error> {
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        TestModule::new(name.to_string(), config, Ok(Default::default()))
+    }
+
+    fn runtime(names: &[&str]) -> TestRuntime<Error> {
+        TestRuntime::new(Ok(module("edgeAgent")))
+            .with_modules(names.iter().map(|name| module(name)).collect())
+    }
+
+    fn handle(handler: &BulkModules<TestRuntime<Error>>, body: Value) -> (StatusCode, Value) {
+        let request = Request::post("http://localhost/modules/stop")
+            .body(body.to_string().into())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn named_modules_are_stopped() {
+        let runtime = runtime(&["edgeAgent", "edgeHub", "sensor"]);
+        let handler = BulkModules::stop(runtime.clone());
+
+        let (status, body) = handle(&handler, json!({"names": ["sensor", "edgeAgent", "sensor"]}));
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            json!({"modules": [
+                {"name": "sensor", "status": "succeeded"},
+                {"name": "edgeAgent", "status": "succeeded"},
+            ]}),
+            body
+        );
+        assert_eq!(vec!["stop sensor", "stop edgeAgent"], runtime.calls());
+    }
+
+    #[test]
+    fn partial_failure_is_multi_status() {
+        let runtime =
+            runtime(&["edgeHub", "sensor", "opcua"]).with_failure("sensor", Error::General);
+        let handler = BulkModules::start(runtime.clone());
+
+        let (status, body) = handle(&handler, json!({"names": ["edgeHub", "sensor", "opcua"]}));
+
+        assert_eq!(StatusCode::MULTI_STATUS, status);
+        assert_eq!(
+            json!({"modules": [
+                {"name": "edgeHub", "status": "succeeded"},
+                {"name": "sensor", "status": "failed", "code": 500, "message": "General error"},
+                {"name": "opcua", "status": "succeeded"},
+            ]}),
+            body
+        );
+        // A failure doesn't keep the other modules from being started.
+        assert_eq!(
+            vec!["start edgeHub", "start sensor", "start opcua"],
+            runtime.calls()
+        );
+    }
+
+    #[test]
+    fn total_failure_is_internal_server_error() {
+        let runtime = runtime(&["sensor"]).with_failure("sensor", Error::General);
+        let handler = BulkModules::stop(runtime);
+
+        let (status, body) = handle(&handler, json!({"names": ["sensor"]}));
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(json!("failed"), body["modules"][0]["status"]);
+    }
+
+    #[test]
+    fn labels_select_modules_but_not_the_agent() {
+        let mut labels = BTreeMap::new();
+        labels.insert("app".to_string(), "telemetry".to_string());
+        labels.insert("tier".to_string(), "edge".to_string());
+        let runtime = runtime(&["edgeAgent", "edgeHub", "sensor"])
+            .with_inspection(ModuleInspection::default().with_labels(labels));
+        let handler = BulkModules::stop(runtime.clone());
+
+        let (status, body) = handle(&handler, json!({"labels": {"app": "telemetry"}}));
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(2, body["modules"].as_array().unwrap().len());
+        assert_eq!(vec!["stop edgeHub", "stop sensor"], runtime.calls());
+
+        let (status, body) = handle(&handler, json!({"labels": {"app": "camera"}}));
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!({"modules": []}), body);
+        assert_eq!(2, runtime.calls().len());
+    }
+
+    #[test]
+    fn modules_removed_while_selecting_by_labels_are_skipped() {
+        let mut labels = BTreeMap::new();
+        labels.insert("app".to_string(), "telemetry".to_string());
+        let runtime = runtime(&["edgeHub", "sensor", "opcua"])
+            .with_inspection(ModuleInspection::default().with_labels(labels))
+            .with_inspect_failure("sensor", Error::NotFound);
+        let handler = BulkModules::stop(runtime.clone());
+
+        let (status, body) = handle(&handler, json!({"labels": {"app": "telemetry"}}));
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(2, body["modules"].as_array().unwrap().len());
+        assert_eq!(vec!["stop edgeHub", "stop opcua"], runtime.calls());
+    }
+
+    #[test]
+    fn other_inspection_failures_fail_selecting_by_labels() {
+        let runtime =
+            runtime(&["edgeHub", "sensor"]).with_inspect_failure("sensor", Error::General);
+        let handler = BulkModules::stop(runtime.clone());
+
+        let (status, body) = handle(&handler, json!({"labels": {"app": "telemetry"}}));
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(json!({"message": "General error"}), body);
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[test]
+    fn selector_must_be_one_of_names_or_labels() {
+        let runtime = runtime(&["sensor"]);
+        let handler = BulkModules::stop(runtime.clone());
+
+        for body in &[
+            json!({}),
+            json!({"names": []}),
+            json!({"names": [""]}),
+            json!({"labels": {}}),
+            json!({"names": ["sensor"], "labels": {"app": "telemetry"}}),
+            json!({"modules": ["sensor"]}),
+        ] {
+            let (status, body) = handle(&handler, body.clone());
+
+            assert_eq!(StatusCode::BAD_REQUEST, status);
+            let error: ErrorResponse = serde_json::from_value(body).unwrap();
+            assert!(error.message().starts_with("Bad body"));
+        }
+        assert!(runtime.calls().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use edgelet_core::{ModuleInspection, ModuleRuntime};
+use edgelet_http::route::{Handler, Parameters};
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use management::models::{EnvVar, ResolvedConfig};
+use serde_json::{self, Value};
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// What the values of redacted environment variables are replaced with.
+const REDACTED: &str = "********";
+
+/// Parts of environment variable names that suggest the variable holds a
+/// secret.
+const DEFAULT_REDACTED_NAMES: &[&str] = &["KEY", "SECRET", "PASSWORD", "CONNECTIONSTRING", "SAS"];
+
+/// Decides which environment variables have their values hidden when the
+/// configuration of a module is reported. A variable is redacted when its name
+/// contains any of the patterns, ignoring case.
+#[derive(Clone, Debug)]
+pub struct EnvRedaction {
+    patterns: Vec<String>,
+}
+
+impl Default for EnvRedaction {
+    fn default() -> Self {
+        EnvRedaction {
+            patterns: DEFAULT_REDACTED_NAMES
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl EnvRedaction {
+    pub fn new() -> Self {
+        EnvRedaction::default()
+    }
+
+    /// Redacts variables whose names contain any of `patterns` too.
+    pub fn with_patterns(mut self, patterns: &[String]) -> Self {
+        self.patterns
+            .extend(patterns.iter().map(|pattern| pattern.to_uppercase()));
+        self
+    }
+
+    pub fn is_redacted(&self, name: &str) -> bool {
+        let name = name.to_uppercase();
+        self.patterns
+            .iter()
+            .any(|pattern| name.contains(pattern.as_str()))
+    }
+
+    fn redact(&self, name: &str, value: &str) -> String {
+        if self.is_redacted(name) {
+            REDACTED.to_string()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Redacts the `NAME=value` entries of the `Env` list in create options.
+    fn redact_create_options(&self, mut create_options: Value) -> Value {
+        if let Some(env) = create_options
+            .get_mut("Env")
+            .and_then(|env| env.as_array_mut())
+        {
+            for var in env.iter_mut() {
+                let redacted = var.as_str().map(|var| {
+                    let mut tokens = var.splitn(2, '=');
+                    let name = tokens.next().unwrap_or("");
+                    match tokens.next() {
+                        Some(value) => format!("{}={}", name, self.redact(name, value)),
+                        None => name.to_string(),
+                    }
+                });
+                if let Some(redacted) = redacted {
+                    *var = Value::String(redacted);
+                }
+            }
+        }
+        create_options
+    }
+}
+
+pub struct GetModuleConfig<M>
+where
+    M: 'static + ModuleRuntime,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    runtime: M,
+    redaction: EnvRedaction,
+}
+
+impl<M> GetModuleConfig<M>
+where
+    M: 'static + ModuleRuntime,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    pub fn new(runtime: M, redaction: EnvRedaction) -> Self {
+        GetModuleConfig { runtime, redaction }
+    }
+}
+
+impl<M> Handler<Parameters> for GetModuleConfig<M>
+where
+    M: 'static + ModuleRuntime + Send,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let response = match params.name("name") {
+            Some(name) => {
+                debug!("Inspect module {}", name);
+                let redaction = self.redaction.clone();
+                let result = self
+                    .runtime
+                    .inspect(name)
+                    .map_err(|e| e.into_response())
+                    .and_then(move |inspection| {
+                        let config = resolved_config(&inspection, &redaction);
+                        serde_json::to_string(&config)
+                            .context(ErrorKind::Serde)
+                            .map_err(Error::from)
+                            .and_then(|b| {
+                                Ok(Response::builder()
+                                    .status(StatusCode::OK)
+                                    .header(CONTENT_TYPE, "application/json")
+                                    .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                                    .body(b.into())?)
+                            }).map_err(|e: Error| e.into_response())
+                    }).or_else(Ok);
+                future::Either::A(result)
+            }
+            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+        };
+
+        Box::new(response)
+    }
+}
+
+fn resolved_config(inspection: &ModuleInspection, redaction: &EnvRedaction) -> ResolvedConfig {
+    let env = inspection
+        .env()
+        .iter()
+        .map(|(name, value)| EnvVar::new(name.clone(), redaction.redact(name, value)))
+        .collect();
+    let labels = inspection
+        .labels()
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let create_options = redaction.redact_create_options(inspection.create_options().clone());
+
+    let config = ResolvedConfig::new(
+        inspection.name().to_string(),
+        inspection.image().to_string(),
+        env,
+        labels,
+        create_options,
+    );
+    match inspection.image_id() {
+        Some(image_id) => config.with_image_id(image_id.to_string()),
+        None => config,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use edgelet_core::{ModuleRuntimeState, ModuleStatus};
+    use edgelet_test_utils::module::*;
+    use futures::Stream;
+    use management::models::ErrorResponse;
+    use server::module::tests::Error;
+
+    use super::*;
+
+    fn inspection() -> ModuleInspection {
+        let env = vec![
+            "EdgeHubConnectionString=HostName=hub;SharedAccessKey=c2VjcmV0",
+            "IOTEDGE_IOTHUBHOSTNAME=hub.azure-devices.net",
+            "IOTEDGE_SAS_TOKEN=SharedAccessSignature sr=hub",
+            "API_KEY=1234",
+            "DbPassword=hunter2",
+            "CLIENT_SECRET=s3cr3t",
+            "RuntimeLogLevel=info",
+            "FEATURE_TOKEN=abcd",
+        ];
+        let create_options = json!({
+            "Image": "microsoft/azureiotedge-hub:1.0",
+            "Env": env,
+            "HostConfig": { "Dns": ["1.1.1.1"] },
+        });
+        let env = env
+            .iter()
+            .map(|var| {
+                let mut tokens = var.splitn(2, '=');
+                (
+                    tokens.next().unwrap().to_string(),
+                    tokens.next().unwrap().to_string(),
+                )
+            }).collect();
+        let mut labels = BTreeMap::new();
+        labels.insert(
+            "net.azure-devices.edge.owner".to_string(),
+            "Microsoft.Azure.Devices.Edge.Agent".to_string(),
+        );
+
+        ModuleInspection::new(
+            "edgeHub".to_string(),
+            "microsoft/azureiotedge-hub:1.0".to_string(),
+        ).with_image_id(Some("sha256:8b1f".to_string()))
+        .with_env(env)
+        .with_labels(labels)
+        .with_create_options(create_options)
+    }
+
+    fn get_config(
+        module: Result<TestModule<Error>, Error>,
+        redaction: EnvRedaction,
+    ) -> Response<Body> {
+        let runtime = TestRuntime::new(module).with_inspection(inspection());
+        let handler = GetModuleConfig::new(runtime, redaction);
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "edgeHub".to_string())]);
+        let request = Request::get("http://localhost/modules/edgeHub/config")
+            .body(Body::default())
+            .unwrap();
+        handler.handle(request, parameters).wait().unwrap()
+    }
+
+    fn module() -> Result<TestModule<Error>, Error> {
+        let state = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        let config = TestConfig::new("microsoft/azureiotedge-hub:1.0".to_string());
+        Ok(TestModule::new("edgeHub".to_string(), config, Ok(state)))
+    }
+
+    fn resolved(response: Response<Body>) -> ResolvedConfig {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| serde_json::from_slice::<ResolvedConfig>(&b).unwrap())
+            .wait()
+            .unwrap()
+    }
+
+    fn env(config: &ResolvedConfig) -> BTreeMap<&str, &str> {
+        config
+            .env()
+            .iter()
+            .map(|var| (var.key(), var.value()))
+            .collect()
+    }
+
+    fn create_options_env(config: &ResolvedConfig) -> Vec<&str> {
+        config.create_options()["Env"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| var.as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn success() {
+        let response = get_config(module(), EnvRedaction::default());
+
+        assert_eq!(StatusCode::OK, response.status());
+        let config = resolved(response);
+        assert_eq!("edgeHub", config.name());
+        assert_eq!("microsoft/azureiotedge-hub:1.0", config.image());
+        assert_eq!(Some("sha256:8b1f"), config.image_id());
+        assert_eq!(
+            "Microsoft.Azure.Devices.Edge.Agent",
+            config.labels()["net.azure-devices.edge.owner"]
+        );
+        assert_eq!(
+            json!(["1.1.1.1"]),
+            config.create_options()["HostConfig"]["Dns"]
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let config = resolved(get_config(module(), EnvRedaction::default()));
+
+        let env = env(&config);
+        assert_eq!("********", env["EdgeHubConnectionString"]);
+        assert_eq!("********", env["IOTEDGE_SAS_TOKEN"]);
+        assert_eq!("********", env["API_KEY"]);
+        assert_eq!("********", env["DbPassword"]);
+        assert_eq!("********", env["CLIENT_SECRET"]);
+        assert_eq!("hub.azure-devices.net", env["IOTEDGE_IOTHUBHOSTNAME"]);
+        assert_eq!("info", env["RuntimeLogLevel"]);
+        assert_eq!("abcd", env["FEATURE_TOKEN"]);
+
+        assert_eq!(
+            vec![
+                "EdgeHubConnectionString=********",
+                "IOTEDGE_IOTHUBHOSTNAME=hub.azure-devices.net",
+                "IOTEDGE_SAS_TOKEN=********",
+                "API_KEY=********",
+                "DbPassword=********",
+                "CLIENT_SECRET=********",
+                "RuntimeLogLevel=info",
+                "FEATURE_TOKEN=abcd",
+            ],
+            create_options_env(&config)
+        );
+    }
+
+    #[test]
+    fn redaction_can_be_extended() {
+        let redaction = EnvRedaction::default().with_patterns(&["token".to_string()]);
+        let config = resolved(get_config(module(), redaction));
+
+        let env = env(&config);
+        assert_eq!("********", env["FEATURE_TOKEN"]);
+        assert_eq!("********", env["API_KEY"]);
+        assert_eq!("info", env["RuntimeLogLevel"]);
+        assert!(create_options_env(&config).contains(&"FEATURE_TOKEN=********"));
+    }
+
+    #[test]
+    fn bad_params() {
+        let runtime = TestRuntime::new(module()).with_inspection(inspection());
+        let handler = GetModuleConfig::new(runtime, EnvRedaction::default());
+        let request = Request::get("http://localhost/modules/edgeHub/config")
+            .body(Body::default())
+            .unwrap();
+
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[test]
+    fn runtime_error() {
+        let response = get_config(Err(Error::General), EnvRedaction::default());
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!("General error", error.message());
+            }).wait()
+            .unwrap();
+    }
+}
@@ -63,7 +63,7 @@ where
                     Ok((core_spec, spec)) => {
                         let created = runtime
                             .registry()
-                            .pull(core_spec.config())
+                            .pull_module(&core_spec)
                             .and_then(move |_| {
                                 runtime
                                     .create(core_spec)
@@ -0,0 +1,256 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use edgelet_core::{Clock, ModuleEvents, ModuleStateChange, Subscription, SystemClock};
+use edgelet_http::route::{Handler, Parameters};
+use failure::{self, ResultExt};
+use futures::{future, Async, Future, Poll, Stream};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use serde_json;
+use tokio::timer::Error as TimerError;
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// How long the stream may go without an event before a comment is sent, so
+/// that proxies don't take the connection for idle and cut it.
+const KEEP_ALIVE_SECS: u64 = 15;
+
+/// The most events held for a client that doesn't read them. A client that
+/// falls further behind is dropped.
+const CLIENT_BUFFER: usize = 64;
+
+/// Streams the changes of the status of modules as server-sent events, one
+/// `data:` line of JSON per change. The stream ends when the client goes away,
+/// which hyper tells by dropping the response body and with it the
+/// subscription, or when the client falls too far behind.
+pub struct ModuleStateEvents {
+    events: ModuleEvents,
+    clock: Arc<Clock>,
+    buffer: usize,
+}
+
+impl ModuleStateEvents {
+    pub fn new(events: ModuleEvents) -> Self {
+        ModuleStateEvents {
+            events,
+            clock: Arc::new(SystemClock),
+            buffer: CLIENT_BUFFER,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+}
+
+impl Handler<Parameters> for ModuleStateEvents {
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let events = EventStream::new(
+            self.events.subscribe(self.buffer),
+            self.clock.clone(),
+            Duration::from_secs(KEEP_ALIVE_SECS),
+        ).map_err(failure::Error::compat);
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(Body::wrap_stream(events))
+            .unwrap_or_else(|e| e.into_response());
+        Box::new(future::ok(response))
+    }
+}
+
+fn event(change: &ModuleStateChange) -> Result<String, Error> {
+    let json = serde_json::to_string(change).context(ErrorKind::Serde)?;
+    Ok(format!("data: {}\n\n", json))
+}
+
+struct EventStream {
+    changes: Subscription,
+    clock: Arc<Clock>,
+    keep_alive: Duration,
+    delay: Box<Future<Item = (), Error = TimerError> + Send>,
+}
+
+impl EventStream {
+    fn new(changes: Subscription, clock: Arc<Clock>, keep_alive: Duration) -> Self {
+        let delay = clock.delay(clock.instant() + keep_alive);
+        EventStream {
+            changes,
+            clock,
+            keep_alive,
+            delay,
+        }
+    }
+
+    fn reset_keep_alive(&mut self) {
+        self.delay = self.clock.delay(self.clock.instant() + self.keep_alive);
+    }
+}
+
+impl Stream for EventStream {
+    type Item = String;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.changes.poll() {
+            Ok(Async::Ready(Some(change))) => {
+                self.reset_keep_alive();
+                return Ok(Async::Ready(Some(event(&change)?)));
+            }
+            // The subscription only ends when the client fell behind.
+            Ok(Async::Ready(None)) | Err(()) => return Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => (),
+        }
+
+        try_ready!(self.delay.poll());
+        self.reset_keep_alive();
+        Ok(Async::Ready(Some(": keep-alive\n\n".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use edgelet_core::ModuleStatus;
+    use edgelet_test_utils::TestClock;
+    use futures::executor::{self, Notify, Spawn};
+
+    use super::*;
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    fn change(name: &str, old: ModuleStatus, new: ModuleStatus) -> ModuleStateChange {
+        let at = Utc.ymd(2018, 10, 15).and_hms(14, 0, 0);
+        ModuleStateChange::new(name.to_string(), Some(old), new, at)
+    }
+
+    fn open(handler: &ModuleStateEvents) -> (StatusCode, String, Spawn<Body>) {
+        let request = Request::get("http://localhost/modules/events")
+            .body(Body::default())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        (
+            response.status(),
+            content_type,
+            executor::spawn(response.into_body()),
+        )
+    }
+
+    // Reads what the stream has without waiting.
+    fn read(body: &mut Spawn<Body>) -> Option<String> {
+        match body.poll_stream_notify(&Arc::new(NoopNotify), 0) {
+            Ok(Async::Ready(Some(chunk))) => Some(String::from_utf8(chunk.to_vec()).unwrap()),
+            Ok(Async::Ready(None)) => Some("<end>".to_string()),
+            Ok(Async::NotReady) => None,
+            Err(err) => panic!("stream failed: {}", err),
+        }
+    }
+
+    #[test]
+    fn changes_are_sent_as_events() {
+        let events = ModuleEvents::new();
+        let handler = ModuleStateEvents::new(events.clone())
+            .with_clock(Arc::new(TestClock::default()));
+
+        let (status, content_type, mut body) = open(&handler);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("text/event-stream", content_type);
+        assert_eq!(None, read(&mut body));
+
+        let failed = change("sensor", ModuleStatus::Running, ModuleStatus::Failed);
+        events.publish(&failed.with_exit_code(Some(1)));
+        events.publish(&change("opcua", ModuleStatus::Stopped, ModuleStatus::Running));
+
+        let expected = concat!(
+            "data: {\"name\":\"sensor\",\"oldStatus\":\"running\",\"newStatus\":\"failed\",",
+            "\"exitCode\":1,\"timestamp\":\"2018-10-15T14:00:00Z\"}\n\n"
+        );
+        assert_eq!(Some(expected.to_string()), read(&mut body));
+        let expected = concat!(
+            "data: {\"name\":\"opcua\",\"oldStatus\":\"stopped\",\"newStatus\":\"running\",",
+            "\"timestamp\":\"2018-10-15T14:00:00Z\"}\n\n"
+        );
+        assert_eq!(Some(expected.to_string()), read(&mut body));
+        assert_eq!(None, read(&mut body));
+    }
+
+    #[test]
+    fn quiet_streams_are_kept_alive() {
+        let clock = TestClock::default();
+        let events = ModuleEvents::new();
+        let handler = ModuleStateEvents::new(events.clone()).with_clock(Arc::new(clock.clone()));
+        let (_, _, mut body) = open(&handler);
+        assert_eq!(None, read(&mut body));
+
+        clock.advance(Duration::from_secs(KEEP_ALIVE_SECS - 1));
+        assert_eq!(None, read(&mut body));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(": keep-alive\n\n".to_string()), read(&mut body));
+        assert_eq!(None, read(&mut body));
+
+        // An event puts the next comment off.
+        clock.advance(Duration::from_secs(KEEP_ALIVE_SECS - 1));
+        events.publish(&change("sensor", ModuleStatus::Stopped, ModuleStatus::Running));
+        assert!(read(&mut body).unwrap().starts_with("data: "));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(None, read(&mut body));
+    }
+
+    #[test]
+    fn clients_that_go_away_unsubscribe() {
+        let events = ModuleEvents::new();
+        let handler = ModuleStateEvents::new(events.clone());
+        let (_, _, body) = open(&handler);
+        assert_eq!(1, events.subscribers());
+
+        drop(body);
+
+        assert_eq!(0, events.subscribers());
+    }
+
+    #[test]
+    fn clients_that_fall_behind_are_dropped() {
+        let events = ModuleEvents::new();
+        let handler = ModuleStateEvents::new(events.clone())
+            .with_clock(Arc::new(TestClock::default()))
+            .with_buffer(1);
+        let (_, _, mut body) = open(&handler);
+
+        for _ in 0..4 {
+            events.publish(&change("sensor", ModuleStatus::Stopped, ModuleStatus::Running));
+        }
+        assert_eq!(0, events.subscribers());
+
+        // What was buffered still goes out before the stream ends.
+        let mut read_events = 0;
+        while let Some(data) = read(&mut body) {
+            if data == "<end>" {
+                break;
+            }
+            assert!(data.starts_with("data: "));
+            read_events += 1;
+        }
+        assert!(read_events > 0 && read_events < 4);
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use edgelet_core::{KillSignals, ModuleRuntime};
+use edgelet_http::route::{Handler, Parameters, Query};
+use failure::ResultExt;
+use futures::{future, Future};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// Sends a signal to a module, for modules that reload their config or dump
+/// their state on one. Signals outside of what the runtime was configured to
+/// send are refused before the runtime is asked.
+pub struct KillModule<M>
+where
+    M: 'static + ModuleRuntime,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    runtime: M,
+    signals: KillSignals,
+}
+
+impl<M> KillModule<M>
+where
+    M: 'static + ModuleRuntime,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    pub fn new(runtime: M) -> Self {
+        KillModule {
+            runtime,
+            signals: KillSignals::default(),
+        }
+    }
+
+    pub fn with_signals(mut self, signals: KillSignals) -> Self {
+        self.signals = signals;
+        self
+    }
+}
+
+impl<M> Handler<Parameters> for KillModule<M>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    <M as ModuleRuntime>::Error: IntoResponse,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let signals = self.signals;
+        let response = match params
+            .name("name")
+            .ok_or_else(|| Error::from(ErrorKind::BadParam))
+            .and_then(|name| {
+                let query = Query::from_request(&req);
+                let signal = query
+                    .get("signal")
+                    .context(ErrorKind::BadParam)?
+                    .ok_or_else(|| Error::from(ErrorKind::BadParam))?;
+                let signal = signals.check(signal).context(ErrorKind::BadParam)?;
+                Ok((name, signal))
+            }) {
+            Ok((name, signal)) => {
+                let result = self
+                    .runtime
+                    .kill(name, &signal)
+                    .map(|_| {
+                        Response::builder()
+                            .status(StatusCode::NO_CONTENT)
+                            .body(Body::default())
+                            .unwrap_or_else(|e| e.into_response())
+                    }).or_else(|e| future::ok(e.into_response()));
+                future::Either::A(result)
+            }
+            Err(e) => future::Either::B(future::ok(e.into_response())),
+        };
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use edgelet_core::{ModuleRuntimeState, ModuleStatus};
+    use edgelet_test_utils::module::*;
+    use futures::Stream;
+    use management::models::ErrorResponse;
+    use serde_json;
+    use server::module::tests::Error;
+
+    use super::*;
+
+    fn runtime() -> TestRuntime<Error> {
+        let state = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(state));
+        TestRuntime::new(Ok(module))
+    }
+
+    fn kill(handler: &KillModule<TestRuntime<Error>>, uri: &str) -> Response<Body> {
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "test".to_string())]);
+        let request = Request::post(uri).body(Body::default()).unwrap();
+        handler.handle(request, parameters).wait().unwrap()
+    }
+
+    fn error_message(response: Response<Body>) -> String {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                serde_json::from_slice::<ErrorResponse>(&b)
+                    .unwrap()
+                    .message()
+                    .to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn allowed_signals_are_sent() {
+        let handler = KillModule::new(runtime());
+        for signal in &["SIGHUP", "USR1", "sigusr2", "SIGTERM"] {
+            let uri = format!("http://localhost/modules/test/kill?signal={}", signal);
+            let response = kill(&handler, &uri);
+
+            assert_eq!(StatusCode::NO_CONTENT, response.status());
+        }
+    }
+
+    #[test]
+    fn disallowed_signals_are_refused() {
+        let handler = KillModule::new(runtime());
+        for signal in &["SIGKILL", "STOP", "9"] {
+            let uri = format!("http://localhost/modules/test/kill?signal={}", signal);
+            let response = kill(&handler, &uri);
+
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+            assert!(
+                error_message(response).contains("may not be sent to modules"),
+                "{}",
+                signal
+            );
+        }
+    }
+
+    #[test]
+    fn any_signal_is_sent_when_opened_up() {
+        let handler = KillModule::new(runtime()).with_signals(KillSignals::Any);
+        let response = kill(&handler, "http://localhost/modules/test/kill?signal=SIGKILL");
+
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+    }
+
+    #[test]
+    fn missing_or_malformed_signal_is_bad_request() {
+        let handler = KillModule::new(runtime()).with_signals(KillSignals::Any);
+        for uri in &[
+            "http://localhost/modules/test/kill",
+            "http://localhost/modules/test/kill?signal=",
+            "http://localhost/modules/test/kill?signal=HUP%3Brm",
+        ] {
+            let response = kill(&handler, uri);
+
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", uri);
+        }
+    }
+}
@@ -1,9 +1,13 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::{Module, ModuleRuntime};
-use edgelet_http::route::{Handler, Parameters};
+use std::i32;
+
+use base64;
+use edgelet_core::{Module, ModuleRuntime, ModuleRuntimeState};
+use edgelet_http::route::{Handler, Parameters, Query};
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
 use failure::ResultExt;
-use futures::{Future, Stream};
+use futures::{future, Future, Stream};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
@@ -40,21 +44,28 @@ where
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         _params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         debug!("List modules");
+        let page = match Page::from_request(&req) {
+            Ok(page) => page,
+            Err(err) => return Box::new(future::ok(err.into_response())),
+        };
         let response = self
             .runtime
             .list_with_details()
             .collect()
-            .then(|result| {
-                let details: Result<_, Error> = result
-                    .context(ErrorKind::ModuleRuntime)?
-                    .into_iter()
-                    .map(|(module, state)| core_to_details(&module, &state))
+            .then(move |result| {
+                let (modules, next) = page.select(result.context(ErrorKind::ModuleRuntime)?);
+                let details: Result<_, Error> = modules
+                    .iter()
+                    .map(|(module, state)| core_to_details(module, state))
                     .collect();
-                let body = ModuleList::new(details?);
+                let mut body = ModuleList::new(details?);
+                if let Some(last) = next {
+                    body.set_continuation_token(base64::encode_config(&last, base64::URL_SAFE));
+                }
                 let b = serde_json::to_string(&body).context(ErrorKind::Serde)?;
                 Ok(Response::builder()
                     .status(StatusCode::OK)
@@ -66,6 +77,67 @@ where
     }
 }
 
+/// The part of the modules, in order of their names, a request asks for.
+///
+/// The continuation token holds the name of the last module of the previous
+/// page, so that the next page starts right after it no matter which modules
+/// were created or removed in between.
+struct Page {
+    limit: Option<usize>,
+    after: Option<String>,
+}
+
+impl Page {
+    fn from_request(req: &Request<Body>) -> Result<Self, Error> {
+        let query = Query::from_request(req);
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+        let limit = query
+            .get_i32_in_range("limit", 1, i32::MAX)
+            .context(ErrorKind::BadParam)?
+            .map(|limit| limit as usize);
+        let after = match query.get("continuationToken").context(ErrorKind::BadParam)? {
+            Some(token) => Some(decode_token(token).context(ErrorKind::BadParam)?),
+            None => None,
+        };
+        Ok(Page { limit, after })
+    }
+
+    /// The modules of the page, and the name of its last module if more
+    /// modules follow.
+    fn select<T: Module>(&self, modules: Details<T>) -> (Details<T>, Option<String>) {
+        let mut modules: Vec<_> = modules
+            .into_iter()
+            .filter(|(module, _)| {
+                self.after
+                    .as_ref()
+                    .map_or(true, |after| module.name() > after.as_str())
+            }).collect();
+        modules.sort_by(|(a, _), (b, _)| a.name().cmp(b.name()));
+
+        let next = match self.limit {
+            Some(limit) if modules.len() > limit => {
+                modules.truncate(limit);
+                modules.last().map(|(module, _)| module.name().to_string())
+            }
+            _ => None,
+        };
+        (modules, next)
+    }
+}
+
+type Details<T> = Vec<(T, ModuleRuntimeState)>;
+
+fn decode_token(token: &str) -> Result<String, HttpError> {
+    base64::decode_config(token, base64::URL_SAFE)
+        .ok()
+        .and_then(|name| String::from_utf8(name).ok())
+        .ok_or_else(|| {
+            HttpError::from(HttpErrorKind::InvalidQueryParameter(
+                "continuationToken".to_string(),
+            ))
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::prelude::*;
@@ -85,6 +157,7 @@ mod tests {
             .with_status(ModuleStatus::Running)
             .with_exit_code(Some(0))
             .with_status_description(Some("description".to_string()))
+            .with_created_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(14, 19, 0, 1)))
             .with_started_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(14, 20, 0, 1)))
             .with_finished_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(15, 20, 0, 1)))
             .with_image_id(Some("image-id".to_string()));
@@ -121,6 +194,10 @@ mod tests {
                     "2018-04-13T15:20:00.001+00:00",
                     module.status().exit_status().unwrap().exit_time()
                 );
+                assert_eq!(
+                    "2018-04-13T14:19:00.001+00:00",
+                    module.status().create_time().unwrap()
+                );
                 assert_eq!(
                     "2018-04-13T14:20:00.001+00:00",
                     module.status().start_time().unwrap()
@@ -190,4 +267,92 @@ mod tests {
             }).wait()
             .unwrap();
     }
+
+    fn runtime(names: &[&str]) -> TestRuntime<Error> {
+        let modules: Vec<_> = names
+            .iter()
+            .map(|name| {
+                let config = TestConfig::new("microsoft/test-image".to_string());
+                TestModule::new(name.to_string(), config, Ok(ModuleRuntimeState::default()))
+            }).collect();
+        TestRuntime::new(Ok(modules[0].clone())).with_modules(modules)
+    }
+
+    fn list(runtime: TestRuntime<Error>, query: &str) -> (Vec<String>, Option<String>) {
+        let request = Request::get(format!("http://localhost/modules{}", query))
+            .body(Body::default())
+            .unwrap();
+        let response = ListModules::new(runtime)
+            .handle(request, Parameters::new())
+            .wait()
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let b = response.into_body().concat2().wait().unwrap();
+        let list: ModuleList = serde_json::from_slice(&b).unwrap();
+        let names = list
+            .modules()
+            .iter()
+            .map(|module| module.name().to_string())
+            .collect();
+        (names, list.continuation_token().map(ToString::to_string))
+    }
+
+    #[test]
+    fn modules_are_listed_in_order_of_their_names() {
+        let (names, token) = list(runtime(&["edgeHub", "b", "a", "edgeAgent"]), "");
+        assert_eq!(vec!["a", "b", "edgeAgent", "edgeHub"], names);
+        assert_eq!(None, token);
+    }
+
+    #[test]
+    fn pages_continue_after_the_last_module_of_the_previous_page() {
+        let runtime = runtime(&["d", "b", "e", "a", "c"]);
+        let next = |token: Option<String>| format!("?limit=2&continuationToken={}", token.unwrap());
+
+        let (names, token) = list(runtime.clone(), "?limit=2");
+        assert_eq!(vec!["a", "b"], names);
+
+        let (names, token) = list(runtime.clone(), &next(token));
+        assert_eq!(vec!["c", "d"], names);
+
+        let (names, token) = list(runtime.clone(), &next(token));
+        assert_eq!(vec!["e"], names);
+        assert_eq!(None, token);
+
+        let (names, token) = list(runtime, "?limit=5");
+        assert_eq!(5, names.len());
+        assert_eq!(None, token);
+    }
+
+    #[test]
+    fn modules_removed_between_pages_do_not_shift_the_next_page() {
+        let (names, token) = list(runtime(&["a", "b", "c", "d", "e"]), "?limit=2");
+        assert_eq!(vec!["a", "b"], names);
+        let query = format!("?limit=2&continuationToken={}", token.unwrap());
+
+        // Both the module the token names and one before it are gone, and one
+        // was created before it.
+        let (names, token) = list(runtime(&["a", "aa", "c", "d", "e"]), &query);
+        assert_eq!(vec!["c", "d"], names);
+        assert!(token.is_some());
+
+        // The module after the token is gone too.
+        let (names, token) = list(runtime(&["a", "d"]), &query);
+        assert_eq!(vec!["d"], names);
+        assert_eq!(None, token);
+    }
+
+    #[test]
+    fn bad_page_parameters_are_rejected() {
+        for query in &["?limit=0", "?limit=x", "?continuationToken=%25%25", "?limit=1&limit=2"] {
+            let request = Request::get(format!("http://localhost/modules{}", query))
+                .body(Body::default())
+                .unwrap();
+            let response = ListModules::new(runtime(&["a"]))
+                .handle(request, Parameters::new())
+                .wait()
+                .unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, response.status(), "{}", query);
+        }
+    }
 }
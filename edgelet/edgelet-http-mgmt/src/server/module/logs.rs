@@ -1,12 +1,22 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::{LogOptions, LogTail, ModuleRuntime};
-use edgelet_http::route::{Handler, Parameters};
-use failure::ResultExt;
-use futures::{future, Future};
+use std::sync::Arc;
+
+use edgelet_core::metrics::{LOG_STREAMS_ENDED, LOG_STREAMS_OPEN};
+use edgelet_core::{LogOptions, LogTail, Metrics, MetricsRegistry, ModuleRuntime};
+use edgelet_docker::LogLines;
+use edgelet_http::route::{Handler, Parameters, Query};
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
+use edgelet_utils::log_failure;
+use failure::{self, Fail, ResultExt};
+use futures::sync::oneshot;
+use futures::{future, Async, Future, Poll, Stream};
+use http::header::CONTENT_TYPE;
 use http::{Request, Response, StatusCode};
-use hyper::{Body, Error as HyperError};
-use url::form_urlencoded;
+use hyper::{Body, Chunk as HyperChunk, Error as HyperError};
+use log::Level;
+use serde::Serialize;
+use serde_json;
 
 use error::{Error, ErrorKind};
 use IntoResponse;
@@ -16,6 +26,7 @@ where
     M: 'static + ModuleRuntime + Clone,
 {
     runtime: M,
+    metrics: Arc<Metrics>,
 }
 
 impl<M> ModuleLogs<M>
@@ -23,7 +34,17 @@ where
     M: 'static + ModuleRuntime + Clone,
 {
     pub fn new(runtime: M) -> Self {
-        ModuleLogs { runtime }
+        ModuleLogs {
+            runtime,
+            metrics: Arc::new(MetricsRegistry::new()),
+        }
+    }
+
+    /// Sets where the log streams that are open, and why they ended, are
+    /// recorded.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
     }
 }
 
@@ -31,7 +52,7 @@ impl<M> Handler<Parameters> for ModuleLogs<M>
 where
     M: 'static + ModuleRuntime + Clone + Send,
     M::Error: IntoResponse,
-    M::Logs: Into<Body>,
+    M::Chunk: Into<HyperChunk>,
 {
     fn handle(
         &self,
@@ -39,25 +60,42 @@ where
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         let runtime = self.runtime.clone();
+        let metrics = self.metrics.clone();
         let response = match params
             .name("name")
             .ok_or_else(|| Error::from(ErrorKind::BadParam))
             .and_then(|name| {
-                let options = req
-                    .uri()
-                    .query()
-                    .map_or_else(|| Ok(LogOptions::default()), parse_options)
-                    .context(ErrorKind::BadParam);
-                Ok((name, options?))
+                let query = Query::from_request(&req);
+                let options = parse_options(&query).context(ErrorKind::BadParam);
+                let format = parse_format(&query).context(ErrorKind::BadParam);
+                Ok((name, options?, format?))
             }) {
-            Ok((name, options)) => {
+            Ok((name, options, format)) => {
+                let timestamps = options.timestamps();
+                let follow = options.follow();
+                let removal_check = removal_check(runtime.clone(), name.to_string());
                 let result = runtime
                     .logs(name, &options)
-                    .map(|s| {
-                        Response::builder()
-                            .status(StatusCode::OK)
-                            .body(s.into())
-                            .unwrap_or_else(|e| e.into_response())
+                    .map(move |logs| {
+                        let mut logs = Tethered::new(logs, metrics);
+                        if follow {
+                            logs = logs.with_removal_check(removal_check);
+                        }
+                        let mut response = Response::builder();
+                        response.status(StatusCode::OK);
+                        let body = match format {
+                            // Forward the chunks read from the runtime as
+                            // they are, rather than copying them into a new
+                            // body.
+                            LogFormat::Raw => {
+                                Body::wrap_stream(logs.map(Into::into).map_err(Fail::compat))
+                            }
+                            LogFormat::Lines => {
+                                response.header(CONTENT_TYPE, "application/x-ndjson");
+                                Body::wrap_stream(lines(logs, timestamps).map_err(Fail::compat))
+                            }
+                        };
+                        response.body(body).unwrap_or_else(|e| e.into_response())
                     }).or_else(|e| future::ok(e.into_response()));
                 future::Either::A(result)
             }
@@ -67,35 +105,275 @@ where
     }
 }
 
-fn parse_options(query: &str) -> Result<LogOptions, Error> {
-    let parse = form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
-    let tail = parse
-        .iter()
-        .find(|&(ref key, _)| key == "tail")
-        .map_or_else(|| Ok(LogTail::default()), |(_, val)| val.parse::<LogTail>())?;
-    let follow = parse
-        .iter()
-        .find(|&(ref key, _)| key == "follow")
-        .map_or_else(|| Ok(false), |(_, val)| val.parse::<bool>())?;
-    let options = LogOptions::new().with_follow(follow).with_tail(tail);
+/// Tells whether a module is gone, by asking for its logs again: the runtime
+/// only answers that with not found once the module was removed.
+fn removal_check<M>(runtime: M, name: String) -> RemovalCheck
+where
+    M: 'static + ModuleRuntime + Send,
+    M::Error: IntoResponse,
+{
+    Box::new(move || -> Box<Future<Item = bool, Error = ()> + Send> {
+        let options = LogOptions::new().with_tail(LogTail::Num(0));
+        Box::new(runtime.logs(&name, &options).then(|result| {
+            Ok(match result {
+                Ok(_) => false,
+                Err(err) => err.into_response().status() == StatusCode::NOT_FOUND,
+            })
+        }))
+    })
+}
+
+/// The logs as a JSON object per line, followed by a line that says why they
+/// ended if they ended early.
+fn lines<S>(
+    mut logs: Tethered<S>,
+    timestamps: bool,
+) -> impl Stream<Item = HyperChunk, Error = failure::Error>
+where
+    S: Stream,
+    S::Item: AsRef<[u8]>,
+    S::Error: Fail,
+{
+    let end = logs.end();
+    let last_line = end
+        .then(|end| Ok::<_, failure::Error>(end.ok().and_then(|end| end.last_line())))
+        .into_stream()
+        .filter_map(|line| line)
+        .and_then(|line| ndjson(&line));
+    LogLines::new(logs, timestamps)
+        .map_err(failure::Error::from)
+        .and_then(|line| ndjson(&line))
+        .chain(last_line)
+}
+
+/// Creates the check whether the module whose logs ended was removed.
+type RemovalCheck = Box<Fn() -> Box<Future<Item = bool, Error = ()> + Send> + Send>;
+
+/// Why the logs of a module stopped being forwarded.
+#[derive(Clone, Debug, PartialEq)]
+enum LogsEnd {
+    Finished,
+    ModuleRemoved,
+    Failed(String),
+    ClientGone,
+}
+
+impl LogsEnd {
+    fn label(&self) -> &'static str {
+        match *self {
+            LogsEnd::Finished => "finished",
+            LogsEnd::ModuleRemoved => "module_removed",
+            LogsEnd::Failed(_) => "failed",
+            LogsEnd::ClientGone => "client_gone",
+        }
+    }
+
+    /// The line the logs end with, for logs that ended early.
+    fn last_line(&self) -> Option<LastLine> {
+        match *self {
+            LogsEnd::ModuleRemoved => Some(LastLine {
+                end: "moduleRemoved",
+                message: None,
+            }),
+            LogsEnd::Failed(ref message) => Some(LastLine {
+                end: "failed",
+                message: Some(message.clone()),
+            }),
+            LogsEnd::Finished | LogsEnd::ClientGone => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LastLine {
+    end: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// Forwards the logs of a module from the container runtime for only as long
+/// as both the runtime and the client are there. The runtime's stream is
+/// dropped as soon as it ends, fails or the client goes away and the response
+/// body with this stream is dropped, so that neither keeps the other open.
+/// Failing to read the logs ends the stream rather than aborting the response.
+struct Tethered<S> {
+    logs: Option<S>,
+    removal_check: Option<RemovalCheck>,
+    checking: Option<Box<Future<Item = bool, Error = ()> + Send>>,
+    end: Option<oneshot::Sender<LogsEnd>>,
+    end_receiver: Option<oneshot::Receiver<LogsEnd>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Tethered<S> {
+    fn new(logs: S, metrics: Arc<Metrics>) -> Self {
+        metrics.add(&LOG_STREAMS_OPEN, &[], 1.0);
+        let (end, end_receiver) = oneshot::channel();
+        Tethered {
+            logs: Some(logs),
+            removal_check: None,
+            checking: None,
+            end: Some(end),
+            end_receiver: Some(end_receiver),
+            metrics,
+        }
+    }
+
+    /// Checks whether the module was removed when its logs end, for logs
+    /// that are followed.
+    fn with_removal_check(mut self, removal_check: RemovalCheck) -> Self {
+        self.removal_check = Some(removal_check);
+        self
+    }
+
+    /// Resolves to why the logs ended, once they have.
+    fn end(&mut self) -> oneshot::Receiver<LogsEnd> {
+        self.end_receiver
+            .take()
+            .expect("the end of the logs was already taken")
+    }
+
+    fn close_logs(&mut self) {
+        if self.logs.take().is_some() {
+            self.metrics.add(&LOG_STREAMS_OPEN, &[], -1.0);
+        }
+    }
+
+    fn ended(&mut self, end: LogsEnd) {
+        self.metrics
+            .increment(&LOG_STREAMS_ENDED, &[("reason", end.label())]);
+        if let Some(sender) = self.end.take() {
+            // The receiver is gone for logs that need no last line.
+            let _ = sender.send(end);
+        }
+    }
+}
+
+impl<S> Stream for Tethered<S>
+where
+    S: Stream,
+    S::Error: Fail,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.logs.is_some() {
+            let polled = match self.logs {
+                Some(ref mut logs) => logs.poll(),
+                None => Ok(Async::Ready(None)),
+            };
+            match polled {
+                Ok(Async::Ready(None)) => {
+                    self.close_logs();
+                    match self.removal_check.take() {
+                        Some(removal_check) => self.checking = Some(removal_check()),
+                        None => {
+                            self.ended(LogsEnd::Finished);
+                            return Ok(Async::Ready(None));
+                        }
+                    }
+                }
+                Err(err) => {
+                    self.close_logs();
+                    log_failure(Level::Warn, &err);
+                    self.ended(LogsEnd::Failed(err.to_string()));
+                    return Ok(Async::Ready(None));
+                }
+                polled => return polled,
+            }
+        }
+
+        let checked = self.checking.as_mut().map(Future::poll);
+        match checked {
+            Some(Ok(Async::NotReady)) => Ok(Async::NotReady),
+            Some(Ok(Async::Ready(removed))) => {
+                self.checking = None;
+                self.ended(if removed {
+                    LogsEnd::ModuleRemoved
+                } else {
+                    LogsEnd::Finished
+                });
+                Ok(Async::Ready(None))
+            }
+            Some(Err(())) => {
+                self.checking = None;
+                self.ended(LogsEnd::Finished);
+                Ok(Async::Ready(None))
+            }
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+impl<S> Drop for Tethered<S> {
+    fn drop(&mut self) {
+        self.close_logs();
+        if self.end.is_some() {
+            self.metrics
+                .increment(&LOG_STREAMS_ENDED, &[("reason", LogsEnd::ClientGone.label())]);
+        }
+    }
+}
+
+pub(super) fn parse_options(query: &Query) -> Result<LogOptions, HttpError> {
+    let tail = query.get_parsed::<LogTail>("tail")?.unwrap_or_default();
+    let follow = query.get_bool("follow")?.unwrap_or(false);
+    let since = query.get_parsed::<i32>("since")?.unwrap_or(0);
+    let timestamps = query.get_bool("timestamps")?.unwrap_or(false);
+    let options = LogOptions::new()
+        .with_follow(follow)
+        .with_tail(tail)
+        .with_since(since)
+        .with_timestamps(timestamps);
     Ok(options)
 }
 
+/// How the logs are written to the response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    /// The stream docker multiplexes stdout and stderr into, as it is.
+    Raw,
+    /// A JSON object per line, separated by line breaks.
+    Lines,
+}
+
+fn parse_format(query: &Query) -> Result<LogFormat, HttpError> {
+    match query.get("format")? {
+        None | Some("raw") => Ok(LogFormat::Raw),
+        Some("lines") => Ok(LogFormat::Lines),
+        Some(_) => Err(HttpError::from(HttpErrorKind::InvalidQueryParameter(
+            "format".to_string(),
+        ))),
+    }
+}
+
+/// Writes `value` as a line of JSON.
+pub(super) fn ndjson<T: Serialize>(value: &T) -> Result<HyperChunk, failure::Error> {
+    let mut json = serde_json::to_vec(value)?;
+    json.push(b'\n');
+    Ok(HyperChunk::from(json))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::sync::atomic::{AtomicBool, Ordering};
+
     use chrono::prelude::*;
     use edgelet_core::{ModuleRuntimeState, ModuleStatus};
+    use edgelet_docker::{LogLine, StdStream};
     use edgelet_test_utils::module::*;
-    use futures::Stream;
+    use futures::executor::{self, Notify};
+    use futures::{stream, Stream};
     use management::models::*;
     use serde_json;
     use server::module::tests::Error;
 
     #[test]
     fn correct_logoptions() {
-        let query = "follow=true&tail=6";
+        let query = Query::parse("follow=true&tail=6");
         let options = parse_options(&query).unwrap();
         assert_eq!(LogTail::Num(6), *options.tail());
         assert_eq!(true, options.follow());
@@ -103,26 +381,123 @@ mod tests {
 
     #[test]
     fn logoption_defaults() {
-        let query = "";
+        let query = Query::parse("");
         let options = parse_options(&query).unwrap();
         assert_eq!(LogTail::default(), *options.tail());
         assert_eq!(false, options.follow());
+        assert_eq!(0, options.since());
+        assert_eq!(false, options.timestamps());
+    }
+
+    #[test]
+    fn logoption_since_and_timestamps() {
+        let query = Query::parse("since=1539612000&timestamps=true");
+        let options = parse_options(&query).unwrap();
+        assert_eq!(1_539_612_000, options.since());
+        assert_eq!(true, options.timestamps());
+    }
+
+    #[test]
+    fn logoption_since_error() {
+        let query = Query::parse("since=yesterday");
+        let options = parse_options(&query);
+        assert_eq!(
+            "Invalid value for query parameter since",
+            options.err().unwrap().to_string()
+        );
     }
 
     #[test]
     fn logoption_follow_error() {
-        let query = "follow=34&tail=6";
+        let query = Query::parse("follow=34&tail=6");
         let options = parse_options(&query);
         assert!(options.is_err());
-        assert_eq!("Parse error", options.err().unwrap().to_string());
+        assert_eq!(
+            "Invalid value for query parameter follow",
+            options.err().unwrap().to_string()
+        );
     }
 
     #[test]
     fn logoption_tail_error() {
-        let query = "follow=false&tail=adsaf";
+        let query = Query::parse("follow=false&tail=adsaf");
         let options = parse_options(&query);
         assert!(options.is_err());
-        assert_eq!("Core error", options.err().unwrap().to_string());
+        assert_eq!(
+            "Invalid value for query parameter tail",
+            options.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn logoption_follow_without_value() {
+        let query = Query::parse("follow&tail=6");
+        let options = parse_options(&query).unwrap();
+        assert_eq!(true, options.follow());
+    }
+
+    #[test]
+    fn logoption_repeated_tail_error() {
+        let query = Query::parse("tail=6&tail=7");
+        let options = parse_options(&query);
+        assert_eq!(
+            "Query parameter tail was given more than once",
+            options.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn logformat_defaults_to_raw() {
+        assert_eq!(LogFormat::Raw, parse_format(&Query::parse("")).unwrap());
+        assert_eq!(LogFormat::Raw, parse_format(&Query::parse("format=raw")).unwrap());
+        assert_eq!(
+            LogFormat::Lines,
+            parse_format(&Query::parse("format=lines")).unwrap()
+        );
+    }
+
+    #[test]
+    fn logformat_error() {
+        let format = parse_format(&Query::parse("format=xml"));
+        assert_eq!(
+            "Invalid value for query parameter format",
+            format.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn ndjson_ends_lines_with_a_line_break() {
+        let line = LogLine::new(StdStream::Stdout, None, "Roses are red".into());
+        let expected = concat!(
+            "{\"stream\":\"stdout\",\"text\":\"Roses are red\",",
+            "\"truncated\":false}\n"
+        );
+        assert_eq!(expected.as_bytes(), &ndjson(&line).unwrap()[..]);
+    }
+
+    #[test]
+    fn lines_success() {
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(ModuleRuntimeState::default()));
+        let runtime = TestRuntime::new(Ok(module));
+        let handler = ModuleLogs::new(runtime);
+        let request = Request::get(
+            "http://localhost/modules/mod1/logs?api-version=2018-06-28&format=lines",
+        ).body(Body::default())
+        .unwrap();
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "mod1".to_string())]);
+
+        // act
+        let response = handler.handle(request, parameters).wait().unwrap();
+
+        // assert
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "application/x-ndjson",
+            response.headers().get(CONTENT_TYPE).unwrap()
+        );
     }
 
     #[test]
@@ -217,10 +592,195 @@ mod tests {
             .concat2()
             .and_then(|b| {
                 let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
-                assert_eq!("Bad parameter\n\tcaused by: Core error\n\tcaused by: Parse error\n\tcaused by: invalid digit found in string", error.message());
+                assert_eq!("Bad parameter\n\tcaused by: Invalid value for query parameter tail\n\tcaused by: Invalid log tail \"asfafda\", expected \"all\" or a number of lines\n\tcaused by: invalid digit found in string", error.message());
                 Ok(())
             })
             .wait()
             .unwrap();
     }
+
+    #[test]
+    fn negative_tail_fails() {
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(ModuleRuntimeState::default()));
+        let runtime = TestRuntime::new(Ok(module));
+        let handler = ModuleLogs::new(runtime);
+        let request =
+            Request::get("http://localhost/modules/mod1/logs?api-version=2018-06-28&tail=-1")
+                .body(Body::default())
+                .unwrap();
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "mod1".to_string())]);
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert!(
+                    error.message().contains("Invalid log tail \"-1\""),
+                    "{}",
+                    error.message()
+                );
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    /// Logs from the runtime that record whether they were dropped.
+    struct Upstream<S> {
+        logs: S,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl<S> Upstream<S> {
+        fn new(logs: S) -> (Self, Arc<AtomicBool>) {
+            let dropped = Arc::new(AtomicBool::new(false));
+            let upstream = Upstream {
+                logs,
+                dropped: dropped.clone(),
+            };
+            (upstream, dropped)
+        }
+    }
+
+    impl<S: Stream> Stream for Upstream<S> {
+        type Item = S::Item;
+        type Error = S::Error;
+
+        fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            self.logs.poll()
+        }
+    }
+
+    impl<S> Drop for Upstream<S> {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    /// A message from the runtime with a line written to stdout.
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![1, 0, 0, 0, 0, 0, 0, 14];
+        frame.extend_from_slice(b"Roses are red\n");
+        frame
+    }
+
+    fn ended(metrics: &MetricsRegistry, reason: &str) -> u64 {
+        metrics.counter(&LOG_STREAMS_ENDED, &[("reason", reason)])
+    }
+
+    fn read_lines<S>(logs: Tethered<S>) -> Vec<String>
+    where
+        S: 'static + Stream<Item = Vec<u8>, Error = Error> + Send,
+    {
+        lines(logs, false)
+            .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn logs_are_dropped_when_the_client_goes_away() {
+        // arrange
+        let metrics = MetricsRegistry::new();
+        let (upstream, dropped) =
+            Upstream::new(stream::poll_fn(|| Ok::<_, Error>(Async::NotReady)));
+        let logs: Tethered<Upstream<_>> = Tethered::new(upstream, Arc::new(metrics.clone()));
+        let mut logs = executor::spawn(logs);
+        assert!(
+            logs.poll_stream_notify(&Arc::new(NoopNotify), 0)
+                .unwrap()
+                .is_not_ready()
+        );
+        assert_eq!("1", metrics.gauge(&LOG_STREAMS_OPEN, &[]).to_string());
+
+        // act
+        drop(logs);
+
+        // assert
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!("0", metrics.gauge(&LOG_STREAMS_OPEN, &[]).to_string());
+        assert_eq!(1, ended(&metrics, "client_gone"));
+        assert_eq!(0, ended(&metrics, "finished"));
+    }
+
+    #[test]
+    fn logs_of_removed_modules_end_with_a_last_line() {
+        // arrange
+        let metrics = MetricsRegistry::new();
+        let (upstream, dropped) =
+            Upstream::new(stream::iter_ok::<_, Error>(vec![frame()]));
+        let checked = Arc::new(AtomicBool::new(false));
+        let checked_copy = checked.clone();
+        let logs = Tethered::new(upstream, Arc::new(metrics.clone())).with_removal_check(
+            Box::new(move || -> Box<Future<Item = bool, Error = ()> + Send> {
+                // The runtime's logs are let go of before the module is looked for.
+                assert!(dropped.load(Ordering::SeqCst));
+                checked_copy.store(true, Ordering::SeqCst);
+                Box::new(future::ok(true))
+            }),
+        );
+
+        // act
+        let lines = read_lines(logs);
+
+        // assert
+        assert!(checked.load(Ordering::SeqCst));
+        assert_eq!(2, lines.len());
+        assert!(lines[0].contains("Roses are red"));
+        assert_eq!("{\"end\":\"moduleRemoved\"}\n", lines[1]);
+        assert_eq!("0", metrics.gauge(&LOG_STREAMS_OPEN, &[]).to_string());
+        assert_eq!(1, ended(&metrics, "module_removed"));
+        assert_eq!(0, ended(&metrics, "client_gone"));
+    }
+
+    #[test]
+    fn logs_of_modules_still_there_end_without_a_last_line() {
+        let metrics = MetricsRegistry::new();
+        let (upstream, _) =
+            Upstream::new(stream::iter_ok::<_, Error>(vec![frame()]));
+        let logs = Tethered::new(upstream, Arc::new(metrics.clone())).with_removal_check(
+            Box::new(|| -> Box<Future<Item = bool, Error = ()> + Send> {
+                Box::new(future::ok(false))
+            }),
+        );
+
+        assert_eq!(1, read_lines(logs).len());
+        assert_eq!(1, ended(&metrics, "finished"));
+        assert_eq!(0, ended(&metrics, "client_gone"));
+    }
+
+    #[test]
+    fn failing_logs_end_with_a_last_line() {
+        let metrics = MetricsRegistry::new();
+        let (upstream, dropped) = Upstream::new(
+            stream::iter_ok::<_, Error>(vec![frame()])
+                .chain(stream::once(Err(Error::General))),
+        );
+        let logs = Tethered::new(upstream, Arc::new(metrics.clone()));
+
+        let lines = read_lines(logs);
+
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            "{\"end\":\"failed\",\"message\":\"General error\"}\n",
+            lines[1]
+        );
+        assert_eq!("0", metrics.gauge(&LOG_STREAMS_OPEN, &[]).to_string());
+        assert_eq!(1, ended(&metrics, "failed"));
+        assert_eq!(0, ended(&metrics, "client_gone"));
+    }
 }
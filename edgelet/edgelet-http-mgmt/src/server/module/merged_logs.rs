@@ -0,0 +1,524 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use edgelet_core::{Clock, ModuleRuntime, SystemClock};
+use edgelet_docker::{LogLine, LogLines};
+use edgelet_http::route::{Handler, Parameters, Query};
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
+use failure::{self, Fail, ResultExt};
+use futures::{future, Async, Future, Poll, Stream};
+use http::header::CONTENT_TYPE;
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use tokio::timer::Error as TimerError;
+
+use super::logs::{ndjson, parse_options};
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// The most modules whose logs can be merged at once.
+const MAX_MERGED_MODULES: usize = 8;
+
+/// How long a line is held back for lines of other modules that were logged
+/// before it but haven't been read yet.
+const MERGE_WINDOW_MILLIS: u64 = 250;
+
+/// The most lines read ahead of the merge for each module. A module that
+/// logs faster than the others are read waits for them instead of growing
+/// the buffer.
+const MAX_PENDING_LINES: usize = 256;
+
+/// Merges the logs of several modules into one stream of lines in the order
+/// they were logged, each with the module that logged it. A module that
+/// can't be read from is reported in a warning record and the logs of the
+/// others go on. When the client goes away hyper drops the response body,
+/// and with it the logs of every module.
+pub struct MergedModuleLogs<M>
+where
+    M: 'static + ModuleRuntime + Clone,
+{
+    runtime: M,
+}
+
+impl<M> MergedModuleLogs<M>
+where
+    M: 'static + ModuleRuntime + Clone,
+{
+    pub fn new(runtime: M) -> Self {
+        MergedModuleLogs { runtime }
+    }
+}
+
+impl<M> Handler<Parameters> for MergedModuleLogs<M>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let runtime = self.runtime.clone();
+        let query = Query::from_request(&req);
+        let request = parse_names(&query)
+            .and_then(|names| Ok((names, parse_options(&query)?)))
+            .context(ErrorKind::BadParam);
+        let response = match request {
+            Ok((names, options)) => {
+                // Lines can only be put in order by their timestamps.
+                let options = options.with_timestamps(true);
+                let logs = names.into_iter().map(move |name| {
+                    runtime
+                        .logs(&name, &options)
+                        .then(move |logs| Ok::<_, HyperError>((name, logs)))
+                });
+                let result = future::join_all(logs).map(|logs| {
+                    let mut merged = MergedLogs::new(
+                        Arc::new(SystemClock),
+                        Duration::from_millis(MERGE_WINDOW_MILLIS),
+                    );
+                    for (name, logs) in logs {
+                        match logs {
+                            Ok(logs) => merged.add(name, LogLines::new(logs, true)),
+                            Err(err) => merged.warn(name, err.to_string()),
+                        }
+                    }
+                    let body = merged
+                        .and_then(|record| ndjson(&record))
+                        .map_err(failure::Error::compat);
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .header(CONTENT_TYPE, "application/x-ndjson")
+                        .body(Body::wrap_stream(body))
+                        .unwrap_or_else(|e| e.into_response())
+                });
+                future::Either::A(result)
+            }
+            Err(e) => future::Either::B(future::ok(Error::from(e).into_response())),
+        };
+        Box::new(response)
+    }
+}
+
+/// The names of the modules to merge the logs of, from a comma separated
+/// list.
+fn parse_names(query: &Query) -> Result<Vec<String>, HttpError> {
+    let invalid = || HttpError::from(HttpErrorKind::InvalidQueryParameter("names".to_string()));
+    let mut names: Vec<String> = vec![];
+    for name in query.get("names")?.ok_or_else(invalid)?.split(',') {
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    if names.len() > MAX_MERGED_MODULES {
+        return Err(invalid());
+    }
+    Ok(names)
+}
+
+/// A line of the merged logs.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+enum LogRecord {
+    Line(ModuleLogLine),
+    Warning(ModuleLogWarning),
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ModuleLogLine {
+    module: String,
+    #[serde(flatten)]
+    line: LogLine,
+}
+
+/// Why the logs of a module are missing from the merge, or end early.
+#[derive(Debug, PartialEq, Serialize)]
+struct ModuleLogWarning {
+    module: String,
+    warning: String,
+}
+
+struct Source<S> {
+    module: String,
+    /// `None` once the logs have ended.
+    lines: Option<S>,
+    /// The lines read but not merged yet, with when they were read.
+    pending: VecDeque<(LogLine, Instant)>,
+}
+
+/// Merges lines by their timestamps. The lines of each module are already in
+/// order, so a line can be merged as soon as every module that is still
+/// logging has a line waiting. While a module is quiet, the lines of the
+/// others are held back for the window in case a line of the quiet module is
+/// on its way. Warnings are written as soon as they happen.
+struct MergedLogs<S> {
+    sources: Vec<Source<S>>,
+    warnings: VecDeque<LogRecord>,
+    clock: Arc<Clock>,
+    window: Duration,
+    delay: Option<(Instant, Box<Future<Item = (), Error = TimerError> + Send>)>,
+}
+
+impl<S> MergedLogs<S>
+where
+    S: Stream<Item = LogLine>,
+    S::Error: Fail,
+{
+    fn new(clock: Arc<Clock>, window: Duration) -> Self {
+        MergedLogs {
+            sources: vec![],
+            warnings: VecDeque::new(),
+            clock,
+            window,
+            delay: None,
+        }
+    }
+
+    fn add(&mut self, module: String, lines: S) {
+        self.sources.push(Source {
+            module,
+            lines: Some(lines),
+            pending: VecDeque::new(),
+        });
+    }
+
+    fn warn(&mut self, module: String, warning: String) {
+        self.warnings
+            .push_back(LogRecord::Warning(ModuleLogWarning { module, warning }));
+    }
+
+    /// Reads the lines that are ready, up to the most kept for each module.
+    fn read(&mut self) {
+        let now = self.clock.instant();
+        for source in &mut self.sources {
+            while source.pending.len() < MAX_PENDING_LINES {
+                let polled = match source.lines {
+                    Some(ref mut lines) => lines.poll(),
+                    None => break,
+                };
+                match polled {
+                    Ok(Async::Ready(Some(line))) => source.pending.push_back((line, now)),
+                    Ok(Async::Ready(None)) => source.lines = None,
+                    Ok(Async::NotReady) => break,
+                    Err(err) => {
+                        self.warnings
+                            .push_back(LogRecord::Warning(ModuleLogWarning {
+                                module: source.module.clone(),
+                                warning: err.to_string(),
+                            }));
+                        source.lines = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S> Stream for MergedLogs<S>
+where
+    S: Stream<Item = LogLine>,
+    S::Error: Fail,
+{
+    type Item = LogRecord;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(warning) = self.warnings.pop_front() {
+                return Ok(Async::Ready(Some(warning)));
+            }
+
+            self.read();
+            if !self.warnings.is_empty() {
+                continue;
+            }
+
+            let next = self
+                .sources
+                .iter()
+                .enumerate()
+                .filter_map(|(i, source)| source.pending.front().map(|p| (i, p)))
+                .min_by_key(|&(_, pending)| pending.0.timestamp())
+                .map(|(i, _)| i);
+            let next = match next {
+                Some(next) => next,
+                None if self.sources.iter().all(|s| s.lines.is_none()) => {
+                    return Ok(Async::Ready(None))
+                }
+                None => return Ok(Async::NotReady),
+            };
+
+            let complete = self
+                .sources
+                .iter()
+                .all(|s| s.lines.is_none() || !s.pending.is_empty());
+            let window = self.window;
+            let deadline = self
+                .sources
+                .iter()
+                .filter_map(|s| s.pending.front().map(|&(_, read)| read + window))
+                .min()
+                .expect("a line is pending");
+
+            if complete || self.clock.instant() >= deadline {
+                self.delay = None;
+                let source = &mut self.sources[next];
+                let (line, _) = source.pending.pop_front().expect("a line is pending");
+                return Ok(Async::Ready(Some(LogRecord::Line(ModuleLogLine {
+                    module: source.module.clone(),
+                    line,
+                }))));
+            }
+
+            let stale = self.delay.as_ref().map_or(true, |&(at, _)| at != deadline);
+            if stale {
+                self.delay = Some((deadline, self.clock.delay(deadline)));
+            }
+            if let Some((_, ref mut delay)) = self.delay {
+                try_ready!(delay.poll());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+    use edgelet_docker::StdStream;
+    use edgelet_test_utils::module::*;
+    use edgelet_test_utils::TestClock;
+    use futures::executor::{self, Notify, Spawn};
+    use futures::stream;
+    use futures::sync::mpsc;
+    use management::models::ErrorResponse;
+    use serde_json;
+    use server::module::tests::Error;
+
+    type Lines = Box<Stream<Item = LogLine, Error = Error> + Send>;
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    fn time() -> DateTime<Utc> {
+        Utc.ymd(2018, 10, 15).and_hms(14, 0, 0)
+    }
+
+    fn window() -> Duration {
+        Duration::from_millis(MERGE_WINDOW_MILLIS)
+    }
+
+    fn line(millis: i64, text: &'static str) -> LogLine {
+        let timestamp = time() + ChronoDuration::milliseconds(millis);
+        LogLine::new(StdStream::Stdout, Some(timestamp), text.into())
+    }
+
+    fn canned(lines: Vec<LogLine>) -> Lines {
+        Box::new(stream::iter_ok(lines))
+    }
+
+    /// Lines that are followed by nothing more, without the logs ending.
+    fn followed(lines: Vec<LogLine>) -> Lines {
+        Box::new(stream::iter_ok(lines).chain(stream::poll_fn(|| Ok(Async::NotReady))))
+    }
+
+    fn text(record: &LogRecord) -> String {
+        match *record {
+            LogRecord::Line(ref line) => String::from_utf8_lossy(line.line.text()).into_owned(),
+            LogRecord::Warning(ref warning) => warning.warning.clone(),
+        }
+    }
+
+    // Merges as far as it gets without the clock moving.
+    fn poll<S: Stream<Item = LogRecord>>(merged: &mut Spawn<S>) -> Option<String> {
+        match merged.poll_stream_notify(&Arc::new(NoopNotify), 0) {
+            Ok(Async::Ready(Some(record))) => Some(text(&record)),
+            Ok(Async::NotReady) => None,
+            _ => panic!("merge ended"),
+        }
+    }
+
+    fn handle(runtime: TestRuntime<Error>, uri: &str) -> (StatusCode, String) {
+        let request = Request::get(uri).body(Body::default()).unwrap();
+        let response = MergedModuleLogs::new(runtime)
+            .handle(request, Parameters::new())
+            .wait()
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn lines_are_merged_by_timestamp() {
+        let mut merged = MergedLogs::new(Arc::new(TestClock::default()), window());
+        merged.add(
+            "edgeHub".to_string(),
+            canned(vec![
+                line(0, "hub 0"),
+                line(20, "hub 20"),
+                line(40, "hub 40"),
+                line(50, "hub 50"),
+            ]),
+        );
+        merged.add(
+            "opcua".to_string(),
+            canned(vec![
+                line(10, "opcua 10"),
+                line(30, "opcua 30"),
+                line(60, "opcua 60"),
+            ]),
+        );
+
+        let records = merged.collect().wait().unwrap();
+
+        let texts: Vec<_> = records.iter().map(text).collect();
+        assert_eq!(
+            vec!["hub 0", "opcua 10", "hub 20", "opcua 30", "hub 40", "hub 50", "opcua 60"],
+            texts
+        );
+        let timestamps: Vec<_> = records
+            .iter()
+            .map(|record| match *record {
+                LogRecord::Line(ref line) => line.line.timestamp(),
+                LogRecord::Warning(_) => panic!("unexpected warning"),
+            }).collect();
+        let mut sorted = timestamps.clone();
+        sorted.sort();
+        assert_eq!(sorted, timestamps);
+    }
+
+    #[test]
+    fn lines_are_held_back_for_quiet_modules_within_the_window() {
+        let clock = TestClock::default();
+        let (opcua, opcua_lines) = mpsc::unbounded();
+        let mut merged = MergedLogs::new(Arc::new(clock.clone()), window());
+        merged.add("edgeHub".to_string(), followed(vec![line(50, "hub 50")]));
+        merged.add(
+            "opcua".to_string(),
+            Box::new(opcua_lines.map_err(|()| Error::General)) as Lines,
+        );
+        let mut merged = executor::spawn(merged);
+
+        // A line that was logged earlier but read later is still merged
+        // before it.
+        assert_eq!(None, poll(&mut merged));
+        clock.advance(window() / 2);
+        opcua.unbounded_send(line(30, "opcua 30")).unwrap();
+        assert_eq!(Some("opcua 30".to_string()), poll(&mut merged));
+        assert_eq!(None, poll(&mut merged));
+
+        // Once the window has passed the line goes out without waiting for
+        // the quiet module any longer.
+        clock.advance(window() / 2);
+        assert_eq!(Some("hub 50".to_string()), poll(&mut merged));
+        assert_eq!(None, poll(&mut merged));
+    }
+
+    #[test]
+    fn failing_modules_are_reported_without_ending_the_merge() {
+        let mut merged = MergedLogs::new(Arc::new(TestClock::default()), window());
+        merged.warn("opcua".to_string(), "No such container: opcua".to_string());
+        merged.add(
+            "edgeHub".to_string(),
+            Box::new(stream::iter_result(vec![
+                Ok(line(0, "hub 0")),
+                Err(Error::General),
+            ])) as Lines,
+        );
+        merged.add(
+            "tempSensor".to_string(),
+            canned(vec![line(10, "sensor 10")]),
+        );
+
+        let texts: Vec<_> = merged.collect().wait().unwrap().iter().map(text).collect();
+
+        assert_eq!(
+            vec![
+                "No such container: opcua",
+                "General error",
+                "hub 0",
+                "sensor 10"
+            ],
+            texts
+        );
+    }
+
+    #[test]
+    fn records_carry_the_module() {
+        let line = LogRecord::Line(ModuleLogLine {
+            module: "edgeHub".to_string(),
+            line: LogLine::new(StdStream::Stderr, None, "Roses are red".into()),
+        });
+        let expected = concat!(
+            "{\"module\":\"edgeHub\",\"stream\":\"stderr\",",
+            "\"text\":\"Roses are red\",\"truncated\":false}\n"
+        );
+        assert_eq!(expected.as_bytes(), &ndjson(&line).unwrap()[..]);
+
+        let warning = LogRecord::Warning(ModuleLogWarning {
+            module: "opcua".to_string(),
+            warning: "No such container: opcua".to_string(),
+        });
+        let expected = "{\"module\":\"opcua\",\"warning\":\"No such container: opcua\"}\n";
+        assert_eq!(expected.as_bytes(), &ndjson(&warning).unwrap()[..]);
+    }
+
+    #[test]
+    fn modules_that_fail_are_warnings() {
+        let runtime = TestRuntime::new(Err(Error::General));
+
+        let (status, body) = handle(runtime, "http://localhost/modules/logs?names=edgeHub,opcua");
+
+        assert_eq!(StatusCode::OK, status);
+        let records: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                json!({"module": "edgeHub", "warning": "General error"}),
+                json!({"module": "opcua", "warning": "General error"}),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn names_are_required() {
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module = TestModule::new("edgeHub".to_string(), config, Ok(Default::default()));
+
+        for uri in &[
+            "http://localhost/modules/logs",
+            "http://localhost/modules/logs?names=",
+            "http://localhost/modules/logs?names=edgeHub,,opcua",
+            "http://localhost/modules/logs?names=m1,m2,m3,m4,m5,m6,m7,m8,m9",
+        ] {
+            let (status, body) = handle(TestRuntime::new(Ok(module.clone())), uri);
+
+            assert_eq!(StatusCode::BAD_REQUEST, status);
+            let error: ErrorResponse = serde_json::from_str(&body).unwrap();
+            assert_eq!(
+                "Bad parameter\n\tcaused by: Invalid value for query parameter names",
+                error.message()
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_names_are_merged_once() {
+        let query = Query::parse("names=edgeHub,opcua,edgeHub");
+        assert_eq!(vec!["edgeHub", "opcua"], parse_names(&query).unwrap());
+    }
+}
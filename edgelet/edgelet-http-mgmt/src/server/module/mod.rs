@@ -18,21 +18,31 @@ use serde_json;
 use error::{Error, ErrorKind};
 use IntoResponse;
 
+mod bulk;
+mod config;
 mod create;
 mod delete;
+mod events;
 mod get;
+mod kill;
 mod list;
 mod logs;
+mod merged_logs;
 mod restart;
 mod start;
 mod stop;
 mod update;
 
+pub use self::bulk::BulkModules;
+pub use self::config::{EnvRedaction, GetModuleConfig};
 pub use self::create::CreateModule;
 pub use self::delete::DeleteModule;
+pub use self::events::ModuleStateEvents;
 pub use self::get::GetModule;
+pub use self::kill::KillModule;
 pub use self::list::ListModules;
 pub use self::logs::ModuleLogs;
+pub use self::merged_logs::MergedModuleLogs;
 pub use self::restart::RestartModule;
 pub use self::start::StartModule;
 pub use self::stop::StopModule;
@@ -47,18 +57,31 @@ impl IntoResponse for DockerError {
             fail = cause;
         }
 
-        let status_code = match *self.kind() {
-            DockerErrorKind::NotFound(_) => StatusCode::NOT_FOUND,
-            DockerErrorKind::Conflict => StatusCode::CONFLICT,
-            DockerErrorKind::NotModified => StatusCode::NOT_MODIFIED,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        let (status_code, code) = match *self.kind() {
+            DockerErrorKind::NotFound(_) | DockerErrorKind::ModuleNotFound(_) => {
+                (StatusCode::NOT_FOUND, Some("NOT_FOUND"))
+            }
+            DockerErrorKind::Conflict => (StatusCode::CONFLICT, Some("CONFLICT")),
+            DockerErrorKind::NotModified => (StatusCode::NOT_MODIFIED, None),
+            DockerErrorKind::Timeout | DockerErrorKind::OperationTimedOut(_) => {
+                (StatusCode::GATEWAY_TIMEOUT, Some("TIMED_OUT"))
+            }
+            DockerErrorKind::UnknownEngine(_) => {
+                (StatusCode::BAD_REQUEST, Some("UNKNOWN_ENGINE"))
+            }
+            DockerErrorKind::InvalidSignal(_) => (StatusCode::BAD_REQUEST, Some("INVALID_SIGNAL")),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, None),
         };
 
         // Per the RFC, status code NotModified should not have a body
         let body = if status_code == StatusCode::NOT_MODIFIED {
             None
         } else {
-            let b = serde_json::to_string(&ErrorResponse::new(message))
+            let mut response = ErrorResponse::new(message);
+            if let Some(code) = code {
+                response.set_code(code.to_string());
+            }
+            let b = serde_json::to_string(&response)
                 .expect("serialization of ErrorResponse failed.");
             Some(b)
         };
@@ -88,12 +111,17 @@ where
     M::Config: Serialize,
 {
     let settings = serde_json::to_value(module.config()).context(ErrorKind::Serde)?;
-    let config = Config::new(settings).with_env(vec![]);
+    let config = Config::new(settings)
+        .with_env(vec![])
+        .with_volumes(module.volumes());
     let mut runtime_status = RuntimeStatus::new(state.status().to_string());
     if let Some(description) = state.status_description() {
         runtime_status.set_description(description.to_string());
     }
     let mut status = Status::new(runtime_status);
+    if let Some(created_at) = state.created_at() {
+        status.set_create_time(created_at.to_rfc3339());
+    }
     if let Some(started_at) = state.started_at() {
         status.set_start_time(started_at.to_rfc3339());
     }
@@ -123,62 +151,122 @@ where
     let type_ = spec.type_();
     let env = spec.config().env().map_or_else(HashMap::new, |vars| {
         vars.into_iter()
-            .map(|var| (var.key().clone(), var.value().clone()))
+            .map(|var| (var.key().to_string(), var.value().to_string()))
             .collect()
     });
     let config = serde_json::from_value(spec.config().settings().clone())?;
-    let module_spec = CoreModuleSpec::new(name, type_, config, env)?;
+    let module_spec = CoreModuleSpec::new(name, type_, config, env)?
+        .with_runtime(spec.runtime().map(ToOwned::to_owned));
     Ok(module_spec)
 }
 
 fn spec_to_details(spec: &ModuleSpec, module_status: ModuleStatus) -> ModuleDetails {
-    let id = spec.name().clone();
-    let name = spec.name().clone();
-    let type_ = spec.type_().clone();
-
-    let env = spec.config().env().map(|e| {
-        e.iter()
-            .map(|ev| EnvVar::new(ev.key().clone(), ev.value().clone()))
-            .collect()
-    });
-    let mut config = Config::new(spec.config().settings().clone());
-    if let Some(e) = env {
-        config.set_env(e);
-    }
+    let id = spec.name().to_string();
+    let name = spec.name().to_string();
+    let type_ = spec.type_().to_string();
 
     let runtime_status = RuntimeStatus::new(module_status.to_string());
     let status = Status::new(runtime_status);
-    ModuleDetails::new(id, name, type_, config, status)
+    ModuleDetails::new(id, name, type_, spec.config().clone(), status)
 }
 
 #[cfg(test)]
 pub mod tests {
-    use edgelet_docker::{Error as DockerError, ErrorKind as DockerErrorKind};
+    use edgelet_core::{
+        Error as CoreError, ErrorKind as CoreErrorKind, Module, ModuleRuntimeState, ModuleStatus,
+    };
+    use edgelet_docker::{DockerConfig, Error as DockerError, ErrorKind as DockerErrorKind};
+    use futures::future::{self, FutureResult};
     use futures::{Future, Stream};
     use http::{Response, StatusCode};
     use hyper::Body;
-    use management::models::ErrorResponse;
+    use management::models::{Config, EnvVar, ErrorResponse, ModuleSpec};
     use serde_json;
 
+    use super::{core_to_details, spec_to_details};
     use IntoResponse;
 
     #[derive(Clone, Copy, Debug, Fail)]
     pub enum Error {
         #[fail(display = "General error")]
         General,
+        #[fail(display = "Not found")]
+        NotFound,
     }
 
     impl IntoResponse for Error {
         fn into_response(self) -> Response<Body> {
             let body = serde_json::to_string(&ErrorResponse::new(self.to_string()))
                 .expect("serialization of ErrorResponse failed.");
+            let status = match self {
+                Error::General => StatusCode::INTERNAL_SERVER_ERROR,
+                Error::NotFound => StatusCode::NOT_FOUND,
+            };
             Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .status(status)
                 .body(body.into())
                 .unwrap()
         }
     }
 
+    impl From<Error> for CoreError {
+        fn from(_err: Error) -> Self {
+            CoreError::from(CoreErrorKind::ModuleRuntime)
+        }
+    }
+
+    struct RegistryModule(DockerConfig);
+
+    impl Module for RegistryModule {
+        type Config = DockerConfig;
+        type Error = DockerError;
+        type RuntimeStateFuture = FutureResult<ModuleRuntimeState, Self::Error>;
+
+        fn name(&self) -> &str {
+            "sensor"
+        }
+
+        fn type_(&self) -> &str {
+            "docker"
+        }
+
+        fn config(&self) -> &Self::Config {
+            &self.0
+        }
+
+        fn runtime_state(&self) -> Self::RuntimeStateFuture {
+            future::ok(ModuleRuntimeState::default())
+        }
+    }
+
+    #[test]
+    fn details_keep_the_spec_config() {
+        let config = Config::new(json!({ "image": "microsoft/test-image" }))
+            .with_env(vec![EnvVar::new("K".to_string(), "V".to_string())]);
+        let spec = ModuleSpec::new("test-module".to_string(), "docker".to_string(), config);
+
+        let details = spec_to_details(&spec, ModuleStatus::Running);
+
+        assert_eq!(spec.name(), details.name());
+        assert_eq!(spec.config(), details.config());
+        assert_eq!("running", details.status().runtime_status().status());
+    }
+
+    #[test]
+    fn details_refer_to_registry_credential_by_name() {
+        let config: DockerConfig = serde_json::from_value(json!({
+            "image": "contoso.azurecr.io/sensor:1.0",
+            "auth": "contoso"
+        })).unwrap();
+        let module = RegistryModule(config);
+
+        let details = core_to_details(&module, &ModuleRuntimeState::default()).unwrap();
+
+        let json = serde_json::to_value(&details).unwrap();
+        assert_eq!(json!("contoso"), json["config"]["settings"]["auth"]);
+        assert!(!json.to_string().contains("password"));
+    }
+
     #[test]
     fn not_found() {
         // arrange
@@ -197,6 +285,7 @@ pub mod tests {
             .and_then(|b| {
                 let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
                 assert_eq!("manifest for image:latest not found", error.message());
+                assert_eq!(Some("NOT_FOUND"), error.code());
                 Ok(())
             }).wait()
             .unwrap();
@@ -218,6 +307,77 @@ pub mod tests {
             .and_then(|b| {
                 let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
                 assert_eq!("Conflict with current operation", error.message());
+                assert_eq!(Some("CONFLICT"), error.code());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+        // arrange
+        let error = DockerError::from(DockerErrorKind::Timeout);
+
+        // act
+        let response = error.into_response();
+
+        // assert
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!("Container runtime request timed out", error.message());
+                assert_eq!(Some("TIMED_OUT"), error.code());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn operation_timed_out() {
+        // arrange
+        let error = DockerError::from(DockerErrorKind::OperationTimedOut("stop"));
+
+        // act
+        let response = error.into_response();
+
+        // assert
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!(
+                    "Container runtime operation stop timed out",
+                    error.message()
+                );
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn unknown_engine() {
+        // arrange
+        let error = DockerError::from(DockerErrorKind::UnknownEngine("sandbox".to_string()));
+
+        // act
+        let response = error.into_response();
+
+        // assert
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!(
+                    "Container engine sandbox is not configured",
+                    error.message()
+                );
                 Ok(())
             }).wait()
             .unwrap();
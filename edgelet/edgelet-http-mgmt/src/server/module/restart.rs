@@ -1,7 +1,11 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::time::Duration;
+
 use edgelet_core::ModuleRuntime;
-use edgelet_http::route::{Handler, Parameters};
+use edgelet_http::route::{Handler, Parameters, Query};
+use edgelet_utils::Error as UtilsError;
+use failure::ResultExt;
 use futures::{future, Future};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
@@ -9,6 +13,9 @@ use hyper::{Body, Error as HyperError};
 use error::{Error, ErrorKind};
 use IntoResponse;
 
+const MIN_RESTART_TIMEOUT_SECS: i32 = 1;
+const MAX_RESTART_TIMEOUT_SECS: i32 = 600;
+
 pub struct RestartModule<M>
 where
     M: 'static + ModuleRuntime,
@@ -29,19 +36,42 @@ where
 
 impl<M> Handler<Parameters> for RestartModule<M>
 where
-    M: 'static + ModuleRuntime + Send,
+    M: 'static + ModuleRuntime + Clone + Send,
     <M as ModuleRuntime>::Error: IntoResponse,
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-        let response = match params.name("name") {
-            Some(name) => {
-                let result = self
-                    .runtime
-                    .restart(name)
+        let runtime = self.runtime.clone();
+        let response = match params
+            .name("name")
+            .ok_or_else(|| Error::from(ErrorKind::BadParam))
+            .and_then(|name| {
+                let timeout = match Query::from_request(&req)
+                    .get_parsed::<i32>("timeout")
+                    .context(ErrorKind::BadParam)?
+                {
+                    Some(timeout) => Some(check_timeout(timeout).context(ErrorKind::BadParam)?),
+                    None => None,
+                };
+                Ok((name.to_string(), timeout))
+            }) {
+            Ok((name, timeout)) => {
+                // Without a timeout the runtime's own restart is used, which
+                // keeps its default grace period.
+                let restart = match timeout {
+                    Some(timeout) => {
+                        // The range check above guarantees the timeout is positive.
+                        #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+                        let wait_before_kill = Duration::from_secs(u64::from(timeout as u32));
+                        let stop = runtime.stop(&name, Some(wait_before_kill));
+                        future::Either::A(stop.and_then(move |_| runtime.start(&name)))
+                    }
+                    None => future::Either::B(runtime.restart(&name)),
+                };
+                let result = restart
                     .map(|_| {
                         Response::builder()
                             .status(StatusCode::NO_CONTENT)
@@ -50,20 +80,31 @@ where
                     }).or_else(|e| future::ok(e.into_response()));
                 future::Either::A(result)
             }
-
-            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+            Err(e) => future::Either::B(future::ok(e.into_response())),
         };
 
         Box::new(response)
     }
 }
 
+fn check_timeout(timeout: i32) -> Result<i32, UtilsError> {
+    Ok(ensure_range!(
+        timeout,
+        MIN_RESTART_TIMEOUT_SECS,
+        MAX_RESTART_TIMEOUT_SECS,
+        "timeout"
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::prelude::*;
     use edgelet_core::{ModuleRuntimeState, ModuleStatus};
     use edgelet_http::route::Parameters;
     use edgelet_test_utils::module::*;
+    use futures::Stream;
+    use management::models::ErrorResponse;
+    use serde_json;
     use server::module::tests::Error;
 
     use super::*;
@@ -121,4 +162,68 @@ mod tests {
         // assert
         assert_eq!(StatusCode::BAD_REQUEST, response.status());
     }
+
+    fn runtime() -> TestRuntime<Error> {
+        let state = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(state));
+        TestRuntime::new(Ok(module))
+    }
+
+    fn restart(uri: &str) -> Response<Body> {
+        let handler = RestartModule::new(runtime());
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "test".to_string())]);
+        let request = Request::post(uri).body(Body::default()).unwrap();
+        handler.handle(request, parameters).wait().unwrap()
+    }
+
+    fn error_message(response: Response<Body>) -> String {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                serde_json::from_slice::<ErrorResponse>(&b)
+                    .unwrap()
+                    .message()
+                    .to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn success_with_timeout() {
+        let response = restart("http://localhost/modules/test/restart?timeout=30");
+
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+    }
+
+    #[test]
+    fn timeout_out_of_range() {
+        for timeout in &["0", "601", "-1"] {
+            let uri = format!("http://localhost/modules/test/restart?timeout={}", timeout);
+            let response = restart(&uri);
+
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+            assert_eq!(
+                format!(
+                    "Bad parameter\n\tcaused by: timeout must be between 1 and 600, got {}",
+                    timeout
+                ),
+                error_message(response)
+            );
+        }
+    }
+
+    #[test]
+    fn timeout_not_a_number() {
+        let response = restart("http://localhost/modules/test/restart?timeout=soon");
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "Bad parameter\n\tcaused by: Invalid value for query parameter timeout\n\tcaused by: invalid digit found in string",
+            error_message(response)
+        );
+    }
 }
@@ -1,14 +1,27 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::ModuleRuntime;
-use edgelet_http::route::{Handler, Parameters};
+use std::time::Duration;
+
+use edgelet_core::{start_and_confirm, Module, ModuleRuntime, ModuleRuntimeState, StartOutcome};
+use edgelet_http::route::{Handler, Parameters, Query};
+use failure::ResultExt;
 use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
+use serde::Serialize;
+use serde_json;
 
+use super::core_to_details;
 use error::{Error, ErrorKind};
 use IntoResponse;
 
+/// The longest a caller may ask to wait for a module to start, in seconds.
+const MAX_WAIT_SECS: i32 = 60;
+
+/// Starts a module. With `?wait=<secs>`, the module is then checked on for
+/// up to that long, and the response is the module as it was last seen, or an
+/// error with the exit code if it exited right away.
 pub struct StartModule<M>
 where
     M: 'static + ModuleRuntime,
@@ -29,16 +42,22 @@ where
 
 impl<M> Handler<Parameters> for StartModule<M>
 where
-    M: 'static + ModuleRuntime + Send,
+    M: 'static + ModuleRuntime + Clone + Send,
     <M as ModuleRuntime>::Error: IntoResponse,
+    M::Module: Send,
+    <M::Module as Module>::Config: Serialize,
+    <M::Module as Module>::Error: Into<M::Error>,
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-        let response = match params.name("name") {
-            Some(name) => {
+        let wait = Query::from_request(&req)
+            .get_i32_in_range("wait", 0, MAX_WAIT_SECS)
+            .context(ErrorKind::BadParam);
+        let response = match (params.name("name"), wait) {
+            (Some(name), Ok(None)) => {
                 let result = self
                     .runtime
                     .start(name)
@@ -48,22 +67,71 @@ where
                             .body(Body::default())
                             .unwrap_or_else(|e| e.into_response())
                     }).or_else(|e| future::ok(e.into_response()));
-                future::Either::A(result)
+                future::Either::A(future::Either::A(result))
+            }
+
+            (Some(name), Ok(Some(wait))) => {
+                let name = name.to_string();
+                #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+                let wait = Duration::from_secs(wait as u64);
+                let result = start_and_confirm(&self.runtime, &name, wait)
+                    .map(move |outcome| {
+                        started(&name, outcome).unwrap_or_else(|e| e.into_response())
+                    }).or_else(|e| future::ok(e.into_response()));
+                future::Either::A(future::Either::B(result))
             }
 
-            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+            (_, Err(e)) => future::Either::B(future::ok(Error::from(e).into_response())),
+
+            (None, _) => {
+                future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response()))
+            }
         };
 
         Box::new(response)
     }
 }
 
+fn started<M>(name: &str, outcome: StartOutcome<M>) -> Result<Response<Body>, Error>
+where
+    M: 'static + Module + Send,
+    M::Config: Serialize,
+{
+    match outcome {
+        StartOutcome::Running(module, state) | StartOutcome::Starting(module, state) => {
+            details(&module, &state)
+        }
+        StartOutcome::Exited(_, state) => Err(Error::from(ErrorKind::ModuleExited(
+            name.to_string(),
+            state.exit_code().unwrap_or_default(),
+        ))),
+        StartOutcome::Removed => Err(Error::from(ErrorKind::ModuleRemoved(name.to_string()))),
+    }
+}
+
+fn details<M>(module: &M, state: &ModuleRuntimeState) -> Result<Response<Body>, Error>
+where
+    M: 'static + Module + Send,
+    M::Config: Serialize,
+{
+    let details = core_to_details(module, state)?;
+    let b = serde_json::to_string(&details).context(ErrorKind::Serde)?;
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .header(CONTENT_LENGTH, b.len().to_string().as_str())
+        .body(b.into())?;
+    Ok(response)
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::prelude::*;
     use edgelet_core::{ModuleRuntimeState, ModuleStatus};
     use edgelet_http::route::Parameters;
     use edgelet_test_utils::module::*;
+    use futures::Stream;
+    use management::models::{ErrorResponse, ModuleDetails};
     use server::module::tests::Error;
 
     use super::*;
@@ -121,4 +189,76 @@ mod tests {
         // assert
         assert_eq!(StatusCode::BAD_REQUEST, response.status());
     }
+    fn start_and_wait(state: ModuleRuntimeState, query: &str) -> (StatusCode, String) {
+        let config = TestConfig::new("microsoft/test-image".to_string());
+        let module: TestModule<Error> =
+            TestModule::new("test-module".to_string(), config, Ok(state));
+        let runtime = TestRuntime::new(Ok(module));
+        let handler = StartModule::new(runtime);
+        let parameters =
+            Parameters::with_captures(vec![(Some("name".to_string()), "test-module".to_string())]);
+        let uri = format!("http://localhost/modules/test-module/start?{}", query);
+        let request = Request::post(uri).body(Body::default()).unwrap();
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn wait_for_running_module() {
+        let state = ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Running)
+            .with_started_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(14, 20, 0, 1)));
+        let (status, body) = start_and_wait(state, "wait=5");
+
+        assert_eq!(StatusCode::OK, status);
+        let details: ModuleDetails = serde_json::from_str(&body).unwrap();
+        assert_eq!("test-module", details.name());
+        assert_eq!("running", details.status().runtime_status().status());
+    }
+
+    #[test]
+    fn wait_for_immediately_exiting_module() {
+        let state = ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Failed)
+            .with_exit_code(Some(127))
+            .with_status_description(Some("exited".to_string()))
+            .with_started_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(14, 20, 0, 1)))
+            .with_finished_at(Some(Utc.ymd(2018, 4, 13).and_hms_milli(14, 20, 0, 5)));
+        let (status, body) = start_and_wait(state, "wait=5");
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        let error: ErrorResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            "Module test-module exited right after it started, with exit code 127",
+            error.message()
+        );
+    }
+
+    #[test]
+    fn wait_for_module_that_is_still_starting() {
+        let state = ModuleRuntimeState::default()
+            .with_status(ModuleStatus::Stopped)
+            .with_status_description(Some("created".to_string()));
+        let (status, body) = start_and_wait(state, "wait=0");
+
+        assert_eq!(StatusCode::OK, status);
+        let details: ModuleDetails = serde_json::from_str(&body).unwrap();
+        assert_eq!("stopped", details.status().runtime_status().status());
+    }
+
+    #[test]
+    fn wait_out_of_range() {
+        let state = ModuleRuntimeState::default().with_status(ModuleStatus::Running);
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            start_and_wait(state.clone(), "wait=61").0
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            start_and_wait(state, "wait=soon").0
+        );
+    }
 }
@@ -80,50 +80,35 @@ where
                             info!("Updating module {}", name);
                         }
 
-                        let created = runtime
-                            .remove(&name)
+                        // The new image is pulled while the old container
+                        // still runs, so that the module is down only for
+                        // as long as replacing the container takes.
+                        let updated = runtime
+                            .registry()
+                            .pull_module(&core_spec)
                             .and_then(move |_| {
-                                debug!("Removed existing module {}", name);
-                                runtime
-                                    .registry()
-                                    .pull(core_spec.config())
-                                    .and_then(move |_| {
-                                        debug!("Successfully pulled new image for module {}", name);
-                                        runtime.create(core_spec).and_then(move |_| {
-                                            debug!("Created module {}", name);
-                                            if start {
-                                                info!("Starting module {}", name);
-                                                future::Either::A(
-                                                    runtime
-                                                        .start(&name)
-                                                        .map(|_| ModuleStatus::Running),
-                                                )
-                                            } else {
-                                                future::Either::B(future::ok(ModuleStatus::Stopped))
-                                            }.map(
-                                                move |status| {
-                                                    let details = spec_to_details(&spec, status);
-                                                    match serde_json::to_string(&details)
-                                                        .context(ErrorKind::Serde)
-                                                    {
-                                                        Ok(b) => Response::builder()
-                                                            .status(StatusCode::OK)
-                                                            .header(
-                                                                CONTENT_TYPE,
-                                                                "application/json",
-                                                            ).header(
-                                                                CONTENT_LENGTH,
-                                                                b.len().to_string().as_str(),
-                                                            ).body(b.into())
-                                                            .unwrap_or_else(|e| e.into_response()),
-                                                        Err(e) => e.into_response(),
-                                                    }
-                                                },
-                                            )
-                                        })
-                                    })
+                                debug!("Successfully pulled new image for module {}", name);
+                                runtime.update(core_spec, start).map(move |_| {
+                                    debug!("Updated module {}", name);
+                                    if start {
+                                        ModuleStatus::Running
+                                    } else {
+                                        ModuleStatus::Stopped
+                                    }
+                                })
+                            }).map(move |status| {
+                                let details = spec_to_details(&spec, status);
+                                match serde_json::to_string(&details).context(ErrorKind::Serde) {
+                                    Ok(b) => Response::builder()
+                                        .status(StatusCode::OK)
+                                        .header(CONTENT_TYPE, "application/json")
+                                        .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                                        .body(b.into())
+                                        .unwrap_or_else(|e| e.into_response()),
+                                    Err(e) => e.into_response(),
+                                }
                             }).or_else(|e| future::ok(e.into_response()));
-                        future::Either::A(created)
+                        future::Either::A(updated)
                     }
                     Err(e) => future::Either::B(future::ok(e.into_response())),
                 }
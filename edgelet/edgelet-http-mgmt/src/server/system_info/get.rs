@@ -1,8 +1,11 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::{Module, ModuleRuntime};
-use edgelet_http::route::{Handler, Parameters};
-use failure::ResultExt;
+use edgelet_core::{
+    version, DiskMonitor, DiskUsage as CoreDiskUsage, Module, ModuleRuntime,
+    SystemInfo as CoreSystemInfo, SystemResources as CoreSystemResources,
+};
+use edgelet_http::route::{Handler, Parameters, Query};
+use failure::{Fail, ResultExt};
 use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
@@ -21,6 +24,7 @@ where
     <M::Module as Module>::Config: Serialize,
 {
     runtime: M,
+    disks: Option<DiskMonitor>,
 }
 
 impl<M> GetSystemInfo<M>
@@ -30,7 +34,17 @@ where
     <M::Module as Module>::Config: Serialize,
 {
     pub fn new(runtime: M) -> Self {
-        GetSystemInfo { runtime }
+        GetSystemInfo {
+            runtime,
+            disks: None,
+        }
+    }
+
+    /// Reports the disk space `disks` measures, along with the rest of the
+    /// system information.
+    pub fn with_disk_monitor(mut self, disks: Option<DiskMonitor>) -> Self {
+        self.disks = disks;
+        self
     }
 }
 
@@ -42,20 +56,63 @@ where
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         _params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         debug!("Get System Information");
-        let response = self
-            .runtime
-            .system_info()
-            .and_then(|systeminfo| {
-                let body = SystemInfo::new(
-                    systeminfo.os_type().to_string(),
-                    systeminfo.architecture().to_string(),
-                    systeminfo.version().to_string(),
-                );
-                let response = match serde_json::to_string(&body).context(ErrorKind::Serde) {
+        let refresh = match Query::from_request(&req)
+            .get_bool("refresh")
+            .context(ErrorKind::BadParam)
+        {
+            Ok(refresh) => refresh.unwrap_or(false),
+            Err(e) => return Box::new(future::ok(e.into_response())),
+        };
+        let disks = self.disks.clone();
+
+        // The container runtime is queried while the disks are measured, and
+        // a runtime that can't be reached only leaves its part null, with the
+        // reason in the runtime error. Runtimes
+        // that keep the system information for a while are asked anew when
+        // the caller wants it fresh.
+        let system_info = if refresh {
+            self.runtime.refresh_system_info()
+        } else {
+            self.runtime.system_info()
+        };
+        let system_info = system_info.then(Ok);
+        let measured = future::lazy(move || Ok(disks.map(|disks| (disks.resources(None), disks))));
+        let response = system_info
+            .join(measured)
+            .map(|(system_info, measured)| {
+                let body = match system_info {
+                    Ok(systeminfo) => {
+                        let mut body = runtime_body(&systeminfo);
+                        let resources = systeminfo.resources().cloned().or_else(|| {
+                            measured.map(|(resources, disks)| {
+                                let runtime_root = systeminfo
+                                    .runtime_root_dir()
+                                    .map(|path| disks.usage(path));
+                                resources.with_runtime_root(runtime_root)
+                            })
+                        });
+                        if let Some(resources) = resources {
+                            body.set_resources(system_resources(&resources));
+                        }
+                        body
+                    }
+                    Err(err) => match measured {
+                        Some((ref resources, _)) if any_measured(resources) => {
+                            SystemInfo::new(version().to_string())
+                                .with_resources(system_resources(resources))
+                                .with_runtime_error(format!(
+                                    "Container runtime is unavailable: {}",
+                                    error_message(&err)
+                                ))
+                        }
+                        _ => return err.into_response(),
+                    },
+                };
+                match serde_json::to_string(&body).context(ErrorKind::Serde) {
                     Ok(b) => Response::builder()
                         .status(StatusCode::OK)
                         .header(CONTENT_TYPE, "application/json")
@@ -63,17 +120,85 @@ where
                         .body(b.into())
                         .unwrap_or_else(|e| e.into_response()),
                     Err(e) => e.into_response(),
-                };
-                future::ok(response)
-            }).or_else(|e| future::ok(e.into_response()));
+                }
+            });
 
         Box::new(response)
     }
 }
 
+fn runtime_body(systeminfo: &CoreSystemInfo) -> SystemInfo {
+    let mut body = SystemInfo::new(systeminfo.version().to_string())
+        .with_os_type(systeminfo.os_type().to_string())
+        .with_architecture(systeminfo.architecture().to_string());
+    if let Some(runtime_version) = systeminfo.runtime_version() {
+        body.set_runtime_version(runtime_version.to_string());
+    }
+    if let Some(runtime_api_version) = systeminfo.runtime_api_version() {
+        body.set_runtime_api_version(runtime_api_version.to_string());
+    }
+    if let Some(storage_driver) = systeminfo.storage_driver() {
+        body.set_storage_driver(storage_driver.to_string());
+    }
+    if !systeminfo.storage_driver_status().is_empty() {
+        body.set_storage_driver_status(systeminfo.storage_driver_status().clone());
+    }
+    if let Some(logging_driver) = systeminfo.logging_driver() {
+        body.set_logging_driver(logging_driver.to_string());
+    }
+    body
+}
+
+/// Whether the disk space of any of the filesystems could be measured. The
+/// response is only worth sending without the container runtime if one was.
+fn any_measured(resources: &CoreSystemResources) -> bool {
+    resources
+        .runtime_root()
+        .into_iter()
+        .chain(resources.homedir())
+        .any(|usage| usage.space().is_ok())
+}
+
+fn error_message(err: &Fail) -> String {
+    let mut message = err.to_string();
+    let mut fail = err;
+    while let Some(cause) = fail.cause() {
+        message.push_str(&format!("\n\tcaused by: {}", cause));
+        fail = cause;
+    }
+    message
+}
+
+fn system_resources(resources: &CoreSystemResources) -> SystemResources {
+    let mut body = SystemResources::new();
+    if let Some(runtime_root) = resources.runtime_root() {
+        body.set_runtime_root(disk_usage(runtime_root));
+    }
+    if let Some(homedir) = resources.homedir() {
+        body.set_homedir(disk_usage(homedir));
+    }
+    body
+}
+
+// No filesystem comes close to 2^63 bytes.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_wrap))]
+fn disk_usage(usage: &CoreDiskUsage) -> DiskUsage {
+    let body = DiskUsage::new(usage.path().to_string_lossy().into_owned());
+    match usage.space() {
+        Ok(space) => body
+            .with_total_bytes(space.total_bytes() as i64)
+            .with_available_bytes(space.available_bytes() as i64),
+        Err(err) => body.with_error(err.to_string()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use edgelet_core::{self, ModuleRuntimeState};
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use edgelet_core::{self, DiskSpace, DiskStats, ModuleRuntimeState};
     use edgelet_http::route::Parameters;
     use edgelet_test_utils::module::*;
     use futures::Stream;
@@ -107,15 +232,164 @@ mod tests {
                 let os_type = system_info.os_type();
                 let architecture = system_info.architecture();
 
-                assert_eq!("os_type_sample", os_type);
-                assert_eq!("architecture_sample", architecture);
+                assert_eq!(Some("os_type_sample"), os_type);
+                assert_eq!(Some("architecture_sample"), architecture);
                 assert_eq!(edgelet_core::version(), system_info.version());
+                assert_eq!(
+                    Some("runtime_version_sample"),
+                    system_info.runtime_version()
+                );
+                assert_eq!(
+                    Some("runtime_api_version_sample"),
+                    system_info.runtime_api_version()
+                );
+
+                let json: serde_json::Value = serde_json::from_slice(&b).unwrap();
+                assert_eq!(json!("runtime_version_sample"), json["runtimeVersion"]);
+                assert_eq!(json!("runtime_api_version_sample"), json["runtimeApiVersion"]);
+                assert_eq!(json!("storage_driver_sample"), json["storageDriver"]);
+                assert_eq!(json!("logging_driver_sample"), json["loggingDriver"]);
+                assert_eq!(None, json.get("storageDriverStatus"));
 
                 Ok(())
             }).wait()
             .unwrap();
     }
 
+    #[test]
+    fn system_info_is_refreshed_when_asked() {
+        for &(uri, refreshed) in &[
+            ("http://localhost/info", false),
+            ("http://localhost/info?refresh=false", false),
+            ("http://localhost/info?refresh=true", true),
+            ("http://localhost/info?refresh", true),
+        ] {
+            let runtime = runtime(true);
+            let handler = GetSystemInfo::new(runtime.clone());
+            let request = Request::get(uri).body(Body::default()).unwrap();
+
+            let response = handler.handle(request, Parameters::new()).wait().unwrap();
+            assert_eq!(StatusCode::OK, response.status(), "{}", uri);
+            assert_eq!(
+                refreshed,
+                runtime.calls().contains(&"refresh_system_info".to_string()),
+                "{}",
+                uri
+            );
+        }
+
+        let handler = GetSystemInfo::new(runtime(true));
+        let request = Request::get("http://localhost/info?refresh=maybe")
+            .body(Body::default())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    /// Knows the disk space of the filesystem at `/var/lib/iotedge` and is
+    /// denied access to the rest.
+    struct TestDiskStats;
+
+    impl DiskStats for TestDiskStats {
+        fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+            if path == Path::new("/var/lib/iotedge") {
+                Ok(DiskSpace::new(8_000_000_000, 100_000_000))
+            } else {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            }
+        }
+    }
+
+    fn runtime(up: bool) -> TestRuntime<Error> {
+        if up {
+            let state = ModuleRuntimeState::default();
+            let config = TestConfig::new("microsoft/test-image".to_string());
+            let module: TestModule<Error> =
+                TestModule::new("test-module".to_string(), config, Ok(state));
+            TestRuntime::new(Ok(module))
+        } else {
+            TestRuntime::new(Err(Error::General))
+        }
+    }
+
+    fn system_info_with_disks(runtime: TestRuntime<Error>, homedir: &str) -> Response<Body> {
+        let disks = DiskMonitor::new(Arc::new(TestDiskStats), PathBuf::from(homedir));
+        let handler = GetSystemInfo::new(runtime).with_disk_monitor(Some(disks));
+        let request = Request::get("http://localhost/info")
+            .body(Body::default())
+            .unwrap();
+
+        handler.handle(request, Parameters::new()).wait().unwrap()
+    }
+
+    fn json_body(response: Response<Body>) -> serde_json::Value {
+        let body = response.into_body().concat2().wait().unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn disk_resources(homedir: &str) -> serde_json::Value {
+        let response = system_info_with_disks(runtime(true), homedir);
+
+        json_body(response)["resources"].clone()
+    }
+
+    #[test]
+    fn system_info_reports_disk_space() {
+        let resources = disk_resources("/var/lib/iotedge");
+
+        assert_eq!(
+            json!({
+                "homedir": {
+                    "path": "/var/lib/iotedge",
+                    "totalBytes": 8_000_000_000_i64,
+                    "availableBytes": 100_000_000,
+                }
+            }),
+            resources
+        );
+    }
+
+    #[test]
+    fn system_info_reports_disks_that_cannot_be_measured() {
+        let resources = disk_resources("/home/iotedge");
+
+        assert_eq!(json!("/home/iotedge"), resources["homedir"]["path"]);
+        assert_eq!(
+            json!(io::Error::from(io::ErrorKind::PermissionDenied).to_string()),
+            resources["homedir"]["error"]
+        );
+        assert_eq!(None, resources["homedir"].get("availableBytes"));
+    }
+
+    #[test]
+    fn system_info_without_runtime_reports_the_rest() {
+        let response = system_info_with_disks(runtime(false), "/var/lib/iotedge");
+
+        assert_eq!(StatusCode::OK, response.status());
+        let json = json_body(response);
+        assert_eq!(
+            json!("Container runtime is unavailable: General error"),
+            json["runtimeError"]
+        );
+        assert_eq!(Some(&json!(null)), json.get("osType"));
+        assert_eq!(Some(&json!(null)), json.get("architecture"));
+        assert_eq!(json!(edgelet_core::version()), json["version"]);
+        assert_eq!(None, json.get("runtimeVersion"));
+        assert_eq!(
+            json!(100_000_000),
+            json["resources"]["homedir"]["availableBytes"]
+        );
+    }
+
+    #[test]
+    fn system_info_fails_when_nothing_is_known() {
+        let response = system_info_with_disks(runtime(false), "/home/iotedge");
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        let error: ErrorResponse = serde_json::from_value(json_body(response)).unwrap();
+        assert_eq!("General error", error.message());
+    }
+
     #[test]
     fn system_info_failed() {
         // arrange
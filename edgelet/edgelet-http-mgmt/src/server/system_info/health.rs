@@ -0,0 +1,179 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use chrono::{SecondsFormat, Utc};
+use edgelet_core::{DaemonHealth, HealthStatus, SubsystemHealth as CoreSubsystemHealth};
+use edgelet_http::route::{Handler, Parameters};
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use management::models::*;
+use serde_json;
+
+use error::ErrorKind;
+use IntoResponse;
+
+/// Reports the health the daemon's background tasks last recorded. It never
+/// contacts the subsystems itself, so it stays cheap enough for frequent
+/// liveness probes.
+pub struct GetHealth {
+    health: DaemonHealth,
+}
+
+impl GetHealth {
+    pub fn new(health: DaemonHealth) -> Self {
+        GetHealth { health }
+    }
+}
+
+impl Handler<Parameters> for GetHealth {
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        debug!("Get Health");
+        let report = self.health.report(Utc::now());
+        let status = match report.status() {
+            HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+            HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_wrap))]
+        let body = Health::new(
+            report.status().to_string(),
+            report.uptime().as_secs() as i64,
+            subsystem(report.runtime()),
+            subsystem(report.identity()),
+            subsystem(report.provisioning()),
+            subsystem(report.watchdog()),
+        ).with_integrity(subsystem(report.integrity()));
+
+        let response = match serde_json::to_string(&body).context(ErrorKind::Serde) {
+            Ok(b) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                .body(b.into())
+                .unwrap_or_else(|e| e.into_response()),
+            Err(e) => e.into_response(),
+        };
+
+        Box::new(future::ok(response))
+    }
+}
+
+fn subsystem(health: &CoreSubsystemHealth) -> SubsystemHealth {
+    let mut subsystem = SubsystemHealth::new(health.status().to_string());
+    if let Some(last_checked) = health.last_checked() {
+        subsystem.set_last_checked(last_checked.to_rfc3339_opts(SecondsFormat::Secs, true));
+    }
+    if let Some(message) = health.message() {
+        subsystem.set_message(message.to_string());
+    }
+    subsystem
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use edgelet_http::route::Parameters;
+    use futures::Stream;
+
+    use super::*;
+
+    fn healthy() -> DaemonHealth {
+        let now = Utc::now();
+        let health = DaemonHealth::new(now);
+        health.record_runtime(Ok(()), now);
+        health.record_identity(Ok(()), now);
+        health.record_provisioned(true);
+        health.record_watchdog_run(now);
+        health
+    }
+
+    fn get(health: DaemonHealth) -> (StatusCode, Health) {
+        let handler = GetHealth::new(health);
+        let request = Request::get("http://localhost/systeminfo/health")
+            .body(Body::default())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn healthy_daemon() {
+        let (status, health) = get(healthy());
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("healthy", health.status());
+        assert_eq!("ok", health.runtime().status());
+        assert_eq!("ok", health.provisioning().status());
+        assert!(health.watchdog().last_checked().is_some());
+        assert_eq!(None, health.identity().message());
+        assert_eq!("unknown", health.integrity().unwrap().status());
+    }
+
+    #[test]
+    fn module_discrepancies() {
+        let health = healthy();
+        health.record_integrity(Err("module tempSensor is missing".to_string()), Utc::now());
+        let (status, health) = get(health);
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("degraded", health.status());
+        assert_eq!("down", health.integrity().unwrap().status());
+        assert_eq!(
+            Some("module tempSensor is missing"),
+            health.integrity().unwrap().message()
+        );
+    }
+
+    #[test]
+    fn degraded_daemon() {
+        let health = healthy();
+        health.record_identity(Err("IoT Hub unreachable".to_string()), Utc::now());
+        let (status, health) = get(health);
+
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("degraded", health.status());
+        assert_eq!("down", health.identity().status());
+        assert_eq!(Some("IoT Hub unreachable"), health.identity().message());
+    }
+
+    #[test]
+    fn runtime_down() {
+        let health = healthy();
+        health.record_runtime(Err("docker is not running".to_string()), Utc::now());
+        let (status, health) = get(health);
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!("unhealthy", health.status());
+        assert_eq!("down", health.runtime().status());
+        assert_eq!(Some("docker is not running"), health.runtime().message());
+    }
+
+    #[test]
+    fn stale_watchdog() {
+        let health = healthy();
+        health.record_watchdog_run(Utc::now() - Duration::minutes(10));
+        let (status, health) = get(health);
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!("unhealthy", health.status());
+        assert_eq!("stale", health.watchdog().status());
+        assert_eq!("ok", health.runtime().status());
+    }
+
+    #[test]
+    fn nothing_checked_yet() {
+        let (status, health) = get(DaemonHealth::new(Utc::now()));
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!("unknown", health.runtime().status());
+        assert_eq!("unknown", health.provisioning().status());
+        assert_eq!(None, health.runtime().last_checked());
+    }
+}
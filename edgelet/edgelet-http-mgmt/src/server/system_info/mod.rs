@@ -1,4 +1,6 @@
 // Copyright (c) Microsoft. All rights reserved.
 mod get;
+mod health;
 
 pub use self::get::GetSystemInfo;
+pub use self::health::GetHealth;
@@ -5,7 +5,7 @@ use std::str::Utf8Error;
 
 use base64::DecodeError;
 use chrono::format::ParseError;
-use edgelet_core::Error as CoreError;
+use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
 use edgelet_utils::Error as UtilsError;
 use failure::{Backtrace, Context, Fail};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
@@ -39,18 +39,56 @@ pub enum ErrorKind {
     BadBody,
     #[fail(display = "Invalid private key error")]
     BadPrivateKey,
+    #[fail(display = "Invalid certificate")]
+    BadCertificate,
+    #[fail(display = "The certificate signing request is invalid")]
+    InvalidCertificateRequest,
+    #[fail(display = "Module is not allowed to issue certificates for leaf devices")]
+    LeafCertIssuerNotAllowed,
+    #[fail(display = "The device CA has expired")]
+    DeviceCaExpired,
     #[fail(display = "Module not found")]
     NotFound,
+    #[fail(display = "Certificate not found")]
+    CertificateNotFound,
+    #[fail(display = "Manifest trust bundle is not configured")]
+    ManifestTrustBundleNotFound,
+    #[fail(display = "Module generation id mismatch")]
+    GenerationIdMismatch,
+    #[fail(display = "Identity manager error")]
+    IdentityManager,
     #[fail(display = "Sign failed")]
     Sign,
+    #[fail(display = "Invalid sign algorithm")]
+    InvalidSignAlgorithm,
+    #[fail(display = "Sign algorithm not supported by the key")]
+    UnsupportedSignAlgorithm,
+    #[fail(display = "Invalid key identifier")]
+    InvalidKeyId,
+    #[fail(display = "Invalid subject alternative name")]
+    InvalidSan,
+    #[fail(display = "Wildcard subject alternative names are not allowed")]
+    WildcardSanNotAllowed,
     #[fail(display = "Invalid base64 string")]
     Base64,
+    #[fail(display = "Invalid base64 encoding for {}", _0)]
+    MalformedBase64(&'static str),
+    #[fail(display = "Request body exceeds the maximum size of {} bytes", _0)]
+    RequestTooLarge(usize),
+    #[fail(display = "The batch exceeds the maximum of {} sign requests", _0)]
+    BatchTooLarge(usize),
+    #[fail(display = "The {} exceeds the maximum size of {} bytes", _0, _1)]
+    FieldTooLarge(&'static str, usize),
+    #[fail(display = "The {} must not be empty", _0)]
+    EmptyField(&'static str),
     #[fail(display = "Invalid ISO 8601 date")]
     DateParse,
     #[fail(display = "Utils error")]
     Utils,
     #[fail(display = "UTF-8 encode/decode")]
     Utf8,
+    #[fail(display = "The ciphertext was tampered with or is corrupt")]
+    PayloadTampered,
 }
 
 impl Fail for Error {
@@ -75,6 +113,16 @@ impl Error {
     }
 }
 
+impl ErrorKind {
+    /// The code in the response of the errors that callers tell apart.
+    fn code(self) -> Option<&'static str> {
+        match self {
+            ErrorKind::PayloadTampered => Some("PAYLOAD_TAMPERED"),
+            _ => None,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
         Error {
@@ -123,8 +171,12 @@ impl From<DecodeError> for Error {
 
 impl From<CoreError> for Error {
     fn from(error: CoreError) -> Self {
-        Error {
-            inner: error.context(ErrorKind::Sign),
+        match *error.kind() {
+            // The cause would only say the same again.
+            CoreErrorKind::PayloadTampered => Error::from(ErrorKind::PayloadTampered),
+            _ => Error {
+                inner: error.context(ErrorKind::Sign),
+            },
         }
     }
 }
@@ -153,21 +205,51 @@ impl From<Utf8Error> for Error {
     }
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response<Body> {
-        let mut fail: &Fail = &self;
+impl Error {
+    /// The error as the body of a response, with the message of each cause.
+    pub fn to_error_response(&self) -> ErrorResponse {
+        let mut fail: &Fail = self;
         let mut message = self.to_string();
         while let Some(cause) = fail.cause() {
             message.push_str(&format!("\n\tcaused by: {}", cause.to_string()));
             fail = cause;
         }
 
+        let mut response = ErrorResponse::new(message);
+        if let Some(code) = self.kind().code() {
+            response.set_code(code.to_string());
+        }
+        response
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        let response = self.to_error_response();
+
         let status_code = match *self.kind() {
-            ErrorKind::NotFound => StatusCode::NOT_FOUND,
-            ErrorKind::BadParam | ErrorKind::BadBody => StatusCode::BAD_REQUEST,
+            ErrorKind::LeafCertIssuerNotAllowed => StatusCode::FORBIDDEN,
+            ErrorKind::NotFound
+            | ErrorKind::CertificateNotFound
+            | ErrorKind::ManifestTrustBundleNotFound
+            | ErrorKind::GenerationIdMismatch => StatusCode::NOT_FOUND,
+            ErrorKind::BadParam
+            | ErrorKind::BadBody
+            | ErrorKind::InvalidSignAlgorithm
+            | ErrorKind::UnsupportedSignAlgorithm
+            | ErrorKind::InvalidCertificateRequest
+            | ErrorKind::InvalidKeyId
+            | ErrorKind::InvalidSan
+            | ErrorKind::WildcardSanNotAllowed
+            | ErrorKind::MalformedBase64(_)
+            | ErrorKind::EmptyField(_)
+            | ErrorKind::PayloadTampered => StatusCode::BAD_REQUEST,
+            ErrorKind::RequestTooLarge(_)
+            | ErrorKind::BatchTooLarge(_)
+            | ErrorKind::FieldTooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
             ErrorKind::Base64 => StatusCode::UNPROCESSABLE_ENTITY,
             _ => {
-                error!("Internal server error: {}", message);
+                error!("Internal server error: {}", response.message());
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         };
@@ -176,7 +258,7 @@ impl IntoResponse for Error {
         let body = if status_code == StatusCode::NOT_MODIFIED {
             None
         } else {
-            let b = serde_json::to_string(&ErrorResponse::new(message))
+            let b = serde_json::to_string(&response)
                 .expect("serialization of ErrorResponse failed.");
             Some(b)
         };
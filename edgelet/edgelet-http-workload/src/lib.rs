@@ -30,8 +30,13 @@ extern crate http;
 extern crate hyper;
 #[macro_use]
 extern crate log;
+extern crate openssl;
 extern crate serde;
 extern crate serde_json;
+#[cfg(test)]
+extern crate tempfile;
+extern crate tokio;
+extern crate url;
 extern crate workload;
 
 use http::Response;
@@ -40,7 +45,7 @@ use hyper::Body;
 mod error;
 mod server;
 
-pub use server::WorkloadService;
+pub use server::{IssuedCerts, WorkloadService};
 
 pub trait IntoResponse {
     fn into_response(self) -> Response<Body>;
@@ -1,6 +1,8 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use super::{compute_validity, refresh_cert};
+use std::sync::Arc;
+
+use super::{compute_validity, refresh_cert, IssuedCerts};
 use failure::ResultExt;
 use futures::{future, Future, Stream};
 use http::{Request, Response};
@@ -8,7 +10,8 @@ use hyper::{Body, Error as HyperError};
 use serde_json;
 
 use edgelet_core::{
-    Certificate, CertificateProperties, CertificateType, CreateCertificate, WorkloadConfig,
+    Certificate, CertificateProperties, CertificateType, Clock, CreateCertificate, SystemClock,
+    WorkloadConfig,
 };
 use edgelet_http::route::{Handler, Parameters};
 use edgelet_utils::prepare_cert_uri_module;
@@ -20,11 +23,29 @@ use IntoResponse;
 pub struct IdentityCertHandler<T: CreateCertificate, W: WorkloadConfig> {
     hsm: T,
     config: W,
+    issued: IssuedCerts,
+    clock: Arc<Clock>,
 }
 
 impl<T: CreateCertificate, W: WorkloadConfig> IdentityCertHandler<T, W> {
     pub fn new(hsm: T, config: W) -> Self {
-        IdentityCertHandler { hsm, config }
+        IdentityCertHandler {
+            hsm,
+            config,
+            issued: IssuedCerts::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_issued_certs(mut self, issued: IssuedCerts) -> Self {
+        self.issued = issued;
+        self
+    }
+
+    /// Sets the clock requested expirations are counted from.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 }
 
@@ -41,14 +62,17 @@ where
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         let hsm = self.hsm.clone();
         let cfg = self.config.clone();
+        let issued = self.issued.clone();
+        let now = self.clock.now();
         let max_duration = cfg.get_cert_max_duration(CertificateType::Client);
 
         let response = match params.name("name") {
             Some(module_id) => {
                 let cn = module_id.to_string();
+                let module_id = module_id.to_string();
                 let alias = format!("{}identity", module_id);
                 let module_uri =
-                    prepare_cert_uri_module(cfg.iot_hub_name(), cfg.device_id(), module_id);
+                    prepare_cert_uri_module(cfg.iot_hub_name(), cfg.device_id(), &module_id);
                 let result = req
                     .into_body()
                     .concat2()
@@ -59,7 +83,10 @@ where
                             .and_then(|cert_req| {
                                 cert_req.expiration().map_or_else(
                                     || Ok(max_duration),
-                                    |exp| compute_validity(exp, max_duration).map_err(Error::from),
+                                    |exp| {
+                                        compute_validity(exp, max_duration, now)
+                                            .map_err(Error::from)
+                                    },
                                 )
                             }).and_then(move |expiration| {
                                 let sans = vec![module_uri];
@@ -70,7 +97,10 @@ where
                                     CertificateType::Client,
                                     alias.clone(),
                                 ).with_san_entries(sans);
-                                refresh_cert(&hsm, alias, &props)
+                                refresh_cert(&hsm, alias, &props).map(|response| {
+                                    issued.insert(&module_id, props);
+                                    response
+                                })
                             }).unwrap_or_else(|e| e.into_response())
                     }).map_err(Error::from)
                     .or_else(|e| future::ok(e.into_response()));
@@ -86,6 +116,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
     use std::result::Result as StdResult;
     use std::sync::Arc;
 
@@ -94,7 +125,7 @@ mod tests {
 
     use edgelet_core::{
         CertificateProperties, CertificateType, CreateCertificate, Error as CoreError,
-        ErrorKind as CoreErrorKind, KeyBytes, PrivateKey, WorkloadConfig,
+        ErrorKind as CoreErrorKind, KeyBytes, PayloadLimits, PrivateKey, WorkloadConfig,
     };
     use edgelet_test_utils::cert::TestCert;
     use workload::models::{CertificateResponse, ErrorResponse, IdentityCertificateRequest};
@@ -141,6 +172,7 @@ mod tests {
         iot_hub_name: String,
         device_id: String,
         duration: i64,
+        allow_wildcard_sans: bool,
     }
 
     impl Default for TestWorkloadConfig {
@@ -155,6 +187,7 @@ mod tests {
                 iot_hub_name: String::from("zaphods_hub"),
                 device_id: String::from("marvins_device"),
                 duration: MAX_DURATION_SEC as i64,
+                allow_wildcard_sans: false,
             }
         }
     }
@@ -184,6 +217,34 @@ mod tests {
         fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
             self.data.duration
         }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            self.data.allow_wildcard_sans
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
     }
 
     fn test_module_uri(module_id: &str) -> String {
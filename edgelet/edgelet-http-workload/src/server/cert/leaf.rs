@@ -0,0 +1,373 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use failure::ResultExt;
+use futures::{future, Future, Stream};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use openssl::x509::X509Req;
+use serde_json;
+
+use edgelet_core::{CertificateType, Clock, SignCertificateRequest, SystemClock, WorkloadConfig};
+use edgelet_http::route::{Handler, Parameters};
+use workload::models::{LeafCertificateRequest, LeafCertificateResponse};
+
+use super::compute_validity;
+use error::{Error, ErrorKind, Result};
+use IntoResponse;
+
+/// Issues certificates for downstream leaf devices from their certificate
+/// signing requests, to the modules allowed to onboard them.
+pub struct LeafCertHandler<T: SignCertificateRequest, W: WorkloadConfig> {
+    hsm: T,
+    config: W,
+    clock: Arc<Clock>,
+}
+
+impl<T: SignCertificateRequest, W: WorkloadConfig> LeafCertHandler<T, W> {
+    pub fn new(hsm: T, config: W) -> Self {
+        LeafCertHandler {
+            hsm,
+            config,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock requested expirations are counted from.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<T, W> Handler<Parameters> for LeafCertHandler<T, W>
+where
+    T: SignCertificateRequest + Clone + Send + Sync + 'static,
+    W: WorkloadConfig + Clone + Send + Sync + 'static,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let module_id = match params.name("name") {
+            Some(module_id) => module_id,
+            None => return Box::new(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+        };
+        if !self
+            .config
+            .leaf_cert_issuers()
+            .iter()
+            .any(|issuer| issuer == module_id)
+        {
+            return Box::new(future::ok(
+                Error::from(ErrorKind::LeafCertIssuerNotAllowed).into_response(),
+            ));
+        }
+
+        let hsm = self.hsm.clone();
+        let now = self.clock.now();
+        let max_duration = self
+            .config
+            .get_cert_max_duration(CertificateType::Client);
+
+        let response = req
+            .into_body()
+            .concat2()
+            .map(move |body| {
+                serde_json::from_slice::<LeafCertificateRequest>(&body)
+                    .context(ErrorKind::BadBody)
+                    .map_err(Error::from)
+                    .and_then(|cert_req| issue(&hsm, &cert_req, max_duration, now))
+                    .unwrap_or_else(|e| e.into_response())
+            }).map_err(Error::from)
+            .or_else(|e| future::ok(e.into_response()));
+
+        Box::new(response)
+    }
+}
+
+fn issue<T: SignCertificateRequest>(
+    hsm: &T,
+    cert_req: &LeafCertificateRequest,
+    max_duration: i64,
+    now: DateTime<Utc>,
+) -> Result<Response<Body>> {
+    // Rejected here so that a bad request isn't reported as a signing failure.
+    let csr = X509Req::from_pem(cert_req.csr().as_bytes())
+        .context(ErrorKind::InvalidCertificateRequest)?;
+    let signed_by_key = csr
+        .public_key()
+        .and_then(|key| csr.verify(&key))
+        .context(ErrorKind::InvalidCertificateRequest)?;
+    if !signed_by_key {
+        return Err(Error::from(ErrorKind::InvalidCertificateRequest));
+    }
+
+    let requested = cert_req.expiration().map_or_else(
+        || Ok(max_duration),
+        |exp| compute_validity(exp, max_duration, now),
+    )?;
+    let requested = ensure_range!(requested, 0, max_duration);
+
+    // A certificate can't outlive the CA that issued it.
+    let ca_valid_to = hsm.issuer_valid_to()?;
+    let ca_remaining = ca_valid_to.signed_duration_since(now).num_seconds();
+    if ca_remaining <= 0 {
+        return Err(Error::from(ErrorKind::DeviceCaExpired));
+    }
+    let (validity, warning) = if requested > ca_remaining {
+        let warning = format!(
+            "The certificate expires at {} with the device CA, before the requested {}",
+            ca_valid_to.to_rfc3339(),
+            (now + Duration::seconds(requested)).to_rfc3339(),
+        );
+        (ca_remaining, Some(warning))
+    } else {
+        (requested, None)
+    };
+
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+    let certificate = hsm.sign_certificate_request(cert_req.csr().as_bytes(), validity as u64)?;
+    let chain = hsm.issuer_chain()?;
+    let expiration = (now + Duration::seconds(validity)).to_rfc3339();
+
+    let mut cert = LeafCertificateResponse::new(certificate, chain, expiration);
+    if let Some(warning) = warning {
+        cert.set_warning(warning);
+    }
+    let body = serde_json::to_string(&cert)?;
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(CONTENT_TYPE, "application/json")
+        .header(CONTENT_LENGTH, body.len().to_string().as_str())
+        .body(body.into())
+        .map_err(From::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::result::Result as StdResult;
+    use std::sync::Mutex;
+
+    use edgelet_core::{Error as CoreError, PayloadLimits};
+    use edgelet_test_utils::TestClock;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::{X509NameBuilder, X509ReqBuilder};
+    use workload::models::ErrorResponse;
+
+    use super::*;
+
+    const MAX_DURATION_SEC: i64 = 7200;
+
+    #[derive(Clone)]
+    struct TestHsm {
+        valid_to: DateTime<Utc>,
+        signed: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl TestHsm {
+        fn new(valid_to: DateTime<Utc>) -> Self {
+            TestHsm {
+                valid_to,
+                signed: Arc::new(Mutex::new(vec![])),
+            }
+        }
+    }
+
+    impl SignCertificateRequest for TestHsm {
+        fn issuer_chain(&self) -> StdResult<String, CoreError> {
+            Ok("device ca chain".to_string())
+        }
+
+        fn issuer_valid_to(&self) -> StdResult<DateTime<Utc>, CoreError> {
+            Ok(self.valid_to)
+        }
+
+        fn sign_certificate_request(
+            &self,
+            _request: &[u8],
+            validity_in_secs: u64,
+        ) -> StdResult<String, CoreError> {
+            self.signed.lock().unwrap().push(validity_in_secs);
+            Ok("leaf certificate".to_string())
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestWorkloadConfig {
+        leaf_cert_issuers: Vec<String>,
+    }
+
+    impl WorkloadConfig for TestWorkloadConfig {
+        fn iot_hub_name(&self) -> &str {
+            "zaphods_hub"
+        }
+
+        fn device_id(&self) -> &str {
+            "marvins_device"
+        }
+
+        fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
+            MAX_DURATION_SEC
+        }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            false
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &self.leaf_cert_issuers
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    fn csr() -> String {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "leaf1").unwrap();
+        let mut builder = X509ReqBuilder::new().unwrap();
+        builder.set_subject_name(&name.build()).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        String::from_utf8(builder.build().to_pem().unwrap()).unwrap()
+    }
+
+    fn handler(hsm: TestHsm, clock: &TestClock) -> LeafCertHandler<TestHsm, TestWorkloadConfig> {
+        let config = TestWorkloadConfig {
+            leaf_cert_issuers: vec!["edgeHub".to_string()],
+        };
+        LeafCertHandler::new(hsm, config).with_clock(Arc::new(clock.clone()))
+    }
+
+    fn post(
+        handler: &LeafCertHandler<TestHsm, TestWorkloadConfig>,
+        module: &str,
+        cert_req: &LeafCertificateRequest,
+    ) -> Response<Body> {
+        let uri = format!("http://localhost/modules/{}/genid/I/certificate/issue", module);
+        let request = Request::post(uri.as_str())
+            .body(serde_json::to_string(cert_req).unwrap().into())
+            .unwrap();
+        let params =
+            Parameters::with_captures(vec![(Some("name".to_string()), module.to_string())]);
+        handler.handle(request, params).wait().unwrap()
+    }
+
+    fn parse<T: ::serde::de::DeserializeOwned>(response: Response<Body>) -> T {
+        let body = response.into_body().concat2().wait().unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn allowed_module_gets_certificate_and_chain() {
+        let clock = TestClock::default();
+        let hsm = TestHsm::new(clock.now() + Duration::days(30));
+        let handler = handler(hsm.clone(), &clock);
+
+        let cert_req = LeafCertificateRequest::new(csr())
+            .with_expiration((clock.now() + Duration::hours(1)).to_rfc3339());
+        let response = post(&handler, "edgeHub", &cert_req);
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let cert: LeafCertificateResponse = parse(response);
+        assert_eq!("leaf certificate", *cert.certificate());
+        assert_eq!("device ca chain", *cert.chain());
+        assert_eq!(
+            (clock.now() + Duration::hours(1)).to_rfc3339(),
+            *cert.expiration()
+        );
+        assert_eq!(None, cert.warning());
+        assert_eq!(vec![3600], *hsm.signed.lock().unwrap());
+    }
+
+    #[test]
+    fn other_modules_are_denied() {
+        let clock = TestClock::default();
+        let hsm = TestHsm::new(clock.now() + Duration::days(30));
+        let handler = handler(hsm.clone(), &clock);
+
+        let response = post(&handler, "tempSensor", &LeafCertificateRequest::new(csr()));
+
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        assert!(hsm.signed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_csr_is_rejected() {
+        let clock = TestClock::default();
+        let hsm = TestHsm::new(clock.now() + Duration::days(30));
+        let handler = handler(hsm.clone(), &clock);
+
+        let cert_req = LeafCertificateRequest::new("not a csr".to_string());
+        let response = post(&handler, "edgeHub", &cert_req);
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error: ErrorResponse = parse(response);
+        assert!(
+            error
+                .message()
+                .starts_with("The certificate signing request is invalid")
+        );
+        assert!(hsm.signed.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn validity_is_clamped_to_the_device_ca() {
+        let clock = TestClock::default();
+        let hsm = TestHsm::new(clock.now() + Duration::minutes(30));
+        let handler = handler(hsm.clone(), &clock);
+
+        let cert_req = LeafCertificateRequest::new(csr())
+            .with_expiration((clock.now() + Duration::hours(1)).to_rfc3339());
+        let response = post(&handler, "edgeHub", &cert_req);
+
+        assert_eq!(StatusCode::CREATED, response.status());
+        let cert: LeafCertificateResponse = parse(response);
+        assert_eq!(
+            (clock.now() + Duration::minutes(30)).to_rfc3339(),
+            *cert.expiration()
+        );
+        assert!(cert.warning().is_some());
+        assert_eq!(vec![1800], *hsm.signed.lock().unwrap());
+    }
+
+    #[test]
+    fn expired_device_ca_issues_nothing() {
+        let clock = TestClock::default();
+        let hsm = TestHsm::new(clock.now() - Duration::minutes(1));
+        let handler = handler(hsm.clone(), &clock);
+
+        let response = post(&handler, "edgeHub", &LeafCertificateRequest::new(csr()));
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert!(hsm.signed.lock().unwrap().is_empty());
+    }
+}
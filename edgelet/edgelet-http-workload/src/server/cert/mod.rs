@@ -1,21 +1,159 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use chrono::{DateTime, Utc};
-use edgelet_core::{Certificate, CertificateProperties, CreateCertificate, KeyBytes, PrivateKey};
+use edgelet_core::{
+    Certificate, CertificateProperties, CertificateType, CreateCertificate, Error as CoreError,
+    KeyBytes, PrivateKey,
+};
 use error::{Error, ErrorKind, Result};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Response, StatusCode};
 use hyper::Body;
 use serde_json;
 use std::cmp;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::result::Result as StdResult;
+use std::sync::{Arc, Mutex};
 use workload::models::{CertificateResponse, PrivateKey as PrivateKeyResponse};
 
 mod identity;
+mod leaf;
+mod renew;
 mod server;
 
 pub use self::identity::IdentityCertHandler;
+pub use self::leaf::LeafCertHandler;
+pub use self::renew::RenewCertHandler;
 pub use self::server::ServerCertHandler;
 
+type Certs = HashMap<(String, CertificateType), CertificateProperties>;
+
+/// Remembers the properties of the last certificate of each type issued to a
+/// module so that it can be renewed with the same properties later on.
+///
+/// When opened on a file, the properties are kept in it as JSON, so that
+/// certificates issued before the daemon restarted can be renewed too. A file
+/// that can't be read is started over. Failing to write the file is logged
+/// but doesn't fail issuing the certificate.
+#[derive(Clone, Default)]
+pub struct IssuedCerts {
+    certs: Arc<Mutex<Certs>>,
+    path: Option<PathBuf>,
+}
+
+impl IssuedCerts {
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let certs = match fs::read(&path) {
+            Ok(contents) => {
+                match serde_json::from_slice::<Vec<(String, CertificateProperties)>>(&contents) {
+                    Ok(certs) => certs
+                        .into_iter()
+                        .map(|(module_id, props)| ((module_id, *props.certificate_type()), props))
+                        .collect(),
+                    Err(err) => {
+                        warn!(
+                            "Issued certificates in {} are corrupt and will be started over: {}",
+                            path.display(),
+                            err
+                        );
+                        HashMap::new()
+                    }
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                warn!(
+                    "Could not read issued certificates from {} and will start them over: {}",
+                    path.display(),
+                    err
+                );
+                HashMap::new()
+            }
+        };
+
+        IssuedCerts {
+            certs: Arc::new(Mutex::new(certs)),
+            path: Some(path),
+        }
+    }
+
+    pub fn insert(&self, module_id: &str, props: CertificateProperties) {
+        let mut certs = self
+            .certs
+            .lock()
+            .expect("issued certificates lock poisoned");
+        certs.insert((module_id.to_string(), *props.certificate_type()), props);
+        if let Some(ref path) = self.path {
+            save(path, &certs);
+        }
+    }
+
+    pub fn get(&self, module_id: &str, cert_type: CertificateType) -> Option<CertificateProperties> {
+        self.certs
+            .lock()
+            .expect("issued certificates lock poisoned")
+            .get(&(module_id.to_string(), cert_type))
+            .cloned()
+    }
+
+    /// Issues every remembered certificate again with the same properties,
+    /// so that they chain up to a CA that was replaced since. Returns how many
+    /// were issued, or the first error once all of them were tried.
+    pub fn reissue<T: CreateCertificate>(&self, hsm: &T) -> StdResult<usize, CoreError> {
+        let certs: Vec<CertificateProperties> = self
+            .certs
+            .lock()
+            .expect("issued certificates lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+
+        let mut reissued = 0;
+        let mut first_error = None;
+        for props in certs {
+            let result = hsm
+                .destroy_certificate(props.alias().to_string())
+                .and_then(|_| hsm.create_certificate(&props));
+            match result {
+                Ok(_) => reissued += 1,
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(reissued),
+        }
+    }
+}
+
+fn save(path: &Path, certs: &Certs) {
+    // Write a temporary file and move it over the old one, so that the file
+    // is never left half written.
+    let certs: Vec<(&str, &CertificateProperties)> = certs
+        .iter()
+        .map(|(key, props)| (key.0.as_str(), props))
+        .collect();
+    let tmp = path.with_extension("tmp");
+    let result = serde_json::to_vec(&certs)
+        .map_err(io::Error::from)
+        .and_then(|contents| fs::write(&tmp, contents))
+        .and_then(|()| fs::rename(&tmp, path));
+    if let Err(err) = result {
+        warn!(
+            "Could not save issued certificates to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
 fn cert_to_response<T: Certificate>(cert: &T) -> Result<CertificateResponse> {
     let cert_buffer = cert.pem()?;
     let expiration = cert.get_valid_to()?;
@@ -34,13 +172,13 @@ fn cert_to_response<T: Certificate>(cert: &T) -> Result<CertificateResponse> {
     ))
 }
 
-fn compute_validity(expiration: &str, max_duration_sec: i64) -> Result<i64> {
+fn compute_validity(expiration: &str, max_duration_sec: i64, now: DateTime<Utc>) -> Result<i64> {
     ensure_not_empty!(expiration);
     DateTime::parse_from_rfc3339(expiration)
         .map(|expiration| {
             let secs = expiration
                 .with_timezone(&Utc)
-                .signed_duration_since(Utc::now())
+                .signed_duration_since(now)
                 .num_seconds();
             cmp::min(secs, max_duration_sec)
         }).map_err(Error::from)
@@ -66,3 +204,134 @@ fn refresh_cert<T: CreateCertificate>(
                 .map_err(From::from)
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use edgelet_core::ErrorKind as CoreErrorKind;
+    use edgelet_test_utils::cert::TestCert;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct RecordingHsm {
+        calls: Mutex<Vec<String>>,
+        failing: Option<&'static str>,
+    }
+
+    impl CreateCertificate for RecordingHsm {
+        type Certificate = TestCert;
+
+        fn create_certificate(
+            &self,
+            properties: &CertificateProperties,
+        ) -> StdResult<Self::Certificate, CoreError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("create {}", properties.alias()));
+            if self.failing == Some(properties.alias()) {
+                Err(CoreError::from(CoreErrorKind::KeyStore))
+            } else {
+                Ok(TestCert::default())
+            }
+        }
+
+        fn destroy_certificate(&self, alias: String) -> StdResult<(), CoreError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("destroy {}", alias));
+            Ok(())
+        }
+    }
+
+    fn issued() -> IssuedCerts {
+        let issued = IssuedCerts::default();
+        issued.insert(
+            "m1",
+            CertificateProperties::new(
+                3600,
+                "m1".to_string(),
+                CertificateType::Server,
+                "m1g1server".to_string(),
+            ),
+        );
+        issued.insert(
+            "m2",
+            CertificateProperties::new(
+                3600,
+                "m2".to_string(),
+                CertificateType::Client,
+                "m2identity".to_string(),
+            ),
+        );
+        issued
+    }
+
+    #[test]
+    fn reissue_issues_every_certificate_again() {
+        let hsm = RecordingHsm::default();
+        assert_eq!(2, issued().reissue(&hsm).unwrap());
+
+        let mut calls = hsm.calls.lock().unwrap().clone();
+        calls.sort();
+        assert_eq!(
+            vec![
+                "create m1g1server",
+                "create m2identity",
+                "destroy m1g1server",
+                "destroy m2identity",
+            ],
+            calls
+        );
+    }
+
+    #[test]
+    fn reissue_tries_every_certificate_before_failing() {
+        let hsm = RecordingHsm {
+            failing: Some("m1g1server"),
+            ..RecordingHsm::default()
+        };
+        assert!(issued().reissue(&hsm).is_err());
+        assert_eq!(4, hsm.calls.lock().unwrap().len());
+    }
+
+    #[test]
+    fn issued_certs_are_kept_across_restarts() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("issued_certs.json");
+
+        let issued = IssuedCerts::open(&path);
+        issued.insert(
+            "m1",
+            CertificateProperties::new(
+                3600,
+                "m1".to_string(),
+                CertificateType::Server,
+                "m1g1server".to_string(),
+            ).with_san_entries(vec!["DNS:m1".to_string()]),
+        );
+
+        let props = IssuedCerts::open(&path)
+            .get("m1", CertificateType::Server)
+            .unwrap();
+        assert_eq!("m1g1server", props.alias());
+        assert_eq!(Some(&["DNS:m1".to_string()][..]), props.san_entries());
+        assert!(
+            IssuedCerts::open(&path)
+                .get("m1", CertificateType::Client)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn corrupt_issued_certs_are_started_over() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("issued_certs.json");
+        fs::write(&path, "not json").unwrap();
+
+        let issued = IssuedCerts::open(&path);
+        assert!(issued.get("m1", CertificateType::Server).is_none());
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use super::{refresh_cert, IssuedCerts};
+use failure::ResultExt;
+use futures::{future, Future, Stream};
+use http::{Request, Response};
+use hyper::{Body, Error as HyperError};
+use serde_json;
+
+use edgelet_core::{Certificate, CertificateType, CreateCertificate};
+use edgelet_http::route::{Handler, Parameters};
+use workload::models::RenewCertificateRequest;
+
+use error::{Error, ErrorKind, Result};
+use IntoResponse;
+
+pub struct RenewCertHandler<T: CreateCertificate> {
+    hsm: T,
+    issued: IssuedCerts,
+}
+
+impl<T: CreateCertificate> RenewCertHandler<T> {
+    pub fn new(hsm: T, issued: IssuedCerts) -> Self {
+        RenewCertHandler { hsm, issued }
+    }
+}
+
+impl<T> Handler<Parameters> for RenewCertHandler<T>
+where
+    T: CreateCertificate + Clone + Send + Sync + 'static,
+    <T as CreateCertificate>::Certificate: Certificate,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let hsm = self.hsm.clone();
+        let issued = self.issued.clone();
+
+        let response = match params.name("name") {
+            Some(module_id) => {
+                let module_id = module_id.to_string();
+                let result = req
+                    .into_body()
+                    .concat2()
+                    .map(move |body| {
+                        serde_json::from_slice::<RenewCertificateRequest>(&body)
+                            .context(ErrorKind::BadBody)
+                            .map_err(Error::from)
+                            .and_then(|renew_req| certificate_type(renew_req.certificate_type()))
+                            .and_then(|cert_type| {
+                                issued
+                                    .get(&module_id, cert_type)
+                                    .ok_or_else(|| Error::from(ErrorKind::CertificateNotFound))
+                            }).and_then(|props| {
+                                // the HSM hands back an existing certificate for a known
+                                // alias, so the old one has to be destroyed to force reissue
+                                refresh_cert(&hsm, props.alias().to_string(), &props)
+                            }).unwrap_or_else(|e| e.into_response())
+                    }).map_err(Error::from)
+                    .or_else(|e| future::ok(e.into_response()));
+
+                future::Either::A(result)
+            }
+
+            None => future::Either::B(future::ok(Error::from(ErrorKind::BadParam).into_response())),
+        };
+
+        Box::new(response)
+    }
+}
+
+fn certificate_type(cert_type: &str) -> Result<CertificateType> {
+    match cert_type {
+        "identity" => Ok(CertificateType::Client),
+        "server" => Ok(CertificateType::Server),
+        _ => Err(Error::from(ErrorKind::BadParam)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::result::Result as StdResult;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{Duration, Utc};
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::{X509, X509NameBuilder};
+
+    use super::*;
+    use edgelet_core::{
+        CertificateProperties, Error as CoreError, PayloadLimits, PrivateKey, WorkloadConfig,
+    };
+    use edgelet_test_utils::cert::TestCert;
+    use http::StatusCode;
+    use server::cert::ServerCertHandler;
+    use workload::models::{CertificateResponse, ErrorResponse, ServerCertificateRequest};
+
+    #[derive(Clone, Default)]
+    struct TestHsm {
+        serial: Arc<AtomicUsize>,
+        destroyed: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl CreateCertificate for TestHsm {
+        type Certificate = TestCert;
+
+        fn create_certificate(
+            &self,
+            properties: &CertificateProperties,
+        ) -> StdResult<Self::Certificate, CoreError> {
+            let serial = self.serial.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(issue_cert(properties, serial as u32))
+        }
+
+        fn destroy_certificate(&self, alias: String) -> StdResult<(), CoreError> {
+            self.destroyed.lock().unwrap().push(alias);
+            Ok(())
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestWorkloadConfig;
+
+    impl WorkloadConfig for TestWorkloadConfig {
+        fn iot_hub_name(&self) -> &str {
+            "zaphods_hub"
+        }
+
+        fn device_id(&self) -> &str {
+            "marvins_device"
+        }
+
+        fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
+            7200
+        }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            false
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    fn issue_cert(props: &CertificateProperties, serial: u32) -> TestCert {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, props.common_name())
+            .unwrap();
+        let name = name.build();
+
+        let serial = BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        TestCert::default()
+            .with_cert(builder.build().to_pem().unwrap())
+            .with_private_key(PrivateKey::Ref("Betelgeuse".to_string()))
+    }
+
+    fn parse_body<T>(response: Response<Body>) -> T
+    where
+        T: ::serde::de::DeserializeOwned,
+    {
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| Ok(serde_json::from_slice::<T>(&b).unwrap()))
+            .wait()
+            .unwrap()
+    }
+
+    fn serial(response: CertificateResponse) -> Vec<u8> {
+        X509::from_pem(response.certificate().as_bytes())
+            .unwrap()
+            .serial_number()
+            .to_bn()
+            .unwrap()
+            .to_vec()
+    }
+
+    fn issue_server_cert(hsm: &TestHsm, issued: &IssuedCerts) -> CertificateResponse {
+        let handler = ServerCertHandler::new(hsm.clone(), TestWorkloadConfig)
+            .with_issued_certs(issued.clone());
+        let cert_req = ServerCertificateRequest::new(
+            "marvin".to_string(),
+            (Utc::now() + Duration::hours(1)).to_rfc3339(),
+        );
+        let request =
+            Request::post("http://localhost/modules/beeblebrox/genid/I/certificate/server")
+                .body(serde_json::to_string(&cert_req).unwrap().into())
+                .unwrap();
+        let params = Parameters::with_captures(vec![
+            (Some("name".to_string()), "beeblebrox".to_string()),
+            (Some("genid".to_string()), "I".to_string()),
+        ]);
+
+        let response = handler.handle(request, params).wait().unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        parse_body(response)
+    }
+
+    fn renew(hsm: &TestHsm, issued: &IssuedCerts, cert_type: &str) -> Response<Body> {
+        let handler = RenewCertHandler::new(hsm.clone(), issued.clone());
+        let renew_req = RenewCertificateRequest::new(cert_type.to_string());
+        let request = Request::post("http://localhost/modules/beeblebrox/genid/I/certificate/renew")
+            .body(serde_json::to_string(&renew_req).unwrap().into())
+            .unwrap();
+        let params =
+            Parameters::with_captures(vec![(Some("name".to_string()), "beeblebrox".to_string())]);
+
+        handler.handle(request, params).wait().unwrap()
+    }
+
+    #[test]
+    fn missing_name() {
+        let handler = RenewCertHandler::new(TestHsm::default(), IssuedCerts::default());
+        let request = Request::post("http://localhost/modules//genid/I/certificate/renew")
+            .body("".into())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[test]
+    fn bad_body() {
+        let handler = RenewCertHandler::new(TestHsm::default(), IssuedCerts::default());
+        let request = Request::post("http://localhost/modules/beeblebrox/genid/I/certificate/renew")
+            .body("invalid".into())
+            .unwrap();
+        let params =
+            Parameters::with_captures(vec![(Some("name".to_string()), "beeblebrox".to_string())]);
+        let response = handler.handle(request, params).wait().unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[test]
+    fn unknown_certificate_type() {
+        let response = renew(&TestHsm::default(), &IssuedCerts::default(), "client");
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "Bad parameter",
+            parse_body::<ErrorResponse>(response).message()
+        );
+    }
+
+    #[test]
+    fn not_issued_yet() {
+        let response = renew(&TestHsm::default(), &IssuedCerts::default(), "server");
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(
+            "Certificate not found",
+            parse_body::<ErrorResponse>(response).message()
+        );
+    }
+
+    #[test]
+    fn renewal_reissues_certificate() {
+        let hsm = TestHsm::default();
+        let issued = IssuedCerts::default();
+
+        let original = issue_server_cert(&hsm, &issued);
+        assert!(!original.expiration().is_empty());
+        let original_serial = serial(original);
+
+        let response = renew(&hsm, &issued, "server");
+        assert_eq!(StatusCode::CREATED, response.status());
+        let renewed = parse_body::<CertificateResponse>(response);
+        assert!(!renewed.expiration().is_empty());
+        assert_ne!(original_serial, serial(renewed));
+
+        // issuing and renewing both have to destroy the cached certificate
+        let destroyed = hsm.destroyed.lock().unwrap();
+        assert_eq!(2, destroyed.len());
+        assert_eq!(destroyed[0], destroyed[1]);
+    }
+}
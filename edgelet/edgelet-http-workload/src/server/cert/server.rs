@@ -1,6 +1,9 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use super::{compute_validity, refresh_cert};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use super::{compute_validity, refresh_cert, IssuedCerts};
 use failure::ResultExt;
 use futures::{future, Future, Stream};
 use http::{Request, Response};
@@ -8,22 +11,44 @@ use hyper::{Body, Error as HyperError};
 use serde_json;
 
 use edgelet_core::{
-    Certificate, CertificateProperties, CertificateType, CreateCertificate, WorkloadConfig,
+    Certificate, CertificateProperties, CertificateType, Clock, CreateCertificate, SystemClock,
+    WorkloadConfig,
 };
 use edgelet_http::route::{Handler, Parameters};
 use workload::models::ServerCertificateRequest;
 
-use error::{Error, ErrorKind};
+use error::{Error, ErrorKind, Result};
 use IntoResponse;
 
+const MAX_DNS_NAME_LEN: usize = 253;
+const MAX_DNS_LABEL_LEN: usize = 63;
+
 pub struct ServerCertHandler<T: CreateCertificate, W: WorkloadConfig> {
     hsm: T,
     config: W,
+    issued: IssuedCerts,
+    clock: Arc<Clock>,
 }
 
 impl<T: CreateCertificate, W: WorkloadConfig> ServerCertHandler<T, W> {
     pub fn new(hsm: T, config: W) -> Self {
-        ServerCertHandler { hsm, config }
+        ServerCertHandler {
+            hsm,
+            config,
+            issued: IssuedCerts::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_issued_certs(mut self, issued: IssuedCerts) -> Self {
+        self.issued = issued;
+        self
+    }
+
+    /// Sets the clock requested expirations are counted from.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 }
 impl<T, W> Handler<Parameters> for ServerCertHandler<T, W>
@@ -39,11 +64,15 @@ where
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         let hsm = self.hsm.clone();
         let cfg = self.config.clone();
+        let issued = self.issued.clone();
+        let now = self.clock.now();
         let max_duration = cfg.get_cert_max_duration(CertificateType::Server);
+        let allow_wildcard = cfg.allow_wildcard_sans();
 
         let response = match (params.name("name"), params.name("genid")) {
             (Some(module_id), Some(genid)) => {
                 let alias = format!("{}{}server", module_id.to_string(), genid.to_string());
+                let module_id = module_id.to_string();
                 let result = req
                     .into_body()
                     .concat2()
@@ -55,16 +84,28 @@ where
                                 compute_validity(
                                     ensure_not_empty!(cert_req.expiration()).as_str(),
                                     max_duration,
+                                    now,
                                 ).map(|expiration| (cert_req, expiration))
                             }).and_then(move |(cert_req, expiration)| {
                                 #[cfg_attr(feature = "cargo-clippy", allow(cast_sign_loss))]
+                                let validity = ensure_range!(expiration, 0, max_duration) as u64;
+                                let common_name =
+                                    ensure_not_empty!(cert_req.common_name().to_string());
+                                let sans = san_entries(
+                                    &common_name,
+                                    cert_req.sans().unwrap_or(&[]),
+                                    allow_wildcard,
+                                )?;
                                 let props = CertificateProperties::new(
-                                    ensure_range!(expiration, 0, max_duration) as u64,
-                                    ensure_not_empty!(cert_req.common_name().to_string()),
+                                    validity,
+                                    common_name,
                                     CertificateType::Server,
                                     alias.clone(),
-                                );
-                                refresh_cert(&hsm, alias, &props)
+                                ).with_san_entries(sans);
+                                refresh_cert(&hsm, alias, &props).map(|response| {
+                                    issued.insert(&module_id, props);
+                                    response
+                                })
                             }).unwrap_or_else(|e| e.into_response())
                     }).map_err(Error::from)
                     .or_else(|e| future::ok(e.into_response()));
@@ -81,8 +122,57 @@ where
     }
 }
 
+/// Builds the SAN extension entries for a server certificate. The common
+/// name is included first (when it is a valid DNS name or IP address) since
+/// modern TLS clients ignore the CN when validating a host name.
+fn san_entries(common_name: &str, sans: &[String], allow_wildcard: bool) -> Result<Vec<String>> {
+    let mut entries = vec![];
+    if let Ok(entry) = normalize_san(common_name, allow_wildcard) {
+        entries.push(entry);
+    }
+
+    for san in sans {
+        let entry = normalize_san(san, allow_wildcard)?;
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+fn normalize_san(san: &str, allow_wildcard: bool) -> Result<String> {
+    let san = san.trim();
+    if let Ok(ip) = san.parse::<IpAddr>() {
+        return Ok(format!("IP:{}", ip));
+    }
+
+    let name = san.trim_right_matches('.').to_lowercase();
+    if name.is_empty() || name.len() > MAX_DNS_NAME_LEN {
+        return Err(Error::from(ErrorKind::InvalidSan));
+    }
+
+    for (i, label) in name.split('.').enumerate() {
+        if label == "*" && i == 0 && name.len() > 1 {
+            if !allow_wildcard {
+                return Err(Error::from(ErrorKind::WildcardSanNotAllowed));
+            }
+        } else if label.is_empty()
+            || label.len() > MAX_DNS_LABEL_LEN
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(Error::from(ErrorKind::InvalidSan));
+        }
+    }
+
+    Ok(format!("DNS:{}", name))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
     use std::result::Result as StdResult;
     use std::sync::Arc;
 
@@ -92,10 +182,18 @@ mod tests {
     use super::*;
     use edgelet_core::{
         CertificateProperties, CertificateType, CreateCertificate, Error as CoreError,
-        ErrorKind as CoreErrorKind, KeyBytes, PrivateKey, WorkloadConfig,
+        ErrorKind as CoreErrorKind, KeyBytes, PayloadLimits, PrivateKey, WorkloadConfig,
     };
     use edgelet_test_utils::cert::TestCert;
+    use edgelet_test_utils::TestClock;
     use http::StatusCode;
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509, X509NameBuilder};
     use workload::models::{CertificateResponse, ErrorResponse, ServerCertificateRequest};
 
     const MAX_DURATION_SEC: u64 = 7200;
@@ -137,6 +235,7 @@ mod tests {
         iot_hub_name: String,
         device_id: String,
         duration: i64,
+        allow_wildcard_sans: bool,
     }
 
     impl Default for TestWorkloadConfig {
@@ -151,6 +250,7 @@ mod tests {
                 iot_hub_name: String::from("zaphods_hub"),
                 device_id: String::from("marvins_device"),
                 duration: MAX_DURATION_SEC as i64,
+                allow_wildcard_sans: false,
             }
         }
     }
@@ -180,6 +280,34 @@ mod tests {
         fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
             self.data.duration
         }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            self.data.allow_wildcard_sans
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
     }
 
     fn parse_error_response(response: Response<Body>) -> ErrorResponse {
@@ -610,6 +738,36 @@ mod tests {
         assert_eq!(Some("Betelgeuse"), cert_resp.private_key().ref_());
     }
 
+    #[test]
+    fn validity_is_counted_from_the_clock() {
+        let clock = TestClock::default();
+        let handler = ServerCertHandler::new(
+            TestHsm::default().with_on_create(|props| {
+                assert_eq!(3600, *props.validity_in_secs());
+                Ok(TestCert::default().with_private_key(PrivateKey::Ref("Betelgeuse".to_string())))
+            }),
+            TestWorkloadData::default(),
+        ).with_clock(Arc::new(clock.clone()));
+
+        let cert_req = ServerCertificateRequest::new(
+            "marvin".to_string(),
+            (clock.now() + Duration::hours(1)).to_rfc3339(),
+        );
+
+        let request =
+            Request::get("http://localhost/modules/beeblebrox/genid/I/certificate/server")
+                .body(serde_json::to_string(&cert_req).unwrap().into())
+                .unwrap();
+
+        let params = Parameters::with_captures(vec![
+            (Some("name".to_string()), "beeblebrox".to_string()),
+            (Some("genid".to_string()), "I".to_string()),
+        ]);
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::CREATED, response.status());
+    }
+
     #[test]
     fn long_expiration_capped_to_max_duration_ok() {
         let handler = ServerCertHandler::new(
@@ -689,4 +847,194 @@ mod tests {
                 .is_some()
         );
     }
+
+    fn issue_cert(props: &CertificateProperties) -> TestCert {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, props.common_name())
+            .unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+
+        let mut san = SubjectAlternativeName::new();
+        for entry in props.san_entries().unwrap() {
+            if entry.starts_with("DNS:") {
+                san.dns(&entry[4..]);
+            } else if entry.starts_with("IP:") {
+                san.ip(&entry[3..]);
+            } else {
+                panic!("unexpected SAN entry {}", entry);
+            }
+        }
+        let san = san.build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        TestCert::default()
+            .with_cert(builder.build().to_pem().unwrap())
+            .with_private_key(PrivateKey::Ref("Betelgeuse".to_string()))
+    }
+
+    fn request_sans(
+        common_name: &str,
+        sans: Vec<&str>,
+        config: TestWorkloadData,
+    ) -> Response<Body> {
+        let handler = ServerCertHandler::new(
+            TestHsm::default().with_on_create(|props| Ok(issue_cert(props))),
+            config,
+        );
+
+        let cert_req = ServerCertificateRequest::new(
+            common_name.to_string(),
+            (Utc::now() + Duration::hours(1)).to_rfc3339(),
+        ).with_sans(sans.into_iter().map(ToString::to_string).collect());
+
+        let request =
+            Request::get("http://localhost/modules/beeblebrox/genid/I/certificate/server")
+                .body(serde_json::to_string(&cert_req).unwrap().into())
+                .unwrap();
+
+        let params = Parameters::with_captures(vec![
+            (Some("name".to_string()), "beeblebrox".to_string()),
+            (Some("genid".to_string()), "I".to_string()),
+        ]);
+        handler.handle(request, params).wait().unwrap()
+    }
+
+    fn issued_sans(response: Response<Body>) -> (Vec<String>, Vec<Vec<u8>>) {
+        let cert_resp = response
+            .into_body()
+            .concat2()
+            .and_then(|b| Ok(serde_json::from_slice::<CertificateResponse>(&b).unwrap()))
+            .wait()
+            .unwrap();
+        let cert = X509::from_pem(cert_resp.certificate().as_bytes()).unwrap();
+        let names = cert.subject_alt_names().unwrap();
+        let dns = names
+            .iter()
+            .filter_map(|n| n.dnsname().map(ToString::to_string))
+            .collect();
+        let ips = names
+            .iter()
+            .filter_map(|n| n.ipaddress().map(|ip| ip.to_vec()))
+            .collect();
+        (dns, ips)
+    }
+
+    #[test]
+    fn succeeds_dns_sans() {
+        let response = request_sans(
+            "marvin",
+            vec!["edgehub.contoso.com", "LOCALHOST", "marvin"],
+            TestWorkloadData::default(),
+        );
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let (dns, ips) = issued_sans(response);
+        assert_eq!(vec!["marvin", "edgehub.contoso.com", "localhost"], dns);
+        assert!(ips.is_empty());
+    }
+
+    #[test]
+    fn succeeds_ip_sans() {
+        let response = request_sans(
+            "10.0.0.1",
+            vec!["127.0.0.1", "::1"],
+            TestWorkloadData::default(),
+        );
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let (dns, ips) = issued_sans(response);
+        assert!(dns.is_empty());
+        assert_eq!(
+            vec![
+                vec![10, 0, 0, 1],
+                vec![127, 0, 0, 1],
+                vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            ],
+            ips
+        );
+    }
+
+    #[test]
+    fn succeeds_mixed_sans() {
+        let response = request_sans(
+            "edgehub",
+            vec![" localhost. ", "192.168.1.10"],
+            TestWorkloadData::default(),
+        );
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let (dns, ips) = issued_sans(response);
+        assert_eq!(vec!["edgehub", "localhost"], dns);
+        assert_eq!(vec![vec![192, 168, 1, 10]], ips);
+    }
+
+    #[test]
+    fn invalid_san() {
+        let response = request_sans(
+            "marvin",
+            vec!["not a host name"],
+            TestWorkloadData::default(),
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "Invalid subject alternative name",
+            parse_error_response(response).message()
+        );
+    }
+
+    #[test]
+    fn wildcard_san_rejected() {
+        let response = request_sans("marvin", vec!["*.contoso.com"], TestWorkloadData::default());
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "Wildcard subject alternative names are not allowed",
+            parse_error_response(response).message()
+        );
+    }
+
+    #[test]
+    fn wildcard_san_allowed_by_config() {
+        let config = TestWorkloadData {
+            data: Arc::new(TestWorkloadConfig {
+                allow_wildcard_sans: true,
+                ..TestWorkloadConfig::default()
+            }),
+        };
+        let response = request_sans("marvin", vec!["*.contoso.com"], config);
+        assert_eq!(StatusCode::CREATED, response.status());
+
+        let (dns, _) = issued_sans(response);
+        assert_eq!(vec!["marvin", "*.contoso.com"], dns);
+    }
+
+    #[test]
+    fn normalize_san_rejects_malformed_names() {
+        for san in &[
+            "",
+            "*",
+            "-edge",
+            "edge-",
+            "foo..bar",
+            "foo.*.bar",
+            "foo_bar",
+        ] {
+            assert!(normalize_san(san, true).is_err(), "{} was accepted", san);
+        }
+    }
 }
@@ -1,11 +1,11 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use base64;
-use edgelet_core::Decrypt;
+use edgelet_core::{Decrypt, PayloadLimits};
 use edgelet_http::route::{Handler, Parameters};
 use error::{Error, ErrorKind};
 use failure::ResultExt;
-use futures::{future, Future, Stream};
+use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
@@ -13,13 +13,26 @@ use serde_json;
 use workload::models::{DecryptRequest, DecryptResponse};
 use IntoResponse;
 
+use super::payload::{
+    client_id, decode_field, ensure_field_size, max_request_size, read_body, CIPHERTEXT_OVERHEAD,
+};
+
 pub struct DecryptHandler<T: Decrypt> {
     hsm: T,
+    limits: PayloadLimits,
 }
 
 impl<T: Decrypt> DecryptHandler<T> {
     pub fn new(hsm: T) -> Self {
-        DecryptHandler { hsm }
+        DecryptHandler {
+            hsm,
+            limits: PayloadLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: PayloadLimits) -> Self {
+        self.limits = limits;
+        self
     }
 }
 
@@ -33,6 +46,7 @@ where
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         let hsm = self.hsm.clone();
+        let limits = self.limits;
         let response = match params
             .name("name")
             .ok_or_else(|| Error::from(ErrorKind::BadParam))
@@ -43,31 +57,55 @@ where
                     .map(|genid| (name, genid))
             }) {
             Ok((module_id, genid)) => {
-                let id = format!("{}{}", module_id.to_string(), genid.to_string());
-                let ok = req.into_body().concat2().map(move |b| {
-                    serde_json::from_slice::<DecryptRequest>(&b)
-                        .context(ErrorKind::BadBody)
-                        .map_err(Error::from)
-                        .and_then(|request| {
-                            let ciphertext = base64::decode(request.ciphertext())?;
-                            let initialization_vector =
-                                base64::decode(request.initialization_vector())?;
-                            hsm.decrypt(id.as_bytes(), &ciphertext, &initialization_vector)
-                                .map_err(Error::from)
-                        }).and_then(|plaintext| {
-                            let encoded = base64::encode(&plaintext);
-                            let response = DecryptResponse::new(encoded);
-                            let body = serde_json::to_string(&response)
-                                .expect("Generated an invalid DecryptResponse object");
-
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header(CONTENT_TYPE, "application/json")
-                                .header(CONTENT_LENGTH, body.len().to_string().as_str())
-                                .body(body.into())
-                                .expect("Generated an invalid http::Response object"))
-                        }).unwrap_or_else(|e| e.into_response())
-                });
+                let module_id = module_id.to_string();
+                let genid = genid.to_string();
+                let ok = read_body(req.into_body(), max_request_size(&limits))
+                    .and_then(move |b| {
+                        let request = serde_json::from_slice::<DecryptRequest>(&b)
+                            .context(ErrorKind::BadBody)?;
+                        let ciphertext = decode_field("ciphertext", request.ciphertext())?;
+                        ensure_field_size(
+                            "ciphertext",
+                            &ciphertext,
+                            limits.max_payload_size() + CIPHERTEXT_OVERHEAD,
+                        )?;
+                        let initialization_vector =
+                            decode_field("initializationVector", request.initialization_vector())?;
+                        let associated_data = match request.associated_data() {
+                            Some(associated_data) => {
+                                let associated_data =
+                                    decode_field("associatedData", associated_data)?;
+                                ensure_field_size(
+                                    "associatedData",
+                                    &associated_data,
+                                    limits.max_associated_data_size(),
+                                )?;
+                                Some(associated_data)
+                            }
+                            None => None,
+                        };
+
+                        let id = client_id(
+                            &module_id,
+                            &genid,
+                            associated_data.as_ref().map(AsRef::as_ref),
+                        );
+                        let plaintext = hsm
+                            .decrypt(&id, &ciphertext, &initialization_vector)
+                            .map_err(Error::from)?;
+
+                        let encoded = base64::encode(&plaintext);
+                        let response = DecryptResponse::new(encoded);
+                        let body = serde_json::to_string(&response)
+                            .expect("Generated an invalid DecryptResponse object");
+
+                        Ok(Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, "application/json")
+                            .header(CONTENT_LENGTH, body.len().to_string().as_str())
+                            .body(body.into())
+                            .expect("Generated an invalid http::Response object"))
+                    }).or_else(|e| future::ok(e.into_response()));
                 future::Either::A(ok)
             }
             Err(e) => future::Either::B(future::ok(e.into_response())),
@@ -78,15 +116,16 @@ where
 
 #[cfg(test)]
 mod tests {
-    use edgelet_core::Decrypt;
-    use edgelet_core::Error as CoreError;
+    use edgelet_core::{
+        Decrypt, Encrypt, Envelope, Error as CoreError, ErrorKind as CoreErrorKind, MakeRandom,
+    };
     use edgelet_http::route::Parameters;
-    use futures::Future;
+    use futures::{Future, Stream};
     use http::{Request, StatusCode};
-    use workload::models::DecryptResponse;
-    use workload::models::ErrorResponse;
+    use workload::models::{DecryptResponse, EncryptRequest, EncryptResponse, ErrorResponse};
 
     use super::*;
+    use server::encrypt::EncryptHandler;
 
     #[derive(Clone, Debug, Default)]
     struct TestHsm {}
@@ -272,22 +311,229 @@ mod tests {
     }
 
     #[test]
-    fn handler_responds_with_unprocessable_entity_when_request_args_are_not_base64_encoded() {
+    fn handler_responds_with_bad_request_when_request_args_are_not_base64_encoded() {
         let bodies = &[
-            request_with_unencoded_ciphertext(),
-            request_with_unencoded_init_vector(),
+            (
+                request_with_unencoded_ciphertext(),
+                "Invalid base64 encoding for ciphertext\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
+            (
+                request_with_unencoded_init_vector(),
+                "Invalid base64 encoding for initializationVector\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
+            (
+                request_ok().with_associated_data(raw_text!()),
+                "Invalid base64 encoding for associatedData\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
         ];
         let handler = DecryptHandler::new(TestHsm::default());
 
-        for body in bodies {
+        for (body, message) in bodies {
             let (request, params) = create_args(Some(body), params_ok!());
             let response = handler.handle(request, params).wait().unwrap();
 
-            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
-            assert_response_message_eq(
-                "Invalid base64 string\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
-                response,
-            );
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+            assert_response_message_eq(*message, response);
+        }
+    }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_ciphertext_exceeds_limit() {
+        let body = DecryptRequest::new(
+            base64::encode(&vec![0_u8; CIPHERTEXT_OVERHEAD + 1]),
+            b64_text!(),
+        );
+        let (request, params) = create_args(Some(&body), params_ok!());
+        let handler = DecryptHandler::new(TestHsm::default())
+            .with_limits(PayloadLimits::default().with_max_payload_size(0));
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "The ciphertext exceeds the maximum size of 64 bytes",
+            response,
+        );
+    }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_associated_data_exceeds_limit() {
+        let body = request_ok().with_associated_data(b64_text!());
+        let (request, params) = create_args(Some(&body), params_ok!());
+        let handler = DecryptHandler::new(TestHsm::default()).with_limits(
+            PayloadLimits::default().with_max_associated_data_size(RAW_TEXT.len() - 1),
+        );
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "The associatedData exceeds the maximum size of 4 bytes",
+            response,
+        );
+    }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_body_exceeds_limit() {
+        let body = DecryptRequest::new(base64::encode(&vec![0_u8; 8 * 1024]), b64_text!());
+        let (request, params) = create_args(Some(&body), params_ok!());
+        let handler = DecryptHandler::new(TestHsm::default()).with_limits(
+            PayloadLimits::default()
+                .with_max_payload_size(3)
+                .with_max_associated_data_size(3),
+        );
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "Request body exceeds the maximum size of 4104 bytes",
+            response,
+        );
+    }
+
+    /// Stand-in for the HSM that authenticates the ciphertext against the
+    /// client id the same way AES-GCM authenticates its associated data.
+    #[derive(Clone, Debug, Default)]
+    struct TestAuthenticatingHsm {}
+
+    impl Encrypt for TestAuthenticatingHsm {
+        type Buffer = Vec<u8>;
+
+        fn encrypt(
+            &self,
+            client_id: &[u8],
+            plaintext: &[u8],
+            _initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, CoreError> {
+            let mut ciphertext = client_id.to_vec();
+            ciphertext.extend(plaintext.iter().rev());
+            Ok(ciphertext)
         }
     }
+
+    impl Decrypt for TestAuthenticatingHsm {
+        type Buffer = Vec<u8>;
+
+        fn decrypt(
+            &self,
+            client_id: &[u8],
+            ciphertext: &[u8],
+            _initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, CoreError> {
+            if ciphertext.starts_with(client_id) {
+                Ok(ciphertext[client_id.len()..]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect())
+            } else {
+                Err(CoreError::from(CoreErrorKind::Io))
+            }
+        }
+    }
+
+    impl MakeRandom for TestAuthenticatingHsm {
+        fn get_random_bytes(&self, buffer: &mut [u8]) -> Result<(), CoreError> {
+            for b in buffer.iter_mut() {
+                *b = 7;
+            }
+            Ok(())
+        }
+    }
+
+    fn encrypt_with_associated_data(associated_data: &[u8]) -> String {
+        let request = EncryptRequest::new(b64_text!(), b64_text!())
+            .with_associated_data(base64::encode(associated_data));
+        let response = EncryptHandler::new(TestAuthenticatingHsm::default())
+            .handle(
+                Request::builder()
+                    .body(serde_json::to_string(&request).unwrap().into())
+                    .unwrap(),
+                Parameters::with_captures(params_ok!().unwrap()),
+            ).wait()
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                serde_json::from_slice::<EncryptResponse>(&b)
+                    .unwrap()
+                    .ciphertext()
+                    .to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    fn decrypt_with_associated_data(ciphertext: String, associated_data: &[u8]) -> Response<Body> {
+        let request = DecryptRequest::new(ciphertext, b64_text!())
+            .with_associated_data(base64::encode(associated_data));
+        let (request, params) = create_args(Some(&request), params_ok!());
+        DecryptHandler::new(TestAuthenticatingHsm::default())
+            .handle(request, params)
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_with_associated_data_round_trips() {
+        let ciphertext = encrypt_with_associated_data(b"context");
+
+        let response = decrypt_with_associated_data(ciphertext, b"context");
+
+        assert_eq!(StatusCode::OK, response.status());
+        let body = response
+            .into_body()
+            .concat2()
+            .map(move |b| serde_json::from_slice::<DecryptResponse>(&b).unwrap())
+            .wait()
+            .unwrap();
+        assert_eq!(b64_text!(), body.plaintext().to_string());
+    }
+
+    #[test]
+    fn decrypt_fails_when_associated_data_differs() {
+        let ciphertext = encrypt_with_associated_data(b"context");
+
+        let response = decrypt_with_associated_data(ciphertext, b"tampered");
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+    }
+
+    #[test]
+    fn tampered_envelope_is_bad_request() {
+        let envelope = Envelope::new(TestAuthenticatingHsm::default());
+        let id = client_id("test", "I", None);
+        let mut ciphertext = envelope
+            .encrypt(&id, RAW_TEXT.as_bytes(), RAW_TEXT.as_bytes())
+            .unwrap();
+        let handler = DecryptHandler::new(envelope);
+        let decrypt = |ciphertext: &[u8]| {
+            let request = DecryptRequest::new(base64::encode(ciphertext), b64_text!());
+            let (request, params) = create_args(Some(&request), params_ok!());
+            handler.handle(request, params).wait().unwrap()
+        };
+
+        let response = decrypt(&ciphertext[..]);
+        assert_eq!(StatusCode::OK, response.status());
+
+        let last = ciphertext.len() - 1;
+        ciphertext[last] ^= 0x01;
+        let response = decrypt(&ciphertext[..]);
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let error = response
+            .into_body()
+            .concat2()
+            .map(|b| serde_json::from_slice::<ErrorResponse>(&b).unwrap())
+            .wait()
+            .unwrap();
+        assert_eq!(Some("PAYLOAD_TAMPERED"), error.code());
+        assert_eq!(
+            "The ciphertext was tampered with or is corrupt",
+            error.message()
+        );
+    }
 }
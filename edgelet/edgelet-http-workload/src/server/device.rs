@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use serde_json;
+
+use edgelet_core::WorkloadConfig;
+use edgelet_http::route::{Handler, Parameters};
+use workload::models::TrustBundleResponse;
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// Returns the certificate the device authenticates to IoT Hub with, so that
+/// edgeAgent can present it to the hub. Devices that authenticate with shared
+/// access keys have no such certificate.
+pub struct DeviceIdentityCertHandler<W: WorkloadConfig> {
+    config: W,
+}
+
+impl<W: WorkloadConfig> DeviceIdentityCertHandler<W> {
+    pub fn new(config: W) -> Self {
+        DeviceIdentityCertHandler { config }
+    }
+}
+
+impl<W> Handler<Parameters> for DeviceIdentityCertHandler<W>
+where
+    W: 'static + WorkloadConfig + Send,
+{
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let response = self
+            .config
+            .device_identity_cert()
+            .ok_or_else(|| Error::from(ErrorKind::CertificateNotFound))
+            .and_then(|cert| {
+                serde_json::to_string(&TrustBundleResponse::new(cert.to_string()))
+                    .context(ErrorKind::Serde)
+                    .map_err(Error::from)
+            }).and_then(|b| {
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                    .body(b.into())
+                    .map_err(Error::from)
+            }).unwrap_or_else(|e| e.into_response());
+
+        Box::new(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use edgelet_core::{CertificateType, PayloadLimits};
+    use futures::Stream;
+    use workload::models::ErrorResponse;
+
+    use super::*;
+
+    struct TestWorkloadConfig {
+        device_identity_cert: Option<String>,
+    }
+
+    impl WorkloadConfig for TestWorkloadConfig {
+        fn iot_hub_name(&self) -> &str {
+            "zaphods_hub"
+        }
+
+        fn device_id(&self) -> &str {
+            "marvins_device"
+        }
+
+        fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
+            0
+        }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            false
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            self.device_identity_cert.as_ref().map(AsRef::as_ref)
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    fn get(handler: &DeviceIdentityCertHandler<TestWorkloadConfig>) -> Response<Body> {
+        let request = Request::get("http://localhost/device/certificate/identity")
+            .body(Body::default())
+            .unwrap();
+        handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn identity_cert_is_returned() {
+        let handler = DeviceIdentityCertHandler::new(TestWorkloadConfig {
+            device_identity_cert: Some("identity cert".to_string()),
+        });
+
+        let response = get(&handler);
+        assert_eq!(StatusCode::OK, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let response: TrustBundleResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!("identity cert", response.certificate());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn sas_device_has_no_identity_cert() {
+        let handler = DeviceIdentityCertHandler::new(TestWorkloadConfig {
+            device_identity_cert: None,
+        });
+
+        let response = get(&handler);
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!("Certificate not found", error.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+}
@@ -1,11 +1,11 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use base64;
-use edgelet_core::Encrypt;
+use edgelet_core::{Encrypt, PayloadLimits};
 use edgelet_http::route::{Handler, Parameters};
 use error::{Error, ErrorKind};
 use failure::ResultExt;
-use futures::{future, Future, Stream};
+use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
@@ -13,13 +13,24 @@ use serde_json;
 use workload::models::{EncryptRequest, EncryptResponse};
 use IntoResponse;
 
+use super::payload::{client_id, decode_field, ensure_field_size, max_request_size, read_body};
+
 pub struct EncryptHandler<T: Encrypt> {
     hsm: T,
+    limits: PayloadLimits,
 }
 
 impl<T: Encrypt> EncryptHandler<T> {
     pub fn new(hsm: T) -> Self {
-        EncryptHandler { hsm }
+        EncryptHandler {
+            hsm,
+            limits: PayloadLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: PayloadLimits) -> Self {
+        self.limits = limits;
+        self
     }
 }
 
@@ -33,6 +44,7 @@ where
         params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
         let hsm = self.hsm.clone();
+        let limits = self.limits;
         let response = match params
             .name("name")
             .ok_or_else(|| Error::from(ErrorKind::BadParam))
@@ -43,31 +55,51 @@ where
                     .map(|genid| (name, genid))
             }) {
             Ok((module_id, genid)) => {
-                let id = format!("{}{}", module_id.to_string(), genid.to_string());
-                let ok = req.into_body().concat2().map(move |b| {
-                    serde_json::from_slice::<EncryptRequest>(&b)
-                        .context(ErrorKind::BadBody)
-                        .map_err(Error::from)
-                        .and_then(|request| {
-                            let plaintext = base64::decode(request.plaintext())?;
-                            let initialization_vector =
-                                base64::decode(request.initialization_vector())?;
-                            hsm.encrypt(id.as_bytes(), &plaintext, &initialization_vector)
-                                .map_err(Error::from)
-                        }).and_then(|ciphertext| {
-                            let encoded = base64::encode(&ciphertext);
-                            let response = EncryptResponse::new(encoded);
-                            let body = serde_json::to_string(&response)
-                                .expect("Generated an invalid EncryptResponse object");
-
-                            Ok(Response::builder()
-                                .status(StatusCode::OK)
-                                .header(CONTENT_TYPE, "application/json")
-                                .header(CONTENT_LENGTH, body.len().to_string().as_str())
-                                .body(body.into())
-                                .expect("Generated an invalid http::Response object"))
-                        }).unwrap_or_else(|e| e.into_response())
-                });
+                let module_id = module_id.to_string();
+                let genid = genid.to_string();
+                let ok = read_body(req.into_body(), max_request_size(&limits))
+                    .and_then(move |b| {
+                        let request = serde_json::from_slice::<EncryptRequest>(&b)
+                            .context(ErrorKind::BadBody)?;
+                        let plaintext = decode_field("plaintext", request.plaintext())?;
+                        ensure_field_size("plaintext", &plaintext, limits.max_payload_size())?;
+                        let initialization_vector =
+                            decode_field("initializationVector", request.initialization_vector())?;
+                        let associated_data = match request.associated_data() {
+                            Some(associated_data) => {
+                                let associated_data =
+                                    decode_field("associatedData", associated_data)?;
+                                ensure_field_size(
+                                    "associatedData",
+                                    &associated_data,
+                                    limits.max_associated_data_size(),
+                                )?;
+                                Some(associated_data)
+                            }
+                            None => None,
+                        };
+
+                        let id = client_id(
+                            &module_id,
+                            &genid,
+                            associated_data.as_ref().map(AsRef::as_ref),
+                        );
+                        let ciphertext = hsm
+                            .encrypt(&id, &plaintext, &initialization_vector)
+                            .map_err(Error::from)?;
+
+                        let encoded = base64::encode(&ciphertext);
+                        let response = EncryptResponse::new(encoded);
+                        let body = serde_json::to_string(&response)
+                            .expect("Generated an invalid EncryptResponse object");
+
+                        Ok(Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, "application/json")
+                            .header(CONTENT_LENGTH, body.len().to_string().as_str())
+                            .body(body.into())
+                            .expect("Generated an invalid http::Response object"))
+                    }).or_else(|e| future::ok(e.into_response()));
                 future::Either::A(ok)
             }
             Err(e) => future::Either::B(future::ok(e.into_response())),
@@ -81,7 +113,7 @@ mod tests {
     use edgelet_core::Encrypt;
     use edgelet_core::Error as CoreError;
     use edgelet_http::route::Parameters;
-    use futures::Future;
+    use futures::{Future, Stream};
     use http::{Request, StatusCode};
     use workload::models::EncryptResponse;
     use workload::models::ErrorResponse;
@@ -272,22 +304,80 @@ mod tests {
     }
 
     #[test]
-    fn handler_responds_with_unprocessable_entity_when_request_args_are_not_base64_encoded() {
+    fn handler_responds_with_bad_request_when_request_args_are_not_base64_encoded() {
         let bodies = &[
-            request_with_unencoded_plaintext(),
-            request_with_unencoded_init_vector(),
+            (
+                request_with_unencoded_plaintext(),
+                "Invalid base64 encoding for plaintext\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
+            (
+                request_with_unencoded_init_vector(),
+                "Invalid base64 encoding for initializationVector\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
+            (
+                request_ok().with_associated_data(raw_text!()),
+                "Invalid base64 encoding for associatedData\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
+            ),
         ];
         let handler = EncryptHandler::new(TestHsm::default());
 
-        for body in bodies {
+        for (body, message) in bodies {
             let (request, params) = create_args(Some(body), params_ok!());
             let response = handler.handle(request, params).wait().unwrap();
 
-            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
-            assert_response_message_eq(
-                "Invalid base64 string\n\tcaused by: Encoded text cannot have a 6-bit remainder.",
-                response,
-            );
+            assert_eq!(StatusCode::BAD_REQUEST, response.status());
+            assert_response_message_eq(*message, response);
         }
     }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_plaintext_exceeds_limit() {
+        let (request, params) = args_ok();
+        let handler = EncryptHandler::new(TestHsm::default())
+            .with_limits(PayloadLimits::default().with_max_payload_size(RAW_TEXT.len() - 1));
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "The plaintext exceeds the maximum size of 4 bytes",
+            response,
+        );
+    }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_associated_data_exceeds_limit() {
+        let body = request_ok().with_associated_data(b64_text!());
+        let (request, params) = create_args(Some(&body), params_ok!());
+        let handler = EncryptHandler::new(TestHsm::default()).with_limits(
+            PayloadLimits::default().with_max_associated_data_size(RAW_TEXT.len() - 1),
+        );
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "The associatedData exceeds the maximum size of 4 bytes",
+            response,
+        );
+    }
+
+    #[test]
+    fn handler_responds_with_payload_too_large_when_body_exceeds_limit() {
+        let body = EncryptRequest::new(base64::encode(&vec![0_u8; 8 * 1024]), b64_text!());
+        let (request, params) = create_args(Some(&body), params_ok!());
+        let handler = EncryptHandler::new(TestHsm::default()).with_limits(
+            PayloadLimits::default()
+                .with_max_payload_size(3)
+                .with_max_associated_data_size(3),
+        );
+
+        let response = handler.handle(request, params).wait().unwrap();
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_response_message_eq(
+            "Request body exceeds the maximum size of 4104 bytes",
+            response,
+        );
+    }
 }
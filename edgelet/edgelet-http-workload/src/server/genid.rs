@@ -0,0 +1,468 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use edgelet_core::{Identity, IdentityManager, IdentitySpec};
+use edgelet_http::route::{Handler, Parameters};
+use edgelet_utils::log_failure;
+use failure::ResultExt;
+use futures::future::{self, Either};
+use futures::Future;
+use http::{Request, Response};
+use hyper::{Body, Error as HyperError};
+use log::Level;
+use tokio;
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// How long a module's generation id is trusted before it's looked up again.
+pub const GENERATION_ID_TTL_SECS: u64 = 30;
+
+struct CacheEntry {
+    generation_id: Option<String>,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+enum Lookup {
+    Fresh(Option<String>),
+    Stale(Option<String>),
+    Missing,
+}
+
+/// Caches the current generation id of each module as reported by the
+/// identity manager, so that it isn't queried on every request.
+///
+/// Once an entry expires, a request with the generation id it holds is let
+/// through while the entry is refreshed in the background, so that requests
+/// don't wait on the identity manager's retries. It keeps being let through
+/// for as long as the identity manager can't be reached. Only a generation id
+/// the identity manager confirms is different gets a request rejected.
+pub struct GenerationIdCache<I> {
+    id_man: I,
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, CacheEntry>>>,
+}
+
+impl<I: Clone> Clone for GenerationIdCache<I> {
+    fn clone(&self) -> Self {
+        GenerationIdCache {
+            id_man: self.id_man.clone(),
+            ttl: self.ttl,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<I> GenerationIdCache<I>
+where
+    I: IdentityManager,
+    I::GetFuture: 'static,
+{
+    pub fn new(id_man: I, ttl: Duration) -> Self {
+        GenerationIdCache {
+            id_man,
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Resolves if `generation_id` is the current generation id of the
+    /// module, and fails with `GenerationIdMismatch` if it's not, or
+    /// `NotFound` if the module has no identity.
+    pub fn check(
+        &self,
+        module_id: &str,
+        generation_id: &str,
+    ) -> impl Future<Item = (), Error = Error> + Send {
+        let lookup = self.lookup(module_id);
+        let module_id = module_id.to_string();
+        let generation_id = generation_id.to_string();
+
+        match lookup {
+            Lookup::Fresh(current) => {
+                Either::A(future::result(compare(&module_id, &generation_id, current)))
+            }
+            Lookup::Stale(ref current) if current.as_ref() == Some(&generation_id) => {
+                self.refresh_in_background(module_id);
+                Either::A(future::ok(()))
+            }
+            Lookup::Stale(_) | Lookup::Missing => Either::B(
+                self.fetch(module_id.clone())
+                    .and_then(move |current| compare(&module_id, &generation_id, current)),
+            ),
+        }
+    }
+
+    fn lookup(&self, module_id: &str) -> Lookup {
+        let entries = self
+            .entries
+            .lock()
+            .expect("generation id cache lock poisoned");
+        match entries.get(module_id) {
+            Some(entry) if entry.fetched_at.elapsed() < self.ttl => {
+                Lookup::Fresh(entry.generation_id.clone())
+            }
+            Some(entry) => Lookup::Stale(entry.generation_id.clone()),
+            None => Lookup::Missing,
+        }
+    }
+
+    // Refreshes the entry of the module unless it's already being refreshed.
+    // The entry is kept as it is if the identity manager can't be reached.
+    fn refresh_in_background(&self, module_id: String) {
+        {
+            let mut entries = self
+                .entries
+                .lock()
+                .expect("generation id cache lock poisoned");
+            let entry = match entries.get_mut(&module_id) {
+                Some(entry) => entry,
+                None => return,
+            };
+            if entry.refreshing {
+                return;
+            }
+            entry.refreshing = true;
+        }
+
+        let refresh = self.fetch(module_id.clone()).then(move |result| {
+            if let Err(err) = result {
+                warn!(
+                    "Could not refresh the generation id of module {}, using the last known one:",
+                    module_id
+                );
+                log_failure(Level::Warn, &err);
+            }
+            Ok(())
+        });
+        tokio::spawn(refresh);
+    }
+
+    // Looks up the current generation id of the module, or `None` if the
+    // module has no identity, and caches it.
+    fn fetch(&self, module_id: String) -> impl Future<Item = Option<String>, Error = Error> + Send {
+        let entries = self.entries.clone();
+        self.id_man
+            .get(IdentitySpec::new(&module_id))
+            .then(move |identity| {
+                let mut entries = entries.lock().expect("generation id cache lock poisoned");
+                let generation_id = match identity.context(ErrorKind::IdentityManager) {
+                    Ok(identity) => identity.map(|identity| identity.generation_id().to_string()),
+                    Err(err) => {
+                        if let Some(entry) = entries.get_mut(&module_id) {
+                            entry.refreshing = false;
+                        }
+                        return Err(Error::from(err));
+                    }
+                };
+                entries.insert(
+                    module_id,
+                    CacheEntry {
+                        generation_id: generation_id.clone(),
+                        fetched_at: Instant::now(),
+                        refreshing: false,
+                    },
+                );
+                Ok(generation_id)
+            })
+    }
+}
+
+fn compare(module_id: &str, generation_id: &str, current: Option<String>) -> Result<(), Error> {
+    match current {
+        Some(ref current) if current == generation_id => Ok(()),
+        Some(current) => {
+            info!(
+                "Request for module {} with generation id {} rejected, current generation id is {}",
+                module_id, generation_id, current
+            );
+            Err(Error::from(ErrorKind::GenerationIdMismatch))
+        }
+        None => Err(Error::from(ErrorKind::NotFound)),
+    }
+}
+
+/// Rejects requests whose generation id doesn't match the current generation
+/// of the module named in the route, before handing them to `inner`.
+pub struct GenerationIdCheck<H, I> {
+    inner: Arc<H>,
+    cache: GenerationIdCache<I>,
+}
+
+impl<H, I> GenerationIdCheck<H, I> {
+    pub fn new(inner: H, cache: GenerationIdCache<I>) -> Self {
+        GenerationIdCheck {
+            inner: Arc::new(inner),
+            cache,
+        }
+    }
+}
+
+impl<H, I> Handler<Parameters> for GenerationIdCheck<H, I>
+where
+    H: Handler<Parameters> + Sync,
+    I: 'static + IdentityManager + Send + Sync,
+    I::GetFuture: 'static,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let (name, genid) = match (params.name("name"), params.name("genid")) {
+            (Some(name), Some(genid)) => (name.to_string(), genid.to_string()),
+            _ => {
+                return Box::new(future::ok(
+                    Error::from(ErrorKind::BadParam).into_response(),
+                ))
+            }
+        };
+        let inner = self.inner.clone();
+
+        let response = self
+            .cache
+            .check(&name, &genid)
+            .then(move |result| match result {
+                Ok(()) => future::Either::A(inner.handle(req, params)),
+                Err(err) => future::Either::B(future::ok(err.into_response())),
+            });
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use edgelet_core::AuthType;
+    use edgelet_test_utils::identity::{Error as TestError, TestIdentity};
+    use futures::future::FutureResult;
+    use futures::Stream;
+    use http::StatusCode;
+    use tokio::runtime::current_thread::Runtime;
+    use workload::models::ErrorResponse;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct TestIdentityManager {
+        identities: Vec<TestIdentity>,
+        gets: Arc<AtomicUsize>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl TestIdentityManager {
+        fn new(identities: Vec<TestIdentity>) -> Self {
+            TestIdentityManager {
+                identities,
+                gets: Arc::new(AtomicUsize::new(0)),
+                fail: Arc::new(AtomicBool::new(false)),
+            }
+        }
+
+        fn gets(&self) -> usize {
+            self.gets.load(Ordering::SeqCst)
+        }
+
+        fn fail(&self) {
+            self.fail.store(true, Ordering::SeqCst);
+        }
+    }
+
+    impl IdentityManager for TestIdentityManager {
+        type Identity = TestIdentity;
+        type Error = TestError;
+        type CreateFuture = FutureResult<Self::Identity, Self::Error>;
+        type UpdateFuture = FutureResult<Self::Identity, Self::Error>;
+        type ListFuture = FutureResult<Vec<Self::Identity>, Self::Error>;
+        type GetFuture = FutureResult<Option<Self::Identity>, Self::Error>;
+        type DeleteFuture = FutureResult<(), Self::Error>;
+        type RotateFuture = FutureResult<Self::Identity, Self::Error>;
+
+        fn create(&mut self, _id: IdentitySpec) -> Self::CreateFuture {
+            unimplemented!()
+        }
+
+        fn update(&mut self, _id: IdentitySpec) -> Self::UpdateFuture {
+            unimplemented!()
+        }
+
+        fn list(&self) -> Self::ListFuture {
+            unimplemented!()
+        }
+
+        fn get(&self, id: IdentitySpec) -> Self::GetFuture {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                return future::err(TestError::General);
+            }
+            future::ok(
+                self.identities
+                    .iter()
+                    .find(|identity| identity.module_id() == id.module_id())
+                    .cloned(),
+            )
+        }
+
+        fn delete(&mut self, _id: IdentitySpec) -> Self::DeleteFuture {
+            unimplemented!()
+        }
+
+        fn rotate_keys(&mut self, _id: IdentitySpec) -> Self::RotateFuture {
+            unimplemented!()
+        }
+    }
+
+    fn identities() -> TestIdentityManager {
+        TestIdentityManager::new(vec![TestIdentity::new(
+            "marvin",
+            "iotedge",
+            "42",
+            AuthType::Sas,
+        )])
+    }
+
+    fn new_cache(
+        id_man: TestIdentityManager,
+        ttl_secs: u64,
+    ) -> GenerationIdCache<TestIdentityManager> {
+        GenerationIdCache::new(id_man, Duration::from_secs(ttl_secs))
+    }
+
+    fn inner_handler(
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        Box::new(future::ok(Response::new("from inner handler".into())))
+    }
+
+    fn handle(
+        cache: &GenerationIdCache<TestIdentityManager>,
+        name: &str,
+        genid: &str,
+    ) -> Response<Body> {
+        let handler = GenerationIdCheck::new(inner_handler, cache.clone());
+        let params = Parameters::with_captures(vec![
+            (Some("name".to_string()), name.to_string()),
+            (Some("genid".to_string()), genid.to_string()),
+        ]);
+        // Runs until the entry is refreshed too, if it was stale.
+        let mut runtime = Runtime::new().unwrap();
+        let response = runtime
+            .block_on(handler.handle(Request::default(), params))
+            .unwrap();
+        runtime.run().unwrap();
+        response
+    }
+
+    fn error_message(response: Response<Body>) -> String {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                serde_json::from_slice::<ErrorResponse>(&b)
+                    .unwrap()
+                    .message()
+                    .to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn matching_generation_id_calls_inner_handler() {
+        let cache = new_cache(identities(), GENERATION_ID_TTL_SECS);
+
+        let response = handle(&cache, "marvin", "42");
+
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[test]
+    fn mismatched_generation_id_is_rejected() {
+        let cache = new_cache(identities(), GENERATION_ID_TTL_SECS);
+
+        let response = handle(&cache, "marvin", "41");
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("Module generation id mismatch", error_message(response));
+    }
+
+    #[test]
+    fn unknown_module_is_rejected() {
+        let cache = new_cache(identities(), GENERATION_ID_TTL_SECS);
+
+        let response = handle(&cache, "zaphod", "42");
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!("Module not found", error_message(response));
+    }
+
+    #[test]
+    fn generation_id_is_cached() {
+        let id_man = identities();
+        let cache = new_cache(id_man.clone(), GENERATION_ID_TTL_SECS);
+
+        handle(&cache, "marvin", "42");
+        handle(&cache, "marvin", "41");
+        handle(&cache, "marvin", "42");
+
+        assert_eq!(1, id_man.gets());
+    }
+
+    #[test]
+    fn expired_generation_id_is_fetched_again() {
+        let id_man = identities();
+        let cache = new_cache(id_man.clone(), 0);
+
+        handle(&cache, "marvin", "42");
+        handle(&cache, "marvin", "42");
+
+        assert_eq!(2, id_man.gets());
+    }
+
+    #[test]
+    fn last_known_generation_id_is_used_when_identity_manager_fails() {
+        let id_man = identities();
+        let cache = new_cache(id_man.clone(), 0);
+        handle(&cache, "marvin", "42");
+
+        id_man.fail();
+        let response = handle(&cache, "marvin", "42");
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(2, id_man.gets());
+    }
+
+    #[test]
+    fn mismatch_is_not_confirmed_when_identity_manager_fails() {
+        let id_man = identities();
+        let cache = new_cache(id_man.clone(), 0);
+        handle(&cache, "marvin", "42");
+
+        id_man.fail();
+        let response = handle(&cache, "marvin", "43");
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!(
+            "Identity manager error\n\tcaused by: General error",
+            error_message(response)
+        );
+    }
+
+    #[test]
+    fn mismatch_with_expired_generation_id_is_looked_up_again() {
+        let id_man = identities();
+        let cache = new_cache(id_man.clone(), 0);
+        handle(&cache, "marvin", "42");
+
+        let response = handle(&cache, "marvin", "41");
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(2, id_man.gets());
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use base64;
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use openssl::sha::sha256;
+use serde_json;
+
+use edgelet_core::WorkloadConfig;
+use edgelet_http::route::{Handler, Parameters};
+use workload::models::TrustBundleResponse;
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+/// Returns the certificates edgeAgent verifies signed deployment manifests
+/// with. They are kept apart from the trust bundle of `/trust-bundle` so that
+/// TLS stacks reading that one do not start trusting them for connections.
+///
+/// The bundle only changes when iotedged restarts with another one, so the
+/// response has an entity tag that callers revalidate their copy with.
+pub struct ManifestTrustBundleHandler<W: WorkloadConfig> {
+    config: W,
+    etag: Option<String>,
+}
+
+impl<W: WorkloadConfig> ManifestTrustBundleHandler<W> {
+    pub fn new(config: W) -> Self {
+        let etag = config.manifest_trust_bundle().map(|bundle| {
+            format!(
+                "\"{}\"",
+                base64::encode_config(&sha256(bundle.as_bytes()), base64::URL_SAFE_NO_PAD)
+            )
+        });
+        ManifestTrustBundleHandler { config, etag }
+    }
+}
+
+impl<W> Handler<Parameters> for ManifestTrustBundleHandler<W>
+where
+    W: 'static + WorkloadConfig + Send,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let response = match (self.config.manifest_trust_bundle(), self.etag.as_ref()) {
+            (Some(bundle), Some(etag)) => {
+                let unchanged = req
+                    .headers()
+                    .get(IF_NONE_MATCH)
+                    .map_or(false, |tag| tag.as_bytes() == etag.as_bytes());
+                if unchanged {
+                    Response::builder()
+                        .status(StatusCode::NOT_MODIFIED)
+                        .header(ETAG, etag.as_str())
+                        .header(CACHE_CONTROL, "no-cache")
+                        .body(Body::default())
+                        .map_err(Error::from)
+                } else {
+                    serde_json::to_string(&TrustBundleResponse::new(bundle.to_string()))
+                        .context(ErrorKind::Serde)
+                        .map_err(Error::from)
+                        .and_then(|b| {
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header(CONTENT_TYPE, "application/json")
+                                .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                                .header(ETAG, etag.as_str())
+                                .header(CACHE_CONTROL, "no-cache")
+                                .body(b.into())
+                                .map_err(Error::from)
+                        })
+                }
+            }
+            _ => Err(Error::from(ErrorKind::ManifestTrustBundleNotFound)),
+        }.unwrap_or_else(|e| e.into_response());
+
+        Box::new(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use edgelet_core::{CertificateType, PayloadLimits};
+    use futures::Stream;
+    use workload::models::ErrorResponse;
+
+    use super::*;
+
+    const BUNDLE: &str = "-----BEGIN CERTIFICATE-----\nmanifest signer\n-----END CERTIFICATE-----\n";
+
+    struct TestWorkloadConfig {
+        manifest_trust_bundle: Option<String>,
+    }
+
+    impl WorkloadConfig for TestWorkloadConfig {
+        fn iot_hub_name(&self) -> &str {
+            "zaphods_hub"
+        }
+
+        fn device_id(&self) -> &str {
+            "marvins_device"
+        }
+
+        fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
+            0
+        }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            false
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            None
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            self.manifest_trust_bundle.as_ref().map(AsRef::as_ref)
+        }
+    }
+
+    fn handler(bundle: Option<&str>) -> ManifestTrustBundleHandler<TestWorkloadConfig> {
+        ManifestTrustBundleHandler::new(TestWorkloadConfig {
+            manifest_trust_bundle: bundle.map(ToString::to_string),
+        })
+    }
+
+    fn get(
+        handler: &ManifestTrustBundleHandler<TestWorkloadConfig>,
+        etag: Option<&str>,
+    ) -> Response<Body> {
+        let mut request = Request::get("http://localhost/trust-bundle/manifest");
+        if let Some(etag) = etag {
+            request.header(IF_NONE_MATCH, etag);
+        }
+        let request = request.body(Body::default()).unwrap();
+        handler
+            .handle(request, Parameters::default())
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn configured_bundle_is_returned() {
+        let response = get(&handler(Some(BUNDLE)), None);
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("no-cache", response.headers().get(CACHE_CONTROL).unwrap());
+        assert!(response.headers().get(ETAG).is_some());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let response: TrustBundleResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!(BUNDLE, response.certificate());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn unchanged_bundle_is_not_sent_again() {
+        let handler = handler(Some(BUNDLE));
+        let etag = get(&handler, None).headers()[ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = get(&handler, Some(&etag));
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(etag, response.headers()[ETAG]);
+
+        // Another bundle has another tag.
+        let response = get(&handler, Some("\"some other bundle\""));
+        assert_eq!(StatusCode::OK, response.status());
+        let other = ManifestTrustBundleHandler::new(TestWorkloadConfig {
+            manifest_trust_bundle: Some(BUNDLE.replace("signer", "signer 2")),
+        });
+        assert_eq!(StatusCode::OK, get(&other, Some(&etag)).status());
+    }
+
+    #[test]
+    fn unconfigured_bundle_is_not_found() {
+        let response = get(&handler(None), None);
+
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|body| {
+                let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+                assert_eq!("Manifest trust bundle is not configured", error.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+}
@@ -2,15 +2,23 @@
 
 mod cert;
 mod decrypt;
+mod device;
 mod encrypt;
+mod genid;
+mod manifest_trust_bundle;
+mod payload;
 mod sign;
+mod sign_batch;
 mod trust_bundle;
 
 use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Duration;
 
 use edgelet_core::{
-    CreateCertificate, Decrypt, Encrypt, Error as CoreError, GetTrustBundle, KeyStore, Module,
-    ModuleRuntime, Policy, WorkloadConfig,
+    Clock, CreateCertificate, Decrypt, Encrypt, Envelope, Error as CoreError, GetTrustBundle,
+    IdentityManager, KeyStore, MakeRandom, Module, ModuleRuntime, Policy, SignCertificateRequest,
+    WorkloadConfig,
 };
 use edgelet_http::authorization::Authorization;
 use edgelet_http::route::*;
@@ -21,12 +29,19 @@ use hyper::service::{NewService, Service};
 use hyper::{Body, Error as HyperError, Request, Response};
 use serde::Serialize;
 
-use self::cert::{IdentityCertHandler, ServerCertHandler};
+pub use self::cert::IssuedCerts;
+use self::cert::{IdentityCertHandler, LeafCertHandler, RenewCertHandler, ServerCertHandler};
 use self::decrypt::DecryptHandler;
+use self::device::DeviceIdentityCertHandler;
 use self::encrypt::EncryptHandler;
+use self::genid::{GenerationIdCache, GenerationIdCheck, GENERATION_ID_TTL_SECS};
+use self::manifest_trust_bundle::ManifestTrustBundleHandler;
 use self::sign::SignHandler;
+use self::sign_batch::SignBatchHandler;
 use self::trust_bundle::TrustBundleHandler;
 
+const AGENT_NAME: &str = "edgeAgent";
+
 #[derive(Clone)]
 pub struct WorkloadService {
     inner: RouterService<RegexRecognizer>,
@@ -35,31 +50,57 @@ pub struct WorkloadService {
 impl WorkloadService {
     // clippy bug: https://github.com/rust-lang-nursery/rust-clippy/issues/3220
     #[cfg_attr(feature = "cargo-clippy", allow(new_ret_no_self))]
-    pub fn new<K, H, M, W>(
+    pub fn new<K, H, M, I, W>(
         key_store: &K,
         hsm: H,
         runtime: &M,
+        identity: &I,
         config: W,
+        issued: IssuedCerts,
+        clock: Arc<Clock>,
     ) -> impl Future<Item = Self, Error = failure::Error>
     where
         K: KeyStore + Clone + Send + Sync + 'static,
-        H: CreateCertificate + Decrypt + Encrypt + GetTrustBundle + Clone + Send + Sync + 'static,
+        H: CreateCertificate
+            + Decrypt
+            + Encrypt
+            + GetTrustBundle
+            + MakeRandom
+            + SignCertificateRequest
+            + Clone
+            + Send
+            + Sync
+            + 'static,
         M: ModuleRuntime + Clone + Send + Sync + 'static,
         M::Error: Into<CoreError>,
         <M::Module as Module>::Config: Serialize,
         <M::Module as Module>::Error: Into<CoreError>,
         M::Logs: Into<Body>,
+        I: IdentityManager + Clone + Send + Sync + 'static,
         W: WorkloadConfig + Clone + Send + Sync + 'static,
     {
+        let genids = GenerationIdCache::new(
+            identity.clone(),
+            Duration::from_secs(GENERATION_ID_TTL_SECS),
+        );
+        let allow_host_processes = config.allow_host_processes();
+        // Modules get authenticated ciphertexts whatever the HSM's cipher.
+        let envelope = Envelope::new(hsm.clone());
         let router = router!(
             get    "/modules" => Authorization::new(ListModules::new(runtime.clone()), Policy::Anonymous, runtime.clone()),
-            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/sign" => Authorization::new(SignHandler::new(key_store.clone()), Policy::Caller, runtime.clone()),
-            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/decrypt" => Authorization::new(DecryptHandler::new(hsm.clone()), Policy::Caller, runtime.clone()),
-            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/encrypt" => Authorization::new(EncryptHandler::new(hsm.clone()), Policy::Caller, runtime.clone()),
-            post   "/modules/(?P<name>[^/]+)/certificate/identity" => Authorization::new(IdentityCertHandler::new(hsm.clone(), config.clone()), Policy::Caller, runtime.clone()),
-            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/certificate/server" => Authorization::new(ServerCertHandler::new(hsm.clone(), config), Policy::Caller, runtime.clone()),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/sign" => Authorization::new(GenerationIdCheck::new(SignHandler::new(key_store.clone()).with_limits(config.payload_limits()), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/sign/batch" => Authorization::new(GenerationIdCheck::new(SignBatchHandler::new(key_store.clone()).with_limits(config.payload_limits()), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/decrypt" => Authorization::new(GenerationIdCheck::new(DecryptHandler::new(envelope.clone()).with_limits(config.payload_limits()), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/encrypt" => Authorization::new(GenerationIdCheck::new(EncryptHandler::new(envelope).with_limits(config.payload_limits()), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/certificate/identity" => Authorization::new(IdentityCertHandler::new(hsm.clone(), config.clone()).with_issued_certs(issued.clone()).with_clock(clock.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/certificate/server" => Authorization::new(GenerationIdCheck::new(ServerCertHandler::new(hsm.clone(), config.clone()).with_issued_certs(issued.clone()).with_clock(clock.clone()), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/certificate/renew" => Authorization::new(GenerationIdCheck::new(RenewCertHandler::new(hsm.clone(), issued), genids.clone()), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+            post   "/modules/(?P<name>[^/]+)/genid/(?P<genid>[^/]+)/certificate/issue" => Authorization::new(GenerationIdCheck::new(LeafCertHandler::new(hsm.clone(), config.clone()).with_clock(clock.clone()), genids), Policy::Caller, runtime.clone()).with_allow_host_processes(allow_host_processes),
+
+            get    "/device/certificate/identity" => Authorization::new(DeviceIdentityCertHandler::new(config.clone()), Policy::Module(AGENT_NAME), runtime.clone()),
 
-            get    "/trust-bundle" => Authorization::new(TrustBundleHandler::new(hsm), Policy::Anonymous, runtime.clone()),
+            get    "/trust-bundle" => Authorization::new(TrustBundleHandler::new(hsm, config.clone()), Policy::Anonymous, runtime.clone()),
+            get    "/trust-bundle/manifest" => Authorization::new(ManifestTrustBundleHandler::new(config), Policy::Module(AGENT_NAME), runtime.clone()),
         );
 
         router
@@ -0,0 +1,170 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use base64;
+use edgelet_core::PayloadLimits;
+use failure::ResultExt;
+use futures::{Future, Stream};
+use hyper::Body;
+
+use error::{Error, ErrorKind, Result};
+
+/// Room left in a request body for the JSON framing and the initialization
+/// vector, neither of which count against the payload limits.
+const REQUEST_OVERHEAD: usize = 4 * 1024;
+
+/// Headroom for the version and authentication tag that the HSM adds to a
+/// ciphertext, and for the envelope around that, so that any payload within
+/// the limit can be decrypted again.
+pub const CIPHERTEXT_OVERHEAD: usize = 64;
+
+/// Largest request body that can still hold a payload and associated data that
+/// are within `limits` once they have been base64 encoded.
+pub fn max_request_size(limits: &PayloadLimits) -> usize {
+    encoded_len(limits.max_payload_size())
+        + encoded_len(limits.max_associated_data_size())
+        + REQUEST_OVERHEAD
+}
+
+/// Largest request body that can still hold a payload to sign that is within
+/// `limits` once it has been base64 encoded.
+pub fn max_sign_request_size(limits: &PayloadLimits) -> usize {
+    encoded_len(limits.max_sign_payload_size()) + REQUEST_OVERHEAD
+}
+
+/// Largest request body that can still hold as many sign requests as a batch
+/// may have, each within `limits`.
+pub fn max_sign_batch_request_size(limits: &PayloadLimits) -> usize {
+    max_sign_request_size(limits).saturating_mul(limits.max_sign_batch_size())
+}
+
+fn encoded_len(len: usize) -> usize {
+    (len + 2) / 3 * 4
+}
+
+/// Length of the data that `value` decodes to, if it is valid base64.
+fn decoded_len(value: &str) -> usize {
+    let padding = value.bytes().rev().take(2).take_while(|&b| b == b'=').count();
+    (value.len() * 3 / 4).saturating_sub(padding)
+}
+
+/// Collects the request body, failing as soon as it grows beyond `max_size`
+/// rather than buffering an unbounded amount of data.
+pub fn read_body(body: Body, max_size: usize) -> impl Future<Item = Vec<u8>, Error = Error> {
+    body.map_err(Error::from)
+        .fold(Vec::new(), move |mut buffer, chunk| {
+            if buffer.len() + chunk.len() > max_size {
+                Err(Error::from(ErrorKind::RequestTooLarge(max_size)))
+            } else {
+                buffer.extend_from_slice(&chunk);
+                Ok(buffer)
+            }
+        })
+}
+
+pub fn decode_field(name: &'static str, value: &str) -> Result<Vec<u8>> {
+    base64::decode(value)
+        .context(ErrorKind::MalformedBase64(name))
+        .map_err(Error::from)
+}
+
+/// Checks the size `value` decodes to before decoding it, so that a field that
+/// is too large is rejected without allocating room for it.
+pub fn ensure_encoded_field_size(name: &'static str, value: &str, max_size: usize) -> Result<()> {
+    if value.is_empty() {
+        Err(Error::from(ErrorKind::EmptyField(name)))
+    } else if decoded_len(value) > max_size {
+        Err(Error::from(ErrorKind::FieldTooLarge(name, max_size)))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn ensure_field_size(name: &'static str, value: &[u8], max_size: usize) -> Result<()> {
+    if value.len() > max_size {
+        Err(Error::from(ErrorKind::FieldTooLarge(name, max_size)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Builds the identity that the HSM authenticates the ciphertext against. Any
+/// associated data is appended together with its length so that it can't be
+/// confused with a longer module or generation id.
+pub fn client_id(module_id: &str, genid: &str, associated_data: Option<&[u8]>) -> Vec<u8> {
+    let mut id = format!("{}{}", module_id, genid).into_bytes();
+    if let Some(associated_data) = associated_data {
+        id.extend_from_slice(associated_data);
+        id.extend_from_slice(format!("{:016x}", associated_data.len()).as_bytes());
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_id_without_associated_data_is_unchanged() {
+        assert_eq!(b"modI".to_vec(), client_id("mod", "I", None));
+    }
+
+    #[test]
+    fn client_id_distinguishes_associated_data() {
+        assert_ne!(
+            client_id("mod", "I", Some(b"")),
+            client_id("mod", "I", None)
+        );
+        assert_ne!(
+            client_id("mod", "I", Some(b"a")),
+            client_id("mod", "I", Some(b"b"))
+        );
+    }
+
+    #[test]
+    fn read_body_enforces_limit() {
+        let body = read_body(Body::from("0123456789"), 10).wait().unwrap();
+        assert_eq!(b"0123456789".to_vec(), body);
+
+        let err = read_body(Body::from("0123456789"), 9).wait().unwrap_err();
+        match *err.kind() {
+            ErrorKind::RequestTooLarge(9) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn decoded_len_matches_base64() {
+        for len in 0..10 {
+            let data = vec![0xa5; len];
+            assert_eq!(len, decoded_len(&base64::encode(&data)));
+            assert_eq!(
+                len,
+                decoded_len(&base64::encode_config(&data, base64::STANDARD_NO_PAD))
+            );
+        }
+    }
+
+    #[test]
+    fn ensure_encoded_field_size_checks_decoded_size() {
+        assert!(ensure_encoded_field_size("data", "AAAA", 3).is_ok());
+        match *ensure_encoded_field_size("data", "AAAAAA==", 3)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::FieldTooLarge("data", 3) => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        match *ensure_encoded_field_size("data", "", 3).unwrap_err().kind() {
+            ErrorKind::EmptyField("data") => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn max_request_size_covers_encoded_limits() {
+        let limits = PayloadLimits::default()
+            .with_max_payload_size(3)
+            .with_max_associated_data_size(4);
+        assert_eq!(4 + 8 + REQUEST_OVERHEAD, max_request_size(&limits));
+    }
+}
@@ -2,23 +2,32 @@
 
 use base64;
 use edgelet_core::crypto::{KeyIdentity, KeyStore, Sign, Signature, SignatureAlgorithm};
+use edgelet_core::{ErrorKind as CoreErrorKind, PayloadLimits};
 use edgelet_http::route::{Handler, Parameters};
+use edgelet_utils::log_failure;
 use failure::ResultExt;
-use futures::{future, Future, Stream};
+use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
+use log::Level;
 use serde_json;
 use workload::models::{SignRequest, SignResponse};
 
 use error::{Error, ErrorKind};
 use IntoResponse;
 
+use super::payload::{ensure_encoded_field_size, max_sign_request_size, read_body};
+
+const PRIMARY_KEY_ID: &str = "primary";
+const SECONDARY_KEY_ID: &str = "secondary";
+
 pub struct SignHandler<K>
 where
     K: 'static + KeyStore + Clone,
 {
     key_store: K,
+    limits: PayloadLimits,
 }
 
 impl<K> SignHandler<K>
@@ -26,26 +35,88 @@ where
     K: 'static + KeyStore + Clone,
 {
     pub fn new(key_store: K) -> Self {
-        SignHandler { key_store }
+        SignHandler {
+            key_store,
+            limits: PayloadLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: PayloadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+fn signature_algorithm(algo: Option<&str>) -> Result<SignatureAlgorithm, Error> {
+    match algo {
+        None | Some("HMACSHA256") => Ok(SignatureAlgorithm::HMACSHA256),
+        Some("HMACSHA512") => Ok(SignatureAlgorithm::HMACSHA512),
+        Some(_) => Err(Error::from(ErrorKind::InvalidSignAlgorithm)),
+    }
+}
+
+pub fn validate_key_id(key_id: &str) -> Result<(), Error> {
+    match key_id {
+        PRIMARY_KEY_ID | SECONDARY_KEY_ID => Ok(()),
+        _ => Err(Error::from(ErrorKind::InvalidKeyId)),
     }
 }
 
+/// The algorithm and the decoded data of `request`.
+pub fn sign_input(
+    request: &SignRequest,
+    limits: &PayloadLimits,
+) -> Result<(SignatureAlgorithm, Vec<u8>), Error> {
+    let algorithm = signature_algorithm(request.algo())?;
+    ensure_encoded_field_size("data", request.data(), limits.max_sign_payload_size())?;
+    let data = base64::decode(request.data())?;
+    Ok((algorithm, data))
+}
+
+/// Looks up the key of module `id` named `key_id`.
+pub fn module_key<K: KeyStore>(key_store: &K, id: &str, key_id: &str) -> Result<K::Key, Error> {
+    // Errors from the key store can carry details about the keys it holds, so
+    // they are logged here rather than returned to the module.
+    key_store
+        .get(&KeyIdentity::Module(id.to_string()), key_id)
+        .map_err(|err| {
+            log_failure(Level::Warn, &err);
+            match *err.kind() {
+                CoreErrorKind::NotFound => Error::from(ErrorKind::NotFound),
+                _ => Error::from(ErrorKind::KeyStore),
+            }
+        })
+}
+
+/// Signs `data` with `key`, returning the base64 encoded signature.
+pub fn sign_data<S: Sign>(
+    key: &S,
+    algorithm: SignatureAlgorithm,
+    data: &[u8],
+) -> Result<String, Error> {
+    let signature = key.sign(algorithm, data).map_err(|err| {
+        log_failure(Level::Warn, &err);
+        match *err.kind() {
+            CoreErrorKind::UnsupportedSignatureAlgorithm(_) => {
+                Error::from(ErrorKind::UnsupportedSignAlgorithm)
+            }
+            _ => Error::from(ErrorKind::Sign),
+        }
+    })?;
+    Ok(base64::encode(signature.as_bytes()))
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn sign<K: KeyStore>(
     key_store: K,
     id: String,
     request: SignRequest,
+    limits: &PayloadLimits,
 ) -> Result<SignResponse, Error> {
-    key_store
-        .get(&KeyIdentity::Module(id), request.key_id())
-        .context(ErrorKind::NotFound)
-        .map_err(Error::from)
-        .and_then(|k| {
-            let data: Vec<u8> = base64::decode(request.data())?;
-            let signature = k.sign(SignatureAlgorithm::HMACSHA256, &data)?;
-            let encoded = base64::encode(signature.as_bytes());
-            Ok(SignResponse::new(encoded))
-        })
+    let (algorithm, data) = sign_input(&request, limits)?;
+    let key = module_key(&key_store, &id, request.key_id())?;
+    let encoded = sign_data(&key, algorithm, &data)?;
+    Ok(SignResponse::new(encoded))
 }
 
 impl<K> Handler<Parameters> for SignHandler<K>
@@ -70,26 +141,22 @@ where
                 let id = name.to_string();
                 let genid = genid.to_string();
                 let key_store = self.key_store.clone();
-                let ok = req.into_body().concat2().map(move |b| {
-                    serde_json::from_slice::<SignRequest>(&b)
-                        .context(ErrorKind::BadBody)
-                        .map_err(From::from)
-                        .and_then(|request| {
-                            let key_id = format!("{}{}", request.key_id(), genid);
-                            sign(key_store, id, request.with_key_id(key_id))
-                        }).and_then(|r| {
-                            serde_json::to_string(&r)
-                                .context(ErrorKind::Serde)
-                                .map_err(From::from)
-                        }).and_then(|b| {
-                            Response::builder()
-                                .status(StatusCode::OK)
-                                .header(CONTENT_TYPE, "application/json")
-                                .header(CONTENT_LENGTH, b.len().to_string().as_str())
-                                .body(b.into())
-                                .map_err(From::from)
-                        }).unwrap_or_else(|e| e.into_response())
-                });
+                let limits = self.limits;
+                let ok = read_body(req.into_body(), max_sign_request_size(&limits))
+                    .and_then(move |b| {
+                        let request = serde_json::from_slice::<SignRequest>(&b)
+                            .context(ErrorKind::BadBody)?;
+                        validate_key_id(request.key_id())?;
+                        let key_id = format!("{}{}", request.key_id(), genid);
+                        let response = sign(key_store, id, request.with_key_id(key_id), &limits)?;
+                        let b = serde_json::to_string(&response).context(ErrorKind::Serde)?;
+                        let response = Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, "application/json")
+                            .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                            .body(b.into())?;
+                        Ok(response)
+                    }).or_else(|e| future::ok(e.into_response()));
                 future::Either::A(ok)
             }
             Err(e) => future::Either::B(future::ok(e.into_response())),
@@ -102,9 +169,11 @@ where
 mod tests {
     use std::sync::{Arc, Mutex};
 
-    use edgelet_core::crypto::MemoryKey;
-    use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind, KeyStore};
+    use edgelet_core::crypto::{Digest, MemoryKey};
+    use edgelet_core::{Error as CoreError, KeyStore};
     use edgelet_http::route::Parameters;
+    use failure::err_msg;
+    use futures::Stream;
     use workload::models::ErrorResponse;
 
     use super::*;
@@ -183,7 +252,6 @@ mod tests {
 
         let sign_request = SignRequest::new(
             "primary".to_string(),
-            "hmac".to_string(),
             base64::encode("The quick brown fox jumps over the lazy dog"),
         );
         let body = serde_json::to_string(&sign_request).unwrap();
@@ -225,7 +293,6 @@ mod tests {
 
         let sign_request = SignRequest::new(
             "primary".to_string(),
-            "hmac".to_string(),
             base64::encode("The quick brown fox jumps over the lazy dog"),
         );
         let body = serde_json::to_string(&sign_request).unwrap();
@@ -248,10 +315,7 @@ mod tests {
             .concat2()
             .and_then(|b| {
                 let error_response: ErrorResponse = serde_json::from_slice(&b).unwrap();
-                assert_eq!(
-                    "Module not found\n\tcaused by: Item not found.",
-                    error_response.message()
-                );
+                assert_eq!("Module not found", error_response.message());
                 Ok(())
             }).wait()
             .unwrap();
@@ -266,7 +330,6 @@ mod tests {
 
         let sign_request = SignRequest::new(
             "primary".to_string(),
-            "hmac".to_string(),
             base64::encode("The quick brown fox jumps over the lazy dog"),
         );
         let body = serde_json::to_string(&sign_request).unwrap();
@@ -291,7 +354,6 @@ mod tests {
 
         let sign_request = SignRequest::new(
             "primary".to_string(),
-            "hmac".to_string(),
             base64::encode("The quick brown fox jumps over the lazy dog"),
         );
         let body = serde_json::to_string(&sign_request).unwrap();
@@ -316,11 +378,7 @@ mod tests {
         let store = TestKeyStore::new(key);
         let handler = SignHandler::new(store);
 
-        let sign_request = SignRequest::new(
-            "primary".to_string(),
-            "hmac".to_string(),
-            "alsjdfasf".to_string(),
-        );
+        let sign_request = SignRequest::new("primary".to_string(), "alsjdfasf".to_string());
         let body = serde_json::to_string(&sign_request).unwrap();
 
         let parameters = Parameters::with_captures(vec![
@@ -382,4 +440,289 @@ mod tests {
             }).wait()
             .unwrap();
     }
+
+    fn sign_with(key_id: &str, algo: Option<&str>) -> (Response<Body>, Arc<Mutex<State>>) {
+        let key = MemoryKey::new("key");
+        let store = TestKeyStore::new(key);
+        let handler = SignHandler::new(store.clone());
+
+        let mut sign_request = SignRequest::new(
+            key_id.to_string(),
+            base64::encode("The quick brown fox jumps over the lazy dog"),
+        );
+        if let Some(algo) = algo {
+            sign_request.set_algo(algo.to_string());
+        }
+        let body = serde_json::to_string(&sign_request).unwrap();
+
+        let parameters = Parameters::with_captures(vec![
+            (Some("name".to_string()), "test".to_string()),
+            (Some("genid".to_string()), "g1".to_string()),
+        ]);
+        let request = Request::post("http://localhost/modules/name/sign")
+            .body(body.into())
+            .unwrap();
+
+        let response = handler.handle(request, parameters).wait().unwrap();
+        (response, store.state.clone())
+    }
+
+    fn digest(response: Response<Body>) -> String {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                let sign_response: SignResponse = serde_json::from_slice(&b).unwrap();
+                sign_response.digest().to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn success_hmacsha256() {
+        // act
+        let (response, _) = sign_with("primary", Some("HMACSHA256"));
+
+        // assert
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg=", digest(response));
+    }
+
+    #[test]
+    fn success_hmacsha512() {
+        // act
+        let (response, _) = sign_with("primary", Some("HMACSHA512"));
+
+        // assert
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "tCrwkFe6weLUFwjkipAuCbX/fxKrQopP6GZTxz3SSPuC+UilSfe3kaW0GRXuTR7Dk1NX5OIxclDQNyr6Lr7rOg==",
+            digest(response)
+        );
+    }
+
+    #[test]
+    fn success_secondary_key() {
+        // act
+        let (response, state) = sign_with("secondary", None);
+
+        // assert
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(state.lock().unwrap().last_key_name, "secondaryg1");
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        // act
+        let (response, _) = sign_with("primary", Some("HMACMD5"));
+
+        // assert
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error_response: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!("Invalid sign algorithm", error_response.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn unknown_key_id() {
+        // act
+        let (response, state) = sign_with("tertiary", None);
+
+        // assert
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(state.lock().unwrap().last_key_name, "");
+        response
+            .into_body()
+            .concat2()
+            .and_then(|b| {
+                let error_response: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                assert_eq!("Invalid key identifier", error_response.message());
+                Ok(())
+            }).wait()
+            .unwrap();
+    }
+
+    #[derive(Clone, Debug)]
+    struct FailingKeyStore;
+
+    impl KeyStore for FailingKeyStore {
+        type Key = MemoryKey;
+
+        fn get(&self, _identity: &KeyIdentity, key_name: &str) -> Result<Self::Key, CoreError> {
+            Err(CoreError::from(
+                err_msg(format!("could not open key {}", key_name))
+                    .context(CoreErrorKind::KeyStore),
+            ))
+        }
+    }
+
+    // A key store whose keys only sign with HMACSHA256, like those of a TPM.
+    #[derive(Clone, Debug)]
+    struct Sha256OnlyKeyStore;
+
+    #[derive(Clone, Debug)]
+    struct Sha256OnlyKey(MemoryKey);
+
+    impl Sign for Sha256OnlyKey {
+        type Signature = Digest;
+
+        fn sign(
+            &self,
+            signature_algorithm: SignatureAlgorithm,
+            data: &[u8],
+        ) -> Result<Self::Signature, CoreError> {
+            match signature_algorithm {
+                SignatureAlgorithm::HMACSHA256 => self.0.sign(signature_algorithm, data),
+                _ => Err(CoreError::from(
+                    CoreErrorKind::UnsupportedSignatureAlgorithm(signature_algorithm),
+                )),
+            }
+        }
+    }
+
+    impl KeyStore for Sha256OnlyKeyStore {
+        type Key = Sha256OnlyKey;
+
+        fn get(&self, _identity: &KeyIdentity, _key_name: &str) -> Result<Self::Key, CoreError> {
+            Ok(Sha256OnlyKey(MemoryKey::new("key")))
+        }
+    }
+
+    fn sign_data<K>(handler: &SignHandler<K>, data: String, algo: &str) -> Response<Body>
+    where
+        K: 'static + KeyStore + Clone + Send,
+    {
+        let mut sign_request = SignRequest::new("primary".to_string(), data);
+        sign_request.set_algo(algo.to_string());
+        let body = serde_json::to_string(&sign_request).unwrap();
+
+        let parameters = Parameters::with_captures(vec![
+            (Some("name".to_string()), "test".to_string()),
+            (Some("genid".to_string()), "g1".to_string()),
+        ]);
+        let request = Request::post("http://localhost/modules/name/sign")
+            .body(body.into())
+            .unwrap();
+
+        handler.handle(request, parameters).wait().unwrap()
+    }
+
+    fn error_message(response: Response<Body>) -> String {
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                let error_response: ErrorResponse = serde_json::from_slice(&b).unwrap();
+                error_response.message().to_string()
+            }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn rfc4231_known_answers() {
+        // RFC 4231, test case 2
+        let handler = SignHandler::new(TestKeyStore::new(MemoryKey::new("Jefe")));
+        let data = base64::encode("what do ya want for nothing?");
+
+        let response = sign_data(&handler, data.clone(), "HMACSHA256");
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=", digest(response));
+
+        let response = sign_data(&handler, data, "HMACSHA512");
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "Fkt6e/z4GeLjlfvnO1bgo4e9ZCIugx/WECcM1+olBVSXWL91wFqZSm0DT2X48Ob9yuqxo01Ka0tjbgcKOLznNw==",
+            digest(response)
+        );
+    }
+
+    #[test]
+    fn empty_data() {
+        let store = TestKeyStore::new(MemoryKey::new("key"));
+        let handler = SignHandler::new(store.clone());
+
+        let response = sign_data(&handler, "".to_string(), "HMACSHA256");
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!("The data must not be empty", error_message(response));
+        assert_eq!(store.state.lock().unwrap().last_key_name, "");
+    }
+
+    #[test]
+    fn data_at_limit() {
+        let handler = SignHandler::new(TestKeyStore::new(MemoryKey::new("key")))
+            .with_limits(PayloadLimits::default().with_max_sign_payload_size(4));
+
+        let response = sign_data(&handler, base64::encode("abcd"), "HMACSHA256");
+
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[test]
+    fn data_too_large() {
+        let store = TestKeyStore::new(MemoryKey::new("key"));
+        let handler = SignHandler::new(store.clone())
+            .with_limits(PayloadLimits::default().with_max_sign_payload_size(4));
+
+        let response = sign_data(&handler, base64::encode("abcde"), "HMACSHA256");
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_eq!(
+            "The data exceeds the maximum size of 4 bytes",
+            error_message(response)
+        );
+        assert_eq!(store.state.lock().unwrap().last_key_name, "");
+    }
+
+    #[test]
+    fn body_too_large() {
+        let limits = PayloadLimits::default().with_max_sign_payload_size(4);
+        let handler =
+            SignHandler::new(TestKeyStore::new(MemoryKey::new("key"))).with_limits(limits);
+        let data = base64::encode(&vec![0; max_sign_request_size(&limits)]);
+
+        let response = sign_data(&handler, data, "HMACSHA256");
+
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+        assert_eq!(
+            format!(
+                "Request body exceeds the maximum size of {} bytes",
+                max_sign_request_size(&limits)
+            ),
+            error_message(response)
+        );
+    }
+
+    #[test]
+    fn key_store_errors_are_not_returned() {
+        let handler = SignHandler::new(FailingKeyStore);
+
+        let response = sign_data(&handler, base64::encode("data"), "HMACSHA256");
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        let message = error_message(response);
+        assert_eq!("Keystore error", message);
+        assert!(!message.contains("primaryg1"));
+    }
+
+    #[test]
+    fn algorithms_the_key_does_not_support_are_bad_requests() {
+        let handler = SignHandler::new(Sha256OnlyKeyStore);
+
+        let response = sign_data(&handler, base64::encode("data"), "HMACSHA256");
+        assert_eq!(StatusCode::OK, response.status());
+
+        let response = sign_data(&handler, base64::encode("data"), "HMACSHA512");
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "Sign algorithm not supported by the key",
+            error_message(response)
+        );
+    }
 }
@@ -0,0 +1,312 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::HashMap;
+
+use edgelet_core::crypto::KeyStore;
+use edgelet_core::PayloadLimits;
+use edgelet_http::route::{Handler, Parameters};
+use failure::ResultExt;
+use futures::{future, Future};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::{Body, Error as HyperError};
+use serde_json;
+use workload::models::{SignBatchRequest, SignBatchResponse, SignBatchResult, SignRequest};
+
+use error::{Error, ErrorKind};
+use IntoResponse;
+
+use super::payload::{max_sign_batch_request_size, read_body};
+use super::sign::{module_key, sign_data, sign_input, validate_key_id};
+
+/// Signs several payloads of a module in one request. A payload that cannot be
+/// signed gets an error in its result instead of failing the whole batch.
+pub struct SignBatchHandler<K>
+where
+    K: 'static + KeyStore + Clone,
+{
+    key_store: K,
+    limits: PayloadLimits,
+}
+
+impl<K> SignBatchHandler<K>
+where
+    K: 'static + KeyStore + Clone,
+{
+    pub fn new(key_store: K) -> Self {
+        SignBatchHandler {
+            key_store,
+            limits: PayloadLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: PayloadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Signs each request of the batch in order. Each key is looked up only once,
+/// however many requests use it; a failed lookup fails every request that uses
+/// that key.
+fn sign_batch<K: KeyStore>(
+    key_store: &K,
+    id: &str,
+    genid: &str,
+    requests: &[SignRequest],
+    limits: &PayloadLimits,
+) -> Vec<SignBatchResult> {
+    let mut keys: HashMap<String, Result<K::Key, ErrorKind>> = HashMap::new();
+    requests
+        .iter()
+        .map(|request| {
+            let digest = validate_key_id(request.key_id())
+                .and_then(|_| sign_input(request, limits))
+                .and_then(|(algorithm, data)| {
+                    let key_id = format!("{}{}", request.key_id(), genid);
+                    let key = keys.entry(key_id.clone()).or_insert_with(|| {
+                        module_key(key_store, id, &key_id).map_err(|err| *err.kind())
+                    });
+                    match *key {
+                        Ok(ref key) => sign_data(key, algorithm, &data),
+                        Err(kind) => Err(Error::from(kind)),
+                    }
+                });
+            match digest {
+                Ok(digest) => SignBatchResult::new().with_digest(digest),
+                Err(err) => SignBatchResult::new().with_error(err.to_error_response()),
+            }
+        }).collect()
+}
+
+impl<K> Handler<Parameters> for SignBatchHandler<K>
+where
+    K: 'static + KeyStore + Clone + Send,
+{
+    fn handle(
+        &self,
+        req: Request<Body>,
+        params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let response = match params
+            .name("name")
+            .ok_or_else(|| Error::from(ErrorKind::BadParam))
+            .and_then(|name| {
+                params
+                    .name("genid")
+                    .ok_or_else(|| Error::from(ErrorKind::BadParam))
+                    .map(|genid| (name, genid))
+            }) {
+            Ok((name, genid)) => {
+                let id = name.to_string();
+                let genid = genid.to_string();
+                let key_store = self.key_store.clone();
+                let limits = self.limits;
+                let ok = read_body(req.into_body(), max_sign_batch_request_size(&limits))
+                    .and_then(move |b| {
+                        let request = serde_json::from_slice::<SignBatchRequest>(&b)
+                            .context(ErrorKind::BadBody)?;
+                        if request.requests().len() > limits.max_sign_batch_size() {
+                            return Err(Error::from(ErrorKind::BatchTooLarge(
+                                limits.max_sign_batch_size(),
+                            )));
+                        }
+                        let results =
+                            sign_batch(&key_store, &id, &genid, request.requests(), &limits);
+                        let b = serde_json::to_string(&SignBatchResponse::new(results))
+                            .context(ErrorKind::Serde)?;
+                        let response = Response::builder()
+                            .status(StatusCode::OK)
+                            .header(CONTENT_TYPE, "application/json")
+                            .header(CONTENT_LENGTH, b.len().to_string().as_str())
+                            .body(b.into())?;
+                        Ok(response)
+                    }).or_else(|e| future::ok(e.into_response()));
+                future::Either::A(ok)
+            }
+            Err(e) => future::Either::B(future::ok(e.into_response())),
+        };
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use base64;
+    use edgelet_core::crypto::{KeyIdentity, MemoryKey};
+    use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
+    use futures::Stream;
+    use workload::models::ErrorResponse;
+
+    use super::*;
+
+    /// Has the primary keys of every module, but no secondary keys, and
+    /// records the keys it was asked for.
+    #[derive(Clone, Debug)]
+    struct TestKeyStore {
+        key: MemoryKey,
+        lookups: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestKeyStore {
+        fn new(key: MemoryKey) -> Self {
+            TestKeyStore {
+                key,
+                lookups: Arc::new(Mutex::new(vec![])),
+            }
+        }
+    }
+
+    impl KeyStore for TestKeyStore {
+        type Key = MemoryKey;
+
+        fn get(&self, _identity: &KeyIdentity, key_name: &str) -> Result<Self::Key, CoreError> {
+            self.lookups.lock().unwrap().push(key_name.to_string());
+            if key_name.starts_with("primary") {
+                Ok(self.key.clone())
+            } else {
+                Err(CoreError::from(CoreErrorKind::NotFound))
+            }
+        }
+    }
+
+    const DATA: &str = "The quick brown fox jumps over the lazy dog";
+    const DIGEST: &str = "97yD9DBThCSxMpjmqm+xQ+9NWaFJRhdZl0edvC0aPNg=";
+    const BASE64_ERROR: &str =
+        "Invalid base64 string\n\tcaused by: Encoded text cannot have a 6-bit remainder.";
+
+    fn sign_request(key_id: &str) -> SignRequest {
+        SignRequest::new(key_id.to_string(), base64::encode(DATA))
+    }
+
+    fn post(handler: &SignBatchHandler<TestKeyStore>, body: String) -> Response<Body> {
+        let parameters = Parameters::with_captures(vec![
+            (Some("name".to_string()), "test".to_string()),
+            (Some("genid".to_string()), "g1".to_string()),
+        ]);
+        let request = Request::post("http://localhost/modules/test/genid/g1/sign/batch")
+            .body(body.into())
+            .unwrap();
+        handler.handle(request, parameters).wait().unwrap()
+    }
+
+    fn results(response: Response<Body>) -> Vec<(Option<String>, Option<String>)> {
+        let b = response.into_body().concat2().wait().unwrap();
+        let response: SignBatchResponse = serde_json::from_slice(&b).unwrap();
+        response
+            .results()
+            .iter()
+            .map(|result| {
+                (
+                    result.digest().map(ToString::to_string),
+                    result.error().map(|error| error.message().to_string()),
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn mixed_batch() {
+        // arrange
+        let store = TestKeyStore::new(MemoryKey::new("key"));
+        let handler = SignBatchHandler::new(store.clone());
+        let batch = SignBatchRequest::new(vec![
+            sign_request("primary"),
+            sign_request("secondary"),
+            sign_request("tertiary"),
+            sign_request("primary").with_algo("MD5".to_string()),
+            sign_request("primary").with_data("alsjdfasf".to_string()),
+            sign_request("primary"),
+        ]);
+        let body = serde_json::to_string(&batch).unwrap();
+
+        // act
+        let response = post(&handler, body);
+
+        // assert
+        assert_eq!(StatusCode::OK, response.status());
+        let error = |message: &str| (None, Some(message.to_string()));
+        assert_eq!(
+            vec![
+                (Some(DIGEST.to_string()), None),
+                error("Module not found"),
+                error("Invalid key identifier"),
+                error("Invalid sign algorithm"),
+                error(BASE64_ERROR),
+                (Some(DIGEST.to_string()), None),
+            ],
+            results(response)
+        );
+
+        // The primary key was looked up once for both requests that use it.
+        assert_eq!(
+            vec!["primaryg1".to_string(), "secondaryg1".to_string()],
+            *store.lookups.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn empty_batch() {
+        let handler = SignBatchHandler::new(TestKeyStore::new(MemoryKey::new("key")));
+        let body = serde_json::to_string(&SignBatchRequest::new(vec![])).unwrap();
+
+        let response = post(&handler, body);
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(results(response).is_empty());
+    }
+
+    #[test]
+    fn batch_too_large() {
+        // arrange
+        let store = TestKeyStore::new(MemoryKey::new("key"));
+        let handler = SignBatchHandler::new(store.clone())
+            .with_limits(PayloadLimits::default().with_max_sign_batch_size(2));
+        let at_limit =
+            SignBatchRequest::new(vec![sign_request("primary"), sign_request("primary")]);
+        let over_limit = SignBatchRequest::new(vec![
+            sign_request("primary"),
+            sign_request("primary"),
+            sign_request("primary"),
+        ]);
+
+        // act
+        let ok = post(&handler, serde_json::to_string(&at_limit).unwrap());
+        let too_large = post(&handler, serde_json::to_string(&over_limit).unwrap());
+
+        // assert
+        assert_eq!(StatusCode::OK, ok.status());
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, too_large.status());
+        let b = too_large.into_body().concat2().wait().unwrap();
+        let error_response: ErrorResponse = serde_json::from_slice(&b).unwrap();
+        assert_eq!(
+            "The batch exceeds the maximum of 2 sign requests",
+            error_response.message()
+        );
+
+        // Batches over the limit are rejected before any key is looked up.
+        assert_eq!(1, store.lookups.lock().unwrap().len());
+    }
+
+    #[test]
+    fn bad_body() {
+        let handler = SignBatchHandler::new(TestKeyStore::new(MemoryKey::new("key")));
+
+        let response = post(&handler, "[]".to_string());
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[test]
+    fn bad_params() {
+        let handler = SignBatchHandler::new(TestKeyStore::new(MemoryKey::new("key")));
+        let request = Request::post("http://localhost/modules/test/genid/g1/sign/batch")
+            .body(Body::from("{\"requests\":[]}"))
+            .unwrap();
+
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+}
@@ -1,5 +1,7 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::fs;
+use std::path::Path;
 use std::str;
 
 use failure::ResultExt;
@@ -7,48 +9,68 @@ use futures::{future, Future};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use http::{Request, Response, StatusCode};
 use hyper::{Body, Error as HyperError};
+use openssl::x509::{X509, X509VerifyResult};
 use serde_json;
+use url::form_urlencoded;
 
-use edgelet_core::{Certificate, GetTrustBundle};
+use edgelet_core::{Certificate, GetTrustBundle, WorkloadConfig};
 use edgelet_http::route::{Handler, Parameters};
 use workload::models::TrustBundleResponse;
 
-use error::{Error, ErrorKind};
+use error::{Error, ErrorKind, Result};
 use IntoResponse;
 
-pub struct TrustBundleHandler<T: GetTrustBundle> {
+pub struct TrustBundleHandler<T: GetTrustBundle, W: WorkloadConfig> {
     hsm: T,
+    config: W,
 }
 
-impl<T> TrustBundleHandler<T>
+impl<T, W> TrustBundleHandler<T, W>
 where
     T: 'static + GetTrustBundle + Clone,
+    W: WorkloadConfig,
 {
-    pub fn new(hsm: T) -> Self {
-        TrustBundleHandler { hsm }
+    pub fn new(hsm: T, config: W) -> Self {
+        TrustBundleHandler { hsm, config }
     }
 }
 
-impl<T> Handler<Parameters> for TrustBundleHandler<T>
+impl<T, W> Handler<Parameters> for TrustBundleHandler<T, W>
 where
     T: 'static + GetTrustBundle + Send,
     <T as GetTrustBundle>::Certificate: Certificate,
+    W: 'static + WorkloadConfig + Send,
 {
     fn handle(
         &self,
-        _req: Request<Body>,
+        req: Request<Body>,
         _params: Parameters,
     ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
-        let response = self
-            .hsm
-            .get_trust_bundle()
-            .and_then(|cert| cert.pem())
-            .map_err(Error::from)
-            .and_then(|cert| {
-                str::from_utf8(cert.as_ref())
+        let response = req
+            .uri()
+            .query()
+            .map_or_else(|| Ok(false), parse_full)
+            .and_then(|full| {
+                self.hsm
+                    .get_trust_bundle()
+                    .and_then(|cert| cert.pem())
+                    .map_err(Error::from)
+                    .map(|cert| (full, cert))
+            }).and_then(|(full, cert)| {
+                let mut certs = str::from_utf8(cert.as_ref())
                     .context(ErrorKind::Utf8)
-                    .map_err(From::from)
-                    .map(|s| s.to_string())
+                    .map_err(Error::from)
+                    .and_then(|s| {
+                        X509::stack_from_pem(s.as_bytes())
+                            .context(ErrorKind::BadCertificate)
+                            .map_err(Error::from)
+                    })?;
+                if full {
+                    if let Some(dir) = self.config.additional_trusted_ca_dir() {
+                        certs.extend(read_trusted_certs(dir));
+                    }
+                }
+                chain_to_pem(order_chain(certs))
             }).and_then(|cert| {
                 serde_json::to_string(&TrustBundleResponse::new(cert))
                     .context(ErrorKind::Serde)
@@ -66,16 +88,255 @@ where
     }
 }
 
+fn parse_full(query: &str) -> Result<bool> {
+    form_urlencoded::parse(query.as_bytes())
+        .find(|&(ref key, _)| key == "full")
+        .map_or_else(
+            || Ok(false),
+            |(_, val)| {
+                val.parse::<bool>()
+                    .context(ErrorKind::BadParam)
+                    .map_err(Error::from)
+            },
+        )
+}
+
+/// Reads the PEM encoded certificates in `dir`. Files that can't be read or
+/// don't hold valid certificates are skipped with a warning.
+fn read_trusted_certs(dir: &Path) -> Vec<X509> {
+    let mut paths = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            warn!(
+                "Could not read trusted CA certificates directory {}: {}",
+                dir.display(),
+                err
+            );
+            return vec![];
+        }
+    };
+    paths.sort();
+
+    let mut certs = vec![];
+    for path in paths {
+        match fs::read(&path).map(|pem| X509::stack_from_pem(&pem)) {
+            Ok(Ok(ref parsed)) if !parsed.is_empty() => certs.extend(parsed.iter().cloned()),
+            _ => warn!(
+                "Skipping {}: not a valid PEM encoded certificate",
+                path.display()
+            ),
+        }
+    }
+    certs
+}
+
+/// Removes duplicate certificates and orders them so that every chain starts
+/// with the lowest CA certificate and is followed by its issuers up to the root.
+fn order_chain(certs: Vec<X509>) -> Vec<X509> {
+    let mut unique: Vec<X509> = vec![];
+    let mut seen: Vec<Vec<u8>> = vec![];
+    for cert in certs {
+        if let Ok(der) = cert.to_der() {
+            if !seen.contains(&der) {
+                seen.push(der);
+                unique.push(cert);
+            }
+        }
+    }
+
+    let issued_by = |issuer: usize, subject: usize| {
+        issuer != subject && unique[issuer].issued(&unique[subject]) == X509VerifyResult::OK
+    };
+
+    let mut ordered = vec![];
+    let mut visited = vec![false; unique.len()];
+    for leaf in 0..unique.len() {
+        if (0..unique.len()).any(|other| issued_by(leaf, other)) {
+            continue;
+        }
+
+        let mut current = Some(leaf);
+        while let Some(index) = current {
+            if visited[index] {
+                break;
+            }
+            visited[index] = true;
+            ordered.push(index);
+            current = (0..unique.len()).find(|&issuer| issued_by(issuer, index));
+        }
+    }
+
+    // anything left over is part of an issuer cycle; keep the original order
+    ordered.extend((0..unique.len()).filter(|&index| !visited[index]));
+
+    let mut unique: Vec<Option<X509>> = unique.into_iter().map(Some).collect();
+    ordered
+        .into_iter()
+        .filter_map(|index| unique[index].take())
+        .collect()
+}
+
+fn chain_to_pem(certs: Vec<X509>) -> Result<String> {
+    let mut bundle = String::new();
+    for cert in certs {
+        let pem = cert.to_pem().context(ErrorKind::BadCertificate)?;
+        bundle.push_str(
+            str::from_utf8(&pem)
+                .context(ErrorKind::Utf8)
+                .map_err(Error::from)?,
+        );
+    }
+    Ok(bundle)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::result::Result as StdResult;
+
     use futures::Future;
     use futures::Stream;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::X509NameBuilder;
+    use tempfile::TempDir;
 
-    use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
+    use edgelet_core::{
+        CertificateType, Error as CoreError, ErrorKind as CoreErrorKind, PayloadLimits,
+    };
     use edgelet_test_utils::cert::TestCert;
 
     use super::*;
 
+    #[derive(Clone, Default)]
+    struct TestWorkloadConfig {
+        trusted_ca_dir: Option<PathBuf>,
+    }
+
+    impl WorkloadConfig for TestWorkloadConfig {
+        fn iot_hub_name(&self) -> &str {
+            "zaphods_hub"
+        }
+
+        fn device_id(&self) -> &str {
+            "marvins_device"
+        }
+
+        fn get_cert_max_duration(&self, _cert_type: CertificateType) -> i64 {
+            7200
+        }
+
+        fn allow_wildcard_sans(&self) -> bool {
+            false
+        }
+
+        fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+            self.trusted_ca_dir.as_ref().map(AsRef::as_ref)
+        }
+
+        fn payload_limits(&self) -> PayloadLimits {
+            PayloadLimits::default()
+        }
+
+        fn allow_host_processes(&self) -> bool {
+            false
+        }
+
+        fn device_identity_cert(&self) -> Option<&str> {
+            None
+        }
+
+        fn leaf_cert_issuers(&self) -> &[String] {
+            &[]
+        }
+
+        fn manifest_trust_bundle(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    struct TestCa {
+        cert: X509,
+        key: PKey<Private>,
+    }
+
+    fn make_ca(common_name: &str, serial: u32, issuer: Option<&TestCa>) -> TestCa {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder
+            .set_issuer_name(issuer.map_or(&name, |ca| ca.cert.subject_name()))
+            .unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder
+            .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+            .unwrap();
+        builder
+            .sign(issuer.map_or(&key, |ca| &ca.key), MessageDigest::sha256())
+            .unwrap();
+
+        TestCa {
+            cert: builder.build(),
+            key,
+        }
+    }
+
+    fn three_tier_chain() -> (TestCa, TestCa, TestCa) {
+        let root = make_ca("root", 1, None);
+        let intermediate = make_ca("intermediate", 2, Some(&root));
+        let device = make_ca("device", 3, Some(&intermediate));
+        (root, intermediate, device)
+    }
+
+    fn pem(cas: &[&TestCa]) -> String {
+        cas.iter()
+            .map(|ca| String::from_utf8(ca.cert.to_pem().unwrap()).unwrap())
+            .collect()
+    }
+
+    fn get_bundle(hsm: TestHsm, config: TestWorkloadConfig, uri: &str) -> String {
+        let handler = TrustBundleHandler::new(hsm, config);
+        let request = Request::get(uri).body("".into()).unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        response
+            .into_body()
+            .concat2()
+            .map(|b| {
+                let trust_bundle: TrustBundleResponse = serde_json::from_slice(&b).unwrap();
+                trust_bundle.certificate().to_string()
+            }).wait()
+            .unwrap()
+    }
+
     #[derive(Clone, Default, Debug)]
     struct TestHsm {
         fail_call: bool,
@@ -97,7 +358,7 @@ mod tests {
     impl GetTrustBundle for TestHsm {
         type Certificate = TestCert;
 
-        fn get_trust_bundle(&self) -> Result<Self::Certificate, CoreError> {
+        fn get_trust_bundle(&self) -> StdResult<Self::Certificate, CoreError> {
             if self.fail_call {
                 Err(CoreError::from(CoreErrorKind::Io))
             } else {
@@ -108,7 +369,10 @@ mod tests {
 
     #[test]
     fn get_fail() {
-        let handler = TrustBundleHandler::new(TestHsm::default().with_fail_call(true));
+        let handler = TrustBundleHandler::new(
+            TestHsm::default().with_fail_call(true),
+            TestWorkloadConfig::default(),
+        );
         let request = Request::get("http://localhost/trust-bundle")
             .body("".into())
             .unwrap();
@@ -120,6 +384,7 @@ mod tests {
     fn pem_fail() {
         let handler = TrustBundleHandler::new(
             TestHsm::default().with_cert(TestCert::default().with_fail_pem(true)),
+            TestWorkloadConfig::default(),
         );
         let request = Request::get("http://localhost/trust-bundle")
             .body("".into())
@@ -132,6 +397,7 @@ mod tests {
     fn utf8_decode_fail() {
         let handler = TrustBundleHandler::new(
             TestHsm::default().with_cert(TestCert::default().with_cert(vec![0, 159, 146, 150])),
+            TestWorkloadConfig::default(),
         );
         let request = Request::get("http://localhost/trust-bundle")
             .body("".into())
@@ -142,8 +408,10 @@ mod tests {
 
     #[test]
     fn success() {
+        let root = make_ca("root", 1, None);
         let handler = TrustBundleHandler::new(
-            TestHsm::default().with_cert(TestCert::default().with_cert(b"boo".to_vec())),
+            TestHsm::default().with_cert(TestCert::default().with_cert(pem(&[&root]).into_bytes())),
+            TestWorkloadConfig::default(),
         );
         let request = Request::get("http://localhost/trust-bundle")
             .body("".into())
@@ -169,9 +437,99 @@ mod tests {
             .and_then(|b| {
                 assert_eq!(content_length, b.len().to_string());
                 let trust_bundle: TrustBundleResponse = serde_json::from_slice(&b).unwrap();
-                assert_eq!("boo", trust_bundle.certificate().as_str());
+                assert_eq!(pem(&[&root]), trust_bundle.certificate().as_str());
                 Ok(())
             }).wait()
             .unwrap();
     }
+
+    #[test]
+    fn bad_full_param() {
+        let handler = TrustBundleHandler::new(TestHsm::default(), TestWorkloadConfig::default());
+        let request = Request::get("http://localhost/trust-bundle?full=maybe")
+            .body("".into())
+            .unwrap();
+        let response = handler.handle(request, Parameters::new()).wait().unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+
+    #[test]
+    fn orders_chain_leaf_ca_first() {
+        let (root, intermediate, device) = three_tier_chain();
+        let bundle = pem(&[&root, &device, &intermediate]);
+        let hsm = TestHsm::default().with_cert(TestCert::default().with_cert(bundle.into_bytes()));
+
+        let actual = get_bundle(
+            hsm,
+            TestWorkloadConfig::default(),
+            "http://localhost/trust-bundle",
+        );
+
+        assert_eq!(pem(&[&device, &intermediate, &root]), actual);
+    }
+
+    #[test]
+    fn removes_duplicates() {
+        let (root, intermediate, device) = three_tier_chain();
+        let bundle = pem(&[&intermediate, &root, &device, &root, &intermediate]);
+        let hsm = TestHsm::default().with_cert(TestCert::default().with_cert(bundle.into_bytes()));
+
+        let actual = get_bundle(
+            hsm,
+            TestWorkloadConfig::default(),
+            "http://localhost/trust-bundle",
+        );
+
+        assert_eq!(pem(&[&device, &intermediate, &root]), actual);
+    }
+
+    #[test]
+    fn full_includes_additional_certs() {
+        let (root, intermediate, device) = three_tier_chain();
+        let other = make_ca("other", 4, None);
+
+        let dir = TempDir::new().unwrap();
+        File::create(dir.path().join("a.pem"))
+            .unwrap()
+            .write_all(pem(&[&other, &root]).as_bytes())
+            .unwrap();
+        File::create(dir.path().join("b.pem"))
+            .unwrap()
+            .write_all(b"-----BEGIN CERTIFICATE-----\nnot a cert\n-----END CERTIFICATE-----\n")
+            .unwrap();
+        File::create(dir.path().join("c.txt"))
+            .unwrap()
+            .write_all(b"hello")
+            .unwrap();
+        File::create(dir.path().join("d.pem"))
+            .unwrap()
+            .write_all(pem(&[&intermediate]).as_bytes())
+            .unwrap();
+
+        let config = TestWorkloadConfig {
+            trusted_ca_dir: Some(dir.path().to_path_buf()),
+        };
+        let bundle = pem(&[&device, &root]);
+        let hsm = TestHsm::default().with_cert(TestCert::default().with_cert(bundle.into_bytes()));
+
+        let partial = get_bundle(hsm.clone(), config.clone(), "http://localhost/trust-bundle");
+        assert_eq!(pem(&[&device, &root]), partial);
+
+        let full = get_bundle(hsm, config, "http://localhost/trust-bundle?full=true");
+        assert_eq!(pem(&[&device, &intermediate, &root, &other]), full);
+    }
+
+    #[test]
+    fn full_skips_missing_directory() {
+        let (root, intermediate, device) = three_tier_chain();
+        let config = TestWorkloadConfig {
+            trusted_ca_dir: Some(PathBuf::from("/this/path/does/not/exist")),
+        };
+        let bundle = pem(&[&device, &intermediate, &root]);
+        let hsm = TestHsm::default().with_cert(TestCert::default().with_cert(bundle.into_bytes()));
+
+        let actual = get_bundle(hsm, config, "http://localhost/trust-bundle?full=true");
+
+        assert_eq!(pem(&[&device, &intermediate, &root]), actual);
+    }
 }
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use edgelet_core::pid::Pid;
-use edgelet_core::{Authorization as CoreAuth, Error as CoreError, Module, ModuleRuntime, Policy};
+use edgelet_core::{Authorization as CoreAuth, PidResolver, Policy};
 use error::{Error, ErrorKind};
 use futures::{future, Future};
 use hyper::{self, Body, Request, Response};
@@ -9,36 +9,37 @@ use route::{Handler, Parameters};
 use std::sync::Arc;
 use IntoResponse;
 
-pub struct Authorization<H, M>
+pub struct Authorization<H, R>
 where
     H: Handler<Parameters>,
-    M: 'static + ModuleRuntime,
+    R: 'static + PidResolver,
 {
-    auth: CoreAuth<M>,
+    auth: CoreAuth<R>,
     inner: Arc<H>,
 }
 
-impl<H, M> Authorization<H, M>
+impl<H, R> Authorization<H, R>
 where
     H: Handler<Parameters>,
-    M: 'static + ModuleRuntime,
-    M::Error: Into<CoreError>,
-    <M::Module as Module>::Error: Into<CoreError>,
+    R: 'static + PidResolver,
 {
-    pub fn new(inner: H, policy: Policy, runtime: M) -> Self {
+    pub fn new(inner: H, policy: Policy, resolver: R) -> Self {
         Authorization {
-            auth: CoreAuth::new(runtime, policy),
+            auth: CoreAuth::new(resolver, policy),
             inner: Arc::new(inner),
         }
     }
+
+    pub fn with_allow_host_processes(mut self, allow_host_processes: bool) -> Self {
+        self.auth = self.auth.with_allow_host_processes(allow_host_processes);
+        self
+    }
 }
 
-impl<H, M> Handler<Parameters> for Authorization<H, M>
+impl<H, R> Handler<Parameters> for Authorization<H, R>
 where
     H: Handler<Parameters> + Sync,
-    M: 'static + ModuleRuntime + Send,
-    M::Error: Into<CoreError>,
-    <M::Module as Module>::Error: Into<CoreError>,
+    R: 'static + PidResolver + Send + Sync,
 {
     fn handle(
         &self,
@@ -62,7 +63,7 @@ where
                 if authorized {
                     future::Either::A(inner.handle(req, params).map_err(Error::from))
                 } else {
-                    future::Either::B(future::err(Error::from(ErrorKind::NotFound)))
+                    future::Either::B(future::err(Error::from(ErrorKind::Forbidden)))
                 }
             }).or_else(|e| future::ok(e.into_response()));
 
@@ -75,7 +76,10 @@ mod tests {
     use std::time::Duration;
 
     use super::*;
-    use edgelet_core::{LogOptions, ModuleRegistry, ModuleRuntimeState, ModuleSpec, SystemInfo};
+    use edgelet_core::{
+        IntegrityReport, LogOptions, Module, ModuleInspection, ModuleRegistry, ModuleRuntime,
+        ModuleRuntimeState, ModuleSpec, StopAllOptions, SystemInfo,
+    };
     use futures::future::FutureResult;
     use futures::stream::Empty;
     use futures::{stream, Stream};
@@ -102,7 +106,7 @@ mod tests {
     }
 
     #[test]
-    fn handler_responds_with_not_found_when_not_authorized() {
+    fn handler_responds_with_forbidden_when_not_authorized() {
         let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
         let params = Parameters::with_captures(vec![(Some("name".to_string()), "xyz".to_string())]);
         let mut request = Request::default();
@@ -110,11 +114,11 @@ mod tests {
 
         let auth = Authorization::new(TestHandler::new(), Policy::Caller, runtime);
         let response = auth.handle(request, params).wait().unwrap();
-        assert_eq!(404, response.status());
+        assert_eq!(403, response.status());
     }
 
     #[test]
-    fn handler_responds_with_not_found_when_name_is_omitted() {
+    fn handler_responds_with_forbidden_when_name_is_omitted() {
         let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
         let params = Parameters::with_captures(vec![]);
         let mut request = Request::default();
@@ -122,11 +126,11 @@ mod tests {
 
         let auth = Authorization::new(TestHandler::new(), Policy::Caller, runtime);
         let response = auth.handle(request, params).wait().unwrap();
-        assert_eq!(404, response.status());
+        assert_eq!(403, response.status());
     }
 
     #[test]
-    fn handler_responds_with_not_found_when_pid_is_omitted() {
+    fn handler_responds_with_forbidden_when_pid_is_omitted() {
         let runtime = TestModuleList::new(vec![TestModule::new("abc", 123)]);
         let params = Parameters::with_captures(vec![(Some("name".to_string()), "abc".to_string())]);
         let mut request = Request::default();
@@ -134,7 +138,7 @@ mod tests {
 
         let auth = Authorization::new(TestHandler::new(), Policy::Caller, runtime);
         let response = auth.handle(request, params).wait().unwrap();
-        assert_eq!(404, response.status());
+        assert_eq!(403, response.status());
     }
 
     #[test]
@@ -146,7 +150,7 @@ mod tests {
         )]);
         let params = Parameters::with_captures(vec![(Some("name".to_string()), "abc".to_string())]);
         let mut request = Request::default();
-        request.extensions_mut().insert(Pid::None);
+        request.extensions_mut().insert(Pid::Value(123));
 
         let auth = Authorization::new(TestHandler::new(), Policy::Caller, runtime);
         let response = auth.handle(request, params).wait().unwrap();
@@ -283,8 +287,14 @@ mod tests {
         type RestartFuture = FutureResult<(), Self::Error>;
         type StartFuture = FutureResult<(), Self::Error>;
         type StopFuture = FutureResult<(), Self::Error>;
+        type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
         type SystemInfoFuture = FutureResult<SystemInfo, Self::Error>;
         type RemoveAllFuture = FutureResult<(), Self::Error>;
+        type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+        type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+        type KillFuture = FutureResult<(), Self::Error>;
+        type StopAllFuture = FutureResult<(), Self::Error>;
+        type UpdateFuture = FutureResult<(), Self::Error>;
 
         fn init(&self) -> Self::InitFuture {
             notimpl_error!()
@@ -310,6 +320,10 @@ mod tests {
             notimpl_error!()
         }
 
+        fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
+            notimpl_error!()
+        }
+
         fn remove(&self, _id: &str) -> Self::RemoveFuture {
             notimpl_error!()
         }
@@ -338,5 +352,25 @@ mod tests {
         fn remove_all(&self) -> Self::RemoveAllFuture {
             notimpl_error!()
         }
+
+        fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+            notimpl_error!()
+        }
+
+        fn inspect(&self, _id: &str) -> Self::InspectFuture {
+            notimpl_error!()
+        }
+
+        fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+            notimpl_error!()
+        }
+
+        fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+            notimpl_error!()
+        }
+
+        fn top(&self, _id: &str) -> Self::TopFuture {
+            notimpl_error!()
+        }
     }
 }
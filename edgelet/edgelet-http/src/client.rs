@@ -2,22 +2,34 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration as StdDuration;
 
 use chrono::{DateTime, Duration, Utc};
+use edgelet_core::{Clock, SystemClock};
+use failure::Fail;
+use futures::future::Either;
 use futures::{Future, IntoFuture, Stream};
-use hyper::{self, Body, Error as HyperError, Method, Request, Response};
+use hyper::{self, Body, Chunk, Error as HyperError, Method, Request, Response};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json;
+use tokio::timer::Timeout;
 use typed_headers::{http, mime, ContentLength, ContentType, HeaderMapExt};
 use url::form_urlencoded::Serializer as UrlSerializer;
 use url::Url;
 
-use error::Error;
+use error::{Error, ErrorKind};
+use util::timeout::is_timeout;
+
+/// How long SAS tokens are valid for unless the client is told otherwise.
+pub const DEFAULT_TOKEN_TTL_SECS: u64 = 3600;
 
 pub trait TokenSource {
     type Error;
     fn get(&self, expiry: &DateTime<Utc>) -> Result<String, Self::Error>;
+
+    /// Forgets any token handed out before, because the service rejected it.
+    fn invalidate(&self) {}
 }
 
 pub trait ClientImpl: Send + Sync {
@@ -58,6 +70,9 @@ pub struct Client<C, T> {
     api_version: String,
     host_name: Url,
     user_agent: Option<String>,
+    request_timeout: Option<StdDuration>,
+    token_ttl: StdDuration,
+    clock: Arc<Clock>,
 }
 
 impl<C, T> Client<C, T>
@@ -78,6 +93,9 @@ where
             api_version: ensure_not_empty!(api_version).to_string(),
             host_name,
             user_agent: None,
+            request_timeout: None,
+            token_ttl: StdDuration::from_secs(DEFAULT_TOKEN_TTL_SECS),
+            clock: Arc::new(SystemClock),
         };
 
         Ok(client)
@@ -93,6 +111,24 @@ where
         self
     }
 
+    /// Fails requests that don't complete, body included, within `timeout`.
+    pub fn with_request_timeout(mut self, timeout: StdDuration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Asks the token source for tokens that are valid for `ttl`.
+    pub fn with_token_ttl(mut self, ttl: StdDuration) -> Self {
+        self.token_ttl = ttl;
+        self
+    }
+
+    /// Sets the clock the expiry of the tokens asked for is counted from.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn inner(&self) -> &C {
         &self.inner
     }
@@ -109,10 +145,20 @@ where
         &self.host_name
     }
 
+    pub fn request_timeout(&self) -> Option<StdDuration> {
+        self.request_timeout
+    }
+
+    pub fn token_ttl(&self) -> StdDuration {
+        self.token_ttl
+    }
+
     fn add_sas_token(&self, req: &mut Request<Body>, path: &str) -> Result<(), Error> {
         if let Some(ref source) = self.token_source {
-            let token_duration = Duration::hours(1);
-            let expiry = Utc::now() + token_duration;
+            let expiry = Duration::from_std(self.token_ttl)
+                .ok()
+                .and_then(|ttl| self.clock.now().checked_add_signed(ttl))
+                .ok_or(ErrorKind::TokenSource)?;
             let token = source.get(&expiry).map_err(|err| err.into())?;
             debug!(
                 "Success generating token for request {} {}",
@@ -141,6 +187,71 @@ where
     where
         BodyT: Serialize,
         ResponseT: 'static + DeserializeOwned,
+    {
+        // add an `If-Match: "*"` header if we've been asked to
+        let precondition = if add_if_match {
+            Some((http::header::IF_MATCH, "*"))
+        } else {
+            None
+        };
+        self.send(method, path, query, body, precondition)
+            .and_then(|(_, _, body)| parse_body(&body))
+    }
+
+    /// Sends the request only for the resource to be acted on if its entity
+    /// tag is still `etag`. The service answers with a 412 otherwise.
+    pub fn request_if_match<BodyT, ResponseT>(
+        &self,
+        method: Method,
+        path: &str,
+        query: Option<HashMap<&str, &str>>,
+        body: Option<BodyT>,
+        etag: &str,
+    ) -> impl Future<Item = Option<ResponseT>, Error = Error>
+    where
+        BodyT: Serialize,
+        ResponseT: 'static + DeserializeOwned,
+    {
+        self.send(method, path, query, body, Some((http::header::IF_MATCH, etag)))
+            .and_then(|(_, _, body)| parse_body(&body))
+    }
+
+    /// Gets `path` unless its entity tag is still `etag`, in which case the
+    /// service answers with a 304 and there is no body to read.
+    pub fn request_if_none_match<ResponseT>(
+        &self,
+        path: &str,
+        query: Option<HashMap<&str, &str>>,
+        etag: Option<&str>,
+    ) -> impl Future<Item = Conditional<ResponseT>, Error = Error>
+    where
+        ResponseT: 'static + DeserializeOwned,
+    {
+        let precondition = etag.map(|etag| (http::header::IF_NONE_MATCH, etag));
+        self.send::<()>(Method::GET, path, query, None, precondition)
+            .and_then(|(status, headers, body)| {
+                if status == hyper::StatusCode::NOT_MODIFIED {
+                    Ok(Conditional::NotModified)
+                } else {
+                    let etag = headers
+                        .get(http::header::ETAG)
+                        .and_then(|etag| etag.to_str().ok())
+                        .map(ToString::to_string);
+                    parse_body(&body).map(|body| Conditional::Modified(body, etag))
+                }
+            })
+    }
+
+    fn send<BodyT>(
+        &self,
+        method: Method,
+        path: &str,
+        query: Option<HashMap<&str, &str>>,
+        body: Option<BodyT>,
+        precondition: Option<(http::header::HeaderName, &str)>,
+    ) -> impl Future<Item = (hyper::StatusCode, http::HeaderMap, Chunk), Error = Error>
+    where
+        BodyT: Serialize,
     {
         // append api-version to the query string and url encode it
         let query = query
@@ -151,6 +262,7 @@ where
                 |ser, (key, val)| ser.append_pair(key, val),
             ).finish();
 
+        let token_source = self.token_source.clone();
         self.host_name
             // build the full url
             .join(&format!("{}?{}", path, query))
@@ -164,9 +276,8 @@ where
                     req.header(http::header::USER_AGENT, &**user_agent);
                 }
 
-                // add an `If-Match: "*"` header if we've been asked to
-                if add_if_match {
-                    req.header(http::header::IF_MATCH, "*");
+                if let Some((name, value)) = precondition {
+                    req.header(name, value);
                 }
 
                 // add request body if there is any
@@ -188,36 +299,103 @@ where
 
                 Ok(req)
             }).map(|req| {
-                self.inner
+                let response = self
+                    .inner
                     .call(req)
                     .map_err(|e| {
                         error!("{:?}", e);
-                        Error::from(e)
+                        if is_timeout(&e) {
+                            Error::from(e.context(ErrorKind::Timeout))
+                        } else {
+                            Error::from(e)
+                        }
                     }).and_then(|resp| {
-                        let (http::response::Parts { status, .. }, body) = resp.into_parts();
+                        let (http::response::Parts { status, headers, .. }, body) =
+                            resp.into_parts();
                         body.concat2()
-                            .and_then(move |body| Ok((status, body)))
+                            .and_then(move |body| Ok((status, headers, body)))
                             .map_err(<Error as From<HyperError>>::from)
-                    }).and_then(|(status, body)| {
-                        if status.is_success() {
-                            Ok(body)
-                        } else {
-                            Err(Error::from((status, &*body)))
-                        }
-                    }).and_then(|body| {
-                        if body.len() == 0 {
-                            Ok(None)
+                    }).and_then(move |(status, headers, body)| {
+                        // A 304 is only ever the answer to a conditional
+                        // request, which expects it.
+                        if status.is_success() || status == hyper::StatusCode::NOT_MODIFIED {
+                            Ok((status, headers, body))
                         } else {
-                            serde_json::from_slice::<ResponseT>(&body)
-                                .map_err(Error::from)
-                                .map(Option::Some)
+                            if status == hyper::StatusCode::UNAUTHORIZED {
+                                if let Some(token_source) = token_source {
+                                    token_source.invalidate();
+                                }
+                            }
+                            match retry_after(&headers) {
+                                Some(delay) => Err(Error::from((status, &*body, delay))),
+                                None => Err(Error::from((status, &*body))),
+                            }
                         }
-                    })
+                    });
+
+                match self.request_timeout {
+                    Some(timeout) => Either::A(with_timeout(response, timeout)),
+                    None => Either::B(response),
+                }
             }).into_future()
             .flatten()
     }
 }
 
+/// The answer to a conditional request.
+#[derive(Debug, PartialEq)]
+pub enum Conditional<T> {
+    /// The entity changed, or there was no entity tag to compare with. It
+    /// comes with its new entity tag, if the service gave one.
+    Modified(Option<T>, Option<String>),
+    NotModified,
+}
+
+fn parse_body<T>(body: &[u8]) -> Result<Option<T>, Error>
+where
+    T: DeserializeOwned,
+{
+    if body.is_empty() {
+        Ok(None)
+    } else {
+        serde_json::from_slice::<T>(body)
+            .map_err(Error::from)
+            .map(Option::Some)
+    }
+}
+
+/// Reads a `Retry-After` header given either in seconds or as an HTTP date.
+fn retry_after(headers: &http::HeaderMap) -> Option<StdDuration> {
+    let value = headers.get(http::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(StdDuration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .ok()
+        .or_else(|| Some(StdDuration::from_secs(0)))
+}
+
+fn with_timeout<F>(future: F, timeout: StdDuration) -> impl Future<Item = F::Item, Error = Error>
+where
+    F: Future<Error = Error>,
+{
+    Timeout::new(future, timeout).map_err(|err| {
+        if err.is_elapsed() {
+            Error::from(ErrorKind::Timeout)
+        } else if err.is_inner() {
+            err.into_inner()
+                .expect("timeout error should have an inner error")
+        } else {
+            let err = err
+                .into_timer()
+                .expect("timeout error should have a timer error");
+            Error::from(err.context(ErrorKind::Timeout))
+        }
+    })
+}
+
 impl<C, T> Clone for Client<C, T>
 where
     T: TokenSource + Clone,
@@ -229,6 +407,9 @@ where
             api_version: self.api_version.clone(),
             host_name: self.host_name.clone(),
             user_agent: self.user_agent.clone(),
+            request_timeout: self.request_timeout,
+            token_ttl: self.token_ttl,
+            clock: self.clock.clone(),
         }
     }
 }
@@ -239,8 +420,10 @@ mod tests {
     use std::collections::HashMap;
     use std::mem;
     use std::str;
+    use std::sync::Mutex;
 
     use chrono::{DateTime, Utc};
+    use edgelet_test_utils::TestClock;
     use futures::future;
     use hyper::{Client as HyperClient, Request, Response};
     use tokio;
@@ -569,4 +752,217 @@ mod tests {
             .unwrap();
         assert_eq!(result, "response");
     }
+
+    #[test]
+    fn request_times_out() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source: Option<StaticTokenSource> = None;
+
+        let handler = |_req: Request<Body>| future::empty::<Response<Body>, HyperError>();
+        let client = Client::new(handler, token_source, api_version, host_name)
+            .unwrap()
+            .with_request_timeout(StdDuration::from_millis(50));
+
+        let task = client.request::<String, String>(Method::GET, "/boo", None, None, false);
+
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert_eq!(&ErrorKind::Timeout, err.kind());
+    }
+
+    #[test]
+    fn request_completes_within_timeout() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source: Option<StaticTokenSource> = None;
+
+        let handler = |_req: Request<Body>| Ok(Response::new(r#""response""#.into()));
+        let client = Client::new(handler, token_source, api_version, host_name)
+            .unwrap()
+            .with_request_timeout(StdDuration::from_secs(5));
+        assert_eq!(Some(StdDuration::from_secs(5)), client.request_timeout());
+
+        let task = client.request::<String, String>(Method::GET, "/boo", None, None, false);
+
+        let result: String = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, "response");
+    }
+
+    #[test]
+    fn request_error_carries_retry_after() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source: Option<StaticTokenSource> = None;
+
+        let handler = |_req: Request<Body>| {
+            Ok(Response::builder()
+                .status(hyper::StatusCode::TOO_MANY_REQUESTS)
+                .header(http::header::RETRY_AFTER, "7")
+                .body("throttled".into())
+                .unwrap())
+        };
+        let client = Client::new(handler, token_source, api_version, host_name).unwrap();
+
+        let task = client.request::<String, String>(Method::GET, "/boo", None, None, false);
+
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert_eq!(
+            &ErrorKind::ServiceError(hyper::StatusCode::TOO_MANY_REQUESTS, "throttled".to_string()),
+            err.kind()
+        );
+        assert_eq!(Some(StdDuration::from_secs(7)), err.retry_after());
+    }
+
+    #[test]
+    fn request_if_none_match_returns_the_entity_tag() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source: Option<StaticTokenSource> = None;
+
+        let handler = |req: Request<Body>| {
+            assert_eq!(None, req.headers().get(hyper::header::IF_NONE_MATCH));
+            Ok(Response::builder()
+                .header(http::header::ETAG, "\"1\"")
+                .body(r#""response""#.into())
+                .unwrap())
+        };
+        let client = Client::new(handler, token_source, api_version, host_name).unwrap();
+
+        let task = client.request_if_none_match::<String>("/boo", None, None);
+
+        let result = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        assert_eq!(
+            Conditional::Modified(Some("response".to_string()), Some("\"1\"".to_string())),
+            result
+        );
+    }
+
+    #[test]
+    fn request_if_none_match_handles_not_modified() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source: Option<StaticTokenSource> = None;
+
+        let handler = |req: Request<Body>| {
+            assert_eq!(
+                Some("\"1\"").map(AsRef::as_ref),
+                req.headers()
+                    .get(hyper::header::IF_NONE_MATCH)
+                    .map(AsRef::as_ref)
+            );
+            Ok(Response::builder()
+                .status(hyper::StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap())
+        };
+        let client = Client::new(handler, token_source, api_version, host_name).unwrap();
+
+        let task = client.request_if_none_match::<String>("/boo", None, Some("\"1\""));
+
+        let result = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        assert_eq!(Conditional::NotModified, result);
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingTokenSource {
+        expiries: Arc<Mutex<Vec<DateTime<Utc>>>>,
+        invalidated: Arc<Mutex<usize>>,
+    }
+
+    impl TokenSource for RecordingTokenSource {
+        type Error = Error;
+
+        fn get(&self, expiry: &DateTime<Utc>) -> Result<String, Error> {
+            self.expiries.lock().unwrap().push(*expiry);
+            Ok("token".to_string())
+        }
+
+        fn invalidate(&self) {
+            *self.invalidated.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn request_asks_for_tokens_valid_for_the_token_ttl() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source = RecordingTokenSource::default();
+        let clock = TestClock::default();
+
+        let handler = |_req: Request<Body>| Ok(Response::new(r#""response""#.into()));
+        let client = Client::new(handler, Some(token_source.clone()), api_version, host_name)
+            .unwrap()
+            .with_token_ttl(StdDuration::from_secs(600))
+            .with_clock(Arc::new(clock.clone()));
+        assert_eq!(StdDuration::from_secs(600), client.token_ttl());
+
+        let task = client.request::<String, String>(Method::GET, "/boo", None, None, false);
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        let expiries = token_source.expiries.lock().unwrap();
+        assert_eq!(vec![clock.now() + Duration::seconds(600)], *expiries);
+        assert_eq!(0, *token_source.invalidated.lock().unwrap());
+    }
+
+    #[test]
+    fn unauthorized_response_invalidates_the_token() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let token_source = RecordingTokenSource::default();
+
+        let handler = |_req: Request<Body>| {
+            Ok(Response::builder()
+                .status(hyper::StatusCode::UNAUTHORIZED)
+                .body(Body::empty())
+                .unwrap())
+        };
+        let client =
+            Client::new(handler, Some(token_source.clone()), api_version, host_name).unwrap();
+
+        let task = client.request::<String, String>(Method::GET, "/boo", None, None, false);
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert_eq!(1, *token_source.invalidated.lock().unwrap());
+    }
+
+    #[test]
+    fn retry_after_parses_seconds_and_dates() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(None, retry_after(&headers));
+
+        headers.insert(http::header::RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(Some(StdDuration::from_secs(120)), retry_after(&headers));
+
+        // A date that has already passed means the request can be retried now.
+        headers.insert(
+            http::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(Some(StdDuration::from_secs(0)), retry_after(&headers));
+
+        headers.insert(http::header::RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(None, retry_after(&headers));
+    }
 }
@@ -0,0 +1,213 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Connection draining
+//!
+//! A `Signal` is held by the server and a `Watch` by every connection it
+//! spawns. Draining asks the watched connections to finish their in-flight
+//! requests and waits for them to do so. Connections still open when the
+//! drain period ends, such as followed log streams which never complete on
+//! their own, are aborted.
+
+use std::time::{Duration, Instant};
+
+use futures::future::{Either, Shared};
+use futures::sync::{mpsc, oneshot};
+use futures::{future, Async, Future, Poll, Stream};
+use tokio::timer::Delay;
+
+enum Never {}
+
+pub fn channel() -> (Signal, Watch) {
+    let (drain_tx, drain_rx) = oneshot::channel();
+    let (abort_tx, abort_rx) = oneshot::channel();
+    let (drained_tx, drained_rx) = mpsc::channel(0);
+    let signal = Signal {
+        drain_tx,
+        abort_tx,
+        drained_rx,
+    };
+    let watch = Watch {
+        drain_rx: drain_rx.shared(),
+        abort_rx: abort_rx.shared(),
+        _drained_tx: drained_tx,
+    };
+    (signal, watch)
+}
+
+pub struct Signal {
+    drain_tx: oneshot::Sender<()>,
+    abort_tx: oneshot::Sender<()>,
+    drained_rx: mpsc::Receiver<Never>,
+}
+
+impl Signal {
+    /// Resolves once every `Watch` has been dropped, aborting the watched
+    /// futures that are still running after `timeout`.
+    pub fn drain(self, timeout: Duration) -> impl Future<Item = (), Error = ()> {
+        let Signal {
+            drain_tx,
+            abort_tx,
+            drained_rx,
+        } = self;
+
+        drain_tx.send(()).unwrap_or(());
+
+        // The receiver only ends once all the senders held by the watches
+        // have been dropped.
+        let drained = drained_rx.for_each(|never| -> Result<(), ()> { match never {} });
+        let deadline = Delay::new(Instant::now() + timeout);
+
+        drained
+            .select2(deadline)
+            .then(move |result| match result {
+                Ok(Either::A(((), _))) | Err(Either::A(((), _))) => Either::A(future::ok(())),
+                Ok(Either::B(((), drained))) | Err(Either::B((_, drained))) => {
+                    warn!(
+                        "Connections still open after {:?}, aborting them",
+                        timeout
+                    );
+                    abort_tx.send(()).unwrap_or(());
+                    Either::B(drained)
+                }
+            })
+    }
+}
+
+#[derive(Clone)]
+pub struct Watch {
+    drain_rx: Shared<oneshot::Receiver<()>>,
+    abort_rx: Shared<oneshot::Receiver<()>>,
+    _drained_tx: mpsc::Sender<Never>,
+}
+
+impl Watch {
+    /// Runs `future` until it completes or is aborted, calling `on_drain`
+    /// on it once draining starts.
+    pub fn watch<F, D>(self, future: F, on_drain: D) -> Watching<F, D>
+    where
+        F: Future<Item = ()>,
+        D: FnOnce(&mut F),
+    {
+        Watching {
+            future,
+            on_drain: Some(on_drain),
+            watch: self,
+        }
+    }
+}
+
+pub struct Watching<F, D> {
+    future: F,
+    on_drain: Option<D>,
+    watch: Watch,
+}
+
+impl<F, D> Future for Watching<F, D>
+where
+    F: Future<Item = ()>,
+    D: FnOnce(&mut F),
+{
+    type Item = ();
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // A signal that was dropped without being sent doesn't count, so
+        // only a successful receive starts draining or aborts.
+        if let Ok(Async::Ready(_)) = self.watch.abort_rx.poll() {
+            debug!("aborting connection that did not drain in time");
+            return Ok(Async::Ready(()));
+        }
+
+        if self.on_drain.is_some() {
+            if let Ok(Async::Ready(_)) = self.watch.drain_rx.poll() {
+                if let Some(on_drain) = self.on_drain.take() {
+                    on_drain(&mut self.future);
+                }
+            }
+        }
+
+        self.future.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn drain_without_watches_completes() {
+        let (signal, watch) = channel();
+        drop(watch);
+
+        Runtime::new()
+            .unwrap()
+            .block_on(signal.drain(Duration::from_secs(60)))
+            .unwrap();
+    }
+
+    #[test]
+    fn drain_waits_for_watched_futures() {
+        let (signal, watch) = channel();
+        let drained = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let drained_copy = drained.clone();
+        let mut tx = Some(tx);
+        let watched = watch
+            .watch(rx.map_err(|_| ()), move |_| {
+                drained_copy.store(true, Ordering::SeqCst);
+                // Finish the watched future as soon as it is asked to drain.
+                tx.take().unwrap().send(()).unwrap();
+            }).map_err(|_| ());
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(watched);
+        runtime
+            .block_on(signal.drain(Duration::from_secs(60)))
+            .unwrap();
+
+        assert!(drained.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn drain_timeout_aborts_watched_futures() {
+        let (signal, watch) = channel();
+        let aborted = Arc::new(AtomicBool::new(false));
+
+        let aborted_copy = aborted.clone();
+        let watched = watch
+            .watch(future::empty::<(), ()>(), |_| ())
+            .map(move |()| aborted_copy.store(true, Ordering::SeqCst));
+
+        let start = Instant::now();
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(watched);
+        runtime
+            .block_on(signal.drain(Duration::from_millis(50)))
+            .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(aborted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dropped_signal_does_not_abort() {
+        let (signal, watch) = channel();
+        let (tx, rx) = oneshot::channel::<()>();
+        let mut watched = watch.watch(rx.map_err(|_| ()), |_| panic!("should not drain"));
+        drop(signal);
+
+        future::lazy(move || {
+            assert_eq!(Ok(Async::NotReady), watched.poll());
+            tx.send(()).unwrap();
+            assert_eq!(Ok(Async::Ready(())), watched.poll());
+            Ok::<_, ()>(())
+        }).wait()
+        .unwrap();
+    }
+}
@@ -5,6 +5,7 @@ use std::io;
 use std::num::ParseIntError;
 use std::str;
 use std::str::Utf8Error;
+use std::time::Duration;
 
 use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
 use failure::{Backtrace, Context, Fail};
@@ -16,6 +17,7 @@ use hyper_named_pipe::Error as PipeError;
 use hyper_tls::Error as HyperTlsError;
 #[cfg(unix)]
 use nix::Error as NixError;
+use openssl::error::ErrorStack;
 use serde_json::Error as SerdeError;
 use systemd::Error as SystemdError;
 use typed_headers::Error as TypedHeadersError;
@@ -54,17 +56,58 @@ pub enum ErrorKind {
     InvalidUri(String),
     #[fail(display = "Cannot parse uri")]
     UrlParse,
+    #[fail(display = "Invalid no_proxy entry {}", _0)]
+    InvalidNoProxy(String),
+    #[fail(display = "Invalid value for query parameter {}", _0)]
+    InvalidQueryParameter(String),
+    #[fail(display = "Query parameter {} was given more than once", _0)]
+    DuplicateQueryParameter(String),
+    #[fail(display = "Query parameter {} must be between {} and {}", _0, _1, _2)]
+    QueryParameterOutOfRange(String, i32, i32),
     #[fail(display = "Token source error")]
     TokenSource,
+    #[fail(display = "Request timed out")]
+    Timeout,
     #[cfg(windows)]
     #[fail(display = "Named pipe error")]
     HyperPipe,
     #[fail(display = "A TLS error occurred.")]
     HyperTls,
+    #[fail(display = "TLS is required but no TLS material is configured for uri {}", _0)]
+    TlsRequired(String),
+    #[fail(display = "Invalid TLS certificate file {}", _0)]
+    InvalidTlsCertificate(String),
+    #[fail(display = "Invalid TLS private key file {}", _0)]
+    InvalidTlsKey(String),
+    #[fail(display = "TLS client authentication requires both a certificate and a private key")]
+    IncompleteTlsIdentity,
+    #[fail(display = "TLS certificate {} does not match private key {}", _0, _1)]
+    MismatchedTlsIdentity(String, String),
+    #[fail(display = "Invalid TLS server certificate or private key")]
+    InvalidServerIdentity,
+    #[fail(display = "TLS is only served on https listeners, not on {}", _0)]
+    TlsNotSupported(String),
+    #[fail(display = "Invalid TLS version {}, expected one of 1.0, 1.1, 1.2 or 1.3", _0)]
+    InvalidTlsVersion(String),
+    #[fail(display = "Invalid TLS cipher suites {}", _0)]
+    InvalidCipherSuites(String),
     #[fail(display = "Systemd error")]
     Systemd,
     #[fail(display = "Module not found")]
     NotFound,
+    #[fail(display = "Caller is not authorized to access this module")]
+    Forbidden,
+    #[fail(display = "Socket {} is in use by another process", _0)]
+    SocketInUse(String),
+    #[fail(display = "Could not set the mode of socket {}", _0)]
+    SocketMode(String),
+    #[fail(display = "Could not change the owner of socket {}", _0)]
+    SocketOwner(String),
+    #[fail(
+        display = "Not permitted to change the owner of socket {}; this requires running as root or the CAP_CHOWN capability",
+        _0
+    )]
+    SocketOwnerNotPermitted(String),
     #[cfg(unix)]
     #[fail(display = "Syscall for socket failed.")]
     Nix,
@@ -72,8 +115,20 @@ pub enum ErrorKind {
     Utf8,
     #[fail(display = "Error creating HTTP header")]
     TypedHeaders,
+    #[fail(display = "Request headers are too large")]
+    HeadersTooLarge,
+    #[fail(display = "Request has conflicting Content-Length and Transfer-Encoding headers")]
+    AmbiguousFraming,
+    #[fail(display = "Too many requests, try again later")]
+    TooManyRequests,
 }
 
+/// How long a service asked to be left alone for with a `Retry-After` header.
+/// It is the cause of the `ServiceError` of the response that carried it.
+#[derive(Debug, Fail)]
+#[fail(display = "The service asked to retry after {:?}", _0)]
+pub struct RetryAfter(pub Duration);
+
 impl Fail for Error {
     fn cause(&self) -> Option<&Fail> {
         self.inner.cause()
@@ -94,6 +149,13 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         self.inner.get_context()
     }
+
+    /// The delay a service error response asked for before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.cause()
+            .and_then(|cause| cause.downcast_ref::<RetryAfter>())
+            .map(|retry_after| retry_after.0)
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -167,8 +229,16 @@ impl IntoResponse for Error {
         }
 
         let status_code = match *self.kind() {
-            ErrorKind::InvalidApiVersion => StatusCode::BAD_REQUEST,
+            ErrorKind::InvalidApiVersion
+            | ErrorKind::InvalidQueryParameter(_)
+            | ErrorKind::DuplicateQueryParameter(_)
+            | ErrorKind::QueryParameterOutOfRange(..)
+            | ErrorKind::AmbiguousFraming => StatusCode::BAD_REQUEST,
+            ErrorKind::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
             ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorKind::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -188,15 +258,26 @@ impl IntoResponse for Error {
 impl<'a> From<(HyperStatusCode, &'a [u8])> for Error {
     fn from(err: (HyperStatusCode, &'a [u8])) -> Self {
         let (status_code, msg) = err;
-        Error::from(ErrorKind::ServiceError(
-            status_code,
-            str::from_utf8(msg)
-                .unwrap_or_else(|_| "Could not decode error message")
-                .to_string(),
-        ))
+        Error::from(service_error_kind(status_code, msg))
     }
 }
 
+impl<'a> From<(HyperStatusCode, &'a [u8], Duration)> for Error {
+    fn from(err: (HyperStatusCode, &'a [u8], Duration)) -> Self {
+        let (status_code, msg, retry_after) = err;
+        Error::from(RetryAfter(retry_after).context(service_error_kind(status_code, msg)))
+    }
+}
+
+fn service_error_kind(status_code: HyperStatusCode, msg: &[u8]) -> ErrorKind {
+    ErrorKind::ServiceError(
+        status_code,
+        str::from_utf8(msg)
+            .unwrap_or_else(|_| "Could not decode error message")
+            .to_string(),
+    )
+}
+
 impl IntoResponse for Context<ErrorKind> {
     fn into_response(self) -> Response<Body> {
         let error: Error = Error::from(self);
@@ -261,6 +342,14 @@ impl From<HyperTlsError> for Error {
     }
 }
 
+impl From<ErrorStack> for Error {
+    fn from(error: ErrorStack) -> Self {
+        Error {
+            inner: error.context(ErrorKind::HyperTls),
+        }
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(error: Utf8Error) -> Self {
         Error {
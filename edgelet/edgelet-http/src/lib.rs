@@ -15,6 +15,8 @@
 extern crate bytes;
 extern crate chrono;
 extern crate edgelet_core;
+#[cfg(test)]
+extern crate edgelet_test_utils;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
@@ -28,13 +30,18 @@ extern crate hyper_proxy;
 extern crate hyper_tls;
 #[cfg(unix)]
 extern crate hyperlocal;
+#[cfg(test)]
+#[macro_use]
+extern crate lazy_static;
 #[cfg(target_os = "linux")]
 #[cfg(unix)]
 extern crate libc;
 #[macro_use]
 extern crate log;
+extern crate native_tls;
 #[cfg(unix)]
 extern crate nix;
+extern crate openssl;
 extern crate percent_encoding;
 extern crate regex;
 #[cfg(unix)]
@@ -50,6 +57,7 @@ extern crate tempfile;
 extern crate tokio;
 #[cfg(windows)]
 extern crate tokio_named_pipe;
+extern crate tokio_openssl;
 #[cfg(unix)]
 extern crate tokio_uds;
 extern crate typed_headers;
@@ -58,13 +66,16 @@ extern crate url;
 #[macro_use]
 extern crate edgelet_utils;
 
+use std::fs;
 use std::io;
 #[cfg(unix)]
 use std::net;
 use std::net::ToSocketAddrs;
 #[cfg(unix)]
 use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::{future, Future, Poll, Stream};
 use hyper::server::conn::Http;
@@ -73,29 +84,55 @@ use hyper::{Body, Error as HyperError, Response};
 #[cfg(unix)]
 use systemd::Socket;
 use tokio::net::TcpListener;
+use tokio::timer::{Interval, Timeout};
 #[cfg(unix)]
 use tokio_uds::UnixListener;
 use url::Url;
 
 pub mod authorization;
 pub mod client;
+mod drain;
 pub mod error;
+mod limit;
 pub mod logging;
+pub mod metrics;
+mod permissions;
 mod pid;
+mod rate;
 pub mod route;
+mod sanitize;
+mod tls;
 mod unix;
 mod util;
 mod version;
 
-pub use self::error::{Error, ErrorKind};
-pub use self::util::proxy::MaybeProxyClient;
-pub use self::util::UrlConnector;
+pub use self::error::{Error, ErrorKind, RetryAfter};
+pub use self::limit::{ConnectionLimits, SharedLimits};
+pub use self::permissions::SocketPermissions;
+pub use self::pid::Uid;
+pub use self::rate::{Caller, Rate, RateLimitService, RateLimiter, RateLimits, RouteClass};
+pub use self::tls::{ServerTlsConfig, TlsPolicy, TlsVersion};
+pub use self::util::no_proxy::NoProxy;
+pub use self::util::proxy::{ClientFactory, MaybeProxyClient};
+pub use self::util::timeout::{is_timeout, TimeoutConnector, DEFAULT_CONNECT_TIMEOUT_SECS};
+pub use self::util::{TlsConfig, UrlConnector};
 pub use self::version::{ApiVersionService, API_VERSION};
 
+use self::limit::{Connections, IdleStream, InFlightService};
 use self::pid::PidService;
+use self::sanitize::SanitizeService;
+use self::tls::{handshake_failed, TlsAcceptor};
 use self::util::incoming::Incoming;
+use self::util::StreamSelector;
+
+/// How long in-flight requests are given to complete on shutdown by default.
+pub const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 10;
+
+/// How often the number of open connections is logged.
+const CONNECTION_STATS_INTERVAL_SECS: u64 = 60;
 
 const HTTP_SCHEME: &str = "http";
+const HTTPS_SCHEME: &str = "https";
 const TCP_SCHEME: &str = "tcp";
 #[cfg(unix)]
 const UNIX_SCHEME: &str = "unix";
@@ -127,6 +164,79 @@ pub struct Server<S> {
     protocol: Http,
     new_service: S,
     incoming: Incoming,
+    url: Url,
+    drain_timeout: Duration,
+    socket_path: Option<PathBuf>,
+    limits: SharedLimits,
+    tls: Option<Arc<TlsAcceptor>>,
+}
+
+impl<S> Server<S> {
+    /// Sets how long in-flight requests are given to complete once the
+    /// shutdown signal fires. Connections still open after that, such as
+    /// followed log streams, are aborted.
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Closes connections accepted while `max_connections` are already open.
+    pub fn with_max_connections(self, max_connections: usize) -> Self {
+        let limits = self.limits.get().with_max_connections(Some(max_connections));
+        self.limits.set(limits);
+        self
+    }
+
+    /// Closes connections on which no request arrives within `timeout`,
+    /// whether they are new, idle between requests, or sending headers too
+    /// slowly. Connections serving a request are never closed this way.
+    pub fn with_header_read_timeout(self, timeout: Duration) -> Self {
+        let limits = self.limits.get().with_header_read_timeout(Some(timeout));
+        self.limits.set(limits);
+        self
+    }
+
+    /// Fails requests whose body stops arriving for longer than `timeout`.
+    pub fn with_body_read_timeout(self, timeout: Duration) -> Self {
+        let limits = self.limits.get().with_body_read_timeout(Some(timeout));
+        self.limits.set(limits);
+        self
+    }
+
+    /// Answers requests with more than `max_headers` header fields, or with
+    /// header fields larger than `max_header_bytes` together, with 431.
+    pub fn with_header_limits(self, max_headers: usize, max_header_bytes: usize) -> Self {
+        let limits = self
+            .limits
+            .get()
+            .with_max_headers(Some(max_headers))
+            .with_max_header_bytes(Some(max_header_bytes));
+        self.limits.set(limits);
+        self
+    }
+
+    /// Makes the server take its connection limits from `limits`, which can
+    /// be updated while it runs. Each new connection is admitted and given
+    /// its timeouts according to the limits current when it is accepted.
+    pub fn with_connection_limits(mut self, limits: SharedLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Applies `permissions` to the socket file of a `unix://` listener.
+    /// Other listeners, including sockets handed over by systemd, are left
+    /// as they are.
+    pub fn with_socket_permissions(self, permissions: &SocketPermissions) -> Result<Self, Error> {
+        match self.socket_path {
+            #[cfg(unix)]
+            Some(ref path) => unix::set_permissions(path, permissions)?,
+            _ => debug!(
+                "ignoring socket permissions {:?} for a listener without a socket file",
+                permissions
+            ),
+        }
+        Ok(self)
+    }
 }
 
 impl<S> Server<S>
@@ -149,15 +259,44 @@ where
             protocol,
             new_service,
             incoming,
+            url,
+            drain_timeout,
+            socket_path,
+            limits,
+            tls,
         } = self;
 
         let protocol = Arc::new(protocol);
+        let (drain_signal, watch) = drain::channel();
+        let connections = Connections::default();
+        let stats = log_connections(url, connections.clone(), limits.clone());
 
         let srv = incoming.for_each(move |(socket, addr)| {
             let protocol = protocol.clone();
+            let watch = watch.clone();
+            let current = limits.get();
+
+            let counted = match connections.try_acquire(current.max_connections()) {
+                Some(counted) => counted,
+                None => {
+                    warn!(
+                        "refusing connection ({}): {} connections are already open",
+                        addr,
+                        connections.count()
+                    );
+                    return Ok(());
+                }
+            };
 
             debug!("accepted new connection ({})", addr);
             let pid = socket.pid()?;
+            let uid = socket.uid()?;
+            let socket = accept_tls(
+                socket,
+                tls.clone(),
+                current.header_read_timeout(),
+                addr.to_string(),
+            );
             let fut = new_service
                 .new_service()
                 .then(move |srv| match srv {
@@ -166,15 +305,31 @@ where
                         error!("server connection error: ({}) {}", addr, err);
                         Err(())
                     }
-                }).and_then(move |(srv, addr)| {
-                    let service = PidService::new(pid, srv);
-                    protocol
-                        .serve_connection(socket, service)
-                        .then(move |result| match result {
-                            Ok(_) => Ok(()),
-                            Err(err) => {
-                                error!("server connection error: ({}) {}", addr, err);
-                                Err(())
+                }).join(socket)
+                .and_then(move |((srv, addr), socket)| {
+                    let in_flight = Connections::default();
+                    let service = InFlightService::new(
+                        SanitizeService::new(PidService::new(pid, srv).with_uid(uid), current),
+                        in_flight.clone(),
+                        current.body_read_timeout(),
+                    );
+                    let socket =
+                        IdleStream::new(socket, current.header_read_timeout(), in_flight);
+                    let connection = protocol.serve_connection(socket, service);
+                    watch
+                        .watch(connection, |connection| connection.graceful_shutdown())
+                        .then(move |result| {
+                            drop(counted);
+                            match result {
+                                Ok(_) => Ok(()),
+                                Err(ref err) if is_timeout(err) => {
+                                    debug!("closed idle connection ({})", addr);
+                                    Ok(())
+                                }
+                                Err(err) => {
+                                    error!("server connection error: ({}) {}", addr, err);
+                                    Err(())
+                                }
                             }
                         })
                 });
@@ -182,6 +337,8 @@ where
             Ok(())
         });
 
+        let srv = srv.select(stats).map(|_| ()).map_err(|(err, _)| err);
+
         // We don't care if the shut_down signal errors.
         // Swallow the error.
         let shutdown_signal = shutdown_signal.then(|_| Ok(()));
@@ -191,18 +348,96 @@ where
         let main_execution = shutdown_signal
             .select(srv)
             .then(move |result| match result {
-                Ok(((), _incoming)) => future::ok(()),
-                Err((e, _other)) => future::err(e.into()),
+                Ok(((), incoming)) => {
+                    // Stop accepting connections, then give the open ones
+                    // time to finish.
+                    drop(incoming);
+                    debug!("draining connections for up to {:?}", drain_timeout);
+                    future::Either::A(drain_signal.drain(drain_timeout).then(|_| Ok(())))
+                }
+                Err((e, _other)) => future::Either::B(future::err(e.into())),
+            }).then(move |result| {
+                if let Some(path) = socket_path {
+                    remove_socket(&path);
+                }
+                result
             });
 
         Run(Box::new(main_execution))
     }
 }
 
+/// Completes the TLS handshake of connections to `https` listeners, and
+/// passes other connections on as they are. Clients get as long for the
+/// handshake as they get for sending the headers of a request.
+fn accept_tls(
+    socket: StreamSelector,
+    acceptor: Option<Arc<TlsAcceptor>>,
+    timeout: Option<Duration>,
+    addr: String,
+) -> Box<Future<Item = StreamSelector, Error = ()> + Send> {
+    let (acceptor, stream) = match (acceptor, socket) {
+        (Some(acceptor), StreamSelector::Tcp(stream)) => (acceptor, stream),
+        (_, socket) => return Box::new(future::ok(socket)),
+    };
+    let min_version = acceptor.min_version();
+    let handshake = acceptor.accept(stream).map(StreamSelector::TlsServer);
+    match timeout {
+        Some(timeout) => Box::new(Timeout::new(handshake, timeout).map_err(move |err| {
+            warn!("{}", handshake_failed(&addr, min_version, err));
+        })),
+        None => Box::new(handshake.map_err(move |err| {
+            warn!("{}", handshake_failed(&addr, min_version, err));
+        })),
+    }
+}
+
+fn log_connections(
+    url: Url,
+    connections: Connections,
+    limits: SharedLimits,
+) -> impl Future<Item = (), Error = io::Error> {
+    let interval = Duration::from_secs(CONNECTION_STATS_INTERVAL_SECS);
+    Interval::new(Instant::now() + interval, interval)
+        .for_each(move |_| {
+            match limits.get().max_connections() {
+                Some(max) => debug!("{}: {} of {} connections open", url, connections.count(), max),
+                None => debug!("{}: {} connections open", url, connections.count()),
+            }
+            Ok(())
+        }).then(|result| {
+            if let Err(err) = result {
+                warn!("stopped logging connection counts: {}", err);
+            }
+            // Logging is not a reason for the server to stop.
+            future::empty()
+        })
+}
+
+fn remove_socket(path: &Path) {
+    debug!("removing {}", path.display());
+    if let Err(err) = fs::remove_file(path) {
+        if err.kind() != io::ErrorKind::NotFound {
+            warn!("could not remove {}: {}", path.display(), err);
+        }
+    }
+}
+
 pub trait HyperExt {
     fn bind_url<S>(&self, url: Url, new_service: S) -> Result<Server<S>, Error>
     where
         S: NewService<ReqBody = Body> + 'static;
+
+    /// Binds `url` like `bind_url`, serving `https` URLs over TLS with `tls`.
+    /// An `https` URL requires TLS, and only `https` URLs may have it.
+    fn bind_url_with_tls<S>(
+        &self,
+        url: Url,
+        new_service: S,
+        tls: Option<&ServerTlsConfig>,
+    ) -> Result<Server<S>, Error>
+    where
+        S: NewService<ReqBody = Body> + 'static;
 }
 
 impl HyperExt for Http {
@@ -210,8 +445,27 @@ impl HyperExt for Http {
     where
         S: NewService<ReqBody = Body> + 'static,
     {
+        self.bind_url_with_tls(url, new_service, None)
+    }
+
+    fn bind_url_with_tls<S>(
+        &self,
+        url: Url,
+        new_service: S,
+        tls: Option<&ServerTlsConfig>,
+    ) -> Result<Server<S>, Error>
+    where
+        S: NewService<ReqBody = Body> + 'static,
+    {
+        let tls = match (url.scheme(), tls) {
+            (HTTPS_SCHEME, Some(tls)) => Some(Arc::new(tls.acceptor()?)),
+            (HTTPS_SCHEME, None) => Err(Error::from(ErrorKind::TlsRequired(url.to_string())))?,
+            (_, Some(_)) => Err(Error::from(ErrorKind::TlsNotSupported(url.to_string())))?,
+            (_, None) => None,
+        };
+
         let incoming = match url.scheme() {
-            HTTP_SCHEME | TCP_SCHEME => {
+            HTTP_SCHEME | HTTPS_SCHEME | TCP_SCHEME => {
                 let addr = url.to_socket_addrs()?.next().ok_or_else(|| {
                     io::Error::new(io::ErrorKind::Other, format!("Invalid url: {}", url))
                 })?;
@@ -250,10 +504,22 @@ impl HyperExt for Http {
             _ => Err(Error::from(ErrorKind::InvalidUri(url.to_string())))?,
         };
 
+        // Sockets handed over by systemd belong to it and are left in place.
+        let socket_path = match url.scheme() {
+            #[cfg(unix)]
+            UNIX_SCHEME => Some(PathBuf::from(url.path())),
+            _ => None,
+        };
+
         Ok(Server {
             protocol: self.clone(),
             new_service,
             incoming,
+            url,
+            drain_timeout: Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            socket_path,
+            limits: SharedLimits::default(),
+            tls,
         })
     }
 }
@@ -0,0 +1,447 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Connection limits
+//!
+//! `Connections` caps how many connections a server keeps open at once.
+//! `IdleStream` closes connections whose client has not sent a request within
+//! the header read timeout, which covers both idle keep-alive connections and
+//! half-open ones. The timer is paused while a request is being served, so
+//! long-running calls and streamed responses are not affected; a stalled
+//! request body is handled separately by `BodyTimeout`.
+//!
+//! The limits themselves live in a `SharedLimits`, so they can be changed
+//! while the server runs. New values apply to connections accepted
+//! afterwards; connections already open keep the timeouts they started with.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use bytes::Buf;
+use futures::{Async, Future, Poll, Stream};
+use http::{HeaderMap, Request, Response};
+use hyper::body::Payload;
+use hyper::service::Service;
+use hyper::{Body, Chunk};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::Delay;
+
+/// Limits applied to the connections of a server. Unset limits are not
+/// enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionLimits {
+    max_connections: Option<usize>,
+    header_read_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    max_headers: Option<usize>,
+    max_header_bytes: Option<usize>,
+}
+
+impl ConnectionLimits {
+    pub fn new() -> Self {
+        ConnectionLimits::default()
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    pub fn with_max_connections(mut self, max_connections: Option<usize>) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        self.header_read_timeout
+    }
+
+    pub fn with_header_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.header_read_timeout = timeout;
+        self
+    }
+
+    pub fn body_read_timeout(&self) -> Option<Duration> {
+        self.body_read_timeout
+    }
+
+    pub fn with_body_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.body_read_timeout = timeout;
+        self
+    }
+
+    /// How many header fields a request may have, counting each value of a
+    /// repeated field.
+    pub fn max_headers(&self) -> Option<usize> {
+        self.max_headers
+    }
+
+    pub fn with_max_headers(mut self, max_headers: Option<usize>) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// How large the header fields of a request may be together, as they
+    /// were sent.
+    pub fn max_header_bytes(&self) -> Option<usize> {
+        self.max_header_bytes
+    }
+
+    pub fn with_max_header_bytes(mut self, max_header_bytes: Option<usize>) -> Self {
+        self.max_header_bytes = max_header_bytes;
+        self
+    }
+}
+
+/// `ConnectionLimits` shared between a server and whoever configures it.
+#[derive(Clone, Debug, Default)]
+pub struct SharedLimits(Arc<RwLock<ConnectionLimits>>);
+
+impl SharedLimits {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        SharedLimits(Arc::new(RwLock::new(limits)))
+    }
+
+    pub fn get(&self) -> ConnectionLimits {
+        // The lock only guards a plain value, so a panic while it was held
+        // cannot have left it half written.
+        *self.0.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn set(&self, limits: ConnectionLimits) {
+        *self.0.write().unwrap_or_else(|err| err.into_inner()) = limits;
+    }
+}
+
+/// Counts the open connections of a server.
+#[derive(Clone, Debug, Default)]
+pub struct Connections(Arc<AtomicUsize>);
+
+impl Connections {
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Registers a new connection, unless `max` connections are already open.
+    pub fn try_acquire(&self, max: Option<usize>) -> Option<Counted> {
+        let previous = self.0.fetch_add(1, Ordering::SeqCst);
+        let counted = Counted(self.0.clone());
+        match max {
+            Some(max) if previous >= max => None,
+            _ => Some(counted),
+        }
+    }
+}
+
+/// Decrements its counter when dropped.
+#[derive(Debug)]
+pub struct Counted(Arc<AtomicUsize>);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Fails reads with `TimedOut` once the client has been silent for
+/// `timeout` while none of its requests are being served. Without a timeout
+/// it only passes reads and writes through.
+pub struct IdleStream<T> {
+    inner: T,
+    timeout: Option<Duration>,
+    in_flight: Connections,
+    deadline: Option<Delay>,
+}
+
+impl<T> IdleStream<T> {
+    pub fn new(inner: T, timeout: Option<Duration>, in_flight: Connections) -> Self {
+        IdleStream {
+            inner,
+            timeout,
+            in_flight,
+            deadline: None,
+        }
+    }
+
+    fn poll_idle(&mut self) -> io::Result<()> {
+        let timeout = match self.timeout {
+            Some(timeout) if self.in_flight.count() == 0 => timeout,
+            _ => {
+                self.deadline = None;
+                return Ok(());
+            }
+        };
+
+        let deadline = self
+            .deadline
+            .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+        match deadline.poll() {
+            Ok(Async::Ready(())) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no request was received within {:?}", timeout),
+            )),
+            Ok(Async::NotReady) => Ok(()),
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+impl<T: Read> Read for IdleStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(read) => {
+                self.deadline = None;
+                Ok(read)
+            }
+            Err(err) => {
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    self.poll_idle()?;
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T: Write> Write for IdleStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for IdleStream<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for IdleStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.inner.write_buf(buf)
+    }
+}
+
+/// Counts the requests being served on a connection, from the time the
+/// service is called until the response body has been written, and applies
+/// the body read timeout to request bodies.
+pub struct InFlightService<S> {
+    inner: S,
+    in_flight: Connections,
+    body_read_timeout: Option<Duration>,
+}
+
+impl<S> InFlightService<S> {
+    pub fn new(inner: S, in_flight: Connections, body_read_timeout: Option<Duration>) -> Self {
+        InFlightService {
+            inner,
+            in_flight,
+            body_read_timeout,
+        }
+    }
+}
+
+impl<S> Service for InFlightService<S>
+where
+    S: Service<ReqBody = Body>,
+    S::ResBody: Payload,
+{
+    type ReqBody = Body;
+    type ResBody = InFlightBody<S::ResBody>;
+    type Error = S::Error;
+    type Future = InFlightFuture<S::Future>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let counted = self.in_flight.try_acquire(None);
+        let req = match self.body_read_timeout {
+            Some(timeout) => req.map(|body| Body::wrap_stream(BodyTimeout::new(body, timeout))),
+            None => req,
+        };
+        InFlightFuture {
+            inner: self.inner.call(req),
+            counted,
+        }
+    }
+}
+
+pub struct InFlightFuture<F> {
+    inner: F,
+    counted: Option<Counted>,
+}
+
+impl<F, B> Future for InFlightFuture<F>
+where
+    F: Future<Item = Response<B>>,
+{
+    type Item = Response<InFlightBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        let counted = self.counted.take();
+        Ok(Async::Ready(response.map(|inner| InFlightBody {
+            inner,
+            _counted: counted,
+        })))
+    }
+}
+
+pub struct InFlightBody<B> {
+    inner: B,
+    _counted: Option<Counted>,
+}
+
+impl<B: Payload> Payload for InFlightBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        self.inner.poll_data()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.inner.content_length()
+    }
+}
+
+/// Fails a request body whose client goes quiet for longer than `timeout`
+/// between chunks.
+struct BodyTimeout {
+    inner: Body,
+    timeout: Duration,
+    deadline: Option<Delay>,
+}
+
+impl BodyTimeout {
+    fn new(inner: Body, timeout: Duration) -> Self {
+        BodyTimeout {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+}
+
+impl Stream for BodyTimeout {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::Ready(chunk)) => {
+                self.deadline = None;
+                Ok(Async::Ready(chunk))
+            }
+            Ok(Async::NotReady) => {
+                let timeout = self.timeout;
+                let deadline = self
+                    .deadline
+                    .get_or_insert_with(|| Delay::new(Instant::now() + timeout));
+                match deadline.poll() {
+                    Ok(Async::Ready(())) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("request body was not received within {:?}", timeout),
+                    )),
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+                }
+            }
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+
+    #[test]
+    fn connections_are_limited() {
+        let connections = Connections::default();
+        let first = connections.try_acquire(Some(2)).unwrap();
+        let _second = connections.try_acquire(Some(2)).unwrap();
+        assert!(connections.try_acquire(Some(2)).is_none());
+        assert_eq!(2, connections.count());
+
+        drop(first);
+        assert_eq!(1, connections.count());
+        assert!(connections.try_acquire(Some(2)).is_some());
+        assert!(connections.try_acquire(None).is_some());
+    }
+
+    #[test]
+    fn shared_limits_are_seen_by_all_clones() {
+        let limits = SharedLimits::default();
+        let server = limits.clone();
+        assert_eq!(ConnectionLimits::new(), server.get());
+
+        let updated = ConnectionLimits::new()
+            .with_max_connections(Some(4))
+            .with_header_read_timeout(Some(Duration::from_secs(5)));
+        limits.set(updated);
+        assert_eq!(updated, server.get());
+        assert_eq!(Some(4), server.get().max_connections());
+        assert_eq!(None, server.get().body_read_timeout());
+    }
+
+    #[test]
+    fn stalled_body_times_out() {
+        let (_sender, body) = Body::channel();
+        let body = BodyTimeout::new(body, Duration::from_millis(50));
+
+        let err = Runtime::new()
+            .unwrap()
+            .block_on(body.concat2())
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn complete_body_does_not_time_out() {
+        let body = BodyTimeout::new(Body::from("hello"), Duration::from_millis(50));
+        let body = Runtime::new()
+            .unwrap()
+            .block_on(body.concat2())
+            .unwrap();
+        assert_eq!(b"hello", &body[..]);
+    }
+
+    #[test]
+    fn response_body_is_counted_until_dropped() {
+        let in_flight = Connections::default();
+        let mut service = InFlightService::new(
+            ::hyper::service::service_fn(|_req| {
+                future::ok::<_, io::Error>(Response::new(Body::from("hello")))
+            }),
+            in_flight.clone(),
+            None,
+        );
+
+        let response = service.call(Request::new(Body::empty()));
+        assert_eq!(1, in_flight.count());
+        let response = response.wait().unwrap();
+        assert_eq!(1, in_flight.count());
+        assert_eq!(Some(5), response.body().content_length());
+
+        drop(response);
+        assert_eq!(0, in_flight.count());
+    }
+}
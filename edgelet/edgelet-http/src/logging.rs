@@ -2,24 +2,69 @@
 #![allow(deprecated)]
 
 use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::Instant;
 
 use chrono::prelude::*;
 use edgelet_core::pid::Pid;
+use edgelet_utils::with_correlation_id;
 use futures::future;
 use futures::prelude::*;
-use http::header::{CONTENT_LENGTH, USER_AGENT};
+use http::header::{HeaderValue, CONTENT_LENGTH, USER_AGENT};
+use log::Level;
 use hyper::service::{NewService, Service};
 use hyper::{Body, Request, Response};
 
+/// Response header carrying the id assigned to each request.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Query parameters whose names contain any of these are not logged.
+const SENSITIVE_QUERY_NAMES: &[&str] = &["key", "token", "secret", "password", "sig"];
+
+static NEXT_REQUEST: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Identifies a single request in the logs. `LoggingService` stores it in
+/// the request's extensions, where the router picks it up for handlers, and
+/// makes it the correlation id of whatever the handler logs on the thread
+/// that polls it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    fn next(prefix: &str) -> Self {
+        CorrelationId(format!(
+            "{}-{:x}",
+            prefix,
+            NEXT_REQUEST.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Clone)]
 pub struct LoggingService<T> {
     label: String,
+    id_prefix: String,
     inner: T,
 }
 
 impl<T> LoggingService<T> {
     pub fn new(label: String, inner: T) -> Self {
-        LoggingService { label, inner }
+        LoggingService {
+            label,
+            id_prefix: format!("{:x}", Utc::now().timestamp()),
+            inner,
+        }
     }
 }
 
@@ -29,6 +74,26 @@ pub struct ResponseFuture<T> {
     request: String,
     user_agent: String,
     pid: Option<Pid>,
+    correlation_id: CorrelationId,
+    started: Instant,
+}
+
+fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| {
+            let name = pair.split('=').next().unwrap_or("");
+            let lowercase = name.to_lowercase();
+            let sensitive = SENSITIVE_QUERY_NAMES
+                .iter()
+                .any(|sensitive| lowercase.contains(sensitive));
+            if sensitive && pair.contains('=') {
+                format!("{}=***", name)
+            } else {
+                pair.to_string()
+            }
+        }).collect::<Vec<_>>()
+        .join("&")
 }
 
 impl<T> Future for ResponseFuture<T>
@@ -39,7 +104,13 @@ where
     type Error = T::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let response = try_ready!(self.inner.poll());
+        let inner = &mut self.inner;
+        let mut response = try_ready!(with_correlation_id(self.correlation_id.as_str(), || {
+            inner.poll()
+        }));
+        if let Ok(value) = HeaderValue::from_str(self.correlation_id.as_str()) {
+            response.headers_mut().insert(CORRELATION_ID_HEADER, value);
+        }
 
         let body_length = response
             .headers()
@@ -51,16 +122,30 @@ where
             .as_ref()
             .map_or_else(|| "-".to_string(), |p| p.to_string());
 
-        info!(
-            "[{}] - - - [{}] \"{}\" {} {} \"-\" \"{}\" pid({})",
-            self.label,
-            Utc::now(),
-            self.request,
-            response.status(),
-            body_length,
-            self.user_agent,
-            pid,
-        );
+        let elapsed = self.started.elapsed();
+        let elapsed_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+        let status = response.status();
+        let level = if status.is_client_error() || status.is_server_error() {
+            Level::Info
+        } else {
+            Level::Debug
+        };
+        with_correlation_id(self.correlation_id.as_str(), || {
+            log!(
+                level,
+                "[{}] - - - [{}] \"{}\" {} {} \"-\" \"{}\" pid({}) id({}) {}ms",
+                self.label,
+                Utc::now(),
+                self.request,
+                status,
+                body_length,
+                self.user_agent,
+                pid,
+                self.correlation_id,
+                elapsed_ms,
+            )
+        });
         Ok(Async::Ready(response))
     }
 }
@@ -74,10 +159,11 @@ where
     type Error = T::Error;
     type Future = ResponseFuture<T::Future>;
 
-    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Self::ReqBody>) -> Self::Future {
+        let started = Instant::now();
         let uri = req.uri().query().map_or_else(
             || req.uri().path().to_string(),
-            |q| format!("{}?{}", req.uri().path(), q),
+            |q| format!("{}?{}", req.uri().path(), redact_query(q)),
         );
         let request = format!("{} {} {:?}", req.method(), uri, req.version());
         let user_agent = req
@@ -87,14 +173,19 @@ where
             .unwrap_or_else(|| "-")
             .to_string();
         let pid = req.extensions().get::<Pid>().cloned();
+        let correlation_id = CorrelationId::next(&self.id_prefix);
+        req.extensions_mut().insert(correlation_id.clone());
 
-        let inner = self.inner.call(req);
+        let inner = &mut self.inner;
+        let inner = with_correlation_id(correlation_id.as_str(), || inner.call(req));
         ResponseFuture {
             label: self.label.clone(),
             inner,
             request,
             user_agent,
             pid,
+            correlation_id,
+            started,
         }
     }
 }
@@ -114,3 +205,137 @@ where
         future::ok(self.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Once, ONCE_INIT};
+
+    use hyper::service::service_fn;
+    use hyper::Error as HyperError;
+    use log::{self, LevelFilter, Log, Metadata, Record};
+
+    use super::*;
+    use route::{Builder, Parameters, RegexRoutesBuilder, Router};
+
+    struct TestLogger {
+        records: Mutex<Vec<String>>,
+    }
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(format!("{}", record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    lazy_static! {
+        static ref LOGGER: TestLogger = TestLogger {
+            records: Mutex::new(vec![]),
+        };
+    }
+
+    static INIT_LOGGER: Once = ONCE_INIT;
+
+    fn logged_lines(correlation_id: &str) -> Vec<String> {
+        LOGGER
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(&format!("id({})", correlation_id)))
+            .cloned()
+            .collect()
+    }
+
+    fn init_logger() {
+        INIT_LOGGER.call_once(|| {
+            log::set_logger(&*LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Debug);
+        });
+    }
+
+    fn empty_response(_req: Request<Body>) -> Result<Response<Body>, HyperError> {
+        Ok(Response::new(Body::empty()))
+    }
+
+    fn correlation_id(response: &Response<Body>) -> String {
+        response
+            .headers()
+            .get(CORRELATION_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn response_has_correlation_id_header() {
+        let mut service = LoggingService::new("test".to_string(), service_fn(empty_response));
+
+        let first = service.call(Request::default()).wait().unwrap();
+        let second = service.call(Request::default()).wait().unwrap();
+
+        assert!(!correlation_id(&first).is_empty());
+        assert_ne!(correlation_id(&first), correlation_id(&second));
+    }
+
+    #[test]
+    fn handler_sees_correlation_id() {
+        fn handler(
+            _req: Request<Body>,
+            params: Parameters,
+        ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+            let id = params.correlation_id().unwrap_or("").to_string();
+            Box::new(future::ok(Response::new(id.into())))
+        }
+
+        let router = Router::from(RegexRoutesBuilder::default().get("/", handler).finish());
+        let mut service = LoggingService::new(
+            "test".to_string(),
+            router.new_service().wait().unwrap(),
+        );
+
+        let response = service.call(Request::default()).wait().unwrap();
+        let header = correlation_id(&response);
+        let body = response.into_body().concat2().wait().unwrap();
+
+        assert_eq!(header.as_bytes(), &*body);
+    }
+
+    #[test]
+    fn sensitive_query_values_are_not_logged() {
+        init_logger();
+
+        let mut service = LoggingService::new("test".to_string(), service_fn(empty_response));
+        let request = Request::get(
+            "/modules/marvin/logs?api-version=2018-06-28&sas_token=hunter2&SharedAccessKey=hunter3&tail=10",
+        ).body(Body::empty())
+            .unwrap();
+
+        let response = service.call(request).wait().unwrap();
+
+        let lines = logged_lines(&correlation_id(&response));
+        assert_eq!(1, lines.len());
+        assert!(lines[0].contains("/modules/marvin/logs?api-version=2018-06-28"));
+        assert!(lines[0].contains("sas_token=***"));
+        assert!(lines[0].contains("SharedAccessKey=***"));
+        assert!(lines[0].contains("tail=10"));
+        assert!(!lines[0].contains("hunter2"));
+        assert!(!lines[0].contains("hunter3"));
+    }
+
+    #[test]
+    fn redact_query_keeps_names_and_harmless_values() {
+        assert_eq!("api-version=1", redact_query("api-version=1"));
+        assert_eq!("key=***&follow", redact_query("key=abc&follow"));
+        assert_eq!("Token=***&Token=***", redact_query("Token=a&Token=b"));
+    }
+}
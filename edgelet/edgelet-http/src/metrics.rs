@@ -0,0 +1,298 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::Instant;
+
+use edgelet_core::metrics::{
+    duration_secs, HTTP_REQUESTS, HTTP_REQUESTS_IN_FLIGHT, HTTP_REQUEST_DURATION,
+};
+use edgelet_core::{Metrics, MetricsRegistry};
+use futures::future;
+use futures::prelude::*;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use hyper::service::{NewService, Service};
+use hyper::{Body, Error as HyperError};
+
+use error::Error;
+use route::{Handler, Parameters};
+use IntoResponse;
+
+/// The content type of the Prometheus text exposition format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counts the requests a server answers, how long it takes to answer them and
+/// how many it is working on. `server` names the server in the metrics.
+#[derive(Clone)]
+pub struct MetricsService<T> {
+    server: String,
+    metrics: Arc<Metrics>,
+    inner: T,
+}
+
+impl<T> MetricsService<T> {
+    pub fn new(server: String, metrics: Arc<Metrics>, inner: T) -> Self {
+        MetricsService {
+            server,
+            metrics,
+            inner,
+        }
+    }
+}
+
+pub struct ResponseFuture<T> {
+    inner: T,
+    server: String,
+    method: String,
+    metrics: Arc<Metrics>,
+    started: Instant,
+    finished: bool,
+}
+
+impl<T> ResponseFuture<T> {
+    fn finish(&mut self, status: &str) {
+        self.finished = true;
+        self.metrics.increment(
+            &HTTP_REQUESTS,
+            &[
+                ("server", &self.server),
+                ("method", &self.method),
+                ("status", status),
+            ],
+        );
+        self.metrics.observe(
+            &HTTP_REQUEST_DURATION,
+            &[("server", &self.server)],
+            duration_secs(self.started.elapsed()),
+        );
+        self.metrics
+            .add(&HTTP_REQUESTS_IN_FLIGHT, &[("server", &self.server)], -1.0);
+    }
+}
+
+impl<T> Future for ResponseFuture<T>
+where
+    T: Future<Item = Response<Body>>,
+{
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::Ready(response)) => {
+                self.finish(response.status().as_str());
+                Ok(Async::Ready(response))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                self.finish("error");
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T> Drop for ResponseFuture<T> {
+    fn drop(&mut self) {
+        // The connection went away before the request was answered.
+        if !self.finished {
+            self.metrics
+                .add(&HTTP_REQUESTS_IN_FLIGHT, &[("server", &self.server)], -1.0);
+        }
+    }
+}
+
+impl<T> Service for MetricsService<T>
+where
+    T: Service<ResBody = Body>,
+{
+    type ReqBody = T::ReqBody;
+    type ResBody = T::ResBody;
+    type Error = T::Error;
+    type Future = ResponseFuture<T::Future>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let method = req.method().to_string();
+        self.metrics
+            .add(&HTTP_REQUESTS_IN_FLIGHT, &[("server", &self.server)], 1.0);
+        ResponseFuture {
+            inner: self.inner.call(req),
+            server: self.server.clone(),
+            method,
+            metrics: self.metrics.clone(),
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+}
+
+impl<T> NewService for MetricsService<T>
+where
+    T: Clone + Service<ResBody = Body>,
+{
+    type ReqBody = <Self::Service as Service>::ReqBody;
+    type ResBody = <Self::Service as Service>::ResBody;
+    type Error = <Self::Service as Service>::Error;
+    type Service = Self;
+    type Future = future::FutureResult<Self::Service, Self::InitError>;
+    type InitError = Box<StdError + Send + Sync>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+/// Answers with the metrics in the Prometheus text exposition format.
+pub struct GetMetrics {
+    registry: MetricsRegistry,
+}
+
+impl GetMetrics {
+    pub fn new(registry: MetricsRegistry) -> Self {
+        GetMetrics { registry }
+    }
+}
+
+impl Handler<Parameters> for GetMetrics {
+    fn handle(
+        &self,
+        _req: Request<Body>,
+        _params: Parameters,
+    ) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+        let body = self.registry.render();
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, METRICS_CONTENT_TYPE)
+            .header(CONTENT_LENGTH, body.len().to_string().as_str())
+            .body(body.into())
+            .unwrap_or_else(|e| Error::from(e).into_response());
+
+        Box::new(future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::service::service_fn;
+
+    use super::*;
+    use route::{Builder, RegexRoutesBuilder, Router};
+
+    fn respond(req: Request<Body>) -> Result<Response<Body>, HyperError> {
+        let status = if req.uri().path() == "/missing" {
+            StatusCode::NOT_FOUND
+        } else {
+            StatusCode::OK
+        };
+        Ok(Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .unwrap())
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn requests_are_counted_by_method_and_status() {
+        let registry = MetricsRegistry::new();
+        let mut service = MetricsService::new(
+            "mgmt".to_string(),
+            Arc::new(registry.clone()),
+            service_fn(respond),
+        );
+
+        service.call(get("/modules")).wait().unwrap();
+        service.call(get("/modules")).wait().unwrap();
+        service.call(get("/missing")).wait().unwrap();
+        service
+            .call(Request::post("/modules").body(Body::empty()).unwrap())
+            .wait()
+            .unwrap();
+
+        let labels =
+            |method, status| vec![("server", "mgmt"), ("method", method), ("status", status)];
+        assert_eq!(2, registry.counter(&HTTP_REQUESTS, &labels("GET", "200")));
+        assert_eq!(1, registry.counter(&HTTP_REQUESTS, &labels("GET", "404")));
+        assert_eq!(1, registry.counter(&HTTP_REQUESTS, &labels("POST", "200")));
+        assert_eq!(
+            4,
+            registry.histogram_count(&HTTP_REQUEST_DURATION, &[("server", "mgmt")])
+        );
+        assert_eq!(
+            "0",
+            registry
+                .gauge(&HTTP_REQUESTS_IN_FLIGHT, &[("server", "mgmt")])
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn unanswered_requests_are_in_flight() {
+        let registry = MetricsRegistry::new();
+        let mut service = MetricsService::new(
+            "mgmt".to_string(),
+            Arc::new(registry.clone()),
+            service_fn(respond),
+        );
+
+        let first = service.call(get("/modules"));
+        let second = service.call(get("/modules"));
+        assert_eq!(
+            "2",
+            registry
+                .gauge(&HTTP_REQUESTS_IN_FLIGHT, &[("server", "mgmt")])
+                .to_string()
+        );
+
+        first.wait().unwrap();
+        drop(second);
+        assert_eq!(
+            "0",
+            registry
+                .gauge(&HTTP_REQUESTS_IN_FLIGHT, &[("server", "mgmt")])
+                .to_string()
+        );
+        assert_eq!(
+            1,
+            registry.counter(
+                &HTTP_REQUESTS,
+                &[("server", "mgmt"), ("method", "GET"), ("status", "200")]
+            )
+        );
+    }
+
+    #[test]
+    fn scrape_reports_requests() {
+        let registry = MetricsRegistry::new();
+        let router = Router::from(
+            RegexRoutesBuilder::default()
+                .get("/metrics", GetMetrics::new(registry.clone()))
+                .finish(),
+        );
+        let mut service = MetricsService::new(
+            "metrics".to_string(),
+            Arc::new(registry.clone()),
+            router.new_service().wait().unwrap(),
+        );
+
+        service.call(get("/metrics")).wait().unwrap();
+        let response = service.call(get("/metrics")).wait().unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            METRICS_CONTENT_TYPE,
+            response.headers().get(CONTENT_TYPE).unwrap()
+        );
+        let body = response.into_body().concat2().wait().unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        // The scrape being answered is counted once it is done.
+        assert!(body.contains("# TYPE edgelet_http_requests_total counter\n"));
+        assert!(body.contains(
+            "edgelet_http_requests_total{method=\"GET\",server=\"metrics\",status=\"200\"} 1\n"
+        ));
+        assert!(body.contains("edgelet_http_requests_in_flight{server=\"metrics\"} 1\n"));
+    }
+}
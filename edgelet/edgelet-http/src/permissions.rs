@@ -0,0 +1,43 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+/// Mode and ownership given to a unix socket once it has been bound. Unset
+/// values are left as the socket was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SocketPermissions {
+    mode: Option<u32>,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+impl SocketPermissions {
+    pub fn new() -> Self {
+        SocketPermissions::default()
+    }
+
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn owner(&self) -> Option<u32> {
+        self.owner
+    }
+
+    pub fn with_owner(mut self, owner: u32) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn group(&self) -> Option<u32> {
+        self.group
+    }
+
+    pub fn with_group(mut self, group: u32) -> Self {
+        self.group = Some(group);
+        self
+    }
+}
@@ -5,15 +5,30 @@ use futures::prelude::*;
 use hyper::service::Service;
 use hyper::{Body, Error as HyperError, Request};
 
+/// The user id of the process on the other end of a connection, for
+/// listeners that can tell.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Uid(pub u32);
+
 #[derive(Clone)]
 pub struct PidService<T> {
     pid: Pid,
+    uid: Option<Uid>,
     inner: T,
 }
 
 impl<T> PidService<T> {
     pub fn new(pid: Pid, inner: T) -> Self {
-        PidService { pid, inner }
+        PidService {
+            pid,
+            uid: None,
+            inner,
+        }
+    }
+
+    pub fn with_uid(mut self, uid: Option<Uid>) -> Self {
+        self.uid = uid;
+        self
     }
 }
 
@@ -31,6 +46,9 @@ where
     fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
         let mut req = req;
         req.extensions_mut().insert(self.pid);
+        if let Some(uid) = self.uid {
+            req.extensions_mut().insert(uid);
+        }
         self.inner.call(req)
     }
 }
@@ -49,15 +67,20 @@ mod impl_unix {
 
     pub trait UnixStreamExt {
         fn pid(&self) -> io::Result<Pid>;
+        fn uid(&self) -> io::Result<Uid>;
     }
 
     impl UnixStreamExt for UnixStream {
         fn pid(&self) -> io::Result<Pid> {
-            get_pid(self)
+            get_ucred(self).map(|ucred| Pid::Value(ucred.pid))
+        }
+
+        fn uid(&self) -> io::Result<Uid> {
+            get_ucred(self).map(|ucred| Uid(ucred.uid))
         }
     }
 
-    pub fn get_pid(sock: &UnixStream) -> io::Result<Pid> {
+    fn get_ucred(sock: &UnixStream) -> io::Result<ucred> {
         let raw_fd = sock.as_raw_fd();
         let mut ucred = ucred {
             pid: 0,
@@ -83,7 +106,7 @@ mod impl_unix {
             )
         };
         if ret == 0 && ucred_size as usize == mem::size_of::<ucred>() {
-            Ok(Pid::Value(ucred.pid))
+            Ok(ucred)
         } else {
             Err(io::Error::last_os_error())
         }
@@ -0,0 +1,495 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Request rate limits per caller
+//!
+//! Every caller gets a token bucket for each class of route it calls. A
+//! bucket holds up to `burst` requests and refills at `per_sec` requests a
+//! second. A request that finds its bucket empty is answered with 429 and a
+//! `Retry-After` header saying when the bucket will have a token again.
+//!
+//! Callers are told apart by the user id of their process, so that a caller
+//! can't get fresh buckets by making requests from new processes. Only where
+//! the user id can't be seen are callers told apart by their process id. On
+//! listeners that can't see either, like TCP ones, all callers share their
+//! buckets. Callers whose user id is exempt are never limited.
+//!
+//! At most `max_callers` buckets are kept. Once that many exist, the buckets
+//! that refilled completely are dropped first, which loses nothing, and then
+//! those of the callers that were idle the longest.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use edgelet_core::pid::Pid;
+use futures::future::{self, Either, FutureResult};
+use http::header::RETRY_AFTER;
+use hyper::service::{NewService, Service};
+use hyper::{Body, Request, Response};
+
+use error::{Error, ErrorKind};
+use pid::Uid;
+use IntoResponse;
+
+/// How many callers are remembered by default.
+const DEFAULT_MAX_CALLERS: usize = 1024;
+
+/// How expensive the requests to a route are to serve.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RouteClass {
+    Cheap,
+    Expensive,
+}
+
+/// The size and refill rate of a token bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    per_sec: u32,
+    burst: u32,
+}
+
+impl Rate {
+    /// A bucket that refills too slowly to count is refilled once a second.
+    pub fn new(per_sec: u32, burst: u32) -> Self {
+        Rate {
+            per_sec: per_sec.max(1),
+            burst: burst.max(1),
+        }
+    }
+
+    pub fn per_sec(&self) -> u32 {
+        self.per_sec
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+/// The rates callers are limited to. Classes without a rate are not limited.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimits {
+    cheap: Option<Rate>,
+    expensive: Option<Rate>,
+    exempt_uids: Vec<u32>,
+    max_callers: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            cheap: None,
+            expensive: None,
+            exempt_uids: vec![],
+            max_callers: DEFAULT_MAX_CALLERS,
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn new() -> Self {
+        RateLimits::default()
+    }
+
+    pub fn rate(&self, class: RouteClass) -> Option<Rate> {
+        match class {
+            RouteClass::Cheap => self.cheap,
+            RouteClass::Expensive => self.expensive,
+        }
+    }
+
+    pub fn with_cheap(mut self, rate: Option<Rate>) -> Self {
+        self.cheap = rate;
+        self
+    }
+
+    pub fn with_expensive(mut self, rate: Option<Rate>) -> Self {
+        self.expensive = rate;
+        self
+    }
+
+    pub fn exempt_uids(&self) -> &[u32] {
+        &self.exempt_uids
+    }
+
+    pub fn with_exempt_uids(mut self, exempt_uids: Vec<u32>) -> Self {
+        self.exempt_uids = exempt_uids;
+        self
+    }
+
+    pub fn max_callers(&self) -> usize {
+        self.max_callers
+    }
+
+    pub fn with_max_callers(mut self, max_callers: usize) -> Self {
+        self.max_callers = max_callers.max(1);
+        self
+    }
+
+    fn is_exempt(&self, caller: Caller) -> bool {
+        caller
+            .uid
+            .map_or(false, |Uid(uid)| self.exempt_uids.contains(&uid))
+    }
+}
+
+/// Whom a request's bucket belongs to. The process id only counts when the
+/// user id is unknown.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Caller {
+    uid: Option<Uid>,
+    pid: Option<i32>,
+}
+
+impl Caller {
+    pub fn new(uid: Option<Uid>, pid: Pid) -> Self {
+        let pid = match (uid, pid) {
+            (None, Pid::Value(pid)) => Some(pid),
+            _ => None,
+        };
+        Caller { uid, pid }
+    }
+
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        Caller::new(
+            req.extensions().get::<Uid>().cloned(),
+            req.extensions().get::<Pid>().cloned().unwrap_or(Pid::None),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.duration_since(self.updated);
+        #[cfg_attr(feature = "cargo-clippy", allow(cast_precision_loss))]
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + elapsed * f64::from(rate.per_sec)).min(f64::from(rate.burst));
+        self.updated = now;
+    }
+
+    fn is_full(&self, rate: Rate, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs();
+        elapsed >= u64::from(rate.burst / rate.per_sec + 1)
+            || self.tokens >= f64::from(rate.burst)
+    }
+}
+
+type Buckets = HashMap<(Caller, RouteClass), Bucket>;
+
+#[derive(Debug, Default)]
+struct State {
+    limits: RateLimits,
+    buckets: Buckets,
+}
+
+/// Clones share the limits and the buckets.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            inner: Arc::new(Mutex::new(State {
+                limits,
+                buckets: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.lock().limits.clone()
+    }
+
+    /// Applies new limits. Buckets that hold more than the new burst are
+    /// cut down the next time they are used.
+    pub fn set_limits(&self, limits: RateLimits) {
+        self.lock().limits = limits;
+    }
+
+    /// Takes a token from the caller's bucket for the class, or tells how
+    /// long it will take until there is one.
+    pub fn acquire(&self, caller: Caller, class: RouteClass) -> Result<(), Duration> {
+        self.acquire_at(caller, class, Instant::now())
+    }
+
+    fn acquire_at(&self, caller: Caller, class: RouteClass, now: Instant) -> Result<(), Duration> {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        let rate = match state.limits.rate(class) {
+            Some(rate) if !state.limits.is_exempt(caller) => rate,
+            _ => return Ok(()),
+        };
+
+        let key = (caller, class);
+        if !state.buckets.contains_key(&key) && state.buckets.len() >= state.limits.max_callers {
+            evict(&state.limits, &mut state.buckets, now);
+        }
+        let bucket = state.buckets.entry(key).or_insert_with(|| Bucket {
+            tokens: f64::from(rate.burst),
+            updated: now,
+        });
+        bucket.refill(rate, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            #[cfg_attr(
+                feature = "cargo-clippy",
+                allow(cast_possible_truncation, cast_sign_loss)
+            )]
+            let millis = ((1.0 - bucket.tokens) * 1000.0 / f64::from(rate.per_sec)).ceil() as u64;
+            Err(Duration::from_millis(millis))
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        // Each change is made whole while the lock is held, so a panic while
+        // it was held cannot have left the buckets inconsistent.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Makes room for another bucket.
+fn evict(limits: &RateLimits, buckets: &mut Buckets, now: Instant) {
+    buckets.retain(|&(_, class), bucket| {
+        limits
+            .rate(class)
+            .map_or(false, |rate| !bucket.is_full(rate, now))
+    });
+    while buckets.len() >= limits.max_callers {
+        let idlest = buckets
+            .iter()
+            .min_by_key(|(_, bucket)| bucket.updated)
+            .map(|(key, _)| *key);
+        match idlest {
+            Some(key) => {
+                buckets.remove(&key);
+            }
+            None => break,
+        }
+    }
+}
+
+/// Answers requests whose caller ran out of tokens for the route's class
+/// with 429.
+#[derive(Clone)]
+pub struct RateLimitService<T> {
+    inner: T,
+    limiter: RateLimiter,
+    classify: fn(&Request<Body>) -> RouteClass,
+}
+
+impl<T> RateLimitService<T> {
+    pub fn new(inner: T, limiter: RateLimiter, classify: fn(&Request<Body>) -> RouteClass) -> Self {
+        RateLimitService {
+            inner,
+            limiter,
+            classify,
+        }
+    }
+}
+
+impl<T> Service for RateLimitService<T>
+where
+    T: Service<ReqBody = Body, ResBody = Body>,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = T::Error;
+    type Future = Either<FutureResult<Response<Body>, T::Error>, T::Future>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let caller = Caller::from_request(&req);
+        match self.limiter.acquire(caller, (self.classify)(&req)) {
+            Ok(()) => Either::B(self.inner.call(req)),
+            Err(retry_after) => {
+                debug!(
+                    "rate limiting {:?} calling {} {}",
+                    caller,
+                    req.method(),
+                    req.uri().path()
+                );
+                Either::A(future::ok(too_many_requests(retry_after)))
+            }
+        }
+    }
+}
+
+impl<T> NewService for RateLimitService<T>
+where
+    T: Clone + Service<ReqBody = Body, ResBody = Body>,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = T::Error;
+    type Service = Self;
+    type Future = FutureResult<Self::Service, Self::InitError>;
+    type InitError = Box<StdError + Send + Sync>;
+
+    fn new_service(&self) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> Response<Body> {
+    let mut response = Error::from(ErrorKind::TooManyRequests).into_response();
+    let secs = retry_after.as_secs() + if retry_after.subsec_nanos() > 0 { 1 } else { 0 };
+    let value = secs.max(1).to_string().parse().expect("seconds are a valid header value");
+    response.headers_mut().insert(RETRY_AFTER, value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Future;
+    use http::StatusCode;
+    use hyper::service::service_fn;
+    use hyper::Error as HyperError;
+
+    const AGENT_UID: u32 = 13623;
+
+    fn caller(uid: u32, pid: i32) -> Caller {
+        Caller::new(Some(Uid(uid)), Pid::Value(pid))
+    }
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(
+            RateLimits::new()
+                .with_cheap(Some(Rate::new(10, 5)))
+                .with_expensive(Some(Rate::new(1, 2)))
+                .with_exempt_uids(vec![AGENT_UID]),
+        )
+    }
+
+    /// How many of ten requests sent at once are let through.
+    fn burst(limiter: &RateLimiter, caller: Caller, class: RouteClass, now: Instant) -> usize {
+        (0..10)
+            .filter(|_| limiter.acquire_at(caller, class, now).is_ok())
+            .count()
+    }
+
+    #[test]
+    fn callers_are_limited_independently() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let (a, b) = (caller(1000, 10), caller(1001, 20));
+
+        assert_eq!(2, burst(&limiter, a, RouteClass::Expensive, now));
+        assert_eq!(2, burst(&limiter, b, RouteClass::Expensive, now));
+        assert_eq!(5, burst(&limiter, a, RouteClass::Cheap, now));
+
+        assert_eq!(
+            Err(Duration::from_secs(1)),
+            limiter.acquire_at(a, RouteClass::Expensive, now)
+        );
+        let later = now + Duration::from_millis(1500);
+        assert_eq!(1, burst(&limiter, a, RouteClass::Expensive, later));
+        assert_eq!(
+            Err(Duration::from_millis(500)),
+            limiter.acquire_at(a, RouteClass::Expensive, later)
+        );
+    }
+
+    #[test]
+    fn processes_of_a_user_share_buckets() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert_eq!(2, burst(&limiter, caller(1000, 10), RouteClass::Expensive, now));
+        assert_eq!(0, burst(&limiter, caller(1000, 20), RouteClass::Expensive, now));
+
+        // Without a user id, processes are told apart.
+        let (a, b) = (Caller::new(None, Pid::Value(10)), Caller::new(None, Pid::Value(20)));
+        assert_eq!(2, burst(&limiter, a, RouteClass::Expensive, now));
+        assert_eq!(2, burst(&limiter, b, RouteClass::Expensive, now));
+    }
+
+    #[test]
+    fn exempt_callers_are_not_limited() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let agent = caller(AGENT_UID, 10);
+
+        assert_eq!(10, burst(&limiter, agent, RouteClass::Expensive, now));
+        assert_eq!(2, burst(&limiter, caller(0, 20), RouteClass::Expensive, now));
+        assert_eq!(10, burst(&limiter, agent, RouteClass::Expensive, now));
+    }
+
+    #[test]
+    fn idle_callers_are_forgotten() {
+        let limiter = RateLimiter::new(
+            RateLimits::new()
+                .with_expensive(Some(Rate::new(1, 2)))
+                .with_max_callers(2),
+        );
+        let now = Instant::now();
+        let mut at = now;
+        for uid in 0..100 {
+            at += Duration::from_millis(1);
+            assert_eq!(2, burst(&limiter, caller(uid, 10), RouteClass::Expensive, at));
+            assert!(limiter.lock().buckets.len() <= 2);
+        }
+
+        // Buckets that refilled are dropped before those still in use.
+        let limiter = RateLimiter::new(
+            RateLimits::new()
+                .with_expensive(Some(Rate::new(1, 2)))
+                .with_max_callers(2),
+        );
+        let (a, b, c) = (caller(1, 10), caller(2, 10), caller(3, 10));
+        burst(&limiter, a, RouteClass::Expensive, now);
+        burst(&limiter, b, RouteClass::Expensive, now + Duration::from_secs(5));
+        burst(&limiter, c, RouteClass::Expensive, now + Duration::from_secs(6));
+        // b kept its bucket, which has refilled by a single token since.
+        assert_eq!(
+            1,
+            burst(&limiter, b, RouteClass::Expensive, now + Duration::from_secs(6))
+        );
+        assert!(!limiter.lock().buckets.contains_key(&(a, RouteClass::Expensive)));
+    }
+
+    #[test]
+    fn limited_requests_are_answered_with_429() {
+        let limiter = limiter();
+        let mut service = RateLimitService::new(
+            service_fn(|_req: Request<Body>| {
+                future::ok::<_, HyperError>(Response::new(Body::empty()))
+            }),
+            limiter,
+            |req| {
+                if req.uri().path().ends_with("/logs") {
+                    RouteClass::Expensive
+                } else {
+                    RouteClass::Cheap
+                }
+            },
+        );
+        let call = |service: &mut RateLimitService<_>, path: &str| {
+            let mut req = Request::get(format!("http://localhost{}", path))
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut().insert(Pid::Value(10));
+            req.extensions_mut().insert(Uid(1000));
+            service.call(req).wait().unwrap()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(StatusCode::OK, call(&mut service, "/modules/abc/logs").status());
+        }
+        let response = call(&mut service, "/modules/abc/logs");
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+        assert_eq!("1", response.headers()[RETRY_AFTER]);
+        assert_eq!(StatusCode::OK, call(&mut service, "/modules/abc").status());
+    }
+}
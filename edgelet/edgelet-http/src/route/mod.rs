@@ -11,7 +11,10 @@ use futures::{future, Future};
 use hyper::service::{NewService, Service};
 use hyper::{self, Body, Method, Request, Response, StatusCode};
 
+use logging::CorrelationId;
+
 pub mod macros;
+mod query;
 mod regex;
 
 pub type BoxFuture<T, E> = Box<Future<Item = T, Error = E>>;
@@ -108,7 +111,7 @@ impl<R: Recognizer> From<R> for Router<R> {
 
 impl<R> NewService for Router<R>
 where
-    R: Recognizer,
+    R: Recognizer<Parameters = Parameters>,
 {
     type ReqBody = <Self::Service as Service>::ReqBody;
     type ResBody = <Self::Service as Service>::ResBody;
@@ -141,7 +144,7 @@ where
 
 impl<R> Service for RouterService<R>
 where
-    R: Recognizer,
+    R: Recognizer<Parameters = Parameters>,
 {
     type ReqBody = Body;
     type ResBody = Body;
@@ -152,7 +155,13 @@ where
         let method = req.method().clone();
         let path = req.uri().path().to_owned();
         match self.inner.recognize(&method, &path) {
-            Ok((handler, params)) => handler.handle(req, params),
+            Ok((handler, params)) => {
+                let params = match req.extensions().get::<CorrelationId>() {
+                    Some(id) => params.with_correlation_id(id.to_string()),
+                    None => params,
+                };
+                handler.handle(req, params)
+            }
 
             Err(code) => Box::new(future::ok(
                 Response::builder()
@@ -164,4 +173,5 @@ where
     }
 }
 
+pub use route::query::Query;
 pub use route::regex::{Parameters, RegexRecognizer, RegexRoutesBuilder};
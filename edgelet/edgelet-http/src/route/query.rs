@@ -0,0 +1,222 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::str::FromStr;
+
+use failure::{Fail, ResultExt};
+use http::Request;
+use percent_encoding::percent_decode;
+
+use error::{Error, ErrorKind};
+
+/// The parsed query string of a request.
+///
+/// Keys are case sensitive and may appear more than once. A key given
+/// without a value (`?follow`) has an empty value. The typed getters reject
+/// keys that appear more than once, since there's no way to tell which value
+/// the caller meant.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let key = decode(parts.next().unwrap_or(""));
+                let value = decode(parts.next().unwrap_or(""));
+                (key, value)
+            }).collect();
+        Query { pairs }
+    }
+
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        req.uri().query().map_or_else(Query::default, Query::parse)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    /// All values given for `key`, in the order they appear.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<&str>, Error> {
+        let mut values = self.get_all(key).into_iter();
+        match (values.next(), values.next()) {
+            (None, _) => Ok(None),
+            (Some(value), None) => Ok(Some(value)),
+            (Some(_), Some(_)) => Err(Error::from(ErrorKind::DuplicateQueryParameter(
+                key.to_string(),
+            ))),
+        }
+    }
+
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: Fail,
+    {
+        match self.get(key)? {
+            Some(value) => {
+                let value = value
+                    .parse::<T>()
+                    .context(ErrorKind::InvalidQueryParameter(key.to_string()))?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// A key without a value counts as `true`, so `?follow` and
+    /// `?follow=true` are equivalent.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        match self.get(key)? {
+            Some("") | Some("true") | Some("1") => Ok(Some(true)),
+            Some("false") | Some("0") => Ok(Some(false)),
+            Some(_) => Err(Error::from(ErrorKind::InvalidQueryParameter(
+                key.to_string(),
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_i32_in_range(&self, key: &str, min: i32, max: i32) -> Result<Option<i32>, Error> {
+        match self.get_parsed::<i32>(key)? {
+            Some(value) if value < min || value > max => Err(Error::from(
+                ErrorKind::QueryParameterOutOfRange(key.to_string(), min, max),
+            )),
+            value => Ok(value),
+        }
+    }
+}
+
+fn decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+
+    use super::*;
+
+    #[test]
+    fn parse_empty() {
+        let query = Query::parse("");
+        assert_eq!(Query::default(), query);
+        assert!(!query.contains("follow"));
+        assert_eq!(None, query.get("follow").unwrap());
+    }
+
+    #[test]
+    fn parse_pairs() {
+        let query = Query::parse("follow=true&tail=10");
+        assert_eq!(Some("true"), query.get("follow").unwrap());
+        assert_eq!(Some("10"), query.get("tail").unwrap());
+    }
+
+    #[test]
+    fn keys_are_case_sensitive() {
+        let query = Query::parse("Follow=true");
+        assert!(query.contains("Follow"));
+        assert!(!query.contains("follow"));
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let query = Query::parse("tail=1&tail=2");
+        assert_eq!(vec!["1", "2"], query.get_all("tail"));
+        assert_eq!(
+            &ErrorKind::DuplicateQueryParameter("tail".to_string()),
+            query.get("tail").unwrap_err().kind()
+        );
+        assert_eq!(
+            &ErrorKind::DuplicateQueryParameter("tail".to_string()),
+            query.get_i32_in_range("tail", 0, 10).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn percent_encoding() {
+        let query = Query::parse("na%20me=a%2Fb&plus=a+b&bad=%zz");
+        assert_eq!(Some("a/b"), query.get("na me").unwrap());
+        assert_eq!(Some("a b"), query.get("plus").unwrap());
+        assert_eq!(Some("%zz"), query.get("bad").unwrap());
+    }
+
+    #[test]
+    fn missing_values() {
+        let query = Query::parse("follow&tail=&&timeout");
+        assert_eq!(Some(""), query.get("follow").unwrap());
+        assert_eq!(Some(true), query.get_bool("follow").unwrap());
+        assert_eq!(Some(true), query.get_bool("tail").unwrap());
+        assert_eq!(
+            &ErrorKind::InvalidQueryParameter("timeout".to_string()),
+            query.get_i32_in_range("timeout", 1, 600).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn value_with_equals_sign() {
+        let query = Query::parse("filter=a=b");
+        assert_eq!(Some("a=b"), query.get("filter").unwrap());
+    }
+
+    #[test]
+    fn get_bool() {
+        let query = Query::parse("a=true&b=false&c=1&d=0&e=yes");
+        assert_eq!(Some(true), query.get_bool("a").unwrap());
+        assert_eq!(Some(false), query.get_bool("b").unwrap());
+        assert_eq!(Some(true), query.get_bool("c").unwrap());
+        assert_eq!(Some(false), query.get_bool("d").unwrap());
+        assert_eq!(None, query.get_bool("f").unwrap());
+        assert_eq!(
+            &ErrorKind::InvalidQueryParameter("e".to_string()),
+            query.get_bool("e").unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn get_i32_in_range() {
+        let query = Query::parse("a=1&b=600&c=0&d=601&e=-5&f=ten");
+        assert_eq!(Some(1), query.get_i32_in_range("a", 1, 600).unwrap());
+        assert_eq!(Some(600), query.get_i32_in_range("b", 1, 600).unwrap());
+        assert_eq!(None, query.get_i32_in_range("g", 1, 600).unwrap());
+        for key in &["c", "d", "e"] {
+            assert_eq!(
+                &ErrorKind::QueryParameterOutOfRange(key.to_string(), 1, 600),
+                query.get_i32_in_range(key, 1, 600).unwrap_err().kind()
+            );
+        }
+        assert_eq!(
+            &ErrorKind::InvalidQueryParameter("f".to_string()),
+            query.get_i32_in_range("f", 1, 600).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn from_request() {
+        let req = Request::get("http://localhost/modules?api-version=2018-06-28&follow")
+            .body(Body::empty())
+            .unwrap();
+        let query = Query::from_request(&req);
+        assert_eq!(Some("2018-06-28"), query.get("api-version").unwrap());
+        assert!(query.contains("follow"));
+
+        let req = Request::get("http://localhost/modules")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(Query::default(), Query::from_request(&req));
+    }
+}
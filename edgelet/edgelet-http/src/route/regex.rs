@@ -17,11 +17,15 @@ pub trait IntoCaptures {
 #[derive(Debug, PartialEq)]
 pub struct Parameters {
     captures: Vec<(Option<String>, String)>,
+    correlation_id: Option<String>,
 }
 
 impl Parameters {
     pub fn new() -> Self {
-        Parameters { captures: vec![] }
+        Parameters {
+            captures: vec![],
+            correlation_id: None,
+        }
     }
 
     pub fn with_captures<I>(captures: I) -> Self
@@ -30,9 +34,21 @@ impl Parameters {
     {
         Parameters {
             captures: captures.into_captures(),
+            correlation_id: None,
         }
     }
 
+    /// The id `LoggingService` assigned to the request, for use in logs
+    /// written while handling it.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_correlation_id(mut self, correlation_id: String) -> Self {
+        self.correlation_id = Some(correlation_id);
+        self
+    }
+
     pub fn name(&self, k: &str) -> Option<&str> {
         for capture in &self.captures {
             if let (Some(ref key), ref val) = *capture {
@@ -134,7 +150,10 @@ fn match_route(re: &Regex, path: &str) -> Option<Parameters> {
                 );
             captures.push((name.map(|s| s.to_owned()), val));
         }
-        Parameters { captures }
+        Parameters {
+            captures,
+            correlation_id: None,
+        }
     })
 }
 
@@ -0,0 +1,229 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Cleans requests up before they are handled, for when a listener is
+//! fronted by a reverse proxy.
+//!
+//! Requests whose headers exceed the connection limits are answered with 431,
+//! and requests whose framing could be read more than one way, like one with
+//! both `Content-Length` and `Transfer-Encoding`, with 400, since a proxy in
+//! front may have read them the other way. The hop-by-hop headers of RFC 7230
+//! and those the `Connection` header names are removed from the requests that
+//! are let through, as are the `Forwarded` and `X-Forwarded-*` headers proxies
+//! add.
+
+use futures::future::{self, Either, FutureResult};
+use http::header::{HeaderName, CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::HeaderMap;
+use hyper::service::Service;
+use hyper::{Body, Request, Response};
+
+use error::{Error, ErrorKind};
+use limit::ConnectionLimits;
+use IntoResponse;
+
+/// The headers that only concern the connection they were sent on.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-";
+
+/// Each header field is sent as `name: value` followed by CRLF.
+const HEADER_OVERHEAD: usize = 4;
+
+pub struct SanitizeService<T> {
+    inner: T,
+    limits: ConnectionLimits,
+}
+
+impl<T> SanitizeService<T> {
+    pub fn new(inner: T, limits: ConnectionLimits) -> Self {
+        SanitizeService { inner, limits }
+    }
+}
+
+impl<T> Service for SanitizeService<T>
+where
+    T: Service<ReqBody = Body, ResBody = Body>,
+{
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = T::Error;
+    type Future = Either<FutureResult<Response<Body>, T::Error>, T::Future>;
+
+    fn call(&mut self, req: Request<Self::ReqBody>) -> Self::Future {
+        let mut req = req;
+        let checked = check_size(req.headers(), &self.limits)
+            .and_then(|()| check_framing(req.headers_mut()));
+        match checked {
+            Ok(()) => {
+                strip_hop_by_hop(req.headers_mut());
+                Either::B(self.inner.call(req))
+            }
+            Err(err) => {
+                debug!("rejecting request for {}: {}", req.uri().path(), err);
+                Either::A(future::ok(err.into_response()))
+            }
+        }
+    }
+}
+
+fn check_size(headers: &HeaderMap, limits: &ConnectionLimits) -> Result<(), Error> {
+    if let Some(max) = limits.max_headers() {
+        if headers.len() > max {
+            return Err(Error::from(ErrorKind::HeadersTooLarge));
+        }
+    }
+    if let Some(max) = limits.max_header_bytes() {
+        let size: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + HEADER_OVERHEAD)
+            .sum();
+        if size > max {
+            return Err(Error::from(ErrorKind::HeadersTooLarge));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects requests that don't say one way how long their body is. Repeated
+/// `Content-Length` headers that agree are merged into one.
+fn check_framing(headers: &mut HeaderMap) -> Result<(), Error> {
+    let lengths: Vec<_> = headers.get_all(CONTENT_LENGTH).iter().cloned().collect();
+    let encodings: Vec<_> = headers.get_all(TRANSFER_ENCODING).iter().cloned().collect();
+
+    let ambiguous = (!lengths.is_empty() && !encodings.is_empty())
+        || lengths.windows(2).any(|pair| pair[0] != pair[1]);
+    if ambiguous {
+        return Err(Error::from(ErrorKind::AmbiguousFraming));
+    }
+    if lengths.len() > 1 {
+        headers.insert(CONTENT_LENGTH, lengths[0].clone());
+    }
+
+    // Only a body whose last coding is chunked has a known end.
+    if let Some(last) = encodings.last() {
+        let chunked = last
+            .to_str()
+            .ok()
+            .and_then(|codings| codings.split(',').last())
+            .map_or(false, |coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        if !chunked {
+            return Err(Error::from(ErrorKind::AmbiguousFraming));
+        }
+    }
+    Ok(())
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let mut names: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    names.extend(
+        headers
+            .keys()
+            .filter(|name| {
+                HOP_BY_HOP.contains(&name.as_str())
+                    || name.as_str() == FORWARDED
+                    || name.as_str().starts_with(X_FORWARDED_PREFIX)
+            }).cloned(),
+    );
+    for name in names {
+        headers.remove(&name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{Future, Stream};
+    use http::StatusCode;
+    use hyper::service::service_fn;
+    use hyper::Error as HyperError;
+
+    /// Answers with the names of the headers it got, one per line.
+    fn header_names(req: Request<Body>) -> FutureResult<Response<Body>, HyperError> {
+        let mut names: Vec<_> = req.headers().keys().map(HeaderName::as_str).collect();
+        names.sort();
+        future::ok(Response::new(Body::from(names.join("\n"))))
+    }
+
+    fn call(limits: ConnectionLimits, headers: &[(&str, &str)]) -> (StatusCode, String) {
+        let mut req = Request::post("http://localhost/modules");
+        for &(name, value) in headers {
+            req.header(name, value);
+        }
+        let mut service = SanitizeService::new(service_fn(header_names), limits);
+        let response = service.call(req.body(Body::empty()).unwrap()).wait().unwrap();
+        let status = response.status();
+        let body = response.into_body().concat2().wait().unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn hop_by_hop_headers_are_stripped() {
+        let (status, names) = call(
+            ConnectionLimits::new(),
+            &[
+                ("connection", "keep-alive, x-session"),
+                ("keep-alive", "timeout=5"),
+                ("x-session", "abc"),
+                ("te", "trailers"),
+                ("upgrade", "websocket"),
+                ("forwarded", "for=10.0.0.1"),
+                ("x-forwarded-for", "10.0.0.1"),
+                ("x-forwarded-proto", "https"),
+                ("content-type", "application/json"),
+                ("x-request-id", "1"),
+            ],
+        );
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("content-type\nx-request-id", names);
+    }
+
+    #[test]
+    fn requests_beyond_the_header_limits_are_rejected() {
+        let limits = ConnectionLimits::new()
+            .with_max_headers(Some(2))
+            .with_max_header_bytes(Some(32));
+        let (status, _) = call(limits, &[("a", "1"), ("b", "2")]);
+        assert_eq!(StatusCode::OK, status);
+
+        let (status, _) = call(limits, &[("a", "1"), ("b", "2"), ("a", "3")]);
+        assert_eq!(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, status);
+
+        let (status, _) = call(limits, &[("a", &"x".repeat(28)[..])]);
+        assert_eq!(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, status);
+    }
+
+    #[test]
+    fn ambiguous_framing_is_rejected() {
+        let limits = ConnectionLimits::new();
+        for headers in &[
+            &[("content-length", "5"), ("transfer-encoding", "chunked")][..],
+            &[("content-length", "5"), ("content-length", "6")][..],
+            &[("transfer-encoding", "chunked, gzip")][..],
+        ] {
+            let (status, _) = call(limits, headers);
+            assert_eq!(StatusCode::BAD_REQUEST, status, "{:?}", headers);
+        }
+
+        let (status, names) = call(limits, &[("content-length", "0"), ("content-length", "0")]);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("content-length", names);
+    }
+}
@@ -0,0 +1,386 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! TLS for `https` listeners, and the protocol versions and cipher suites
+//! TLS connections are limited to.
+//!
+//! A listener is given a certificate, the rest of its chain and the private
+//! key to go with it, either from files or as PEM, for example when they were
+//! issued by the workload CA. With a client CA, clients have to present a
+//! certificate it issued before the handshake completes.
+//!
+//! Listeners are served with OpenSSL, which a `TlsPolicy` fully applies to.
+//! Outgoing connections are made with the TLS library of the platform, which
+//! can only be told the lowest version to allow, and no higher than 1.2. They
+//! keep to the cipher suites of the platform.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use failure::ResultExt;
+use native_tls::{Protocol, TlsConnectorBuilder};
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{
+    SslAcceptor, SslContext, SslContextBuilder, SslMethod, SslVerifyMode, SslVersion,
+};
+use openssl::x509::X509;
+use tokio::net::TcpStream;
+use tokio_openssl::{AcceptAsync, SslAcceptorExt};
+
+use error::{Error, ErrorKind};
+use util::connector::load_certs;
+
+/// Cipher suites of TLS 1.3 are named differently from those of the earlier
+/// versions, and OpenSSL is given them separately.
+const TLS13_CIPHER_SUITE_PREFIX: &str = "TLS_";
+
+/// A version of the TLS protocol, ordered from oldest to newest.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    fn ssl_version(self) -> SslVersion {
+        match self {
+            TlsVersion::Tls10 => SslVersion::TLS1,
+            TlsVersion::Tls11 => SslVersion::TLS1_1,
+            TlsVersion::Tls12 => SslVersion::TLS1_2,
+            TlsVersion::Tls13 => SslVersion::TLS1_3,
+        }
+    }
+
+    /// The closest the platform's TLS library can come to requiring this
+    /// version.
+    fn protocol(self) -> Protocol {
+        match self {
+            TlsVersion::Tls10 => Protocol::Tlsv10,
+            TlsVersion::Tls11 => Protocol::Tlsv11,
+            TlsVersion::Tls12 | TlsVersion::Tls13 => Protocol::Tlsv12,
+        }
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.0" => Ok(TlsVersion::Tls10),
+            "1.1" => Ok(TlsVersion::Tls11),
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(Error::from(ErrorKind::InvalidTlsVersion(s.to_string()))),
+        }
+    }
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = match *self {
+            TlsVersion::Tls10 => "1.0",
+            TlsVersion::Tls11 => "1.1",
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        };
+        f.write_str(version)
+    }
+}
+
+/// The lowest TLS version connections may use, and the cipher suites they
+/// may use, by their OpenSSL names. Without either, the defaults of the TLS
+/// library apply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TlsPolicy {
+    min_version: Option<TlsVersion>,
+    cipher_suites: Vec<String>,
+}
+
+impl TlsPolicy {
+    pub fn new() -> Self {
+        TlsPolicy::default()
+    }
+
+    pub fn min_version(&self) -> Option<TlsVersion> {
+        self.min_version
+    }
+
+    pub fn with_min_version(mut self, min_version: TlsVersion) -> Self {
+        self.min_version = Some(min_version);
+        self
+    }
+
+    pub fn cipher_suites(&self) -> &[String] {
+        &self.cipher_suites
+    }
+
+    /// Limits the cipher suites to `cipher_suites`. Naming only suites of
+    /// TLS 1.3, such as `TLS_AES_256_GCM_SHA384`, rules out the earlier
+    /// versions, and naming none of them rules out TLS 1.3.
+    pub fn with_cipher_suites(mut self, cipher_suites: Vec<String>) -> Self {
+        self.cipher_suites = cipher_suites;
+        self
+    }
+
+    /// Checks that OpenSSL knows every cipher suite, and that some of them
+    /// can be used with the lowest version allowed.
+    pub fn check(&self) -> Result<(), Error> {
+        let mut builder = SslContext::builder(SslMethod::tls())?;
+        self.apply(&mut builder)
+    }
+
+    fn apply(&self, builder: &mut SslContextBuilder) -> Result<(), Error> {
+        let mut min_version = self.min_version;
+        let mut max_version = None;
+
+        if !self.cipher_suites.is_empty() {
+            let (tls13, earlier): (Vec<&str>, Vec<&str>) = self
+                .cipher_suites
+                .iter()
+                .map(String::as_str)
+                .partition(|suite| suite.starts_with(TLS13_CIPHER_SUITE_PREFIX));
+
+            // OpenSSL accepts a list as long as it knows one of its suites,
+            // so each is tried on its own first.
+            for suite in &earlier {
+                builder
+                    .set_cipher_list(suite)
+                    .with_context(|_| ErrorKind::InvalidCipherSuites(suite.to_string()))?;
+            }
+            for suite in &tls13 {
+                builder
+                    .set_ciphersuites(suite)
+                    .with_context(|_| ErrorKind::InvalidCipherSuites(suite.to_string()))?;
+            }
+
+            if earlier.is_empty() {
+                min_version = Some(TlsVersion::Tls13);
+            } else {
+                builder.set_cipher_list(&earlier.join(":"))?;
+            }
+            if tls13.is_empty() {
+                if self.min_version == Some(TlsVersion::Tls13) {
+                    return Err(Error::from(ErrorKind::InvalidCipherSuites(format!(
+                        "{}: none of them can be used with TLS 1.3",
+                        self.cipher_suites.join(", ")
+                    ))));
+                }
+                max_version = Some(TlsVersion::Tls12);
+            } else {
+                builder.set_ciphersuites(&tls13.join(":"))?;
+            }
+        }
+
+        builder.set_min_proto_version(min_version.map(TlsVersion::ssl_version))?;
+        builder.set_max_proto_version(max_version.map(TlsVersion::ssl_version))?;
+        Ok(())
+    }
+
+    pub(crate) fn apply_to_connector(&self, builder: &mut TlsConnectorBuilder) {
+        builder.min_protocol_version(self.min_version.map(TlsVersion::protocol));
+    }
+}
+
+/// Describes a failed handshake with `peer`, along with the lowest TLS
+/// version that was allowed, since a peer that only offers older versions is
+/// a common reason for one to fail.
+pub(crate) fn handshake_failed<E: fmt::Display>(
+    peer: &str,
+    min_version: Option<TlsVersion>,
+    err: E,
+) -> String {
+    match min_version {
+        Some(min_version) => format!(
+            "TLS handshake with {} failed, allowing TLS {} or later: {}",
+            peer, min_version, err
+        ),
+        None => format!("TLS handshake with {} failed: {}", peer, err),
+    }
+}
+
+/// The identity a listener presents to its clients, and the CA their
+/// certificates must be issued by, if any.
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    certs: Vec<X509>,
+    key: PKey<Private>,
+    client_ca: Vec<X509>,
+    policy: TlsPolicy,
+}
+
+impl ServerTlsConfig {
+    /// Reads the certificate chain, starting with the listener's own
+    /// certificate, and its private key from PEM files.
+    pub fn from_files(cert: &Path, key: &Path) -> Result<Self, Error> {
+        let certs = load_certs(cert)?;
+        let key_pem = fs::read(key)
+            .with_context(|_| ErrorKind::InvalidTlsKey(key.display().to_string()))?;
+        let pkey = PKey::private_key_from_pem(&key_pem)
+            .with_context(|_| ErrorKind::InvalidTlsKey(key.display().to_string()))?;
+        if !matches(&certs[0], &pkey) {
+            return Err(Error::from(ErrorKind::MismatchedTlsIdentity(
+                cert.display().to_string(),
+                key.display().to_string(),
+            )));
+        }
+        Ok(ServerTlsConfig {
+            certs,
+            key: pkey,
+            client_ca: vec![],
+            policy: TlsPolicy::default(),
+        })
+    }
+
+    /// Takes the certificate chain and its private key as PEM.
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let certs = X509::stack_from_pem(cert).context(ErrorKind::InvalidServerIdentity)?;
+        let pkey = PKey::private_key_from_pem(key).context(ErrorKind::InvalidServerIdentity)?;
+        match certs.first() {
+            Some(cert) if matches(cert, &pkey) => (),
+            _ => return Err(Error::from(ErrorKind::InvalidServerIdentity)),
+        }
+        Ok(ServerTlsConfig {
+            certs,
+            key: pkey,
+            client_ca: vec![],
+            policy: TlsPolicy::default(),
+        })
+    }
+
+    /// Requires clients to present a certificate issued by one of the CAs in
+    /// the PEM file at `path`.
+    pub fn with_client_ca(mut self, path: &Path) -> Result<Self, Error> {
+        self.client_ca = load_certs(path)?;
+        Ok(self)
+    }
+
+    pub fn requires_client_cert(&self) -> bool {
+        !self.client_ca.is_empty()
+    }
+
+    pub fn policy(&self) -> &TlsPolicy {
+        &self.policy
+    }
+
+    pub fn with_policy(mut self, policy: TlsPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        self.policy.apply(&mut builder)?;
+        builder.set_certificate(&self.certs[0])?;
+        for cert in &self.certs[1..] {
+            builder.add_extra_chain_cert(cert.clone())?;
+        }
+        builder.set_private_key(&self.key)?;
+        builder.check_private_key()?;
+
+        if self.requires_client_cert() {
+            for ca in &self.client_ca {
+                builder.cert_store_mut().add_cert(ca.clone())?;
+                builder.add_client_ca(ca)?;
+            }
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(TlsAcceptor {
+            acceptor: builder.build(),
+            min_version: self.policy.min_version(),
+        })
+    }
+}
+
+/// Performs the handshakes of an `https` listener.
+pub(crate) struct TlsAcceptor {
+    acceptor: SslAcceptor,
+    min_version: Option<TlsVersion>,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn accept(&self, stream: TcpStream) -> AcceptAsync<TcpStream> {
+        self.acceptor.accept_async(stream)
+    }
+
+    pub(crate) fn min_version(&self) -> Option<TlsVersion> {
+        self.min_version
+    }
+}
+
+fn matches(cert: &X509, key: &PKey<Private>) -> bool {
+    cert.public_key()
+        .map(|public_key| public_key.public_eq(key))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(min_version: Option<TlsVersion>, cipher_suites: &[&str]) -> TlsPolicy {
+        let policy = TlsPolicy::new().with_cipher_suites(
+            cipher_suites.iter().map(ToString::to_string).collect(),
+        );
+        match min_version {
+            Some(min_version) => policy.with_min_version(min_version),
+            None => policy,
+        }
+    }
+
+    #[test]
+    fn versions_are_parsed_and_shown() {
+        for version in &["1.0", "1.1", "1.2", "1.3"] {
+            assert_eq!(*version, version.parse::<TlsVersion>().unwrap().to_string());
+        }
+        assert!(TlsVersion::Tls12 < TlsVersion::Tls13);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for version in &["", "1", "1.4", "tls1.2", "SSLv3"] {
+            let err = version.parse::<TlsVersion>().unwrap_err();
+            assert_eq!(
+                &ErrorKind::InvalidTlsVersion(version.to_string()),
+                err.kind()
+            );
+        }
+    }
+
+    #[test]
+    fn known_cipher_suites_are_accepted() {
+        policy(None, &[]).check().unwrap();
+        policy(Some(TlsVersion::Tls12), &["ECDHE-RSA-AES256-GCM-SHA384"])
+            .check()
+            .unwrap();
+        policy(Some(TlsVersion::Tls13), &["TLS_AES_256_GCM_SHA384"])
+            .check()
+            .unwrap();
+    }
+
+    #[test]
+    fn unknown_cipher_suites_are_rejected() {
+        let err = policy(None, &["ECDHE-RSA-AES256-GCM-SHA384", "NOT-A-CIPHER"])
+            .check()
+            .unwrap_err();
+        assert_eq!(
+            &ErrorKind::InvalidCipherSuites("NOT-A-CIPHER".to_string()),
+            err.kind()
+        );
+    }
+
+    #[test]
+    fn tls13_requires_tls13_cipher_suites() {
+        let err = policy(Some(TlsVersion::Tls13), &["ECDHE-RSA-AES256-GCM-SHA384"])
+            .check()
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidCipherSuites(_) => (),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+}
@@ -2,18 +2,32 @@
 
 #![cfg(unix)]
 
-use std::fs;
-use std::os::unix::fs::MetadataExt;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
 use std::path::Path;
 
+use failure::{Fail, ResultExt};
+use nix::errno::Errno;
 use nix::sys::stat::{umask, Mode};
+use nix::unistd::{chown, Gid, Uid};
+use nix::Error as NixError;
 use tokio_uds::UnixListener;
 
-use error::Error;
+use error::{Error, ErrorKind};
+use permissions::SocketPermissions;
 use util::incoming::Incoming;
 
 pub fn listener<P: AsRef<Path>>(path: P) -> Result<Incoming, Error> {
     let listener = if path.as_ref().exists() {
+        // A socket left behind by a process that is gone refuses connections,
+        // but one that is still served must not be taken over.
+        if UnixStream::connect(&path).is_ok() {
+            return Err(Error::from(ErrorKind::SocketInUse(
+                path.as_ref().display().to_string(),
+            )));
+        }
+
         // get the previous file's metadata
         let metadata = fs::metadata(&path)?;
         debug!(
@@ -22,7 +36,7 @@ pub fn listener<P: AsRef<Path>>(path: P) -> Result<Incoming, Error> {
             path.as_ref().display()
         );
 
-        debug!("unlinking {}...", path.as_ref().display());
+        debug!("unlinking stale socket {}...", path.as_ref().display());
         fs::remove_file(&path)?;
         debug!("unlinked {}", path.as_ref().display());
 
@@ -51,15 +65,53 @@ pub fn listener<P: AsRef<Path>>(path: P) -> Result<Incoming, Error> {
     Ok(listener)
 }
 
+pub fn set_permissions<P: AsRef<Path>>(
+    path: P,
+    permissions: &SocketPermissions,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+
+    if let Some(mode) = permissions.mode() {
+        debug!("setting mode {:#o} for {}...", mode, path.display());
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .context(ErrorKind::SocketMode(path.display().to_string()))?;
+    }
+
+    if permissions.owner().is_some() || permissions.group().is_some() {
+        debug!(
+            "setting owner {:?} and group {:?} for {}...",
+            permissions.owner(),
+            permissions.group(),
+            path.display()
+        );
+        chown(
+            path,
+            permissions.owner().map(Uid::from_raw),
+            permissions.group().map(Gid::from_raw),
+        ).map_err(|err| {
+            let kind = if err == NixError::Sys(Errno::EPERM) {
+                ErrorKind::SocketOwnerNotPermitted(path.display().to_string())
+            } else {
+                ErrorKind::SocketOwner(path.display().to_string())
+            };
+            Error::from(err.context(kind))
+        })?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use std::fs::OpenOptions;
     use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::net::UnixListener as StdUnixListener;
 
     use futures::Stream;
     use nix::sys::stat::stat;
+    use nix::unistd::getgid;
     use tempfile::tempdir;
 
     #[test]
@@ -85,4 +137,69 @@ mod tests {
 
         dir.close().unwrap();
     }
+
+    #[test]
+    fn stale_socket_is_replaced() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("stale.sock");
+        drop(StdUnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let _listener = listener(&path).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn live_socket_is_not_replaced() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("live.sock");
+        let _live = StdUnixListener::bind(&path).unwrap();
+
+        let err = listener(&path).err().unwrap();
+        assert_eq!(
+            &ErrorKind::SocketInUse(path.display().to_string()),
+            err.kind()
+        );
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn socket_permissions_are_applied() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("workload.sock");
+        let _listener = listener(&path).unwrap();
+
+        let gid = getgid().into();
+        let permissions = SocketPermissions::new().with_mode(0o660).with_group(gid);
+        set_permissions(&path, &permissions).unwrap();
+
+        let file_stat = stat(&path).unwrap();
+        assert_eq!(0o660, file_stat.st_mode & 0o777);
+        assert_eq!(gid, file_stat.st_gid);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn chown_without_privileges_fails() {
+        if Uid::current().is_root() {
+            return;
+        }
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("mgmt.sock");
+        let _listener = listener(&path).unwrap();
+
+        let permissions = SocketPermissions::new().with_owner(0);
+        let err = set_permissions(&path, &permissions).unwrap_err();
+        assert_eq!(
+            &ErrorKind::SocketOwnerNotPermitted(path.display().to_string()),
+            err.kind()
+        );
+
+        dir.close().unwrap();
+    }
 }
@@ -12,22 +12,39 @@
 //! an enumeration that switches between a `TcpStream` or a `UnixStream` (or
 //! other kinds of streams in the future when we support more protocols) for
 //! HTTP and Unix sockets respectively.
+//!
+//! `tcp` URLs, as used by the Docker daemon, are served over TLS when the
+//! `TlsConfig` passed to `UrlConnector::with_tls_config` carries TLS
+//! material, and over plain HTTP otherwise.
+//!
+//! Connections to network endpoints are given up on after a connect timeout,
+//! which can be changed with `UrlConnector::with_connect_timeout`. Unix
+//! sockets and named pipes are local and aren't subject to it.
 
+use std::fs;
 use std::io;
-#[cfg(unix)]
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use failure::ResultExt;
 use futures::{future, Future};
 use hyper::client::connect::{Connect, Connected, Destination};
 use hyper::client::HttpConnector;
 use hyper::Uri;
 #[cfg(windows)]
 use hyper_named_pipe::{PipeConnector, Uri as PipeUri};
+use hyper_tls::HttpsConnector;
 #[cfg(unix)]
 use hyperlocal::{UnixConnector, Uri as HyperlocalUri};
-use url::{ParseError, Url};
+use native_tls::{Certificate, Identity, TlsConnector};
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+use url::{ParseError, Position, Url};
 
 use error::{Error, ErrorKind};
+use tls::TlsPolicy;
+use util::timeout::TimeoutConnector;
 use util::StreamSelector;
 
 #[cfg(unix)]
@@ -35,9 +52,158 @@ const UNIX_SCHEME: &str = "unix";
 #[cfg(windows)]
 const PIPE_SCHEME: &str = "npipe";
 const HTTP_SCHEME: &str = "http";
+const HTTPS_SCHEME: &str = "https";
+const TCP_SCHEME: &str = "tcp";
+
+// NOTE: We are defaulting to using 4 threads here. Is this a good
+//       default? This is what the "hyper" crate uses by default at
+//       this time.
+const DNS_WORKER_THREADS: usize = 4;
+
+/// TLS material used to connect to `https` and `tcp` endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    require_tls: bool,
+    policy: TlsPolicy,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        TlsConfig::default()
+    }
+
+    pub fn ca_cert(&self) -> Option<&PathBuf> {
+        self.ca_cert.as_ref()
+    }
+
+    pub fn with_ca_cert(mut self, ca_cert: PathBuf) -> Self {
+        self.ca_cert = Some(ca_cert);
+        self
+    }
+
+    pub fn client_cert(&self) -> Option<&PathBuf> {
+        self.client_cert.as_ref()
+    }
+
+    pub fn with_client_cert(mut self, client_cert: PathBuf) -> Self {
+        self.client_cert = Some(client_cert);
+        self
+    }
+
+    pub fn client_key(&self) -> Option<&PathBuf> {
+        self.client_key.as_ref()
+    }
+
+    pub fn with_client_key(mut self, client_key: PathBuf) -> Self {
+        self.client_key = Some(client_key);
+        self
+    }
+
+    /// When set, URLs that would otherwise be served in plain text are
+    /// rejected.
+    pub fn require_tls(&self) -> bool {
+        self.require_tls
+    }
+
+    pub fn with_require_tls(mut self, require_tls: bool) -> Self {
+        self.require_tls = require_tls;
+        self
+    }
+
+    pub fn policy(&self) -> &TlsPolicy {
+        &self.policy
+    }
+
+    /// Sets the lowest TLS version to connect with. The cipher suites of the
+    /// policy are left to the platform.
+    pub fn with_policy(mut self, policy: TlsPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn has_material(&self) -> bool {
+        self.ca_cert.is_some() || self.client_cert.is_some() || self.client_key.is_some()
+    }
+
+    /// Loads the TLS material, so that problems with it are found before a
+    /// connection is made.
+    pub fn check(&self) -> Result<(), Error> {
+        self.connector().map(|_| ())
+    }
+
+    pub(crate) fn connector(&self) -> Result<TlsConnector, Error> {
+        let mut builder = TlsConnector::builder();
+        self.policy.apply_to_connector(&mut builder);
+
+        if let Some(ref path) = self.ca_cert {
+            for cert in load_certs(path)? {
+                let cert = cert
+                    .to_der()
+                    .map_err(Error::from)
+                    .and_then(|der| Ok(Certificate::from_der(&der)?))
+                    .with_context(|_| invalid_cert(path))?;
+                builder.add_root_certificate(cert);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let cert = load_certs(cert_path)?.remove(0);
+                let key = fs::read(key_path)
+                    .map_err(Error::from)
+                    .and_then(|pem| Ok(PKey::private_key_from_pem(&pem)?))
+                    .with_context(|_| {
+                        ErrorKind::InvalidTlsKey(key_path.display().to_string())
+                    })?;
+                let matches = cert
+                    .public_key()
+                    .map(|public_key| public_key.public_eq(&key))
+                    .unwrap_or(false);
+                if !matches {
+                    return Err(Error::from(ErrorKind::MismatchedTlsIdentity(
+                        cert_path.display().to_string(),
+                        key_path.display().to_string(),
+                    )));
+                }
+                let identity = Pkcs12::builder()
+                    .build("", "", &key, &cert)
+                    .and_then(|pkcs12| pkcs12.to_der())
+                    .map_err(Error::from)
+                    .and_then(|der| Ok(Identity::from_pkcs12(&der, "")?))?;
+                builder.identity(identity);
+            }
+            (None, None) => (),
+            _ => return Err(Error::from(ErrorKind::IncompleteTlsIdentity)),
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+fn invalid_cert(path: &Path) -> ErrorKind {
+    ErrorKind::InvalidTlsCertificate(path.display().to_string())
+}
+
+/// Reads the certificates in the PEM file at `path`, of which there has to be
+/// at least one.
+pub(crate) fn load_certs(path: &Path) -> Result<Vec<X509>, Error> {
+    let certs = fs::read(path)
+        .map_err(Error::from)
+        .and_then(|pem| Ok(X509::stack_from_pem(&pem)?))
+        .with_context(|_| invalid_cert(path))?;
+    if certs.is_empty() {
+        Err(Error::from(invalid_cert(path)))
+    } else {
+        Ok(certs)
+    }
+}
 
 pub enum UrlConnector {
-    Http(HttpConnector),
+    Http(TimeoutConnector<HttpConnector>),
+    Https(TimeoutConnector<HttpsConnector<HttpConnector>>),
     #[cfg(windows)]
     Pipe(PipeConnector),
     #[cfg(unix)]
@@ -46,9 +212,19 @@ pub enum UrlConnector {
 
 impl UrlConnector {
     pub fn new(url: &Url) -> Result<Self, Error> {
+        UrlConnector::with_tls_config(url, &TlsConfig::default())
+    }
+
+    pub fn with_tls_config(url: &Url, tls: &TlsConfig) -> Result<Self, Error> {
         match url.scheme() {
             #[cfg(windows)]
-            PIPE_SCHEME => Ok(UrlConnector::Pipe(PipeConnector)),
+            PIPE_SCHEME => {
+                if PipeUri::new(url.as_str(), "/").is_ok() {
+                    Ok(UrlConnector::Pipe(PipeConnector))
+                } else {
+                    Err(ErrorKind::InvalidUri(url.to_string()))?
+                }
+            }
 
             #[cfg(unix)]
             UNIX_SCHEME => {
@@ -59,25 +235,67 @@ impl UrlConnector {
                 }
             }
 
-            HTTP_SCHEME => {
-                // NOTE: We are defaulting to using 4 threads here. Is this a good
-                //       default? This is what the "hyper" crate uses by default at
-                //       this time.
-                Ok(UrlConnector::Http(HttpConnector::new(4)))
+            HTTPS_SCHEME => UrlConnector::https(tls),
+            TCP_SCHEME if tls.has_material() => UrlConnector::https(tls),
+
+            HTTP_SCHEME | TCP_SCHEME => {
+                if tls.require_tls() {
+                    Err(ErrorKind::TlsRequired(url.to_string()))?
+                } else {
+                    let http = HttpConnector::new(DNS_WORKER_THREADS);
+                    Ok(UrlConnector::Http(TimeoutConnector::new(http)))
+                }
             }
             _ => Err(ErrorKind::InvalidUri(url.to_string()))?,
         }
     }
 
+    fn https(tls: &TlsConfig) -> Result<Self, Error> {
+        let mut http = HttpConnector::new(DNS_WORKER_THREADS);
+        http.enforce_http(false);
+        let mut https = HttpsConnector::from((http, tls.connector()?));
+        https.https_only(true);
+        Ok(UrlConnector::Https(TimeoutConnector::new(https)))
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        match self {
+            UrlConnector::Http(ref mut connector) => connector.set_timeout(timeout),
+            UrlConnector::Https(ref mut connector) => connector.set_timeout(timeout),
+            #[cfg(windows)]
+            UrlConnector::Pipe(_) => (),
+            #[cfg(unix)]
+            UrlConnector::Unix(_) => (),
+        }
+        self
+    }
+
+    /// The scheme to pass to `build_hyper_uri` for requests made through
+    /// this connector.
+    pub fn scheme(&self) -> &'static str {
+        match *self {
+            UrlConnector::Http(_) => HTTP_SCHEME,
+            UrlConnector::Https(_) => HTTPS_SCHEME,
+            #[cfg(windows)]
+            UrlConnector::Pipe(_) => PIPE_SCHEME,
+            #[cfg(unix)]
+            UrlConnector::Unix(_) => UNIX_SCHEME,
+        }
+    }
+
     pub fn build_hyper_uri(scheme: &str, base_path: &str, path: &str) -> Result<Uri, Error> {
         match scheme {
             #[cfg(windows)]
             PIPE_SCHEME => Ok(PipeUri::new(base_path, path)?.into()),
             #[cfg(unix)]
             UNIX_SCHEME => Ok(HyperlocalUri::new(base_path, path).into()),
-            HTTP_SCHEME => Ok(Url::parse(base_path)
+            // The base path may be a `tcp` URL, so the scheme is swapped for
+            // the one the connector actually speaks.
+            HTTP_SCHEME | HTTPS_SCHEME => Ok(Url::parse(base_path)
                 .and_then(|base| base.join(path))
-                .and_then(|url| url.as_str().parse().map_err(|_| ParseError::IdnaError))?),
+                .and_then(|url| {
+                    Url::parse(&format!("{}://{}", scheme, &url[Position::BeforeUsername..]))
+                }).and_then(|url| url.as_str().parse().map_err(|_| ParseError::IdnaError))?),
             _ => Err(ErrorKind::UrlParse)?,
         }
     }
@@ -92,6 +310,7 @@ impl Connect for UrlConnector {
         #[cfg_attr(feature = "cargo-clippy", allow(match_same_arms))]
         match (self, dst.scheme()) {
             (UrlConnector::Http(_), HTTP_SCHEME) => (),
+            (UrlConnector::Https(_), HTTPS_SCHEME) => (),
 
             #[cfg(windows)]
             (UrlConnector::Pipe(_), PIPE_SCHEME) => (),
@@ -114,6 +333,12 @@ impl Connect for UrlConnector {
                 })) as Self::Future
             }
 
+            UrlConnector::Https(connector) => {
+                Box::new(connector.connect(dst).and_then(|(tls_stream, connected)| {
+                    Ok((StreamSelector::Tls(tls_stream), connected))
+                })) as Self::Future
+            }
+
             #[cfg(windows)]
             UrlConnector::Pipe(connector) => {
                 Box::new(connector.connect(dst).and_then(|(pipe_stream, connected)| {
@@ -133,12 +358,59 @@ impl Connect for UrlConnector {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(unix)]
+    use std::io::Write;
+
+    #[cfg(unix)]
+    use openssl::asn1::Asn1Time;
+    #[cfg(unix)]
+    use openssl::hash::MessageDigest;
+    #[cfg(unix)]
+    use openssl::rsa::Rsa;
+    #[cfg(unix)]
+    use openssl::x509::{X509Builder, X509NameBuilder};
     #[cfg(unix)]
     use tempfile::NamedTempFile;
     use url::Url;
 
+    use util::timeout::DEFAULT_CONNECT_TIMEOUT_SECS;
+
     use super::*;
 
+    #[cfg(unix)]
+    fn write_temp(contents: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents).unwrap();
+        file
+    }
+
+    #[cfg(unix)]
+    fn self_signed() -> (NamedTempFile, NamedTempFile) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = builder.build();
+
+        (
+            write_temp(&cert.to_pem().unwrap()),
+            write_temp(&key.private_key_to_pem_pkcs8().unwrap()),
+        )
+    }
+
     #[test]
     #[should_panic(expected = "Invalid uri")]
     fn invalid_url_scheme() {
@@ -168,9 +440,207 @@ mod tests {
         let _connector = UrlConnector::new(&Url::parse("http://localhost:2375").unwrap()).unwrap();
     }
 
+    #[test]
+    fn connect_timeout_is_applied() {
+        let url = Url::parse("tcp://localhost:2375").unwrap();
+        let connector = UrlConnector::new(&url).unwrap();
+        match connector {
+            UrlConnector::Http(ref connector) => assert_eq!(
+                Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+                connector.timeout()
+            ),
+            _ => panic!("expected an http connector"),
+        }
+
+        let connector = connector.with_connect_timeout(Duration::from_secs(5));
+        match connector {
+            UrlConnector::Http(ref connector) => {
+                assert_eq!(Duration::from_secs(5), connector.timeout())
+            }
+            _ => panic!("expected an http connector"),
+        }
+    }
+
     #[cfg(windows)]
     #[test]
     fn create_pipe_succeeds() {
         let _connector = UrlConnector::new(&Url::parse("npipe://./pipe/boo").unwrap()).unwrap();
     }
+
+    #[cfg(windows)]
+    #[test]
+    fn create_docker_pipe_succeeds() {
+        let connector =
+            UrlConnector::new(&Url::parse("npipe:////./pipe/docker_engine").unwrap()).unwrap();
+        assert_eq!(PIPE_SCHEME, connector.scheme());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    #[should_panic(expected = "Invalid uri")]
+    fn invalid_pipe_url() {
+        let _connector = UrlConnector::new(&Url::parse("npipe://./boo").unwrap()).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    #[should_panic(expected = "Invalid uri")]
+    fn pipe_url_is_rejected() {
+        let _connector =
+            UrlConnector::new(&Url::parse("npipe:////./pipe/docker_engine").unwrap()).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn build_pipe_uri_from_docker_url() {
+        let uri = UrlConnector::build_hyper_uri(
+            PIPE_SCHEME,
+            "npipe:////./pipe/docker_engine",
+            "/containers/json?all=true",
+        ).unwrap();
+        assert_eq!(
+            "npipe://5c5c2e5c706970655c646f636b65725f656e67696e65/containers/json?all=true",
+            uri.to_string()
+        );
+    }
+
+    #[test]
+    fn create_https_succeeds() {
+        let connector =
+            UrlConnector::new(&Url::parse("https://localhost:2376").unwrap()).unwrap();
+        assert_eq!(HTTPS_SCHEME, connector.scheme());
+    }
+
+    #[test]
+    fn create_tcp_without_tls_uses_http() {
+        let connector = UrlConnector::new(&Url::parse("tcp://localhost:2375").unwrap()).unwrap();
+        assert_eq!(HTTP_SCHEME, connector.scheme());
+    }
+
+    #[test]
+    fn tcp_without_tls_material_is_rejected_when_tls_is_required() {
+        let tls = TlsConfig::new().with_require_tls(true);
+        let url = Url::parse("tcp://localhost:2376").unwrap();
+        let err = UrlConnector::with_tls_config(&url, &tls).err().unwrap();
+        assert_eq!(&ErrorKind::TlsRequired(url.to_string()), err.kind());
+    }
+
+    #[test]
+    fn http_is_rejected_when_tls_is_required() {
+        let tls = TlsConfig::new().with_require_tls(true);
+        let url = Url::parse("http://localhost:2375").unwrap();
+        let err = UrlConnector::with_tls_config(&url, &tls).err().unwrap();
+        assert_eq!(&ErrorKind::TlsRequired(url.to_string()), err.kind());
+    }
+
+    #[test]
+    fn missing_ca_cert_is_rejected() {
+        let tls = TlsConfig::new().with_ca_cert(PathBuf::from("/this/file/does/not/exist"));
+        let err = UrlConnector::with_tls_config(&Url::parse("tcp://localhost:2376").unwrap(), &tls)
+            .err()
+            .unwrap();
+        assert_eq!(
+            &ErrorKind::InvalidTlsCertificate("/this/file/does/not/exist".to_string()),
+            err.kind()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_ca_cert_is_rejected() {
+        let ca = write_temp(b"not a certificate");
+        let tls = TlsConfig::new().with_ca_cert(ca.path().to_path_buf());
+        let err = UrlConnector::with_tls_config(&Url::parse("tcp://localhost:2376").unwrap(), &tls)
+            .err()
+            .unwrap();
+        assert_eq!(
+            &ErrorKind::InvalidTlsCertificate(ca.path().display().to_string()),
+            err.kind()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn invalid_client_key_is_rejected() {
+        let (cert, _) = self_signed();
+        let key = write_temp(b"not a key");
+        let tls = TlsConfig::new()
+            .with_client_cert(cert.path().to_path_buf())
+            .with_client_key(key.path().to_path_buf());
+        let err = UrlConnector::with_tls_config(&Url::parse("tcp://localhost:2376").unwrap(), &tls)
+            .err()
+            .unwrap();
+        assert_eq!(
+            &ErrorKind::InvalidTlsKey(key.path().display().to_string()),
+            err.kind()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mismatched_client_key_is_rejected() {
+        let (cert, _) = self_signed();
+        let (_, key) = self_signed();
+        let tls = TlsConfig::new()
+            .with_client_cert(cert.path().to_path_buf())
+            .with_client_key(key.path().to_path_buf());
+        let err = tls.check().unwrap_err();
+        assert_eq!(
+            &ErrorKind::MismatchedTlsIdentity(
+                cert.path().display().to_string(),
+                key.path().display().to_string(),
+            ),
+            err.kind()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn client_cert_without_key_is_rejected() {
+        let (cert, _) = self_signed();
+        let tls = TlsConfig::new().with_client_cert(cert.path().to_path_buf());
+        let err = UrlConnector::with_tls_config(&Url::parse("tcp://localhost:2376").unwrap(), &tls)
+            .err()
+            .unwrap();
+        assert_eq!(&ErrorKind::IncompleteTlsIdentity, err.kind());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_tcp_with_tls_material_succeeds() {
+        let (cert, key) = self_signed();
+        let tls = TlsConfig::new()
+            .with_ca_cert(cert.path().to_path_buf())
+            .with_client_cert(cert.path().to_path_buf())
+            .with_client_key(key.path().to_path_buf())
+            .with_require_tls(true);
+        let connector =
+            UrlConnector::with_tls_config(&Url::parse("tcp://localhost:2376").unwrap(), &tls)
+                .unwrap();
+        assert_eq!(HTTPS_SCHEME, connector.scheme());
+    }
+
+    #[test]
+    fn build_https_uri_from_tcp_base_path() {
+        let uri = UrlConnector::build_hyper_uri(
+            HTTPS_SCHEME,
+            "tcp://localhost:2376/",
+            "/containers/json?all=true",
+        ).unwrap();
+        assert_eq!("https://localhost:2376/containers/json?all=true", uri.to_string());
+    }
+
+    #[test]
+    fn build_http_uri_from_tcp_base_path() {
+        let uri =
+            UrlConnector::build_hyper_uri(HTTP_SCHEME, "tcp://localhost:2375/", "/version").unwrap();
+        assert_eq!("http://localhost:2375/version", uri.to_string());
+    }
+
+    #[test]
+    fn build_https_uri_from_https_base_path() {
+        let uri = UrlConnector::build_hyper_uri(HTTPS_SCHEME, "https://docker:2376/", "/version")
+            .unwrap();
+        assert_eq!("https://docker:2376/version", uri.to_string());
+    }
 }
@@ -0,0 +1,61 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! TLS handshake errors
+//!
+//! The platform's TLS library reports a failed handshake without saying who
+//! it was with, or which versions of TLS were allowed. `HandshakeConnector`
+//! adds both to the handshake errors of the connector it wraps. It goes
+//! around the proxy connector, so that handshakes with destinations reached
+//! through a tunnel are described as well.
+
+use std::io;
+
+use futures::Future;
+use hyper::client::connect::{Connect, Connected, Destination};
+use native_tls::Error as TlsError;
+
+use tls::{handshake_failed, TlsVersion};
+
+#[derive(Clone, Debug)]
+pub struct HandshakeConnector<C> {
+    inner: C,
+    min_version: Option<TlsVersion>,
+}
+
+impl<C> HandshakeConnector<C> {
+    pub fn new(inner: C, min_version: Option<TlsVersion>) -> Self {
+        HandshakeConnector { inner, min_version }
+    }
+}
+
+impl<C> Connect for HandshakeConnector<C>
+where
+    C: Connect<Error = io::Error>,
+    C::Future: 'static,
+{
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = Box<Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let port = dst
+            .port()
+            .unwrap_or_else(|| if dst.scheme() == "https" { 443 } else { 80 });
+        let peer = format!("{}:{}", dst.host(), port);
+        let min_version = self.min_version;
+        let connecting = self.inner.connect(dst).map_err(move |err| {
+            if is_handshake_error(&err) {
+                io::Error::new(err.kind(), handshake_failed(&peer, min_version, err))
+            } else {
+                err
+            }
+        });
+        Box::new(connecting)
+    }
+}
+
+/// Whether a connector error is a TLS error, which connectors report wrapped
+/// in an `io::Error`.
+fn is_handshake_error(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |inner| inner.is::<TlsError>())
+}
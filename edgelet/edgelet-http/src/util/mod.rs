@@ -9,26 +9,35 @@ use std::os::unix::net::SocketAddr as UnixSocketAddr;
 use bytes::{Buf, BufMut};
 use edgelet_core::pid::Pid;
 use futures::Poll;
+use hyper_tls::MaybeHttpsStream;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 #[cfg(windows)]
 use tokio_named_pipe::PipeStream;
+use tokio_openssl::SslStream;
 #[cfg(unix)]
 use tokio_uds::UnixStream;
 
+use pid::Uid;
 #[cfg(unix)]
 use pid::UnixStreamExt;
 
 pub mod connector;
+mod handshake;
 mod hyperwrap;
 pub mod incoming;
+pub mod no_proxy;
 pub mod proxy;
+pub mod timeout;
 
-pub use self::connector::UrlConnector;
+pub use self::connector::{TlsConfig, UrlConnector};
 pub use self::incoming::Incoming;
 
 pub enum StreamSelector {
     Tcp(TcpStream),
+    Tls(MaybeHttpsStream<TcpStream>),
+    /// A connection accepted by an `https` listener.
+    TlsServer(SslStream<TcpStream>),
     #[cfg(windows)]
     Pipe(PipeStream),
     #[cfg(unix)]
@@ -40,18 +49,35 @@ impl StreamSelector {
     pub fn pid(&self) -> io::Result<Pid> {
         match *self {
             StreamSelector::Tcp(_) => Ok(Pid::Any),
+            StreamSelector::Tls(_) => Ok(Pid::Any),
+            StreamSelector::TlsServer(_) => Ok(Pid::Any),
             #[cfg(windows)]
             StreamSelector::Pipe(_) => Ok(Pid::Any),
             #[cfg(unix)]
             StreamSelector::Unix(ref stream) => stream.pid(),
         }
     }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(match_same_arms))]
+    pub fn uid(&self) -> io::Result<Option<Uid>> {
+        match *self {
+            StreamSelector::Tcp(_) => Ok(None),
+            StreamSelector::Tls(_) => Ok(None),
+            StreamSelector::TlsServer(_) => Ok(None),
+            #[cfg(windows)]
+            StreamSelector::Pipe(_) => Ok(None),
+            #[cfg(unix)]
+            StreamSelector::Unix(ref stream) => stream.uid().map(Some),
+        }
+    }
 }
 
 impl Read for StreamSelector {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => stream.read(buf),
+            StreamSelector::Tls(ref mut stream) => stream.read(buf),
+            StreamSelector::TlsServer(ref mut stream) => stream.read(buf),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => stream.read(buf),
             #[cfg(unix)]
@@ -64,6 +90,8 @@ impl Write for StreamSelector {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => stream.write(buf),
+            StreamSelector::Tls(ref mut stream) => stream.write(buf),
+            StreamSelector::TlsServer(ref mut stream) => stream.write(buf),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => stream.write(buf),
             #[cfg(unix)]
@@ -74,6 +102,8 @@ impl Write for StreamSelector {
     fn flush(&mut self) -> io::Result<()> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => stream.flush(),
+            StreamSelector::Tls(ref mut stream) => stream.flush(),
+            StreamSelector::TlsServer(ref mut stream) => stream.flush(),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => stream.flush(),
             #[cfg(unix)]
@@ -87,6 +117,8 @@ impl AsyncRead for StreamSelector {
     unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
         match *self {
             StreamSelector::Tcp(ref stream) => stream.prepare_uninitialized_buffer(buf),
+            StreamSelector::Tls(ref stream) => stream.prepare_uninitialized_buffer(buf),
+            StreamSelector::TlsServer(ref stream) => stream.prepare_uninitialized_buffer(buf),
             #[cfg(windows)]
             StreamSelector::Pipe(ref stream) => stream.prepare_uninitialized_buffer(buf),
             #[cfg(unix)]
@@ -98,6 +130,8 @@ impl AsyncRead for StreamSelector {
     fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => stream.read_buf(buf),
+            StreamSelector::Tls(ref mut stream) => stream.read_buf(buf),
+            StreamSelector::TlsServer(ref mut stream) => stream.read_buf(buf),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => stream.read_buf(buf),
             #[cfg(unix)]
@@ -110,6 +144,8 @@ impl AsyncWrite for StreamSelector {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => <&TcpStream>::shutdown(&mut &*stream),
+            StreamSelector::Tls(ref mut stream) => AsyncWrite::shutdown(stream),
+            StreamSelector::TlsServer(ref mut stream) => AsyncWrite::shutdown(stream),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => PipeStream::shutdown(stream),
             #[cfg(unix)]
@@ -121,6 +157,8 @@ impl AsyncWrite for StreamSelector {
     fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
         match *self {
             StreamSelector::Tcp(ref mut stream) => stream.write_buf(buf),
+            StreamSelector::Tls(ref mut stream) => stream.write_buf(buf),
+            StreamSelector::TlsServer(ref mut stream) => stream.write_buf(buf),
             #[cfg(windows)]
             StreamSelector::Pipe(ref mut stream) => stream.write_buf(buf),
             #[cfg(unix)]
@@ -171,4 +209,10 @@ mod tests {
             Pid::Value(_) => (),
         }
     }
+
+    #[test]
+    fn test_uid() {
+        let (a, b) = UnixStream::pair().unwrap();
+        assert_eq!(a.uid().unwrap(), b.uid().unwrap());
+    }
 }
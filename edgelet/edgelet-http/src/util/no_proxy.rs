@@ -0,0 +1,302 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Which destinations are reached without the proxy, as listed by the
+//! `no_proxy` environment variable most HTTP clients honor.
+//!
+//! The list is separated by commas. Each entry is one of
+//!
+//! - `*`, which matches every destination,
+//! - an IP address or a network in CIDR notation, like `10.0.0.0/8`,
+//! - a domain, which matches itself and its subdomains,
+//! - a domain starting with `.` or `*.`, which only matches its subdomains.
+//!
+//! Addresses and domains may end in `:port` to only match that port. IPv6
+//! addresses then have to be in brackets, like `[::1]:8080`.
+
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use error::{Error, ErrorKind};
+
+const NO_PROXY_KEYS: &[&str] = &["NO_PROXY", "no_proxy"];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NoProxy {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Rule {
+    All,
+    Network {
+        addr: IpAddr,
+        prefix: u8,
+        port: Option<u16>,
+    },
+    Domain {
+        name: String,
+        subdomains_only: bool,
+        port: Option<u16>,
+    },
+}
+
+impl NoProxy {
+    /// The list in `NO_PROXY` or `no_proxy`. No list sends everything
+    /// through the proxy.
+    pub fn from_env() -> Result<Self, Error> {
+        NO_PROXY_KEYS
+            .iter()
+            .filter_map(|key| env::var(key).ok())
+            .next()
+            .map_or_else(|| Ok(NoProxy::default()), |list| list.parse())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether requests to `host` on `port` bypass the proxy. `host` may be
+    /// an IPv6 address in brackets, as it appears in URIs.
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        let host = host
+            .trim_left_matches('[')
+            .trim_right_matches(']')
+            .trim_right_matches('.')
+            .to_lowercase();
+        let addr = host.parse::<IpAddr>().ok();
+
+        self.rules.iter().any(|rule| match *rule {
+            Rule::All => true,
+            Rule::Network {
+                addr: network,
+                prefix,
+                port: rule_port,
+            } => {
+                port_matches(rule_port, port)
+                    && addr.map_or(false, |addr| in_network(addr, network, prefix))
+            }
+            Rule::Domain {
+                ref name,
+                subdomains_only,
+                port: rule_port,
+            } => {
+                port_matches(rule_port, port)
+                    && addr.is_none()
+                    && ((!subdomains_only && host == *name)
+                        || (host.ends_with(name.as_str())
+                            && host[..host.len() - name.len()].ends_with('.')))
+            }
+        })
+    }
+}
+
+impl FromStr for NoProxy {
+    type Err = Error;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let rules = list
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .map(parse_rule)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(NoProxy { rules })
+    }
+}
+
+fn parse_rule(entry: &str) -> Result<Rule, Error> {
+    let invalid = || Error::from(ErrorKind::InvalidNoProxy(entry.to_string()));
+
+    if entry == "*" {
+        return Ok(Rule::All);
+    }
+
+    if let Some(slash) = entry.find('/') {
+        let addr = entry[..slash]
+            .trim_left_matches('[')
+            .trim_right_matches(']')
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?;
+        let prefix = entry[slash + 1..].parse::<u8>().map_err(|_| invalid())?;
+        if prefix > max_prefix(addr) {
+            return Err(invalid());
+        }
+        return Ok(Rule::Network {
+            addr,
+            prefix,
+            port: None,
+        });
+    }
+
+    let (host, port) = split_port(entry).ok_or_else(invalid)?;
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(Rule::Network {
+            addr,
+            prefix: max_prefix(addr),
+            port,
+        });
+    }
+
+    let (name, subdomains_only) = if host.starts_with("*.") {
+        (&host[2..], true)
+    } else if host.starts_with('.') {
+        (&host[1..], true)
+    } else {
+        (host, false)
+    };
+    let name = name.trim_right_matches('.').to_lowercase();
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_');
+    if !valid {
+        return Err(invalid());
+    }
+    Ok(Rule::Domain {
+        name,
+        subdomains_only,
+        port,
+    })
+}
+
+/// Splits the port off an entry. IPv6 addresses without brackets have no
+/// port, since it couldn't be told apart from the address.
+fn split_port(entry: &str) -> Option<(&str, Option<u16>)> {
+    if entry.starts_with('[') {
+        let end = entry.find(']')?;
+        let host = &entry[1..end];
+        match &entry[end + 1..] {
+            "" => Some((host, None)),
+            rest if rest.starts_with(':') => Some((host, Some(rest[1..].parse().ok()?))),
+            _ => None,
+        }
+    } else if entry.matches(':').count() == 1 {
+        let colon = entry.find(':')?;
+        Some((&entry[..colon], Some(entry[colon + 1..].parse().ok()?)))
+    } else {
+        Some((entry, None))
+    }
+}
+
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn port_matches(rule: Option<u16>, port: Option<u16>) -> bool {
+    rule.map_or(true, |rule| Some(rule) == port)
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            same_prefix(&addr.octets(), &network.octets(), prefix)
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            same_prefix(&addr.octets(), &network.octets(), prefix)
+        }
+        _ => false,
+    }
+}
+
+fn same_prefix(addr: &[u8], network: &[u8], prefix: u8) -> bool {
+    let prefix = usize::from(prefix);
+    let (bytes, bits) = (prefix / 8, prefix % 8);
+    addr[..bytes] == network[..bytes]
+        && (bits == 0 || (addr[bytes] ^ network[bytes]) & (0xff_u8 << (8 - bits)) == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_proxy(list: &str) -> NoProxy {
+        list.parse().unwrap()
+    }
+
+    #[test]
+    fn empty_list_matches_nothing() {
+        let list = no_proxy(" , ");
+        assert!(list.is_empty());
+        assert!(!list.matches("localhost", None));
+    }
+
+    #[test]
+    fn star_matches_everything() {
+        let list = no_proxy("*");
+        assert!(list.matches("example.com", Some(443)));
+        assert!(list.matches("10.1.2.3", None));
+    }
+
+    #[test]
+    fn domains_match_themselves_and_subdomains() {
+        let list = no_proxy("Example.com, internal.");
+        assert!(list.matches("example.com", Some(443)));
+        assert!(list.matches("registry.EXAMPLE.com.", Some(443)));
+        assert!(list.matches("a.b.internal", None));
+        assert!(!list.matches("notexample.com", Some(443)));
+        assert!(!list.matches("example.com.evil", Some(443)));
+    }
+
+    #[test]
+    fn suffixes_only_match_subdomains() {
+        let list = no_proxy(".azurecr.io,*.local");
+        assert!(list.matches("myregistry.azurecr.io", Some(443)));
+        assert!(!list.matches("azurecr.io", Some(443)));
+        assert!(list.matches("printer.local", None));
+        assert!(!list.matches("local", None));
+    }
+
+    #[test]
+    fn networks_match_their_addresses() {
+        let list = no_proxy("10.0.0.0/8,192.168.1.0/25,fd00::/8,127.0.0.1");
+        assert!(list.matches("10.255.0.1", Some(80)));
+        assert!(!list.matches("11.0.0.1", Some(80)));
+        assert!(list.matches("192.168.1.127", None));
+        assert!(!list.matches("192.168.1.128", None));
+        assert!(list.matches("[fd12:3456::1]", Some(443)));
+        assert!(!list.matches("fe80::1", Some(443)));
+        assert!(list.matches("127.0.0.1", Some(8080)));
+        assert!(!list.matches("127.0.0.2", Some(8080)));
+        // Domains are never resolved to be matched against networks.
+        assert!(!list.matches("localhost", Some(8080)));
+    }
+
+    #[test]
+    fn ports_restrict_entries() {
+        let list = no_proxy("example.com:8080,[::1]:443,10.0.0.1:22");
+        assert!(list.matches("example.com", Some(8080)));
+        assert!(!list.matches("example.com", Some(443)));
+        assert!(list.matches("[::1]", Some(443)));
+        assert!(!list.matches("::1", Some(80)));
+        assert!(list.matches("10.0.0.1", Some(22)));
+        assert!(!list.matches("10.0.0.1", None));
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for list in &[
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/x",
+            "example.com:http",
+            "[::1",
+            "[::1]8080",
+            "example.com:1:2",
+            "bad/entry",
+            ".",
+            "exam!ple.com",
+        ] {
+            match list.parse::<NoProxy>() {
+                Err(ref err) => match *err.kind() {
+                    ErrorKind::InvalidNoProxy(_) => (),
+                    ref kind => panic!("unexpected error kind {:?} for {}", kind, list),
+                },
+                Ok(parsed) => panic!("parsed {} as {:?}", list, parsed),
+            }
+        }
+    }
+}
@@ -1,9 +1,16 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use super::super::client::ClientImpl;
+use super::connector::TlsConfig;
 use super::hyperwrap::Client;
+use super::no_proxy::NoProxy;
+use super::timeout::DEFAULT_CONNECT_TIMEOUT_SECS;
 use error::Error;
 use hyper::{Body, Request, Uri};
+use tls::TlsPolicy;
 
 #[derive(Clone)]
 pub struct MaybeProxyClient {
@@ -12,17 +19,53 @@ pub struct MaybeProxyClient {
 
 impl MaybeProxyClient {
     pub fn new(proxy_uri: Option<Uri>) -> Result<Self, Error> {
-        MaybeProxyClient::create(false, proxy_uri)
+        MaybeProxyClient::with_connect_timeout(
+            proxy_uri,
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+        )
+    }
+
+    pub fn with_connect_timeout(
+        proxy_uri: Option<Uri>,
+        connect_timeout: Duration,
+    ) -> Result<Self, Error> {
+        MaybeProxyClient::create(false, proxy_uri, NoProxy::default(), connect_timeout, None)
     }
 
-    fn create(null: bool, proxy_uri: Option<Uri>) -> Result<Self, Error> {
+    /// Creates a client that connects with the given TLS material, for
+    /// example to authenticate with a client certificate.
+    pub fn with_tls_config(
+        proxy_uri: Option<Uri>,
+        connect_timeout: Duration,
+        tls: TlsConfig,
+    ) -> Result<Self, Error> {
+        MaybeProxyClient::create(
+            false,
+            proxy_uri,
+            NoProxy::default(),
+            connect_timeout,
+            Some(tls),
+        )
+    }
+
+    fn create(
+        null: bool,
+        proxy_uri: Option<Uri>,
+        no_proxy: NoProxy,
+        connect_timeout: Duration,
+        tls: Option<TlsConfig>,
+    ) -> Result<Self, Error> {
         let mut config = Client::configure();
+        config.connect_timeout(connect_timeout).no_proxy(no_proxy);
         if null {
             config.null();
         }
         if let Some(uri) = proxy_uri {
             config.proxy(uri);
         }
+        if let Some(tls) = tls {
+            config.tls(tls);
+        }
         Ok(MaybeProxyClient {
             client: config.build()?,
         })
@@ -30,7 +73,13 @@ impl MaybeProxyClient {
 
     #[cfg(test)]
     pub fn new_null() -> Result<Self, Error> {
-        MaybeProxyClient::create(true, None)
+        MaybeProxyClient::create(
+            true,
+            None,
+            NoProxy::default(),
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            None,
+        )
     }
 
     #[cfg(test)]
@@ -44,6 +93,122 @@ impl MaybeProxyClient {
     }
 }
 
+/// Makes the clients of the calls edgelet makes to the internet, such as to
+/// IoT Hub, DPS and container registries, so that they all reach it the same
+/// way: through the proxy at `proxy_uri`, if there is one, except for the
+/// destinations `no_proxy` matches. Servers are trusted by the certificates
+/// in `trust_bundle` as well as by the system's, and connections keep to
+/// the lowest TLS version of `tls_policy`.
+#[derive(Clone, Debug)]
+pub struct ClientFactory {
+    proxy_uri: Option<Uri>,
+    no_proxy: NoProxy,
+    connect_timeout: Duration,
+    trust_bundle: Option<PathBuf>,
+    tls_policy: TlsPolicy,
+}
+
+impl ClientFactory {
+    pub fn new() -> Self {
+        ClientFactory {
+            proxy_uri: None,
+            no_proxy: NoProxy::default(),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            trust_bundle: None,
+            tls_policy: TlsPolicy::default(),
+        }
+    }
+
+    pub fn with_proxy_uri(mut self, proxy_uri: Option<Uri>) -> Self {
+        self.proxy_uri = proxy_uri;
+        self
+    }
+
+    pub fn with_no_proxy(mut self, no_proxy: NoProxy) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Sets the PEM file of the CA certificates servers are trusted by
+    /// besides the system's, such as the CA of a parent device that serves
+    /// IoT Hub and DPS to the devices below it.
+    pub fn with_trust_bundle(mut self, trust_bundle: Option<PathBuf>) -> Self {
+        self.trust_bundle = trust_bundle;
+        self
+    }
+
+    pub fn with_tls_policy(mut self, tls_policy: TlsPolicy) -> Self {
+        self.tls_policy = tls_policy;
+        self
+    }
+
+    pub fn proxy_uri(&self) -> Option<&Uri> {
+        self.proxy_uri.as_ref()
+    }
+
+    pub fn no_proxy(&self) -> &NoProxy {
+        &self.no_proxy
+    }
+
+    pub fn trust_bundle(&self) -> Option<&Path> {
+        self.trust_bundle.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn tls_policy(&self) -> &TlsPolicy {
+        &self.tls_policy
+    }
+
+    pub fn client(&self) -> Result<MaybeProxyClient, Error> {
+        let tls = if self.trust_bundle.is_none() && self.tls_policy == TlsPolicy::default() {
+            None
+        } else {
+            let tls = TlsConfig::new().with_policy(self.tls_policy.clone());
+            Some(match self.trust_bundle {
+                Some(ref trust_bundle) => tls.with_ca_cert(trust_bundle.clone()),
+                None => tls,
+            })
+        };
+        MaybeProxyClient::create(
+            false,
+            self.proxy_uri.clone(),
+            self.no_proxy.clone(),
+            self.connect_timeout,
+            tls,
+        )
+    }
+
+    /// A client that connects with the given TLS material, for example to
+    /// authenticate with a client certificate. The trust bundle is used
+    /// unless `tls` has CA certificates of its own, and the TLS policy
+    /// replaces that of `tls`.
+    pub fn tls_client(&self, tls: TlsConfig) -> Result<MaybeProxyClient, Error> {
+        let tls = match self.trust_bundle {
+            Some(ref trust_bundle) if tls.ca_cert().is_none() => {
+                tls.with_ca_cert(trust_bundle.clone())
+            }
+            _ => tls,
+        }.with_policy(self.tls_policy.clone());
+        MaybeProxyClient::create(
+            false,
+            self.proxy_uri.clone(),
+            self.no_proxy.clone(),
+            self.connect_timeout,
+            Some(tls),
+        )
+    }
+}
+
+impl Default for ClientFactory {
+    fn default() -> Self {
+        ClientFactory::new()
+    }
+}
+
 impl ClientImpl for MaybeProxyClient {
     type Response = <Client as ClientImpl>::Response;
 
@@ -55,7 +220,8 @@ impl ClientImpl for MaybeProxyClient {
 #[cfg(test)]
 mod tests {
     use super::super::super::client::ClientImpl;
-    use super::MaybeProxyClient;
+    use super::super::no_proxy::NoProxy;
+    use super::{ClientFactory, MaybeProxyClient};
     use futures::Future;
     use http::Request;
     use hyper::{StatusCode, Uri};
@@ -73,6 +239,19 @@ mod tests {
         assert!(client.has_proxy() && !client.is_null());
     }
 
+    #[test]
+    fn factory_creates_clients_with_its_proxy() {
+        let factory = ClientFactory::new();
+        assert!(!factory.client().unwrap().has_proxy());
+
+        let uri = "http://example.com".parse::<Uri>().unwrap();
+        let factory = factory
+            .with_proxy_uri(Some(uri))
+            .with_no_proxy("localhost".parse::<NoProxy>().unwrap());
+        assert!(factory.client().unwrap().has_proxy());
+        assert!(factory.no_proxy().matches("localhost", Some(80)));
+    }
+
     #[test]
     fn client_calls_underlying_service() {
         let client = MaybeProxyClient::new_null().unwrap();
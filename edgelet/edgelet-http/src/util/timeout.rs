@@ -0,0 +1,141 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Connect timeouts
+//!
+//! hyper's connectors wait for as long as the operating system lets them,
+//! which for a peer that silently drops packets can be several minutes.
+//! `TimeoutConnector` bounds the time spent establishing a connection,
+//! including the TLS handshake when it wraps a TLS connector.
+
+use std::io;
+use std::time::Duration;
+
+use futures::Future;
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::Error as HyperError;
+use tokio::timer::Timeout;
+
+/// How long to wait for a connection to be established by default.
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct TimeoutConnector<C> {
+    inner: C,
+    timeout: Duration,
+}
+
+impl<C> TimeoutConnector<C> {
+    pub fn new(inner: C) -> Self {
+        TimeoutConnector {
+            inner,
+            timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl<C> Connect for TimeoutConnector<C>
+where
+    C: Connect<Error = io::Error>,
+    C::Future: 'static,
+{
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = Box<Future<Item = (Self::Transport, Connected), Error = Self::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let timeout = self.timeout;
+        let connecting = Timeout::new(self.inner.connect(dst), timeout).map_err(move |err| {
+            if err.is_elapsed() {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("connection was not established within {:?}", timeout),
+                )
+            } else if err.is_inner() {
+                err.into_inner().expect("timeout error should have an inner error")
+            } else {
+                io::Error::new(io::ErrorKind::Other, err)
+            }
+        });
+        Box::new(connecting)
+    }
+}
+
+/// Whether a client error was caused by a connect timeout.
+pub fn is_timeout(err: &HyperError) -> bool {
+    err.cause2()
+        .and_then(|cause| cause.downcast_ref::<io::Error>())
+        .map_or(false, |err| err.kind() == io::ErrorKind::TimedOut)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use futures::future;
+    use hyper::Client;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+
+    struct NeverConnector;
+
+    impl Connect for NeverConnector {
+        type Transport = ::tokio::net::TcpStream;
+        type Error = io::Error;
+        type Future = future::Empty<(Self::Transport, Connected), Self::Error>;
+
+        fn connect(&self, _dst: Destination) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    #[test]
+    fn default_timeout() {
+        let connector = TimeoutConnector::new(NeverConnector);
+        assert_eq!(
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            connector.timeout()
+        );
+    }
+
+    #[test]
+    fn connect_times_out() {
+        let mut connector = TimeoutConnector::new(NeverConnector);
+        connector.set_timeout(Duration::from_millis(50));
+        let client = Client::builder().build::<_, ::hyper::Body>(connector);
+
+        let err = Runtime::new()
+            .unwrap()
+            .block_on(client.get("http://localhost/".parse().unwrap()))
+            .unwrap_err();
+
+        assert!(is_timeout(&err));
+    }
+
+    #[test]
+    fn connect_errors_are_not_timeouts() {
+        // Bind and drop a listener to find a port nothing listens on.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let connector = TimeoutConnector::new(::hyper::client::HttpConnector::new(1));
+        let client = Client::builder().build::<_, ::hyper::Body>(connector);
+
+        let err = Runtime::new()
+            .unwrap()
+            .block_on(client.get(format!("http://127.0.0.1:{}/", port).parse().unwrap()))
+            .unwrap_err();
+
+        assert!(!is_timeout(&err));
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate hyper;
+extern crate openssl;
+extern crate tokio;
+
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use edgelet_http::client::ClientImpl;
+use edgelet_http::{ClientFactory, MaybeProxyClient, TlsConfig, TlsPolicy, TlsVersion};
+use hyper::{Body, Request, StatusCode};
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode, SslVersion};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+
+struct TestIdentity {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl TestIdentity {
+    fn self_signed(common_name: &str) -> Self {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        let san = SubjectAlternativeName::new()
+            .ip("127.0.0.1")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        TestIdentity {
+            cert: builder.build(),
+            key,
+        }
+    }
+
+    /// Writes the certificate and the private key to `dir`, returning their
+    /// paths.
+    fn write(&self, dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = dir.join(format!("{}.cert.pem", name));
+        let key = dir.join(format!("{}.key.pem", name));
+        fs::write(&cert, self.cert.to_pem().unwrap()).unwrap();
+        fs::write(&key, self.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert, key)
+    }
+}
+
+fn test_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("edgelet-http-client-tls-{}-{}", process::id(), test));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Answers one HTTPS request on `listener`. With `require_client_cert`, the
+/// TLS handshake fails unless the client presents a certificate, like IoT Hub
+/// does for devices that authenticate with X.509 certificates. Returns the
+/// common name of the client certificate, if the client presented one.
+fn serve_one(
+    listener: TcpListener,
+    server: &TestIdentity,
+    require_client_cert: bool,
+) -> JoinHandle<Option<String>> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(&server.cert).unwrap();
+    acceptor.set_private_key(&server.key).unwrap();
+    if require_client_cert {
+        // Client certificates are self-signed, so accept any of them.
+        acceptor.set_verify_callback(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            |_, _| true,
+        );
+    }
+    respond_once(listener, acceptor.build())
+}
+
+/// Answers one HTTPS request on `listener` with TLS `version` and no other.
+fn serve_one_with_version(
+    listener: TcpListener,
+    server: &TestIdentity,
+    version: SslVersion,
+) -> JoinHandle<Option<String>> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(&server.cert).unwrap();
+    acceptor.set_private_key(&server.key).unwrap();
+    acceptor.set_min_proto_version(Some(version)).unwrap();
+    acceptor.set_max_proto_version(Some(version)).unwrap();
+    // Current OpenSSL only negotiates TLS 1.0 at security level 0.
+    acceptor.set_cipher_list("DEFAULT:@SECLEVEL=0").unwrap();
+    respond_once(listener, acceptor.build())
+}
+
+fn respond_once(listener: TcpListener, acceptor: SslAcceptor) -> JoinHandle<Option<String>> {
+    thread::spawn(move || {
+        let stream = accept(&listener);
+        let mut stream = acceptor.accept(stream).ok()?;
+        let common_name = stream.ssl().peer_certificate().and_then(|cert| {
+            cert.subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .and_then(|entry| entry.data().as_utf8().ok())
+                .map(|name| name.to_string())
+        });
+
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        while !request.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => request.extend_from_slice(&buf[..read]),
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+            .unwrap();
+        common_name
+    })
+}
+
+/// Waits a few seconds at most for a connection, so that a client that fails
+/// to connect fails the test rather than hanging it.
+fn accept(listener: &TcpListener) -> TcpStream {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    listener.set_nonblocking(true).unwrap();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).unwrap();
+                return stream;
+            }
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("client did not connect: {}", err),
+        }
+    }
+}
+
+fn get(client: &MaybeProxyClient, port: u16) -> Result<StatusCode, hyper::Error> {
+    let request = Request::get(format!("https://127.0.0.1:{}/devices/d1", port))
+        .body(Body::empty())
+        .unwrap();
+    tokio::runtime::current_thread::Runtime::new()
+        .unwrap()
+        .block_on(client.call(request))
+        .map(|response| response.status())
+}
+
+#[test]
+fn client_certificate_is_presented() {
+    let dir = test_dir("presented");
+    let server = TestIdentity::self_signed("iothub");
+    let device = TestIdentity::self_signed("d1");
+    let (server_cert, _) = server.write(&dir, "server");
+    let (device_cert, device_key) = device.write(&dir, "device");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one(listener, &server, true);
+
+    let tls = TlsConfig::new()
+        .with_ca_cert(server_cert)
+        .with_client_cert(device_cert)
+        .with_client_key(device_key);
+    let client = MaybeProxyClient::with_tls_config(None, Duration::from_secs(5), tls).unwrap();
+
+    assert_eq!(StatusCode::OK, get(&client, port).unwrap());
+    assert_eq!(Some("d1".to_string()), handle.join().unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn server_requiring_client_certificate_rejects_client_without_one() {
+    let dir = test_dir("missing");
+    let server = TestIdentity::self_signed("iothub");
+    let (server_cert, _) = server.write(&dir, "server");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one(listener, &server, true);
+
+    let tls = TlsConfig::new().with_ca_cert(server_cert);
+    let client = MaybeProxyClient::with_tls_config(None, Duration::from_secs(5), tls).unwrap();
+
+    assert!(get(&client, port).is_err());
+    assert_eq!(None, handle.join().unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// A parent device serves IoT Hub and DPS to the devices below it with a
+/// certificate issued by a CA that only their trust bundle has.
+#[test]
+fn parent_is_trusted_through_the_trust_bundle() {
+    let dir = test_dir("parent-trusted");
+    let parent = TestIdentity::self_signed("parent");
+    let (trust_bundle, _) = parent.write(&dir, "trust-bundle");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one(listener, &parent, false);
+
+    let client = ClientFactory::new()
+        .with_connect_timeout(Duration::from_secs(5))
+        .with_trust_bundle(Some(trust_bundle))
+        .client()
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, get(&client, port).unwrap());
+    assert_eq!(None, handle.join().unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parent_is_not_trusted_without_the_trust_bundle() {
+    let parent = TestIdentity::self_signed("parent");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one(listener, &parent, false);
+
+    let client = ClientFactory::new()
+        .with_connect_timeout(Duration::from_secs(5))
+        .client()
+        .unwrap();
+
+    assert!(get(&client, port).is_err());
+    assert_eq!(None, handle.join().unwrap());
+}
+
+fn client_with_floor(trust_bundle: PathBuf, min_version: TlsVersion) -> MaybeProxyClient {
+    ClientFactory::new()
+        .with_connect_timeout(Duration::from_secs(5))
+        .with_trust_bundle(Some(trust_bundle))
+        .with_tls_policy(TlsPolicy::new().with_min_version(min_version))
+        .client()
+        .unwrap()
+}
+
+#[test]
+fn server_offering_the_lowest_version_allowed_is_connected_to() {
+    let dir = test_dir("floor-met");
+    let server = TestIdentity::self_signed("iothub");
+    let (trust_bundle, _) = server.write(&dir, "trust-bundle");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one_with_version(listener, &server, SslVersion::TLS1_2);
+
+    let client = client_with_floor(trust_bundle, TlsVersion::Tls12);
+
+    assert_eq!(StatusCode::OK, get(&client, port).unwrap());
+    assert_eq!(None, handle.join().unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn server_offering_only_older_versions_is_rejected() {
+    let dir = test_dir("floor-not-met");
+    let server = TestIdentity::self_signed("iothub");
+    let (trust_bundle, _) = server.write(&dir, "trust-bundle");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = serve_one_with_version(listener, &server, SslVersion::TLS1);
+
+    let client = client_with_floor(trust_bundle, TlsVersion::Tls12);
+
+    let err = get(&client, port).unwrap_err();
+    let message = err.cause2().map(ToString::to_string).unwrap_or_default();
+    let expected = format!(
+        "TLS handshake with 127.0.0.1:{} failed, allowing TLS 1.2 or later",
+        port
+    );
+    assert!(message.starts_with(&expected), "{}", message);
+    assert_eq!(None, handle.join().unwrap());
+    fs::remove_dir_all(dir).unwrap();
+}
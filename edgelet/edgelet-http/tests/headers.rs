@@ -0,0 +1,121 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate tokio;
+extern crate url;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+
+use edgelet_http::{ConnectionLimits, HyperExt, SharedLimits};
+use edgelet_test_utils::get_unused_tcp_port;
+use futures::sync::oneshot;
+use futures::{future, Future};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Error as HyperError, Request, Response};
+use tokio::runtime::current_thread::Runtime;
+use url::Url;
+
+/// Serves the names of the headers each request arrived with, one per line.
+fn serve(port: u16) -> (oneshot::Sender<()>, thread::JoinHandle<()>) {
+    let url = Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+    let limits = ConnectionLimits::new()
+        .with_max_headers(Some(16))
+        .with_max_header_bytes(Some(1024));
+    let server = Http::new()
+        .bind_url(url, || {
+            Ok::<_, io::Error>(service_fn(|req: Request<Body>| {
+                let mut names: Vec<_> = req.headers().keys().map(|name| name.as_str()).collect();
+                names.sort();
+                future::ok::<_, HyperError>(Response::new(Body::from(names.join("\n"))))
+            }))
+        }).unwrap()
+        .with_connection_limits(SharedLimits::new(limits));
+
+    let (tx, rx) = oneshot::channel();
+    let run = server.run_until(rx.map_err(|_| ()));
+    let handle = thread::spawn(move || Runtime::new().unwrap().block_on(run).unwrap());
+    (tx, handle)
+}
+
+/// Sends `headers` with a request that closes the connection, and reads the
+/// whole response.
+fn send(port: u16, headers: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let request = format!(
+        "POST /modules HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n{}",
+        headers, body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn status(response: &str) -> &str {
+    &response[9..12]
+}
+
+#[test]
+fn hop_by_hop_headers_do_not_reach_handlers() {
+    let port = get_unused_tcp_port();
+    let (shutdown, server) = serve(port);
+
+    let response = send(
+        port,
+        "Keep-Alive: timeout=5\r\nX-Forwarded-For: 10.0.0.1\r\nX-Request-Id: 1\r\n",
+        "",
+    );
+    assert_eq!("200", status(&response));
+    assert!(response.ends_with("\r\n\r\nhost\nx-request-id"), "{}", response);
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn oversized_headers_are_rejected() {
+    let port = get_unused_tcp_port();
+    let (shutdown, server) = serve(port);
+
+    let many: String = (0..20).map(|i| format!("X-Header-{}: {}\r\n", i, i)).collect();
+    assert_eq!("431", status(&send(port, &many, "")));
+
+    let large = format!("X-Large: {}\r\n", "x".repeat(2048));
+    assert_eq!("431", status(&send(port, &large, "")));
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn conflicting_framing_is_rejected() {
+    let port = get_unused_tcp_port();
+    let (shutdown, server) = serve(port);
+
+    let response = send(
+        port,
+        "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+        "0\r\n\r\n",
+    );
+    assert_eq!("400", status(&response));
+
+    let response = send(port, "Content-Length: 2\r\nContent-Length: 3\r\n", "abc");
+    assert_eq!("400", status(&response));
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
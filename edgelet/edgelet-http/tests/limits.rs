@@ -0,0 +1,158 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![cfg(unix)]
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate futures;
+extern crate hyper;
+extern crate tempfile;
+extern crate tokio;
+extern crate url;
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use edgelet_http::{ConnectionLimits, HyperExt, SharedLimits};
+use futures::sync::oneshot;
+use futures::{future, Future};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Error as HyperError, Response};
+use tempfile::tempdir;
+use tokio::runtime::current_thread::Runtime;
+use url::Url;
+
+const REQUEST: &[u8] = b"GET /modules HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+fn serve(path: &Path, limits: SharedLimits) -> (oneshot::Sender<()>, thread::JoinHandle<()>) {
+    let url = Url::parse(&format!("unix://{}", path.display())).unwrap();
+    let server = Http::new()
+        .bind_url(url, || {
+            Ok::<_, io::Error>(service_fn(|_req| {
+                future::ok::<_, HyperError>(Response::new(Body::from("ok")))
+            }))
+        }).unwrap()
+        .with_connection_limits(limits);
+
+    let (tx, rx) = oneshot::channel();
+    let run = server.run_until(rx.map_err(|_| ()));
+    let handle = thread::spawn(move || Runtime::new().unwrap().block_on(run).unwrap());
+    (tx, handle)
+}
+
+/// Sends a request and reads the response head and its two byte body.
+fn get(stream: &mut UnixStream) -> io::Result<String> {
+    stream.write_all(REQUEST)?;
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    while !response.ends_with(b"\r\n\r\nok") {
+        let read = stream.read(&mut buf)?;
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        response.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8(response).unwrap())
+}
+
+fn is_closed(stream: &mut UnixStream) -> bool {
+    // Writes to a connection the server already closed may still succeed,
+    // but the response never comes.
+    let _ = stream.write_all(REQUEST);
+    let mut buf = [0; 1024];
+    match stream.read(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(err) => err.kind() == io::ErrorKind::ConnectionReset,
+    }
+}
+
+#[test]
+fn connections_beyond_the_limit_are_refused() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("mgmt.sock");
+    let limits = ConnectionLimits::new().with_max_connections(Some(2));
+    let (shutdown, server) = serve(&path, SharedLimits::new(limits));
+
+    let mut first = UnixStream::connect(&path).unwrap();
+    let mut second = UnixStream::connect(&path).unwrap();
+    assert!(get(&mut first).unwrap().starts_with("HTTP/1.1 200 OK"));
+    assert!(get(&mut second).unwrap().starts_with("HTTP/1.1 200 OK"));
+
+    let mut third = UnixStream::connect(&path).unwrap();
+    assert!(is_closed(&mut third));
+
+    // The connections that were let in keep working.
+    assert!(get(&mut first).unwrap().starts_with("HTTP/1.1 200 OK"));
+    assert!(get(&mut second).unwrap().starts_with("HTTP/1.1 200 OK"));
+
+    // Once one of them goes away there is room for another.
+    drop(first);
+    thread::sleep(Duration::from_millis(100));
+    let mut fourth = UnixStream::connect(&path).unwrap();
+    assert!(get(&mut fourth).unwrap().starts_with("HTTP/1.1 200 OK"));
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn idle_connections_are_closed() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("mgmt.sock");
+    let limits =
+        ConnectionLimits::new().with_header_read_timeout(Some(Duration::from_millis(100)));
+    let (shutdown, server) = serve(&path, SharedLimits::new(limits));
+
+    let start = Instant::now();
+    let mut silent = UnixStream::connect(&path).unwrap();
+    let mut partial = UnixStream::connect(&path).unwrap();
+    let mut active = UnixStream::connect(&path).unwrap();
+    assert!(get(&mut active).unwrap().starts_with("HTTP/1.1 200 OK"));
+
+    // A client that sends half of its headers and then goes quiet.
+    partial.write_all(b"GET /modules HTTP/1.1\r\n").unwrap();
+
+    let mut buf = [0; 1024];
+    assert_eq!(0, silent.read(&mut buf).unwrap());
+    assert_eq!(0, partial.read(&mut buf).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // Connections idle between requests are closed as well.
+    assert_eq!(0, active.read(&mut buf).unwrap());
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn limits_can_be_changed_while_running() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("mgmt.sock");
+    let limits = SharedLimits::new(ConnectionLimits::new().with_max_connections(Some(1)));
+    let (shutdown, server) = serve(&path, limits.clone());
+
+    let mut first = UnixStream::connect(&path).unwrap();
+    assert!(get(&mut first).unwrap().starts_with("HTTP/1.1 200 OK"));
+    let mut second = UnixStream::connect(&path).unwrap();
+    assert!(is_closed(&mut second));
+
+    // Raising the limit lets the next connection in without a restart.
+    limits.set(limits.get().with_max_connections(Some(2)));
+    let mut third = UnixStream::connect(&path).unwrap();
+    assert!(get(&mut third).unwrap().starts_with("HTTP/1.1 200 OK"));
+    assert!(get(&mut first).unwrap().starts_with("HTTP/1.1 200 OK"));
+
+    shutdown.send(()).unwrap();
+    server.join().unwrap();
+}
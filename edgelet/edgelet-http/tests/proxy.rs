@@ -0,0 +1,89 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate tokio;
+
+use std::sync::{Arc, Mutex};
+
+use edgelet_http::client::ClientImpl;
+use edgelet_http::{ClientFactory, NoProxy};
+use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+use futures::{future, Future};
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use tokio::runtime::current_thread::Runtime;
+
+/// Answers every request and records the URI it was made for.
+fn recording_server(
+    runtime: &mut Runtime,
+    status: StatusCode,
+) -> (u16, Arc<Mutex<Vec<String>>>) {
+    let port = get_unused_tcp_port();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let server = run_tcp_server("127.0.0.1", port, move |req: Request<Body>| {
+        recorded.lock().unwrap().push(req.uri().to_string());
+        future::ok(
+            Response::builder()
+                .status(status)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    }).map_err(|err| eprintln!("{}", err));
+    runtime.spawn(server);
+    (port, requests)
+}
+
+fn get(runtime: &mut Runtime, factory: &ClientFactory, uri: &str) -> StatusCode {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = factory.client().unwrap().call(request);
+    runtime.block_on(response).unwrap().status()
+}
+
+fn proxy_uri(port: u16) -> Option<Uri> {
+    Some(format!("http://127.0.0.1:{}", port).parse().unwrap())
+}
+
+#[test]
+fn requests_go_through_the_proxy() {
+    let mut runtime = Runtime::new().unwrap();
+    let (proxy_port, proxied) = recording_server(&mut runtime, StatusCode::ACCEPTED);
+
+    let factory = ClientFactory::new()
+        .with_proxy_uri(proxy_uri(proxy_port))
+        .with_no_proxy("localhost,.internal".parse::<NoProxy>().unwrap());
+    let status = get(&mut runtime, &factory, "http://registry.example:5000/v2/");
+
+    assert_eq!(StatusCode::ACCEPTED, status);
+    assert_eq!(
+        vec!["http://registry.example:5000/v2/".to_string()],
+        *proxied.lock().unwrap()
+    );
+}
+
+#[test]
+fn no_proxy_destinations_are_reached_directly() {
+    let mut runtime = Runtime::new().unwrap();
+    let (proxy_port, proxied) = recording_server(&mut runtime, StatusCode::ACCEPTED);
+    let (server_port, served) = recording_server(&mut runtime, StatusCode::OK);
+
+    let factory = ClientFactory::new()
+        .with_proxy_uri(proxy_uri(proxy_port))
+        .with_no_proxy("10.0.0.0/8,127.0.0.0/8".parse::<NoProxy>().unwrap());
+    let uri = format!("http://127.0.0.1:{}/v2/", server_port);
+    let status = get(&mut runtime, &factory, &uri);
+
+    assert_eq!(StatusCode::OK, status);
+    assert!(proxied.lock().unwrap().is_empty());
+    assert_eq!(vec!["/v2/".to_string()], *served.lock().unwrap());
+}
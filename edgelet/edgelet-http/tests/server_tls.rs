@@ -0,0 +1,318 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate futures;
+extern crate hyper;
+extern crate openssl;
+extern crate tokio;
+extern crate url;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use edgelet_http::{ErrorKind, HyperExt, ServerTlsConfig, TlsPolicy, TlsVersion};
+use futures::sync::oneshot;
+use futures::{future, Future};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Error as HyperError, Response};
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslConnector, SslMethod, SslVersion};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509Builder, X509NameBuilder, X509};
+use tokio::runtime::current_thread::Runtime;
+use url::Url;
+
+const REQUEST: &[u8] = b"GET /modules HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n";
+
+struct TestIdentity {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+impl TestIdentity {
+    fn ca(common_name: &str) -> Self {
+        TestIdentity::new(common_name, None, true)
+    }
+
+    /// A certificate for 127.0.0.1 issued by `issuer`.
+    fn issued(common_name: &str, issuer: &TestIdentity) -> Self {
+        TestIdentity::new(common_name, Some(issuer), false)
+    }
+
+    fn new(common_name: &str, issuer: Option<&TestIdentity>, ca: bool) -> Self {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        if ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        } else {
+            let issuer_cert = issuer.map(|issuer| &issuer.cert);
+            let san = SubjectAlternativeName::new()
+                .ip("127.0.0.1")
+                .build(&builder.x509v3_context(issuer_cert.map(|cert| &**cert), None))
+                .unwrap();
+            builder.append_extension(san).unwrap();
+        }
+        match issuer {
+            Some(issuer) => {
+                builder
+                    .set_issuer_name(issuer.cert.subject_name())
+                    .unwrap();
+                builder.sign(&issuer.key, MessageDigest::sha256()).unwrap();
+            }
+            None => {
+                builder.set_issuer_name(&name).unwrap();
+                builder.sign(&key, MessageDigest::sha256()).unwrap();
+            }
+        }
+
+        TestIdentity {
+            cert: builder.build(),
+            key,
+        }
+    }
+
+    /// Writes the certificate and the private key to `dir`, returning their
+    /// paths.
+    fn write(&self, dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = dir.join(format!("{}.cert.pem", name));
+        let key = dir.join(format!("{}.key.pem", name));
+        fs::write(&cert, self.cert.to_pem().unwrap()).unwrap();
+        fs::write(&key, self.key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert, key)
+    }
+}
+
+fn test_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("edgelet-http-server-tls-{}-{}", process::id(), test));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn serve(url: Url, tls: &ServerTlsConfig) -> (oneshot::Sender<()>, thread::JoinHandle<()>) {
+    let server = Http::new()
+        .bind_url_with_tls(
+            url,
+            || {
+                Ok::<_, io::Error>(service_fn(|_req| {
+                    future::ok::<_, HyperError>(Response::new(Body::from("ok")))
+                }))
+            },
+            Some(tls),
+        ).unwrap();
+
+    let (tx, rx) = oneshot::channel();
+    let run = server.run_until(rx.map_err(|_| ()));
+    let handle = thread::spawn(move || Runtime::new().unwrap().block_on(run).unwrap());
+    (tx, handle)
+}
+
+/// Sends a request over TLS, trusting `ca` and presenting `client` if given,
+/// and returns the response.
+fn get(port: u16, ca: &Path, client: Option<&TestIdentity>) -> Result<String, String> {
+    get_with_max_version(port, ca, client, None)
+}
+
+/// Sends a request like `get`, offering no TLS version newer than
+/// `max_version`.
+fn get_with_max_version(
+    port: u16,
+    ca: &Path,
+    client: Option<&TestIdentity>,
+    max_version: Option<SslVersion>,
+) -> Result<String, String> {
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_ca_file(ca).unwrap();
+    if let Some(client) = client {
+        connector.set_certificate(&client.cert).unwrap();
+        connector.set_private_key(&client.key).unwrap();
+    }
+    if max_version.is_some() {
+        connector.set_max_proto_version(max_version).unwrap();
+        // Current OpenSSL only negotiates versions before 1.2 at security
+        // level 0.
+        connector.set_cipher_list("DEFAULT:@SECLEVEL=0").unwrap();
+    }
+    let connector = connector.build();
+
+    let stream = TcpStream::connect(("127.0.0.1", port)).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut stream = connector
+        .connect("127.0.0.1", stream)
+        .map_err(|err| err.to_string())?;
+
+    // With TLS 1.3 the server checks the client certificate after the client
+    // considers the handshake done, so a rejection may only show when reading.
+    stream.write_all(REQUEST).map_err(|err| err.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|err| err.to_string())?;
+    if response.is_empty() {
+        Err("connection closed".to_string())
+    } else {
+        Ok(response)
+    }
+}
+
+#[test]
+fn clients_with_a_certificate_from_the_client_ca_are_served() {
+    let dir = test_dir("accepted");
+    let ca = TestIdentity::ca("test ca");
+    let server = TestIdentity::issued("127.0.0.1", &ca);
+    let client = TestIdentity::issued("edgeHub", &ca);
+    let (ca_cert, _) = ca.write(&dir, "ca");
+    let (server_cert, server_key) = server.write(&dir, "server");
+
+    let tls = ServerTlsConfig::from_files(&server_cert, &server_key)
+        .unwrap()
+        .with_client_ca(&ca_cert)
+        .unwrap();
+    assert!(tls.requires_client_cert());
+    let port = free_port();
+    let url = Url::parse(&format!("https://127.0.0.1:{}", port)).unwrap();
+    let (shutdown, handle) = serve(url, &tls);
+
+    let response = get(port, &ca_cert, Some(&client)).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.ends_with("ok"), "{}", response);
+
+    shutdown.send(()).unwrap();
+    handle.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn clients_without_a_certificate_are_rejected() {
+    let dir = test_dir("rejected");
+    let ca = TestIdentity::ca("test ca");
+    let server = TestIdentity::issued("127.0.0.1", &ca);
+    let other_ca = TestIdentity::ca("other ca");
+    let stranger = TestIdentity::issued("stranger", &other_ca);
+    let (ca_cert, _) = ca.write(&dir, "ca");
+
+    let tls = ServerTlsConfig::from_pem(
+        &server.cert.to_pem().unwrap(),
+        &server.key.private_key_to_pem_pkcs8().unwrap(),
+    ).unwrap()
+    .with_client_ca(&ca_cert)
+    .unwrap();
+    let port = free_port();
+    let url = Url::parse(&format!("https://127.0.0.1:{}", port)).unwrap();
+    let (shutdown, handle) = serve(url, &tls);
+
+    assert!(get(port, &ca_cert, None).is_err());
+    assert!(get(port, &ca_cert, Some(&stranger)).is_err());
+
+    // The listener keeps serving clients that do present a certificate.
+    let client = TestIdentity::issued("edgeHub", &ca);
+    assert!(get(port, &ca_cert, Some(&client)).is_ok());
+
+    shutdown.send(()).unwrap();
+    handle.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn clients_offering_only_older_versions_are_rejected() {
+    let dir = test_dir("floor");
+    let ca = TestIdentity::ca("test ca");
+    let server = TestIdentity::issued("127.0.0.1", &ca);
+    let (ca_cert, _) = ca.write(&dir, "ca");
+
+    let tls = ServerTlsConfig::from_pem(
+        &server.cert.to_pem().unwrap(),
+        &server.key.private_key_to_pem_pkcs8().unwrap(),
+    ).unwrap()
+    .with_policy(TlsPolicy::new().with_min_version(TlsVersion::Tls12));
+    let port = free_port();
+    let url = Url::parse(&format!("https://127.0.0.1:{}", port)).unwrap();
+    let (shutdown, handle) = serve(url, &tls);
+
+    assert!(get_with_max_version(port, &ca_cert, None, Some(SslVersion::TLS1_1)).is_err());
+    assert!(get_with_max_version(port, &ca_cert, None, Some(SslVersion::TLS1_2)).is_ok());
+
+    shutdown.send(()).unwrap();
+    handle.join().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn https_listeners_require_tls() {
+    let new_service = || {
+        Ok::<_, io::Error>(service_fn(|_req| {
+            future::ok::<_, HyperError>(Response::new(Body::from("ok")))
+        }))
+    };
+    let url = Url::parse(&format!("https://127.0.0.1:{}", free_port())).unwrap();
+    let err = Http::new()
+        .bind_url(url.clone(), new_service)
+        .err()
+        .unwrap();
+    assert_eq!(&ErrorKind::TlsRequired(url.to_string()), err.kind());
+
+    let ca = TestIdentity::ca("test ca");
+    let tls = ServerTlsConfig::from_pem(
+        &ca.cert.to_pem().unwrap(),
+        &ca.key.private_key_to_pem_pkcs8().unwrap(),
+    ).unwrap();
+    let url = Url::parse(&format!("http://127.0.0.1:{}", free_port())).unwrap();
+    let err = Http::new()
+        .bind_url_with_tls(url.clone(), new_service, Some(&tls))
+        .err()
+        .unwrap();
+    assert_eq!(&ErrorKind::TlsNotSupported(url.to_string()), err.kind());
+}
+
+#[test]
+fn mismatched_server_identity_is_rejected() {
+    let ca = TestIdentity::ca("test ca");
+    let other = TestIdentity::ca("other ca");
+    let err = ServerTlsConfig::from_pem(
+        &ca.cert.to_pem().unwrap(),
+        &other.key.private_key_to_pem_pkcs8().unwrap(),
+    ).err()
+    .unwrap();
+    assert_eq!(&ErrorKind::InvalidServerIdentity, err.kind());
+}
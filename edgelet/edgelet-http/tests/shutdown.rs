@@ -0,0 +1,93 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![cfg(unix)]
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http;
+extern crate futures;
+extern crate hyper;
+extern crate tempfile;
+extern crate tokio;
+extern crate url;
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use edgelet_http::{HyperExt, Run};
+use futures::sync::oneshot;
+use futures::{future, Future};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Error as HyperError, Response};
+use tempfile::tempdir;
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Delay;
+use url::Url;
+
+fn unresponsive_server<F>(path: &Path, drain_timeout: Duration, shutdown: F) -> Run
+where
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    let url = Url::parse(&format!("unix://{}", path.display())).unwrap();
+    Http::new()
+        .bind_url(url, || {
+            Ok::<_, io::Error>(service_fn(|_req| {
+                future::empty::<Response<Body>, HyperError>()
+            }))
+        }).unwrap()
+        .with_drain_timeout(drain_timeout)
+        .run_until(shutdown)
+}
+
+#[test]
+fn socket_is_removed_on_shutdown() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("mgmt.sock");
+    let (tx, rx) = oneshot::channel();
+
+    let run = unresponsive_server(&path, Duration::from_secs(10), rx.map_err(|_| ()));
+    assert!(path.exists());
+
+    tx.send(()).unwrap();
+    Runtime::new().unwrap().block_on(run).unwrap();
+
+    assert!(!path.exists());
+}
+
+#[test]
+fn drain_timeout_aborts_open_requests() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("mgmt.sock");
+    let drain_timeout = Duration::from_millis(100);
+
+    let start = Instant::now();
+    let shutdown = Delay::new(start + Duration::from_millis(200)).map_err(|_| ());
+    let run = unresponsive_server(&path, drain_timeout, shutdown);
+
+    // The connection is queued on the listener until the server accepts it.
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"GET /modules HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let client = thread::spawn(move || {
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    });
+
+    Runtime::new().unwrap().block_on(run).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    // The request never got an answer; its connection was closed instead.
+    assert!(client.join().unwrap().is_empty());
+    assert!(!path.exists());
+}
@@ -0,0 +1,57 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate chrono;
+extern crate edgelet_http;
+extern crate hyper;
+extern crate tokio;
+extern crate url;
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use edgelet_http::client::{Client, TokenSource};
+use edgelet_http::{Error, ErrorKind, UrlConnector};
+use hyper::{Body, Client as HyperClient, Method};
+use url::Url;
+
+#[derive(Clone)]
+struct NoTokenSource;
+
+impl TokenSource for NoTokenSource {
+    type Error = Error;
+
+    fn get(&self, _expiry: &DateTime<Utc>) -> Result<String, Error> {
+        unreachable!()
+    }
+}
+
+#[test]
+fn request_to_unresponsive_server_times_out() {
+    // The kernel completes the handshake for connections queued on the
+    // listener, but nothing ever reads from them or answers.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+
+    let connector = UrlConnector::new(&url).unwrap();
+    let hyper_client = HyperClient::builder().build::<_, Body>(connector);
+    let client = Client::new(hyper_client, None as Option<NoTokenSource>, "2018-06-28", url)
+        .unwrap()
+        .with_request_timeout(Duration::from_millis(200));
+
+    let task = client.request::<(), String>(Method::GET, "/", None, None, false);
+    let err = tokio::runtime::current_thread::Runtime::new()
+        .unwrap()
+        .block_on(task)
+        .unwrap_err();
+
+    assert_eq!(&ErrorKind::Timeout, err.kind());
+}
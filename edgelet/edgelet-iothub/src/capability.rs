@@ -0,0 +1,218 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Finds out whether IoT Hub has the device registered as an IoT Edge device.
+//!
+//! Provisioning a plain device identity succeeds, and so does everything
+//! iotedged does with it at first, but the edge hub module fails later on
+//! because the device has no modules of its own. The device's registry entry
+//! says whether it is an edge device, when the hub lets the device read it.
+//! When it doesn't, the hub's answer to listing the device's modules tells,
+//! as only edge devices are authorized to manage modules.
+
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::Future;
+use hyper::StatusCode;
+
+use edgelet_http::client::{ClientImpl, TokenSource};
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
+use iothubservice::{DeviceClient, Error as HubError};
+
+use error::Error;
+
+/// Whether the device may act as an IoT Edge device.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EdgeCapability {
+    Enabled,
+    Disabled,
+    /// The hub's answers don't tell, for the given reason, for example
+    /// because it cannot be reached.
+    Unknown(String),
+}
+
+/// Asks the hub whether the device of `client` is an IoT Edge device. Failed
+/// requests make the answer unknown rather than an error.
+pub fn edge_capability<C, T>(
+    client: &DeviceClient<C, T>,
+) -> impl Future<Item = EdgeCapability, Error = Error>
+where
+    C: 'static + ClientImpl,
+    T: 'static + TokenSource + Clone,
+    T::Error: Into<HttpError>,
+{
+    let modules_client = client.clone();
+    client.get_device().then(move |result| match result {
+        Ok(device) => match device.capabilities() {
+            Some(capabilities) if capabilities.iot_edge() => {
+                Either::A(future::ok(EdgeCapability::Enabled))
+            }
+            Some(_) => Either::A(future::ok(EdgeCapability::Disabled)),
+            None => Either::B(capability_from_modules(&modules_client)),
+        },
+        Err(ref err) if is_unauthorized(err) => {
+            Either::B(capability_from_modules(&modules_client))
+        }
+        Err(err) => Either::A(future::ok(EdgeCapability::Unknown(describe(&err)))),
+    })
+}
+
+fn capability_from_modules<C, T>(
+    client: &DeviceClient<C, T>,
+) -> impl Future<Item = EdgeCapability, Error = Error>
+where
+    C: 'static + ClientImpl,
+    T: 'static + TokenSource + Clone,
+    T::Error: Into<HttpError>,
+{
+    client.list_modules().then(|result| {
+        Ok(match result {
+            Ok(_) => EdgeCapability::Enabled,
+            Err(ref err) if is_unauthorized(err) => EdgeCapability::Disabled,
+            Err(err) => EdgeCapability::Unknown(describe(&err)),
+        })
+    })
+}
+
+fn is_unauthorized(error: &HubError) -> bool {
+    match error
+        .cause()
+        .and_then(|cause| cause.downcast_ref::<HttpError>())
+        .map(HttpError::kind)
+    {
+        Some(HttpErrorKind::ServiceError(status, _)) => {
+            *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN
+        }
+        _ => false,
+    }
+}
+
+fn describe(error: &Fail) -> String {
+    let mut description = error.to_string();
+    let mut fail = error;
+    while let Some(cause) = fail.cause() {
+        description.push_str(&format!(": {}", cause));
+        fail = cause;
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use hyper::{Body, Request, Response};
+    use url::Url;
+
+    use edgelet_core::crypto::MemoryKey;
+    use edgelet_http::client::Client;
+
+    use SasTokenSource;
+
+    fn response(status: StatusCode, body: &'static str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(body.into())
+            .expect("could not build hyper::Response")
+    }
+
+    /// Asks a hub that answers reading the device with `device` and listing
+    /// its modules with `modules`, returning the answer and how many requests
+    /// the hub got.
+    fn ask(
+        device: (StatusCode, &'static str),
+        modules: (StatusCode, &'static str),
+    ) -> (EdgeCapability, usize) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            requests_copy.fetch_add(1, Ordering::SeqCst);
+            let (status, body) = match req.uri().path() {
+                "/devices/d1" => device,
+                "/devices/d1/modules" => modules,
+                path => panic!("unexpected request for {}", path),
+            };
+            Ok(response(status, body))
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(
+            handler,
+            Some(token_source),
+            "2018-04-10",
+            Url::parse("http://localhost").unwrap(),
+        ).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let capability = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(edge_capability(&device_client))
+            .unwrap();
+        (capability, requests.load(Ordering::SeqCst))
+    }
+
+    const EDGE_DEVICE: &str = r#"{"deviceId":"d1","capabilities":{"iotEdge":true}}"#;
+    const PLAIN_DEVICE: &str = r#"{"deviceId":"d1","capabilities":{"iotEdge":false}}"#;
+
+    #[test]
+    fn edge_devices_are_enabled() {
+        let modules = (StatusCode::OK, "[]");
+        assert_eq!(
+            (EdgeCapability::Enabled, 1),
+            ask((StatusCode::OK, EDGE_DEVICE), modules)
+        );
+
+        // Devices that may not read the registry can still list modules.
+        assert_eq!(
+            (EdgeCapability::Enabled, 2),
+            ask((StatusCode::UNAUTHORIZED, ""), modules)
+        );
+        assert_eq!(
+            (EdgeCapability::Enabled, 2),
+            ask((StatusCode::OK, r#"{"deviceId":"d1"}"#), modules)
+        );
+    }
+
+    #[test]
+    fn plain_devices_are_disabled() {
+        let modules = (StatusCode::UNAUTHORIZED, "");
+        assert_eq!(
+            (EdgeCapability::Disabled, 1),
+            ask((StatusCode::OK, PLAIN_DEVICE), modules)
+        );
+        assert_eq!(
+            (EdgeCapability::Disabled, 2),
+            ask((StatusCode::FORBIDDEN, ""), modules)
+        );
+        assert_eq!(
+            (EdgeCapability::Disabled, 2),
+            ask((StatusCode::UNAUTHORIZED, ""), (StatusCode::FORBIDDEN, ""))
+        );
+    }
+
+    #[test]
+    fn other_answers_are_unknown() {
+        for &(device, modules) in &[
+            (
+                (StatusCode::SERVICE_UNAVAILABLE, ""),
+                (StatusCode::OK, "[]"),
+            ),
+            (
+                (StatusCode::UNAUTHORIZED, ""),
+                (StatusCode::INTERNAL_SERVER_ERROR, ""),
+            ),
+            ((StatusCode::UNAUTHORIZED, ""), (StatusCode::NOT_FOUND, "")),
+            ((StatusCode::OK, "not json"), (StatusCode::OK, "[]")),
+        ] {
+            match ask(device, modules).0 {
+                EdgeCapability::Unknown(reason) => assert!(!reason.is_empty()),
+                capability => panic!("unexpected capability {:?}", capability),
+            }
+        }
+    }
+}
@@ -5,7 +5,7 @@ use std::fmt::Display;
 
 use failure::{Backtrace, Context, Fail};
 
-use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind};
+use edgelet_core::{Error as CoreError, ErrorKind as CoreErrorKind, IdentityError};
 use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
 use edgelet_utils::Error as UtilsError;
 use iothubservice::error::{Error as HubServiceError, ErrorKind as HubServiceErrorKind};
@@ -33,6 +33,23 @@ pub enum ErrorKind {
     InvalidHubResponse,
     #[fail(display = "Generation Id was not provided")]
     MissingGenerationId,
+    #[fail(display = "Identity for module {} already exists", _0)]
+    IdentityConflict(String),
+    #[fail(display = "Identity for module {} does not exist", _0)]
+    IdentityNotFound(String),
+    #[fail(display = "Identity for module {} has another generation id", _0)]
+    StaleGenerationId(String),
+    #[fail(display = "Could not read identities from {}", _0)]
+    LoadIdentities(String),
+    #[fail(display = "Could not save identities to {}", _0)]
+    SaveIdentities(String),
+    #[fail(
+        display = "Device {} in IoT Hub {} is not an IoT Edge device. \
+                   Provision the device with the identity of an IoT Edge device instead.",
+        _0,
+        _1
+    )]
+    NotEdgeDevice(String, String),
 }
 
 impl Fail for Error {
@@ -61,6 +78,22 @@ impl Error {
     }
 }
 
+impl IdentityError for Error {
+    fn is_conflict(&self) -> bool {
+        match self.kind() {
+            ErrorKind::IdentityConflict(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        match self.kind() {
+            ErrorKind::StaleGenerationId(_) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
         Error {
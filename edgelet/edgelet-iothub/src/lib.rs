@@ -16,15 +16,13 @@ extern crate chrono;
 #[macro_use]
 extern crate failure;
 extern crate futures;
-#[cfg(test)]
 extern crate hyper;
 #[macro_use]
 extern crate percent_encoding;
+extern crate rand;
 #[macro_use]
 extern crate serde_derive;
-#[cfg(test)]
 extern crate serde_json;
-#[cfg(test)]
 extern crate tokio;
 #[cfg(test)]
 extern crate typed_headers;
@@ -32,35 +30,54 @@ extern crate url;
 
 extern crate edgelet_core;
 extern crate edgelet_http;
+#[cfg(test)]
+extern crate edgelet_test_utils;
 extern crate edgelet_utils;
 extern crate iothubservice;
 
+mod capability;
 mod error;
+mod local;
+mod retry;
 
 use std::convert::AsRef;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration as StdDuration, Instant};
 
 use chrono::{DateTime, Utc};
-use failure::ResultExt;
+use failure::{Fail, ResultExt};
 use futures::future::{self, Either};
 use futures::Future;
 use percent_encoding::{percent_encode, PATH_SEGMENT_ENCODE_SET};
 use url::form_urlencoded::Serializer as UrlSerializer;
 
 use edgelet_core::crypto::{KeyIdentity, KeyStore, Sign, Signature, SignatureAlgorithm};
-use edgelet_core::{AuthType, Identity, IdentityManager, IdentitySpec};
+use edgelet_core::{AuthType, Clock, Identity, IdentityManager, IdentitySpec, SystemClock};
 use edgelet_http::client::{ClientImpl, TokenSource};
 use iothubservice::{
-    AuthMechanism, AuthType as HubAuthType, DeviceClient, ErrorKind as HubErrorKind, Module,
-    SymmetricKey,
+    AuthMechanism, AuthType as HubAuthType, DeviceClient, Error as HubError,
+    ErrorKind as HubErrorKind, Module, SymmetricKey,
 };
 
+pub use capability::{edge_capability, EdgeCapability};
 pub use error::{Error, ErrorKind};
+pub use local::{LocalIdentity, LocalIdentityManager};
+pub use retry::RetryPolicy;
+
+use retry::retry;
 
 const KEY_PRIMARY: &str = "primary";
 const KEY_SECONDARY: &str = "secondary";
 
+/// How long before a SAS token expires it is replaced by default.
+pub const DEFAULT_TOKEN_RENEW_BEFORE_SECS: u64 = 300;
+
+/// How long a list of module identities is served without asking the hub
+/// by default.
+pub const DEFAULT_LIST_CACHE_TTL_SECS: u64 = 5;
+
 define_encode_set! {
     pub IOTHUB_ENCODE_SET = [PATH_SEGMENT_ENCODE_SET] | { '=' }
 }
@@ -118,8 +135,40 @@ where
 {
     key_store: K,
     client: DeviceClient<C, SasTokenSource<D>>,
+    list_cache: Mutex<ListCache>,
 }
 
+impl<K, C, D> State<K, C, D>
+where
+    K: KeyStore,
+    K::Key: AsRef<[u8]> + Clone,
+    C: ClientImpl,
+    D: 'static + Sign + Clone,
+{
+    fn invalidate_list(&self) {
+        let mut cache = self.list_cache.lock().unwrap();
+        cache.list = None;
+        cache.generation = cache.generation.wrapping_add(1);
+    }
+}
+
+/// The modules the hub listed last.
+#[derive(Default)]
+struct ListCache {
+    list: Option<CachedList>,
+    /// Bumped whenever the identities are changed, so that a list asked for
+    /// before a change isn't cached once the change is made.
+    generation: usize,
+}
+
+struct CachedList {
+    modules: Vec<Module>,
+    etag: Option<String>,
+    listed: Instant,
+}
+
+/// Signs SAS tokens for IoT Hub with the device key. A token is reused until
+/// it is within `renew_before` of expiring, and clones share the tokens.
 pub struct SasTokenSource<K>
 where
     K: Sign + Clone,
@@ -127,8 +176,14 @@ where
     hub_id: String,
     device_id: String,
     key: K,
+    renew_before: StdDuration,
+    cached: Arc<Mutex<Option<CachedToken>>>,
+    clock: Arc<Clock>,
 }
 
+/// A token and when it expires.
+type CachedToken = (DateTime<Utc>, String);
+
 impl<K> SasTokenSource<K>
 where
     K: Sign + Clone,
@@ -138,17 +193,25 @@ where
             hub_id,
             device_id,
             key,
+            renew_before: StdDuration::from_secs(DEFAULT_TOKEN_RENEW_BEFORE_SECS),
+            cached: Arc::new(Mutex::new(None)),
+            clock: Arc::new(SystemClock),
         }
     }
-}
 
-impl<K> TokenSource for SasTokenSource<K>
-where
-    K: Sign + Clone,
-{
-    type Error = Error;
+    /// Signs a new token once the last one expires within `renew_before`.
+    pub fn with_renew_before(mut self, renew_before: StdDuration) -> Self {
+        self.renew_before = renew_before;
+        self
+    }
 
-    fn get(&self, expiry: &DateTime<Utc>) -> Result<String, Error> {
+    /// Sets the clock the time left on cached tokens is checked against.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn sign(&self, expiry: &DateTime<Utc>) -> Result<String, Error> {
         let expiry = expiry.timestamp().to_string();
         let audience = format!("{}/devices/{}", self.hub_id, self.device_id);
 
@@ -171,6 +234,34 @@ where
     }
 }
 
+impl<K> TokenSource for SasTokenSource<K>
+where
+    K: Sign + Clone,
+{
+    type Error = Error;
+
+    fn get(&self, expiry: &DateTime<Utc>) -> Result<String, Error> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((cached_expiry, ref token)) = *cached {
+            // An expired token has no time left at all.
+            let left = cached_expiry
+                .signed_duration_since(self.clock.now())
+                .to_std();
+            if left.map(|left| left > self.renew_before).unwrap_or(false) {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.sign(expiry)?;
+        *cached = Some((*expiry, token.clone()));
+        Ok(token)
+    }
+
+    fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
 impl<K> Clone for SasTokenSource<K>
 where
     K: Sign + Clone,
@@ -180,6 +271,9 @@ where
             hub_id: self.hub_id.clone(),
             device_id: self.device_id.clone(),
             key: self.key.clone(),
+            renew_before: self.renew_before,
+            cached: self.cached.clone(),
+            clock: self.clock.clone(),
         }
     }
 }
@@ -192,6 +286,9 @@ where
     D: 'static + Sign + Clone,
 {
     state: Arc<State<K, C, D>>,
+    retry_policy: RetryPolicy,
+    list_cache_ttl: StdDuration,
+    clock: Arc<Clock>,
     phantom: PhantomData<D>,
 }
 
@@ -204,11 +301,41 @@ where
 {
     pub fn new(key_store: K, client: DeviceClient<C, SasTokenSource<D>>) -> Self {
         HubIdentityManager {
-            state: Arc::new(State { key_store, client }),
+            state: Arc::new(State {
+                key_store,
+                client,
+                list_cache: Mutex::new(ListCache::default()),
+            }),
+            retry_policy: RetryPolicy::default(),
+            list_cache_ttl: StdDuration::from_secs(DEFAULT_LIST_CACHE_TTL_SECS),
+            clock: Arc::new(SystemClock),
             phantom: PhantomData,
         }
     }
 
+    /// Sets how calls to IoT Hub that fail transiently are retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets how long the modules the hub listed are listed again without
+    /// asking it. Once that is over the hub is still asked to only send the
+    /// list if it changed. A zero `ttl` always asks the hub.
+    ///
+    /// Creating, updating, deleting or rotating an identity through this
+    /// manager or a clone of it forgets the list.
+    pub fn with_list_cache_ttl(mut self, ttl: StdDuration) -> Self {
+        self.list_cache_ttl = ttl;
+        self
+    }
+
+    /// Sets the clock the age of the cached list is measured with.
+    pub fn with_clock(mut self, clock: Arc<Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     fn get_key_pair(&self, id: &str, generation_id: &str) -> Result<(K::Key, K::Key), Error> {
         self.state
             .key_store
@@ -227,6 +354,155 @@ where
     }
 }
 
+// Calls to the hub, retried as the retry policy allows.
+impl<K, C, D> HubIdentityManager<K, C, D>
+where
+    K: 'static + KeyStore + Send + Sync,
+    K::Key: AsRef<[u8]> + Clone + Send,
+    C: 'static + ClientImpl,
+    D: 'static + Sign + Clone + Send + Sync,
+{
+    /// Creating a module isn't idempotent. An attempt that failed may have
+    /// created the module anyway, so when a retry finds that the module
+    /// already exists, the module is fetched and the create succeeds.
+    fn create_module(
+        &self,
+        id: &IdentitySpec,
+        auth: AuthMechanism,
+    ) -> impl Future<Item = Module, Error = HubError> + Send {
+        let state = self.state.clone();
+        let module_id = id.module_id().to_string();
+        let managed_by = id.managed_by().map(ToString::to_string);
+        let attempts = AtomicUsize::new(0);
+        self.changing_identities(retry(self.retry_policy.clone(), move || {
+            let retried = attempts.fetch_add(1, Ordering::SeqCst) > 0;
+            let (state_copy, module_id_copy) = (state.clone(), module_id.clone());
+            state
+                .client
+                .create_module(
+                    &module_id,
+                    Some(auth.clone()),
+                    managed_by.as_ref().map(AsRef::as_ref),
+                ).or_else(move |err| {
+                    if retried && *err.kind() == HubErrorKind::ModuleAlreadyExists {
+                        Either::A(state_copy.client.get_module_by_id(&module_id_copy))
+                    } else {
+                        Either::B(future::err(err))
+                    }
+                })
+        }))
+    }
+
+    fn update_module(
+        &self,
+        id: &IdentitySpec,
+        auth: AuthMechanism,
+    ) -> impl Future<Item = Module, Error = HubError> + Send {
+        let state = self.state.clone();
+        let module_id = id.module_id().to_string();
+        let managed_by = id.managed_by().map(ToString::to_string);
+        self.changing_identities(retry(self.retry_policy.clone(), move || {
+            state.client.update_module(
+                &module_id,
+                Some(auth.clone()),
+                managed_by.as_ref().map(AsRef::as_ref),
+            )
+        }))
+    }
+
+    /// Lists the modules from the cache while it is fresh, and otherwise
+    /// asks the hub for them unless they haven't changed since they were
+    /// cached.
+    fn list_modules(&self) -> impl Future<Item = Vec<Module>, Error = HubError> + Send {
+        let (generation, cached) = {
+            let cache = self.state.list_cache.lock().unwrap();
+            if let Some(ref list) = cache.list {
+                if self.clock.instant().duration_since(list.listed) < self.list_cache_ttl {
+                    return Either::A(future::ok(list.modules.clone()));
+                }
+            }
+            let cached = cache.list.as_ref().and_then(|list| {
+                list.etag
+                    .as_ref()
+                    .map(|etag| (etag.clone(), list.modules.clone()))
+            });
+            (cache.generation, cached)
+        };
+
+        let state = self.state.clone();
+        let clock = self.clock.clone();
+        let etag = cached.as_ref().map(|&(ref etag, _)| etag.clone());
+        let listed = retry(self.retry_policy.clone(), move || {
+            state
+                .client
+                .list_modules_if_none_match(etag.as_ref().map(AsRef::as_ref))
+        });
+
+        let state = self.state.clone();
+        Either::B(listed.and_then(move |listed| {
+            let (modules, etag) = match (listed, cached) {
+                (Some(listed), _) => listed,
+                (None, Some((etag, modules))) => (modules, Some(etag)),
+                // The hub can only answer that nothing changed when it was
+                // asked with the entity tag of a cached list.
+                (None, None) => return Err(HubError::from(HubErrorKind::EmptyResponse)),
+            };
+            let mut cache = state.list_cache.lock().unwrap();
+            if cache.generation == generation {
+                cache.list = Some(CachedList {
+                    modules: modules.clone(),
+                    etag,
+                    listed: clock.instant(),
+                });
+            }
+            Ok(modules)
+        }))
+    }
+
+    fn get_module_by_id(
+        &self,
+        id: &IdentitySpec,
+    ) -> impl Future<Item = Module, Error = HubError> + Send {
+        let state = self.state.clone();
+        let module_id = id.module_id().to_string();
+        retry(self.retry_policy.clone(), move || {
+            state.client.get_module_by_id(&module_id)
+        })
+    }
+
+    /// Deletes the module, only if its entity tag is still `etag` if given.
+    fn delete_module(
+        &self,
+        id: &IdentitySpec,
+        etag: Option<String>,
+    ) -> impl Future<Item = (), Error = HubError> + Send {
+        let state = self.state.clone();
+        let module_id = id.module_id().to_string();
+        self.changing_identities(retry(self.retry_policy.clone(), move || match etag {
+            Some(ref etag) => Either::A(state.client.delete_module_if_match(&module_id, etag)),
+            None => Either::B(state.client.delete_module(&module_id)),
+        }))
+    }
+
+    /// Forgets the cached list of modules both before and after `change`,
+    /// which may have changed the identities in the hub even if it failed.
+    fn changing_identities<F>(
+        &self,
+        change: F,
+    ) -> impl Future<Item = F::Item, Error = HubError> + Send
+    where
+        F: Future<Error = HubError> + Send,
+        F::Item: Send,
+    {
+        self.state.invalidate_list();
+        let state = self.state.clone();
+        change.then(move |result| {
+            state.invalidate_list();
+            result
+        })
+    }
+}
+
 fn build_key_name(key_name: &str, generation_id: &str) -> String {
     format!("{}{}", key_name, generation_id)
 }
@@ -241,6 +517,9 @@ where
     fn clone(&self) -> Self {
         HubIdentityManager {
             state: self.state.clone(),
+            retry_policy: self.retry_policy.clone(),
+            list_cache_ttl: self.list_cache_ttl,
+            clock: self.clock.clone(),
             phantom: PhantomData,
         }
     }
@@ -260,6 +539,7 @@ where
     type ListFuture = Box<Future<Item = Vec<Self::Identity>, Error = Self::Error> + Send>;
     type GetFuture = Box<Future<Item = Option<Self::Identity>, Error = Self::Error> + Send>;
     type DeleteFuture = Box<Future<Item = (), Error = Self::Error> + Send>;
+    type RotateFuture = Box<Future<Item = Self::Identity, Error = Self::Error> + Send>;
 
     fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture {
         // This code first creates a module in the hub with the auth type
@@ -268,14 +548,16 @@ where
         // derive the keys for the module which we then proceed to update in
         // the hub.
         let (idman_copy1, idman_copy2) = (self.clone(), self.clone());
+        let module_id = id.module_id().to_string();
         Box::new(
-            self.state
-                .client
-                .create_module(
-                    id.module_id(),
-                    Some(AuthMechanism::default().with_type(HubAuthType::None)),
-                    id.managed_by(),
-                ).map_err(Error::from)
+            self.create_module(&id, AuthMechanism::default().with_type(HubAuthType::None))
+                .map_err(move |err| {
+                    if *err.kind() == HubErrorKind::ModuleAlreadyExists {
+                        Error::from(err.context(ErrorKind::IdentityConflict(module_id)))
+                    } else {
+                        Error::from(err)
+                    }
+                })
                 .and_then(move |module| {
                     if let (Some(module_id), Some(generation_id)) =
                         (module.module_id(), module.generation_id())
@@ -294,9 +576,7 @@ where
                         );
 
                     idman_copy2
-                        .state
-                        .client
-                        .update_module(id.module_id(), Some(auth), id.managed_by())
+                        .update_module(&id, auth)
                         .map_err(Error::from)
                         .map(HubIdentity::new)
                 }),
@@ -316,9 +596,7 @@ where
                         );
 
                     Either::A(
-                        self.state
-                            .client
-                            .update_module(id.module_id(), Some(auth), id.managed_by())
+                        self.update_module(&id, auth)
                             .map_err(Error::from)
                             .map(HubIdentity::new),
                     )
@@ -335,9 +613,7 @@ where
 
     fn list(&self) -> Self::ListFuture {
         Box::new(
-            self.state
-                .client
-                .list_modules()
+            self.list_modules()
                 .map_err(Error::from)
                 .map(|modules| modules.into_iter().map(HubIdentity::new).collect()),
         )
@@ -345,9 +621,7 @@ where
 
     fn get(&self, id: IdentitySpec) -> Self::GetFuture {
         Box::new(
-            self.state
-                .client
-                .get_module_by_id(id.module_id())
+            self.get_module_by_id(&id)
                 .map(Some)
                 .then(|result| {
                     result.or_else(|err| {
@@ -363,11 +637,69 @@ where
     }
 
     fn delete(&mut self, id: IdentitySpec) -> Self::DeleteFuture {
+        // The generation id is checked, and the module is then deleted on
+        // condition that its etag is still the one it had, so that a module
+        // created again in between is left alone.
+        let generation_id = id.generation_id().map(ToString::to_string);
+        let result = match generation_id {
+            Some(generation_id) => {
+                let idman = self.clone();
+                Either::A(
+                    self.get_module_by_id(&id)
+                        .map_err(Error::from)
+                        .and_then(move |module| {
+                            if module.generation_id() == Some(generation_id.as_str()) {
+                                let module_id = id.module_id().to_string();
+                                let etag = module.etag().map(ToString::to_string);
+                                Either::A(idman.delete_module(&id, etag).map_err(move |err| {
+                                    if *err.kind() == HubErrorKind::ModuleChanged {
+                                        Error::from(ErrorKind::StaleGenerationId(module_id))
+                                    } else {
+                                        Error::from(err)
+                                    }
+                                }))
+                            } else {
+                                Either::B(future::err(Error::from(
+                                    ErrorKind::StaleGenerationId(id.module_id().to_string()),
+                                )))
+                            }
+                        }),
+                )
+            }
+            None => Either::B(self.delete_module(&id, None).map_err(Error::from)),
+        };
+
+        Box::new(result)
+    }
+
+    fn rotate_keys(&mut self, id: IdentitySpec) -> Self::RotateFuture {
+        // Module keys are derived from the generation id, and the hub only
+        // hands out a new generation id when a module is created. So the
+        // module is deleted and created again, which derives a new pair of
+        // keys for it. Whoever managed the module before keeps managing it.
+        let idman = self.clone();
         Box::new(
-            self.state
-                .client
-                .delete_module(id.module_id())
-                .map_err(Error::from),
+            self.get_module_by_id(&id)
+                .map_err(Error::from)
+                .and_then(move |module| {
+                    let managed_by = id
+                        .managed_by()
+                        .or_else(|| module.managed_by())
+                        .map(ToString::to_string);
+                    let spec = IdentitySpec::new(id.module_id());
+                    let spec = match managed_by {
+                        Some(managed_by) => spec.with_managed_by(managed_by),
+                        None => spec,
+                    };
+
+                    idman
+                        .delete_module(&spec, None)
+                        .map_err(Error::from)
+                        .and_then(move |_| {
+                            let mut idman = idman;
+                            idman.create(spec)
+                        })
+                }),
         )
     }
 }
@@ -376,6 +708,9 @@ where
 mod tests {
     use super::*;
 
+    use std::sync::Mutex;
+    use std::time::Duration;
+
     use bytes::Bytes;
     use chrono::TimeZone;
     use futures::Stream;
@@ -383,8 +718,10 @@ mod tests {
     use typed_headers::{mime, ContentType, HeaderMapExt};
     use url::Url;
 
-    use edgelet_core::crypto::{MemoryKey, MemoryKeyStore};
+    use edgelet_core::crypto::{DerivedKeyStore, MemoryKey, MemoryKeyStore};
+    use edgelet_core::{Error as CoreError, IdentityError};
     use edgelet_http::client::Client;
+    use edgelet_test_utils::TestClock;
 
     #[test]
     fn hub_identity_empty_prop() {
@@ -846,6 +1183,744 @@ mod tests {
             .unwrap();
     }
 
+    fn conditional_delete(
+        current: &'static str,
+        generation_id: &str,
+        changed: bool,
+    ) -> (Result<(), Error>, bool) {
+        let key_store = MemoryKeyStore::new();
+
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let deleted = Arc::new(Mutex::new(false));
+        let deleted_copy = deleted.clone();
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.uri().path(), "/devices/d1/modules/m1");
+            let response = match *req.method() {
+                Method::GET => {
+                    let module = Module::default()
+                        .with_module_id("m1".to_string())
+                        .with_generation_id(current.to_string())
+                        .with_etag("\"e1\"".to_string());
+                    Response::new(serde_json::to_string(&module).unwrap().into())
+                }
+                Method::DELETE => {
+                    assert_eq!("\"e1\"", req.headers()[hyper::header::IF_MATCH]);
+                    if changed {
+                        status_response(StatusCode::PRECONDITION_FAILED)
+                    } else {
+                        *deleted_copy.lock().unwrap() = true;
+                        Response::new(Body::empty())
+                    }
+                }
+                _ => panic!("unexpected request {}", req.method()),
+            };
+            Ok(response)
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager = HubIdentityManager::new(key_store, device_client);
+        let task = identity_manager
+            .delete(IdentitySpec::new("m1").with_generation_id(generation_id.to_string()));
+
+        let result = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task);
+        let deleted = *deleted.lock().unwrap();
+        (result, deleted)
+    }
+
+    #[test]
+    fn delete_succeeds_when_generation_matches() {
+        let (result, deleted) = conditional_delete("g1", "g1", false);
+
+        result.unwrap();
+        assert!(deleted);
+    }
+
+    #[test]
+    fn delete_fails_when_generation_differs() {
+        let (result, deleted) = conditional_delete("g2", "g1", false);
+
+        let err = result.unwrap_err();
+        assert!(err.is_stale());
+        assert!(!err.is_conflict());
+        match *err.kind() {
+            ErrorKind::StaleGenerationId(ref module_id) => assert_eq!("m1", module_id),
+            ref kind => panic!("Expected a stale generation id, got {:?}", kind),
+        }
+        assert!(!deleted);
+    }
+
+    #[test]
+    fn delete_fails_when_module_changes_after_generation_is_checked() {
+        let (result, deleted) = conditional_delete("g1", "g1", true);
+
+        let err = result.unwrap_err();
+        assert!(err.is_stale());
+        match *err.kind() {
+            ErrorKind::StaleGenerationId(ref module_id) => assert_eq!("m1", module_id),
+            ref kind => panic!("Expected a stale generation id, got {:?}", kind),
+        }
+        assert!(!deleted);
+    }
+
+    #[test]
+    fn sync_creates_and_removes_modules() {
+        let mut key_store = MemoryKeyStore::new();
+        key_store.insert(
+            &KeyIdentity::Module("m3".to_string()),
+            &format!("{}{}", KEY_PRIMARY, "g3"),
+            MemoryKey::new("pkey"),
+        );
+        key_store.insert(
+            &KeyIdentity::Module("m3".to_string()),
+            &format!("{}{}", KEY_SECONDARY, "g3"),
+            MemoryKey::new("skey"),
+        );
+
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            requests_copy
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", req.method(), req.uri().path()));
+
+            let response: Box<Future<Item = Response<Body>, Error = hyper::Error> + Send> =
+                match *req.method() {
+                    Method::GET => {
+                        let modules = vec![
+                            Module::default()
+                                .with_module_id("m1".to_string())
+                                .with_managed_by("iotedge".to_string()),
+                            Module::default()
+                                .with_module_id("m2".to_string())
+                                .with_managed_by("someone else".to_string()),
+                        ];
+                        Box::new(future::ok(Response::new(
+                            serde_json::to_string(&modules).unwrap().into(),
+                        )))
+                    }
+                    Method::PUT => Box::new(req.into_body().concat2().map(|body| {
+                        let module = serde_json::from_slice::<Module>(&body).unwrap();
+                        assert_eq!(Some("iotedge"), module.managed_by());
+                        Response::new(
+                            serde_json::to_string(&module.with_generation_id("g3".to_string()))
+                                .unwrap()
+                                .into(),
+                        )
+                    })),
+                    _ => Box::new(future::ok(Response::new(Body::empty()))),
+                };
+            response
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let identity_manager = HubIdentityManager::new(key_store, device_client);
+        let task = identity_manager.sync(vec![IdentitySpec::new("m2"), IdentitySpec::new("m3")]);
+
+        let report = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+
+        assert_eq!(&["m3".to_string()], report.created());
+        assert_eq!(&["m1".to_string()], report.removed());
+        assert_eq!(&["m2".to_string()], report.skipped());
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(
+            vec![
+                "DELETE /devices/d1/modules/m1",
+                "GET /devices/d1/modules",
+                "PUT /devices/d1/modules/m3",
+                "PUT /devices/d1/modules/m3",
+            ],
+            requests
+        );
+    }
+
+    #[test]
+    fn rotate_keys_recreates_module_with_new_generation_id() {
+        let key_store = DerivedKeyStore::new(MemoryKey::new("root"));
+        let old_keys = (
+            key_store
+                .get(&KeyIdentity::Module("m1".to_string()), "primaryg1")
+                .unwrap(),
+            key_store
+                .get(&KeyIdentity::Module("m1".to_string()), "secondaryg1")
+                .unwrap(),
+        );
+        let new_keys = (
+            key_store
+                .get(&KeyIdentity::Module("m1".to_string()), "primaryg2")
+                .unwrap(),
+            key_store
+                .get(&KeyIdentity::Module("m1".to_string()), "secondaryg2")
+                .unwrap(),
+        );
+
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        // The hub mock keeps a single module, m1, and bumps its generation id
+        // every time it is created.
+        let hub = Arc::new(Mutex::new((Some(1), vec![])));
+        let hub_copy = hub.clone();
+        let handler = move |req: Request<Body>| {
+            let hub = hub_copy.clone();
+            hub.lock()
+                .unwrap()
+                .1
+                .push(format!("{} {}", req.method(), req.uri().path()));
+            assert_eq!(req.uri().path(), "/devices/d1/modules/m1");
+
+            let response: Box<Future<Item = Response<Body>, Error = hyper::Error> + Send> =
+                match *req.method() {
+                    Method::GET => {
+                        let module = Module::default()
+                            .with_module_id("m1".to_string())
+                            .with_managed_by("someone else".to_string())
+                            .with_generation_id("g1".to_string());
+                        Box::new(future::ok(Response::new(
+                            serde_json::to_string(&module).unwrap().into(),
+                        )))
+                    }
+                    Method::DELETE => {
+                        hub.lock().unwrap().0 = None;
+                        Box::new(future::ok(Response::new(Body::empty())))
+                    }
+                    Method::PUT => {
+                        let is_update = req.headers().get(hyper::header::IF_MATCH).is_some();
+                        Box::new(req.into_body().concat2().map(move |body| {
+                            let module = serde_json::from_slice::<Module>(&body).unwrap();
+                            assert_eq!(Some("someone else"), module.managed_by());
+                            let mut hub = hub.lock().unwrap();
+                            let generation = if is_update {
+                                hub.0.unwrap()
+                            } else {
+                                assert_eq!(None, hub.0);
+                                2
+                            };
+                            hub.0 = Some(generation);
+                            Response::new(
+                                serde_json::to_string(
+                                    &module.with_generation_id(format!("g{}", generation)),
+                                ).unwrap()
+                                .into(),
+                            )
+                        }))
+                    }
+                    _ => panic!("unexpected request {}", req.method()),
+                };
+            response
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager = HubIdentityManager::new(key_store, device_client);
+        let task = identity_manager.rotate_keys(IdentitySpec::new("m1"));
+
+        let hub_identity = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+
+        assert_eq!("g2", hub_identity.generation_id());
+        assert_eq!("someone else", hub_identity.managed_by());
+        let symmetric_key = hub_identity
+            .hub_module()
+            .authentication()
+            .and_then(AuthMechanism::symmetric_key)
+            .unwrap();
+        assert_eq!(
+            Some(base64::encode(new_keys.0.as_ref()).as_str()),
+            symmetric_key.primary_key()
+        );
+        assert_eq!(
+            Some(base64::encode(new_keys.1.as_ref()).as_str()),
+            symmetric_key.secondary_key()
+        );
+        assert_ne!(old_keys.0.as_ref(), new_keys.0.as_ref());
+        assert_ne!(old_keys.1.as_ref(), new_keys.1.as_ref());
+        assert_eq!(
+            vec![
+                "GET /devices/d1/modules/m1",
+                "DELETE /devices/d1/modules/m1",
+                "PUT /devices/d1/modules/m1",
+                "PUT /devices/d1/modules/m1",
+            ],
+            hub.lock().unwrap().1
+        );
+    }
+
+    #[test]
+    fn rotate_keys_fails_for_missing_module() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::GET);
+            Ok(status_response(StatusCode::NOT_FOUND))
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager =
+            HubIdentityManager::new(MemoryKeyStore::new(), device_client);
+        let task = identity_manager.rotate_keys(IdentitySpec::new("m1"));
+
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        let hub_error = err
+            .cause()
+            .and_then(|cause| cause.downcast_ref::<HubError>())
+            .unwrap();
+        assert_eq!(&HubErrorKind::ModuleNotFound, hub_error.kind());
+    }
+
+    #[test]
+    fn create_fails_with_conflict_for_existing_module() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::PUT);
+            assert_eq!(None, req.headers().get(hyper::header::IF_MATCH));
+            Ok(status_response(StatusCode::CONFLICT))
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager =
+            HubIdentityManager::new(MemoryKeyStore::new(), device_client);
+        let task = identity_manager.create(IdentitySpec::new("m1"));
+
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert!(err.is_conflict());
+        match err.kind() {
+            ErrorKind::IdentityConflict(module_id) => assert_eq!("m1", module_id),
+            kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn create_uses_the_module_an_earlier_attempt_created() {
+        let mut key_store = MemoryKeyStore::new();
+        key_store.insert(
+            &KeyIdentity::Module("m1".to_string()),
+            &format!("{}{}", KEY_PRIMARY, "g1"),
+            MemoryKey::new("pkey"),
+        );
+        key_store.insert(
+            &KeyIdentity::Module("m1".to_string()),
+            &format!("{}{}", KEY_SECONDARY, "g1"),
+            MemoryKey::new("skey"),
+        );
+
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let creates = Arc::new(AtomicUsize::new(0));
+        let creates_copy = creates.clone();
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.uri().path(), "/devices/d1/modules/m1");
+
+            // The first attempt to create the module fails after the hub
+            // created it, so the retry finds it already there.
+            let is_update = req.headers().get(hyper::header::IF_MATCH).is_some();
+            if req.method() == &Method::PUT && !is_update {
+                return if creates_copy.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Ok(status_response(StatusCode::SERVICE_UNAVAILABLE))
+                } else {
+                    Ok(status_response(StatusCode::CONFLICT))
+                };
+            }
+
+            let module = Module::default()
+                .with_device_id("d1".to_string())
+                .with_module_id("m1".to_string())
+                .with_generation_id("g1".to_string());
+            let mut response = Response::new(serde_json::to_string(&module).unwrap().into());
+            response
+                .headers_mut()
+                .typed_insert(&ContentType(mime::APPLICATION_JSON));
+            Ok(response)
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager =
+            HubIdentityManager::new(key_store, device_client).with_retry_policy(fast_retries());
+        let task = identity_manager.create(IdentitySpec::new("m1"));
+
+        let identity = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        assert_eq!("m1", identity.module_id());
+        assert_eq!("g1", identity.generation_id());
+        assert_eq!(2, creates.load(Ordering::SeqCst));
+    }
+
+    fn status_response(status: StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::empty())
+            .expect("could not build hyper::Response")
+    }
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy::default()
+            .with_initial_delay(Duration::from_millis(1))
+            .with_max_elapsed(Duration::from_secs(5))
+    }
+
+    /// A device key that counts the SAS tokens signed with it.
+    #[derive(Clone)]
+    struct CountingKey {
+        key: MemoryKey,
+        signed: Arc<AtomicUsize>,
+    }
+
+    impl Sign for CountingKey {
+        type Signature = <MemoryKey as Sign>::Signature;
+
+        fn sign(
+            &self,
+            signature_algorithm: SignatureAlgorithm,
+            data: &[u8],
+        ) -> Result<Self::Signature, CoreError> {
+            self.signed.fetch_add(1, Ordering::SeqCst);
+            self.key.sign(signature_algorithm, data)
+        }
+    }
+
+    #[test]
+    fn list_retries_transient_failures() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::GET);
+            assert_eq!(req.uri().path(), "/devices/d1/modules");
+
+            match requests_copy.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(status_response(StatusCode::TOO_MANY_REQUESTS)),
+                1 => Ok(status_response(StatusCode::SERVICE_UNAVAILABLE)),
+                _ => Ok(Response::new("[]".into())),
+            }
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_retry_policy(fast_retries());
+        let task = identity_manager.list();
+
+        let modules = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        assert!(modules.is_empty());
+        assert_eq!(3, requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn unauthorized_request_is_retried_with_a_new_token() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            assert!(req.headers().get(hyper::header::AUTHORIZATION).is_some());
+
+            if requests_copy.fetch_add(1, Ordering::SeqCst) == 0 {
+                Ok(status_response(StatusCode::UNAUTHORIZED))
+            } else {
+                Ok(Response::new(Body::empty()))
+            }
+        };
+        let signed = Arc::new(AtomicUsize::new(0));
+        let key = CountingKey {
+            key: MemoryKey::new("device"),
+            signed: signed.clone(),
+        };
+        let token_source = SasTokenSource::new("hub".to_string(), "device".to_string(), key);
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_retry_policy(fast_retries());
+        let task = identity_manager.delete(IdentitySpec::new("m1"));
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+        assert_eq!(2, requests.load(Ordering::SeqCst));
+        assert_eq!(2, signed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn unauthorized_request_is_retried_once() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        let handler = move |_req: Request<Body>| {
+            requests_copy.fetch_add(1, Ordering::SeqCst);
+            Ok(status_response(StatusCode::UNAUTHORIZED))
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_retry_policy(fast_retries());
+        let task = identity_manager.list();
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert_eq!(2, requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn forbidden_and_not_found_are_not_retried() {
+        for &status in &[StatusCode::FORBIDDEN, StatusCode::NOT_FOUND] {
+            let api_version = "2018-04-10";
+            let host_name = Url::parse("http://localhost").unwrap();
+
+            let requests = Arc::new(AtomicUsize::new(0));
+            let requests_copy = requests.clone();
+            let handler = move |req: Request<Body>| {
+                assert_eq!(req.method(), &Method::DELETE);
+                requests_copy.fetch_add(1, Ordering::SeqCst);
+                Ok(status_response(status))
+            };
+            let token_source = SasTokenSource::new(
+                "hub".to_string(),
+                "device".to_string(),
+                MemoryKey::new("device"),
+            );
+            let client =
+                Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+            let device_client = DeviceClient::new(client, "d1").unwrap();
+
+            let mut identity_manager =
+                HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+                    .with_retry_policy(fast_retries());
+            let task = identity_manager.delete(IdentitySpec::new("m1"));
+
+            tokio::runtime::current_thread::Runtime::new()
+                .unwrap()
+                .block_on(task)
+                .unwrap_err();
+            assert_eq!(1, requests.load(Ordering::SeqCst), "{}", status);
+        }
+    }
+
+    #[test]
+    fn list_is_cached_until_the_ttl_expires_and_then_revalidated() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::GET);
+            let etag = req
+                .headers()
+                .get(hyper::header::IF_NONE_MATCH)
+                .map(|etag| etag.to_str().unwrap().to_string());
+            let response = if etag.is_some() {
+                status_response(StatusCode::NOT_MODIFIED)
+            } else {
+                let modules = vec![Module::default().with_module_id("m1".to_string())];
+                Response::builder()
+                    .header(hyper::header::ETAG, "\"e1\"")
+                    .body(serde_json::to_string(&modules).unwrap().into())
+                    .unwrap()
+            };
+            requests_copy.lock().unwrap().push(etag);
+            Ok(response)
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let clock = TestClock::default();
+        let identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_list_cache_ttl(Duration::from_secs(5))
+            .with_clock(Arc::new(clock.clone()));
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let module_ids = |identities: Vec<HubIdentity>| {
+            identities
+                .iter()
+                .map(|identity| identity.module_id().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let listed = runtime.block_on(identity_manager.list()).unwrap();
+        assert_eq!(vec!["m1"], module_ids(listed));
+        clock.advance(Duration::from_secs(4));
+        let listed = runtime.block_on(identity_manager.clone().list()).unwrap();
+        assert_eq!(vec!["m1"], module_ids(listed));
+        assert_eq!(vec![None], *requests.lock().unwrap());
+
+        // Once the list is stale the hub answers that it hasn't changed.
+        clock.advance(Duration::from_secs(2));
+        let listed = runtime.block_on(identity_manager.list()).unwrap();
+        assert_eq!(vec!["m1"], module_ids(listed));
+        let listed = runtime.block_on(identity_manager.list()).unwrap();
+        assert_eq!(vec!["m1"], module_ids(listed));
+        assert_eq!(
+            vec![None, Some("\"e1\"".to_string())],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn changing_identities_forgets_the_cached_list() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let requests_copy = requests.clone();
+        let handler = move |req: Request<Body>| {
+            assert_eq!(None, req.headers().get(hyper::header::IF_NONE_MATCH));
+            let mut requests = requests_copy.lock().unwrap();
+            requests.push(req.method().clone());
+            let response = match *req.method() {
+                Method::GET => {
+                    let modules = (0..requests.len())
+                        .map(|i| Module::default().with_module_id(format!("m{}", i)))
+                        .collect::<Vec<_>>();
+                    Response::new(serde_json::to_string(&modules).unwrap().into())
+                }
+                _ => Response::new(Body::empty()),
+            };
+            Ok(response)
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_clock(Arc::new(TestClock::default()));
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        assert_eq!(1, runtime.block_on(identity_manager.list()).unwrap().len());
+        assert_eq!(1, runtime.block_on(identity_manager.list()).unwrap().len());
+
+        runtime
+            .block_on(identity_manager.clone().delete(IdentitySpec::new("m0")))
+            .unwrap();
+        assert_eq!(3, runtime.block_on(identity_manager.list()).unwrap().len());
+        assert_eq!(
+            vec![Method::GET, Method::DELETE, Method::GET],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn failed_list_is_not_cached() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests_copy = requests.clone();
+        let handler = move |_req: Request<Body>| {
+            if requests_copy.fetch_add(1, Ordering::SeqCst) == 0 {
+                Ok(status_response(StatusCode::FORBIDDEN))
+            } else {
+                Ok(Response::new("[]".into()))
+            }
+        };
+        let token_source = SasTokenSource::new(
+            "hub".to_string(),
+            "device".to_string(),
+            MemoryKey::new("device"),
+        );
+        let client = Client::new(handler, Some(token_source), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let identity_manager = HubIdentityManager::new(MemoryKeyStore::new(), device_client)
+            .with_clock(Arc::new(TestClock::default()));
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        runtime.block_on(identity_manager.list()).unwrap_err();
+        assert!(runtime.block_on(identity_manager.list()).unwrap().is_empty());
+        assert!(runtime.block_on(identity_manager.list()).unwrap().is_empty());
+        assert_eq!(2, requests.load(Ordering::SeqCst));
+    }
+
     #[test]
     fn token_source_success() {
         // arrange
@@ -887,4 +1962,68 @@ mod tests {
         );
         assert_eq!(expected, token);
     }
+
+    fn expiry_claim(token: &str) -> i64 {
+        url::form_urlencoded::parse(token.as_bytes())
+            .find(|(key, _)| key == "se")
+            .map(|(_, value)| value.parse().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn token_source_renews_tokens_about_to_expire() {
+        let signed = Arc::new(AtomicUsize::new(0));
+        let key = CountingKey {
+            key: MemoryKey::new("device"),
+            signed: signed.clone(),
+        };
+        let clock = TestClock::new(Utc.ymd(2018, 10, 15).and_hms(12, 0, 0));
+        let token_source = SasTokenSource::new("hub".to_string(), "device".to_string(), key)
+            .with_renew_before(Duration::from_secs(600))
+            .with_clock(Arc::new(clock.clone()));
+        let ttl = chrono::Duration::hours(1);
+
+        let first_expiry = clock.now() + ttl;
+        let first = token_source.get(&first_expiry).unwrap();
+        assert_eq!(first_expiry.timestamp(), expiry_claim(&first));
+
+        // With more than 10 minutes left the token is reused, even though a
+        // later expiry is asked for.
+        clock.advance(Duration::from_secs(49 * 60));
+        assert_eq!(first, token_source.get(&(clock.now() + ttl)).unwrap());
+        assert_eq!(1, signed.load(Ordering::SeqCst));
+
+        // Within 10 minutes of expiring, and after, it is replaced.
+        clock.advance(Duration::from_secs(2 * 60));
+        let second = token_source.get(&(clock.now() + ttl)).unwrap();
+        assert_eq!((clock.now() + ttl).timestamp(), expiry_claim(&second));
+        assert_eq!(2, signed.load(Ordering::SeqCst));
+
+        clock.advance(Duration::from_secs(2 * 3600));
+        let third = token_source.get(&(clock.now() + ttl)).unwrap();
+        assert_eq!((clock.now() + ttl).timestamp(), expiry_claim(&third));
+        assert_eq!(3, signed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn token_source_clones_share_tokens_until_invalidated() {
+        let signed = Arc::new(AtomicUsize::new(0));
+        let key = CountingKey {
+            key: MemoryKey::new("device"),
+            signed: signed.clone(),
+        };
+        let clock = TestClock::default();
+        let token_source = SasTokenSource::new("hub".to_string(), "device".to_string(), key)
+            .with_clock(Arc::new(clock.clone()));
+        let clone = token_source.clone();
+        let expiry = clock.now() + chrono::Duration::hours(1);
+
+        let token = token_source.get(&expiry).unwrap();
+        assert_eq!(token, clone.get(&expiry).unwrap());
+        assert_eq!(1, signed.load(Ordering::SeqCst));
+
+        clone.invalidate();
+        token_source.get(&expiry).unwrap();
+        assert_eq!(2, signed.load(Ordering::SeqCst));
+    }
 }
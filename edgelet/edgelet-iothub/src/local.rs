@@ -0,0 +1,423 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Module identities kept on the device, for when there is no IoT Hub to keep
+//! them.
+//!
+//! The identities are kept in a JSON file that is rewritten whole on every
+//! change. Every identity gets a generation id of its own, which is never
+//! handed out again, so the keys the key store holds for a generation only
+//! ever belong to one identity.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use failure::ResultExt;
+use futures::future::{self, FutureResult};
+use serde_json;
+
+use edgelet_core::crypto::{KeyIdentity, KeyStore};
+use edgelet_core::{AuthType, Identity, IdentityManager, IdentitySpec};
+
+use super::{build_key_name, KEY_PRIMARY, KEY_SECONDARY};
+use error::{Error, ErrorKind};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LocalIdentity {
+    #[serde(rename = "moduleId")]
+    module_id: String,
+    #[serde(rename = "managedBy")]
+    managed_by: String,
+    #[serde(rename = "generationId")]
+    generation_id: String,
+    #[serde(rename = "authType")]
+    auth_type: AuthType,
+}
+
+impl Identity for LocalIdentity {
+    fn module_id(&self) -> &str {
+        &self.module_id
+    }
+
+    fn managed_by(&self) -> &str {
+        &self.managed_by
+    }
+
+    fn generation_id(&self) -> &str {
+        &self.generation_id
+    }
+
+    fn auth_type(&self) -> AuthType {
+        self.auth_type
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct IdentityTable {
+    /// The last generation id handed out.
+    generation: u64,
+    identities: BTreeMap<String, LocalIdentity>,
+}
+
+/// Creates, rotates and removes module identities in a table on the device
+/// rather than in IoT Hub. Clones share the table.
+pub struct LocalIdentityManager<K> {
+    key_store: K,
+    path: PathBuf,
+    table: Arc<Mutex<IdentityTable>>,
+}
+
+impl<K> LocalIdentityManager<K>
+where
+    K: KeyStore,
+{
+    /// Reads the identities kept in `path`. There are none yet when the file
+    /// doesn't exist. A file that cannot be read is an error rather than
+    /// starting over, which would hand out generation ids again.
+    pub fn open<P: AsRef<Path>>(path: P, key_store: K) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let table = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .context(ErrorKind::LoadIdentities(path.display().to_string()))?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => IdentityTable::default(),
+            Err(err) => {
+                return Err(Error::from(
+                    err.context(ErrorKind::LoadIdentities(path.display().to_string())),
+                ))
+            }
+        };
+
+        Ok(LocalIdentityManager {
+            key_store,
+            path,
+            table: Arc::new(Mutex::new(table)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gives the identity of `module_id` a new generation id, once the key
+    /// store has the keys of that generation.
+    fn next_generation(&self, table: &mut IdentityTable, module_id: &str) -> Result<String, Error> {
+        let generation_id = (table.generation + 1).to_string();
+        self.check_keys(module_id, &generation_id)?;
+        table.generation += 1;
+        Ok(generation_id)
+    }
+
+    fn check_keys(&self, module_id: &str, generation_id: &str) -> Result<(), Error> {
+        let identity = KeyIdentity::Module(module_id.to_string());
+        for key in &[KEY_PRIMARY, KEY_SECONDARY] {
+            self.key_store
+                .get(&identity, &build_key_name(key, generation_id))
+                .context(ErrorKind::CannotGetKey(module_id.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Changes a copy of the table and saves it. The table is only replaced
+    /// once the copy is saved, so a change that could not be saved is not
+    /// made at all, rather than forgotten when iotedged restarts.
+    fn change<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut IdentityTable) -> Result<T, Error>,
+    {
+        let mut table = self.lock();
+        let mut changed = table.clone();
+        let result = f(&mut changed)?;
+        self.save(&changed)?;
+        *table = changed;
+        Ok(result)
+    }
+
+    fn save(&self, table: &IdentityTable) -> Result<(), Error> {
+        // Write a temporary file and move it over the old one, so that the
+        // file is never left half written.
+        let tmp = self.path.with_extension("tmp");
+        serde_json::to_vec(table)
+            .map_err(io::Error::from)
+            .and_then(|contents| fs::write(&tmp, contents))
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .context(ErrorKind::SaveIdentities(self.path.display().to_string()))?;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<IdentityTable> {
+        // The table is only ever replaced whole, so a panic while the lock
+        // was held cannot have left it inconsistent.
+        self.table.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<K> Clone for LocalIdentityManager<K>
+where
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        LocalIdentityManager {
+            key_store: self.key_store.clone(),
+            path: self.path.clone(),
+            table: self.table.clone(),
+        }
+    }
+}
+
+impl<K> IdentityManager for LocalIdentityManager<K>
+where
+    K: KeyStore,
+{
+    type Identity = LocalIdentity;
+    type Error = Error;
+    type CreateFuture = FutureResult<Self::Identity, Self::Error>;
+    type UpdateFuture = FutureResult<Self::Identity, Self::Error>;
+    type ListFuture = FutureResult<Vec<Self::Identity>, Self::Error>;
+    type GetFuture = FutureResult<Option<Self::Identity>, Self::Error>;
+    type DeleteFuture = FutureResult<(), Self::Error>;
+    type RotateFuture = FutureResult<Self::Identity, Self::Error>;
+
+    fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture {
+        future::result(self.change(|table| {
+            let module_id = id.module_id();
+            if table.identities.contains_key(module_id) {
+                return Err(Error::from(ErrorKind::IdentityConflict(
+                    module_id.to_string(),
+                )));
+            }
+            let identity = LocalIdentity {
+                module_id: module_id.to_string(),
+                managed_by: id.managed_by().unwrap_or("").to_string(),
+                generation_id: self.next_generation(table, module_id)?,
+                auth_type: AuthType::Sas,
+            };
+            table
+                .identities
+                .insert(module_id.to_string(), identity.clone());
+            Ok(identity)
+        }))
+    }
+
+    fn update(&mut self, id: IdentitySpec) -> Self::UpdateFuture {
+        future::result(self.change(|table| {
+            let generation_id = id
+                .generation_id()
+                .ok_or_else(|| Error::from(ErrorKind::MissingGenerationId))?;
+            let identity = table
+                .identities
+                .get_mut(id.module_id())
+                .ok_or_else(|| not_found(&id))?;
+            // Only rotating the keys hands out another generation.
+            if identity.generation_id != generation_id {
+                return Err(Error::from(ErrorKind::StaleGenerationId(
+                    id.module_id().to_string(),
+                )));
+            }
+            if let Some(managed_by) = id.managed_by() {
+                identity.managed_by = managed_by.to_string();
+            }
+            Ok(identity.clone())
+        }))
+    }
+
+    fn list(&self) -> Self::ListFuture {
+        future::ok(self.lock().identities.values().cloned().collect())
+    }
+
+    fn get(&self, id: IdentitySpec) -> Self::GetFuture {
+        future::ok(self.lock().identities.get(id.module_id()).cloned())
+    }
+
+    fn delete(&mut self, id: IdentitySpec) -> Self::DeleteFuture {
+        future::result(self.change(|table| {
+            {
+                let identity = table
+                    .identities
+                    .get(id.module_id())
+                    .ok_or_else(|| not_found(&id))?;
+                if let Some(generation_id) = id.generation_id() {
+                    if identity.generation_id != generation_id {
+                        return Err(Error::from(ErrorKind::StaleGenerationId(
+                            id.module_id().to_string(),
+                        )));
+                    }
+                }
+            }
+            table.identities.remove(id.module_id());
+            Ok(())
+        }))
+    }
+
+    fn rotate_keys(&mut self, id: IdentitySpec) -> Self::RotateFuture {
+        future::result(self.change(|table| {
+            if !table.identities.contains_key(id.module_id()) {
+                return Err(not_found(&id));
+            }
+            let generation_id = self.next_generation(table, id.module_id())?;
+            let identity = table
+                .identities
+                .get_mut(id.module_id())
+                .ok_or_else(|| not_found(&id))?;
+            identity.generation_id = generation_id;
+            // Whoever managed the identity before keeps managing it, unless
+            // someone else is named.
+            if let Some(managed_by) = id.managed_by() {
+                identity.managed_by = managed_by.to_string();
+            }
+            Ok(identity.clone())
+        }))
+    }
+}
+
+fn not_found(id: &IdentitySpec) -> Error {
+    Error::from(ErrorKind::IdentityNotFound(id.module_id().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use edgelet_core::crypto::{DerivedKeyStore, MemoryKey, MemoryKeyStore};
+    use edgelet_core::{IdentityError, MANAGED_BY_IOTEDGE};
+    use futures::Future;
+
+    use super::*;
+
+    fn test_path(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("edgelet-iothub-local-{}-{}", process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("identities.json")
+    }
+
+    fn open(path: &Path) -> LocalIdentityManager<DerivedKeyStore<MemoryKey>> {
+        LocalIdentityManager::open(path, DerivedKeyStore::new(MemoryKey::new("device"))).unwrap()
+    }
+
+    fn spec(module_id: &str) -> IdentitySpec {
+        IdentitySpec::new(module_id).with_managed_by(MANAGED_BY_IOTEDGE.to_string())
+    }
+
+    fn ids(manager: &LocalIdentityManager<DerivedKeyStore<MemoryKey>>) -> Vec<(String, String)> {
+        manager
+            .list()
+            .wait()
+            .unwrap()
+            .iter()
+            .map(|id| (id.module_id().to_string(), id.generation_id().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn identities_go_through_their_lifecycle() {
+        let path = test_path("lifecycle");
+        let mut manager = open(&path);
+
+        let created = manager.create(spec("m1")).wait().unwrap();
+        assert_eq!("m1", created.module_id());
+        assert_eq!("iotedge", created.managed_by());
+        assert_eq!("1", created.generation_id());
+        assert_eq!(AuthType::Sas, created.auth_type());
+        manager.create(IdentitySpec::new("m2")).wait().unwrap();
+        assert!(
+            manager
+                .create(spec("m1"))
+                .wait()
+                .unwrap_err()
+                .is_conflict()
+        );
+
+        let updated = manager
+            .update(
+                IdentitySpec::new("m2")
+                    .with_generation_id("2".to_string())
+                    .with_managed_by("someone".to_string()),
+            ).wait()
+            .unwrap();
+        assert_eq!("someone", updated.managed_by());
+        assert_eq!("2", updated.generation_id());
+        match *manager
+            .update(IdentitySpec::new("m2").with_generation_id("1".to_string()))
+            .wait()
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::StaleGenerationId(ref id) => assert_eq!("m2", id),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+
+        let rotated = manager.rotate_keys(IdentitySpec::new("m1")).wait().unwrap();
+        assert_eq!("3", rotated.generation_id());
+        assert_eq!("iotedge", rotated.managed_by());
+        assert_eq!(Some(rotated), manager.get(IdentitySpec::new("m1")).wait().unwrap());
+
+        assert!(
+            manager
+                .delete(IdentitySpec::new("m1").with_generation_id("1".to_string()))
+                .wait()
+                .unwrap_err()
+                .is_stale()
+        );
+        manager
+            .delete(IdentitySpec::new("m1").with_generation_id("3".to_string()))
+            .wait()
+            .unwrap();
+        assert_eq!(None, manager.get(IdentitySpec::new("m1")).wait().unwrap());
+        assert!(manager.delete(IdentitySpec::new("m1")).wait().is_err());
+        manager.delete(IdentitySpec::new("m2")).wait().unwrap();
+        assert!(manager.rotate_keys(IdentitySpec::new("m1")).wait().is_err());
+
+        // Generations are not handed out again, even to a new identity with
+        // the same module id.
+        let recreated = manager.create(spec("m1")).wait().unwrap();
+        assert_eq!("4", recreated.generation_id());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn identities_are_kept_across_restarts() {
+        let path = test_path("restart");
+        let mut manager = open(&path);
+        manager.create(spec("m1")).wait().unwrap();
+        manager.create(spec("m2")).wait().unwrap();
+        manager.rotate_keys(IdentitySpec::new("m2")).wait().unwrap();
+        manager.delete(IdentitySpec::new("m1")).wait().unwrap();
+
+        let mut manager = open(&path);
+        assert_eq!(vec![("m2".to_string(), "3".to_string())], ids(&manager));
+        let created = manager.create(spec("m3")).wait().unwrap();
+        assert_eq!("4", created.generation_id());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unreadable_table_is_an_error() {
+        let path = test_path("corrupt");
+        fs::write(&path, b"{\"generation\": 3, \"identi").unwrap();
+        let result = LocalIdentityManager::open(&path, DerivedKeyStore::new(MemoryKey::new("d")));
+        match result {
+            Err(ref err) => match *err.kind() {
+                ErrorKind::LoadIdentities(_) => (),
+                ref kind => panic!("unexpected error kind {:?}", kind),
+            },
+            Ok(_) => panic!("opened a corrupt table"),
+        }
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn identities_without_keys_are_not_created() {
+        let path = test_path("keys");
+        let mut manager = LocalIdentityManager::open(&path, MemoryKeyStore::new()).unwrap();
+        match *manager.create(spec("m1")).wait().unwrap_err().kind() {
+            ErrorKind::CannotGetKey(ref id) => assert_eq!("m1", id),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert!(manager.list().wait().unwrap().is_empty());
+        assert!(!path.exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
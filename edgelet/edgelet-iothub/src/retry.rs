@@ -0,0 +1,347 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use failure::Fail;
+use futures::future::{self, Either, Loop};
+use futures::{Future, IntoFuture};
+use hyper::StatusCode;
+use rand::{thread_rng, Rng};
+use tokio::timer::Delay;
+
+use edgelet_http::{Error as HttpError, ErrorKind as HttpErrorKind};
+use iothubservice::error::Error as HubServiceError;
+
+const DEFAULT_INITIAL_DELAY_MILLIS: u64 = 1_000;
+const DEFAULT_MAX_DELAY_SECS: u64 = 60;
+const DEFAULT_MAX_ELAPSED_SECS: u64 = 300;
+const DEFAULT_JITTER: f64 = 0.2;
+
+/// How requests to IoT Hub are retried when they fail transiently, that is
+/// when the hub throttles them (429), fails with a 5xx or cannot be reached.
+///
+/// The delay between attempts doubles from `initial_delay` up to `max_delay`
+/// and is spread by up to `jitter` of itself either way, unless the hub says
+/// how long to wait with a `Retry-After` header. No attempt is made once
+/// waiting for it would take the time spent on the request past `max_elapsed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_elapsed: Duration,
+    jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_millis(DEFAULT_INITIAL_DELAY_MILLIS),
+            max_delay: Duration::from_secs(DEFAULT_MAX_DELAY_SECS),
+            max_elapsed: Duration::from_secs(DEFAULT_MAX_ELAPSED_SECS),
+            jitter: DEFAULT_JITTER,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// A zero `max_elapsed` turns retries off.
+    pub fn with_max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = max_elapsed;
+        self
+    }
+
+    /// The fraction of a delay by which it is randomly lengthened or shortened.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn initial_delay(&self) -> Duration {
+        self.initial_delay
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        self.max_delay
+    }
+
+    pub fn max_elapsed(&self) -> Duration {
+        self.max_elapsed
+    }
+
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// The delay before the retry following `attempt` failed attempts.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let spread = if self.jitter > 0.0 {
+            thread_rng().gen_range(-self.jitter, self.jitter)
+        } else {
+            0.0
+        };
+        self.scaled_backoff(attempt, 1.0 + spread)
+    }
+
+    fn scaled_backoff(&self, attempt: u32, factor: f64) -> Duration {
+        let initial = millis(self.initial_delay);
+        let delay = cmp::min(
+            initial.saturating_mul(1 << cmp::min(attempt, 31)),
+            millis(self.max_delay),
+        );
+        #[cfg_attr(
+            feature = "cargo-clippy",
+            allow(cast_possible_truncation, cast_precision_loss, cast_sign_loss)
+        )]
+        let delay = (delay as f64 * factor) as u64;
+        Duration::from_millis(delay)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000)
+        .saturating_add(u64::from(duration.subsec_millis()))
+}
+
+/// What to do about a failed request.
+#[derive(Debug, PartialEq)]
+enum Retry {
+    /// Retry straight away. The client signs each request with a fresh SAS
+    /// token, so a request that was not authorized is retried once in case
+    /// the token it was sent with was no longer valid.
+    Now,
+    After(Duration),
+    Never,
+}
+
+fn classify(
+    policy: &RetryPolicy,
+    error: &HubServiceError,
+    attempt: u32,
+    refreshed: bool,
+) -> Retry {
+    let http_error = match error
+        .cause()
+        .and_then(|cause| cause.downcast_ref::<HttpError>())
+    {
+        Some(http_error) => http_error,
+        None => return Retry::Never,
+    };
+
+    match *http_error.kind() {
+        HttpErrorKind::ServiceError(status, _) if status == StatusCode::UNAUTHORIZED => {
+            if refreshed {
+                Retry::Never
+            } else {
+                Retry::Now
+            }
+        }
+        HttpErrorKind::ServiceError(status, _)
+            if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() =>
+        {
+            Retry::After(
+                http_error
+                    .retry_after()
+                    .unwrap_or_else(|| policy.backoff(attempt)),
+            )
+        }
+        HttpErrorKind::Hyper | HttpErrorKind::Timeout => Retry::After(policy.backoff(attempt)),
+        _ => Retry::Never,
+    }
+}
+
+struct Attempts {
+    started: Instant,
+    attempt: u32,
+    refreshed: bool,
+}
+
+/// Runs the request `request` makes until it succeeds, fails for good or
+/// runs out of time as `policy` allows.
+pub fn retry<F, R>(
+    policy: RetryPolicy,
+    request: F,
+) -> impl Future<Item = R::Item, Error = HubServiceError> + Send
+where
+    F: 'static + Fn() -> R + Send,
+    R: IntoFuture<Error = HubServiceError>,
+    R::Future: 'static + Send,
+    R::Item: 'static + Send,
+{
+    let attempts = Attempts {
+        started: Instant::now(),
+        attempt: 0,
+        refreshed: false,
+    };
+    future::loop_fn(attempts, move |attempts| {
+        let policy = policy.clone();
+        request().into_future().then(move |result| match result {
+            Ok(item) => Either::A(future::ok(Loop::Break(item))),
+            Err(err) => {
+                let Attempts {
+                    started,
+                    attempt,
+                    refreshed,
+                } = attempts;
+                let (delay, refreshed) = match classify(&policy, &err, attempt, refreshed) {
+                    Retry::Now => (Duration::from_secs(0), true),
+                    Retry::After(delay) => (delay, refreshed),
+                    Retry::Never => return Either::A(future::err(err)),
+                };
+                if started.elapsed() + delay > policy.max_elapsed() {
+                    return Either::A(future::err(err));
+                }
+
+                let attempts = Attempts {
+                    started,
+                    attempt: attempt + 1,
+                    refreshed,
+                };
+                Either::B(
+                    Delay::new(Instant::now() + delay).then(move |timer| match timer {
+                        Ok(()) => Ok(Loop::Continue(attempts)),
+                        Err(_) => Err(err),
+                    }),
+                )
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use iothubservice::error::ErrorKind as HubServiceErrorKind;
+
+    fn service_error(status: StatusCode) -> HubServiceError {
+        HubServiceError::from(HttpError::from((status, &b"error"[..])))
+    }
+
+    fn no_jitter() -> RetryPolicy {
+        RetryPolicy::default()
+            .with_initial_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(1_000))
+            .with_jitter(0.0)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_delay() {
+        let policy = no_jitter();
+        let delays: Vec<Duration> = (0..6).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(
+            vec![100, 200, 400, 800, 1_000, 1_000]
+                .into_iter()
+                .map(Duration::from_millis)
+                .collect::<Vec<_>>(),
+            delays
+        );
+        assert_eq!(Duration::from_millis(1_000), policy.backoff(100));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_bounds() {
+        let policy = no_jitter().with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(1);
+            assert!(delay >= Duration::from_millis(100), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(300), "{:?}", delay);
+        }
+        assert_eq!(Duration::from_millis(100), policy.scaled_backoff(1, 0.5));
+        assert_eq!(Duration::from_millis(300), policy.scaled_backoff(1, 1.5));
+    }
+
+    #[test]
+    fn classify_retries_transient_errors() {
+        let policy = no_jitter();
+        assert_eq!(
+            Retry::After(Duration::from_millis(400)),
+            classify(&policy, &service_error(StatusCode::TOO_MANY_REQUESTS), 2, false)
+        );
+        assert_eq!(
+            Retry::After(Duration::from_millis(100)),
+            classify(&policy, &service_error(StatusCode::SERVICE_UNAVAILABLE), 0, false)
+        );
+        let timeout = HubServiceError::from(HttpError::from(HttpErrorKind::Timeout));
+        assert_eq!(
+            Retry::After(Duration::from_millis(200)),
+            classify(&policy, &timeout, 1, false)
+        );
+    }
+
+    #[test]
+    fn classify_honors_retry_after() {
+        let error = HubServiceError::from(HttpError::from((
+            StatusCode::TOO_MANY_REQUESTS,
+            &b"throttled"[..],
+            Duration::from_secs(7),
+        )));
+        assert_eq!(
+            Retry::After(Duration::from_secs(7)),
+            classify(&no_jitter(), &error, 0, false)
+        );
+    }
+
+    #[test]
+    fn classify_retries_unauthorized_once() {
+        let error = service_error(StatusCode::UNAUTHORIZED);
+        assert_eq!(Retry::Now, classify(&no_jitter(), &error, 0, false));
+        assert_eq!(Retry::Never, classify(&no_jitter(), &error, 1, true));
+    }
+
+    #[test]
+    fn classify_passes_other_errors_through() {
+        let policy = no_jitter();
+        for status in &[
+            StatusCode::BAD_REQUEST,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+        ] {
+            assert_eq!(
+                Retry::Never,
+                classify(&policy, &service_error(*status), 0, false)
+            );
+        }
+        let not_found = HubServiceError::from(HubServiceErrorKind::ModuleNotFound);
+        assert_eq!(Retry::Never, classify(&policy, &not_found, 0, false));
+    }
+
+    #[test]
+    fn retry_stops_once_out_of_time() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let attempts_copy = attempts.clone();
+        let policy = no_jitter()
+            .with_initial_delay(Duration::from_millis(10))
+            .with_max_elapsed(Duration::from_millis(100));
+        let task = retry(policy, move || {
+            attempts_copy.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(service_error(StatusCode::SERVICE_UNAVAILABLE))
+        });
+
+        let started = Instant::now();
+        let err = ::tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+
+        // Waits of 10, 20 and 40ms fit in 100ms, the next 80ms does not.
+        assert_eq!(4, attempts.load(Ordering::SeqCst));
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(&HubServiceErrorKind::Http, err.kind());
+    }
+}
@@ -10,6 +10,7 @@ pub struct TestCert {
     private_key: Option<PrivateKey<String>>,
     fail_private_key: bool,
     fail_valid_to: bool,
+    valid_to: Option<DateTime<Utc>>,
 }
 
 impl TestCert {
@@ -37,6 +38,11 @@ impl TestCert {
         self.fail_valid_to = fail_valid_to;
         self
     }
+
+    pub fn with_valid_to(mut self, valid_to: DateTime<Utc>) -> Self {
+        self.valid_to = Some(valid_to);
+        self
+    }
 }
 
 impl Certificate for TestCert {
@@ -63,7 +69,7 @@ impl Certificate for TestCert {
         if self.fail_valid_to {
             Err(CoreError::from(CoreErrorKind::Io))
         } else {
-            Ok(Utc::now())
+            Ok(self.valid_to.unwrap_or_else(Utc::now))
         }
     }
 }
@@ -0,0 +1,95 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use futures::prelude::*;
+use futures::task::{self, Task};
+use tokio::timer::Error as TimerError;
+
+use edgelet_core::Clock;
+
+struct State {
+    now: DateTime<Utc>,
+    instant: Instant,
+    waiting: Vec<Task>,
+}
+
+/// A clock that only moves when it is advanced. Its delays complete once the
+/// clock has been advanced past their deadline, so tests can step through
+/// backoffs and expiry without waiting for them. Clones share the time.
+#[derive(Clone)]
+pub struct TestClock {
+    state: Arc<Mutex<State>>,
+}
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        TestClock {
+            state: Arc::new(Mutex::new(State {
+                now,
+                instant: Instant::now(),
+                waiting: vec![],
+            })),
+        }
+    }
+
+    /// Moves the clock forward by `duration` and wakes up the delays waiting
+    /// on it.
+    pub fn advance(&self, duration: Duration) {
+        let waiting = {
+            let mut state = self.state.lock().unwrap();
+            state.now =
+                state.now + ChronoDuration::from_std(duration).expect("duration is out of range");
+            state.instant += duration;
+            state.waiting.split_off(0)
+        };
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        TestClock::new(Utc.ymd(2018, 1, 1).and_hms(0, 0, 0))
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().now
+    }
+
+    fn instant(&self) -> Instant {
+        self.state.lock().unwrap().instant
+    }
+
+    fn delay(&self, deadline: Instant) -> Box<Future<Item = (), Error = TimerError> + Send> {
+        Box::new(TestDelay {
+            state: self.state.clone(),
+            deadline,
+        })
+    }
+}
+
+struct TestDelay {
+    state: Arc<Mutex<State>>,
+    deadline: Instant,
+}
+
+impl Future for TestDelay {
+    type Item = ();
+    type Error = TimerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.instant >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            state.waiting.push(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
@@ -3,8 +3,8 @@
 use futures::future::{self, FutureResult, IntoFuture};
 
 use edgelet_core::{
-    AuthType, Error as CoreError, ErrorKind as CoreErrorKind, Identity, IdentityManager,
-    IdentitySpec,
+    AuthType, Error as CoreError, ErrorKind as CoreErrorKind, Identity, IdentityError,
+    IdentityManager, IdentitySpec,
 };
 
 #[derive(Clone, Copy, Debug, Fail)]
@@ -17,6 +17,28 @@ pub enum Error {
 
     #[fail(display = "Module generation ID was not provided")]
     MissingGenerationId,
+
+    #[fail(display = "Module already exists")]
+    Conflict,
+
+    #[fail(display = "Module has another generation ID")]
+    StaleGenerationId,
+}
+
+impl IdentityError for Error {
+    fn is_conflict(&self) -> bool {
+        match *self {
+            Error::Conflict => true,
+            _ => false,
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        match *self {
+            Error::StaleGenerationId => true,
+            _ => false,
+        }
+    }
 }
 
 impl From<Error> for CoreError {
@@ -115,10 +137,17 @@ impl IdentityManager for TestIdentityManager {
     type ListFuture = FutureResult<Vec<Self::Identity>, Self::Error>;
     type GetFuture = FutureResult<Option<Self::Identity>, Self::Error>;
     type DeleteFuture = FutureResult<(), Self::Error>;
+    type RotateFuture = FutureResult<Self::Identity, Self::Error>;
 
     fn create(&mut self, id: IdentitySpec) -> Self::CreateFuture {
         if self.fail_create {
             future::err(Error::General)
+        } else if self
+            .identities
+            .iter()
+            .any(|m| m.module_id() == id.module_id())
+        {
+            future::err(Error::Conflict)
         } else {
             self.gen_id_sentinel += 1;
             let id = TestIdentity::new(
@@ -190,9 +219,31 @@ impl IdentityManager for TestIdentityManager {
         self.identities
             .iter()
             .position(|ref mid| mid.module_id() == id.module_id())
-            .map(|index| {
+            .ok_or(Error::ModuleNotFound)
+            .and_then(|index| {
+                let current = self.identities[index].generation_id.as_str();
+                if id.generation_id().map_or(false, |generation_id| generation_id != current) {
+                    Err(Error::StaleGenerationId)
+                } else {
+                    Ok(index)
+                }
+            }).map(|index| {
                 self.identities.remove(index);
-            }).ok_or(Error::ModuleNotFound)
-            .into_future()
+            }).into_future()
+    }
+
+    fn rotate_keys(&mut self, id: IdentitySpec) -> Self::RotateFuture {
+        match self
+            .identities
+            .iter_mut()
+            .find(|m| m.module_id() == id.module_id())
+        {
+            Some(module) => {
+                self.gen_id_sentinel += 1;
+                module.generation_id = format!("{}", self.gen_id_sentinel);
+                future::ok(module.clone())
+            }
+            None => future::err(Error::ModuleNotFound),
+        }
     }
 }
@@ -0,0 +1,117 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Checks that the models of an API survive being read and written back
+//! out. Services keep adding fields to what they send, which a model must
+//! accept without knowing them, and a model that loses fields it does know
+//! about loses data whenever an object is passed on.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+/// A field no model knows about, standing in for one a later version of a
+/// service adds. Its value is a string so that it fits maps of strings too.
+pub const UNKNOWN_FIELD: &str = "fieldAddedLater";
+
+const UNKNOWN_VALUE: &str = "added later";
+
+/// `json` with `UNKNOWN_FIELD` added to each of its objects.
+pub fn with_unknown_fields(json: &Value) -> Value {
+    match *json {
+        Value::Object(ref fields) => {
+            let mut fields: serde_json::Map<String, Value> = fields
+                .iter()
+                .map(|(key, value)| (key.clone(), with_unknown_fields(value)))
+                .collect();
+            fields.insert(UNKNOWN_FIELD.to_string(), Value::from(UNKNOWN_VALUE));
+            Value::Object(fields)
+        }
+        Value::Array(ref values) => Value::Array(values.iter().map(with_unknown_fields).collect()),
+        ref value => value.clone(),
+    }
+}
+
+/// Reads `json`, with an unknown field added to each of its objects, as a
+/// `T` and writes it back out. Panics unless it can be read and all of the
+/// fields of `json` come back as they were. Returns what was written, for
+/// checks of what became of the unknown fields.
+pub fn assert_round_trip<T>(json: &Value) -> Value
+where
+    T: DeserializeOwned + Serialize,
+{
+    let input = with_unknown_fields(json);
+    let model: T = serde_json::from_value(input.clone())
+        .unwrap_or_else(|err| panic!("Could not read {}: {}", input, err));
+    let output = serde_json::to_value(&model).unwrap();
+    assert_contains(json, &output, "");
+    output
+}
+
+/// Panics unless each field of `expected`, however deeply nested, is in
+/// `actual` with the same value. `actual` may have more fields.
+pub fn assert_contains(expected: &Value, actual: &Value, path: &str) {
+    match (expected, actual) {
+        (&Value::Object(ref expected), &Value::Object(ref actual)) => {
+            for (key, value) in expected {
+                let path = format!("{}/{}", path, key);
+                match actual.get(key) {
+                    Some(actual) => assert_contains(value, actual, &path),
+                    None => panic!("{} was lost", path),
+                }
+            }
+        }
+        (&Value::Array(ref expected), &Value::Array(ref actual)) => {
+            assert_eq!(expected.len(), actual.len(), "{} changed length", path);
+            for (i, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                assert_contains(expected, actual, &format!("{}/{}", path, i));
+            }
+        }
+        _ => assert_eq!(expected, actual, "{} changed", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Serialize)]
+    struct Known {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tags: Option<Vec<String>>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Lossy {
+        name: String,
+        #[serde(skip_serializing)]
+        tags: Option<Vec<String>>,
+    }
+
+    #[test]
+    fn unknown_fields_are_added_to_every_object() {
+        let json = json!({"a": {"b": [{"c": 1}, 2]}});
+        assert_eq!(
+            json!({
+                "a": {
+                    "b": [{"c": 1, "fieldAddedLater": "added later"}, 2],
+                    "fieldAddedLater": "added later",
+                },
+                "fieldAddedLater": "added later",
+            }),
+            with_unknown_fields(&json)
+        );
+    }
+
+    #[test]
+    fn models_that_keep_their_fields_round_trip() {
+        let output = assert_round_trip::<Known>(&json!({"name": "m1", "tags": ["a", "b"]}));
+        assert_eq!(None, output.get(UNKNOWN_FIELD));
+    }
+
+    #[test]
+    #[should_panic(expected = "/tags was lost")]
+    fn models_that_lose_fields_do_not_round_trip() {
+        assert_round_trip::<Lossy>(&json!({"name": "m1", "tags": ["a"]}));
+    }
+}
@@ -29,17 +29,24 @@ extern crate mio_named_pipes;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+#[cfg(not(test))]
 extern crate serde_json;
 extern crate tokio;
 
 use std::net::TcpListener;
 
 pub mod cert;
+mod clock;
 pub mod identity;
+pub mod json;
 mod json_connector;
 pub mod module;
 pub mod web;
 
+pub use clock::TestClock;
 pub use json_connector::{JsonConnector, StaticStream};
 pub use web::run_tcp_server;
 
@@ -1,8 +1,10 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use edgelet_core::pid::Pid;
 use edgelet_core::*;
 use failure::Fail;
 use futures::future::{self, FutureResult};
@@ -13,15 +15,31 @@ use hyper::Body;
 
 #[derive(Clone, Debug)]
 pub struct NullRegistry<E: Fail> {
+    images: Arc<Mutex<Vec<ImageInfo>>>,
+    pruned: Arc<Mutex<Vec<String>>>,
     phantom: PhantomData<E>,
 }
 
 impl<E: Fail> NullRegistry<E> {
     pub fn new() -> Self {
         NullRegistry {
+            images: Arc::new(Mutex::new(vec![])),
+            pruned: Arc::new(Mutex::new(vec![])),
             phantom: PhantomData,
         }
     }
+
+    /// Lists these images until they are pruned.
+    pub fn with_images(self, images: Vec<ImageInfo>) -> Self {
+        *self.images.lock().expect("images lock poisoned") = images;
+        self
+    }
+
+    /// The ids of the images pruned so far, shared by every clone of the
+    /// registry.
+    pub fn pruned(&self) -> Vec<String> {
+        self.pruned.lock().expect("pruned lock poisoned").clone()
+    }
 }
 
 impl<E: Fail> Default for NullRegistry<E> {
@@ -43,6 +61,25 @@ impl<E: Fail> ModuleRegistry for NullRegistry<E> {
     fn remove(&self, _name: &str) -> Self::RemoveFuture {
         future::ok(())
     }
+
+    fn images(&self) -> Box<Future<Item = Vec<ImageInfo>, Error = Self::Error> + Send> {
+        Box::new(future::ok(
+            self.images.lock().expect("images lock poisoned").clone(),
+        ))
+    }
+
+    fn prune_images(
+        &self,
+        ids: Vec<String>,
+    ) -> Box<Future<Item = Vec<String>, Error = Self::Error> + Send> {
+        self.images
+            .lock()
+            .expect("images lock poisoned")
+            .retain(|image| !ids.iter().any(|id| id == image.id()));
+        let mut pruned = self.pruned.lock().expect("pruned lock poisoned");
+        pruned.extend(ids.iter().cloned());
+        Box::new(future::ok(ids))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -97,19 +134,85 @@ impl<E: Clone + Fail> Module for TestModule<E> {
     fn runtime_state(&self) -> Self::RuntimeStateFuture {
         self.state.clone().into_future()
     }
+
+    fn image_id(&self) -> Option<&str> {
+        Some(self.config.image())
+    }
 }
 
 #[derive(Clone)]
 pub struct TestRuntime<E: Fail> {
     module: Result<TestModule<E>, E>,
+    modules: Option<Vec<TestModule<E>>>,
     registry: NullRegistry<E>,
+    inspection: ModuleInspection,
+    failures: Vec<(String, E)>,
+    inspect_failures: Vec<(String, E)>,
+    calls: Arc<Mutex<Vec<String>>>,
 }
 
 impl<E: Fail> TestRuntime<E> {
     pub fn new(module: Result<TestModule<E>, E>) -> Self {
         TestRuntime {
             module,
+            modules: None,
             registry: NullRegistry::new(),
+            inspection: ModuleInspection::default(),
+            failures: vec![],
+            inspect_failures: vec![],
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub fn with_inspection(mut self, inspection: ModuleInspection) -> Self {
+        self.inspection = inspection;
+        self
+    }
+
+    /// Lists these modules instead of the one the runtime was made with.
+    pub fn with_modules(mut self, modules: Vec<TestModule<E>>) -> Self {
+        self.modules = Some(modules);
+        self
+    }
+
+    /// Has the registry list these images until they are pruned.
+    pub fn with_images(mut self, images: Vec<ImageInfo>) -> Self {
+        self.registry = self.registry.with_images(images);
+        self
+    }
+
+    /// Fails starting and stopping the named module with the error.
+    pub fn with_failure(mut self, name: &str, err: E) -> Self {
+        self.failures.push((name.to_string(), err));
+        self
+    }
+
+    /// Fails inspecting the named module with the error.
+    pub fn with_inspect_failure(mut self, name: &str, err: E) -> Self {
+        self.inspect_failures.push((name.to_string(), err));
+        self
+    }
+
+    /// The modules started and stopped so far, as "start <name>" and
+    /// "stop <name>", and the system information refreshed, as
+    /// "refresh_system_info", shared by every clone of the runtime.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().expect("calls lock poisoned").clone()
+    }
+}
+
+impl<E: Clone + Fail> TestRuntime<E> {
+    fn record(&self, call: &str, id: &str) -> FutureResult<(), E> {
+        self.calls
+            .lock()
+            .expect("calls lock poisoned")
+            .push(format!("{} {}", call, id));
+        match self.module {
+            Ok(_) => match self.failures.iter().find(|&&(ref name, _)| name == id) {
+                Some(&(_, ref e)) => future::err(e.clone()),
+                None => future::ok(()),
+            },
+            Err(ref e) => future::err(e.clone()),
         }
     }
 }
@@ -165,19 +268,38 @@ impl<E: Clone + Fail> ModuleRuntime for TestRuntime<E> {
     type RestartFuture = FutureResult<(), Self::Error>;
     type StartFuture = FutureResult<(), Self::Error>;
     type StopFuture = FutureResult<(), Self::Error>;
+    type TopFuture = FutureResult<Vec<Pid>, Self::Error>;
     type SystemInfoFuture = FutureResult<SystemInfo, Self::Error>;
     type RemoveAllFuture = FutureResult<(), Self::Error>;
+    type CheckIntegrityFuture = FutureResult<IntegrityReport, Self::Error>;
+    type InspectFuture = FutureResult<ModuleInspection, Self::Error>;
+    type KillFuture = FutureResult<(), Self::Error>;
+    type StopAllFuture = FutureResult<(), Self::Error>;
+    type UpdateFuture = FutureResult<(), Self::Error>;
 
     fn system_info(&self) -> Self::SystemInfoFuture {
         match self.module {
-            Ok(_) => future::ok(SystemInfo::new(
-                "os_type_sample".to_string(),
-                "architecture_sample".to_string(),
-            )),
+            Ok(_) => future::ok(
+                SystemInfo::new(
+                    "os_type_sample".to_string(),
+                    "architecture_sample".to_string(),
+                ).with_runtime_version(Some("runtime_version_sample".to_string()))
+                .with_runtime_api_version(Some("runtime_api_version_sample".to_string()))
+                .with_storage_driver(Some("storage_driver_sample".to_string()))
+                .with_logging_driver(Some("logging_driver_sample".to_string())),
+            ),
             Err(ref e) => future::err(e.clone()),
         }
     }
 
+    fn refresh_system_info(&self) -> Self::SystemInfoFuture {
+        self.calls
+            .lock()
+            .expect("calls lock poisoned")
+            .push("refresh_system_info".to_string());
+        self.system_info()
+    }
+
     fn init(&self) -> Self::InitFuture {
         match self.module {
             Ok(_) => future::ok(()),
@@ -192,21 +314,22 @@ impl<E: Clone + Fail> ModuleRuntime for TestRuntime<E> {
         }
     }
 
-    fn start(&self, _id: &str) -> Self::StartFuture {
-        match self.module {
-            Ok(_) => future::ok(()),
-            Err(ref e) => future::err(e.clone()),
-        }
+    fn start(&self, id: &str) -> Self::StartFuture {
+        self.record("start", id)
+    }
+
+    fn stop(&self, id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
+        self.record("stop", id)
     }
 
-    fn stop(&self, _id: &str, _wait_before_kill: Option<Duration>) -> Self::StopFuture {
+    fn restart(&self, _id: &str) -> Self::RestartFuture {
         match self.module {
             Ok(_) => future::ok(()),
             Err(ref e) => future::err(e.clone()),
         }
     }
 
-    fn restart(&self, _id: &str) -> Self::RestartFuture {
+    fn kill(&self, _id: &str, _signal: &str) -> Self::KillFuture {
         match self.module {
             Ok(_) => future::ok(()),
             Err(ref e) => future::err(e.clone()),
@@ -222,7 +345,7 @@ impl<E: Clone + Fail> ModuleRuntime for TestRuntime<E> {
 
     fn list(&self) -> Self::ListFuture {
         match self.module {
-            Ok(ref m) => future::ok(vec![m.clone()]),
+            Ok(ref m) => future::ok(self.modules.clone().unwrap_or_else(|| vec![m.clone()])),
             Err(ref e) => future::err(e.clone()),
         }
     }
@@ -230,8 +353,11 @@ impl<E: Clone + Fail> ModuleRuntime for TestRuntime<E> {
     fn list_with_details(&self) -> Self::ListWithDetailsStream {
         match self.module {
             Ok(ref m) => {
-                let m = m.clone();
-                Box::new(m.runtime_state().map(|rs| (m, rs)).into_stream())
+                let modules = self.modules.clone().unwrap_or_else(|| vec![m.clone()]);
+                Box::new(
+                    stream::iter_ok(modules)
+                        .and_then(|m| m.runtime_state().map(|rs| (m, rs))),
+                )
             }
             Err(ref e) => Box::new(stream::once(Err(e.clone()))),
         }
@@ -251,4 +377,47 @@ impl<E: Clone + Fail> ModuleRuntime for TestRuntime<E> {
     fn remove_all(&self) -> Self::RemoveAllFuture {
         future::ok(())
     }
+
+    fn check_integrity(&self) -> Self::CheckIntegrityFuture {
+        future::ok(IntegrityReport::default())
+    }
+
+    fn inspect(&self, id: &str) -> Self::InspectFuture {
+        match self.module {
+            Ok(_) => match self.inspect_failures.iter().find(|&&(ref name, _)| name == id) {
+                Some(&(_, ref e)) => future::err(e.clone()),
+                None => future::ok(self.inspection.clone()),
+            },
+            Err(ref e) => future::err(e.clone()),
+        }
+    }
+
+    fn stop_all(&self, _options: &StopAllOptions) -> Self::StopAllFuture {
+        match self.module {
+            Ok(_) => future::ok(()),
+            Err(ref e) => future::err(e.clone()),
+        }
+    }
+
+    fn update(&self, _module: ModuleSpec<Self::Config>, _start: bool) -> Self::UpdateFuture {
+        match self.module {
+            Ok(_) => future::ok(()),
+            Err(ref e) => future::err(e.clone()),
+        }
+    }
+
+    fn top(&self, id: &str) -> Self::TopFuture {
+        match self.module {
+            Ok(ref m) => future::ok(
+                self.modules
+                    .as_ref()
+                    .map_or_else(|| vec![m], |modules| modules.iter().collect())
+                    .into_iter()
+                    .filter(|m| m.name == id)
+                    .filter_map(|m| m.state.as_ref().ok().map(ModuleRuntimeState::pid))
+                    .collect(),
+            ),
+            Err(ref e) => future::err(e.clone()),
+        }
+    }
 }
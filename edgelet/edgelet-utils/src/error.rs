@@ -30,6 +30,15 @@ pub enum ErrorKind {
     )]
     ArgumentEmpty(String),
 
+    #[fail(display = "{} must be between {} and {}, got {}", _0, _1, _2, _3)]
+    ParameterOutOfRange(String, String, String, String),
+
+    #[fail(display = "Length of {} must be between {} and {}, got {}", _0, _1, _2, _3)]
+    ParameterLength(String, usize, usize, usize),
+
+    #[fail(display = "{} must match {}, got {:?}", _0, _1, _2)]
+    ParameterMismatch(String, String, String),
+
     #[fail(display = "Serde error")]
     Serde,
 }
@@ -11,10 +11,11 @@
 
 #[macro_use]
 extern crate failure;
-#[cfg(test)]
 extern crate futures;
 #[macro_use]
 extern crate log;
+#[cfg(test)]
+extern crate regex;
 extern crate serde;
 
 // Need serde_derive only for unit tests.
@@ -35,12 +36,16 @@ mod error;
 mod logging;
 pub mod macros;
 mod ser_de;
+mod timing;
 
 use std::collections::HashMap;
 
 pub use error::{Error, ErrorKind};
-pub use logging::log_failure;
+pub use logging::{
+    correlation_id, failure_causes, log_failure, set_structured_failures, with_correlation_id,
+};
 pub use ser_de::{serde_clone, string_or_struct};
+pub use timing::{timed, Now, Timed};
 
 pub fn parse_query(query: &str) -> HashMap<&str, &str> {
     query
@@ -1,13 +1,169 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
 use failure::Fail;
 use log::Level;
 
+static STRUCTURED_FAILURES: AtomicBool = ATOMIC_BOOL_INIT;
+
+thread_local! {
+    static CAUSES: RefCell<Vec<String>> = RefCell::new(vec![]);
+    static CORRELATION_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Makes `log_failure` log an error and its causes as a single record. The
+/// logger formatting that record finds the causes with `failure_causes`.
+pub fn set_structured_failures(structured: bool) {
+    STRUCTURED_FAILURES.store(structured, Ordering::SeqCst);
+}
+
 pub fn log_failure<F: Fail>(level: Level, error: &F) {
     let mut fail: &Fail = error;
-    log!(level, "{}", fail.to_string());
-    while let Some(cause) = fail.cause() {
-        log!(level, "\tcaused by: {}", cause.to_string());
-        fail = cause;
+    if STRUCTURED_FAILURES.load(Ordering::SeqCst) {
+        let mut causes = vec![];
+        while let Some(cause) = fail.cause() {
+            causes.push(cause.to_string());
+            fail = cause;
+        }
+        let causes = CAUSES.with(|current| current.replace(causes));
+        log!(level, "{}", error.to_string());
+        CAUSES.with(|current| current.replace(causes));
+    } else {
+        log!(level, "{}", fail.to_string());
+        while let Some(cause) = fail.cause() {
+            log!(level, "\tcaused by: {}", cause.to_string());
+            fail = cause;
+        }
+    }
+}
+
+/// The causes of the error being logged by `log_failure` on this thread, if
+/// it logs structured failures.
+pub fn failure_causes() -> Vec<String> {
+    CAUSES.with(|causes| causes.borrow().clone())
+}
+
+/// Runs `f` with `id` as the correlation id of everything it logs on this
+/// thread.
+pub fn with_correlation_id<F, T>(id: &str, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = CORRELATION_ID.with(|current| current.replace(Some(id.to_string())));
+    let result = f();
+    CORRELATION_ID.with(|current| current.replace(previous));
+    result
+}
+
+/// The correlation id of the request being handled on this thread, if any.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.with(|id| id.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+
+    use log::{self, LevelFilter, Log, Metadata, Record};
+
+    use super::*;
+
+    struct TestLogger;
+
+    // The message, causes and correlation id of a record.
+    type Logged = (String, Vec<String>, Option<String>);
+
+    thread_local! {
+        static RECORDS: RefCell<Vec<Logged>> = RefCell::new(vec![]);
+    }
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS.with(|records| {
+                records.borrow_mut().push((
+                    record.args().to_string(),
+                    failure_causes(),
+                    correlation_id(),
+                ))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger;
+
+    fn records<F: FnOnce()>(f: F) -> Vec<Logged> {
+        // Only the first call sets the logger; records are kept per thread.
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+        RECORDS.with(|records| records.borrow_mut().clear());
+        f();
+        RECORDS.with(|records| records.borrow().clone())
+    }
+
+    #[derive(Debug)]
+    struct Chain(&'static str, Option<Box<Chain>>);
+
+    impl fmt::Display for Chain {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Fail for Chain {
+        fn cause(&self) -> Option<&Fail> {
+            self.1.as_ref().map(|cause| &**cause as &Fail)
+        }
+    }
+
+    fn error() -> Chain {
+        Chain(
+            "could not start",
+            Some(Box::new(Chain(
+                "could not connect",
+                Some(Box::new(Chain("connection refused", None))),
+            ))),
+        )
+    }
+
+    // Both modes are covered by one test, since the mode is process-wide.
+    #[test]
+    fn failures_are_logged_per_line_or_structured() {
+        let logged = records(|| log_failure(Level::Warn, &error()));
+        assert_eq!(
+            vec![
+                "could not start",
+                "\tcaused by: could not connect",
+                "\tcaused by: connection refused",
+            ],
+            logged.iter().map(|r| r.0.as_str()).collect::<Vec<_>>()
+        );
+        assert!(logged.iter().all(|r| r.1.is_empty()));
+
+        set_structured_failures(true);
+        let logged = records(|| log_failure(Level::Warn, &error()));
+        set_structured_failures(false);
+        assert_eq!(1, logged.len());
+        assert_eq!("could not start", logged[0].0);
+        assert_eq!(vec!["could not connect", "connection refused"], logged[0].1);
+        assert!(failure_causes().is_empty());
+    }
+
+    #[test]
+    fn correlation_id_is_scoped() {
+        let logged = records(|| {
+            with_correlation_id("abc-1", || info!("handling"));
+            info!("idle");
+        });
+        assert_eq!(Some("abc-1".to_string()), logged[0].2);
+        assert_eq!(None, logged[1].2);
+        assert_eq!(None, correlation_id());
     }
 }
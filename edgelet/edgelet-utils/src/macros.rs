@@ -252,23 +252,43 @@ macro_rules! ensure_range_impl {
             ),
         }
     };
+    ($val:expr, $low:expr, $high:expr, $name:expr, $ensure:tt) => {
+        match (&$val, &$low, &$high) {
+            (val_val, low_val, high_val) => $ensure!(
+                *val_val,
+                *val_val >= *low_val && *val_val <= *high_val,
+                $crate::ErrorKind::ParameterOutOfRange(
+                    $name.to_string(),
+                    format!("{}", low_val),
+                    format!("{}", high_val),
+                    format!("{}", val_val),
+                )
+            ),
+        }
+    };
 }
 
 /// Check if a value falls within the range (low, high].
 ///
+/// When given the name of the parameter being checked, the range is [low, high]
+/// instead and the error names the parameter, as in "timeout must be between 1
+/// and 600, got 0".
+///
 /// # Examples
 ///
 /// ```
 /// # #[macro_use] extern crate edgelet_utils;
 /// # use edgelet_utils::Error;
 /// struct Foo {
-///     ival: i32
+///     ival: i32,
+///     timeout: u64,
 /// }
 ///
 /// impl Foo {
-///     fn new(ival: i32) -> Result<Foo, Error> {
+///     fn new(ival: i32, timeout: u64) -> Result<Foo, Error> {
 ///         Ok(Foo {
 ///             ival: ensure_range!(ival, 10, 25),
+///             timeout: ensure_range!(timeout, 1, 600, "timeout"),
 ///         })
 ///     }
 /// }
@@ -277,17 +297,24 @@ macro_rules! ensure_range_impl {
 /// ```
 #[macro_export]
 macro_rules! ensure_range {
+    ($val:expr, $low:expr, $high:expr, $name:expr) => {
+        ensure_range_impl!($val, $low, $high, $name, ensure)
+    };
     ($val:expr, $low:expr, $high:expr) => {
         ensure_range_impl!($val, $low, $high, ensure)
     };
 }
 
-/// Check if a value falls within the range (low, high].
+/// Check if a value falls within the range (low, high], or [low, high] when
+/// given the name of the parameter being checked.
 ///
 /// Use this macro when your function returns a `Box<Future<T, E>>` instead of
 /// a `Result<T, E>`. For usage examples see documentation for `ensure_range!`.
 #[macro_export]
 macro_rules! fensure_range {
+    ($val:expr, $low:expr, $high:expr, $name:expr) => {
+        ensure_range_impl!($val, $low, $high, $name, fensure)
+    };
     ($val:expr, $low:expr, $high:expr) => {
         ensure_range_impl!($val, $low, $high, fensure)
     };
@@ -416,6 +443,153 @@ macro_rules! fensure_not_empty {
     };
 }
 
+/// Internal macro used for implementing other validation macros.
+///
+/// Not to be directly invoked. Use one of the other `ensure*` macros.
+#[macro_export]
+macro_rules! ensure_len_impl {
+    ($val:expr, $min:expr, $max:expr, $name:expr, $ensure:tt) => {{
+        let (len, min_len, max_len): (usize, usize, usize) = ($val.len(), $min, $max);
+        $ensure!(
+            $val,
+            len >= min_len && len <= max_len,
+            $crate::ErrorKind::ParameterLength($name.to_string(), min_len, max_len, len)
+        )
+    }};
+}
+
+/// Check if the `len()` of a value, such as a string or a vector, falls
+/// within the range [min, max] and bail with an error naming the parameter if
+/// it does not.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate edgelet_utils;
+/// # use edgelet_utils::Error;
+/// struct Foo {
+///     name: String,
+/// }
+///
+/// impl Foo {
+///     fn new(name: String) -> Result<Foo, Error> {
+///         Ok(Foo {
+///             name: ensure_len!(name, 1, 64, "name"),
+///         })
+///     }
+/// }
+///
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! ensure_len {
+    ($val:expr, $min:expr, $max:expr, $name:expr) => {
+        ensure_len_impl!($val, $min, $max, $name, ensure)
+    };
+}
+
+/// Check if the `len()` of a value falls within the range [min, max].
+///
+/// Use this macro when your function returns a `Box<Future<T, E>>` instead of
+/// a `Result<T, E>`. For usage examples see documentation for `ensure_len!`.
+#[macro_export]
+macro_rules! fensure_len {
+    ($val:expr, $min:expr, $max:expr, $name:expr) => {
+        ensure_len_impl!($val, $min, $max, $name, fensure)
+    };
+}
+
+/// Internal macro used for implementing other validation macros.
+///
+/// Not to be directly invoked. Use one of the other `ensure*` macros.
+#[macro_export]
+macro_rules! ensure_matches_impl {
+    ($val:expr, $regex:expr, $name:expr, $ensure:tt) => {{
+        let regex = &$regex;
+        $ensure!(
+            $val,
+            regex.is_match(&$val),
+            $crate::ErrorKind::ParameterMismatch(
+                $name.to_string(),
+                regex.as_str().to_string(),
+                $val.to_string(),
+            )
+        )
+    }};
+    ($val:expr, $pred:expr, $constraint:expr, $name:expr, $ensure:tt) => {
+        $ensure!(
+            $val,
+            ($pred)(&$val),
+            $crate::ErrorKind::ParameterMismatch(
+                $name.to_string(),
+                $constraint.to_string(),
+                $val.to_string(),
+            )
+        )
+    };
+}
+
+/// Check if a string matches a pattern and bail with an error naming the
+/// parameter if it does not.
+///
+/// The pattern is either a `regex::Regex`, which should be anchored if the
+/// whole string has to match, or a predicate that is passed a reference to the
+/// value together with a description of what it accepts.
+///
+/// # Examples
+///
+/// ```
+/// # #[macro_use] extern crate edgelet_utils;
+/// # extern crate regex;
+/// # use edgelet_utils::Error;
+/// # use regex::Regex;
+/// struct Foo {
+///     id: String,
+///     tag: String,
+/// }
+///
+/// impl Foo {
+///     fn new(id: String, tag: String) -> Result<Foo, Error> {
+///         let id_pattern = Regex::new("^[a-z0-9]+$").unwrap();
+///         Ok(Foo {
+///             id: ensure_matches!(id, id_pattern, "id"),
+///             tag: ensure_matches!(
+///                 tag,
+///                 |tag: &String| tag.is_ascii(),
+///                 "ASCII characters",
+///                 "tag"
+///             ),
+///         })
+///     }
+/// }
+///
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! ensure_matches {
+    ($val:expr, $regex:expr, $name:expr) => {
+        ensure_matches_impl!($val, $regex, $name, ensure)
+    };
+    ($val:expr, $pred:expr, $constraint:expr, $name:expr) => {
+        ensure_matches_impl!($val, $pred, $constraint, $name, ensure)
+    };
+}
+
+/// Check if a string matches a pattern and bail with an error naming the
+/// parameter if it does not.
+///
+/// Use this macro when your function returns a `Box<Future<T, E>>` instead of
+/// a `Result<T, E>`. For usage examples see documentation for `ensure_matches!`.
+#[macro_export]
+macro_rules! fensure_matches {
+    ($val:expr, $regex:expr, $name:expr) => {
+        ensure_matches_impl!($val, $regex, $name, fensure)
+    };
+    ($val:expr, $pred:expr, $constraint:expr, $name:expr) => {
+        ensure_matches_impl!($val, $pred, $constraint, $name, fensure)
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Debug;
@@ -423,6 +597,7 @@ mod tests {
 
     use futures::future;
     use futures::prelude::*;
+    use regex::Regex;
 
     use error::{Error, ErrorKind};
 
@@ -637,4 +812,123 @@ mod tests {
             Box::new(future::ok(fensure_not_empty!("  not empty  ".to_string())))
         });
     }
+
+    #[test]
+    fn validate_ensure_named_range() {
+        check_value(&1, || Ok(ensure_range!(1, 1, 600, "timeout")));
+        check_value(&600, || Ok(ensure_range!(600, 1, 600, "timeout")));
+        check_error(
+            |err| err.to_string() == "timeout must be between 1 and 600, got 0",
+            || Ok(ensure_range!(0, 1, 600, "timeout")),
+        );
+        check_error(
+            |err| err.to_string() == "timeout must be between 1 and 600, got 601",
+            || Ok(ensure_range!(601, 1, 600, "timeout")),
+        );
+    }
+
+    #[test]
+    fn validate_fensure_named_range() {
+        check_fvalue(&600, || {
+            Box::new(future::ok(fensure_range!(600, 1, 600, "timeout")))
+        });
+        check_ferror(
+            |err| err.to_string() == "timeout must be between 1 and 600, got -1",
+            || Box::new(future::ok(fensure_range!(-1, 1, 600, "timeout"))),
+        );
+    }
+
+    #[test]
+    fn validate_ensure_len() {
+        let validator: Box<Fn(&Error) -> bool> = Box::new(|err| {
+            mem::discriminant(err.kind())
+                == mem::discriminant(&ErrorKind::ParameterLength("".to_string(), 0, 0, 0))
+        });
+
+        check_value(&"a", || Ok(ensure_len!("a", 1, 3, "name")));
+        check_value(&"abc".to_string(), || {
+            Ok(ensure_len!("abc".to_string(), 1, 3, "name"))
+        });
+        check_value(&vec![1, 2], || Ok(ensure_len!(vec![1, 2], 1, 3, "values")));
+        check_error(validator.as_ref(), || Ok(ensure_len!("", 1, 3, "name")));
+        check_error(
+            |err| err.to_string() == "Length of name must be between 1 and 3, got 4",
+            || Ok(ensure_len!("abcd", 1, 3, "name")),
+        );
+    }
+
+    #[test]
+    fn validate_fensure_len() {
+        check_fvalue(&"abc", || {
+            Box::new(future::ok(fensure_len!("abc", 1, 3, "name")))
+        });
+        check_ferror(
+            |err| err.to_string() == "Length of name must be between 1 and 3, got 0",
+            || Box::new(future::ok(fensure_len!("", 1, 3, "name"))),
+        );
+    }
+
+    #[test]
+    fn validate_ensure_matches_regex() {
+        let pattern = Regex::new("^[a-z][a-z0-9]*$").unwrap();
+
+        check_value(&"m1", || Ok(ensure_matches!("m1", pattern, "id")));
+        check_value(&"m1".to_string(), || {
+            Ok(ensure_matches!("m1".to_string(), pattern, "id"))
+        });
+        check_error(
+            |err| err.to_string() == r#"id must match ^[a-z][a-z0-9]*$, got "1m""#,
+            || Ok(ensure_matches!("1m", pattern, "id")),
+        );
+        check_error(
+            |err| err.to_string() == r#"id must match ^[a-z][a-z0-9]*$, got """#,
+            || Ok(ensure_matches!("", pattern, "id")),
+        );
+    }
+
+    #[test]
+    fn validate_ensure_matches_predicate() {
+        let lower = |val: &&str| val.chars().all(|c| c.is_ascii_lowercase());
+
+        check_value(&"abc", || {
+            Ok(ensure_matches!("abc", lower, "lowercase letters", "name"))
+        });
+        check_error(
+            |err| err.to_string() == r#"name must match lowercase letters, got "aBc""#,
+            || Ok(ensure_matches!("aBc", lower, "lowercase letters", "name")),
+        );
+    }
+
+    #[test]
+    fn validate_fensure_matches() {
+        let pattern = Regex::new("^[a-z][a-z0-9]*$").unwrap();
+        let lower = |val: &&str| val.chars().all(|c| c.is_ascii_lowercase());
+
+        check_fvalue(&"m1", || {
+            Box::new(future::ok(fensure_matches!("m1", pattern, "id")))
+        });
+        check_ferror(
+            |err| err.to_string() == r#"id must match ^[a-z][a-z0-9]*$, got "m/1""#,
+            || Box::new(future::ok(fensure_matches!("m/1", pattern, "id"))),
+        );
+        check_fvalue(&"abc", || {
+            Box::new(future::ok(fensure_matches!(
+                "abc",
+                lower,
+                "lowercase letters",
+                "name"
+            )))
+        });
+        check_ferror(
+            |err| err.to_string() == r#"name must match lowercase letters, got "ABC""#,
+            || {
+                Box::new(future::ok(fensure_matches!(
+                    "ABC",
+                    lower,
+                    "lowercase letters",
+                    "name"
+                )))
+            },
+        );
+    }
 }
@@ -0,0 +1,251 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+
+/// Where a `Timed` future gets the current monotonic time from.
+pub type Now = Arc<Fn() -> Instant + Send + Sync>;
+
+/// Measures how long `future` takes to complete, whether it succeeds or
+/// fails. `operation` names it in the warning logged when it is slow.
+pub fn timed<F: Future>(operation: &'static str, future: F) -> Timed<F> {
+    let now: Now = Arc::new(Instant::now);
+    Timed {
+        inner: future,
+        operation,
+        started: now(),
+        now,
+        slow_threshold: None,
+        on_finish: None,
+        on_slow: None,
+        finished: false,
+    }
+}
+
+/// A future that measures how long the future it wraps takes. See `timed`.
+pub struct Timed<F> {
+    inner: F,
+    operation: &'static str,
+    now: Now,
+    started: Instant,
+    slow_threshold: Option<Duration>,
+    on_finish: Option<Box<FnMut(Duration) + Send>>,
+    on_slow: Option<Box<FnMut(Duration) + Send>>,
+    finished: bool,
+}
+
+impl<F> Timed<F> {
+    /// Sets where the time is taken from, and starts measuring over.
+    pub fn with_clock(mut self, now: Now) -> Self {
+        self.started = now();
+        self.now = now;
+        self
+    }
+
+    /// Logs a warning when the operation takes longer than `threshold`.
+    pub fn with_slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Calls `on_finish` with how long the operation took once it finishes.
+    pub fn on_finish<O>(mut self, on_finish: O) -> Self
+    where
+        O: 'static + FnMut(Duration) + Send,
+    {
+        self.on_finish = Some(Box::new(on_finish));
+        self
+    }
+
+    /// Calls `on_slow` with how long the operation took if it finishes after
+    /// the slow threshold.
+    pub fn on_slow<O>(mut self, on_slow: O) -> Self
+    where
+        O: 'static + FnMut(Duration) + Send,
+    {
+        self.on_slow = Some(Box::new(on_slow));
+        self
+    }
+
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Ends the measurement, for operations that are given up on before they
+    /// finish. Only the first call, or the operation finishing, counts.
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        let elapsed = (self.now)() - self.started;
+        if let Some(ref mut on_finish) = self.on_finish {
+            on_finish(elapsed);
+        }
+        if let Some(threshold) = self.slow_threshold {
+            if elapsed > threshold {
+                warn!(
+                    "Slow operation: operation={} elapsed_secs={} threshold_secs={}",
+                    self.operation,
+                    Secs(elapsed),
+                    Secs(threshold)
+                );
+                if let Some(ref mut on_slow) = self.on_slow {
+                    on_slow(elapsed);
+                }
+            }
+        }
+    }
+}
+
+impl<F: Future> Future for Timed<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.inner.poll();
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.finish();
+        result
+    }
+}
+
+/// Shows a duration as seconds, to the millisecond.
+struct Secs(Duration);
+
+impl fmt::Display for Secs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:03}",
+            self.0.as_secs(),
+            self.0.subsec_nanos() / 1_000_000
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::future;
+
+    use super::*;
+
+    /// A clock that moves only when told to, and the durations a `Timed`
+    /// future reported.
+    struct Fixture {
+        now: Arc<Mutex<Instant>>,
+        finished: Arc<Mutex<Vec<Duration>>>,
+        slow: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                now: Arc::new(Mutex::new(Instant::now())),
+                finished: Arc::new(Mutex::new(vec![])),
+                slow: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.now.lock().unwrap() += by;
+        }
+
+        fn timed<F: Future>(&self, future: F) -> Timed<F> {
+            let now = self.now.clone();
+            let finished = self.finished.clone();
+            let slow = self.slow.clone();
+            timed("test", future)
+                .with_clock(Arc::new(move || *now.lock().unwrap()))
+                .with_slow_threshold(Duration::from_secs(30))
+                .on_finish(move |elapsed| finished.lock().unwrap().push(elapsed))
+                .on_slow(move |elapsed| slow.lock().unwrap().push(elapsed))
+        }
+
+        fn finished(&self) -> Vec<Duration> {
+            self.finished.lock().unwrap().clone()
+        }
+
+        fn slow(&self) -> Vec<Duration> {
+            self.slow.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn measures_until_the_operation_finishes() {
+        let fixture = Fixture::new();
+        let mut polls = 0;
+        let mut operation = fixture.timed(future::poll_fn(|| {
+            polls += 1;
+            if polls < 3 {
+                Ok(Async::NotReady)
+            } else {
+                Ok::<_, ()>(Async::Ready(polls))
+            }
+        }));
+
+        assert_eq!(Ok(Async::NotReady), operation.poll());
+        fixture.advance(Duration::from_secs(10));
+        assert_eq!(Ok(Async::NotReady), operation.poll());
+        assert!(fixture.finished().is_empty());
+        fixture.advance(Duration::from_secs(5));
+        assert_eq!(Ok(Async::Ready(3)), operation.poll());
+
+        assert_eq!(vec![Duration::from_secs(15)], fixture.finished());
+        assert!(fixture.slow().is_empty());
+    }
+
+    #[test]
+    fn failed_operations_are_measured() {
+        let fixture = Fixture::new();
+        let mut operation = fixture.timed(future::err::<(), _>("failed"));
+
+        assert_eq!(Err("failed"), operation.poll());
+        assert_eq!(vec![Duration::from_secs(0)], fixture.finished());
+    }
+
+    #[test]
+    fn slow_operations_are_reported() {
+        let fixture = Fixture::new();
+        let mut operation = fixture.timed(future::ok::<_, ()>(()));
+        fixture.advance(Duration::from_secs(30));
+        operation.poll().unwrap();
+        assert!(fixture.slow().is_empty());
+
+        let mut operation = fixture.timed(future::ok::<_, ()>(()));
+        fixture.advance(Duration::from_millis(30_001));
+        operation.poll().unwrap();
+        assert_eq!(vec![Duration::from_millis(30_001)], fixture.slow());
+    }
+
+    #[test]
+    fn operations_given_up_on_are_measured_once() {
+        let fixture = Fixture::new();
+        let mut operation = fixture.timed(future::empty::<(), ()>());
+        assert_eq!(Ok(Async::NotReady), operation.poll());
+        fixture.advance(Duration::from_secs(60));
+        operation.finish();
+        operation.finish();
+
+        assert_eq!(vec![Duration::from_secs(60)], fixture.finished());
+        assert_eq!(vec![Duration::from_secs(60)], fixture.slow());
+    }
+
+    #[test]
+    fn secs_are_shown_to_the_millisecond() {
+        assert_eq!("1.500", Secs(Duration::from_millis(1500)).to_string());
+        assert_eq!("300.000", Secs(Duration::from_secs(300)).to_string());
+    }
+}
@@ -5,6 +5,7 @@ use std::convert::AsRef;
 use std::ffi::{CStr, CString, NulError};
 use std::ops::{Deref, Drop};
 use std::os::raw::{c_char, c_uchar, c_void};
+use std::ptr;
 use std::slice;
 use std::str;
 
@@ -42,18 +43,29 @@ unsafe impl Send for Crypto {}
 
 impl Drop for Crypto {
     fn drop(&mut self) {
-        if let Some(f) = self.interface.hsm_client_crypto_destroy {
-            unsafe {
-                f(self.handle);
-            }
-        }
-        unsafe { hsm_client_crypto_deinit() };
+        self.close();
     }
 }
 
 impl Crypto {
     /// Create a new Cryptography implementation for the HSM API.
     pub fn new() -> Result<Self, Error> {
+        let (handle, interface) = Crypto::open()?;
+        Ok(Crypto { handle, interface })
+    }
+
+    /// Shuts the HSM API down and initializes it again. The HSM generates the
+    /// certificates it needs and does not have, such as a quick start device
+    /// CA that was destroyed, when it is initialized.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.close();
+        let (handle, interface) = Crypto::open()?;
+        self.handle = handle;
+        self.interface = interface;
+        Ok(())
+    }
+
+    fn open() -> Result<(HSM_CLIENT_HANDLE, HSM_CLIENT_CRYPTO_INTERFACE_TAG), Error> {
         let result = unsafe { hsm_client_crypto_init() as isize };
         if result != 0 {
             Err(result)?
@@ -69,13 +81,28 @@ impl Crypto {
                 unsafe { hsm_client_crypto_deinit() };
                 Err(ErrorKind::NullResponse)?
             }
-            Ok(Crypto { handle, interface })
+            Ok((handle, interface))
         } else {
             unsafe { hsm_client_crypto_deinit() };
             Err(ErrorKind::NullResponse)?
         }
     }
 
+    /// Releases the handle and shuts the HSM API down, unless that was done
+    /// already by a `reset` that could not initialize it again.
+    fn close(&mut self) {
+        if self.handle.is_null() {
+            return;
+        }
+        if let Some(f) = self.interface.hsm_client_crypto_destroy {
+            unsafe {
+                f(self.handle);
+            }
+        }
+        unsafe { hsm_client_crypto_deinit() };
+        self.handle = ptr::null_mut();
+    }
+
     pub fn get_device_ca_alias(&self) -> String {
         unsafe {
             CStr::from_ptr(hsm_get_device_ca_alias())
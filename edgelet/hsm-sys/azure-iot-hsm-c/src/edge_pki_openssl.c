@@ -52,6 +52,10 @@
 #define ASN1_TIME_STRING_UTC_FORMAT 0x17
 #define ASN1_TIME_STRING_UTC_LEN 13
 
+// certificates are issued slightly in the past to tolerate clock skew between
+// the issuer and the parties validating the certificate
+#define NOT_BEFORE_CLOCK_SKEW_SECS 300
+
 struct SUBJECT_FIELD_OFFSET_TAG
 {
     char field[MAX_SUBJECT_FIELD_SIZE];
@@ -682,7 +686,7 @@ static int cert_set_expiration
 {
     int result;
 
-    if (!X509_gmtime_adj(X509_get_notBefore(x509_cert), 0))
+    if (!X509_gmtime_adj(X509_get_notBefore(x509_cert), -NOT_BEFORE_CLOCK_SKEW_SECS))
     {
         LOG_ERROR("Failure setting not before time");
         result = __FAILURE__;
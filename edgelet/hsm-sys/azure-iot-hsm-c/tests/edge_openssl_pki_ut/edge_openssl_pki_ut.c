@@ -211,6 +211,7 @@ static TEST_MUTEX_HANDLE g_dllByDll;
 #define TEST_VALID_ECC_SERVER_KEY_LEN 256
 #define TEST_VALID_ECC_CLIENT_KEY_LEN 256
 #define MAX_SUBJECT_VALUE_SIZE 129
+#define TEST_NOT_BEFORE_CLOCK_SKEW_SECS 300
 
 #define TEST_PROPS_VALIDITY_SECONDS (uint64_t)1000
 #define TEST_PROPS_COMMON_NAME "test_common_name"
@@ -1366,7 +1367,7 @@ static void test_helper_cert_create_with_subject
     ASSERT_IS_TRUE_WITH_MSG((i < failed_function_size), "Line:" TOSTRING(__LINE__));
     i++;
 
-    STRICT_EXPECTED_CALL(X509_gmtime_adj(&TEST_ASN1_TIME_BEFORE, 0));
+    STRICT_EXPECTED_CALL(X509_gmtime_adj(&TEST_ASN1_TIME_BEFORE, -TEST_NOT_BEFORE_CLOCK_SKEW_SECS));
     ASSERT_IS_TRUE_WITH_MSG((i < failed_function_size), "Line:" TOSTRING(__LINE__));
     failed_function_list[i++] = 1;
 
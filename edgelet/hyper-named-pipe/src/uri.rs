@@ -21,6 +21,17 @@ impl Uri {
         // "host" is the name of the machine which should be "." for localhost
         // and "path" will be "/pipe/<name>" where <name> is the pipe name
         let url = Url::parse(ensure_not_empty!(base_path))?;
+
+        // Docker writes named pipe urls as "npipe:////./pipe/<name>" which
+        // leaves the authority empty and puts the host in the path instead.
+        let url = if url.host_str().map_or(true, |h| h.trim().is_empty())
+            && url.path().starts_with("//")
+        {
+            Url::parse(&format!("{}:{}", url.scheme(), url.path()))?
+        } else {
+            url
+        };
+
         if url.scheme() != NAMED_PIPE_SCHEME {
             Err(ErrorKind::InvalidUrlScheme)?
         } else if url.host_str().map_or("", |h| h.trim()) == "" {
@@ -108,6 +119,16 @@ mod tests {
         assert!(Uri::new("npipe://./pipe/boo", "").is_ok());
     }
 
+    #[test]
+    fn valid_docker_url() {
+        assert!(Uri::new("npipe:////./pipe/boo", "").is_ok());
+    }
+
+    #[test]
+    fn missing_pipe_name_in_docker_url() {
+        assert!(Uri::new("npipe:////./pipe/", "").is_err());
+    }
+
     #[test]
     fn url_path() {
         let url = Uri::new("npipe://./pipe/docker_engine", "/containers/json?all=true").unwrap();
@@ -125,6 +146,15 @@ mod tests {
         assert_eq!(uri, expected.parse::<HyperUri>().unwrap());
     }
 
+    #[test]
+    fn docker_url_hyper_uri() {
+        let uri = Uri::new("npipe:////./pipe/docker_engine", "/containers/json?all=true").unwrap();
+        let uri: HyperUri = uri.into();
+        let expected =
+            "npipe://5c5c2e5c706970655c646f636b65725f656e67696e65/containers/json?all=true";
+        assert_eq!(uri, expected.parse::<HyperUri>().unwrap());
+    }
+
     #[test]
     fn uri_host_scheme() {
         let uri: HyperUri = "foo://boo".parse().unwrap();
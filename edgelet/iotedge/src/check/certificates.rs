@@ -0,0 +1,388 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, TimeZone, Utc};
+use openssl::x509::{X509, X509Ref};
+
+use super::{CheckContext, CheckResult, Checker, Settings};
+
+/// Where the HSM library keeps certificates, relative to the daemon's home
+/// directory.
+const HSM_CERTS_DIR: &str = "hsm/certs";
+
+/// The HSM library names a certificate file after its alias, followed by a
+/// hash of the alias.
+const HSM_CERT_FILE_EXT: &str = ".cert.pem";
+
+/// Aliases of the certificates the HSM library generates. Without
+/// configured certificates, the device and trusted CAs are quickstart
+/// certificates generated under these aliases too.
+const QUICKSTART_DEVICE_CA_ALIAS: &str = "iotedged_ca";
+const QUICKSTART_TRUSTED_CA_ALIAS: &str = "edge_owner_ca";
+const WORKLOAD_CA_ALIAS: &str = "iotedged-workload-ca";
+
+/// An expired device CA stops the daemon from issuing module certificates,
+/// so modules can no longer talk to each other.
+pub struct DeviceCaExpiry;
+
+impl Checker for DeviceCaExpiry {
+    fn id(&self) -> &'static str {
+        "certificates-device-ca"
+    }
+
+    fn description(&self) -> &'static str {
+        "device CA certificate is not about to expire"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        check_expiry(context, |settings| match settings.certificates() {
+            Some(certificates) => read_file(certificates.device_ca_cert()),
+            None => read_hsm_store(settings.homedir(), QUICKSTART_DEVICE_CA_ALIAS),
+        })
+    }
+}
+
+/// Modules stop trusting the edge hub once the root of its certificate
+/// chain expires.
+pub struct TrustedCaExpiry;
+
+impl Checker for TrustedCaExpiry {
+    fn id(&self) -> &'static str {
+        "certificates-trusted-ca"
+    }
+
+    fn description(&self) -> &'static str {
+        "trusted CA certificates are not about to expire"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        check_expiry(context, |settings| match settings.certificates() {
+            Some(certificates) => read_file(certificates.trusted_ca_certs()),
+            None => read_hsm_store(settings.homedir(), QUICKSTART_TRUSTED_CA_ALIAS),
+        })
+    }
+}
+
+/// The daemon generates the workload CA itself, whether or not the device
+/// CA is configured, and signs the module certificates with it.
+pub struct WorkloadCaExpiry;
+
+impl Checker for WorkloadCaExpiry {
+    fn id(&self) -> &'static str {
+        "certificates-workload-ca"
+    }
+
+    fn description(&self) -> &'static str {
+        "workload CA certificate is not about to expire"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        check_expiry(context, |settings| read_hsm_store(settings.homedir(), WORKLOAD_CA_ALIAS))
+    }
+}
+
+/// A certificate and the file it was read from.
+type Certificate = (PathBuf, X509);
+
+/// Finds the certificates a check looks at, then reports the one that
+/// expires first.
+fn check_expiry<F>(context: &CheckContext, certificates: F) -> CheckResult
+where
+    F: FnOnce(&Settings) -> Result<Vec<Certificate>, String>,
+{
+    let settings = match context.settings() {
+        Ok(settings) => settings,
+        Err(err) => return CheckResult::Failed(err.to_string()),
+    };
+    let certificates = match certificates(settings) {
+        Ok(certificates) => certificates,
+        Err(err) => return CheckResult::Failed(err),
+    };
+
+    let mut first = None;
+    for (path, cert) in certificates {
+        let not_after = match not_after(&cert) {
+            Ok(not_after) => not_after,
+            Err(err) => {
+                return CheckResult::Failed(format!(
+                    "Could not read the expiry of {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+        if first
+            .as_ref()
+            .map_or(true, |&(_, _, first_not_after)| not_after < first_not_after)
+        {
+            first = Some((path, cert, not_after));
+        }
+    }
+
+    let (path, cert, not_after) = match first {
+        Some(first) => first,
+        None => return CheckResult::Failed("No certificates were found.".to_string()),
+    };
+    let now = context.now();
+    if not_after <= now {
+        CheckResult::Failed(format!(
+            "Certificate {} in {} expired on {}. Replace it, then restart iotedged.",
+            subject(&cert),
+            path.display(),
+            not_after
+        ))
+    } else if not_after - now <= context.expiry_warning() {
+        CheckResult::Warning(format!(
+            "Certificate {} in {} expires on {}, in {} days. Replace it before then, \
+             and restart iotedged.",
+            subject(&cert),
+            path.display(),
+            not_after,
+            (not_after - now).num_days()
+        ))
+    } else {
+        CheckResult::Ok
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<Certificate>, String> {
+    let pem =
+        fs::read(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let certs = X509::stack_from_pem(&pem)
+        .map_err(|err| format!("Could not parse {}: {}", path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("{} does not contain any certificates", path.display()));
+    }
+    Ok(certs
+        .into_iter()
+        .map(|cert| (path.to_path_buf(), cert))
+        .collect())
+}
+
+/// Reads the certificates the HSM library keeps under `alias`.
+fn read_hsm_store(homedir: &Path, alias: &str) -> Result<Vec<Certificate>, String> {
+    let dir = homedir.join(HSM_CERTS_DIR);
+    let entries =
+        fs::read_dir(&dir).map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+
+    let mut certificates = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+        let is_alias = entry.file_name().to_str().map_or(false, |name| {
+            name.starts_with(alias) && name.ends_with(HSM_CERT_FILE_EXT)
+        });
+        if is_alias {
+            certificates.extend(read_file(&entry.path())?);
+        }
+    }
+    if certificates.is_empty() {
+        Err(format!(
+            "Could not find the {} certificate in {}. Has iotedged been started?",
+            alias,
+            dir.display()
+        ))
+    } else {
+        Ok(certificates)
+    }
+}
+
+fn not_after(cert: &X509Ref) -> Result<DateTime<Utc>, String> {
+    // OpenSSL prints times as "Jan  2 15:04:05 2019 GMT".
+    let not_after = cert.not_after().to_string();
+    Utc.datetime_from_str(&not_after, "%b %e %H:%M:%S %Y GMT")
+        .map_err(|err| format!("{}: {}", not_after, err))
+}
+
+fn subject(cert: &X509Ref) -> String {
+    let entries: Vec<String> = cert
+        .subject_name()
+        .entries()
+        .filter_map(|entry| {
+            let name = entry.object().nid().short_name().ok()?;
+            let value = entry.data().as_utf8().ok()?;
+            Some(format!("{}={}", name, value))
+        }).collect();
+    format!("\"{}\"", entries.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Duration;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Name, X509};
+    use tempdir::TempDir;
+
+    use super::*;
+    use check::Certificates;
+
+    /// A self-signed certificate valid for `days` from today.
+    fn certificate(common_name: &str, days: u32) -> Vec<u8> {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(days).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build().to_pem().unwrap()
+    }
+
+    fn context(settings: Settings, days_from_now: i64) -> CheckContext {
+        CheckContext::new(
+            Err("not needed".to_string()),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(json!({})),
+        ).with_settings(Ok(settings))
+        .with_now(Utc::now() + Duration::days(days_from_now))
+    }
+
+    fn gateway(dir: &TempDir, device_ca: &[u8], trusted_ca: &[u8]) -> Settings {
+        let device_ca_cert = dir.path().join("device-ca.cert.pem");
+        let trusted_ca_certs = dir.path().join("trusted-ca.cert.pem");
+        fs::write(&device_ca_cert, device_ca).unwrap();
+        fs::write(&trusted_ca_certs, trusted_ca).unwrap();
+        Settings::new(dir.path().to_path_buf())
+            .with_certificates(Some(Certificates::new(device_ca_cert, trusted_ca_certs)))
+    }
+
+    /// Lays out certificates the way the HSM library stores them.
+    fn quickstart(dir: &TempDir, certs: &[(&str, &[u8])]) -> Settings {
+        let certs_dir = dir.path().join(HSM_CERTS_DIR);
+        fs::create_dir_all(&certs_dir).unwrap();
+        for &(alias, cert) in certs {
+            let file = format!("{}3a5c0e{}", alias, HSM_CERT_FILE_EXT);
+            fs::write(certs_dir.join(file), cert).unwrap();
+        }
+        Settings::new(dir.path().to_path_buf())
+    }
+
+    fn context_with_window(dir: &TempDir, days: i64) -> CheckContext {
+        let settings = Settings::new(dir.path().to_path_buf()).with_certificates(Some(
+            Certificates::new(
+                dir.path().join("device-ca.cert.pem"),
+                dir.path().join("trusted-ca.cert.pem"),
+            ),
+        ));
+        context(settings, 0).with_expiry_warning(Duration::days(days))
+    }
+
+    #[test]
+    fn certificates_far_from_expiry_are_ok() {
+        let dir = TempDir::new("check").unwrap();
+        let settings = gateway(&dir, &certificate("Device CA", 90), &certificate("Root", 365));
+        let context = context(settings, 0);
+
+        assert_eq!(CheckResult::Ok, DeviceCaExpiry.execute(&context));
+        assert_eq!(CheckResult::Ok, TrustedCaExpiry.execute(&context));
+    }
+
+    #[test]
+    fn certificates_close_to_expiry_warn() {
+        let dir = TempDir::new("check").unwrap();
+        let settings = gateway(&dir, &certificate("Device CA", 20), &certificate("Root", 365));
+        let context = context(settings, 0);
+
+        match DeviceCaExpiry.execute(&context) {
+            CheckResult::Warning(message) => {
+                assert!(message.contains("\"CN=Device CA\""), "{}", message);
+                assert!(message.contains("device-ca.cert.pem"), "{}", message);
+            }
+            other => panic!("Expected a warning, got {:?}", other),
+        }
+        assert_eq!(CheckResult::Ok, TrustedCaExpiry.execute(&context));
+
+        let context = context_with_window(&dir, 10);
+        assert_eq!(CheckResult::Ok, DeviceCaExpiry.execute(&context));
+    }
+
+    #[test]
+    fn expired_certificates_fail() {
+        let dir = TempDir::new("check").unwrap();
+        let settings = gateway(&dir, &certificate("Device CA", 20), &certificate("Root", 365));
+        let context = context(settings, 21);
+
+        match DeviceCaExpiry.execute(&context) {
+            CheckResult::Failed(message) => {
+                assert!(message.contains("expired"), "{}", message);
+                assert!(message.contains("\"CN=Device CA\""), "{}", message);
+            }
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+        assert_eq!(CheckResult::Ok, TrustedCaExpiry.execute(&context));
+    }
+
+    #[test]
+    fn trust_bundle_reports_the_first_certificate_to_expire() {
+        let dir = TempDir::new("check").unwrap();
+        let mut bundle = certificate("Root", 365);
+        bundle.extend(certificate("Old Root", 10));
+        let settings = gateway(&dir, &certificate("Device CA", 90), &bundle);
+
+        match TrustedCaExpiry.execute(&context(settings, 0)) {
+            CheckResult::Warning(message) => {
+                assert!(message.contains("\"CN=Old Root\""), "{}", message)
+            }
+            other => panic!("Expected a warning, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn quickstart_certificates_are_read_from_the_hsm_store() {
+        let dir = TempDir::new("check").unwrap();
+        let settings = quickstart(
+            &dir,
+            &[
+                (QUICKSTART_DEVICE_CA_ALIAS, &certificate("Test Edge Device CA", 90)),
+                (QUICKSTART_TRUSTED_CA_ALIAS, &certificate("Test Edge Owner CA", 90)),
+                (WORKLOAD_CA_ALIAS, &certificate("iotedged workload ca", 20)),
+            ],
+        );
+        let context = context(settings, 0);
+
+        assert_eq!(CheckResult::Ok, DeviceCaExpiry.execute(&context));
+        assert_eq!(CheckResult::Ok, TrustedCaExpiry.execute(&context));
+        match WorkloadCaExpiry.execute(&context) {
+            CheckResult::Warning(message) => {
+                assert!(message.contains("\"CN=iotedged workload ca\""), "{}", message);
+                assert!(message.contains("iotedged-workload-ca3a5c0e"), "{}", message);
+            }
+            other => panic!("Expected a warning, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_certificates_fail() {
+        let dir = TempDir::new("check").unwrap();
+        let context = context(quickstart(&dir, &[]), 0);
+        match WorkloadCaExpiry.execute(&context) {
+            CheckResult::Failed(message) => {
+                assert!(message.contains("iotedged-workload-ca"), "{}", message)
+            }
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+
+        let context = context_with_window(&dir, 30);
+        match DeviceCaExpiry.execute(&context) {
+            CheckResult::Failed(message) => {
+                assert!(message.contains("device-ca.cert.pem"), "{}", message)
+            }
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use serde_json::Value;
+
+use super::{CheckContext, CheckResult, Checker};
+
+/// The logging driver the container engine uses unless configured otherwise.
+const DEFAULT_LOGGING_DRIVER: &str = "json-file";
+
+/// Storage driver status entry that devicemapper reports in loopback mode.
+const LOOPBACK_STATUS: &str = "Data loop file";
+
+/// Without a DNS server in the container engine's configuration, containers
+/// inherit the host's resolver settings, which often point at a local
+/// resolver they cannot reach.
+pub struct ContainerEngineDns;
+
+impl Checker for ContainerEngineDns {
+    fn id(&self) -> &'static str {
+        "container-engine-dns"
+    }
+
+    fn description(&self) -> &'static str {
+        "container engine is configured with DNS"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        let config = match context.container_engine_config() {
+            Ok(config) => config,
+            Err(err) => return CheckResult::Failed(err.to_string()),
+        };
+
+        let has_dns = config
+            .get("dns")
+            .and_then(Value::as_array)
+            .map_or(false, |dns| !dns.is_empty());
+        if has_dns {
+            CheckResult::Ok
+        } else {
+            CheckResult::Warning(format!(
+                "No DNS server is configured, so modules may not be able to resolve IoT Hub. \
+                 Set \"dns\" to the DNS servers of your network in {}, then restart the \
+                 container engine.",
+                context.container_engine_config_file().display()
+            ))
+        }
+    }
+}
+
+/// The default `json-file` logging driver keeps every line a module ever
+/// logged unless it is told to rotate.
+pub struct LogRotation;
+
+impl Checker for LogRotation {
+    fn id(&self) -> &'static str {
+        "container-engine-logs"
+    }
+
+    fn description(&self) -> &'static str {
+        "container engine is configured to rotate module logs"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        let config = match context.container_engine_config() {
+            Ok(config) => config,
+            Err(err) => return CheckResult::Failed(err.to_string()),
+        };
+
+        // The running engine knows best; its configuration file may have
+        // changed since it started.
+        let driver = context
+            .system_info()
+            .ok()
+            .and_then(|info| info.logging_driver())
+            .or_else(|| config.get("log-driver").and_then(Value::as_str))
+            .unwrap_or(DEFAULT_LOGGING_DRIVER);
+        if driver != DEFAULT_LOGGING_DRIVER {
+            return CheckResult::Ok;
+        }
+
+        let rotates = config
+            .get("log-opts")
+            .and_then(|opts| opts.get("max-size"))
+            .is_some();
+        if rotates {
+            CheckResult::Ok
+        } else {
+            CheckResult::Warning(format!(
+                "Module logs are never rotated and may fill up the disk. \
+                 Set \"log-opts\" to {{\"max-size\": \"10m\", \"max-file\": \"3\"}} in {}, \
+                 then restart the container engine. Existing modules keep their settings \
+                 until they are recreated.",
+                context.container_engine_config_file().display()
+            ))
+        }
+    }
+}
+
+/// devicemapper is deprecated, and in loopback mode, which old installs fall
+/// back to without a dedicated block device, it is slow and prone to
+/// running out of space.
+pub struct StorageDriver;
+
+impl Checker for StorageDriver {
+    fn id(&self) -> &'static str {
+        "container-engine-storage-driver"
+    }
+
+    fn description(&self) -> &'static str {
+        "container engine uses a supported storage driver"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        let info = match context.system_info() {
+            Ok(info) => info,
+            Err(err) => {
+                return CheckResult::Failed(format!(
+                    "Could not get the container engine's system information: {}",
+                    err
+                ))
+            }
+        };
+
+        match info.storage_driver() {
+            Some("devicemapper") if info.storage_driver_status().contains_key(LOOPBACK_STATUS) => {
+                CheckResult::Failed(
+                    "The devicemapper storage driver runs in loopback mode, which is not fit \
+                     for production. Switch the container engine to the overlay2 storage \
+                     driver."
+                        .to_string(),
+                )
+            }
+            Some("devicemapper") => CheckResult::Warning(
+                "The devicemapper storage driver is deprecated. Switch the container engine \
+                 to the overlay2 storage driver."
+                    .to_string(),
+            ),
+            _ => CheckResult::Ok,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use edgelet_core::SystemInfo;
+
+    use super::*;
+
+    /// Turns a synthetic container engine /info payload into the system
+    /// information the daemon reports for it.
+    fn system_info(info: &Value) -> SystemInfo {
+        let storage_driver_status: HashMap<String, String> = info["DriverStatus"]
+            .as_array()
+            .map(|status| {
+                status
+                    .iter()
+                    .map(|pair| {
+                        (
+                            pair[0].as_str().unwrap().to_string(),
+                            pair[1].as_str().unwrap().to_string(),
+                        )
+                    }).collect()
+            }).unwrap_or_default();
+        SystemInfo::new("linux".to_string(), "x86_64".to_string())
+            .with_storage_driver(info["Driver"].as_str().map(ToString::to_string))
+            .with_storage_driver_status(storage_driver_status)
+            .with_logging_driver(info["LoggingDriver"].as_str().map(ToString::to_string))
+    }
+
+    fn context(info: &Value, config: Value) -> CheckContext {
+        CheckContext::new(
+            Ok(system_info(info)),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(config),
+        )
+    }
+
+    fn is_warning(result: &CheckResult) -> bool {
+        match *result {
+            CheckResult::Warning(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_failed(result: &CheckResult) -> bool {
+        match *result {
+            CheckResult::Failed(_) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn dns() {
+        let info = json!({ "Driver": "overlay2", "LoggingDriver": "json-file" });
+        let check = ContainerEngineDns;
+
+        assert_eq!(
+            CheckResult::Ok,
+            check.execute(&context(&info, json!({ "dns": ["1.1.1.1"] })))
+        );
+        assert!(is_warning(&check.execute(&context(&info, json!({ "dns": [] })))));
+
+        let result = check.execute(&context(&info, json!({})));
+        match result {
+            CheckResult::Warning(message) => assert!(message.contains("/etc/docker/daemon.json")),
+            other => panic!("Expected a warning, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreadable_config_fails_dns_and_logs() {
+        let context = CheckContext::new(
+            Ok(system_info(&json!({}))),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Err("Could not parse /etc/docker/daemon.json".to_string()),
+        );
+        assert!(is_failed(&ContainerEngineDns.execute(&context)));
+        assert!(is_failed(&LogRotation.execute(&context)));
+        assert_eq!(CheckResult::Ok, StorageDriver.execute(&context));
+    }
+
+    #[test]
+    fn log_rotation() {
+        let json_file = json!({ "Driver": "overlay2", "LoggingDriver": "json-file" });
+        let check = LogRotation;
+
+        assert!(is_warning(&check.execute(&context(&json_file, json!({})))));
+        assert!(is_warning(&check.execute(&context(
+            &json_file,
+            json!({ "log-opts": { "max-file": "3" } })
+        ))));
+        assert_eq!(
+            CheckResult::Ok,
+            check.execute(&context(
+                &json_file,
+                json!({ "log-opts": { "max-size": "10m" } })
+            ))
+        );
+
+        let journald = json!({ "Driver": "overlay2", "LoggingDriver": "journald" });
+        assert_eq!(CheckResult::Ok, check.execute(&context(&journald, json!({}))));
+
+        // Without a logging driver from the engine, the configuration decides.
+        let unknown = json!({ "Driver": "overlay2" });
+        assert!(is_warning(&check.execute(&context(&unknown, json!({})))));
+        assert_eq!(
+            CheckResult::Ok,
+            check.execute(&context(&unknown, json!({ "log-driver": "local" })))
+        );
+    }
+
+    #[test]
+    fn storage_driver() {
+        let check = StorageDriver;
+
+        let overlay = json!({
+            "Driver": "overlay2",
+            "DriverStatus": [["Backing Filesystem", "extfs"], ["Supports d_type", "true"]],
+        });
+        assert_eq!(CheckResult::Ok, check.execute(&context(&overlay, json!({}))));
+
+        let direct_lvm = json!({
+            "Driver": "devicemapper",
+            "DriverStatus": [["Pool Name", "docker-thinpool"], ["Data file", ""]],
+        });
+        assert!(is_warning(&check.execute(&context(&direct_lvm, json!({})))));
+
+        let loopback = json!({
+            "Driver": "devicemapper",
+            "DriverStatus": [
+                ["Pool Name", "docker-8:1-1234-pool"],
+                ["Data loop file", "/var/lib/docker/devicemapper/devicemapper/data"],
+            ],
+        });
+        assert!(is_failed(&check.execute(&context(&loopback, json!({})))));
+    }
+
+    #[test]
+    fn storage_driver_needs_system_info() {
+        let context = CheckContext::new(
+            Err("connection refused".to_string()),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(json!({})),
+        );
+        match StorageDriver.execute(&context) {
+            CheckResult::Failed(message) => assert!(message.contains("connection refused")),
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+    }
+}
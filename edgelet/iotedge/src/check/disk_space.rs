@@ -0,0 +1,178 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use edgelet_core::DiskUsage;
+
+use super::{CheckContext, CheckResult, Checker};
+
+const MB: u64 = 1024 * 1024;
+
+/// Images, container layers and module logs fill up the filesystem the
+/// container engine keeps its data on, and the daemon keeps its state in its
+/// home directory. Deployments fail in confusing ways once either is full.
+pub struct FreeDiskSpace;
+
+impl Checker for FreeDiskSpace {
+    fn id(&self) -> &'static str {
+        "disk-space"
+    }
+
+    fn description(&self) -> &'static str {
+        "there is enough free disk space for modules"
+    }
+
+    fn execute(&self, context: &CheckContext) -> CheckResult {
+        let info = match context.system_info() {
+            Ok(info) => info,
+            Err(err) => {
+                return CheckResult::Failed(format!(
+                    "Could not get the container engine's system information: {}",
+                    err
+                ))
+            }
+        };
+
+        // Daemons that predate the report of system resources say nothing
+        // about disk space.
+        let resources = match info.resources() {
+            Some(resources) => resources,
+            None => return CheckResult::Skipped,
+        };
+
+        let problems: Vec<String> = resources
+            .runtime_root()
+            .into_iter()
+            .chain(resources.homedir())
+            .filter_map(|usage| problem(usage, context.disk_space_warning()))
+            .collect();
+        if problems.is_empty() {
+            CheckResult::Ok
+        } else {
+            CheckResult::Warning(problems.join(" "))
+        }
+    }
+}
+
+fn problem(usage: &DiskUsage, warning: u64) -> Option<String> {
+    match usage.space() {
+        Ok(space) if space.available_bytes() < warning => Some(format!(
+            "Only {} MB of {} MB is free on the filesystem {} is on. Free up some disk space, \
+             for example by removing images that no module uses.",
+            space.available_bytes() / MB,
+            space.total_bytes() / MB,
+            usage.path().display()
+        )),
+        Ok(_) => None,
+        Err(err) => Some(format!(
+            "Could not find out how much disk space is free on the filesystem {} is on: {}",
+            usage.path().display(),
+            err
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use edgelet_core::{DiskMonitor, DiskSpace, DiskStats, SystemInfo};
+    use serde_json::Value;
+
+    use super::*;
+
+    const GB: u64 = 1024 * MB;
+
+    /// Knows the disk space of some paths and is denied access to the rest.
+    struct TestDiskStats(HashMap<PathBuf, DiskSpace>);
+
+    impl DiskStats for TestDiskStats {
+        fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+            self.0
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::PermissionDenied))
+        }
+    }
+
+    fn context(docker: Option<DiskSpace>, iotedge: DiskSpace) -> CheckContext {
+        let mut known = HashMap::new();
+        known.insert(PathBuf::from("/var/lib/iotedge"), iotedge);
+        if let Some(docker) = docker {
+            known.insert(PathBuf::from("/var/lib/docker"), docker);
+        }
+        let monitor = DiskMonitor::new(
+            Arc::new(TestDiskStats(known)),
+            PathBuf::from("/var/lib/iotedge"),
+        );
+        let info = SystemInfo::new("linux".to_string(), "x86_64".to_string())
+            .with_resources(Some(monitor.resources(Some(Path::new("/var/lib/docker")))));
+        CheckContext::new(
+            Ok(info),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(Value::Null),
+        ).with_disk_space_warning(GB)
+    }
+
+    fn warning(result: CheckResult) -> String {
+        match result {
+            CheckResult::Warning(message) => message,
+            other => panic!("Expected a warning, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn enough_disk_space() {
+        let context = context(
+            Some(DiskSpace::new(32 * GB, 20 * GB)),
+            DiskSpace::new(32 * GB, 20 * GB),
+        );
+        assert_eq!(CheckResult::Ok, FreeDiskSpace.execute(&context));
+    }
+
+    #[test]
+    fn low_disk_space_is_a_warning() {
+        let context = context(
+            Some(DiskSpace::new(32 * GB, 512 * MB)),
+            DiskSpace::new(8 * GB, 4 * GB),
+        );
+        let message = warning(FreeDiskSpace.execute(&context));
+        assert!(message.contains("Only 512 MB of 32768 MB"), "{}", message);
+        assert!(message.contains("/var/lib/docker"), "{}", message);
+        assert!(!message.contains("/var/lib/iotedge"), "{}", message);
+    }
+
+    #[test]
+    fn unmeasured_disk_space_is_a_warning() {
+        let context = context(None, DiskSpace::new(8 * GB, 4 * GB));
+        let message = warning(FreeDiskSpace.execute(&context));
+        assert!(message.contains("/var/lib/docker"), "{}", message);
+        assert!(
+            message.contains(&io::Error::from(io::ErrorKind::PermissionDenied).to_string()),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn disk_space_needs_system_resources() {
+        let info = SystemInfo::new("linux".to_string(), "x86_64".to_string());
+        let context = CheckContext::new(
+            Ok(info),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(Value::Null),
+        );
+        assert_eq!(CheckResult::Skipped, FreeDiskSpace.execute(&context));
+
+        let context = CheckContext::new(
+            Err("connection refused".to_string()),
+            PathBuf::from("/etc/docker/daemon.json"),
+            Ok(Value::Null),
+        );
+        match FreeDiskSpace.execute(&context) {
+            CheckResult::Failed(message) => assert!(message.contains("connection refused")),
+            other => panic!("Expected a failure, got {:?}", other),
+        }
+    }
+}
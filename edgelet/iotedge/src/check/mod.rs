@@ -0,0 +1,633 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use edgelet_core::{ModuleRuntime, SystemInfo};
+use futures::Future;
+use serde_json::{self, Value};
+use serde_yaml;
+
+use error::{error_message, Error, ErrorKind};
+use Command;
+
+mod certificates;
+mod container_engine;
+mod disk_space;
+
+pub use self::certificates::{DeviceCaExpiry, TrustedCaExpiry, WorkloadCaExpiry};
+pub use self::container_engine::{ContainerEngineDns, LogRotation, StorageDriver};
+pub use self::disk_space::FreeDiskSpace;
+
+#[cfg(unix)]
+const CONFIG_FILE: &str = "/etc/iotedge/config.yaml";
+#[cfg(windows)]
+const CONFIG_FILE: &str = r"C:\ProgramData\iotedge\config.yaml";
+
+#[cfg(unix)]
+const DEFAULT_HOMEDIR: &str = "/var/lib/iotedge";
+#[cfg(windows)]
+const DEFAULT_HOMEDIR: &str = r"C:\ProgramData\iotedge";
+
+/// How close to expiry a certificate gets before the checks warn about it.
+const EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// How little free disk space the checks warn about, in megabytes.
+pub const DISK_SPACE_WARNING_MB: u64 = 1024;
+
+#[cfg(unix)]
+const CONTAINER_ENGINE_CONFIG_FILE: &str = "/etc/docker/daemon.json";
+#[cfg(windows)]
+const CONTAINER_ENGINE_CONFIG_FILE: &str = r"C:\ProgramData\iotedge-moby\config\daemon.json";
+
+/// The outcome of a single check. Warnings and failures carry the text that
+/// tells the user what to do about them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheckResult {
+    Ok,
+    Warning(String),
+    Failed(String),
+    Skipped,
+}
+
+/// The parts of the daemon's configuration file the checks look at.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Settings {
+    #[serde(default = "default_homedir")]
+    homedir: PathBuf,
+    certificates: Option<Certificates>,
+}
+
+impl Settings {
+    pub fn new(homedir: PathBuf) -> Self {
+        Settings {
+            homedir,
+            certificates: None,
+        }
+    }
+
+    pub fn with_certificates(mut self, certificates: Option<Certificates>) -> Self {
+        self.certificates = certificates;
+        self
+    }
+
+    pub fn homedir(&self) -> &Path {
+        &self.homedir
+    }
+
+    /// The configured device CA, if any. Without one, the daemon runs with
+    /// quickstart certificates generated by the HSM library.
+    pub fn certificates(&self) -> Option<&Certificates> {
+        self.certificates.as_ref()
+    }
+}
+
+fn default_homedir() -> PathBuf {
+    PathBuf::from(DEFAULT_HOMEDIR)
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Certificates {
+    device_ca_cert: PathBuf,
+    trusted_ca_certs: PathBuf,
+}
+
+impl Certificates {
+    pub fn new(device_ca_cert: PathBuf, trusted_ca_certs: PathBuf) -> Self {
+        Certificates {
+            device_ca_cert,
+            trusted_ca_certs,
+        }
+    }
+
+    pub fn device_ca_cert(&self) -> &Path {
+        &self.device_ca_cert
+    }
+
+    pub fn trusted_ca_certs(&self) -> &Path {
+        &self.trusted_ca_certs
+    }
+}
+
+/// What the checks get to look at. It is collected once, before any check
+/// runs, so that checks stay synchronous and easy to test.
+pub struct CheckContext {
+    system_info: Result<SystemInfo, String>,
+    container_engine_config_file: PathBuf,
+    container_engine_config: Result<Value, String>,
+    settings: Result<Settings, String>,
+    expiry_warning: Duration,
+    disk_space_warning: u64,
+    now: DateTime<Utc>,
+}
+
+impl CheckContext {
+    pub fn new(
+        system_info: Result<SystemInfo, String>,
+        container_engine_config_file: PathBuf,
+        container_engine_config: Result<Value, String>,
+    ) -> Self {
+        CheckContext {
+            system_info,
+            container_engine_config_file,
+            container_engine_config,
+            settings: Err("The daemon's configuration file was not read".to_string()),
+            expiry_warning: Duration::days(EXPIRY_WARNING_DAYS),
+            disk_space_warning: DISK_SPACE_WARNING_MB * 1024 * 1024,
+            now: Utc::now(),
+        }
+    }
+
+    pub fn with_settings(mut self, settings: Result<Settings, String>) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_expiry_warning(mut self, expiry_warning: Duration) -> Self {
+        self.expiry_warning = expiry_warning;
+        self
+    }
+
+    /// The free disk space, in bytes, below which a filesystem is running
+    /// out.
+    pub fn with_disk_space_warning(mut self, disk_space_warning: u64) -> Self {
+        self.disk_space_warning = disk_space_warning;
+        self
+    }
+
+    /// The time certificate expiry is measured from.
+    pub fn with_now(mut self, now: DateTime<Utc>) -> Self {
+        self.now = now;
+        self
+    }
+
+    /// What the container runtime reported about itself, through the daemon.
+    pub fn system_info(&self) -> Result<&SystemInfo, &str> {
+        self.system_info.as_ref().map_err(AsRef::as_ref)
+    }
+
+    pub fn container_engine_config_file(&self) -> &Path {
+        &self.container_engine_config_file
+    }
+
+    /// The container engine's configuration file. A missing file reads as
+    /// an empty configuration, which is what the engine does too.
+    pub fn container_engine_config(&self) -> Result<&Value, &str> {
+        self.container_engine_config
+            .as_ref()
+            .map_err(AsRef::as_ref)
+    }
+
+    /// The daemon's configuration file.
+    pub fn settings(&self) -> Result<&Settings, &str> {
+        self.settings.as_ref().map_err(AsRef::as_ref)
+    }
+
+    pub fn expiry_warning(&self) -> Duration {
+        self.expiry_warning
+    }
+
+    pub fn disk_space_warning(&self) -> u64 {
+        self.disk_space_warning
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now
+    }
+}
+
+pub trait Checker {
+    /// Names the check for `--dont-check` and in JSON output.
+    fn id(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn execute(&self, context: &CheckContext) -> CheckResult;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+pub struct Check<M, W> {
+    runtime: M,
+    checks: Vec<Box<Checker + Send>>,
+    dont_check: Vec<String>,
+    container_engine_config_file: PathBuf,
+    config_file: PathBuf,
+    expiry_warning: Duration,
+    disk_space_warning: u64,
+    output_format: OutputFormat,
+    output: Arc<Mutex<W>>,
+}
+
+impl<M, W> Check<M, W> {
+    pub fn new(runtime: M, output: W) -> Self {
+        Check {
+            runtime,
+            checks: vec![
+                Box::new(ContainerEngineDns),
+                Box::new(LogRotation),
+                Box::new(StorageDriver),
+                Box::new(DeviceCaExpiry),
+                Box::new(TrustedCaExpiry),
+                Box::new(WorkloadCaExpiry),
+                Box::new(FreeDiskSpace),
+            ],
+            dont_check: vec![],
+            container_engine_config_file: PathBuf::from(CONTAINER_ENGINE_CONFIG_FILE),
+            config_file: PathBuf::from(CONFIG_FILE),
+            expiry_warning: Duration::days(EXPIRY_WARNING_DAYS),
+            disk_space_warning: DISK_SPACE_WARNING_MB * 1024 * 1024,
+            output_format: OutputFormat::Text,
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// Ids of the checks to skip.
+    pub fn with_dont_check(mut self, dont_check: Vec<String>) -> Self {
+        self.dont_check = dont_check;
+        self
+    }
+
+    pub fn with_container_engine_config_file(mut self, path: PathBuf) -> Self {
+        self.container_engine_config_file = path;
+        self
+    }
+
+    /// The daemon's configuration file, which says where its certificates
+    /// are.
+    pub fn with_config_file(mut self, path: PathBuf) -> Self {
+        self.config_file = path;
+        self
+    }
+
+    /// Warns about certificates that expire within `expiry_warning`.
+    pub fn with_expiry_warning(mut self, expiry_warning: Duration) -> Self {
+        self.expiry_warning = expiry_warning;
+        self
+    }
+
+    /// Warns about filesystems with less than `disk_space_warning` bytes
+    /// free.
+    pub fn with_disk_space_warning(mut self, disk_space_warning: u64) -> Self {
+        self.disk_space_warning = disk_space_warning;
+        self
+    }
+
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+}
+
+impl<M, W> Command for Check<M, W>
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+    W: 'static + Write + Send,
+{
+    type Future = Box<Future<Item = (), Error = Error> + Send>;
+
+    fn execute(&mut self) -> Self::Future {
+        let write = self.output.clone();
+        let config_file = self.container_engine_config_file.clone();
+        let config = read_container_engine_config(&config_file);
+        let settings = read_settings(&self.config_file);
+        let expiry_warning = self.expiry_warning;
+        let disk_space_warning = self.disk_space_warning;
+        let checks = ::std::mem::replace(&mut self.checks, vec![]);
+        let dont_check = self.dont_check.clone();
+        let output_format = self.output_format;
+
+        let result = self.runtime.system_info().then(move |system_info| {
+            let system_info = system_info.map_err(|err| {
+                let err: Error = err.into();
+                error_message(&err)
+            });
+            let context = CheckContext::new(system_info, config_file, config)
+                .with_settings(settings)
+                .with_expiry_warning(expiry_warning)
+                .with_disk_space_warning(disk_space_warning);
+            let results = run_checks(&checks, &dont_check, &context);
+
+            let mut w = write.lock().unwrap();
+            match output_format {
+                OutputFormat::Text => write_text(&mut *w, &results)?,
+                OutputFormat::Json => write_json(&mut *w, &results)?,
+            }
+            w.flush()?;
+
+            if results.iter().any(|r| r.result.is_failed()) {
+                Err(Error::from(ErrorKind::ChecksFailed))
+            } else {
+                Ok(())
+            }
+        });
+        Box::new(result)
+    }
+}
+
+impl CheckResult {
+    fn is_failed(&self) -> bool {
+        match *self {
+            CheckResult::Failed(_) => true,
+            _ => false,
+        }
+    }
+}
+
+struct Outcome {
+    id: &'static str,
+    description: &'static str,
+    result: CheckResult,
+}
+
+#[derive(Serialize)]
+struct JsonOutcome<'a> {
+    id: &'a str,
+    description: &'a str,
+    result: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+#[derive(Default, Serialize)]
+struct Summary {
+    ok: usize,
+    warnings: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    checks: Vec<JsonOutcome<'a>>,
+    summary: Summary,
+}
+
+fn run_checks(
+    checks: &[Box<Checker + Send>],
+    dont_check: &[String],
+    context: &CheckContext,
+) -> Vec<Outcome> {
+    checks
+        .iter()
+        .map(|check| Outcome {
+            id: check.id(),
+            description: check.description(),
+            result: if dont_check.iter().any(|id| id == check.id()) {
+                CheckResult::Skipped
+            } else {
+                check.execute(context)
+            },
+        }).collect()
+}
+
+fn summarize(results: &[Outcome]) -> Summary {
+    let mut summary = Summary::default();
+    for outcome in results {
+        match outcome.result {
+            CheckResult::Ok => summary.ok += 1,
+            CheckResult::Warning(_) => summary.warnings += 1,
+            CheckResult::Failed(_) => summary.failed += 1,
+            CheckResult::Skipped => summary.skipped += 1,
+        }
+    }
+    summary
+}
+
+fn write_text<W: Write>(w: &mut W, results: &[Outcome]) -> io::Result<()> {
+    for outcome in results {
+        let (label, message) = match outcome.result {
+            CheckResult::Ok => ("[OK]", None),
+            CheckResult::Warning(ref message) => ("[WARNING]", Some(message)),
+            CheckResult::Failed(ref message) => ("[FAILED]", Some(message)),
+            CheckResult::Skipped => ("[SKIPPED]", None),
+        };
+        writeln!(w, "{:<9} {}", label, outcome.description)?;
+        if let Some(message) = message {
+            writeln!(w, "          {}", message)?;
+        }
+    }
+
+    let summary = summarize(results);
+    writeln!(
+        w,
+        "\n{} ok, {} warnings, {} failed, {} skipped",
+        summary.ok, summary.warnings, summary.failed, summary.skipped
+    )
+}
+
+fn write_json<W: Write>(w: &mut W, results: &[Outcome]) -> io::Result<()> {
+    let checks = results
+        .iter()
+        .map(|outcome| {
+            let (result, message) = match outcome.result {
+                CheckResult::Ok => ("ok", None),
+                CheckResult::Warning(ref message) => ("warning", Some(message.as_str())),
+                CheckResult::Failed(ref message) => ("failed", Some(message.as_str())),
+                CheckResult::Skipped => ("skipped", None),
+            };
+            JsonOutcome {
+                id: outcome.id,
+                description: outcome.description,
+                result,
+                message,
+            }
+        }).collect();
+    let report = JsonReport {
+        checks,
+        summary: summarize(results),
+    };
+    serde_json::to_writer_pretty(&mut *w, &report)?;
+    writeln!(w)
+}
+
+fn read_container_engine_config(path: &Path) -> Result<Value, String> {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(file)
+            .map_err(|err| format!("Could not parse {}: {}", path.display(), err)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(Value::Object(Default::default()))
+        }
+        Err(err) => Err(format!("Could not read {}: {}", path.display(), err)),
+    }
+}
+
+fn read_settings(path: &Path) -> Result<Settings, String> {
+    let file =
+        File::open(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    serde_yaml::from_reader(file)
+        .map_err(|err| format!("Could not parse {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use edgelet_core::ModuleRuntimeState;
+    use edgelet_test_utils::module::*;
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, Fail)]
+    #[fail(display = "General error")]
+    pub struct TestError;
+
+    impl From<TestError> for Error {
+        fn from(_: TestError) -> Self {
+            Error::from(ErrorKind::ModuleRuntime)
+        }
+    }
+
+    fn runtime() -> TestRuntime<TestError> {
+        let module = TestModule::new(
+            "edgeAgent".to_string(),
+            TestConfig::new("microsoft/azureiotedge-agent:1.0".to_string()),
+            Ok(ModuleRuntimeState::default()),
+        );
+        TestRuntime::new(Ok(module))
+    }
+
+    // The certificate checks have tests of their own.
+    const CERTIFICATE_CHECKS: &[&str] = &[
+        "certificates-device-ca",
+        "certificates-trusted-ca",
+        "certificates-workload-ca",
+    ];
+
+    fn check(config: &str, dont_check: &[&str], format: OutputFormat) -> (String, bool) {
+        let dir = TempDir::new("check").unwrap();
+        let path = dir.path().join("daemon.json");
+        fs::write(&path, config).unwrap();
+        let dont_check = dont_check.iter().chain(CERTIFICATE_CHECKS);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let mut check = Check::new(runtime(), SharedWriter(output.clone()))
+            .with_container_engine_config_file(path)
+            .with_dont_check(dont_check.map(ToString::to_string).collect())
+            .with_output_format(format);
+        let succeeded = check.execute().wait().is_ok();
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        (output, succeeded)
+    }
+
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const GOOD_CONFIG: &str = r#"{
+        "dns": ["1.1.1.1"],
+        "log-driver": "json-file",
+        "log-opts": { "max-size": "10m" }
+    }"#;
+
+    #[test]
+    fn text_output_lists_every_check() {
+        let (output, succeeded) = check(GOOD_CONFIG, &[], OutputFormat::Text);
+        assert!(succeeded, "{}", output);
+        assert!(output.contains("[OK]      container engine is configured with DNS"));
+        assert!(output.contains("\n3 ok, 0 warnings, 0 failed, 4 skipped\n"));
+    }
+
+    #[test]
+    fn skipped_checks_are_reported_as_skipped() {
+        let (output, succeeded) = check(
+            "{}",
+            &["container-engine-dns", "container-engine-logs"],
+            OutputFormat::Text,
+        );
+        assert!(succeeded, "{}", output);
+        assert!(output.contains("[SKIPPED] container engine is configured with DNS"));
+        assert!(output.contains("1 ok, 0 warnings, 0 failed, 6 skipped"));
+    }
+
+    #[test]
+    fn json_output_has_results_and_summary() {
+        let (output, succeeded) = check("{}", &["container-engine-logs"], OutputFormat::Json);
+        assert!(succeeded);
+
+        let report: Value = serde_json::from_str(&output).unwrap();
+        let checks = report["checks"].as_array().unwrap();
+        assert_eq!(7, checks.len());
+        assert_eq!("container-engine-dns", checks[0]["id"]);
+        assert_eq!("warning", checks[0]["result"]);
+        assert!(checks[0]["message"].as_str().unwrap().contains("daemon.json"));
+        assert_eq!("skipped", checks[1]["result"]);
+        assert_eq!(None, checks[1].get("message"));
+        assert_eq!(1, report["summary"]["warnings"]);
+        assert_eq!(5, report["summary"]["skipped"]);
+    }
+
+    #[test]
+    fn failed_checks_fail_the_command() {
+        let (output, succeeded) = check("not json", &[], OutputFormat::Text);
+        assert!(!succeeded);
+        assert!(output.contains("[FAILED]  container engine is configured with DNS"));
+        assert!(output.contains("Could not parse"));
+    }
+
+    #[test]
+    fn missing_config_file_is_empty() {
+        let dir = TempDir::new("check").unwrap();
+        let config = read_container_engine_config(&dir.path().join("daemon.json"));
+        assert_eq!(Ok(json!({})), config);
+    }
+
+    #[test]
+    fn runtime_errors_are_passed_to_checks() {
+        let mut check = Check::new(
+            TestRuntime::<TestError>::new(Err(TestError)),
+            Vec::new(),
+        ).with_dont_check(
+            ["container-engine-dns", "container-engine-logs"]
+                .iter()
+                .chain(CERTIFICATE_CHECKS)
+                .map(ToString::to_string)
+                .collect(),
+        );
+        assert!(check.execute().wait().is_err());
+    }
+
+    #[test]
+    fn settings_are_read_from_the_daemon_config_file() {
+        let dir = TempDir::new("check").unwrap();
+        let path = dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            "provisioning:\n  source: manual\n\
+             certificates:\n  device_ca_cert: /certs/device-ca.cert.pem\n  \
+             device_ca_pk: /certs/device-ca.key.pem\n  trusted_ca_certs: /certs/root.cert.pem\n\
+             homedir: /data/iotedge\n",
+        ).unwrap();
+        let settings = read_settings(&path).unwrap();
+        assert_eq!(Path::new("/data/iotedge"), settings.homedir());
+        assert_eq!(
+            Some(&Certificates::new(
+                PathBuf::from("/certs/device-ca.cert.pem"),
+                PathBuf::from("/certs/root.cert.pem"),
+            )),
+            settings.certificates()
+        );
+
+        fs::write(&path, "provisioning:\n  source: manual\n").unwrap();
+        let settings = read_settings(&path).unwrap();
+        assert_eq!(Path::new(DEFAULT_HOMEDIR), settings.homedir());
+        assert_eq!(None, settings.certificates());
+
+        assert!(read_settings(&dir.path().join("missing.yaml")).is_err());
+    }
+}
@@ -13,7 +13,7 @@ pub struct Error {
     inner: Context<ErrorKind>,
 }
 
-#[derive(Clone, Copy, Debug, Fail)]
+#[derive(Clone, Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "A module runtime error occurred.")]
     ModuleRuntime,
@@ -25,6 +25,23 @@ pub enum ErrorKind {
     HttpMgmt,
     #[fail(display = "Missing host")]
     NoHost,
+    #[fail(display = "One or more checks failed.")]
+    ChecksFailed,
+    #[fail(
+        display = "Invalid time {:?}. Use an RFC3339 timestamp like 2018-10-15T14:00:00Z, \
+                   or a duration like 2h or 45m.",
+        _0
+    )]
+    InvalidTime(String),
+    #[fail(
+        display = "Invalid tail {:?}. Use all or a number of lines.",
+        _0
+    )]
+    InvalidTail(String),
+    #[fail(display = "Could not create the support bundle.")]
+    SupportBundle,
+    #[fail(display = "Could not restart {} of {} modules.", failed, total)]
+    RestartFailed { failed: usize, total: usize },
 }
 
 impl Fail for Error {
@@ -51,6 +68,15 @@ impl Error {
     pub fn kind(&self) -> &ErrorKind {
         self.inner.get_context()
     }
+
+    /// What the iotedge process exits with because of this error.
+    pub fn exit_code(&self) -> i32 {
+        match *self.kind() {
+            ErrorKind::RestartFailed { failed, total } if failed < total => 2,
+            ErrorKind::RestartFailed { .. } => 3,
+            _ => 1,
+        }
+    }
 }
 
 impl From<ErrorKind> for Error {
@@ -90,3 +116,14 @@ impl From<HttpMgmtError> for Error {
         }
     }
 }
+
+/// Puts an error and its causes on one line.
+pub fn error_message(error: &Fail) -> String {
+    let mut message = error.to_string();
+    let mut fail = error;
+    while let Some(cause) = fail.cause() {
+        message.push_str(&format!(": {}", cause));
+        fail = cause;
+    }
+    message
+}
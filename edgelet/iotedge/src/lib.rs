@@ -15,29 +15,52 @@ extern crate chrono_humanize;
 #[macro_use]
 extern crate clap;
 extern crate edgelet_core;
+extern crate edgelet_http;
 extern crate edgelet_http_mgmt;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 #[macro_use]
 extern crate futures;
+extern crate hyper;
+extern crate openssl;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg(not(test))]
+extern crate serde_json;
+extern crate serde_yaml;
 extern crate tabwriter;
 extern crate tokio;
 extern crate url;
+extern crate zip;
+
+#[cfg(test)]
+extern crate edgelet_test_utils;
+// Need macros from serde_json for unit tests.
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+#[cfg(test)]
+extern crate tempdir;
 
 use futures::Future;
 
+mod check;
 mod error;
 mod list;
 mod logs;
 mod restart;
+mod support_bundle;
 mod unknown;
 mod version;
 
+pub use check::{Check, CheckResult, Checker, OutputFormat};
 pub use error::{Error, ErrorKind};
-pub use list::List;
-pub use logs::Logs;
+pub use list::{List, ListFormat, ListSort};
+pub use logs::{parse_time, since_timestamp, Logs};
 pub use restart::Restart;
+pub use support_bundle::SupportBundle;
 pub use unknown::Unknown;
 pub use version::Version;
 
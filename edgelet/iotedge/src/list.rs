@@ -1,21 +1,51 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::cmp::{self, Ordering};
 use std::fmt::Display;
-use std::io::Write;
+use std::io::{self, Write};
 use std::sync::{Arc, Mutex};
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use chrono_humanize::{Accuracy, HumanTime, Tense};
 use edgelet_core::{Module, ModuleRuntime, ModuleRuntimeState, ModuleStatus};
 use futures::{Future, Stream};
+use serde::Serialize;
+use serde_json;
 use tabwriter::TabWriter;
 
 use error::Error;
 use Command;
 
+const MIN_WIDTH: usize = 15;
+const PADDING: usize = 2;
+const ELLIPSIS: &str = "...";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    Table,
+    /// The table with the full config, the time a module started and the
+    /// code it last exited with.
+    Wide,
+    /// An array of the modules as the management API describes them. See
+    /// ModuleDetails in the management API docs for the fields.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListSort {
+    Name,
+    Status,
+    /// Modules that started earliest first, then those that have not.
+    Started,
+}
+
 pub struct List<M, W> {
     runtime: M,
     output: Arc<Mutex<TabWriter<W>>>,
+    format: ListFormat,
+    sort: Option<ListSort>,
+    width: Option<usize>,
+    now: Option<DateTime<Utc>>,
 }
 
 impl<M, W> List<M, W>
@@ -23,18 +53,47 @@ where
     W: Write,
 {
     pub fn new(runtime: M, output: W) -> Self {
-        let tab = TabWriter::new(output).minwidth(15);
+        let tab = TabWriter::new(output).minwidth(MIN_WIDTH).padding(PADDING);
         List {
             runtime,
             output: Arc::new(Mutex::new(tab)),
+            format: ListFormat::Table,
+            sort: None,
+            width: None,
+            now: None,
         }
     }
+
+    pub fn with_format(mut self, format: ListFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Without a sort, modules are listed in the order the runtime returns them.
+    pub fn with_sort(mut self, sort: Option<ListSort>) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Truncates the config column of the table so that rows fit in `width`
+    /// characters.
+    pub fn with_width(mut self, width: Option<usize>) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Describes how long ago modules started or stopped relative to `now`
+    /// rather than the current time.
+    pub fn with_now(mut self, now: DateTime<Utc>) -> Self {
+        self.now = Some(now);
+        self
+    }
 }
 
 impl<M, W> Command for List<M, W>
 where
     M: 'static + ModuleRuntime + Clone,
-    M::Module: Clone,
+    M::Module: Clone + Serialize,
     M::Config: Display,
     M::Error: Into<Error>,
     <M::Module as Module>::Error: Into<Error>,
@@ -44,23 +103,42 @@ where
 
     fn execute(&mut self) -> Self::Future {
         let write = self.output.clone();
+        let format = self.format;
+        let sort = self.sort;
+        let width = self.width;
+        let now = self.now;
         let result = self
             .runtime
             .list_with_details()
             .map_err(|e| e.into())
             .collect()
-            .and_then(move |result| {
+            .and_then(move |mut result| {
+                if let Some(sort) = sort {
+                    result.sort_by(|a, b| compare(sort, a, b));
+                }
+
                 let mut w = write.lock().unwrap();
-                writeln!(w, "NAME\tSTATUS\tDESCRIPTION\tCONFIG")?;
-                for (module, state) in result {
-                    writeln!(
-                        w,
-                        "{}\t{}\t{}\t{}",
-                        module.name(),
-                        state.status(),
-                        humanize_state(&state),
-                        module.config(),
-                    )?;
+                if format == ListFormat::Json {
+                    let modules: Vec<&M::Module> =
+                        result.iter().map(|(module, _)| module).collect();
+                    serde_json::to_writer_pretty(&mut *w, &modules).map_err(io::Error::from)?;
+                    writeln!(w)?;
+                } else {
+                    let now = now.unwrap_or_else(Utc::now);
+                    let rows = result
+                        .iter()
+                        .map(|(module, state)| row(format, now, module, state))
+                        .collect();
+                    let headers = if format == ListFormat::Wide {
+                        vec!["NAME", "STATUS", "DESCRIPTION", "STARTED", "EXIT CODE", "CONFIG"]
+                    } else {
+                        vec!["NAME", "STATUS", "DESCRIPTION", "CONFIG"]
+                    };
+                    let headers = headers.into_iter().map(ToString::to_string).collect();
+                    let width = if format == ListFormat::Table { width } else { None };
+                    for row in fit(headers, rows, width) {
+                        writeln!(w, "{}", row.join("\t"))?;
+                    }
                 }
                 w.flush()?;
                 Ok(())
@@ -69,7 +147,97 @@ where
     }
 }
 
-fn humanize_state(state: &ModuleRuntimeState) -> String {
+fn compare<M>(
+    sort: ListSort,
+    a: &(M, ModuleRuntimeState),
+    b: &(M, ModuleRuntimeState),
+) -> Ordering
+where
+    M: Module,
+{
+    match sort {
+        ListSort::Name => a.0.name().cmp(b.0.name()),
+        ListSort::Status => a
+            .1
+            .status()
+            .to_string()
+            .cmp(&b.1.status().to_string())
+            .then_with(|| a.0.name().cmp(b.0.name())),
+        ListSort::Started => match (a.1.started_at(), b.1.started_at()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }.then_with(|| a.0.name().cmp(b.0.name())),
+    }
+}
+
+fn row<M>(
+    format: ListFormat,
+    now: DateTime<Utc>,
+    module: &M,
+    state: &ModuleRuntimeState,
+) -> Vec<String>
+where
+    M: Module,
+    M::Config: Display,
+{
+    let mut row = vec![
+        module.name().to_string(),
+        state.status().to_string(),
+        humanize_state(state, now),
+    ];
+    if format == ListFormat::Wide {
+        row.push(state.started_at().map_or_else(
+            || "-".to_string(),
+            |time| time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ));
+        row.push(
+            state
+                .exit_code()
+                .map_or_else(|| "-".to_string(), |code| code.to_string()),
+        );
+    }
+    row.push(module.config().to_string());
+    row
+}
+
+/// Truncates the last column of `rows` so that, once the tab writer has
+/// aligned the others, rows are at most `width` characters wide. The last
+/// column is left as is when too little of it would remain to be useful.
+fn fit(headers: Vec<String>, rows: Vec<Vec<String>>, width: Option<usize>) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = Some(headers).into_iter().chain(rows).collect();
+    let width = match width {
+        Some(width) => width,
+        None => return rows,
+    };
+
+    let columns = rows[0].len();
+    let aligned: usize = (0..columns - 1)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count() + PADDING)
+                .fold(MIN_WIDTH, cmp::max)
+        }).sum();
+    let available = width.saturating_sub(aligned);
+    if available <= ELLIPSIS.len() * 2 {
+        return rows;
+    }
+
+    for row in &mut rows {
+        let last = &mut row[columns - 1];
+        if last.chars().count() > available {
+            *last = last
+                .chars()
+                .take(available - ELLIPSIS.len())
+                .chain(ELLIPSIS.chars())
+                .collect();
+        }
+    }
+    rows
+}
+
+fn humanize_state(state: &ModuleRuntimeState, now: DateTime<Utc>) -> String {
     match *state.status() {
         ModuleStatus::Unknown => "Unknown".to_string(),
         ModuleStatus::Stopped => state.finished_at().map_or_else(
@@ -77,7 +245,7 @@ fn humanize_state(state: &ModuleRuntimeState) -> String {
             |time| {
                 format!(
                     "Stopped {}",
-                    time_string(&HumanTime::from(Utc::now() - *time), Tense::Past)
+                    time_string(&HumanTime::from(now - *time), Tense::Past)
                 )
             },
         ),
@@ -88,16 +256,17 @@ fn humanize_state(state: &ModuleRuntimeState) -> String {
                     format!(
                         "Failed ({}) {}",
                         code,
-                        time_string(&HumanTime::from(Utc::now() - *time), Tense::Past)
+                        time_string(&HumanTime::from(now - *time), Tense::Past)
                     )
                 })
             }).unwrap_or_else(|| "Failed".to_string()),
+        ModuleStatus::Backoff => "Backoff".to_string(),
         ModuleStatus::Running => state.started_at().map_or_else(
             || "Up".to_string(),
             |time| {
                 format!(
                     "Up {}",
-                    time_string(&HumanTime::from(Utc::now() - *time), Tense::Present)
+                    time_string(&HumanTime::from(now - *time), Tense::Present)
                 )
             },
         ),
@@ -111,3 +280,56 @@ fn time_string(ht: &HumanTime, tense: Tense) -> String {
         ht.to_text_en(Accuracy::Rough, tense)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn fit_truncates_the_last_column() {
+        let headers = strings(&["NAME", "CONFIG"]);
+        let rows = vec![
+            strings(&["edgeAgent", "mcr.microsoft.com/azureiotedge-agent:1.0"]),
+            strings(&["tempSensor", "short:1.0"]),
+        ];
+
+        // The name column is 15 wide, leaving 25 for the config.
+        assert_eq!(
+            vec![
+                strings(&["NAME", "CONFIG"]),
+                strings(&["edgeAgent", "mcr.microsoft.com/azur..."]),
+                strings(&["tempSensor", "short:1.0"]),
+            ],
+            fit(headers.clone(), rows.clone(), Some(40))
+        );
+        assert_eq!(
+            Some(headers.clone()).into_iter().chain(rows.clone()).collect::<Vec<_>>(),
+            fit(headers.clone(), rows.clone(), None)
+        );
+        // Too narrow to show anything useful.
+        assert_eq!(
+            Some(headers.clone()).into_iter().chain(rows.clone()).collect::<Vec<_>>(),
+            fit(headers, rows, Some(20))
+        );
+    }
+
+    #[test]
+    fn fit_accounts_for_wide_columns() {
+        let headers = strings(&["NAME", "CONFIG"]);
+        let rows = vec![strings(&["aVeryLongModuleName", "image:1.0"])];
+
+        // The name column grows to 21, leaving 9 for the config.
+        assert_eq!(
+            vec![headers.clone(), strings(&["aVeryLongModuleName", "image:1.0"])],
+            fit(headers.clone(), rows.clone(), Some(30))
+        );
+        assert_eq!(
+            vec![headers.clone(), strings(&["aVeryLongModuleName", "image..."])],
+            fit(headers, rows, Some(29))
+        );
+    }
+}
@@ -2,8 +2,11 @@
 
 use std::cmp;
 use std::io::{self, Write};
+use std::str;
+use std::sync::{Arc, Mutex};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use chrono::{DateTime, Duration, Utc};
 use edgelet_core::{LogOptions, ModuleRuntime};
 use futures::prelude::*;
 use tokio::codec::length_delimited;
@@ -13,45 +16,71 @@ use tokio::io::AsyncRead;
 use error::{Error, ErrorKind};
 use Command;
 
-pub struct Logs<M> {
+pub struct Logs<M, W> {
     id: String,
     options: LogOptions,
+    until: Option<DateTime<Utc>>,
     runtime: M,
+    output: Arc<Mutex<W>>,
 }
 
-impl<M> Logs<M> {
-    pub fn new(id: String, options: LogOptions, runtime: M) -> Self {
+impl<M, W> Logs<M, W> {
+    pub fn new(id: String, options: LogOptions, runtime: M, output: W) -> Self {
         Logs {
             id,
             options,
+            until: None,
             runtime,
+            output: Arc::new(Mutex::new(output)),
         }
     }
+
+    /// Stops at the first line written after `until`.
+    pub fn with_until(mut self, until: Option<DateTime<Utc>>) -> Self {
+        self.until = until;
+        self
+    }
 }
 
-impl<M> Command for Logs<M>
+impl<M, W> Command for Logs<M, W>
 where
     M: 'static + ModuleRuntime + Clone,
+    W: 'static + Write + Send,
 {
     type Future = Box<Future<Item = (), Error = Error> + Send>;
 
     fn execute(&mut self) -> Self::Future {
         let id = self.id.clone();
+        let write = self.output.clone();
+        let until = self.until;
+        let timestamps = self.options.timestamps();
+
+        // The daemon can't cut the logs off at a point in time, so ask it
+        // for timestamps to find the cutoff with.
+        let options = self
+            .options
+            .clone()
+            .with_timestamps(timestamps || until.is_some());
         let result = self
             .runtime
-            .logs(&id, &self.options)
+            .logs(&id, &options)
             .map_err(|_| Error::from(ErrorKind::ModuleRuntime))
             .and_then(move |logs| {
                 let chunked =
                     Chunked::new(logs.map_err(|_| io::Error::new(io::ErrorKind::Other, "unknown")));
                 LogDecode::new(chunked)
-                    .for_each(|chunk| {
-                        match chunk {
-                            LogChunk::Stdin(b)
-                            | LogChunk::Stdout(b)
-                            | LogChunk::Stderr(b)
-                            | LogChunk::Unknown(b) => io::stdout().write(&b)?,
+                    .take_while(move |chunk| {
+                        Ok(until.map_or(true, |until| {
+                            written_at(chunk.payload()).map_or(true, |time| time <= until)
+                        }))
+                    }).for_each(move |chunk| {
+                        let payload = chunk.payload();
+                        let line = if timestamps {
+                            payload
+                        } else {
+                            strip_timestamp(payload)
                         };
+                        write.lock().unwrap().write_all(line)?;
                         Ok(())
                     }).map_err(|_| Error::from(ErrorKind::ModuleRuntime))
             });
@@ -59,6 +88,68 @@ where
     }
 }
 
+/// Parses a time given on the command line. It is either an RFC3339
+/// timestamp, or a duration before `now` like "2h", "45m" or "1h30m".
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, Error> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    parse_duration(value)
+        .and_then(|duration| now.checked_sub_signed(duration))
+        .ok_or_else(|| Error::from(ErrorKind::InvalidTime(value.to_string())))
+}
+
+/// `time` as the container runtime counts it, in seconds since the epoch.
+#[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+pub fn since_timestamp(time: DateTime<Utc>) -> i32 {
+    cmp::max(0, cmp::min(time.timestamp(), i64::from(i32::max_value()))) as i32
+}
+
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let n = i64::from(number.parse::<u32>().ok()?);
+        number.clear();
+        let part = match c {
+            's' => Duration::seconds(n),
+            'm' => Duration::minutes(n),
+            'h' => Duration::hours(n),
+            'd' => Duration::days(n),
+            _ => return None,
+        };
+        duration = duration.checked_add(&part)?;
+    }
+
+    if value.is_empty() || !number.is_empty() {
+        None
+    } else {
+        Some(duration)
+    }
+}
+
+/// The time a line was written, from the timestamp the container runtime
+/// prefixes it with.
+fn written_at(line: &[u8]) -> Option<DateTime<Utc>> {
+    let end = line.iter().position(|&b| b == b' ')?;
+    let timestamp = str::from_utf8(&line[..end]).ok()?;
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn strip_timestamp(line: &[u8]) -> &[u8] {
+    match written_at(line) {
+        Some(_) => &line[line.iter().position(|&b| b == b' ').unwrap_or(0) + 1..],
+        None => line,
+    }
+}
+
 /// Logs parser
 /// Logs are emitted with a simple header to specify stdout or stderr
 ///
@@ -83,6 +174,17 @@ enum LogChunk {
     Unknown(Bytes),
 }
 
+impl LogChunk {
+    fn payload(&self) -> &Bytes {
+        match *self {
+            LogChunk::Stdin(ref b)
+            | LogChunk::Stdout(ref b)
+            | LogChunk::Stderr(ref b)
+            | LogChunk::Unknown(ref b) => b,
+        }
+    }
+}
+
 struct LogDecode<T: AsyncRead> {
     inner: FramedRead<T, length_delimited::LengthDelimitedCodec>,
 }
@@ -223,6 +325,7 @@ mod tests {
 
     use std::io::Read;
 
+    use chrono::TimeZone;
     use futures::stream::iter_ok;
 
     #[test]
@@ -267,4 +370,60 @@ mod tests {
         }
         assert_eq!(b"Roses are red violets are blue", read_buffer);
     }
+
+    #[test]
+    fn rfc3339_times() {
+        let now = Utc::now();
+        assert_eq!(
+            Utc.ymd(2018, 10, 15).and_hms(14, 0, 0),
+            parse_time("2018-10-15T14:00:00Z", now).unwrap()
+        );
+        assert_eq!(
+            Utc.ymd(2018, 10, 15).and_hms(12, 0, 0),
+            parse_time("2018-10-15T14:00:00+02:00", now).unwrap()
+        );
+    }
+
+    #[test]
+    fn relative_times() {
+        let now = Utc.ymd(2018, 10, 15).and_hms(14, 0, 0);
+        assert_eq!(now - Duration::hours(2), parse_time("2h", now).unwrap());
+        assert_eq!(now - Duration::minutes(45), parse_time("45m", now).unwrap());
+        assert_eq!(now - Duration::seconds(30), parse_time("30s", now).unwrap());
+        assert_eq!(now - Duration::days(1), parse_time("1d", now).unwrap());
+        assert_eq!(
+            now - Duration::minutes(90),
+            parse_time("1h30m", now).unwrap()
+        );
+        assert_eq!(now, parse_time("0m", now).unwrap());
+    }
+
+    #[test]
+    fn invalid_times() {
+        let now = Utc::now();
+        for value in &[
+            "", "2", "h", "2w", "-2h", "2h 30m", "yesterday", "2018-10-15", "99999999999d",
+            "4000000000d",
+        ] {
+            let err = parse_time(value, now).unwrap_err();
+            match *err.kind() {
+                ErrorKind::InvalidTime(ref time) => assert_eq!(value, time),
+                ref kind => panic!("Expected InvalidTime for {:?}, got {:?}", value, kind),
+            }
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let line = &b"2018-10-15T14:00:00.123456789Z Roses are red\n"[..];
+        assert_eq!(
+            Some(Utc.ymd(2018, 10, 15).and_hms_nano(14, 0, 0, 123_456_789)),
+            written_at(line)
+        );
+        assert_eq!(&b"Roses are red\n"[..], strip_timestamp(line));
+
+        let line = &b"Roses are red\n"[..];
+        assert_eq!(None, written_at(line));
+        assert_eq!(line, strip_timestamp(line));
+    }
 }
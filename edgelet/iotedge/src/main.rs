@@ -8,23 +8,31 @@
 #![allow(renamed_and_removed_lints)]
 #![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
 
+extern crate chrono;
 #[macro_use]
 extern crate clap;
 extern crate edgelet_core;
 extern crate edgelet_http_mgmt;
 extern crate failure;
 extern crate iotedge;
+#[cfg(unix)]
+extern crate libc;
 extern crate tokio;
 extern crate url;
 
+use std::env;
 use std::io;
 use std::io::Write;
+#[cfg(unix)]
+use std::mem;
+use std::path::PathBuf;
 use std::process;
 
+use chrono::{Duration, Utc};
 use clap::{App, AppSettings, Arg, SubCommand};
 use edgelet_core::{LogOptions, LogTail};
 use edgelet_http_mgmt::ModuleClient;
-use failure::Fail;
+use failure::{Fail, ResultExt};
 use iotedge::*;
 use url::Url;
 
@@ -45,7 +53,7 @@ fn main() {
                 .unwrap_or_else(|_| panic!(errmsg));
             fail = cause;
         }
-        process::exit(1);
+        process::exit(error.exit_code());
     }
 }
 
@@ -66,15 +74,49 @@ fn run() -> Result<(), Error> {
                 .global(true)
                 .env("IOTEDGE_HOST")
                 .default_value(default_uri),
-        ).subcommand(SubCommand::with_name("list").about("List modules"))
+        ).subcommand(
+            SubCommand::with_name("list")
+                .about("List modules")
+                .arg(
+                    Arg::with_name("output")
+                        .help("Output format; json lists the modules as the management API does")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["table", "wide", "json"])
+                        .default_value("table"),
+                ).arg(
+                    Arg::with_name("sort")
+                        .help("Sorts the modules")
+                        .long("sort")
+                        .takes_value(true)
+                        .value_name("FIELD")
+                        .possible_values(&["name", "status", "started"]),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("restart")
-                .about("Restart a module")
-                .arg(
+                .about("Restart modules")
+                .after_help(
+                    "Exits with 2 if some of the modules could not be restarted, \
+                     and with 3 if none could.",
+                ).arg(
                     Arg::with_name("MODULE")
-                        .help("Sets the module identity to restart")
-                        .required(true)
+                        .help("Sets the module identities to restart")
+                        .required_unless("all")
+                        .conflicts_with("all")
+                        .multiple(true)
                         .index(1),
+                ).arg(
+                    Arg::with_name("all")
+                        .help("Restarts all modules except edgeAgent")
+                        .long("all"),
+                ).arg(
+                    Arg::with_name("include-edge-agent")
+                        .help("Also restarts edgeAgent when restarting all modules")
+                        .long("include-edge-agent")
+                        .requires("all"),
                 ),
         ).subcommand(
             SubCommand::with_name("logs")
@@ -96,8 +138,105 @@ fn run() -> Result<(), Error> {
                         .help("Follow output log")
                         .short("f")
                         .long("follow"),
+                ).arg(
+                    Arg::with_name("since")
+                        .help("Only show logs since this time, as RFC3339 or a duration like 2h")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("TIME"),
+                ).arg(
+                    Arg::with_name("until")
+                        .help("Only show logs until this time, as RFC3339 or a duration like 2h")
+                        .long("until")
+                        .takes_value(true)
+                        .value_name("TIME"),
+                ).arg(
+                    Arg::with_name("timestamps")
+                        .help("Show the time each line was written")
+                        .short("t")
+                        .long("timestamps"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("check")
+                .about("Check for common configuration issues")
+                .arg(
+                    Arg::with_name("dont-check")
+                        .help("Comma-separated ids of checks to skip")
+                        .long("dont-check")
+                        .takes_value(true)
+                        .value_name("IDS")
+                        .use_delimiter(true)
+                        .multiple(true),
+                ).arg(
+                    Arg::with_name("output")
+                        .help("Output format")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                ).arg(
+                    Arg::with_name("container-engine-config-file")
+                        .help("Sets the container engine's configuration file")
+                        .long("container-engine-config-file")
+                        .takes_value(true)
+                        .value_name("FILE"),
+                ).arg(
+                    Arg::with_name("config-file")
+                        .help("Sets the configuration file of iotedged")
+                        .short("c")
+                        .long("config-file")
+                        .takes_value(true)
+                        .value_name("FILE"),
+                ).arg(
+                    Arg::with_name("expiry-warning-days")
+                        .help("Warns about certificates that expire within this many days")
+                        .long("expiry-warning-days")
+                        .takes_value(true)
+                        .value_name("DAYS")
+                        .default_value("30"),
+                ).arg(
+                    Arg::with_name("disk-space-warning-mb")
+                        .help("Warns about filesystems with fewer megabytes than this free")
+                        .long("disk-space-warning-mb")
+                        .takes_value(true)
+                        .value_name("MB")
+                        .default_value("1024"),
                 ),
-        ).subcommand(SubCommand::with_name("version").about("Show the version information"))
+        ).subcommand(
+            SubCommand::with_name("support-bundle")
+                .about("Bundle logs, check results and daemon state into a zip file for support")
+                .arg(
+                    Arg::with_name("output")
+                        .help("Sets the zip file to write")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .default_value("support_bundle.zip"),
+                ).arg(
+                    Arg::with_name("since")
+                        .help("Only collect logs since this time, as RFC3339 or a duration like 2h")
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("TIME")
+                        .default_value("6h"),
+                ).arg(
+                    Arg::with_name("exclude-edgehub-logs")
+                        .help("Leaves the logs of edgeHub out of the bundle")
+                        .long("exclude-edgehub-logs"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("version")
+                .about("Show the version information")
+                .arg(
+                    Arg::with_name("daemon")
+                        .help("Also show the versions of iotedged and the container runtime")
+                        .short("d")
+                        .long("daemon"),
+                ),
+        )
         .get_matches();
 
     let url = matches.value_of("host").map_or_else(
@@ -109,25 +248,130 @@ fn run() -> Result<(), Error> {
     let mut tokio_runtime = tokio::runtime::Runtime::new()?;
 
     match matches.subcommand() {
-        ("list", Some(_args)) => tokio_runtime.block_on(List::new(runtime, io::stdout()).execute()),
-        ("restart", Some(args)) => tokio_runtime.block_on(
-            Restart::new(
-                args.value_of("MODULE").unwrap().to_string(),
-                runtime,
-                io::stdout(),
-            ).execute(),
-        ),
+        ("list", Some(args)) => {
+            let format = match args.value_of("output") {
+                Some("wide") => ListFormat::Wide,
+                Some("json") => ListFormat::Json,
+                _ => ListFormat::Table,
+            };
+            let sort = match args.value_of("sort") {
+                Some("name") => Some(ListSort::Name),
+                Some("status") => Some(ListSort::Status),
+                Some("started") => Some(ListSort::Started),
+                _ => None,
+            };
+            tokio_runtime.block_on(
+                List::new(runtime, io::stdout())
+                    .with_format(format)
+                    .with_sort(sort)
+                    .with_width(terminal_width())
+                    .execute(),
+            )
+        }
+        ("restart", Some(args)) => {
+            let ids = args
+                .values_of("MODULE")
+                .map_or_else(Vec::new, |ids| ids.map(ToString::to_string).collect());
+            tokio_runtime.block_on(
+                Restart::new(ids, runtime, io::stdout())
+                    .with_all(args.is_present("all"))
+                    .with_edge_agent(args.is_present("include-edge-agent"))
+                    .execute(),
+            )
+        }
         ("logs", Some(args)) => {
             let id = args.value_of("MODULE").unwrap().to_string();
             let follow = args.is_present("follow");
-            let tail = args
-                .value_of("tail")
-                .and_then(|a| a.parse::<LogTail>().ok())
-                .unwrap_or_default();
-            let options = LogOptions::new().with_follow(follow).with_tail(tail);
-            tokio_runtime.block_on(Logs::new(id, options, runtime).execute())
+            let tail = args.value_of("tail").map_or(Ok(LogTail::default()), |tail| {
+                tail.parse::<LogTail>().context(ErrorKind::InvalidTail(tail.to_string()))
+            })?;
+            let now = Utc::now();
+            let since = args
+                .value_of("since")
+                .map(|since| parse_time(since, now))
+                .map_or(Ok(None), |since| since.map(Some))?;
+            let until = args
+                .value_of("until")
+                .map(|until| parse_time(until, now))
+                .map_or(Ok(None), |until| until.map(Some))?;
+            let options = LogOptions::new()
+                .with_follow(follow)
+                .with_tail(tail)
+                .with_since(since.map_or(0, since_timestamp))
+                .with_timestamps(args.is_present("timestamps"));
+            tokio_runtime.block_on(
+                Logs::new(id, options, runtime, io::stdout())
+                    .with_until(until)
+                    .execute(),
+            )
+        }
+        ("check", Some(args)) => {
+            let dont_check = args
+                .values_of("dont-check")
+                .map_or_else(Vec::new, |ids| ids.map(ToString::to_string).collect());
+            let output_format = match args.value_of("output") {
+                Some("json") => OutputFormat::Json,
+                _ => OutputFormat::Text,
+            };
+            let expiry_warning_days = value_t!(args, "expiry-warning-days", u32)
+                .unwrap_or_else(|e| e.exit());
+            let disk_space_warning_mb = value_t!(args, "disk-space-warning-mb", u64)
+                .unwrap_or_else(|e| e.exit());
+            let mut check = Check::new(runtime, io::stdout())
+                .with_dont_check(dont_check)
+                .with_expiry_warning(Duration::days(i64::from(expiry_warning_days)))
+                .with_disk_space_warning(disk_space_warning_mb * 1024 * 1024)
+                .with_output_format(output_format);
+            if let Some(path) = args.value_of("config-file") {
+                check = check.with_config_file(PathBuf::from(path));
+            }
+            if let Some(path) = args.value_of("container-engine-config-file") {
+                check = check.with_container_engine_config_file(PathBuf::from(path));
+            }
+            tokio_runtime.block_on(check.execute())
         }
-        ("version", Some(_args)) => tokio_runtime.block_on(Version::new().execute()),
+        ("support-bundle", Some(args)) => {
+            let output_file = PathBuf::from(args.value_of("output").unwrap());
+            let since = args
+                .value_of("since")
+                .map(|since| parse_time(since, Utc::now()))
+                .map_or(Ok(None), |since| since.map(Some))?;
+            tokio_runtime.block_on(
+                SupportBundle::new(url.clone(), runtime, output_file, io::stdout())
+                    .with_since(since)
+                    .with_edge_hub_logs(!args.is_present("exclude-edgehub-logs"))
+                    .execute(),
+            )
+        }
+        ("version", Some(args)) => tokio_runtime.block_on(
+            Version::new(runtime, io::stdout())
+                .with_daemon(args.is_present("daemon"))
+                .execute(),
+        ),
         (command, _) => tokio_runtime.block_on(Unknown::new(command.to_string()).execute()),
     }
 }
+
+/// The width of the terminal stdout is written to, if it is one.
+fn terminal_width() -> Option<usize> {
+    if let Some(columns) = env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+    stdout_width()
+}
+
+#[cfg(unix)]
+fn stdout_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result == 0 && size.ws_col > 0 {
+        Some(usize::from(size.ws_col))
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn stdout_width() -> Option<usize> {
+    None
+}
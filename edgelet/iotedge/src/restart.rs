@@ -1,50 +1,153 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::collections::HashMap;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
-use edgelet_core::ModuleRuntime;
-use futures::Future;
+use edgelet_core::{Module, ModuleRuntime};
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
 
-use error::Error;
+use error::{error_message, Error, ErrorKind};
 use Command;
 
+/// Restarting edgeAgent stops it reconciling the deployment, so restarting
+/// all modules leaves it out unless asked.
+const EDGE_AGENT: &str = "edgeAgent";
+
+/// How many restarts are in flight at once by default.
+const DEFAULT_PARALLELISM: usize = 4;
+
 pub struct Restart<M, W> {
-    id: String,
+    ids: Vec<String>,
+    all: bool,
+    edge_agent: bool,
+    parallelism: usize,
     runtime: M,
     output: Arc<Mutex<W>>,
 }
 
 impl<M, W> Restart<M, W> {
-    pub fn new(id: String, runtime: M, output: W) -> Self {
+    pub fn new(ids: Vec<String>, runtime: M, output: W) -> Self {
         Restart {
-            id,
+            ids,
+            all: false,
+            edge_agent: false,
+            parallelism: DEFAULT_PARALLELISM,
             runtime,
             output: Arc::new(Mutex::new(output)),
         }
     }
+
+    /// Restarts every module instead of those given by id.
+    pub fn with_all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Whether restarting all modules includes edgeAgent.
+    pub fn with_edge_agent(mut self, edge_agent: bool) -> Self {
+        self.edge_agent = edge_agent;
+        self
+    }
+
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
 }
 
 impl<M, W> Command for Restart<M, W>
 where
-    M: 'static + ModuleRuntime + Clone,
+    M: 'static + ModuleRuntime + Clone + Send,
     M::Error: Into<Error>,
     W: 'static + Write + Send,
 {
     type Future = Box<Future<Item = (), Error = Error> + Send>;
 
     fn execute(&mut self) -> Self::Future {
-        let id = self.id.clone();
+        let ids = if self.all {
+            let edge_agent = self.edge_agent;
+            Either::A(
+                self.runtime
+                    .list()
+                    .map_err(|e| e.into())
+                    .map(move |modules| {
+                        modules
+                            .iter()
+                            .map(|module| module.name().to_string())
+                            .filter(|name| edge_agent || name != EDGE_AGENT)
+                            .collect()
+                    }),
+            )
+        } else {
+            Either::B(future::ok(dedup(&self.ids)))
+        };
+
+        let runtime = self.runtime.clone();
+        let parallelism = self.parallelism;
         let write = self.output.clone();
-        let result = self
-            .runtime
-            .restart(&id)
-            .map_err(|e| e.into())
-            .and_then(move |_| {
-                let mut w = write.lock().unwrap();
-                writeln!(w, "{}", id)?;
-                Ok(())
+        let result = ids.and_then(move |ids: Vec<String>| {
+            let restarts = ids.clone().into_iter().map(move |id| {
+                runtime
+                    .restart(&id)
+                    .then(move |result| Ok::<_, Error>((id, result.map_err(|e| e.into()))))
             });
+            stream::iter_ok::<_, Error>(restarts)
+                .buffer_unordered(parallelism)
+                .collect()
+                .and_then(move |results| {
+                    // Restarts finish in any order; report them in the order asked for.
+                    let mut results: HashMap<String, Result<(), Error>> =
+                        results.into_iter().collect();
+                    let mut w = write.lock().unwrap();
+                    let mut failed = 0;
+                    for id in &ids {
+                        match results.remove(id) {
+                            Some(Ok(())) => writeln!(w, "{}", id)?,
+                            Some(Err(err)) => {
+                                failed += 1;
+                                writeln!(w, "Could not restart {}: {}", id, error_message(&err))?;
+                            }
+                            None => (),
+                        }
+                    }
+
+                    if failed == 0 {
+                        Ok(())
+                    } else {
+                        Err(Error::from(ErrorKind::RestartFailed {
+                            failed,
+                            total: ids.len(),
+                        }))
+                    }
+                })
+        });
         Box::new(result)
     }
 }
+
+/// Drops repeated ids, so that a module is only restarted once.
+fn dedup(ids: &[String]) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::with_capacity(ids.len());
+    for id in ids {
+        if !unique.contains(id) {
+            unique.push(id.clone());
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup_keeps_the_first_of_each_id() {
+        let ids: Vec<String> = ["edgeHub", "m1", "edgeHub", "m2", "m1"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(vec!["edgeHub", "m1", "m2"], dedup(&ids));
+    }
+}
@@ -0,0 +1,377 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use edgelet_core::{LogOptions, Module, ModuleRuntime};
+use edgelet_http::{UrlConnector, API_VERSION};
+use failure::{Fail, ResultExt};
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+use hyper::{Body, Client};
+use serde_json::{self, Value};
+use url::Url;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use check::{Check, OutputFormat};
+use error::{error_message, Error, ErrorKind};
+use logs::{since_timestamp, Logs};
+use Command;
+
+mod redact;
+
+use self::redact::{redact_json, redact_text};
+
+const EDGE_HUB: &str = "edgeHub";
+
+/// Collects what support needs to diagnose a device into one zip file:
+/// module logs, `iotedge check` results, what the management API reports
+/// about modules, the system and recent runtime errors, and the daemon's own
+/// logs. Secrets found
+/// in any of them are redacted.
+pub struct SupportBundle<M, W> {
+    url: Url,
+    runtime: M,
+    output_file: PathBuf,
+    since: Option<DateTime<Utc>>,
+    edge_hub_logs: bool,
+    output: Arc<Mutex<W>>,
+}
+
+impl<M, W> SupportBundle<M, W> {
+    /// `url` is that of the management API `runtime` talks to.
+    pub fn new(url: Url, runtime: M, output_file: PathBuf, output: W) -> Self {
+        SupportBundle {
+            url,
+            runtime,
+            output_file,
+            since: None,
+            edge_hub_logs: true,
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// Only collects logs written at or after `since`.
+    pub fn with_since(mut self, since: Option<DateTime<Utc>>) -> Self {
+        self.since = since;
+        self
+    }
+
+    pub fn with_edge_hub_logs(mut self, edge_hub_logs: bool) -> Self {
+        self.edge_hub_logs = edge_hub_logs;
+        self
+    }
+}
+
+impl<M, W> Command for SupportBundle<M, W>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: Into<Error>,
+    W: 'static + Write + Send,
+{
+    type Future = Box<Future<Item = (), Error = Error> + Send>;
+
+    fn execute(&mut self) -> Self::Future {
+        let connector = match UrlConnector::new(&self.url) {
+            Ok(connector) => connector,
+            Err(err) => {
+                return Box::new(future::err(Error::from(err.context(ErrorKind::SupportBundle))))
+            }
+        };
+        let api = Api {
+            scheme: connector.scheme(),
+            base_path: base_path(&self.url).to_string(),
+            client: Client::builder().build(connector),
+        };
+
+        let write = self.output.clone();
+        let output_file = self.output_file.clone();
+        let since = self.since;
+        let edge_hub_logs = self.edge_hub_logs;
+        let runtime = self.runtime.clone();
+
+        let modules = api.get("/modules").then(|result| {
+            Ok(Entry::new("modules.json", "Modules, from GET /modules", result))
+        });
+        let system_info = api.get("/systeminfo").then(|result| {
+            Ok(Entry::new("systeminfo.json", "System information, from GET /systeminfo", result))
+        });
+        let runtime_errors = api.get("/diagnostics/runtime-errors").then(|result| {
+            Ok(Entry::new(
+                "runtime-errors.json",
+                "Recent container runtime errors, from GET /diagnostics/runtime-errors",
+                result,
+            ))
+        });
+        let check = check(self.runtime.clone());
+        let logs = self
+            .runtime
+            .list()
+            .map_err(|err| {
+                let err: Error = err.into();
+                error_message(&err)
+            })
+            .then(move |modules| match modules {
+                Ok(modules) => {
+                    let names: Vec<String> = modules
+                        .iter()
+                        .map(|module| module.name().to_string())
+                        .filter(|name| edge_hub_logs || name != EDGE_HUB)
+                        .collect();
+                    Either::A(
+                        stream::iter_ok(names)
+                            .and_then(move |name| module_logs(runtime.clone(), name, since))
+                            .collect(),
+                    )
+                }
+                Err(err) => Either::B(future::ok(vec![Entry::new(
+                    "logs",
+                    "Module logs",
+                    Err(err),
+                )])),
+            });
+
+        let result = modules
+            .join5(system_info, runtime_errors, check, logs)
+            .and_then(move |(modules, system_info, runtime_errors, check, logs)| {
+                let mut entries = vec![modules, system_info, runtime_errors, check];
+                entries.extend(logs);
+                entries.push(Entry::new(
+                    "iotedged.log",
+                    "Logs of the iotedged daemon",
+                    daemon_logs(since),
+                ));
+
+                write_bundle(&output_file, &entries, since)
+                    .context(ErrorKind::SupportBundle)?;
+
+                let mut w = write.lock().unwrap();
+                for entry in &entries {
+                    if let Err(ref err) = entry.content {
+                        writeln!(w, "Could not collect {}: {}", entry.name, err)?;
+                    }
+                }
+                writeln!(w, "Created support bundle at {}", output_file.display())?;
+                Ok(())
+            });
+        Box::new(result)
+    }
+}
+
+/// A file in the bundle, or why it could not be collected.
+struct Entry {
+    name: String,
+    description: String,
+    content: Result<Vec<u8>, String>,
+}
+
+impl Entry {
+    fn new<N, D>(name: N, description: D, content: Result<Vec<u8>, String>) -> Self
+    where
+        N: Into<String>,
+        D: Into<String>,
+    {
+        Entry {
+            name: name.into(),
+            description: description.into(),
+            content,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    files: Vec<ManifestFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile<'a> {
+    name: &'a str,
+    description: &'a str,
+    /// Why the file is missing from the bundle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// Requests the management API directly, since the bundle keeps its
+/// responses as they were sent.
+struct Api {
+    scheme: &'static str,
+    base_path: String,
+    client: Client<UrlConnector, Body>,
+}
+
+impl Api {
+    fn get(&self, path: &str) -> Box<Future<Item = Vec<u8>, Error = String> + Send> {
+        let path = format!("{}?api-version={}", path, API_VERSION);
+        let uri = match UrlConnector::build_hyper_uri(self.scheme, &self.base_path, &path) {
+            Ok(uri) => uri,
+            Err(err) => return Box::new(future::err(error_message(&err))),
+        };
+
+        let result = self
+            .client
+            .get(uri)
+            .and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map(move |body| (status, body))
+            }).map_err(|err| error_message(&err))
+            .and_then(|(status, body)| {
+                if !status.is_success() {
+                    return Err(format!(
+                        "{}: {}",
+                        status,
+                        String::from_utf8_lossy(&body)
+                    ));
+                }
+                let mut value: Value = serde_json::from_slice(&body)
+                    .map_err(|err| format!("Could not parse the response: {}", err))?;
+                redact_json(&mut value);
+                serde_json::to_vec_pretty(&value).map_err(|err| err.to_string())
+            });
+        Box::new(result)
+    }
+}
+
+fn base_path(url: &Url) -> &str {
+    match url.scheme() {
+        "unix" => url.path(),
+        _ => url.as_str(),
+    }
+}
+
+fn check<M>(runtime: M) -> impl Future<Item = Entry, Error = Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+{
+    let output = SharedBuffer::default();
+    Check::new(runtime, output.clone())
+        .with_output_format(OutputFormat::Json)
+        .execute()
+        .then(move |result| {
+            // Failed checks are what the report is for.
+            let report = output.contents();
+            let content = match result {
+                Err(ref err) if report.is_empty() => Err(error_message(err)),
+                _ => Ok(report),
+            };
+            Ok(Entry::new("check.json", "Output of iotedge check", content))
+        })
+}
+
+fn module_logs<M>(
+    runtime: M,
+    name: String,
+    since: Option<DateTime<Utc>>,
+) -> impl Future<Item = Entry, Error = Error> + Send
+where
+    M: 'static + ModuleRuntime + Clone,
+{
+    let options = LogOptions::new()
+        .with_since(since.map_or(0, since_timestamp))
+        .with_timestamps(true);
+    let output = SharedBuffer::default();
+    Logs::new(name.clone(), options, runtime, output.clone())
+        .execute()
+        .then(move |result| {
+            let content = result
+                .map(|()| redact_text(&String::from_utf8_lossy(&output.contents())).into_bytes())
+                .map_err(|err| error_message(&err));
+            Ok(Entry::new(
+                format!("logs/{}.log", name),
+                format!("Logs of module {}", name),
+                content,
+            ))
+        })
+}
+
+#[cfg(unix)]
+fn daemon_logs(since: Option<DateTime<Utc>>) -> Result<Vec<u8>, String> {
+    let mut journalctl = process::Command::new("journalctl");
+    journalctl.args(&["--unit", "iotedge", "--no-pager", "--output", "short-iso"]);
+    if let Some(since) = since {
+        journalctl.arg("--since").arg(format!("@{}", since.timestamp()));
+    }
+
+    let output = journalctl
+        .output()
+        .map_err(|err| format!("Could not run journalctl: {}", err))?;
+    if output.status.success() {
+        Ok(redact_text(&String::from_utf8_lossy(&output.stdout)).into_bytes())
+    } else {
+        Err(format!(
+            "journalctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(windows)]
+fn daemon_logs(_since: Option<DateTime<Utc>>) -> Result<Vec<u8>, String> {
+    Err("The daemon's logs are in the Windows event log, which is not collected.".to_string())
+}
+
+fn write_bundle(
+    path: &Path,
+    entries: &[Entry],
+    since: Option<DateTime<Utc>>,
+) -> io::Result<()> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(File::create(path)?);
+
+    for entry in entries {
+        if let Ok(ref content) = entry.content {
+            zip.start_file(entry.name.as_str(), options)?;
+            zip.write_all(content)?;
+        }
+    }
+
+    let manifest = Manifest {
+        created: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        since: since.map(|since| since.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        files: entries
+            .iter()
+            .map(|entry| ManifestFile {
+                name: &entry.name,
+                description: &entry.description,
+                error: entry.content.as_ref().err().map(String::as_str),
+            }).collect(),
+    };
+    zip.start_file("manifest.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Lets a command write into a buffer that is read after it finishes.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use serde_json::Value;
+
+const REDACTED: &str = "<redacted>";
+
+/// Names that mark a value as secret, compared case-insensitively and
+/// ignoring separators, so that "RegistryCredentials__acr__password" and
+/// "SharedAccessKey" both count.
+const SECRET_NAMES: &[&str] = &[
+    "password",
+    "secret",
+    "connectionstring",
+    "sharedaccesskey",
+    "token",
+    "privatekey",
+];
+
+/// Characters that end the value of a `name=value` pair in free text.
+const VALUE_DELIMITERS: &[char] = &[';', '"', '\'', '&', ',', ' ', '\t', '\r', '\n'];
+
+fn is_secret(name: &str) -> bool {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    SECRET_NAMES.iter().any(|secret| name.contains(secret))
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+/// Redacts the values of secret `name=value` pairs in `text`, like the
+/// `SharedAccessKey` of a connection string or a secret environment
+/// variable in a container's create options.
+pub fn redact_text(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(equals) = rest.find('=') {
+        let name_start = rest[..equals]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_name_char(c))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let (before, value) = rest.split_at(equals + 1);
+        redacted.push_str(before);
+
+        if is_secret(&rest[name_start..equals]) {
+            let end = value.find(VALUE_DELIMITERS).unwrap_or(value.len());
+            if end > 0 {
+                redacted.push_str(REDACTED);
+            }
+            rest = &value[end..];
+        } else {
+            rest = value;
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Redacts secrets anywhere in `value`: values of secret fields, values of
+/// `{"key": ..., "value": ...}` pairs with a secret key, as module
+/// environment variables are listed, and secret `name=value` pairs in
+/// strings.
+pub fn redact_json(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut map) => {
+            let secret_pair = ["key", "name"].iter().any(|field| {
+                map.get(*field)
+                    .and_then(Value::as_str)
+                    .map_or(false, is_secret)
+            });
+            for (name, value) in map.iter_mut() {
+                if is_secret(name) || (secret_pair && name == "value") {
+                    if !value.is_null() {
+                        *value = Value::String(REDACTED.to_string());
+                    }
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for value in values {
+                redact_json(value);
+            }
+        }
+        Value::String(ref mut s) => {
+            *s = redact_text(s);
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_strings() {
+        assert_eq!(
+            "HostName=hub.azure-devices.net;DeviceId=dev1;SharedAccessKey=<redacted>",
+            redact_text("HostName=hub.azure-devices.net;DeviceId=dev1;SharedAccessKey=c2VjcmV0")
+        );
+        assert_eq!(
+            "EdgeHubConnectionString=<redacted>;SharedAccessKey=<redacted>",
+            redact_text("EdgeHubConnectionString=HostName=hub;SharedAccessKey=abc")
+        );
+    }
+
+    #[test]
+    fn free_text() {
+        assert_eq!(
+            "login with password=<redacted> as user=admin",
+            redact_text("login with password=hunter2 as user=admin")
+        );
+        assert_eq!("a == b, token=", redact_text("a == b, token="));
+        assert_eq!("nothing to see", redact_text("nothing to see"));
+        assert_eq!("ünïcode=ok sas_token=<redacted>", redact_text("ünïcode=ok sas_token=x"));
+        assert_eq!("→token=<redacted>", redact_text("→token=x"));
+    }
+
+    #[test]
+    fn json() {
+        let mut value = json!({
+            "name": "edgeAgent",
+            "config": {
+                "settings": {
+                    "image": "microsoft/azureiotedge-agent:1.0",
+                    "createOptions": "{\"Env\":[\"RegistryCredentials__acr__password=p4ss\",\"Mode=iotedged\"]}",
+                    "registryPassword": "p4ss",
+                },
+                "env": [
+                    { "key": "EdgeDeviceConnectionString", "value": "HostName=hub;SharedAccessKey=abc" },
+                    { "key": "UpstreamProtocol", "value": "Amqp" },
+                ],
+            },
+            "secrets": ["a", "b"],
+            "tokenExpiry": null,
+        });
+        redact_json(&mut value);
+        assert_eq!(
+            json!({
+                "name": "edgeAgent",
+                "config": {
+                    "settings": {
+                        "image": "microsoft/azureiotedge-agent:1.0",
+                        "createOptions": "{\"Env\":[\"RegistryCredentials__acr__password=<redacted>\",\"Mode=iotedged\"]}",
+                        "registryPassword": "<redacted>",
+                    },
+                    "env": [
+                        { "key": "EdgeDeviceConnectionString", "value": "<redacted>" },
+                        { "key": "UpstreamProtocol", "value": "Amqp" },
+                    ],
+                },
+                "secrets": "<redacted>",
+                "tokenExpiry": null,
+            }),
+            value
+        );
+    }
+}
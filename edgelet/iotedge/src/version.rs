@@ -1,26 +1,130 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core;
-use futures::future::{self, FutureResult};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use edgelet_core::{self, ModuleRuntime, SystemInfo};
+use futures::future::{self, Either};
+use futures::Future;
 
 use error::Error;
 use Command;
 
-#[derive(Default)]
-pub struct Version;
+pub struct Version<M, W> {
+    runtime: M,
+    daemon: bool,
+    output: Arc<Mutex<W>>,
+}
 
-impl Version {
-    pub fn new() -> Self {
-        Version
+impl<M, W> Version<M, W> {
+    pub fn new(runtime: M, output: W) -> Self {
+        Version {
+            runtime,
+            daemon: false,
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// Also asks the daemon for its version, and that of the container
+    /// runtime it uses.
+    pub fn with_daemon(mut self, daemon: bool) -> Self {
+        self.daemon = daemon;
+        self
     }
 }
 
-impl Command for Version {
-    type Future = FutureResult<(), Error>;
+impl<M, W> Command for Version<M, W>
+where
+    M: 'static + ModuleRuntime + Clone,
+    M::Error: Into<Error>,
+    W: 'static + Write + Send,
+{
+    type Future = Box<Future<Item = (), Error = Error> + Send>;
 
-    #[cfg_attr(feature = "cargo-clippy", allow(print_literal))]
+    #[cfg_attr(feature = "cargo-clippy", allow(write_literal))]
     fn execute(&mut self) -> Self::Future {
-        println!("{} {}", crate_name!(), edgelet_core::version());
-        future::ok(())
+        let write = self.output.clone();
+        let system_info = if self.daemon {
+            Either::A(self.runtime.system_info().map(Some).map_err(Into::into))
+        } else {
+            Either::B(future::ok(None))
+        };
+        let result = system_info.and_then(move |system_info| {
+            let mut w = write.lock().unwrap();
+            writeln!(w, "{} {}", crate_name!(), edgelet_core::version())?;
+            if let Some(system_info) = system_info {
+                write_daemon_version(&mut *w, edgelet_core::version(), &system_info)?;
+            }
+            Ok(())
+        });
+        Box::new(result)
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(write_literal))]
+fn write_daemon_version<W>(w: &mut W, client: &str, daemon: &SystemInfo) -> io::Result<()>
+where
+    W: Write,
+{
+    writeln!(w, "iotedged {}", daemon.version())?;
+    match (daemon.runtime_version(), daemon.runtime_api_version()) {
+        (Some(version), Some(api_version)) => {
+            writeln!(w, "container runtime {} (API {})", version, api_version)?
+        }
+        (Some(version), None) => writeln!(w, "container runtime {}", version)?,
+        (None, _) => (),
+    }
+    if daemon.version() != client {
+        writeln!(
+            w,
+            "warning: {} {} does not match iotedged {}",
+            crate_name!(),
+            client,
+            daemon.version()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daemon(version: &str) -> SystemInfo {
+        SystemInfo::new("linux".to_string(), "x86_64".to_string())
+            .with_version(version.to_string())
+            .with_runtime_version(Some("18.06.1-ce".to_string()))
+            .with_runtime_api_version(Some("1.38".to_string()))
+    }
+
+    fn output(client: &str, daemon: &SystemInfo) -> String {
+        let mut output = Vec::new();
+        write_daemon_version(&mut output, client, daemon).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn matching_versions() {
+        assert_eq!(
+            "iotedged 1.0.2\ncontainer runtime 18.06.1-ce (API 1.38)\n",
+            output("1.0.2", &daemon("1.0.2"))
+        );
+    }
+
+    #[test]
+    fn mismatched_versions_are_flagged() {
+        assert_eq!(
+            "iotedged 1.0.1\n\
+             container runtime 18.06.1-ce (API 1.38)\n\
+             warning: iotedge 1.0.2 does not match iotedged 1.0.1\n",
+            output("1.0.2", &daemon("1.0.1"))
+        );
+    }
+
+    #[test]
+    fn unknown_runtime_version_is_left_out() {
+        let daemon = SystemInfo::new("linux".to_string(), "x86_64".to_string())
+            .with_version("1.0.2".to_string());
+        assert_eq!("iotedged 1.0.2\n", output("1.0.2", &daemon));
     }
 }
@@ -0,0 +1,209 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate chrono;
+extern crate edgelet_http_mgmt;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate iotedge;
+extern crate management;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio;
+extern crate url;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use chrono::{TimeZone, Utc};
+use edgelet_http_mgmt::ModuleClient;
+use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+use futures::{future, Future};
+use hyper::{Body, Error as HyperError, Method, Request, Response};
+use iotedge::{Command, List, ListFormat, ListSort};
+use management::models::ModuleDetails;
+use serde_json::Value;
+use url::Url;
+
+fn modules() -> Value {
+    json!({
+        "modules": [
+            {
+                "id": "id1",
+                "name": "edgeHub",
+                "type": "docker",
+                "config": {
+                    "settings": {
+                        "image": "mcr.microsoft.com/azureiotedge-hub:1.0",
+                        "createOptions": {},
+                    },
+                    "env": [{ "key": "OptimizeForPerformance", "value": "false" }],
+                },
+                "status": {
+                    "startTime": "2018-10-15T15:30:00Z",
+                    "runtimeStatus": { "status": "running" },
+                },
+            },
+            {
+                "id": "id2",
+                "name": "tempSensor",
+                "type": "docker",
+                "config": {
+                    "settings": {
+                        "image": "mcr.microsoft.com/azureiotedge-simulated-temperature-sensor:1.0",
+                        "createOptions": {},
+                    },
+                    "env": [],
+                },
+                "status": {
+                    "startTime": "2018-10-15T15:00:00Z",
+                    "exitStatus": {
+                        "exitTime": "2018-10-15T15:50:00Z",
+                        "statusCode": "137",
+                    },
+                    "runtimeStatus": { "status": "failed", "description": "OOMKilled" },
+                },
+            },
+            {
+                "id": "id3",
+                "name": "edgeAgent",
+                "type": "docker",
+                "config": {
+                    "settings": {
+                        "image": "mcr.microsoft.com/azureiotedge-agent:1.0",
+                        "createOptions": {},
+                    },
+                    "env": [],
+                },
+                "status": {
+                    "startTime": "2018-10-15T14:00:00Z",
+                    "runtimeStatus": { "status": "running" },
+                },
+            },
+        ],
+    })
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn list_modules_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::GET);
+    assert_eq!(req.uri().path(), "/modules");
+
+    let body = serde_json::to_string(&modules()).unwrap();
+    Box::new(future::ok(Response::new(body.into())))
+}
+
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn list(format: ListFormat, sort: Option<ListSort>, width: Option<usize>) -> String {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, list_modules_handler)
+        .map_err(|err| eprintln!("{}", err));
+    let client = ModuleClient::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+        .unwrap();
+
+    let output = SharedWriter(Arc::new(Mutex::new(vec![])));
+    let mut list = List::new(client, output.clone())
+        .with_format(format)
+        .with_sort(sort)
+        .with_width(width)
+        .with_now(Utc.ymd(2018, 10, 15).and_hms(16, 0, 0));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(list.execute()).unwrap();
+
+    let output = output.0.lock().unwrap().clone();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn table() {
+    assert_eq!(
+        "\
+NAME             STATUS           DESCRIPTION                  CONFIG
+edgeHub          running          Up 30 minutes                mcr.microsoft.com/azureiotedge-hub:1.0
+tempSensor       failed           Failed (137) 10 minutes ago  mcr.microsoft.com/azureiotedge-simulated-temperature-sensor:1.0
+edgeAgent        running          Up 2 hours                   mcr.microsoft.com/azureiotedge-agent:1.0
+",
+        list(ListFormat::Table, None, None)
+    );
+}
+
+#[test]
+fn table_fits_the_terminal() {
+    // The config of tempSensor is cut off at 100 characters.
+    assert_eq!(
+        "\
+NAME             STATUS           DESCRIPTION                  CONFIG
+edgeHub          running          Up 30 minutes                mcr.microsoft.com/azureiotedge-hub:1.0
+tempSensor       failed           Failed (137) 10 minutes ago  mcr.microsoft.com/azureiotedge-simulat...
+edgeAgent        running          Up 2 hours                   mcr.microsoft.com/azureiotedge-agent:1.0
+",
+        list(ListFormat::Table, None, Some(100))
+    );
+}
+
+#[test]
+fn wide() {
+    assert_eq!(
+        "\
+NAME             STATUS           DESCRIPTION                  STARTED               EXIT CODE        CONFIG
+edgeAgent        running          Up 2 hours                   2018-10-15T14:00:00Z  -                mcr.microsoft.com/azureiotedge-agent:1.0
+tempSensor       failed           Failed (137) 10 minutes ago  2018-10-15T15:00:00Z  137              mcr.microsoft.com/azureiotedge-simulated-temperature-sensor:1.0
+edgeHub          running          Up 30 minutes                2018-10-15T15:30:00Z  -                mcr.microsoft.com/azureiotedge-hub:1.0
+",
+        // The terminal width only applies to the table.
+        list(ListFormat::Wide, Some(ListSort::Started), Some(100))
+    );
+}
+
+#[test]
+fn sort() {
+    let names = |output: String| -> Vec<String> {
+        output
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().next().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        vec!["edgeAgent", "edgeHub", "tempSensor"],
+        names(list(ListFormat::Table, Some(ListSort::Name), None))
+    );
+    assert_eq!(
+        vec!["tempSensor", "edgeAgent", "edgeHub"],
+        names(list(ListFormat::Table, Some(ListSort::Status), None))
+    );
+}
+
+#[test]
+fn json_is_the_management_api_module_details() {
+    let output = list(ListFormat::Json, None, None);
+
+    // The output must keep deserializing into the management API's models.
+    let details: Vec<ModuleDetails> = serde_json::from_str(&output).unwrap();
+    assert_eq!(modules()["modules"], serde_json::to_value(&details).unwrap());
+}
@@ -0,0 +1,128 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate chrono;
+extern crate edgelet_core;
+extern crate edgelet_http_mgmt;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate iotedge;
+extern crate tokio;
+extern crate url;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use chrono::{TimeZone, Utc};
+use edgelet_core::{LogOptions, LogTail};
+use edgelet_http_mgmt::ModuleClient;
+use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+use futures::{future, Future};
+use hyper::{Body, Error as HyperError, Method, Request, Response};
+use iotedge::{parse_time, Command, Logs};
+use url::form_urlencoded::parse as parse_query;
+use url::Url;
+
+/// Log lines, as the container runtime returns them with timestamps.
+const LINES: &[&str] = &[
+    "2018-10-15T14:00:05.000000001Z first\n",
+    "2018-10-15T14:59:59.999999999Z second\n",
+    "2018-10-15T15:00:00.000000001Z third\n",
+];
+
+/// Frames a line the way the container runtime multiplexes stdout.
+fn frame(line: &str) -> Vec<u8> {
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    let len = line.len() as u8;
+    let mut frame = vec![0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, len];
+    frame.extend_from_slice(line.as_bytes());
+    frame
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn module_logs_handler(
+    req: Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::GET);
+    assert_eq!(req.uri().path(), "/modules/mod1/logs");
+
+    let query: HashMap<String, String> = parse_query(req.uri().query().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+    assert_eq!("false", query["follow"]);
+    assert_eq!("all", query["tail"]);
+    assert_eq!("1539612000", query["since"]);
+    // Cutting the logs off at --until needs the timestamps.
+    assert_eq!("true", query["timestamps"]);
+
+    let body: Vec<u8> = LINES.iter().flat_map(|line| frame(line)).collect();
+    Box::new(future::ok(Response::new(body.into())))
+}
+
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn logs(timestamps: bool, until: &str) -> String {
+    let port = get_unused_tcp_port();
+    let server =
+        run_tcp_server("127.0.0.1", port, module_logs_handler).map_err(|err| eprintln!("{}", err));
+    let client = ModuleClient::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+        .unwrap();
+
+    let now = Utc.ymd(2018, 10, 15).and_hms(16, 0, 0);
+    let since = parse_time("2h", now).unwrap();
+    let until = parse_time(until, now).unwrap();
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    let options = LogOptions::new()
+        .with_tail(LogTail::All)
+        .with_since(since.timestamp() as i32)
+        .with_timestamps(timestamps);
+
+    let output = SharedWriter(Arc::new(Mutex::new(vec![])));
+    let mut logs = Logs::new("mod1".to_string(), options, client, output.clone())
+        .with_until(Some(until));
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(logs.execute()).unwrap();
+
+    let output = output.0.lock().unwrap().clone();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn logs_are_cut_off_at_until() {
+    assert_eq!("first\nsecond\n", logs(false, "2018-10-15T15:00:00Z"));
+}
+
+#[test]
+fn logs_keep_timestamps_when_asked() {
+    assert_eq!(
+        "2018-10-15T14:00:05.000000001Z first\n",
+        logs(true, "2018-10-15T14:00:05.000000001Z")
+    );
+}
+
+#[test]
+fn relative_until() {
+    assert_eq!("first\nsecond\nthird\n", logs(false, "30m"));
+}
@@ -0,0 +1,221 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate edgelet_http_mgmt;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate iotedge;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio;
+extern crate url;
+
+use std::cmp;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use edgelet_http_mgmt::ModuleClient;
+use edgelet_test_utils::get_unused_tcp_port;
+use futures::{future, Future};
+use hyper::service::service_fn;
+use hyper::{Body, Error as HyperError, Method, Request, Response, Server, StatusCode};
+use iotedge::{Command, Error, Restart};
+use tokio::timer::Delay;
+use url::Url;
+
+/// Modules the mocked management API knows about. Later ones take less time
+/// to restart, so restarts finish in the reverse of the order they start in.
+const MODULES: &[&str] = &["edgeAgent", "edgeHub", "m1", "m2", "m3", "m4"];
+
+/// A module whose restart fails.
+const BROKEN: &str = "broken";
+
+#[derive(Default)]
+struct State {
+    in_flight: usize,
+    max_in_flight: usize,
+    restarted: Vec<String>,
+}
+
+fn handler(
+    state: &Arc<Mutex<State>>,
+    req: &Request<Body>,
+) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    let path = req.uri().path().to_string();
+    if path == "/modules" {
+        assert_eq!(req.method(), &Method::GET);
+        let modules: Vec<_> = MODULES
+            .iter()
+            .map(|name| {
+                json!({
+                    "id": name,
+                    "name": name,
+                    "type": "docker",
+                    "config": { "settings": { "image": "image:1.0" } },
+                    "status": { "runtimeStatus": { "status": "running" } },
+                })
+            }).collect();
+        let body = json!({ "modules": modules }).to_string();
+        return Box::new(future::ok(Response::new(body.into())));
+    }
+
+    assert_eq!(req.method(), &Method::POST);
+    let name = path
+        .trim_left_matches("/modules/")
+        .trim_right_matches("/restart")
+        .to_string();
+    let position = MODULES.iter().position(|m| *m == name).unwrap_or(0);
+    {
+        let mut state = state.lock().unwrap();
+        state.in_flight += 1;
+        state.max_in_flight = cmp::max(state.max_in_flight, state.in_flight);
+    }
+
+    let state = state.clone();
+    let delay = Duration::from_millis(50 * (MODULES.len() - position) as u64);
+    let response = Delay::new(Instant::now() + delay).then(move |_| {
+        let mut state = state.lock().unwrap();
+        state.in_flight -= 1;
+        let response = if name == BROKEN {
+            let mut response =
+                Response::new(json!({ "message": "Module not found" }).to_string().into());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        } else {
+            state.restarted.push(name);
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+            response
+        };
+        Ok(response)
+    });
+    Box::new(response)
+}
+
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Restarted {
+    result: Result<(), Error>,
+    output: String,
+    max_in_flight: usize,
+    restarted: Vec<String>,
+}
+
+fn restart(ids: &[&str], all: bool, edge_agent: bool, parallelism: usize) -> Restarted {
+    // Unlike the test utils' servers, this one serves connections concurrently.
+    let port = get_unused_tcp_port();
+    let state = Arc::new(Mutex::new(State::default()));
+    let server_state = state.clone();
+    let server = Server::bind(&format!("127.0.0.1:{}", port).parse().unwrap())
+        .serve(move || {
+            let state = server_state.clone();
+            service_fn(move |req| handler(&state, &req))
+        }).map_err(|err| eprintln!("{}", err));
+    let client = ModuleClient::new(&Url::parse(&format!("http://localhost:{}/", port)).unwrap())
+        .unwrap();
+
+    let output = SharedWriter(Arc::new(Mutex::new(vec![])));
+    let ids = ids.iter().map(ToString::to_string).collect();
+    let mut restart = Restart::new(ids, client, output.clone())
+        .with_all(all)
+        .with_edge_agent(edge_agent)
+        .with_parallelism(parallelism);
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    let result = runtime.block_on(restart.execute());
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let state = state.lock().unwrap();
+    let mut restarted = state.restarted.clone();
+    restarted.sort();
+    Restarted {
+        result,
+        output,
+        max_in_flight: state.max_in_flight,
+        restarted,
+    }
+}
+
+#[test]
+fn restarts_are_concurrent_and_bounded() {
+    let restarted = restart(&["edgeHub", "m1", "m2", "m3", "m4"], false, false, 2);
+
+    assert!(restarted.result.is_ok());
+    assert_eq!(2, restarted.max_in_flight);
+    assert_eq!(vec!["edgeHub", "m1", "m2", "m3", "m4"], restarted.restarted);
+}
+
+#[test]
+fn results_are_reported_in_the_order_asked_for() {
+    // m4 finishes restarting first and edgeHub last.
+    let restarted = restart(&["edgeHub", "m1", "m4", "m1"], false, false, 4);
+
+    assert!(restarted.result.is_ok());
+    assert_eq!(3, restarted.max_in_flight);
+    assert_eq!("edgeHub\nm1\nm4\n", restarted.output);
+}
+
+#[test]
+fn partial_failure_exits_with_2() {
+    let restarted = restart(&["m1", BROKEN, "m2"], false, false, 4);
+
+    let err = restarted.result.unwrap_err();
+    assert_eq!("Could not restart 1 of 3 modules.", err.to_string());
+    assert_eq!(2, err.exit_code());
+    assert_eq!(vec!["m1", "m2"], restarted.restarted);
+    let lines: Vec<&str> = restarted.output.lines().collect();
+    assert_eq!(3, lines.len());
+    assert_eq!("m1", lines[0]);
+    assert!(lines[1].starts_with("Could not restart broken: "));
+    assert_eq!("m2", lines[2]);
+}
+
+#[test]
+fn total_failure_exits_with_3() {
+    let restarted = restart(&[BROKEN], false, false, 4);
+
+    let err = restarted.result.unwrap_err();
+    assert_eq!(3, err.exit_code());
+    assert!(restarted.restarted.is_empty());
+}
+
+#[test]
+fn all_leaves_out_edge_agent() {
+    let restarted = restart(&[], true, false, 4);
+
+    assert!(restarted.result.is_ok());
+    assert_eq!(vec!["edgeHub", "m1", "m2", "m3", "m4"], restarted.restarted);
+    assert_eq!("edgeHub\nm1\nm2\nm3\nm4\n", restarted.output);
+}
+
+#[test]
+fn all_can_include_edge_agent() {
+    let restarted = restart(&[], true, true, 4);
+
+    assert!(restarted.result.is_ok());
+    assert_eq!(
+        vec!["edgeAgent", "edgeHub", "m1", "m2", "m3", "m4"],
+        restarted.restarted
+    );
+}
@@ -0,0 +1,242 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+#![deny(unused_extern_crates, warnings)]
+// Remove this when clippy stops warning about old-style `allow()`,
+// which can only be silenced by enabling a feature and thus requires nightly
+//
+// Ref: https://github.com/rust-lang-nursery/rust-clippy/issues/3159#issuecomment-420530386
+#![allow(renamed_and_removed_lints)]
+#![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+
+extern crate chrono;
+extern crate edgelet_http_mgmt;
+extern crate edgelet_test_utils;
+extern crate futures;
+extern crate hyper;
+extern crate iotedge;
+#[macro_use]
+extern crate serde_json;
+extern crate tempdir;
+extern crate tokio;
+extern crate url;
+extern crate zip;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use chrono::{TimeZone, Utc};
+use edgelet_http_mgmt::ModuleClient;
+use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+use futures::{future, Future};
+use hyper::header::{HeaderValue, CONNECTION};
+use hyper::{Body, Error as HyperError, Method, Request, Response, StatusCode};
+use iotedge::{Command, SupportBundle};
+use serde_json::Value;
+use tempdir::TempDir;
+use url::form_urlencoded::parse as parse_query;
+use url::Url;
+use zip::ZipArchive;
+
+fn modules() -> Value {
+    let modules: Vec<Value> = ["edgeAgent", "edgeHub"]
+        .iter()
+        .map(|name| {
+            json!({
+                "id": format!("{}-id", name),
+                "name": name,
+                "type": "docker",
+                "config": {
+                    "settings": {
+                        "image": format!("microsoft/azureiotedge-{}:1.0", name),
+                        "createOptions": "{\"Env\":[\"RegistryCredentials__acr__password=p4ss\"]}",
+                    },
+                    "env": [
+                        {
+                            "key": "EdgeHubConnectionString",
+                            "value": "HostName=hub;DeviceId=dev1;SharedAccessKey=c2VjcmV0",
+                        },
+                        { "key": "UpstreamProtocol", "value": "Amqp" },
+                    ],
+                },
+                "status": {
+                    "runtimeStatus": { "status": "running" },
+                },
+            })
+        }).collect();
+    json!({ "modules": modules })
+}
+
+/// Frames a line the way the container runtime multiplexes stdout.
+fn frame(line: &str) -> Vec<u8> {
+    #[cfg_attr(feature = "cargo-clippy", allow(cast_possible_truncation))]
+    let len = line.len() as u8;
+    let mut frame = vec![0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, len];
+    frame.extend_from_slice(line.as_bytes());
+    frame
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+fn handler(req: Request<Body>) -> Box<Future<Item = Response<Body>, Error = HyperError> + Send> {
+    assert_eq!(req.method(), &Method::GET);
+    let query: HashMap<String, String> = parse_query(req.uri().query().unwrap().as_bytes())
+        .into_owned()
+        .collect();
+
+    let body: Vec<u8> = match req.uri().path() {
+        "/modules" => serde_json::to_vec(&modules()).unwrap(),
+        "/systeminfo" => serde_json::to_vec(&json!({
+            "osType": "linux",
+            "architecture": "x86_64",
+            "version": "1.0.3",
+        })).unwrap(),
+        "/diagnostics/runtime-errors" => serde_json::to_vec(&json!({
+            "errors": [{
+                "timestamp": "2018-10-15T14:00:07Z",
+                "operation": "start",
+                "moduleId": "tempSensor",
+                "kind": "NotFound",
+                "message": "No such container: tempSensor",
+            }],
+        })).unwrap(),
+        "/modules/edgeAgent/logs" | "/modules/edgeHub/logs" => {
+            assert_eq!("1539612000", query["since"]);
+            assert_eq!("true", query["timestamps"]);
+            let name = req.uri().path().split('/').nth(2).unwrap();
+            [
+                format!("2018-10-15T14:00:05.000000001Z {} started\n", name),
+                "2018-10-15T14:00:06.000000001Z token=abc123 accepted\n".to_string(),
+            ].iter()
+            .flat_map(|line| frame(line))
+            .collect()
+        }
+        _ => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return Box::new(future::ok(close(response)));
+        }
+    };
+    Box::new(future::ok(close(Response::new(body.into()))))
+}
+
+/// The test server serves one connection at a time, while the bundle holds
+/// several, so none may be kept alive.
+fn close(mut response: Response<Body>) -> Response<Body> {
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    response
+}
+
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Bundle {
+    files: HashMap<String, String>,
+    output: String,
+}
+
+fn support_bundle(edge_hub_logs: bool) -> Bundle {
+    let port = get_unused_tcp_port();
+    let server = run_tcp_server("127.0.0.1", port, handler).map_err(|err| eprintln!("{}", err));
+    let url = Url::parse(&format!("http://localhost:{}/", port)).unwrap();
+    let client = ModuleClient::new(&url).unwrap();
+
+    let dir = TempDir::new("support_bundle").unwrap();
+    let path = dir.path().join("bundle.zip");
+    let output = SharedWriter(Arc::new(Mutex::new(vec![])));
+    let mut bundle = SupportBundle::new(url, client, path.clone(), output.clone())
+        .with_since(Some(Utc.ymd(2018, 10, 15).and_hms(14, 0, 0)))
+        .with_edge_hub_logs(edge_hub_logs);
+
+    let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+    runtime.spawn(server);
+    runtime.block_on(bundle.execute()).unwrap();
+
+    let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+    let mut files = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        files.insert(file.name().to_string(), contents);
+    }
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    Bundle { files, output }
+}
+
+#[test]
+fn bundle_contains_daemon_state_and_logs() {
+    let bundle = support_bundle(true);
+
+    for name in &[
+        "modules.json",
+        "systeminfo.json",
+        "runtime-errors.json",
+        "check.json",
+        "logs/edgeAgent.log",
+        "logs/edgeHub.log",
+        "manifest.json",
+    ] {
+        assert!(bundle.files.contains_key(*name), "{} is missing", name);
+    }
+
+    let system_info: Value = serde_json::from_str(&bundle.files["systeminfo.json"]).unwrap();
+    assert_eq!("linux", system_info["osType"]);
+    let runtime_errors: Value =
+        serde_json::from_str(&bundle.files["runtime-errors.json"]).unwrap();
+    assert_eq!("tempSensor", runtime_errors["errors"][0]["moduleId"]);
+    assert!(
+        bundle.files["logs/edgeAgent.log"]
+            .starts_with("2018-10-15T14:00:05.000000001Z edgeAgent started\n")
+    );
+
+    let manifest: Value = serde_json::from_str(&bundle.files["manifest.json"]).unwrap();
+    assert_eq!("2018-10-15T14:00:00Z", manifest["since"]);
+    let listed: Vec<&str> = manifest["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["name"].as_str().unwrap())
+        .collect();
+    for name in bundle.files.keys().filter(|name| *name != "manifest.json") {
+        assert!(listed.contains(&name.as_str()), "{} is not listed", name);
+    }
+    assert!(listed.contains(&"iotedged.log"));
+    assert!(bundle.output.ends_with(".zip\n"));
+}
+
+#[test]
+fn bundle_redacts_secrets() {
+    let bundle = support_bundle(true);
+
+    let modules = &bundle.files["modules.json"];
+    assert!(!modules.contains("c2VjcmV0"));
+    assert!(!modules.contains("p4ss"));
+    assert!(modules.contains("RegistryCredentials__acr__password=<redacted>"));
+    assert!(modules.contains("Amqp"));
+
+    let log = &bundle.files["logs/edgeHub.log"];
+    assert!(!log.contains("abc123"));
+    assert!(log.contains("token=<redacted> accepted"));
+}
+
+#[test]
+fn bundle_can_exclude_edge_hub_logs() {
+    let bundle = support_bundle(false);
+
+    assert!(bundle.files.contains_key("logs/edgeAgent.log"));
+    assert!(!bundle.files.contains_key("logs/edgeHub.log"));
+}
@@ -26,6 +26,32 @@ pub fn create_base_app<'a, 'b>() -> App<'a, 'b> {
 #[cfg(not(target_os = "windows"))]
 pub fn create_app<'a, 'b>() -> App<'a, 'b> {
     create_base_app()
+        .arg(
+            Arg::with_name("purge")
+                .long("purge")
+                .help(
+                    "Removes the modules and their network, and their volumes if the settings \
+                     purge them, then exits. Meant for when the daemon is uninstalled",
+                ).required(false)
+                .takes_value(false),
+        ).arg(
+            Arg::with_name("prune-images")
+                .long("prune-images")
+                .help("Removes the images labeled as owned by the edge runtime too")
+                .requires("purge")
+                .takes_value(false),
+        )
+}
+
+/// What the daemon was started to do.
+#[cfg(not(target_os = "windows"))]
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Runs until it is shut down, reading its settings again from
+    /// `config_file` whenever it is asked to reload.
+    Run { config_file: Option<String> },
+    /// Removes what the daemon created in the container runtime and exits.
+    Purge { prune_images: bool },
 }
 
 #[cfg(target_os = "windows")]
@@ -81,10 +107,21 @@ pub fn init() -> Result<Settings<DockerConfig>, Error> {
 }
 
 #[cfg(not(target_os = "windows"))]
-pub fn init() -> Result<Settings<DockerConfig>, Error> {
+pub fn init() -> Result<(Settings<DockerConfig>, Command), Error> {
     logging::init();
     log_banner();
-    init_common().map(|(settings, _)| settings)
+    init_common().map(|(settings, matches)| {
+        let command = if matches.is_present("purge") {
+            Command::Purge {
+                prune_images: matches.is_present("prune-images"),
+            }
+        } else {
+            Command::Run {
+                config_file: matches.value_of("config-file").map(ToString::to_string),
+            }
+        };
+        (settings, command)
+    })
 }
 
 #[cfg(target_os = "windows")]
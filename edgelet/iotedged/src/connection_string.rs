@@ -0,0 +1,236 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Reads the device connection string of manual provisioning from a file or
+//! the environment instead of the config file, and checks that it has
+//! everything provisioning needs.
+//!
+//! The connection string carries the device's shared access key, so a file
+//! holding it has to belong to the user iotedged runs as and must not be
+//! accessible by anyone else.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use base64;
+
+/// The environment variable the connection string is read from when neither
+/// the config file nor a file named by it has one.
+pub const DEVICE_CONNECTION_STRING_ENV: &str = "IOTEDGE_DEVICE_CONNECTION_STRING";
+
+/// The parts of a connection string provisioning needs.
+const REQUIRED_PARTS: &[&str] = &["HostName", "DeviceId", "SharedAccessKey"];
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ConnectionStringError {
+    #[fail(display = "{} could not be read: {}", _0, _1)]
+    Read(String, String),
+    #[fail(
+        display = "{} is owned by user {}, but iotedged runs as user {}",
+        _0,
+        _1,
+        _2
+    )]
+    Owner(String, u32, u32),
+    #[fail(
+        display = "{} has mode {:04o}, but must not be accessible by group or others",
+        _0,
+        _1
+    )]
+    Mode(String, u32),
+    #[fail(display = "has no {}", _0)]
+    MissingPart(&'static str),
+    #[fail(display = "has a SharedAccessKey that is not valid base64")]
+    InvalidKey,
+}
+
+/// Reads the connection string from the file at `path`, without the line
+/// break editors leave at its end.
+pub fn read_file(path: &Path) -> Result<String, ConnectionStringError> {
+    #[cfg(unix)]
+    check_permissions(path, unsafe { ::libc::geteuid() })?;
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .map_err(|err| ConnectionStringError::Read(path.display().to_string(), err.to_string()))
+}
+
+/// The connection string in the environment, if there is one.
+pub fn from_env() -> Option<String> {
+    env::var(DEVICE_CONNECTION_STRING_ENV)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Checks that `connection_string` has a host name, a device ID and a shared
+/// access key, and that the key can be decoded.
+pub fn check(connection_string: &str) -> Result<(), ConnectionStringError> {
+    let parts: Vec<(&str, &str)> = connection_string
+        .split(';')
+        .filter_map(|part| {
+            let mut name_value = part.splitn(2, '=');
+            match (name_value.next(), name_value.next()) {
+                (Some(name), Some(value)) => Some((name.trim(), value.trim())),
+                _ => None,
+            }
+        }).collect();
+    let value = |name: &str| {
+        parts
+            .iter()
+            .find(|&&(part, value)| part == name && !value.is_empty())
+            .map(|&(_, value)| value)
+    };
+
+    for part in REQUIRED_PARTS {
+        if value(*part).is_none() {
+            return Err(ConnectionStringError::MissingPart(*part));
+        }
+    }
+    let key = value("SharedAccessKey").unwrap_or_default();
+    base64::decode(key)
+        .map(|_| ())
+        .map_err(|_| ConnectionStringError::InvalidKey)
+}
+
+/// Access to files is controlled by ACLs on Windows, so only Unix modes and
+/// owners are checked.
+#[cfg(unix)]
+fn check_permissions(path: &Path, uid: u32) -> Result<(), ConnectionStringError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let name = || path.display().to_string();
+    let metadata =
+        fs::metadata(path).map_err(|err| ConnectionStringError::Read(name(), err.to_string()))?;
+    if metadata.uid() != uid {
+        return Err(ConnectionStringError::Owner(name(), metadata.uid(), uid));
+    }
+    let mode = metadata.mode() & 0o7777;
+    if mode & 0o077 != 0 {
+        return Err(ConnectionStringError::Mode(name(), mode));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD: &str = "HostName=hub.azure-devices.net;DeviceId=dev1;SharedAccessKey=a2V5";
+
+    #[test]
+    fn complete_connection_strings_are_valid() {
+        assert_eq!(Ok(()), check(GOOD));
+        assert_eq!(
+            Ok(()),
+            check("DeviceId=dev1;SharedAccessKey=a2V5==;HostName=hub;GatewayHostName=gw")
+        );
+    }
+
+    #[test]
+    fn malformed_connection_strings_are_rejected() {
+        for (connection_string, expected) in vec![
+            ("", ConnectionStringError::MissingPart("HostName")),
+            ("not a connection string", ConnectionStringError::MissingPart("HostName")),
+            (
+                "DeviceId=dev1;SharedAccessKey=a2V5",
+                ConnectionStringError::MissingPart("HostName"),
+            ),
+            (
+                "HostName=hub;SharedAccessKey=a2V5",
+                ConnectionStringError::MissingPart("DeviceId"),
+            ),
+            (
+                "HostName=hub;DeviceId=dev1",
+                ConnectionStringError::MissingPart("SharedAccessKey"),
+            ),
+            (
+                "HostName=hub;DeviceId=;SharedAccessKey=a2V5",
+                ConnectionStringError::MissingPart("DeviceId"),
+            ),
+            (
+                "HostName=hub;DeviceId=dev1;SharedAccessKey=not base64!",
+                ConnectionStringError::InvalidKey,
+            ),
+            (
+                "HostName=hub;DeviceId=dev1;SharedAccessKey=a2V5%",
+                ConnectionStringError::InvalidKey,
+            ),
+        ] {
+            assert_eq!(Err(expected), check(connection_string), "{}", connection_string);
+        }
+    }
+
+    #[cfg(unix)]
+    mod unix {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::PathBuf;
+
+        use tempdir::TempDir;
+
+        use super::super::*;
+        use super::GOOD;
+
+        fn write(dir: &TempDir, mode: u32) -> PathBuf {
+            let path = dir.path().join("connection_string");
+            fs::write(&path, format!("{}\n", GOOD)).unwrap();
+            fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+            path
+        }
+
+        #[test]
+        fn private_files_are_read() {
+            let dir = TempDir::new("connection_string").unwrap();
+            let path = write(&dir, 0o600);
+            assert_eq!(Ok(GOOD.to_string()), read_file(&path));
+
+            let path = write(&dir, 0o400);
+            assert_eq!(Ok(GOOD.to_string()), read_file(&path));
+        }
+
+        #[test]
+        fn files_others_can_access_are_rejected() {
+            let dir = TempDir::new("connection_string").unwrap();
+            for &mode in &[0o640, 0o604, 0o620, 0o602, 0o610, 0o644, 0o666] {
+                let path = write(&dir, mode);
+                let name = path.display().to_string();
+                let err = read_file(&path).unwrap_err();
+                assert_eq!(ConnectionStringError::Mode(name.clone(), mode), err);
+                assert_eq!(
+                    format!(
+                        "{} has mode {:04o}, but must not be accessible by group or others",
+                        name, mode
+                    ),
+                    err.to_string()
+                );
+            }
+        }
+
+        #[test]
+        fn files_of_other_users_are_rejected() {
+            let dir = TempDir::new("connection_string").unwrap();
+            let path = write(&dir, 0o600);
+            let uid = unsafe { ::libc::geteuid() };
+            assert_eq!(
+                Err(ConnectionStringError::Owner(
+                    path.display().to_string(),
+                    uid,
+                    uid + 1
+                )),
+                check_permissions(&path, uid + 1)
+            );
+        }
+
+        #[test]
+        fn missing_files_are_rejected() {
+            let dir = TempDir::new("connection_string").unwrap();
+            let path = dir.path().join("missing");
+            match read_file(&path) {
+                Err(ConnectionStringError::Read(name, _)) => {
+                    assert_eq!(path.display().to_string(), name)
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
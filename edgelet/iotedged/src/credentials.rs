@@ -0,0 +1,228 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64;
+use chrono::Utc;
+use edgelet_core::crypto::{Decrypt, Encrypt};
+use edgelet_core::{
+    Error as CoreError, ErrorKind as CoreErrorKind, RegistryCredential, RegistryCredentials,
+};
+use failure::{Fail, ResultExt};
+use serde_json;
+use sha2::{Digest, Sha256};
+
+use error::Error;
+
+/// The client the credentials are encrypted for, which keeps them apart from
+/// the secrets modules encrypt with the workload API.
+const CLIENT_ID: &[u8] = b"$iotedged.registry-credentials";
+
+#[derive(Debug, Deserialize, Serialize)]
+struct EncryptedCredential {
+    iv: String,
+    ciphertext: String,
+}
+
+/// Registry credentials that module configs refer to by name, kept in a JSON
+/// file. Each credential is encrypted with the HSM, so the file never holds
+/// them in plain text.
+pub struct RegistryCredentialStore<C> {
+    path: PathBuf,
+    crypto: C,
+}
+
+impl<C> RegistryCredentialStore<C>
+where
+    C: Encrypt + Decrypt,
+{
+    pub fn new<P: AsRef<Path>>(path: P, crypto: C) -> Self {
+        RegistryCredentialStore {
+            path: path.as_ref().to_path_buf(),
+            crypto,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stores `credential` as `name`, replacing the credential of that name if
+    /// there is one.
+    pub fn set(&self, name: &str, credential: &RegistryCredential) -> Result<(), Error> {
+        let mut credentials = self.read()?;
+
+        let plaintext = serde_json::to_vec(credential)?;
+        let iv = initialization_vector(name);
+        let ciphertext = self.crypto.encrypt(CLIENT_ID, &plaintext, &iv)?;
+        credentials.insert(
+            name.to_string(),
+            EncryptedCredential {
+                iv: base64::encode(&iv),
+                ciphertext: base64::encode(ciphertext.as_ref()),
+            },
+        );
+
+        self.write(&credentials)
+    }
+
+    /// Removes the credential `name`. Returns whether there was one.
+    pub fn remove(&self, name: &str) -> Result<bool, Error> {
+        let mut credentials = self.read()?;
+        if credentials.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.write(&credentials)?;
+        Ok(true)
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, EncryptedCredential>, CoreError> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(
+                serde_json::from_slice(&contents).context(CoreErrorKind::RegistryCredentials)?
+            ),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(CoreError::from(
+                err.context(CoreErrorKind::RegistryCredentials),
+            )),
+        }
+    }
+
+    fn write(&self, credentials: &BTreeMap<String, EncryptedCredential>) -> Result<(), Error> {
+        // Write a temporary file and move it over the old one, so that the
+        // file is never left half written.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(credentials)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl<C> RegistryCredentials for RegistryCredentialStore<C>
+where
+    C: Encrypt + Decrypt + Send + Sync,
+{
+    fn credential(&self, name: &str) -> Result<Option<RegistryCredential>, CoreError> {
+        let credentials = self.read()?;
+        let encrypted = match credentials.get(name) {
+            Some(encrypted) => encrypted,
+            None => return Ok(None),
+        };
+
+        let iv = base64::decode(&encrypted.iv).context(CoreErrorKind::RegistryCredentials)?;
+        let ciphertext =
+            base64::decode(&encrypted.ciphertext).context(CoreErrorKind::RegistryCredentials)?;
+        let plaintext = self
+            .crypto
+            .decrypt(CLIENT_ID, &ciphertext, &iv)
+            .context(CoreErrorKind::RegistryCredentials)?;
+        let credential = serde_json::from_slice(plaintext.as_ref())
+            .context(CoreErrorKind::RegistryCredentials)?;
+        Ok(Some(credential))
+    }
+}
+
+/// A fresh initialization vector for every time a credential is encrypted.
+fn initialization_vector(name: &str) -> Vec<u8> {
+    let now = Utc::now();
+    let seed = format!(
+        "{}:{}.{}",
+        name,
+        now.timestamp(),
+        now.timestamp_subsec_nanos()
+    );
+    Sha256::digest_str(&seed)[..16].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    /// Encrypts by flipping the bits of the plaintext, which is enough to
+    /// tell whether a credential was stored encrypted.
+    struct TestCrypto;
+
+    impl Encrypt for TestCrypto {
+        type Buffer = Vec<u8>;
+
+        fn encrypt(
+            &self,
+            client_id: &[u8],
+            plaintext: &[u8],
+            _initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, CoreError> {
+            assert_eq!(CLIENT_ID, client_id);
+            Ok(plaintext.iter().map(|b| !b).collect())
+        }
+    }
+
+    impl Decrypt for TestCrypto {
+        type Buffer = Vec<u8>;
+
+        fn decrypt(
+            &self,
+            client_id: &[u8],
+            ciphertext: &[u8],
+            _initialization_vector: &[u8],
+        ) -> Result<Self::Buffer, CoreError> {
+            assert_eq!(CLIENT_ID, client_id);
+            Ok(ciphertext.iter().map(|b| !b).collect())
+        }
+    }
+
+    fn credential() -> RegistryCredential {
+        RegistryCredential::new(
+            "contoso.azurecr.io".to_string(),
+            "contoso".to_string(),
+            "hunter2".to_string(),
+        )
+    }
+
+    #[test]
+    fn stored_credential_is_resolved() {
+        let tmp_dir = TempDir::new("credentials").unwrap();
+        let store = RegistryCredentialStore::new(tmp_dir.path().join("creds.json"), TestCrypto);
+
+        store.set("contoso", &credential()).unwrap();
+
+        assert_eq!(Some(credential()), store.credential("contoso").unwrap());
+        assert_eq!(None, store.credential("fabrikam").unwrap());
+    }
+
+    #[test]
+    fn file_never_holds_plaintext() {
+        let tmp_dir = TempDir::new("credentials").unwrap();
+        let store = RegistryCredentialStore::new(tmp_dir.path().join("creds.json"), TestCrypto);
+
+        store.set("contoso", &credential()).unwrap();
+
+        let contents = fs::read_to_string(store.path()).unwrap();
+        assert!(contents.contains("contoso"));
+        assert!(!contents.contains("hunter2"));
+    }
+
+    #[test]
+    fn missing_file_has_no_credentials() {
+        let tmp_dir = TempDir::new("credentials").unwrap();
+        let store = RegistryCredentialStore::new(tmp_dir.path().join("creds.json"), TestCrypto);
+
+        assert_eq!(None, store.credential("contoso").unwrap());
+        assert_eq!(false, store.remove("contoso").unwrap());
+    }
+
+    #[test]
+    fn removed_credential_is_gone() {
+        let tmp_dir = TempDir::new("credentials").unwrap();
+        let store = RegistryCredentialStore::new(tmp_dir.path().join("creds.json"), TestCrypto);
+        store.set("contoso", &credential()).unwrap();
+
+        assert_eq!(true, store.remove("contoso").unwrap());
+
+        assert_eq!(None, store.credential("contoso").unwrap());
+    }
+}
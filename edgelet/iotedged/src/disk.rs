@@ -0,0 +1,86 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! The disk statistics of the platform, for the system resources the
+//! management API reports.
+
+use std::io;
+use std::path::Path;
+
+use edgelet_core::{DiskSpace, DiskStats};
+
+/// Asks the operating system for the size of the filesystem a path is on:
+/// `statvfs` on Linux and `GetDiskFreeSpaceExW` on Windows. The space
+/// available is what unprivileged users may fill, which is what modules run
+/// as.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StatVfs;
+
+#[cfg(unix)]
+impl DiskStats for StatVfs {
+    // The widths of the fields of statvfs differ between platforms.
+    #[cfg_attr(feature = "cargo-clippy", allow(identity_conversion))]
+    fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+        use std::ffi::CString;
+        use std::mem;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut stats: ::libc::statvfs = unsafe { mem::zeroed() };
+        if unsafe { ::libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fragment_size = u64::from(stats.f_frsize);
+        Ok(DiskSpace::new(
+            u64::from(stats.f_blocks) * fragment_size,
+            u64::from(stats.f_bavail) * fragment_size,
+        ))
+    }
+}
+
+#[cfg(windows)]
+impl DiskStats for StatVfs {
+    fn disk_space(&self, path: &Path) -> io::Result<DiskSpace> {
+        use std::os::windows::ffi::OsStrExt;
+        use std::ptr;
+
+        use winapi::um::fileapi::GetDiskFreeSpaceExW;
+        use winapi::um::winnt::ULARGE_INTEGER;
+
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available: ULARGE_INTEGER = unsafe { ::std::mem::zeroed() };
+        let mut total: ULARGE_INTEGER = unsafe { ::std::mem::zeroed() };
+        if unsafe {
+            GetDiskFreeSpaceExW(path.as_ptr(), &mut available, &mut total, ptr::null_mut())
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(DiskSpace::new(unsafe { *total.QuadPart() }, unsafe {
+            *available.QuadPart()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn filesystems_are_measured() {
+        let space = StatVfs.disk_space(&env::temp_dir()).unwrap();
+        assert!(space.total_bytes() > 0);
+        assert!(space.available_bytes() <= space.total_bytes());
+    }
+
+    #[test]
+    fn missing_paths_are_errors() {
+        let path = env::temp_dir().join("iotedged-disk-stats-does-not-exist");
+        let err = StatVfs.disk_space(&path).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+    }
+}
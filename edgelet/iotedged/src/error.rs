@@ -10,10 +10,12 @@ use std::sync::Mutex;
 
 use base64::DecodeError;
 use config::ConfigError as SettingsError;
+use connection_string::ConnectionStringError;
 use edgelet_core::Error as CoreError;
 use edgelet_docker::Error as DockerError;
 use edgelet_hsm::Error as SoftHsmError;
 use edgelet_http::Error as HttpError;
+use edgelet_iothub::Error as HubIdentityError;
 use failure::{Backtrace, Context, Fail};
 use hsm::Error as HardHsmError;
 use http;
@@ -23,6 +25,7 @@ use iothubservice::error::Error as IotHubError;
 use provisioning::Error as ProvisioningError;
 use serde_json::Error as JsonError;
 use url::ParseError;
+use validation::InvalidSettings;
 #[cfg(target_os = "windows")]
 use windows_service::Error as WindowsServiceError;
 
@@ -71,12 +74,24 @@ pub enum ErrorKind {
     Unconfigured,
     #[fail(display = "A provisioning error occurred.")]
     Provisioning,
+    #[fail(display = "The device connection string could not be read.")]
+    DeviceConnectionString,
     #[fail(display = "A hardware hsm error occurred.")]
     HardHsm,
     #[fail(display = "An hsm error occurred.")]
     SoftHsm,
     #[fail(display = "Env var error")]
     Var,
+    #[fail(display = "The state in the home directory could not be migrated.")]
+    StateMigration,
+    #[fail(display = "The state in the home directory is laid out for a newer iotedged.")]
+    StateLayoutTooNew,
+    #[fail(display = "A migration of the state in the home directory is pending.")]
+    StateMigrationPending,
+    #[fail(display = "The TLS certificate of a listener could not be prepared.")]
+    ListenerTls,
+    #[fail(display = "The module runtime could not be initialized.")]
+    RuntimeInit,
     #[cfg(target_os = "windows")]
     #[fail(display = "Windows service error")]
     WindowsService,
@@ -126,6 +141,30 @@ impl From<SettingsError> for Error {
     }
 }
 
+impl From<ConnectionStringError> for Error {
+    fn from(error: ConnectionStringError) -> Self {
+        Error {
+            inner: error.context(ErrorKind::DeviceConnectionString),
+        }
+    }
+}
+
+impl From<HubIdentityError> for Error {
+    fn from(error: HubIdentityError) -> Self {
+        Error {
+            inner: error.context(ErrorKind::IotHub),
+        }
+    }
+}
+
+impl From<InvalidSettings> for Error {
+    fn from(error: InvalidSettings) -> Self {
+        Error {
+            inner: error.context(ErrorKind::Settings),
+        }
+    }
+}
+
 // The use of the Mutex below is an artifact of trying to unify 2 different error
 // handling crates. `windows_service` uses `error_chain` and we use `failure`.
 // `error_chain`'s error type does not implement `Sync` unfortunately (they have
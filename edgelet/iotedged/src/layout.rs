@@ -0,0 +1,629 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Where iotedged keeps its state in the home directory, and how that state
+//! is brought over from an older layout or from another home directory.
+//!
+//! The home directory records the version of its layout in `layout.json`.
+//! One without the manifest is either fresh, or was last used by a release
+//! from before there was one, in which case the version is told from which
+//! files are there. A migration backs up everything it moves first, and puts
+//! it all back if any of it fails, so a half migrated home directory never
+//! makes iotedged provision again as if it were a new device.
+
+use std::fmt;
+use std::fs::{self, DirBuilder, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use failure::{Fail, ResultExt};
+use serde_json;
+
+use error::{Error, ErrorKind};
+use {
+    EDGE_LOCAL_IDENTITIES_FILENAME, EDGE_MODULE_STATE_FILENAME, EDGE_PROVISIONING_BACKUP_FILENAME,
+    EDGE_REGISTRY_CREDENTIALS_FILENAME, EDGE_SETTINGS_STATE_FILENAME, EDGE_SETTINGS_SUBDIR,
+};
+
+/// The version of the layout this release uses.
+pub const LAYOUT_VERSION: u32 = 2;
+
+const MANIFEST_FILENAME: &str = "layout.json";
+
+/// Where what is being moved is copied to until the migration is done.
+const BACKUP_DIRNAME: &str = "layout_backup";
+
+/// The directory the HSM library keeps the device's keys and certificates in.
+const HSM_DIRNAME: &str = "hsm";
+
+/// The file system types whose contents are gone after a reboot.
+#[cfg(target_os = "linux")]
+const VOLATILE_FS_TYPES: &[&str] = &["tmpfs", "ramfs"];
+
+/// The state iotedged keeps in the home directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StateFile {
+    SettingsState,
+    ProvisioningBackup,
+    ModuleState,
+    RegistryCredentials,
+    LocalIdentities,
+    Hsm,
+}
+
+const STATE_FILES: &[StateFile] = &[
+    StateFile::SettingsState,
+    StateFile::ProvisioningBackup,
+    StateFile::ModuleState,
+    StateFile::RegistryCredentials,
+    StateFile::LocalIdentities,
+    StateFile::Hsm,
+];
+
+/// Where a version of the layout keeps each of the state files, relative to
+/// the home directory.
+fn path(version: u32, file: StateFile) -> PathBuf {
+    match (version, file) {
+        // Layout 1 kept everything at the top of the home directory.
+        (1, StateFile::SettingsState) => PathBuf::from(EDGE_SETTINGS_STATE_FILENAME),
+        (1, StateFile::ProvisioningBackup) => PathBuf::from(EDGE_PROVISIONING_BACKUP_FILENAME),
+        // Layout 2 moved what is thrown away when the settings change into a
+        // directory of its own.
+        (_, StateFile::SettingsState) => {
+            Path::new(EDGE_SETTINGS_SUBDIR).join(EDGE_SETTINGS_STATE_FILENAME)
+        }
+        (_, StateFile::ProvisioningBackup) => {
+            Path::new(EDGE_SETTINGS_SUBDIR).join(EDGE_PROVISIONING_BACKUP_FILENAME)
+        }
+        (_, StateFile::ModuleState) => PathBuf::from(EDGE_MODULE_STATE_FILENAME),
+        (_, StateFile::RegistryCredentials) => PathBuf::from(EDGE_REGISTRY_CREDENTIALS_FILENAME),
+        (_, StateFile::LocalIdentities) => PathBuf::from(EDGE_LOCAL_IDENTITIES_FILENAME),
+        (_, StateFile::Hsm) => PathBuf::from(HSM_DIRNAME),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    version: u32,
+}
+
+fn read_manifest(homedir: &Path) -> Result<Option<u32>, Error> {
+    match File::open(homedir.join(MANIFEST_FILENAME)) {
+        Ok(file) => {
+            let manifest: Manifest =
+                serde_json::from_reader(file).context(ErrorKind::StateMigration)?;
+            Ok(Some(manifest.version))
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Error::from(err.context(ErrorKind::StateMigration))),
+    }
+}
+
+/// Writes the manifest next to where it goes first, so that it is never seen
+/// half written.
+fn write_manifest(homedir: &Path, version: u32) -> io::Result<()> {
+    let json = serde_json::to_vec(&Manifest { version })?;
+    let temp = homedir.join(format!("{}.tmp", MANIFEST_FILENAME));
+    File::create(&temp)
+        .and_then(|mut file| file.write_all(&json).and_then(|()| file.sync_all()))
+        .and_then(|()| fs::rename(&temp, homedir.join(MANIFEST_FILENAME)))
+}
+
+/// The version of the layout of the state in `homedir`, or `None` when there
+/// is no state.
+fn detect_version(homedir: &Path) -> Result<Option<u32>, Error> {
+    if let Some(version) = read_manifest(homedir)? {
+        return Ok(Some(version));
+    }
+
+    // Only the state files that moved between versions tell them apart.
+    let moved = |version, file| {
+        (1..=LAYOUT_VERSION).any(|other| path(other, file) != path(version, file))
+    };
+    for version in (1..=LAYOUT_VERSION).rev() {
+        let found = STATE_FILES.iter().any(|&file| {
+            moved(version, file) && homedir.join(path(version, file)).exists()
+        });
+        if found {
+            return Ok(Some(version));
+        }
+    }
+
+    // The rest are where they have always been, so there is nothing to move.
+    let found = STATE_FILES
+        .iter()
+        .any(|&file| homedir.join(path(LAYOUT_VERSION, file)).exists())
+        || engine_module_states(homedir)
+            .map(|paths| !paths.is_empty())
+            .unwrap_or(false);
+    Ok(if found { Some(LAYOUT_VERSION) } else { None })
+}
+
+/// The module state files of the engines besides the primary one, which are
+/// named after the engine.
+fn engine_module_states(homedir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(homedir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("module_state.")
+            && name.ends_with(".json")
+            && name != EDGE_MODULE_STATE_FILENAME
+        {
+            paths.push(PathBuf::from(name.into_owned()));
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// The moves that bring the state from a version of the layout in one home
+/// directory to the current layout in another, or the same, one.
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    from_dir: PathBuf,
+    from_version: u32,
+    to_dir: PathBuf,
+    /// Paths relative to `from_dir` and `to_dir`.
+    moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Migration {
+    /// Plans the migration of the state in `from_dir` to `to_dir`. Nothing is
+    /// planned when `from_dir` has no state. State that would be moved onto
+    /// state already at `to_dir` is an error rather than either of them
+    /// being lost.
+    pub fn plan(from_dir: &Path, to_dir: &Path) -> Result<Option<Self>, Error> {
+        let from_version = match detect_version(from_dir)? {
+            Some(version) if version > LAYOUT_VERSION => {
+                return Err(Error::from(ErrorKind::StateLayoutTooNew))
+            }
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        let mut moves: Vec<(PathBuf, PathBuf)> = STATE_FILES
+            .iter()
+            .map(|&file| (path(from_version, file), path(LAYOUT_VERSION, file)))
+            .filter(|&(ref from, _)| from_dir.join(from).exists())
+            .collect();
+        if from_dir != to_dir {
+            let engines = engine_module_states(from_dir).context(ErrorKind::StateMigration)?;
+            moves.extend(engines.into_iter().map(|path| (path.clone(), path)));
+        }
+        moves.retain(|&(ref from, ref to)| from_dir.join(from) != to_dir.join(to));
+
+        for &(_, ref to) in &moves {
+            if to_dir.join(to).exists() {
+                error!(
+                    "Cannot migrate the state in {}: {} already exists",
+                    from_dir.display(),
+                    to_dir.join(to).display()
+                );
+                return Err(Error::from(ErrorKind::StateMigration));
+            }
+        }
+
+        Ok(Some(Migration {
+            from_dir: from_dir.to_path_buf(),
+            from_version,
+            to_dir: to_dir.to_path_buf(),
+            moves,
+        }))
+    }
+
+    /// Whether anything is moved, rather than only the manifest written.
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    pub fn apply(&self) -> Result<(), Error> {
+        self.apply_with(move_path)
+    }
+
+    fn apply_with<F>(&self, mut move_to: F) -> Result<(), Error>
+    where
+        F: FnMut(&Path, &Path) -> io::Result<()>,
+    {
+        create_private_dir(&self.to_dir).context(ErrorKind::StateMigration)?;
+        let backup = self.to_dir.join(BACKUP_DIRNAME);
+        if !self.moves.is_empty() {
+            create_private_dir(&backup).context(ErrorKind::StateMigration)?;
+            for &(ref from, _) in &self.moves {
+                copy_path(&self.from_dir.join(from), &backup.join(from))
+                    .context(ErrorKind::StateMigration)?;
+            }
+        }
+
+        let mut moved = vec![];
+        for &(ref from, ref to) in &self.moves {
+            let (from, to) = (self.from_dir.join(from), self.to_dir.join(to));
+            let result = to
+                .parent()
+                .map_or(Ok(()), create_private_dir)
+                .and_then(|()| move_to(&from, &to));
+            if let Err(err) = result {
+                error!("Could not move {} to {}", from.display(), to.display());
+                self.roll_back(&moved, &backup);
+                return Err(Error::from(err.context(ErrorKind::StateMigration)));
+            }
+            moved.push((from, to));
+        }
+
+        if let Err(err) = write_manifest(&self.to_dir, LAYOUT_VERSION) {
+            error!("Could not write the layout manifest in {}", self.to_dir.display());
+            self.roll_back(&moved, &backup);
+            return Err(Error::from(err.context(ErrorKind::StateMigration)));
+        }
+
+        if let Err(err) = remove_path(&backup) {
+            warn!("Could not remove {}: {}", backup.display(), err);
+        }
+        Ok(())
+    }
+
+    /// Moves back what was moved, last first. What can't be moved back is
+    /// restored from the backup, which is only removed when all of it was.
+    fn roll_back(&self, moved: &[(PathBuf, PathBuf)], backup: &Path) {
+        let mut restored = true;
+        for &(ref from, ref to) in moved.iter().rev() {
+            if move_path(to, from).is_ok() {
+                continue;
+            }
+            let relative = from.strip_prefix(&self.from_dir).unwrap_or(from);
+            if let Err(err) = copy_path(&backup.join(relative), from) {
+                error!("Could not restore {}: {}", from.display(), err);
+                restored = false;
+            }
+        }
+
+        if restored {
+            if let Err(err) = remove_path(backup) {
+                warn!("Could not remove {}: {}", backup.display(), err);
+            }
+        } else {
+            error!(
+                "The state that could not be restored is backed up in {}",
+                backup.display()
+            );
+        }
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "migrate the state in {} from layout {} to layout {}",
+            self.from_dir.display(),
+            self.from_version,
+            LAYOUT_VERSION
+        )?;
+        if self.from_dir != self.to_dir {
+            write!(f, " in {}", self.to_dir.display())?;
+        }
+        for &(ref from, ref to) in &self.moves {
+            write!(
+                f,
+                "\n    {} -> {}",
+                self.from_dir.join(from).display(),
+                self.to_dir.join(to).display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Brings the state in `homedir`, or the state in `previous_homedir` when
+/// `homedir` has none yet, to the current layout. Returns the migration that
+/// is still pending because of `dry_run`, if any.
+pub fn migrate(
+    homedir: &Path,
+    previous_homedir: Option<&Path>,
+    dry_run: bool,
+) -> Result<Option<Migration>, Error> {
+    let from_dir = match previous_homedir {
+        Some(previous) if previous != homedir && detect_version(homedir)?.is_none() => previous,
+        Some(previous) if previous != homedir => {
+            warn!(
+                "Not moving the state in {} since {} already has state of its own",
+                previous.display(),
+                homedir.display()
+            );
+            homedir
+        }
+        _ => homedir,
+    };
+
+    let migration = match Migration::plan(from_dir, homedir)? {
+        Some(ref migration) if migration.is_empty() && migration.from_version == LAYOUT_VERSION =>
+        {
+            if read_manifest(homedir)?.is_none() && !dry_run {
+                write_manifest(homedir, LAYOUT_VERSION).context(ErrorKind::StateMigration)?;
+            }
+            return Ok(None);
+        }
+        Some(migration) => migration,
+        None => {
+            // A fresh home directory.
+            if !dry_run {
+                create_private_dir(homedir)
+                    .and_then(|()| write_manifest(homedir, LAYOUT_VERSION))
+                    .context(ErrorKind::StateMigration)?;
+            }
+            return Ok(None);
+        }
+    };
+
+    if dry_run {
+        info!("Dry run, would {}", migration);
+        return Ok(Some(migration));
+    }
+
+    info!("Starting to {}", migration);
+    migration.apply()?;
+    info!("Finished migrating the state in the home directory.");
+    Ok(None)
+}
+
+/// Warns when `homedir` is on a file system that is gone after a reboot, with
+/// the device's identity and keys in it.
+pub fn warn_if_volatile(homedir: &Path) {
+    #[cfg(target_os = "linux")]
+    {
+        let path = fs::canonicalize(homedir).unwrap_or_else(|_| homedir.to_path_buf());
+        if let Ok(mounts) = fs::read_to_string("/proc/mounts") {
+            if let Some(fs_type) = fs_type(&mounts, &path) {
+                if VOLATILE_FS_TYPES.contains(&fs_type) {
+                    warn!(
+                        "The home directory {} is on {}, so the device will provision \
+                         again after every reboot.",
+                        homedir.display(),
+                        fs_type
+                    );
+                }
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = homedir;
+}
+
+/// The type of the file system `path` is on, according to the mount table in
+/// the format of `/proc/mounts`.
+#[cfg(target_os = "linux")]
+fn fs_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            // Spaces in mount points are escaped as octal.
+            let mount_point = PathBuf::from(fields.next()?.replace("\\040", " "));
+            let fs_type = fields.next()?;
+            Some((mount_point, fs_type))
+        }).filter(|&(ref mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|&(ref mount_point, _)| mount_point.components().count())
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// The state includes the device's keys, so only its owner may see into the
+/// directories it is moved through.
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    // Renaming fails across file systems, as when the home directory moves
+    // to another disk.
+    fs::rename(from, to).or_else(|_| copy_path(from, to).and_then(|()| remove_path(from)))
+}
+
+fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        create_private_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        if let Some(parent) = to.parent() {
+            create_private_dir(parent)?;
+        }
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    /// Lays out state the way layout 1 did, the HSM's keys included.
+    fn write_layout_1(homedir: &Path) {
+        fs::create_dir_all(homedir.join("hsm/enc_keys")).unwrap();
+        fs::write(homedir.join("hsm/enc_keys/key"), "key").unwrap();
+        fs::write(homedir.join("settings_state"), "settings").unwrap();
+        fs::write(homedir.join("provisioning_backup.json"), "provisioning").unwrap();
+        fs::write(homedir.join("module_state.json"), "modules").unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn layout_matches_where_iotedged_looks() {
+        let homedir = Path::new("/iotedge");
+        assert_eq!(
+            homedir
+                .join(EDGE_SETTINGS_SUBDIR)
+                .join(EDGE_PROVISIONING_BACKUP_FILENAME),
+            homedir.join(path(LAYOUT_VERSION, StateFile::ProvisioningBackup))
+        );
+        assert_eq!(
+            homedir
+                .join(EDGE_SETTINGS_SUBDIR)
+                .join(EDGE_SETTINGS_STATE_FILENAME),
+            homedir.join(path(LAYOUT_VERSION, StateFile::SettingsState))
+        );
+    }
+
+    #[test]
+    fn layout_1_is_migrated() {
+        let dir = TempDir::new("homedir").unwrap();
+        write_layout_1(dir.path());
+
+        assert_eq!(None, migrate(dir.path(), None, false).unwrap());
+
+        assert_eq!("settings", read(&dir.path().join("cache/settings_state")));
+        assert_eq!(
+            "provisioning",
+            read(&dir.path().join("cache/provisioning_backup.json"))
+        );
+        assert_eq!("modules", read(&dir.path().join("module_state.json")));
+        assert_eq!("key", read(&dir.path().join("hsm/enc_keys/key")));
+        assert!(!dir.path().join("settings_state").exists());
+        assert!(!dir.path().join(BACKUP_DIRNAME).exists());
+        assert_eq!(Some(LAYOUT_VERSION), read_manifest(dir.path()).unwrap());
+
+        // Migrating again changes nothing.
+        assert_eq!(None, migrate(dir.path(), None, false).unwrap());
+        assert_eq!("settings", read(&dir.path().join("cache/settings_state")));
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let dir = TempDir::new("homedir").unwrap();
+        write_layout_1(dir.path());
+
+        let migration = migrate(dir.path(), None, true).unwrap().unwrap();
+
+        let report = migration.to_string();
+        assert!(report.contains("from layout 1 to layout 2"), "{}", report);
+        assert_eq!(3, report.lines().count(), "{}", report);
+        assert_eq!("settings", read(&dir.path().join("settings_state")));
+        assert!(!dir.path().join(EDGE_SETTINGS_SUBDIR).exists());
+        assert_eq!(None, read_manifest(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn failed_migration_is_rolled_back() {
+        let dir = TempDir::new("homedir").unwrap();
+        write_layout_1(dir.path());
+        let migration = Migration::plan(dir.path(), dir.path()).unwrap().unwrap();
+
+        let mut moves = 0;
+        let err = migration
+            .apply_with(|from, to| {
+                moves += 1;
+                if moves == 2 {
+                    Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+                } else {
+                    move_path(from, to)
+                }
+            }).unwrap_err();
+
+        assert_eq!(&ErrorKind::StateMigration, err.kind());
+        assert_eq!("settings", read(&dir.path().join("settings_state")));
+        assert_eq!(
+            "provisioning",
+            read(&dir.path().join("provisioning_backup.json"))
+        );
+        assert!(!dir.path().join("cache/settings_state").exists());
+        assert!(!dir.path().join(BACKUP_DIRNAME).exists());
+        assert_eq!(None, read_manifest(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn state_is_moved_to_a_new_homedir() {
+        let previous = TempDir::new("previous").unwrap();
+        let dir = TempDir::new("homedir").unwrap();
+        let homedir = dir.path().join("iotedge");
+        write_layout_1(previous.path());
+        fs::write(previous.path().join("module_state.gpu.json"), "gpu").unwrap();
+
+        assert_eq!(None, migrate(&homedir, Some(previous.path()), false).unwrap());
+
+        assert_eq!("settings", read(&homedir.join("cache/settings_state")));
+        assert_eq!("modules", read(&homedir.join("module_state.json")));
+        assert_eq!("gpu", read(&homedir.join("module_state.gpu.json")));
+        assert_eq!("key", read(&homedir.join("hsm/enc_keys/key")));
+        assert!(!previous.path().join("hsm").exists());
+        assert_eq!(Some(LAYOUT_VERSION), read_manifest(&homedir).unwrap());
+
+        // Once the new home directory has state, the previous one is left
+        // alone.
+        fs::write(previous.path().join("module_state.json"), "stale").unwrap();
+        assert_eq!(None, migrate(&homedir, Some(previous.path()), false).unwrap());
+        assert_eq!("modules", read(&homedir.join("module_state.json")));
+    }
+
+    #[test]
+    fn conflicting_state_is_not_overwritten() {
+        let dir = TempDir::new("homedir").unwrap();
+        write_layout_1(dir.path());
+        fs::create_dir(dir.path().join("cache")).unwrap();
+        fs::write(dir.path().join("cache/settings_state"), "newer").unwrap();
+        fs::remove_file(dir.path().join("module_state.json")).unwrap();
+        fs::remove_dir_all(dir.path().join("hsm")).unwrap();
+
+        // The newer file makes this look like layout 2 with a stray file of
+        // layout 1, which is left where it is.
+        assert_eq!(None, migrate(dir.path(), None, false).unwrap());
+        assert_eq!("newer", read(&dir.path().join("cache/settings_state")));
+        assert_eq!("settings", read(&dir.path().join("settings_state")));
+
+        let previous = TempDir::new("previous").unwrap();
+        write_layout_1(previous.path());
+        let err = Migration::plan(previous.path(), dir.path()).unwrap_err();
+        assert_eq!(&ErrorKind::StateMigration, err.kind());
+    }
+
+    #[test]
+    fn fresh_and_newer_homedirs() {
+        let dir = TempDir::new("homedir").unwrap();
+        let homedir = dir.path().join("iotedge");
+
+        assert_eq!(None, migrate(&homedir, None, true).unwrap());
+        assert!(!homedir.exists());
+        assert_eq!(None, migrate(&homedir, None, false).unwrap());
+        assert_eq!(Some(LAYOUT_VERSION), read_manifest(&homedir).unwrap());
+
+        write_manifest(&homedir, LAYOUT_VERSION + 1).unwrap();
+        let err = migrate(&homedir, None, false).unwrap_err();
+        assert_eq!(&ErrorKind::StateLayoutTooNew, err.kind());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fs_type_is_that_of_the_deepest_mount_point() {
+        let mounts = "\
+            /dev/sda1 / ext4 rw,relatime 0 0\n\
+            tmpfs /run tmpfs rw,nosuid,nodev 0 0\n\
+            /dev/sdb1 /var/lib ext4 rw 0 0\n\
+            tmpfs /var/lib/iot\\040edge tmpfs rw 0 0\n";
+
+        assert_eq!(Some("ext4"), fs_type(mounts, Path::new("/var/lib/iotedge")));
+        assert_eq!(Some("tmpfs"), fs_type(mounts, Path::new("/var/lib/iot edge/hsm")));
+        assert_eq!(Some("tmpfs"), fs_type(mounts, Path::new("/run/iotedge")));
+        assert_eq!(Some("ext4"), fs_type(mounts, Path::new("/home")));
+        assert_eq!(None, fs_type("", Path::new("/home")));
+    }
+}
@@ -15,6 +15,7 @@
 ))]
 
 extern crate base64;
+extern crate chrono;
 #[macro_use]
 extern crate clap;
 extern crate config;
@@ -38,13 +39,21 @@ extern crate http;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate iothubservice;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate log;
+#[cfg(test)]
+extern crate openssl;
 extern crate provisioning;
 extern crate serde;
 extern crate sha2;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+#[cfg(not(test))]
 extern crate serde_json;
 #[cfg(test)]
 extern crate tempdir;
@@ -57,12 +66,19 @@ extern crate url_serde;
 extern crate windows_service;
 #[cfg(target_os = "windows")]
 extern crate win_logger;
+#[cfg(windows)]
+extern crate winapi;
 
 pub mod app;
+pub mod connection_string;
+pub mod credentials;
+pub mod disk;
 mod error;
+pub mod layout;
 pub mod logging;
 pub mod settings;
 pub mod signal;
+pub mod validation;
 pub mod workload;
 
 #[cfg(not(target_os = "windows"))]
@@ -71,94 +87,94 @@ pub mod unix;
 #[cfg(target_os = "windows")]
 pub mod windows;
 
-use std::collections::HashMap;
 use std::env;
+use std::fmt::Display;
 use std::fs;
 use std::fs::{DirBuilder, File};
 use std::io::{self, Write};
+use std::iter;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use docker::models::HostConfig;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use docker::models::{ContainerCreateBodyNetworkingConfig, EndpointSettings, HostConfig};
 use edgelet_core::crypto::{
-    CreateCertificate, Decrypt, DerivedKeyStore, Encrypt, GetTrustBundle, KeyIdentity, KeyStore,
-    MasterEncryptionKey, MemoryKey, MemoryKeyStore, Sign, IOTEDGED_CA_ALIAS,
+    Certificate, CreateCertificate, Decrypt, DerivedKeyStore, Encrypt, GetTrustBundle, KeyIdentity,
+    KeyStore, MakeRandom, MasterEncryptionKey, MemoryKey, MemoryKeyStore, RenewQuickstartCa, Sign,
+    SignCertificateRequest, IOTEDGED_CA_ALIAS,
 };
 use edgelet_core::watchdog::Watchdog;
 use edgelet_core::WorkloadConfig;
+use edgelet_core::{
+    AuthType, BusyFlag, Clock, DaemonHealth, DiskMonitor, Error as CoreError, IdentityError,
+    IdentityManager, ImageGarbageCollector, ImageGcPolicy, IntegrityReport, KeyBytes, Metrics,
+    MetricsRegistry, Module, ModuleEnvSettings, ModuleEpochs, ModuleEvents, ModuleRegistry,
+    ModuleRestarts, ModuleRuntime, ModuleSpec, PrivateKey, RunningModules, RuntimeErrorLog,
+    SystemClock,
+};
+use edgelet_core::{init_with_retries, watch_modules, DEFAULT_WATCH_INTERVAL_SECS};
 use edgelet_core::{CertificateIssuer, CertificateProperties, CertificateType};
-use edgelet_core::{ModuleRuntime, ModuleSpec};
-use edgelet_docker::{DockerConfig, DockerModuleRuntime};
+use edgelet_docker::{
+    DockerConfig, DockerModuleRuntime, Error as DockerError, ModuleStateStore,
+    MultiDockerModuleRuntime, NullModuleRuntime,
+};
 use edgelet_hsm::tpm::{TpmKey, TpmKeyStore};
-use edgelet_hsm::Crypto;
+use edgelet_hsm::{CachedKeyStore, Crypto};
 use edgelet_http::client::{Client as HttpClient, ClientImpl};
 use edgelet_http::logging::LoggingService;
-use edgelet_http::{ApiVersionService, HyperExt, MaybeProxyClient, API_VERSION};
-use edgelet_http_mgmt::ManagementService;
-use edgelet_http_workload::WorkloadService;
-use edgelet_iothub::{HubIdentityManager, SasTokenSource};
+use edgelet_http::metrics::{GetMetrics, MetricsService};
+use edgelet_http::route::{Builder, RegexRoutesBuilder, Router};
+use edgelet_http::{
+    ApiVersionService, ClientFactory, HyperExt, MaybeProxyClient, NoProxy, RateLimitService,
+    RateLimiter, ServerTlsConfig, SharedLimits, TlsConfig, TlsPolicy, TlsVersion, API_VERSION,
+};
+use edgelet_http_mgmt::{route_class, EnvRedaction, IntoResponse, ManagementService};
+use edgelet_http_workload::{IssuedCerts, WorkloadService};
+use edgelet_iothub::{
+    edge_capability, EdgeCapability, Error as HubIdentityError,
+    ErrorKind as HubIdentityErrorKind, HubIdentityManager, LocalIdentityManager, SasTokenSource,
+};
+use edgelet_utils::log_failure;
+use failure::ResultExt;
 use futures::future::Either;
 use futures::sync::oneshot::{self, Receiver};
-use futures::{future, Future};
+use futures::{future, Future, Stream};
 use hsm::tpm::Tpm;
 use hsm::ManageTpmKeys;
 use hyper::server::conn::Http;
-use hyper::Uri;
+use hyper::service::NewService;
+use hyper::{Body, Chunk as HyperChunk, Uri};
 use iothubservice::DeviceClient;
+use log::Level;
 use provisioning::provisioning::{
-    BackupProvisioning, DpsProvisioning, ManualProvisioning, Provision, ProvisioningResult,
+    BackupProvisioning, DpsProvisioning, DpsSymmetricKeyProvisioning, ManualProvisioning,
+    Provision, ProvisioningResult,
 };
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
-use url::Url;
-
-use settings::{Dps, Manual, Provisioning, Settings, DEFAULT_CONNECTION_STRING};
+use tokio::timer::Interval;
+use url::{Host, Url};
+
+use credentials::RegistryCredentialStore;
+use disk::StatVfs;
+use settings::{
+    Dps, EdgeDeviceCheck, IdentityManagerType, ListenTls, Manual, ManualX509, Provisioning,
+    RuntimeType, Settings, DEFAULT_CONNECTION_STRING,
+};
+use validation::validate;
 
 use workload::WorkloadData;
 
 pub use self::error::{Error, ErrorKind};
 
+/// The store of the keys modules sign with, derived from the device key.
+type ModuleKeyStore<K> = CachedKeyStore<DerivedKeyStore<K>>;
+
 const EDGE_RUNTIME_MODULEID: &str = "$edgeAgent";
 const EDGE_RUNTIME_MODULE_NAME: &str = "edgeAgent";
-const AUTH_SCHEME: &str = "sasToken";
-
-/// The following constants are all environment variables names injected into
-/// the Edge Agent container.
-///
-/// This variable holds the host name of the IoT Hub instance that edge agent
-/// is expected to work with.
-const HOSTNAME_KEY: &str = "IOTEDGE_IOTHUBHOSTNAME";
-
-/// This variable holds the host name for the edge device. This name is used
-/// by the edge agent to provide the edge hub container an alias name in the
-/// network so that TLS cert validation works.
-const GATEWAY_HOSTNAME_KEY: &str = "EDGEDEVICEHOSTNAME";
-
-/// This variable holds the IoT Hub device identifier.
-const DEVICEID_KEY: &str = "IOTEDGE_DEVICEID";
-
-/// This variable holds the IoT Hub module identifier.
-const MODULEID_KEY: &str = "IOTEDGE_MODULEID";
-
-/// This variable holds the URI to use for connecting to the workload endpoint
-/// in iotedged. This is used by the edge agent to connect to the workload API
-/// for its own needs and is also used for volume mounting into module
-/// containers when the URI refers to a Unix domain socket.
-const WORKLOAD_URI_KEY: &str = "IOTEDGE_WORKLOADURI";
-
-/// This variable holds the URI to use for connecting to the management
-/// endpoint in iotedged. This is used by the edge agent for managing module
-/// lifetimes and module identities.
-const MANAGEMENT_URI_KEY: &str = "IOTEDGE_MANAGEMENTURI";
-
-/// This variable holds the authentication scheme that modules are to use when
-/// connecting to other server modules (like Edge Hub). The authentication
-/// scheme can mean either that we are to use SAS tokens or a TLS client cert.
-const AUTHSCHEME_KEY: &str = "IOTEDGE_AUTHSCHEME";
-
-/// This is the key for the edge runtime mode.
-const EDGE_RUNTIME_MODE_KEY: &str = "Mode";
-
-/// This is the edge runtime mode - it should always be iotedged, when iotedged starts edge runtime.
-const EDGE_RUNTIME_MODE: &str = "iotedged";
 
 /// The HSM lib expects this variable to be set with home directory of the daemon.
 const HOMEDIR_KEY: &str = "IOTEDGE_HOMEDIR";
@@ -168,12 +184,6 @@ const DEVICE_CA_CERT_KEY: &str = "IOTEDGE_DEVICE_CA_CERT";
 const DEVICE_CA_PK_KEY: &str = "IOTEDGE_DEVICE_CA_PK";
 const TRUSTED_CA_CERTS_KEY: &str = "IOTEDGE_TRUSTED_CA_CERTS";
 
-/// This is the key for the docker network Id.
-const EDGE_NETWORKID_KEY: &str = "NetworkId";
-
-/// This is the key for the largest API version that this edgelet supports
-const API_VERSION_KEY: &str = "IOTEDGE_APIVERSION";
-
 const IOTHUB_API_VERSION: &str = "2017-11-08-preview";
 const UNIX_SCHEME: &str = "unix";
 
@@ -186,6 +196,21 @@ const EDGE_SETTINGS_STATE_FILENAME: &str = "settings_state";
 /// This is the name of the cache subdirectory for settings state
 const EDGE_SETTINGS_SUBDIR: &str = "cache";
 
+/// This is the name of the file the modules the runtime created are recorded in
+const EDGE_MODULE_STATE_FILENAME: &str = "module_state.json";
+
+/// This is the name of the file module identities are kept in when IoT Hub doesn't keep them
+const EDGE_LOCAL_IDENTITIES_FILENAME: &str = "identities.json";
+
+/// This is the name of the file the encrypted registry credentials are kept in
+const EDGE_REGISTRY_CREDENTIALS_FILENAME: &str = "registry_credentials.json";
+
+/// This is the name of the file the properties of the certificates issued to modules are kept in
+const EDGE_ISSUED_CERTS_FILENAME: &str = "issued_certs.json";
+
+/// This is the name of the file the modules to start while offline are kept in
+const EDGE_RUNNING_MODULES_FILENAME: &str = "running_modules.json";
+
 /// These are the properties of the workload CA certificate
 const IOTEDGED_VALIDITY: u64 = 7_776_000; // 90 days
 const IOTEDGED_COMMONNAME: &str = "iotedged workload ca";
@@ -193,39 +218,85 @@ const IOTEDGED_COMMONNAME: &str = "iotedged workload ca";
 const IOTEDGE_ID_CERT_MAX_DURATION_SECS: i64 = 7200; // 2 hours
 const IOTEDGE_SERVER_CERT_MAX_DURATION_SECS: i64 = 7_776_000; // 90 days
 
+/// How often the container runtime, the identity backend and the modules are
+/// checked for the health endpoint
+const HEALTH_CHECK_FREQUENCY_SECS: u64 = 60;
+
+/// Mixed into the key module keys are derived from on devices with an X.509
+/// identity, so that it is not just a hash of the identity private key
+const X509_MODULE_ROOT_KEY_LABEL: &[u8] = b"iotedged module keys";
+
+/// How often the quick start device CA is checked for being close to expiry
+const CA_RENEWAL_CHECK_FREQUENCY_SECS: u64 = 86_400;
+
+/// How many times the module runtime is initialized before iotedged gives up,
+/// and how long it waits between attempts
+const RUNTIME_INIT_ATTEMPTS: u32 = 5;
+const RUNTIME_INIT_RETRY_DELAY_SECS: u64 = 3;
+
 pub struct Main {
     settings: Settings<DockerConfig>,
+    config_file: Option<String>,
 }
 
 impl Main {
     pub fn new(settings: Settings<DockerConfig>) -> Self {
-        Main { settings }
+        Main {
+            settings,
+            config_file: None,
+        }
+    }
+
+    /// Sets the file the settings were read from. It is read again whenever
+    /// the daemon is asked to reload, and the settings that can change while
+    /// it runs are applied.
+    pub fn with_config_file(mut self, config_file: String) -> Self {
+        self.config_file = Some(config_file);
+        self
     }
 
     pub fn run_until<F>(self, shutdown_signal: F) -> Result<(), Error>
     where
         F: Future<Item = (), Error = ()> + Send + 'static,
     {
-        let Main { settings } = self;
+        let Main {
+            settings,
+            config_file,
+        } = self;
 
+        let health = DaemonHealth::new(Utc::now());
+        let metrics = MetricsRegistry::new();
+        let runtime_errors = RuntimeErrorLog::default();
         let mut tokio_runtime = tokio::runtime::Runtime::new()?;
 
         if let Provisioning::Manual(ref manual) = settings.provisioning() {
-            if manual.device_connection_string() == DEFAULT_CONNECTION_STRING {
+            if manual.x509().is_none()
+                && manual.device_connection_string() == DEFAULT_CONNECTION_STRING
+            {
                 Err(ErrorKind::Unconfigured)?;
             }
         }
 
-        let hyper_client = MaybeProxyClient::new(get_proxy_uri()?)?;
-
-        info!(
-            "Using runtime network id {}",
-            settings.moby_runtime().network()
-        );
-        let runtime = DockerModuleRuntime::new(settings.moby_runtime().uri())?
-            .with_network_id(settings.moby_runtime().network().to_string());
+        // Report every invalid setting before anything is started.
+        validate(&settings)?;
+        logging::set_level(settings.log_level());
+
+        // The state has to be where this release looks for it before
+        // anything reads it, or the device would provision again.
+        layout::warn_if_volatile(settings.homedir());
+        let state_layout = settings.state_layout();
+        let pending = layout::migrate(
+            settings.homedir(),
+            state_layout.previous_homedir(),
+            state_layout.dry_run(),
+        )?;
+        if pending.is_some() {
+            Err(ErrorKind::StateMigrationPending)?;
+        }
 
-        init_docker_runtime(&runtime, &mut tokio_runtime)?;
+        let timeouts = settings.timeouts();
+        let clients = client_factory(&settings)?;
+        let hyper_client = clients.client()?;
 
         info!(
             "Configuring {} as the home directory.",
@@ -259,66 +330,105 @@ impl Main {
         let crypto = Crypto::new()?;
         info!("Finished initializing hsm.");
 
-        // Detect if the settings were changed and if the device needs to be reconfigured
-        let cache_subdir_path = Path::new(&settings.homedir()).join(EDGE_SETTINGS_SUBDIR);
-        check_settings_state(
-            cache_subdir_path.clone(),
-            EDGE_SETTINGS_STATE_FILENAME,
-            &settings,
-            &runtime,
-            &crypto,
-            &mut tokio_runtime,
-        )?;
+        if let (None, Some(renew_before)) = (
+            certificates.as_ref(),
+            settings.quickstart_ca().renew_before(),
+        ) {
+            renew_quickstart_ca_if_expiring(&crypto, renew_before, Utc::now())?;
+        }
 
-        info!("Provisioning edge device...");
-        match settings.provisioning() {
-            Provisioning::Manual(manual) => {
-                let (key_store, provisioning_result, root_key) =
-                    manual_provision(&manual, &mut tokio_runtime)?;
-                info!("Finished provisioning edge device.");
-                let cfg = WorkloadData::new(
-                    provisioning_result.hub_name().to_string(),
-                    provisioning_result.device_id().to_string(),
-                    IOTEDGE_ID_CERT_MAX_DURATION_SECS,
-                    IOTEDGE_SERVER_CERT_MAX_DURATION_SECS,
+        let restarts = ModuleRestarts::new(settings.watchdog().restart_policy());
+        // The container engines share the epochs, so that the workload API
+        // resolves callers again when a module of any of them restarts, and
+        // the busy flag, so that no engine has images collected mid-deployment.
+        let epochs = ModuleEpochs::new();
+        let busy = BusyFlag::new();
+        match settings.moby_runtime().runtime_type() {
+            RuntimeType::Docker => {
+                info!(
+                    "Using runtime network id {}",
+                    settings.moby_runtime().network()
                 );
-                start_api(
+                let credentials = Arc::new(RegistryCredentialStore::new(
+                    settings.homedir().join(EDGE_REGISTRY_CREDENTIALS_FILENAME),
+                    crypto.clone(),
+                ));
+                let mut docker_runtime = |uri: &Url,
+                                          tls: &TlsConfig,
+                                          state_file: String|
+                 -> Result<DockerModuleRuntime, Error> {
+                    let runtime = DockerModuleRuntime::with_timeouts(
+                        uri,
+                        tls,
+                        timeouts.connect(),
+                        timeouts.moby_runtime_request(),
+                    )?.with_operation_timeout(timeouts.moby_runtime_operation())
+                    .with_network_id(settings.moby_runtime().network().to_string())
+                    .with_restarts(restarts.clone())
+                    .with_epochs(epochs.clone())
+                    .with_busy(busy.clone())
+                    .with_metrics(Arc::new(metrics.clone()))
+                    .with_slow_threshold("pull", timeouts.slow_pull())
+                    .with_slow_threshold("create", timeouts.slow_create())
+                    .with_slow_threshold("start", timeouts.slow_start())
+                    .with_runtime_errors(runtime_errors.clone())
+                    .with_default_dns(settings.moby_runtime().default_dns().to_vec())
+                    .with_default_extra_hosts(
+                        settings.moby_runtime().default_extra_hosts().to_vec(),
+                    ).with_purge_volumes(settings.moby_runtime().purge_volumes())
+                    .with_signals(settings.moby_runtime().kill_signals())
+                    .with_registry_mirrors(settings.moby_runtime().registry_mirrors().to_vec())
+                    .with_system_info_ttl(settings.moby_runtime().system_info_ttl())
+                    .with_stop_options(settings.shutdown().stop_options())
+                    .with_state_store(ModuleStateStore::open(settings.homedir().join(state_file)))
+                    .with_registry_credentials(credentials.clone())
+                    .with_parent_hostname(settings.parent_hostname().map(ToString::to_string));
+                    let windows = runs_windows_containers(&runtime, &mut tokio_runtime);
+                    Ok(runtime.with_case_insensitive_env(windows))
+                };
+                let mut runtime = MultiDockerModuleRuntime::new(docker_runtime(
+                    settings.moby_runtime().uri(),
+                    &settings.moby_runtime().tls_config(),
+                    EDGE_MODULE_STATE_FILENAME.to_string(),
+                )?);
+                for (name, engine) in settings.moby_runtime().engines() {
+                    info!("Using container engine {} at {}", name, engine.uri());
+                    let engine_runtime = docker_runtime(
+                        engine.uri(),
+                        &engine.tls_config(),
+                        format!("module_state.{}.json", name),
+                    )?;
+                    runtime = runtime.with_engine(name.clone(), engine_runtime);
+                }
+                start(
                     &settings,
+                    config_file,
+                    runtime,
+                    restarts,
+                    &crypto,
+                    &clients,
                     hyper_client,
-                    &runtime,
-                    &key_store,
-                    cfg,
-                    root_key,
+                    &health,
+                    &metrics,
+                    &runtime_errors,
                     shutdown_signal,
-                    &crypto,
                     tokio_runtime,
                 )?;
             }
-            Provisioning::Dps(dps) => {
-                let dps_path = cache_subdir_path.join(EDGE_PROVISIONING_BACKUP_FILENAME);
-                let (key_store, provisioning_result, root_key, runtime) = dps_provision(
-                    &dps,
-                    hyper_client.clone(),
-                    dps_path,
-                    runtime,
-                    &mut tokio_runtime,
-                )?;
-                info!("Finished provisioning edge device.");
-                let cfg = WorkloadData::new(
-                    provisioning_result.hub_name().to_string(),
-                    provisioning_result.device_id().to_string(),
-                    IOTEDGE_ID_CERT_MAX_DURATION_SECS,
-                    IOTEDGE_SERVER_CERT_MAX_DURATION_SECS,
-                );
-                start_api(
+            RuntimeType::None => {
+                warn!("No module runtime is configured, modules will not be run.");
+                start(
                     &settings,
+                    config_file,
+                    NullModuleRuntime::new(),
+                    restarts,
+                    &crypto,
+                    &clients,
                     hyper_client,
-                    &runtime,
-                    &key_store,
-                    cfg,
-                    root_key,
+                    &health,
+                    &metrics,
+                    &runtime_errors,
                     shutdown_signal,
-                    &crypto,
                     tokio_runtime,
                 )?;
             }
@@ -327,6 +437,288 @@ impl Main {
         info!("Shutdown complete.");
         Ok(())
     }
+
+    /// Removes the modules, the network they were attached to and, if
+    /// `prune_images` is set, the images labeled as owned by the edge runtime,
+    /// for when the daemon is uninstalled. Module volumes are removed too if
+    /// the settings purge them.
+    pub fn purge(self, prune_images: bool) -> Result<(), Error> {
+        let settings = self.settings;
+        if settings.moby_runtime().runtime_type() == RuntimeType::None {
+            info!("No module runtime is configured, there is nothing to purge.");
+            return Ok(());
+        }
+
+        let timeouts = settings.timeouts();
+        let moby_runtime = settings.moby_runtime();
+        let engines = moby_runtime
+            .engines()
+            .values()
+            .map(|engine| (engine.uri(), engine.tls_config()));
+        let mut tokio_runtime = tokio::runtime::Runtime::new()?;
+        let primary = (moby_runtime.uri(), moby_runtime.tls_config());
+        for (uri, tls) in iter::once(primary).chain(engines) {
+            let runtime = DockerModuleRuntime::with_timeouts(
+                uri,
+                &tls,
+                timeouts.connect(),
+                timeouts.moby_runtime_request(),
+            )?.with_operation_timeout(timeouts.moby_runtime_operation())
+            .with_network_id(moby_runtime.network().to_string())
+            .with_purge_volumes(moby_runtime.purge_volumes())
+            .with_prune_images(prune_images)
+            .with_stop_options(settings.shutdown().stop_options());
+
+            info!("Purging modules of the container engine at {}...", uri);
+            tokio_runtime.block_on(runtime.purge())?;
+        }
+        info!("Finished purging modules.");
+        Ok(())
+    }
+}
+
+/// Initializes the module runtime, reconciles it with the settings and
+/// provisions the device, then serves the APIs and runs the edge runtime
+/// until the shutdown signal.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn start<M, F>(
+    settings: &Settings<DockerConfig>,
+    config_file: Option<String>,
+    runtime: M,
+    restarts: ModuleRestarts,
+    crypto: &Crypto,
+    clients: &ClientFactory,
+    hyper_client: MaybeProxyClient,
+    health: &DaemonHealth,
+    metrics: &MetricsRegistry,
+    runtime_errors: &RuntimeErrorLog,
+    shutdown_signal: F,
+    mut tokio_runtime: tokio::runtime::Runtime,
+) -> Result<(), Error>
+where
+    M: 'static + ModuleRuntime<Config = DockerConfig, Error = DockerError> + Clone + Send + Sync,
+    <M::Module as Module>::Config: Clone + DeserializeOwned + Serialize,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<CoreError> + Into<DockerError>,
+    <M::ModuleRegistry as ModuleRegistry>::Error: IntoResponse,
+    M::Chunk: Into<HyperChunk>,
+    M::Logs: Into<Body>,
+    M::InitFuture: Send,
+    M::SystemInfoFuture: Send,
+    M::RemoveAllFuture: Send,
+    M::CheckIntegrityFuture: Send,
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    let timeouts = settings.timeouts();
+
+    init_runtime(&runtime, &mut tokio_runtime)?;
+
+    // Detect if the settings were changed and if the device needs to be reconfigured
+    let cache_subdir_path = Path::new(settings.homedir()).join(EDGE_SETTINGS_SUBDIR);
+    check_settings_state(
+        cache_subdir_path.clone(),
+        EDGE_SETTINGS_STATE_FILENAME,
+        settings,
+        &runtime,
+        crypto,
+        &mut tokio_runtime,
+    )?;
+
+    check_module_integrity(&runtime, health, &mut tokio_runtime);
+
+    info!("Provisioning edge device...");
+    match settings.provisioning() {
+        Provisioning::Manual(manual) => {
+            let (key_store, provisioning_result, root_key) =
+                manual_provision(&manual, &mut tokio_runtime)?;
+            info!("Finished provisioning edge device.");
+            health.record_provisioned(true);
+            let cfg = WorkloadData::new(
+                provisioning_result.hub_name().to_string(),
+                provisioning_result.device_id().to_string(),
+                IOTEDGE_ID_CERT_MAX_DURATION_SECS,
+                IOTEDGE_SERVER_CERT_MAX_DURATION_SECS,
+                settings.allow_wildcard_sans(),
+                settings.additional_trusted_ca_dir().map(ToOwned::to_owned),
+                settings.payload_limits(),
+                settings.allow_host_processes(),
+                settings.leaf_cert_issuers().to_vec(),
+            );
+            let cfg = with_manifest_trust_bundle(cfg, settings)?;
+            // Devices with an X.509 identity present their certificate
+            // to IoT Hub instead of signing SAS tokens.
+            let (hyper_client, cfg) = match manual.x509() {
+                Some(x509) => {
+                    info!(
+                        "Authenticating with IoT Hub using identity certificate {}",
+                        x509.identity_cert().display()
+                    );
+                    let hyper_client = clients.tls_client(x509.tls_config())?;
+                    let identity_cert = fs::read_to_string(x509.identity_cert())?;
+                    (hyper_client, cfg.with_device_identity_cert(identity_cert))
+                }
+                None => (hyper_client, cfg),
+            };
+            start_api(
+                settings,
+                hyper_client,
+                &runtime,
+                &key_store,
+                cfg,
+                root_key,
+                provisioning_result.auth_type(),
+                shutdown_signal,
+                crypto,
+                tokio_runtime,
+                config_file,
+                restarts,
+                health,
+                metrics,
+                runtime_errors,
+            )
+        }
+        Provisioning::Dps(dps) => {
+            let dps_path = cache_subdir_path.join(EDGE_PROVISIONING_BACKUP_FILENAME);
+            match dps.group_key() {
+                Some(group_key) => {
+                    let (key_store, provisioning_result, root_key, runtime) =
+                        dps_symmetric_key_provision(
+                            &dps,
+                            group_key,
+                            hyper_client.clone(),
+                            timeouts.dps_request(),
+                            settings.parent_hostname(),
+                            dps_path,
+                            runtime,
+                            &mut tokio_runtime,
+                        )?;
+                    info!("Finished provisioning edge device.");
+                    health.record_provisioned(true);
+                    let cfg = WorkloadData::new(
+                        provisioning_result.hub_name().to_string(),
+                        provisioning_result.device_id().to_string(),
+                        IOTEDGE_ID_CERT_MAX_DURATION_SECS,
+                        IOTEDGE_SERVER_CERT_MAX_DURATION_SECS,
+                        settings.allow_wildcard_sans(),
+                        settings.additional_trusted_ca_dir().map(ToOwned::to_owned),
+                        settings.payload_limits(),
+                        settings.allow_host_processes(),
+                        settings.leaf_cert_issuers().to_vec(),
+                    );
+                    let cfg = with_manifest_trust_bundle(cfg, settings)?;
+                    start_api(
+                        settings,
+                        hyper_client,
+                        &runtime,
+                        &key_store,
+                        cfg,
+                        root_key,
+                        provisioning_result.auth_type(),
+                        shutdown_signal,
+                        crypto,
+                        tokio_runtime,
+                        config_file,
+                        restarts,
+                        health,
+                        metrics,
+                        runtime_errors,
+                    )
+                }
+                None => {
+                    let (key_store, provisioning_result, root_key, runtime) = dps_provision(
+                        &dps,
+                        hyper_client.clone(),
+                        timeouts.dps_request(),
+                        settings.parent_hostname(),
+                        dps_path,
+                        runtime,
+                        &mut tokio_runtime,
+                    )?;
+                    info!("Finished provisioning edge device.");
+                    health.record_provisioned(true);
+                    let cfg = WorkloadData::new(
+                        provisioning_result.hub_name().to_string(),
+                        provisioning_result.device_id().to_string(),
+                        IOTEDGE_ID_CERT_MAX_DURATION_SECS,
+                        IOTEDGE_SERVER_CERT_MAX_DURATION_SECS,
+                        settings.allow_wildcard_sans(),
+                        settings.additional_trusted_ca_dir().map(ToOwned::to_owned),
+                        settings.payload_limits(),
+                        settings.allow_host_processes(),
+                        settings.leaf_cert_issuers().to_vec(),
+                    );
+                    let cfg = with_manifest_trust_bundle(cfg, settings)?;
+                    start_api(
+                        settings,
+                        hyper_client,
+                        &runtime,
+                        &key_store,
+                        cfg,
+                        root_key,
+                        provisioning_result.auth_type(),
+                        shutdown_signal,
+                        crypto,
+                        tokio_runtime,
+                        config_file,
+                        restarts,
+                        health,
+                        metrics,
+                        runtime_errors,
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Makes the clients iotedged calls IoT Hub and DPS with. The proxy
+/// settings take precedence over the environment.
+fn client_factory(settings: &Settings<DockerConfig>) -> Result<ClientFactory, Error> {
+    let proxy = settings.proxy();
+    let proxy_uri = match proxy.https_proxy() {
+        Some(url) => Some(url.as_str().parse::<Uri>()?),
+        None => get_proxy_uri()?,
+    };
+    let no_proxy = match proxy.no_proxy() {
+        Some(no_proxy) => no_proxy.parse::<NoProxy>()?,
+        None => NoProxy::from_env()?,
+    };
+    // A parent device serves IoT Hub and DPS with a certificate issued by a
+    // CA in the trust bundle of the devices below it.
+    let trust_bundle = settings.parent_hostname().and_then(|_| {
+        settings
+            .certificates()
+            .map(|certificates| certificates.trusted_ca_certs().to_path_buf())
+    });
+    Ok(ClientFactory::new()
+        .with_proxy_uri(proxy_uri)
+        .with_no_proxy(no_proxy)
+        .with_connect_timeout(settings.timeouts().connect())
+        .with_trust_bundle(trust_bundle)
+        .with_tls_policy(tls_policy(settings)?))
+}
+
+/// The TLS versions and cipher suites the listeners and the clients of
+/// iotedged are limited to.
+fn tls_policy(settings: &Settings<DockerConfig>) -> Result<TlsPolicy, Error> {
+    let min_version = settings.min_tls_version().parse::<TlsVersion>()?;
+    Ok(TlsPolicy::new()
+        .with_min_version(min_version)
+        .with_cipher_suites(settings.tls_cipher_suites().to_vec()))
+}
+
+/// The URL the device reaches an endpoint of IoT Hub or DPS at. A device
+/// below another edge device reaches them through its parent instead.
+fn upstream_url(endpoint: &Url, parent_hostname: Option<&str>) -> Result<Url, Error> {
+    match parent_hostname {
+        Some(parent_hostname) => {
+            let mut url = endpoint.clone();
+            url.set_host(Some(parent_hostname))?;
+            info!("Connecting to {} through the parent device at {}", endpoint, url);
+            Ok(url)
+        }
+        None => Ok(endpoint.clone()),
+    }
 }
 
 pub fn get_proxy_uri() -> Result<Option<Uri>, Error> {
@@ -344,23 +736,54 @@ pub fn get_proxy_uri() -> Result<Option<Uri>, Error> {
     Ok(proxy_uri)
 }
 
-fn prepare_workload_ca<C>(crypto: &C) -> Result<(), Error>
-where
-    C: CreateCertificate,
-{
-    let edgelet_ca_props = CertificateProperties::new(
+fn workload_ca_props() -> CertificateProperties {
+    CertificateProperties::new(
         IOTEDGED_VALIDITY,
         IOTEDGED_COMMONNAME.to_string(),
         CertificateType::Ca,
         IOTEDGED_CA_ALIAS.to_string(),
-    ).with_issuer(CertificateIssuer::DeviceCa);
+    ).with_issuer(CertificateIssuer::DeviceCa)
+}
 
+fn prepare_workload_ca<C>(crypto: &C) -> Result<(), Error>
+where
+    C: CreateCertificate,
+{
     crypto
-        .create_certificate(&edgelet_ca_props)
+        .create_certificate(&workload_ca_props())
         .map_err(Error::from)?;
     Ok(())
 }
 
+/// Replaces the quick start device CA, and the workload CA issued by it, when
+/// the workload CA expires within `renew_before` of `now`. The workload CA
+/// never outlives the device CA, so its expiry is the one that matters.
+/// Returns whether the CAs were replaced.
+fn renew_quickstart_ca_if_expiring<C>(
+    crypto: &C,
+    renew_before: Duration,
+    now: DateTime<Utc>,
+) -> Result<bool, Error>
+where
+    C: CreateCertificate + RenewQuickstartCa,
+{
+    let valid_to = crypto
+        .create_certificate(&workload_ca_props())?
+        .get_valid_to()?;
+    let renew_before =
+        ChronoDuration::from_std(renew_before).unwrap_or_else(|_| ChronoDuration::max_value());
+    if valid_to.signed_duration_since(now) > renew_before {
+        return Ok(false);
+    }
+
+    info!("Device CA expires on {}, replacing it...", valid_to);
+    destroy_workload_ca(crypto)?;
+    crypto.renew_quickstart_ca()?;
+    prepare_workload_ca(crypto)?;
+    info!("Finished replacing the device CA.");
+    Ok(true)
+}
+
 fn destroy_workload_ca<C>(crypto: &C) -> Result<(), Error>
 where
     C: CreateCertificate,
@@ -371,6 +794,70 @@ where
     Ok(())
 }
 
+/// The TLS an `https` listener is served with: the certificate and key from
+/// its settings, or else a certificate for the host of its URL issued by the
+/// workload CA. The issued certificate is replaced every time iotedged
+/// starts. Modules verify it with the trust bundle. Other listeners are
+/// served without TLS.
+fn listener_tls<C>(
+    url: &Url,
+    tls: &ListenTls,
+    policy: &TlsPolicy,
+    name: &str,
+    crypto: &C,
+) -> Result<Option<ServerTlsConfig>, Error>
+where
+    C: CreateCertificate,
+{
+    if url.scheme() != "https" {
+        return Ok(None);
+    }
+
+    let config = match (tls.server_cert(), tls.server_key()) {
+        (Some(cert), Some(key)) => {
+            ServerTlsConfig::from_files(cert, key).context(ErrorKind::ListenerTls)?
+        }
+        _ => {
+            let host = match url.host() {
+                Some(Host::Ipv6(ip)) => ip.to_string(),
+                Some(host) => host.to_string(),
+                None => return Err(Error::from(ErrorKind::ListenerTls)),
+            };
+            let san = match host.parse::<IpAddr>() {
+                Ok(ip) => format!("IP:{}", ip),
+                Err(_) => format!("DNS:{}", host),
+            };
+            let props = CertificateProperties::new(
+                IOTEDGED_VALIDITY,
+                host,
+                CertificateType::Server,
+                format!("iotedged{}listener", name),
+            ).with_san_entries(vec![san]);
+            let cert = crypto
+                .create_certificate(&props)
+                .context(ErrorKind::ListenerTls)?;
+            let pem = cert.pem().context(ErrorKind::ListenerTls)?;
+            match cert.get_private_key().context(ErrorKind::ListenerTls)? {
+                Some(PrivateKey::Key(KeyBytes::Pem(key))) => {
+                    ServerTlsConfig::from_pem(pem.as_ref(), key.as_ref())
+                        .context(ErrorKind::ListenerTls)?
+                }
+                _ => return Err(Error::from(ErrorKind::ListenerTls)),
+            }
+        }
+    };
+
+    let config = config.with_policy(policy.clone());
+    match tls.client_ca_cert() {
+        Some(client_ca_cert) => Ok(Some(
+            config
+                .with_client_ca(client_ca_cert)
+                .context(ErrorKind::ListenerTls)?,
+        )),
+        None => Ok(Some(config)),
+    }
+}
+
 fn check_settings_state<M, C>(
     subdir_path: PathBuf,
     filename: &str,
@@ -458,18 +945,33 @@ where
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
-fn start_api<HC, K, F, C, W>(
+fn start_api<M, HC, K, F, C, W>(
     settings: &Settings<DockerConfig>,
     hyper_client: HC,
-    runtime: &DockerModuleRuntime,
+    runtime: &M,
     key_store: &DerivedKeyStore<K>,
     workload_config: W,
     root_key: K,
+    auth_type: AuthType,
     shutdown_signal: F,
     crypto: &C,
     mut tokio_runtime: tokio::runtime::Runtime,
+    config_file: Option<String>,
+    restarts: ModuleRestarts,
+    health: &DaemonHealth,
+    metrics: &MetricsRegistry,
+    runtime_errors: &RuntimeErrorLog,
 ) -> Result<(), Error>
 where
+    M: 'static + ModuleRuntime<Config = DockerConfig, Error = DockerError> + Clone + Send + Sync,
+    <M::Module as Module>::Config: Clone + DeserializeOwned + Serialize,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<CoreError> + Into<DockerError>,
+    <M::ModuleRegistry as ModuleRegistry>::Error: IntoResponse,
+    M::Chunk: Into<HyperChunk>,
+    M::Logs: Into<Body>,
+    M::SystemInfoFuture: Send,
+    M::CheckIntegrityFuture: Send,
     F: Future<Item = (), Error = ()> + Send + 'static,
     HC: ClientImpl + 'static,
     K: Sign + Clone + Send + Sync + 'static,
@@ -477,7 +979,10 @@ where
         + Decrypt
         + Encrypt
         + GetTrustBundle
+        + MakeRandom
         + MasterEncryptionKey
+        + RenewQuickstartCa
+        + SignCertificateRequest
         + Clone
         + Send
         + Sync
@@ -486,39 +991,288 @@ where
 {
     let hub_name = workload_config.iot_hub_name().to_string();
     let device_id = workload_config.device_id().to_string();
-    let hostname = format!("https://{}", hub_name);
-    let token_source = SasTokenSource::new(hub_name.clone(), device_id.clone(), root_key);
+    let hub_url = upstream_url(
+        &Url::parse(&format!("https://{}", hub_name))?,
+        settings.parent_hostname(),
+    )?;
+    let sas_tokens = settings.sas_tokens();
+    let token_source = match auth_type {
+        // The client certificate of the hyper client authenticates the device.
+        AuthType::X509 => None,
+        AuthType::Sas | AuthType::None => Some(
+            SasTokenSource::new(hub_name.clone(), device_id.clone(), root_key)
+                .with_renew_before(sas_tokens.renew_before()),
+        ),
+    };
     let http_client = HttpClient::new(
         hyper_client,
-        Some(token_source),
+        token_source,
         IOTHUB_API_VERSION,
-        Url::parse(&hostname)?,
-    )?;
+        hub_url,
+    )?.with_token_ttl(sas_tokens.ttl());
+    let http_client = match settings.timeouts().iothub_request() {
+        Some(timeout) => http_client.with_request_timeout(timeout),
+        None => http_client,
+    };
     let device_client = DeviceClient::new(http_client, &device_id)?;
-    let id_man = HubIdentityManager::new(key_store.clone(), device_client);
+    let key_store = CachedKeyStore::new(key_store.clone(), settings.key_cache().max_entries());
+
+    match settings.identity_manager() {
+        IdentityManagerType::IotHub => {
+            check_edge_device(
+                &device_client,
+                &hub_name,
+                settings.edge_device_check(),
+                &mut tokio_runtime,
+            )?;
+            start_services(
+                settings,
+                runtime,
+                &key_store,
+                HubIdentityManager::new(key_store.clone(), device_client),
+                workload_config,
+                shutdown_signal,
+                crypto,
+                tokio_runtime,
+                config_file,
+                restarts,
+                health,
+                metrics,
+                runtime_errors,
+            )
+        }
+        IdentityManagerType::Local => {
+            let path = settings.homedir().join(EDGE_LOCAL_IDENTITIES_FILENAME);
+            warn!(
+                "Module identities are kept in {} and not created in IoT Hub.",
+                path.display()
+            );
+            let id_man =
+                LocalIdentityManager::open(path, key_store.clone()).map_err(CoreError::from)?;
+            start_services(
+                settings,
+                runtime,
+                &key_store,
+                id_man,
+                workload_config,
+                shutdown_signal,
+                crypto,
+                tokio_runtime,
+                config_file,
+                restarts,
+                health,
+                metrics,
+                runtime_errors,
+            )
+        }
+    }
+}
+
+/// Makes sure IoT Hub has the device registered as an IoT Edge device. A
+/// plain device provisions fine, but the edge hub module cannot run on it.
+fn check_edge_device<HC, K>(
+    device_client: &DeviceClient<HC, SasTokenSource<K>>,
+    hub_name: &str,
+    check: EdgeDeviceCheck,
+    tokio_runtime: &mut tokio::runtime::Runtime,
+) -> Result<(), Error>
+where
+    HC: ClientImpl + 'static,
+    K: Sign + Clone + Send + Sync + 'static,
+{
+    match tokio_runtime.block_on(edge_capability(device_client))? {
+        EdgeCapability::Enabled => Ok(()),
+        EdgeCapability::Disabled => {
+            let err = HubIdentityError::from(HubIdentityErrorKind::NotEdgeDevice(
+                device_client.device_id().to_string(),
+                hub_name.to_string(),
+            ));
+            match check {
+                EdgeDeviceCheck::Enforce => Err(Error::from(err)),
+                EdgeDeviceCheck::Warn => {
+                    log_failure(Level::Warn, &err);
+                    Ok(())
+                }
+            }
+        }
+        EdgeCapability::Unknown(reason) => {
+            warn!(
+                "Could not check whether device {} in IoT Hub {} is an IoT Edge device: {}",
+                device_client.device_id(),
+                hub_name,
+                reason
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Serves the management and workload APIs and runs the edge runtime module
+/// until shutdown is signaled, with `id_man` keeping the module identities.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn start_services<M, I, K, F, C, W>(
+    settings: &Settings<DockerConfig>,
+    runtime: &M,
+    key_store: &ModuleKeyStore<K>,
+    id_man: I,
+    workload_config: W,
+    shutdown_signal: F,
+    crypto: &C,
+    mut tokio_runtime: tokio::runtime::Runtime,
+    config_file: Option<String>,
+    restarts: ModuleRestarts,
+    health: &DaemonHealth,
+    metrics: &MetricsRegistry,
+    runtime_errors: &RuntimeErrorLog,
+) -> Result<(), Error>
+where
+    M: 'static + ModuleRuntime<Config = DockerConfig, Error = DockerError> + Clone + Send + Sync,
+    <M::Module as Module>::Config: Clone + DeserializeOwned + Serialize,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<CoreError> + Into<DockerError>,
+    <M::ModuleRegistry as ModuleRegistry>::Error: IntoResponse,
+    M::Chunk: Into<HyperChunk>,
+    M::Logs: Into<Body>,
+    M::SystemInfoFuture: Send,
+    M::CheckIntegrityFuture: Send,
+    I: 'static + IdentityManager + Clone + Send + Sync,
+    I::Identity: Serialize,
+    I::Error: IntoResponse + IdentityError + Into<CoreError>,
+    F: Future<Item = (), Error = ()> + Send + 'static,
+    K: Sign + Clone + Send + Sync + 'static,
+    C: CreateCertificate
+        + Decrypt
+        + Encrypt
+        + GetTrustBundle
+        + MakeRandom
+        + MasterEncryptionKey
+        + RenewQuickstartCa
+        + SignCertificateRequest
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    W: WorkloadConfig + Clone + Send + Sync + 'static,
+{
+    let hub_name = workload_config.iot_hub_name().to_string();
+    let device_id = workload_config.device_id().to_string();
 
     let (mgmt_tx, mgmt_rx) = oneshot::channel();
     let (work_tx, work_rx) = oneshot::channel();
+    let (metrics_tx, metrics_rx) = oneshot::channel();
+
+    let mgmt_limits = SharedLimits::new(settings.listen().management_limits().connection_limits());
+    let work_limits = SharedLimits::new(settings.listen().workload_limits().connection_limits());
+    let mgmt_rates = RateLimiter::new(settings.listen().management_rates().rate_limits());
+
+    let listen = settings.listen();
+    let tls_policy = tls_policy(settings)?;
+    let mgmt_tls = listener_tls(
+        listen.management_uri(),
+        listen.management_tls(),
+        &tls_policy,
+        "mgmt",
+        crypto,
+    )?;
+    let work_tls = listener_tls(
+        listen.workload_uri(),
+        listen.workload_tls(),
+        &tls_policy,
+        "work",
+        crypto,
+    )?;
 
-    let mgmt = start_management(&settings, &runtime, &id_man, mgmt_rx);
+    let mgmt = start_management(
+        settings,
+        runtime,
+        &id_man,
+        health,
+        metrics,
+        runtime_errors,
+        mgmt_rx,
+        mgmt_limits.clone(),
+        mgmt_rates.clone(),
+        mgmt_tls,
+    );
+    let mgmt = match settings.metrics().listen_uri() {
+        Some(url) => Either::A(
+            mgmt.join(start_metrics(url.clone(), metrics, metrics_rx))
+                .map(|((), ())| ()),
+        ),
+        None => Either::B(mgmt),
+    };
 
+    let issued = IssuedCerts::open(settings.homedir().join(EDGE_ISSUED_CERTS_FILENAME));
     let workload = start_workload(
-        &settings,
+        settings,
         key_store,
-        &runtime,
+        runtime,
+        &id_man,
         work_rx,
         crypto,
         workload_config,
+        work_limits.clone(),
+        issued.clone(),
+        metrics,
+        work_tls,
     );
 
+    let image_gc = settings.image_garbage_collection();
+    let image_gc_policy =
+        ImageGcPolicy::new(image_gc.enabled(), image_gc.cleanup_time(), image_gc.min_age());
+
+    if let Some(config_file) = config_file {
+        tokio_runtime.spawn(reload_settings(
+            config_file,
+            settings.clone(),
+            mgmt_limits,
+            work_limits,
+            mgmt_rates,
+            restarts.clone(),
+            image_gc_policy.clone(),
+        ));
+    }
+
+    tokio_runtime.spawn(check_health(runtime.clone(), id_man.clone(), health.clone()));
+
+    // The collector runs even while collection is disabled, so that enabling
+    // it on reload takes effect.
+    if let Some(busy) = runtime.busy() {
+        tokio_runtime.spawn(
+            ImageGarbageCollector::new(runtime.clone(), busy.clone())
+                .with_policy(image_gc_policy)
+                .run(),
+        );
+    } else if image_gc.enabled() {
+        warn!("The module runtime can't tell when modules change, unused images are kept");
+    }
+
+    if let (None, Some(renew_before)) = (
+        settings.certificates(),
+        settings.quickstart_ca().renew_before(),
+    ) {
+        tokio_runtime.spawn(renew_quickstart_ca(crypto.clone(), issued, renew_before));
+    }
+
     let (runt_tx, runt_rx) = oneshot::channel();
-    let edge_rt = start_runtime(&runtime, &id_man, &hub_name, &device_id, &settings, runt_rx)?;
+    let edge_rt = start_runtime(
+        runtime,
+        restarts,
+        &id_man,
+        &hub_name,
+        &device_id,
+        settings,
+        health,
+        metrics,
+        runt_rx,
+    )?;
 
     // Wait for the watchdog to finish, and then send signal to the workload and management services.
     // This way the edgeAgent can finish shutting down all modules.
     let edge_rt_with_cleanup = edge_rt.map_err(Into::into).and_then(|_| {
         mgmt_tx.send(()).unwrap_or(());
         work_tx.send(()).unwrap_or(());
+        metrics_tx.send(()).unwrap_or(());
         future::ok(())
     });
 
@@ -545,21 +1299,101 @@ where
     Ok(())
 }
 
-fn init_docker_runtime(
-    runtime: &DockerModuleRuntime,
-    tokio_runtime: &mut tokio::runtime::Runtime,
-) -> Result<(), Error> {
+/// Initializes the module runtime, which creates the network modules are
+/// attached to. Nothing is created in the runtime before it succeeds.
+fn init_runtime<M>(runtime: &M, tokio_runtime: &mut tokio::runtime::Runtime) -> Result<(), Error>
+where
+    M: 'static + ModuleRuntime + Clone + Send,
+    M::Error: Into<Error>,
+{
     info!("Initializing the module runtime...");
-    tokio_runtime.block_on(runtime.init())?;
+    let init = init_with_retries(
+        runtime,
+        RUNTIME_INIT_ATTEMPTS,
+        Duration::from_secs(RUNTIME_INIT_RETRY_DELAY_SECS),
+    );
+    tokio_runtime
+        .block_on(init)
+        .map_err(Into::<Error>::into)
+        .context(ErrorKind::RuntimeInit)?;
     info!("Finished initializing the module runtime.");
     Ok(())
 }
 
+/// Whether the container runtime runs Windows containers, whose environment
+/// variable names are not case sensitive. When the runtime can't be asked,
+/// its containers are assumed to be for the platform iotedged runs on.
+fn runs_windows_containers(
+    runtime: &DockerModuleRuntime,
+    tokio_runtime: &mut tokio::runtime::Runtime,
+) -> bool {
+    match tokio_runtime.block_on(runtime.system_info()) {
+        Ok(info) => info.os_type().eq_ignore_ascii_case("windows"),
+        Err(err) => {
+            warn!("Could not get the OS type of the container runtime.");
+            log_failure(Level::Warn, &err);
+            cfg!(windows)
+        }
+    }
+}
+
+/// Compares the modules that exist with the ones the runtime last recorded
+/// creating, and logs every difference. Failing to list the modules is not
+/// fatal; the health check tries again later.
+fn check_module_integrity<M>(
+    runtime: &M,
+    health: &DaemonHealth,
+    tokio_runtime: &mut tokio::runtime::Runtime,
+) where
+    M: ModuleRuntime,
+    M::Error: Into<Error> + Send,
+    M::CheckIntegrityFuture: Send + 'static,
+{
+    info!("Checking modules against their last known state...");
+    match tokio_runtime.block_on(runtime.check_integrity().map_err(Into::<Error>::into)) {
+        Ok(report) => {
+            if report.rebuilt() {
+                info!("Rebuilt the last known module state from the modules that exist.");
+            }
+            for discrepancy in report.discrepancies() {
+                warn!("Module state mismatch: {}", discrepancy);
+            }
+            health.record_integrity(integrity_result(&report), Utc::now());
+            info!("Finished checking modules.");
+        }
+        Err(err) => {
+            warn!("Could not check modules against their last known state.");
+            log_failure(Level::Warn, &err);
+            health.record_integrity(Err(err.to_string()), Utc::now());
+        }
+    }
+}
+
+fn integrity_result(report: &IntegrityReport) -> Result<(), String> {
+    if report.is_consistent() {
+        Ok(())
+    } else {
+        let discrepancies: Vec<String> = report
+            .discrepancies()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        Err(discrepancies.join("; "))
+    }
+}
+
 fn manual_provision(
     provisioning: &Manual,
     tokio_runtime: &mut tokio::runtime::Runtime,
 ) -> Result<(DerivedKeyStore<MemoryKey>, ProvisioningResult, MemoryKey), Error> {
-    let manual = ManualProvisioning::new(provisioning.device_connection_string())?;
+    let manual = match provisioning.x509() {
+        Some(x509) => ManualProvisioning::with_x509(
+            x509.iothub_hostname(),
+            x509.device_id(),
+            x509_module_root_key(x509)?,
+        )?,
+        None => ManualProvisioning::new(provisioning.device_connection_string())?,
+    };
     let memory_hsm = MemoryKeyStore::new();
     let provision = manual
         .provision(memory_hsm.clone())
@@ -576,9 +1410,22 @@ fn manual_provision(
     tokio_runtime.block_on(provision)
 }
 
+/// The key module keys are derived from when the device authenticates with an
+/// X.509 certificate, and so has no shared access key. It is derived from the
+/// identity private key, so module keys stay the same for as long as it does.
+fn x509_module_root_key(x509: &ManualX509) -> Result<MemoryKey, Error> {
+    let identity_pk = fs::read(x509.identity_pk())?;
+    let mut hasher = Sha256::new();
+    hasher.input(X509_MODULE_ROOT_KEY_LABEL);
+    hasher.input(&identity_pk);
+    Ok(MemoryKey::new(hasher.result()))
+}
+
 fn dps_provision<HC, M>(
     provisioning: &Dps,
     hyper_client: HC,
+    request_timeout: Option<Duration>,
+    parent_hostname: Option<&str>,
     backup_path: PathBuf,
     runtime: M,
     tokio_runtime: &mut tokio::runtime::Runtime,
@@ -593,17 +1440,105 @@ where
     let srk_result = tpm.get_srk().map_err(Error::from)?;
     let dps = DpsProvisioning::new(
         hyper_client,
-        provisioning.global_endpoint().clone(),
+        upstream_url(provisioning.global_endpoint(), parent_hostname)?,
         provisioning.scope_id().to_string(),
-        provisioning.registration_id().to_string(),
+        dps_registration_id(provisioning)?,
         "2017-11-15",
         ek_result,
         srk_result,
     )?;
+    let dps = match request_timeout {
+        Some(timeout) => dps.with_request_timeout(timeout),
+        None => dps,
+    };
     let tpm_hsm = TpmKeyStore::from_hsm(tpm)?;
-    let provision_with_file_backup = BackupProvisioning::new(dps, backup_path);
+    provision_with_backup(dps, tpm_hsm, backup_path, runtime, tokio_runtime)
+}
+
+/// Provisions a device of a symmetric key enrollment group, which attests
+/// with a key derived from `group_key` rather than with its TPM.
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn dps_symmetric_key_provision<HC, M>(
+    provisioning: &Dps,
+    group_key: &str,
+    hyper_client: HC,
+    request_timeout: Option<Duration>,
+    parent_hostname: Option<&str>,
+    backup_path: PathBuf,
+    runtime: M,
+    tokio_runtime: &mut tokio::runtime::Runtime,
+) -> Result<(DerivedKeyStore<MemoryKey>, ProvisioningResult, MemoryKey, M), Error>
+where
+    HC: 'static + ClientImpl,
+    M: ModuleRuntime + Send + 'static,
+    M::Error: Into<Error>,
+{
+    let dps = DpsSymmetricKeyProvisioning::new(
+        hyper_client,
+        upstream_url(provisioning.global_endpoint(), parent_hostname)?,
+        provisioning.scope_id().to_string(),
+        dps_registration_id(provisioning)?,
+        MemoryKey::new(base64::decode(group_key)?),
+        "2017-11-15",
+    )?;
+    let dps = match request_timeout {
+        Some(timeout) => dps.with_request_timeout(timeout),
+        None => dps,
+    };
+    provision_with_backup(dps, MemoryKeyStore::new(), backup_path, runtime, tokio_runtime)
+}
+
+/// The registration id the device registers with in DPS, from where its
+/// settings say to take it.
+fn dps_registration_id(provisioning: &Dps) -> Result<String, Error> {
+    let registration_id = provisioning
+        .registration_id_source()
+        .resolve(provisioning.registration_id(), os_hostname)?;
+    info!(
+        "Using DPS registration id \"{}\" from the {} registration id source",
+        registration_id,
+        provisioning.registration_id_source()
+    );
+    Ok(registration_id)
+}
+
+/// The name the operating system knows the device by.
+#[cfg(unix)]
+fn os_hostname() -> io::Result<String> {
+    let mut name = [0_u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or_else(|| name.len());
+    Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+/// The name the operating system knows the device by.
+#[cfg(windows)]
+fn os_hostname() -> io::Result<String> {
+    env::var("COMPUTERNAME").map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))
+}
+
+/// Provisions with `provisioner`, falling back to the result of the last
+/// successful provisioning in `backup_path` if that fails. The modules are
+/// removed whenever the device is provisioned anew.
+fn provision_with_backup<P, K, M>(
+    provisioner: P,
+    key_store: P::Hsm,
+    backup_path: PathBuf,
+    runtime: M,
+    tokio_runtime: &mut tokio::runtime::Runtime,
+) -> Result<(DerivedKeyStore<K>, ProvisioningResult, K, M), Error>
+where
+    P: 'static + Provision,
+    P::Hsm: KeyStore<Key = K> + Clone + Send + 'static,
+    K: Clone + Send + 'static,
+    M: ModuleRuntime + Send + 'static,
+    M::Error: Into<Error>,
+{
+    let provision_with_file_backup = BackupProvisioning::new(provisioner, backup_path);
     let provision = provision_with_file_backup
-        .provision(tpm_hsm.clone())
+        .provision(key_store.clone())
         .map_err(Error::from)
         .and_then(|prov_result| {
             if prov_result.reconfigure() {
@@ -621,7 +1556,7 @@ where
                 Either::B(future::ok((prov_result, runtime)))
             }
         }).and_then(move |(prov_result, runtime)| {
-            tpm_hsm
+            key_store
                 .get(&KeyIdentity::Device, "primary")
                 .map_err(Error::from)
                 .and_then(|k| {
@@ -633,37 +1568,54 @@ where
     tokio_runtime.block_on(provision)
 }
 
-fn start_runtime<K, HC>(
-    runtime: &DockerModuleRuntime,
-    id_man: &HubIdentityManager<DerivedKeyStore<K>, HC, K>,
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn start_runtime<M, I>(
+    runtime: &M,
+    restarts: ModuleRestarts,
+    id_man: &I,
     hostname: &str,
     device_id: &str,
     settings: &Settings<DockerConfig>,
+    health: &DaemonHealth,
+    metrics: &MetricsRegistry,
     shutdown: Receiver<()>,
 ) -> Result<impl Future<Item = (), Error = Error>, Error>
 where
-    K: 'static + Sign + Clone + Send + Sync,
-    HC: 'static + ClientImpl,
+    M: 'static + ModuleRuntime<Config = DockerConfig> + Clone,
+    M::Error: Into<CoreError>,
+    <M::Module as Module>::Config: Clone,
+    <M::Module as Module>::Error: Into<CoreError>,
+    I: 'static + IdentityManager + Clone,
+    I::Error: Into<CoreError>,
 {
     let spec = settings.agent().clone();
-    let env = build_env(spec.env(), hostname, device_id, settings);
     let mut spec = ModuleSpec::<DockerConfig>::new(
         EDGE_RUNTIME_MODULE_NAME,
         spec.type_(),
         spec.config().clone(),
-        env,
+        spec.env().clone(),
     )?;
 
-    // volume mount management and workload URIs
-    vol_mount_uri(
+    let (listen, connect) = (settings.listen(), settings.connect());
+    agent_config(
         spec.config_mut(),
         &[
-            settings.connect().management_uri(),
-            settings.connect().workload_uri(),
+            (listen.management_uri(), connect.management_uri()),
+            (listen.workload_uri(), connect.workload_uri()),
         ],
+        settings.moby_runtime().network(),
     )?;
 
-    let watchdog = Watchdog::new(runtime.clone(), id_man.clone());
+    let watchdog = Watchdog::new(runtime.clone(), id_man.clone())
+        .with_restarts(restarts)
+        .with_health(health.clone())
+        .with_offline_start(settings.allow_offline_start())
+        .with_running_modules(RunningModules::open(
+            settings.homedir().join(EDGE_RUNNING_MODULES_FILENAME),
+        ))
+        .with_metrics(Arc::new(metrics.clone()))
+        .with_stop_options(settings.shutdown().stop_options())
+        .with_module_env(module_env(hostname, device_id, settings));
     let runtime_future = watchdog
         .run_until(spec, EDGE_RUNTIME_MODULEID, shutdown.map_err(|_| ()))
         .map_err(Error::from);
@@ -671,112 +1623,368 @@ where
     Ok(runtime_future)
 }
 
-fn vol_mount_uri(config: &mut DockerConfig, uris: &[&Url]) -> Result<(), Error> {
-    let create_options = config.clone_create_options()?;
+/// Adds what the edge agent needs to reach iotedged to its create options.
+/// Each of the `(listen, connect)` URIs of the APIs served on a unix socket
+/// has the socket mounted where the agent connects to it. The container is
+/// attached to `network` unless the options choose a network themselves, so
+/// that it doesn't depend on the network being there when it is created.
+fn agent_config(
+    config: &mut DockerConfig,
+    sockets: &[(&Url, &Url)],
+    network: &str,
+) -> Result<(), Error> {
+    let mut create_options = config.clone_create_options()?;
     let host_config = create_options
         .host_config()
         .cloned()
         .unwrap_or_else(HostConfig::new);
     let mut binds = host_config.binds().map_or_else(Vec::new, ToOwned::to_owned);
 
-    // if the url is a domain socket URL then vol mount it into the container
-    for uri in uris {
-        if uri.scheme() == UNIX_SCHEME {
-            binds.push(format!("{}:{}", uri.path(), uri.path()));
+    for &(listen, connect) in sockets {
+        if let Some(bind) = socket_bind(listen, connect) {
+            if !binds.contains(&bind) {
+                binds.push(bind);
+            }
         }
     }
 
-    if !binds.is_empty() {
-        let host_config = host_config.with_binds(binds);
-        let create_options = create_options.with_host_config(host_config);
+    let has_network = host_config.network_mode().is_some()
+        || create_options
+            .networking_config()
+            .and_then(|config| config.endpoints_config())
+            .map_or(false, |endpoints| !endpoints.is_empty());
+    if !has_network && !network.is_empty() {
+        let endpoints = iter::once((network.to_string(), EndpointSettings::new())).collect();
+        create_options = create_options.with_networking_config(
+            ContainerCreateBodyNetworkingConfig::new().with_endpoints_config(endpoints),
+        );
+    }
 
-        config.set_create_options(create_options);
+    if !binds.is_empty() {
+        create_options = create_options.with_host_config(host_config.with_binds(binds));
     }
+    config.set_create_options(create_options);
 
     Ok(())
 }
 
-// Add the environment variables needed by the EdgeAgent.
-fn build_env(
-    spec_env: &HashMap<String, String>,
+/// The bind mount of the socket an API is served on, if modules connect to
+/// it over a unix socket. The socket of a unix listener is where it listens;
+/// the one of a socket activated listener is where modules connect to it.
+fn socket_bind(listen: &Url, connect: &Url) -> Option<String> {
+    if connect.scheme() != UNIX_SCHEME {
+        return None;
+    }
+    let socket = if listen.scheme() == UNIX_SCHEME {
+        listen.path()
+    } else {
+        connect.path()
+    };
+    Some(format!("{}:{}", socket, connect.path()))
+}
+
+/// Gives the workload API the bundle edgeAgent verifies signed deployment
+/// manifests with, if one is configured. Validating the settings already
+/// made sure it holds current certificates.
+fn with_manifest_trust_bundle(
+    cfg: WorkloadData,
+    settings: &Settings<DockerConfig>,
+) -> Result<WorkloadData, Error> {
+    match settings.manifest_trust_bundle() {
+        Some(path) => {
+            info!("Serving manifest trust bundle {}", path.display());
+            let bundle = fs::read_to_string(path)?;
+            Ok(cfg.with_manifest_trust_bundle(bundle))
+        }
+        None => Ok(cfg),
+    }
+}
+
+/// What the environment of the edge runtime module is made of, besides its
+/// identity.
+fn module_env(
     hostname: &str,
     device_id: &str,
     settings: &Settings<DockerConfig>,
-) -> HashMap<String, String> {
-    let mut env = HashMap::new();
-    env.insert(HOSTNAME_KEY.to_string(), hostname.to_string());
-    env.insert(
-        GATEWAY_HOSTNAME_KEY.to_string(),
-        settings.hostname().to_string().to_lowercase(),
-    );
-    env.insert(DEVICEID_KEY.to_string(), device_id.to_string());
-    env.insert(MODULEID_KEY.to_string(), EDGE_RUNTIME_MODULEID.to_string());
-    env.insert(
-        WORKLOAD_URI_KEY.to_string(),
-        settings.connect().workload_uri().to_string(),
-    );
-    env.insert(
-        MANAGEMENT_URI_KEY.to_string(),
-        settings.connect().management_uri().to_string(),
-    );
-    env.insert(AUTHSCHEME_KEY.to_string(), AUTH_SCHEME.to_string());
-    env.insert(
-        EDGE_RUNTIME_MODE_KEY.to_string(),
-        EDGE_RUNTIME_MODE.to_string(),
-    );
-    env.insert(
-        EDGE_NETWORKID_KEY.to_string(),
-        settings.moby_runtime().network().to_string(),
-    );
-    for (key, val) in spec_env.iter() {
-        env.insert(key.clone(), val.clone());
-    }
-    env.insert(API_VERSION_KEY.to_string(), API_VERSION.to_string());
-    env
+) -> ModuleEnvSettings {
+    ModuleEnvSettings::new(hostname, device_id, settings.hostname())
+        .with_parent_hostname(settings.parent_hostname().map(ToString::to_string))
+        .with_workload_uri(settings.connect().workload_uri().to_string())
+        .with_management_uri(settings.connect().management_uri().to_string())
+        .with_api_version(API_VERSION.to_string())
+        .with_network_id(settings.moby_runtime().network().to_string())
+        .with_manifest_trust_bundle(settings.manifest_trust_bundle().is_some())
+        .with_injection(settings.module_env().clone())
 }
 
-fn start_management<K, HC>(
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn start_management<M, I>(
     settings: &Settings<DockerConfig>,
-    mgmt: &DockerModuleRuntime,
-    id_man: &HubIdentityManager<DerivedKeyStore<K>, HC, K>,
+    mgmt: &M,
+    id_man: &I,
+    health: &DaemonHealth,
+    metrics: &MetricsRegistry,
+    runtime_errors: &RuntimeErrorLog,
     shutdown: Receiver<()>,
+    limits: SharedLimits,
+    rates: RateLimiter,
+    tls: Option<ServerTlsConfig>,
 ) -> impl Future<Item = (), Error = failure::Error>
 where
-    K: 'static + Sign + Clone + Send + Sync,
-    HC: 'static + ClientImpl + Send + Sync,
+    M: 'static + ModuleRuntime + Clone + Send + Sync,
+    <M::Module as Module>::Config: DeserializeOwned + Serialize,
+    M::Error: IntoResponse + Into<CoreError>,
+    M::Module: Send,
+    <M::Module as Module>::Error: Into<CoreError> + Into<M::Error>,
+    <M::ModuleRegistry as ModuleRegistry>::Error: IntoResponse,
+    M::Chunk: Into<HyperChunk>,
+    I: 'static + IdentityManager + Clone + Send + Sync,
+    I::Identity: Serialize,
+    I::Error: IntoResponse + IdentityError,
 {
     info!("Starting management API...");
 
     let label = "mgmt".to_string();
     let url = settings.listen().management_uri().clone();
+    let drain_timeout = settings.listen().drain_timeout();
+    let permissions = settings.listen().management_socket().permissions();
+
+    let recorder: Arc<Metrics> = Arc::new(metrics.clone());
+
+    let redaction = EnvRedaction::default().with_patterns(settings.redacted_env());
+
+    let events = ModuleEvents::default();
+    let watch = watch_modules(
+        mgmt.clone(),
+        events.clone(),
+        Arc::new(SystemClock),
+        Duration::from_secs(DEFAULT_WATCH_INTERVAL_SECS),
+    ).map_err(failure::Error::from);
+
+    let disks = DiskMonitor::new(Arc::new(StatVfs), settings.homedir().to_path_buf());
+
+    ManagementService::new(
+        mgmt,
+        id_man,
+        health,
+        metrics,
+        runtime_errors,
+        &redaction,
+        settings.moby_runtime().kill_signals(),
+        &events,
+        Some(&disks),
+    ).map(move |service| {
+        let service = RateLimitService::new(service, rates, route_class);
+        let service = ApiVersionService::new(service);
+        let service = MetricsService::new(label.clone(), recorder, service);
+        LoggingService::new(label, service)
+    }).and_then(move |service| {
+        let server = Http::new()
+            .bind_url_with_tls(url.clone(), service, tls.as_ref())
+            .map_err(failure::Fail::compat)?
+            .with_socket_permissions(&permissions)
+            .map_err(failure::Fail::compat)?
+            .with_drain_timeout(drain_timeout)
+            .with_connection_limits(limits);
+        // The modules are only watched for as long as the API is served.
+        let run = server
+            .run_until(shutdown.map_err(|_| ()))
+            .select(watch)
+            .map(|_| ())
+            .map_err(|(err, _)| err);
+        info!("Listening on {} with 1 thread for management API.", url);
+        Ok(run)
+    }).flatten()
+}
 
-    ManagementService::new(mgmt, id_man)
-        .map(|service| LoggingService::new(label, ApiVersionService::new(service)))
-        .and_then(move |service| {
-            let run = Http::new()
+/// Serves the metrics on a listener of their own, without the API version
+/// the management API requires, so that scrapers on the network can reach
+/// them.
+fn start_metrics(
+    url: Url,
+    metrics: &MetricsRegistry,
+    shutdown: Receiver<()>,
+) -> impl Future<Item = (), Error = failure::Error> {
+    info!("Starting metrics listener...");
+
+    let label = "metrics".to_string();
+    let recorder: Arc<Metrics> = Arc::new(metrics.clone());
+    let router = Router::from(
+        RegexRoutesBuilder::default()
+            .get("/metrics", GetMetrics::new(metrics.clone()))
+            .finish(),
+    );
+
+    router
+        .new_service()
+        .map_err(failure::Error::from_boxed_compat)
+        .map(move |service| {
+            let service = MetricsService::new(label.clone(), recorder, service);
+            LoggingService::new(label, service)
+        }).and_then(move |service| {
+            let server = Http::new()
                 .bind_url(url.clone(), service)
-                .map_err(failure::Fail::compat)?
-                .run_until(shutdown.map_err(|_| ()));
-            info!("Listening on {} with 1 thread for management API.", url);
+                .map_err(failure::Fail::compat)?;
+            let run = server.run_until(shutdown.map_err(|_| ()));
+            info!("Listening on {} with 1 thread for metrics.", url);
             Ok(run)
         }).flatten()
 }
 
-fn start_workload<K, C, W>(
+/// Checks the container runtime, the identity backend and the modules in the
+/// background and records the results, so that the health endpoint can answer without
+/// waiting on either of them.
+fn check_health<M, I>(
+    runtime: M,
+    id_man: I,
+    health: DaemonHealth,
+) -> impl Future<Item = (), Error = ()>
+where
+    M: ModuleRuntime,
+    M::Error: Display,
+    I: IdentityManager,
+{
+    Interval::new(Instant::now(), Duration::from_secs(HEALTH_CHECK_FREQUENCY_SECS))
+        .map_err(|err| warn!("Health check timer failed: {}", err))
+        .for_each(move |_| {
+            let runtime_health = health.clone();
+            let identity_health = health.clone();
+            let integrity_health = health.clone();
+            let runtime_check = runtime.system_info().then(move |result| {
+                let result = result.map(|_| ()).map_err(|err| err.to_string());
+                runtime_health.record_runtime(result, Utc::now());
+                Ok(())
+            });
+            let identity_check = id_man.list().then(move |result| {
+                let result = result.map(|_| ()).map_err(|err| err.to_string());
+                identity_health.record_identity(result, Utc::now());
+                Ok(())
+            });
+            let integrity_check = runtime.check_integrity().then(move |result| {
+                let result = result
+                    .map_err(|err| err.to_string())
+                    .and_then(|report| integrity_result(&report));
+                integrity_health.record_integrity(result, Utc::now());
+                Ok(())
+            });
+            runtime_check
+                .join3(identity_check, integrity_check)
+                .map(|((), (), ())| ())
+        })
+}
+
+/// Checks the quick start device CA every day and replaces it when it is
+/// about to expire. The certificates issued to modules so far are then issued
+/// again, so that they chain up to the new CA.
+fn renew_quickstart_ca<C>(
+    crypto: C,
+    issued: IssuedCerts,
+    renew_before: Duration,
+) -> impl Future<Item = (), Error = ()>
+where
+    C: CreateCertificate + RenewQuickstartCa,
+{
+    let period = Duration::from_secs(CA_RENEWAL_CHECK_FREQUENCY_SECS);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|err| warn!("Device CA renewal timer failed: {}", err))
+        .for_each(move |_| {
+            match renew_quickstart_ca_if_expiring(&crypto, renew_before, Utc::now()) {
+                Ok(true) => match issued.reissue(&crypto) {
+                    Ok(count) => info!("Issued {} module certificates again", count),
+                    Err(err) => {
+                        log_failure(Level::Warn, &err);
+                        warn!("Could not issue every module certificate again");
+                    }
+                },
+                Ok(false) => (),
+                Err(err) => {
+                    log_failure(Level::Warn, &err);
+                    warn!("Could not replace the device CA");
+                }
+            }
+            Ok(())
+        })
+}
+
+/// Re-reads the config file on every reload signal and applies the settings
+/// that can change at runtime. The rest are compared against the settings
+/// the daemon started with, and only logged.
+fn reload_settings(
+    config_file: String,
+    mut running: Settings<DockerConfig>,
+    mgmt_limits: SharedLimits,
+    work_limits: SharedLimits,
+    mgmt_rates: RateLimiter,
+    restarts: ModuleRestarts,
+    image_gc: ImageGcPolicy,
+) -> impl Future<Item = (), Error = ()> {
+    signal::reload().for_each(move |()| {
+        let settings = match Settings::<DockerConfig>::new(Some(&config_file)) {
+            Ok(settings) => settings,
+            Err(err) => {
+                log_failure(Level::Warn, &err);
+                warn!("Could not reload {}, keeping the current settings", config_file);
+                return Ok(());
+            }
+        };
+        if let Err(err) = validate(&settings) {
+            log_failure(Level::Warn, &err);
+            warn!("Could not reload {}, keeping the current settings", config_file);
+            return Ok(());
+        }
+
+        let diff = running.diff(&settings);
+        if diff.is_empty() {
+            info!("Settings in {} are unchanged", config_file);
+        }
+        for field in diff.reloadable() {
+            info!("Applying new {} setting", field);
+        }
+        for field in diff.restart_required() {
+            warn!("Setting {} changed, restart iotedged to apply it", field);
+        }
+
+        running.apply_reloadable(&settings);
+        mgmt_limits.set(running.listen().management_limits().connection_limits());
+        work_limits.set(running.listen().workload_limits().connection_limits());
+        mgmt_rates.set_limits(running.listen().management_rates().rate_limits());
+        restarts.set_policy(running.watchdog().restart_policy());
+        let gc = running.image_garbage_collection();
+        image_gc.set(gc.enabled(), gc.cleanup_time(), gc.min_age());
+        logging::set_level(running.log_level());
+        Ok(())
+    })
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn start_workload<M, I, K, C, W>(
     settings: &Settings<DockerConfig>,
-    key_store: &K,
-    runtime: &DockerModuleRuntime,
+    key_store: &ModuleKeyStore<K>,
+    runtime: &M,
+    id_man: &I,
     shutdown: Receiver<()>,
     crypto: &C,
     config: W,
+    limits: SharedLimits,
+    issued: IssuedCerts,
+    metrics: &MetricsRegistry,
+    tls: Option<ServerTlsConfig>,
 ) -> impl Future<Item = (), Error = failure::Error>
 where
-    K: KeyStore + Clone + Send + Sync + 'static,
+    M: 'static + ModuleRuntime + Clone + Send + Sync,
+    M::Error: Into<CoreError>,
+    <M::Module as Module>::Config: Serialize,
+    <M::Module as Module>::Error: Into<CoreError>,
+    M::Logs: Into<Body>,
+    I: 'static + IdentityManager + Clone + Send + Sync,
+    K: 'static + Sign + Clone + Send + Sync,
     C: CreateCertificate
         + Decrypt
         + Encrypt
         + GetTrustBundle
+        + MakeRandom
         + MasterEncryptionKey
+        + SignCertificateRequest
         + Clone
         + Send
         + Sync
@@ -787,14 +1995,27 @@ where
 
     let label = "work".to_string();
     let url = settings.listen().workload_uri().clone();
-
-    WorkloadService::new(key_store, crypto.clone(), runtime, config)
-        .map(|service| LoggingService::new(label, ApiVersionService::new(service)))
+    let drain_timeout = settings.listen().drain_timeout();
+    let permissions = settings.listen().workload_socket().permissions();
+
+    let recorder: Arc<Metrics> = Arc::new(metrics.clone());
+    let clock: Arc<Clock> = Arc::new(SystemClock);
+
+    WorkloadService::new(key_store, crypto.clone(), runtime, id_man, config, issued, clock)
+        .map(move |service| {
+            let service = ApiVersionService::new(service);
+            let service = MetricsService::new(label.clone(), recorder, service);
+            LoggingService::new(label, service)
+        })
         .and_then(move |service| {
-            let run = Http::new()
-                .bind_url(url.clone(), service)
+            let server = Http::new()
+                .bind_url_with_tls(url.clone(), service, tls.as_ref())
+                .map_err(failure::Fail::compat)?
+                .with_socket_permissions(&permissions)
                 .map_err(failure::Fail::compat)?
-                .run_until(shutdown.map_err(|_| ()));
+                .with_drain_timeout(drain_timeout)
+                .with_connection_limits(limits);
+            let run = server.run_until(shutdown.map_err(|_| ()));
             info!("Listening on {} with 1 thread for workload API.", url);
             Ok(run)
         }).flatten()
@@ -804,6 +2025,7 @@ where
 mod tests {
     use super::*;
     use std::io::Read;
+    use std::sync::Mutex;
 
     use edgelet_core::ModuleRuntimeState;
     use edgelet_core::{KeyBytes, PrivateKey};
@@ -863,6 +2085,91 @@ mod tests {
         }
     }
 
+    /// Hands out a workload CA that expires at `valid_to`, and records what is
+    /// done to the CAs.
+    struct ExpiringCrypto {
+        valid_to: DateTime<Utc>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ExpiringCrypto {
+        fn new(valid_to: DateTime<Utc>) -> Self {
+            ExpiringCrypto {
+                valid_to,
+                calls: Mutex::new(vec![]),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl CreateCertificate for ExpiringCrypto {
+        type Certificate = TestCert;
+
+        fn create_certificate(
+            &self,
+            properties: &CertificateProperties,
+        ) -> Result<Self::Certificate, edgelet_core::Error> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("create {}", properties.alias()));
+            Ok(TestCert::default().with_valid_to(self.valid_to))
+        }
+
+        fn destroy_certificate(&self, alias: String) -> Result<(), edgelet_core::Error> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("destroy {}", alias));
+            Ok(())
+        }
+    }
+
+    impl RenewQuickstartCa for ExpiringCrypto {
+        fn renew_quickstart_ca(&self) -> Result<(), edgelet_core::Error> {
+            self.calls.lock().unwrap().push("renew".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn quickstart_ca_close_to_expiry_is_renewed() {
+        let now = Utc::now();
+        let crypto = ExpiringCrypto::new(now + ChronoDuration::days(1));
+        let renewed =
+            renew_quickstart_ca_if_expiring(&crypto, Duration::from_secs(14 * 86_400), now)
+                .unwrap();
+
+        assert!(renewed);
+        assert_eq!(
+            vec![
+                format!("create {}", IOTEDGED_CA_ALIAS),
+                format!("destroy {}", IOTEDGED_CA_ALIAS),
+                "renew".to_string(),
+                format!("create {}", IOTEDGED_CA_ALIAS),
+            ],
+            crypto.calls()
+        );
+    }
+
+    #[test]
+    fn quickstart_ca_far_from_expiry_is_kept() {
+        let now = Utc::now();
+        let crypto = ExpiringCrypto::new(now + ChronoDuration::days(30));
+        let renewed =
+            renew_quickstart_ca_if_expiring(&crypto, Duration::from_secs(14 * 86_400), now)
+                .unwrap();
+
+        assert!(!renewed);
+        assert_eq!(
+            vec![format!("create {}", IOTEDGED_CA_ALIAS)],
+            crypto.calls()
+        );
+    }
+
     #[test]
     fn default_settings_raise_unconfigured_error() {
         let settings = Settings::<DockerConfig>::new(None).unwrap();
@@ -952,6 +2259,114 @@ mod tests {
         assert_ne!(written1, written);
     }
 
+    fn agent_create_options(create_options: &str, sockets: &[(&str, &str)]) -> DockerConfig {
+        let create_options = serde_json::from_str(create_options).unwrap();
+        let mut config = DockerConfig::new("edge-agent:1.0", create_options, None).unwrap();
+        let sockets = sockets
+            .iter()
+            .map(|&(listen, connect)| (Url::parse(listen).unwrap(), Url::parse(connect).unwrap()))
+            .collect::<Vec<_>>();
+        let sockets = sockets
+            .iter()
+            .map(|&(ref listen, ref connect)| (listen, connect))
+            .collect::<Vec<_>>();
+        agent_config(&mut config, &sockets, "azure-iot-edge").unwrap();
+        config
+    }
+
+    #[test]
+    fn agent_mounts_sockets_where_it_connects_to_them() {
+        let config = agent_create_options(
+            "{}",
+            &[
+                (
+                    "unix:///var/lib/iotedge/mgmt.sock",
+                    "unix:///var/run/iotedge/mgmt.sock",
+                ),
+                ("fd://iotedge.workload", "unix:///var/run/iotedge/workload.sock"),
+                ("http://127.0.0.1:8081", "http://127.0.0.1:8081"),
+            ],
+        );
+        let host_config = config.create_options().host_config().unwrap();
+        assert_eq!(
+            Some(
+                &[
+                    "/var/lib/iotedge/mgmt.sock:/var/run/iotedge/mgmt.sock".to_string(),
+                    "/var/run/iotedge/workload.sock:/var/run/iotedge/workload.sock".to_string(),
+                ][..]
+            ),
+            host_config.binds()
+        );
+
+        let config = agent_create_options(
+            r#"{"HostConfig": {"Binds": ["/run/mgmt.sock:/run/mgmt.sock"]}}"#,
+            &[("unix:///run/mgmt.sock", "unix:///run/mgmt.sock")],
+        );
+        assert_eq!(
+            1,
+            config
+                .create_options()
+                .host_config()
+                .and_then(HostConfig::binds)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn agent_is_attached_to_the_network() {
+        let config = agent_create_options("{}", &[]);
+        let endpoints = config
+            .create_options()
+            .networking_config()
+            .and_then(|config| config.endpoints_config())
+            .unwrap();
+        assert_eq!(
+            vec!["azure-iot-edge"],
+            endpoints.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+        assert!(config.create_options().host_config().is_none());
+    }
+
+    #[test]
+    fn agent_keeps_the_network_its_options_choose() {
+        let config = agent_create_options(r#"{"HostConfig": {"NetworkMode": "host"}}"#, &[]);
+        assert!(config.create_options().networking_config().is_none());
+
+        let config = agent_create_options(
+            r#"{"NetworkingConfig": {"EndpointsConfig": {"other": {}}}}"#,
+            &[],
+        );
+        let endpoints = config
+            .create_options()
+            .networking_config()
+            .and_then(|config| config.endpoints_config())
+            .unwrap();
+        assert_eq!(
+            vec!["other"],
+            endpoints.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn upstream_endpoints_are_reached_through_the_parent() {
+        let dps = Url::parse("https://global.azure-devices-provisioning.net/").unwrap();
+        assert_eq!(dps, upstream_url(&dps, None).unwrap());
+        assert_eq!(
+            "https://parent.contoso.com/",
+            upstream_url(&dps, Some("parent.contoso.com"))
+                .unwrap()
+                .as_str()
+        );
+
+        let hub = Url::parse("https://hub.azure-devices.net").unwrap();
+        assert_eq!(
+            "https://10.0.0.4/",
+            upstream_url(&hub, Some("10.0.0.4")).unwrap().as_str()
+        );
+        assert!(upstream_url(&hub, Some("bad host")).is_err());
+    }
+
     #[test]
     fn get_proxy_uri_recognizes_https_proxy() {
         // TODO:
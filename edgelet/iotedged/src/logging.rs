@@ -1,11 +1,14 @@
 // Copyright (c) Microsoft. All rights reserved.
 
 use std::env;
-use std::io::Write;
+use std::fmt::Display;
+use std::io::{self, Write};
 
-use edgelet_utils::log_failure;
+use chrono::{DateTime, SecondsFormat, Utc};
+use edgelet_utils::{correlation_id, failure_causes, log_failure, set_structured_failures};
 use env_logger;
-use log::{Level, LevelFilter};
+use log::{self, Level, LevelFilter, Record};
+use serde_json::{self, Map, Value};
 #[cfg(target_os = "windows")]
 use win_logger::EventLogger;
 
@@ -14,55 +17,141 @@ use error::Error;
 #[cfg(target_os = "windows")]
 const IOTEDGED_SERVICE_NAME: &str = crate_name!();
 const ENV_LOG: &str = "IOTEDGE_LOG";
+const ENV_LOG_FORMAT: &str = "IOTEDGE_LOG_FORMAT";
+
+/// The level logged when neither `IOTEDGE_LOG` nor the settings set one.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// How log records are written. `IOTEDGE_LOG_FORMAT=json` selects `Json`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// One line per record, prefixed with its syslog level.
+    Text,
+    /// One JSON object per record and line. The causes of a failure are part
+    /// of the record of the failure.
+    Json,
+}
+
+impl LogFormat {
+    fn from_env() -> Self {
+        match env::var(ENV_LOG_FORMAT) {
+            Ok(ref format) if format.eq_ignore_ascii_case("json") => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+}
 
 pub fn init() {
+    let format = LogFormat::from_env();
+    set_structured_failures(format == LogFormat::Json);
+    // Without IOTEDGE_LOG, every level gets past the logger, and what is
+    // logged is up to the maximum level of the log crate, which `set_level`
+    // changes.
+    let env_filter = env::var(ENV_LOG).ok();
+    let filter = if env_filter.is_some() {
+        DEFAULT_LEVEL
+    } else {
+        LevelFilter::Trace
+    };
     env_logger::Builder::new()
-        .format(|fmt, record| {
-            let level = match record.level() {
-                Level::Trace => "TRCE",
-                Level::Debug => "DBUG",
-                Level::Info => "INFO",
-                Level::Warn => "WARN",
-                Level::Error => "ERR!",
-            };
-            let timestamp = fmt.timestamp();
-
-            if record.level() >= Level::Debug {
-                writeln!(
-                    fmt,
-                    "<{}>{} [{}] - [{}] {}",
-                    syslog_level(record.level()),
-                    timestamp,
-                    level,
-                    record.target(),
-                    record.args()
-                )
-            } else {
-                writeln!(
-                    fmt,
-                    "<{}>{} [{}] - {}",
-                    syslog_level(record.level()),
-                    timestamp,
-                    level,
-                    record.args()
-                )
+        .format(move |fmt, record| match format {
+            LogFormat::Text => {
+                let timestamp = fmt.timestamp();
+                write_text(fmt, record, timestamp)
             }
-        }).filter_level(LevelFilter::Info)
-        .parse(&env::var(ENV_LOG).unwrap_or_default())
+            LogFormat::Json => write_json(fmt, record, Utc::now()),
+        }).filter_level(filter)
+        .parse(env_filter.as_ref().map_or("", String::as_str))
         .init();
+    if env_filter.is_none() {
+        log::set_max_level(DEFAULT_LEVEL);
+    }
+}
+
+/// Logs records of `level` and less verbose ones from now on, or of the
+/// default level for `None`. Has no effect when `IOTEDGE_LOG` is set, which
+/// takes precedence.
+pub fn set_level(level: Option<LevelFilter>) {
+    if env::var(ENV_LOG).is_err() {
+        log::set_max_level(level.unwrap_or(DEFAULT_LEVEL));
+    }
+}
+
+fn write_text<W, T>(w: &mut W, record: &Record, timestamp: T) -> io::Result<()>
+where
+    W: Write,
+    T: Display,
+{
+    let level = match record.level() {
+        Level::Trace => "TRCE",
+        Level::Debug => "DBUG",
+        Level::Info => "INFO",
+        Level::Warn => "WARN",
+        Level::Error => "ERR!",
+    };
+
+    if record.level() >= Level::Debug {
+        writeln!(
+            w,
+            "<{}>{} [{}] - [{}] {}",
+            syslog_level(record.level()),
+            timestamp,
+            level,
+            record.target(),
+            record.args()
+        )
+    } else {
+        writeln!(
+            w,
+            "<{}>{} [{}] - {}",
+            syslog_level(record.level()),
+            timestamp,
+            level,
+            record.args()
+        )
+    }
+}
+
+fn write_json<W>(w: &mut W, record: &Record, timestamp: DateTime<Utc>) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut entry = Map::new();
+    entry.insert(
+        "timestamp".to_string(),
+        Value::from(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    entry.insert("level".to_string(), Value::from(record.level().to_string()));
+    entry.insert("target".to_string(), Value::from(record.target()));
+    if let Some(module_path) = record.module_path() {
+        entry.insert("module_path".to_string(), Value::from(module_path));
+    }
+    entry.insert("message".to_string(), Value::from(record.args().to_string()));
+    if let Some(correlation_id) = correlation_id() {
+        entry.insert("correlation_id".to_string(), Value::from(correlation_id));
+    }
+    let causes = failure_causes();
+    if !causes.is_empty() {
+        entry.insert("causes".to_string(), Value::from(causes));
+    }
+
+    serde_json::to_writer(&mut *w, &entry)?;
+    writeln!(w)
 }
 
 #[cfg(target_os = "windows")]
 pub fn init_win_log() {
-    let mut min_log_level = "info".to_string();
-    if let Ok(log_level) = env::var(ENV_LOG) {
-        min_log_level = log_level;
-    }
+    // As with `init`, the level is left to `set_level` without IOTEDGE_LOG.
+    let env_filter = env::var(ENV_LOG).ok();
+    let min_log_level = env_filter.clone().unwrap_or_else(|| "trace".to_string());
 
     EventLogger::new(IOTEDGED_SERVICE_NAME, &min_log_level)
         .expect("Could not create Windows EventLogger")
         .init()
         .expect("Could not initialize Windows EventLogger");
+    if env_filter.is_none() {
+        log::set_max_level(DEFAULT_LEVEL);
+    }
 }
 
 fn syslog_level(level: Level) -> i8 {
@@ -77,3 +166,120 @@ fn syslog_level(level: Level) -> i8 {
 pub fn log_error(error: &Error) {
     log_failure(Level::Error, error);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use edgelet_utils::with_correlation_id;
+    use failure::{Context, Fail};
+    use log::{self, Log, Metadata};
+
+    use super::*;
+
+    struct TestLogger;
+
+    thread_local! {
+        static FORMAT: Cell<LogFormat> = Cell::new(LogFormat::Text);
+        static OUTPUT: RefCell<Vec<u8>> = RefCell::new(vec![]);
+    }
+
+    impl Log for TestLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            OUTPUT.with(|output| {
+                let mut output = output.borrow_mut();
+                match FORMAT.with(Cell::get) {
+                    LogFormat::Text => write_text(&mut *output, record, "2018-10-16T09:00:00Z"),
+                    LogFormat::Json => write_json(&mut *output, record, Utc::now()),
+                }.unwrap();
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger;
+
+    fn capture<F: FnOnce()>(format: LogFormat, f: F) -> Vec<String> {
+        // Only the first call sets the logger; output is kept per thread.
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Debug);
+        FORMAT.with(|current| current.set(format));
+        OUTPUT.with(|output| output.borrow_mut().clear());
+        f();
+        OUTPUT.with(|output| {
+            String::from_utf8(output.borrow().clone())
+                .unwrap()
+                .lines()
+                .map(ToString::to_string)
+                .collect()
+        })
+    }
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn text_format_is_the_default() {
+        assert_eq!(LogFormat::Text, LogFormat::from_env());
+        let lines = capture(LogFormat::Text, || {
+            info!("Starting");
+            debug!("Details");
+        });
+        assert_eq!(
+            vec![
+                "<6>2018-10-16T09:00:00Z [INFO] - Starting",
+                "<7>2018-10-16T09:00:00Z [DBUG] - [iotedged::logging::tests] Details",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn json_format_writes_an_object_per_line() {
+        let lines = capture(LogFormat::Json, || {
+            info!("Starting");
+            with_correlation_id("5bc5a0f0-1", || warn!("Request \"failed\""));
+        });
+        assert_eq!(2, lines.len());
+
+        let first = parse(&lines[0]);
+        assert_eq!("INFO", first["level"]);
+        assert_eq!("iotedged::logging::tests", first["target"]);
+        assert_eq!("iotedged::logging::tests", first["module_path"]);
+        assert_eq!("Starting", first["message"]);
+        assert!(DateTime::parse_from_rfc3339(first["timestamp"].as_str().unwrap()).is_ok());
+        assert_eq!(None, first.get("correlation_id"));
+        assert_eq!(None, first.get("causes"));
+
+        let second = parse(&lines[1]);
+        assert_eq!("WARN", second["level"]);
+        assert_eq!("Request \"failed\"", second["message"]);
+        assert_eq!("5bc5a0f0-1", second["correlation_id"]);
+    }
+
+    #[test]
+    fn json_format_lists_causes_of_failures() {
+        let error = Context::new("connection refused")
+            .context("could not connect")
+            .context("could not start");
+        let lines = capture(LogFormat::Json, || {
+            set_structured_failures(true);
+            log_failure(Level::Error, &error);
+            set_structured_failures(false);
+        });
+
+        assert_eq!(1, lines.len());
+        let entry = parse(&lines[0]);
+        assert_eq!("could not start", entry["message"]);
+        assert_eq!(
+            json!(["could not connect", "connection refused"]),
+            entry["causes"]
+        );
+    }
+}
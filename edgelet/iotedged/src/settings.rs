@@ -1,13 +1,18 @@
 // Copyright (c) Microsoft. All rights reserved.
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{File as FsFile, OpenOptions};
 use std::io::Read;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use base64;
+use chrono::NaiveTime;
 use config::{Config, Environment, File, FileFormat};
 use edgelet_utils::log_failure;
-use log::Level;
+use log::{Level, LevelFilter};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serde_json;
@@ -15,12 +20,69 @@ use sha2::{Digest, Sha256};
 use url::Url;
 use url_serde;
 
-use edgelet_core::ModuleSpec;
+use edgelet_core::{
+    EnvInjection, KillSignals, ModuleSpec, PayloadLimits, RestartPolicy, StopAllOptions,
+    DEFAULT_MIN_IMAGE_AGE_SECS,
+};
+use edgelet_docker::{RegistryMirror, DEFAULT_SYSTEM_INFO_TTL_SECS};
+use edgelet_http::client::DEFAULT_TOKEN_TTL_SECS;
+use edgelet_http::{
+    ConnectionLimits, Rate, RateLimits, SocketPermissions, TlsConfig,
+    DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_DRAIN_TIMEOUT_SECS,
+};
+use edgelet_hsm::DEFAULT_KEY_CACHE_MAX_ENTRIES;
+use edgelet_iothub::DEFAULT_TOKEN_RENEW_BEFORE_SECS;
+use provisioning::RegistrationIdSource;
+use connection_string::{self, ConnectionStringError};
 use error::Error;
 
 /// This is the name of the network created by the iotedged
 const DEFAULT_NETWORKID: &str = "azure-iot-edge";
 
+/// How long requests to the container runtime, DPS and IoT Hub may take by
+/// default
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 60;
+
+/// How long creating, starting, stopping or removing a module and the like
+/// may take by default, however many requests to the container runtime it
+/// takes
+const DEFAULT_OPERATION_TIMEOUT_SECS: u64 = 300;
+
+/// How long pulling an image, and creating or starting a module, may take by
+/// default before it is logged and counted as slow
+const DEFAULT_SLOW_PULL_SECS: u64 = 300;
+const DEFAULT_SLOW_CREATE_SECS: u64 = 30;
+const DEFAULT_SLOW_START_SECS: u64 = 30;
+
+/// How many connections the management and workload listeners accept by
+/// default. The workload API is called by every module, so it gets more.
+const DEFAULT_MANAGEMENT_MAX_CONNECTIONS: usize = 64;
+const DEFAULT_WORKLOAD_MAX_CONNECTIONS: usize = 256;
+
+/// How long clients of the listeners may take to send a request by default
+const DEFAULT_HEADER_READ_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_BODY_READ_TIMEOUT_SECS: u64 = 30;
+
+/// How many header fields, and how many bytes of them, requests to the
+/// listeners may have by default
+const DEFAULT_MAX_HEADERS: usize = 64;
+const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// How many requests a second, and how many at once, each caller of the
+/// management API may send by default. The edge agent lists the modules every
+/// few seconds, well within these.
+const DEFAULT_CHEAP_REQUESTS_PER_SEC: u32 = 20;
+const DEFAULT_CHEAP_REQUESTS_BURST: u32 = 50;
+const DEFAULT_EXPENSIVE_REQUESTS_PER_SEC: u32 = 2;
+const DEFAULT_EXPENSIVE_REQUESTS_BURST: u32 = 10;
+
+/// Consecutive failed health checks after which a module is restarted, once
+/// restarting unhealthy modules is turned on.
+const DEFAULT_UNHEALTHY_CHECKS: u32 = 3;
+
+/// How long before it expires the quick start device CA is replaced by default
+const DEFAULT_QUICKSTART_CA_RENEW_BEFORE_SECS: u64 = 14 * 24 * 3600;
+
 /// This is the default connection string
 pub const DEFAULT_CONNECTION_STRING: &str = "<ADD DEVICE CONNECTION STRING HERE>";
 
@@ -30,25 +92,137 @@ static DEFAULTS: &str = include_str!("config/unix/default.yaml");
 #[cfg(windows)]
 static DEFAULTS: &str = include_str!("config/windows/default.yaml");
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub struct Manual {
+    #[serde(default)]
     device_connection_string: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_connection_string_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x509: Option<ManualX509>,
+    /// Whether `device_connection_string` was read from its file or the
+    /// environment rather than the config file.
+    #[serde(skip)]
+    loaded: bool,
 }
 
 impl Manual {
     pub fn device_connection_string(&self) -> &str {
         &self.device_connection_string
     }
+
+    /// The file the device connection string is read from, when the config
+    /// file doesn't have it.
+    pub fn device_connection_string_file(&self) -> Option<&Path> {
+        self.device_connection_string_file
+            .as_ref()
+            .map(AsRef::as_ref)
+    }
+
+    /// Whether the config file itself has a connection string, rather than
+    /// the placeholder it comes with.
+    pub fn has_inline_connection_string(&self) -> bool {
+        !self.loaded
+            && !self.device_connection_string.is_empty()
+            && self.device_connection_string != DEFAULT_CONNECTION_STRING
+    }
+
+    /// Reads the device connection string from its file, or else takes the
+    /// one from the environment, unless the config file has one or the
+    /// device uses X.509.
+    fn load_device_connection_string(
+        &mut self,
+        from_env: Option<String>,
+    ) -> Result<(), ConnectionStringError> {
+        if self.x509.is_some() || self.has_inline_connection_string() {
+            return Ok(());
+        }
+        let loaded = match self.device_connection_string_file {
+            Some(ref path) => Some(connection_string::read_file(path)?),
+            None => from_env,
+        };
+        if let Some(loaded) = loaded {
+            self.device_connection_string = loaded;
+            self.loaded = true;
+        }
+        Ok(())
+    }
+
+    /// The X.509 identity of the device, if it authenticates with IoT Hub
+    /// using a certificate rather than the shared access key in its
+    /// connection string.
+    pub fn x509(&self) -> Option<&ManualX509> {
+        self.x509.as_ref()
+    }
+}
+
+/// Leaves out the connection string, wherever it came from, as it carries the
+/// device's key.
+impl fmt::Debug for Manual {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let connection_string = if self.device_connection_string.is_empty() {
+            ""
+        } else {
+            "<redacted>"
+        };
+        f.debug_struct("Manual")
+            .field("device_connection_string", &connection_string)
+            .field(
+                "device_connection_string_file",
+                &self.device_connection_string_file,
+            ).field("x509", &self.x509)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub struct ManualX509 {
+    iothub_hostname: String,
+    device_id: String,
+    identity_cert: PathBuf,
+    identity_pk: PathBuf,
+}
+
+impl ManualX509 {
+    pub fn iothub_hostname(&self) -> &str {
+        &self.iothub_hostname
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    pub fn identity_cert(&self) -> &Path {
+        &self.identity_cert
+    }
+
+    pub fn identity_pk(&self) -> &Path {
+        &self.identity_pk
+    }
+
+    /// The TLS material the device authenticates with when it connects to
+    /// IoT Hub.
+    pub fn tls_config(&self) -> TlsConfig {
+        TlsConfig::new()
+            .with_client_cert(self.identity_cert.clone())
+            .with_client_key(self.identity_pk.clone())
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub struct Dps {
     #[serde(with = "url_serde")]
     global_endpoint: Url,
     scope_id: String,
-    registration_id: String,
+    #[serde(default)]
+    registration_id: Option<String>,
+    #[serde(default)]
+    registration_id_source: RegistrationIdSource,
+    #[serde(default)]
+    group_key: Option<String>,
 }
 
 impl Dps {
@@ -60,12 +234,38 @@ impl Dps {
         &self.scope_id
     }
 
-    pub fn registration_id(&self) -> &str {
-        &self.registration_id
+    /// The registration id of the config file, which is the one the device
+    /// registers with unless `registration_id_source` says otherwise.
+    pub fn registration_id(&self) -> Option<&str> {
+        self.registration_id.as_ref().map(String::as_str)
+    }
+
+    pub fn registration_id_source(&self) -> &RegistrationIdSource {
+        &self.registration_id_source
+    }
+
+    /// The base64 encoded key of the symmetric key enrollment group of the
+    /// device. Without one, the device attests with its TPM.
+    pub fn group_key(&self) -> Option<&str> {
+        self.group_key.as_ref().map(String::as_str)
+    }
+}
+
+/// Leaves out the key of the enrollment group, which every device in the
+/// group derives its key from.
+impl fmt::Debug for Dps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dps")
+            .field("global_endpoint", &self.global_endpoint)
+            .field("scope_id", &self.scope_id)
+            .field("registration_id", &self.registration_id)
+            .field("registration_id_source", &self.registration_id_source)
+            .field("group_key", &self.group_key.as_ref().map(|_| "<redacted>"))
+            .finish()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "source")]
 #[serde(rename_all = "lowercase")]
 pub enum Provisioning {
@@ -73,7 +273,7 @@ pub enum Provisioning {
     Dps(Dps),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Connect {
     #[serde(with = "url_serde")]
     workload_uri: Url,
@@ -91,12 +291,28 @@ impl Connect {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Listen {
     #[serde(with = "url_serde")]
     workload_uri: Url,
     #[serde(with = "url_serde")]
     management_uri: Url,
+    #[serde(default = "default_drain_timeout_secs")]
+    drain_timeout_secs: u64,
+    #[serde(default)]
+    workload_socket: Socket,
+    #[serde(default)]
+    management_socket: Socket,
+    #[serde(default)]
+    workload_tls: ListenTls,
+    #[serde(default)]
+    management_tls: ListenTls,
+    #[serde(default = "Limits::workload")]
+    workload_limits: Limits,
+    #[serde(default = "Limits::management")]
+    management_limits: Limits,
+    #[serde(default)]
+    management_rates: RequestRates,
 }
 
 impl Listen {
@@ -107,16 +323,391 @@ impl Listen {
     pub fn management_uri(&self) -> &Url {
         &self.management_uri
     }
+
+    /// How long in-flight requests are given to complete on shutdown.
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+
+    pub fn workload_socket(&self) -> &Socket {
+        &self.workload_socket
+    }
+
+    pub fn management_socket(&self) -> &Socket {
+        &self.management_socket
+    }
+
+    pub fn workload_tls(&self) -> &ListenTls {
+        &self.workload_tls
+    }
+
+    pub fn management_tls(&self) -> &ListenTls {
+        &self.management_tls
+    }
+
+    pub fn workload_limits(&self) -> &Limits {
+        &self.workload_limits
+    }
+
+    pub fn management_limits(&self) -> &Limits {
+        &self.management_limits
+    }
+
+    pub fn management_rates(&self) -> &RequestRates {
+        &self.management_rates
+    }
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    DEFAULT_DRAIN_TIMEOUT_SECS
+}
+
+/// Mode and ownership of a `unix://` listener's socket file. The mode is an
+/// octal string such as "0660".
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Socket {
+    #[serde(default, with = "octal_mode")]
+    mode: Option<u32>,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+impl Socket {
+    pub fn permissions(&self) -> SocketPermissions {
+        let permissions = SocketPermissions::new();
+        let permissions = match self.mode {
+            Some(mode) => permissions.with_mode(mode),
+            None => permissions,
+        };
+        let permissions = match self.owner {
+            Some(owner) => permissions.with_owner(owner),
+            None => permissions,
+        };
+        match self.group {
+            Some(group) => permissions.with_group(group),
+            None => permissions,
+        }
+    }
+}
+
+/// TLS for an `https://` listener. A listener without a certificate and key
+/// of its own is given a certificate for its host issued by the workload CA.
+/// With a client CA, clients must present a certificate issued by it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ListenTls {
+    server_cert: Option<PathBuf>,
+    server_key: Option<PathBuf>,
+    client_ca_cert: Option<PathBuf>,
+}
+
+impl ListenTls {
+    pub fn server_cert(&self) -> Option<&Path> {
+        self.server_cert.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn server_key(&self) -> Option<&Path> {
+        self.server_key.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn client_ca_cert(&self) -> Option<&Path> {
+        self.client_ca_cert.as_ref().map(AsRef::as_ref)
+    }
+}
+
+/// Limits on a listener's connections. A value of 0 disables a limit.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Limits {
+    max_connections: usize,
+    #[serde(default = "default_header_read_timeout_secs")]
+    header_read_timeout_secs: u64,
+    #[serde(default = "default_body_read_timeout_secs")]
+    body_read_timeout_secs: u64,
+    #[serde(default = "default_max_headers")]
+    max_headers: usize,
+    #[serde(default = "default_max_header_bytes")]
+    max_header_bytes: usize,
+}
+
+impl Limits {
+    fn management() -> Self {
+        Limits::with_max_connections(DEFAULT_MANAGEMENT_MAX_CONNECTIONS)
+    }
+
+    fn workload() -> Self {
+        Limits::with_max_connections(DEFAULT_WORKLOAD_MAX_CONNECTIONS)
+    }
+
+    fn with_max_connections(max_connections: usize) -> Self {
+        Limits {
+            max_connections,
+            header_read_timeout_secs: DEFAULT_HEADER_READ_TIMEOUT_SECS,
+            body_read_timeout_secs: DEFAULT_BODY_READ_TIMEOUT_SECS,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+        }
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        optional_limit(self.max_connections)
+    }
+
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        optional_secs(self.header_read_timeout_secs)
+    }
+
+    pub fn body_read_timeout(&self) -> Option<Duration> {
+        optional_secs(self.body_read_timeout_secs)
+    }
+
+    pub fn max_headers(&self) -> Option<usize> {
+        optional_limit(self.max_headers)
+    }
+
+    pub fn max_header_bytes(&self) -> Option<usize> {
+        optional_limit(self.max_header_bytes)
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits::new()
+            .with_max_connections(self.max_connections())
+            .with_header_read_timeout(self.header_read_timeout())
+            .with_body_read_timeout(self.body_read_timeout())
+            .with_max_headers(self.max_headers())
+            .with_max_header_bytes(self.max_header_bytes())
+    }
+}
+
+fn optional_limit(limit: usize) -> Option<usize> {
+    if limit == 0 {
+        None
+    } else {
+        Some(limit)
+    }
+}
+
+/// How many requests each caller of a listener may send, as a token bucket
+/// per caller and class of request. A rate of 0 disables a limit. Callers
+/// running as one of the exempt users, such as the edge agent's, are not
+/// limited.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct RequestRates {
+    cheap_per_sec: u32,
+    cheap_burst: u32,
+    expensive_per_sec: u32,
+    expensive_burst: u32,
+    exempt_uids: Vec<u32>,
+}
+
+impl Default for RequestRates {
+    fn default() -> Self {
+        RequestRates {
+            cheap_per_sec: DEFAULT_CHEAP_REQUESTS_PER_SEC,
+            cheap_burst: DEFAULT_CHEAP_REQUESTS_BURST,
+            expensive_per_sec: DEFAULT_EXPENSIVE_REQUESTS_PER_SEC,
+            expensive_burst: DEFAULT_EXPENSIVE_REQUESTS_BURST,
+            exempt_uids: vec![],
+        }
+    }
+}
+
+impl RequestRates {
+    pub fn exempt_uids(&self) -> &[u32] {
+        &self.exempt_uids
+    }
+
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits::new()
+            .with_cheap(optional_rate(self.cheap_per_sec, self.cheap_burst))
+            .with_expensive(optional_rate(self.expensive_per_sec, self.expensive_burst))
+            .with_exempt_uids(self.exempt_uids.clone())
+    }
+}
+
+fn optional_rate(per_sec: u32, burst: u32) -> Option<Rate> {
+    if per_sec == 0 {
+        None
+    } else {
+        Some(Rate::new(per_sec, burst))
+    }
+}
+
+fn default_header_read_timeout_secs() -> u64 {
+    DEFAULT_HEADER_READ_TIMEOUT_SECS
+}
+
+fn default_body_read_timeout_secs() -> u64 {
+    DEFAULT_BODY_READ_TIMEOUT_SECS
+}
+
+fn default_max_headers() -> usize {
+    DEFAULT_MAX_HEADERS
+}
+
+fn default_max_header_bytes() -> usize {
+    DEFAULT_MAX_HEADER_BYTES
+}
+
+mod octal_mode {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(mode) => u32::from_str_radix(&mode, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom(format!("invalid socket mode {:?}", mode))),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(trivially_copy_pass_by_ref))]
+    pub fn serialize<S>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *mode {
+            Some(mode) => serializer.serialize_some(&format!("{:04o}", mode)),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MobyRuntimeTls {
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    #[serde(default)]
+    require_tls: bool,
+}
+
+impl MobyRuntimeTls {
+    pub fn tls_config(&self) -> TlsConfig {
+        let tls = TlsConfig::new().with_require_tls(self.require_tls);
+        let tls = match self.ca_cert {
+            Some(ref ca_cert) => tls.with_ca_cert(ca_cert.clone()),
+            None => tls,
+        };
+        let tls = match self.client_cert {
+            Some(ref client_cert) => tls.with_client_cert(client_cert.clone()),
+            None => tls,
+        };
+        match self.client_key {
+            Some(ref client_key) => tls.with_client_key(client_key.clone()),
+            None => tls,
+        }
+    }
+}
+
+/// A container engine besides the one at `moby_runtime.uri`, that modules
+/// can name to run on instead.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MobyEngine {
+    #[serde(with = "url_serde")]
+    uri: Url,
+    #[serde(default)]
+    tls: MobyRuntimeTls,
+}
+
+impl MobyEngine {
+    pub fn uri(&self) -> &Url {
+        &self.uri
+    }
+
+    pub fn tls_config(&self) -> TlsConfig {
+        self.tls.tls_config()
+    }
+}
+
+/// The module runtime the daemon manages modules with.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeType {
+    /// Modules run as containers of the Moby engine at `moby_runtime.uri`.
+    Docker,
+    /// Modules are only recorded in memory and nothing is ever run. Meant
+    /// for CI and simulated devices that have no container engine.
+    None,
+}
+
+impl Default for RuntimeType {
+    fn default() -> Self {
+        RuntimeType::Docker
+    }
+}
+
+/// Where the identities of the modules are kept.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentityManagerType {
+    /// Module identities are created in IoT Hub.
+    IotHub,
+    /// Module identities only exist in a table in the home directory, and
+    /// IoT Hub never hears of them. Meant for development and devices that
+    /// run without the cloud.
+    Local,
+}
+
+impl Default for IdentityManagerType {
+    fn default() -> Self {
+        IdentityManagerType::IotHub
+    }
+}
+
+/// What happens when IoT Hub doesn't have the device registered as an IoT
+/// Edge device.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeDeviceCheck {
+    /// The daemon doesn't start.
+    Enforce,
+    /// The daemon logs a warning and starts anyway, for hubs whose answers
+    /// mislead the check.
+    Warn,
+}
+
+impl Default for EdgeDeviceCheck {
+    fn default() -> Self {
+        EdgeDeviceCheck::Enforce
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MobyRuntime {
+    #[serde(default, rename = "type")]
+    runtime_type: RuntimeType,
     #[serde(with = "url_serde")]
     uri: Url,
     network: String,
+    #[serde(default)]
+    tls: MobyRuntimeTls,
+    #[serde(default)]
+    default_dns: Vec<IpAddr>,
+    #[serde(default)]
+    default_extra_hosts: Vec<String>,
+    #[serde(default)]
+    purge_volumes: bool,
+    #[serde(default)]
+    allow_any_signal: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    registry_mirrors: Vec<RegistryMirror>,
+    #[serde(default = "default_system_info_ttl_secs")]
+    system_info_ttl_secs: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    engines: BTreeMap<String, MobyEngine>,
 }
 
 impl MobyRuntime {
+    pub fn runtime_type(&self) -> RuntimeType {
+        self.runtime_type
+    }
+
     pub fn uri(&self) -> &Url {
         &self.uri
     }
@@ -128,9 +719,60 @@ impl MobyRuntime {
             &self.network
         }
     }
+
+    /// DNS servers of the modules that don't set their own.
+    pub fn default_dns(&self) -> &[IpAddr] {
+        &self.default_dns
+    }
+
+    /// Host entries, in the form `host:ip`, of the modules that don't set
+    /// their own.
+    pub fn default_extra_hosts(&self) -> &[String] {
+        &self.default_extra_hosts
+    }
+
+    /// Whether the volumes created for modules are removed along with the
+    /// modules when the device is provisioned anew.
+    pub fn purge_volumes(&self) -> bool {
+        self.purge_volumes
+    }
+
+    /// Which signals the management API sends to modules.
+    pub fn kill_signals(&self) -> KillSignals {
+        if self.allow_any_signal {
+            KillSignals::Any
+        } else {
+            KillSignals::Safe
+        }
+    }
+
+    /// Registries whose module images are pulled through a mirror.
+    pub fn registry_mirrors(&self) -> &[RegistryMirror] {
+        &self.registry_mirrors
+    }
+
+    /// How long the system information the container runtime reported is
+    /// kept before it is asked for it again.
+    pub fn system_info_ttl(&self) -> Duration {
+        Duration::from_secs(self.system_info_ttl_secs)
+    }
+
+    pub fn tls_config(&self) -> TlsConfig {
+        self.tls.tls_config()
+    }
+
+    /// The other container engines modules can run on, by the name modules
+    /// refer to them by.
+    pub fn engines(&self) -> &BTreeMap<String, MobyEngine> {
+        &self.engines
+    }
+}
+
+fn default_system_info_ttl_secs() -> u64 {
+    DEFAULT_SYSTEM_INFO_TTL_SECS
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Certificates {
     device_ca_cert: PathBuf,
     device_ca_pk: PathBuf,
@@ -151,245 +793,1810 @@ impl Certificates {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Settings<T> {
-    provisioning: Provisioning,
-    agent: ModuleSpec<T>,
-    hostname: String,
-    connect: Connect,
-    listen: Listen,
-    homedir: PathBuf,
-    moby_runtime: MobyRuntime,
-    certificates: Option<Certificates>,
+/// Timeouts, in seconds, for the daemon's outbound connections and for the
+/// operations on the container runtime. A request or operation timeout of 0
+/// disables it. Operations that take longer than their `slow_*_secs` are
+/// logged and counted as slow; 0 never counts them as slow.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Timeouts {
+    connect_secs: u64,
+    moby_runtime_request_secs: u64,
+    moby_runtime_operation_secs: u64,
+    dps_request_secs: u64,
+    iothub_request_secs: u64,
+    slow_pull_secs: u64,
+    slow_create_secs: u64,
+    slow_start_secs: u64,
 }
 
-impl<T> Settings<T>
-where
-    T: DeserializeOwned + Serialize,
-{
-    pub fn new(filename: Option<&str>) -> Result<Self, Error> {
-        let mut config = Config::default();
-        config.merge(File::from_str(DEFAULTS, FileFormat::Yaml))?;
-        if let Some(file) = filename {
-            config.merge(File::with_name(file).required(true))?;
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            moby_runtime_request_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+            moby_runtime_operation_secs: DEFAULT_OPERATION_TIMEOUT_SECS,
+            dps_request_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+            iothub_request_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+            slow_pull_secs: DEFAULT_SLOW_PULL_SECS,
+            slow_create_secs: DEFAULT_SLOW_CREATE_SECS,
+            slow_start_secs: DEFAULT_SLOW_START_SECS,
         }
+    }
+}
 
-        config.merge(Environment::with_prefix("iotedge"))?;
+impl Timeouts {
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect_secs)
+    }
 
-        let settings: Self = config.try_into()?;
+    pub fn moby_runtime_request(&self) -> Option<Duration> {
+        optional_secs(self.moby_runtime_request_secs)
+    }
 
-        Ok(settings)
+    pub fn moby_runtime_operation(&self) -> Option<Duration> {
+        optional_secs(self.moby_runtime_operation_secs)
     }
 
-    pub fn provisioning(&self) -> &Provisioning {
-        &self.provisioning
+    pub fn dps_request(&self) -> Option<Duration> {
+        optional_secs(self.dps_request_secs)
     }
 
-    pub fn agent(&self) -> &ModuleSpec<T> {
-        &self.agent
+    pub fn iothub_request(&self) -> Option<Duration> {
+        optional_secs(self.iothub_request_secs)
     }
 
-    pub fn agent_mut(&mut self) -> &mut ModuleSpec<T> {
-        &mut self.agent
+    pub fn slow_pull(&self) -> Option<Duration> {
+        optional_secs(self.slow_pull_secs)
     }
 
-    pub fn hostname(&self) -> &str {
-        &self.hostname
+    pub fn slow_create(&self) -> Option<Duration> {
+        optional_secs(self.slow_create_secs)
     }
 
-    pub fn connect(&self) -> &Connect {
-        &self.connect
+    pub fn slow_start(&self) -> Option<Duration> {
+        optional_secs(self.slow_start_secs)
     }
+}
 
-    pub fn listen(&self) -> &Listen {
-        &self.listen
+/// How the watchdog restarts modules that keep failing. The wait between
+/// restarts doubles from `restart_base_delay_secs` up to
+/// `restart_max_delay_secs` and starts over once a module has been up for
+/// `healthy_after_secs`. Without `max_restarts` failing modules are restarted
+/// forever. With `restart_on_unhealthy`, a running module whose health check
+/// fails for `unhealthy_checks` checks in a row is restarted as well.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Watchdog {
+    restart_base_delay_secs: u64,
+    restart_max_delay_secs: u64,
+    healthy_after_secs: u64,
+    max_restarts: Option<u32>,
+    restart_on_unhealthy: bool,
+    unhealthy_checks: u32,
+    unhealthy_grace_period_secs: u64,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        let policy = RestartPolicy::default();
+        Watchdog {
+            restart_base_delay_secs: policy.base_delay().as_secs(),
+            restart_max_delay_secs: policy.max_delay().as_secs(),
+            healthy_after_secs: policy.healthy_after().as_secs(),
+            max_restarts: policy.max_restarts(),
+            restart_on_unhealthy: false,
+            unhealthy_checks: DEFAULT_UNHEALTHY_CHECKS,
+            unhealthy_grace_period_secs: policy.unhealthy_grace_period().as_secs(),
+        }
     }
+}
 
-    pub fn homedir(&self) -> &Path {
-        &self.homedir
+impl Watchdog {
+    pub fn restart_base_delay(&self) -> Duration {
+        Duration::from_secs(self.restart_base_delay_secs)
     }
 
-    pub fn moby_runtime(&self) -> &MobyRuntime {
-        &self.moby_runtime
+    pub fn restart_max_delay(&self) -> Duration {
+        Duration::from_secs(self.restart_max_delay_secs)
     }
 
-    pub fn certificates(&self) -> Option<&Certificates> {
-        self.certificates.as_ref()
+    pub fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::new()
+            .with_base_delay(self.restart_base_delay())
+            .with_max_delay(self.restart_max_delay())
+            .with_healthy_after(Duration::from_secs(self.healthy_after_secs))
+            .with_max_restarts(self.max_restarts)
+            .with_unhealthy_checks(if self.restart_on_unhealthy {
+                Some(self.unhealthy_checks)
+            } else {
+                None
+            }).with_unhealthy_grace_period(Duration::from_secs(self.unhealthy_grace_period_secs))
     }
 
-    pub fn diff_with_cached(&self, path: PathBuf) -> Result<bool, Error> {
-        OpenOptions::new()
-            .read(true)
-            .open(path)
-            .map_err(Error::from)
-            .and_then(|mut file: FsFile| {
-                let mut buffer = String::new();
-                file.read_to_string(&mut buffer)?;
-                let encoded = serde_json::to_string(self)
-                    .map_err(Error::from)
-                    .map(|s| Sha256::digest_str(&s))
-                    .map(|s| base64::encode(&s))?;
-                if encoded == buffer {
-                    debug!("Config state matches supplied config.");
+    pub fn unhealthy_checks(&self) -> u32 {
+        self.unhealthy_checks
+    }
+}
+
+/// The order modules are stopped in when the daemon shuts down. Modules named
+/// in `stop_last` are stopped after all others, in the order they are listed,
+/// unless they carry a stop priority label. Each phase waits for up to
+/// `phase_timeout_secs`, stopping at most `max_concurrency` modules at once.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Shutdown {
+    stop_last: Vec<String>,
+    phase_timeout_secs: u64,
+    max_concurrency: usize,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let options = StopAllOptions::default();
+        Shutdown {
+            stop_last: options.stop_last().to_vec(),
+            phase_timeout_secs: options.phase_timeout().as_secs(),
+            max_concurrency: options.max_concurrency(),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn stop_options(&self) -> StopAllOptions {
+        StopAllOptions::new()
+            .with_stop_last(self.stop_last.clone())
+            .with_phase_timeout(Duration::from_secs(self.phase_timeout_secs))
+            .with_max_concurrency(self.max_concurrency)
+    }
+}
+
+/// Removes the images no module uses once a day at `cleanup_time`, local
+/// time, once they are older than `min_age`, like "7d", "12h", "30m" or a
+/// number of seconds.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ImageGarbageCollection {
+    enabled: bool,
+    #[serde(with = "time_of_day")]
+    cleanup_time: NaiveTime,
+    #[serde(with = "age")]
+    min_age: Duration,
+}
+
+impl Default for ImageGarbageCollection {
+    fn default() -> Self {
+        ImageGarbageCollection {
+            enabled: false,
+            cleanup_time: NaiveTime::from_hms(2, 0, 0),
+            min_age: Duration::from_secs(DEFAULT_MIN_IMAGE_AGE_SECS),
+        }
+    }
+}
+
+impl ImageGarbageCollection {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn cleanup_time(&self) -> NaiveTime {
+        self.cleanup_time
+    }
+
+    pub fn min_age(&self) -> Duration {
+        self.min_age
+    }
+}
+
+mod time_of_day {
+    use chrono::NaiveTime;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map_err(|_| D::Error::custom(format!("invalid time of day {:?}", time)))
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(trivially_copy_pass_by_ref))]
+    pub fn serialize<S>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&time.format("%H:%M").to_string())
+    }
+}
+
+mod age {
+    use std::time::Duration;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let age = String::deserialize(deserializer)?;
+        parse(age.trim()).ok_or_else(|| D::Error::custom(format!("invalid age {:?}", age)))
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(trivially_copy_pass_by_ref))]
+    pub fn serialize<S>(age: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("{}s", age.as_secs()))
+    }
+
+    fn parse(age: &str) -> Option<Duration> {
+        let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => age.split_at(index),
+            None => (age, "s"),
+        };
+        let unit_secs = match unit {
+            "d" => 24 * 60 * 60,
+            "h" => 60 * 60,
+            "m" => 60,
+            "s" => 1,
+            _ => return None,
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit_secs))
+            .map(Duration::from_secs)
+    }
+}
+
+mod log_level {
+    use log::LevelFilter;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<LevelFilter>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let level = String::deserialize(deserializer)?;
+        level
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("invalid log level {:?}", level)))
+    }
+
+    #[cfg_attr(feature = "cargo-clippy", allow(trivially_copy_pass_by_ref))]
+    pub fn serialize<S>(level: &Option<LevelFilter>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *level {
+            Some(level) => serializer.serialize_str(&level.to_string().to_lowercase()),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// How long the SAS tokens the daemon signs for IoT Hub are valid for, and
+/// how long before they expire they are replaced by new ones.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SasTokens {
+    ttl_secs: u64,
+    renew_before_secs: u64,
+}
+
+impl Default for SasTokens {
+    fn default() -> Self {
+        SasTokens {
+            ttl_secs: DEFAULT_TOKEN_TTL_SECS,
+            renew_before_secs: DEFAULT_TOKEN_RENEW_BEFORE_SECS,
+        }
+    }
+}
+
+impl SasTokens {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn renew_before(&self) -> Duration {
+        Duration::from_secs(self.renew_before_secs)
+    }
+}
+
+/// How long before the quick start device CA expires it is replaced, or
+/// `None` if it is left to expire. A device CA provided by the operator is
+/// never replaced.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct QuickstartCa {
+    renew_before_secs: u64,
+}
+
+impl Default for QuickstartCa {
+    fn default() -> Self {
+        QuickstartCa {
+            renew_before_secs: DEFAULT_QUICKSTART_CA_RENEW_BEFORE_SECS,
+        }
+    }
+}
+
+impl QuickstartCa {
+    pub fn renew_before(&self) -> Option<Duration> {
+        optional_secs(self.renew_before_secs)
+    }
+}
+
+/// Whether the module keys derived from the device key are cached, and how
+/// many of them.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct KeyCache {
+    enabled: bool,
+    max_entries: usize,
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        KeyCache {
+            enabled: true,
+            max_entries: DEFAULT_KEY_CACHE_MAX_ENTRIES,
+        }
+    }
+}
+
+impl KeyCache {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// How many keys are cached at most, 0 when the cache is turned off.
+    pub fn max_entries(&self) -> usize {
+        if self.enabled {
+            self.max_entries
+        } else {
+            0
+        }
+    }
+}
+
+/// Where metrics can be scraped from besides the management API, which
+/// only modules reach. `None`, the default, adds no listener.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Metrics {
+    #[serde(with = "url_serde")]
+    listen_uri: Option<Url>,
+}
+
+impl Metrics {
+    pub fn listen_uri(&self) -> Option<&Url> {
+        self.listen_uri.as_ref()
+    }
+}
+
+/// How iotedged reaches the internet. What is set here takes precedence over
+/// the `https_proxy` and `no_proxy` environment variables.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Proxy {
+    #[serde(with = "url_serde")]
+    https_proxy: Option<Url>,
+    no_proxy: Option<String>,
+}
+
+impl Proxy {
+    pub fn https_proxy(&self) -> Option<&Url> {
+        self.https_proxy.as_ref()
+    }
+
+    /// The destinations reached without the proxy. An empty list sends
+    /// everything through it, whatever `no_proxy` is in the environment.
+    pub fn no_proxy(&self) -> Option<&str> {
+        self.no_proxy.as_ref().map(AsRef::as_ref)
+    }
+}
+
+/// How the state iotedged keeps in the home directory is brought over from an
+/// older layout, or from a home directory it was moved out of.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct StateLayout {
+    previous_homedir: Option<PathBuf>,
+    dry_run: bool,
+}
+
+impl StateLayout {
+    /// The home directory the state is moved from when `homedir` has none
+    /// yet.
+    pub fn previous_homedir(&self) -> Option<&Path> {
+        self.previous_homedir.as_ref().map(AsRef::as_ref)
+    }
+
+    /// Whether a pending migration is only reported. iotedged doesn't start
+    /// while one is pending, so it doesn't provision again for lack of the
+    /// state it would have moved.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+/// Turns a number of seconds where 0 means "disabled" into a duration.
+fn optional_secs(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Settings<T> {
+    provisioning: Provisioning,
+    agent: ModuleSpec<T>,
+    hostname: String,
+    parent_hostname: Option<String>,
+    connect: Connect,
+    listen: Listen,
+    homedir: PathBuf,
+    moby_runtime: MobyRuntime,
+    #[serde(default)]
+    identity_manager: IdentityManagerType,
+    #[serde(default)]
+    edge_device_check: EdgeDeviceCheck,
+    certificates: Option<Certificates>,
+    #[serde(default)]
+    allow_wildcard_sans: bool,
+    additional_trusted_ca_dir: Option<PathBuf>,
+    manifest_trust_bundle: Option<PathBuf>,
+    #[serde(default)]
+    payload_limits: PayloadLimits,
+    #[serde(default)]
+    allow_host_processes: bool,
+    #[serde(default)]
+    timeouts: Timeouts,
+    #[serde(default)]
+    watchdog: Watchdog,
+    #[serde(default)]
+    shutdown: Shutdown,
+    #[serde(default)]
+    image_garbage_collection: ImageGarbageCollection,
+    #[serde(default, with = "log_level", skip_serializing_if = "Option::is_none")]
+    log_level: Option<LevelFilter>,
+    #[serde(default)]
+    allow_offline_start: bool,
+    #[serde(default)]
+    sas_tokens: SasTokens,
+    #[serde(default)]
+    key_cache: KeyCache,
+    #[serde(default)]
+    quickstart_ca: QuickstartCa,
+    #[serde(default)]
+    metrics: Metrics,
+    #[serde(default)]
+    proxy: Proxy,
+    #[serde(default)]
+    redacted_env: Vec<String>,
+    #[serde(default)]
+    leaf_cert_issuers: Vec<String>,
+    #[serde(default)]
+    module_env: EnvInjection,
+    #[serde(default)]
+    state_layout: StateLayout,
+    #[serde(default = "default_min_tls_version")]
+    min_tls_version: String,
+    #[serde(default)]
+    tls_cipher_suites: Vec<String>,
+}
+
+/// TLS 1.0 and 1.1 are no longer considered secure.
+fn default_min_tls_version() -> String {
+    "1.2".to_string()
+}
+
+impl<T> Settings<T>
+where
+    T: DeserializeOwned + Serialize,
+{
+    pub fn new(filename: Option<&str>) -> Result<Self, Error> {
+        let mut config = Config::default();
+        config.merge(File::from_str(DEFAULTS, FileFormat::Yaml))?;
+        if let Some(file) = filename {
+            config.merge(File::with_name(file).required(true))?;
+        }
+
+        config.merge(Environment::with_prefix("iotedge"))?;
+
+        let mut settings: Self = config.try_into()?;
+        if let Provisioning::Manual(ref mut manual) = settings.provisioning {
+            manual.load_device_connection_string(connection_string::from_env())?;
+        }
+
+        Ok(settings)
+    }
+
+    pub fn provisioning(&self) -> &Provisioning {
+        &self.provisioning
+    }
+
+    pub fn agent(&self) -> &ModuleSpec<T> {
+        &self.agent
+    }
+
+    pub fn agent_mut(&mut self) -> &mut ModuleSpec<T> {
+        &mut self.agent
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// The host name of the edge device this device sits below, if any.
+    pub fn parent_hostname(&self) -> Option<&str> {
+        self.parent_hostname.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn connect(&self) -> &Connect {
+        &self.connect
+    }
+
+    pub fn listen(&self) -> &Listen {
+        &self.listen
+    }
+
+    pub fn homedir(&self) -> &Path {
+        &self.homedir
+    }
+
+    pub fn moby_runtime(&self) -> &MobyRuntime {
+        &self.moby_runtime
+    }
+
+    pub fn identity_manager(&self) -> IdentityManagerType {
+        self.identity_manager
+    }
+
+    pub fn edge_device_check(&self) -> EdgeDeviceCheck {
+        self.edge_device_check
+    }
+
+    pub fn certificates(&self) -> Option<&Certificates> {
+        self.certificates.as_ref()
+    }
+
+    pub fn allow_wildcard_sans(&self) -> bool {
+        self.allow_wildcard_sans
+    }
+
+    pub fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+        self.additional_trusted_ca_dir.as_ref().map(AsRef::as_ref)
+    }
+
+    /// The certificates edgeAgent verifies signed deployment manifests with,
+    /// if manifests are signed.
+    pub fn manifest_trust_bundle(&self) -> Option<&Path> {
+        self.manifest_trust_bundle.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn payload_limits(&self) -> PayloadLimits {
+        self.payload_limits
+    }
+
+    pub fn allow_host_processes(&self) -> bool {
+        self.allow_host_processes
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        self.timeouts
+    }
+
+    /// Whether the modules that were running before are started while the
+    /// identity of the edge runtime module cannot be got, for example because
+    /// IoT Hub is unreachable when the device boots.
+    pub fn allow_offline_start(&self) -> bool {
+        self.allow_offline_start
+    }
+
+    pub fn watchdog(&self) -> Watchdog {
+        self.watchdog
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    pub fn image_garbage_collection(&self) -> ImageGarbageCollection {
+        self.image_garbage_collection
+    }
+
+    /// The most verbose level that is logged, unless `IOTEDGE_LOG` sets it.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        self.log_level
+    }
+
+    pub fn sas_tokens(&self) -> SasTokens {
+        self.sas_tokens
+    }
+
+    pub fn key_cache(&self) -> KeyCache {
+        self.key_cache
+    }
+
+    pub fn quickstart_ca(&self) -> QuickstartCa {
+        self.quickstart_ca
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn proxy(&self) -> &Proxy {
+        &self.proxy
+    }
+
+    /// Parts of environment variable names whose values the management API
+    /// hides, besides those it always hides.
+    pub fn redacted_env(&self) -> &[String] {
+        &self.redacted_env
+    }
+
+    /// The modules that may have the device CA issue certificates for leaf
+    /// devices.
+    pub fn leaf_cert_issuers(&self) -> &[String] {
+        &self.leaf_cert_issuers
+    }
+
+    /// Which of the variables iotedged sets are injected into the
+    /// environment of the edge runtime module.
+    pub fn module_env(&self) -> &EnvInjection {
+        &self.module_env
+    }
+
+    pub fn state_layout(&self) -> &StateLayout {
+        &self.state_layout
+    }
+
+    /// The lowest TLS version the listeners and the clients of iotedged
+    /// allow, one of 1.0, 1.1, 1.2 or 1.3.
+    pub fn min_tls_version(&self) -> &str {
+        &self.min_tls_version
+    }
+
+    /// The OpenSSL names of the cipher suites the `https` listeners allow.
+    /// When empty, those of the TLS library are allowed.
+    pub fn tls_cipher_suites(&self) -> &[String] {
+        &self.tls_cipher_suites
+    }
+
+    /// Compares these settings with a newly read version of them.
+    pub fn diff(&self, other: &Self) -> SettingsDiff {
+        let (this, other) = (to_value(self), to_value(other));
+        let mut diff = SettingsDiff::default();
+        for field in RELOADABLE.iter().chain(RESTART_REQUIRED) {
+            let pointer = format!("/{}", field.replace('.', "/"));
+            let changed = match (&this, &other) {
+                (Some(this), Some(other)) => this.pointer(&pointer) != other.pointer(&pointer),
+                _ => true,
+            };
+            if changed {
+                if RELOADABLE.contains(field) {
+                    diff.reloadable.push(field);
+                } else {
+                    diff.restart_required.push(field);
+                }
+            }
+        }
+        diff
+    }
+
+    /// Takes over the fields of `other` that can change while iotedged runs.
+    /// Everything else keeps its current value until the daemon restarts.
+    pub fn apply_reloadable(&mut self, other: &Self) {
+        self.listen.workload_limits = other.listen.workload_limits.clone();
+        self.listen.management_limits = other.listen.management_limits.clone();
+        self.listen.management_rates = other.listen.management_rates.clone();
+        self.watchdog = other.watchdog;
+        self.image_garbage_collection = other.image_garbage_collection;
+        self.log_level = other.log_level;
+    }
+
+    pub fn diff_with_cached(&self, path: PathBuf) -> Result<bool, Error> {
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(Error::from)
+            .and_then(|mut file: FsFile| {
+                let mut buffer = String::new();
+                file.read_to_string(&mut buffer)?;
+                let encoded = serde_json::to_string(self)
+                    .map_err(Error::from)
+                    .map(|s| Sha256::digest_str(&s))
+                    .map(|s| base64::encode(&s))?;
+                if encoded == buffer {
+                    debug!("Config state matches supplied config.");
                     Ok(false)
                 } else {
                     Ok(true)
                 }
-            }).or_else(|err| {
-                log_failure(Level::Debug, &err);
-                debug!("Error reading config backup.");
-                Ok(true)
-            })
+            }).or_else(|err| {
+                log_failure(Level::Debug, &err);
+                debug!("Error reading config backup.");
+                Ok(true)
+            })
+    }
+}
+
+/// Fields that `Settings::apply_reloadable` takes over from a new config,
+/// named after their keys in the config file.
+const RELOADABLE: &[&str] = &[
+    "listen.workload_limits",
+    "listen.management_limits",
+    "listen.management_rates",
+    "watchdog",
+    "image_garbage_collection",
+    "log_level",
+];
+
+/// Fields that are only read when the daemon starts.
+const RESTART_REQUIRED: &[&str] = &[
+    "provisioning",
+    "agent",
+    "hostname",
+    "parent_hostname",
+    "connect",
+    "listen.workload_uri",
+    "listen.management_uri",
+    "listen.drain_timeout_secs",
+    "listen.workload_socket",
+    "listen.management_socket",
+    "listen.workload_tls",
+    "listen.management_tls",
+    "homedir",
+    "moby_runtime.type",
+    "moby_runtime.uri",
+    "moby_runtime.network",
+    "moby_runtime.tls",
+    "moby_runtime.default_dns",
+    "moby_runtime.default_extra_hosts",
+    "moby_runtime.purge_volumes",
+    "moby_runtime.allow_any_signal",
+    "moby_runtime.registry_mirrors",
+    "moby_runtime.system_info_ttl_secs",
+    "moby_runtime.engines",
+    "identity_manager",
+    "edge_device_check",
+    "certificates",
+    "allow_wildcard_sans",
+    "additional_trusted_ca_dir",
+    "manifest_trust_bundle",
+    "payload_limits",
+    "allow_host_processes",
+    "timeouts",
+    "shutdown",
+    "allow_offline_start",
+    "sas_tokens",
+    "key_cache",
+    "quickstart_ca",
+    "metrics",
+    "proxy",
+    "redacted_env",
+    "leaf_cert_issuers",
+    "module_env",
+    "state_layout",
+    "min_tls_version",
+    "tls_cipher_suites",
+];
+
+/// The fields that differ between two versions of the settings, split by
+/// whether a running daemon can apply them.
+#[derive(Debug, Default, PartialEq)]
+pub struct SettingsDiff {
+    reloadable: Vec<&'static str>,
+    restart_required: Vec<&'static str>,
+}
+
+impl SettingsDiff {
+    pub fn reloadable(&self) -> &[&'static str] {
+        &self.reloadable
+    }
+
+    pub fn restart_required(&self) -> &[&'static str] {
+        &self.restart_required
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reloadable.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+fn to_value<V: Serialize>(value: &V) -> Option<serde_json::Value> {
+    serde_json::to_value(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use edgelet_docker::DockerConfig;
+    use edgelet_http::RouteClass;
+    use error::ErrorKind;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[cfg(unix)]
+    static GOOD_SETTINGS: &str = "test/linux/sample_settings.yaml";
+    #[cfg(unix)]
+    static GOOD_SETTINGS1: &str = "test/linux/sample_settings1.yaml";
+    #[cfg(unix)]
+    static BAD_SETTINGS: &str = "test/linux/bad_sample_settings.yaml";
+    #[cfg(unix)]
+    static GOOD_SETTINGS_TG: &str = "test/linux/sample_settings.tg.yaml";
+    #[cfg(unix)]
+    static GOOD_SETTINGS_X509: &str = "test/linux/sample_settings.x509.yaml";
+
+    #[cfg(windows)]
+    static GOOD_SETTINGS: &str = "test/windows/sample_settings.yaml";
+    #[cfg(windows)]
+    static GOOD_SETTINGS1: &str = "test/windows/sample_settings1.yaml";
+    #[cfg(windows)]
+    static BAD_SETTINGS: &str = "test/windows/bad_sample_settings.yaml";
+    #[cfg(windows)]
+    static GOOD_SETTINGS_TG: &str = "test/windows/sample_settings.tg.yaml";
+    #[cfg(windows)]
+    static GOOD_SETTINGS_X509: &str = "test/windows/sample_settings.x509.yaml";
+
+    fn unwrap_manual_provisioning(p: &Provisioning) -> String {
+        match p {
+            Provisioning::Manual(manual) => manual.device_connection_string().to_string(),
+            _ => "not implemented".to_string(),
+        }
+    }
+
+    #[test]
+    fn default_in_yaml_matches_constant() {
+        let mut config = Config::default();
+        config
+            .merge(File::from_str(DEFAULTS, FileFormat::Yaml))
+            .unwrap();
+        let settings: Settings<DockerConfig> = config.try_into().unwrap();
+
+        match settings.provisioning() {
+            Provisioning::Manual(ref manual) => {
+                assert_eq!(manual.device_connection_string(), DEFAULT_CONNECTION_STRING)
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn no_file_gets_error() {
+        let settings = Settings::<DockerConfig>::new(Some("garbage"));
+        assert!(settings.is_err());
+    }
+
+    #[test]
+    fn bad_file_gets_error() {
+        let settings = Settings::<DockerConfig>::new(Some(BAD_SETTINGS));
+        assert!(settings.is_err());
+    }
+
+    #[test]
+    fn manual_file_gets_sample_connection_string() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS));
+        println!("{:?}", settings);
+        assert!(settings.is_ok());
+        let s = settings.unwrap();
+        let p = s.provisioning();
+        let connection_string = unwrap_manual_provisioning(p);
+        assert_eq!(
+            connection_string,
+            "HostName=something.something.com;DeviceId=something;SharedAccessKey=c29tZXRoaW5n"
+        );
+    }
+
+    #[test]
+    fn manual_file_gets_sample_tg_paths() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS_TG));
+        println!("{:?}", settings);
+        assert!(settings.is_ok());
+        let s = settings.unwrap();
+        let certificates = s.certificates();
+        certificates
+            .map(|c| {
+                assert_eq!(c.device_ca_cert().to_str().unwrap(), "device_ca_cert.pem");
+                assert_eq!(c.device_ca_pk().to_str().unwrap(), "device_ca_pk.pem");
+                assert_eq!(
+                    c.trusted_ca_certs().to_str().unwrap(),
+                    "trusted_ca_certs.pem"
+                );
+            }).expect("certificates not configured");
+    }
+
+    #[test]
+    fn manual_file_gets_sample_x509_identity() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS_X509)).unwrap();
+        let x509 = match settings.provisioning() {
+            Provisioning::Manual(manual) => manual.x509().expect("x509 not configured"),
+            _ => panic!("manual provisioning not configured"),
+        };
+        assert_eq!("something.azure-devices.net", x509.iothub_hostname());
+        assert_eq!("something", x509.device_id());
+        assert_eq!(Path::new("identity_cert.pem"), x509.identity_cert());
+        assert_eq!(Path::new("identity_pk.pem"), x509.identity_pk());
+
+        let tls = x509.tls_config();
+        assert_eq!(Some(&PathBuf::from("identity_cert.pem")), tls.client_cert());
+        assert_eq!(Some(&PathBuf::from("identity_pk.pem")), tls.client_key());
+    }
+
+    #[test]
+    fn manual_file_without_x509_has_no_x509_identity() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        match settings.provisioning() {
+            Provisioning::Manual(manual) => assert!(manual.x509().is_none()),
+            _ => panic!("manual provisioning not configured"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connection_string_is_read_from_file() {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = TempDir::new("connection_string").unwrap();
+        let file = tmp_dir.path().join("connection_string");
+        fs::write(&file, "HostName=hub;DeviceId=d1;SharedAccessKey=a2V5\n").unwrap();
+        fs::set_permissions(&file, Permissions::from_mode(0o600)).unwrap();
+
+        let sample = fs::read_to_string(GOOD_SETTINGS).unwrap();
+        let config: String = sample
+            .lines()
+            .map(|line| {
+                if line.trim_left().starts_with("device_connection_string:") {
+                    format!("  device_connection_string_file: \"{}\"\n", file.display())
+                } else {
+                    format!("{}\n", line)
+                }
+            }).collect();
+        let config_file = tmp_dir.path().join("config.yaml");
+        fs::write(&config_file, config).unwrap();
+        let config_file = config_file.to_str().unwrap();
+
+        let settings = Settings::<DockerConfig>::new(Some(config_file)).unwrap();
+        match settings.provisioning() {
+            Provisioning::Manual(manual) => {
+                assert_eq!(
+                    "HostName=hub;DeviceId=d1;SharedAccessKey=a2V5",
+                    manual.device_connection_string()
+                );
+                assert!(!manual.has_inline_connection_string());
+            }
+            _ => panic!("manual provisioning not configured"),
+        }
+
+        fs::set_permissions(&file, Permissions::from_mode(0o640)).unwrap();
+        let err = Settings::<DockerConfig>::new(Some(config_file)).unwrap_err();
+        assert_eq!(&ErrorKind::DeviceConnectionString, err.kind());
+    }
+
+    #[test]
+    fn connection_string_falls_back_to_environment() {
+        let from_env = || Some("HostName=hub;DeviceId=env;SharedAccessKey=a2V5".to_string());
+
+        let placeholder = json!({ "device_connection_string": DEFAULT_CONNECTION_STRING });
+        let mut manual: Manual = serde_json::from_value(placeholder).unwrap();
+        manual.load_device_connection_string(from_env()).unwrap();
+        assert_eq!(from_env().unwrap(), manual.device_connection_string());
+        assert!(!manual.has_inline_connection_string());
+
+        let inline = "HostName=hub;DeviceId=inline;SharedAccessKey=a2V5";
+        let mut manual: Manual =
+            serde_json::from_value(json!({ "device_connection_string": inline })).unwrap();
+        manual.load_device_connection_string(from_env()).unwrap();
+        assert_eq!(inline, manual.device_connection_string());
+        assert!(manual.has_inline_connection_string());
+    }
+
+    #[test]
+    fn debug_output_redacts_connection_string() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let debug = format!("{:?}", settings);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("SharedAccessKey"));
+    }
+
+    #[test]
+    fn diff_with_same_cached_returns_false() {
+        let tmp_dir = TempDir::new("blah").unwrap();
+        let path = tmp_dir.path().join("cache");
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let settings_to_write = serde_json::to_string(&settings).unwrap();
+        let sha_to_write = Sha256::digest_str(&settings_to_write);
+        let base64_to_write = base64::encode(&sha_to_write);
+        FsFile::create(path.clone())
+            .unwrap()
+            .write_all(base64_to_write.as_bytes())
+            .unwrap();
+        assert_eq!(settings.diff_with_cached(path).unwrap(), false);
+    }
+
+    #[test]
+    fn diff_with_different_cached_returns_true() {
+        let tmp_dir = TempDir::new("blah").unwrap();
+        let path = tmp_dir.path().join("cache");
+        let settings1 = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS1)).unwrap();
+        let settings_to_write = serde_json::to_string(&settings1).unwrap();
+        let sha_to_write = Sha256::digest_str(&settings_to_write);
+        let base64_to_write = base64::encode(&sha_to_write);
+        FsFile::create(path.clone())
+            .unwrap()
+            .write_all(base64_to_write.as_bytes())
+            .unwrap();
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(settings.diff_with_cached(path).unwrap(), true);
+    }
+
+    #[test]
+    fn diff_with_no_file_returns_true() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(
+            settings
+                .diff_with_cached(PathBuf::from("i dont exist"))
+                .unwrap(),
+            true
+        );
+    }
+
+    #[test]
+    fn network_default() {
+        let moby1 = MobyRuntime {
+            runtime_type: RuntimeType::Docker,
+            uri: Url::parse("http://test").unwrap(),
+            network: "".to_string(),
+            tls: MobyRuntimeTls::default(),
+            default_dns: vec![],
+            default_extra_hosts: vec![],
+            purge_volumes: false,
+            allow_any_signal: false,
+            registry_mirrors: vec![],
+            system_info_ttl_secs: DEFAULT_SYSTEM_INFO_TTL_SECS,
+            engines: BTreeMap::new(),
+        };
+        assert_eq!(DEFAULT_NETWORKID, moby1.network());
+
+        let moby2 = MobyRuntime {
+            runtime_type: RuntimeType::Docker,
+            uri: Url::parse("http://test").unwrap(),
+            network: "some-network".to_string(),
+            tls: MobyRuntimeTls::default(),
+            default_dns: vec![],
+            default_extra_hosts: vec![],
+            purge_volumes: false,
+            allow_any_signal: false,
+            registry_mirrors: vec![],
+            system_info_ttl_secs: DEFAULT_SYSTEM_INFO_TTL_SECS,
+            engines: BTreeMap::new(),
+        };
+        assert_eq!("some-network", moby2.network());
+    }
+
+    #[test]
+    fn engines_are_read_by_name() {
+        let moby: MobyRuntime = serde_json::from_str(
+            r#"{
+                "uri": "unix:///var/run/docker.sock",
+                "network": "azure-iot-edge",
+                "engines": {
+                    "sandbox": { "uri": "unix:///var/run/sandbox.sock" }
+                }
+            }"#,
+        ).unwrap();
+        assert_eq!(1, moby.engines().len());
+        let sandbox = &moby.engines()["sandbox"];
+        assert_eq!("unix:///var/run/sandbox.sock", sandbox.uri().as_str());
+        assert!(!sandbox.tls_config().require_tls());
+    }
+
+    #[test]
+    fn only_safe_signals_are_sent_unless_opened_up() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(KillSignals::Safe, settings.moby_runtime().kill_signals());
+
+        let moby: MobyRuntime = serde_json::from_str(
+            r#"{"uri": "unix:///var/run/docker.sock", "network": "", "allow_any_signal": true}"#,
+        ).unwrap();
+        assert_eq!(KillSignals::Any, moby.kill_signals());
+    }
+
+    #[test]
+    fn system_info_is_kept_for_five_minutes_unless_configured() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(
+            Duration::from_secs(300),
+            settings.moby_runtime().system_info_ttl()
+        );
+
+        let moby: MobyRuntime = serde_json::from_str(
+            r#"{"uri": "unix:///var/run/docker.sock", "network": "", "system_info_ttl_secs": 0}"#,
+        ).unwrap();
+        assert_eq!(Duration::from_secs(0), moby.system_info_ttl());
+    }
+
+    #[test]
+    fn registry_mirrors_from_settings() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert!(settings.moby_runtime().registry_mirrors().is_empty());
+
+        let moby: MobyRuntime = serde_json::from_str(
+            r#"{
+                "uri": "unix:///var/run/docker.sock",
+                "network": "",
+                "registry_mirrors": [
+                    {"registry": "contoso.azurecr.io", "mirror": "mirror.local:5000"},
+                    {"registry": "docker.io", "mirror": "hub.local", "credential": "hub"}
+                ]
+            }"#,
+        ).unwrap();
+        assert_eq!(
+            &[
+                RegistryMirror::new(
+                    "contoso.azurecr.io".to_string(),
+                    "mirror.local:5000".to_string()
+                ),
+                RegistryMirror::new("docker.io".to_string(), "hub.local".to_string())
+                    .with_credential(Some("hub".to_string())),
+            ],
+            moby.registry_mirrors()
+        );
+    }
+
+    #[test]
+    fn tls_config_from_settings() {
+        let moby = MobyRuntime {
+            runtime_type: RuntimeType::Docker,
+            uri: Url::parse("tcp://docker:2376").unwrap(),
+            network: "".to_string(),
+            tls: MobyRuntimeTls {
+                ca_cert: Some(PathBuf::from("ca.pem")),
+                client_cert: Some(PathBuf::from("cert.pem")),
+                client_key: Some(PathBuf::from("key.pem")),
+                require_tls: true,
+            },
+            default_dns: vec![],
+            default_extra_hosts: vec![],
+            purge_volumes: false,
+            allow_any_signal: false,
+            registry_mirrors: vec![],
+            system_info_ttl_secs: DEFAULT_SYSTEM_INFO_TTL_SECS,
+            engines: BTreeMap::new(),
+        };
+        let tls = moby.tls_config();
+        assert_eq!(Some(&PathBuf::from("ca.pem")), tls.ca_cert());
+        assert_eq!(Some(&PathBuf::from("cert.pem")), tls.client_cert());
+        assert_eq!(Some(&PathBuf::from("key.pem")), tls.client_key());
+        assert!(tls.require_tls());
+
+        let moby = MobyRuntime {
+            runtime_type: RuntimeType::Docker,
+            uri: Url::parse("unix:///var/run/docker.sock").unwrap(),
+            network: "".to_string(),
+            tls: MobyRuntimeTls::default(),
+            default_dns: vec![],
+            default_extra_hosts: vec![],
+            purge_volumes: false,
+            allow_any_signal: false,
+            registry_mirrors: vec![],
+            system_info_ttl_secs: DEFAULT_SYSTEM_INFO_TTL_SECS,
+            engines: BTreeMap::new(),
+        };
+        let tls = moby.tls_config();
+        assert_eq!(None, tls.ca_cert());
+        assert!(!tls.require_tls());
+    }
+
+    #[test]
+    fn runtime_type() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(RuntimeType::Docker, settings.moby_runtime().runtime_type());
+
+        let moby: MobyRuntime = serde_json::from_str(
+            r#"{"type": "none", "uri": "unix:///var/run/docker.sock", "network": ""}"#,
+        ).unwrap();
+        assert_eq!(RuntimeType::None, moby.runtime_type());
+
+        let moby: Result<MobyRuntime, _> = serde_json::from_str(
+            r#"{"type": "rkt", "uri": "unix:///var/run/docker.sock", "network": ""}"#,
+        );
+        assert!(moby.is_err());
+    }
+
+    #[test]
+    fn identity_manager_type() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(IdentityManagerType::IotHub, settings.identity_manager());
+
+        let manager_type: IdentityManagerType = serde_json::from_str(r#""local""#).unwrap();
+        assert_eq!(IdentityManagerType::Local, manager_type);
+
+        let manager_type: Result<IdentityManagerType, _> = serde_json::from_str(r#""dps""#);
+        assert!(manager_type.is_err());
+    }
+
+    #[test]
+    fn edge_device_check() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(EdgeDeviceCheck::Enforce, settings.edge_device_check());
+
+        let check: EdgeDeviceCheck = serde_json::from_str(r#""warn""#).unwrap();
+        assert_eq!(EdgeDeviceCheck::Warn, check);
+
+        let check: Result<EdgeDeviceCheck, _> = serde_json::from_str(r#""off""#);
+        assert!(check.is_err());
+    }
+
+    #[test]
+    fn timeouts_default() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let timeouts = settings.timeouts();
+        assert_eq!(Timeouts::default(), timeouts);
+        assert_eq!(
+            Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            timeouts.connect()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS)),
+            timeouts.iothub_request()
+        );
+    }
+
+    #[test]
+    fn partial_timeouts_use_defaults() {
+        let timeouts: Timeouts =
+            serde_json::from_str(r#"{"connect_secs": 5, "moby_runtime_request_secs": 0}"#)
+                .unwrap();
+        assert_eq!(Duration::from_secs(5), timeouts.connect());
+        assert_eq!(None, timeouts.moby_runtime_request());
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS)),
+            timeouts.dps_request()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_OPERATION_TIMEOUT_SECS)),
+            timeouts.moby_runtime_operation()
+        );
+    }
+
+    #[test]
+    fn operation_timeout_can_be_disabled() {
+        let timeouts: Timeouts =
+            serde_json::from_str(r#"{"moby_runtime_operation_secs": 30}"#).unwrap();
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            timeouts.moby_runtime_operation()
+        );
+
+        let timeouts: Timeouts =
+            serde_json::from_str(r#"{"moby_runtime_operation_secs": 0}"#).unwrap();
+        assert_eq!(None, timeouts.moby_runtime_operation());
+    }
+
+    #[test]
+    fn slow_thresholds() {
+        let timeouts = Timeouts::default();
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_SLOW_PULL_SECS)),
+            timeouts.slow_pull()
+        );
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_SLOW_CREATE_SECS)),
+            timeouts.slow_create()
+        );
+
+        let timeouts: Timeouts =
+            serde_json::from_str(r#"{"slow_pull_secs": 600, "slow_start_secs": 0}"#).unwrap();
+        assert_eq!(Some(Duration::from_secs(600)), timeouts.slow_pull());
+        assert_eq!(None, timeouts.slow_start());
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(
+            Duration::from_secs(DEFAULT_DRAIN_TIMEOUT_SECS),
+            settings.listen().drain_timeout()
+        );
+
+        let listen: Listen = serde_json::from_str(
+            r#"{
+                "management_uri": "unix:///var/run/iotedge/mgmt.sock",
+                "workload_uri": "unix:///var/run/iotedge/workload.sock",
+                "drain_timeout_secs": 3
+            }"#,
+        ).unwrap();
+        assert_eq!(Duration::from_secs(3), listen.drain_timeout());
+    }
+
+    #[test]
+    fn socket_permissions() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(
+            SocketPermissions::new(),
+            settings.listen().management_socket().permissions()
+        );
+
+        let socket: Socket =
+            serde_json::from_str(r#"{"mode": "0660", "group": 1001}"#).unwrap();
+        assert_eq!(
+            SocketPermissions::new().with_mode(0o660).with_group(1001),
+            socket.permissions()
+        );
+        assert_eq!(
+            r#"{"mode":"0660","owner":null,"group":1001}"#,
+            serde_json::to_string(&socket).unwrap()
+        );
+    }
+
+    #[test]
+    fn listener_tls() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(&ListenTls::default(), settings.listen().workload_tls());
+        assert_eq!(&ListenTls::default(), settings.listen().management_tls());
+
+        let listen: Listen = serde_json::from_str(
+            r#"{
+                "management_uri": "unix:///var/run/iotedge/mgmt.sock",
+                "workload_uri": "https://127.0.0.1:15581",
+                "workload_tls": {
+                    "server_cert": "/etc/iotedge/workload.cert.pem",
+                    "server_key": "/etc/iotedge/workload.key.pem",
+                    "client_ca_cert": "/etc/iotedge/modules-ca.cert.pem"
+                }
+            }"#,
+        ).unwrap();
+        let tls = listen.workload_tls();
+        assert_eq!(
+            Some(Path::new("/etc/iotedge/workload.cert.pem")),
+            tls.server_cert()
+        );
+        assert_eq!(
+            Some(Path::new("/etc/iotedge/workload.key.pem")),
+            tls.server_key()
+        );
+        assert_eq!(
+            Some(Path::new("/etc/iotedge/modules-ca.cert.pem")),
+            tls.client_ca_cert()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use config::{Config, File, FileFormat};
-    use edgelet_docker::DockerConfig;
-    use std::io::Write;
-    use tempdir::TempDir;
+    #[test]
+    fn invalid_socket_mode() {
+        for mode in &["rw-rw----", "0890", "17777"] {
+            let socket = serde_json::from_str::<Socket>(&format!(r#"{{"mode": "{}"}}"#, mode));
+            assert!(socket.is_err(), "{}", mode);
+        }
+    }
 
-    #[cfg(unix)]
-    static GOOD_SETTINGS: &str = "test/linux/sample_settings.yaml";
-    #[cfg(unix)]
-    static GOOD_SETTINGS1: &str = "test/linux/sample_settings1.yaml";
-    #[cfg(unix)]
-    static BAD_SETTINGS: &str = "test/linux/bad_sample_settings.yaml";
-    #[cfg(unix)]
-    static GOOD_SETTINGS_TG: &str = "test/linux/sample_settings.tg.yaml";
+    #[test]
+    fn listener_limits() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let management = settings.listen().management_limits();
+        assert_eq!(Some(DEFAULT_MANAGEMENT_MAX_CONNECTIONS), management.max_connections());
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_HEADER_READ_TIMEOUT_SECS)),
+            management.header_read_timeout()
+        );
+        assert_eq!(
+            Some(DEFAULT_WORKLOAD_MAX_CONNECTIONS),
+            settings.listen().workload_limits().max_connections()
+        );
 
-    #[cfg(windows)]
-    static GOOD_SETTINGS: &str = "test/windows/sample_settings.yaml";
-    #[cfg(windows)]
-    static GOOD_SETTINGS1: &str = "test/windows/sample_settings1.yaml";
-    #[cfg(windows)]
-    static BAD_SETTINGS: &str = "test/windows/bad_sample_settings.yaml";
-    #[cfg(windows)]
-    static GOOD_SETTINGS_TG: &str = "test/windows/sample_settings.tg.yaml";
+        let limits: Limits =
+            serde_json::from_str(r#"{"max_connections": 0, "body_read_timeout_secs": 0}"#)
+                .unwrap();
+        assert_eq!(None, limits.max_connections());
+        assert_eq!(None, limits.body_read_timeout());
+        assert_eq!(
+            Some(Duration::from_secs(DEFAULT_HEADER_READ_TIMEOUT_SECS)),
+            limits.header_read_timeout()
+        );
+        assert_eq!(Some(DEFAULT_MAX_HEADERS), limits.max_headers());
+        assert_eq!(Some(DEFAULT_MAX_HEADER_BYTES), limits.max_header_bytes());
 
-    fn unwrap_manual_provisioning(p: &Provisioning) -> String {
-        match p {
-            Provisioning::Manual(manual) => manual.device_connection_string().to_string(),
-            _ => "not implemented".to_string(),
-        }
+        let limits: Limits =
+            serde_json::from_str(r#"{"max_connections": 8, "max_header_bytes": 0}"#).unwrap();
+        assert_eq!(None, limits.connection_limits().max_header_bytes());
+        assert_eq!(Some(DEFAULT_MAX_HEADERS), limits.connection_limits().max_headers());
     }
 
     #[test]
-    fn default_in_yaml_matches_constant() {
-        let mut config = Config::default();
-        config
-            .merge(File::from_str(DEFAULTS, FileFormat::Yaml))
-            .unwrap();
-        let settings: Settings<DockerConfig> = config.try_into().unwrap();
+    fn watchdog_restart_policy() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(RestartPolicy::default(), settings.watchdog().restart_policy());
 
-        match settings.provisioning() {
-            Provisioning::Manual(ref manual) => {
-                assert_eq!(manual.device_connection_string(), DEFAULT_CONNECTION_STRING)
-            }
-            _ => assert!(false),
+        let watchdog: Watchdog =
+            serde_json::from_str(r#"{"restart_base_delay_secs": 5, "max_restarts": 3}"#).unwrap();
+        let policy = watchdog.restart_policy();
+        assert_eq!(Duration::from_secs(5), policy.base_delay());
+        assert_eq!(RestartPolicy::default().max_delay(), policy.max_delay());
+        assert_eq!(Some(3), policy.max_restarts());
+    }
+
+    #[test]
+    fn watchdog_restart_on_unhealthy() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(None, settings.watchdog().restart_policy().unhealthy_checks());
+
+        let watchdog: Watchdog =
+            serde_json::from_str(r#"{"restart_on_unhealthy": true, "unhealthy_checks": 5}"#)
+                .unwrap();
+        let policy = watchdog.restart_policy();
+        assert_eq!(Some(5), policy.unhealthy_checks());
+        assert_eq!(
+            RestartPolicy::default().unhealthy_grace_period(),
+            policy.unhealthy_grace_period()
+        );
+
+        let watchdog: Watchdog = serde_json::from_str(r#"{"unhealthy_checks": 5}"#).unwrap();
+        assert_eq!(None, watchdog.restart_policy().unhealthy_checks());
+    }
+
+    #[test]
+    fn shutdown_stop_options() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(StopAllOptions::default(), settings.shutdown().stop_options());
+
+        let shutdown: Shutdown =
+            serde_json::from_str(r#"{"stop_last": ["broker", "edgeAgent"], "max_concurrency": 0}"#)
+                .unwrap();
+        let options = shutdown.stop_options();
+        assert_eq!(&["broker".to_string(), "edgeAgent".to_string()], options.stop_last());
+        assert_eq!(StopAllOptions::default().phase_timeout(), options.phase_timeout());
+        assert_eq!(1, options.max_concurrency());
+    }
+
+    #[test]
+    fn image_garbage_collection() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(
+            ImageGarbageCollection::default(),
+            settings.image_garbage_collection()
+        );
+        assert!(!settings.image_garbage_collection().enabled());
+
+        let gc: ImageGarbageCollection = serde_json::from_str(
+            r#"{"enabled": true, "cleanup_time": "23:30", "min_age": "12h"}"#,
+        ).unwrap();
+        assert!(gc.enabled());
+        assert_eq!(NaiveTime::from_hms(23, 30, 0), gc.cleanup_time());
+        assert_eq!(Duration::from_secs(12 * 60 * 60), gc.min_age());
+
+        for &(age, secs) in &[("7d", 7 * 24 * 60 * 60), ("30m", 30 * 60), ("90", 90)] {
+            let json = format!(r#"{{"min_age": "{}"}}"#, age);
+            let gc: ImageGarbageCollection = serde_json::from_str(&json).unwrap();
+            assert_eq!(Duration::from_secs(secs), gc.min_age());
+        }
+        for json in &[
+            r#"{"min_age": "7w"}"#,
+            r#"{"min_age": "d"}"#,
+            r#"{"cleanup_time": "25:00"}"#,
+            r#"{"cleanup_time": "2am"}"#,
+        ] {
+            assert!(serde_json::from_str::<ImageGarbageCollection>(json).is_err());
         }
     }
 
     #[test]
-    fn no_file_gets_error() {
-        let settings = Settings::<DockerConfig>::new(Some("garbage"));
-        assert!(settings.is_err());
+    fn sas_tokens() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(Duration::from_secs(3600), settings.sas_tokens().ttl());
+        assert_eq!(Duration::from_secs(300), settings.sas_tokens().renew_before());
+
+        let sas_tokens: SasTokens = serde_json::from_str(r#"{"ttl_secs": 900}"#).unwrap();
+        assert_eq!(Duration::from_secs(900), sas_tokens.ttl());
+        assert_eq!(Duration::from_secs(300), sas_tokens.renew_before());
     }
 
     #[test]
-    fn bad_file_gets_error() {
-        let settings = Settings::<DockerConfig>::new(Some(BAD_SETTINGS));
-        assert!(settings.is_err());
+    fn key_cache() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(true, settings.key_cache().enabled());
+        assert_eq!(256, settings.key_cache().max_entries());
+
+        let key_cache: KeyCache = serde_json::from_str(r#"{"max_entries": 16}"#).unwrap();
+        assert_eq!(16, key_cache.max_entries());
+
+        let key_cache: KeyCache = serde_json::from_str(r#"{"enabled": false}"#).unwrap();
+        assert_eq!(false, key_cache.enabled());
+        assert_eq!(0, key_cache.max_entries());
     }
 
     #[test]
-    fn manual_file_gets_sample_connection_string() {
-        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS));
-        println!("{:?}", settings);
-        assert!(settings.is_ok());
-        let s = settings.unwrap();
-        let p = s.provisioning();
-        let connection_string = unwrap_manual_provisioning(p);
+    fn quickstart_ca() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
         assert_eq!(
-            connection_string,
-            "HostName=something.something.com;DeviceId=something;SharedAccessKey=something"
+            Some(Duration::from_secs(14 * 24 * 3600)),
+            settings.quickstart_ca().renew_before()
         );
+
+        let quickstart_ca: QuickstartCa =
+            serde_json::from_str(r#"{"renew_before_secs": 0}"#).unwrap();
+        assert_eq!(None, quickstart_ca.renew_before());
     }
 
     #[test]
-    fn manual_file_gets_sample_tg_paths() {
-        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS_TG));
-        println!("{:?}", settings);
-        assert!(settings.is_ok());
-        let s = settings.unwrap();
-        let certificates = s.certificates();
-        certificates
-            .map(|c| {
-                assert_eq!(c.device_ca_cert().to_str().unwrap(), "device_ca_cert.pem");
-                assert_eq!(c.device_ca_pk().to_str().unwrap(), "device_ca_pk.pem");
-                assert_eq!(
-                    c.trusted_ca_certs().to_str().unwrap(),
-                    "trusted_ca_certs.pem"
-                );
-            }).expect("certificates not configured");
+    fn metrics_listener_is_opt_in() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(None, settings.metrics().listen_uri());
+
+        let metrics: Metrics =
+            serde_json::from_str(r#"{"listen_uri": "http://0.0.0.0:9600"}"#).unwrap();
+        assert_eq!(
+            Some(&Url::parse("http://0.0.0.0:9600").unwrap()),
+            metrics.listen_uri()
+        );
+
+        let mut changed = settings.clone();
+        changed.metrics = metrics;
+        assert_eq!(vec!["metrics"], settings.diff(&changed).restart_required());
     }
 
     #[test]
-    fn diff_with_same_cached_returns_false() {
-        let tmp_dir = TempDir::new("blah").unwrap();
-        let path = tmp_dir.path().join("cache");
+    fn proxy_defaults_to_the_environment() {
         let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
-        let settings_to_write = serde_json::to_string(&settings).unwrap();
-        let sha_to_write = Sha256::digest_str(&settings_to_write);
-        let base64_to_write = base64::encode(&sha_to_write);
-        FsFile::create(path.clone())
-            .unwrap()
-            .write_all(base64_to_write.as_bytes())
-            .unwrap();
-        assert_eq!(settings.diff_with_cached(path).unwrap(), false);
+        assert_eq!(None, settings.proxy().https_proxy());
+        assert_eq!(None, settings.proxy().no_proxy());
+
+        let proxy: Proxy = serde_json::from_str(
+            r#"{"https_proxy": "http://proxy:3128", "no_proxy": "10.0.0.0/8,.local"}"#,
+        ).unwrap();
+        assert_eq!(
+            Some(&Url::parse("http://proxy:3128").unwrap()),
+            proxy.https_proxy()
+        );
+        assert_eq!(Some("10.0.0.0/8,.local"), proxy.no_proxy());
+
+        let mut changed = settings.clone();
+        changed.proxy = proxy;
+        assert_eq!(vec!["proxy"], settings.diff(&changed).restart_required());
     }
 
     #[test]
-    fn diff_with_different_cached_returns_true() {
-        let tmp_dir = TempDir::new("blah").unwrap();
-        let path = tmp_dir.path().join("cache");
-        let settings1 = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS1)).unwrap();
-        let settings_to_write = serde_json::to_string(&settings1).unwrap();
-        let sha_to_write = Sha256::digest_str(&settings_to_write);
-        let base64_to_write = base64::encode(&sha_to_write);
-        FsFile::create(path.clone())
-            .unwrap()
-            .write_all(base64_to_write.as_bytes())
-            .unwrap();
+    fn state_layout_defaults_to_migrating_in_place() {
         let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
-        assert_eq!(settings.diff_with_cached(path).unwrap(), true);
+        assert_eq!(None, settings.state_layout().previous_homedir());
+        assert_eq!(false, settings.state_layout().dry_run());
+
+        let state_layout: StateLayout =
+            serde_json::from_str(r#"{"previous_homedir": "/var/lib/iotedge"}"#).unwrap();
+        assert_eq!(
+            Some(Path::new("/var/lib/iotedge")),
+            state_layout.previous_homedir()
+        );
+        assert_eq!(false, state_layout.dry_run());
     }
 
     #[test]
-    fn diff_with_no_file_returns_true() {
+    fn tls_defaults_to_version_1_2_and_the_library_cipher_suites() {
         let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!("1.2", settings.min_tls_version());
+        assert!(settings.tls_cipher_suites().is_empty());
+
+        let mut changed = settings.clone();
+        changed.min_tls_version = "1.3".to_string();
+        changed.tls_cipher_suites = vec!["TLS_AES_256_GCM_SHA384".to_string()];
         assert_eq!(
-            settings
-                .diff_with_cached(PathBuf::from("i dont exist"))
-                .unwrap(),
-            true
+            vec!["min_tls_version", "tls_cipher_suites"],
+            settings.diff(&changed).restart_required()
         );
     }
 
     #[test]
-    fn network_default() {
-        let moby1 = MobyRuntime {
-            uri: Url::parse("http://test").unwrap(),
-            network: "".to_string(),
-        };
-        assert_eq!(DEFAULT_NETWORKID, moby1.network());
+    fn module_env_injects_everything_by_default() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(None, settings.parent_hostname());
+        assert_eq!(&EnvInjection::default(), settings.module_env());
 
-        let moby2 = MobyRuntime {
-            uri: Url::parse("http://test").unwrap(),
-            network: "some-network".to_string(),
-        };
-        assert_eq!("some-network", moby2.network());
+        let mut changed = settings.clone();
+        changed.parent_hostname = Some("parent.contoso.com".to_string());
+        changed.module_env = EnvInjection::default().with_deny(vec!["NetworkId".to_string()]);
+        assert_eq!(
+            vec!["parent_hostname", "module_env"],
+            settings.diff(&changed).restart_required()
+        );
+    }
+
+    #[test]
+    fn offline_start_is_opt_in() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(false, settings.allow_offline_start());
+
+        let mut changed = settings.clone();
+        changed.allow_offline_start = true;
+        assert_eq!(
+            vec!["allow_offline_start"],
+            settings.diff(&changed).restart_required()
+        );
+    }
+
+    #[test]
+    fn redacted_env_defaults_to_empty() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert!(settings.redacted_env().is_empty());
+
+        let mut changed = settings.clone();
+        changed.redacted_env = vec!["TOKEN".to_string()];
+        assert_eq!(
+            vec!["redacted_env"],
+            settings.diff(&changed).restart_required()
+        );
+    }
+
+    #[test]
+    fn leaf_cert_issuers_default_to_none() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert!(settings.leaf_cert_issuers().is_empty());
+
+        let mut changed = settings.clone();
+        changed.leaf_cert_issuers = vec!["edgeHub".to_string()];
+        assert_eq!(
+            vec!["leaf_cert_issuers"],
+            settings.diff(&changed).restart_required()
+        );
+    }
+
+    #[test]
+    fn unchanged_settings_have_no_diff() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let reread = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert!(settings.diff(&reread).is_empty());
+    }
+
+    #[test]
+    fn management_rates() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let limits = settings.listen().management_rates().rate_limits();
+        assert_eq!(
+            Some(Rate::new(DEFAULT_CHEAP_REQUESTS_PER_SEC, DEFAULT_CHEAP_REQUESTS_BURST)),
+            limits.rate(RouteClass::Cheap)
+        );
+        assert!(limits.exempt_uids().is_empty());
+
+        let rates: RequestRates = serde_json::from_str(
+            r#"{"expensive_per_sec": 0, "cheap_burst": 5, "exempt_uids": [13622]}"#,
+        ).unwrap();
+        let limits = rates.rate_limits();
+        assert_eq!(None, limits.rate(RouteClass::Expensive));
+        assert_eq!(
+            Some(Rate::new(DEFAULT_CHEAP_REQUESTS_PER_SEC, 5)),
+            limits.rate(RouteClass::Cheap)
+        );
+        assert_eq!(&[13622], limits.exempt_uids());
+    }
+
+    #[test]
+    fn listener_limits_are_reloadable() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut changed = settings.clone();
+        changed.listen.workload_limits.max_connections = 10;
+        changed.listen.management_limits.header_read_timeout_secs = 5;
+        changed.watchdog.max_restarts = Some(5);
+
+        let diff = settings.diff(&changed);
+        assert_eq!(
+            &["listen.workload_limits", "listen.management_limits", "watchdog"],
+            diff.reloadable()
+        );
+        assert!(diff.restart_required().is_empty());
+    }
+
+    #[test]
+    fn log_level_and_image_garbage_collection_are_reloadable() {
+        let mut settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(None, settings.log_level());
+        let mut changed = settings.clone();
+        changed.image_garbage_collection.enabled = true;
+        changed.log_level = Some(LevelFilter::Debug);
+
+        let diff = settings.diff(&changed);
+        assert_eq!(&["image_garbage_collection", "log_level"], diff.reloadable());
+        assert!(diff.restart_required().is_empty());
+
+        settings.apply_reloadable(&changed);
+        assert!(settings.image_garbage_collection().enabled());
+        assert_eq!(Some(LevelFilter::Debug), settings.log_level());
+    }
+
+    #[test]
+    fn log_level_is_parsed() {
+        #[derive(Debug, Deserialize, Serialize)]
+        struct Logging {
+            #[serde(default, with = "log_level")]
+            log_level: Option<LevelFilter>,
+        }
+
+        let logging: Logging = serde_json::from_str(r#"{"log_level": " Debug "}"#).unwrap();
+        assert_eq!(Some(LevelFilter::Debug), logging.log_level);
+        assert_eq!(
+            r#"{"log_level":"debug"}"#,
+            serde_json::to_string(&logging).unwrap()
+        );
+        assert!(serde_json::from_str::<Logging>(r#"{"log_level": "loud"}"#).is_err());
+        assert_eq!(None, serde_json::from_str::<Logging>("{}").unwrap().log_level);
+    }
+
+    #[test]
+    fn identity_and_listener_changes_require_restart() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut changed = settings.clone();
+        changed.provisioning = Provisioning::Manual(Manual {
+            device_connection_string: "HostName=other;DeviceId=d1;SharedAccessKey=a2V5"
+                .to_string(),
+            device_connection_string_file: None,
+            x509: None,
+            loaded: false,
+        });
+        changed.hostname = "other".to_string();
+        changed.listen.workload_uri = Url::parse("unix:///var/run/other.sock").unwrap();
+        changed.listen.drain_timeout_secs += 1;
+        changed.moby_runtime.network = "other-network".to_string();
+        changed.timeouts.connect_secs += 1;
+
+        let diff = settings.diff(&changed);
+        assert!(diff.reloadable().is_empty());
+        assert_eq!(
+            &[
+                "provisioning",
+                "hostname",
+                "listen.workload_uri",
+                "listen.drain_timeout_secs",
+                "moby_runtime.network",
+                "timeouts",
+            ],
+            diff.restart_required()
+        );
+    }
+
+    #[test]
+    fn every_setting_is_categorized() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let value = serde_json::to_value(&settings).unwrap();
+        let fields: Vec<_> = RELOADABLE.iter().chain(RESTART_REQUIRED).collect();
+
+        for (key, field) in value.as_object().unwrap() {
+            let nested = match key.as_str() {
+                "listen" | "moby_runtime" => field.as_object().unwrap().keys().cloned().collect(),
+                _ => vec![],
+            };
+            if nested.is_empty() {
+                assert!(fields.contains(&&key.as_str()), "{}", key);
+            }
+            for nested in nested {
+                let name = format!("{}.{}", key, nested);
+                assert!(fields.contains(&&name.as_str()), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_reloadable_keeps_restart_required_fields() {
+        let mut settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut changed = settings.clone();
+        changed.listen.workload_limits.max_connections = 10;
+        changed.hostname = "other".to_string();
+
+        settings.apply_reloadable(&changed);
+        assert_eq!(Some(10), settings.listen().workload_limits().max_connections());
+        assert_ne!("other", settings.hostname());
+
+        let diff = settings.diff(&changed);
+        assert!(diff.reloadable().is_empty());
+        assert_eq!(&["hostname"], diff.restart_required());
     }
 }
@@ -1,12 +1,19 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use app;
+use app::{self, Command};
 use error::Error;
 use signal;
 
 pub fn run() -> Result<(), Error> {
-    let settings = app::init()?;
+    let (settings, command) = app::init()?;
     let main = super::Main::new(settings);
+    let main = match command {
+        Command::Purge { prune_images } => return main.purge(prune_images),
+        Command::Run {
+            config_file: Some(config_file),
+        } => main.with_config_file(config_file),
+        Command::Run { config_file: None } => main,
+    };
 
     let shutdown_signal = signal::shutdown();
     main.run_until(shutdown_signal)?;
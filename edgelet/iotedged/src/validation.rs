@@ -0,0 +1,1262 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Checks of the settings that go beyond what parsing them already ensures.
+//!
+//! `validate` looks at every setting and reports all of the problems it
+//! finds, so they can be fixed in one go instead of one restart at a time.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::net::IpAddr;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use base64;
+use openssl::asn1::Asn1Time;
+use openssl::x509::X509;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+#[cfg(unix)]
+use url::Host;
+use url::Url;
+
+use edgelet_docker::DEFAULT_ENGINE;
+use edgelet_http::{NoProxy, ServerTlsConfig, TlsPolicy, TlsVersion};
+use provisioning::RegistrationIdSource;
+
+use connection_string;
+use settings::{ListenTls, Provisioning, Settings, DEFAULT_CONNECTION_STRING};
+
+/// The longest timeout, in seconds, any of the settings may have
+const MAX_TIMEOUT_SECS: u64 = 3600;
+
+/// The shortest operation timeout, in seconds. Creating a container from a
+/// large image or stopping a busy one routinely takes a few seconds.
+const MIN_OPERATION_TIMEOUT_SECS: u64 = 10;
+
+/// The shortest and longest time, in seconds, SAS tokens may be valid for
+const MIN_SAS_TOKEN_TTL_SECS: u64 = 5 * 60;
+const MAX_SAS_TOKEN_TTL_SECS: u64 = 90 * 24 * 3600;
+
+/// The quick start device CA is valid for 90 days, so it has to be used for a
+/// while before it is due to be replaced.
+const MAX_QUICKSTART_CA_RENEW_BEFORE_SECS: u64 = 60 * 24 * 3600;
+
+const MAX_HOSTNAME_LEN: usize = 255;
+const MAX_LABEL_LEN: usize = 63;
+
+#[cfg(unix)]
+const CONNECT_SCHEMES: &[&str] = &["http", "https", "unix"];
+#[cfg(windows)]
+const CONNECT_SCHEMES: &[&str] = &["http", "https", "npipe"];
+
+#[cfg(unix)]
+const LISTEN_SCHEMES: &[&str] = &["http", "https", "tcp", "unix", "fd"];
+#[cfg(windows)]
+const LISTEN_SCHEMES: &[&str] = &["http", "https", "tcp"];
+
+#[cfg(unix)]
+const MOBY_RUNTIME_SCHEMES: &[&str] = &["http", "https", "tcp", "unix"];
+#[cfg(windows)]
+const MOBY_RUNTIME_SCHEMES: &[&str] = &["http", "https", "tcp", "npipe"];
+
+const DPS_SCHEMES: &[&str] = &["https"];
+
+const PROXY_SCHEMES: &[&str] = &["http", "https"];
+
+/// A setting that cannot be used, named after its key in the config file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvalidSetting {
+    field: &'static str,
+    reason: String,
+}
+
+impl InvalidSetting {
+    pub fn field(&self) -> &str {
+        self.field
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.reason)
+    }
+}
+
+/// Every problem `validate` found, one per line when displayed.
+#[derive(Debug, Fail, PartialEq)]
+pub struct InvalidSettings(Vec<InvalidSetting>);
+
+impl InvalidSettings {
+    pub fn settings(&self) -> &[InvalidSetting] {
+        &self.0
+    }
+}
+
+impl fmt::Display for InvalidSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} invalid setting(s):", self.0.len())?;
+        for setting in &self.0 {
+            write!(f, "\n\t{}", setting)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks `settings` without connecting to anything. Files the settings
+/// refer to must exist and be readable.
+pub fn validate<T>(settings: &Settings<T>) -> Result<(), InvalidSettings>
+where
+    T: DeserializeOwned + Serialize,
+{
+    let mut problems = Problems::default();
+
+    match settings.provisioning() {
+        Provisioning::Dps(ref dps) => {
+            problems.check_url(
+                "provisioning.global_endpoint",
+                dps.global_endpoint(),
+                DPS_SCHEMES,
+            );
+            match *dps.registration_id_source() {
+                RegistrationIdSource::Explicit => problems.add(
+                    "provisioning.registration_id",
+                    dps.registration_id().map_or_else(
+                        || Err("is required unless registration_id_source is set".to_string()),
+                        check_not_empty,
+                    ),
+                ),
+                RegistrationIdSource::Hostname => (),
+                RegistrationIdSource::File(ref path) => {
+                    problems.add("provisioning.registration_id_source", check_file(path))
+                }
+            }
+            if let Some(group_key) = dps.group_key() {
+                problems.add(
+                    "provisioning.group_key",
+                    match base64::decode(group_key) {
+                        Ok(ref key) if key.is_empty() => Err("is empty".to_string()),
+                        Ok(_) => Ok(()),
+                        Err(err) => Err(format!("is not base64: {}", err)),
+                    },
+                );
+            }
+        }
+        Provisioning::Manual(ref manual) => {
+            if manual.device_connection_string_file().is_some()
+                && (manual.x509().is_some() || manual.has_inline_connection_string())
+            {
+                problems.add(
+                    "provisioning.device_connection_string_file",
+                    Err(format!(
+                        "cannot be used together with {}",
+                        if manual.x509().is_some() {
+                            "x509"
+                        } else {
+                            "device_connection_string"
+                        }
+                    )),
+                );
+            }
+            if let Some(x509) = manual.x509() {
+                if manual.has_inline_connection_string() {
+                    problems.add(
+                        "provisioning.device_connection_string",
+                        Err("cannot be used together with x509".to_string()),
+                    );
+                }
+                problems.add(
+                    "provisioning.x509.iothub_hostname",
+                    check_not_empty(x509.iothub_hostname()),
+                );
+                problems.add("provisioning.x509.device_id", check_not_empty(x509.device_id()));
+
+                // The certificate and the private key have to belong together,
+                // which is only worth checking once both can be read.
+                let cert = check_file(x509.identity_cert());
+                let pk = check_file(x509.identity_pk());
+                let readable = cert.is_ok() && pk.is_ok();
+                problems.add("provisioning.x509.identity_cert", cert);
+                problems.add("provisioning.x509.identity_pk", pk);
+                if readable {
+                    problems.add(
+                        "provisioning.x509",
+                        x509.tls_config().check().map_err(|err| err.to_string()),
+                    );
+                }
+            } else if manual.device_connection_string() != DEFAULT_CONNECTION_STRING {
+                let field = if !manual.has_inline_connection_string()
+                    && manual.device_connection_string_file().is_some()
+                {
+                    "provisioning.device_connection_string_file"
+                } else {
+                    "provisioning.device_connection_string"
+                };
+                problems.add(
+                    field,
+                    connection_string::check(manual.device_connection_string())
+                        .map_err(|err| err.to_string()),
+                );
+            }
+        }
+    }
+
+    problems.add("hostname", check_hostname(settings.hostname()));
+
+    let connect = settings.connect();
+    problems.check_url("connect.workload_uri", connect.workload_uri(), CONNECT_SCHEMES);
+    problems.check_url("connect.management_uri", connect.management_uri(), CONNECT_SCHEMES);
+
+    let listen = settings.listen();
+    problems.check_url("listen.workload_uri", listen.workload_uri(), LISTEN_SCHEMES);
+    problems.check_url("listen.management_uri", listen.management_uri(), LISTEN_SCHEMES);
+    #[cfg(unix)]
+    problems.add("listen.workload_uri", check_not_plain_text(listen.workload_uri()));
+    problems.check_listen_tls(
+        "listen.workload_tls",
+        listen.workload_uri(),
+        listen.workload_tls(),
+    );
+    problems.check_listen_tls(
+        "listen.management_tls",
+        listen.management_uri(),
+        listen.management_tls(),
+    );
+    problems.check_timeout("listen.drain_timeout_secs", Some(listen.drain_timeout()));
+    for &(name, limits) in &[
+        ("listen.workload_limits", listen.workload_limits()),
+        ("listen.management_limits", listen.management_limits()),
+    ] {
+        problems.check_timeout(name, limits.header_read_timeout());
+        problems.check_timeout(name, limits.body_read_timeout());
+    }
+
+    let moby_runtime = settings.moby_runtime();
+    problems.check_url("moby_runtime.uri", moby_runtime.uri(), MOBY_RUNTIME_SCHEMES);
+    problems.add("moby_runtime.network", check_network(moby_runtime.network()));
+    let tls = moby_runtime.tls_config();
+    for path in tls.ca_cert().into_iter().chain(tls.client_cert()).chain(tls.client_key()) {
+        problems.add("moby_runtime.tls", check_file(path));
+    }
+    for extra_host in moby_runtime.default_extra_hosts() {
+        problems.add("moby_runtime.default_extra_hosts", check_extra_host(extra_host));
+    }
+    for (name, engine) in moby_runtime.engines() {
+        problems.add("moby_runtime.engines", check_engine_name(name));
+        problems.check_url("moby_runtime.engines", engine.uri(), MOBY_RUNTIME_SCHEMES);
+        let tls = engine.tls_config();
+        for path in tls.ca_cert().into_iter().chain(tls.client_cert()).chain(tls.client_key()) {
+            problems.add("moby_runtime.engines", check_file(path));
+        }
+    }
+
+    if let Some(certificates) = settings.certificates() {
+        problems.add("certificates.device_ca_cert", check_file(certificates.device_ca_cert()));
+        problems.add("certificates.device_ca_pk", check_file(certificates.device_ca_pk()));
+        problems.add(
+            "certificates.trusted_ca_certs",
+            check_file(certificates.trusted_ca_certs()),
+        );
+    }
+    if let Some(dir) = settings.additional_trusted_ca_dir() {
+        problems.add("additional_trusted_ca_dir", check_dir(dir));
+    }
+    if let Some(path) = settings.manifest_trust_bundle() {
+        problems.add("manifest_trust_bundle", check_trust_bundle(path));
+    }
+    problems.add("homedir", check_writable_dir(settings.homedir()));
+    if let Some(dir) = settings.state_layout().previous_homedir() {
+        problems.add("state_layout.previous_homedir", check_dir(dir));
+    }
+
+    let timeouts = settings.timeouts();
+    if timeouts.connect() == Duration::from_secs(0) {
+        problems.add(
+            "timeouts.connect_secs",
+            Err("must be at least 1 second".to_string()),
+        );
+    }
+    problems.check_timeout("timeouts.connect_secs", Some(timeouts.connect()));
+    problems.check_timeout(
+        "timeouts.moby_runtime_request_secs",
+        timeouts.moby_runtime_request(),
+    );
+    if let Some(timeout) = timeouts
+        .moby_runtime_operation()
+        .filter(|timeout| timeout.as_secs() < MIN_OPERATION_TIMEOUT_SECS)
+    {
+        problems.add(
+            "timeouts.moby_runtime_operation_secs",
+            Err(format!(
+                "{} seconds is shorter than the minimum of {} seconds",
+                timeout.as_secs(),
+                MIN_OPERATION_TIMEOUT_SECS
+            )),
+        );
+    }
+    problems.check_timeout(
+        "timeouts.moby_runtime_operation_secs",
+        timeouts.moby_runtime_operation(),
+    );
+    problems.check_timeout("timeouts.dps_request_secs", timeouts.dps_request());
+    problems.check_timeout("timeouts.iothub_request_secs", timeouts.iothub_request());
+
+    let watchdog = settings.watchdog();
+    if watchdog.restart_base_delay() == Duration::from_secs(0) {
+        problems.add(
+            "watchdog.restart_base_delay_secs",
+            Err("must be at least 1 second".to_string()),
+        );
+    }
+    if watchdog.restart_max_delay() < watchdog.restart_base_delay() {
+        problems.add(
+            "watchdog.restart_max_delay_secs",
+            Err(format!(
+                "must be at least restart_base_delay_secs ({} seconds)",
+                watchdog.restart_base_delay().as_secs()
+            )),
+        );
+    }
+    if watchdog.unhealthy_checks() == 0 {
+        problems.add(
+            "watchdog.unhealthy_checks",
+            Err("must be at least 1".to_string()),
+        );
+    }
+
+    let sas_tokens = settings.sas_tokens();
+    let ttl = sas_tokens.ttl().as_secs();
+    if ttl < MIN_SAS_TOKEN_TTL_SECS || ttl > MAX_SAS_TOKEN_TTL_SECS {
+        problems.add(
+            "sas_tokens.ttl_secs",
+            Err(format!(
+                "{} seconds is not between {} seconds (5 minutes) and {} seconds (90 days)",
+                ttl, MIN_SAS_TOKEN_TTL_SECS, MAX_SAS_TOKEN_TTL_SECS
+            )),
+        );
+    }
+    if sas_tokens.renew_before() >= sas_tokens.ttl() {
+        problems.add(
+            "sas_tokens.renew_before_secs",
+            Err(format!("must be less than ttl_secs ({} seconds)", ttl)),
+        );
+    }
+
+    if let Some(renew_before) = settings.quickstart_ca().renew_before() {
+        if renew_before.as_secs() > MAX_QUICKSTART_CA_RENEW_BEFORE_SECS {
+            problems.add(
+                "quickstart_ca.renew_before_secs",
+                Err(format!(
+                    "{} seconds is more than {} seconds (60 days)",
+                    renew_before.as_secs(),
+                    MAX_QUICKSTART_CA_RENEW_BEFORE_SECS
+                )),
+            );
+        }
+    }
+
+    let proxy = settings.proxy();
+    if let Some(uri) = proxy.https_proxy() {
+        problems.check_url("proxy.https_proxy", uri, PROXY_SCHEMES);
+    }
+    if let Some(no_proxy) = proxy.no_proxy() {
+        problems.add(
+            "proxy.no_proxy",
+            no_proxy
+                .parse::<NoProxy>()
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+        );
+    }
+
+    match settings.min_tls_version().parse::<TlsVersion>() {
+        Ok(min_version) => problems.add(
+            "tls_cipher_suites",
+            TlsPolicy::new()
+                .with_min_version(min_version)
+                .with_cipher_suites(settings.tls_cipher_suites().to_vec())
+                .check()
+                .map_err(|err| err.to_string()),
+        ),
+        Err(err) => problems.add("min_tls_version", Err(err.to_string())),
+    }
+
+    if problems.0.is_empty() {
+        Ok(())
+    } else {
+        Err(InvalidSettings(problems.0))
+    }
+}
+
+#[derive(Default)]
+struct Problems(Vec<InvalidSetting>);
+
+impl Problems {
+    fn add(&mut self, field: &'static str, result: Result<(), String>) {
+        if let Err(reason) = result {
+            self.0.push(InvalidSetting { field, reason });
+        }
+    }
+
+    fn check_url(&mut self, field: &'static str, url: &Url, schemes: &[&str]) {
+        if !schemes.contains(&url.scheme()) {
+            self.add(
+                field,
+                Err(format!(
+                    "{} uses scheme {:?}, expected one of {}",
+                    url,
+                    url.scheme(),
+                    schemes.join(", ")
+                )),
+            );
+        }
+    }
+
+    fn check_listen_tls(&mut self, field: &'static str, url: &Url, tls: &ListenTls) {
+        if url.scheme() != "https" {
+            if *tls != ListenTls::default() {
+                self.add(field, Err(format!("is only used for https listeners, not {}", url)));
+            }
+            return;
+        }
+
+        match (tls.server_cert(), tls.server_key()) {
+            (Some(cert), Some(key)) => {
+                // The certificate and the private key have to belong
+                // together, which is only worth checking once both can be
+                // read.
+                let cert_result = check_file(cert);
+                let key_result = check_file(key);
+                let readable = cert_result.is_ok() && key_result.is_ok();
+                self.add(field, cert_result);
+                self.add(field, key_result);
+                if readable {
+                    self.add(
+                        field,
+                        ServerTlsConfig::from_files(cert, key)
+                            .map(|_| ())
+                            .map_err(|err| err.to_string()),
+                    );
+                }
+            }
+            (None, None) => (),
+            _ => self.add(
+                field,
+                Err("server_cert and server_key must be given together".to_string()),
+            ),
+        }
+        if let Some(client_ca_cert) = tls.client_ca_cert() {
+            self.add(field, check_file(client_ca_cert));
+        }
+    }
+
+    fn check_timeout(&mut self, field: &'static str, timeout: Option<Duration>) {
+        if let Some(timeout) = timeout.filter(|timeout| timeout.as_secs() > MAX_TIMEOUT_SECS) {
+            self.add(
+                field,
+                Err(format!(
+                    "{} seconds is longer than the maximum of {} seconds",
+                    timeout.as_secs(),
+                    MAX_TIMEOUT_SECS
+                )),
+            );
+        }
+    }
+}
+
+/// Modules reach a workload API served over TCP through the network, where
+/// anyone on it could call the API in their name unless TLS keeps them out.
+/// Only a loopback address keeps plain text traffic on the device. Windows
+/// containers can only reach iotedged through the address of their network's
+/// gateway, so this is not enforced there.
+#[cfg(unix)]
+fn check_not_plain_text(url: &Url) -> Result<(), String> {
+    if url.scheme() != "http" && url.scheme() != "tcp" {
+        return Ok(());
+    }
+    let loopback = match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+    if loopback {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} would serve the workload API in plain text on an address other than loopback; \
+             use https",
+            url
+        ))
+    }
+}
+
+/// Hostnames follow RFC 1035, and must be lowercase since certificates are
+/// issued for them.
+fn check_hostname(hostname: &str) -> Result<(), String> {
+    if hostname.is_empty() || hostname.len() > MAX_HOSTNAME_LEN {
+        return Err(format!(
+            "{:?} must be between 1 and {} characters long",
+            hostname, MAX_HOSTNAME_LEN
+        ));
+    }
+    if hostname.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(format!("{:?} must be lowercase", hostname));
+    }
+    for label in hostname.split('.') {
+        let valid = label.len() <= MAX_LABEL_LEN
+            && label.starts_with(|c: char| c.is_ascii_lowercase())
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(format!(
+                "{:?} is not a valid hostname: each label must start with a letter, \
+                 contain only letters, digits and hyphens, not end with a hyphen and \
+                 be at most {} characters long",
+                hostname, MAX_LABEL_LEN
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Docker network names start with a letter or digit, followed by letters,
+/// digits, underscores, periods or hyphens.
+fn check_network(network: &str) -> Result<(), String> {
+    let valid = network.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && network
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a valid docker network name", network))
+    }
+}
+
+/// Modules name engines in their specs, so the names are kept to what is
+/// easy to type there, and the primary engine's own name is taken.
+fn check_engine_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if name == DEFAULT_ENGINE {
+        Err(format!("{:?} is the name of the engine at moby_runtime.uri", name))
+    } else if valid {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a valid engine name", name))
+    }
+}
+
+/// Extra hosts take the form `host:ip`, as in `docker run --add-host`. The
+/// address may be IPv6, so only the first colon separates the two.
+fn check_extra_host(extra_host: &str) -> Result<(), String> {
+    let mut parts = extra_host.splitn(2, ':');
+    let host = parts.next().unwrap_or("");
+    let valid = match parts.next() {
+        Some(ip) => !host.trim().is_empty() && ip.parse::<IpAddr>().is_ok(),
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not of the form host:ip", extra_host))
+    }
+}
+
+fn check_not_empty(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err("must not be empty".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn check_file(path: &Path) -> Result<(), String> {
+    File::open(path)
+        .and_then(|file| file.metadata())
+        .map_err(|err| format!("cannot read {}: {}", path.display(), err))
+        .and_then(|metadata| {
+            if metadata.is_file() {
+                Ok(())
+            } else {
+                Err(format!("{} is not a file", path.display()))
+            }
+        })
+}
+
+fn check_dir(path: &Path) -> Result<(), String> {
+    fs::read_dir(path)
+        .map(|_| ())
+        .map_err(|err| format!("cannot read directory {}: {}", path.display(), err))
+}
+
+/// edgeAgent would reject every signed deployment manifest if the
+/// certificates it verifies them with could not be read or had expired.
+fn check_trust_bundle(path: &Path) -> Result<(), String> {
+    let pem = fs::read(path).map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+    let certs = X509::stack_from_pem(&pem)
+        .map_err(|err| format!("cannot parse {}: {}", path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("{} has no certificates", path.display()));
+    }
+    let now = Asn1Time::days_from_now(0).map_err(|err| err.to_string())?;
+    match certs.iter().find(|cert| cert.not_after() <= now) {
+        Some(cert) => Err(format!(
+            "a certificate in {} expired on {}",
+            path.display(),
+            cert.not_after()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The directory need not exist yet, as long as it can be created: then its
+/// nearest ancestor that exists is the one written to.
+fn check_writable_dir(path: &Path) -> Result<(), String> {
+    let mut existing = path;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => return Err(format!("{} has no ancestor that exists", path.display())),
+        }
+    }
+    if !existing.is_dir() {
+        return Err(format!("{} is not a directory", existing.display()));
+    }
+
+    let probe = existing.join(format!(".iotedged-write-test-{}", process::id()));
+    File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| format!("cannot write to {}: {}", existing.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use edgelet_docker::DockerConfig;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+    use serde_json::{self, Value};
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[cfg(unix)]
+    static GOOD_SETTINGS: &str = "test/linux/sample_settings.yaml";
+    #[cfg(windows)]
+    static GOOD_SETTINGS: &str = "test/windows/sample_settings.yaml";
+
+    /// Reads the sample settings with the values at the given JSON pointers
+    /// replaced.
+    fn settings_with(changes: &[(&str, Value)]) -> Settings<DockerConfig> {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut value = serde_json::to_value(&settings).unwrap();
+        for &(pointer, ref new) in changes {
+            *value.pointer_mut(pointer).unwrap() = new.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Writes a self-signed certificate and its private key to `dir`,
+    /// returning their paths.
+    fn write_identity(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let (cert, key) = self_signed(name, &Asn1Time::days_from_now(1).unwrap());
+        let cert_path = dir.join(format!("{}.cert.pem", name));
+        let key_path = dir.join(format!("{}.key.pem", name));
+        fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+        fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn self_signed(name: &str, not_after: &Asn1Time) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_issuer_name(&subject).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::from_unix(0).unwrap())
+            .unwrap();
+        builder.set_not_after(not_after).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    fn x509_provisioning(cert: &Path, key: &Path) -> Value {
+        json!({
+            "source": "manual",
+            "x509": {
+                "iothub_hostname": "something.azure-devices.net",
+                "device_id": "something",
+                "identity_cert": cert,
+                "identity_pk": key,
+            },
+        })
+    }
+
+    fn invalid_fields(settings: &Settings<DockerConfig>) -> Vec<String> {
+        match validate(settings) {
+            Ok(()) => vec![],
+            Err(err) => err
+                .settings()
+                .iter()
+                .map(|setting| setting.field().to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn sample_settings_are_valid() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        assert_eq!(Ok(()), validate(&settings));
+    }
+
+    #[test]
+    fn broken_settings_are_reported() {
+        let cases = vec![
+            ("/hostname", json!("Edge-Device"), "hostname"),
+            ("/hostname", json!("-edge"), "hostname"),
+            ("/hostname", json!("edge-"), "hostname"),
+            ("/hostname", json!("1edge"), "hostname"),
+            ("/hostname", json!("edge..device"), "hostname"),
+            ("/hostname", json!("edge_device"), "hostname"),
+            ("/hostname", json!("a".repeat(64)), "hostname"),
+            ("/hostname", json!(""), "hostname"),
+            (
+                "/connect/workload_uri",
+                json!("ftp://localhost:8081"),
+                "connect.workload_uri",
+            ),
+            (
+                "/connect/management_uri",
+                json!("tcp://localhost:8080"),
+                "connect.management_uri",
+            ),
+            (
+                "/listen/workload_uri",
+                json!("wss://0.0.0.0:8081"),
+                "listen.workload_uri",
+            ),
+            (
+                "/listen/management_uri",
+                json!("ftp://0.0.0.0:8080"),
+                "listen.management_uri",
+            ),
+            (
+                "/listen/drain_timeout_secs",
+                json!(MAX_TIMEOUT_SECS + 1),
+                "listen.drain_timeout_secs",
+            ),
+            (
+                "/listen/workload_limits",
+                json!({"max_connections": 10, "header_read_timeout_secs": 86400}),
+                "listen.workload_limits",
+            ),
+            (
+                "/listen/management_limits",
+                json!({"max_connections": 10, "body_read_timeout_secs": 86400}),
+                "listen.management_limits",
+            ),
+            (
+                "/moby_runtime/uri",
+                json!("ftp://localhost:2375"),
+                "moby_runtime.uri",
+            ),
+            (
+                "/moby_runtime/network",
+                json!("azure iot edge"),
+                "moby_runtime.network",
+            ),
+            (
+                "/moby_runtime/network",
+                json!("-azure-iot-edge"),
+                "moby_runtime.network",
+            ),
+            (
+                "/moby_runtime/tls",
+                json!({"ca_cert": "/does/not/exist/ca.pem"}),
+                "moby_runtime.tls",
+            ),
+            (
+                "/moby_runtime/default_extra_hosts",
+                json!(["gateway.local"]),
+                "moby_runtime.default_extra_hosts",
+            ),
+            (
+                "/moby_runtime/default_extra_hosts",
+                json!(["gateway.local:10.0.0.256"]),
+                "moby_runtime.default_extra_hosts",
+            ),
+            (
+                "/moby_runtime/default_extra_hosts",
+                json!([":10.0.0.1"]),
+                "moby_runtime.default_extra_hosts",
+            ),
+            (
+                "/additional_trusted_ca_dir",
+                json!("/does/not/exist"),
+                "additional_trusted_ca_dir",
+            ),
+            (
+                "/timeouts",
+                json!({"connect_secs": 0}),
+                "timeouts.connect_secs",
+            ),
+            (
+                "/timeouts",
+                json!({"iothub_request_secs": 86400}),
+                "timeouts.iothub_request_secs",
+            ),
+            (
+                "/timeouts",
+                json!({"moby_runtime_operation_secs": 5}),
+                "timeouts.moby_runtime_operation_secs",
+            ),
+            (
+                "/timeouts",
+                json!({"moby_runtime_operation_secs": 86400}),
+                "timeouts.moby_runtime_operation_secs",
+            ),
+            (
+                "/watchdog",
+                json!({"restart_base_delay_secs": 0}),
+                "watchdog.restart_base_delay_secs",
+            ),
+            (
+                "/watchdog",
+                json!({"restart_base_delay_secs": 60, "restart_max_delay_secs": 30}),
+                "watchdog.restart_max_delay_secs",
+            ),
+            (
+                "/watchdog",
+                json!({"restart_on_unhealthy": true, "unhealthy_checks": 0}),
+                "watchdog.unhealthy_checks",
+            ),
+            (
+                "/sas_tokens",
+                json!({"ttl_secs": 299, "renew_before_secs": 60}),
+                "sas_tokens.ttl_secs",
+            ),
+            (
+                "/sas_tokens",
+                json!({"ttl_secs": 90 * 24 * 3600 + 1}),
+                "sas_tokens.ttl_secs",
+            ),
+            (
+                "/sas_tokens",
+                json!({"ttl_secs": 600, "renew_before_secs": 600}),
+                "sas_tokens.renew_before_secs",
+            ),
+            (
+                "/quickstart_ca",
+                json!({"renew_before_secs": 60 * 24 * 3600 + 1}),
+                "quickstart_ca.renew_before_secs",
+            ),
+            (
+                "/proxy",
+                json!({"https_proxy": "socks5://proxy:1080"}),
+                "proxy.https_proxy",
+            ),
+            (
+                "/proxy",
+                json!({"no_proxy": "localhost,10.0.0.0/33"}),
+                "proxy.no_proxy",
+            ),
+            ("/min_tls_version", json!("1.4"), "min_tls_version"),
+            ("/min_tls_version", json!("TLSv1.2"), "min_tls_version"),
+            (
+                "/tls_cipher_suites",
+                json!(["ECDHE-RSA-AES256-GCM-SHA384", "NOT-A-CIPHER"]),
+                "tls_cipher_suites",
+            ),
+            (
+                "/provisioning",
+                json!({
+                    "source": "dps",
+                    "global_endpoint": "http://global.azure-devices-provisioning.net",
+                    "scope_id": "scope",
+                    "registration_id": "device",
+                }),
+                "provisioning.global_endpoint",
+            ),
+            (
+                "/provisioning",
+                json!({
+                    "source": "dps",
+                    "global_endpoint": "https://global.azure-devices-provisioning.net",
+                    "scope_id": "scope",
+                }),
+                "provisioning.registration_id",
+            ),
+            (
+                "/provisioning",
+                json!({
+                    "source": "dps",
+                    "global_endpoint": "https://global.azure-devices-provisioning.net",
+                    "scope_id": "scope",
+                    "registration_id_source": "hostname",
+                    "group_key": "not base64!",
+                }),
+                "provisioning.group_key",
+            ),
+            (
+                "/provisioning",
+                json!({
+                    "source": "dps",
+                    "global_endpoint": "https://global.azure-devices-provisioning.net",
+                    "scope_id": "scope",
+                    "registration_id_source": "file:/does/not/exist/serial",
+                }),
+                "provisioning.registration_id_source",
+            ),
+        ];
+
+        for (pointer, value, field) in cases {
+            let settings = settings_with(&[(pointer, value.clone())]);
+            assert_eq!(vec![field], invalid_fields(&settings), "{} = {}", pointer, value);
+        }
+    }
+
+    #[test]
+    fn engines_are_checked() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut value = serde_json::to_value(&settings).unwrap();
+        value["moby_runtime"]["engines"] = json!({
+            "sandbox": {"uri": "unix:///var/run/sandbox.sock"},
+        });
+        let settings: Settings<DockerConfig> = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(Ok(()), validate(&settings));
+
+        for engines in &[
+            json!({"default": {"uri": "unix:///var/run/sandbox.sock"}}),
+            json!({"Sandbox": {"uri": "unix:///var/run/sandbox.sock"}}),
+            json!({"sandbox": {"uri": "ftp://localhost:2375"}}),
+            json!({"sandbox": {
+                "uri": "tcp://localhost:2376",
+                "tls": {"ca_cert": "/does/not/exist/ca.pem"},
+            }}),
+        ] {
+            value["moby_runtime"]["engines"] = engines.clone();
+            let settings: Settings<DockerConfig> = serde_json::from_value(value.clone()).unwrap();
+            assert_eq!(vec!["moby_runtime.engines"], invalid_fields(&settings), "{}", engines);
+        }
+    }
+
+    #[test]
+    fn host_defaults_are_valid() {
+        let settings = settings_with(&[
+            ("/moby_runtime/default_dns", json!(["10.0.0.53", "fd00::53"])),
+            (
+                "/moby_runtime/default_extra_hosts",
+                json!(["gateway.local:10.0.0.1", "gateway6.local:fd00::1"]),
+            ),
+        ]);
+        assert_eq!(Ok(()), validate(&settings));
+        assert_eq!(2, settings.moby_runtime().default_dns().len());
+    }
+
+    #[test]
+    fn invalid_dns_server_fails_to_load() {
+        let settings = Settings::<DockerConfig>::new(Some(GOOD_SETTINGS)).unwrap();
+        let mut value = serde_json::to_value(&settings).unwrap();
+        *value.pointer_mut("/moby_runtime/default_dns").unwrap() = json!(["10.0.0.256"]);
+        assert!(serde_json::from_value::<Settings<DockerConfig>>(value).is_err());
+    }
+
+    #[test]
+    fn all_problems_are_reported_at_once() {
+        let settings = settings_with(&[
+            ("/hostname", json!("Edge")),
+            ("/moby_runtime/network", json!("bad network")),
+            (
+                "/certificates",
+                json!({
+                    "device_ca_cert": "/does/not/exist/device_ca_cert.pem",
+                    "device_ca_pk": "/does/not/exist/device_ca_pk.pem",
+                    "trusted_ca_certs": "/does/not/exist/trusted_ca_certs.pem",
+                }),
+            ),
+        ]);
+
+        assert_eq!(
+            vec![
+                "hostname",
+                "moby_runtime.network",
+                "certificates.device_ca_cert",
+                "certificates.device_ca_pk",
+                "certificates.trusted_ca_certs",
+            ],
+            invalid_fields(&settings)
+        );
+
+        let message = validate(&settings).unwrap_err().to_string();
+        assert!(message.starts_with("5 invalid setting(s):"), "{}", message);
+        assert!(message.contains("\n\thostname: \"Edge\" must be lowercase"));
+    }
+
+    #[test]
+    fn x509_identity_is_valid() {
+        let dir = TempDir::new("x509").unwrap();
+        let (cert, key) = write_identity(dir.path(), "device");
+
+        let settings = settings_with(&[("/provisioning", x509_provisioning(&cert, &key))]);
+        assert_eq!(Ok(()), validate(&settings));
+    }
+
+    #[test]
+    fn x509_identity_with_mismatched_key_is_reported() {
+        let dir = TempDir::new("x509").unwrap();
+        let (cert, _) = write_identity(dir.path(), "device");
+        let (_, other_key) = write_identity(dir.path(), "other");
+
+        let settings = settings_with(&[("/provisioning", x509_provisioning(&cert, &other_key))]);
+        assert_eq!(vec!["provisioning.x509"], invalid_fields(&settings));
+
+        let message = validate(&settings).unwrap_err().to_string();
+        assert!(message.contains("does not match private key"), "{}", message);
+    }
+
+    #[test]
+    fn x509_identity_problems_are_reported() {
+        let dir = TempDir::new("x509").unwrap();
+        let (cert, key) = write_identity(dir.path(), "device");
+
+        let missing = Path::new("/does/not/exist/identity.pem");
+        let settings = settings_with(&[("/provisioning", x509_provisioning(missing, missing))]);
+        assert_eq!(
+            vec![
+                "provisioning.x509.identity_cert",
+                "provisioning.x509.identity_pk",
+            ],
+            invalid_fields(&settings)
+        );
+
+        let mut provisioning = x509_provisioning(&cert, &key);
+        provisioning["device_connection_string"] =
+            json!("HostName=something.something.com;DeviceId=something;SharedAccessKey=something");
+        provisioning["x509"]["device_id"] = json!("");
+        let settings = settings_with(&[("/provisioning", provisioning)]);
+        assert_eq!(
+            vec![
+                "provisioning.device_connection_string",
+                "provisioning.x509.device_id",
+            ],
+            invalid_fields(&settings)
+        );
+    }
+
+    #[test]
+    fn https_listeners_are_valid() {
+        let dir = TempDir::new("listen").unwrap();
+        let (cert, key) = write_identity(dir.path(), "workload");
+        let settings = settings_with(&[
+            ("/listen/workload_uri", json!("https://0.0.0.0:15581")),
+            (
+                "/listen/workload_tls",
+                json!({"server_cert": cert, "server_key": key, "client_ca_cert": cert}),
+            ),
+            // Without a certificate of its own, the listener is given one
+            // by the workload CA.
+            ("/listen/management_uri", json!("https://127.0.0.1:15580")),
+        ]);
+        assert_eq!(Ok(()), validate(&settings));
+    }
+
+    #[test]
+    fn listener_tls_problems_are_reported() {
+        let dir = TempDir::new("listen").unwrap();
+        let (cert, _) = write_identity(dir.path(), "workload");
+        let (_, other_key) = write_identity(dir.path(), "other");
+
+        for tls in vec![
+            json!({"server_cert": cert}),
+            json!({"server_key": other_key}),
+            json!({"server_cert": cert, "server_key": other_key}),
+            json!({"server_cert": "/does/not/exist.pem", "server_key": other_key}),
+            json!({"client_ca_cert": "/does/not/exist.pem"}),
+        ] {
+            let settings = settings_with(&[
+                ("/listen/workload_uri", json!("https://127.0.0.1:15581")),
+                ("/listen/workload_tls", tls.clone()),
+            ]);
+            assert_eq!(
+                vec!["listen.workload_tls"],
+                invalid_fields(&settings),
+                "{}",
+                tls
+            );
+        }
+
+        let settings = settings_with(&[("/listen/management_tls", json!({"server_cert": cert}))]);
+        assert_eq!(vec!["listen.management_tls"], invalid_fields(&settings));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plain_text_workload_listeners_must_be_on_loopback() {
+        for uri in &[
+            "http://127.0.0.1:15581",
+            "http://localhost:15581",
+            "tcp://[::1]:15581",
+            "unix:///var/run/iotedge/workload.sock",
+        ] {
+            let settings = settings_with(&[("/listen/workload_uri", json!(uri))]);
+            assert_eq!(Ok(()), validate(&settings), "{}", uri);
+        }
+
+        for uri in &["http://0.0.0.0:15581", "tcp://172.17.0.1:15581", "http://edge:15581"] {
+            let settings = settings_with(&[("/listen/workload_uri", json!(uri))]);
+            assert_eq!(vec!["listen.workload_uri"], invalid_fields(&settings), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn connection_string_file_cannot_be_combined() {
+        let dir = TempDir::new("x509").unwrap();
+        let (cert, key) = write_identity(dir.path(), "device");
+
+        let mut provisioning = x509_provisioning(&cert, &key);
+        provisioning["device_connection_string_file"] = json!("/etc/iotedge/connection_string");
+        let settings = settings_with(&[("/provisioning", provisioning)]);
+        assert_eq!(
+            vec!["provisioning.device_connection_string_file"],
+            invalid_fields(&settings)
+        );
+
+        let settings = settings_with(&[(
+            "/provisioning",
+            json!({
+                "source": "manual",
+                "device_connection_string": "HostName=hub;DeviceId=d1;SharedAccessKey=a2V5",
+                "device_connection_string_file": "/etc/iotedge/connection_string",
+            }),
+        )]);
+        assert_eq!(
+            vec!["provisioning.device_connection_string_file"],
+            invalid_fields(&settings)
+        );
+    }
+
+    #[test]
+    fn malformed_connection_strings_are_reported() {
+        for connection_string in &[
+            "HostName=hub;DeviceId=d1",
+            "HostName=hub;SharedAccessKey=a2V5",
+            "DeviceId=d1;SharedAccessKey=a2V5",
+            "HostName=hub;DeviceId=d1;SharedAccessKey=not base64!",
+        ] {
+            let settings = settings_with(&[(
+                "/provisioning/device_connection_string",
+                json!(connection_string),
+            )]);
+            assert_eq!(
+                vec!["provisioning.device_connection_string"],
+                invalid_fields(&settings),
+                "{}",
+                connection_string
+            );
+        }
+    }
+
+    #[test]
+    fn readable_certificates_are_valid() {
+        let dir = TempDir::new("certificates").unwrap();
+        let cert = dir.path().join("cert.pem");
+        fs::write(&cert, "cert").unwrap();
+
+        let settings = settings_with(&[
+            (
+                "/certificates",
+                json!({
+                    "device_ca_cert": cert,
+                    "device_ca_pk": cert,
+                    "trusted_ca_certs": cert,
+                }),
+            ),
+            ("/additional_trusted_ca_dir", json!(dir.path())),
+        ]);
+        assert_eq!(Ok(()), validate(&settings));
+
+        // A directory is not a certificate.
+        let settings = settings_with(&[(
+            "/certificates",
+            json!({
+                "device_ca_cert": dir.path(),
+                "device_ca_pk": cert,
+                "trusted_ca_certs": cert,
+            }),
+        )]);
+        assert_eq!(vec!["certificates.device_ca_cert"], invalid_fields(&settings));
+    }
+
+    #[test]
+    fn manifest_trust_bundle_must_hold_current_certificates() {
+        let dir = TempDir::new("manifest").unwrap();
+        let (current, _) = self_signed("signer", &Asn1Time::days_from_now(30).unwrap());
+        let (expired, _) = self_signed("old signer", &Asn1Time::from_unix(3600).unwrap());
+        let bundle = dir.path().join("bundle.pem");
+        fs::write(&bundle, current.to_pem().unwrap()).unwrap();
+        let settings = settings_with(&[("/manifest_trust_bundle", json!(bundle))]);
+        assert_eq!(Ok(()), validate(&settings));
+
+        let mut pem = current.to_pem().unwrap();
+        pem.extend(expired.to_pem().unwrap());
+        fs::write(&bundle, pem).unwrap();
+        let err = validate(&settings).unwrap_err();
+        assert_eq!("manifest_trust_bundle", err.settings()[0].field());
+        assert!(err.settings()[0].reason().contains("expired"), "{}", err);
+
+        for contents in &["", "not a certificate"] {
+            fs::write(&bundle, contents).unwrap();
+            assert_eq!(vec!["manifest_trust_bundle"], invalid_fields(&settings));
+        }
+
+        let settings = settings_with(&[(
+            "/manifest_trust_bundle",
+            json!(dir.path().join("missing.pem")),
+        )]);
+        assert_eq!(vec!["manifest_trust_bundle"], invalid_fields(&settings));
+    }
+    #[test]
+    fn homedir_must_be_a_writable_directory() {
+        let dir = TempDir::new("homedir").unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "not a directory").unwrap();
+
+        // One that doesn't exist yet is created under an existing one.
+        let settings = settings_with(&[("/homedir", json!(dir.path().join("iotedge/new")))]);
+        assert_eq!(Ok(()), validate(&settings));
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+        let settings = settings_with(&[("/homedir", json!(file))]);
+        assert_eq!(vec!["homedir"], invalid_fields(&settings));
+        let settings = settings_with(&[("/homedir", json!(file.join("iotedge")))]);
+        assert_eq!(vec!["homedir"], invalid_fields(&settings));
+
+        let settings = settings_with(&[(
+            "/state_layout/previous_homedir",
+            json!(dir.path().join("missing")),
+        )]);
+        assert_eq!(vec!["state_layout.previous_homedir"], invalid_fields(&settings));
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright (c) Microsoft. All rights reserved.
 
-use edgelet_core::{CertificateType, WorkloadConfig};
+use edgelet_core::{CertificateType, PayloadLimits, WorkloadConfig};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -9,6 +10,13 @@ struct WorkloadConfigData {
     device_id: String,
     id_cert_max_duration: i64,
     srv_cert_max_duration: i64,
+    allow_wildcard_sans: bool,
+    additional_trusted_ca_dir: Option<PathBuf>,
+    payload_limits: PayloadLimits,
+    allow_host_processes: bool,
+    leaf_cert_issuers: Vec<String>,
+    device_identity_cert: Option<String>,
+    manifest_trust_bundle: Option<String>,
 }
 
 impl WorkloadConfigData {
@@ -17,12 +25,24 @@ impl WorkloadConfigData {
         device_id: String,
         id_cert_max_duration: i64,
         srv_cert_max_duration: i64,
+        allow_wildcard_sans: bool,
+        additional_trusted_ca_dir: Option<PathBuf>,
+        payload_limits: PayloadLimits,
+        allow_host_processes: bool,
+        leaf_cert_issuers: Vec<String>,
     ) -> Self {
         WorkloadConfigData {
             iot_hub_name,
             device_id,
             id_cert_max_duration,
             srv_cert_max_duration,
+            allow_wildcard_sans,
+            additional_trusted_ca_dir,
+            payload_limits,
+            allow_host_processes,
+            leaf_cert_issuers,
+            device_identity_cert: None,
+            manifest_trust_bundle: None,
         }
     }
 
@@ -41,6 +61,34 @@ impl WorkloadConfigData {
     pub fn server_cert_max(&self) -> i64 {
         self.srv_cert_max_duration
     }
+
+    pub fn allow_wildcard_sans(&self) -> bool {
+        self.allow_wildcard_sans
+    }
+
+    pub fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+        self.additional_trusted_ca_dir.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn payload_limits(&self) -> PayloadLimits {
+        self.payload_limits
+    }
+
+    pub fn allow_host_processes(&self) -> bool {
+        self.allow_host_processes
+    }
+
+    pub fn leaf_cert_issuers(&self) -> &[String] {
+        &self.leaf_cert_issuers
+    }
+
+    pub fn device_identity_cert(&self) -> Option<&str> {
+        self.device_identity_cert.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn manifest_trust_bundle(&self) -> Option<&str> {
+        self.manifest_trust_bundle.as_ref().map(AsRef::as_ref)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -54,15 +102,39 @@ impl WorkloadData {
         device_id: String,
         id_cert_max_duration: i64,
         srv_cert_max_duration: i64,
+        allow_wildcard_sans: bool,
+        additional_trusted_ca_dir: Option<PathBuf>,
+        payload_limits: PayloadLimits,
+        allow_host_processes: bool,
+        leaf_cert_issuers: Vec<String>,
     ) -> Self {
         let w = WorkloadConfigData::new(
             iot_hub_name,
             device_id,
             id_cert_max_duration,
             srv_cert_max_duration,
+            allow_wildcard_sans,
+            additional_trusted_ca_dir,
+            payload_limits,
+            allow_host_processes,
+            leaf_cert_issuers,
         );
         WorkloadData { data: Arc::new(w) }
     }
+
+    /// Sets the PEM of the certificate the device authenticates with, for
+    /// devices provisioned with an X.509 identity.
+    pub fn with_device_identity_cert(mut self, device_identity_cert: String) -> Self {
+        Arc::make_mut(&mut self.data).device_identity_cert = Some(device_identity_cert);
+        self
+    }
+
+    /// Sets the PEM of the certificates edgeAgent verifies signed deployment
+    /// manifests with.
+    pub fn with_manifest_trust_bundle(mut self, manifest_trust_bundle: String) -> Self {
+        Arc::make_mut(&mut self.data).manifest_trust_bundle = Some(manifest_trust_bundle);
+        self
+    }
 }
 
 impl WorkloadConfig for WorkloadData {
@@ -81,4 +153,32 @@ impl WorkloadConfig for WorkloadData {
             _ => 0,
         }
     }
+
+    fn allow_wildcard_sans(&self) -> bool {
+        self.data.allow_wildcard_sans()
+    }
+
+    fn additional_trusted_ca_dir(&self) -> Option<&Path> {
+        self.data.additional_trusted_ca_dir()
+    }
+
+    fn payload_limits(&self) -> PayloadLimits {
+        self.data.payload_limits()
+    }
+
+    fn allow_host_processes(&self) -> bool {
+        self.data.allow_host_processes()
+    }
+
+    fn device_identity_cert(&self) -> Option<&str> {
+        self.data.device_identity_cert()
+    }
+
+    fn leaf_cert_issuers(&self) -> &[String] {
+        self.data.leaf_cert_issuers()
+    }
+
+    fn manifest_trust_bundle(&self) -> Option<&str> {
+        self.data.manifest_trust_bundle()
+    }
 }
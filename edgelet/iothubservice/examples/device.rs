@@ -101,7 +101,7 @@ fn list_modules<C, T>(
     device_client: &DeviceClient<C, T>,
 ) where
     C: 'static + ClientImpl,
-    T: 'static + TokenSource + Clone,
+    T: 'static + TokenSource + Clone + Send,
     T::Error: Into<HttpError>,
 {
     let response = tokio_runtime
@@ -116,7 +116,7 @@ fn get_module<C, T>(
     module_id: &str,
 ) where
     C: 'static + ClientImpl,
-    T: 'static + TokenSource + Clone,
+    T: 'static + TokenSource + Clone + Send,
     T::Error: Into<HttpError>,
 {
     let response = tokio_runtime
@@ -131,7 +131,7 @@ fn create_module<C, T>(
     module_id: &str,
 ) where
     C: 'static + ClientImpl,
-    T: 'static + TokenSource + Clone,
+    T: 'static + TokenSource + Clone + Send,
     T::Error: Into<HttpError>,
 {
     let response = tokio_runtime
@@ -146,7 +146,7 @@ fn delete_module<C, T>(
     module_id: &str,
 ) where
     C: 'static + ClientImpl,
-    T: 'static + TokenSource + Clone,
+    T: 'static + TokenSource + Clone + Send,
     T::Error: Into<HttpError>,
 {
     tokio_runtime
@@ -4,10 +4,10 @@ use futures::future::{self, Either};
 use futures::Future;
 use hyper::{Method, StatusCode};
 
-use edgelet_http::client::{Client, ClientImpl, TokenSource};
+use edgelet_http::client::{Client, ClientImpl, Conditional, TokenSource};
 use edgelet_http::error::{Error as HttpError, ErrorKind as HttpErrorKind};
 use error::{Error, ErrorKind};
-use model::{AuthMechanism, Module};
+use model::{AuthMechanism, Device, Module};
 
 pub struct DeviceClient<C, T>
 where
@@ -35,6 +35,20 @@ where
         self.device_id.as_ref()
     }
 
+    /// The device's own identity in the hub's registry, which a device is
+    /// only allowed to read if its hub grants it access to the registry.
+    pub fn get_device(&self) -> impl Future<Item = Device, Error = Error> {
+        self.client
+            .request::<(), Device>(
+                Method::GET,
+                &format!("/devices/{}", &self.device_id),
+                None,
+                None,
+                false,
+            ).map_err(Error::from)
+            .and_then(|device| device.ok_or_else(|| Error::from(ErrorKind::EmptyResponse)))
+    }
+
     pub fn create_module(
         &self,
         module_id: &str,
@@ -83,8 +97,15 @@ where
                     None,
                     Some(module),
                     add_if_match,
-                ).map_err(Error::from)
-                .and_then(|module| module.ok_or_else(|| Error::from(ErrorKind::ModuleNotFound)));
+                ).map_err(|err| {
+                    if let HttpErrorKind::ServiceError(code, _) = err.kind() {
+                        if *code == StatusCode::CONFLICT {
+                            return Error::from(ErrorKind::ModuleAlreadyExists);
+                        }
+                    }
+
+                    Error::from(err)
+                }).and_then(|module| module.ok_or_else(|| Error::from(ErrorKind::ModuleNotFound)));
 
             Either::A(res)
         }
@@ -128,6 +149,26 @@ where
             .and_then(|modules| modules.ok_or_else(|| Error::from(ErrorKind::EmptyResponse)))
     }
 
+    /// Lists the modules of the device along with the entity tag of the list,
+    /// unless the list is still the one the hub tagged `etag`, in which case
+    /// there is nothing to list.
+    pub fn list_modules_if_none_match(
+        &self,
+        etag: Option<&str>,
+    ) -> impl Future<Item = Option<(Vec<Module>, Option<String>)>, Error = Error> {
+        self.client
+            .request_if_none_match::<Vec<Module>>(
+                &format!("/devices/{}/modules", &self.device_id),
+                None,
+                etag,
+            ).map_err(Error::from)
+            .and_then(|response| match response {
+                Conditional::Modified(Some(modules), etag) => Ok(Some((modules, etag))),
+                Conditional::Modified(None, _) => Err(Error::from(ErrorKind::EmptyResponse)),
+                Conditional::NotModified => Ok(None),
+            })
+    }
+
     pub fn delete_module(&self, module_id: &str) -> impl Future<Item = (), Error = Error> {
         if module_id.trim().is_empty() {
             Either::B(future::err(Error::from(ErrorKind::EmptyModuleId)))
@@ -146,6 +187,38 @@ where
             Either::A(res)
         }
     }
+
+    /// Deletes the module only if it hasn't changed since it had the entity
+    /// tag `etag`.
+    pub fn delete_module_if_match(
+        &self,
+        module_id: &str,
+        etag: &str,
+    ) -> impl Future<Item = (), Error = Error> {
+        if module_id.trim().is_empty() {
+            Either::B(future::err(Error::from(ErrorKind::EmptyModuleId)))
+        } else {
+            let res = self
+                .client
+                .request_if_match::<(), ()>(
+                    Method::DELETE,
+                    &format!("/devices/{}/modules/{}", self.device_id, module_id),
+                    None,
+                    None,
+                    etag,
+                ).map_err(|err| {
+                    if let HttpErrorKind::ServiceError(code, _) = err.kind() {
+                        if *code == StatusCode::PRECONDITION_FAILED {
+                            return Error::from(ErrorKind::ModuleChanged);
+                        }
+                    }
+
+                    Error::from(err)
+                }).and_then(|_| Ok(()));
+
+            Either::A(res)
+        }
+    }
 }
 
 impl<C, T> Clone for DeviceClient<C, T>
@@ -175,7 +248,7 @@ mod tests {
     use url::Url;
 
     use error::ErrorKind;
-    use model::{AuthType, SymmetricKey};
+    use model::{AuthType, DeviceCapabilities, SymmetricKey};
 
     struct NullTokenSource;
 
@@ -490,6 +563,85 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn module_delete_if_match_request() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::DELETE);
+            assert_eq!(req.uri().path(), "/devices/d1/modules/m1");
+            assert_eq!(req.headers().get(hyper::header::IF_MATCH).unwrap(), "\"e1\"");
+
+            Ok(Response::new(Body::empty()))
+        };
+        let client = Client::new(handler, Some(NullTokenSource), api_version, host_name).unwrap();
+
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+        let task = device_client.delete_module_if_match("m1", "\"e1\"");
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+    }
+
+    #[test]
+    fn module_delete_if_match_changed() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |_req: Request<Body>| {
+            let response = Response::builder()
+                .status(StatusCode::PRECONDITION_FAILED)
+                .body(Body::empty())
+                .expect("could not build hyper::Response");
+            Ok(response)
+        };
+        let client = Client::new(handler, Some(NullTokenSource), api_version, host_name).unwrap();
+
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+        let task = device_client.delete_module_if_match("m1", "\"e1\"");
+
+        let err = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap_err();
+        assert_eq!(ErrorKind::ModuleChanged, *err.kind());
+    }
+
+    #[test]
+    fn device_get_request() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::GET);
+            assert_eq!(req.uri().path(), "/devices/d1");
+
+            let body = r#"{"deviceId":"d1","generationId":"g1","capabilities":{"iotEdge":true}}"#;
+            let mut response = Response::new(body.into());
+            response
+                .headers_mut()
+                .typed_insert(&ContentType(mime::APPLICATION_JSON));
+            Ok(response)
+        };
+        let client = Client::new(handler, Some(NullTokenSource), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let device = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(device_client.get_device())
+            .unwrap();
+        assert_eq!(
+            Device::default()
+                .with_device_id("d1".to_string())
+                .with_generation_id("g1".to_string())
+                .with_capabilities(DeviceCapabilities::new().with_iot_edge(true)),
+            device
+        );
+    }
+
     #[test]
     fn modules_list_request() {
         let api_version = "2018-04-10";
@@ -546,6 +698,48 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn modules_list_if_none_match_request() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+        let modules = vec![
+            Module::default()
+                .with_device_id("d1".to_string())
+                .with_module_id("m1".to_string()),
+        ];
+        let expected_modules = modules.clone();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::GET);
+            assert_eq!(req.uri().path(), "/devices/d1/modules");
+
+            let response = match req.headers().get(hyper::header::IF_NONE_MATCH) {
+                Some(etag) if etag == "\"e1\"" => Response::builder()
+                    .status(hyper::StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap(),
+                _ => Response::builder()
+                    .header(hyper::header::ETAG, "\"e1\"")
+                    .body(serde_json::to_string(&modules).unwrap().into())
+                    .unwrap(),
+            };
+            Ok(response)
+        };
+        let client = Client::new(handler, Some(NullTokenSource), api_version, host_name).unwrap();
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let listed = runtime
+            .block_on(device_client.list_modules_if_none_match(None))
+            .unwrap();
+        assert_eq!(Some((expected_modules, Some("\"e1\"".to_string()))), listed);
+
+        let listed = runtime
+            .block_on(device_client.list_modules_if_none_match(Some("\"e1\"")))
+            .unwrap();
+        assert_eq!(None, listed);
+    }
+
     #[test]
     fn modules_get_request() {
         let api_version = "2018-04-10";
@@ -622,4 +816,38 @@ mod tests {
             .block_on(task)
             .unwrap();
     }
+
+    #[test]
+    fn modules_create_conflict() {
+        let api_version = "2018-04-10";
+        let host_name = Url::parse("http://localhost").unwrap();
+
+        let handler = move |req: Request<Body>| {
+            assert_eq!(req.method(), &Method::PUT);
+            assert_eq!(req.uri().path(), "/devices/d1/modules/m1");
+            assert_eq!(None, req.headers().get(hyper::header::IF_MATCH));
+
+            let response = Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body(Body::empty())
+                .expect("could not build hyper::Response");
+            Ok(response)
+        };
+        let client = Client::new(handler, Some(NullTokenSource), api_version, host_name).unwrap();
+
+        let device_client = DeviceClient::new(client, "d1").unwrap();
+        let task = device_client
+            .create_module("m1", None, None)
+            .then(|module| {
+                assert!(module.is_err());
+                assert_eq!(ErrorKind::ModuleAlreadyExists, *module.unwrap_err().kind());
+
+                Ok::<_, Error>(())
+            });
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+    }
 }
@@ -35,6 +35,10 @@ pub enum ErrorKind {
     EmptyResponse,
     #[fail(display = "Module not found")]
     ModuleNotFound,
+    #[fail(display = "Module already exists")]
+    ModuleAlreadyExists,
+    #[fail(display = "Module was changed")]
+    ModuleChanged,
     #[fail(display = "Module ID is empty")]
     EmptyModuleId,
     #[fail(display = "Failed to get sas token")]
@@ -34,4 +34,7 @@ mod model;
 
 pub use device::DeviceClient;
 pub use error::{Error, ErrorKind};
-pub use model::{AuthMechanism, AuthType, Module, Properties, SymmetricKey, Twin, X509Thumbprint};
+pub use model::{
+    AuthMechanism, AuthType, Device, DeviceCapabilities, Module, Properties, SymmetricKey, Twin,
+    X509Thumbprint,
+};
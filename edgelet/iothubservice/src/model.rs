@@ -252,6 +252,8 @@ pub struct Module {
     #[serde(skip_serializing_if = "Option::is_none")]
     generation_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     authentication: Option<AuthMechanism>,
 }
 
@@ -262,6 +264,7 @@ impl Module {
             managed_by: None,
             device_id: None,
             generation_id: None,
+            etag: None,
             authentication: None,
         }
     }
@@ -302,6 +305,16 @@ impl Module {
         self.generation_id.as_ref().map(AsRef::as_ref)
     }
 
+    pub fn with_etag(mut self, etag: String) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    /// Changes whenever the module does.
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_ref().map(AsRef::as_ref)
+    }
+
     pub fn with_authentication(mut self, authentication: AuthMechanism) -> Self {
         self.authentication = Some(authentication);
         self
@@ -317,3 +330,82 @@ impl Default for Module {
         Module::new()
     }
 }
+
+/// A device identity as IoT Hub's registry has it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capabilities: Option<DeviceCapabilities>,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Device {
+            device_id: None,
+            generation_id: None,
+            capabilities: None,
+        }
+    }
+
+    pub fn with_device_id(mut self, device_id: String) -> Self {
+        self.device_id = Some(device_id);
+        self
+    }
+
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_generation_id(mut self, generation_id: String) -> Self {
+        self.generation_id = Some(generation_id);
+        self
+    }
+
+    pub fn generation_id(&self) -> Option<&str> {
+        self.generation_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn with_capabilities(mut self, capabilities: DeviceCapabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn capabilities(&self) -> Option<&DeviceCapabilities> {
+        self.capabilities.as_ref()
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCapabilities {
+    #[serde(default)]
+    iot_edge: bool,
+}
+
+impl DeviceCapabilities {
+    pub fn new() -> Self {
+        DeviceCapabilities::default()
+    }
+
+    pub fn with_iot_edge(mut self, iot_edge: bool) -> Self {
+        self.iot_edge = iot_edge;
+        self
+    }
+
+    /// Whether the device may act as an IoT Edge device, with modules of its
+    /// own.
+    pub fn iot_edge(&self) -> bool {
+        self.iot_edge
+    }
+}
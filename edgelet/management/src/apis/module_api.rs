@@ -55,6 +55,8 @@ pub trait ModuleApi: Send + Sync {
         name: &str,
         follow: bool,
         tail: &str,
+        since: i32,
+        timestamps: bool,
     ) -> Box<Future<Item = hyper::Body, Error = Error<serde_json::Value>> + Send>;
     fn restart_module(
         &self,
@@ -302,6 +304,8 @@ where
         name: &str,
         follow: bool,
         tail: &str,
+        since: i32,
+        timestamps: bool,
     ) -> Box<Future<Item = hyper::Body, Error = Error<serde_json::Value>> + Send> {
         let configuration: &configuration::Configuration<C> = self.configuration.borrow();
 
@@ -311,6 +315,8 @@ where
             .append_pair("api-version", &api_version.to_string())
             .append_pair("follow", &follow.to_string())
             .append_pair("tail", &tail.to_string())
+            .append_pair("since", &since.to_string())
+            .append_pair("timestamps", &timestamps.to_string())
             .finish();
         let uri_str = format!("/modules/{name}/logs?{}", query, name = name);
 
@@ -0,0 +1,532 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! A typed client of the management API, for the iotedge tool and tests.
+//!
+//! Unlike the generated clients in `apis`, it reaches the API over a unix
+//! socket as well as over http, adds the API version to every request, and
+//! turns error responses into an `Error` that keeps the error's code.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use edgelet_core::LogOptions;
+use edgelet_http::{UrlConnector, API_VERSION};
+use failure::ResultExt;
+use futures::future::{self, Either};
+use futures::{Future, Poll, Stream};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use hyper::{Body, Chunk, Client, Method, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+use tokio::timer::Timeout;
+use url::form_urlencoded::Serializer as QuerySerializer;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use url::Url;
+
+use error::{Error, ErrorKind};
+use models::{
+    ErrorResponse, Identity, IdentityList, IdentitySpec, ModuleList, SystemInfo, UpdateIdentity,
+};
+
+#[derive(Clone)]
+pub struct ManagementClient {
+    client: Arc<Client<UrlConnector>>,
+    scheme: String,
+    base_path: String,
+}
+
+impl ManagementClient {
+    pub fn new(url: &Url) -> Result<Self, Error> {
+        let connector = UrlConnector::new(url)?;
+        let scheme = connector.scheme().to_string();
+        // Requests to a unix socket name the socket by its path rather than
+        // by the whole URL.
+        let base_path = match url.scheme() {
+            "unix" => url.path(),
+            _ => url.as_str(),
+        };
+        Ok(ManagementClient {
+            client: Arc::new(Client::builder().build(connector)),
+            scheme,
+            base_path: base_path.to_string(),
+        })
+    }
+
+    pub fn list_modules(&self) -> Box<Future<Item = ModuleList, Error = Error> + Send> {
+        self.json(Method::GET, "/modules", &[], None)
+    }
+
+    pub fn start_module(&self, name: &str) -> Box<Future<Item = (), Error = Error> + Send> {
+        self.empty(Method::POST, &module_path(name, "/start"), None)
+    }
+
+    pub fn stop_module(&self, name: &str) -> Box<Future<Item = (), Error = Error> + Send> {
+        self.empty(Method::POST, &module_path(name, "/stop"), None)
+    }
+
+    /// Restarts the module named `name`. Without a `timeout` the client waits
+    /// for as long as the daemon takes to restart it.
+    pub fn restart_module(
+        &self,
+        name: &str,
+        timeout: Option<Duration>,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        let restart = self.empty(Method::POST, &module_path(name, "/restart"), None);
+        match timeout {
+            Some(timeout) => Box::new(Timeout::new(restart, timeout).map_err(|err| {
+                if err.is_inner() {
+                    err.into_inner().expect("inner errors have an error")
+                } else {
+                    Error::from(ErrorKind::TimedOut)
+                }
+            })),
+            None => restart,
+        }
+    }
+
+    /// Sends `signal`, like SIGHUP, to the module named `name`.
+    pub fn kill_module(
+        &self,
+        name: &str,
+        signal: &str,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        let query = [("signal", signal.to_string())];
+        let response = self
+            .send(Method::POST, &module_path(name, "/kill"), &query, None)
+            .and_then(|body| body.concat2().map_err(Error::from))
+            .map(|_| ());
+        Box::new(response)
+    }
+
+    /// The logs of the module named `name`, as they are read.
+    pub fn module_logs(
+        &self,
+        name: &str,
+        options: &LogOptions,
+    ) -> Box<Future<Item = Logs, Error = Error> + Send> {
+        let query = [
+            ("follow", options.follow().to_string()),
+            ("tail", options.tail().to_string()),
+            ("since", options.since().to_string()),
+            ("timestamps", options.timestamps().to_string()),
+        ];
+        let logs = self
+            .send(Method::GET, &module_path(name, "/logs"), &query, None)
+            .map(Logs);
+        Box::new(logs)
+    }
+
+    pub fn list_identities(&self) -> Box<Future<Item = IdentityList, Error = Error> + Send> {
+        self.json(Method::GET, "/identities", &[], None)
+    }
+
+    pub fn create_identity(
+        &self,
+        spec: &IdentitySpec,
+    ) -> Box<Future<Item = Identity, Error = Error> + Send> {
+        match to_body(spec) {
+            Ok(body) => self.json(Method::POST, "/identities", &[], Some(body)),
+            Err(err) => Box::new(future::err(err)),
+        }
+    }
+
+    pub fn update_identity(
+        &self,
+        name: &str,
+        update: &UpdateIdentity,
+    ) -> Box<Future<Item = Identity, Error = Error> + Send> {
+        match to_body(update) {
+            Ok(body) => self.json(Method::PUT, &identity_path(name), &[], Some(body)),
+            Err(err) => Box::new(future::err(err)),
+        }
+    }
+
+    pub fn delete_identity(&self, name: &str) -> Box<Future<Item = (), Error = Error> + Send> {
+        self.empty(Method::DELETE, &identity_path(name), None)
+    }
+
+    pub fn system_info(&self) -> Box<Future<Item = SystemInfo, Error = Error> + Send> {
+        self.json(Method::GET, "/systeminfo", &[], None)
+    }
+
+    /// Sends a request and parses the JSON it is answered with.
+    fn json<T>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> Box<Future<Item = T, Error = Error> + Send>
+    where
+        T: 'static + DeserializeOwned + Send,
+    {
+        let response = self
+            .send(method, path, query, body)
+            .and_then(|body| body.concat2().map_err(Error::from))
+            .and_then(|body| Ok(serde_json::from_slice(&body)?));
+        Box::new(response)
+    }
+
+    /// Sends a request that is answered with nothing of interest.
+    fn empty(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> Box<Future<Item = (), Error = Error> + Send> {
+        let response = self
+            .send(method, path, &[], body)
+            .and_then(|body| body.concat2().map_err(Error::from))
+            .map(|_| ());
+        Box::new(response)
+    }
+
+    /// Sends a request and hands back the body of a successful response.
+    fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> impl Future<Item = Body, Error = Error> + Send {
+        let request = self.request(method, path, query, body);
+        let client = self.client.clone();
+        future::result(request)
+            .and_then(move |request| client.request(request).map_err(Error::from))
+            .and_then(|response| {
+                let (parts, body) = response.into_parts();
+                if parts.status.is_success() {
+                    Either::A(future::ok(body))
+                } else {
+                    let status = parts.status;
+                    Either::B(
+                        body.concat2()
+                            .map_err(Error::from)
+                            .and_then(move |body| Err::<Body, _>(api_error(status, &body))),
+                    )
+                }
+            })
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<String>,
+    ) -> Result<Request<Body>, Error> {
+        let mut serializer = QuerySerializer::new(String::new());
+        serializer.append_pair("api-version", API_VERSION);
+        for &(key, ref value) in query {
+            serializer.append_pair(key, value);
+        }
+        let path = format!("{}?{}", path, serializer.finish());
+        let uri = UrlConnector::build_hyper_uri(&self.scheme, &self.base_path, &path)?;
+
+        let mut builder = Request::builder();
+        builder
+            .method(method)
+            .uri(uri)
+            .header(USER_AGENT, &*format!("iotedge/{}", env!("CARGO_PKG_VERSION")));
+        let request = match body {
+            Some(body) => builder
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, &*body.len().to_string())
+                .body(Body::from(body)),
+            None => builder.body(Body::empty()),
+        };
+        Ok(request.context(ErrorKind::InvalidUrl)?)
+    }
+}
+
+fn module_path(name: &str, action: &str) -> String {
+    format!(
+        "/modules/{}{}",
+        utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET),
+        action
+    )
+}
+
+fn identity_path(name: &str) -> String {
+    format!(
+        "/identities/{}",
+        utf8_percent_encode(name, PATH_SEGMENT_ENCODE_SET)
+    )
+}
+
+fn to_body<T: Serialize>(value: &T) -> Result<String, Error> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// The error a response with `status` and `body` stands for. Responses that
+/// aren't an `ErrorResponse`, like those of proxies, keep their body as the
+/// message.
+fn api_error(status: StatusCode, body: &[u8]) -> Error {
+    let (code, message) = match serde_json::from_slice::<ErrorResponse>(body) {
+        Ok(response) => (
+            response.code().map(ToString::to_string),
+            response.message().to_string(),
+        ),
+        Err(_) if body.is_empty() => (
+            None,
+            status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string(),
+        ),
+        Err(_) => (None, String::from_utf8_lossy(body).into_owned()),
+    };
+    Error::from(ErrorKind::Api {
+        status,
+        code,
+        message,
+    })
+}
+
+/// The logs of a module, in the chunks they arrive in.
+pub struct Logs(Body);
+
+impl Stream for Logs {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.0.poll().map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use edgelet_core::LogTail;
+    use edgelet_test_utils::{get_unused_tcp_port, run_tcp_server};
+    use hyper::{Error as HyperError, Response};
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::*;
+
+    type Seen = Arc<Mutex<Vec<String>>>;
+
+    /// Runs `f` against a management API that answers each request with
+    /// `status` and `body`, and returns what `f` got along with the method,
+    /// path and query of each request.
+    fn call<F, T>(status: StatusCode, body: &str, f: F) -> (Result<T, Error>, Vec<String>)
+    where
+        F: FnOnce(&ManagementClient) -> Box<Future<Item = T, Error = Error> + Send>,
+    {
+        let body = body.to_string();
+        let seen: Seen = Arc::new(Mutex::new(vec![]));
+        let seen_copy = seen.clone();
+        let handler = move |req: Request<Body>| -> Box<
+            Future<Item = Response<Body>, Error = HyperError> + Send,
+        > {
+            seen_copy
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", req.method(), req.uri()));
+            let response = Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone().into())
+                .unwrap();
+            Box::new(future::ok(response))
+        };
+
+        let port = get_unused_tcp_port();
+        let server = run_tcp_server("127.0.0.1", port, handler).map_err(|err| eprintln!("{}", err));
+        let url = Url::parse(&format!("http://localhost:{}", port)).unwrap();
+        let client = ManagementClient::new(&url).unwrap();
+
+        let mut runtime = Runtime::new().unwrap();
+        runtime.spawn(server);
+        let result = runtime.block_on(f(&client));
+        let seen = seen.lock().unwrap().clone();
+        (result, seen)
+    }
+
+    fn query(path: &str) -> String {
+        format!("{}api-version={}", path, API_VERSION)
+    }
+
+    const NOT_FOUND: &str = r#"{"message":"Module m1 not found","code":"NOT_FOUND"}"#;
+
+    fn assert_not_found<T>(result: Result<T, Error>) {
+        let err = result.err().expect("expected an error");
+        assert_eq!(Some(StatusCode::NOT_FOUND), err.status());
+        assert_eq!(Some("NOT_FOUND"), err.code());
+        assert_eq!("Module m1 not found", err.to_string());
+    }
+
+    #[test]
+    fn list_modules() {
+        let body = r#"{"modules":[{
+            "id": "m1", "name": "m1", "type": "docker",
+            "config": {"settings": {"image": "image:1.0"}},
+            "status": {"runtimeStatus": {"status": "running"}}
+        }]}"#;
+        let (result, seen) = call(StatusCode::OK, body, ManagementClient::list_modules);
+        let modules = result.unwrap();
+        assert_eq!(1, modules.modules().len());
+        assert_eq!("m1", modules.modules()[0].name());
+        assert_eq!(vec![format!("GET {}", query("/modules?"))], seen);
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, ManagementClient::list_modules);
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn start_and_stop_module() {
+        let (result, seen) = call(StatusCode::NO_CONTENT, "", |c| c.start_module("m1"));
+        result.unwrap();
+        assert_eq!(vec![format!("POST {}", query("/modules/m1/start?"))], seen);
+
+        let (result, seen) = call(StatusCode::NO_CONTENT, "", |c| c.stop_module("m1"));
+        result.unwrap();
+        assert_eq!(vec![format!("POST {}", query("/modules/m1/stop?"))], seen);
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| c.stop_module("m1"));
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn restart_module() {
+        let (result, seen) = call(StatusCode::NO_CONTENT, "", |c| {
+            c.restart_module("m1", Some(Duration::from_secs(30)))
+        });
+        result.unwrap();
+        assert_eq!(vec![format!("POST {}", query("/modules/m1/restart?"))], seen);
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| {
+            c.restart_module("m1", Some(Duration::from_secs(30)))
+        });
+        assert_not_found(result);
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| {
+            c.restart_module("m1", None)
+        });
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn kill_module() {
+        let (result, seen) = call(StatusCode::NO_CONTENT, "", |c| c.kill_module("m1", "SIGHUP"));
+        result.unwrap();
+        assert_eq!(
+            vec![format!("POST {}&signal=SIGHUP", query("/modules/m1/kill?"))],
+            seen
+        );
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| {
+            c.kill_module("m1", "SIGHUP")
+        });
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn names_are_escaped() {
+        let (_, seen) = call(StatusCode::NO_CONTENT, "", |c| {
+            c.restart_module("m1/../identities?", None)
+        });
+        assert_eq!(
+            vec![format!(
+                "POST {}",
+                query("/modules/m1%2F..%2Fidentities%3F/restart?")
+            )],
+            seen
+        );
+    }
+
+    #[test]
+    fn module_logs() {
+        let options = LogOptions::new()
+            .with_follow(true)
+            .with_tail(LogTail::Num(10))
+            .with_since(5);
+        let (result, seen) = call(StatusCode::OK, "line 1\nline 2\n", |c| {
+            let logs: Box<Future<Item = Chunk, Error = Error> + Send> =
+                Box::new(c.module_logs("m1", &options).and_then(Stream::concat2));
+            logs
+        });
+        assert_eq!(b"line 1\nline 2\n", &result.unwrap()[..]);
+        assert_eq!(
+            vec![format!(
+                "GET /modules/m1/logs?api-version={}&follow=true&tail=10&since=5&timestamps=false",
+                API_VERSION
+            )],
+            seen
+        );
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| {
+            c.module_logs("m1", &options)
+        });
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn identities() {
+        let identity = r#"{
+            "moduleId": "m1", "managedBy": "iotedge", "generationId": "1",
+            "authType": "Sas"
+        }"#;
+
+        let (result, seen) = call(StatusCode::OK, identity, |c| {
+            c.create_identity(&IdentitySpec::new("m1".to_string()))
+        });
+        assert_eq!("m1", result.unwrap().module_id());
+        assert_eq!(vec![format!("POST {}", query("/identities?"))], seen);
+
+        let (result, seen) = call(StatusCode::OK, identity, |c| {
+            c.update_identity("m1", &UpdateIdentity::new("1".to_string()))
+        });
+        assert_eq!("1", result.unwrap().generation_id());
+        assert_eq!(vec![format!("PUT {}", query("/identities/m1?"))], seen);
+
+        let (result, seen) = call(StatusCode::NO_CONTENT, "", |c| c.delete_identity("m1"));
+        result.unwrap();
+        assert_eq!(vec![format!("DELETE {}", query("/identities/m1?"))], seen);
+
+        let body = format!(r#"{{"identities":[{}]}}"#, identity);
+        let (result, seen) = call(StatusCode::OK, &body, ManagementClient::list_identities);
+        assert_eq!(1, result.unwrap().identities().len());
+        assert_eq!(vec![format!("GET {}", query("/identities?"))], seen);
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, |c| c.delete_identity("m1"));
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn system_info() {
+        let body = r#"{"osType": "linux", "architecture": "x86_64", "version": "1.0"}"#;
+        let (result, seen) = call(StatusCode::OK, body, ManagementClient::system_info);
+        let info = result.unwrap();
+        assert_eq!(Some("linux"), info.os_type());
+        assert_eq!(vec![format!("GET {}", query("/systeminfo?"))], seen);
+
+        let (result, _) = call(StatusCode::NOT_FOUND, NOT_FOUND, ManagementClient::system_info);
+        assert_not_found(result);
+    }
+
+    #[test]
+    fn errors_without_an_error_response_keep_the_body() {
+        let (result, _) = call(StatusCode::BAD_GATEWAY, "upstream went away", |c| {
+            c.system_info()
+        });
+        let err = result.err().unwrap();
+        assert_eq!(Some(StatusCode::BAD_GATEWAY), err.status());
+        assert_eq!(None, err.code());
+        assert_eq!("upstream went away", err.to_string());
+
+        let (result, _) = call(StatusCode::SERVICE_UNAVAILABLE, "", |c| c.system_info());
+        assert_eq!("Service Unavailable", result.err().unwrap().to_string());
+    }
+
+    #[test]
+    fn unparseable_responses_are_serde_errors() {
+        let (result, _) = call(StatusCode::OK, "not json", ManagementClient::system_info);
+        match *result.err().unwrap().kind() {
+            ErrorKind::Serde => (),
+            ref kind => panic!("unexpected error kind {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! The settings of a module carry the options its container is created with
+//! either as an object or, as some versions of edgeAgent send them, as a
+//! string holding the JSON of one. A string too long for a twin property is
+//! split into `createOptions` followed by `createOptions01`,
+//! `createOptions02` and so on.
+//!
+//! Settings are read in any of these forms and kept with the create options
+//! as an object, which is the form the API documents and the one they are
+//! written back out in.
+
+use std::iter;
+
+use serde::de::{Deserialize, Deserializer, Error as DeError};
+use serde_json::{self, Map, Value};
+
+const CREATE_OPTIONS: &str = "createOptions";
+
+/// Continuation chunks are numbered with two digits.
+const MAX_CHUNKS: usize = 99;
+
+/// Deserializes module settings, with their create options as an object.
+pub fn deserialize_settings<'de, D>(deserializer: D) -> Result<Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let settings = Value::deserialize(deserializer)?;
+    normalize_settings(settings).map_err(D::Error::custom)
+}
+
+/// Replaces the create options in `settings` with the object they hold,
+/// joining them from their chunks first if they were split. Settings without
+/// create options, or that are not an object, are left as they are.
+pub fn normalize_settings(mut settings: Value) -> Result<Value, String> {
+    if let Value::Object(ref mut settings) = settings {
+        let chunks = take_chunks(settings)?;
+        let create_options = match settings.remove(CREATE_OPTIONS) {
+            Some(Value::String(create_options)) => Some(parse(
+                &iter::once(create_options).chain(chunks).collect::<String>(),
+            )?),
+            Some(Value::Object(create_options)) => {
+                if !chunks.is_empty() {
+                    return Err(format!(
+                        "{}01 continues {}, which is not a string",
+                        CREATE_OPTIONS, CREATE_OPTIONS
+                    ));
+                }
+                Some(Value::Object(create_options))
+            }
+            Some(_) => {
+                return Err(format!(
+                    "{} must be an object or a string holding one",
+                    CREATE_OPTIONS
+                ))
+            }
+            None => {
+                if !chunks.is_empty() {
+                    return Err(format!(
+                        "{}01 continues {}, which is missing",
+                        CREATE_OPTIONS, CREATE_OPTIONS
+                    ));
+                }
+                None
+            }
+        };
+        if let Some(create_options) = create_options {
+            settings.insert(CREATE_OPTIONS.to_string(), create_options);
+        }
+    }
+    Ok(settings)
+}
+
+fn parse(create_options: &str) -> Result<Value, String> {
+    match serde_json::from_str(create_options) {
+        Ok(Value::Object(create_options)) => Ok(Value::Object(create_options)),
+        Ok(_) => Err(format!("{} does not hold an object", CREATE_OPTIONS)),
+        Err(err) => Err(format!("{} is not valid JSON: {}", CREATE_OPTIONS, err)),
+    }
+}
+
+/// Removes the continuation chunks of the create options from `settings`, in
+/// order.
+fn take_chunks(settings: &mut Map<String, Value>) -> Result<Vec<String>, String> {
+    let mut chunks = vec![];
+    for n in 1..=MAX_CHUNKS {
+        let name = format!("{}{:02}", CREATE_OPTIONS, n);
+        match settings.remove(&name) {
+            Some(Value::String(chunk)) => chunks.push(chunk),
+            Some(_) => return Err(format!("{} must be a string", name)),
+            None => break,
+        }
+    }
+    match settings.keys().find(|name| is_chunk(name)) {
+        Some(name) => Err(format!("{} does not follow the chunk before it", name)),
+        None => Ok(chunks),
+    }
+}
+
+fn is_chunk(name: &str) -> bool {
+    name.len() == CREATE_OPTIONS.len() + 2
+        && name.starts_with(CREATE_OPTIONS)
+        && name[CREATE_OPTIONS.len()..].bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use models::{Config, ModuleSpec};
+
+    fn normalized(settings: Value) -> Result<Value, String> {
+        normalize_settings(settings)
+    }
+
+    fn expected() -> Value {
+        json!({
+            "image": "edge-hub:1.0",
+            "createOptions": {"HostConfig": {"PortBindings": {"22/tcp": [{"HostPort": "11022"}]}}}
+        })
+    }
+
+    #[test]
+    fn object_create_options_are_kept() {
+        assert_eq!(Ok(expected()), normalized(expected()));
+    }
+
+    #[test]
+    fn string_create_options_are_parsed() {
+        let settings = json!({
+            "image": "edge-hub:1.0",
+            "createOptions": r#"{"HostConfig":{"PortBindings":{"22/tcp":[{"HostPort":"11022"}]}}}"#
+        });
+        assert_eq!(Ok(expected()), normalized(settings));
+    }
+
+    #[test]
+    fn chunked_create_options_are_joined() {
+        let settings = json!({
+            "image": "edge-hub:1.0",
+            "createOptions": r#"{"HostConfig":{"PortBin"#,
+            "createOptions01": r#"dings":{"22/tcp":[{"Host"#,
+            "createOptions02": r#"Port":"11022"}]}}}"#
+        });
+        assert_eq!(Ok(expected()), normalized(settings));
+    }
+
+    #[test]
+    fn settings_without_create_options_are_kept() {
+        let settings = json!({"image": "edge-hub:1.0"});
+        assert_eq!(Ok(settings.clone()), normalized(settings));
+        assert_eq!(Ok(json!("edge-hub:1.0")), normalized(json!("edge-hub:1.0")));
+    }
+
+    #[test]
+    fn invalid_create_options_are_rejected() {
+        for (settings, error) in vec![
+            (json!({"createOptions": 1}), "createOptions must be an object"),
+            (json!({"createOptions": ["a"]}), "createOptions must be an object"),
+            (json!({"createOptions": "{\"Env\":"}), "createOptions is not valid JSON"),
+            (json!({"createOptions": "[]"}), "createOptions does not hold an object"),
+            (
+                json!({"createOptions": "{", "createOptions01": 1}),
+                "createOptions01 must be a string",
+            ),
+            (
+                json!({"createOptions": "{", "createOptions02": "}"}),
+                "createOptions02 does not follow",
+            ),
+            (
+                json!({"createOptions": {}, "createOptions01": "}"}),
+                "createOptions01 continues createOptions, which is not a string",
+            ),
+            (
+                json!({"createOptions01": "{}"}),
+                "createOptions01 continues createOptions, which is missing",
+            ),
+        ] {
+            let result = normalized(settings.clone());
+            match result {
+                Err(ref message) if message.starts_with(error) => (),
+                _ => panic!("{} gave {:?}, not {}", settings, result, error),
+            }
+        }
+    }
+
+    #[test]
+    fn module_specs_are_written_with_object_create_options() {
+        let spec: ModuleSpec = serde_json::from_value(json!({
+            "name": "edgeHub",
+            "type": "docker",
+            "config": {
+                "settings": {
+                    "image": "edge-hub:1.0",
+                    "createOptions": r#"{"HostConfig":{"PortBin"#,
+                    "createOptions01": r#"dings":{"22/tcp":[{"HostPort":"11022"}]}}}"#
+                }
+            }
+        })).unwrap();
+        assert_eq!(&expected(), spec.config().settings());
+        assert_eq!(
+            expected(),
+            serde_json::to_value(spec.config()).unwrap()["settings"]
+        );
+
+        let config: Result<Config, _> =
+            serde_json::from_value(json!({"settings": {"createOptions": "{"}}));
+        assert!(config.is_err());
+    }
+}
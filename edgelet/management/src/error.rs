@@ -0,0 +1,110 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+use std::fmt::{self, Display};
+
+use edgelet_http::Error as EdgeletHttpError;
+use failure::{Backtrace, Context, Fail};
+use hyper::{Error as HyperError, StatusCode};
+use serde_json;
+
+#[derive(Debug)]
+pub struct Error {
+    inner: Context<ErrorKind>,
+}
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "Invalid management API URL")]
+    InvalidUrl,
+    #[fail(display = "Could not reach the management API")]
+    Request,
+    #[fail(display = "Could not parse the response of the management API")]
+    Serde,
+    #[fail(display = "The management API did not answer in time")]
+    TimedOut,
+    /// The management API answered with an error. `code` identifies the
+    /// errors callers tell apart, when the API gives one.
+    #[fail(display = "{}", message)]
+    Api {
+        status: StatusCode,
+        code: Option<String>,
+        message: String,
+    },
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&Fail> {
+        self.inner.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.inner.backtrace()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.inner, f)
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        self.inner.get_context()
+    }
+
+    /// The status the management API answered with, if it answered.
+    pub fn status(&self) -> Option<StatusCode> {
+        match *self.kind() {
+            ErrorKind::Api { status, .. } => Some(status),
+            _ => None,
+        }
+    }
+
+    /// The machine-readable code of the error the management API answered
+    /// with, if it gave one.
+    pub fn code(&self) -> Option<&str> {
+        match *self.kind() {
+            ErrorKind::Api { ref code, .. } => code.as_ref().map(AsRef::as_ref),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            inner: Context::new(kind),
+        }
+    }
+}
+
+impl From<Context<ErrorKind>> for Error {
+    fn from(inner: Context<ErrorKind>) -> Self {
+        Error { inner }
+    }
+}
+
+impl From<EdgeletHttpError> for Error {
+    fn from(error: EdgeletHttpError) -> Self {
+        Error {
+            inner: error.context(ErrorKind::InvalidUrl),
+        }
+    }
+}
+
+impl From<HyperError> for Error {
+    fn from(error: HyperError) -> Self {
+        Error {
+            inner: error.context(ErrorKind::Request),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error {
+            inner: error.context(ErrorKind::Serde),
+        }
+    }
+}
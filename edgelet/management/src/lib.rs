@@ -12,14 +12,29 @@
 #[macro_use]
 extern crate serde_derive;
 
+extern crate edgelet_core;
+extern crate edgelet_http;
+#[cfg(test)]
+extern crate edgelet_test_utils;
 #[macro_use]
 extern crate failure;
 extern crate futures;
 extern crate hyper;
 extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+#[cfg(not(test))]
 extern crate serde_json;
+extern crate tokio;
 extern crate typed_headers;
 extern crate url;
 
 pub mod apis;
+mod client;
+pub mod create_options;
+mod error;
 pub mod models;
+
+pub use client::{Logs, ManagementClient};
+pub use error::{Error, ErrorKind};
@@ -11,12 +11,21 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
-    #[serde(rename = "settings")]
+    /// Create options given as a string, whole or in chunks, are read into
+    /// the object they hold.
+    #[serde(rename = "settings", deserialize_with = "::create_options::deserialize_settings")]
     settings: Value,
     #[serde(rename = "env", skip_serializing_if = "Option::is_none")]
     env: Option<Vec<::models::EnvVar>>,
+    /// The named volumes the module keeps its data in.
+    #[serde(rename = "volumes", skip_serializing_if = "Option::is_none")]
+    volumes: Option<Vec<String>>,
+    /// Fields of later versions of the API, kept so that configs passed on
+    /// keep them too.
+    #[serde(flatten)]
+    other: ::serde_json::Map<String, Value>,
 }
 
 impl Config {
@@ -24,6 +33,8 @@ impl Config {
         Config {
             settings,
             env: None,
+            volumes: None,
+            other: ::serde_json::Map::new(),
         }
     }
 
@@ -56,4 +67,21 @@ impl Config {
     pub fn reset_env(&mut self) {
         self.env = None;
     }
+
+    pub fn set_volumes(&mut self, volumes: Vec<String>) {
+        self.volumes = Some(volumes);
+    }
+
+    pub fn with_volumes(mut self, volumes: Vec<String>) -> Self {
+        self.volumes = Some(volumes);
+        self
+    }
+
+    pub fn volumes(&self) -> Option<&[String]> {
+        self.volumes.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_volumes(&mut self) {
+        self.volumes = None;
+    }
 }
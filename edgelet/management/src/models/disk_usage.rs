@@ -0,0 +1,108 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiskUsage {
+    #[serde(rename = "path")]
+    path: String,
+    #[serde(
+        rename = "totalBytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    total_bytes: Option<i64>,
+    #[serde(
+        rename = "availableBytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    available_bytes: Option<i64>,
+    #[serde(
+        rename = "error",
+        skip_serializing_if = "Option::is_none"
+    )]
+    error: Option<String>,
+}
+
+impl DiskUsage {
+    pub fn new(path: String) -> Self {
+        DiskUsage {
+            path,
+            total_bytes: None,
+            available_bytes: None,
+            error: None,
+        }
+    }
+
+    pub fn set_path(&mut self, path: String) {
+        self.path = path;
+    }
+
+    pub fn with_path(mut self, path: String) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn set_total_bytes(&mut self, total_bytes: i64) {
+        self.total_bytes = Some(total_bytes);
+    }
+
+    pub fn with_total_bytes(mut self, total_bytes: i64) -> Self {
+        self.total_bytes = Some(total_bytes);
+        self
+    }
+
+    pub fn total_bytes(&self) -> Option<&i64> {
+        self.total_bytes.as_ref()
+    }
+
+    pub fn reset_total_bytes(&mut self) {
+        self.total_bytes = None;
+    }
+
+    pub fn set_available_bytes(&mut self, available_bytes: i64) {
+        self.available_bytes = Some(available_bytes);
+    }
+
+    pub fn with_available_bytes(mut self, available_bytes: i64) -> Self {
+        self.available_bytes = Some(available_bytes);
+        self
+    }
+
+    pub fn available_bytes(&self) -> Option<&i64> {
+        self.available_bytes.as_ref()
+    }
+
+    pub fn reset_available_bytes(&mut self) {
+        self.available_bytes = None;
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn with_error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_error(&mut self) {
+        self.error = None;
+    }
+}
@@ -11,7 +11,7 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EnvVar {
     #[serde(rename = "key")]
     key: String,
@@ -33,7 +33,7 @@ impl EnvVar {
         self
     }
 
-    pub fn key(&self) -> &String {
+    pub fn key(&self) -> &str {
         &self.key
     }
 
@@ -46,7 +46,7 @@ impl EnvVar {
         self
     }
 
-    pub fn value(&self) -> &String {
+    pub fn value(&self) -> &str {
         &self.value
     }
 }
@@ -15,11 +15,17 @@ use serde_json::Value;
 pub struct ErrorResponse {
     #[serde(rename = "message")]
     message: String,
+    /// A code that identifies the error, for errors that callers tell apart.
+    #[serde(rename = "code", skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
 }
 
 impl ErrorResponse {
     pub fn new(message: String) -> Self {
-        ErrorResponse { message }
+        ErrorResponse {
+            message,
+            code: None,
+        }
     }
 
     pub fn set_message(&mut self, message: String) {
@@ -31,7 +37,24 @@ impl ErrorResponse {
         self
     }
 
-    pub fn message(&self) -> &String {
+    pub fn message(&self) -> &str {
         &self.message
     }
+
+    pub fn set_code(&mut self, code: String) {
+        self.code = Some(code);
+    }
+
+    pub fn with_code(mut self, code: String) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_code(&mut self) {
+        self.code = None;
+    }
 }
@@ -11,7 +11,7 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ExitStatus {
     #[serde(rename = "exitTime")]
     exit_time: String,
@@ -36,7 +36,7 @@ impl ExitStatus {
         self
     }
 
-    pub fn exit_time(&self) -> &String {
+    pub fn exit_time(&self) -> &str {
         &self.exit_time
     }
 
@@ -49,7 +49,7 @@ impl ExitStatus {
         self
     }
 
-    pub fn status_code(&self) -> &String {
+    pub fn status_code(&self) -> &str {
         &self.status_code
     }
 }
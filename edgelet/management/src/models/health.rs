@@ -0,0 +1,146 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Health {
+    #[serde(rename = "status")]
+    status: String,
+    #[serde(rename = "uptime")]
+    uptime: i64,
+    #[serde(rename = "runtime")]
+    runtime: ::models::SubsystemHealth,
+    #[serde(rename = "identity")]
+    identity: ::models::SubsystemHealth,
+    #[serde(rename = "provisioning")]
+    provisioning: ::models::SubsystemHealth,
+    #[serde(rename = "watchdog")]
+    watchdog: ::models::SubsystemHealth,
+    #[serde(rename = "integrity", skip_serializing_if = "Option::is_none")]
+    integrity: Option<::models::SubsystemHealth>,
+}
+
+impl Health {
+    pub fn new(
+        status: String,
+        uptime: i64,
+        runtime: ::models::SubsystemHealth,
+        identity: ::models::SubsystemHealth,
+        provisioning: ::models::SubsystemHealth,
+        watchdog: ::models::SubsystemHealth,
+    ) -> Self {
+        Health {
+            status,
+            uptime,
+            runtime,
+            identity,
+            provisioning,
+            watchdog,
+            integrity: None,
+        }
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    pub fn with_status(mut self, status: String) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn set_uptime(&mut self, uptime: i64) {
+        self.uptime = uptime;
+    }
+
+    pub fn with_uptime(mut self, uptime: i64) -> Self {
+        self.uptime = uptime;
+        self
+    }
+
+    pub fn uptime(&self) -> &i64 {
+        &self.uptime
+    }
+
+    pub fn set_runtime(&mut self, runtime: ::models::SubsystemHealth) {
+        self.runtime = runtime;
+    }
+
+    pub fn with_runtime(mut self, runtime: ::models::SubsystemHealth) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    pub fn runtime(&self) -> &::models::SubsystemHealth {
+        &self.runtime
+    }
+
+    pub fn set_identity(&mut self, identity: ::models::SubsystemHealth) {
+        self.identity = identity;
+    }
+
+    pub fn with_identity(mut self, identity: ::models::SubsystemHealth) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    pub fn identity(&self) -> &::models::SubsystemHealth {
+        &self.identity
+    }
+
+    pub fn set_provisioning(&mut self, provisioning: ::models::SubsystemHealth) {
+        self.provisioning = provisioning;
+    }
+
+    pub fn with_provisioning(mut self, provisioning: ::models::SubsystemHealth) -> Self {
+        self.provisioning = provisioning;
+        self
+    }
+
+    pub fn provisioning(&self) -> &::models::SubsystemHealth {
+        &self.provisioning
+    }
+
+    pub fn set_watchdog(&mut self, watchdog: ::models::SubsystemHealth) {
+        self.watchdog = watchdog;
+    }
+
+    pub fn with_watchdog(mut self, watchdog: ::models::SubsystemHealth) -> Self {
+        self.watchdog = watchdog;
+        self
+    }
+
+    pub fn watchdog(&self) -> &::models::SubsystemHealth {
+        &self.watchdog
+    }
+
+    pub fn set_integrity(&mut self, integrity: ::models::SubsystemHealth) {
+        self.integrity = Some(integrity);
+    }
+
+    pub fn with_integrity(mut self, integrity: ::models::SubsystemHealth) -> Self {
+        self.integrity = Some(integrity);
+        self
+    }
+
+    pub fn integrity(&self) -> Option<&::models::SubsystemHealth> {
+        self.integrity.as_ref()
+    }
+
+    pub fn reset_integrity(&mut self) {
+        self.integrity = None;
+    }
+}
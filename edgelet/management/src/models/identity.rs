@@ -11,7 +11,7 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Identity {
     #[serde(rename = "moduleId")]
     module_id: String,
@@ -47,7 +47,7 @@ impl Identity {
         self
     }
 
-    pub fn module_id(&self) -> &String {
+    pub fn module_id(&self) -> &str {
         &self.module_id
     }
 
@@ -60,7 +60,7 @@ impl Identity {
         self
     }
 
-    pub fn managed_by(&self) -> &String {
+    pub fn managed_by(&self) -> &str {
         &self.managed_by
     }
 
@@ -73,7 +73,7 @@ impl Identity {
         self
     }
 
-    pub fn generation_id(&self) -> &String {
+    pub fn generation_id(&self) -> &str {
         &self.generation_id
     }
 
@@ -86,7 +86,7 @@ impl Identity {
         self
     }
 
-    pub fn auth_type(&self) -> &String {
+    pub fn auth_type(&self) -> &str {
         &self.auth_type
     }
 }
@@ -15,11 +15,18 @@ use serde_json::Value;
 pub struct IdentityList {
     #[serde(rename = "identities")]
     identities: Vec<::models::Identity>,
+    /// Pass this as the continuationToken query parameter to get the next
+    /// page. Missing on the last page.
+    #[serde(rename = "continuationToken", skip_serializing_if = "Option::is_none")]
+    continuation_token: Option<String>,
 }
 
 impl IdentityList {
     pub fn new(identities: Vec<::models::Identity>) -> Self {
-        IdentityList { identities }
+        IdentityList {
+            identities,
+            continuation_token: None,
+        }
     }
 
     pub fn set_identities(&mut self, identities: Vec<::models::Identity>) {
@@ -34,4 +41,21 @@ impl IdentityList {
     pub fn identities(&self) -> &[::models::Identity] {
         &self.identities
     }
+
+    pub fn set_continuation_token(&mut self, continuation_token: String) {
+        self.continuation_token = Some(continuation_token);
+    }
+
+    pub fn with_continuation_token(mut self, continuation_token: String) -> Self {
+        self.continuation_token = Some(continuation_token);
+        self
+    }
+
+    pub fn continuation_token(&self) -> Option<&str> {
+        self.continuation_token.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_continuation_token(&mut self) {
+        self.continuation_token = None;
+    }
 }
@@ -39,7 +39,7 @@ impl IdentitySpec {
         self
     }
 
-    pub fn module_id(&self) -> &String {
+    pub fn module_id(&self) -> &str {
         &self.module_id
     }
 
@@ -1,11 +1,15 @@
 mod config;
 pub use self::config::Config;
+mod disk_usage;
+pub use self::disk_usage::DiskUsage;
 mod env_var;
 pub use self::env_var::EnvVar;
 mod error_response;
 pub use self::error_response::ErrorResponse;
 mod exit_status;
 pub use self::exit_status::ExitStatus;
+mod health;
+pub use self::health::Health;
 mod identity;
 pub use self::identity::Identity;
 mod identity_list;
@@ -20,12 +24,247 @@ mod module_list;
 pub use self::module_list::ModuleList;
 mod module_spec;
 pub use self::module_spec::ModuleSpec;
+mod resolved_config;
+pub use self::resolved_config::ResolvedConfig;
+mod runtime_error;
+pub use self::runtime_error::RuntimeError;
+mod runtime_error_list;
+pub use self::runtime_error_list::RuntimeErrorList;
 mod runtime_status;
 pub use self::runtime_status::RuntimeStatus;
 mod status;
 pub use self::status::Status;
+mod subsystem_health;
+pub use self::subsystem_health::SubsystemHealth;
 mod system_info;
 pub use self::system_info::SystemInfo;
+mod system_resources;
+pub use self::system_resources::SystemResources;
 
 // TODO(farcaller): sort out files
 pub struct File;
+
+#[cfg(test)]
+mod tests {
+    use edgelet_test_utils::json::{assert_round_trip, with_unknown_fields, UNKNOWN_FIELD};
+    use serde_json::Value;
+
+    use super::*;
+
+    fn config() -> Value {
+        json!({
+            "settings": {
+                "image": "microsoft/azureiotedge-hub:1.0",
+                "createOptions": { "HostConfig": { "Privileged": false } },
+            },
+            "env": [{ "key": "a", "value": "b" }],
+            "volumes": ["edgehub-data"],
+        })
+    }
+
+    fn status() -> Value {
+        json!({
+            "createTime": "2018-10-01T00:00:00Z",
+            "startTime": "2018-10-01T00:00:01Z",
+            "exitStatus": { "exitTime": "2018-10-01T00:00:02Z", "statusCode": "137" },
+            "runtimeStatus": { "status": "running", "description": "up 1 minute" },
+        })
+    }
+
+    fn module_details() -> Value {
+        json!({
+            "id": "0123456789abcdef",
+            "name": "edgeHub",
+            "type": "docker",
+            "config": config(),
+            "status": status(),
+        })
+    }
+
+    fn identity() -> Value {
+        json!({
+            "moduleId": "edgeHub",
+            "managedBy": "iotedge",
+            "generationId": "636463636967581550",
+            "authType": "Sas",
+        })
+    }
+
+    fn subsystem_health() -> Value {
+        json!({
+            "status": "healthy",
+            "lastChecked": "2018-10-01T00:00:00Z",
+            "message": "message",
+        })
+    }
+
+    fn disk_usage() -> Value {
+        json!({
+            "path": "/var/lib/docker",
+            "totalBytes": 32_000_000_000_i64,
+            "availableBytes": 20_000_000_000_i64,
+            "error": "error",
+        })
+    }
+
+    fn runtime_error() -> Value {
+        json!({
+            "timestamp": "2018-10-01T00:00:00Z",
+            "operation": "create",
+            "moduleId": "edgeHub",
+            "kind": "NotFound",
+            "message": "message",
+        })
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let output = assert_round_trip::<Config>(&config());
+
+        // Configs are passed on to the module runtime and back, so later
+        // fields are kept.
+        assert_eq!(
+            with_unknown_fields(&config())[UNKNOWN_FIELD],
+            output[UNKNOWN_FIELD]
+        );
+    }
+
+    #[test]
+    fn module_spec_round_trips() {
+        let spec = json!({
+            "name": "edgeHub",
+            "type": "docker",
+            "config": config(),
+            "runtime": "sandbox",
+        });
+        let output = assert_round_trip::<ModuleSpec>(&spec);
+
+        let input = with_unknown_fields(&spec);
+        assert_eq!(input[UNKNOWN_FIELD], output[UNKNOWN_FIELD]);
+        assert_eq!(
+            input["config"][UNKNOWN_FIELD],
+            output["config"][UNKNOWN_FIELD]
+        );
+    }
+
+    #[test]
+    fn module_details_round_trip() {
+        assert_round_trip::<ModuleDetails>(&module_details());
+    }
+
+    #[test]
+    fn module_list_round_trips() {
+        assert_round_trip::<ModuleList>(&json!({
+            "modules": [module_details()],
+            "continuationToken": "ZWRnZUh1Yg==",
+        }));
+    }
+
+    #[test]
+    fn status_round_trips() {
+        assert_round_trip::<Status>(&status());
+        assert_round_trip::<ExitStatus>(&status()["exitStatus"]);
+        assert_round_trip::<RuntimeStatus>(&status()["runtimeStatus"]);
+    }
+
+    #[test]
+    fn env_var_round_trips() {
+        assert_round_trip::<EnvVar>(&json!({ "key": "a", "value": "b" }));
+    }
+
+    #[test]
+    fn resolved_config_round_trips() {
+        assert_round_trip::<ResolvedConfig>(&json!({
+            "name": "edgeHub",
+            "image": "microsoft/azureiotedge-hub:1.0",
+            "imageId": "sha256:0123456789abcdef",
+            "env": [{ "key": "a", "value": "b" }],
+            "labels": { "net.azure-devices.edge.owner": "Microsoft.Azure.Devices.Edge.Agent" },
+            "createOptions": { "HostConfig": { "Privileged": false } },
+        }));
+    }
+
+    #[test]
+    fn identity_round_trips() {
+        assert_round_trip::<Identity>(&identity());
+    }
+
+    #[test]
+    fn identity_list_round_trips() {
+        assert_round_trip::<IdentityList>(&json!({
+            "identities": [identity()],
+            "continuationToken": "ZWRnZUh1Yg==",
+        }));
+    }
+
+    #[test]
+    fn identity_spec_round_trips() {
+        assert_round_trip::<IdentitySpec>(&json!({
+            "moduleId": "edgeHub",
+            "managedBy": "iotedge",
+        }));
+    }
+
+    #[test]
+    fn update_identity_round_trips() {
+        assert_round_trip::<UpdateIdentity>(&json!({
+            "generationId": "636463636967581550",
+            "managedBy": "iotedge",
+        }));
+    }
+
+    #[test]
+    fn error_response_round_trips() {
+        assert_round_trip::<ErrorResponse>(&json!({
+            "message": "message",
+            "code": "ModuleNotFound",
+        }));
+    }
+
+    #[test]
+    fn health_round_trips() {
+        assert_round_trip::<Health>(&json!({
+            "status": "healthy",
+            "uptime": 3600,
+            "runtime": subsystem_health(),
+            "identity": subsystem_health(),
+            "provisioning": subsystem_health(),
+            "watchdog": subsystem_health(),
+            "integrity": subsystem_health(),
+        }));
+        assert_round_trip::<SubsystemHealth>(&subsystem_health());
+    }
+
+    #[test]
+    fn runtime_errors_round_trip() {
+        assert_round_trip::<RuntimeError>(&runtime_error());
+        assert_round_trip::<RuntimeErrorList>(&json!({ "errors": [runtime_error()] }));
+    }
+
+    #[test]
+    fn system_info_round_trips() {
+        assert_round_trip::<SystemInfo>(&json!({
+            "osType": "linux",
+            "architecture": "x86_64",
+            "version": "1.0.5",
+            "runtimeVersion": "3.0.1",
+            "runtimeApiVersion": "1.38",
+            "storageDriver": "overlay2",
+            "storageDriverStatus": { "Backing Filesystem": "extfs" },
+            "loggingDriver": "json-file",
+            "resources": {
+                "runtimeRoot": disk_usage(),
+                "homedir": disk_usage(),
+            },
+        }));
+        assert_round_trip::<SystemInfo>(&json!({
+            "osType": null,
+            "architecture": null,
+            "version": "1.0.5",
+            "resources": { "homedir": disk_usage() },
+            "runtimeError": "Container runtime is unavailable: connection refused",
+        }));
+        assert_round_trip::<SystemResources>(&json!({ "homedir": disk_usage() }));
+        assert_round_trip::<DiskUsage>(&disk_usage());
+    }
+}
@@ -11,7 +11,7 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ModuleDetails {
     /// System generated unique identitier.
     #[serde(rename = "id")]
@@ -54,7 +54,7 @@ impl ModuleDetails {
         self
     }
 
-    pub fn id(&self) -> &String {
+    pub fn id(&self) -> &str {
         &self.id
     }
 
@@ -67,7 +67,7 @@ impl ModuleDetails {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -80,7 +80,7 @@ impl ModuleDetails {
         self
     }
 
-    pub fn type_(&self) -> &String {
+    pub fn type_(&self) -> &str {
         &self.type_
     }
 
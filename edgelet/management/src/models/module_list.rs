@@ -15,11 +15,18 @@ use serde_json::Value;
 pub struct ModuleList {
     #[serde(rename = "modules")]
     modules: Vec<::models::ModuleDetails>,
+    /// Pass this as the continuationToken query parameter to get the next
+    /// page. Missing on the last page.
+    #[serde(rename = "continuationToken", skip_serializing_if = "Option::is_none")]
+    continuation_token: Option<String>,
 }
 
 impl ModuleList {
     pub fn new(modules: Vec<::models::ModuleDetails>) -> Self {
-        ModuleList { modules }
+        ModuleList {
+            modules,
+            continuation_token: None,
+        }
     }
 
     pub fn set_modules(&mut self, modules: Vec<::models::ModuleDetails>) {
@@ -34,4 +41,21 @@ impl ModuleList {
     pub fn modules(&self) -> &[::models::ModuleDetails] {
         &self.modules
     }
+
+    pub fn set_continuation_token(&mut self, continuation_token: String) {
+        self.continuation_token = Some(continuation_token);
+    }
+
+    pub fn with_continuation_token(mut self, continuation_token: String) -> Self {
+        self.continuation_token = Some(continuation_token);
+        self
+    }
+
+    pub fn continuation_token(&self) -> Option<&str> {
+        self.continuation_token.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_continuation_token(&mut self) {
+        self.continuation_token = None;
+    }
 }
@@ -11,7 +11,7 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ModuleSpec {
     /// The name of a the module.
     #[serde(rename = "name")]
@@ -20,6 +20,16 @@ pub struct ModuleSpec {
     type_: String,
     #[serde(rename = "config")]
     config: ::models::Config,
+    /// The container engine the module runs on. The primary engine if not set.
+    #[serde(
+        rename = "runtime",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime: Option<String>,
+    /// Fields of later versions of the API, kept so that specs passed on
+    /// keep them too.
+    #[serde(flatten)]
+    other: ::serde_json::Map<String, Value>,
 }
 
 impl ModuleSpec {
@@ -28,6 +38,8 @@ impl ModuleSpec {
             name,
             type_,
             config,
+            runtime: None,
+            other: ::serde_json::Map::new(),
         }
     }
 
@@ -40,7 +52,7 @@ impl ModuleSpec {
         self
     }
 
-    pub fn name(&self) -> &String {
+    pub fn name(&self) -> &str {
         &self.name
     }
 
@@ -53,7 +65,7 @@ impl ModuleSpec {
         self
     }
 
-    pub fn type_(&self) -> &String {
+    pub fn type_(&self) -> &str {
         &self.type_
     }
 
@@ -69,4 +81,21 @@ impl ModuleSpec {
     pub fn config(&self) -> &::models::Config {
         &self.config
     }
+
+    pub fn set_runtime(&mut self, runtime: String) {
+        self.runtime = Some(runtime);
+    }
+
+    pub fn with_runtime(mut self, runtime: String) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn runtime(&self) -> Option<&str> {
+        self.runtime.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_runtime(&mut self) {
+        self.runtime = None;
+    }
 }
@@ -0,0 +1,134 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolvedConfig {
+    /// The name of the module.
+    #[serde(rename = "name")]
+    name: String,
+    /// The image the module runs.
+    #[serde(rename = "image")]
+    image: String,
+    /// The ID of the image the module runs.
+    #[serde(rename = "imageId", skip_serializing_if = "Option::is_none")]
+    image_id: Option<String>,
+    /// The environment the module runs with, with the values of secrets redacted.
+    #[serde(rename = "env")]
+    env: Vec<::models::EnvVar>,
+    #[serde(rename = "labels")]
+    labels: ::std::collections::HashMap<String, String>,
+    /// The options the module was created with, as the module runtime reports them.
+    #[serde(rename = "createOptions")]
+    create_options: Value,
+}
+
+impl ResolvedConfig {
+    pub fn new(
+        name: String,
+        image: String,
+        env: Vec<::models::EnvVar>,
+        labels: ::std::collections::HashMap<String, String>,
+        create_options: Value,
+    ) -> Self {
+        ResolvedConfig {
+            name,
+            image,
+            image_id: None,
+            env,
+            labels,
+            create_options,
+        }
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_image(&mut self, image: String) {
+        self.image = image;
+    }
+
+    pub fn with_image(mut self, image: String) -> Self {
+        self.image = image;
+        self
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    pub fn set_image_id(&mut self, image_id: String) {
+        self.image_id = Some(image_id);
+    }
+
+    pub fn with_image_id(mut self, image_id: String) -> Self {
+        self.image_id = Some(image_id);
+        self
+    }
+
+    pub fn image_id(&self) -> Option<&str> {
+        self.image_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_image_id(&mut self) {
+        self.image_id = None;
+    }
+
+    pub fn set_env(&mut self, env: Vec<::models::EnvVar>) {
+        self.env = env;
+    }
+
+    pub fn with_env(mut self, env: Vec<::models::EnvVar>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn env(&self) -> &Vec<::models::EnvVar> {
+        &self.env
+    }
+
+    pub fn set_labels(&mut self, labels: ::std::collections::HashMap<String, String>) {
+        self.labels = labels;
+    }
+
+    pub fn with_labels(mut self, labels: ::std::collections::HashMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn labels(&self) -> &::std::collections::HashMap<String, String> {
+        &self.labels
+    }
+
+    pub fn set_create_options(&mut self, create_options: Value) {
+        self.create_options = create_options;
+    }
+
+    pub fn with_create_options(mut self, create_options: Value) -> Self {
+        self.create_options = create_options;
+        self
+    }
+
+    pub fn create_options(&self) -> &Value {
+        &self.create_options
+    }
+}
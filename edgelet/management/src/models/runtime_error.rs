@@ -0,0 +1,107 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuntimeError {
+    #[serde(rename = "timestamp")]
+    timestamp: String,
+    #[serde(rename = "operation")]
+    operation: String,
+    #[serde(rename = "moduleId", skip_serializing_if = "Option::is_none")]
+    module_id: Option<String>,
+    #[serde(rename = "kind")]
+    kind: String,
+    #[serde(rename = "message")]
+    message: String,
+}
+
+impl RuntimeError {
+    pub fn new(timestamp: String, operation: String, kind: String, message: String) -> Self {
+        RuntimeError {
+            timestamp,
+            operation,
+            module_id: None,
+            kind,
+            message,
+        }
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: String) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn with_timestamp(mut self, timestamp: String) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
+
+    pub fn set_operation(&mut self, operation: String) {
+        self.operation = operation;
+    }
+
+    pub fn with_operation(mut self, operation: String) -> Self {
+        self.operation = operation;
+        self
+    }
+
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    pub fn set_module_id(&mut self, module_id: String) {
+        self.module_id = Some(module_id);
+    }
+
+    pub fn with_module_id(mut self, module_id: String) -> Self {
+        self.module_id = Some(module_id);
+        self
+    }
+
+    pub fn module_id(&self) -> Option<&str> {
+        self.module_id.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_module_id(&mut self) {
+        self.module_id = None;
+    }
+
+    pub fn set_kind(&mut self, kind: String) {
+        self.kind = kind;
+    }
+
+    pub fn with_kind(mut self, kind: String) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = message;
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
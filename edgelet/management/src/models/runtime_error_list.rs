@@ -0,0 +1,37 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuntimeErrorList {
+    #[serde(rename = "errors")]
+    errors: Vec<::models::RuntimeError>,
+}
+
+impl RuntimeErrorList {
+    pub fn new(errors: Vec<::models::RuntimeError>) -> Self {
+        RuntimeErrorList { errors }
+    }
+
+    pub fn set_errors(&mut self, errors: Vec<::models::RuntimeError>) {
+        self.errors = errors;
+    }
+
+    pub fn with_errors(mut self, errors: Vec<::models::RuntimeError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn errors(&self) -> &[::models::RuntimeError] {
+        &self.errors
+    }
+}
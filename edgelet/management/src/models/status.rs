@@ -11,8 +11,13 @@
 #[allow(unused_imports)]
 use serde_json::Value;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
+    #[serde(
+        rename = "createTime",
+        skip_serializing_if = "Option::is_none"
+    )]
+    create_time: Option<String>,
     #[serde(
         rename = "startTime",
         skip_serializing_if = "Option::is_none"
@@ -30,12 +35,30 @@ pub struct Status {
 impl Status {
     pub fn new(runtime_status: ::models::RuntimeStatus) -> Self {
         Status {
+            create_time: None,
             start_time: None,
             exit_status: None,
             runtime_status,
         }
     }
 
+    pub fn set_create_time(&mut self, create_time: String) {
+        self.create_time = Some(create_time);
+    }
+
+    pub fn with_create_time(mut self, create_time: String) -> Self {
+        self.create_time = Some(create_time);
+        self
+    }
+
+    pub fn create_time(&self) -> Option<&str> {
+        self.create_time.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_create_time(&mut self) {
+        self.create_time = None;
+    }
+
     pub fn set_start_time(&mut self, start_time: String) {
         self.start_time = Some(start_time);
     }
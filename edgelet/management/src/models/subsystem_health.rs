@@ -0,0 +1,85 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubsystemHealth {
+    #[serde(rename = "status")]
+    status: String,
+    #[serde(
+        rename = "lastChecked",
+        skip_serializing_if = "Option::is_none"
+    )]
+    last_checked: Option<String>,
+    #[serde(
+        rename = "message",
+        skip_serializing_if = "Option::is_none"
+    )]
+    message: Option<String>,
+}
+
+impl SubsystemHealth {
+    pub fn new(status: String) -> Self {
+        SubsystemHealth {
+            status,
+            last_checked: None,
+            message: None,
+        }
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    pub fn with_status(mut self, status: String) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn set_last_checked(&mut self, last_checked: String) {
+        self.last_checked = Some(last_checked);
+    }
+
+    pub fn with_last_checked(mut self, last_checked: String) -> Self {
+        self.last_checked = Some(last_checked);
+        self
+    }
+
+    pub fn last_checked(&self) -> Option<&str> {
+        self.last_checked.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_last_checked(&mut self) {
+        self.last_checked = None;
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = Some(message);
+        self
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_message(&mut self) {
+        self.message = None;
+    }
+}
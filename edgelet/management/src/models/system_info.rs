@@ -13,47 +13,99 @@ use serde_json::Value;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SystemInfo {
+    /// Null when the container runtime could not be queried.
     #[serde(rename = "osType")]
-    os_type: String,
+    os_type: Option<String>,
+    /// Null when the container runtime could not be queried.
     #[serde(rename = "architecture")]
-    architecture: String,
+    architecture: Option<String>,
     #[serde(rename = "version")]
     version: String,
+    #[serde(
+        rename = "runtimeVersion",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime_version: Option<String>,
+    #[serde(
+        rename = "runtimeApiVersion",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime_api_version: Option<String>,
+    #[serde(
+        rename = "storageDriver",
+        skip_serializing_if = "Option::is_none"
+    )]
+    storage_driver: Option<String>,
+    #[serde(
+        rename = "storageDriverStatus",
+        skip_serializing_if = "Option::is_none"
+    )]
+    storage_driver_status: Option<::std::collections::HashMap<String, String>>,
+    #[serde(
+        rename = "loggingDriver",
+        skip_serializing_if = "Option::is_none"
+    )]
+    logging_driver: Option<String>,
+    #[serde(
+        rename = "resources",
+        skip_serializing_if = "Option::is_none"
+    )]
+    resources: Option<::models::SystemResources>,
+    #[serde(
+        rename = "runtimeError",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime_error: Option<String>,
 }
 
 impl SystemInfo {
-    pub fn new(os_type: String, architecture: String, version: String) -> Self {
+    pub fn new(version: String) -> Self {
         SystemInfo {
-            os_type,
-            architecture,
+            os_type: None,
+            architecture: None,
             version,
+            runtime_version: None,
+            runtime_api_version: None,
+            storage_driver: None,
+            storage_driver_status: None,
+            logging_driver: None,
+            resources: None,
+            runtime_error: None,
         }
     }
 
     pub fn set_os_type(&mut self, os_type: String) {
-        self.os_type = os_type;
+        self.os_type = Some(os_type);
     }
 
     pub fn with_os_type(mut self, os_type: String) -> Self {
-        self.os_type = os_type;
+        self.os_type = Some(os_type);
         self
     }
 
-    pub fn os_type(&self) -> &String {
-        &self.os_type
+    pub fn os_type(&self) -> Option<&str> {
+        self.os_type.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_os_type(&mut self) {
+        self.os_type = None;
     }
 
     pub fn set_architecture(&mut self, architecture: String) {
-        self.architecture = architecture;
+        self.architecture = Some(architecture);
     }
 
     pub fn with_architecture(mut self, architecture: String) -> Self {
-        self.architecture = architecture;
+        self.architecture = Some(architecture);
         self
     }
 
-    pub fn architecture(&self) -> &String {
-        &self.architecture
+    pub fn architecture(&self) -> Option<&str> {
+        self.architecture.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_architecture(&mut self) {
+        self.architecture = None;
     }
 
     pub fn set_version(&mut self, version: String) {
@@ -65,7 +117,132 @@ impl SystemInfo {
         self
     }
 
-    pub fn version(&self) -> &String {
+    pub fn version(&self) -> &str {
         &self.version
     }
+
+    pub fn set_runtime_version(&mut self, runtime_version: String) {
+        self.runtime_version = Some(runtime_version);
+    }
+
+    pub fn with_runtime_version(mut self, runtime_version: String) -> Self {
+        self.runtime_version = Some(runtime_version);
+        self
+    }
+
+    pub fn runtime_version(&self) -> Option<&str> {
+        self.runtime_version.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_runtime_version(&mut self) {
+        self.runtime_version = None;
+    }
+
+    pub fn set_runtime_api_version(&mut self, runtime_api_version: String) {
+        self.runtime_api_version = Some(runtime_api_version);
+    }
+
+    pub fn with_runtime_api_version(mut self, runtime_api_version: String) -> Self {
+        self.runtime_api_version = Some(runtime_api_version);
+        self
+    }
+
+    pub fn runtime_api_version(&self) -> Option<&str> {
+        self.runtime_api_version.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_runtime_api_version(&mut self) {
+        self.runtime_api_version = None;
+    }
+
+    pub fn set_storage_driver(&mut self, storage_driver: String) {
+        self.storage_driver = Some(storage_driver);
+    }
+
+    pub fn with_storage_driver(mut self, storage_driver: String) -> Self {
+        self.storage_driver = Some(storage_driver);
+        self
+    }
+
+    pub fn storage_driver(&self) -> Option<&str> {
+        self.storage_driver.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_storage_driver(&mut self) {
+        self.storage_driver = None;
+    }
+
+    pub fn set_storage_driver_status(
+        &mut self,
+        storage_driver_status: ::std::collections::HashMap<String, String>,
+    ) {
+        self.storage_driver_status = Some(storage_driver_status);
+    }
+
+    pub fn with_storage_driver_status(
+        mut self,
+        storage_driver_status: ::std::collections::HashMap<String, String>,
+    ) -> Self {
+        self.storage_driver_status = Some(storage_driver_status);
+        self
+    }
+
+    pub fn storage_driver_status(&self) -> Option<&::std::collections::HashMap<String, String>> {
+        self.storage_driver_status.as_ref()
+    }
+
+    pub fn reset_storage_driver_status(&mut self) {
+        self.storage_driver_status = None;
+    }
+
+    pub fn set_logging_driver(&mut self, logging_driver: String) {
+        self.logging_driver = Some(logging_driver);
+    }
+
+    pub fn with_logging_driver(mut self, logging_driver: String) -> Self {
+        self.logging_driver = Some(logging_driver);
+        self
+    }
+
+    pub fn logging_driver(&self) -> Option<&str> {
+        self.logging_driver.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_logging_driver(&mut self) {
+        self.logging_driver = None;
+    }
+
+    pub fn set_resources(&mut self, resources: ::models::SystemResources) {
+        self.resources = Some(resources);
+    }
+
+    pub fn with_resources(mut self, resources: ::models::SystemResources) -> Self {
+        self.resources = Some(resources);
+        self
+    }
+
+    pub fn resources(&self) -> Option<&::models::SystemResources> {
+        self.resources.as_ref()
+    }
+
+    pub fn reset_resources(&mut self) {
+        self.resources = None;
+    }
+
+    pub fn set_runtime_error(&mut self, runtime_error: String) {
+        self.runtime_error = Some(runtime_error);
+    }
+
+    pub fn with_runtime_error(mut self, runtime_error: String) -> Self {
+        self.runtime_error = Some(runtime_error);
+        self
+    }
+
+    pub fn runtime_error(&self) -> Option<&str> {
+        self.runtime_error.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_runtime_error(&mut self) {
+        self.runtime_error = None;
+    }
 }
@@ -0,0 +1,69 @@
+/*
+ * IoT Edge Management API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SystemResources {
+    #[serde(
+        rename = "runtimeRoot",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime_root: Option<::models::DiskUsage>,
+    #[serde(
+        rename = "homedir",
+        skip_serializing_if = "Option::is_none"
+    )]
+    homedir: Option<::models::DiskUsage>,
+}
+
+impl SystemResources {
+    pub fn new() -> Self {
+        SystemResources {
+            runtime_root: None,
+            homedir: None,
+        }
+    }
+
+    pub fn set_runtime_root(&mut self, runtime_root: ::models::DiskUsage) {
+        self.runtime_root = Some(runtime_root);
+    }
+
+    pub fn with_runtime_root(mut self, runtime_root: ::models::DiskUsage) -> Self {
+        self.runtime_root = Some(runtime_root);
+        self
+    }
+
+    pub fn runtime_root(&self) -> Option<&::models::DiskUsage> {
+        self.runtime_root.as_ref()
+    }
+
+    pub fn reset_runtime_root(&mut self) {
+        self.runtime_root = None;
+    }
+
+    pub fn set_homedir(&mut self, homedir: ::models::DiskUsage) {
+        self.homedir = Some(homedir);
+    }
+
+    pub fn with_homedir(mut self, homedir: ::models::DiskUsage) -> Self {
+        self.homedir = Some(homedir);
+        self
+    }
+
+    pub fn homedir(&self) -> Option<&::models::DiskUsage> {
+        self.homedir.as_ref()
+    }
+
+    pub fn reset_homedir(&mut self) {
+        self.homedir = None;
+    }
+}
@@ -39,7 +39,7 @@ impl UpdateIdentity {
         self
     }
 
-    pub fn generation_id(&self) -> &String {
+    pub fn generation_id(&self) -> &str {
         &self.generation_id
     }
 
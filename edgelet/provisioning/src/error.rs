@@ -44,6 +44,12 @@ pub enum ErrorKind {
     Io,
     #[fail(display = "Serde error")]
     Serde,
+    #[fail(display = "Invalid registration id: {}", _0)]
+    InvalidRegistrationId(String),
+    #[fail(display = "Could not read the registration id from {}", _0)]
+    RegistrationIdFile(String),
+    #[fail(display = "Could not get the hostname of the device")]
+    Hostname,
 }
 
 impl Fail for Error {
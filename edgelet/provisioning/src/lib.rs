@@ -15,9 +15,12 @@ extern crate bytes;
 extern crate failure;
 extern crate futures;
 extern crate hsm;
+#[cfg(test)]
+extern crate hyper;
 #[macro_use]
 extern crate log;
 extern crate regex;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
@@ -36,6 +39,11 @@ extern crate edgelet_utils;
 
 pub mod error;
 pub mod provisioning;
+pub mod registration_id;
 
 pub use error::Error;
-pub use provisioning::{BackupProvisioning, DpsProvisioning, Provision, ProvisioningResult};
+pub use provisioning::{
+    derive_device_key, BackupProvisioning, DpsProvisioning, DpsSymmetricKeyProvisioning,
+    Provision, ProvisioningResult,
+};
+pub use registration_id::RegistrationIdSource;
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use base64;
 use bytes::Bytes;
@@ -15,7 +16,11 @@ use serde_json;
 use url::Url;
 
 use dps::registration::{DpsClient, DpsTokenSource};
-use edgelet_core::crypto::{Activate, KeyIdentity, KeyStore, MemoryKey, MemoryKeyStore};
+use edgelet_core::crypto::{
+    Activate, KeyIdentity, KeyStore, MemoryKey, MemoryKeyStore, Sign, Signature,
+    SignatureAlgorithm,
+};
+use edgelet_core::AuthType;
 use edgelet_hsm::tpm::{TpmKey, TpmKeyStore};
 use edgelet_http::client::{Client as HttpClient, ClientImpl};
 use edgelet_utils::log_failure;
@@ -35,10 +40,16 @@ const SHAREDACCESSKEY_REGEX: &str = r"SharedAccessKey=(.+)";
 pub struct ProvisioningResult {
     device_id: String,
     hub_name: String,
+    #[serde(default = "sas")]
+    auth_type: AuthType,
     #[serde(skip)]
     reconfigure: bool,
 }
 
+fn sas() -> AuthType {
+    AuthType::Sas
+}
+
 impl ProvisioningResult {
     pub fn device_id(&self) -> &str {
         &self.device_id
@@ -48,6 +59,11 @@ impl ProvisioningResult {
         &self.hub_name
     }
 
+    /// How the device authenticates with IoT Hub.
+    pub fn auth_type(&self) -> AuthType {
+        self.auth_type
+    }
+
     pub fn reconfigure(&self) -> bool {
         self.reconfigure
     }
@@ -67,6 +83,7 @@ pub struct ManualProvisioning {
     key: MemoryKey,
     device_id: String,
     hub: String,
+    auth_type: AuthType,
 }
 
 impl ManualProvisioning {
@@ -92,6 +109,21 @@ impl ManualProvisioning {
             key,
             device_id: device_id.to_owned(),
             hub: hub.to_owned(),
+            auth_type: AuthType::Sas,
+        };
+        Ok(result)
+    }
+
+    /// Provisions a device that authenticates with IoT Hub using an X.509
+    /// identity certificate instead of a shared access key. Such a device
+    /// has no key to derive the keys of its modules from, so `key` is used
+    /// for that instead.
+    pub fn with_x509(hub: &str, device_id: &str, key: MemoryKey) -> Result<Self, Error> {
+        let result = ManualProvisioning {
+            key,
+            device_id: ensure_not_empty!(device_id, "The device id is empty").to_string(),
+            hub: ensure_not_empty!(hub, "The IoT Hub hostname is empty").to_string(),
+            auth_type: AuthType::X509,
         };
         Ok(result)
     }
@@ -128,6 +160,7 @@ impl Provision for ManualProvisioning {
             key,
             device_id,
             hub,
+            auth_type,
         } = self;
 
         info!(
@@ -139,6 +172,7 @@ impl Provision for ManualProvisioning {
             .map(|_| ProvisioningResult {
                 device_id,
                 hub_name: hub,
+                auth_type,
                 reconfigure: false,
             }).map_err(Error::from);
         Box::new(future::result(result))
@@ -185,6 +219,11 @@ where
         };
         Ok(result)
     }
+
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.with_request_timeout(timeout);
+        self
+    }
 }
 
 impl<C> Provision for DpsProvisioning<C>
@@ -215,6 +254,7 @@ where
                         ProvisioningResult {
                             device_id,
                             hub_name,
+                            auth_type: AuthType::Sas,
                             reconfigure: false,
                         }
                     }).map_err(Error::from),
@@ -226,6 +266,109 @@ where
     }
 }
 
+/// Provisions a device of a symmetric key enrollment group in DPS. The
+/// device attests with a key derived from the key of the group, which it
+/// then keeps as its device key.
+pub struct DpsSymmetricKeyProvisioning<C>
+where
+    C: ClientImpl,
+{
+    client: HttpClient<C, DpsTokenSource<MemoryKey>>,
+    scope_id: String,
+    registration_id: String,
+    group_key: MemoryKey,
+}
+
+impl<C> DpsSymmetricKeyProvisioning<C>
+where
+    C: ClientImpl,
+{
+    pub fn new(
+        client_impl: C,
+        endpoint: Url,
+        scope_id: String,
+        registration_id: String,
+        group_key: MemoryKey,
+        api_version: &str,
+    ) -> Result<Self, Error> {
+        let client = HttpClient::new(
+            client_impl,
+            None as Option<DpsTokenSource<MemoryKey>>,
+            &api_version,
+            endpoint,
+        )?;
+
+        let result = DpsSymmetricKeyProvisioning {
+            client,
+            scope_id,
+            registration_id,
+            group_key,
+        };
+        Ok(result)
+    }
+
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.with_request_timeout(timeout);
+        self
+    }
+}
+
+impl<C> Provision for DpsSymmetricKeyProvisioning<C>
+where
+    C: 'static + ClientImpl,
+{
+    type Hsm = MemoryKeyStore;
+
+    fn provision(
+        self,
+        mut key_activator: Self::Hsm,
+    ) -> Box<Future<Item = ProvisioningResult, Error = Error> + Send> {
+        let DpsSymmetricKeyProvisioning {
+            client,
+            scope_id,
+            registration_id,
+            group_key,
+        } = self;
+
+        let d = derive_device_key(&group_key, &registration_id)
+            .and_then(|key| {
+                key_activator
+                    .activate_identity_key(KeyIdentity::Device, "primary".to_string(), key)
+                    .map_err(Error::from)
+            }).and_then(|_| {
+                DpsClient::with_symmetric_key(client, scope_id, registration_id, key_activator)
+                    .map_err(Error::from)
+            });
+        let d = match d {
+            Ok(c) => Either::A(
+                c.register()
+                    .map(|(device_id, hub_name)| {
+                        info!(
+                            "DPS registration assigned device \"{}\" in hub \"{}\"",
+                            device_id, hub_name
+                        );
+                        ProvisioningResult {
+                            device_id,
+                            hub_name,
+                            auth_type: AuthType::Sas,
+                            reconfigure: false,
+                        }
+                    }).map_err(Error::from),
+            ),
+            Err(err) => Either::B(future::err(err)),
+        };
+
+        Box::new(d)
+    }
+}
+
+/// The key of a device in a symmetric key enrollment group: the HMAC-SHA256
+/// of its registration id, keyed with the key of the group.
+pub fn derive_device_key(group_key: &MemoryKey, registration_id: &str) -> Result<MemoryKey, Error> {
+    let signature = group_key.sign(SignatureAlgorithm::HMACSHA256, registration_id.as_bytes())?;
+    Ok(MemoryKey::new(signature.as_bytes()))
+}
+
 pub struct BackupProvisioning<P>
 where
     P: 'static + Provision,
@@ -303,6 +446,8 @@ where
 mod tests {
     use super::*;
 
+    use dps::{DeviceRegistrationResult, RegistrationOperationStatus};
+    use hyper::{Body, Method, Request, Response};
     use tempdir::TempDir;
     use tokio;
 
@@ -320,6 +465,7 @@ mod tests {
             Box::new(future::ok(ProvisioningResult {
                 device_id: "TestDevice".to_string(),
                 hub_name: "TestHub".to_string(),
+                auth_type: AuthType::Sas,
                 reconfigure: false,
             }))
         }
@@ -363,6 +509,87 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn manual_x509_provisions_with_x509_auth() {
+        let provisioning =
+            ManualProvisioning::with_x509("test.com", "test", MemoryKey::new("key")).unwrap();
+        let memory_hsm = MemoryKeyStore::new();
+        let task = provisioning.provision(memory_hsm.clone());
+        let result = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(task)
+            .unwrap();
+
+        assert_eq!("test.com", result.hub_name());
+        assert_eq!("test", result.device_id());
+        assert_eq!(AuthType::X509, result.auth_type());
+        let key = memory_hsm.get(&KeyIdentity::Device, "primary").unwrap();
+        assert_eq!(b"key", key.as_ref());
+    }
+
+    #[test]
+    fn group_keys_derive_device_keys() {
+        let key = derive_device_key(&MemoryKey::new("group key"), "edge-device-01").unwrap();
+        assert_eq!(
+            "/wNX3nErTr17ifB28EKtemLo1bvKGMyTbuK3JAg1PMA=",
+            base64::encode(key.as_ref())
+        );
+    }
+
+    #[test]
+    fn dps_symmetric_key_provisions_with_the_derived_key() {
+        let handler = |req: Request<Body>| {
+            let status = if req.method() == Method::PUT {
+                RegistrationOperationStatus::new("operation".to_string())
+                    .with_status("assigning".to_string())
+            } else {
+                RegistrationOperationStatus::new("operation".to_string()).with_registration_state(
+                    DeviceRegistrationResult::new(
+                        "edge-device-01".to_string(),
+                        "assigned".to_string(),
+                    ).with_device_id("edge-device-01".to_string())
+                    .with_assigned_hub("hub.azure-devices.net".to_string()),
+                )
+            };
+            future::ok(Response::new(serde_json::to_string(&status).unwrap().into()))
+        };
+        let provisioning = DpsSymmetricKeyProvisioning::new(
+            handler,
+            Url::parse("https://global.azure-devices-provisioning.net/").unwrap(),
+            "scope".to_string(),
+            "edge-device-01".to_string(),
+            MemoryKey::new("group key"),
+            "2017-11-15",
+        ).unwrap();
+        let memory_hsm = MemoryKeyStore::new();
+        let result = tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(provisioning.provision(memory_hsm.clone()))
+            .unwrap();
+
+        assert_eq!("hub.azure-devices.net", result.hub_name());
+        assert_eq!("edge-device-01", result.device_id());
+        assert_eq!(AuthType::Sas, result.auth_type());
+        let key = memory_hsm.get(&KeyIdentity::Device, "primary").unwrap();
+        assert_eq!(
+            "/wNX3nErTr17ifB28EKtemLo1bvKGMyTbuK3JAg1PMA=",
+            base64::encode(key.as_ref())
+        );
+    }
+
+    #[test]
+    fn manual_x509_without_device_id_gets_error() {
+        let test = ManualProvisioning::with_x509("test.com", "", MemoryKey::new("key"));
+        assert_eq!(test.is_err(), true);
+    }
+
+    #[test]
+    fn prov_result_without_auth_type_is_sas() {
+        let json = "{\"device_id\":\"something\",\"hub_name\":\"something\"}";
+        let result: ProvisioningResult = serde_json::from_str(json).unwrap();
+        assert_eq!(AuthType::Sas, result.auth_type());
+    }
+
     #[test]
     fn manual_malformed_conn_string_gets_error() {
         let test = ManualProvisioning::new("HostName=test.com;DeviceId=test;");
@@ -515,10 +742,11 @@ mod tests {
         let json = serde_json::to_string(&ProvisioningResult {
             device_id: "something".to_string(),
             hub_name: "something".to_string(),
+            auth_type: AuthType::Sas,
             reconfigure: true,
         }).unwrap();
         assert_eq!(
-            "{\"device_id\":\"something\",\"hub_name\":\"something\"}",
+            "{\"device_id\":\"something\",\"hub_name\":\"something\",\"auth_type\":\"Sas\"}",
             json
         );
         let result: ProvisioningResult = serde_json::from_str(&json).unwrap();
@@ -0,0 +1,250 @@
+// Copyright (c) Microsoft. All rights reserved.
+
+//! Devices enrolled as a group have no registration id of their own in the
+//! config file. They derive one instead, from their hostname or from a file
+//! such as one holding the serial number of the device, and normalize it to
+//! the characters DPS allows.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use failure::ResultExt;
+use serde::de::{Deserialize, Deserializer, Error as DeError};
+use serde::ser::{Serialize, Serializer};
+
+use error::{Error, ErrorKind};
+
+const FILE_PREFIX: &str = "file:";
+
+/// DPS registration ids are at most this many characters long.
+const MAX_LENGTH: usize = 128;
+
+/// Where the registration id of the device comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RegistrationIdSource {
+    /// The `registration_id` of the config file.
+    Explicit,
+    /// The hostname of the device.
+    Hostname,
+    /// The contents of a file.
+    File(PathBuf),
+}
+
+impl RegistrationIdSource {
+    /// Finds the registration id, normalized to what DPS allows. `explicit`
+    /// is the registration id of the config file, if it has one, and
+    /// `hostname` asks the operating system for the name of the device.
+    pub fn resolve<F>(&self, explicit: Option<&str>, hostname: F) -> Result<String, Error>
+    where
+        F: FnOnce() -> io::Result<String>,
+    {
+        let original = match *self {
+            RegistrationIdSource::Explicit => match explicit {
+                Some(id) => id.to_string(),
+                None => {
+                    return Err(Error::from(ErrorKind::InvalidRegistrationId(
+                        "no registration id is configured".to_string(),
+                    )))
+                }
+            },
+            RegistrationIdSource::Hostname => hostname().context(ErrorKind::Hostname)?,
+            RegistrationIdSource::File(ref path) => fs::read_to_string(path)
+                .context(ErrorKind::RegistrationIdFile(path.display().to_string()))?,
+        };
+
+        let normalized = normalize(&original);
+        if normalized.is_empty() {
+            return Err(Error::from(ErrorKind::InvalidRegistrationId(format!(
+                "\"{}\" has no characters DPS allows",
+                original.trim()
+            ))));
+        }
+        if normalized != original.trim() {
+            // Devices whose ids only differ in case or in characters DPS does
+            // not allow end up with the same registration.
+            warn!(
+                "Registration id \"{}\" was normalized to \"{}\", which other devices in the \
+                 enrollment group may derive too",
+                original.trim(),
+                normalized
+            );
+        }
+        Ok(normalized)
+    }
+}
+
+impl Default for RegistrationIdSource {
+    fn default() -> Self {
+        RegistrationIdSource::Explicit
+    }
+}
+
+impl FromStr for RegistrationIdSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "explicit" => Ok(RegistrationIdSource::Explicit),
+            "hostname" => Ok(RegistrationIdSource::Hostname),
+            _ if s.starts_with(FILE_PREFIX) && s.len() > FILE_PREFIX.len() => Ok(
+                RegistrationIdSource::File(PathBuf::from(&s[FILE_PREFIX.len()..])),
+            ),
+            _ => Err(format!(
+                "unknown registration id source \"{}\", expected \"explicit\", \"hostname\" \
+                 or \"file:<path>\"",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RegistrationIdSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistrationIdSource::Explicit => write!(f, "explicit"),
+            RegistrationIdSource::Hostname => write!(f, "hostname"),
+            RegistrationIdSource::File(ref path) => write!(f, "{}{}", FILE_PREFIX, path.display()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RegistrationIdSource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for RegistrationIdSource {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Lowercases `id` and replaces the characters DPS does not allow with
+/// `-`. Surrounding whitespace, like the newline a file ends with, is left
+/// out.
+fn normalize(id: &str) -> String {
+    id.trim()
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ 'a'...'z' | c @ '0'...'9' | c @ '-' | c @ '.' | c @ '_' | c @ ':' => c,
+            _ => '-',
+        }).take(MAX_LENGTH)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn resolve(source: &RegistrationIdSource, explicit: Option<&str>) -> Result<String, Error> {
+        source.resolve(explicit, || Ok("edge-device-01".to_string()))
+    }
+
+    fn resolve_hostname(hostname: &str) -> Result<String, Error> {
+        RegistrationIdSource::Hostname.resolve(None, || Ok(hostname.to_string()))
+    }
+
+    #[test]
+    fn sources_are_parsed() {
+        assert_eq!(Ok(RegistrationIdSource::Explicit), "explicit".parse());
+        assert_eq!(Ok(RegistrationIdSource::Hostname), "hostname".parse());
+        assert_eq!(
+            Ok(RegistrationIdSource::File(PathBuf::from("/etc/serial"))),
+            "file:/etc/serial".parse()
+        );
+        for source in &["", "file:", "Hostname", "serial"] {
+            assert!(source.parse::<RegistrationIdSource>().is_err(), "{}", source);
+        }
+        assert_eq!(
+            "file:/etc/serial",
+            RegistrationIdSource::File(PathBuf::from("/etc/serial")).to_string()
+        );
+    }
+
+    #[test]
+    fn explicit_ids_come_from_the_config() {
+        let source = RegistrationIdSource::Explicit;
+        assert_eq!("device-a", resolve(&source, Some("device-a")).unwrap());
+        match *resolve(&source, None).unwrap_err().kind() {
+            ErrorKind::InvalidRegistrationId(_) => (),
+            ref kind => panic!("Expected an invalid registration id, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn hostname_ids_come_from_the_hostname() {
+        let source = RegistrationIdSource::Hostname;
+        assert_eq!("edge-device-01", resolve(&source, Some("device-a")).unwrap());
+
+        let err = source
+            .resolve(None, || Err(io::Error::from(io::ErrorKind::Other)))
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::Hostname => (),
+            ref kind => panic!("Expected a hostname error, got {:?}", kind),
+        }
+
+        // The hostname is only looked up when it is needed.
+        let source = RegistrationIdSource::Explicit;
+        assert_eq!(
+            "device-a",
+            source
+                .resolve(Some("device-a"), || panic!("hostname looked up"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn file_ids_come_from_the_file() {
+        let dir = TempDir::new("registration_id").unwrap();
+        let path = dir.path().join("serial");
+        fs::write(&path, "SN-0042\n").unwrap();
+        let source = RegistrationIdSource::File(path);
+        assert_eq!("sn-0042", resolve(&source, None).unwrap());
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let dir = TempDir::new("registration_id").unwrap();
+        let path = dir.path().join("serial");
+        let source = RegistrationIdSource::File(path.clone());
+        let err = resolve(&source, None).unwrap_err();
+        match *err.kind() {
+            ErrorKind::RegistrationIdFile(ref file) => {
+                assert_eq!(&path.display().to_string(), file)
+            }
+            ref kind => panic!("Expected a file error, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn odd_hostnames_are_normalized() {
+        for (hostname, expected) in &[
+            ("Edge-Device-01", "edge-device-01"),
+            ("edge device #1", "edge-device--1"),
+            ("edge.contoso.local", "edge.contoso.local"),
+            ("  edge_01:a  ", "edge_01:a"),
+            ("édge", "-dge"),
+        ] {
+            assert_eq!(*expected, resolve_hostname(hostname).unwrap(), "{}", hostname);
+        }
+
+        assert_eq!(MAX_LENGTH, resolve_hostname(&"a".repeat(200)).unwrap().len());
+        assert!(resolve_hostname("  ").is_err());
+    }
+}
@@ -56,6 +56,13 @@ pub trait WorkloadApi {
         genid: &str,
         payload: ::models::EncryptRequest,
     ) -> Box<Future<Item = ::models::EncryptResponse, Error = Error<serde_json::Value>>>;
+    fn renew_certificate(
+        &self,
+        api_version: &str,
+        name: &str,
+        genid: &str,
+        request: ::models::RenewCertificateRequest,
+    ) -> Box<Future<Item = ::models::CertificateResponse, Error = Error<serde_json::Value>>>;
     fn sign(
         &self,
         api_version: &str,
@@ -341,6 +348,73 @@ where
         )
     }
 
+    fn renew_certificate(
+        &self,
+        api_version: &str,
+        name: &str,
+        genid: &str,
+        request: ::models::RenewCertificateRequest,
+    ) -> Box<Future<Item = ::models::CertificateResponse, Error = Error<serde_json::Value>>> {
+        let configuration: &configuration::Configuration<C> = self.configuration.borrow();
+
+        let method = hyper::Method::POST;
+
+        let query = ::url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("api-version", &api_version.to_string())
+            .finish();
+        let uri_str = format!(
+            "/modules/{name}/genid/{genid}/certificate/renew?{}",
+            query,
+            name = name,
+            genid = genid
+        );
+
+        let uri = (configuration.uri_composer)(&configuration.base_path, &uri_str);
+        // TODO(farcaller): handle error
+        // if let Err(e) = uri {
+        //     return Box::new(futures::future::err(e));
+        // }
+        let serialized = serde_json::to_string(&request).unwrap();
+        let serialized_len = serialized.len();
+
+        let mut req = hyper::Request::builder();
+        req.method(method).uri(uri.unwrap());
+        if let Some(ref user_agent) = configuration.user_agent {
+            req.header(http::header::USER_AGENT, &**user_agent);
+        }
+        let mut req = req
+            .body(hyper::Body::from(serialized))
+            .expect("could not build hyper::Request");
+        req.headers_mut()
+            .typed_insert(&typed_headers::ContentType(mime::APPLICATION_JSON));
+        req.headers_mut()
+            .typed_insert(&typed_headers::ContentLength(serialized_len as u64));
+
+        // send request
+        Box::new(
+            configuration
+                .client
+                .request(req)
+                .map_err(Error::from)
+                .and_then(|resp| {
+                    let (http::response::Parts { status, .. }, body) = resp.into_parts();
+                    body.concat2()
+                        .and_then(move |body| Ok((status, body)))
+                        .map_err(Error::from)
+                }).and_then(|(status, body)| {
+                    if status.is_success() {
+                        Ok(body)
+                    } else {
+                        Err(Error::from((status, &*body)))
+                    }
+                }).and_then(|body| {
+                    let parsed: Result<::models::CertificateResponse, _> =
+                        serde_json::from_slice(&body);
+                    parsed.map_err(Error::from)
+                }),
+        )
+    }
+
     fn sign(
         &self,
         api_version: &str,
@@ -19,6 +19,9 @@ pub struct EncryptRequest {
     /// An initialization vector used to encrypt the data.
     #[serde(rename = "initializationVector")]
     initialization_vector: String,
+    /// Additional data that is authenticated but not encrypted.
+    #[serde(rename = "associatedData", skip_serializing_if = "Option::is_none")]
+    associated_data: Option<String>,
 }
 
 impl EncryptRequest {
@@ -26,6 +29,7 @@ impl EncryptRequest {
         EncryptRequest {
             plaintext,
             initialization_vector,
+            associated_data: None,
         }
     }
 
@@ -54,4 +58,21 @@ impl EncryptRequest {
     pub fn initialization_vector(&self) -> &String {
         &self.initialization_vector
     }
+
+    pub fn set_associated_data(&mut self, associated_data: String) {
+        self.associated_data = Some(associated_data);
+    }
+
+    pub fn with_associated_data(mut self, associated_data: String) -> Self {
+        self.associated_data = Some(associated_data);
+        self
+    }
+
+    pub fn associated_data(&self) -> Option<&str> {
+        self.associated_data.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_associated_data(&mut self) {
+        self.associated_data = None;
+    }
 }
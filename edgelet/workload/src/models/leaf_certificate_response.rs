@@ -0,0 +1,95 @@
+/*
+ * IoT Edge Module Workload API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeafCertificateResponse {
+    /// PEM formatted certificate of the leaf device.
+    #[serde(rename = "certificate")]
+    certificate: String,
+    /// PEM formatted chain of the device CA that issued the certificate.
+    #[serde(rename = "chain")]
+    chain: String,
+    /// Certificate expiration date-time (ISO 8601)
+    #[serde(rename = "expiration")]
+    expiration: String,
+    /// Set when the certificate expires earlier than requested.
+    #[serde(rename = "warning", skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+impl LeafCertificateResponse {
+    pub fn new(certificate: String, chain: String, expiration: String) -> Self {
+        LeafCertificateResponse {
+            certificate,
+            chain,
+            expiration,
+            warning: None,
+        }
+    }
+
+    pub fn set_certificate(&mut self, certificate: String) {
+        self.certificate = certificate;
+    }
+
+    pub fn with_certificate(mut self, certificate: String) -> Self {
+        self.certificate = certificate;
+        self
+    }
+
+    pub fn certificate(&self) -> &String {
+        &self.certificate
+    }
+
+    pub fn set_chain(&mut self, chain: String) {
+        self.chain = chain;
+    }
+
+    pub fn with_chain(mut self, chain: String) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    pub fn chain(&self) -> &String {
+        &self.chain
+    }
+
+    pub fn set_expiration(&mut self, expiration: String) {
+        self.expiration = expiration;
+    }
+
+    pub fn with_expiration(mut self, expiration: String) -> Self {
+        self.expiration = expiration;
+        self
+    }
+
+    pub fn expiration(&self) -> &String {
+        &self.expiration
+    }
+
+    pub fn set_warning(&mut self, warning: String) {
+        self.warning = Some(warning);
+    }
+
+    pub fn with_warning(mut self, warning: String) -> Self {
+        self.warning = Some(warning);
+        self
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_warning(&mut self) {
+        self.warning = None;
+    }
+}
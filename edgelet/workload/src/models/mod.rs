@@ -12,10 +12,22 @@ mod error_response;
 pub use self::error_response::ErrorResponse;
 mod identity_certificate_request;
 pub use self::identity_certificate_request::IdentityCertificateRequest;
+mod leaf_certificate_request;
+pub use self::leaf_certificate_request::LeafCertificateRequest;
+mod leaf_certificate_response;
+pub use self::leaf_certificate_response::LeafCertificateResponse;
 mod private_key;
 pub use self::private_key::PrivateKey;
+mod renew_certificate_request;
+pub use self::renew_certificate_request::RenewCertificateRequest;
 mod server_certificate_request;
 pub use self::server_certificate_request::ServerCertificateRequest;
+mod sign_batch_request;
+pub use self::sign_batch_request::SignBatchRequest;
+mod sign_batch_response;
+pub use self::sign_batch_response::SignBatchResponse;
+mod sign_batch_result;
+pub use self::sign_batch_result::SignBatchResult;
 mod sign_request;
 pub use self::sign_request::SignRequest;
 mod sign_response;
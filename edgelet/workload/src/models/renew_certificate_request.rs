@@ -0,0 +1,38 @@
+/*
+ * IoT Edge Module Workload API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenewCertificateRequest {
+    /// Type of the previously issued certificate to renew (identity or server)
+    #[serde(rename = "certificateType")]
+    certificate_type: String,
+}
+
+impl RenewCertificateRequest {
+    pub fn new(certificate_type: String) -> Self {
+        RenewCertificateRequest { certificate_type }
+    }
+
+    pub fn set_certificate_type(&mut self, certificate_type: String) {
+        self.certificate_type = certificate_type;
+    }
+
+    pub fn with_certificate_type(mut self, certificate_type: String) -> Self {
+        self.certificate_type = certificate_type;
+        self
+    }
+
+    pub fn certificate_type(&self) -> &String {
+        &self.certificate_type
+    }
+}
@@ -19,6 +19,9 @@ pub struct ServerCertificateRequest {
     /// Certificate expiration date-time (ISO 8601)
     #[serde(rename = "expiration")]
     expiration: String,
+    /// Subject alternative names (DNS names or IP addresses)
+    #[serde(rename = "sans", skip_serializing_if = "Option::is_none")]
+    sans: Option<Vec<String>>,
 }
 
 impl ServerCertificateRequest {
@@ -26,6 +29,7 @@ impl ServerCertificateRequest {
         ServerCertificateRequest {
             common_name,
             expiration,
+            sans: None,
         }
     }
 
@@ -54,4 +58,21 @@ impl ServerCertificateRequest {
     pub fn expiration(&self) -> &String {
         &self.expiration
     }
+
+    pub fn set_sans(&mut self, sans: Vec<String>) {
+        self.sans = Some(sans);
+    }
+
+    pub fn with_sans(mut self, sans: Vec<String>) -> Self {
+        self.sans = Some(sans);
+        self
+    }
+
+    pub fn sans(&self) -> Option<&[String]> {
+        self.sans.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_sans(&mut self) {
+        self.sans = None;
+    }
 }
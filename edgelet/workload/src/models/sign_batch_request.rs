@@ -0,0 +1,44 @@
+/*
+ * IoT Edge Module Workload API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+use super::SignRequest;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignBatchRequest {
+    /// The sign requests, signed in order.
+    #[serde(rename = "requests")]
+    requests: Vec<SignRequest>,
+}
+
+impl SignBatchRequest {
+    pub fn new(requests: Vec<SignRequest>) -> Self {
+        SignBatchRequest { requests }
+    }
+
+    pub fn set_requests(&mut self, requests: Vec<SignRequest>) {
+        self.requests = requests;
+    }
+
+    pub fn with_requests(mut self, requests: Vec<SignRequest>) -> Self {
+        self.requests = requests;
+        self
+    }
+
+    pub fn requests(&self) -> &[SignRequest] {
+        &self.requests
+    }
+
+    pub fn into_requests(self) -> Vec<SignRequest> {
+        self.requests
+    }
+}
@@ -0,0 +1,40 @@
+/*
+ * IoT Edge Module Workload API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+use super::SignBatchResult;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignBatchResponse {
+    /// The result of each sign request, in the order of the requests.
+    #[serde(rename = "results")]
+    results: Vec<SignBatchResult>,
+}
+
+impl SignBatchResponse {
+    pub fn new(results: Vec<SignBatchResult>) -> Self {
+        SignBatchResponse { results }
+    }
+
+    pub fn set_results(&mut self, results: Vec<SignBatchResult>) {
+        self.results = results;
+    }
+
+    pub fn with_results(mut self, results: Vec<SignBatchResult>) -> Self {
+        self.results = results;
+        self
+    }
+
+    pub fn results(&self) -> &[SignBatchResult] {
+        &self.results
+    }
+}
@@ -0,0 +1,73 @@
+/*
+ * IoT Edge Module Workload API
+ *
+ * No description provided (generated by Swagger Codegen https://github.com/swagger-api/swagger-codegen)
+ *
+ * OpenAPI spec version: 2018-06-28
+ *
+ * Generated by: https://github.com/swagger-api/swagger-codegen.git
+ */
+
+#[allow(unused_imports)]
+use serde_json::Value;
+
+use super::ErrorResponse;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignBatchResult {
+    /// Signature of the data, if it could be signed.
+    #[serde(rename = "digest", skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    /// Why the data could not be signed.
+    #[serde(rename = "error", skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+impl SignBatchResult {
+    pub fn new() -> Self {
+        SignBatchResult {
+            digest: None,
+            error: None,
+        }
+    }
+
+    pub fn set_digest(&mut self, digest: String) {
+        self.digest = Some(digest);
+    }
+
+    pub fn with_digest(mut self, digest: String) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_digest(&mut self) {
+        self.digest = None;
+    }
+
+    pub fn set_error(&mut self, error: ErrorResponse) {
+        self.error = Some(error);
+    }
+
+    pub fn with_error(mut self, error: ErrorResponse) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub fn error(&self) -> Option<&ErrorResponse> {
+        self.error.as_ref()
+    }
+
+    pub fn reset_error(&mut self) {
+        self.error = None;
+    }
+}
+
+impl Default for SignBatchResult {
+    fn default() -> Self {
+        SignBatchResult::new()
+    }
+}
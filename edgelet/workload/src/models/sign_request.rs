@@ -16,17 +16,21 @@ pub struct SignRequest {
     /// Name of key to perform sign operation.
     #[serde(rename = "keyId")]
     key_id: String,
-    /// Sign algorithm to be used.
-    #[serde(rename = "algo")]
-    algo: String,
+    /// Sign algorithm to be used. Defaults to HMACSHA256.
+    #[serde(rename = "algo", skip_serializing_if = "Option::is_none")]
+    algo: Option<String>,
     /// Data to be signed.
     #[serde(rename = "data")]
     data: String,
 }
 
 impl SignRequest {
-    pub fn new(key_id: String, algo: String, data: String) -> Self {
-        SignRequest { key_id, algo, data }
+    pub fn new(key_id: String, data: String) -> Self {
+        SignRequest {
+            key_id,
+            algo: None,
+            data,
+        }
     }
 
     pub fn set_key_id(&mut self, key_id: String) {
@@ -43,16 +47,20 @@ impl SignRequest {
     }
 
     pub fn set_algo(&mut self, algo: String) {
-        self.algo = algo;
+        self.algo = Some(algo);
     }
 
     pub fn with_algo(mut self, algo: String) -> Self {
-        self.algo = algo;
+        self.algo = Some(algo);
         self
     }
 
-    pub fn algo(&self) -> &String {
-        &self.algo
+    pub fn algo(&self) -> Option<&str> {
+        self.algo.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn reset_algo(&mut self) {
+        self.algo = None;
     }
 
     pub fn set_data(&mut self, data: String) {